@@ -34,6 +34,8 @@ pub struct ClientInfoSrcDst<A: Hash + PartialEq + Clone> {
  * - transport layer protocol
  * - source and dest. transport port
  * - syn cookie
+ * - whether the connection should be closed after
+ *   the answer (set by upper layers, e.g., HTTP)
  **/
 #[derive(Copy, Clone)]
 pub struct ClientInfo {
@@ -42,6 +44,7 @@ pub struct ClientInfo {
     pub transport: Option<IpNextHeaderProtocol>,
     pub port: ClientInfoSrcDst<u16>,
     pub cookie: Option<u32>,
+    pub close: bool,
 }
 
 impl ClientInfo {
@@ -61,6 +64,7 @@ impl ClientInfo {
                 dst: None,
             },
             cookie: None,
+            close: false,
         }
     }
 }
@@ -85,6 +89,7 @@ impl PartialEq for ClientInfo {
         if self.cookie != other.cookie {
             return false;
         }
+        /* close is not part of the identity of a client */
         true
     }
 }
@@ -129,6 +134,7 @@ mod tests {
                     dst: Some(0),
                 },
                 cookie: Some(0),
+                close: false,
             }
         }
     }
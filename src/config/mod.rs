@@ -0,0 +1,246 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::layer_4::udp::UdpConfig;
+use crate::proto::{
+    AdbConfig, AmqpConfig, BacnetConfig, BitcoinConfig, CoapConfig, DcerpcConfig, DhcpConfig,
+    Dhcpv6Config, DhtConfig, Dnp3Config, DnsConfig, EnipConfig, FhrpConfig, FingerConfig,
+    FtpConfig, Gh0stConfig, GitConfig, GopherConfig, HintsConfig, HttpConfig, Iec104Config,
+    IkeConfig, ImapConfig, IpmiConfig, JdwpConfig, KafkaConfig, KerberosConfig, L2tpConfig,
+    LdapConfig, MdnsConfig, MemcachedConfig, MinecraftConfig, MongodbConfig, MqttConfig,
+    MssqlConfig, MysqlConfig, NatsConfig, NbnsConfig, NfsConfig, OpenvpnConfig, Pop3Config,
+    PostgresConfig, PptpConfig, RadiusConfig, RdpConfig, RedisConfig, RmiConfig, RpcConfig,
+    RsyncConfig, RtspConfig, S7Config, SipConfig, SmallServicesConfig, SmbConfig, SmtpConfig,
+    SnmpConfig, SocksConfig, SshConfig, SteamConfig, StunConfig, SyslogConfig, TacacsConfig,
+    TcpConfig, TelnetConfig, TftpConfig, TlsConfig, TnsConfig, TorConfig, VncConfig, WhoisConfig,
+    WireguardConfig, WsdiscoveryConfig, X11Config, ZabbixConfig,
+};
+
+/* Configuration of masscanned, as read from the (TOML) file given
+ * with `-c`: each section configures one protocol, and every section
+ * and field is optional (built-in defaults are used instead).
+ **/
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub adb: AdbConfig,
+    pub amqp: AmqpConfig,
+    pub bacnet: BacnetConfig,
+    pub bitcoin: BitcoinConfig,
+    pub coap: CoapConfig,
+    pub dcerpc: DcerpcConfig,
+    pub dhcp: DhcpConfig,
+    pub dhcpv6: Dhcpv6Config,
+    pub dht: DhtConfig,
+    pub dnp3: Dnp3Config,
+    pub dns: DnsConfig,
+    pub enip: EnipConfig,
+    pub fhrp: FhrpConfig,
+    pub finger: FingerConfig,
+    pub ftp: FtpConfig,
+    pub gh0st: Gh0stConfig,
+    pub git: GitConfig,
+    pub gopher: GopherConfig,
+    pub hints: HintsConfig,
+    pub http: HttpConfig,
+    pub iec104: Iec104Config,
+    pub ike: IkeConfig,
+    pub imap: ImapConfig,
+    pub ipmi: IpmiConfig,
+    pub jdwp: JdwpConfig,
+    pub kafka: KafkaConfig,
+    pub kerberos: KerberosConfig,
+    pub l2tp: L2tpConfig,
+    pub ldap: LdapConfig,
+    pub mdns: MdnsConfig,
+    pub memcached: MemcachedConfig,
+    pub minecraft: MinecraftConfig,
+    pub mongodb: MongodbConfig,
+    pub mqtt: MqttConfig,
+    pub mssql: MssqlConfig,
+    pub mysql: MysqlConfig,
+    pub nats: NatsConfig,
+    pub nbns: NbnsConfig,
+    pub nfs: NfsConfig,
+    pub openvpn: OpenvpnConfig,
+    pub pop3: Pop3Config,
+    pub postgres: PostgresConfig,
+    pub pptp: PptpConfig,
+    pub radius: RadiusConfig,
+    pub rdp: RdpConfig,
+    pub redis: RedisConfig,
+    pub rmi: RmiConfig,
+    pub rpc: RpcConfig,
+    pub rsync: RsyncConfig,
+    pub rtsp: RtspConfig,
+    pub s7: S7Config,
+    pub sip: SipConfig,
+    pub small_services: SmallServicesConfig,
+    pub smb: SmbConfig,
+    pub smtp: SmtpConfig,
+    pub snmp: SnmpConfig,
+    pub socks: SocksConfig,
+    pub ssh: SshConfig,
+    pub steam: SteamConfig,
+    pub stun: StunConfig,
+    pub syslog: SyslogConfig,
+    pub tacacs: TacacsConfig,
+    pub tcp: TcpConfig,
+    pub telnet: TelnetConfig,
+    pub tftp: TftpConfig,
+    pub tls: TlsConfig,
+    pub tns: TnsConfig,
+    pub tor: TorConfig,
+    pub udp: UdpConfig,
+    pub vnc: VncConfig,
+    pub whois: WhoisConfig,
+    pub wireguard: WireguardConfig,
+    pub wsdiscovery: WsdiscoveryConfig,
+    pub x11: X11Config,
+    pub zabbix: ZabbixConfig,
+}
+
+impl Config {
+    pub fn from_file(path: &str) -> Result<Self, io::Error> {
+        let mut config: Config = fs::read_to_string(path)?.parse()?;
+        config.tls.init()?;
+        Ok(config)
+    }
+}
+
+impl FromStr for Config {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config: Config =
+            toml::from_str(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        config.adb.check()?;
+        config.bacnet.check()?;
+        config.bitcoin.check()?;
+        config.dcerpc.check()?;
+        config.dhcp.check()?;
+        config.dhcpv6.check()?;
+        config.dht.check()?;
+        config.dnp3.check()?;
+        config.enip.check()?;
+        config.finger.check()?;
+        config.ftp.check()?;
+        config.gh0st.check()?;
+        config.gopher.check()?;
+        config.hints.check()?;
+        config.http.check()?;
+        config.ike.check()?;
+        config.imap.check()?;
+        config.ipmi.check()?;
+        config.kafka.check()?;
+        config.mdns.check()?;
+        config.mongodb.check()?;
+        config.mqtt.check()?;
+        config.mssql.check()?;
+        config.mysql.check()?;
+        config.nbns.check()?;
+        config.nfs.check()?;
+        config.pop3.check()?;
+        config.pptp.check()?;
+        config.rsync.check()?;
+        config.s7.check()?;
+        config.small_services.check()?;
+        config.smb.check()?;
+        config.smtp.check()?;
+        config.snmp.check()?;
+        config.ssh.check()?;
+        config.tls.check()?;
+        config.tns.check()?;
+        config.tor.check()?;
+        config.vnc.check()?;
+        config.wsdiscovery.check()?;
+        config.x11.check()?;
+        config.zabbix.check()?;
+        Ok(config)
+    }
+}
+
+/* Pick one item of a list (e.g., a persona) for a given IP address:
+ * the choice is deterministic (FNV-1a hash of the address), so that
+ * one address always presents the same item while different addresses
+ * of the same instance present different ones.
+ **/
+pub fn select_by_ip<T>(items: &[T], ip: Option<IpAddr>) -> Option<&T> {
+    if items.is_empty() {
+        return None;
+    }
+    let octets = match ip {
+        Some(IpAddr::V4(ip)) => ip.octets().to_vec(),
+        Some(IpAddr::V6(ip)) => ip.octets().to_vec(),
+        None => return items.first(),
+    };
+    let mut h: u64 = 0xcbf29ce484222325;
+    for o in octets {
+        h ^= o as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    items.get((h % items.len() as u64) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_empty() {
+        let config = Config::from_str("").expect("error parsing empty config");
+        assert!(config.http.post_body == HttpConfig::default().post_body);
+    }
+
+    #[test]
+    fn test_config_http() {
+        let config =
+            Config::from_str("[http]\npost_body = \"OK\"\n").expect("error parsing config");
+        assert!(config.http.post_body == "OK");
+        /* unknown sections or fields are errors */
+        assert!(Config::from_str("[htp]\n").is_err());
+        assert!(Config::from_str("[http]\npostbody = \"OK\"\n").is_err());
+    }
+
+    #[test]
+    fn test_select_by_ip() {
+        use std::net::Ipv4Addr;
+        let items = [0, 1, 2];
+        let empty: [usize; 0] = [];
+        assert!(select_by_ip(&empty, None).is_none());
+        assert!(select_by_ip(&items, None) == Some(&0));
+        /* same address, same item */
+        let ip = Some(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)));
+        let item = select_by_ip(&items, ip);
+        for _ in 0..10 {
+            assert!(select_by_ip(&items, ip) == item);
+        }
+        /* every item is used */
+        for i in items.iter() {
+            assert!((0..=255).any(|o| select_by_ip(
+                &items,
+                Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, o)))
+            ) == Some(i)));
+        }
+    }
+}
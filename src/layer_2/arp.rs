@@ -70,6 +70,7 @@ pub fn repl<'a, 'b>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
     use std::collections::HashSet;
     use std::net::Ipv4Addr;
     use std::str::FromStr;
@@ -86,6 +87,7 @@ mod tests {
             mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
             iface: None,
             ip_addresses: Some(&ips),
+            config: Config::default(),
         };
         let mut arp_req =
             MutableArpPacket::owned([0; 28].to_vec()).expect("error constructing ARP request");
@@ -42,6 +42,9 @@ type EtherAddr = [u8; 6];
  * - masscanned own MAC address,
  * - layer 2 broadcast MAC addresses,
  * - layer 2 IPv6 multicast MAC address,
+ * - layer 2 mDNS multicast MAC addresses (IPv4 and IPv6),
+ * - layer 2 DHCPv6 multicast MAC address (All_DHCP_Relay_Agents_and_Servers),
+ * - layer 2 WS-Discovery multicast MAC addresses (IPv4 and IPv6),
  * - layer 2 IPv6 solicited-node multicast addresses for each IPv6 address
  *      of masscanned
  **/
@@ -58,6 +61,37 @@ pub fn get_authorized_eth_addr(
             .parse()
             .expect("error parsing generic MAC address"),
     );
+    /* add mDNS multicast addr (224.0.0.251 and ff02::fb) */
+    for addr in ["01:00:5e:00:00:fb", "33:33:00:00:00:fb"].iter() {
+        auth_addr.insert(addr.parse().expect("error parsing mDNS MAC address"));
+    }
+    /* add DHCPv6 multicast addr (ff02::1:2) */
+    auth_addr.insert(
+        "33:33:00:01:00:02"
+            .parse()
+            .expect("error parsing DHCPv6 MAC address"),
+    );
+    /* add WS-Discovery multicast addr (239.255.255.250 and ff02::c) */
+    for addr in ["01:00:5e:7f:ff:fa", "33:33:00:00:00:0c"].iter() {
+        auth_addr.insert(
+            addr.parse()
+                .expect("error parsing WS-Discovery MAC address"),
+        );
+    }
+    /* add HSRP and VRRP multicast addr (224.0.0.2, 224.0.0.18 and
+     * ff02::12) */
+    for addr in [
+        "01:00:5e:00:00:02",
+        "01:00:5e:00:00:12",
+        "33:33:00:00:00:12",
+    ]
+    .iter()
+    {
+        auth_addr.insert(
+            addr.parse()
+                .expect("error parsing HSRP or VRRP MAC address"),
+        );
+    }
     /* Add:
      * - IPv4 multicast address for every IPv4
      * - IPv6 Solicited-Node multicast address for every IPv6
@@ -182,7 +216,9 @@ pub fn reply<'a, 'b>(
         }
     };
     eth_repl.set_source(masscanned.mac);
-    eth_repl.set_destination(eth_req.get_source());
+    /* Note: client info could have been modified by upper layers (e.g.,
+     * mDNS answers sent to the multicast group) */
+    eth_repl.set_destination(client_info.mac.src.unwrap_or_else(|| eth_req.get_source()));
     debug!("sending Ethernet packet: {:?}", eth_repl);
     Some(eth_repl)
 }
@@ -190,6 +226,7 @@ pub fn reply<'a, 'b>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
     use std::net::{Ipv4Addr, Ipv6Addr};
     use std::str::FromStr;
 
@@ -212,6 +249,7 @@ mod tests {
             mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
             iface: None,
             ip_addresses: Some(&ips),
+            config: Config::default(),
         };
         let mut eth_req = MutableEthernetPacket::owned(vec![
             0;
@@ -32,6 +32,7 @@ use pnet::packet::{
 
 use crate::client::ClientInfo;
 use crate::layer_4;
+use crate::proto;
 use crate::Masscanned;
 
 pub fn repl<'a, 'b>(
@@ -40,12 +41,28 @@ pub fn repl<'a, 'b>(
     mut client_info: &mut ClientInfo,
 ) -> Option<MutableIpv4Packet<'b>> {
     debug!("receiving IPv4 packet: {:?}", ip_req);
+    /* Multicast and (limited) broadcast destinations are only handled
+     * over UDP (upper layers decide whether to answer, e.g., mDNS or
+     * DHCP) and VRRP (observed): other packets are dropped.
+     **/
+    let group = ip_req.get_destination().is_multicast() || ip_req.get_destination().is_broadcast();
+    if group
+        && ip_req.get_next_level_protocol() != IpNextHeaderProtocols::Udp
+        && ip_req.get_next_level_protocol() != IpNextHeaderProtocols::Vrrp
+    {
+        info!(
+            "Ignoring IP packet from {} for multicast or broadcast address {}",
+            ip_req.get_source(),
+            ip_req.get_destination()
+        );
+        return None;
+    }
     /* If masscanned is configured with IP addresses, then
      * check that the dest. IP address of the packet is one of
      * those handled by masscanned - otherwise, drop the packet.
      **/
     if let Some(ip_addr_list) = masscanned.ip_addresses {
-        if !ip_addr_list.contains(&IpAddr::V4(ip_req.get_destination())) {
+        if !group && !ip_addr_list.contains(&IpAddr::V4(ip_req.get_destination())) {
             info!(
                 "Ignoring IP packet from {} for {}",
                 ip_req.get_source(),
@@ -59,6 +76,9 @@ pub fn repl<'a, 'b>(
     client_info.ip.dst = Some(IpAddr::V4(ip_req.get_destination()));
     /* Fill client info with transport layer procotol */
     client_info.transport = Some(ip_req.get_next_level_protocol());
+    /* source and dest. IP addresses of the answer */
+    let mut src = ip_req.get_destination();
+    let mut dst = ip_req.get_source();
     let mut ip_repl;
     match ip_req.get_next_level_protocol() {
         /* Answer to an ICMP packet */
@@ -106,11 +126,15 @@ pub fn repl<'a, 'b>(
         IpNextHeaderProtocols::Udp => {
             let udp_req = UdpPacket::new(ip_req.payload()).expect("error parsing UDP packet");
             if let Some(mut udp_repl) = layer_4::udp::repl(&udp_req, masscanned, &mut client_info) {
-                udp_repl.set_checksum(ipv4_checksum_udp(
-                    &udp_repl.to_immutable(),
-                    &ip_req.get_destination(),
-                    &ip_req.get_source(),
-                ));
+                /* Note: client info could have been modified by upper
+                 * layers (e.g., mDNS answers sent to the multicast group) */
+                if let (Some(IpAddr::V4(s)), Some(IpAddr::V4(d))) =
+                    (client_info.ip.dst, client_info.ip.src)
+                {
+                    src = s;
+                    dst = d;
+                }
+                udp_repl.set_checksum(ipv4_checksum_udp(&udp_repl.to_immutable(), &src, &dst));
                 let udp_len = udp_repl.packet().len();
                 udp_repl.set_length(udp_len.try_into().unwrap());
                 debug!("udp len: {}", udp_len);
@@ -126,6 +150,11 @@ pub fn repl<'a, 'b>(
                 return None;
             }
         }
+        /* VRRP advertisements are only observed */
+        IpNextHeaderProtocols::Vrrp => {
+            proto::vrrp(ip_req.payload(), masscanned, client_info);
+            return None;
+        }
         /* Next layer protocol not handled (yet) - dropping packet */
         _ => {
             info!(
@@ -137,7 +166,12 @@ pub fn repl<'a, 'b>(
     };
     /* Set IP packet fields before sending */
     ip_repl.set_version(4);
-    ip_repl.set_ttl(64);
+    /* RFC 6762 section 11: mDNS answers are sent with a TTL of 255 */
+    if ip_req.get_destination().is_multicast() || dst.is_multicast() {
+        ip_repl.set_ttl(255);
+    } else {
+        ip_repl.set_ttl(64);
+    }
     ip_repl.set_identification(0);
     /* These values are already initialized with 0s
      * ip_repl.set_dscp(0);
@@ -147,9 +181,8 @@ pub fn repl<'a, 'b>(
     /* Do not fragment packet */
     ip_repl.set_flags(Ipv4Flags::DontFragment);
     /* Set source and dest. IP address */
-    /* FIXME when dest. was a multicast IP address */
-    ip_repl.set_source(ip_req.get_destination());
-    ip_repl.set_destination(ip_req.get_source());
+    ip_repl.set_source(src);
+    ip_repl.set_destination(dst);
     debug!("sending IPv4 packet: {:?}", ip_repl);
     Some(ip_repl)
 }
@@ -157,6 +190,7 @@ pub fn repl<'a, 'b>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
     use std::collections::HashSet;
     use std::net::Ipv4Addr;
     use std::str::FromStr;
@@ -178,6 +212,7 @@ mod tests {
             mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
             iface: None,
             ip_addresses: Some(&ips),
+            config: Config::default(),
         };
         let mut ip_req =
             MutableIpv4Packet::owned(vec![0; Ipv4Packet::minimum_packet_size() + payload.len()])
@@ -207,4 +242,116 @@ mod tests {
         ip_req.set_destination(Ipv4Addr::new(2, 2, 2, 2));
         assert!(repl(&ip_req.to_immutable(), &masscanned, &mut client_info) == None);
     }
+
+    #[test]
+    fn test_ipv4_multicast() {
+        use pnet::packet::udp::MutableUdpPacket;
+        /* test payload is scapy> DNS(id=0, qd=DNSQR(qname="_http._tcp.local",
+         * qtype="PTR")) */
+        let dns = b"\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x05_http\x04_tcp\x05local\x00\x00\x0c\x00\x01";
+        let test_ip_addr = Ipv4Addr::new(3, 2, 1, 0);
+        let masscanned_ip_addr = Ipv4Addr::new(0, 1, 2, 3);
+        let group = Ipv4Addr::new(224, 0, 0, 251);
+        let mut ips = HashSet::new();
+        ips.insert(IpAddr::V4(masscanned_ip_addr));
+        let masscanned = Masscanned {
+            synack_key: [0, 0],
+            mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
+            iface: None,
+            ip_addresses: Some(&ips),
+            config: Config::from_str("[[mdns.service]]\n").expect("error parsing config"),
+        };
+        let mut udp = MutableUdpPacket::owned(vec![0; 8 + dns.len()]).unwrap();
+        udp.set_source(5353);
+        udp.set_destination(5353);
+        udp.set_length((8 + dns.len()) as u16);
+        udp.set_payload(dns);
+        let mut ip_req =
+            MutableIpv4Packet::owned(vec![0; Ipv4Packet::minimum_packet_size() + 8 + dns.len()])
+                .expect("error constructing IPv4 packet");
+        ip_req.set_version(4);
+        ip_req.set_header_length(5);
+        ip_req.set_ttl(255);
+        ip_req.set_total_length(ip_req.packet().len() as u16);
+        ip_req.set_source(test_ip_addr);
+        ip_req.set_destination(group);
+        ip_req.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        ip_req.set_payload(udp.packet());
+        /* mDNS answer to the group, from our address */
+        let mut client_info = ClientInfo::new();
+        let ip_repl = repl(&ip_req.to_immutable(), &masscanned, &mut client_info)
+            .expect("expected an IP answer, got None");
+        assert!(ip_repl.get_source() == masscanned_ip_addr);
+        assert!(ip_repl.get_destination() == group);
+        assert!(ip_repl.get_ttl() == 255);
+        let udp_repl = UdpPacket::new(ip_repl.payload()).unwrap();
+        assert!(udp_repl.get_destination() == 5353);
+        assert!(
+            udp_repl.get_checksum() == ipv4_checksum_udp(&udp_repl, &masscanned_ip_addr, &group)
+        );
+        /* other protocols to a multicast address are dropped */
+        ip_req.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
+        ip_req.set_payload(b"\x08\x00\xf7\xff\x00\x00\x00\x00");
+        let mut client_info = ClientInfo::new();
+        assert!(repl(&ip_req.to_immutable(), &masscanned, &mut client_info).is_none());
+    }
+
+    #[test]
+    fn test_ipv4_broadcast() {
+        use pnet::packet::udp::MutableUdpPacket;
+        /* DHCPDISCOVER, with the broadcast flag */
+        let mut dhcp = vec![0; 240];
+        dhcp[..4].copy_from_slice(b"\x01\x01\x06\x00");
+        dhcp[10] = 0x80;
+        dhcp[28..34].copy_from_slice(b"\x08\x00\x27\x1a\x2b\x3c");
+        dhcp[236..].copy_from_slice(b"\x63\x82\x53\x63");
+        dhcp.extend_from_slice(b"\x35\x01\x01\xff");
+        let masscanned_ip_addr = Ipv4Addr::new(0, 1, 2, 3);
+        let mut ips = HashSet::new();
+        ips.insert(IpAddr::V4(masscanned_ip_addr));
+        let mut masscanned = Masscanned {
+            synack_key: [0, 0],
+            mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
+            iface: None,
+            ip_addresses: Some(&ips),
+            config: Config::default(),
+        };
+        let mut udp = MutableUdpPacket::owned(vec![0; 8 + dhcp.len()]).unwrap();
+        udp.set_source(68);
+        udp.set_destination(67);
+        udp.set_length((8 + dhcp.len()) as u16);
+        udp.set_payload(&dhcp);
+        let mut ip_req =
+            MutableIpv4Packet::owned(vec![0; Ipv4Packet::minimum_packet_size() + 8 + dhcp.len()])
+                .expect("error constructing IPv4 packet");
+        ip_req.set_version(4);
+        ip_req.set_header_length(5);
+        ip_req.set_ttl(64);
+        ip_req.set_total_length(ip_req.packet().len() as u16);
+        ip_req.set_source(Ipv4Addr::UNSPECIFIED);
+        ip_req.set_destination(Ipv4Addr::BROADCAST);
+        ip_req.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        ip_req.set_payload(udp.packet());
+        /* DHCP is disabled by default: nothing is answered */
+        let mut client_info = ClientInfo::new();
+        assert!(repl(&ip_req.to_immutable(), &masscanned, &mut client_info).is_none());
+        /* DHCPOFFER, broadcast, from our address */
+        masscanned.config.dhcp.enabled = true;
+        let mut client_info = ClientInfo::new();
+        let ip_repl = repl(&ip_req.to_immutable(), &masscanned, &mut client_info)
+            .expect("expected an IP answer, got None");
+        assert!(ip_repl.get_source() == masscanned_ip_addr);
+        assert!(ip_repl.get_destination() == Ipv4Addr::BROADCAST);
+        let udp_repl = UdpPacket::new(ip_repl.payload()).unwrap();
+        assert!(udp_repl.get_source() == 67 && udp_repl.get_destination() == 68);
+        assert!(
+            udp_repl.get_checksum()
+                == ipv4_checksum_udp(&udp_repl, &masscanned_ip_addr, &Ipv4Addr::BROADCAST)
+        );
+        /* other protocols to the broadcast address are dropped */
+        ip_req.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
+        ip_req.set_payload(b"\x08\x00\xf7\xff\x00\x00\x00\x00");
+        let mut client_info = ClientInfo::new();
+        assert!(repl(&ip_req.to_immutable(), &masscanned, &mut client_info).is_none());
+    }
 }
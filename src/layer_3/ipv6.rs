@@ -28,6 +28,7 @@ use pnet::packet::{
 
 use crate::client::ClientInfo;
 use crate::layer_4;
+use crate::proto;
 use crate::Masscanned;
 
 pub fn repl<'a, 'b>(
@@ -36,14 +37,31 @@ pub fn repl<'a, 'b>(
     mut client_info: &mut ClientInfo,
 ) -> Option<MutableIpv6Packet<'b>> {
     debug!("receiving IPv6 packet: {:?}", ip_req);
-    let src = ip_req.get_source();
+    let mut src = ip_req.get_source();
     let mut dst = ip_req.get_destination();
     /* If masscanned is configured with IP addresses, check that
      * the dest. IP address corresponds to one of those
      * Otherwise, drop the packet.
      **/
+    /* Multicast destinations are only handled over ICMPv6 (e.g.,
+     * neighbor solicitations), UDP (upper layers decide whether to
+     * answer, e.g., mDNS) and VRRP (observed): other packets are
+     * dropped.
+     **/
+    if dst.is_multicast()
+        && ip_req.get_next_header() != IpNextHeaderProtocols::Icmpv6
+        && ip_req.get_next_header() != IpNextHeaderProtocols::Udp
+        && ip_req.get_next_header() != IpNextHeaderProtocols::Vrrp
+    {
+        info!(
+            "Ignoring IP packet from {} for multicast address {}",
+            &src, &dst
+        );
+        return None;
+    }
     if let Some(ip_addr_list) = masscanned.ip_addresses {
         if !ip_addr_list.contains(&IpAddr::V6(dst))
+            && !dst.is_multicast()
             && ip_req.get_next_header() != IpNextHeaderProtocols::Icmpv6
         {
             info!("Ignoring IP packet from {} for {}", &src, &dst);
@@ -115,12 +133,16 @@ pub fn repl<'a, 'b>(
         IpNextHeaderProtocols::Udp => {
             let udp_req = UdpPacket::new(ip_req.payload()).expect("error parsing UDP packet");
             if let Some(mut udp_repl) = layer_4::udp::repl(&udp_req, masscanned, &mut client_info) {
+                /* Note: client info could have been modified by upper
+                 * layers (e.g., mDNS answers sent to the multicast group) */
+                if let (Some(IpAddr::V6(s)), Some(IpAddr::V6(d))) =
+                    (client_info.ip.src, client_info.ip.dst)
+                {
+                    src = s;
+                    dst = d;
+                }
                 /* Compute and set UDP checksum */
-                udp_repl.set_checksum(ipv6_checksum_udp(
-                    &udp_repl.to_immutable(),
-                    &ip_req.get_destination(),
-                    &ip_req.get_source(),
-                ));
+                udp_repl.set_checksum(ipv6_checksum_udp(&udp_repl.to_immutable(), &dst, &src));
                 /* Compute answer length */
                 let udp_len = udp_repl.packet().len();
                 let ip_len = Ipv6Packet::minimum_packet_size() + udp_len;
@@ -135,6 +157,11 @@ pub fn repl<'a, 'b>(
                 return None;
             }
         }
+        /* VRRP advertisements are only observed */
+        IpNextHeaderProtocols::Vrrp => {
+            proto::vrrp(ip_req.payload(), masscanned, client_info);
+            return None;
+        }
         /* Other protocols are not handled (yet) - dropping */
         _ => {
             info!(
@@ -144,6 +171,12 @@ pub fn repl<'a, 'b>(
             return None;
         }
     };
+    /* RFC 6762 section 11: mDNS answers are sent with a hop limit of 255 */
+    if (ip_req.get_destination().is_multicast() || src.is_multicast())
+        && ip_repl.get_hop_limit() == 0
+    {
+        ip_repl.set_hop_limit(255);
+    }
     /* If not already set, we set the hlim value */
     if ip_repl.get_hop_limit() == 0 {
         ip_repl.set_hop_limit(64);
@@ -160,6 +193,7 @@ pub fn repl<'a, 'b>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
     use std::collections::HashSet;
     use std::net::Ipv6Addr;
     use std::str::FromStr;
@@ -187,6 +221,7 @@ mod tests {
             mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
             iface: None,
             ip_addresses: Some(&ips),
+            config: Config::default(),
         };
         let mut ip_req =
             MutableIpv6Packet::owned(vec![0; Ipv6Packet::minimum_packet_size() + payload.len()])
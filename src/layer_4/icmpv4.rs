@@ -66,6 +66,7 @@ pub fn repl<'a, 'b>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
     use std::str::FromStr;
 
     use pnet::util::MacAddr;
@@ -81,6 +82,7 @@ mod tests {
             mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
             iface: None,
             ip_addresses: None,
+            config: Config::default(),
         };
         let mut icmp_req =
             MutableIcmpPacket::owned(vec![0; IcmpPacket::minimum_packet_size() + payload.len()])
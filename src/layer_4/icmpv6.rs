@@ -153,6 +153,7 @@ pub fn repl<'a, 'b>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
     use std::collections::HashSet;
     use std::net::Ipv6Addr;
     use std::str::FromStr;
@@ -174,6 +175,7 @@ mod tests {
             mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
             iface: None,
             ip_addresses: Some(&ips),
+            config: Config::default(),
         };
         /* Legitimate solicitation */
         let ndp_ns = NeighborSolicit {
@@ -246,6 +248,7 @@ mod tests {
             mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
             iface: None,
             ip_addresses: Some(&ips),
+            config: Config::default(),
         };
         let mut icmpv6_echo_req = MutableIcmpv6Packet::owned(vec![
             0;
@@ -23,6 +23,7 @@ use pnet::packet::{
 
 use crate::client::ClientInfo;
 use crate::proto;
+use crate::proto::tcb;
 use crate::synackcookie;
 use crate::Masscanned;
 
@@ -49,7 +50,10 @@ pub fn repl<'a, 'b>(
             };
             /* Compute syncookie */
             if let Ok(cookie) = synackcookie::generate(&client_info, &masscanned.synack_key) {
-                if cookie != ackno {
+                /* data already sent on the flow is acknowledged too */
+                if cookie != ackno
+                    && tcb::get_sent(cookie).map(|sent| cookie.wrapping_add(sent)) != Some(ackno)
+                {
                     info!("PSH-ACK ignored: synackcookie not valid");
                     return None;
                 }
@@ -59,11 +63,19 @@ pub fn repl<'a, 'b>(
             let payload = tcp_req.payload();
             /* Any answer to upper-layer protocol? */
             if let Some(repl) = proto::repl(&payload, masscanned, &mut client_info) {
+                if let Some(cookie) = client_info.cookie {
+                    tcb::add_sent(cookie, repl.len());
+                }
                 tcp_repl = MutableTcpPacket::owned(
                     [vec![0; MutableTcpPacket::minimum_packet_size()], repl].concat(),
                 )
                 .expect("error constructing a TCP packet");
-                tcp_repl.set_flags(TcpFlags::ACK | TcpFlags::PSH);
+                /* upper layer may ask to close the connection after the answer */
+                if client_info.close {
+                    tcp_repl.set_flags(TcpFlags::ACK | TcpFlags::PSH | TcpFlags::FIN);
+                } else {
+                    tcp_repl.set_flags(TcpFlags::ACK | TcpFlags::PSH);
+                }
             } else {
                 tcp_repl =
                     MutableTcpPacket::owned(vec![0; MutableTcpPacket::minimum_packet_size()])
@@ -73,10 +85,28 @@ pub fn repl<'a, 'b>(
             tcp_repl.set_acknowledgement(tcp_req.get_sequence() + (tcp_req.payload().len() as u32));
             tcp_repl.set_sequence(tcp_req.get_acknowledgement());
         }
-        /* Answer to ACK: nothing */
+        /* Answer to ACK: nothing, unless the ACK completes the handshake
+         * and the server speaks first (e.g., FTP) */
         flags if flags == TcpFlags::ACK => {
-            /* answer here when server needs to speak first after handshake */
-            return None;
+            let cookie = synackcookie::generate(client_info, &masscanned.synack_key).ok()?;
+            if tcp_req.get_acknowledgement().wrapping_sub(1) != cookie {
+                return None;
+            }
+            client_info.cookie = Some(cookie);
+            let repl = proto::greeting(masscanned, client_info)?;
+            warn!("greeting to ACK on port {}", tcp_req.get_destination());
+            tcb::add_sent(cookie, repl.len());
+            tcp_repl = MutableTcpPacket::owned(
+                [vec![0; MutableTcpPacket::minimum_packet_size()], repl].concat(),
+            )
+            .expect("error constructing a TCP packet");
+            if client_info.close {
+                tcp_repl.set_flags(TcpFlags::ACK | TcpFlags::PSH | TcpFlags::FIN);
+            } else {
+                tcp_repl.set_flags(TcpFlags::ACK | TcpFlags::PSH);
+            }
+            tcp_repl.set_acknowledgement(tcp_req.get_sequence());
+            tcp_repl.set_sequence(tcp_req.get_acknowledgement());
         }
         /* Answer to RST and FIN: nothing */
         flags if (flags == TcpFlags::RST || flags == (TcpFlags::FIN | TcpFlags::ACK)) => {
@@ -115,6 +145,7 @@ pub fn repl<'a, 'b>(
 mod tests {
     use super::*;
     use crate::client::ClientInfoSrcDst;
+    use crate::config::Config;
     use pnet::util::MacAddr;
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
@@ -125,6 +156,7 @@ mod tests {
             ip_addresses: None,
             synack_key: [0x06a0a1d63f305e9b, 0xd4d4bcbb7304875f],
             iface: None,
+            config: Config::default(),
         };
         /* reference */
         let ip_src = IpAddr::V4(Ipv4Addr::new(27, 198, 143, 1));
@@ -146,6 +178,7 @@ mod tests {
                 dst: Some(tcp_dport),
             },
             cookie: None,
+            close: false,
         };
         let cookie = synackcookie::generate(&client_info, &masscanned.synack_key).unwrap();
         let mut tcp_req =
@@ -174,6 +207,7 @@ mod tests {
             ip_addresses: None,
             synack_key: [0x06a0a1d63f305e9b, 0xd4d4bcbb7304875f],
             iface: None,
+            config: Config::default(),
         };
         /* reference */
         let ip_src = IpAddr::V6(Ipv6Addr::new(234, 52, 183, 47, 184, 172, 64, 141));
@@ -195,6 +229,7 @@ mod tests {
                 dst: Some(tcp_dport),
             },
             cookie: None,
+            close: false,
         };
         let cookie = synackcookie::generate(&client_info, &masscanned.synack_key).unwrap();
         let mut tcp_req =
@@ -215,4 +250,68 @@ mod tests {
         ));
         assert!(cookie == tcp_repl.get_sequence());
     }
+
+    #[test]
+    fn test_greeting() {
+        let masscanned = Masscanned {
+            mac: MacAddr(0, 0, 0, 0, 0, 0),
+            ip_addresses: None,
+            synack_key: [0x06a0a1d63f305e9b, 0xd4d4bcbb7304875f],
+            iface: None,
+            config: Config::default(),
+        };
+        let mut client_info = ClientInfo::new();
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)));
+        let mut tcp_req =
+            MutableTcpPacket::owned(vec![0; MutableTcpPacket::minimum_packet_size()]).unwrap();
+        tcp_req.set_source(65021);
+        tcp_req.set_flags(TcpFlags::SYN);
+        tcp_req.set_sequence(1000);
+        for port in [80, 21].iter() {
+            tcp_req.set_destination(*port);
+            tcp_req.set_flags(TcpFlags::SYN);
+            let cookie = repl(&tcp_req.to_immutable(), &masscanned, &mut client_info)
+                .unwrap()
+                .get_sequence();
+            /* ACK of the SYN-ACK */
+            tcp_req.set_flags(TcpFlags::ACK);
+            tcp_req.set_sequence(1001);
+            tcp_req.set_acknowledgement(cookie.wrapping_add(1));
+            let tcp_repl = repl(&tcp_req.to_immutable(), &masscanned, &mut client_info);
+            if *port == 80 {
+                /* client-first protocol */
+                assert!(tcp_repl.is_none());
+                continue;
+            }
+            let tcp_repl = tcp_repl.unwrap();
+            assert!(tcp_repl.get_flags() == TcpFlags::ACK | TcpFlags::PSH);
+            assert!(tcp_repl.get_sequence() == cookie.wrapping_add(1));
+            assert!(tcp_repl.get_acknowledgement() == 1001);
+            let banner = tcp_repl.payload().to_vec();
+            assert!(banner.starts_with(b"220 "));
+            /* no second greeting, no greeting with a bad cookie */
+            assert!(repl(&tcp_req.to_immutable(), &masscanned, &mut client_info).is_none());
+            tcp_req.set_acknowledgement(cookie.wrapping_add(2));
+            assert!(repl(&tcp_req.to_immutable(), &masscanned, &mut client_info).is_none());
+            /* the client answers the banner */
+            let mut data = MutableTcpPacket::owned(
+                [
+                    vec![0; MutableTcpPacket::minimum_packet_size()],
+                    b"USER ftp\r\n".to_vec(),
+                ]
+                .concat(),
+            )
+            .unwrap();
+            data.set_source(65021);
+            data.set_destination(21);
+            data.set_data_offset(5);
+            data.set_flags(TcpFlags::PSH | TcpFlags::ACK);
+            data.set_sequence(1001);
+            data.set_acknowledgement(cookie.wrapping_add(1 + banner.len() as u32));
+            let tcp_repl = repl(&data.to_immutable(), &masscanned, &mut client_info).unwrap();
+            assert!(tcp_repl.payload().starts_with(b"331 "));
+            tcb::CONTABLE.lock().unwrap().remove(cookie);
+        }
+    }
 }
@@ -20,11 +20,35 @@ use pnet::packet::{
     udp::{MutableUdpPacket, UdpPacket},
     Packet,
 };
+use serde::Deserialize;
 
 use crate::client::ClientInfo;
 use crate::proto;
 use crate::Masscanned;
 
+/* Amplification limiter: the source of UDP packets can be spoofed (to
+ * reflect traffic to a victim), so answers larger than
+ * max_amplification times the request are dropped (0: no limit).
+ * Answers of at most amplification_floor bytes are always sent: some
+ * protocols answer short (or empty) requests, e.g. the SQL Server
+ * Browser, daytime or time.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UdpConfig {
+    pub max_amplification: usize,
+    pub amplification_floor: usize,
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        UdpConfig {
+            max_amplification: 10,
+            amplification_floor: 512,
+        }
+    }
+}
+
 pub fn repl<'a, 'b>(
     udp_req: &'a UdpPacket,
     masscanned: &Masscanned,
@@ -37,6 +61,18 @@ pub fn repl<'a, 'b>(
     let payload = udp_req.payload();
     let mut udp_repl;
     if let Some(repl) = proto::repl(&payload, masscanned, &mut client_info) {
+        let max = masscanned.config.udp.max_amplification;
+        if max != 0
+            && repl.len() > masscanned.config.udp.amplification_floor
+            && repl.len() > max * payload.len()
+        {
+            info!(
+                "UDP answer dropped ({} bytes for a {}-byte request)",
+                repl.len(),
+                payload.len()
+            );
+            return None;
+        }
         udp_repl = MutableUdpPacket::owned(
             [vec![0; MutableUdpPacket::minimum_packet_size()], repl].concat(),
         )
@@ -52,3 +88,55 @@ pub fn repl<'a, 'b>(
     debug!("sending UDP packet: {:?}", udp_repl);
     Some(udp_repl)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn udp_repl(port: u16, data: &[u8], masscanned: &Masscanned) -> Option<Vec<u8>> {
+        let mut packet = vec![0; MutableUdpPacket::minimum_packet_size()];
+        packet.extend_from_slice(data);
+        let mut udp_req = MutableUdpPacket::new(&mut packet).unwrap();
+        udp_req.set_source(40000);
+        udp_req.set_destination(port);
+        udp_req.set_length((8 + data.len()) as u16);
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Udp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        let udp_repl = repl(&udp_req.to_immutable(), masscanned, &mut client_info)?;
+        assert!(udp_repl.get_source() == port && udp_repl.get_destination() == 40000);
+        Some(udp_repl.payload().to_vec())
+    }
+
+    #[test]
+    fn test_udp_amplification() {
+        let mut masscanned = masscanned(Config::default());
+        /* short answers to short (or empty) requests: SQL Server
+         * Browser, daytime, time */
+        for data in [&b"\x02"[..], b"\x03"].iter() {
+            assert!(udp_repl(1434, data, &masscanned).unwrap().len() > 10);
+        }
+        for data in [&b""[..], b"\r\n"].iter() {
+            assert!(udp_repl(13, data, &masscanned).unwrap().ends_with(b"\r\n"));
+        }
+        assert!(udp_repl(37, b"", &masscanned).unwrap().len() == 4);
+        /* chargen: 4 lines by default */
+        assert!(udp_repl(19, b"\n", &masscanned).unwrap().len() == 4 * 74);
+        /* larger answers are limited */
+        masscanned.config.small_services.chargen_lines = 10;
+        assert!(udp_repl(19, b"\n", &masscanned).is_none());
+        assert!(udp_repl(19, &[b'a'; 74], &masscanned).unwrap().len() == 10 * 74);
+        masscanned.config.udp.max_amplification = 0;
+        assert!(udp_repl(19, b"\n", &masscanned).unwrap().len() == 10 * 74);
+        /* without a floor */
+        masscanned.config.udp.max_amplification = 10;
+        masscanned.config.udp.amplification_floor = 0;
+        assert!(udp_repl(37, b"", &masscanned).is_none());
+        assert!(udp_repl(37, b"\n", &masscanned).unwrap().len() == 4);
+    }
+}
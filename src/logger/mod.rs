@@ -0,0 +1,180 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::Write;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::*;
+use pnet::packet::ip::IpNextHeaderProtocols;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::client::ClientInfo;
+
+lazy_static! {
+    /* where events are written, one JSON object per line - when not
+     * set (e.g., in tests), events go to the regular log instead */
+    static ref EVENT_SINK: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+}
+
+pub fn set_sink(sink: Box<dyn Write + Send>) {
+    *EVENT_SINK.lock().unwrap() = Some(sink);
+}
+
+/* how binary data (e.g., request content) is written in events */
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Hex,
+    Base64,
+}
+
+pub fn encode(data: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Hex => data.iter().map(|b| format!("{:02x}", b)).collect(),
+        Encoding::Base64 => base64::encode(data),
+    }
+}
+
+/* keys of the fields set from the client 5-tuple: the fields of the
+ * protocols (some named by the clients) cannot overwrite them */
+const EVENT_ENVELOPE: [&str; 7] = ["time", "event", "src", "sport", "dst", "dport", "transport"];
+
+/* Structured record of something a client did (e.g., an HTTP request),
+ * along with the client 5-tuple (addresses, ports, transport protocol).
+ **/
+pub struct Event {
+    pub name: &'static str,
+    pub client: ClientInfo,
+    pub fields: Map<String, Value>,
+}
+
+impl Event {
+    pub fn new(name: &'static str, client_info: &ClientInfo) -> Self {
+        Event {
+            name,
+            client: *client_info,
+            fields: Map::new(),
+        }
+    }
+
+    /* fields named as the envelope are prefixed with the name of the
+     * event (e.g., http_request_src) */
+    pub fn set<V: Into<Value>>(&mut self, key: &str, value: V) {
+        let key = if EVENT_ENVELOPE.contains(&key) {
+            format!("{}_{}", self.name, key)
+        } else {
+            key.to_string()
+        };
+        self.fields.insert(key, value.into());
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut obj = Map::new();
+        for (k, v) in self.fields.iter() {
+            obj.insert(k.clone(), v.clone());
+        }
+        obj.insert("time".to_string(), Utc::now().to_rfc3339().into());
+        obj.insert("event".to_string(), self.name.into());
+        if let Some(ip) = self.client.ip.src {
+            obj.insert("src".to_string(), ip.to_string().into());
+        }
+        if let Some(port) = self.client.port.src {
+            obj.insert("sport".to_string(), port.into());
+        }
+        if let Some(ip) = self.client.ip.dst {
+            obj.insert("dst".to_string(), ip.to_string().into());
+        }
+        if let Some(port) = self.client.port.dst {
+            obj.insert("dport".to_string(), port.into());
+        }
+        match self.client.transport {
+            Some(IpNextHeaderProtocols::Tcp) => {
+                obj.insert("transport".to_string(), "tcp".into());
+            }
+            Some(IpNextHeaderProtocols::Udp) => {
+                obj.insert("transport".to_string(), "udp".into());
+            }
+            Some(p) => {
+                obj.insert("transport".to_string(), p.0.into());
+            }
+            None => {}
+        }
+        Value::Object(obj)
+    }
+
+    pub fn log(&self) {
+        let line = self.to_json().to_string();
+        let mut sink = EVENT_SINK.lock().unwrap();
+        if let Some(w) = sink.as_mut() {
+            if let Err(e) = writeln!(w, "{}", line).and_then(|_| w.flush()) {
+                error!("cannot write event: {}", e);
+            }
+        } else {
+            info!("event: {}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn test_encode() {
+        assert!(encode(b"\x00\xffab", Encoding::Hex) == "00ff6162");
+        assert!(encode(b"\x00\xffab", Encoding::Base64) == "AP9hYg==");
+        assert!(encode(b"", Encoding::Hex).is_empty());
+    }
+
+    #[test]
+    fn test_event_json() {
+        let mut client_info = ClientInfo::new();
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 0, 1)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 0, 2)));
+        client_info.port.src = Some(65000);
+        client_info.port.dst = Some(80);
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        let mut event = Event::new("test", &client_info);
+        event.set("field", "value");
+        let json = event.to_json();
+        assert!(json["event"] == "test");
+        assert!(json["src"] == "192.0.0.1");
+        assert!(json["sport"] == 65000);
+        assert!(json["dst"] == "192.0.0.2");
+        assert!(json["dport"] == 80);
+        assert!(json["transport"] == "tcp");
+        assert!(json["field"] == "value");
+        assert!(json["time"].is_string());
+        /* the fields cannot overwrite the 5-tuple */
+        for key in EVENT_ENVELOPE.iter() {
+            event.set(key, "spoofed");
+        }
+        event.fields.insert("src".to_string(), "spoofed".into());
+        let json = event.to_json();
+        assert!(json["event"] == "test" && json["test_event"] == "spoofed");
+        assert!(json["src"] == "192.0.0.1" && json["test_src"] == "spoofed");
+        assert!(json["dport"] == 80 && json["test_dport"] == "spoofed");
+        assert!(json["transport"] == "tcp" && json["time"] != "spoofed");
+        /* unknown parts of the 5-tuple are omitted */
+        let json = Event::new("test", &ClientInfo::new()).to_json();
+        assert!(json.get("src").is_none());
+        assert!(json.get("transport").is_none());
+    }
+}
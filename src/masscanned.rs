@@ -20,7 +20,8 @@ extern crate lazy_static;
 
 use std::boxed::Box;
 use std::collections::HashSet;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
+use std::io;
 use std::net::IpAddr;
 use std::str::FromStr;
 
@@ -35,12 +36,15 @@ use pnet::{
     util::MacAddr,
 };
 
+use crate::config::Config;
 use crate::utils::IpAddrParser;
 
 mod client;
+mod config;
 mod layer_2;
 mod layer_3;
 mod layer_4;
+mod logger;
 mod proto;
 mod smack;
 mod synackcookie;
@@ -55,6 +59,7 @@ pub struct Masscanned<'a> {
     /* iface is an Option to make tests easier */
     pub iface: Option<&'a NetworkInterface>,
     pub ip_addresses: Option<&'a HashSet<IpAddr>>,
+    pub config: Config,
 }
 
 /* Get the L2 network interface from its name */
@@ -124,6 +129,29 @@ fn main() {
                 .help("File with the list of IP addresses to impersonate")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("file")
+                .help("Configuration file (TOML) for the protocol answers")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls-ports")
+                .long("tls-ports")
+                .value_name("port=protocol,...")
+                .help("Implicit TLS ports and the protocols behind them (e.g., 465=smtp,993=imap) - enables TLS termination")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("events")
+                .short("e")
+                .long("events")
+                .value_name("file")
+                .help("File where events (e.g., HTTP requests) are written as JSON lines (- for stdout)")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("verbosity")
                 .short("v")
@@ -187,11 +215,48 @@ fn main() {
     } else {
         None
     };
+    /* Parse configuration file if any */
+    let mut config = if let Some(path) = args.value_of("config") {
+        info!("parsing configuration file: {}", &path);
+        match Config::from_file(path) {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Cannot parse configuration file \"{}\": {}", path, e);
+                return;
+            }
+        }
+    } else {
+        Config::default()
+    };
+    if let Some(ports) = args.value_of("tls-ports") {
+        if let Err(e) = config.tls.add_ports(ports) {
+            error!("Cannot use TLS ports \"{}\": {}", ports, e);
+            return;
+        }
+    }
+    /* Open events file if any */
+    if let Some(path) = args.value_of("events") {
+        if path == "-" {
+            logger::set_sink(Box::new(io::stdout()));
+        } else {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => logger::set_sink(Box::new(file)),
+                Err(e) => {
+                    error!("Cannot open events file \"{}\": {}", path, e);
+                    return;
+                }
+            }
+        }
+    }
     let masscanned = Masscanned {
-        synack_key: [0, 0],
+        /* secret: SYNACK-cookies, and the stateless cookies, nonces and
+         * tokens of the UDP protocols (DTLS, TURN, DHT, Steam, etc.) are
+         * derived from it */
+        synack_key: rand::random(),
         mac,
         iface: Some(&iface),
         ip_addresses,
+        config,
     };
     info!("interface......{}", masscanned.iface.unwrap().name);
     info!("mac address....{}", masscanned.mac);
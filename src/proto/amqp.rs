@@ -0,0 +1,669 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* protocol header of AMQP 0-9-1 */
+pub const AMQP_PROTOCOL_HEADER: &[u8; 8] = b"AMQP\x00\x00\x09\x01";
+
+const AMQP_FRAME_METHOD: u8 = 1;
+const AMQP_FRAME_HEARTBEAT: u8 = 8;
+const AMQP_FRAME_END: u8 = 0xce;
+
+/* class and methods of the connection class */
+const AMQP_CONNECTION: u16 = 10;
+const AMQP_CONNECTION_START: u16 = 10;
+const AMQP_CONNECTION_START_OK: u16 = 11;
+const AMQP_CONNECTION_CLOSE: u16 = 50;
+const AMQP_CONNECTION_CLOSE_OK: u16 = 51;
+
+/* frame-max is not negotiated yet: largest frame accepted (and size of
+ * the data kept between two segments) */
+const AMQP_MAX_FRAME: usize = 131072;
+/* nesting of field tables and arrays */
+const AMQP_MAX_DEPTH: usize = 16;
+
+/* AMQP 0-9-1 broker (e.g., RabbitMQ): the protocol header gets a
+ * Connection.Start with these server properties, the Connection.StartOk
+ * (client properties and credentials) that follows gets a
+ * Connection.Close (access refused).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmqpConfig {
+    pub ports: Vec<u16>,
+    pub product: String,
+    pub version: String,
+    pub platform: String,
+    pub cluster_name: String,
+}
+
+impl Default for AmqpConfig {
+    fn default() -> Self {
+        AmqpConfig {
+            ports: vec![5672],
+            product: "RabbitMQ".to_string(),
+            version: "3.12.12".to_string(),
+            platform: "Erlang/OTP 25.3.2.8".to_string(),
+            cluster_name: "rabbit@rabbitmq".to_string(),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum AmqpPhase {
+    /* waiting for the protocol header */
+    Header,
+    /* Connection.Start sent, waiting for Connection.StartOk */
+    Start,
+    /* Connection.Close sent */
+    Close,
+}
+
+/* state of an AMQP flow: data received, not a complete frame yet */
+pub struct AmqpState {
+    buffer: Vec<u8>,
+    phase: AmqpPhase,
+}
+
+impl AmqpState {
+    pub fn new() -> Self {
+        AmqpState {
+            buffer: Vec::new(),
+            phase: AmqpPhase::Header,
+        }
+    }
+}
+
+struct AmqpFrame {
+    ftype: u8,
+    channel: u16,
+    payload: Vec<u8>,
+}
+
+/* next frame (type, channel, size, payload and frame-end octet):
+ * Ok(None) when data does not hold an entire frame, Err on invalid
+ * frames */
+fn amqp_parse_frame(data: &[u8]) -> Result<Option<(AmqpFrame, usize)>, &'static str> {
+    if data.len() < 7 {
+        return Ok(None);
+    }
+    let size = u32::from_be_bytes([data[3], data[4], data[5], data[6]]) as usize;
+    if size > AMQP_MAX_FRAME {
+        return Err("frame too large");
+    }
+    if data.len() < 8 + size {
+        return Ok(None);
+    }
+    if data[7 + size] != AMQP_FRAME_END {
+        return Err("invalid frame end");
+    }
+    Ok(Some((
+        AmqpFrame {
+            ftype: data[0],
+            channel: u16::from_be_bytes([data[1], data[2]]),
+            payload: data[7..7 + size].to_vec(),
+        },
+        8 + size,
+    )))
+}
+
+fn amqp_frame(ftype: u8, channel: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![ftype];
+    frame.extend_from_slice(&channel.to_be_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame.push(AMQP_FRAME_END);
+    frame
+}
+
+/* method frame, on channel 0 for the connection class */
+fn amqp_method(class: u16, method: u16, args: &[u8]) -> Vec<u8> {
+    let mut payload = class.to_be_bytes().to_vec();
+    payload.extend_from_slice(&method.to_be_bytes());
+    payload.extend_from_slice(args);
+    amqp_frame(AMQP_FRAME_METHOD, 0, &payload)
+}
+
+/* values of the field tables sent */
+enum AmqpValue<'a> {
+    Bool(bool),
+    Str(&'a str),
+    Table(Vec<(&'a str, AmqpValue<'a>)>),
+}
+
+fn amqp_shortstr(out: &mut Vec<u8>, s: &[u8]) {
+    let s = &s[..std::cmp::min(s.len(), 255)];
+    out.push(s.len() as u8);
+    out.extend_from_slice(s);
+}
+
+fn amqp_longstr(out: &mut Vec<u8>, s: &[u8]) {
+    out.extend_from_slice(&(s.len() as u32).to_be_bytes());
+    out.extend_from_slice(s);
+}
+
+fn amqp_table(out: &mut Vec<u8>, table: &[(&str, AmqpValue)]) {
+    let mut data = Vec::new();
+    for (name, value) in table.iter() {
+        amqp_shortstr(&mut data, name.as_bytes());
+        match value {
+            AmqpValue::Bool(b) => {
+                data.push(b't');
+                data.push(*b as u8);
+            }
+            AmqpValue::Str(s) => {
+                data.push(b'S');
+                amqp_longstr(&mut data, s.as_bytes());
+            }
+            AmqpValue::Table(t) => {
+                data.push(b'F');
+                amqp_table(&mut data, t);
+            }
+        }
+    }
+    amqp_longstr(out, &data);
+}
+
+/* reader of method arguments */
+struct AmqpReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> AmqpReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        AmqpReader { data, offset: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let b = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(b)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let mut a = [0u8; N];
+        a.copy_from_slice(self.bytes(N)?);
+        Some(a)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.array()?))
+    }
+
+    fn shortstr(&mut self) -> Option<&'a [u8]> {
+        let len = self.u8()? as usize;
+        self.bytes(len)
+    }
+
+    fn longstr(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    fn done(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+}
+
+/* field value, as JSON */
+fn amqp_value(r: &mut AmqpReader, depth: usize) -> Option<Value> {
+    if depth > AMQP_MAX_DEPTH {
+        return None;
+    }
+    Some(match r.u8()? {
+        b't' => (r.u8()? != 0).into(),
+        b'b' => (r.u8()? as i8).into(),
+        b'B' => r.u8()?.into(),
+        b's' | b'U' => i16::from_be_bytes(r.array()?).into(),
+        b'u' => r.u16()?.into(),
+        b'I' => i32::from_be_bytes(r.array()?).into(),
+        b'i' => r.u32()?.into(),
+        b'l' | b'L' => i64::from_be_bytes(r.array()?).into(),
+        b'T' => u64::from_be_bytes(r.array()?).into(),
+        b'f' => (f32::from_be_bytes(r.array()?) as f64).into(),
+        b'd' => f64::from_be_bytes(r.array()?).into(),
+        b'D' => {
+            let scale = r.u8()?;
+            let value = i32::from_be_bytes(r.array()?);
+            (value as f64 / 10f64.powi(scale as i32)).into()
+        }
+        b'S' => String::from_utf8_lossy(r.longstr()?).into(),
+        b'x' => base64::encode(r.longstr()?).into(),
+        b'A' => {
+            let mut a = AmqpReader::new(r.longstr()?);
+            let mut values = Vec::new();
+            while !a.done() {
+                values.push(amqp_value(&mut a, depth + 1)?);
+            }
+            Value::Array(values)
+        }
+        b'F' => Value::Object(amqp_read_table(r.longstr()?, depth + 1)?),
+        b'V' => Value::Null,
+        _ => return None,
+    })
+}
+
+/* content of a field table (without its length), as JSON */
+fn amqp_read_table(data: &[u8], depth: usize) -> Option<Map<String, Value>> {
+    let mut r = AmqpReader::new(data);
+    let mut table = Map::new();
+    while !r.done() {
+        let name = String::from_utf8_lossy(r.shortstr()?).to_string();
+        table.insert(name, amqp_value(&mut r, depth)?);
+    }
+    Some(table)
+}
+
+fn amqp_start(config: &AmqpConfig) -> Vec<u8> {
+    let capabilities = [
+        "publisher_confirms",
+        "exchange_exchange_bindings",
+        "basic.nack",
+        "consumer_cancel_notify",
+        "connection.blocked",
+        "consumer_priorities",
+        "authentication_failure_close",
+        "per_consumer_qos",
+        "direct_reply_to",
+    ]
+    .iter()
+    .map(|c| (*c, AmqpValue::Bool(true)))
+    .collect();
+    /* version 0-9 */
+    let mut args = vec![0, 9];
+    amqp_table(
+        &mut args,
+        &[
+            ("capabilities", AmqpValue::Table(capabilities)),
+            ("cluster_name", AmqpValue::Str(&config.cluster_name)),
+            (
+                "copyright",
+                AmqpValue::Str("Copyright (c) 2007-2023 VMware, Inc. or its affiliates."),
+            ),
+            (
+                "information",
+                AmqpValue::Str("Licensed under the MPL 2.0. Website: https://rabbitmq.com"),
+            ),
+            ("platform", AmqpValue::Str(&config.platform)),
+            ("product", AmqpValue::Str(&config.product)),
+            ("version", AmqpValue::Str(&config.version)),
+        ],
+    );
+    amqp_longstr(&mut args, b"PLAIN AMQPLAIN");
+    amqp_longstr(&mut args, b"en_US");
+    amqp_method(AMQP_CONNECTION, AMQP_CONNECTION_START, &args)
+}
+
+fn amqp_close(code: u16, text: &str) -> Vec<u8> {
+    let mut args = code.to_be_bytes().to_vec();
+    amqp_shortstr(&mut args, text.as_bytes());
+    /* class and method that caused the close: none */
+    args.extend_from_slice(&[0, 0, 0, 0]);
+    amqp_method(AMQP_CONNECTION, AMQP_CONNECTION_CLOSE, &args)
+}
+
+/* credentials of a SASL response: PLAIN (authzid, user and password
+ * separated by NUL bytes) and AMQPLAIN (LOGIN and PASSWORD fields of a
+ * table, without its length) */
+fn amqp_credentials(mechanism: &[u8], response: &[u8]) -> Option<(String, String)> {
+    match mechanism {
+        b"PLAIN" => {
+            let mut parts = response.split(|c| *c == 0);
+            let (_, user, password) = (parts.next()?, parts.next()?, parts.next()?);
+            Some((
+                String::from_utf8_lossy(user).to_string(),
+                String::from_utf8_lossy(password).to_string(),
+            ))
+        }
+        b"AMQPLAIN" => {
+            let table = amqp_read_table(response, 0)?;
+            let get = |k: &str| table.get(k).and_then(|v| v.as_str()).map(|s| s.to_string());
+            Some((get("LOGIN")?, get("PASSWORD")?))
+        }
+        _ => None,
+    }
+}
+
+/* Connection.StartOk: client properties, mechanism, response, locale */
+fn amqp_start_ok(args: &[u8], client_info: &ClientInfo) -> Option<Event> {
+    let mut r = AmqpReader::new(args);
+    let properties = amqp_read_table(r.longstr()?, 0)?;
+    let mechanism = r.shortstr()?;
+    let response = r.longstr()?;
+    let locale = r.shortstr()?;
+    let mut event = Event::new("amqp_login", client_info);
+    event.set("client_properties", Value::Object(properties));
+    event.set("mechanism", String::from_utf8_lossy(mechanism));
+    event.set("locale", String::from_utf8_lossy(locale));
+    match amqp_credentials(mechanism, response) {
+        Some((user, password)) => {
+            warn!("AMQP login: {}/{}", user, password);
+            event.set("user", user);
+            event.set("password", password);
+        }
+        None => event.set("response", base64::encode(response)),
+    }
+    Some(event)
+}
+
+/* answer to a frame received after Connection.Start */
+fn amqp_answer(frame: &AmqpFrame, state: &mut AmqpState, client_info: &mut ClientInfo) -> Vec<u8> {
+    if frame.ftype == AMQP_FRAME_HEARTBEAT {
+        return Vec::new();
+    }
+    let method = match (frame.ftype, frame.payload.get(..4)) {
+        (AMQP_FRAME_METHOD, Some(m)) if frame.channel == 0 => (
+            u16::from_be_bytes([m[0], m[1]]),
+            u16::from_be_bytes([m[2], m[3]]),
+        ),
+        _ => {
+            info!("AMQP unexpected frame type {}", frame.ftype);
+            state.phase = AmqpPhase::Close;
+            return amqp_close(503, "COMMAND_INVALID - unexpected frame");
+        }
+    };
+    match (&state.phase, method) {
+        (AmqpPhase::Start, (AMQP_CONNECTION, AMQP_CONNECTION_START_OK)) => {
+            state.phase = AmqpPhase::Close;
+            match amqp_start_ok(&frame.payload[4..], client_info) {
+                Some(event) => {
+                    let mechanism = event.fields["mechanism"].as_str().unwrap_or("").to_string();
+                    event.log();
+                    amqp_close(
+                        403,
+                        &format!("ACCESS_REFUSED - Login was refused using authentication mechanism {}. For details see the broker logfile.", mechanism),
+                    )
+                }
+                None => {
+                    info!("AMQP invalid Connection.StartOk");
+                    amqp_close(502, "SYNTAX_ERROR - invalid Connection.StartOk")
+                }
+            }
+        }
+        (_, (AMQP_CONNECTION, AMQP_CONNECTION_CLOSE)) => {
+            client_info.close = true;
+            amqp_method(AMQP_CONNECTION, AMQP_CONNECTION_CLOSE_OK, &[])
+        }
+        (AmqpPhase::Close, (AMQP_CONNECTION, AMQP_CONNECTION_CLOSE_OK)) => {
+            client_info.close = true;
+            Vec::new()
+        }
+        (_, (class, method)) => {
+            info!("AMQP unexpected method {}.{}", class, method);
+            state.phase = AmqpPhase::Close;
+            amqp_close(
+                503,
+                &format!(
+                    "COMMAND_INVALID - expected 'connection.start_ok' method, got {}.{}",
+                    class, method
+                ),
+            )
+        }
+    }
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving AMQP data");
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Amqp(s)) => s,
+        _ => AmqpState::new(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    if state.phase == AmqpPhase::Header {
+        let header = &state.buffer[..std::cmp::min(state.buffer.len(), 8)];
+        if header.len() < 8 && AMQP_PROTOCOL_HEADER.starts_with(header) {
+            if let Some(t) = tcb {
+                t.proto_state = ProtoState::Amqp(state);
+            }
+            return None;
+        }
+        let mut event = Event::new("amqp_header", client_info);
+        event.set("header", base64::encode(header));
+        event.log();
+        if header != AMQP_PROTOCOL_HEADER {
+            /* AMQP 0-9-1 section 4.2.2: answer with the supported
+             * header, and close the connection */
+            info!("AMQP unsupported protocol header: {:?}", header);
+            client_info.close = true;
+            return Some(AMQP_PROTOCOL_HEADER.to_vec());
+        }
+        warn!("AMQP protocol header");
+        state.buffer.drain(..8);
+        state.phase = AmqpPhase::Start;
+        repl_data.extend(amqp_start(&masscanned.config.amqp));
+    }
+    while !client_info.close {
+        let (frame, len) = match amqp_parse_frame(&state.buffer) {
+            Ok(Some(f)) => f,
+            Ok(None) => break,
+            Err(e) => {
+                info!("AMQP frame error: {}", e);
+                repl_data.extend(amqp_close(501, &format!("FRAME_ERROR - {}", e)));
+                client_info.close = true;
+                break;
+            }
+        };
+        state.buffer.drain(..len);
+        repl_data.extend(amqp_answer(&frame, &mut state, client_info));
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Amqp(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending AMQP data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+
+    /* Connection.StartOk sent by pika 1.3.2 (guest/guest) */
+    const PIKA_START_OK: &[u8] =
+        b"\x01\x00\x00\x00\x00\x01#\x00\x0a\x00\x0b\x00\x00\x00\xff\x07productS\x00\x00\
+        \x00\x1aPika Python Client Library\x08platformS\x00\x00\x00\x0ePython 3.10.12\
+        \x0ccapabilitiesF\x00\x00\x00o\x1cauthentication_failure_closet\x01\x0abasic.nac\
+        kt\x01\x12connection.blockedt\x01\x16consumer_cancel_notifyt\x01\x12publisher_co\
+        nfirmst\x01\x0binformationS\x00\x00\x00\x18See http://pika.rtfd.org\x07versionS\
+        \x00\x00\x00\x051.3.2\x05PLAIN\x00\x00\x00\x0c\x00guest\x00guest\x05en_US\xce";
+
+    /* class, method and arguments of a method frame */
+    fn parse_method(data: &[u8]) -> (u16, u16, Vec<u8>, usize) {
+        let (frame, len) = amqp_parse_frame(data).unwrap().unwrap();
+        assert!(frame.ftype == AMQP_FRAME_METHOD && frame.channel == 0);
+        let p = frame.payload;
+        (
+            u16::from_be_bytes([p[0], p[1]]),
+            u16::from_be_bytes([p[2], p[3]]),
+            p[4..].to_vec(),
+            len,
+        )
+    }
+
+    #[test]
+    fn test_amqp_frame() {
+        let frame = amqp_frame(AMQP_FRAME_HEARTBEAT, 0, b"");
+        assert!(frame == b"\x08\x00\x00\x00\x00\x00\x00\xce");
+        let frame = amqp_frame(AMQP_FRAME_METHOD, 1, b"abc");
+        let (f, len) = amqp_parse_frame(&frame).unwrap().unwrap();
+        assert!(len == 11 && f.ftype == 1 && f.channel == 1 && f.payload == b"abc");
+        for i in 0..frame.len() {
+            assert!(amqp_parse_frame(&frame[..i]).unwrap().is_none());
+        }
+        /* invalid frame-end octet, absurd size */
+        assert!(amqp_parse_frame(b"\x08\x00\x00\x00\x00\x00\x00\x00").is_err());
+        assert!(amqp_parse_frame(b"\x01\x00\x00\xff\xff\xff\xff").is_err());
+    }
+
+    #[test]
+    fn test_amqp_table() {
+        let mut data = Vec::new();
+        amqp_table(
+            &mut data,
+            &[
+                ("a", AmqpValue::Bool(true)),
+                ("b", AmqpValue::Table(vec![("c", AmqpValue::Str("d"))])),
+            ],
+        );
+        assert!(data == b"\x00\x00\x00\x13\x01at\x01\x01bF\x00\x00\x00\x08\x01cS\x00\x00\x00\x01d");
+        let table = amqp_read_table(&data[4..], 0).unwrap();
+        assert!(Value::Object(table) == serde_json::json!({"a": true, "b": {"c": "d"}}));
+        /* other types */
+        let table = amqp_read_table(
+            b"\x01ib\xff\x01jI\xff\xff\xff\xfe\x01kl\x00\x00\x00\x00\x00\x00\x01\x00\
+            \x01lD\x02\x00\x00\x01\x39\x01mA\x00\x00\x00\x04U\x00\x07V\x01nx\x00\x00\x00\x01\xff",
+            0,
+        )
+        .unwrap();
+        assert!(
+            Value::Object(table)
+                == serde_json::json!({"i": -1, "j": -2, "k": 256, "l": 3.13, "m": [7, null], "n": "/w=="})
+        );
+        /* truncated, unknown type, too deep */
+        assert!(amqp_read_table(b"\x01aS\x00\x00\x00\x05abc", 0).is_none());
+        assert!(amqp_read_table(b"\x01aZ", 0).is_none());
+        let mut data = b"\x01aV".to_vec();
+        for _ in 0..20 {
+            let mut t = b"\x01aF".to_vec();
+            t.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            t.extend(data);
+            data = t;
+        }
+        assert!(amqp_read_table(&data, 0).is_none());
+    }
+
+    #[test]
+    fn test_amqp_start() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        /* as nmap amqp-info: the header, possibly split */
+        assert!(repl(b"AMQP\x00", &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        let repl_data = repl(
+            b"\x00\x09\x01",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        let (class, method, args, len) = parse_method(&repl_data);
+        assert!(len == repl_data.len());
+        assert!((class, method) == (AMQP_CONNECTION, AMQP_CONNECTION_START));
+        assert!(args[..2] == [0, 9]);
+        let mut r = AmqpReader::new(&args[2..]);
+        let properties = amqp_read_table(r.longstr().unwrap(), 0).unwrap();
+        assert!(properties["product"] == "RabbitMQ");
+        assert!(properties["version"] == "3.12.12");
+        assert!(properties["platform"] == "Erlang/OTP 25.3.2.8");
+        assert!(properties["capabilities"]["authentication_failure_close"] == true);
+        assert!(r.longstr().unwrap() == b"PLAIN AMQPLAIN");
+        assert!(r.longstr().unwrap() == b"en_US");
+        assert!(r.done());
+        /* as pika: the StartOk gets a Close */
+        let repl_data = repl(PIKA_START_OK, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let (class, method, args, _) = parse_method(&repl_data);
+        assert!((class, method) == (AMQP_CONNECTION, AMQP_CONNECTION_CLOSE));
+        assert!(args[..2] == 403u16.to_be_bytes());
+        assert!(AmqpReader::new(&args[2..]).shortstr().unwrap().starts_with(
+            b"ACCESS_REFUSED - Login was refused using authentication mechanism PLAIN."
+        ));
+        assert!(!client_info.close);
+        /* the CloseOk ends the connection */
+        let close_ok = amqp_method(AMQP_CONNECTION, AMQP_CONNECTION_CLOSE_OK, &[]);
+        assert!(repl(&close_ok, &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_amqp_login() {
+        let client_info = ClientInfo::new();
+        let event =
+            amqp_start_ok(&PIKA_START_OK[11..PIKA_START_OK.len() - 1], &client_info).unwrap();
+        assert!(event.fields["mechanism"] == "PLAIN");
+        assert!(event.fields["user"] == "guest");
+        assert!(event.fields["password"] == "guest");
+        assert!(event.fields["locale"] == "en_US");
+        assert!(event.fields["client_properties"]["product"] == "Pika Python Client Library");
+        assert!(event.fields["client_properties"]["capabilities"]["basic.nack"] == true);
+        /* AMQPLAIN */
+        assert!(
+            amqp_credentials(
+                b"AMQPLAIN",
+                b"\x05LOGINS\x00\x00\x00\x05admin\x08PASSWORDS\x00\x00\x00\x06s3cr3t"
+            ) == Some(("admin".to_string(), "s3cr3t".to_string()))
+        );
+        assert!(amqp_credentials(b"EXTERNAL", b"").is_none());
+    }
+
+    #[test]
+    fn test_amqp_header() {
+        let masscanned = masscanned(Config::default());
+        /* other versions (here, AMQP 1.0) get the supported header */
+        for header in [
+            &b"AMQP\x00\x01\x00\x00"[..],
+            b"AMQP\x01\x01\x00\x09",
+            b"GET / HTTP/1.1\r\n",
+        ]
+        .iter()
+        {
+            let mut client_info = ClientInfo::new();
+            let mut tcb = TCPControlBlock::new();
+            let repl_data = repl(header, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+            assert!(repl_data == AMQP_PROTOCOL_HEADER);
+            assert!(client_info.close);
+        }
+        /* unexpected methods get a Close */
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        let mut data = AMQP_PROTOCOL_HEADER.to_vec();
+        data.extend(amqp_method(20, 10, b"\x00"));
+        let repl_data = repl(&data, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let (_, len) = amqp_parse_frame(&repl_data).unwrap().unwrap();
+        let (class, method, args, _) = parse_method(&repl_data[len..]);
+        assert!((class, method) == (AMQP_CONNECTION, AMQP_CONNECTION_CLOSE));
+        assert!(args[..2] == 503u16.to_be_bytes());
+    }
+}
@@ -0,0 +1,625 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::Masscanned;
+
+/* BACnet Virtual Link Control (BACnet/IP, ASHRAE 135 annex J) */
+const BVLC_TYPE: u8 = 0x81;
+const BVLC_FORWARDED_NPDU: u8 = 0x04;
+const BVLC_ORIGINAL_UNICAST_NPDU: u8 = 0x0a;
+const BVLC_ORIGINAL_BROADCAST_NPDU: u8 = 0x0b;
+
+/* NPDU control: network layer message, destination and source
+ * specifiers */
+const NPDU_VERSION: u8 = 1;
+const NPDU_NETWORK_MESSAGE: u8 = 0x80;
+const NPDU_DNET: u8 = 0x20;
+const NPDU_SNET: u8 = 0x08;
+
+/* APDU types */
+const APDU_CONFIRMED_REQUEST: u8 = 0;
+const APDU_UNCONFIRMED_REQUEST: u8 = 1;
+const APDU_COMPLEX_ACK: u8 = 3;
+const APDU_ERROR: u8 = 5;
+const APDU_REJECT: u8 = 6;
+const APDU_ABORT: u8 = 7;
+
+/* segmented message flag of confirmed requests */
+const APDU_SEGMENTED: u8 = 0x08;
+
+/* services */
+const SERVICE_I_AM: u8 = 0;
+const SERVICE_WHO_IS: u8 = 8;
+const SERVICE_READ_PROPERTY: u8 = 12;
+
+/* application tags */
+const TAG_UNSIGNED: u8 = 2;
+const TAG_CHARACTER_STRING: u8 = 7;
+const TAG_ENUMERATED: u8 = 9;
+const TAG_OBJECT_IDENTIFIER: u8 = 12;
+
+const OBJECT_DEVICE: u32 = 8;
+/* instance of the device object targeted by requests to any device */
+const DEVICE_WILDCARD: u32 = 0x3fffff;
+
+/* properties */
+const PROP_APPLICATION_SOFTWARE_VERSION: u32 = 12;
+const PROP_DESCRIPTION: u32 = 28;
+const PROP_FIRMWARE_REVISION: u32 = 44;
+const PROP_LOCATION: u32 = 58;
+const PROP_MAX_APDU_LENGTH_ACCEPTED: u32 = 62;
+const PROP_MODEL_NAME: u32 = 70;
+const PROP_OBJECT_IDENTIFIER: u32 = 75;
+const PROP_OBJECT_NAME: u32 = 77;
+const PROP_OBJECT_TYPE: u32 = 79;
+const PROP_PROTOCOL_VERSION: u32 = 98;
+const PROP_SEGMENTATION_SUPPORTED: u32 = 107;
+const PROP_SYSTEM_STATUS: u32 = 112;
+const PROP_VENDOR_IDENTIFIER: u32 = 120;
+const PROP_VENDOR_NAME: u32 = 121;
+const PROP_PROTOCOL_REVISION: u32 = 139;
+
+/* segmentation supported: no segmentation */
+const NO_SEGMENTATION: u32 = 3;
+
+/* error classes and codes */
+const ERROR_CLASS_OBJECT: u32 = 1;
+const ERROR_CLASS_PROPERTY: u32 = 2;
+const ERROR_UNKNOWN_OBJECT: u32 = 31;
+const ERROR_UNKNOWN_PROPERTY: u32 = 32;
+const ERROR_PROPERTY_IS_NOT_AN_ARRAY: u32 = 50;
+
+/* reject and abort reasons */
+const REJECT_INVALID_TAG: u8 = 4;
+const REJECT_UNRECOGNIZED_SERVICE: u8 = 9;
+const ABORT_SEGMENTATION_NOT_SUPPORTED: u8 = 4;
+
+/* BACnet/IP device (on the UDP ports): Who-Is requests get an I-Am for
+ * the device object, ReadProperty requests for the device object the
+ * values of its properties, and other confirmed services a Reject.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BacnetConfig {
+    pub ports: Vec<u16>,
+    pub device_instance: u32,
+    pub vendor_id: u16,
+    pub max_apdu: u16,
+    pub object_name: String,
+    pub vendor_name: String,
+    pub model_name: String,
+    pub firmware_revision: String,
+    pub application_software_version: String,
+    pub description: String,
+    pub location: String,
+}
+
+impl Default for BacnetConfig {
+    fn default() -> Self {
+        BacnetConfig {
+            ports: vec![47808],
+            device_instance: 1001,
+            vendor_id: 24,
+            max_apdu: 1476,
+            object_name: "AHU-1 Controller".to_string(),
+            vendor_name: "Automated Logic Corporation".to_string(),
+            model_name: "LGR1000".to_string(),
+            firmware_revision: "6.00a".to_string(),
+            application_software_version: "PRG:ahu_1".to_string(),
+            description: "Air handling unit".to_string(),
+            location: "Mechanical room".to_string(),
+        }
+    }
+}
+
+impl BacnetConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        let err = |e: String| Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        if self.device_instance >= DEVICE_WILDCARD {
+            return err(format!(
+                "bacnet: invalid device instance: {}",
+                self.device_instance
+            ));
+        }
+        if self.max_apdu < 50 {
+            return err(format!("bacnet: invalid max APDU: {}", self.max_apdu));
+        }
+        Ok(())
+    }
+}
+
+/* tag (application or context specific, ASHRAE 135 section 20.2.1)
+ * and its value */
+fn bacnet_tag(number: u8, context: bool, value: &[u8]) -> Vec<u8> {
+    let class = if context { 0x08 } else { 0 };
+    let mut tag = if value.len() <= 4 {
+        vec![number << 4 | class | value.len() as u8]
+    } else if value.len() < 254 {
+        vec![number << 4 | class | 5, value.len() as u8]
+    } else {
+        let mut tag = vec![number << 4 | class | 5, 254];
+        tag.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        tag
+    };
+    tag.extend_from_slice(value);
+    tag
+}
+
+/* unsigned and enumerated values: as few octets as possible */
+fn bacnet_unsigned(value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = std::cmp::min(value.leading_zeros() as usize / 8, 3);
+    bytes[skip..].to_vec()
+}
+
+fn bacnet_object_id(object_type: u32, instance: u32) -> [u8; 4] {
+    (object_type << 22 | instance).to_be_bytes()
+}
+
+/* character string, ANSI X3.4 / UTF-8 (character set 0) */
+fn bacnet_string(s: &str) -> Vec<u8> {
+    let mut value = vec![0];
+    value.extend_from_slice(s.as_bytes());
+    bacnet_tag(TAG_CHARACTER_STRING, false, &value)
+}
+
+/* context tag (number, value) at the beginning of data, and its
+ * length: primitive values only */
+fn bacnet_parse_context_tag(data: &[u8]) -> Option<(u8, &[u8], usize)> {
+    let tag = *data.first()?;
+    if tag & 0x08 == 0 || tag >> 4 == 0x0f {
+        return None;
+    }
+    let (len, offset) = match tag & 0x07 {
+        5 => match *data.get(1)? {
+            254 => (
+                u16::from_be_bytes([*data.get(2)?, *data.get(3)?]) as usize,
+                4,
+            ),
+            255 => return None,
+            len => (len as usize, 2),
+        },
+        /* opening and closing tags */
+        6 | 7 => return None,
+        len => (len as usize, 1),
+    };
+    Some((tag >> 4, data.get(offset..offset + len)?, offset + len))
+}
+
+fn bacnet_decode_unsigned(value: &[u8]) -> Option<u32> {
+    if value.is_empty() || value.len() > 4 {
+        return None;
+    }
+    Some(value.iter().fold(0, |v, b| v << 8 | *b as u32))
+}
+
+/* context tags of a service request (number, value), in order */
+fn bacnet_context_tags(mut data: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut tags = Vec::new();
+    while !data.is_empty() {
+        let (number, value, len) = bacnet_parse_context_tag(data)?;
+        tags.push((number, value));
+        data = &data[len..];
+    }
+    Some(tags)
+}
+
+/* value of a property of the device object */
+fn bacnet_property(config: &BacnetConfig, property: u32) -> Option<Vec<u8>> {
+    Some(match property {
+        PROP_OBJECT_IDENTIFIER => bacnet_tag(
+            TAG_OBJECT_IDENTIFIER,
+            false,
+            &bacnet_object_id(OBJECT_DEVICE, config.device_instance),
+        ),
+        PROP_OBJECT_TYPE => bacnet_tag(TAG_ENUMERATED, false, &bacnet_unsigned(OBJECT_DEVICE)),
+        PROP_OBJECT_NAME => bacnet_string(&config.object_name),
+        PROP_VENDOR_NAME => bacnet_string(&config.vendor_name),
+        PROP_VENDOR_IDENTIFIER => bacnet_tag(
+            TAG_UNSIGNED,
+            false,
+            &bacnet_unsigned(config.vendor_id as u32),
+        ),
+        PROP_MODEL_NAME => bacnet_string(&config.model_name),
+        PROP_FIRMWARE_REVISION => bacnet_string(&config.firmware_revision),
+        PROP_APPLICATION_SOFTWARE_VERSION => bacnet_string(&config.application_software_version),
+        PROP_DESCRIPTION => bacnet_string(&config.description),
+        PROP_LOCATION => bacnet_string(&config.location),
+        PROP_MAX_APDU_LENGTH_ACCEPTED => bacnet_tag(
+            TAG_UNSIGNED,
+            false,
+            &bacnet_unsigned(config.max_apdu as u32),
+        ),
+        PROP_SEGMENTATION_SUPPORTED => {
+            bacnet_tag(TAG_ENUMERATED, false, &bacnet_unsigned(NO_SEGMENTATION))
+        }
+        /* operational */
+        PROP_SYSTEM_STATUS => bacnet_tag(TAG_ENUMERATED, false, &[0]),
+        PROP_PROTOCOL_VERSION => bacnet_tag(TAG_UNSIGNED, false, &[1]),
+        PROP_PROTOCOL_REVISION => bacnet_tag(TAG_UNSIGNED, false, &[14]),
+        _ => return None,
+    })
+}
+
+/* I-Am of the device object */
+fn bacnet_i_am(config: &BacnetConfig) -> Vec<u8> {
+    let mut apdu = vec![APDU_UNCONFIRMED_REQUEST << 4, SERVICE_I_AM];
+    apdu.extend(bacnet_tag(
+        TAG_OBJECT_IDENTIFIER,
+        false,
+        &bacnet_object_id(OBJECT_DEVICE, config.device_instance),
+    ));
+    apdu.extend(bacnet_tag(
+        TAG_UNSIGNED,
+        false,
+        &bacnet_unsigned(config.max_apdu as u32),
+    ));
+    apdu.extend(bacnet_tag(
+        TAG_ENUMERATED,
+        false,
+        &bacnet_unsigned(NO_SEGMENTATION),
+    ));
+    apdu.extend(bacnet_tag(
+        TAG_UNSIGNED,
+        false,
+        &bacnet_unsigned(config.vendor_id as u32),
+    ));
+    apdu
+}
+
+fn bacnet_error(invoke_id: u8, service: u8, class: u32, code: u32) -> Vec<u8> {
+    let mut apdu = vec![APDU_ERROR << 4, invoke_id, service];
+    apdu.extend(bacnet_tag(TAG_ENUMERATED, false, &bacnet_unsigned(class)));
+    apdu.extend(bacnet_tag(TAG_ENUMERATED, false, &bacnet_unsigned(code)));
+    apdu
+}
+
+/* answer to ReadProperty: the value (Complex-ACK), or an error */
+fn bacnet_read_property(
+    invoke_id: u8,
+    request: &[u8],
+    config: &BacnetConfig,
+    event: &mut Event,
+) -> Vec<u8> {
+    let reject = vec![APDU_REJECT << 4, invoke_id, REJECT_INVALID_TAG];
+    let (object, property, index) = match bacnet_context_tags(request).as_deref() {
+        Some([(0, object), (1, property), rest @ ..]) if object.len() == 4 && rest.len() <= 1 => {
+            let object = u32::from_be_bytes([object[0], object[1], object[2], object[3]]);
+            let index = match rest {
+                [(2, index)] => Some(bacnet_decode_unsigned(index)),
+                [] => None,
+                _ => return reject,
+            };
+            match (bacnet_decode_unsigned(property), index) {
+                (Some(property), None) => (object, property, None),
+                (Some(property), Some(Some(index))) => (object, property, Some(index)),
+                _ => return reject,
+            }
+        }
+        _ => return reject,
+    };
+    let (object_type, instance) = (object >> 22, object & DEVICE_WILDCARD);
+    event.set("object_type", object_type);
+    event.set("instance", instance);
+    event.set("property", property);
+    warn!(
+        "BACnet ReadProperty {} of object {}:{}",
+        property, object_type, instance
+    );
+    if object_type != OBJECT_DEVICE
+        || (instance != config.device_instance && instance != DEVICE_WILDCARD)
+    {
+        return bacnet_error(
+            invoke_id,
+            SERVICE_READ_PROPERTY,
+            ERROR_CLASS_OBJECT,
+            ERROR_UNKNOWN_OBJECT,
+        );
+    }
+    let value = match (bacnet_property(config, property), index) {
+        (Some(value), None) => value,
+        (Some(_), Some(_)) => {
+            return bacnet_error(
+                invoke_id,
+                SERVICE_READ_PROPERTY,
+                ERROR_CLASS_PROPERTY,
+                ERROR_PROPERTY_IS_NOT_AN_ARRAY,
+            )
+        }
+        (None, _) => {
+            return bacnet_error(
+                invoke_id,
+                SERVICE_READ_PROPERTY,
+                ERROR_CLASS_PROPERTY,
+                ERROR_UNKNOWN_PROPERTY,
+            )
+        }
+    };
+    /* object identifier (the actual one), property, value (between
+     * opening and closing tags 3) */
+    let mut apdu = vec![APDU_COMPLEX_ACK << 4, invoke_id, SERVICE_READ_PROPERTY];
+    apdu.extend(bacnet_tag(
+        0,
+        true,
+        &bacnet_object_id(OBJECT_DEVICE, config.device_instance),
+    ));
+    apdu.extend(bacnet_tag(1, true, &bacnet_unsigned(property)));
+    apdu.push(0x3e);
+    apdu.extend(value);
+    apdu.push(0x3f);
+    apdu
+}
+
+/* answer (APDU) to a request, None for those that get no answer */
+fn bacnet_answer(apdu: &[u8], config: &BacnetConfig, event: &mut Event) -> Option<Vec<u8>> {
+    match apdu.first()? >> 4 {
+        APDU_UNCONFIRMED_REQUEST => {
+            let service = *apdu.get(1)?;
+            event.set("service", service);
+            if service != SERVICE_WHO_IS {
+                return None;
+            }
+            /* device instance range limits */
+            let range = match bacnet_context_tags(&apdu[2..])?.as_slice() {
+                [] => None,
+                [(0, low), (1, high)] => {
+                    Some((bacnet_decode_unsigned(low)?, bacnet_decode_unsigned(high)?))
+                }
+                _ => return None,
+            };
+            warn!("BACnet Who-Is {:?}", range);
+            if let Some((low, high)) = range {
+                event.set("low_limit", low);
+                event.set("high_limit", high);
+                if !(low..=high).contains(&config.device_instance) {
+                    return None;
+                }
+            }
+            Some(bacnet_i_am(config))
+        }
+        APDU_CONFIRMED_REQUEST => {
+            let invoke_id = *apdu.get(2)?;
+            event.set("invoke_id", invoke_id);
+            if apdu[0] & APDU_SEGMENTED != 0 {
+                return Some(vec![
+                    APDU_ABORT << 4 | 1,
+                    invoke_id,
+                    ABORT_SEGMENTATION_NOT_SUPPORTED,
+                ]);
+            }
+            let service = *apdu.get(3)?;
+            event.set("service", service);
+            if service == SERVICE_READ_PROPERTY {
+                Some(bacnet_read_property(invoke_id, &apdu[4..], config, event))
+            } else {
+                info!("BACnet confirmed service not handled: {}", service);
+                Some(vec![
+                    APDU_REJECT << 4,
+                    invoke_id,
+                    REJECT_UNRECOGNIZED_SERVICE,
+                ])
+            }
+        }
+        _ => None,
+    }
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving BACnet data");
+    let config = &masscanned.config.bacnet;
+    /* BVLC header (the address of the originating device is
+     * inserted in forwarded NPDUs) */
+    let (function, npdu) = match data {
+        [BVLC_TYPE, function, l0, l1, rest @ ..]
+            if u16::from_be_bytes([*l0, *l1]) as usize == data.len() =>
+        {
+            match *function {
+                BVLC_ORIGINAL_UNICAST_NPDU | BVLC_ORIGINAL_BROADCAST_NPDU => (*function, rest),
+                BVLC_FORWARDED_NPDU if rest.len() >= 6 => (*function, &rest[6..]),
+                _ => {
+                    info!("BACnet BVLC function not handled: {}", function);
+                    return None;
+                }
+            }
+        }
+        _ => {
+            info!("BACnet data not handled (invalid BVLC header)");
+            return None;
+        }
+    };
+    /* NPDU: version, control, destination and source specifiers (the
+     * source one is the destination of the answer) */
+    let control = *npdu.get(1)?;
+    if npdu[0] != NPDU_VERSION {
+        info!("BACnet data not handled (invalid NPDU)");
+        return None;
+    }
+    let mut offset = 2;
+    if control & NPDU_DNET != 0 {
+        offset += 3 + *npdu.get(offset + 2)? as usize;
+    }
+    let source = if control & NPDU_SNET != 0 {
+        let len = *npdu.get(offset + 2)? as usize;
+        let source = npdu.get(offset..offset + 3 + len)?;
+        offset += 3 + len;
+        Some(source)
+    } else {
+        None
+    };
+    if control & NPDU_DNET != 0 {
+        /* hop count */
+        offset += 1;
+    }
+    let mut event = Event::new("bacnet_request", client_info);
+    event.set("bvlc_function", function);
+    if control & NPDU_NETWORK_MESSAGE != 0 {
+        event.set("network_message", *npdu.get(offset)?);
+        event.log();
+        return None;
+    }
+    let apdu = npdu.get(offset..)?;
+    let answer = bacnet_answer(apdu, config, &mut event);
+    event.log();
+    let answer = answer?;
+    let mut npdu = vec![NPDU_VERSION];
+    match source {
+        Some(source) => {
+            npdu.push(NPDU_DNET);
+            npdu.extend_from_slice(source);
+            npdu.push(0xff);
+        }
+        None => npdu.push(0),
+    }
+    npdu.extend(answer);
+    let mut repl_data = vec![BVLC_TYPE, BVLC_ORIGINAL_UNICAST_NPDU];
+    repl_data.extend_from_slice(&((4 + npdu.len()) as u16).to_be_bytes());
+    repl_data.extend(npdu);
+    debug!("sending BACnet data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::{client_info, masscanned, SERVER_IP};
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    /* global broadcast Who-Is (e.g., bacnet-discover-enumerate) */
+    const WHO_IS: &[u8] = b"\x81\x0b\x00\x0c\x01\x20\xff\xff\x00\xff\x10\x08";
+    /* nmap bacnet-info: ReadProperty of the device 4194303 (object-name) */
+    const NMAP_OBJECT_NAME: &[u8] =
+        b"\x81\x0a\x00\x11\x01\x04\x00\x05\x01\x0c\x0c\x02\x3f\xff\xff\x19\x4d";
+
+    /* ReadProperty (invoke id 1) of a property of an object */
+    fn read_property(object: &[u8], property: u8) -> Vec<u8> {
+        let mut data = b"\x81\x0a\x00\x00\x01\x04\x00\x05\x01\x0c\x0c".to_vec();
+        data.extend_from_slice(object);
+        data.extend_from_slice(&[0x19, property]);
+        data[3] = data.len() as u8;
+        data
+    }
+
+    #[test]
+    fn test_bacnet_tags() {
+        assert!(bacnet_unsigned(0) == [0]);
+        assert!(bacnet_unsigned(24) == [24]);
+        assert!(bacnet_unsigned(1476) == [0x05, 0xc4]);
+        assert!(bacnet_unsigned(0x01000000) == [1, 0, 0, 0]);
+        /* length in the tag, then extended lengths */
+        assert!(bacnet_tag(TAG_UNSIGNED, false, &[0x05, 0xc4]) == [0x22, 0x05, 0xc4]);
+        assert!(bacnet_tag(1, true, &[0x4d]) == [0x19, 0x4d]);
+        assert!(bacnet_string("LGR1000") == b"\x75\x08\x00LGR1000");
+        let long = "a".repeat(300);
+        assert!(bacnet_string(&long)[..4] == [0x75, 254, 0x01, 0x2d]);
+        assert!(bacnet_object_id(OBJECT_DEVICE, DEVICE_WILDCARD) == [0x02, 0x3f, 0xff, 0xff]);
+        assert!(bacnet_parse_context_tag(&[0x19, 0x4d]) == Some((1, &[0x4d][..], 2)));
+        assert!(bacnet_parse_context_tag(&[0x0d, 0x02, 1, 2]) == Some((0, &[1, 2][..], 4)));
+        /* application tags, opening tags, truncated */
+        assert!(bacnet_parse_context_tag(&[0x21, 0x18]).is_none());
+        assert!(bacnet_parse_context_tag(&[0x3e]).is_none());
+        assert!(bacnet_parse_context_tag(&[0x1a, 0x4d]).is_none());
+    }
+
+    #[test]
+    fn test_bacnet_who_is() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 47808);
+        let repl_data = crate::proto::dispatch(WHO_IS, &masscanned, &mut client_info, None)
+            .expect("no answer to Who-Is");
+        /* I-Am: device 1001, max APDU 1476, no segmentation, vendor 24 */
+        assert!(
+            repl_data
+                == b"\x81\x0a\x00\x14\x01\x00\x10\x00\xc4\x02\x00\x03\xe9\x22\x05\xc4\x91\x03\x21\x18"
+        );
+        /* device instance ranges */
+        assert!(repl(
+            b"\x81\x0b\x00\x0c\x01\x00\x10\x08\x09\x00\x19\x0a",
+            &masscanned,
+            &mut client_info
+        )
+        .is_none());
+        assert!(repl(
+            b"\x81\x0a\x00\x0e\x01\x00\x10\x08\x0a\x03\xe8\x1a\x03\xe9",
+            &masscanned,
+            &mut client_info
+        )
+        .is_some());
+        /* I-Am from other devices get no answer */
+        assert!(repl(&repl_data, &masscanned, &mut client_info).is_none());
+        /* invalid BVLC length */
+        assert!(repl(&WHO_IS[..11], &masscanned, &mut client_info).is_none());
+    }
+
+    #[test]
+    fn test_bacnet_read_property() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 47808);
+        let repl_data =
+            crate::proto::dispatch(NMAP_OBJECT_NAME, &masscanned, &mut client_info, None)
+                .expect("no answer to ReadProperty");
+        /* Complex-ACK: actual object identifier, property, value */
+        assert!(
+            repl_data
+                == b"\x81\x0a\x00\x25\x01\x00\x30\x01\x0c\x0c\x02\x00\x03\xe9\x19\x4d\x3e\x75\x11\x00AHU-1 Controller\x3f"
+        );
+        /* vendor identifier */
+        let repl_data = repl(
+            &read_property(b"\x02\x00\x03\xe9", 0x78),
+            &masscanned,
+            &mut client_info,
+        )
+        .unwrap();
+        assert!(repl_data[repl_data.len() - 4..] == [0x3e, 0x21, 0x18, 0x3f]);
+        /* unknown property, unknown object */
+        let repl_data = repl(
+            &read_property(b"\x02\x3f\xff\xff", 0x55),
+            &masscanned,
+            &mut client_info,
+        )
+        .unwrap();
+        assert!(repl_data[6..] == [0x50, 0x01, 0x0c, 0x91, 0x02, 0x91, 0x20]);
+        let repl_data = repl(
+            &read_property(b"\x00\x00\x00\x01", 0x4d),
+            &masscanned,
+            &mut client_info,
+        )
+        .unwrap();
+        assert!(repl_data[6..] == [0x50, 0x01, 0x0c, 0x91, 0x01, 0x91, 0x1f]);
+        /* other services (here, WriteProperty) are rejected */
+        let mut write = read_property(b"\x02\x00\x03\xe9", 0x4d);
+        write[9] = 0x0f;
+        let repl_data = repl(&write, &masscanned, &mut client_info).unwrap();
+        assert!(repl_data[6..] == [0x60, 0x01, 0x09]);
+        /* from a remote network: the answer is routed back */
+        let repl_data = repl(
+            b"\x81\x0a\x00\x15\x01\x0c\x00\x05\x01\x07\x00\x05\x01\x0c\x0c\x02\x3f\xff\xff\x19\x2c",
+            &masscanned,
+            &mut client_info,
+        )
+        .unwrap();
+        assert!(repl_data[4..11] == [0x01, 0x20, 0x00, 0x05, 0x01, 0x07, 0xff]);
+        assert!(repl_data[11..14] == [0x30, 0x01, 0x0c]);
+        assert!(repl_data.ends_with(b"\x75\x06\x006.00a\x3f"));
+    }
+}
@@ -0,0 +1,235 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+/* Minimal bencode (BEP 3) reader and writer, for the BitTorrent
+ * protocols (e.g., the DHT): integers are signed 64-bit, and the
+ * nesting depth is bounded.
+ **/
+
+use std::collections::BTreeMap;
+
+/* maximum nesting of lists and dictionaries */
+const BENCODE_MAX_DEPTH: usize = 16;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Bencode {
+    Integer(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Bencode>),
+    /* keys are written sorted */
+    Dict(BTreeMap<Vec<u8>, Bencode>),
+}
+
+impl Bencode {
+    pub fn get(&self, key: &[u8]) -> Option<&Bencode> {
+        match self {
+            Bencode::Dict(d) => d.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Bencode::Bytes(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn integer(&self) -> Option<i64> {
+        match self {
+            Bencode::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Bencode::Integer(i) => out.extend_from_slice(format!("i{}e", i).as_bytes()),
+            Bencode::Bytes(b) => {
+                out.extend_from_slice(format!("{}:", b.len()).as_bytes());
+                out.extend_from_slice(b);
+            }
+            Bencode::List(l) => {
+                out.push(b'l');
+                for v in l {
+                    v.write(out);
+                }
+                out.push(b'e');
+            }
+            Bencode::Dict(d) => {
+                out.push(b'd');
+                for (k, v) in d {
+                    Bencode::Bytes(k.clone()).write(out);
+                    v.write(out);
+                }
+                out.push(b'e');
+            }
+        }
+    }
+}
+
+/* dictionary from (key, value) pairs */
+pub fn bencode_dict(items: Vec<(&[u8], Bencode)>) -> Bencode {
+    Bencode::Dict(items.into_iter().map(|(k, v)| (k.to_vec(), v)).collect())
+}
+
+pub fn bencode_bytes(b: &[u8]) -> Bencode {
+    Bencode::Bytes(b.to_vec())
+}
+
+/* decimal number up to the terminator, without leading zeros */
+fn bencode_number(data: &[u8], end: u8) -> Option<(i64, usize)> {
+    let len = data.iter().position(|b| *b == end)?;
+    let digits = std::str::from_utf8(&data[..len]).ok()?;
+    let unsigned = digits.strip_prefix('-').unwrap_or(digits);
+    if unsigned.is_empty()
+        || !unsigned.bytes().all(|b| b.is_ascii_digit())
+        || (unsigned.len() > 1 && unsigned.starts_with('0'))
+        || digits == "-0"
+    {
+        return None;
+    }
+    Some((digits.parse().ok()?, len + 1))
+}
+
+fn bencode_read(data: &[u8], depth: usize) -> Option<(Bencode, usize)> {
+    if depth > BENCODE_MAX_DEPTH {
+        return None;
+    }
+    match *data.first()? {
+        b'i' => {
+            let (i, len) = bencode_number(&data[1..], b'e')?;
+            Some((Bencode::Integer(i), 1 + len))
+        }
+        b'0'..=b'9' => {
+            let (n, len) = bencode_number(data, b':')?;
+            if n < 0 {
+                return None;
+            }
+            let end = len.checked_add(n as usize)?;
+            Some((Bencode::Bytes(data.get(len..end)?.to_vec()), end))
+        }
+        b'l' => {
+            let mut list = Vec::new();
+            let mut offset = 1;
+            while *data.get(offset)? != b'e' {
+                let (v, len) = bencode_read(&data[offset..], depth + 1)?;
+                list.push(v);
+                offset += len;
+            }
+            Some((Bencode::List(list), offset + 1))
+        }
+        b'd' => {
+            let mut dict = BTreeMap::new();
+            let mut offset = 1;
+            while *data.get(offset)? != b'e' {
+                let (k, len) = bencode_read(&data[offset..], depth + 1)?;
+                offset += len;
+                let k = match k {
+                    Bencode::Bytes(k) => k,
+                    _ => return None,
+                };
+                let (v, len) = bencode_read(&data[offset..], depth + 1)?;
+                offset += len;
+                dict.insert(k, v);
+            }
+            Some((Bencode::Dict(dict), offset + 1))
+        }
+        _ => None,
+    }
+}
+
+/* the whole of data must be one value */
+pub fn bencode_decode(data: &[u8]) -> Option<Bencode> {
+    match bencode_read(data, 0)? {
+        (v, len) if len == data.len() => Some(v),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bencode() {
+        /* BEP 3 examples */
+        for (data, value) in [
+            (&b"4:spam"[..], bencode_bytes(b"spam")),
+            (b"0:", bencode_bytes(b"")),
+            (b"i3e", Bencode::Integer(3)),
+            (b"i-3e", Bencode::Integer(-3)),
+            (b"i0e", Bencode::Integer(0)),
+            (
+                b"l4:spam4:eggse",
+                Bencode::List(vec![bencode_bytes(b"spam"), bencode_bytes(b"eggs")]),
+            ),
+            (
+                b"d3:cow3:moo4:spam4:eggse",
+                bencode_dict(vec![
+                    (b"spam", bencode_bytes(b"eggs")),
+                    (b"cow", bencode_bytes(b"moo")),
+                ]),
+            ),
+            (
+                b"d4:spaml1:a1:bee",
+                bencode_dict(vec![(
+                    b"spam",
+                    Bencode::List(vec![bencode_bytes(b"a"), bencode_bytes(b"b")]),
+                )]),
+            ),
+        ]
+        .iter()
+        {
+            assert!(bencode_decode(data).as_ref() == Some(value));
+            assert!(value.encode() == *data);
+        }
+        let d = bencode_decode(b"d1:ai42e1:b3:xyze").unwrap();
+        assert!(d.get(b"a").and_then(|v| v.integer()) == Some(42));
+        assert!(d.get(b"b").and_then(|v| v.bytes()) == Some(&b"xyz"[..]));
+        assert!(d.get(b"c").is_none());
+        /* invalid */
+        for data in [
+            &b"i-0e"[..],
+            b"i03e",
+            b"ie",
+            b"i1",
+            b"5:spam",
+            b"l4:spam",
+            b"di1e1:ae",
+            b"4:spamx",
+            b"-1:",
+            b"99999999999999999999:",
+            b"x",
+            b"",
+        ]
+        .iter()
+        {
+            assert!(bencode_decode(data).is_none());
+        }
+        /* nesting */
+        let deep = [vec![b'l'; 16], vec![b'e'; 16]].concat();
+        assert!(bencode_decode(&deep).is_some());
+        let deep = [vec![b'l'; 100], vec![b'e'; 100]].concat();
+        assert!(bencode_decode(&deep).is_none());
+    }
+}
@@ -0,0 +1,294 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+/* Minimal BER (X.690) reader and writer, for the ASN.1 protocols
+ * (e.g., SNMP): definite lengths only, and every length is checked
+ * against the data available.
+ **/
+
+pub const BER_BOOLEAN: u8 = 0x01;
+pub const BER_INTEGER: u8 = 0x02;
+pub const BER_OCTET_STRING: u8 = 0x04;
+pub const BER_NULL: u8 = 0x05;
+pub const BER_OID: u8 = 0x06;
+pub const BER_ENUMERATED: u8 = 0x0a;
+pub const BER_SEQUENCE: u8 = 0x30;
+pub const BER_SET: u8 = 0x31;
+
+/* TLV reader over a buffer */
+pub struct BerReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> BerReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BerReader { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /* number of bytes not read yet */
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    /* next TLV: tag (single byte tags only) and value */
+    pub fn read(&mut self) -> Option<(u8, &'a [u8])> {
+        let tag = *self.data.first()?;
+        /* high-tag-number form */
+        if tag & 0x1f == 0x1f {
+            return None;
+        }
+        let first = *self.data.get(1)? as usize;
+        let (len, offset) = if first & 0x80 == 0 {
+            (first, 2)
+        } else {
+            /* long form, up to 4 bytes (0x80 is the indefinite form) */
+            let n = first & 0x7f;
+            if n == 0 || n > 4 {
+                return None;
+            }
+            let mut len = 0usize;
+            for b in self.data.get(2..2 + n)? {
+                len = (len << 8) | *b as usize;
+            }
+            (len, 2 + n)
+        };
+        let end = offset.checked_add(len)?;
+        let value = self.data.get(offset..end)?;
+        self.data = &self.data[end..];
+        Some((tag, value))
+    }
+
+    /* next TLV, which must have the given tag */
+    pub fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        match self.read()? {
+            (t, value) if t == tag => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn integer(&mut self) -> Option<i64> {
+        ber_decode_integer(self.expect(BER_INTEGER)?)
+    }
+}
+
+pub fn ber_decode_integer(value: &[u8]) -> Option<i64> {
+    if value.is_empty() || value.len() > 8 {
+        return None;
+    }
+    let mut n: i64 = if value[0] & 0x80 != 0 { -1 } else { 0 };
+    for b in value {
+        n = (n << 8) | *b as i64;
+    }
+    Some(n)
+}
+
+pub fn ber_decode_oid(value: &[u8]) -> Option<Vec<u32>> {
+    let mut arcs = Vec::new();
+    let mut n: u32 = 0;
+    for (i, b) in value.iter().enumerate() {
+        /* no leading 0x80 (non-minimal encoding), no overflow */
+        if (n == 0 && *b == 0x80) || n > (u32::MAX >> 7) {
+            return None;
+        }
+        n = (n << 7) | (b & 0x7f) as u32;
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = std::cmp::min(n / 40, 2);
+                arcs.push(first);
+                arcs.push(n - 40 * first);
+            } else {
+                arcs.push(n);
+            }
+            n = 0;
+        } else if i == value.len() - 1 {
+            return None;
+        }
+    }
+    if arcs.is_empty() {
+        return None;
+    }
+    Some(arcs)
+}
+
+/* "1.3.6.1.2.1.1.1.0" */
+pub fn ber_parse_oid(oid: &str) -> Option<Vec<u32>> {
+    let arcs = oid
+        .trim_start_matches('.')
+        .split('.')
+        .map(|a| a.parse().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    if arcs.len() < 2 || arcs[0] > 2 || (arcs[0] < 2 && arcs[1] >= 40) {
+        return None;
+    }
+    Some(arcs)
+}
+
+pub fn ber_oid_string(oid: &[u32]) -> String {
+    oid.iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+pub fn ber_tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut tlv = vec![tag];
+    let len = value.len();
+    if len < 0x80 {
+        tlv.push(len as u8);
+    } else {
+        let bytes = (len as u32).to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        tlv.push(0x80 | (4 - skip) as u8);
+        tlv.extend_from_slice(&bytes[skip..]);
+    }
+    tlv.extend_from_slice(value);
+    tlv
+}
+
+/* minimal two's complement encoding */
+pub fn ber_integer_value(n: i64) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    let mut skip = 0;
+    while skip < 7 {
+        let (b, next) = (bytes[skip], bytes[skip + 1]);
+        if (b == 0 && next & 0x80 == 0) || (b == 0xff && next & 0x80 != 0) {
+            skip += 1;
+        } else {
+            break;
+        }
+    }
+    bytes[skip..].to_vec()
+}
+
+pub fn ber_integer(n: i64) -> Vec<u8> {
+    ber_tlv(BER_INTEGER, &ber_integer_value(n))
+}
+
+pub fn ber_oid(oid: &[u32]) -> Vec<u8> {
+    let mut value = Vec::new();
+    let mut arcs = vec![oid[0] * 40 + oid.get(1).copied().unwrap_or(0)];
+    arcs.extend_from_slice(oid.get(2..).unwrap_or(&[]));
+    for arc in arcs {
+        let mut groups = vec![(arc & 0x7f) as u8];
+        let mut a = arc >> 7;
+        while a != 0 {
+            groups.push(0x80 | (a & 0x7f) as u8);
+            a >>= 7;
+        }
+        groups.reverse();
+        value.extend(groups);
+    }
+    ber_tlv(BER_OID, &value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ber_reader() {
+        let data = b"\x30\x06\x02\x01\x05\x04\x01a\x05\x00";
+        let mut reader = BerReader::new(data);
+        let seq = reader.expect(BER_SEQUENCE).unwrap();
+        assert!(reader.read() == Some((BER_NULL, &b""[..])));
+        assert!(reader.is_empty());
+        let mut inner = BerReader::new(seq);
+        assert!(inner.integer() == Some(5));
+        assert!(inner.expect(BER_OCTET_STRING) == Some(&b"a"[..]));
+        assert!(inner.read().is_none());
+        /* long form */
+        let mut long = vec![0x04, 0x82, 0x01, 0x00];
+        long.extend(vec![b'x'; 256]);
+        assert!(
+            BerReader::new(&long)
+                .expect(BER_OCTET_STRING)
+                .unwrap()
+                .len()
+                == 256
+        );
+        assert!(ber_tlv(BER_OCTET_STRING, &vec![b'x'; 256]) == long);
+        /* truncated, indefinite, too long, oversized lengths */
+        for i in 0..long.len() {
+            assert!(BerReader::new(&long[..i]).read().is_none());
+        }
+        for bad in [
+            &b"\x30\x80\x05\x00\x00\x00"[..],
+            b"\x04\x85\x00\x00\x00\x00\x01a",
+            b"\x04\x84\xff\xff\xff\xff",
+            b"\x04\x84\x7f\xff\xff\xff",
+            b"\x1f\x01\x00",
+            b"\x04",
+        ]
+        .iter()
+        {
+            assert!(BerReader::new(bad).read().is_none());
+        }
+        /* wrong tag */
+        assert!(BerReader::new(b"\x02\x01\x00")
+            .expect(BER_SEQUENCE)
+            .is_none());
+    }
+
+    #[test]
+    fn test_ber_integer() {
+        for (n, encoded) in [
+            (0, &b"\x00"[..]),
+            (127, b"\x7f"),
+            (128, b"\x00\x80"),
+            (256, b"\x01\x00"),
+            (-1, b"\xff"),
+            (-128, b"\x80"),
+            (-129, b"\xff\x7f"),
+            (0x12345678, b"\x12\x34\x56\x78"),
+        ]
+        .iter()
+        {
+            assert!(ber_integer_value(*n) == *encoded);
+            assert!(ber_decode_integer(encoded) == Some(*n));
+        }
+        assert!(ber_decode_integer(b"").is_none());
+        assert!(ber_decode_integer(&[1; 9]).is_none());
+    }
+
+    #[test]
+    fn test_ber_oid() {
+        let oid = ber_parse_oid("1.3.6.1.4.1.8072.3.2.10").unwrap();
+        let encoded = ber_oid(&oid);
+        assert!(encoded == b"\x06\x0a\x2b\x06\x01\x04\x01\xbf\x08\x03\x02\x0a");
+        assert!(ber_decode_oid(&encoded[2..]) == Some(oid.clone()));
+        assert!(ber_oid_string(&oid) == "1.3.6.1.4.1.8072.3.2.10");
+        assert!(ber_parse_oid(".1.3.6").is_some());
+        assert!(ber_decode_oid(b"\x88\x37\x03") == Some(vec![2, 999, 3]));
+        for bad in ["", "1", "3.1", "1.40", "1.3.a", "1..3"].iter() {
+            assert!(ber_parse_oid(bad).is_none());
+        }
+        /* truncated arc, non-minimal arc, overflow */
+        for bad in [
+            &b"\x2b\x86"[..],
+            b"\x2b\x80\x01",
+            b"\x2b\x90\xff\xff\xff\x7f",
+            b"",
+        ]
+        .iter()
+        {
+            assert!(ber_decode_oid(bad).is_none());
+        }
+    }
+}
@@ -0,0 +1,571 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::net::{IpAddr, Ipv6Addr};
+
+use chrono::Utc;
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* Bitcoin P2P messages: network magic, command (NUL-padded), payload
+ * length and checksum (first bytes of the double SHA-256 of the
+ * payload), then the payload */
+const BTC_HEADER_LEN: usize = 24;
+const BTC_COMMAND_LEN: usize = 12;
+/* maximum size of a payload kept between two segments */
+const BTC_MAX_PAYLOAD: usize = 1 << 20;
+
+/* version messages on other ports (mainnet and testnet) */
+pub const BTC_PATTERNS_VERSION: [&[u8; 16]; 2] = [
+    b"\xf9\xbe\xb4\xd9version\x00\x00\x00\x00\x00",
+    b"\x0b\x11\x09\x07version\x00\x00\x00\x00\x00",
+];
+
+const BTC_NETWORKS: [(&str, [u8; 4]); 4] = [
+    ("mainnet", [0xf9, 0xbe, 0xb4, 0xd9]),
+    ("testnet", [0x0b, 0x11, 0x09, 0x07]),
+    ("signet", [0x0a, 0x03, 0xcf, 0x40]),
+    ("regtest", [0xfa, 0xbf, 0xb5, 0xda]),
+];
+
+/* Bitcoin node (on the configured TCP ports, and on other ports for
+ * version messages): version messages of the network get our version
+ * (user_agent, height, services) and a verack, pings a pong and
+ * getaddr an empty addr; every message is recorded.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BitcoinConfig {
+    pub ports: Vec<u16>,
+    /* mainnet, testnet, signet or regtest */
+    pub network: String,
+    pub protocol_version: i32,
+    pub services: u64,
+    pub user_agent: String,
+    pub height: i32,
+}
+
+impl Default for BitcoinConfig {
+    fn default() -> Self {
+        BitcoinConfig {
+            ports: vec![8333],
+            network: "mainnet".to_string(),
+            protocol_version: 70016,
+            /* NODE_NETWORK, NODE_WITNESS, NODE_NETWORK_LIMITED */
+            services: 0x0409,
+            user_agent: "/Satoshi:25.0.0/".to_string(),
+            height: 815000,
+        }
+    }
+}
+
+impl BitcoinConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if self.magic().is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bitcoin: unknown network {:?}", self.network),
+            ));
+        }
+        Ok(())
+    }
+
+    fn magic(&self) -> Option<[u8; 4]> {
+        BTC_NETWORKS
+            .iter()
+            .find(|(n, _)| *n == self.network)
+            .map(|(_, m)| *m)
+    }
+}
+
+/* state of a flow: beginning of a message not received entirely yet */
+#[derive(Default)]
+pub struct BitcoinState {
+    buffer: Vec<u8>,
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/* FIPS 180-4 */
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for block in msg.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, c) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([c[0], c[1], c[2], c[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let mut v = h;
+        for (k, w) in SHA256_K.iter().zip(w.iter()) {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(*k)
+                .wrapping_add(*w);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            v.rotate_right(1);
+            v[4] = v[4].wrapping_add(t1);
+            v[0] = t1.wrapping_add(s0).wrapping_add(maj);
+        }
+        for (h, v) in h.iter_mut().zip(v.iter()) {
+            *h = h.wrapping_add(*v);
+        }
+    }
+    let mut digest = [0u8; 32];
+    for (d, h) in digest.chunks_mut(4).zip(h.iter()) {
+        d.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+fn btc_checksum(payload: &[u8]) -> [u8; 4] {
+    let h = sha256(&sha256(payload));
+    [h[0], h[1], h[2], h[3]]
+}
+
+/* CompactSize integers */
+pub fn var_int(n: u64) -> Vec<u8> {
+    match n {
+        0..=0xfc => vec![n as u8],
+        0xfd..=0xffff => {
+            let mut v = vec![0xfd];
+            v.extend_from_slice(&(n as u16).to_le_bytes());
+            v
+        }
+        0x10000..=0xffffffff => {
+            let mut v = vec![0xfe];
+            v.extend_from_slice(&(n as u32).to_le_bytes());
+            v
+        }
+        _ => {
+            let mut v = vec![0xff];
+            v.extend_from_slice(&n.to_le_bytes());
+            v
+        }
+    }
+}
+
+/* value and number of bytes used */
+pub fn parse_var_int(data: &[u8]) -> Option<(u64, usize)> {
+    let (len, n) = match *data.first()? {
+        0xfd => (3, u16::from_le_bytes([*data.get(1)?, *data.get(2)?]) as u64),
+        0xfe => (5, le_u64(data.get(1..5)?)),
+        0xff => (9, le_u64(data.get(1..9)?)),
+        n => (1, n as u64),
+    };
+    Some((n, len))
+}
+
+pub fn var_str(s: &str) -> Vec<u8> {
+    let mut v = var_int(s.len() as u64);
+    v.extend_from_slice(s.as_bytes());
+    v
+}
+
+pub fn parse_var_str(data: &[u8]) -> Option<(String, usize)> {
+    let (n, len) = parse_var_int(data)?;
+    if n > (data.len() - len) as u64 {
+        return None;
+    }
+    let end = len + n as usize;
+    let s = &data[len..end];
+    Some((String::from_utf8_lossy(s).to_string(), end))
+}
+
+/* little-endian, up to 8 bytes */
+fn le_u64(data: &[u8]) -> u64 {
+    data.iter().rev().fold(0u64, |n, b| (n << 8) | *b as u64)
+}
+
+fn btc_message(magic: &[u8; 4], command: &str, payload: &[u8]) -> Vec<u8> {
+    let mut message = magic.to_vec();
+    let mut c = command.as_bytes().to_vec();
+    c.resize(BTC_COMMAND_LEN, 0);
+    message.extend(c);
+    message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    message.extend_from_slice(&btc_checksum(payload));
+    message.extend_from_slice(payload);
+    message
+}
+
+/* network address (without time): services, IPv6 (or IPv4-mapped)
+ * address and port (big-endian) */
+fn btc_net_addr(services: u64, ip: Option<IpAddr>, port: Option<u16>) -> Vec<u8> {
+    let mut addr = services.to_le_bytes().to_vec();
+    let ip = match ip {
+        Some(IpAddr::V4(ip)) => ip.to_ipv6_mapped(),
+        Some(IpAddr::V6(ip)) => ip,
+        None => Ipv6Addr::UNSPECIFIED,
+    };
+    addr.extend_from_slice(&ip.octets());
+    addr.extend_from_slice(&port.unwrap_or(0).to_be_bytes());
+    addr
+}
+
+fn btc_version(config: &BitcoinConfig, client_info: &ClientInfo) -> Vec<u8> {
+    let mut payload = config.protocol_version.to_le_bytes().to_vec();
+    payload.extend_from_slice(&config.services.to_le_bytes());
+    payload.extend_from_slice(&Utc::now().timestamp().to_le_bytes());
+    /* addr_recv is the peer, addr_from is left empty (as Bitcoin Core
+     * does) */
+    payload.extend(btc_net_addr(0, client_info.ip.src, client_info.port.src));
+    payload.extend(btc_net_addr(config.services, None, None));
+    payload.extend_from_slice(&rand::random::<u64>().to_le_bytes());
+    payload.extend(var_str(&config.user_agent));
+    payload.extend_from_slice(&config.height.to_le_bytes());
+    /* relay */
+    payload.push(1);
+    payload
+}
+
+struct BtcVersion {
+    version: i32,
+    services: u64,
+    timestamp: i64,
+    user_agent: String,
+    height: i32,
+    relay: Option<bool>,
+}
+
+fn btc_parse_version(payload: &[u8]) -> Option<BtcVersion> {
+    let version = le_u64(payload.get(0..4)?) as u32 as i32;
+    let services = le_u64(payload.get(4..12)?);
+    let timestamp = le_u64(payload.get(12..20)?) as i64;
+    /* addr_recv, addr_from, nonce */
+    let (user_agent, len) = parse_var_str(payload.get(80..)?)?;
+    let offset = 80 + len;
+    let height = le_u64(payload.get(offset..offset + 4)?) as u32 as i32;
+    let relay = payload.get(offset + 4).map(|r| *r != 0);
+    Some(BtcVersion {
+        version,
+        services,
+        timestamp,
+        user_agent,
+        height,
+        relay,
+    })
+}
+
+fn btc_event(command: &str, payload: &[u8], client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("bitcoin_message", client_info);
+    event.set("command", command);
+    event.set("length", payload.len());
+    if command == "version" {
+        if let Some(v) = btc_parse_version(payload) {
+            event.set("version", v.version);
+            event.set("services", v.services);
+            event.set("timestamp", v.timestamp);
+            event.set("user_agent", v.user_agent);
+            event.set("height", v.height);
+            if let Some(relay) = v.relay {
+                event.set("relay", relay);
+            }
+        }
+    }
+    let len = std::cmp::min(payload.len(), 1024);
+    event.set("payload", encode(&payload[..len], Encoding::Base64));
+    event
+}
+
+fn btc_answer(
+    command: &str,
+    payload: &[u8],
+    magic: &[u8; 4],
+    config: &BitcoinConfig,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    match command {
+        "version" => {
+            let mut answer = btc_message(magic, "version", &btc_version(config, client_info));
+            answer.extend(btc_message(magic, "verack", b""));
+            Some(answer)
+        }
+        "ping" => Some(btc_message(magic, "pong", payload)),
+        "getaddr" => Some(btc_message(magic, "addr", &var_int(0))),
+        _ => None,
+    }
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving Bitcoin data");
+    let config = &masscanned.config.bitcoin;
+    let magic = config.magic()?;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Bitcoin(s)) => s,
+        _ => BitcoinState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while !state.buffer.is_empty() {
+        let len = std::cmp::min(state.buffer.len(), magic.len());
+        if state.buffer[..len] != magic[..len] {
+            info!("invalid Bitcoin network magic");
+            client_info.close = true;
+            return None;
+        }
+        if state.buffer.len() < BTC_HEADER_LEN {
+            break;
+        }
+        let length = u32::from_le_bytes([
+            state.buffer[16],
+            state.buffer[17],
+            state.buffer[18],
+            state.buffer[19],
+        ]) as usize;
+        if length > BTC_MAX_PAYLOAD {
+            info!("Bitcoin message too large ({} bytes)", length);
+            client_info.close = true;
+            return None;
+        }
+        if state.buffer.len() < BTC_HEADER_LEN + length {
+            break;
+        }
+        let message: Vec<u8> = state.buffer.drain(..BTC_HEADER_LEN + length).collect();
+        let command = &message[4..4 + BTC_COMMAND_LEN];
+        let end = command
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(command.len());
+        let command = String::from_utf8_lossy(&command[..end]).to_string();
+        let payload = &message[BTC_HEADER_LEN..];
+        let mut event = btc_event(&command, payload, client_info);
+        /* messages with a wrong checksum are dropped, as by Bitcoin
+         * Core */
+        if message[20..24] != btc_checksum(payload) {
+            event.set("error", "invalid checksum");
+            event.log();
+            continue;
+        }
+        event.log();
+        if let Some(answer) = btc_answer(&command, payload, &magic, config, client_info) {
+            repl_data.extend(answer);
+        }
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Bitcoin(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending Bitcoin data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.port.src = Some(50000);
+        client_info.port.dst = Some(8333);
+        client_info
+    }
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /* version of a Bitcoin Core 25.0 node, modelled on its messages */
+    const CORE_VERSION: &str = "f9beb4d976657273696f6e000000000066000000a327d41180110100090400000000000000f1536500000000000000000000000000000000000000000000ffffc0000201208d09040000000000000000000000000000000000000000000000008877665544332211102f5361746f7368693a32352e302e302f986f0c0001";
+    /* version of the bitnodes crawler (no services, no relay) */
+    const BITNODES_VERSION: &str = "f9beb4d976657273696f6e000000000067000000b3ac69ad80110100000000000000000000f1536500000000000000000000000000000000000000000000ffffc0000201208d000000000000000000000000000000000000ffff0000000000000100000000000000112f6269746e6f6465732e696f3a302e332f986f0c0000";
+    const VERACK: &str = "f9beb4d976657261636b000000000000000000005df6e0e2";
+
+    /* commands and payloads of the messages of a reply */
+    fn messages(data: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut messages = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            assert!(data[offset..offset + 4] == [0xf9, 0xbe, 0xb4, 0xd9]);
+            let command = &data[offset + 4..offset + 16];
+            let end = command.iter().position(|c| *c == 0).unwrap();
+            let len = le_u64(&data[offset + 16..offset + 20]) as usize;
+            let payload = data[offset + 24..offset + 24 + len].to_vec();
+            assert!(data[offset + 20..offset + 24] == btc_checksum(&payload));
+            messages.push((String::from_utf8(command[..end].to_vec()).unwrap(), payload));
+            offset += 24 + len;
+        }
+        messages
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert!(
+            sha256(b"abc")
+                == *unhex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        assert!(
+            sha256(&[b'a'; 200])
+                == *unhex("c2a908d98f5df987ade41b5fce213067efbcc21ef2240212a41e54b5e7c28ae5")
+        );
+        /* empty payloads (e.g., verack) */
+        assert!(btc_checksum(b"") == [0x5d, 0xf6, 0xe0, 0xe2]);
+    }
+
+    #[test]
+    fn test_var_int_str() {
+        for (n, encoded) in [
+            (0u64, &b"\x00"[..]),
+            (0xfc, b"\xfc"),
+            (0xfd, b"\xfd\xfd\x00"),
+            (0xffff, b"\xfd\xff\xff"),
+            (0x10000, b"\xfe\x00\x00\x01\x00"),
+            (0x100000000, b"\xff\x00\x00\x00\x00\x01\x00\x00\x00"),
+        ] {
+            assert!(var_int(n) == encoded);
+            assert!(parse_var_int(encoded) == Some((n, encoded.len())));
+            assert!(parse_var_int(&encoded[..encoded.len() - 1]).is_none());
+        }
+        assert!(parse_var_int(b"").is_none());
+        let s = var_str("/Satoshi:25.0.0/");
+        assert!(s[0] == 16 && parse_var_str(&s) == Some(("/Satoshi:25.0.0/".to_string(), 17)));
+        assert!(parse_var_str(&s[..10]).is_none());
+        /* absurd lengths */
+        assert!(parse_var_str(b"\xff\xff\xff\xff\xff\xff\xff\xff\xffa").is_none());
+    }
+
+    #[test]
+    fn test_bitcoin_version() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        let core = unhex(CORE_VERSION);
+        let event = btc_event("version", &core[24..], &client_info);
+        assert!(event.fields["version"] == 70016);
+        assert!(event.fields["services"] == 0x409);
+        assert!(event.fields["user_agent"] == "/Satoshi:25.0.0/");
+        assert!(event.fields["height"] == 815000);
+        assert!(event.fields["relay"] == true);
+        /* split across segments */
+        assert!(
+            crate::proto::dispatch(&core[..30], &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        let repl =
+            crate::proto::dispatch(&core[30..], &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        let m = messages(&repl);
+        assert!(m.len() == 2 && m[0].0 == "version" && m[1] == ("verack".to_string(), vec![]));
+        let version = btc_parse_version(&m[0].1).unwrap();
+        assert!(version.version == 70016 && version.services == 0x409);
+        assert!(version.user_agent == "/Satoshi:25.0.0/" && version.height == 815000);
+        /* the peer address, IPv4-mapped */
+        assert!(m[0].1[20..46] == *unhex("000000000000000000000000000000000000ffffc6336407c350"));
+        /* verack, ping and getaddr */
+        let mut data = unhex(VERACK);
+        data.extend(unhex(
+            "f9beb4d970696e670000000000000000080000002502fa940102030405060708",
+        ));
+        data.extend(unhex("f9beb4d9676574616464720000000000000000005df6e0e2"));
+        let repl =
+            crate::proto::dispatch(&data, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let m = messages(&repl);
+        assert!(m[0] == ("pong".to_string(), unhex("0102030405060708")));
+        assert!(m[1] == ("addr".to_string(), vec![0]));
+        assert!(!client_info.close);
+    }
+
+    #[test]
+    fn test_bitcoin_crawler() {
+        /* on any port, with our user agent and height */
+        let mut masscanned = masscanned(Config::default());
+        masscanned.config.bitcoin.user_agent = "/Satoshi:0.21.1/".to_string();
+        masscanned.config.bitcoin.height = 1;
+        let mut client_info = client();
+        client_info.port.dst = Some(9999);
+        let mut tcb = TCPControlBlock::new();
+        let bitnodes = unhex(BITNODES_VERSION);
+        let event = btc_event("version", &bitnodes[24..], &client_info);
+        assert!(event.fields["user_agent"] == "/bitnodes.io:0.3/");
+        assert!(event.fields["services"] == 0 && event.fields["relay"] == false);
+        let repl = crate::proto::dispatch(&bitnodes, &masscanned, &mut client_info, Some(&mut tcb))
+            .unwrap();
+        let version = btc_parse_version(&messages(&repl)[0].1).unwrap();
+        assert!(version.user_agent == "/Satoshi:0.21.1/" && version.height == 1);
+        /* bad checksums are dropped */
+        let mut bad = unhex("f9beb4d970696e670000000000000000080000002502fa940102030405060708");
+        bad[30] ^= 1;
+        assert!(
+            crate::proto::dispatch(&bad, &masscanned, &mut client_info, Some(&mut tcb)).is_none()
+        );
+        assert!(!client_info.close);
+        /* another network */
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        let mut testnet = bitnodes.clone();
+        testnet[..4].copy_from_slice(&[0x0b, 0x11, 0x09, 0x07]);
+        assert!(
+            crate::proto::dispatch(&testnet, &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        assert!(client_info.close);
+        assert!(Config::from_str("[bitcoin]\nnetwork = \"testnet\"\n").is_ok());
+        assert!(Config::from_str("[bitcoin]\nnetwork = \"litecoin\"\n").is_err());
+    }
+}
@@ -0,0 +1,251 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+/* Minimal BSON (bsonspec.org) decoder and encoder, for MongoDB: the
+ * element types used by drivers in their commands, every length is
+ * checked against the data available.
+ **/
+
+use serde_json::{Map, Value};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Bson {
+    Double(f64),
+    String(String),
+    Document(BsonDocument),
+    Array(Vec<Bson>),
+    Binary(u8, Vec<u8>),
+    ObjectId([u8; 12]),
+    Bool(bool),
+    DateTime(i64),
+    Null,
+    Int32(i32),
+    Timestamp(u64),
+    Int64(i64),
+}
+
+pub type BsonDocument = Vec<(String, Bson)>;
+
+impl Bson {
+    /* JSON value, for events */
+    pub fn to_json(&self) -> Value {
+        match self {
+            Bson::Double(f) => (*f).into(),
+            Bson::String(s) => s.clone().into(),
+            Bson::Document(d) => bson_to_json(d),
+            Bson::Array(a) => Value::Array(a.iter().map(|v| v.to_json()).collect()),
+            Bson::Binary(_, b) => base64::encode(b).into(),
+            Bson::ObjectId(o) => o
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+                .into(),
+            Bson::Bool(b) => (*b).into(),
+            Bson::DateTime(t) | Bson::Int64(t) => (*t).into(),
+            Bson::Null => Value::Null,
+            Bson::Int32(i) => (*i).into(),
+            Bson::Timestamp(t) => (*t).into(),
+        }
+    }
+}
+
+pub fn bson_to_json(doc: &[(String, Bson)]) -> Value {
+    let mut obj = Map::new();
+    for (k, v) in doc.iter() {
+        obj.insert(k.clone(), v.to_json());
+    }
+    Value::Object(obj)
+}
+
+pub fn bson_get<'a>(doc: &'a [(String, Bson)], key: &str) -> Option<&'a Bson> {
+    doc.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+fn cstring(data: &[u8]) -> Option<(String, usize)> {
+    let end = data.iter().position(|c| *c == 0)?;
+    Some((String::from_utf8_lossy(&data[..end]).to_string(), end + 1))
+}
+
+fn le_bytes<const N: usize>(data: &[u8], offset: usize) -> Option<[u8; N]> {
+    let mut b = [0; N];
+    b.copy_from_slice(data.get(offset..offset + N)?);
+    Some(b)
+}
+
+/* document at the beginning of data, and its size */
+pub fn bson_decode(data: &[u8]) -> Option<(BsonDocument, usize)> {
+    bson_decode_depth(data, 0)
+}
+
+/* nested documents are limited to 100 levels (as by MongoDB) */
+fn bson_decode_depth(data: &[u8], depth: usize) -> Option<(BsonDocument, usize)> {
+    if depth > 100 {
+        return None;
+    }
+    let len = i32::from_le_bytes(le_bytes(data, 0)?);
+    if len < 5 || data.len() < len as usize {
+        return None;
+    }
+    let len = len as usize;
+    /* the document ends with a NUL byte */
+    if data[len - 1] != 0 {
+        return None;
+    }
+    let body = &data[4..len - 1];
+    let mut doc = Vec::new();
+    let mut offset = 0;
+    while offset < body.len() {
+        let kind = body[offset];
+        let (key, n) = cstring(&body[offset + 1..])?;
+        offset += 1 + n;
+        let (value, n) = bson_value(kind, &body[offset..], depth)?;
+        offset += n;
+        doc.push((key, value));
+    }
+    Some((doc, len))
+}
+
+fn bson_value(kind: u8, data: &[u8], depth: usize) -> Option<(Bson, usize)> {
+    Some(match kind {
+        0x01 => (Bson::Double(f64::from_le_bytes(le_bytes(data, 0)?)), 8),
+        0x02 => {
+            let len = i32::from_le_bytes(le_bytes(data, 0)?);
+            if len < 1 {
+                return None;
+            }
+            let s = data.get(4..4 + len as usize)?;
+            if s[s.len() - 1] != 0 {
+                return None;
+            }
+            (
+                Bson::String(String::from_utf8_lossy(&s[..s.len() - 1]).to_string()),
+                4 + len as usize,
+            )
+        }
+        0x03 | 0x04 => {
+            let (doc, len) = bson_decode_depth(data, depth + 1)?;
+            if kind == 0x03 {
+                (Bson::Document(doc), len)
+            } else {
+                (Bson::Array(doc.into_iter().map(|(_, v)| v).collect()), len)
+            }
+        }
+        0x05 => {
+            let len = i32::from_le_bytes(le_bytes(data, 0)?);
+            if len < 0 {
+                return None;
+            }
+            let subtype = *data.get(4)?;
+            let b = data.get(5..5 + len as usize)?;
+            (Bson::Binary(subtype, b.to_vec()), 5 + len as usize)
+        }
+        0x07 => (Bson::ObjectId(le_bytes(data, 0)?), 12),
+        0x08 => (Bson::Bool(*data.first()? != 0), 1),
+        0x09 => (Bson::DateTime(i64::from_le_bytes(le_bytes(data, 0)?)), 8),
+        0x0a => (Bson::Null, 0),
+        0x10 => (Bson::Int32(i32::from_le_bytes(le_bytes(data, 0)?)), 4),
+        0x11 => (Bson::Timestamp(u64::from_le_bytes(le_bytes(data, 0)?)), 8),
+        0x12 => (Bson::Int64(i64::from_le_bytes(le_bytes(data, 0)?)), 8),
+        _ => return None,
+    })
+}
+
+pub fn bson_encode(doc: &[(String, Bson)]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (key, value) in doc.iter() {
+        let (kind, data) = match value {
+            Bson::Double(f) => (0x01, f.to_le_bytes().to_vec()),
+            Bson::String(s) => {
+                let mut data = ((s.len() + 1) as i32).to_le_bytes().to_vec();
+                data.extend_from_slice(s.as_bytes());
+                data.push(0);
+                (0x02, data)
+            }
+            Bson::Document(d) => (0x03, bson_encode(d)),
+            Bson::Array(a) => (
+                0x04,
+                bson_encode(
+                    &a.iter()
+                        .enumerate()
+                        .map(|(i, v)| (i.to_string(), v.clone()))
+                        .collect::<Vec<(String, Bson)>>(),
+                ),
+            ),
+            Bson::Binary(subtype, b) => {
+                let mut data = (b.len() as i32).to_le_bytes().to_vec();
+                data.push(*subtype);
+                data.extend_from_slice(b);
+                (0x05, data)
+            }
+            Bson::ObjectId(o) => (0x07, o.to_vec()),
+            Bson::Bool(b) => (0x08, vec![*b as u8]),
+            Bson::DateTime(t) => (0x09, t.to_le_bytes().to_vec()),
+            Bson::Null => (0x0a, Vec::new()),
+            Bson::Int32(i) => (0x10, i.to_le_bytes().to_vec()),
+            Bson::Timestamp(t) => (0x11, t.to_le_bytes().to_vec()),
+            Bson::Int64(i) => (0x12, i.to_le_bytes().to_vec()),
+        };
+        body.push(kind);
+        body.extend_from_slice(key.as_bytes());
+        body.push(0);
+        body.extend(data);
+    }
+    let mut doc = ((body.len() + 5) as i32).to_le_bytes().to_vec();
+    doc.extend(body);
+    doc.push(0);
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bson() {
+        /* {"hello": "world"} (bsonspec.org) */
+        let data = b"\x16\x00\x00\x00\x02hello\x00\x06\x00\x00\x00world\x00\x00";
+        let (doc, len) = bson_decode(data).unwrap();
+        assert!(len == data.len());
+        assert!(doc == vec![("hello".to_string(), Bson::String("world".to_string()))]);
+        assert!(bson_encode(&doc) == data.to_vec());
+        /* every type */
+        let doc = vec![
+            ("d".to_string(), Bson::Double(1.5)),
+            (
+                "o".to_string(),
+                Bson::Document(vec![(
+                    "a".to_string(),
+                    Bson::Array(vec![Bson::Int32(1), Bson::Null]),
+                )]),
+            ),
+            ("b".to_string(), Bson::Binary(4, vec![1, 2, 3])),
+            ("i".to_string(), Bson::ObjectId([7; 12])),
+            ("t".to_string(), Bson::Bool(true)),
+            ("m".to_string(), Bson::DateTime(1_700_000_000_000)),
+            ("s".to_string(), Bson::Timestamp(42)),
+            ("l".to_string(), Bson::Int64(-1)),
+        ];
+        let data = bson_encode(&doc);
+        assert!(bson_decode(&data) == Some((doc.clone(), data.len())));
+        assert!(bson_to_json(&doc)["o"]["a"] == serde_json::json!([1, null]));
+        /* truncated or invalid documents */
+        for i in 0..data.len() {
+            assert!(bson_decode(&data[..i]).is_none());
+        }
+        assert!(bson_decode(b"\x0c\x00\x00\x00\x02a\x00\xff\xff\xff\xff\x00").is_none());
+        assert!(bson_decode(b"\x08\x00\x00\x00\x13a\x00\x00").is_none());
+    }
+}
@@ -0,0 +1,394 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::Masscanned;
+
+/* message types */
+const COAP_CON: u8 = 0;
+const COAP_NON: u8 = 1;
+const COAP_ACK: u8 = 2;
+const COAP_RST: u8 = 3;
+
+/* codes (class.detail) */
+const COAP_EMPTY: u8 = 0x00;
+const COAP_GET: u8 = 0x01;
+const COAP_CONTENT: u8 = 0x45;
+const COAP_NOT_FOUND: u8 = 0x84;
+const COAP_METHOD_NOT_ALLOWED: u8 = 0x85;
+
+/* options */
+const COAP_OPTION_URI_PATH: u16 = 11;
+const COAP_OPTION_CONTENT_FORMAT: u16 = 12;
+const COAP_OPTION_URI_QUERY: u16 = 15;
+
+/* RFC 6690 section 7.2 */
+const COAP_FORMAT_LINK: u16 = 40;
+
+const COAP_PAYLOAD_MARKER: u8 = 0xff;
+
+/* CoAP server (on the ports): GET /.well-known/core is answered with
+ * resources (CoRE link format, RFC 6690), any other path with 4.04.
+ * Answers larger than allowed by [udp] max_amplification are dropped.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CoapConfig {
+    pub ports: Vec<u16>,
+    pub resources: String,
+}
+
+impl Default for CoapConfig {
+    fn default() -> Self {
+        CoapConfig {
+            ports: vec![5683],
+            resources: "</.well-known/core>;ct=40,</sensors/temp>;rt=\"temperature-c\";if=\"sensor\",</sensors/light>;rt=\"light-lux\";if=\"sensor\",</firmware>;rt=\"firmware\""
+                .to_string(),
+        }
+    }
+}
+
+struct CoapMessage {
+    mtype: u8,
+    code: u8,
+    message_id: u16,
+    token: Vec<u8>,
+    /* (number, value), in order */
+    options: Vec<(u16, Vec<u8>)>,
+    payload: Vec<u8>,
+}
+
+impl CoapMessage {
+    fn path(&self) -> String {
+        let segments: Vec<String> = self
+            .options
+            .iter()
+            .filter(|(n, _)| *n == COAP_OPTION_URI_PATH)
+            .map(|(_, v)| String::from_utf8_lossy(v).to_string())
+            .collect();
+        format!("/{}", segments.join("/"))
+    }
+}
+
+/* option delta or length: 13 and 14 announce a 1-byte and a 2-byte
+ * extended value, 15 is reserved - value and number of bytes used */
+fn coap_option_value(nibble: u8, data: &[u8]) -> Option<(u16, usize)> {
+    match nibble {
+        13 => Some((*data.first()? as u16 + 13, 1)),
+        14 => {
+            let ext = u16::from_be_bytes([*data.first()?, *data.get(1)?]);
+            Some((ext.checked_add(269)?, 2))
+        }
+        15 => None,
+        n => Some((n as u16, 0)),
+    }
+}
+
+/* Err(Some(message_id)) for malformed messages (to reset), Err(None)
+ * for messages to ignore (RFC 7252 section 3: too short, unknown
+ * version) */
+fn coap_parse(data: &[u8]) -> Result<CoapMessage, Option<u16>> {
+    if data.len() < 4 || data[0] >> 6 != 1 {
+        return Err(None);
+    }
+    let mtype = (data[0] >> 4) & 0x03;
+    let tkl = (data[0] & 0x0f) as usize;
+    let code = data[1];
+    let message_id = u16::from_be_bytes([data[2], data[3]]);
+    let malformed = Err(Some(message_id));
+    /* token lengths 9 to 15 are reserved */
+    if tkl > 8 || data.len() < 4 + tkl {
+        return malformed;
+    }
+    let token = data[4..4 + tkl].to_vec();
+    let mut options = Vec::new();
+    let mut number: u16 = 0;
+    let mut offset = 4 + tkl;
+    let mut payload = Vec::new();
+    while offset < data.len() {
+        if data[offset] == COAP_PAYLOAD_MARKER {
+            /* a marker followed by an empty payload is a format error */
+            if offset + 1 == data.len() {
+                return malformed;
+            }
+            payload = data[offset + 1..].to_vec();
+            break;
+        }
+        let (delta, n) = match coap_option_value(data[offset] >> 4, &data[offset + 1..]) {
+            Some(v) => v,
+            None => return malformed,
+        };
+        let (len, m) = match coap_option_value(data[offset] & 0x0f, &data[offset + 1 + n..]) {
+            Some(v) => v,
+            None => return malformed,
+        };
+        offset += 1 + n + m;
+        number = match number.checked_add(delta) {
+            Some(n) => n,
+            None => return malformed,
+        };
+        let end = offset + len as usize;
+        if end > data.len() {
+            return malformed;
+        }
+        options.push((number, data[offset..end].to_vec()));
+        offset = end;
+    }
+    Ok(CoapMessage {
+        mtype,
+        code,
+        message_id,
+        token,
+        options,
+        payload,
+    })
+}
+
+/* nibble and extended bytes of an option delta or length */
+fn coap_option_nibble(value: u16) -> (u8, Vec<u8>) {
+    if value < 13 {
+        (value as u8, Vec::new())
+    } else if value < 269 {
+        (13, vec![(value - 13) as u8])
+    } else {
+        (14, (value - 269).to_be_bytes().to_vec())
+    }
+}
+
+/* options must be sorted by number */
+fn coap_message(msg: &CoapMessage) -> Vec<u8> {
+    let mut data = vec![0x40 | (msg.mtype << 4) | msg.token.len() as u8, msg.code];
+    data.extend_from_slice(&msg.message_id.to_be_bytes());
+    data.extend_from_slice(&msg.token);
+    let mut number = 0;
+    for (n, value) in msg.options.iter() {
+        let (delta, delta_ext) = coap_option_nibble(n - number);
+        let (len, len_ext) = coap_option_nibble(value.len() as u16);
+        data.push((delta << 4) | len);
+        data.extend(delta_ext);
+        data.extend(len_ext);
+        data.extend_from_slice(value);
+        number = *n;
+    }
+    if !msg.payload.is_empty() {
+        data.push(COAP_PAYLOAD_MARKER);
+        data.extend_from_slice(&msg.payload);
+    }
+    data
+}
+
+/* minimal unsigned integer encoding of option values */
+fn coap_uint(value: u16) -> Vec<u8> {
+    match value {
+        0 => Vec::new(),
+        1..=0xff => vec![value as u8],
+        _ => value.to_be_bytes().to_vec(),
+    }
+}
+
+fn coap_event(msg: &CoapMessage, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("coap_request", client_info);
+    event.set("type", msg.mtype);
+    event.set("code", format!("{}.{:02}", msg.code >> 5, msg.code & 0x1f));
+    event.set("message_id", msg.message_id);
+    event.set(
+        "token",
+        msg.token
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>(),
+    );
+    event.set("path", msg.path());
+    event.set(
+        "query",
+        Value::Array(
+            msg.options
+                .iter()
+                .filter(|(n, _)| *n == COAP_OPTION_URI_QUERY)
+                .map(|(_, v)| Value::from(String::from_utf8_lossy(v).to_string()))
+                .collect(),
+        ),
+    );
+    event.set("payload_length", msg.payload.len());
+    event
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving CoAP data");
+    let config = &masscanned.config.coap;
+    let reset = |message_id| {
+        coap_message(&CoapMessage {
+            mtype: COAP_RST,
+            code: COAP_EMPTY,
+            message_id,
+            token: Vec::new(),
+            options: Vec::new(),
+            payload: Vec::new(),
+        })
+    };
+    let msg = match coap_parse(data) {
+        Ok(m) => m,
+        Err(Some(message_id)) => {
+            info!("CoAP malformed message - reset");
+            return Some(reset(message_id));
+        }
+        Err(None) => {
+            info!("CoAP data not handled (invalid header)");
+            return None;
+        }
+    };
+    match (msg.mtype, msg.code) {
+        (COAP_ACK, _) | (COAP_RST, _) | (COAP_NON, COAP_EMPTY) => return None,
+        /* empty confirmable messages (CoAP ping) get a reset */
+        (COAP_CON, COAP_EMPTY) => return Some(reset(msg.message_id)),
+        /* requests only (class 0) */
+        (_, code) if code >> 5 != 0 => return Some(reset(msg.message_id)),
+        _ => {}
+    }
+    coap_event(&msg, client_info).log();
+    let path = msg.path();
+    warn!("CoAP {} request for {}", msg.code, path);
+    let (code, options, payload) = match (path.as_str(), msg.code) {
+        ("/.well-known/core", COAP_GET) => (
+            COAP_CONTENT,
+            vec![(COAP_OPTION_CONTENT_FORMAT, coap_uint(COAP_FORMAT_LINK))],
+            config.resources.as_bytes().to_vec(),
+        ),
+        ("/.well-known/core", _) => (COAP_METHOD_NOT_ALLOWED, Vec::new(), Vec::new()),
+        _ => (COAP_NOT_FOUND, Vec::new(), Vec::new()),
+    };
+    /* confirmable requests get a piggybacked answer (RFC 7252
+     * section 5.2.1), non-confirmable ones a non-confirmable answer */
+    let (mtype, message_id) = if msg.mtype == COAP_CON {
+        (COAP_ACK, msg.message_id)
+    } else {
+        (COAP_NON, msg.message_id.wrapping_add(1))
+    };
+    debug!("sending CoAP data");
+    Some(coap_message(&CoapMessage {
+        mtype,
+        code,
+        message_id,
+        token: msg.token,
+        options,
+        payload,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    /* coap-client -m get coap://[...]/.well-known/core (libcoap) */
+    const WELL_KNOWN_CORE: &[u8] = b"\x42\x01\x12\x34\xab\xcd\xbb.well-known\x04core";
+
+    #[test]
+    fn test_coap_options() {
+        let msg = coap_parse(WELL_KNOWN_CORE).ok().unwrap();
+        assert!(msg.mtype == COAP_CON && msg.code == COAP_GET);
+        assert!(msg.message_id == 0x1234 && msg.token == b"\xab\xcd");
+        assert!(msg.options.len() == 2 && msg.path() == "/.well-known/core");
+        assert!(coap_message(&msg) == WELL_KNOWN_CORE);
+        /* extended deltas and lengths: Uri-Path (11), Size1 (60), 2000 */
+        let msg = CoapMessage {
+            mtype: COAP_NON,
+            code: COAP_GET,
+            message_id: 1,
+            token: Vec::new(),
+            options: vec![
+                (COAP_OPTION_URI_PATH, b"a".to_vec()),
+                (60, vec![b'x'; 20]),
+                (2000, vec![b'y'; 300]),
+            ],
+            payload: b"p".to_vec(),
+        };
+        let data = coap_message(&msg);
+        /* delta 49: 13 + 36, length 20: 13 + 7 */
+        assert!(data[6..9] == [0xdd, 36, 7]);
+        /* delta 1940: 269 + 1671, length 300: 269 + 31 */
+        assert!(data[29..34] == [0xee, 0x06, 0x87, 0x00, 31]);
+        let parsed = coap_parse(&data).ok().unwrap();
+        assert!(parsed.options == msg.options && parsed.payload == b"p");
+        /* reserved nibble, truncated option, marker without payload,
+         * reserved token length */
+        for data in [
+            &b"\x50\x01\x00\x01\xf0"[..],
+            b"\x50\x01\x00\x01\x0f",
+            b"\x50\x01\x00\x01\xd1",
+            b"\x50\x01\x00\x01\xb4cor",
+            b"\x50\x01\x00\x01\xff",
+            b"\x59\x01\x00\x01",
+        ]
+        .iter()
+        {
+            assert!(matches!(coap_parse(data), Err(Some(1))));
+        }
+        /* too short, unknown version */
+        assert!(matches!(coap_parse(b"\x50\x01\x00"), Err(None)));
+        assert!(matches!(coap_parse(b"\x90\x01\x00\x01"), Err(None)));
+    }
+
+    #[test]
+    fn test_coap_well_known_core() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let repl_data = repl(WELL_KNOWN_CORE, &masscanned, &mut client_info).unwrap();
+        /* ACK, 2.05 Content, same message ID and token, Content-Format 40 */
+        assert!(repl_data[..8] == *b"\x62\x45\x12\x34\xab\xcd\xc1\x28");
+        assert!(repl_data[8] == COAP_PAYLOAD_MARKER);
+        assert!(repl_data[9..] == *masscanned.config.coap.resources.as_bytes());
+        /* non-confirmable */
+        let mut req = WELL_KNOWN_CORE.to_vec();
+        req[0] = 0x52;
+        let repl_data = repl(&req, &masscanned, &mut client_info).unwrap();
+        assert!(repl_data[..6] == *b"\x52\x45\x12\x35\xab\xcd");
+        /* other paths and methods */
+        let repl_data = repl(b"\x40\x01\x00\x07\xb6status", &masscanned, &mut client_info).unwrap();
+        assert!(repl_data == b"\x60\x84\x00\x07");
+        let mut req = WELL_KNOWN_CORE.to_vec();
+        req[1] = 0x02;
+        let repl_data = repl(&req, &masscanned, &mut client_info).unwrap();
+        assert!(repl_data == b"\x62\x85\x12\x34\xab\xcd");
+    }
+
+    #[test]
+    fn test_coap_reset() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        /* malformed, ping */
+        for req in [&b"\x49\x01\x00\x02"[..], b"\x40\x00\x00\x02"].iter() {
+            assert!(repl(req, &masscanned, &mut client_info).unwrap() == b"\x70\x00\x00\x02");
+        }
+        /* acknowledgements, resets, too short */
+        for req in [&b"\x60\x00\x00\x02"[..], b"\x70\x00\x00\x02", b"\x40"].iter() {
+            assert!(repl(req, &masscanned, &mut client_info).is_none());
+        }
+        /* over UDP, on the port */
+        client_info.transport = Some(IpNextHeaderProtocols::Udp);
+        client_info.port.dst = Some(5683);
+        let repl_data =
+            crate::proto::dispatch(WELL_KNOWN_CORE, &masscanned, &mut client_info, None).unwrap();
+        assert!(repl_data.starts_with(b"\x62\x45\x12\x34"));
+    }
+}
@@ -0,0 +1,843 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::net::IpAddr;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* DCE/RPC connection-oriented PDUs (C706 chapter 12, MS-RPCE) */
+const DCERPC_VERSION: u8 = 5;
+const DCERPC_REQUEST: u8 = 0;
+const DCERPC_RESPONSE: u8 = 2;
+const DCERPC_FAULT: u8 = 3;
+const DCERPC_BIND: u8 = 11;
+const DCERPC_BIND_ACK: u8 = 12;
+const DCERPC_ALTER_CONTEXT: u8 = 14;
+const DCERPC_ALTER_CONTEXT_RESP: u8 = 15;
+
+const DCERPC_PFC_FIRST_FRAG: u8 = 0x01;
+const DCERPC_PFC_LAST_FRAG: u8 = 0x02;
+const DCERPC_PFC_OBJECT_UUID: u8 = 0x80;
+
+const DCERPC_HEADER_LEN: usize = 16;
+/* maximum size of a fragment kept between two segments */
+const DCERPC_MAX_FRAG: usize = 65536;
+
+/* presentation context results and reasons */
+const DCERPC_ACCEPTANCE: u16 = 0;
+const DCERPC_PROVIDER_REJECTION: u16 = 2;
+const DCERPC_ABSTRACT_SYNTAX_NOT_SUPPORTED: u16 = 1;
+const DCERPC_TRANSFER_SYNTAXES_NOT_SUPPORTED: u16 = 2;
+
+/* fault status */
+const NCA_S_OP_RNG_ERROR: u32 = 0x1c01_0002;
+const NCA_S_UNK_IF: u32 = 0x1c01_0003;
+/* ept_lookup status: no more entries */
+const EPT_S_NOT_REGISTERED: u32 = 0x16c9_a0d6;
+
+const EPT_LOOKUP: u16 = 2;
+
+/* UUIDs, in the order of their string form */
+const NDR_UUID: [u8; 16] = [
+    0x8a, 0x88, 0x5d, 0x04, 0x1c, 0xeb, 0x11, 0xc9, 0x9f, 0xe8, 0x08, 0x00, 0x2b, 0x10, 0x48, 0x60,
+];
+const NDR_VERSION: u32 = 2;
+const EPM_UUID: [u8; 16] = [
+    0xe1, 0xaf, 0x83, 0x08, 0x5d, 0x1f, 0x11, 0xc9, 0x91, 0xa4, 0x08, 0x00, 0x2b, 0x14, 0xa0, 0xfa,
+];
+
+/* binds on other ports: little-endian, single fragment */
+pub const DCERPC_PATTERN_BIND: &[u8; 8] = b"\x05\x00\x0b\x03\x10\x00\x00\x00";
+
+/* DCE/RPC endpoint mapper (on the configured TCP ports, and on other
+ * ports for bind PDUs): presentation contexts for the interfaces below
+ * (or any interface, with accept_all) and NDR are accepted, ept_lookup
+ * requests get the endpoints below, other requests a fault.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DcerpcConfig {
+    pub ports: Vec<u16>,
+    /* UUIDs of the interfaces accepted (the endpoint mapper always is) */
+    pub interfaces: Vec<String>,
+    pub accept_all: bool,
+    pub endpoints: Vec<DcerpcEndpoint>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DcerpcEndpoint {
+    pub uuid: String,
+    #[serde(default = "default_version")]
+    pub version: u16,
+    #[serde(default)]
+    pub annotation: String,
+    pub port: u16,
+}
+
+fn default_version() -> u16 {
+    1
+}
+
+impl Default for DcerpcConfig {
+    fn default() -> Self {
+        DcerpcConfig {
+            ports: vec![135],
+            interfaces: vec![
+                /* IOXIDResolver */
+                "99fcfec4-5260-101b-bbcb-00aa0021347a".to_string(),
+            ],
+            accept_all: false,
+            endpoints: Vec::new(),
+        }
+    }
+}
+
+impl DcerpcConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for u in self
+            .interfaces
+            .iter()
+            .chain(self.endpoints.iter().map(|e| &e.uuid))
+        {
+            if uuid_parse(u).is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("dcerpc: invalid UUID: {:?}", u),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn accepts(&self, uuid: &[u8; 16]) -> bool {
+        self.accept_all
+            || *uuid == EPM_UUID
+            || self
+                .interfaces
+                .iter()
+                .any(|u| uuid_parse(u).as_ref() == Some(uuid))
+    }
+}
+
+/* state of a DCE/RPC flow: beginning of a fragment, and presentation
+ * contexts accepted (context ID, interface) */
+#[derive(Default)]
+pub struct DcerpcState {
+    buffer: Vec<u8>,
+    contexts: Vec<(u16, [u8; 16])>,
+}
+
+fn uuid_parse(s: &str) -> Option<[u8; 16]> {
+    let hex: String = s.chars().filter(|&c| c != '-').collect();
+    if hex.len() != 32 || s.len() != 36 {
+        return None;
+    }
+    let mut uuid = [0; 16];
+    for (i, b) in uuid.iter_mut().enumerate() {
+        *b = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(uuid)
+}
+
+fn uuid_string(uuid: &[u8; 16]) -> String {
+    let h: Vec<String> = uuid.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        h[..4].concat(),
+        h[4..6].concat(),
+        h[6..8].concat(),
+        h[8..10].concat(),
+        h[10..].concat()
+    )
+}
+
+/* NDR data, in the byte order of the packed data representation */
+struct NdrReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    le: bool,
+}
+
+impl<'a> NdrReader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let b = self.data.get(self.offset..self.offset + len)?;
+        self.offset += len;
+        Some(b)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let b = self.bytes(2)?;
+        let b = [b[0], b[1]];
+        Some(if self.le {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.bytes(4)?);
+        Some(if self.le {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    /* GUID: u32, u16, u16, then bytes */
+    fn uuid(&mut self) -> Option<[u8; 16]> {
+        let mut uuid = [0; 16];
+        uuid[..4].copy_from_slice(&self.u32()?.to_be_bytes());
+        uuid[4..6].copy_from_slice(&self.u16()?.to_be_bytes());
+        uuid[6..8].copy_from_slice(&self.u16()?.to_be_bytes());
+        uuid[8..].copy_from_slice(self.bytes(8)?);
+        Some(uuid)
+    }
+}
+
+struct NdrWriter {
+    data: Vec<u8>,
+    le: bool,
+}
+
+impl NdrWriter {
+    fn u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        if self.le {
+            self.data.extend_from_slice(&v.to_le_bytes());
+        } else {
+            self.data.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+
+    fn u32(&mut self, v: u32) {
+        if self.le {
+            self.data.extend_from_slice(&v.to_le_bytes());
+        } else {
+            self.data.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+
+    fn uuid(&mut self, uuid: &[u8; 16]) {
+        self.u32(u32::from_be_bytes([uuid[0], uuid[1], uuid[2], uuid[3]]));
+        self.u16(u16::from_be_bytes([uuid[4], uuid[5]]));
+        self.u16(u16::from_be_bytes([uuid[6], uuid[7]]));
+        self.data.extend_from_slice(&uuid[8..]);
+    }
+
+    fn align(&mut self, n: usize) {
+        while !self.data.len().is_multiple_of(n) {
+            self.data.push(0);
+        }
+    }
+}
+
+/* PDU common header */
+struct DcerpcHeader {
+    ptype: u8,
+    flags: u8,
+    drep: [u8; 4],
+    frag_length: u16,
+    auth_length: u16,
+    call_id: u32,
+}
+
+impl DcerpcHeader {
+    fn le(&self) -> bool {
+        self.drep[0] & 0x10 != 0
+    }
+}
+
+fn dcerpc_parse_header(data: &[u8]) -> Option<DcerpcHeader> {
+    if data.len() < DCERPC_HEADER_LEN {
+        return None;
+    }
+    let mut drep = [0; 4];
+    drep.copy_from_slice(&data[4..8]);
+    let mut r = NdrReader {
+        data,
+        offset: 8,
+        le: drep[0] & 0x10 != 0,
+    };
+    Some(DcerpcHeader {
+        ptype: data[2],
+        flags: data[3],
+        drep,
+        frag_length: r.u16()?,
+        auth_length: r.u16()?,
+        call_id: r.u32()?,
+    })
+}
+
+/* PDU (a single fragment) and its body, in the data representation of
+ * the request */
+fn dcerpc_pdu(request: &DcerpcHeader, ptype: u8, body: &[u8]) -> Vec<u8> {
+    let mut w = NdrWriter {
+        data: vec![
+            DCERPC_VERSION,
+            0,
+            ptype,
+            DCERPC_PFC_FIRST_FRAG | DCERPC_PFC_LAST_FRAG,
+        ],
+        le: request.le(),
+    };
+    w.data.extend_from_slice(&request.drep);
+    w.u16((DCERPC_HEADER_LEN + body.len()) as u16);
+    w.u16(0);
+    w.u32(request.call_id);
+    w.data.extend_from_slice(body);
+    w.data
+}
+
+/* bind and alter_context: results, for each presentation context */
+fn dcerpc_bind(
+    header: &DcerpcHeader,
+    body: &[u8],
+    config: &DcerpcConfig,
+    client_info: &ClientInfo,
+    state: &mut DcerpcState,
+) -> Option<Vec<u8>> {
+    let le = header.le();
+    let mut r = NdrReader {
+        data: body,
+        offset: 0,
+        le,
+    };
+    let max_xmit = r.u16()?;
+    let max_recv = r.u16()?;
+    let assoc_group = r.u32()?;
+    let n_contexts = r.u8()?;
+    r.bytes(3)?;
+    let mut results = Vec::new();
+    let mut logged = Vec::new();
+    for _ in 0..n_contexts {
+        let context_id = r.u16()?;
+        let n_transfer = r.u8()?;
+        r.u8()?;
+        let abstract_syntax = r.uuid()?;
+        let version = r.u32()?;
+        let mut transfer = Vec::new();
+        for _ in 0..n_transfer {
+            transfer.push((r.uuid()?, r.u32()?));
+        }
+        let ndr = transfer
+            .iter()
+            .any(|(uuid, v)| *uuid == NDR_UUID && *v == NDR_VERSION);
+        let result = if !config.accepts(&abstract_syntax) {
+            (
+                DCERPC_PROVIDER_REJECTION,
+                DCERPC_ABSTRACT_SYNTAX_NOT_SUPPORTED,
+            )
+        } else if !ndr {
+            (
+                DCERPC_PROVIDER_REJECTION,
+                DCERPC_TRANSFER_SYNTAXES_NOT_SUPPORTED,
+            )
+        } else {
+            state.contexts.retain(|(id, _)| *id != context_id);
+            state.contexts.push((context_id, abstract_syntax));
+            (DCERPC_ACCEPTANCE, 0)
+        };
+        logged.push(serde_json::json!({
+            "context_id": context_id,
+            "uuid": uuid_string(&abstract_syntax),
+            "version": format!("{}.{}", version & 0xffff, version >> 16),
+            "transfer_syntaxes": transfer.iter().map(|(u, _)| uuid_string(u)).collect::<Vec<String>>(),
+            "accepted": result.0 == DCERPC_ACCEPTANCE,
+        }));
+        results.push(result);
+    }
+    let mut event = Event::new("dcerpc_bind", client_info);
+    event.set(
+        "ptype",
+        if header.ptype == DCERPC_BIND {
+            "bind"
+        } else {
+            "alter_context"
+        },
+    );
+    event.set("contexts", logged);
+    event.set("auth", header.auth_length != 0);
+    event.log();
+    let mut w = NdrWriter {
+        data: Vec::new(),
+        le,
+    };
+    w.u16(max_xmit);
+    w.u16(max_recv);
+    w.u32(if assoc_group == 0 {
+        0x0001_2345
+    } else {
+        assoc_group
+    });
+    /* secondary address: the port (none for alter_context_resp) */
+    let ptype = if header.ptype == DCERPC_BIND {
+        let port = format!("{}\0", client_info.port.dst.unwrap_or(135));
+        w.u16(port.len() as u16);
+        w.data.extend_from_slice(port.as_bytes());
+        DCERPC_BIND_ACK
+    } else {
+        w.u16(0);
+        DCERPC_ALTER_CONTEXT_RESP
+    };
+    w.align(4);
+    w.u8(results.len() as u8);
+    w.data.extend_from_slice(&[0; 3]);
+    for (result, reason) in results {
+        w.u16(result);
+        w.u16(reason);
+        if result == DCERPC_ACCEPTANCE {
+            w.uuid(&NDR_UUID);
+            w.u32(NDR_VERSION);
+        } else {
+            w.data.extend_from_slice(&[0; 20]);
+        }
+    }
+    Some(dcerpc_pdu(header, ptype, &w.data))
+}
+
+/* protocol tower (C706 appendix L): interface, NDR, connection-oriented
+ * RPC, TCP port and IP address floors */
+fn dcerpc_tower(endpoint: &DcerpcEndpoint, uuid: &[u8; 16], ip: [u8; 4]) -> Vec<u8> {
+    let mut floors: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    for (uuid, major, minor) in [
+        (uuid, endpoint.version, 0u16),
+        (&NDR_UUID, NDR_VERSION as u16, 0),
+    ] {
+        let mut lhs = vec![0x0d];
+        let mut w = NdrWriter {
+            data: Vec::new(),
+            le: true,
+        };
+        w.uuid(uuid);
+        w.u16(major);
+        lhs.extend(w.data);
+        floors.push((lhs, minor.to_le_bytes().to_vec()));
+    }
+    floors.push((vec![0x0b], vec![0, 0]));
+    floors.push((vec![0x07], endpoint.port.to_be_bytes().to_vec()));
+    floors.push((vec![0x09], ip.to_vec()));
+    let mut tower = (floors.len() as u16).to_le_bytes().to_vec();
+    for (lhs, rhs) in floors {
+        tower.extend_from_slice(&(lhs.len() as u16).to_le_bytes());
+        tower.extend(lhs);
+        tower.extend_from_slice(&(rhs.len() as u16).to_le_bytes());
+        tower.extend(rhs);
+    }
+    tower
+}
+
+/* ept_lookup request: inquiry type, object and interface (unique
+ * pointers), version option, entry handle, maximum number of entries */
+fn ept_lookup_max_ents(r: &mut NdrReader) -> Option<u32> {
+    r.u32()?;
+    if r.u32()? != 0 {
+        r.bytes(16)?;
+    }
+    if r.u32()? != 0 {
+        r.bytes(20)?;
+    }
+    r.u32()?;
+    r.bytes(20)?;
+    r.u32()
+}
+
+/* ept_lookup response stub: entry handle, entries, status */
+fn ept_lookup(stub: &[u8], le: bool, config: &DcerpcConfig, client_info: &ClientInfo) -> Vec<u8> {
+    let mut r = NdrReader {
+        data: stub,
+        offset: 0,
+        le,
+    };
+    let max_ents = ept_lookup_max_ents(&mut r).unwrap_or(1);
+    let ip = match client_info.ip.dst {
+        Some(IpAddr::V4(ip)) => ip.octets(),
+        _ => [0; 4],
+    };
+    let endpoints: Vec<(&DcerpcEndpoint, [u8; 16])> = config
+        .endpoints
+        .iter()
+        .filter_map(|e| Some((e, uuid_parse(&e.uuid)?)))
+        .take(max_ents as usize)
+        .collect();
+    let mut w = NdrWriter {
+        data: Vec::new(),
+        le,
+    };
+    /* entry handle: all entries are sent at once */
+    w.data.extend_from_slice(&[0; 20]);
+    w.u32(endpoints.len() as u32);
+    /* conformant varying array: maximum count, offset, actual count */
+    w.u32(max_ents);
+    w.u32(0);
+    w.u32(endpoints.len() as u32);
+    for (i, (e, _)) in endpoints.iter().enumerate() {
+        /* object (nil), tower (referent), annotation */
+        w.data.extend_from_slice(&[0; 16]);
+        w.u32(i as u32 + 1);
+        let annotation = format!("{}\0", e.annotation);
+        w.u32(0);
+        w.u32(annotation.len() as u32);
+        w.data.extend_from_slice(annotation.as_bytes());
+        w.align(4);
+    }
+    for (e, uuid) in endpoints.iter() {
+        let tower = dcerpc_tower(e, uuid, ip);
+        w.u32(tower.len() as u32);
+        w.u32(tower.len() as u32);
+        w.data.extend(tower);
+        w.align(4);
+    }
+    w.u32(if endpoints.is_empty() {
+        EPT_S_NOT_REGISTERED
+    } else {
+        0
+    });
+    w.data
+}
+
+fn dcerpc_fault(header: &DcerpcHeader, context_id: u16, status: u32) -> Vec<u8> {
+    let mut w = NdrWriter {
+        data: Vec::new(),
+        le: header.le(),
+    };
+    /* allocation hint, context ID, cancel count, reserved */
+    w.u32(0);
+    w.u16(context_id);
+    w.u8(0);
+    w.u8(0);
+    w.u32(status);
+    w.u32(0);
+    dcerpc_pdu(header, DCERPC_FAULT, &w.data)
+}
+
+fn dcerpc_request(
+    header: &DcerpcHeader,
+    body: &[u8],
+    config: &DcerpcConfig,
+    client_info: &ClientInfo,
+    state: &DcerpcState,
+) -> Option<Vec<u8>> {
+    let le = header.le();
+    let mut r = NdrReader {
+        data: body,
+        offset: 0,
+        le,
+    };
+    r.u32()?;
+    let context_id = r.u16()?;
+    let opnum = r.u16()?;
+    if header.flags & DCERPC_PFC_OBJECT_UUID != 0 {
+        r.bytes(16)?;
+    }
+    let stub = body.get(r.offset..body.len() - header.auth_length as usize)?;
+    let interface = state
+        .contexts
+        .iter()
+        .find(|(id, _)| *id == context_id)
+        .map(|(_, uuid)| *uuid);
+    let mut event = Event::new("dcerpc_request", client_info);
+    event.set("context_id", context_id);
+    event.set("opnum", opnum);
+    if let Some(uuid) = interface {
+        event.set("uuid", uuid_string(&uuid));
+    }
+    event.set("stub_length", stub.len());
+    event.log();
+    let interface = match interface {
+        Some(uuid) => uuid,
+        /* without state (e.g., UDP), the endpoint mapper */
+        None if state.contexts.is_empty() => EPM_UUID,
+        None => return Some(dcerpc_fault(header, context_id, NCA_S_UNK_IF)),
+    };
+    if interface != EPM_UUID || opnum != EPT_LOOKUP {
+        return Some(dcerpc_fault(header, context_id, NCA_S_OP_RNG_ERROR));
+    }
+    let stub = ept_lookup(stub, le, config, client_info);
+    let mut w = NdrWriter {
+        data: Vec::new(),
+        le,
+    };
+    w.u32(stub.len() as u32);
+    w.u16(context_id);
+    w.u8(0);
+    w.u8(0);
+    w.data.extend(stub);
+    Some(dcerpc_pdu(header, DCERPC_RESPONSE, &w.data))
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving DCE/RPC data");
+    let config = &masscanned.config.dcerpc;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Dcerpc(s)) => s,
+        _ => DcerpcState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while let Some(header) = dcerpc_parse_header(&state.buffer) {
+        let len = header.frag_length as usize;
+        if state.buffer[0] != DCERPC_VERSION
+            || len < DCERPC_HEADER_LEN + header.auth_length as usize
+            || len > DCERPC_MAX_FRAG
+        {
+            info!("invalid DCE/RPC PDU");
+            client_info.close = true;
+            return None;
+        }
+        if state.buffer.len() < len {
+            break;
+        }
+        let pdu: Vec<u8> = state.buffer.drain(..len).collect();
+        let body = &pdu[DCERPC_HEADER_LEN..];
+        let answer = match header.ptype {
+            DCERPC_BIND | DCERPC_ALTER_CONTEXT => {
+                dcerpc_bind(&header, body, config, client_info, &mut state)
+            }
+            /* stubs are answered from their last fragment */
+            DCERPC_REQUEST if header.flags & DCERPC_PFC_LAST_FRAG != 0 => {
+                dcerpc_request(&header, body, config, client_info, &state)
+            }
+            DCERPC_REQUEST => None,
+            ptype => {
+                info!("DCE/RPC PDU type not handled: {}", ptype);
+                None
+            }
+        };
+        if let Some(answer) = answer {
+            repl_data.extend(answer);
+        }
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Dcerpc(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending DCE/RPC data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::Ipv4Addr;
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.dst = Some(135);
+        client_info
+    }
+
+    /* impacket rpcdump: bind to the endpoint mapper v3.0, NDR */
+    const RPCDUMP_BIND: &[u8] = b"\x05\x00\x0b\x03\x10\x00\x00\x00\x48\x00\x00\x00\x01\x00\x00\x00\
+        \xb8\x10\xb8\x10\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x01\x00\
+        \x08\x83\xaf\xe1\x1f\x5d\xc9\x11\x91\xa4\x08\x00\x2b\x14\xa0\xfa\x03\x00\x00\x00\
+        \x04\x5d\x88\x8a\xeb\x1c\xc9\x11\x9f\xe8\x08\x00\x2b\x10\x48\x60\x02\x00\x00\x00";
+
+    /* impacket rpcdump: ept_lookup (RPC_C_EP_ALL_ELTS, no object, no
+     * interface, null entry handle, 500 entries) */
+    const RPCDUMP_LOOKUP: &[u8] =
+        b"\x05\x00\x00\x03\x10\x00\x00\x00\x40\x00\x00\x00\x02\x00\x00\x00\
+        \x28\x00\x00\x00\x00\x00\x02\x00\
+        \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\
+        \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+        \xf4\x01\x00\x00";
+
+    #[test]
+    fn test_uuid() {
+        let uuid = uuid_parse("e1af8308-5d1f-11c9-91a4-08002b14a0fa").unwrap();
+        assert!(uuid == EPM_UUID);
+        assert!(uuid_string(&NDR_UUID) == "8a885d04-1ceb-11c9-9fe8-08002b104860");
+        assert!(uuid_parse("e1af83085d1f11c991a408002b14a0fa").is_none());
+        assert!(uuid_parse("e1af8308-5d1f-11c9-91a4-08002b14a0fg").is_none());
+        /* NDR encoding */
+        let mut w = NdrWriter {
+            data: Vec::new(),
+            le: true,
+        };
+        w.uuid(&EPM_UUID);
+        assert!(w.data == RPCDUMP_BIND[32..48]);
+        let mut r = NdrReader {
+            data: &w.data,
+            offset: 0,
+            le: true,
+        };
+        assert!(r.uuid() == Some(EPM_UUID));
+    }
+
+    #[test]
+    fn test_dcerpc_bind() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        let reply =
+            crate::proto::dispatch(RPCDUMP_BIND, &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        /* header: bind_ack, same data representation and call ID */
+        let header = dcerpc_parse_header(&reply).unwrap();
+        assert!(reply[..4] == [5, 0, DCERPC_BIND_ACK, 3]);
+        assert!(header.drep == [0x10, 0, 0, 0] && header.call_id == 1);
+        assert!(header.frag_length as usize == reply.len() && header.auth_length == 0);
+        /* max fragments, association group, "135", padding */
+        assert!(reply[16..20] == [0xb8, 0x10, 0xb8, 0x10]);
+        assert!(reply[24..30] == *b"\x04\x00135\x00" && reply[30..32] == [0, 0]);
+        /* one result: acceptance, NDR */
+        assert!(reply[32..36] == [1, 0, 0, 0]);
+        assert!(reply[36..40] == [0, 0, 0, 0]);
+        assert!(reply[40..] == RPCDUMP_BIND[52..]);
+        /* ept_lookup: no entries */
+        let reply = crate::proto::dispatch(
+            RPCDUMP_LOOKUP,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        let header = dcerpc_parse_header(&reply).unwrap();
+        assert!(header.ptype == DCERPC_RESPONSE && header.call_id == 2);
+        assert!(header.frag_length as usize == reply.len());
+        let stub = &reply[24..];
+        assert!(stub[..20] == [0; 20] && stub[20..24] == [0; 4]);
+        assert!(stub[24..28] == 500u32.to_le_bytes() && stub[32..36] == [0; 4]);
+        assert!(stub[36..] == EPT_S_NOT_REGISTERED.to_le_bytes());
+        /* other operations: fault */
+        let mut request = RPCDUMP_LOOKUP.to_vec();
+        request[22] = 3;
+        let reply = repl(&request, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(reply[2] == DCERPC_FAULT && reply.len() == 32);
+        assert!(reply[24..28] == NCA_S_OP_RNG_ERROR.to_le_bytes());
+        /* unknown context */
+        let mut request = RPCDUMP_LOOKUP.to_vec();
+        request[20] = 7;
+        let reply = repl(&request, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(reply[24..28] == NCA_S_UNK_IF.to_le_bytes());
+    }
+
+    #[test]
+    fn test_dcerpc_reject_big_endian() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        /* big-endian bind to an unknown interface (and the endpoint
+         * mapper with NDR64 only), call ID 0x01020304 */
+        let mut w = NdrWriter {
+            data: Vec::new(),
+            le: false,
+        };
+        w.u16(4280);
+        w.u16(4280);
+        w.u32(0);
+        w.u8(2);
+        w.data.extend_from_slice(&[0; 3]);
+        w.u16(0);
+        w.u8(1);
+        w.u8(0);
+        w.uuid(&uuid_parse("12345778-1234-abcd-ef00-0123456789ac").unwrap());
+        w.u32(1);
+        w.uuid(&NDR_UUID);
+        w.u32(2);
+        w.u16(1);
+        w.u8(1);
+        w.u8(0);
+        w.uuid(&EPM_UUID);
+        w.u32(3);
+        w.uuid(&uuid_parse("71710533-beba-4937-8319-b5dbef9ccc36").unwrap());
+        w.u32(1);
+        let header = DcerpcHeader {
+            ptype: DCERPC_BIND,
+            flags: 3,
+            drep: [0, 0, 0, 0],
+            frag_length: 0,
+            auth_length: 0,
+            call_id: 0x01020304,
+        };
+        let bind = dcerpc_pdu(&header, DCERPC_BIND, &w.data);
+        let reply = repl(&bind, &masscanned, &mut client_info, None).unwrap();
+        assert!(reply[4..8] == [0, 0, 0, 0] && reply[12..16] == [1, 2, 3, 4]);
+        assert!(u16::from_be_bytes([reply[8], reply[9]]) as usize == reply.len());
+        assert!(reply[32] == 2);
+        /* abstract syntax, then transfer syntaxes not supported */
+        assert!(reply[36..40] == [0, 2, 0, 1] && reply[40..60] == [0; 20]);
+        assert!(reply[60..64] == [0, 2, 0, 2]);
+        assert!(reply.len() == 84);
+    }
+
+    #[test]
+    fn test_dcerpc_towers() {
+        let mut masscanned = masscanned(Config::default());
+        masscanned.config.dcerpc.endpoints = vec![DcerpcEndpoint {
+            /* MS-SAMR */
+            uuid: "12345778-1234-abcd-ef00-0123456789ac".to_string(),
+            version: 1,
+            annotation: "SAM".to_string(),
+            port: 49664,
+        }];
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        let mut data = RPCDUMP_BIND.to_vec();
+        data.extend_from_slice(RPCDUMP_LOOKUP);
+        let reply = repl(&data, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let ack_len = dcerpc_parse_header(&reply).unwrap().frag_length as usize;
+        let resp = &reply[ack_len..];
+        assert!(dcerpc_parse_header(resp).unwrap().frag_length as usize == resp.len());
+        let stub = &resp[24..];
+        /* one entry: nil object, tower referent, "SAM" */
+        assert!(stub[20..24] == [1, 0, 0, 0] && stub[32..36] == [1, 0, 0, 0]);
+        assert!(stub[52..56] == [1, 0, 0, 0]);
+        assert!(stub[56..60] == [0; 4] && stub[60..64] == [4, 0, 0, 0]);
+        assert!(stub[64..68] == *b"SAM\0");
+        /* tower: 5 floors, 75 bytes */
+        assert!(stub[68..72] == [75, 0, 0, 0] && stub[72..76] == [75, 0, 0, 0]);
+        let tower = &stub[76..151];
+        assert!(tower[..5] == [5, 0, 19, 0, 0x0d]);
+        assert!(
+            tower[5..21] == *b"\x78\x57\x34\x12\x34\x12\xcd\xab\xef\x00\x01\x23\x45\x67\x89\xac"
+        );
+        assert!(tower[52..] == *b"\x01\x00\x0b\x02\x00\x00\x00\x01\x00\x07\x02\x00\xc2\x00\x01\x00\x09\x04\x00\xc0\x00\x02\x01");
+        /* padding, status */
+        assert!(stub[151] == 0 && stub[152..] == [0; 4]);
+    }
+}
@@ -0,0 +1,720 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::convert::TryInto;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+
+use log::*;
+use pnet::util::MacAddr;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::Masscanned;
+
+/* RFC 2131, RFC 2132 */
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+
+const BOOTP_REQUEST: u8 = 1;
+const BOOTP_REPLY: u8 = 2;
+const BOOTP_HTYPE_ETHERNET: u8 = 1;
+const BOOTP_FLAG_BROADCAST: u16 = 0x8000;
+/* fixed part of a BOOTP message, then the magic cookie */
+const BOOTP_LEN: usize = 236;
+const DHCP_MAGIC: [u8; 4] = [99, 130, 83, 99];
+/* minimum size of a BOOTP message (RFC 1542 section 2.1) */
+const BOOTP_MIN_LEN: usize = 300;
+
+const DHCP_OPTION_PAD: u8 = 0;
+const DHCP_OPTION_SUBNET_MASK: u8 = 1;
+const DHCP_OPTION_ROUTER: u8 = 3;
+const DHCP_OPTION_DNS: u8 = 6;
+const DHCP_OPTION_HOSTNAME: u8 = 12;
+const DHCP_OPTION_REQUESTED_ADDRESS: u8 = 50;
+const DHCP_OPTION_LEASE_TIME: u8 = 51;
+const DHCP_OPTION_MESSAGE_TYPE: u8 = 53;
+const DHCP_OPTION_SERVER_ID: u8 = 54;
+const DHCP_OPTION_PARAMETER_LIST: u8 = 55;
+const DHCP_OPTION_RENEWAL_TIME: u8 = 58;
+const DHCP_OPTION_REBINDING_TIME: u8 = 59;
+const DHCP_OPTION_VENDOR_CLASS: u8 = 60;
+const DHCP_OPTION_CLIENT_ID: u8 = 61;
+const DHCP_OPTION_END: u8 = 255;
+
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_DECLINE: u8 = 4;
+const DHCP_ACK: u8 = 5;
+const DHCP_NAK: u8 = 6;
+const DHCP_RELEASE: u8 = 7;
+const DHCP_INFORM: u8 = 8;
+
+/* DHCP server (on the UDP ports, disabled by default): DHCPDISCOVER
+ * messages get a DHCPOFFER of an address of the pool (the same one for
+ * a given client), with the mask, routers and DNS servers; DHCPREQUEST
+ * messages get a DHCPNAK (so that no client keeps the lease), or a
+ * DHCPACK when ack is set. Replies are sent from server (by default,
+ * the address probed, or the first IPv4 address of masscanned).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DhcpConfig {
+    pub enabled: bool,
+    pub ports: Vec<u16>,
+    pub server: Option<String>,
+    pub pool_start: String,
+    pub pool_size: u32,
+    pub netmask: String,
+    pub routers: Vec<String>,
+    pub dns: Vec<String>,
+    pub lease_time: u32,
+    pub ack: bool,
+}
+
+impl Default for DhcpConfig {
+    fn default() -> Self {
+        DhcpConfig {
+            enabled: false,
+            ports: vec![DHCP_SERVER_PORT],
+            server: None,
+            pool_start: "192.168.1.100".to_string(),
+            pool_size: 100,
+            netmask: "255.255.255.0".to_string(),
+            routers: vec!["192.168.1.1".to_string()],
+            dns: vec!["192.168.1.1".to_string()],
+            lease_time: 3600,
+            ack: false,
+        }
+    }
+}
+
+fn dhcp_invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("dhcp: {}", msg))
+}
+
+fn dhcp_check_address(name: &str, value: &str) -> Result<(), io::Error> {
+    if value.parse::<Ipv4Addr>().is_err() {
+        return Err(dhcp_invalid(format!("invalid {}: {:?}", name, value)));
+    }
+    Ok(())
+}
+
+impl DhcpConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if let Some(server) = &self.server {
+            dhcp_check_address("server", server)?;
+        }
+        dhcp_check_address("pool_start", &self.pool_start)?;
+        dhcp_check_address("netmask", &self.netmask)?;
+        for router in self.routers.iter() {
+            dhcp_check_address("router", router)?;
+        }
+        for dns in self.dns.iter() {
+            dhcp_check_address("dns", dns)?;
+        }
+        let start = u32::from(dhcp_address(&self.pool_start));
+        if self.pool_size == 0 || start.checked_add(self.pool_size - 1).is_none() {
+            return Err(dhcp_invalid(format!(
+                "invalid pool_size: {}",
+                self.pool_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/* address of the configuration (checked) */
+fn dhcp_address(value: &str) -> Ipv4Addr {
+    value.parse().unwrap_or(Ipv4Addr::UNSPECIFIED)
+}
+
+/* DHCP message to the server, over UDP */
+pub fn is_dhcp(masscanned: &Masscanned, client_info: &ClientInfo) -> bool {
+    let config = &masscanned.config.dhcp;
+    config.enabled
+        && client_info
+            .port
+            .dst
+            .is_some_and(|p| config.ports.contains(&p))
+}
+
+struct DhcpMessage<'a> {
+    htype: u8,
+    hlen: u8,
+    xid: u32,
+    flags: u16,
+    ciaddr: Ipv4Addr,
+    giaddr: Ipv4Addr,
+    chaddr: &'a [u8],
+    options: Vec<(u8, &'a [u8])>,
+}
+
+impl<'a> DhcpMessage<'a> {
+    /* RFC 3396: the values of an option sent several times are
+     * concatenated */
+    fn option(&self, code: u8) -> Option<Vec<u8>> {
+        let mut value: Option<Vec<u8>> = None;
+        for (_, v) in self.options.iter().filter(|(c, _)| *c == code) {
+            value.get_or_insert_with(Vec::new).extend_from_slice(v);
+        }
+        value
+    }
+
+    fn option_address(&self, code: u8) -> Option<Ipv4Addr> {
+        let value = self.option(code)?;
+        let octets: [u8; 4] = value.as_slice().try_into().ok()?;
+        Some(Ipv4Addr::from(octets))
+    }
+
+    fn message_type(&self) -> Option<u8> {
+        match self.option(DHCP_OPTION_MESSAGE_TYPE)?.as_slice() {
+            [t] => Some(*t),
+            _ => None,
+        }
+    }
+
+    fn client_mac(&self) -> Option<MacAddr> {
+        if self.htype != BOOTP_HTYPE_ETHERNET || self.hlen != 6 {
+            return None;
+        }
+        let c = self.chaddr;
+        Some(MacAddr::new(c[0], c[1], c[2], c[3], c[4], c[5]))
+    }
+}
+
+fn dhcp_ipv4(data: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::new(
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    )
+}
+
+fn dhcp_parse(data: &[u8]) -> Option<DhcpMessage<'_>> {
+    if data.len() < BOOTP_LEN + DHCP_MAGIC.len()
+        || data[0] != BOOTP_REQUEST
+        || data[BOOTP_LEN..BOOTP_LEN + DHCP_MAGIC.len()] != DHCP_MAGIC
+    {
+        return None;
+    }
+    let hlen = data[2];
+    if hlen > 16 {
+        return None;
+    }
+    let mut options = Vec::new();
+    let mut offset = BOOTP_LEN + DHCP_MAGIC.len();
+    loop {
+        match *data.get(offset)? {
+            DHCP_OPTION_PAD => offset += 1,
+            DHCP_OPTION_END => break,
+            code => {
+                let len = *data.get(offset + 1)? as usize;
+                options.push((code, data.get(offset + 2..offset + 2 + len)?));
+                offset += 2 + len;
+            }
+        }
+    }
+    Some(DhcpMessage {
+        htype: data[1],
+        hlen,
+        xid: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        flags: u16::from_be_bytes([data[10], data[11]]),
+        ciaddr: dhcp_ipv4(data, 12),
+        giaddr: dhcp_ipv4(data, 24),
+        chaddr: &data[28..44],
+        options,
+    })
+}
+
+/* RFC 3396: values longer than 255 bytes are sent as several
+ * instances of the option */
+fn dhcp_option(code: u8, value: &[u8]) -> Vec<u8> {
+    let mut option = Vec::new();
+    for chunk in value.chunks(255) {
+        option.push(code);
+        option.push(chunk.len() as u8);
+        option.extend_from_slice(chunk);
+    }
+    if value.is_empty() {
+        option.extend_from_slice(&[code, 0]);
+    }
+    option
+}
+
+fn dhcp_option_addresses(code: u8, addresses: &[String]) -> Vec<u8> {
+    let value: Vec<u8> = addresses
+        .iter()
+        .flat_map(|a| dhcp_address(a).octets())
+        .collect();
+    dhcp_option(code, &value)
+}
+
+fn dhcp_message_type_name(message_type: u8) -> String {
+    match message_type {
+        DHCP_DISCOVER => "discover".to_string(),
+        DHCP_OFFER => "offer".to_string(),
+        DHCP_REQUEST => "request".to_string(),
+        DHCP_DECLINE => "decline".to_string(),
+        DHCP_ACK => "ack".to_string(),
+        DHCP_NAK => "nak".to_string(),
+        DHCP_RELEASE => "release".to_string(),
+        DHCP_INFORM => "inform".to_string(),
+        t => t.to_string(),
+    }
+}
+
+/* address of the pool offered to a client: the same one for a given
+ * client hardware address (FNV-1a hash) */
+fn dhcp_pool_address(config: &DhcpConfig, request: &DhcpMessage) -> Ipv4Addr {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in request.chaddr[..request.hlen as usize].iter() {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    let start = u32::from(dhcp_address(&config.pool_start));
+    Ipv4Addr::from(start.wrapping_add((h % config.pool_size.max(1) as u64) as u32))
+}
+
+/* address replies are sent from: configured, probed, or ours */
+fn dhcp_server(masscanned: &Masscanned, client_info: &ClientInfo) -> Option<Ipv4Addr> {
+    if let Some(server) = &masscanned.config.dhcp.server {
+        return Some(dhcp_address(server));
+    }
+    if let Some(IpAddr::V4(ip)) = client_info.ip.dst {
+        if !ip.is_broadcast() && !ip.is_unspecified() && !ip.is_multicast() {
+            return Some(ip);
+        }
+    }
+    masscanned
+        .ip_addresses?
+        .iter()
+        .filter_map(|ip| match ip {
+            IpAddr::V4(ip) if !ip.is_unspecified() && !ip.is_multicast() => Some(*ip),
+            _ => None,
+        })
+        .min()
+}
+
+fn dhcp_event(request: &DhcpMessage, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("dhcp_request", client_info);
+    if let Some(t) = request.message_type() {
+        event.set("message_type", dhcp_message_type_name(t));
+    }
+    event.set("xid", request.xid);
+    match request.client_mac() {
+        Some(mac) => event.set("client_mac", mac.to_string()),
+        None => event.set(
+            "client_hardware_address",
+            encode(&request.chaddr[..request.hlen as usize], Encoding::Hex),
+        ),
+    }
+    event.set("broadcast", request.flags & BOOTP_FLAG_BROADCAST != 0);
+    if !request.ciaddr.is_unspecified() {
+        event.set("ciaddr", request.ciaddr.to_string());
+    }
+    if !request.giaddr.is_unspecified() {
+        event.set("giaddr", request.giaddr.to_string());
+    }
+    if let Some(hostname) = request.option(DHCP_OPTION_HOSTNAME) {
+        event.set("hostname", String::from_utf8_lossy(&hostname).to_string());
+    }
+    if let Some(list) = request.option(DHCP_OPTION_PARAMETER_LIST) {
+        event.set("parameter_request_list", list);
+    }
+    if let Some(id) = request.option(DHCP_OPTION_CLIENT_ID) {
+        event.set("client_id", encode(&id, Encoding::Hex));
+    }
+    if let Some(vendor) = request.option(DHCP_OPTION_VENDOR_CLASS) {
+        event.set("vendor_class", String::from_utf8_lossy(&vendor).to_string());
+    }
+    if let Some(ip) = request.option_address(DHCP_OPTION_REQUESTED_ADDRESS) {
+        event.set("requested_address", ip.to_string());
+    }
+    if let Some(ip) = request.option_address(DHCP_OPTION_SERVER_ID) {
+        event.set("server_id", ip.to_string());
+    }
+    event
+}
+
+/* RFC 2131 section 4.3.1, table 3 */
+fn dhcp_reply(
+    request: &DhcpMessage,
+    config: &DhcpConfig,
+    message_type: u8,
+    yiaddr: Ipv4Addr,
+    server: Ipv4Addr,
+) -> Vec<u8> {
+    let mut flags = request.flags;
+    /* section 4.3.2: DHCPNAK through a relay */
+    if message_type == DHCP_NAK && !request.giaddr.is_unspecified() {
+        flags |= BOOTP_FLAG_BROADCAST;
+    }
+    let ciaddr = if message_type == DHCP_ACK {
+        request.ciaddr
+    } else {
+        Ipv4Addr::UNSPECIFIED
+    };
+    let mut msg = vec![BOOTP_REPLY, request.htype, request.hlen, 0];
+    msg.extend_from_slice(&request.xid.to_be_bytes());
+    msg.extend_from_slice(&[0, 0]);
+    msg.extend_from_slice(&flags.to_be_bytes());
+    msg.extend_from_slice(&ciaddr.octets());
+    msg.extend_from_slice(&yiaddr.octets());
+    msg.extend_from_slice(&Ipv4Addr::UNSPECIFIED.octets());
+    msg.extend_from_slice(&request.giaddr.octets());
+    msg.extend_from_slice(request.chaddr);
+    /* sname, file */
+    msg.resize(BOOTP_LEN, 0);
+    msg.extend_from_slice(&DHCP_MAGIC);
+    msg.extend(dhcp_option(DHCP_OPTION_MESSAGE_TYPE, &[message_type]));
+    msg.extend(dhcp_option(DHCP_OPTION_SERVER_ID, &server.octets()));
+    if message_type != DHCP_NAK {
+        /* no lease for DHCPINFORM (section 4.3.5) */
+        if !yiaddr.is_unspecified() {
+            let lease = config.lease_time;
+            msg.extend(dhcp_option(DHCP_OPTION_LEASE_TIME, &lease.to_be_bytes()));
+            msg.extend(dhcp_option(
+                DHCP_OPTION_RENEWAL_TIME,
+                &(lease / 2).to_be_bytes(),
+            ));
+            msg.extend(dhcp_option(
+                DHCP_OPTION_REBINDING_TIME,
+                &((lease as u64 * 7 / 8) as u32).to_be_bytes(),
+            ));
+        }
+        msg.extend(dhcp_option(
+            DHCP_OPTION_SUBNET_MASK,
+            &dhcp_address(&config.netmask).octets(),
+        ));
+        if !config.routers.is_empty() {
+            msg.extend(dhcp_option_addresses(DHCP_OPTION_ROUTER, &config.routers));
+        }
+        if !config.dns.is_empty() {
+            msg.extend(dhcp_option_addresses(DHCP_OPTION_DNS, &config.dns));
+        }
+    }
+    msg.push(DHCP_OPTION_END);
+    if msg.len() < BOOTP_MIN_LEN {
+        msg.resize(BOOTP_MIN_LEN, DHCP_OPTION_PAD);
+    }
+    msg
+}
+
+/* RFC 2131 section 4.1: to the relay agent, to the address the client
+ * already has, broadcast (when the client asks for it, and DHCPNAK
+ * messages), or to the address offered and the client hardware
+ * address.
+ **/
+fn dhcp_destination(
+    request: &DhcpMessage,
+    message_type: u8,
+    yiaddr: Ipv4Addr,
+    client_info: &mut ClientInfo,
+) {
+    let (ip, port, mac) = if !request.giaddr.is_unspecified() {
+        (request.giaddr, DHCP_SERVER_PORT, None)
+    } else if message_type == DHCP_NAK {
+        (
+            Ipv4Addr::BROADCAST,
+            DHCP_CLIENT_PORT,
+            Some(MacAddr::broadcast()),
+        )
+    } else if !request.ciaddr.is_unspecified() {
+        (request.ciaddr, DHCP_CLIENT_PORT, None)
+    } else if request.flags & BOOTP_FLAG_BROADCAST != 0 || yiaddr.is_unspecified() {
+        (
+            Ipv4Addr::BROADCAST,
+            DHCP_CLIENT_PORT,
+            Some(MacAddr::broadcast()),
+        )
+    } else {
+        (
+            yiaddr,
+            DHCP_CLIENT_PORT,
+            Some(request.client_mac().unwrap_or_else(MacAddr::broadcast)),
+        )
+    };
+    client_info.ip.src = Some(IpAddr::V4(ip));
+    client_info.port.src = Some(port);
+    if let Some(mac) = mac {
+        client_info.mac.src = Some(mac);
+    }
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving DHCP data");
+    let config = &masscanned.config.dhcp;
+    let request = match dhcp_parse(data) {
+        Some(r) => r,
+        None => {
+            info!("DHCP data not handled (invalid message)");
+            return None;
+        }
+    };
+    dhcp_event(&request, client_info).log();
+    let server = match dhcp_server(masscanned, client_info) {
+        Some(ip) => ip,
+        None => {
+            info!("DHCP message not answered (no server address)");
+            return None;
+        }
+    };
+    let offered = dhcp_pool_address(config, &request);
+    let (message_type, yiaddr) = match request.message_type() {
+        Some(DHCP_DISCOVER) => (DHCP_OFFER, offered),
+        Some(DHCP_REQUEST) => {
+            /* another server was selected (section 4.3.2) */
+            if request
+                .option_address(DHCP_OPTION_SERVER_ID)
+                .is_some_and(|ip| ip != server)
+            {
+                return None;
+            }
+            let requested = request
+                .option_address(DHCP_OPTION_REQUESTED_ADDRESS)
+                .unwrap_or(request.ciaddr);
+            if config.ack && requested == offered {
+                (DHCP_ACK, offered)
+            } else {
+                (DHCP_NAK, Ipv4Addr::UNSPECIFIED)
+            }
+        }
+        Some(DHCP_INFORM) => (DHCP_ACK, Ipv4Addr::UNSPECIFIED),
+        t => {
+            debug!("DHCP message not answered (type {:?})", t);
+            return None;
+        }
+    };
+    let repl_data = dhcp_reply(&request, config, message_type, yiaddr, server);
+    dhcp_destination(&request, message_type, yiaddr, client_info);
+    client_info.ip.dst = Some(IpAddr::V4(server));
+    debug!("sending DHCP data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use std::str::FromStr;
+
+    fn config() -> Config {
+        Config::from_str("[dhcp]\nenabled = true\nserver = \"192.168.1.1\"\n")
+            .expect("error parsing config")
+    }
+
+    /* DHCPDISCOVER from 0.0.0.0:68 to 255.255.255.255:67 */
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::BROADCAST));
+        client_info.port.src = Some(DHCP_CLIENT_PORT);
+        client_info.port.dst = Some(DHCP_SERVER_PORT);
+        client_info.mac.src = Some(MacAddr::new(0x08, 0x00, 0x27, 0x1a, 0x2b, 0x3c));
+        client_info
+    }
+
+    /* message from 08:00:27:1a:2b:3c, as sent by dhclient (hostname,
+     * parameter request list, client identifier) */
+    fn message(message_type: u8, flags: u16, giaddr: [u8; 4], options: &[u8]) -> Vec<u8> {
+        let mut msg = vec![BOOTP_REQUEST, 1, 6, 0, 0x3f, 0x1c, 0x6a, 0x5e, 0, 4];
+        msg.extend_from_slice(&flags.to_be_bytes());
+        msg.extend_from_slice(&[0; 12]);
+        msg.extend_from_slice(&giaddr);
+        msg.extend_from_slice(&[0x08, 0x00, 0x27, 0x1a, 0x2b, 0x3c]);
+        msg.resize(BOOTP_LEN, 0);
+        msg.extend_from_slice(&DHCP_MAGIC);
+        msg.extend_from_slice(&[DHCP_OPTION_MESSAGE_TYPE, 1, message_type]);
+        msg.extend_from_slice(b"\x0c\x06laptop");
+        msg.extend_from_slice(b"\x37\x0d\x01\x1c\x02\x03\x0f\x06\x77\x0c\x2c\x2f\x1a\x79\x2a");
+        msg.extend_from_slice(b"\x3d\x07\x01\x08\x00\x27\x1a\x2b\x3c");
+        msg.extend_from_slice(options);
+        msg.push(DHCP_OPTION_END);
+        msg
+    }
+
+    struct Reply {
+        xid: u32,
+        flags: u16,
+        yiaddr: Ipv4Addr,
+        giaddr: Ipv4Addr,
+        chaddr: Vec<u8>,
+        options: Vec<(u8, Vec<u8>)>,
+    }
+
+    fn reply(data: &[u8]) -> Reply {
+        assert!(data.len() >= BOOTP_MIN_LEN);
+        assert!(data[0] == BOOTP_REPLY && data[1] == 1 && data[2] == 6);
+        assert!(data[BOOTP_LEN..BOOTP_LEN + 4] == DHCP_MAGIC);
+        let mut options = Vec::new();
+        let mut offset = BOOTP_LEN + 4;
+        while data[offset] != DHCP_OPTION_END {
+            let len = data[offset + 1] as usize;
+            options.push((data[offset], data[offset + 2..offset + 2 + len].to_vec()));
+            offset += 2 + len;
+        }
+        assert!(data[offset + 1..].iter().all(|b| *b == DHCP_OPTION_PAD));
+        Reply {
+            xid: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            flags: u16::from_be_bytes([data[10], data[11]]),
+            yiaddr: dhcp_ipv4(data, 16),
+            giaddr: dhcp_ipv4(data, 24),
+            chaddr: data[28..44].to_vec(),
+            options,
+        }
+    }
+
+    fn option(reply: &Reply, code: u8) -> Option<&[u8]> {
+        reply
+            .options
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    #[test]
+    fn test_dhcp_option() {
+        assert!(dhcp_option(DHCP_OPTION_MESSAGE_TYPE, &[DHCP_OFFER]) == b"\x35\x01\x02");
+        assert!(dhcp_option(DHCP_OPTION_HOSTNAME, b"") == b"\x0c\x00");
+        assert!(
+            dhcp_option_addresses(
+                DHCP_OPTION_DNS,
+                &["192.0.2.53".to_string(), "198.51.100.53".to_string()]
+            ) == b"\x06\x08\xc0\x00\x02\x35\xc6\x33\x64\x35"
+        );
+        /* long values are split, and concatenated when parsed */
+        let value: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let option = dhcp_option(DHCP_OPTION_VENDOR_CLASS, &value);
+        assert!(option.len() == 304);
+        assert!(option[..2] == [DHCP_OPTION_VENDOR_CLASS, 255]);
+        assert!(option[257..259] == [DHCP_OPTION_VENDOR_CLASS, 45]);
+        let mut data = message(DHCP_DISCOVER, 0, [0; 4], &option);
+        let request = dhcp_parse(&data).unwrap();
+        assert!(request.option(DHCP_OPTION_VENDOR_CLASS) == Some(value));
+        assert!(request.option(DHCP_OPTION_HOSTNAME) == Some(b"laptop".to_vec()));
+        assert!(request.message_type() == Some(DHCP_DISCOVER));
+        assert!(request.client_mac() == Some(MacAddr::new(0x08, 0x00, 0x27, 0x1a, 0x2b, 0x3c)));
+        /* truncated options, no end, BOOTP without the cookie */
+        data.truncate(data.len() - 10);
+        assert!(dhcp_parse(&data).is_none());
+        data.truncate(BOOTP_LEN + 4);
+        assert!(dhcp_parse(&data).is_none());
+        assert!(dhcp_parse(&data[..BOOTP_LEN]).is_none());
+    }
+
+    #[test]
+    fn test_dhcp_discover() {
+        let masscanned = masscanned(config());
+        /* broadcast flag: broadcast reply */
+        let mut client_info = client();
+        let data = message(DHCP_DISCOVER, BOOTP_FLAG_BROADCAST, [0; 4], b"");
+        let r = reply(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(r.xid == 0x3f1c6a5e && r.flags == BOOTP_FLAG_BROADCAST);
+        assert!(r.chaddr[..6] == [0x08, 0x00, 0x27, 0x1a, 0x2b, 0x3c]);
+        let pool = u32::from(Ipv4Addr::new(192, 168, 1, 100));
+        assert!((pool..pool + 100).contains(&u32::from(r.yiaddr)));
+        assert!(option(&r, DHCP_OPTION_MESSAGE_TYPE) == Some(&[DHCP_OFFER][..]));
+        assert!(option(&r, DHCP_OPTION_SERVER_ID) == Some(&[192, 168, 1, 1][..]));
+        assert!(option(&r, DHCP_OPTION_SUBNET_MASK) == Some(&[255, 255, 255, 0][..]));
+        assert!(option(&r, DHCP_OPTION_ROUTER) == Some(&[192, 168, 1, 1][..]));
+        assert!(option(&r, DHCP_OPTION_DNS) == Some(&[192, 168, 1, 1][..]));
+        assert!(option(&r, DHCP_OPTION_LEASE_TIME) == Some(&3600u32.to_be_bytes()[..]));
+        assert!(option(&r, DHCP_OPTION_RENEWAL_TIME) == Some(&1800u32.to_be_bytes()[..]));
+        assert!(option(&r, DHCP_OPTION_REBINDING_TIME) == Some(&3150u32.to_be_bytes()[..]));
+        assert!(client_info.ip.src == Some(IpAddr::V4(Ipv4Addr::BROADCAST)));
+        assert!(client_info.ip.dst == Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))));
+        assert!(client_info.port.src == Some(DHCP_CLIENT_PORT));
+        assert!(client_info.mac.src == Some(MacAddr::broadcast()));
+        /* no broadcast flag: unicast to the address offered */
+        let mut client_info = client();
+        let data = message(DHCP_DISCOVER, 0, [0; 4], b"");
+        let r2 = reply(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(r2.yiaddr == r.yiaddr && r2.flags == 0);
+        assert!(client_info.ip.src == Some(IpAddr::V4(r.yiaddr)));
+        assert!(client_info.mac.src == Some(MacAddr::new(0x08, 0x00, 0x27, 0x1a, 0x2b, 0x3c)));
+        /* through a relay agent */
+        let mut client_info = client();
+        let data = message(DHCP_DISCOVER, BOOTP_FLAG_BROADCAST, [10, 0, 0, 1], b"");
+        let r3 = reply(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(r3.giaddr == Ipv4Addr::new(10, 0, 0, 1));
+        assert!(client_info.ip.src == Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+        assert!(client_info.port.src == Some(DHCP_SERVER_PORT));
+        /* the server address: probed, or none */
+        let mut masscanned = masscanned;
+        masscanned.config.dhcp.server = None;
+        let mut client_info = client();
+        assert!(repl(&data, &masscanned, &mut client_info).is_none());
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 67)));
+        let r4 = reply(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(option(&r4, DHCP_OPTION_SERVER_ID) == Some(&[192, 0, 2, 67][..]));
+    }
+
+    #[test]
+    fn test_dhcp_request() {
+        let mut masscanned = masscanned(config());
+        let mut client_info = client();
+        let data = message(DHCP_DISCOVER, 0, [0; 4], b"");
+        let offered = reply(&repl(&data, &masscanned, &mut client_info).unwrap()).yiaddr;
+        let mut options = vec![DHCP_OPTION_REQUESTED_ADDRESS, 4];
+        options.extend_from_slice(&offered.octets());
+        options.extend_from_slice(&[DHCP_OPTION_SERVER_ID, 4, 192, 168, 1, 1]);
+        let data = message(DHCP_REQUEST, 0, [0; 4], &options);
+        /* DHCPNAK by default, broadcast */
+        let mut client_info = client();
+        let r = reply(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(r.yiaddr.is_unspecified());
+        assert!(option(&r, DHCP_OPTION_MESSAGE_TYPE) == Some(&[DHCP_NAK][..]));
+        assert!(option(&r, DHCP_OPTION_LEASE_TIME).is_none());
+        assert!(client_info.ip.src == Some(IpAddr::V4(Ipv4Addr::BROADCAST)));
+        assert!(client_info.mac.src == Some(MacAddr::broadcast()));
+        /* through a relay agent: broadcast flag set */
+        let relayed = message(DHCP_REQUEST, 0, [10, 0, 0, 1], &options);
+        let mut client_info = client();
+        let r = reply(&repl(&relayed, &masscanned, &mut client_info).unwrap());
+        assert!(r.flags == BOOTP_FLAG_BROADCAST);
+        assert!(client_info.ip.src == Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+        /* DHCPACK when configured, for the address offered only */
+        masscanned.config.dhcp.ack = true;
+        let mut client_info = client();
+        let r = reply(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(option(&r, DHCP_OPTION_MESSAGE_TYPE) == Some(&[DHCP_ACK][..]));
+        assert!(r.yiaddr == offered);
+        let other = message(DHCP_REQUEST, 0, [0; 4], b"\x32\x04\xc0\xa8\x01\x02");
+        let r = reply(&repl(&other, &masscanned, &mut client()).unwrap());
+        assert!(option(&r, DHCP_OPTION_MESSAGE_TYPE) == Some(&[DHCP_NAK][..]));
+        /* another server selected, release: no answer */
+        let other = message(DHCP_REQUEST, 0, [0; 4], b"\x36\x04\xc0\xa8\x01\x02");
+        assert!(repl(&other, &masscanned, &mut client()).is_none());
+        let release = message(DHCP_RELEASE, 0, [0; 4], b"");
+        assert!(repl(&release, &masscanned, &mut client()).is_none());
+        /* invalid configurations */
+        for config in [
+            "[dhcp]\npool_start = \"192.168.1\"\n",
+            "[dhcp]\nrouters = [\"::1\"]\n",
+            "[dhcp]\npool_size = 0\n",
+            "[dhcp]\npool_start = \"255.255.255.250\"\npool_size = 10\n",
+        ]
+        .iter()
+        {
+            assert!(Config::from_str(config).is_err());
+        }
+    }
+}
@@ -0,0 +1,754 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::convert::TryFrom;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr};
+
+use log::*;
+use pnet::util::MacAddr;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::Masscanned;
+
+/* RFC 8415 */
+const DHCPV6_SERVER_PORT: u16 = 547;
+/* All_DHCP_Relay_Agents_and_Servers */
+const DHCPV6_GROUP: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 2);
+/* section 7.6: HOP_COUNT_LIMIT */
+const DHCPV6_HOP_COUNT_LIMIT: u8 = 8;
+
+const DHCPV6_SOLICIT: u8 = 1;
+const DHCPV6_ADVERTISE: u8 = 2;
+const DHCPV6_REQUEST: u8 = 3;
+const DHCPV6_CONFIRM: u8 = 4;
+const DHCPV6_RENEW: u8 = 5;
+const DHCPV6_REBIND: u8 = 6;
+const DHCPV6_REPLY: u8 = 7;
+const DHCPV6_RELEASE: u8 = 8;
+const DHCPV6_DECLINE: u8 = 9;
+const DHCPV6_INFORMATION_REQUEST: u8 = 11;
+const DHCPV6_RELAY_FORW: u8 = 12;
+const DHCPV6_RELAY_REPL: u8 = 13;
+
+const DHCPV6_OPTION_CLIENTID: u16 = 1;
+const DHCPV6_OPTION_SERVERID: u16 = 2;
+const DHCPV6_OPTION_IA_NA: u16 = 3;
+const DHCPV6_OPTION_IAADDR: u16 = 5;
+const DHCPV6_OPTION_ORO: u16 = 6;
+const DHCPV6_OPTION_RELAY_MSG: u16 = 9;
+const DHCPV6_OPTION_STATUS_CODE: u16 = 13;
+const DHCPV6_OPTION_INTERFACE_ID: u16 = 18;
+const DHCPV6_OPTION_DNS_SERVERS: u16 = 23;
+const DHCPV6_OPTION_IA_PD: u16 = 25;
+
+const DHCPV6_STATUS_NOADDRSAVAIL: u16 = 2;
+const DHCPV6_STATUS_NOPREFIXAVAIL: u16 = 6;
+
+/* section 11.2: DUID-LLT, DUID-LL (with an Ethernet address) */
+const DUID_LLT: u16 = 1;
+const DUID_LL: u16 = 3;
+const DUID_HW_ETHERNET: u16 = 1;
+
+/* DHCPv6 server (on the UDP ports, to ff02::1:2 or to our addresses,
+ * disabled by default): Solicit messages get an Advertise of address
+ * for their IA_NA (IA_PD get NoPrefixAvail), Request, Renew and Rebind
+ * messages a Reply with NoAddrsAvail (so that no client keeps the
+ * address), or with the address when assign is set, and
+ * Information-Request messages a Reply with the DNS servers. Messages
+ * forwarded by relay agents are answered through them.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Dhcpv6Config {
+    pub enabled: bool,
+    pub ports: Vec<u16>,
+    /* Server Identifier, in hex (DUID-LL of the interface when not
+     * set) */
+    pub server_duid: Option<String>,
+    pub address: String,
+    pub preferred_lifetime: u32,
+    pub valid_lifetime: u32,
+    pub dns: Vec<String>,
+    pub assign: bool,
+}
+
+impl Default for Dhcpv6Config {
+    fn default() -> Self {
+        Dhcpv6Config {
+            enabled: false,
+            ports: vec![DHCPV6_SERVER_PORT],
+            server_duid: None,
+            address: "2001:db8::100".to_string(),
+            preferred_lifetime: 3600,
+            valid_lifetime: 7200,
+            dns: vec!["2001:db8::53".to_string()],
+            assign: false,
+        }
+    }
+}
+
+fn dhcpv6_invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("dhcpv6: {}", msg))
+}
+
+fn dhcpv6_from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+impl Dhcpv6Config {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if let Some(duid) = &self.server_duid {
+            /* section 11.1: a type, and up to 128 bytes */
+            match dhcpv6_from_hex(duid) {
+                Some(d) if (3..=130).contains(&d.len()) => {}
+                _ => return Err(dhcpv6_invalid(format!("invalid server DUID: {}", duid))),
+            }
+        }
+        for address in std::iter::once(&self.address).chain(self.dns.iter()) {
+            if address.parse::<Ipv6Addr>().is_err() {
+                return Err(dhcpv6_invalid(format!(
+                    "invalid IPv6 address: {:?}",
+                    address
+                )));
+            }
+        }
+        if self.preferred_lifetime > self.valid_lifetime {
+            return Err(dhcpv6_invalid(
+                "preferred_lifetime greater than valid_lifetime".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* address of the configuration (checked) */
+fn dhcpv6_address(value: &str) -> Ipv6Addr {
+    value.parse().unwrap_or(Ipv6Addr::UNSPECIFIED)
+}
+
+/* DHCPv6 message to the servers group, or to us */
+pub fn is_dhcpv6(masscanned: &Masscanned, client_info: &ClientInfo) -> bool {
+    let config = &masscanned.config.dhcpv6;
+    config.enabled
+        && client_info
+            .port
+            .dst
+            .is_some_and(|p| config.ports.contains(&p))
+        && match client_info.ip.dst {
+            Some(IpAddr::V6(ip)) => !ip.is_multicast() || ip == DHCPV6_GROUP,
+            _ => false,
+        }
+}
+
+/* options of a message (section 21.1) */
+fn dhcpv6_options(mut data: &[u8]) -> Option<Vec<(u16, &[u8])>> {
+    let mut options = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 4 {
+            return None;
+        }
+        let code = u16::from_be_bytes([data[0], data[1]]);
+        let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        options.push((code, data.get(4..4 + len)?));
+        data = &data[4 + len..];
+    }
+    Some(options)
+}
+
+fn dhcpv6_option(code: u16, value: &[u8]) -> Vec<u8> {
+    let mut option = code.to_be_bytes().to_vec();
+    option.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    option.extend_from_slice(value);
+    option
+}
+
+fn dhcpv6_find<'a>(options: &[(u16, &'a [u8])], code: u16) -> Option<&'a [u8]> {
+    options.iter().find(|(c, _)| *c == code).map(|(_, v)| *v)
+}
+
+struct Dhcpv6Message<'a> {
+    msg_type: u8,
+    xid: [u8; 3],
+    options: Vec<(u16, &'a [u8])>,
+}
+
+fn dhcpv6_parse(data: &[u8]) -> Option<Dhcpv6Message<'_>> {
+    if data.len() < 4 {
+        return None;
+    }
+    Some(Dhcpv6Message {
+        msg_type: data[0],
+        xid: [data[1], data[2], data[3]],
+        options: dhcpv6_options(&data[4..])?,
+    })
+}
+
+/* Relay-forward message (section 9) */
+struct Dhcpv6Relay<'a> {
+    hop_count: u8,
+    link_address: &'a [u8],
+    peer_address: &'a [u8],
+    interface_id: Option<&'a [u8]>,
+    message: &'a [u8],
+}
+
+fn dhcpv6_parse_relay(data: &[u8]) -> Option<Dhcpv6Relay<'_>> {
+    if data.len() < 34 || data[0] != DHCPV6_RELAY_FORW {
+        return None;
+    }
+    let options = dhcpv6_options(&data[34..])?;
+    Some(Dhcpv6Relay {
+        hop_count: data[1],
+        link_address: &data[2..18],
+        peer_address: &data[18..34],
+        interface_id: dhcpv6_find(&options, DHCPV6_OPTION_INTERFACE_ID),
+        message: dhcpv6_find(&options, DHCPV6_OPTION_RELAY_MSG)?,
+    })
+}
+
+/* section 21.4: IAID, T1, T2, options (IA_NA and IA_PD) */
+fn dhcpv6_iaid(ia: &[u8]) -> Option<u32> {
+    if ia.len() < 12 {
+        return None;
+    }
+    Some(u32::from_be_bytes([ia[0], ia[1], ia[2], ia[3]]))
+}
+
+fn dhcpv6_iaids(message: &Dhcpv6Message, code: u16) -> Vec<u32> {
+    message
+        .options
+        .iter()
+        .filter(|(c, _)| *c == code)
+        .filter_map(|(_, v)| dhcpv6_iaid(v))
+        .collect()
+}
+
+fn dhcpv6_oro(message: &Dhcpv6Message) -> Vec<u16> {
+    dhcpv6_find(&message.options, DHCPV6_OPTION_ORO)
+        .unwrap_or(b"")
+        .chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .collect()
+}
+
+/* Ethernet address of a DUID-LLT or DUID-LL */
+fn dhcpv6_duid_mac(duid: &[u8]) -> Option<MacAddr> {
+    if duid.len() < 4 || u16::from_be_bytes([duid[2], duid[3]]) != DUID_HW_ETHERNET {
+        return None;
+    }
+    let mac = match u16::from_be_bytes([duid[0], duid[1]]) {
+        DUID_LLT => duid.get(8..)?,
+        DUID_LL => duid.get(4..)?,
+        _ => return None,
+    };
+    match mac {
+        [a, b, c, d, e, f] => Some(MacAddr::new(*a, *b, *c, *d, *e, *f)),
+        _ => None,
+    }
+}
+
+fn dhcpv6_server_duid(masscanned: &Masscanned) -> Vec<u8> {
+    if let Some(duid) = masscanned
+        .config
+        .dhcpv6
+        .server_duid
+        .as_ref()
+        .and_then(|d| dhcpv6_from_hex(d))
+    {
+        return duid;
+    }
+    let mut duid = DUID_LL.to_be_bytes().to_vec();
+    duid.extend_from_slice(&DUID_HW_ETHERNET.to_be_bytes());
+    duid.extend_from_slice(&<[u8; 6]>::from(masscanned.mac));
+    duid
+}
+
+/* address replies to the group are sent from: our link-local address
+ * (configured, or derived from the MAC address - RFC 4291 appendix A),
+ * or another one */
+fn dhcpv6_source(masscanned: &Masscanned) -> Ipv6Addr {
+    let mut ours: Vec<Ipv6Addr> = masscanned
+        .ip_addresses
+        .into_iter()
+        .flatten()
+        .filter_map(|ip| match ip {
+            IpAddr::V6(ip) if !ip.is_multicast() && !ip.is_unspecified() => Some(*ip),
+            _ => None,
+        })
+        .collect();
+    ours.sort_by_key(|ip| (ip.segments()[0] & 0xffc0 != 0xfe80, *ip));
+    if let Some(ip) = ours.first() {
+        return *ip;
+    }
+    let mac = <[u8; 6]>::from(masscanned.mac);
+    Ipv6Addr::from([
+        0xfe,
+        0x80,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ])
+}
+
+fn dhcpv6_message_type_name(msg_type: u8) -> String {
+    match msg_type {
+        DHCPV6_SOLICIT => "solicit".to_string(),
+        DHCPV6_ADVERTISE => "advertise".to_string(),
+        DHCPV6_REQUEST => "request".to_string(),
+        DHCPV6_CONFIRM => "confirm".to_string(),
+        DHCPV6_RENEW => "renew".to_string(),
+        DHCPV6_REBIND => "rebind".to_string(),
+        DHCPV6_REPLY => "reply".to_string(),
+        DHCPV6_RELEASE => "release".to_string(),
+        DHCPV6_DECLINE => "decline".to_string(),
+        DHCPV6_INFORMATION_REQUEST => "information-request".to_string(),
+        t => t.to_string(),
+    }
+}
+
+fn dhcpv6_event(
+    message: &Dhcpv6Message,
+    relays: &[Dhcpv6Relay],
+    client_info: &ClientInfo,
+) -> Event {
+    let mut event = Event::new("dhcpv6_request", client_info);
+    event.set("message_type", dhcpv6_message_type_name(message.msg_type));
+    event.set("xid", encode(&message.xid, Encoding::Hex));
+    if let Some(duid) = dhcpv6_find(&message.options, DHCPV6_OPTION_CLIENTID) {
+        event.set("client_duid", encode(duid, Encoding::Hex));
+        if let Some(mac) = dhcpv6_duid_mac(duid) {
+            event.set("client_mac", mac.to_string());
+        }
+    }
+    if let Some(duid) = dhcpv6_find(&message.options, DHCPV6_OPTION_SERVERID) {
+        event.set("server_duid", encode(duid, Encoding::Hex));
+    }
+    event.set("ia_na", dhcpv6_iaids(message, DHCPV6_OPTION_IA_NA));
+    event.set("ia_pd", dhcpv6_iaids(message, DHCPV6_OPTION_IA_PD));
+    event.set("option_request", dhcpv6_oro(message));
+    if let Some(relay) = relays.last() {
+        /* the relay agent closest to the client */
+        if let Ok(peer) = <[u8; 16]>::try_from(relay.peer_address) {
+            event.set("peer_address", Ipv6Addr::from(peer).to_string());
+        }
+        if let Ok(link) = <[u8; 16]>::try_from(relay.link_address) {
+            event.set("link_address", Ipv6Addr::from(link).to_string());
+        }
+        event.set("relays", relays.len());
+    }
+    event
+}
+
+fn dhcpv6_status(code: u16, message: &str) -> Vec<u8> {
+    let mut status = code.to_be_bytes().to_vec();
+    status.extend_from_slice(message.as_bytes());
+    dhcpv6_option(DHCPV6_OPTION_STATUS_CODE, &status)
+}
+
+/* IA_NA with the address (T1 and T2 as recommended in section 21.4),
+ * or with a status when addresses are not assigned */
+fn dhcpv6_ia_na(config: &Dhcpv6Config, iaid: u32, assign: bool) -> Vec<u8> {
+    let mut ia = iaid.to_be_bytes().to_vec();
+    if assign {
+        let preferred = config.preferred_lifetime;
+        ia.extend_from_slice(&(preferred / 2).to_be_bytes());
+        ia.extend_from_slice(&((preferred as u64 * 4 / 5) as u32).to_be_bytes());
+        let mut address = dhcpv6_address(&config.address).octets().to_vec();
+        address.extend_from_slice(&preferred.to_be_bytes());
+        address.extend_from_slice(&config.valid_lifetime.to_be_bytes());
+        ia.extend(dhcpv6_option(DHCPV6_OPTION_IAADDR, &address));
+    } else {
+        ia.extend_from_slice(&[0; 8]);
+        ia.extend(dhcpv6_status(
+            DHCPV6_STATUS_NOADDRSAVAIL,
+            "No addresses available",
+        ));
+    }
+    dhcpv6_option(DHCPV6_OPTION_IA_NA, &ia)
+}
+
+fn dhcpv6_ia_pd(iaid: u32) -> Vec<u8> {
+    let mut ia = iaid.to_be_bytes().to_vec();
+    ia.extend_from_slice(&[0; 8]);
+    ia.extend(dhcpv6_status(
+        DHCPV6_STATUS_NOPREFIXAVAIL,
+        "No prefixes available",
+    ));
+    dhcpv6_option(DHCPV6_OPTION_IA_PD, &ia)
+}
+
+fn dhcpv6_dns(config: &Dhcpv6Config) -> Vec<u8> {
+    let servers: Vec<u8> = config
+        .dns
+        .iter()
+        .flat_map(|a| dhcpv6_address(a).octets())
+        .collect();
+    dhcpv6_option(DHCPV6_OPTION_DNS_SERVERS, &servers)
+}
+
+/* answer to a message from a client (sections 18.3.1, 18.3.2, 18.3.4,
+ * 18.3.5 and 18.3.6) */
+fn dhcpv6_answer(message: &Dhcpv6Message, masscanned: &Masscanned) -> Option<Vec<u8>> {
+    let config = &masscanned.config.dhcpv6;
+    let server_duid = dhcpv6_server_duid(masscanned);
+    let client_duid = dhcpv6_find(&message.options, DHCPV6_OPTION_CLIENTID);
+    let server = dhcpv6_find(&message.options, DHCPV6_OPTION_SERVERID);
+    let (msg_type, assign) = match message.msg_type {
+        DHCPV6_SOLICIT if server.is_none() => (DHCPV6_ADVERTISE, true),
+        DHCPV6_REQUEST | DHCPV6_RENEW if server == Some(server_duid.as_slice()) => {
+            (DHCPV6_REPLY, config.assign)
+        }
+        DHCPV6_REBIND if server.is_none() => (DHCPV6_REPLY, config.assign),
+        DHCPV6_INFORMATION_REQUEST
+            if server.is_none() || server == Some(server_duid.as_slice()) =>
+        {
+            (DHCPV6_REPLY, false)
+        }
+        t => {
+            debug!(
+                "DHCPv6 message not answered ({})",
+                dhcpv6_message_type_name(t)
+            );
+            return None;
+        }
+    };
+    let mut repl = vec![msg_type];
+    repl.extend_from_slice(&message.xid);
+    repl.extend(dhcpv6_option(DHCPV6_OPTION_SERVERID, &server_duid));
+    match client_duid {
+        Some(duid) => repl.extend(dhcpv6_option(DHCPV6_OPTION_CLIENTID, duid)),
+        /* the Client Identifier is only optional in Information-Request */
+        None if message.msg_type != DHCPV6_INFORMATION_REQUEST => return None,
+        None => {}
+    }
+    if message.msg_type == DHCPV6_INFORMATION_REQUEST {
+        repl.extend(dhcpv6_dns(config));
+        return Some(repl);
+    }
+    for iaid in dhcpv6_iaids(message, DHCPV6_OPTION_IA_NA) {
+        repl.extend(dhcpv6_ia_na(config, iaid, assign));
+    }
+    for iaid in dhcpv6_iaids(message, DHCPV6_OPTION_IA_PD) {
+        repl.extend(dhcpv6_ia_pd(iaid));
+    }
+    if dhcpv6_oro(message).contains(&DHCPV6_OPTION_DNS_SERVERS) {
+        repl.extend(dhcpv6_dns(config));
+    }
+    Some(repl)
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving DHCPv6 data");
+    /* section 19.1: messages forwarded by relay agents, possibly
+     * several */
+    let mut relays = Vec::new();
+    let mut inner = data;
+    while inner.first() == Some(&DHCPV6_RELAY_FORW) {
+        match dhcpv6_parse_relay(inner) {
+            Some(r) if relays.len() <= DHCPV6_HOP_COUNT_LIMIT as usize => {
+                inner = r.message;
+                relays.push(r);
+            }
+            _ => {
+                info!("DHCPv6 data not handled (invalid relay message)");
+                return None;
+            }
+        }
+    }
+    let message = match dhcpv6_parse(inner) {
+        Some(m) if m.msg_type != DHCPV6_RELAY_REPL => m,
+        _ => {
+            info!("DHCPv6 data not handled (invalid message)");
+            return None;
+        }
+    };
+    dhcpv6_event(&message, &relays, client_info).log();
+    let mut repl_data = dhcpv6_answer(&message, masscanned)?;
+    /* section 19.3: the answer is wrapped in as many Relay-reply
+     * messages, the outermost to the relay agent that sent the message */
+    for relay in relays.iter().rev() {
+        let mut wrapped = vec![DHCPV6_RELAY_REPL, relay.hop_count];
+        wrapped.extend_from_slice(relay.link_address);
+        wrapped.extend_from_slice(relay.peer_address);
+        if let Some(id) = relay.interface_id {
+            wrapped.extend(dhcpv6_option(DHCPV6_OPTION_INTERFACE_ID, id));
+        }
+        wrapped.extend(dhcpv6_option(DHCPV6_OPTION_RELAY_MSG, &repl_data));
+        repl_data = wrapped;
+    }
+    if client_info.ip.dst.is_some_and(|ip| ip.is_multicast()) {
+        client_info.ip.dst = Some(IpAddr::V6(dhcpv6_source(masscanned)));
+    }
+    debug!("sending DHCPv6 data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use std::str::FromStr;
+
+    fn config() -> Config {
+        Config::from_str("[dhcpv6]\nenabled = true\n").expect("error parsing config")
+    }
+
+    /* from fe80::a00:27ff:fe1a:2b3c, port 546, to ff02::1:2 */
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.ip.src = Some(IpAddr::from_str("fe80::a00:27ff:fe1a:2b3c").unwrap());
+        client_info.ip.dst = Some(IpAddr::V6(DHCPV6_GROUP));
+        client_info.port.src = Some(546);
+        client_info.port.dst = Some(DHCPV6_SERVER_PORT);
+        client_info
+    }
+
+    /* DUID-LLT of 08:00:27:1a:2b:3c */
+    const CLIENT_DUID: &[u8] = b"\x00\x01\x00\x01\x2b\x3c\x4d\x5e\x08\x00\x27\x1a\x2b\x3c";
+    /* Solicit, as sent by dhclient -6 (Client Identifier, Option
+     * Request for DNS servers and domain list, Elapsed Time, IA_NA) */
+    const DHCLIENT_SOLICIT: &[u8] = b"\x01\x5a\x1b\x2c\x00\x01\x00\x0e\x00\x01\x00\x01\x2b\x3c\x4d\x5e\x08\x00\x27\x1a\x2b\x3c\x00\x06\x00\x04\x00\x17\x00\x18\x00\x08\x00\x02\x00\x00\x00\x03\x00\x0c\x27\x1a\x2b\x3c\x00\x00\x0e\x10\x00\x00\x15\x18";
+    /* DUID-LL of 00:11:22:33:44:55 */
+    const SERVER_DUID: &[u8] = b"\x00\x03\x00\x01\x00\x11\x22\x33\x44\x55";
+
+    /* message of the given type, with the client and server
+     * identifiers and the options */
+    fn message(msg_type: u8, server: Option<&[u8]>, options: &[u8]) -> Vec<u8> {
+        let mut msg = vec![msg_type, 0x5a, 0x1b, 0x2d];
+        msg.extend(dhcpv6_option(DHCPV6_OPTION_CLIENTID, CLIENT_DUID));
+        if let Some(duid) = server {
+            msg.extend(dhcpv6_option(DHCPV6_OPTION_SERVERID, duid));
+        }
+        msg.extend_from_slice(options);
+        msg
+    }
+
+    fn ia_na(iaid: u32) -> Vec<u8> {
+        let mut ia = iaid.to_be_bytes().to_vec();
+        ia.extend_from_slice(&[0; 8]);
+        dhcpv6_option(DHCPV6_OPTION_IA_NA, &ia)
+    }
+
+    #[test]
+    fn test_dhcpv6_solicit() {
+        let masscanned = masscanned(config());
+        let mut client_info = client();
+        let repl_data = repl(DHCLIENT_SOLICIT, &masscanned, &mut client_info).unwrap();
+        let advertise = dhcpv6_parse(&repl_data).unwrap();
+        assert!(advertise.msg_type == DHCPV6_ADVERTISE && advertise.xid == [0x5a, 0x1b, 0x2c]);
+        /* Client Identifier echoed, our Server Identifier */
+        assert!(dhcpv6_find(&advertise.options, DHCPV6_OPTION_CLIENTID) == Some(CLIENT_DUID));
+        assert!(dhcpv6_find(&advertise.options, DHCPV6_OPTION_SERVERID) == Some(SERVER_DUID));
+        /* the address of the IA_NA */
+        let ia = dhcpv6_find(&advertise.options, DHCPV6_OPTION_IA_NA).unwrap();
+        assert!(ia[..12] == b"\x27\x1a\x2b\x3c\x00\x00\x07\x08\x00\x00\x0b\x40"[..]);
+        let ia_options = dhcpv6_options(&ia[12..]).unwrap();
+        let address = dhcpv6_find(&ia_options, DHCPV6_OPTION_IAADDR).unwrap();
+        assert!(
+            Ipv6Addr::from(<[u8; 16]>::try_from(&address[..16]).unwrap())
+                == dhcpv6_address("2001:db8::100")
+        );
+        assert!(address[16..] == b"\x00\x00\x0e\x10\x00\x00\x1c\x20"[..]);
+        /* DNS servers (requested) */
+        assert!(
+            dhcpv6_find(&advertise.options, DHCPV6_OPTION_DNS_SERVERS)
+                == Some(&dhcpv6_address("2001:db8::53").octets()[..])
+        );
+        /* sent from our link-local address (from the MAC address) */
+        assert!(client_info.ip.dst == Some(IpAddr::from_str("fe80::211:22ff:fe33:4455").unwrap()));
+        assert!(client_info.ip.src == Some(IpAddr::from_str("fe80::a00:27ff:fe1a:2b3c").unwrap()));
+        /* the DUID gives the MAC address of the client */
+        assert!(
+            dhcpv6_duid_mac(CLIENT_DUID) == Some(MacAddr::new(0x08, 0x00, 0x27, 0x1a, 0x2b, 0x3c))
+        );
+        assert!(dhcpv6_duid_mac(b"\x00\x02\x00\x00\x01\x37\x01").is_none());
+        /* IA_PD: no prefix */
+        let mut pd = 7u32.to_be_bytes().to_vec();
+        pd.extend_from_slice(&[0; 8]);
+        let data = message(
+            DHCPV6_SOLICIT,
+            None,
+            &dhcpv6_option(DHCPV6_OPTION_IA_PD, &pd),
+        );
+        let repl_data = repl(&data, &masscanned, &mut client()).unwrap();
+        let advertise = dhcpv6_parse(&repl_data).unwrap();
+        let ia = dhcpv6_find(&advertise.options, DHCPV6_OPTION_IA_PD).unwrap();
+        let status = dhcpv6_find(
+            &dhcpv6_options(&ia[12..]).unwrap(),
+            DHCPV6_OPTION_STATUS_CODE,
+        )
+        .unwrap();
+        assert!(status[..2] == DHCPV6_STATUS_NOPREFIXAVAIL.to_be_bytes());
+        assert!(dhcpv6_find(&advertise.options, DHCPV6_OPTION_DNS_SERVERS).is_none());
+        /* configured DUID, addresses */
+        let masscanned = self::masscanned(
+            Config::from_str(
+                "[dhcpv6]\nenabled = true\nserver_duid = \"0002000001370102\"\naddress = \"2001:db8:5::1\"\n",
+            )
+            .expect("error parsing config"),
+        );
+        let repl_data = repl(DHCLIENT_SOLICIT, &masscanned, &mut client()).unwrap();
+        let advertise = dhcpv6_parse(&repl_data).unwrap();
+        assert!(
+            dhcpv6_find(&advertise.options, DHCPV6_OPTION_SERVERID)
+                == Some(&b"\x00\x02\x00\x00\x01\x37\x01\x02"[..])
+        );
+        /* truncated messages (but at option boundaries) */
+        for i in 0..DHCLIENT_SOLICIT.len() {
+            if ![4, 22, 30, 36].contains(&i) {
+                assert!(repl(&DHCLIENT_SOLICIT[..i], &masscanned, &mut client()).is_none());
+            }
+        }
+        /* without a Client Identifier */
+        assert!(repl(&DHCLIENT_SOLICIT[..4], &masscanned, &mut client()).is_none());
+        /* invalid configurations */
+        for config in [
+            "[dhcpv6]\nserver_duid = \"0003\"\n",
+            "[dhcpv6]\naddress = \"192.0.2.1\"\n",
+            "[dhcpv6]\ndns = [\"2001:db8::g\"]\n",
+            "[dhcpv6]\npreferred_lifetime = 10\nvalid_lifetime = 5\n",
+        ]
+        .iter()
+        {
+            assert!(Config::from_str(config).is_err());
+        }
+    }
+
+    #[test]
+    fn test_dhcpv6_request() {
+        let mut masscanned = masscanned(config());
+        let request = message(DHCPV6_REQUEST, Some(SERVER_DUID), &ia_na(1));
+        /* NoAddrsAvail by default */
+        let repl_data = repl(&request, &masscanned, &mut client()).unwrap();
+        let reply = dhcpv6_parse(&repl_data).unwrap();
+        assert!(reply.msg_type == DHCPV6_REPLY && reply.xid == [0x5a, 0x1b, 0x2d]);
+        assert!(dhcpv6_find(&reply.options, DHCPV6_OPTION_CLIENTID) == Some(CLIENT_DUID));
+        let ia = dhcpv6_find(&reply.options, DHCPV6_OPTION_IA_NA).unwrap();
+        let ia_options = dhcpv6_options(&ia[12..]).unwrap();
+        assert!(dhcpv6_find(&ia_options, DHCPV6_OPTION_IAADDR).is_none());
+        let status = dhcpv6_find(&ia_options, DHCPV6_OPTION_STATUS_CODE).unwrap();
+        assert!(status == b"\x00\x02No addresses available");
+        /* the address, when configured */
+        masscanned.config.dhcpv6.assign = true;
+        let repl_data = repl(&request, &masscanned, &mut client()).unwrap();
+        let reply = dhcpv6_parse(&repl_data).unwrap();
+        let ia = dhcpv6_find(&reply.options, DHCPV6_OPTION_IA_NA).unwrap();
+        assert!(dhcpv6_find(&dhcpv6_options(&ia[12..]).unwrap(), DHCPV6_OPTION_IAADDR).is_some());
+        /* for another server, or without a Client Identifier: no answer */
+        let other = message(
+            DHCPV6_REQUEST,
+            Some(b"\x00\x03\x00\x01\x00\x00\x5e\x00\x53\x01"),
+            &ia_na(1),
+        );
+        assert!(repl(&other, &masscanned, &mut client()).is_none());
+        assert!(repl(b"\x01\x00\x00\x01", &masscanned, &mut client()).is_none());
+        /* Information-Request: DNS servers */
+        let repl_data = repl(b"\x0b\x00\x00\x02", &masscanned, &mut client()).unwrap();
+        let reply = dhcpv6_parse(&repl_data).unwrap();
+        assert!(reply.msg_type == DHCPV6_REPLY);
+        assert!(dhcpv6_find(&reply.options, DHCPV6_OPTION_CLIENTID).is_none());
+        assert!(dhcpv6_find(&reply.options, DHCPV6_OPTION_DNS_SERVERS).is_some());
+        /* disabled by default, other groups */
+        assert!(is_dhcpv6(&masscanned, &client()));
+        let mut client_info = client();
+        client_info.ip.dst = Some(IpAddr::from_str("ff02::1").unwrap());
+        assert!(!is_dhcpv6(&masscanned, &client_info));
+        assert!(!is_dhcpv6(&self::masscanned(Config::default()), &client()));
+    }
+
+    /* Relay-forward from the relay agent 2001:db8:1::1 */
+    fn relay_forw(hop_count: u8, interface_id: Option<&[u8]>, message: &[u8]) -> Vec<u8> {
+        let mut relay = vec![DHCPV6_RELAY_FORW, hop_count];
+        relay.extend_from_slice(&dhcpv6_address("2001:db8:1::1").octets());
+        relay.extend_from_slice(&dhcpv6_address("fe80::a00:27ff:fe1a:2b3c").octets());
+        if let Some(id) = interface_id {
+            relay.extend(dhcpv6_option(DHCPV6_OPTION_INTERFACE_ID, id));
+        }
+        relay.extend(dhcpv6_option(DHCPV6_OPTION_RELAY_MSG, message));
+        relay
+    }
+
+    #[test]
+    fn test_dhcpv6_relay() {
+        let masscanned = masscanned(config());
+        let mut client_info = client();
+        client_info.ip.src = Some(IpAddr::from_str("2001:db8:1::1").unwrap());
+        client_info.ip.dst = Some(IpAddr::from_str("2001:db8::547").unwrap());
+        client_info.port.src = Some(DHCPV6_SERVER_PORT);
+        let data = relay_forw(0, Some(b"eth0"), DHCLIENT_SOLICIT);
+        let repl_data = repl(&data, &masscanned, &mut client_info).unwrap();
+        /* Relay-reply, with the addresses and the Interface-Id */
+        assert!(repl_data[0] == DHCPV6_RELAY_REPL && repl_data[1] == 0);
+        assert!(repl_data[2..34] == data[2..34]);
+        let options = dhcpv6_options(&repl_data[34..]).unwrap();
+        assert!(dhcpv6_find(&options, DHCPV6_OPTION_INTERFACE_ID) == Some(&b"eth0"[..]));
+        let advertise =
+            dhcpv6_parse(dhcpv6_find(&options, DHCPV6_OPTION_RELAY_MSG).unwrap()).unwrap();
+        assert!(advertise.msg_type == DHCPV6_ADVERTISE);
+        assert!(dhcpv6_find(&advertise.options, DHCPV6_OPTION_CLIENTID) == Some(CLIENT_DUID));
+        /* sent back to the relay agent from the address it used */
+        assert!(client_info.ip.dst == Some(IpAddr::from_str("2001:db8::547").unwrap()));
+        assert!(client_info.port.src == Some(DHCPV6_SERVER_PORT));
+        /* two relay agents */
+        let data = relay_forw(1, None, &relay_forw(0, Some(b"ge-0/0/1"), DHCLIENT_SOLICIT));
+        let repl_data = repl(&data, &masscanned, &mut client_info).unwrap();
+        assert!(repl_data[0] == DHCPV6_RELAY_REPL && repl_data[1] == 1);
+        let options = dhcpv6_options(&repl_data[34..]).unwrap();
+        assert!(dhcpv6_find(&options, DHCPV6_OPTION_INTERFACE_ID).is_none());
+        let inner = dhcpv6_find(&options, DHCPV6_OPTION_RELAY_MSG).unwrap();
+        assert!(inner[0] == DHCPV6_RELAY_REPL && inner[1] == 0);
+        let options = dhcpv6_options(&inner[34..]).unwrap();
+        assert!(dhcpv6_find(&options, DHCPV6_OPTION_INTERFACE_ID) == Some(&b"ge-0/0/1"[..]));
+        let advertise =
+            dhcpv6_parse(dhcpv6_find(&options, DHCPV6_OPTION_RELAY_MSG).unwrap()).unwrap();
+        assert!(advertise.xid == [0x5a, 0x1b, 0x2c]);
+        /* without a relayed message, truncated, too many relays, or a
+         * Relay-reply */
+        let mut data = vec![DHCPV6_RELAY_FORW, 0];
+        data.extend_from_slice(&[0; 32]);
+        assert!(repl(&data, &masscanned, &mut client_info).is_none());
+        assert!(repl(&data[..20], &masscanned, &mut client_info).is_none());
+        let mut data = DHCLIENT_SOLICIT.to_vec();
+        for hop_count in 0..=DHCPV6_HOP_COUNT_LIMIT + 1 {
+            data = relay_forw(hop_count, None, &data);
+        }
+        assert!(repl(&data, &masscanned, &mut client_info).is_none());
+        let mut data = relay_forw(0, None, DHCLIENT_SOLICIT);
+        data[0] = DHCPV6_RELAY_REPL;
+        assert!(repl(&data, &masscanned, &mut client_info).is_none());
+    }
+}
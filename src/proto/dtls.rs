@@ -0,0 +1,287 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::hash::Hasher;
+
+use log::*;
+use siphasher::sip::SipHasher24;
+
+use crate::client::ClientInfo;
+use crate::proto::tls::{
+    ClientHello, TlsReader, TLS_ALERT_HANDSHAKE_FAILURE, TLS_ALERT_LEVEL_FATAL,
+    TLS_CONTENT_TYPE_ALERT, TLS_CONTENT_TYPE_HANDSHAKE, TLS_HANDSHAKE_CLIENT_HELLO,
+};
+use crate::synackcookie;
+use crate::Masscanned;
+
+const DTLS_HANDSHAKE_HELLO_VERIFY_REQUEST: u8 = 3;
+/* RFC 6347 section 4.2.1: HelloVerifyRequest is always sent with
+ * version DTLS 1.0 */
+const DTLS_VERSION_1_0: u16 = 0xfeff;
+
+/* Record header of a ClientHello: handshake, version DTLS 1.0 or 1.2
+ * (also used by DTLS 1.3), epoch 0, sequence number, length, then the
+ * handshake type.
+ **/
+pub const DTLS_PATTERNS_CLIENT_HELLO: [&[u8; 14]; 2] = [
+    b"\x16\xfe\xff\x00\x00********\x01",
+    b"\x16\xfe\xfd\x00\x00********\x01",
+];
+
+/* ClientHello received in one record, without fragmentation */
+struct DtlsClientHello<'a> {
+    record_version: u16,
+    record_seq: &'a [u8],
+    random: &'a [u8],
+    cookie: &'a [u8],
+    hello: ClientHello,
+}
+
+fn dtls_parse_client_hello(data: &[u8]) -> Option<DtlsClientHello<'_>> {
+    let mut r = TlsReader::new(data);
+    if r.u8()? != TLS_CONTENT_TYPE_HANDSHAKE {
+        return None;
+    }
+    let record_version = r.u16()?;
+    /* epoch */
+    r.u16()?;
+    let record_seq = r.bytes(6)?;
+    let mut r = TlsReader::new(r.vec16()?);
+    if r.u8()? != TLS_HANDSHAKE_CLIENT_HELLO {
+        return None;
+    }
+    let len = r.u24()?;
+    /* message_seq */
+    r.u16()?;
+    let fragment_offset = r.u24()?;
+    let fragment_len = r.u24()?;
+    if fragment_offset != 0 || fragment_len != len {
+        info!("DTLS ClientHello not handled (fragmented)");
+        return None;
+    }
+    let body = r.bytes(len)?;
+    /* same as a TLS ClientHello, with a cookie after the session id */
+    let mut b = TlsReader::new(body);
+    let version = b.bytes(2)?;
+    let random = b.bytes(32)?;
+    let session_id = b.vec8()?;
+    let cookie = b.vec8()?;
+    let rest = b.bytes(body.len() - 36 - session_id.len() - cookie.len())?;
+    let mut tls_body = Vec::with_capacity(body.len());
+    tls_body.extend_from_slice(version);
+    tls_body.extend_from_slice(random);
+    tls_body.push(session_id.len() as u8);
+    tls_body.extend_from_slice(session_id);
+    tls_body.extend_from_slice(rest);
+    Some(DtlsClientHello {
+        record_version,
+        record_seq,
+        random,
+        cookie,
+        hello: ClientHello::parse(&tls_body)?,
+    })
+}
+
+/* Stateless cookie (RFC 6347 section 4.2.1): keyed hash of the client
+ * 5-tuple (the SYNACK-cookie) and of the ClientHello random, which the
+ * client keeps in its second ClientHello.
+ **/
+fn dtls_cookie(client_info: &ClientInfo, key: &[u64; 2], random: &[u8]) -> Option<[u8; 8]> {
+    let tuple = synackcookie::generate(client_info, key).ok()?;
+    let mut sip = SipHasher24::new_with_keys(key[0], key[1]);
+    sip.write_u32(tuple);
+    sip.write(random);
+    Some(sip.finish().to_be_bytes())
+}
+
+fn dtls_record(content_type: u8, version: u16, seq: &[u8], fragment: &[u8]) -> Vec<u8> {
+    let mut record = vec![content_type];
+    record.extend_from_slice(&version.to_be_bytes());
+    /* epoch */
+    record.extend_from_slice(&[0, 0]);
+    record.extend_from_slice(seq);
+    record.extend_from_slice(&(fragment.len() as u16).to_be_bytes());
+    record.extend_from_slice(fragment);
+    record
+}
+
+fn dtls_hello_verify_request(seq: &[u8], cookie: &[u8]) -> Vec<u8> {
+    let mut body = DTLS_VERSION_1_0.to_be_bytes().to_vec();
+    body.push(cookie.len() as u8);
+    body.extend_from_slice(cookie);
+    let len = (body.len() as u32).to_be_bytes();
+    let len = &len[1..];
+    let mut handshake = vec![DTLS_HANDSHAKE_HELLO_VERIFY_REQUEST];
+    handshake.extend_from_slice(len);
+    /* message_seq, fragment_offset */
+    handshake.extend_from_slice(&[0, 0, 0, 0, 0]);
+    handshake.extend_from_slice(len);
+    handshake.extend_from_slice(&body);
+    dtls_record(
+        TLS_CONTENT_TYPE_HANDSHAKE,
+        DTLS_VERSION_1_0,
+        seq,
+        &handshake,
+    )
+}
+
+/* Answer a ClientHello without cookie (or with an invalid one) with a
+ * HelloVerifyRequest, so that the handshake does not go further with
+ * spoofed sources, and record a ClientHello with a valid cookie before
+ * answering a handshake_failure alert. Retransmitted ClientHello
+ * messages get the same answers, since no state is kept.
+ **/
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving DTLS data");
+    let hello = match dtls_parse_client_hello(data) {
+        Some(h) => h,
+        None => {
+            info!("DTLS data not handled");
+            return None;
+        }
+    };
+    let cookie = dtls_cookie(client_info, &masscanned.synack_key, hello.random)?;
+    let verified = hello.cookie == cookie;
+    let mut event = hello.hello.event("dtls_client_hello", client_info);
+    event.set("cookie_verified", verified);
+    event.log();
+    if !verified {
+        if !hello.cookie.is_empty() {
+            info!("DTLS ClientHello with an invalid cookie");
+        }
+        return Some(dtls_hello_verify_request(hello.record_seq, &cookie));
+    }
+    warn!(
+        "DTLS ClientHello (SNI {}, JA3 {})",
+        hello.hello.sni.as_deref().unwrap_or("-"),
+        hello.hello.ja3_md5()
+    );
+    /* DTLS is not terminated ([tls] only applies to TCP) */
+    Some(dtls_record(
+        TLS_CONTENT_TYPE_ALERT,
+        hello.record_version,
+        hello.record_seq,
+        &[TLS_ALERT_LEVEL_FATAL, TLS_ALERT_HANDSHAKE_FAILURE],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::{self, SERVER_IP};
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    /* DTLS 1.2 ClientHello, SNI www.example.com */
+    fn dtls_hello(cookie: &[u8]) -> Vec<u8> {
+        let mut body = vec![0xfe, 0xfd];
+        body.extend_from_slice(&[0x42; 32]);
+        /* session id */
+        body.push(0);
+        body.push(cookie.len() as u8);
+        body.extend_from_slice(cookie);
+        /* ciphers, compression methods */
+        body.extend_from_slice(b"\x00\x04\xc0\x2b\xc0\x2f\x01\x00");
+        let sni = b"\x00\x00\x00\x14\x00\x12\x00\x00\x0fwww.example.com";
+        body.extend_from_slice(&(sni.len() as u16).to_be_bytes());
+        body.extend_from_slice(sni);
+        let len = (body.len() as u32).to_be_bytes();
+        let mut handshake = vec![TLS_HANDSHAKE_CLIENT_HELLO];
+        handshake.extend_from_slice(&len[1..]);
+        handshake.extend_from_slice(&[0, 0, 0, 0, 0]);
+        handshake.extend_from_slice(&len[1..]);
+        handshake.extend_from_slice(&body);
+        dtls_record(
+            TLS_CONTENT_TYPE_HANDSHAKE,
+            0xfeff,
+            b"\x00\x00\x00\x00\x00\x01",
+            &handshake,
+        )
+    }
+
+    fn client_info(sport: u16) -> ClientInfo {
+        let mut client_info = test_util::client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 443);
+        client_info.port.src = Some(sport);
+        client_info
+    }
+
+    #[test]
+    fn test_dtls_client_hello() {
+        let data = dtls_hello(b"");
+        assert!(data.starts_with(&DTLS_PATTERNS_CLIENT_HELLO[0][..5]));
+        let hello = dtls_parse_client_hello(&data).unwrap();
+        assert!(hello.record_version == 0xfeff);
+        assert!(hello.record_seq == b"\x00\x00\x00\x00\x00\x01");
+        assert!(hello.random == [0x42; 32]);
+        assert!(hello.cookie.is_empty());
+        assert!(hello.hello.version == 0xfefd);
+        assert!(hello.hello.ciphers == [0xc02b, 0xc02f]);
+        assert!(hello.hello.sni.as_deref() == Some("www.example.com"));
+        let data_cookie = dtls_hello(b"cookie");
+        let hello = dtls_parse_client_hello(&data_cookie).unwrap();
+        assert!(hello.cookie == b"cookie");
+        assert!(hello.hello.ciphers == [0xc02b, 0xc02f]);
+        /* truncated, fragmented */
+        assert!(dtls_parse_client_hello(&data[..data.len() - 1]).is_none());
+        let mut fragment = data.clone();
+        fragment[21] = 1;
+        assert!(dtls_parse_client_hello(&fragment).is_none());
+    }
+
+    #[test]
+    fn test_dtls_cookie() {
+        let key = [0x1e9219e0b0e0b44c, 0x9e460bcddf4eaac9];
+        let random = [0x42; 32];
+        let cookie = dtls_cookie(&client_info(65000), &key, &random).unwrap();
+        /* same client, same cookie */
+        assert!(dtls_cookie(&client_info(65000), &key, &random) == Some(cookie));
+        /* different client, ClientHello or key */
+        assert!(dtls_cookie(&client_info(65001), &key, &random) != Some(cookie));
+        assert!(dtls_cookie(&client_info(65000), &key, &[0x43; 32]) != Some(cookie));
+        assert!(dtls_cookie(&client_info(65000), &[0, 0], &random) != Some(cookie));
+        /* incomplete 5-tuple */
+        assert!(dtls_cookie(&ClientInfo::new(), &key, &random).is_none());
+    }
+
+    #[test]
+    fn test_dtls_repl() {
+        let masscanned = Masscanned {
+            synack_key: [0x1e9219e0b0e0b44c, 0x9e460bcddf4eaac9],
+            ..crate::proto::test_util::masscanned(Config::default())
+        };
+        let ci = client_info(65000);
+        /* first flight, and its retransmission */
+        let verify = repl(&dtls_hello(b""), &masscanned, &ci).unwrap();
+        let mut udp_ci = ci;
+        assert!(
+            crate::proto::repl(&dtls_hello(b""), &masscanned, &mut udp_ci) == Some(verify.clone())
+        );
+        assert!(repl(&dtls_hello(b""), &masscanned, &ci).unwrap() == verify);
+        assert!(verify.starts_with(b"\x16\xfe\xff\x00\x00\x00\x00\x00\x00\x00\x01\x00\x17\x03"));
+        let cookie = &verify[verify.len() - 8..];
+        assert!(verify[verify.len() - 9] == 8);
+        /* invalid cookie */
+        assert!(repl(&dtls_hello(b"invalid!"), &masscanned, &ci).unwrap() == verify);
+        /* second flight, and its retransmission */
+        for _ in 0..2 {
+            let alert = repl(&dtls_hello(cookie), &masscanned, &ci).unwrap();
+            assert!(alert == b"\x15\xfe\xff\x00\x00\x00\x00\x00\x00\x00\x01\x00\x02\x02\x28");
+        }
+        /* the cookie is bound to the client */
+        let other = repl(&dtls_hello(cookie), &masscanned, &client_info(65001)).unwrap();
+        assert!(other[13] == DTLS_HANDSHAKE_HELLO_VERIFY_REQUEST);
+    }
+}
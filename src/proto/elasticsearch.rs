@@ -0,0 +1,472 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+
+/* Elasticsearch node, answered instead of the HTTP persona on the
+ * configured ports: / (cluster banner), /_cluster/health and
+ * /_cat/indices (the configured indices, with made-up statistics) are
+ * answered, any other API call gets an error (and is recorded).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ElasticsearchConfig {
+    pub ports: Vec<u16>,
+    pub cluster_name: String,
+    pub cluster_uuid: String,
+    pub node_name: String,
+    /* x.y.z - also gives the compatibility versions */
+    pub version: String,
+    pub lucene_version: String,
+    pub indices: Vec<String>,
+}
+
+impl Default for ElasticsearchConfig {
+    fn default() -> Self {
+        ElasticsearchConfig {
+            ports: vec![9200],
+            cluster_name: "elasticsearch".to_string(),
+            cluster_uuid: "Rq0sdr-JTOyBkOAXcOGqAQ".to_string(),
+            node_name: "es01".to_string(),
+            version: "8.11.1".to_string(),
+            lucene_version: "9.8.0".to_string(),
+            indices: vec![
+                "customers".to_string(),
+                "orders".to_string(),
+                "users".to_string(),
+            ],
+        }
+    }
+}
+
+impl ElasticsearchConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if es_major(&self.version).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("elasticsearch: invalid version: {:?}", self.version),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* major number of a x.y.z version */
+fn es_major(version: &str) -> Option<u32> {
+    let parts = version
+        .split('.')
+        .map(|p| p.parse::<u32>().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    if parts.len() != 3 {
+        return None;
+    }
+    Some(parts[0])
+}
+
+/* minimum wire and index compatibility versions announced by a node */
+fn es_compatibility(version: &str) -> (String, String) {
+    match es_major(version) {
+        Some(8) => ("7.17.0".to_string(), "7.0.0".to_string()),
+        Some(7) => ("6.8.0".to_string(), "6.0.0-beta1".to_string()),
+        Some(m) if m > 0 => (format!("{}.0.0", m - 1), format!("{}.0.0", m - 1)),
+        _ => (version.to_string(), version.to_string()),
+    }
+}
+
+/* made-up but stable statistics of an index: uuid, documents, size */
+struct EsIndex<'a> {
+    name: &'a str,
+    uuid: String,
+    docs: u64,
+    size: u64,
+}
+
+impl<'a> EsIndex<'a> {
+    fn new(name: &'a str) -> Self {
+        let mut h: u64 = 0xcbf29ce484222325;
+        for c in name.bytes() {
+            h ^= c as u64;
+            h = h.wrapping_mul(0x100000001b3);
+        }
+        let docs = 1000 + h % 250_000;
+        EsIndex {
+            name,
+            uuid: base64::encode_config(h.to_be_bytes().repeat(2), base64::URL_SAFE_NO_PAD)[..22]
+                .to_string(),
+            docs,
+            size: docs * (600 + (h >> 32) % 800),
+        }
+    }
+}
+
+/* sizes in the _cat APIs (e.g., 12.4mb) */
+fn es_size(size: u64) -> String {
+    let units = ["kb", "mb", "gb", "tb"];
+    if size < 1024 {
+        return format!("{}b", size);
+    }
+    let mut s = size as f64 / 1024.0;
+    let mut unit = 0;
+    while s >= 1024.0 && unit < units.len() - 1 {
+        s /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", s, units[unit])
+}
+
+fn es_root(config: &ElasticsearchConfig) -> Value {
+    let (wire, index) = es_compatibility(&config.version);
+    json!({
+        "name": config.node_name,
+        "cluster_name": config.cluster_name,
+        "cluster_uuid": config.cluster_uuid,
+        "version": {
+            "number": config.version,
+            "build_flavor": "default",
+            "build_type": "docker",
+            "build_hash": "6f9ff581fbcde658e6f69d6ce03050f060d1fd0c",
+            "build_date": "2023-11-11T10:05:59.421038163Z",
+            "build_snapshot": false,
+            "lucene_version": config.lucene_version,
+            "minimum_wire_compatibility_version": wire,
+            "minimum_index_compatibility_version": index,
+        },
+        "tagline": "You Know, for Search",
+    })
+}
+
+/* one node: replicas are never assigned */
+fn es_cluster_health(config: &ElasticsearchConfig) -> Value {
+    let shards = config.indices.len();
+    json!({
+        "cluster_name": config.cluster_name,
+        "status": if shards == 0 { "green" } else { "yellow" },
+        "timed_out": false,
+        "number_of_nodes": 1,
+        "number_of_data_nodes": 1,
+        "active_primary_shards": shards,
+        "active_shards": shards,
+        "relocating_shards": 0,
+        "initializing_shards": 0,
+        "unassigned_shards": shards,
+        "delayed_unassigned_shards": 0,
+        "number_of_pending_tasks": 0,
+        "number_of_in_flight_fetch": 0,
+        "task_max_waiting_in_queue_millis": 0,
+        "active_shards_percent_as_number": if shards == 0 { 100.0 } else { 50.0 },
+    })
+}
+
+const ES_CAT_INDICES_COLUMNS: [&str; 10] = [
+    "health",
+    "status",
+    "index",
+    "uuid",
+    "pri",
+    "rep",
+    "docs.count",
+    "docs.deleted",
+    "store.size",
+    "pri.store.size",
+];
+
+fn es_cat_indices_rows(config: &ElasticsearchConfig) -> Vec<[String; 10]> {
+    config
+        .indices
+        .iter()
+        .map(|name| {
+            let index = EsIndex::new(name);
+            [
+                "yellow".to_string(),
+                "open".to_string(),
+                index.name.to_string(),
+                index.uuid,
+                "1".to_string(),
+                "1".to_string(),
+                index.docs.to_string(),
+                "0".to_string(),
+                es_size(index.size),
+                es_size(index.size),
+            ]
+        })
+        .collect()
+}
+
+/* _cat/indices: a table (with column names when verbose), or a list of
+ * objects with format=json */
+fn es_cat_indices(config: &ElasticsearchConfig, json: bool, verbose: bool) -> String {
+    let rows = es_cat_indices_rows(config);
+    if json {
+        let objects: Vec<Value> = rows
+            .iter()
+            .map(|row| {
+                Value::Object(
+                    ES_CAT_INDICES_COLUMNS
+                        .iter()
+                        .zip(row.iter())
+                        .map(|(k, v)| (k.to_string(), Value::from(v.clone())))
+                        .collect(),
+                )
+            })
+            .collect();
+        return Value::from(objects).to_string();
+    }
+    let mut table: Vec<Vec<String>> = Vec::new();
+    if verbose {
+        table.push(
+            ES_CAT_INDICES_COLUMNS
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+        );
+    }
+    table.extend(rows.iter().map(|r| r.to_vec()));
+    let widths: Vec<usize> = (0..ES_CAT_INDICES_COLUMNS.len())
+        .map(|i| table.iter().map(|r| r[i].len()).max().unwrap_or(0))
+        .collect();
+    let mut text = String::new();
+    for row in table.iter() {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(v, w)| format!("{:<1$}", v, w))
+            .collect::<Vec<String>>()
+            .join(" ");
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+/* error of a request to an index that does not exist */
+fn es_index_not_found(index: &str) -> Value {
+    let cause = json!({
+        "type": "index_not_found_exception",
+        "reason": format!("no such index [{}]", index),
+        "resource.type": "index_or_alias",
+        "resource.id": index,
+        "index_uuid": "_na_",
+        "index": index,
+    });
+    let mut error = cause.clone();
+    error["root_cause"] = json!([cause]);
+    json!({"error": error, "status": 404})
+}
+
+fn es_event(verb: &str, uri: &str, body: &[u8], error: &str, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("elasticsearch_request", client_info);
+    event.set("verb", verb);
+    event.set("uri", uri);
+    event.set("error", error);
+    event.set("body", String::from_utf8_lossy(body));
+    event
+}
+
+/* Status, headers and content of the answer to a request (path is the
+ * normalized path of uri, None if invalid). Requests that are not
+ * answered are recorded as elasticsearch_request events, with their
+ * content.
+ **/
+pub fn es_page(
+    config: &ElasticsearchConfig,
+    verb: &str,
+    uri: &str,
+    path: Option<&str>,
+    body: &[u8],
+    client_info: &ClientInfo,
+) -> (String, Vec<String>, String) {
+    let query: Vec<(&str, &str)> = match uri.split_once('?') {
+        Some((_, q)) => q
+            .split('&')
+            .map(|p| p.split_once('=').unwrap_or((p, "")))
+            .collect(),
+        None => Vec::new(),
+    };
+    let param = |name: &str| query.iter().find(|(k, _)| *k == name).map(|(_, v)| *v);
+    let pretty = param("pretty").is_some_and(|v| v != "false");
+    let path = path.map(|p| p.trim_end_matches('/')).unwrap_or("");
+    let json_headers = || {
+        vec![
+            "X-elastic-product: Elasticsearch".to_string(),
+            "Content-Type: application/json".to_string(),
+        ]
+    };
+    let to_string = |v: &Value| {
+        if pretty {
+            serde_json::to_string_pretty(v).unwrap() + "\n"
+        } else {
+            v.to_string()
+        }
+    };
+    let answer = |status: &str, v: Value| (status.to_string(), json_headers(), to_string(&v));
+    let allowed = match path {
+        "" => Some("GET, HEAD"),
+        "/_cluster/health" | "/_cat/indices" => Some("GET"),
+        _ => None,
+    };
+    if let Some(allowed) = allowed {
+        if verb != "HEAD" && !allowed.split(", ").any(|v| v == verb) {
+            return answer(
+                "405 Method Not Allowed",
+                json!({
+                    "error": format!(
+                        "Incorrect HTTP method for uri [{}] and method [{}], allowed: [{}]",
+                        uri, verb, allowed
+                    ),
+                    "status": 405,
+                }),
+            );
+        }
+    }
+    match path {
+        "" => answer("200 OK", es_root(config)),
+        "/_cluster/health" => answer("200 OK", es_cluster_health(config)),
+        "/_cat/indices" => {
+            if param("format") == Some("json") {
+                let mut content = es_cat_indices(config, true, false);
+                if pretty {
+                    content = to_string(&serde_json::from_str(&content).unwrap());
+                }
+                ("200 OK".to_string(), json_headers(), content)
+            } else {
+                (
+                    "200 OK".to_string(),
+                    vec![
+                        "X-elastic-product: Elasticsearch".to_string(),
+                        "Content-Type: text/plain; charset=UTF-8".to_string(),
+                    ],
+                    es_cat_indices(config, false, param("v").is_some()),
+                )
+            }
+        }
+        p if p.starts_with("/_") => {
+            warn!("Elasticsearch {} {}: no handler", verb, uri);
+            es_event(verb, uri, body, "no_handler", client_info).log();
+            answer(
+                "400 Bad Request",
+                json!({
+                    "error": format!("no handler found for uri [{}] and method [{}]", uri, verb),
+                }),
+            )
+        }
+        p => {
+            let index = p[1..].split('/').next().unwrap_or("");
+            warn!("Elasticsearch {} {}: index {}", verb, uri, index);
+            es_event(verb, uri, body, "index_not_found", client_info).log();
+            answer("404 Not Found", es_index_not_found(index))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(config: &ElasticsearchConfig, verb: &str, uri: &str) -> (String, Vec<String>, String) {
+        let path = uri.split('?').next();
+        es_page(config, verb, uri, path, b"", &ClientInfo::new())
+    }
+
+    #[test]
+    fn test_es_root() {
+        let mut config = ElasticsearchConfig {
+            version: "7.17.9".to_string(),
+            ..Default::default()
+        };
+        assert!(config.check().is_ok());
+        let (status, headers, content) = page(&config, "GET", "/");
+        assert!(status == "200 OK");
+        assert!(headers.contains(&"Content-Type: application/json".to_string()));
+        let root: Value = serde_json::from_str(&content).unwrap();
+        assert!(root["cluster_name"] == "elasticsearch");
+        assert!(root["cluster_uuid"] == config.cluster_uuid);
+        assert!(root["version"]["number"] == "7.17.9");
+        assert!(root["version"]["minimum_wire_compatibility_version"] == "6.8.0");
+        assert!(root["tagline"] == "You Know, for Search");
+        /* ?pretty */
+        let (_, _, pretty) = page(&config, "GET", "/?pretty");
+        assert!(pretty.contains("\n  \"name\": \"es01\",\n"));
+        assert!(serde_json::from_str::<Value>(&pretty).unwrap() == root);
+        /* other verbs */
+        let (status, _, content) = page(&config, "DELETE", "/");
+        assert!(status == "405 Method Not Allowed");
+        assert!(content.contains("allowed: [GET, HEAD]"));
+        /* invalid versions */
+        for v in ["8.11", "8.x.1", ""].iter() {
+            config.version = v.to_string();
+            assert!(config.check().is_err());
+        }
+    }
+
+    #[test]
+    fn test_es_cluster() {
+        let config = ElasticsearchConfig::default();
+        let (status, _, content) = page(&config, "GET", "/_cluster/health/");
+        assert!(status == "200 OK");
+        let health: Value = serde_json::from_str(&content).unwrap();
+        assert!(health["status"] == "yellow");
+        assert!(health["number_of_nodes"] == 1);
+        assert!(health["active_primary_shards"] == 3);
+        /* indices, as a table */
+        let (_, headers, content) = page(&config, "GET", "/_cat/indices?v");
+        assert!(headers.contains(&"Content-Type: text/plain; charset=UTF-8".to_string()));
+        let lines: Vec<&str> = content.lines().collect();
+        assert!(lines.len() == 4);
+        assert!(lines[0].starts_with("health status index     uuid "));
+        assert!(lines[1].starts_with("yellow open   customers "));
+        /* without column names, and stable */
+        let (_, _, content2) = page(&config, "GET", "/_cat/indices");
+        assert!(content2.lines().count() == 3);
+        assert!(content2.starts_with("yellow open customers "));
+        assert!(page(&config, "GET", "/_cat/indices").2 == content2);
+        /* as JSON */
+        let (_, headers, content) = page(&config, "GET", "/_cat/indices?format=json&pretty");
+        assert!(headers.contains(&"Content-Type: application/json".to_string()));
+        let indices: Value = serde_json::from_str(&content).unwrap();
+        assert!(indices.as_array().unwrap().len() == 3);
+        assert!(indices[2]["index"] == "users");
+        assert!(indices[2]["uuid"].as_str().unwrap().len() == 22);
+        assert!(indices[2]["docs.count"]
+            .as_str()
+            .unwrap()
+            .parse::<u64>()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_es_errors() {
+        let config = ElasticsearchConfig::default();
+        let (status, headers, content) = page(&config, "POST", "/_search?size=100");
+        assert!(status == "400 Bad Request");
+        assert!(headers.contains(&"Content-Type: application/json".to_string()));
+        let error: Value = serde_json::from_str(&content).unwrap();
+        assert!(error["error"] == "no handler found for uri [/_search?size=100] and method [POST]");
+        let (status, _, content) = page(&config, "GET", "/customers/_search");
+        assert!(status == "404 Not Found");
+        let error: Value = serde_json::from_str(&content).unwrap();
+        assert!(error["status"] == 404);
+        assert!(error["error"]["type"] == "index_not_found_exception");
+        assert!(error["error"]["root_cause"][0]["index"] == "customers");
+        assert!(es_size(512) == "512b" && es_size(1536) == "1.5kb");
+        assert!(es_size(5 * 1024 * 1024 * 1024) == "5.0gb");
+    }
+}
@@ -0,0 +1,436 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::net::IpAddr;
+
+use log::*;
+use pnet::packet::ip::IpNextHeaderProtocols;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* encapsulation header: command, length, session handle, status,
+ * sender context, options (all little endian) */
+const ENIP_HEADER_LEN: usize = 24;
+
+/* encapsulation commands */
+const ENIP_NOP: u16 = 0x0000;
+const ENIP_LIST_SERVICES: u16 = 0x0004;
+const ENIP_LIST_IDENTITY: u16 = 0x0063;
+const ENIP_LIST_INTERFACES: u16 = 0x0064;
+const ENIP_REGISTER_SESSION: u16 = 0x0065;
+const ENIP_UNREGISTER_SESSION: u16 = 0x0066;
+const ENIP_SEND_RR_DATA: u16 = 0x006f;
+const ENIP_SEND_UNIT_DATA: u16 = 0x0070;
+
+/* encapsulation status: invalid or unsupported command */
+const ENIP_STATUS_INVALID_COMMAND: u32 = 0x0001;
+
+/* common packet format item: CIP identity */
+const ENIP_ITEM_IDENTITY: u16 = 0x000c;
+
+const ENIP_PROTOCOL_VERSION: u16 = 1;
+
+/* EtherNet/IP adapter (on the TCP and UDP ports, and the UDP only
+ * udp_ports): List Identity requests get the identity of the device
+ * (CIP identity object), Register Session requests a session handle,
+ * other commands an error status (on TCP).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnipConfig {
+    pub ports: Vec<u16>,
+    pub udp_ports: Vec<u16>,
+    pub vendor_id: u16,
+    pub device_type: u16,
+    pub product_code: u16,
+    /* major.minor */
+    pub revision: String,
+    pub status: u16,
+    pub serial_number: u32,
+    pub product_name: String,
+    pub state: u8,
+}
+
+impl Default for EnipConfig {
+    fn default() -> Self {
+        EnipConfig {
+            ports: vec![44818],
+            udp_ports: vec![2222],
+            /* Rockwell Automation/Allen-Bradley, programmable logic
+             * controller */
+            vendor_id: 1,
+            device_type: 14,
+            product_code: 54,
+            revision: "20.11".to_string(),
+            status: 0x3060,
+            serial_number: 0x6010a4c2,
+            product_name: "1756-L61/B LOGIX5561".to_string(),
+            state: 3,
+        }
+    }
+}
+
+impl EnipConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        let err = |e: String| Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        if enip_revision(&self.revision).is_none() {
+            return err(format!("enip: invalid revision: {:?}", self.revision));
+        }
+        if self.product_name.len() > 32 {
+            return err("enip: product_name longer than 32 bytes".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn enip_revision(revision: &str) -> Option<(u8, u8)> {
+    let (major, minor) = revision.split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/* state of an EtherNet/IP flow: data received, not a complete
+ * encapsulation message yet, and handle of the registered session */
+#[derive(Default)]
+pub struct EnipState {
+    buffer: Vec<u8>,
+    session: Option<u32>,
+}
+
+struct EnipHeader {
+    command: u16,
+    length: u16,
+    session: u32,
+    status: u32,
+    context: [u8; 8],
+    options: u32,
+}
+
+fn enip_parse_header(data: &[u8]) -> Option<EnipHeader> {
+    if data.len() < ENIP_HEADER_LEN {
+        return None;
+    }
+    let u16_at = |o: usize| u16::from_le_bytes([data[o], data[o + 1]]);
+    let u32_at = |o: usize| u32::from_le_bytes([data[o], data[o + 1], data[o + 2], data[o + 3]]);
+    let mut context = [0; 8];
+    context.copy_from_slice(&data[12..20]);
+    Some(EnipHeader {
+        command: u16_at(0),
+        length: u16_at(2),
+        session: u32_at(4),
+        status: u32_at(8),
+        context,
+        options: u32_at(20),
+    })
+}
+
+/* answer: the command and sender context of the request */
+fn enip_message(request: &EnipHeader, session: u32, status: u32, data: &[u8]) -> Vec<u8> {
+    let mut message = request.command.to_le_bytes().to_vec();
+    message.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    message.extend_from_slice(&session.to_le_bytes());
+    message.extend_from_slice(&status.to_le_bytes());
+    message.extend_from_slice(&request.context);
+    message.extend_from_slice(&[0; 4]);
+    message.extend_from_slice(data);
+    message
+}
+
+fn enip_command_name(command: u16) -> &'static str {
+    match command {
+        ENIP_NOP => "NOP",
+        ENIP_LIST_SERVICES => "ListServices",
+        ENIP_LIST_IDENTITY => "ListIdentity",
+        ENIP_LIST_INTERFACES => "ListInterfaces",
+        ENIP_REGISTER_SESSION => "RegisterSession",
+        ENIP_UNREGISTER_SESSION => "UnRegisterSession",
+        ENIP_SEND_RR_DATA => "SendRRData",
+        ENIP_SEND_UNIT_DATA => "SendUnitData",
+        _ => "unknown",
+    }
+}
+
+/* List Identity reply: one CIP identity item */
+fn enip_identity(config: &EnipConfig, client_info: &ClientInfo) -> Vec<u8> {
+    let (major, minor) = enip_revision(&config.revision).unwrap_or((1, 0));
+    let mut item = ENIP_PROTOCOL_VERSION.to_le_bytes().to_vec();
+    /* socket address (big endian): AF_INET, port, address */
+    item.extend_from_slice(&2u16.to_be_bytes());
+    item.extend_from_slice(&client_info.port.dst.unwrap_or(44818).to_be_bytes());
+    match client_info.ip.dst {
+        Some(IpAddr::V4(ip)) => item.extend_from_slice(&ip.octets()),
+        _ => item.extend_from_slice(&[0; 4]),
+    }
+    item.extend_from_slice(&[0; 8]);
+    item.extend_from_slice(&config.vendor_id.to_le_bytes());
+    item.extend_from_slice(&config.device_type.to_le_bytes());
+    item.extend_from_slice(&config.product_code.to_le_bytes());
+    item.extend_from_slice(&[major, minor]);
+    item.extend_from_slice(&config.status.to_le_bytes());
+    item.extend_from_slice(&config.serial_number.to_le_bytes());
+    item.push(config.product_name.len() as u8);
+    item.extend_from_slice(config.product_name.as_bytes());
+    item.push(config.state);
+    let mut data = 1u16.to_le_bytes().to_vec();
+    data.extend_from_slice(&ENIP_ITEM_IDENTITY.to_le_bytes());
+    data.extend_from_slice(&(item.len() as u16).to_le_bytes());
+    data.extend(item);
+    data
+}
+
+/* answer to an encapsulation message (None for those that get no
+ * answer) */
+fn enip_answer(
+    request: &EnipHeader,
+    data: &[u8],
+    state: &mut EnipState,
+    config: &EnipConfig,
+    client_info: &mut ClientInfo,
+) -> Option<Vec<u8>> {
+    let tcp = client_info.transport == Some(IpNextHeaderProtocols::Tcp);
+    let mut event = Event::new("enip_request", client_info);
+    event.set("command", request.command);
+    event.set("command_name", enip_command_name(request.command));
+    event.set("session", request.session);
+    event.set(
+        "sender_context",
+        request
+            .context
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>(),
+    );
+    event.set("length", request.length);
+    warn!(
+        "EtherNet/IP {} (0x{:04x})",
+        enip_command_name(request.command),
+        request.command
+    );
+    let repl = match request.command {
+        ENIP_LIST_IDENTITY => Some(enip_message(
+            request,
+            request.session,
+            0,
+            &enip_identity(config, client_info),
+        )),
+        /* sessions only exist on TCP */
+        ENIP_REGISTER_SESSION if tcp => {
+            let session = *state
+                .session
+                .get_or_insert_with(|| rand::random::<u32>() | 1);
+            event.set("registered_session", session);
+            /* protocol version, options */
+            let version = data.get(..2).unwrap_or(&[1, 0]);
+            let mut repl = version.to_vec();
+            repl.extend_from_slice(&[0, 0]);
+            Some(enip_message(request, session, 0, &repl))
+        }
+        ENIP_UNREGISTER_SESSION => {
+            client_info.close = tcp;
+            None
+        }
+        /* no answer to NOP, nor to requests with a status or options */
+        ENIP_NOP => None,
+        _ if request.status != 0 || request.options != 0 => None,
+        _ if tcp => Some(enip_message(
+            request,
+            request.session,
+            ENIP_STATUS_INVALID_COMMAND,
+            &[],
+        )),
+        _ => None,
+    };
+    event.log();
+    repl
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving EtherNet/IP data");
+    let config = &masscanned.config.enip;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Enip(s)) => s,
+        _ => EnipState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while let Some(request) = enip_parse_header(&state.buffer) {
+        let len = ENIP_HEADER_LEN + request.length as usize;
+        if state.buffer.len() < len {
+            break;
+        }
+        let message: Vec<u8> = state.buffer.drain(..len).collect();
+        if let Some(repl) = enip_answer(
+            &request,
+            &message[ENIP_HEADER_LEN..],
+            &mut state,
+            config,
+            client_info,
+        ) {
+            repl_data.extend(repl);
+        }
+        if client_info.close {
+            break;
+        }
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Enip(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending EtherNet/IP data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    /* nmap enip-info */
+    const NMAP_LIST_IDENTITY: &[u8] = b"\x63\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\xc1\xde\xbe\xd1\x00\x00\x00\x00\x00\x00\x00\x00";
+    const REGISTER_SESSION: &[u8] =
+        b"\x65\x00\x04\x00\x00\x00\x00\x00\x00\x00\x00\x00_pycomm_\x00\x00\x00\x00\x01\x00\x00\x00";
+
+    fn client(transport: pnet::packet::ip::IpNextHeaderProtocol, port: u16) -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(transport);
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.dst = Some(port);
+        client_info
+    }
+
+    #[test]
+    fn test_enip_config() {
+        assert!(EnipConfig::default().check().is_ok());
+        assert!(enip_revision("20.11") == Some((20, 11)));
+        assert!(enip_revision("20").is_none() && enip_revision("20.256").is_none());
+        assert!(Config::from_str("[enip]\nrevision = \"2\"\n").is_err());
+    }
+
+    #[test]
+    fn test_enip_list_identity() {
+        let masscanned = masscanned(Config::default());
+        for (transport, port) in [
+            (IpNextHeaderProtocols::Tcp, 44818),
+            (IpNextHeaderProtocols::Udp, 44818),
+            (IpNextHeaderProtocols::Udp, 2222),
+        ]
+        .iter()
+        {
+            let mut client_info = client(*transport, *port);
+            let mut tcb = TCPControlBlock::new();
+            let tcb = if *transport == IpNextHeaderProtocols::Tcp {
+                Some(&mut tcb)
+            } else {
+                None
+            };
+            let repl_data =
+                crate::proto::dispatch(NMAP_LIST_IDENTITY, &masscanned, &mut client_info, tcb)
+                    .expect("no answer to List Identity");
+            /* command, length, and the sender context, echoed */
+            assert!(repl_data[..2] == [0x63, 0x00]);
+            assert!(
+                u16::from_le_bytes([repl_data[2], repl_data[3]]) as usize == repl_data.len() - 24
+            );
+            assert!(repl_data[4..12] == [0; 8]);
+            assert!(repl_data[12..20] == NMAP_LIST_IDENTITY[12..20]);
+            /* one identity item, at the offsets used by nmap */
+            assert!(repl_data[24..28] == [0x01, 0x00, 0x0c, 0x00]);
+            assert!(
+                repl_data[32..40] == [0x00, 0x02, (*port >> 8) as u8, *port as u8, 192, 0, 2, 1]
+            );
+            assert!(repl_data[48..50] == [0x01, 0x00]);
+            assert!(repl_data[50..52] == [0x0e, 0x00]);
+            assert!(repl_data[52..54] == [0x36, 0x00]);
+            assert!(repl_data[54..56] == [20, 11]);
+            assert!(repl_data[56..58] == [0x60, 0x30]);
+            assert!(repl_data[58..62] == [0xc2, 0xa4, 0x10, 0x60]);
+            assert!(repl_data[62] == 20 && repl_data[63..83] == *b"1756-L61/B LOGIX5561");
+            assert!(repl_data[83..] == [3]);
+        }
+    }
+
+    #[test]
+    fn test_enip_session() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client(IpNextHeaderProtocols::Tcp, 44818);
+        let mut tcb = TCPControlBlock::new();
+        /* in two segments */
+        assert!(crate::proto::dispatch(
+            &REGISTER_SESSION[..10],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl_data = crate::proto::dispatch(
+            &REGISTER_SESSION[10..],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        let header = enip_parse_header(&repl_data).unwrap();
+        assert!(header.command == ENIP_REGISTER_SESSION && header.status == 0);
+        assert!(header.session != 0 && header.context == *b"_pycomm_");
+        assert!(repl_data[24..] == [1, 0, 0, 0]);
+        /* other commands: error status */
+        let mut send = REGISTER_SESSION.to_vec();
+        send[0] = ENIP_SEND_RR_DATA as u8;
+        send[4..8].copy_from_slice(&header.session.to_le_bytes());
+        let repl_data =
+            crate::proto::dispatch(&send, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let error = enip_parse_header(&repl_data).unwrap();
+        assert!(error.command == ENIP_SEND_RR_DATA && error.length == 0);
+        assert!(error.status == ENIP_STATUS_INVALID_COMMAND && error.session == header.session);
+        /* the same session, then unregistering closes the connection */
+        let repl_data = crate::proto::dispatch(
+            REGISTER_SESSION,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(enip_parse_header(&repl_data).unwrap().session == header.session);
+        let mut unregister = REGISTER_SESSION[..24].to_vec();
+        unregister[0] = ENIP_UNREGISTER_SESSION as u8;
+        unregister[2] = 0;
+        assert!(
+            crate::proto::dispatch(&unregister, &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        assert!(client_info.close);
+        /* no sessions over UDP */
+        let mut client_info = client(IpNextHeaderProtocols::Udp, 44818);
+        assert!(repl(REGISTER_SESSION, &masscanned, &mut client_info, None).is_none());
+    }
+}
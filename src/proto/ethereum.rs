@@ -0,0 +1,291 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+
+/* Ethereum node JSON-RPC API (as exposed by geth on 8545), answered
+ * instead of the HTTP persona on the configured ports: POST requests
+ * hold a JSON-RPC 2.0 call, or a batch of calls (answered by a batch,
+ * in the same order). web3_clientVersion, net_version, eth_blockNumber
+ * and eth_accounts get the configured results, any other method (e.g.,
+ * eth_sendTransaction, personal_unlockAccount) an error. Every call is
+ * recorded with its method and parameters.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EthereumConfig {
+    pub ports: Vec<u16>,
+    /* result of web3_clientVersion */
+    pub client_version: String,
+    /* result of net_version (1: mainnet) */
+    pub network_id: String,
+    /* result of eth_blockNumber (sent as hex) */
+    pub block_number: u64,
+    /* result of eth_accounts (0x-prefixed, 20-byte addresses) */
+    pub accounts: Vec<String>,
+}
+
+impl Default for EthereumConfig {
+    fn default() -> Self {
+        EthereumConfig {
+            ports: vec![8545],
+            client_version: "Geth/v1.13.5-stable-916d6a44/linux-amd64/go1.21.4".to_string(),
+            network_id: "1".to_string(),
+            block_number: 18_600_000,
+            accounts: vec!["0x407d73d8a49eeb85d32cf465507dd71d507100c1".to_string()],
+        }
+    }
+}
+
+impl EthereumConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if self.network_id.is_empty() || !self.network_id.bytes().all(|c| c.is_ascii_digit()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("ethereum: invalid network_id: {:?}", self.network_id),
+            ));
+        }
+        for a in self.accounts.iter() {
+            let valid = match a.strip_prefix("0x") {
+                Some(h) => h.len() == 40 && h.bytes().all(|c| c.is_ascii_hexdigit()),
+                None => false,
+            };
+            if !valid {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("ethereum: invalid account: {:?}", a),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/* JSON-RPC 2.0 error codes */
+const ETH_PARSE_ERROR: i64 = -32700;
+const ETH_INVALID_REQUEST: i64 = -32600;
+const ETH_METHOD_NOT_FOUND: i64 = -32601;
+
+fn eth_error(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": code, "message": message},
+    })
+}
+
+fn eth_event(call: &Value, batch: bool, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("ethereum_rpc", client_info);
+    event.set("method", call["method"].clone());
+    if let Some(params) = call.get("params") {
+        event.set("params", params.clone());
+    }
+    if let Some(id) = call.get("id") {
+        event.set("id", id.clone());
+    }
+    event.set("batch", batch);
+    event
+}
+
+/* answer to a call - None for notifications (calls without an id) */
+fn eth_call(config: &EthereumConfig, call: &Value) -> Option<Value> {
+    let id = call.get("id")?.clone();
+    let method = match (call.get("jsonrpc"), call.get("method")) {
+        (Some(Value::String(v)), Some(Value::String(m))) if v == "2.0" => m,
+        _ => return Some(eth_error(id, ETH_INVALID_REQUEST, "invalid request")),
+    };
+    let result = match &method[..] {
+        "web3_clientVersion" => json!(config.client_version),
+        "net_version" => json!(config.network_id),
+        "eth_blockNumber" => json!(format!("{:#x}", config.block_number)),
+        "eth_accounts" => json!(config.accounts),
+        m => {
+            return Some(eth_error(
+                id,
+                ETH_METHOD_NOT_FOUND,
+                &format!("the method {} does not exist/is not available", m),
+            ))
+        }
+    };
+    Some(json!({"jsonrpc": "2.0", "id": id, "result": result}))
+}
+
+/* Content of the answer to a request body: a single answer, a batch of
+ * answers, or nothing when only notifications were sent.
+ **/
+pub fn eth_rpc(config: &EthereumConfig, body: &[u8], client_info: &ClientInfo) -> Option<Value> {
+    let request = match serde_json::from_slice::<Value>(body) {
+        Ok(v) => v,
+        Err(_) => {
+            info!("Ethereum invalid JSON-RPC request");
+            return Some(eth_error(Value::Null, ETH_PARSE_ERROR, "parse error"));
+        }
+    };
+    match request {
+        Value::Array(calls) => {
+            if calls.is_empty() {
+                return Some(eth_error(Value::Null, ETH_INVALID_REQUEST, "empty batch"));
+            }
+            let answers: Vec<Value> = calls
+                .iter()
+                .filter_map(|c| {
+                    eth_event(c, true, client_info).log();
+                    eth_call(config, c)
+                })
+                .collect();
+            if answers.is_empty() {
+                None
+            } else {
+                Some(Value::Array(answers))
+            }
+        }
+        call => {
+            eth_event(&call, false, client_info).log();
+            eth_call(config, &call)
+        }
+    }
+}
+
+/* Status, headers and content of the answer to a request: POST requests
+ * are JSON-RPC calls, GET and HEAD requests (health checks) get an empty
+ * answer.
+ **/
+pub fn eth_page(
+    config: &EthereumConfig,
+    verb: &str,
+    uri: &str,
+    body: &[u8],
+    client_info: &ClientInfo,
+) -> (String, Vec<String>, String) {
+    let headers = vec![
+        "Content-Type: application/json".to_string(),
+        "Vary: Origin".to_string(),
+    ];
+    match verb {
+        "POST" => {
+            warn!("Ethereum JSON-RPC {}", uri);
+            let content = match eth_rpc(config, body, client_info) {
+                Some(v) => v.to_string() + "\n",
+                None => String::new(),
+            };
+            ("200 OK".to_string(), headers, content)
+        }
+        "GET" | "HEAD" => ("200 OK".to_string(), headers, String::new()),
+        _ => (
+            "405 Method Not Allowed".to_string(),
+            vec!["Content-Type: text/plain; charset=utf-8".to_string()],
+            "method not allowed\n".to_string(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eth_batch() {
+        let config = EthereumConfig::default();
+        assert!(config.check().is_ok());
+        let body = br#"[
+            {"jsonrpc":"2.0","id":7,"method":"eth_blockNumber","params":[]},
+            {"jsonrpc":"2.0","id":"a","method":"web3_clientVersion"},
+            {"jsonrpc":"2.0","id":null,"method":"eth_accounts","params":[]}
+        ]"#;
+        let (status, headers, content) = eth_page(&config, "POST", "/", body, &ClientInfo::new());
+        assert!(status == "200 OK");
+        assert!(headers.contains(&"Content-Type: application/json".to_string()));
+        let answers: Value = serde_json::from_str(&content).unwrap();
+        let answers = answers.as_array().unwrap();
+        assert!(answers.len() == 3);
+        assert!(answers[0] == json!({"jsonrpc": "2.0", "id": 7, "result": "0x11bd040"}));
+        assert!(answers[1]["id"] == "a");
+        assert!(answers[1]["result"] == config.client_version);
+        assert!(answers[2]["id"] == Value::Null);
+        assert!(answers[2]["result"] == json!(["0x407d73d8a49eeb85d32cf465507dd71d507100c1"]));
+        /* single call, single answer */
+        let r = eth_rpc(
+            &config,
+            br#"{"jsonrpc":"2.0","id":1,"method":"net_version"}"#,
+            &ClientInfo::new(),
+        )
+        .unwrap();
+        assert!(r == json!({"jsonrpc": "2.0", "id": 1, "result": "1"}));
+        /* notifications are not answered */
+        let body = br#"[{"jsonrpc":"2.0","method":"net_version"}]"#;
+        assert!(eth_rpc(&config, body, &ClientInfo::new()).is_none());
+        let body = br#"[{"jsonrpc":"2.0","method":"net_version"},{"jsonrpc":"2.0","id":2,"method":"net_version"}]"#;
+        let r = eth_rpc(&config, body, &ClientInfo::new()).unwrap();
+        assert!(r.as_array().unwrap().len() == 1 && r[0]["id"] == 2);
+        /* invalid requests */
+        let r = eth_rpc(&config, b"{", &ClientInfo::new()).unwrap();
+        assert!(r["error"]["code"] == ETH_PARSE_ERROR && r["id"] == Value::Null);
+        let r = eth_rpc(&config, b"[]", &ClientInfo::new()).unwrap();
+        assert!(r["error"]["code"] == ETH_INVALID_REQUEST);
+        let r = eth_rpc(&config, br#"[1,{"id":3,"method":"x"}]"#, &ClientInfo::new()).unwrap();
+        assert!(r.as_array().unwrap().len() == 1);
+        assert!(r[0]["id"] == 3 && r[0]["error"]["code"] == ETH_INVALID_REQUEST);
+        /* configuration */
+        for (id, accounts) in [("", vec![]), ("1", vec!["0x1234"]), ("main", vec![])].iter() {
+            let config = EthereumConfig {
+                network_id: id.to_string(),
+                accounts: accounts.iter().map(|a| a.to_string()).collect(),
+                ..Default::default()
+            };
+            assert!(config.check().is_err());
+        }
+    }
+
+    #[test]
+    fn test_eth_send_transaction() {
+        let config = EthereumConfig::default();
+        let call = json!({
+            "jsonrpc": "2.0",
+            "id": 42,
+            "method": "eth_sendTransaction",
+            "params": [{
+                "from": "0x407d73d8a49eeb85d32cf465507dd71d507100c1",
+                "to": "0xd46e8dd67c5d32be8058bb8eb970870f07244567",
+                "value": "0x9184e72a000",
+            }],
+        });
+        let r = eth_call(&config, &call).unwrap();
+        assert!(r["id"] == 42 && r["error"]["code"] == ETH_METHOD_NOT_FOUND);
+        assert!(
+            r["error"]["message"]
+                == "the method eth_sendTransaction does not exist/is not available"
+        );
+        assert!(r.get("result").is_none());
+        /* the whole call is recorded */
+        let event = eth_event(&call, false, &ClientInfo::new());
+        assert!(event.name == "ethereum_rpc");
+        assert!(event.fields["method"] == "eth_sendTransaction");
+        assert!(event.fields["id"] == 42 && event.fields["batch"] == false);
+        assert!(event.fields["params"][0]["to"] == "0xd46e8dd67c5d32be8058bb8eb970870f07244567");
+        assert!(event.fields["params"][0]["value"] == "0x9184e72a000");
+        /* other verbs */
+        let (status, _, content) = eth_page(&config, "GET", "/", b"", &ClientInfo::new());
+        assert!(status == "200 OK" && content.is_empty());
+        let (status, _, _) = eth_page(&config, "PUT", "/", b"", &ClientInfo::new());
+        assert!(status == "405 Method Not Allowed");
+    }
+}
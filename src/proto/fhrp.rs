@@ -0,0 +1,628 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use log::*;
+use pnet::util::MacAddr;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::Masscanned;
+
+/* HSRP version 1 (RFC 2281) */
+const HSRP_PORT: u16 = 1985;
+const HSRP_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 2);
+const HSRP_LEN: usize = 20;
+const HSRP_OPCODE_COUP: u8 = 1;
+
+/* VRRP versions 2 (RFC 3768) and 3 (RFC 5798) */
+const VRRP_GROUP_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 18);
+const VRRP_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x12);
+const VRRP_TYPE_ADVERTISEMENT: u8 = 1;
+const VRRP_AUTH_SIMPLE: u8 = 1;
+
+/* HSRP (to 224.0.0.2) and VRRP (to 224.0.0.18 and ff02::12)
+ * advertisements are observed, never answered: they are recorded once
+ * per group and source, then again after window seconds (steady-state
+ * hellos are not). A priority change for a source, a new source MAC
+ * address for a known group and HSRP coups are logged as warnings
+ * (potential takeover). At most max_sources sources are remembered.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FhrpConfig {
+    pub enabled: bool,
+    pub hsrp_ports: Vec<u16>,
+    pub window: u64,
+    pub max_sources: usize,
+}
+
+impl Default for FhrpConfig {
+    fn default() -> Self {
+        FhrpConfig {
+            enabled: true,
+            hsrp_ports: vec![HSRP_PORT],
+            window: 300,
+            max_sources: 1024,
+        }
+    }
+}
+
+pub fn is_hsrp(masscanned: &Masscanned, client_info: &ClientInfo) -> bool {
+    let config = &masscanned.config.fhrp;
+    config.enabled
+        && client_info
+            .port
+            .dst
+            .is_some_and(|p| config.hsrp_ports.contains(&p))
+        && match client_info.ip.dst {
+            Some(IpAddr::V4(ip)) => !ip.is_multicast() || ip == HSRP_GROUP,
+            Some(IpAddr::V6(_)) => false,
+            None => true,
+        }
+}
+
+struct HsrpAdvertisement {
+    opcode: u8,
+    state: u8,
+    hellotime: u8,
+    holdtime: u8,
+    priority: u8,
+    group: u8,
+    authentication: String,
+    virtual_ip: Ipv4Addr,
+}
+
+/* RFC 2281 section 5 */
+fn hsrp_parse(data: &[u8]) -> Option<HsrpAdvertisement> {
+    if data.len() < HSRP_LEN || data[0] != 0 {
+        return None;
+    }
+    let auth = &data[8..16];
+    let auth_len = auth.iter().position(|&c| c == 0).unwrap_or(auth.len());
+    Some(HsrpAdvertisement {
+        opcode: data[1],
+        state: data[2],
+        hellotime: data[3],
+        holdtime: data[4],
+        priority: data[5],
+        group: data[6],
+        authentication: String::from_utf8_lossy(&auth[..auth_len]).to_string(),
+        virtual_ip: Ipv4Addr::new(data[16], data[17], data[18], data[19]),
+    })
+}
+
+fn hsrp_opcode_name(opcode: u8) -> String {
+    match opcode {
+        0 => "hello".to_string(),
+        HSRP_OPCODE_COUP => "coup".to_string(),
+        2 => "resign".to_string(),
+        o => o.to_string(),
+    }
+}
+
+fn hsrp_state_name(state: u8) -> String {
+    match state {
+        0 => "initial".to_string(),
+        1 => "learn".to_string(),
+        2 => "listen".to_string(),
+        4 => "speak".to_string(),
+        8 => "standby".to_string(),
+        16 => "active".to_string(),
+        s => s.to_string(),
+    }
+}
+
+struct VrrpAdvertisement {
+    version: u8,
+    vrid: u8,
+    priority: u8,
+    /* seconds (version 2) or centiseconds (version 3) */
+    interval: u16,
+    addresses: Vec<IpAddr>,
+    /* version 2, simple text password */
+    authentication: Option<String>,
+}
+
+/* RFC 3768 section 5.1 and RFC 5798 section 5.1: the addresses of
+ * VRRPv3 advertisements have the family of the IP layer */
+fn vrrp_parse(data: &[u8], ipv6: bool) -> Option<VrrpAdvertisement> {
+    if data.len() < 8 || data[0] & 0x0f != VRRP_TYPE_ADVERTISEMENT {
+        return None;
+    }
+    let version = data[0] >> 4;
+    let count = data[3] as usize;
+    let (interval, addr_len) = match version {
+        2 if !ipv6 => (data[5] as u16, 4),
+        3 => (
+            u16::from_be_bytes([data[4] & 0x0f, data[5]]),
+            if ipv6 { 16 } else { 4 },
+        ),
+        _ => return None,
+    };
+    let end = 8 + count * addr_len;
+    if data.len() < end {
+        return None;
+    }
+    let addresses = data[8..end]
+        .chunks(addr_len)
+        .map(|a| {
+            if addr_len == 4 {
+                IpAddr::V4(Ipv4Addr::new(a[0], a[1], a[2], a[3]))
+            } else {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(a);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+        })
+        .collect();
+    let authentication = if version == 2 && data[4] == VRRP_AUTH_SIMPLE {
+        let auth = data.get(end..end + 8)?;
+        let auth_len = auth.iter().position(|&c| c == 0).unwrap_or(auth.len());
+        Some(String::from_utf8_lossy(&auth[..auth_len]).to_string())
+    } else {
+        None
+    };
+    Some(VrrpAdvertisement {
+        version,
+        vrid: data[1],
+        priority: data[2],
+        interval,
+        addresses,
+        authentication,
+    })
+}
+
+/* what an advertisement tells, compared to those already seen */
+#[derive(Debug, PartialEq)]
+enum FhrpVerdict {
+    /* recorded less than window seconds ago */
+    Known,
+    /* new source, or recorded window seconds ago */
+    New,
+    /* the source used to advertise another priority */
+    PriorityChange(u8),
+    /* other sources are known for the group, with other MAC addresses */
+    NewMac,
+}
+
+struct FhrpSource {
+    priority: u8,
+    mac: Option<MacAddr>,
+    logged: Instant,
+    seen: Instant,
+}
+
+/* sources seen, by protocol, group and address */
+struct FhrpTable {
+    sources: HashMap<(&'static str, u8, IpAddr), FhrpSource>,
+}
+
+impl FhrpTable {
+    fn new() -> Self {
+        FhrpTable {
+            sources: HashMap::new(),
+        }
+    }
+
+    fn observe(
+        &mut self,
+        protocol: &'static str,
+        group: u8,
+        priority: u8,
+        client_info: &ClientInfo,
+        config: &FhrpConfig,
+        now: Instant,
+    ) -> FhrpVerdict {
+        let src = match client_info.ip.src {
+            Some(ip) => ip,
+            None => return FhrpVerdict::New,
+        };
+        let mac = client_info.mac.src;
+        let window = Duration::from_secs(config.window);
+        let key = (protocol, group, src);
+        let known_mac = self
+            .sources
+            .iter()
+            .filter(|((p, g, _), _)| *p == protocol && *g == group)
+            .map(|(_, s)| s.mac)
+            .collect::<Vec<_>>();
+        let new_mac = mac.is_some() && !known_mac.is_empty() && !known_mac.contains(&mac);
+        let verdict = match self.sources.get_mut(&key) {
+            Some(source) => {
+                source.seen = now;
+                let verdict = if new_mac {
+                    FhrpVerdict::NewMac
+                } else if source.priority != priority {
+                    FhrpVerdict::PriorityChange(source.priority)
+                } else if now.duration_since(source.logged) >= window {
+                    FhrpVerdict::New
+                } else {
+                    return FhrpVerdict::Known;
+                };
+                source.priority = priority;
+                source.mac = mac;
+                source.logged = now;
+                return verdict;
+            }
+            None if new_mac => FhrpVerdict::NewMac,
+            None => FhrpVerdict::New,
+        };
+        self.expire(config, now);
+        if self.sources.len() < config.max_sources {
+            self.sources.insert(
+                key,
+                FhrpSource {
+                    priority,
+                    mac,
+                    logged: now,
+                    seen: now,
+                },
+            );
+        }
+        verdict
+    }
+
+    /* forget sources not seen for window seconds, and make room for a
+     * new one */
+    fn expire(&mut self, config: &FhrpConfig, now: Instant) {
+        let window = Duration::from_secs(config.window);
+        self.sources
+            .retain(|_, s| now.duration_since(s.seen) < window);
+        while !self.sources.is_empty() && self.sources.len() >= config.max_sources {
+            let oldest = *self.sources.iter().min_by_key(|(_, s)| s.seen).unwrap().0;
+            self.sources.remove(&oldest);
+        }
+    }
+}
+
+lazy_static! {
+    static ref FHRP_TABLE: Mutex<FhrpTable> = Mutex::new(FhrpTable::new());
+}
+
+/* record an advertisement, unless it is already known */
+fn fhrp_log(protocol: &str, mut event: Event, verdict: FhrpVerdict, group: u8, priority: u8) {
+    let src = event
+        .client
+        .ip
+        .src
+        .map(|ip| ip.to_string())
+        .unwrap_or_default();
+    match verdict {
+        FhrpVerdict::Known => return,
+        FhrpVerdict::New => {}
+        FhrpVerdict::PriorityChange(previous) => {
+            warn!(
+                "{}: priority of {} for group {} changed from {} to {} - potential takeover",
+                protocol, src, group, previous, priority
+            );
+            event.set("alert", "priority_change");
+            event.set("previous_priority", previous);
+        }
+        FhrpVerdict::NewMac => {
+            warn!(
+                "{}: new MAC address {} for group {} (from {}) - potential takeover",
+                protocol,
+                event
+                    .client
+                    .mac
+                    .src
+                    .map(|mac| mac.to_string())
+                    .unwrap_or_default(),
+                group,
+                src
+            );
+            event.set("alert", "new_mac");
+        }
+    }
+    event.log();
+}
+
+fn fhrp_event(name: &'static str, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new(name, client_info);
+    if let Some(mac) = client_info.mac.src {
+        event.set("mac", mac.to_string());
+    }
+    event
+}
+
+/* HSRP advertisements (over UDP): never answered */
+pub fn hsrp(data: &[u8], masscanned: &Masscanned, client_info: &ClientInfo) {
+    let adv = match hsrp_parse(data) {
+        Some(adv) => adv,
+        None => {
+            debug!("hsrp: cannot parse advertisement");
+            return;
+        }
+    };
+    let config = &masscanned.config.fhrp;
+    let mut verdict = FHRP_TABLE.lock().unwrap().observe(
+        "hsrp",
+        adv.group,
+        adv.priority,
+        client_info,
+        config,
+        Instant::now(),
+    );
+    let mut event = fhrp_event("hsrp_advertisement", client_info);
+    event.set("version", 1);
+    event.set("opcode", hsrp_opcode_name(adv.opcode));
+    event.set("state", hsrp_state_name(adv.state));
+    event.set("hellotime", adv.hellotime);
+    event.set("holdtime", adv.holdtime);
+    event.set("group", adv.group);
+    event.set("priority", adv.priority);
+    event.set("authentication", adv.authentication);
+    event.set("virtual_ip", adv.virtual_ip.to_string());
+    if adv.opcode == HSRP_OPCODE_COUP {
+        warn!(
+            "hsrp: coup for group {} with priority {} - potential takeover",
+            adv.group, adv.priority
+        );
+        event.set("alert", "coup");
+        if verdict == FhrpVerdict::Known {
+            verdict = FhrpVerdict::New;
+        }
+    }
+    fhrp_log("hsrp", event, verdict, adv.group, adv.priority);
+}
+
+/* VRRP advertisements (IP protocol 112): never answered */
+pub fn vrrp(data: &[u8], masscanned: &Masscanned, client_info: &ClientInfo) {
+    let config = &masscanned.config.fhrp;
+    if !config.enabled {
+        return;
+    }
+    match client_info.ip.dst {
+        Some(IpAddr::V4(ip)) if ip.is_multicast() && ip != VRRP_GROUP_V4 => return,
+        Some(IpAddr::V6(ip)) if ip.is_multicast() && ip != VRRP_GROUP_V6 => return,
+        _ => {}
+    }
+    let ipv6 = matches!(client_info.ip.src, Some(IpAddr::V6(_)));
+    let adv = match vrrp_parse(data, ipv6) {
+        Some(adv) => adv,
+        None => {
+            debug!("vrrp: cannot parse advertisement");
+            return;
+        }
+    };
+    let verdict = FHRP_TABLE.lock().unwrap().observe(
+        "vrrp",
+        adv.vrid,
+        adv.priority,
+        client_info,
+        config,
+        Instant::now(),
+    );
+    let mut event = fhrp_event("vrrp_advertisement", client_info);
+    event.set("version", adv.version);
+    event.set("vrid", adv.vrid);
+    event.set("priority", adv.priority);
+    event.set("interval", adv.interval);
+    event.set(
+        "addresses",
+        adv.addresses
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>(),
+    );
+    if let Some(auth) = adv.authentication {
+        event.set("authentication", auth);
+    }
+    fhrp_log("vrrp", event, verdict, adv.vrid, adv.priority);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    use crate::proto::dispatch;
+
+    fn masscanned<'a>(config: Config, ips: Option<&'a HashSet<IpAddr>>) -> Masscanned<'a> {
+        Masscanned {
+            ip_addresses: ips,
+            ..crate::proto::test_util::masscanned(config)
+        }
+    }
+
+    fn client(src: &str, dst: &str, mac: MacAddr) -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.ip.src = Some(IpAddr::from_str(src).unwrap());
+        client_info.ip.dst = Some(IpAddr::from_str(dst).unwrap());
+        client_info.mac.src = Some(mac);
+        client_info
+    }
+
+    /* HSRPv1 hello from an active router (group 1, priority 120,
+     * authentication "cisco", virtual IP 192.168.0.1) */
+    const HSRP_HELLO: &[u8] = b"\x00\x00\x10\x03\x0a\x78\x01\x00cisco\x00\x00\x00\xc0\xa8\x00\x01";
+    /* VRRPv2 advertisement (VRID 1, priority 100, 192.168.0.1) */
+    const VRRP_V2: &[u8] =
+        b"\x21\x01\x64\x01\x00\x01\xba\x52\xc0\xa8\x00\x01\x00\x00\x00\x00\x00\x00\x00\x00";
+    /* VRRPv3 advertisement over IPv6, from fe80::2 (VRID 1, priority
+     * 100, fe80::1 and 2001:db8::1) */
+    const VRRP_V3: &[u8] = b"\x31\x01\x64\x02\x00\x64\x40\x2c\xfe\x80\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x20\x01\x0d\xb8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01";
+
+    #[test]
+    fn test_hsrp_parse() {
+        let adv = hsrp_parse(HSRP_HELLO).unwrap();
+        assert!(adv.opcode == 0 && hsrp_opcode_name(adv.opcode) == "hello");
+        assert!(hsrp_state_name(adv.state) == "active");
+        assert!(adv.hellotime == 3 && adv.holdtime == 10);
+        assert!(adv.priority == 120 && adv.group == 1);
+        assert!(adv.authentication == "cisco");
+        assert!(adv.virtual_ip == Ipv4Addr::new(192, 168, 0, 1));
+        assert!(hsrp_parse(&HSRP_HELLO[..19]).is_none());
+        /* HSRPv2 (TLVs) is not handled */
+        assert!(hsrp_parse(
+            b"\x01\x28\x02\x00\x04\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00"
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_vrrp_parse() {
+        let adv = vrrp_parse(VRRP_V2, false).unwrap();
+        assert!(adv.version == 2 && adv.vrid == 1 && adv.priority == 100);
+        assert!(adv.interval == 1);
+        assert!(adv.addresses == vec![IpAddr::from_str("192.168.0.1").unwrap()]);
+        assert!(adv.authentication.is_none());
+        /* simple text password */
+        let mut data = VRRP_V2.to_vec();
+        data[4] = VRRP_AUTH_SIMPLE;
+        data[12..17].copy_from_slice(b"vrrp!");
+        let adv = vrrp_parse(&data, false).unwrap();
+        assert!(adv.authentication.as_deref() == Some("vrrp!"));
+        assert!(vrrp_parse(&data[..12], false).is_none());
+        let adv = vrrp_parse(VRRP_V3, true).unwrap();
+        assert!(adv.version == 3 && adv.vrid == 1 && adv.priority == 100);
+        assert!(adv.interval == 100);
+        assert!(
+            adv.addresses
+                == vec![
+                    IpAddr::from_str("fe80::1").unwrap(),
+                    IpAddr::from_str("2001:db8::1").unwrap()
+                ]
+        );
+        /* VRRPv2 is IPv4 only, and the count must match */
+        assert!(vrrp_parse(VRRP_V2, true).is_none());
+        assert!(vrrp_parse(&VRRP_V3[..39], true).is_none());
+    }
+
+    #[test]
+    fn test_fhrp_table() {
+        let config = FhrpConfig {
+            max_sources: 2,
+            ..FhrpConfig::default()
+        };
+        let mut table = FhrpTable::new();
+        let now = Instant::now();
+        let mac = MacAddr::new(0x00, 0x00, 0x0c, 0x07, 0xac, 0x01);
+        let router = client("192.168.0.2", "224.0.0.2", mac);
+        assert!(table.observe("hsrp", 1, 120, &router, &config, now) == FhrpVerdict::New);
+        /* steady-state hellos */
+        for _ in 0..3 {
+            assert!(table.observe("hsrp", 1, 120, &router, &config, now) == FhrpVerdict::Known);
+        }
+        /* other protocol, other group */
+        assert!(table.observe("vrrp", 1, 120, &router, &config, now) == FhrpVerdict::New);
+        /* window elapsed */
+        let later = now + Duration::from_secs(300);
+        assert!(table.observe("hsrp", 1, 120, &router, &config, later) == FhrpVerdict::New);
+        assert!(table.observe("hsrp", 1, 120, &router, &config, later) == FhrpVerdict::Known);
+        assert!(
+            table.observe("hsrp", 1, 255, &router, &config, later)
+                == FhrpVerdict::PriorityChange(120)
+        );
+        assert!(table.observe("hsrp", 1, 255, &router, &config, later) == FhrpVerdict::Known);
+        /* new source with a new MAC address for the group */
+        let attacker = client(
+            "192.168.0.66",
+            "224.0.0.2",
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x66),
+        );
+        assert!(table.observe("hsrp", 1, 255, &attacker, &config, later) == FhrpVerdict::NewMac);
+        assert!(table.observe("hsrp", 1, 255, &attacker, &config, later) == FhrpVerdict::Known);
+        /* known source, new MAC address */
+        let spoofed = client(
+            "192.168.0.2",
+            "224.0.0.2",
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x67),
+        );
+        assert!(table.observe("hsrp", 1, 255, &spoofed, &config, later) == FhrpVerdict::NewMac);
+        /* bounded: the vrrp source has expired */
+        assert!(table.sources.len() == 2);
+        assert!(!table.sources.keys().any(|(p, _, _)| *p == "vrrp"));
+        /* unknown MAC address */
+        let mut unknown = client("192.168.0.3", "224.0.0.2", mac);
+        unknown.mac.src = None;
+        assert!(table.observe("hsrp", 2, 100, &unknown, &config, later) == FhrpVerdict::New);
+    }
+
+    #[test]
+    fn test_fhrp_dispatch() {
+        let mut client_info = client(
+            "192.168.0.2",
+            "224.0.0.2",
+            MacAddr::new(0x00, 0x00, 0x0c, 0x07, 0xac, 0x0a),
+        );
+        client_info.port.src = Some(HSRP_PORT);
+        client_info.port.dst = Some(HSRP_PORT);
+        let masscanned = self::masscanned(Config::default(), None);
+        assert!(is_hsrp(&masscanned, &client_info));
+        /* observed, never answered */
+        assert!(dispatch(HSRP_HELLO, &masscanned, &mut client_info, None).is_none());
+        assert!(FHRP_TABLE.lock().unwrap().sources.contains_key(&(
+            "hsrp",
+            1,
+            IpAddr::from_str("192.168.0.2").unwrap()
+        )));
+        /* other groups are not observed */
+        client_info.ip.dst = Some(IpAddr::from_str("224.0.0.5").unwrap());
+        assert!(!is_hsrp(&masscanned, &client_info));
+        let client_info = client(
+            "fe80::2",
+            "ff02::12",
+            MacAddr::new(0x00, 0x00, 0x5e, 0x00, 0x02, 0x0a),
+        );
+        vrrp(VRRP_V3, &masscanned, &client_info);
+        assert!(FHRP_TABLE.lock().unwrap().sources.contains_key(&(
+            "vrrp",
+            1,
+            IpAddr::from_str("fe80::2").unwrap()
+        )));
+        /* disabled */
+        let mut config = Config::default();
+        config.fhrp.enabled = false;
+        let masscanned = self::masscanned(config, None);
+        let mut client_info = client(
+            "192.168.0.3",
+            "224.0.0.2",
+            MacAddr::new(0x00, 0x00, 0x0c, 0x07, 0xac, 0x0a),
+        );
+        client_info.port.dst = Some(HSRP_PORT);
+        assert!(!is_hsrp(&masscanned, &client_info));
+        let client_info = client(
+            "192.168.0.3",
+            "224.0.0.18",
+            MacAddr::new(0x00, 0x00, 0x5e, 0x00, 0x01, 0x0a),
+        );
+        vrrp(VRRP_V2, &masscanned, &client_info);
+        assert!(!FHRP_TABLE.lock().unwrap().sources.contains_key(&(
+            "vrrp",
+            1,
+            IpAddr::from_str("192.168.0.3").unwrap()
+        )));
+    }
+
+    #[test]
+    fn test_fhrp_config() {
+        let config = Config::from_str("[fhrp]\nwindow = 60\nhsrp_ports = [1985, 2029]\n")
+            .expect("error parsing config");
+        assert!(config.fhrp.enabled && config.fhrp.window == 60);
+        assert!(config.fhrp.hsrp_ports == vec![1985, 2029]);
+        assert!(Config::from_str("[fhrp]\nwindow = -1\n").is_err());
+    }
+}
@@ -0,0 +1,369 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use chrono::{Duration, Utc};
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::line::LineBuffer;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* Finger daemon (RFC 1288), over TCP: the query (one line) gets the
+ * list of the users below when empty, the description of a user (with
+ * the plan for /W queries) otherwise. Forwarding queries (user@host)
+ * are refused.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FingerConfig {
+    pub ports: Vec<u16>,
+    pub users: Vec<FingerUser>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FingerUser {
+    pub login: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    #[serde(default = "default_shell")]
+    pub shell: String,
+    #[serde(default = "default_tty")]
+    pub tty: String,
+    /* content of ~/.plan (empty: no plan) */
+    #[serde(default)]
+    pub plan: String,
+}
+
+fn default_directory() -> String {
+    "/home".to_string()
+}
+
+fn default_shell() -> String {
+    "/bin/sh".to_string()
+}
+
+fn default_tty() -> String {
+    "pts/0".to_string()
+}
+
+impl Default for FingerConfig {
+    fn default() -> Self {
+        FingerConfig {
+            ports: vec![79],
+            users: vec![
+                FingerUser {
+                    login: "root".to_string(),
+                    name: "Charlie Root".to_string(),
+                    directory: "/root".to_string(),
+                    shell: "/bin/csh".to_string(),
+                    tty: "ttyv0".to_string(),
+                    plan: String::new(),
+                },
+                FingerUser {
+                    login: "operator".to_string(),
+                    name: "System Operator".to_string(),
+                    directory: "/home/operator".to_string(),
+                    shell: "/bin/sh".to_string(),
+                    tty: "pts/1".to_string(),
+                    plan: "Backups every night at 2am.\nDo not reboot the box without asking."
+                        .to_string(),
+                },
+            ],
+        }
+    }
+}
+
+impl FingerConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for u in self.users.iter() {
+            if u.login.is_empty() || u.login.contains(|c: char| c.is_whitespace() || c == '@') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("finger: invalid login: {:?}", u.login),
+                ));
+            }
+            for s in [&u.name, &u.directory, &u.shell, &u.tty] {
+                if s.contains(['\r', '\n']) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("finger: invalid field (CR or LF found): {:?}", s),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/* users logged in a few hours ago */
+fn finger_login_time(format: &str) -> String {
+    (Utc::now() - Duration::hours(3)).format(format).to_string()
+}
+
+/* state of a finger flow: partial query */
+#[derive(Default)]
+pub struct FingerState {
+    lines: LineBuffer,
+}
+
+/* RFC 1288 section 2.3: [/W] [user][@host...] */
+#[derive(PartialEq, Debug)]
+struct FingerQuery {
+    verbose: bool,
+    user: String,
+    /* forwarding: the hosts (the last one first) */
+    hosts: Vec<String>,
+}
+
+fn finger_parse(line: &[u8]) -> FingerQuery {
+    let line = String::from_utf8_lossy(line);
+    let mut query = line.trim();
+    let mut verbose = false;
+    if let Some(q) = query
+        .strip_prefix("/W")
+        .or_else(|| query.strip_prefix("/w"))
+    {
+        verbose = true;
+        query = q.trim_start();
+    }
+    let mut parts = query.split('@');
+    let user = parts.next().unwrap_or("").trim().to_string();
+    let hosts = parts.map(|h| h.trim().to_string()).collect();
+    FingerQuery {
+        verbose,
+        user,
+        hosts,
+    }
+}
+
+/* the login, or a word of the name (as fingerd does) */
+fn finger_match(user: &FingerUser, query: &str) -> bool {
+    user.login == query
+        || user
+            .name
+            .split_whitespace()
+            .any(|w| w.eq_ignore_ascii_case(query))
+}
+
+fn finger_list(users: &[FingerUser]) -> String {
+    let mut out = "Login     Name                 Tty      Idle  Login Time\r\n".to_string();
+    for u in users {
+        out.push_str(&format!(
+            "{:<9} {:<20} {:<8} {:>4}  {}\r\n",
+            u.login,
+            u.name,
+            u.tty,
+            "-",
+            finger_login_time("%b %e %H:%M")
+        ));
+    }
+    out
+}
+
+fn finger_long(user: &FingerUser, verbose: bool) -> String {
+    let mut out = format!(
+        "Login: {:<32} Name: {}\r\nDirectory: {:<28} Shell: {}\r\n",
+        user.login, user.name, user.directory, user.shell
+    );
+    out.push_str(&format!(
+        "On since {} (UTC) on {}\r\n",
+        finger_login_time("%a %b %e %H:%M"),
+        user.tty
+    ));
+    if verbose {
+        out.push_str("No Mail.\r\n");
+        if user.plan.is_empty() {
+            out.push_str("No Plan.\r\n");
+        } else {
+            out.push_str("Plan:\r\n");
+            for line in user.plan.lines() {
+                out.push_str(line);
+                out.push_str("\r\n");
+            }
+        }
+    }
+    out
+}
+
+/* answer to a query */
+fn finger_answer(query: &FingerQuery, config: &FingerConfig) -> (String, Option<bool>) {
+    if !query.hosts.is_empty() {
+        return ("finger: forwarding service denied\r\n".to_string(), None);
+    }
+    if query.user.is_empty() {
+        if query.verbose {
+            let users: Vec<String> = config.users.iter().map(|u| finger_long(u, true)).collect();
+            return (users.join("\r\n"), None);
+        }
+        return (finger_list(&config.users), None);
+    }
+    let users: Vec<String> = config
+        .users
+        .iter()
+        .filter(|u| finger_match(u, &query.user))
+        .map(|u| finger_long(u, query.verbose))
+        .collect();
+    if users.is_empty() {
+        (
+            format!("finger: {}: no such user.\r\n", query.user),
+            Some(false),
+        )
+    } else {
+        (users.join("\r\n"), Some(true))
+    }
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving finger data");
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Finger(s)) => s,
+        _ => FingerState::default(),
+    };
+    let line = match state.lines.push(data).into_iter().next() {
+        Some(line) => line,
+        None => {
+            if let Some(t) = tcb {
+                t.proto_state = ProtoState::Finger(state);
+            }
+            return None;
+        }
+    };
+    let query = finger_parse(&line);
+    let (answer, found) = finger_answer(&query, &masscanned.config.finger);
+    let mut event = Event::new("finger_query", client_info);
+    event.set("query", String::from_utf8_lossy(&line).to_string());
+    event.set("verbose", query.verbose);
+    if !query.user.is_empty() {
+        event.set("user", query.user.clone());
+    }
+    if !query.hosts.is_empty() {
+        event.set("forward", query.hosts.clone());
+        warn!("finger forwarding refused: {:?}", query.hosts);
+    }
+    if let Some(found) = found {
+        event.set("found", found);
+    }
+    event.log();
+    /* one query per connection */
+    client_info.close = true;
+    debug!("sending finger data");
+    Some(answer.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    fn query(data: &[u8]) -> (String, bool) {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(79);
+        let mut tcb = TCPControlBlock::new();
+        let reply =
+            crate::proto::dispatch(data, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        (String::from_utf8(reply).unwrap(), client_info.close)
+    }
+
+    #[test]
+    fn test_finger_parse() {
+        assert!(
+            finger_parse(b"/W  root")
+                == FingerQuery {
+                    verbose: true,
+                    user: "root".to_string(),
+                    hosts: vec![],
+                }
+        );
+        assert!(
+            finger_parse(b"bob@a.example@b.example")
+                == FingerQuery {
+                    verbose: false,
+                    user: "bob".to_string(),
+                    hosts: vec!["a.example".to_string(), "b.example".to_string()],
+                }
+        );
+        assert!(finger_parse(b"") == finger_parse(b"  "));
+    }
+
+    #[test]
+    fn test_finger_list() {
+        let (reply, close) = query(b"\r\n");
+        assert!(close);
+        assert!(reply.starts_with("Login     Name"));
+        assert!(reply.contains("\r\nroot      Charlie Root         ttyv0"));
+        assert!(reply.contains("\r\noperator  System Operator"));
+        /* long format for every user */
+        let (reply, _) = query(b"/W\r\n");
+        assert!(reply.starts_with("Login: root "));
+        assert!(reply.contains("Login: operator ") && reply.contains("Plan:\r\n"));
+    }
+
+    #[test]
+    fn test_finger_user() {
+        let (reply, close) = query(b"root\r\n");
+        assert!(close);
+        assert!(reply.starts_with("Login: root "));
+        assert!(reply.contains("Shell: /bin/csh\r\n"));
+        assert!(!reply.contains("Plan"));
+        /* by a word of the name, with the plan */
+        let (reply, _) = query(b"/W operator\r\n");
+        assert!(reply.contains("Plan:\r\nBackups every night at 2am.\r\n"));
+        let (reply, _) = query(b"/W charlie\r\n");
+        assert!(reply.contains("No Plan.\r\n"));
+        let (reply, _) = query(b"alice\r\n");
+        assert!(reply == "finger: alice: no such user.\r\n");
+        /* query split across segments */
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        assert!(repl(b"ro", &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        let reply = repl(b"ot\r\n", &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(reply.starts_with(b"Login: root "));
+    }
+
+    #[test]
+    fn test_finger_forward() {
+        for q in [
+            &b"root@host.example\r\n"[..],
+            b"@host.example\r\n",
+            b"/W @a@b\r\n",
+        ] {
+            let (reply, close) = query(q);
+            assert!(close);
+            assert!(reply == "finger: forwarding service denied\r\n");
+        }
+    }
+}
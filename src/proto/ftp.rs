@@ -0,0 +1,318 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::net::IpAddr;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::line::{line_command, LineBuffer};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* FTP service (server-first): the banner is sent (after "220 ") once
+ * the TCP handshake is completed on one of the ports. Logins are
+ * refused unless login is set.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FtpConfig {
+    pub ports: Vec<u16>,
+    pub banner: String,
+    pub login: bool,
+    pub system: String,
+    pub features: Vec<String>,
+}
+
+impl Default for FtpConfig {
+    fn default() -> Self {
+        FtpConfig {
+            ports: vec![21],
+            banner: "(vsFTPd 3.0.3)".to_string(),
+            login: false,
+            system: "UNIX Type: L8".to_string(),
+            features: [
+                "EPRT",
+                "EPSV",
+                "MDTM",
+                "PASV",
+                "REST STREAM",
+                "SIZE",
+                "TVFS",
+                "UTF8",
+            ]
+            .iter()
+            .map(|f| f.to_string())
+            .collect(),
+        }
+    }
+}
+
+impl FtpConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        let lines = [&self.banner, &self.system];
+        for s in lines.iter().copied().chain(self.features.iter()) {
+            if s.contains(['\r', '\n']) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("ftp: invalid line (CR or LF found): {:?}", s),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/* state of an FTP flow: partial command line, and user name given by
+ * USER */
+#[derive(Default)]
+pub struct FtpState {
+    lines: LineBuffer,
+    user: Option<String>,
+}
+
+fn ftp_reply(code: u16, text: &str) -> Vec<u8> {
+    format!("{} {}\r\n", code, text).into_bytes()
+}
+
+/* port of a (non-functional) passive mode data connection */
+fn ftp_passive_port() -> u16 {
+    1024 + rand::random::<u16>() % (65535 - 1024)
+}
+
+/* answer to a command line, and the event to log (if any) */
+fn ftp_command(
+    line: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    state: &mut FtpState,
+) -> (Vec<u8>, Option<Event>) {
+    let config = &masscanned.config.ftp;
+    let (command, argument) = line_command(line);
+    let argument = argument.as_str();
+    let mut event = Event::new("ftp_command", client_info);
+    event.set("command", command.clone());
+    event.set("argument", argument);
+    let repl = match command.as_str() {
+        "USER" => {
+            state.user = Some(argument.to_string());
+            return (ftp_reply(331, "Please specify the password."), None);
+        }
+        "PASS" => {
+            let user = match state.user.take() {
+                Some(u) => u,
+                None => return (ftp_reply(503, "Login with USER first."), None),
+            };
+            warn!("FTP login: {}:{}", user, argument);
+            let mut event = Event::new("ftp_login", client_info);
+            event.set("user", user);
+            event.set("password", argument);
+            event.set("success", config.login);
+            let repl = if config.login {
+                ftp_reply(230, "Login successful.")
+            } else {
+                ftp_reply(530, "Login incorrect.")
+            };
+            return (repl, Some(event));
+        }
+        "SYST" => ftp_reply(215, &config.system),
+        "FEAT" => {
+            let mut repl = b"211-Features:\r\n".to_vec();
+            for f in config.features.iter() {
+                repl.extend(format!(" {}\r\n", f).into_bytes());
+            }
+            repl.extend(ftp_reply(211, "End"));
+            repl
+        }
+        "QUIT" => {
+            client_info.close = true;
+            ftp_reply(221, "Goodbye.")
+        }
+        "PASV" => {
+            let ip = match client_info.ip.dst {
+                Some(IpAddr::V4(ip)) => ip.octets(),
+                _ => [0, 0, 0, 0],
+            };
+            let port = ftp_passive_port();
+            ftp_reply(
+                227,
+                &format!(
+                    "Entering Passive Mode ({},{},{},{},{},{}).",
+                    ip[0],
+                    ip[1],
+                    ip[2],
+                    ip[3],
+                    port >> 8,
+                    port & 0xff
+                ),
+            )
+        }
+        "EPSV" => ftp_reply(
+            229,
+            &format!(
+                "Entering Extended Passive Mode (|||{}|)",
+                ftp_passive_port()
+            ),
+        ),
+        "PORT" | "EPRT" => ftp_reply(200, "PORT command successful. Consider using PASV."),
+        "" => return (Vec::new(), None),
+        _ => ftp_reply(502, "Command not implemented."),
+    };
+    (repl, Some(event))
+}
+
+/* banner, sent when the TCP handshake is completed */
+pub fn greeting(masscanned: &Masscanned, tcb: &mut TCPControlBlock) -> Option<Vec<u8>> {
+    tcb.proto_state = ProtoState::Ftp(FtpState::default());
+    Some(ftp_reply(220, &masscanned.config.ftp.banner))
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving FTP data");
+    /* FTP is only answered over TCP, after the banner */
+    let state = match tcb {
+        Some(TCPControlBlock {
+            proto_state: ProtoState::Ftp(s),
+            ..
+        }) => s,
+        _ => return None,
+    };
+    let mut repl_data = Vec::new();
+    for line in state.lines.push(data) {
+        let (repl, event) = ftp_command(&line, masscanned, client_info, state);
+        if let Some(e) = event {
+            e.log();
+        }
+        repl_data.extend(repl);
+        if client_info.close {
+            break;
+        }
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending FTP data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_ftp_login() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.ip.dst = Some(IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1)));
+        let mut state = FtpState::default();
+        /* PASS first */
+        let (repl, event) = ftp_command(b"PASS secret", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"503 ") && event.is_none());
+        let (repl, event) = ftp_command(b"user root", &masscanned, &mut client_info, &mut state);
+        assert!(repl == b"331 Please specify the password.\r\n" && event.is_none());
+        let (repl, event) =
+            ftp_command(b"PASS pass word", &masscanned, &mut client_info, &mut state);
+        assert!(repl == b"530 Login incorrect.\r\n");
+        let event = event.unwrap();
+        assert!(event.name == "ftp_login");
+        assert!(event.fields["user"] == "root");
+        assert!(event.fields["password"] == "pass word");
+        assert!(event.fields["success"] == false);
+        /* the user name is used once */
+        let (repl, _) = ftp_command(b"PASS secret", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"503 "));
+        let (repl, _) = ftp_command(b"SYST", &masscanned, &mut client_info, &mut state);
+        assert!(repl == b"215 UNIX Type: L8\r\n");
+        let (repl, _) = ftp_command(b"FEAT", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"211-Features:\r\n EPRT\r\n"));
+        assert!(repl.ends_with(b" UTF8\r\n211 End\r\n"));
+        let (repl, _) = ftp_command(b"PASV", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"227 Entering Passive Mode (192,0,2,1,"));
+        let (repl, _) = ftp_command(b"EPSV", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"229 Entering Extended Passive Mode (|||"));
+        let (repl, event) = ftp_command(
+            b"PORT 192,0,2,2,4,1",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl.starts_with(b"200 "));
+        let event = event.unwrap();
+        assert!(event.fields["command"] == "PORT");
+        assert!(event.fields["argument"] == "192,0,2,2,4,1");
+        let (repl, _) = ftp_command(
+            b"RETR /etc/passwd",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl.starts_with(b"502 "));
+        assert!(!client_info.close);
+        let (repl, _) = ftp_command(b"QUIT", &masscanned, &mut client_info, &mut state);
+        assert!(repl == b"221 Goodbye.\r\n");
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_ftp_repl() {
+        let masscanned = masscanned(
+            Config::from_str("[ftp]\nbanner = \"ProFTPD Server\"\nlogin = true\n").unwrap(),
+        );
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        /* nothing before the banner */
+        assert!(repl(b"USER a\r\n", &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        assert!(repl(b"USER a\r\n", &masscanned, &mut client_info, None).is_none());
+        assert!(greeting(&masscanned, &mut tcb).unwrap() == b"220 ProFTPD Server\r\n");
+        /* commands bundled in one segment, or split across segments */
+        let repl_data = repl(
+            b"USER anonymous\r\nPASS guest@",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"331 Please specify the password.\r\n");
+        let repl_data = repl(
+            b"example.com\r\nQUIT\r\nSYST\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        /* nothing after QUIT */
+        assert!(repl_data == b"230 Login successful.\r\n221 Goodbye.\r\n");
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_ftp_config() {
+        assert!(Config::from_str("[ftp]\nbanner = \"FTP\\r\\n230 OK\"\n").is_err());
+        assert!(Config::from_str("[ftp]\nfeatures = [\"UTF8\\n\"]\n").is_err());
+        assert!(Config::from_str("[ftp]\nports = [21, 2121]\n").is_ok());
+    }
+}
@@ -0,0 +1,485 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::net::Ipv4Addr;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::proto::zlib::{zlib_compress, zlib_decompress};
+use crate::Masscanned;
+
+/* Gh0st RAT packets: a tag ("Gh0st" in the original source, renamed
+ * in many variants), the total length and the uncompressed length
+ * (little-endian), then the zlib-compressed content, whose first byte
+ * is a token (implant to controller) or a command (controller to
+ * implant) */
+pub const GH0ST_PATTERN_TAG: &[u8; 5] = b"Gh0st";
+const GH0ST_MAX_TAG: usize = 16;
+const GH0ST_LENGTHS_LEN: usize = 8;
+
+const GH0ST_TOKEN_AUTH: u8 = 100;
+const GH0ST_TOKEN_HEARTBEAT: u8 = 101;
+const GH0ST_TOKEN_LOGIN: u8 = 102;
+const GH0ST_COMMAND_ACTIVED: u8 = 0x00;
+
+/* tokens recorded with their names (gh0st 3.6) */
+const GH0ST_TOKENS: [(u8, &str); 12] = [
+    (GH0ST_TOKEN_AUTH, "auth"),
+    (GH0ST_TOKEN_HEARTBEAT, "heartbeat"),
+    (GH0ST_TOKEN_LOGIN, "login"),
+    (103, "drive_list"),
+    (104, "file_list"),
+    (105, "file_size"),
+    (106, "file_data"),
+    (107, "transfer_finish"),
+    (108, "delete_finish"),
+    (109, "get_transfer_mode"),
+    (110, "get_filedata"),
+    (111, "createfolder_finish"),
+];
+
+/* LOGININFO (gh0st 3.6, 32-bit alignment): token, OSVERSIONINFOEX,
+ * CPU clock, IPv4 address, host name, webcam flag and link speed */
+const GH0ST_LOGIN_OS_VERSION: usize = 8;
+const GH0ST_LOGIN_CSD_VERSION: usize = 24;
+const GH0ST_LOGIN_CSD_VERSION_LEN: usize = 128;
+const GH0ST_LOGIN_CPU_MHZ: usize = 160;
+const GH0ST_LOGIN_IP: usize = 164;
+const GH0ST_LOGIN_HOSTNAME: usize = 168;
+const GH0ST_LOGIN_HOSTNAME_LEN: usize = 50;
+const GH0ST_LOGIN_WEBCAM: usize = 218;
+const GH0ST_LOGIN_SPEED: usize = 220;
+
+/* Gh0st RAT controller (on the configured TCP ports, and on other
+ * ports for packets tagged "Gh0st"): packets with one of the
+ * configured tags are decompressed (up to max_size bytes) and
+ * recorded, login packets get the command that activates the implant
+ * and heartbeats get heartbeat_reply (COMMAND_REPLAY_HEARTBEAT, whose
+ * value depends on the variant), with the tag of the implant.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Gh0stConfig {
+    pub ports: Vec<u16>,
+    pub tags: Vec<String>,
+    pub max_size: usize,
+    pub heartbeat_reply: u8,
+    pub log_max: usize,
+}
+
+impl Default for Gh0stConfig {
+    fn default() -> Self {
+        Gh0stConfig {
+            ports: Vec::new(),
+            tags: vec!["Gh0st".to_string()],
+            max_size: 65536,
+            heartbeat_reply: 49,
+            log_max: 4096,
+        }
+    }
+}
+
+impl Gh0stConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if self.tags.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "gh0st: at least one tag is needed",
+            ));
+        }
+        for tag in self.tags.iter() {
+            if tag.is_empty() || tag.len() > GH0ST_MAX_TAG {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "gh0st: invalid tag {:?} (1 to {} bytes)",
+                        tag, GH0ST_MAX_TAG
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/* state of a flow: tag used by the implant, and the beginning of a
+ * packet not received entirely yet */
+#[derive(Default)]
+pub struct Gh0stState {
+    tag: Option<Vec<u8>>,
+    buffer: Vec<u8>,
+}
+
+fn le32(data: &[u8], offset: usize) -> Option<u32> {
+    let b = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/* NUL-terminated string of a fixed-size field */
+fn gh0st_cstring(data: &[u8], offset: usize, len: usize) -> Option<String> {
+    let field = data.get(offset..std::cmp::min(offset + len, data.len()))?;
+    let end = field.iter().position(|c| *c == 0).unwrap_or(field.len());
+    Some(String::from_utf8_lossy(&field[..end]).to_string())
+}
+
+fn gh0st_packet(tag: &[u8], content: &[u8]) -> Vec<u8> {
+    let compressed = zlib_compress(content);
+    let mut packet = tag.to_vec();
+    packet.extend_from_slice(
+        &((tag.len() + GH0ST_LENGTHS_LEN + compressed.len()) as u32).to_le_bytes(),
+    );
+    packet.extend_from_slice(&(content.len() as u32).to_le_bytes());
+    packet.extend(compressed);
+    packet
+}
+
+fn gh0st_login(event: &mut Event, content: &[u8]) {
+    if let (Some(major), Some(minor), Some(build)) = (
+        le32(content, GH0ST_LOGIN_OS_VERSION),
+        le32(content, GH0ST_LOGIN_OS_VERSION + 4),
+        le32(content, GH0ST_LOGIN_OS_VERSION + 8),
+    ) {
+        event.set("os_version", format!("{}.{}.{}", major, minor, build));
+    }
+    if let Some(csd) = gh0st_cstring(
+        content,
+        GH0ST_LOGIN_CSD_VERSION,
+        GH0ST_LOGIN_CSD_VERSION_LEN,
+    ) {
+        event.set("service_pack", csd);
+    }
+    if let Some(mhz) = le32(content, GH0ST_LOGIN_CPU_MHZ) {
+        event.set("cpu_mhz", mhz);
+    }
+    if let Some(ip) = content.get(GH0ST_LOGIN_IP..GH0ST_LOGIN_IP + 4) {
+        event.set("ip", Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]).to_string());
+    }
+    if let Some(hostname) = gh0st_cstring(content, GH0ST_LOGIN_HOSTNAME, GH0ST_LOGIN_HOSTNAME_LEN) {
+        event.set("hostname", hostname);
+    }
+    if let Some(webcam) = content.get(GH0ST_LOGIN_WEBCAM) {
+        event.set("webcam", *webcam != 0);
+    }
+    if let Some(speed) = le32(content, GH0ST_LOGIN_SPEED) {
+        event.set("speed", speed);
+    }
+}
+
+fn gh0st_event(
+    tag: &[u8],
+    length: usize,
+    content: &[u8],
+    config: &Gh0stConfig,
+    client_info: &ClientInfo,
+) -> Event {
+    let mut event = Event::new("gh0st_packet", client_info);
+    event.set("tag", String::from_utf8_lossy(tag));
+    event.set("length", length);
+    event.set("uncompressed_length", content.len());
+    if let Some(token) = content.first() {
+        event.set("token", *token);
+        if let Some((_, name)) = GH0ST_TOKENS.iter().find(|(t, _)| t == token) {
+            event.set("name", *name);
+        }
+        if *token == GH0ST_TOKEN_LOGIN {
+            gh0st_login(&mut event, content);
+        }
+    }
+    let len = std::cmp::min(content.len(), config.log_max);
+    event.set("data", encode(&content[..len], Encoding::Base64));
+    event
+}
+
+/* packet that could not be used: recorded, and the connection is
+ * closed */
+fn gh0st_invalid(tag: &[u8], error: &str, client_info: &mut ClientInfo) {
+    info!("invalid Gh0st packet: {}", error);
+    let mut event = Event::new("gh0st_packet", client_info);
+    event.set("tag", String::from_utf8_lossy(tag));
+    event.set("error", error);
+    event.log();
+    client_info.close = true;
+}
+
+/* tag of the packet at the beginning of data (and whether it is
+ * entirely there), or None when data cannot start with any
+ * configured tag */
+fn gh0st_tag(data: &[u8], state: &Gh0stState, config: &Gh0stConfig) -> Option<(Vec<u8>, bool)> {
+    let tags: Vec<&[u8]> = match &state.tag {
+        Some(t) => vec![t],
+        None => config.tags.iter().map(|t| t.as_bytes()).collect(),
+    };
+    if let Some(tag) = tags.iter().find(|t| data.starts_with(t)) {
+        return Some((tag.to_vec(), true));
+    }
+    tags.iter()
+        .find(|t| t.starts_with(data))
+        .map(|t| (t.to_vec(), false))
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving Gh0st data");
+    let config = &masscanned.config.gh0st;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Gh0st(s)) => s,
+        _ => Gh0stState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while !state.buffer.is_empty() {
+        let tag = match gh0st_tag(&state.buffer, &state, config) {
+            Some((tag, true)) => tag,
+            Some((_, false)) => break,
+            None => {
+                info!("Gh0st packet with an unknown tag");
+                client_info.close = true;
+                return None;
+            }
+        };
+        let header_len = tag.len() + GH0ST_LENGTHS_LEN;
+        let (length, uncompressed) = match (
+            le32(&state.buffer, tag.len()),
+            le32(&state.buffer, tag.len() + 4),
+        ) {
+            (Some(l), Some(u)) => (l as usize, u as usize),
+            _ => break,
+        };
+        if length <= header_len || length - header_len > config.max_size {
+            gh0st_invalid(&tag, "invalid length", client_info);
+            return None;
+        }
+        if uncompressed > config.max_size {
+            gh0st_invalid(&tag, "uncompressed length too large", client_info);
+            return None;
+        }
+        if state.buffer.len() < length {
+            break;
+        }
+        let packet: Vec<u8> = state.buffer.drain(..length).collect();
+        let content = match zlib_decompress(&packet[header_len..], uncompressed) {
+            Some(c) if c.len() == uncompressed => c,
+            _ => {
+                gh0st_invalid(&tag, "invalid compressed data", client_info);
+                return None;
+            }
+        };
+        gh0st_event(&tag, length, &content, config, client_info).log();
+        match content.first() {
+            Some(&GH0ST_TOKEN_LOGIN) => {
+                repl_data.extend(gh0st_packet(&tag, &[GH0ST_COMMAND_ACTIVED]));
+            }
+            Some(&GH0ST_TOKEN_HEARTBEAT) => {
+                repl_data.extend(gh0st_packet(&tag, &[config.heartbeat_reply]));
+            }
+            _ => {}
+        }
+        state.tag = Some(tag);
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Gh0st(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending Gh0st data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(8000);
+        client_info
+    }
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /* LOGININFO of a Windows XP SP3 host, compressed by zlib */
+    const LOGIN: &str = "476830737458000000e000000078da4b6360609803c4ac40cc08c41a5c0c0c4c403a38b5a82c333955212031395bc19881ee801944303232447132301c5861911aeee9a76bee66ec68e44cac098c0c0bda181900c2db0c16";
+
+    /* content of the packets of a reply */
+    fn contents(data: &[u8], tag: &[u8]) -> Vec<Vec<u8>> {
+        let mut contents = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            assert!(data[offset..].starts_with(tag));
+            let length = le32(data, offset + tag.len()).unwrap() as usize;
+            let uncompressed = le32(data, offset + tag.len() + 4).unwrap() as usize;
+            let content =
+                zlib_decompress(&data[offset + tag.len() + 8..offset + length], uncompressed)
+                    .unwrap();
+            assert!(content.len() == uncompressed);
+            contents.push(content);
+            offset += length;
+        }
+        contents
+    }
+
+    #[test]
+    fn test_gh0st_login() {
+        let masscanned = masscanned(Config::default());
+        let login = unhex(LOGIN);
+        let content = zlib_decompress(&login[13..], 224).unwrap();
+        let event = gh0st_event(
+            b"Gh0st",
+            login.len(),
+            &content,
+            &masscanned.config.gh0st,
+            &client(),
+        );
+        assert!(event.fields["name"] == "login");
+        assert!(event.fields["os_version"] == "5.1.2600");
+        assert!(event.fields["service_pack"] == "Service Pack 3");
+        assert!(event.fields["cpu_mhz"] == 2394);
+        assert!(event.fields["ip"] == "192.168.56.101");
+        assert!(event.fields["hostname"] == "WIN-7F3A2C");
+        assert!(event.fields["webcam"] == true);
+        assert!(event.fields["speed"] == 100000);
+        /* on any port, split across segments: COMMAND_ACTIVED */
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        assert!(crate::proto::dispatch(
+            &login[..20],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl =
+            crate::proto::dispatch(&login[20..], &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(contents(&repl, b"Gh0st") == vec![vec![GH0ST_COMMAND_ACTIVED]]);
+        assert!(!client_info.close);
+        /* heartbeats are answered */
+        let heartbeat = gh0st_packet(b"Gh0st", &[GH0ST_TOKEN_HEARTBEAT]);
+        let repl = crate::proto::dispatch(
+            &[heartbeat.clone(), heartbeat].concat(),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(contents(&repl, b"Gh0st") == vec![vec![49], vec![49]]);
+    }
+
+    #[test]
+    fn test_gh0st_tags() {
+        /* variant with another tag, on a configured port */
+        let mut masscanned = masscanned(Config::default());
+        masscanned.config.gh0st.ports = vec![8000];
+        masscanned.config.gh0st.tags = vec!["Gh0st".to_string(), "LURK0".to_string()];
+        masscanned.config.gh0st.heartbeat_reply = 52;
+        let heartbeat = unhex("4c55524b30160000000100000078da4b050000660066");
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        let repl =
+            crate::proto::dispatch(&heartbeat, &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(contents(&repl, b"LURK0") == vec![vec![52]]);
+        /* the implant keeps its tag */
+        let login = unhex(LOGIN);
+        assert!(
+            crate::proto::dispatch(&login, &masscanned, &mut client_info, Some(&mut tcb)).is_none()
+        );
+        assert!(client_info.close);
+        /* tags not configured */
+        masscanned.config.gh0st.tags = vec!["Gh0st".to_string()];
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        assert!(
+            crate::proto::dispatch(&heartbeat, &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        assert!(client_info.close);
+        /* invalid tags */
+        for tags in ["[]", "[\"\"]", "[\"0123456789abcdefg\"]"] {
+            assert!(Config::from_str(&format!("[gh0st]\ntags = {}\n", tags)).is_err());
+        }
+        assert!(Config::from_str("[gh0st]\ntags = [\"LURK0\", \"NetBot\"]\n").is_ok());
+    }
+
+    #[test]
+    fn test_gh0st_invalid() {
+        let masscanned = masscanned(Config::default());
+        let login = unhex(LOGIN);
+        let mut bad = Vec::new();
+        /* corrupted compressed data */
+        let mut packet = login.clone();
+        packet[40] ^= 0xff;
+        bad.push(packet);
+        /* uncompressed length not matching */
+        let mut packet = login.clone();
+        packet[9] = 0xdf;
+        bad.push(packet);
+        let mut packet = login.clone();
+        packet[9] = 0xe1;
+        bad.push(packet);
+        /* lengths too large, or too small */
+        let mut packet = login.clone();
+        packet[8] = 0x7f;
+        bad.push(packet);
+        let mut packet = login.clone();
+        packet[11] = 0x7f;
+        bad.push(packet);
+        let mut packet = login.clone();
+        packet[5] = 13;
+        bad.push(packet);
+        for packet in bad.iter() {
+            let mut client_info = client();
+            let mut tcb = TCPControlBlock::new();
+            assert!(
+                crate::proto::dispatch(packet, &masscanned, &mut client_info, Some(&mut tcb))
+                    .is_none()
+            );
+            assert!(client_info.close);
+        }
+        /* data that inflates beyond the limit */
+        let mut masscanned = self::masscanned(Config::default());
+        masscanned.config.gh0st.max_size = 100;
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        assert!(
+            crate::proto::dispatch(&login, &masscanned, &mut client_info, Some(&mut tcb)).is_none()
+        );
+        assert!(client_info.close);
+    }
+}
@@ -0,0 +1,342 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* pkt-line framing (gitprotocol-common(5)): 4 hexadecimal digits give
+ * the length, including themselves */
+const PKT_FLUSH: &[u8] = b"0000";
+const PKT_MAX_LEN: usize = 65520;
+
+/* size of the data kept between two segments */
+const GIT_MAX_BUFFER: usize = 65536;
+
+/* git daemon (on the TCP ports): requests are refused as for a
+ * repository not exported, unless greedy is set: then upload-pack
+ * requests get an advertisement of one (fake) ref, so that clients send
+ * the objects they want.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitConfig {
+    pub ports: Vec<u16>,
+    pub greedy: bool,
+    pub branch: String,
+    pub capabilities: String,
+}
+
+impl Default for GitConfig {
+    fn default() -> Self {
+        GitConfig {
+            ports: vec![9418],
+            greedy: false,
+            branch: "master".to_string(),
+            capabilities: "multi_ack thin-pack side-band side-band-64k ofs-delta shallow \
+                deepen-since deepen-not deepen-relative no-progress include-tag \
+                multi_ack_detailed object-format=sha1 agent=git/2.39.2"
+                .to_string(),
+        }
+    }
+}
+
+/* state of a flow: beginning of a pkt-line not received entirely yet,
+ * and the wants of the client, after the ref advertisement */
+#[derive(Default)]
+pub struct GitState {
+    buffer: Vec<u8>,
+    advertised: bool,
+    wants: Vec<String>,
+}
+
+#[derive(PartialEq, Debug)]
+enum PktLine<'a> {
+    Flush,
+    /* protocol version 2: delimiter and response end */
+    Delim,
+    ResponseEnd,
+    Data(&'a [u8]),
+}
+
+/* pkt-line and number of bytes used: Ok(None) when data does not hold an
+ * entire pkt-line, Err(()) for invalid lengths */
+fn pkt_line_parse(data: &[u8]) -> Result<Option<(PktLine<'_>, usize)>, ()> {
+    let digits = match data.get(..4) {
+        Some(d) => d,
+        None => return Ok(None),
+    };
+    let len = std::str::from_utf8(digits)
+        .ok()
+        .and_then(|d| usize::from_str_radix(d, 16).ok())
+        .ok_or(())?;
+    let line = match len {
+        0 => PktLine::Flush,
+        1 => PktLine::Delim,
+        2 => PktLine::ResponseEnd,
+        3 => return Err(()),
+        _ if len > PKT_MAX_LEN => return Err(()),
+        _ => match data.get(4..len) {
+            Some(payload) => PktLine::Data(payload),
+            None => return Ok(None),
+        },
+    };
+    Ok(Some((line, std::cmp::max(len, 4))))
+}
+
+fn pkt_line(payload: &[u8]) -> Vec<u8> {
+    let mut line = format!("{:04x}", payload.len() + 4).into_bytes();
+    line.extend_from_slice(payload);
+    line
+}
+
+/* fake object ID of the ref of a repository */
+fn git_object_id(path: &str) -> String {
+    encode(&Sha1::digest(path.as_bytes()), Encoding::Hex)
+}
+
+/* first pkt-line: "git-upload-pack /path\0host=example.com\0" */
+fn git_request(
+    payload: &[u8],
+    state: &mut GitState,
+    config: &GitConfig,
+    client_info: &mut ClientInfo,
+) -> Vec<u8> {
+    let payload = String::from_utf8_lossy(payload).to_string();
+    let mut fields = payload.split('\0');
+    let command = fields.next().unwrap_or("");
+    let (service, path) = command.split_once(' ').unwrap_or((command, ""));
+    let path = path.trim_end_matches('\n');
+    let mut event = Event::new("git_request", client_info);
+    event.set("service", service);
+    event.set("path", path);
+    let mut parameters = Vec::new();
+    for f in fields.filter(|f| !f.is_empty()) {
+        match f.strip_prefix("host=") {
+            Some(host) => event.set("host", host),
+            None => parameters.push(f.to_string()),
+        }
+    }
+    if !parameters.is_empty() {
+        event.set("parameters", parameters);
+    }
+    event.log();
+    warn!("git {} of {}", service, path);
+    if !config.greedy || service != "git-upload-pack" {
+        client_info.close = true;
+        return pkt_line(
+            format!("ERR access denied or repository not exported: {}\n", path).as_bytes(),
+        );
+    }
+    /* ref advertisement (protocol version 0) */
+    let oid = git_object_id(path);
+    let mut repl = pkt_line(
+        format!(
+            "{} HEAD\0{} symref=HEAD:refs/heads/{}\n",
+            oid, config.capabilities, config.branch
+        )
+        .as_bytes(),
+    );
+    repl.extend(pkt_line(
+        format!("{} refs/heads/{}\n", oid, config.branch).as_bytes(),
+    ));
+    repl.extend_from_slice(PKT_FLUSH);
+    state.advertised = true;
+    repl
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving git data");
+    let config = &masscanned.config.git;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Git(s)) => s,
+        _ => GitState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    let mut offset = 0;
+    while !client_info.close {
+        let (line, len) = match pkt_line_parse(&state.buffer[offset..]) {
+            Ok(Some(l)) => l,
+            Ok(None) => break,
+            Err(()) => {
+                info!("git: invalid pkt-line - dropped");
+                client_info.close = true;
+                break;
+            }
+        };
+        offset += len;
+        match (state.advertised, line) {
+            (false, PktLine::Data(payload)) => {
+                let payload = payload.to_vec();
+                repl_data.extend(git_request(&payload, &mut state, config, client_info));
+            }
+            (true, PktLine::Data(payload)) => {
+                /* "want <oid> <capabilities>" */
+                let payload = String::from_utf8_lossy(payload);
+                if let Some(want) = payload.strip_prefix("want ") {
+                    state.wants.push(want.trim_end().to_string());
+                }
+            }
+            (true, PktLine::Flush) if !state.wants.is_empty() => {
+                let mut event = Event::new("git_wants", client_info);
+                event.set("wants", std::mem::take(&mut state.wants));
+                event.log();
+                /* no pack can be sent */
+                client_info.close = true;
+            }
+            (true, PktLine::Flush) => {
+                /* nothing wanted */
+                client_info.close = true;
+            }
+            _ => {}
+        }
+    }
+    state.buffer.drain(..offset);
+    if state.buffer.len() > GIT_MAX_BUFFER {
+        info!("git: pkt-line too large - dropped");
+        client_info.close = true;
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Git(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending git data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    const UPLOAD_PACK: &[u8] = b"0032git-upload-pack /project.git\0host=example.com\0";
+
+    fn instance(greedy: bool) -> Masscanned<'static> {
+        let mut config = Config::default();
+        config.git.greedy = greedy;
+        crate::proto::test_util::masscanned(config)
+    }
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(9418);
+        client_info
+    }
+
+    #[test]
+    fn test_pkt_line() {
+        for payload in [&b""[..], b"a\n", &[b'x'; 1000]].iter() {
+            let line = pkt_line(payload);
+            assert!(pkt_line_parse(&line) == Ok(Some((PktLine::Data(payload), line.len()))));
+            for i in 0..line.len() {
+                assert!(pkt_line_parse(&line[..i]) == Ok(None));
+            }
+        }
+        assert!(pkt_line(b"a\n") == b"0006a\n");
+        assert!(pkt_line_parse(b"0000rest") == Ok(Some((PktLine::Flush, 4))));
+        assert!(pkt_line_parse(b"0001") == Ok(Some((PktLine::Delim, 4))));
+        assert!(pkt_line_parse(b"0002") == Ok(Some((PktLine::ResponseEnd, 4))));
+        /* invalid lengths */
+        for bad in [&b"0003"[..], b"zzzz", b"fff1", b"-001", b"\xff\xff\xff\xff"].iter() {
+            assert!(pkt_line_parse(bad) == Err(()));
+        }
+    }
+
+    #[test]
+    fn test_git_refused() {
+        let masscanned = instance(false);
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        let repl_data =
+            crate::proto::dispatch(UPLOAD_PACK, &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        let (line, len) = pkt_line_parse(&repl_data).unwrap().unwrap();
+        assert!(len == repl_data.len());
+        assert!(
+            line == PktLine::Data(b"ERR access denied or repository not exported: /project.git\n")
+        );
+        assert!(client_info.close);
+        /* invalid lengths */
+        let mut client_info = client();
+        assert!(repl(b"zzzzgit-upload-pack", &masscanned, &mut client_info, None).is_none());
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_git_greedy() {
+        let masscanned = instance(true);
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        /* request, in two segments */
+        assert!(crate::proto::dispatch(
+            &UPLOAD_PACK[..10],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl_data = crate::proto::dispatch(
+            &UPLOAD_PACK[10..],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        let oid = git_object_id("/project.git");
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while let Ok(Some((line, len))) = pkt_line_parse(&repl_data[offset..]) {
+            lines.push(line);
+            offset += len;
+        }
+        assert!(offset == repl_data.len() && lines.len() == 3);
+        match lines[0] {
+            PktLine::Data(head) => {
+                assert!(head.starts_with(format!("{} HEAD\0multi_ack ", oid).as_bytes()));
+                assert!(head.ends_with(b" symref=HEAD:refs/heads/master\n"));
+            }
+            _ => panic!("no HEAD"),
+        }
+        assert!(lines[1] == PktLine::Data(format!("{} refs/heads/master\n", oid).as_bytes()));
+        assert!(lines[2] == PktLine::Flush);
+        assert!(!client_info.close);
+        /* wants, then flush */
+        let mut wants = pkt_line(format!("want {} side-band-64k ofs-delta\n", oid).as_bytes());
+        wants.extend_from_slice(PKT_FLUSH);
+        assert!(
+            crate::proto::dispatch(&wants, &masscanned, &mut client_info, Some(&mut tcb)).is_none()
+        );
+        assert!(client_info.close);
+    }
+}
@@ -0,0 +1,371 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::line::LINE_MAX_BUFFER;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* Gopher server (RFC 1436), over TCP: the empty selector gets the menu
+ * below, the selectors of the documents below their content, other
+ * selectors an error item. Selectors are logged raw: gopher:// URLs are
+ * a common way to send SSRF payloads for other protocols, so what
+ * follows the selector line in the same segment is logged too.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GopherConfig {
+    pub ports: Vec<u16>,
+    /* host and port of the menu items, when not set for an item (empty
+     * and 0: the address and port contacted) */
+    pub host: String,
+    pub port: u16,
+    pub menu: Vec<GopherItem>,
+    pub documents: Vec<GopherDocument>,
+    pub log_encoding: Encoding,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GopherItem {
+    /* e.g., 0 (text file), 1 (menu), 7 (search), i (information) */
+    pub item_type: char,
+    pub display: String,
+    #[serde(default)]
+    pub selector: String,
+    #[serde(default)]
+    pub host: String,
+    #[serde(default)]
+    pub port: u16,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GopherDocument {
+    pub selector: String,
+    pub content: String,
+}
+
+impl Default for GopherConfig {
+    fn default() -> Self {
+        GopherConfig {
+            ports: vec![70],
+            host: String::new(),
+            port: 0,
+            menu: vec![
+                GopherItem {
+                    item_type: 'i',
+                    display: "Welcome to this gopher server".to_string(),
+                    selector: String::new(),
+                    host: String::new(),
+                    port: 0,
+                },
+                GopherItem {
+                    item_type: '0',
+                    display: "About this server".to_string(),
+                    selector: "/about.txt".to_string(),
+                    host: String::new(),
+                    port: 0,
+                },
+                GopherItem {
+                    item_type: '0',
+                    display: "Phlog".to_string(),
+                    selector: "/phlog.txt".to_string(),
+                    host: String::new(),
+                    port: 0,
+                },
+            ],
+            documents: vec![
+                GopherDocument {
+                    selector: "/about.txt".to_string(),
+                    content: "This server runs Gophernicus on a Raspberry Pi.\n".to_string(),
+                },
+                GopherDocument {
+                    selector: "/phlog.txt".to_string(),
+                    content: "Nothing new under the sun.\n".to_string(),
+                },
+            ],
+            log_encoding: Encoding::Base64,
+        }
+    }
+}
+
+impl GopherConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for item in self.menu.iter() {
+            for s in [&item.display, &item.selector, &item.host] {
+                if s.contains(['\t', '\r', '\n']) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("gopher: invalid menu field (TAB, CR or LF found): {:?}", s),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/* state of a gopher flow: beginning of the selector line */
+#[derive(Default)]
+pub struct GopherState {
+    buffer: Vec<u8>,
+}
+
+/* RFC 1436 section 3.8: text lines end with CR LF, lines starting with
+ * a period get another one, and a line with a period ends the text */
+fn gopher_text(content: &str) -> Vec<u8> {
+    let mut text = Vec::new();
+    for line in content.lines() {
+        if line.starts_with('.') {
+            text.push(b'.');
+        }
+        text.extend_from_slice(line.as_bytes());
+        text.extend_from_slice(b"\r\n");
+    }
+    text.extend_from_slice(b".\r\n");
+    text
+}
+
+fn gopher_menu(config: &GopherConfig, client_info: &ClientInfo) -> Vec<u8> {
+    let host = if config.host.is_empty() {
+        client_info
+            .ip
+            .dst
+            .map_or_else(String::new, |ip| ip.to_string())
+    } else {
+        config.host.clone()
+    };
+    let port = if config.port == 0 {
+        client_info.port.dst.unwrap_or(70)
+    } else {
+        config.port
+    };
+    let mut menu = Vec::new();
+    for item in config.menu.iter() {
+        menu.extend(
+            format!(
+                "{}{}\t{}\t{}\t{}\r\n",
+                item.item_type,
+                item.display,
+                item.selector,
+                if item.host.is_empty() {
+                    &host
+                } else {
+                    &item.host
+                },
+                if item.port == 0 { port } else { item.port }
+            )
+            .into_bytes(),
+        );
+    }
+    menu.extend_from_slice(b".\r\n");
+    menu
+}
+
+/* answer to a selector (without what follows a TAB, e.g., a search) */
+fn gopher_answer(selector: &[u8], config: &GopherConfig, client_info: &ClientInfo) -> Vec<u8> {
+    let selector = match selector.iter().position(|&c| c == b'\t') {
+        Some(i) => &selector[..i],
+        None => selector,
+    };
+    if selector.is_empty() || selector == b"/" {
+        return gopher_menu(config, client_info);
+    }
+    if let Some(doc) = config
+        .documents
+        .iter()
+        .find(|d| d.selector.as_bytes() == selector)
+    {
+        return gopher_text(&doc.content);
+    }
+    let selector: String = String::from_utf8_lossy(selector)
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect();
+    format!("3'{}' does not exist\t\terror.host\t1\r\n.\r\n", selector).into_bytes()
+}
+
+/* selector line (without the line ending) and what follows, when
+ * received */
+fn gopher_selector(buffer: &[u8]) -> Option<(&[u8], &[u8])> {
+    match buffer.iter().position(|&c| c == b'\n') {
+        Some(i) => {
+            let end = if i > 0 && buffer[i - 1] == b'\r' {
+                i - 1
+            } else {
+                i
+            };
+            Some((&buffer[..end], &buffer[i + 1..]))
+        }
+        /* no selector line: the whole thing is logged */
+        None if buffer.len() > LINE_MAX_BUFFER => Some((buffer, &[])),
+        None => None,
+    }
+}
+
+fn gopher_event(
+    selector: &[u8],
+    rest: &[u8],
+    config: &GopherConfig,
+    client_info: &ClientInfo,
+) -> Event {
+    let mut event = Event::new("gopher_request", client_info);
+    event.set("selector", encode(selector, config.log_encoding));
+    event.set("length", selector.len());
+    if !rest.is_empty() {
+        event.set("extra", encode(rest, config.log_encoding));
+    }
+    event
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving gopher data");
+    let config = &masscanned.config.gopher;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Gopher(s)) => s,
+        _ => GopherState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let (selector, rest) = match gopher_selector(&state.buffer) {
+        Some(s) => s,
+        None => {
+            if let Some(t) = tcb {
+                t.proto_state = ProtoState::Gopher(state);
+            }
+            return None;
+        }
+    };
+    gopher_event(selector, rest, config, client_info).log();
+    /* one selector per connection */
+    client_info.close = true;
+    debug!("sending gopher data");
+    Some(gopher_answer(selector, config, client_info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.dst = Some(70);
+        client_info
+    }
+
+    #[test]
+    fn test_gopher_menu() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        let reply =
+            crate::proto::dispatch(b"\r\n", &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(client_info.close);
+        assert!(
+            reply
+                == b"iWelcome to this gopher server\t\t192.0.2.1\t70\r\n\
+                     0About this server\t/about.txt\t192.0.2.1\t70\r\n\
+                     0Phlog\t/phlog.txt\t192.0.2.1\t70\r\n\
+                     .\r\n"
+        );
+        /* configured host and port, per item or for the whole menu */
+        let mut masscanned = self::masscanned(Config::default());
+        masscanned.config.gopher.host = "gopher.example".to_string();
+        masscanned.config.gopher.menu[2].port = 7070;
+        let reply = String::from_utf8(gopher_menu(&masscanned.config.gopher, &client())).unwrap();
+        let lines: Vec<&str> = reply.split("\r\n").collect();
+        assert!(
+            lines[1].split('\t').collect::<Vec<&str>>()
+                == ["0About this server", "/about.txt", "gopher.example", "70"]
+        );
+        assert!(lines[2].ends_with("\tgopher.example\t7070"));
+        assert!(lines[3] == "." && lines[4].is_empty());
+    }
+
+    #[test]
+    fn test_gopher_documents() {
+        let mut masscanned = masscanned(Config::default());
+        masscanned.config.gopher.documents[0].content = "a\n.b\nc".to_string();
+        let mut client_info = client();
+        let reply = repl(b"/about.txt\r\n", &masscanned, &mut client_info, None).unwrap();
+        assert!(reply == b"a\r\n..b\r\nc\r\n.\r\n");
+        /* search string after a TAB, selector split across segments */
+        let mut tcb = TCPControlBlock::new();
+        assert!(repl(b"/phlog", &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        let reply = repl(
+            b".txt\tsun\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(reply == b"Nothing new under the sun.\r\n.\r\n");
+        let reply = repl(b"/etc/passwd\r\n", &masscanned, &mut client_info, None).unwrap();
+        assert!(reply == b"3'/etc/passwd' does not exist\t\terror.host\t1\r\n.\r\n");
+    }
+
+    #[test]
+    fn test_gopher_binary_selector() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        /* SSRF payload for Redis (gopher://host:70/_...) */
+        let payload = b"_\x00\xff\x80*1\r\n$8\r\nflushall\r\n";
+        let (selector, rest) = gopher_selector(payload).unwrap();
+        assert!(selector == b"_\x00\xff\x80*1" && rest == b"$8\r\nflushall\r\n");
+        /* what is logged decodes to the exact bytes received */
+        let event = gopher_event(selector, rest, &masscanned.config.gopher, &client_info);
+        let logged = |k: &str| base64::decode(event.fields[k].as_str().unwrap()).unwrap();
+        assert!(logged("selector") == selector && logged("extra") == rest);
+        assert!(event.fields["length"] == 6);
+        let config = GopherConfig {
+            log_encoding: Encoding::Hex,
+            ..Default::default()
+        };
+        let event = gopher_event(selector, rest, &config, &client_info);
+        assert!(event.fields["selector"] == "5f00ff802a31");
+        /* the error item holds no control characters */
+        let reply = repl(payload, &masscanned, &mut client_info, None).unwrap();
+        assert!(
+            reply == "3'_\u{fffd}\u{fffd}*1' does not exist\t\terror.host\t1\r\n.\r\n".as_bytes()
+        );
+        /* no line ending: logged once the buffer is full */
+        let mut tcb = TCPControlBlock::new();
+        assert!(repl(&[0; 100], &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        let big = vec![0; LINE_MAX_BUFFER];
+        assert!(repl(&big, &masscanned, &mut client_info, Some(&mut tcb)).is_some());
+    }
+}
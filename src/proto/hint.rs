@@ -0,0 +1,237 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use serde::Deserialize;
+
+use crate::proto::{PROTO_DATAGRAM, PROTO_NAMES};
+
+/* Protocols offered the first data of a flow (or a datagram) on a
+ * destination port, in order, before the protocol is identified as
+ * usual: a protocol takes the data when its markers are found in it
+ * (or when it has none, e.g., DNS). When none takes it, the protocol
+ * is identified as usual, or nothing answers on a strict port. A hint
+ * applies to both transports, unless one is given (protocols only
+ * answering datagrams, e.g., TFTP, must be hinted for UDP).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HintsConfig {
+    pub ports: Vec<PortHint>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PortHint {
+    pub port: u16,
+    pub protocols: Vec<String>,
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default)]
+    pub transport: Option<HintTransport>,
+}
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HintTransport {
+    Tcp,
+    Udp,
+}
+
+impl PortHint {
+    fn applies(&self, tcp: bool) -> bool {
+        self.transport
+            .is_none_or(|t| (t == HintTransport::Tcp) == tcp)
+    }
+}
+
+fn hint(port: u16, protocols: &[&str]) -> PortHint {
+    PortHint {
+        port,
+        protocols: protocols.iter().map(|p| p.to_string()).collect(),
+        strict: false,
+        transport: None,
+    }
+}
+
+impl Default for HintsConfig {
+    /* well-known ports of the protocols identified by their markers
+     * only (the others are chosen by their own ports settings) */
+    fn default() -> Self {
+        HintsConfig {
+            ports: vec![
+                hint(22, &["ssh"]),
+                hint(80, &["http"]),
+                hint(139, &["smb"]),
+                hint(443, &["tls", "http"]),
+                hint(445, &["smb"]),
+                hint(1433, &["mssql"]),
+                hint(3389, &["rdp", "tls"]),
+                hint(5432, &["postgres"]),
+                hint(8080, &["http"]),
+                hint(8443, &["tls", "http"]),
+            ],
+        }
+    }
+}
+
+impl HintsConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for (i, h) in self.ports.iter().enumerate() {
+            if self.ports[..i].iter().any(|o| {
+                o.port == h.port
+                    && (o.transport.is_none()
+                        || h.transport.is_none()
+                        || o.transport == h.transport)
+            }) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("hints: port {} given twice", h.port),
+                ));
+            }
+            if h.protocols.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("hints: no protocol for port {}", h.port),
+                ));
+            }
+            if let Some(p) = h.protocols.iter().find(|p| proto_id(p).is_none()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("hints: unknown protocol {:?} for port {}", p, h.port),
+                ));
+            }
+            /* the handlers of these protocols expect datagrams (and no
+             * flow), they cannot take data over TCP */
+            if h.transport != Some(HintTransport::Udp) {
+                if let Some(p) = h
+                    .protocols
+                    .iter()
+                    .find(|p| proto_id(p).is_some_and(|id| PROTO_DATAGRAM.contains(&id)))
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "hints: protocol {:?} is only available over UDP (port {}, set transport = \"udp\")",
+                            p, h.port
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, port: u16, tcp: bool) -> Option<&PortHint> {
+        self.ports.iter().find(|h| h.port == port && h.applies(tcp))
+    }
+}
+
+pub fn proto_id(name: &str) -> Option<usize> {
+    PROTO_NAMES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, id)| *id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientInfo;
+    use crate::config::Config;
+    use crate::proto::tcb::TCPControlBlock;
+    use crate::proto::{dispatch, PROTO_HTTP, PROTO_RDP, PROTO_S7, PROTO_SSH, PROTO_TFTP};
+    use crate::smack::NO_MATCH;
+    use crate::Masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    /* X.224 Connection Request (RDP, or COTP for S7) */
+    const CR: &[u8] =
+        b"\x03\x00\x00\x16\x11\xe0\x00\x00\x00\x01\x00\xc1\x02\x01\x00\xc2\x02\x01\x02\xc0\x01\x09";
+
+    fn masscanned(config: &str) -> Masscanned<'static> {
+        crate::proto::test_util::masscanned(Config::from_str(config).expect("error parsing config"))
+    }
+
+    /* protocol chosen for the first segment of a flow */
+    fn proto(data: &[u8], port: u16, masscanned: &Masscanned) -> (usize, bool) {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(port);
+        let mut tcb = TCPControlBlock::new();
+        let answer = dispatch(data, masscanned, &mut client_info, Some(&mut tcb));
+        (tcb.proto_id, answer.is_some())
+    }
+
+    #[test]
+    fn test_hints() {
+        let masscanned = masscanned(
+            "[[hints.ports]]\nport = 10102\nprotocols = [\"s7\"]\n\
+             [[hints.ports]]\nport = 10022\nprotocols = [\"ssh\"]\nstrict = true\n\
+             [[hints.ports]]\nport = 10023\nprotocols = [\"ssh\"]\n",
+        );
+        /* same data, routed differently */
+        assert!(proto(CR, 3389, &masscanned).0 == PROTO_RDP);
+        assert!(proto(CR, 10102, &masscanned).0 == PROTO_S7);
+        /* fallback on an unmapped port, or when no hinted protocol
+         * takes the data */
+        let get = b"GET / HTTP/1.1\r\n\r\n";
+        assert!(proto(get, 12345, &masscanned) == (PROTO_HTTP, true));
+        assert!(proto(get, 10023, &masscanned) == (PROTO_HTTP, true));
+        assert!(proto(b"SSH-2.0-x\r\n", 10023, &masscanned).0 == PROTO_SSH);
+        /* strict: nothing else */
+        assert!(proto(get, 10022, &masscanned) == (NO_MATCH, false));
+        assert!(proto(b"SSH-2.0-x\r\n", 10022, &masscanned).0 == PROTO_SSH);
+        /* the default hints are replaced */
+        assert!(masscanned.config.hints.get(443, true).is_none());
+        assert!(HintsConfig::default().get(3389, true).unwrap().protocols == ["rdp", "tls"]);
+    }
+
+    #[test]
+    fn test_hints_config() {
+        assert!(HintsConfig::default().check().is_ok());
+        for config in [
+            "[[hints.ports]]\nport = 1\nprotocols = [\"htp\"]\n",
+            "[[hints.ports]]\nport = 1\nprotocols = []\n",
+            "[[hints.ports]]\nport = 1\nprotocols = [\"http\"]\n\
+             [[hints.ports]]\nport = 1\nprotocols = [\"tls\"]\n",
+            "[[hints.ports]]\nport = 1\nprotocols = [\"http\"]\ntransport = \"udp\"\n\
+             [[hints.ports]]\nport = 1\nprotocols = [\"tls\"]\n",
+            /* UDP only protocols */
+            "[[hints.ports]]\nport = 6969\nprotocols = [\"tftp\"]\n",
+            "[[hints.ports]]\nport = 6969\nprotocols = [\"http\", \"tftp\"]\ntransport = \"tcp\"\n",
+            "[[hints.ports]]\nport = 6969\nprotocols = [\"tftp\"]\ntransport = \"sctp\"\n",
+        ] {
+            assert!(Config::from_str(config).is_err());
+        }
+        /* per transport */
+        let config = Config::from_str(
+            "[[hints.ports]]\nport = 6969\nprotocols = [\"tftp\"]\ntransport = \"udp\"\n\
+             [[hints.ports]]\nport = 6969\nprotocols = [\"http\"]\ntransport = \"tcp\"\n",
+        )
+        .unwrap();
+        assert!(config.hints.get(6969, false).unwrap().protocols == ["tftp"]);
+        assert!(config.hints.get(6969, true).unwrap().protocols == ["http"]);
+        let masscanned = crate::proto::test_util::masscanned(config);
+        /* a TCP flow is not given to TFTP */
+        let rrq = b"\x00\x01a\x00octet\x00";
+        assert!(proto(rrq, 6969, &masscanned).0 != PROTO_TFTP);
+        assert!(proto_id("small_services").is_some());
+        assert!(proto_id("gh0st").is_some());
+    }
+}
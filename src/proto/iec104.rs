@@ -0,0 +1,422 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+const IEC104_START: u8 = 0x68;
+
+/* U-frame functions (first control octet) */
+const IEC104_STARTDT_ACT: u8 = 0x07;
+const IEC104_STARTDT_CON: u8 = 0x0b;
+const IEC104_STOPDT_ACT: u8 = 0x13;
+const IEC104_STOPDT_CON: u8 = 0x23;
+const IEC104_TESTFR_ACT: u8 = 0x43;
+const IEC104_TESTFR_CON: u8 = 0x83;
+
+/* type identification: general interrogation command */
+const C_IC_NA_1: u8 = 100;
+
+/* causes of transmission, and the negative confirmation flag */
+const COT_ACTIVATION: u8 = 6;
+const COT_ACTIVATION_CON: u8 = 7;
+const COT_ACTIVATION_TERM: u8 = 10;
+const COT_UNKNOWN_TYPE: u8 = 44;
+const COT_UNKNOWN_CAUSE: u8 = 45;
+const COT_UNKNOWN_COMMON_ADDRESS: u8 = 46;
+const COT_NEGATIVE: u8 = 0x40;
+
+/* global common address */
+const IEC104_BROADCAST: u16 = 0xffff;
+
+/* sequence numbers are 15 bits */
+const IEC104_SEQ_MASK: u16 = 0x7fff;
+
+/* IEC 60870-5-104 controlled station: data transfer is started and
+ * tested on request, and general interrogations of common_address get
+ * an activation confirmation and termination (with no data).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Iec104Config {
+    pub ports: Vec<u16>,
+    pub common_address: u16,
+}
+
+impl Default for Iec104Config {
+    fn default() -> Self {
+        Iec104Config {
+            ports: vec![2404],
+            common_address: 1,
+        }
+    }
+}
+
+/* state of an IEC 104 connection: data received, not a complete APDU
+ * yet, whether data transfer is started, and sequence numbers of the
+ * next I-frame sent and received */
+#[derive(Default)]
+pub struct Iec104State {
+    buffer: Vec<u8>,
+    started: bool,
+    send_seq: u16,
+    recv_seq: u16,
+}
+
+/* ASDU header: type, variable structure qualifier, cause of
+ * transmission (with its flags and the originator address), common
+ * address */
+struct Asdu<'a> {
+    type_id: u8,
+    vsq: u8,
+    cause: u8,
+    originator: u8,
+    common_address: u16,
+    objects: &'a [u8],
+}
+
+fn iec104_parse_asdu(asdu: &[u8]) -> Option<Asdu<'_>> {
+    match asdu {
+        [type_id, vsq, cause, originator, ca0, ca1, objects @ ..] => Some(Asdu {
+            type_id: *type_id,
+            vsq: *vsq,
+            cause: *cause,
+            originator: *originator,
+            common_address: u16::from_le_bytes([*ca0, *ca1]),
+            objects,
+        }),
+        _ => None,
+    }
+}
+
+fn iec104_apdu(control: [u8; 4], asdu: &[u8]) -> Vec<u8> {
+    let mut apdu = vec![IEC104_START, (4 + asdu.len()) as u8];
+    apdu.extend_from_slice(&control);
+    apdu.extend_from_slice(asdu);
+    apdu
+}
+
+/* I-frame with the next send sequence number, acknowledging every
+ * I-frame received */
+fn iec104_i_frame(state: &mut Iec104State, asdu: &[u8]) -> Vec<u8> {
+    let send = (state.send_seq << 1).to_le_bytes();
+    let recv = (state.recv_seq << 1).to_le_bytes();
+    state.send_seq = (state.send_seq + 1) & IEC104_SEQ_MASK;
+    iec104_apdu([send[0], send[1], recv[0], recv[1]], asdu)
+}
+
+/* the same ASDU with another cause of transmission */
+fn iec104_mirror(asdu: &[u8], cause: u8) -> Vec<u8> {
+    let mut repl = asdu.to_vec();
+    repl[2] = (asdu[2] & 0x80) | cause;
+    repl
+}
+
+/* answer to an ASDU (I-frame) */
+fn iec104_answer(
+    asdu: &[u8],
+    state: &mut Iec104State,
+    config: &Iec104Config,
+    event: &mut Event,
+) -> Vec<u8> {
+    let parsed = match iec104_parse_asdu(asdu) {
+        Some(p) => p,
+        None => {
+            info!("IEC 104 I-frame with an invalid ASDU");
+            return Vec::new();
+        }
+    };
+    event.set("type_id", parsed.type_id);
+    event.set("vsq", parsed.vsq);
+    event.set("cause", parsed.cause & 0x3f);
+    event.set("negative", parsed.cause & COT_NEGATIVE != 0);
+    event.set("test", parsed.cause & 0x80 != 0);
+    event.set("originator", parsed.originator);
+    event.set("common_address", parsed.common_address);
+    if let [a0, a1, a2, ..] = parsed.objects {
+        event.set("ioa", u32::from_le_bytes([*a0, *a1, *a2, 0]));
+    }
+    warn!(
+        "IEC 104 ASDU type {} cause {} for common address {}",
+        parsed.type_id,
+        parsed.cause & 0x3f,
+        parsed.common_address
+    );
+    /* monitoring direction, or data transfer not started: no answer */
+    if parsed.type_id < 45 || !state.started {
+        return Vec::new();
+    }
+    if parsed.common_address != config.common_address && parsed.common_address != IEC104_BROADCAST {
+        let repl = iec104_mirror(asdu, COT_NEGATIVE | COT_UNKNOWN_COMMON_ADDRESS);
+        return iec104_i_frame(state, &repl);
+    }
+    match (parsed.type_id, parsed.cause & 0x3f) {
+        (C_IC_NA_1, COT_ACTIVATION) => {
+            /* confirmation and termination, for our common address */
+            let mut repl = iec104_mirror(asdu, COT_ACTIVATION_CON);
+            repl[4..6].copy_from_slice(&config.common_address.to_le_bytes());
+            let mut repl_data = iec104_i_frame(state, &repl);
+            repl[2] = (asdu[2] & 0x80) | COT_ACTIVATION_TERM;
+            repl_data.extend(iec104_i_frame(state, &repl));
+            repl_data
+        }
+        (C_IC_NA_1, _) => {
+            let repl = iec104_mirror(asdu, COT_NEGATIVE | COT_UNKNOWN_CAUSE);
+            iec104_i_frame(state, &repl)
+        }
+        _ => {
+            let repl = iec104_mirror(asdu, COT_NEGATIVE | COT_UNKNOWN_TYPE);
+            iec104_i_frame(state, &repl)
+        }
+    }
+}
+
+/* answer to an APDU: U-frames are confirmed, S-frames only
+ * acknowledge what we sent */
+fn iec104_frame(
+    apdu: &[u8],
+    state: &mut Iec104State,
+    config: &Iec104Config,
+    client_info: &ClientInfo,
+) -> Vec<u8> {
+    let control = &apdu[2..6];
+    let mut event = Event::new("iec104_frame", client_info);
+    let repl = if control[0] & 0x01 == 0 {
+        let send = u16::from_le_bytes([control[0], control[1]]) >> 1;
+        let recv = u16::from_le_bytes([control[2], control[3]]) >> 1;
+        event.set("format", "I");
+        event.set("send_seq", send);
+        event.set("recv_seq", recv);
+        state.recv_seq = (state.recv_seq + 1) & IEC104_SEQ_MASK;
+        iec104_answer(&apdu[6..], state, config, &mut event)
+    } else if control[0] & 0x03 == 0x01 {
+        event.set("format", "S");
+        event.set(
+            "recv_seq",
+            u16::from_le_bytes([control[2], control[3]]) >> 1,
+        );
+        Vec::new()
+    } else {
+        event.set("format", "U");
+        let (function, repl) = match control[0] {
+            IEC104_STARTDT_ACT => {
+                state.started = true;
+                ("STARTDT_ACT", Some(IEC104_STARTDT_CON))
+            }
+            IEC104_STOPDT_ACT => {
+                state.started = false;
+                ("STOPDT_ACT", Some(IEC104_STOPDT_CON))
+            }
+            IEC104_TESTFR_ACT => ("TESTFR_ACT", Some(IEC104_TESTFR_CON)),
+            IEC104_STARTDT_CON => ("STARTDT_CON", None),
+            IEC104_STOPDT_CON => ("STOPDT_CON", None),
+            IEC104_TESTFR_CON => ("TESTFR_CON", None),
+            _ => ("unknown", None),
+        };
+        event.set("function", function);
+        warn!("IEC 104 U-frame {}", function);
+        match repl {
+            Some(c) => iec104_apdu([c, 0, 0, 0], &[]),
+            None => Vec::new(),
+        }
+    };
+    event.log();
+    repl
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving IEC 104 data");
+    let config = &masscanned.config.iec104;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Iec104(s)) => s,
+        _ => Iec104State::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while state.buffer.len() >= 2 {
+        /* the length counts the control fields and the ASDU */
+        if state.buffer[0] != IEC104_START || state.buffer[1] < 4 {
+            info!("IEC 104 data not handled (invalid APCI)");
+            state.buffer.clear();
+            client_info.close = true;
+            break;
+        }
+        let len = 2 + state.buffer[1] as usize;
+        if state.buffer.len() < len {
+            break;
+        }
+        let apdu: Vec<u8> = state.buffer.drain(..len).collect();
+        repl_data.extend(iec104_frame(&apdu, &mut state, config, client_info));
+    }
+    if state.buffer.first().is_some_and(|b| *b != IEC104_START) {
+        info!("IEC 104 data not handled (invalid APCI)");
+        state.buffer.clear();
+        client_info.close = true;
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Iec104(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending IEC 104 data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::{client_info, masscanned, SERVER_IP};
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    const STARTDT_ACT: &[u8] = b"\x68\x04\x07\x00\x00\x00";
+    /* general interrogation of common address 1, N(S) = N(R) = 0 */
+    const INTERROGATION: &[u8] =
+        b"\x68\x0e\x00\x00\x00\x00\x64\x01\x06\x00\x01\x00\x00\x00\x00\x14";
+
+    #[test]
+    fn test_iec104_u_frames() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 2404);
+        let mut tcb = TCPControlBlock::new();
+        for (request, answer) in [
+            (STARTDT_ACT, &b"\x68\x04\x0b\x00\x00\x00"[..]),
+            (b"\x68\x04\x43\x00\x00\x00", b"\x68\x04\x83\x00\x00\x00"),
+            (b"\x68\x04\x13\x00\x00\x00", b"\x68\x04\x23\x00\x00\x00"),
+        ]
+        .iter()
+        {
+            let repl_data =
+                crate::proto::dispatch(request, &masscanned, &mut client_info, Some(&mut tcb))
+                    .unwrap();
+            assert!(repl_data == *answer);
+        }
+        /* confirmations and S-frames get no answer */
+        assert!(crate::proto::dispatch(
+            b"\x68\x04\x83\x00\x00\x00\x68\x04\x01\x00\x02\x00",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        /* two frames, the second one in two segments */
+        assert!(
+            crate::proto::dispatch(
+                b"\x68\x04\x07\x00\x00\x00\x68\x04\x43",
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb)
+            ) == Some(b"\x68\x04\x0b\x00\x00\x00".to_vec())
+        );
+        assert!(
+            crate::proto::dispatch(
+                b"\x00\x00\x00",
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb)
+            ) == Some(b"\x68\x04\x83\x00\x00\x00".to_vec())
+        );
+        assert!(!client_info.close);
+        /* not IEC 104 */
+        assert!(crate::proto::dispatch(
+            b"GET / HTTP/1.1\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_iec104_interrogation() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 2404);
+        let mut tcb = TCPControlBlock::new();
+        /* data transfer not started: no answer (but the I-frame counts) */
+        assert!(crate::proto::dispatch(
+            INTERROGATION,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        crate::proto::dispatch(STARTDT_ACT, &masscanned, &mut client_info, Some(&mut tcb));
+        let mut interrogation = INTERROGATION.to_vec();
+        interrogation[2] = 0x02;
+        let repl_data = crate::proto::dispatch(
+            &interrogation,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        /* activation confirmation, N(S) = 0, N(R) = 2, then termination,
+         * N(S) = 1 */
+        assert!(
+            repl_data
+                == b"\x68\x0e\x00\x00\x04\x00\x64\x01\x07\x00\x01\x00\x00\x00\x00\x14\x68\x0e\x02\x00\x04\x00\x64\x01\x0a\x00\x01\x00\x00\x00\x00\x14"
+        );
+        /* global address: answered for our common address */
+        interrogation[2] = 0x04;
+        interrogation[10..12].copy_from_slice(&[0xff, 0xff]);
+        let repl_data = crate::proto::dispatch(
+            &interrogation,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data[2..6] == [0x04, 0x00, 0x06, 0x00]);
+        assert!(repl_data[8] == COT_ACTIVATION_CON && repl_data[10..12] == [0x01, 0x00]);
+        /* other common addresses, other commands: negative confirmations */
+        interrogation[2] = 0x06;
+        interrogation[10..12].copy_from_slice(&[0x02, 0x00]);
+        let repl_data = crate::proto::dispatch(
+            &interrogation,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data.len() == 16 && repl_data[2..6] == [0x08, 0x00, 0x08, 0x00]);
+        assert!(repl_data[8] == COT_NEGATIVE | COT_UNKNOWN_COMMON_ADDRESS);
+        /* single command C_SC_NA_1 */
+        let repl_data = crate::proto::dispatch(
+            b"\x68\x0e\x08\x00\x00\x00\x2d\x01\x06\x00\x01\x00\x01\x00\x00\x81",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data[2..6] == [0x0a, 0x00, 0x0a, 0x00]);
+        assert!(repl_data[6] == 0x2d && repl_data[8] == COT_NEGATIVE | COT_UNKNOWN_TYPE);
+    }
+}
@@ -0,0 +1,960 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::hash::Hasher;
+use std::io;
+use std::net::IpAddr;
+
+use log::*;
+use md5::Md5;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use sha1::digest::Digest;
+use sha1::Sha1;
+use siphasher::sip::SipHasher24;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::synackcookie;
+use crate::Masscanned;
+
+const IKE_HEADER_LEN: usize = 28;
+/* RFC 3948 section 2.2 */
+const IKE_NON_ESP_MARKER: &[u8] = &[0, 0, 0, 0];
+
+const IKE_VERSION_1: u8 = 0x10;
+const IKE_VERSION_2: u8 = 0x20;
+
+/* RFC 2408 section 3.1 */
+const IKE_EXCHANGE_MAIN_MODE: u8 = 2;
+const IKE_EXCHANGE_AGGRESSIVE: u8 = 4;
+const IKE_EXCHANGE_INFORMATIONAL: u8 = 5;
+/* RFC 7296 section 3.1 */
+const IKE_EXCHANGE_SA_INIT: u8 = 34;
+const IKE_FLAG_INITIATOR: u8 = 0x08;
+const IKE_FLAG_RESPONSE: u8 = 0x20;
+
+/* payload types (IKEv1, RFC 2408 section 3.1) */
+const IKE_PAYLOAD_NONE: u8 = 0;
+const IKE_PAYLOAD_SA: u8 = 1;
+const IKE_PAYLOAD_KE: u8 = 4;
+const IKE_PAYLOAD_ID: u8 = 5;
+const IKE_PAYLOAD_HASH: u8 = 8;
+const IKE_PAYLOAD_NONCE: u8 = 10;
+const IKE_PAYLOAD_NOTIFY: u8 = 11;
+const IKE_PAYLOAD_VENDOR_ID: u8 = 13;
+/* payload types (IKEv2, RFC 7296 section 3.2) */
+const IKE2_PAYLOAD_SA: u8 = 33;
+const IKE2_PAYLOAD_KE: u8 = 34;
+const IKE2_PAYLOAD_NONCE: u8 = 40;
+const IKE2_PAYLOAD_NOTIFY: u8 = 41;
+const IKE2_PAYLOAD_VENDOR_ID: u8 = 43;
+
+const IKE_NOTIFY_NO_PROPOSAL_CHOSEN: u16 = 14;
+
+/* IKEv1 transform attributes (RFC 2409 appendix A) */
+const IKE_ATTR_ENCRYPTION: u16 = 1;
+const IKE_ATTR_HASH: u16 = 2;
+const IKE_ATTR_AUTH: u16 = 3;
+const IKE_ATTR_GROUP: u16 = 4;
+const IKE_HASH_MD5: u64 = 1;
+const IKE_HASH_SHA: u64 = 2;
+/* IKEv2 transform types (RFC 7296 section 3.3.2) */
+const IKE2_TRANSFORM_DH: u8 = 4;
+
+/* DOI, situation (RFC 2407 section 4.2 and 4.6.1) */
+const IKE_DOI_IPSEC: u32 = 1;
+const IKE_SIT_IDENTITY_ONLY: u32 = 1;
+const IKE_PROTO_ISAKMP: u8 = 1;
+/* RFC 2407 section 4.6.2.1 */
+const IKE_ID_IPV4_ADDR: u8 = 1;
+const IKE_ID_IPV6_ADDR: u8 = 5;
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Ikev2Answer {
+    #[default]
+    SaInit,
+    NoProposalChosen,
+}
+
+/* IKE responder (on the UDP ports, and after the non-ESP marker on the
+ * NAT-T ports): IKEv1 Main Mode and Aggressive Mode proposals get the
+ * first transform whose attributes are all acceptable (or
+ * NO-PROPOSAL-CHOSEN), with the vendor_ids (hex); Aggressive Mode
+ * answers carry a HASH_R computed with psk, so that the usual tools
+ * go on. IKEv2 IKE_SA_INIT requests get an answer with the first
+ * transform of each type, or NO_PROPOSAL_CHOSEN.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IkeConfig {
+    pub ports: Vec<u16>,
+    pub nat_t_ports: Vec<u16>,
+    pub encryption: Vec<u64>,
+    /* 1 (MD5) and 2 (SHA) only */
+    pub hash: Vec<u64>,
+    pub auth: Vec<u64>,
+    pub group: Vec<u64>,
+    pub vendor_ids: Vec<String>,
+    pub psk: String,
+    pub ikev2: Ikev2Answer,
+}
+
+impl Default for IkeConfig {
+    fn default() -> Self {
+        IkeConfig {
+            ports: vec![500],
+            nat_t_ports: vec![4500],
+            /* AES-CBC, 3DES, DES */
+            encryption: vec![7, 5, 1],
+            hash: vec![IKE_HASH_SHA, IKE_HASH_MD5],
+            /* pre-shared key, RSA signatures, XAUTH with pre-shared key */
+            auth: vec![1, 3, 65001],
+            group: vec![2, 5, 14, 1],
+            vendor_ids: vec![
+                /* Cisco Unity */
+                "12f5f28c457168a9702d9fe274cc0100".to_string(),
+                /* XAUTH */
+                "09002689dfd6b712".to_string(),
+                /* Dead Peer Detection v1.0 */
+                "afcad71368a1f1c96b8696fc77570100".to_string(),
+                /* RFC 3947 NAT-T */
+                "4a131c81070358455c5728f20e95452f".to_string(),
+            ],
+            psk: "cisco123".to_string(),
+            ikev2: Ikev2Answer::SaInit,
+        }
+    }
+}
+
+fn ike_from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+impl IkeConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if let Some(v) = self.vendor_ids.iter().find(|v| ike_from_hex(v).is_none()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("ike: invalid vendor ID (not hex): {}", v),
+            ));
+        }
+        if let Some(h) = self
+            .hash
+            .iter()
+            .find(|h| **h != IKE_HASH_MD5 && **h != IKE_HASH_SHA)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("ike: hash not supported: {} (only 1 and 2)", h),
+            ));
+        }
+        Ok(())
+    }
+
+    fn acceptable(&self, transform: &IkeTransform) -> bool {
+        transform.attributes.iter().all(|(t, v)| match *t {
+            IKE_ATTR_ENCRYPTION => self.encryption.contains(v),
+            IKE_ATTR_HASH => self.hash.contains(v),
+            IKE_ATTR_AUTH => self.auth.contains(v),
+            IKE_ATTR_GROUP => self.group.contains(v),
+            _ => true,
+        })
+    }
+}
+
+struct IkeHeader<'a> {
+    icookie: &'a [u8],
+    rcookie: &'a [u8],
+    next: u8,
+    version: u8,
+    exchange: u8,
+    flags: u8,
+    message_id: u32,
+}
+
+/* transform (IKEv1: number and transform ID; IKEv2: type and transform
+ * ID) and its attributes */
+struct IkeTransform<'a> {
+    proposal: u8,
+    protocol: u8,
+    number: u8,
+    type_: u8,
+    id: u16,
+    attributes: Vec<(u16, u64)>,
+    /* the whole transform payload */
+    raw: &'a [u8],
+}
+
+fn ike_parse_header(data: &[u8]) -> Option<(IkeHeader<'_>, &[u8])> {
+    if data.len() < IKE_HEADER_LEN {
+        return None;
+    }
+    let len = u32::from_be_bytes([data[24], data[25], data[26], data[27]]) as usize;
+    if len < IKE_HEADER_LEN || len > data.len() {
+        return None;
+    }
+    Some((
+        IkeHeader {
+            icookie: &data[..8],
+            rcookie: &data[8..16],
+            next: data[16],
+            version: data[17],
+            exchange: data[18],
+            flags: data[19],
+            message_id: u32::from_be_bytes([data[20], data[21], data[22], data[23]]),
+        },
+        &data[IKE_HEADER_LEN..len],
+    ))
+}
+
+/* chain of payloads (type and body), up to the first malformed one */
+fn ike_parse_payloads(mut next: u8, mut data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut payloads = Vec::new();
+    while next != IKE_PAYLOAD_NONE && data.len() >= 4 {
+        let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if len < 4 || len > data.len() {
+            info!("IKE: malformed payload {}", next);
+            break;
+        }
+        payloads.push((next, &data[4..len]));
+        next = data[0];
+        data = &data[len..];
+    }
+    payloads
+}
+
+/* data attributes (RFC 2408 section 3.3): type/value (AF bit set) or
+ * type/length/value - long values are kept up to 8 bytes */
+fn ike_parse_attributes(mut data: &[u8]) -> Vec<(u16, u64)> {
+    let mut attributes = Vec::new();
+    while data.len() >= 4 {
+        let type_ = u16::from_be_bytes([data[0], data[1]]);
+        let (value, len) = if type_ & 0x8000 != 0 {
+            (u16::from_be_bytes([data[2], data[3]]) as u64, 4)
+        } else {
+            let l = u16::from_be_bytes([data[2], data[3]]) as usize;
+            if data.len() < 4 + l {
+                break;
+            }
+            let value = data[4..4 + l]
+                .iter()
+                .take(8)
+                .fold(0u64, |v, b| (v << 8) | *b as u64);
+            (value, 4 + l)
+        };
+        attributes.push((type_ & 0x7fff, value));
+        data = &data[len..];
+    }
+    attributes
+}
+
+/* proposals of an SA payload (IKEv1: after the DOI and situation) */
+fn ike_parse_sa(sa: &[u8], version: u8) -> Vec<IkeTransform<'_>> {
+    let mut transforms = Vec::new();
+    let mut data = if version == IKE_VERSION_1 {
+        match sa.get(8..) {
+            Some(d) => d,
+            None => return transforms,
+        }
+    } else {
+        sa
+    };
+    while data.len() >= 8 {
+        let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        let spi_size = data[6] as usize;
+        if len < 8 + spi_size || len > data.len() {
+            break;
+        }
+        let (proposal, protocol) = (data[4], data[5]);
+        let mut rest = &data[8 + spi_size..len];
+        while rest.len() >= 8 {
+            let tlen = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+            if tlen < 8 || tlen > rest.len() {
+                break;
+            }
+            let (number, type_, id) = if version == IKE_VERSION_1 {
+                (rest[4], 0, rest[5] as u16)
+            } else {
+                (0, rest[4], u16::from_be_bytes([rest[6], rest[7]]))
+            };
+            transforms.push(IkeTransform {
+                proposal,
+                protocol,
+                number,
+                type_,
+                id,
+                attributes: ike_parse_attributes(&rest[8..tlen]),
+                raw: &rest[..tlen],
+            });
+            rest = &rest[tlen..];
+        }
+        let last = data[0] == 0;
+        data = &data[len..];
+        if last {
+            break;
+        }
+    }
+    transforms
+}
+
+fn ike_attribute_name(type_: u16) -> String {
+    match type_ {
+        IKE_ATTR_ENCRYPTION => "encryption".to_string(),
+        IKE_ATTR_HASH => "hash".to_string(),
+        IKE_ATTR_AUTH => "auth".to_string(),
+        IKE_ATTR_GROUP => "group".to_string(),
+        11 => "life_type".to_string(),
+        12 => "life_duration".to_string(),
+        14 => "key_length".to_string(),
+        t => format!("attr_{}", t),
+    }
+}
+
+fn ike_transform_json(transform: &IkeTransform, version: u8) -> Value {
+    let attributes: Map<String, Value> = transform
+        .attributes
+        .iter()
+        .map(|(t, v)| (ike_attribute_name(*t), Value::from(*v)))
+        .collect();
+    let mut obj = json!({
+        "proposal": transform.proposal,
+        "protocol": transform.protocol,
+        "id": transform.id,
+    });
+    if version == IKE_VERSION_1 {
+        obj["number"] = transform.number.into();
+        obj["attributes"] = Value::Object(attributes);
+    } else {
+        obj["type"] = transform.type_.into();
+        if let Some((_, key_length)) = transform.attributes.iter().find(|(t, _)| *t == 14) {
+            obj["key_length"] = (*key_length).into();
+        }
+    }
+    obj
+}
+
+/* stateless responder cookie (or SPI): keyed hash of the client
+ * 5-tuple and of the initiator cookie */
+fn ike_responder_cookie(client_info: &ClientInfo, key: &[u64; 2], icookie: &[u8]) -> [u8; 8] {
+    let tuple = synackcookie::generate(client_info, key).unwrap_or(0);
+    let mut sip = SipHasher24::new_with_keys(key[0], key[1]);
+    sip.write_u32(tuple);
+    sip.write(icookie);
+    sip.finish().to_be_bytes()
+}
+
+/* RFC 2104 (MD5 and SHA-1 have 64-byte blocks) */
+fn ike_hmac<D: Digest>(key: &[u8], data: &[&[u8]]) -> Vec<u8> {
+    let mut k = if key.len() > 64 {
+        D::digest(key).to_vec()
+    } else {
+        key.to_vec()
+    };
+    k.resize(64, 0);
+    let mut inner = D::new();
+    inner.update(k.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>());
+    for d in data {
+        inner.update(d);
+    }
+    let mut outer = D::new();
+    outer.update(k.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>());
+    outer.update(inner.finalize());
+    outer.finalize().to_vec()
+}
+
+fn ike_prf(hash: u64, key: &[u8], data: &[&[u8]]) -> Vec<u8> {
+    if hash == IKE_HASH_MD5 {
+        ike_hmac::<Md5>(key, data)
+    } else {
+        ike_hmac::<Sha1>(key, data)
+    }
+}
+
+/* message from a header and payloads (type and body) */
+fn ike_message(
+    header: [&[u8]; 2],
+    version: u8,
+    exchange: u8,
+    flags: u8,
+    message_id: u32,
+    payloads: &[(u8, Vec<u8>)],
+) -> Vec<u8> {
+    let mut msg = header[0].to_vec();
+    msg.extend_from_slice(header[1]);
+    msg.push(payloads.first().map(|p| p.0).unwrap_or(IKE_PAYLOAD_NONE));
+    msg.push(version);
+    msg.push(exchange);
+    msg.push(flags);
+    msg.extend_from_slice(&message_id.to_be_bytes());
+    msg.extend_from_slice(&[0; 4]);
+    for (i, (_, body)) in payloads.iter().enumerate() {
+        msg.push(payloads.get(i + 1).map(|p| p.0).unwrap_or(IKE_PAYLOAD_NONE));
+        msg.push(0);
+        msg.extend_from_slice(&(body.len() as u16 + 4).to_be_bytes());
+        msg.extend_from_slice(body);
+    }
+    let len = (msg.len() as u32).to_be_bytes();
+    msg[24..28].copy_from_slice(&len);
+    msg
+}
+
+/* proposal substructure with the chosen transforms: the first byte of
+ * each is rewritten (3, for the next transform in IKEv1 and "more" in
+ * IKEv2, or 0 for the last one) */
+fn ike_proposal(number: u8, transforms: &[&IkeTransform]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (i, t) in transforms.iter().enumerate() {
+        let mut raw = t.raw.to_vec();
+        raw[0] = if i + 1 < transforms.len() { 3 } else { 0 };
+        body.extend(raw);
+    }
+    let mut proposal = vec![0, 0];
+    proposal.extend_from_slice(&(body.len() as u16 + 8).to_be_bytes());
+    proposal.extend_from_slice(&[number, IKE_PROTO_ISAKMP, 0, transforms.len() as u8]);
+    proposal.extend(body);
+    proposal
+}
+
+fn ike_vendor_ids(config: &IkeConfig, payload: u8) -> Vec<(u8, Vec<u8>)> {
+    config
+        .vendor_ids
+        .iter()
+        .filter_map(|v| ike_from_hex(v))
+        .map(|v| (payload, v))
+        .collect()
+}
+
+fn ike_notify_v1(header: &IkeHeader, notify: u16) -> Vec<u8> {
+    let mut body = IKE_DOI_IPSEC.to_be_bytes().to_vec();
+    body.extend_from_slice(&[IKE_PROTO_ISAKMP, 0]);
+    body.extend_from_slice(&notify.to_be_bytes());
+    ike_message(
+        [header.icookie, &[0; 8]],
+        IKE_VERSION_1,
+        IKE_EXCHANGE_INFORMATIONAL,
+        0,
+        0,
+        &[(IKE_PAYLOAD_NOTIFY, body)],
+    )
+}
+
+/* IKEv1 Main Mode or Aggressive Mode, first message */
+fn ike_v1(
+    header: &IkeHeader,
+    payloads: &[(u8, &[u8])],
+    transforms: &[IkeTransform],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    let config = &masscanned.config.ike;
+    let sa = payloads.iter().find(|(t, _)| *t == IKE_PAYLOAD_SA)?.1;
+    let chosen = match transforms
+        .iter()
+        .find(|t| t.protocol == IKE_PROTO_ISAKMP && config.acceptable(t))
+    {
+        Some(t) => t,
+        None => {
+            warn!("IKE: no acceptable transform");
+            return Some(ike_notify_v1(header, IKE_NOTIFY_NO_PROPOSAL_CHOSEN));
+        }
+    };
+    let rcookie = ike_responder_cookie(client_info, &masscanned.synack_key, header.icookie);
+    let mut sa_body = IKE_DOI_IPSEC.to_be_bytes().to_vec();
+    sa_body.extend_from_slice(&IKE_SIT_IDENTITY_ONLY.to_be_bytes());
+    sa_body.extend(ike_proposal(chosen.proposal, &[chosen]));
+    let mut repl_payloads = vec![(IKE_PAYLOAD_SA, sa_body.clone())];
+    if header.exchange == IKE_EXCHANGE_AGGRESSIVE {
+        let ke = payloads.iter().find(|(t, _)| *t == IKE_PAYLOAD_KE)?.1;
+        let nonce_i = payloads.iter().find(|(t, _)| *t == IKE_PAYLOAD_NONCE)?.1;
+        let ke_r: Vec<u8> = (0..ke.len()).map(|_| rand::random::<u8>()).collect();
+        let nonce_r = rand::random::<[u8; 20]>().to_vec();
+        /* our address, UDP port 500 */
+        let (id_type, address) = match client_info.ip.dst {
+            Some(IpAddr::V6(ip)) => (IKE_ID_IPV6_ADDR, ip.octets().to_vec()),
+            Some(IpAddr::V4(ip)) => (IKE_ID_IPV4_ADDR, ip.octets().to_vec()),
+            None => (IKE_ID_IPV4_ADDR, vec![0; 4]),
+        };
+        let mut id = vec![id_type, 17, 0x01, 0xf4];
+        id.extend(address);
+        /* RFC 2409 section 5: pre-shared key authentication */
+        let hash = chosen
+            .attributes
+            .iter()
+            .find(|(t, _)| *t == IKE_ATTR_HASH)
+            .map(|(_, v)| *v)
+            .unwrap_or(IKE_HASH_SHA);
+        let skeyid = ike_prf(hash, config.psk.as_bytes(), &[nonce_i, &nonce_r]);
+        let hash_r = ike_prf(
+            hash,
+            &skeyid,
+            &[&ke_r, ke, &rcookie, header.icookie, sa, &id],
+        );
+        repl_payloads.push((IKE_PAYLOAD_KE, ke_r));
+        repl_payloads.push((IKE_PAYLOAD_NONCE, nonce_r));
+        repl_payloads.push((IKE_PAYLOAD_ID, id));
+        repl_payloads.push((IKE_PAYLOAD_HASH, hash_r));
+    }
+    repl_payloads.extend(ike_vendor_ids(config, IKE_PAYLOAD_VENDOR_ID));
+    Some(ike_message(
+        [header.icookie, &rcookie],
+        IKE_VERSION_1,
+        header.exchange,
+        0,
+        0,
+        &repl_payloads,
+    ))
+}
+
+/* IKEv2 IKE_SA_INIT request */
+fn ike_v2(
+    header: &IkeHeader,
+    payloads: &[(u8, &[u8])],
+    transforms: &[IkeTransform],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    let config = &masscanned.config.ike;
+    let ke = payloads
+        .iter()
+        .find(|(t, b)| *t == IKE2_PAYLOAD_KE && b.len() >= 4)
+        .map(|(_, b)| *b);
+    let proposal = transforms
+        .iter()
+        .find(|t| t.protocol == IKE_PROTO_ISAKMP)
+        .map(|t| t.proposal);
+    let (ke, proposal) = match (config.ikev2, ke, proposal) {
+        (Ikev2Answer::SaInit, Some(ke), Some(p)) => (ke, p),
+        _ => {
+            warn!("IKEv2: NO_PROPOSAL_CHOSEN");
+            let mut body = vec![0, 0];
+            body.extend_from_slice(&IKE_NOTIFY_NO_PROPOSAL_CHOSEN.to_be_bytes());
+            return Some(ike_message(
+                [header.icookie, &[0; 8]],
+                IKE_VERSION_2,
+                IKE_EXCHANGE_SA_INIT,
+                IKE_FLAG_RESPONSE,
+                header.message_id,
+                &[(IKE2_PAYLOAD_NOTIFY, body)],
+            ));
+        }
+    };
+    let group = u16::from_be_bytes([ke[0], ke[1]]);
+    /* first transform of each type, the group of the KE payload for
+     * Diffie-Hellman */
+    let offered: Vec<&IkeTransform> = transforms
+        .iter()
+        .filter(|t| t.proposal == proposal)
+        .collect();
+    let mut chosen: Vec<&IkeTransform> = Vec::new();
+    for t in offered.iter() {
+        if chosen.iter().any(|c| c.type_ == t.type_) {
+            continue;
+        }
+        if t.type_ == IKE2_TRANSFORM_DH
+            && t.id != group
+            && offered
+                .iter()
+                .any(|o| o.type_ == IKE2_TRANSFORM_DH && o.id == group)
+        {
+            continue;
+        }
+        chosen.push(t);
+    }
+    let spi = ike_responder_cookie(client_info, &masscanned.synack_key, header.icookie);
+    let mut ke_r = group.to_be_bytes().to_vec();
+    ke_r.extend_from_slice(&[0, 0]);
+    ke_r.extend((4..ke.len()).map(|_| rand::random::<u8>()));
+    let mut repl_payloads = vec![
+        (IKE2_PAYLOAD_SA, ike_proposal(proposal, &chosen)),
+        (IKE2_PAYLOAD_KE, ke_r),
+        (IKE2_PAYLOAD_NONCE, rand::random::<[u8; 32]>().to_vec()),
+    ];
+    repl_payloads.extend(ike_vendor_ids(config, IKE2_PAYLOAD_VENDOR_ID));
+    Some(ike_message(
+        [header.icookie, &spi],
+        IKE_VERSION_2,
+        IKE_EXCHANGE_SA_INIT,
+        IKE_FLAG_RESPONSE,
+        header.message_id,
+        &repl_payloads,
+    ))
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving IKE data");
+    let config = &masscanned.config.ike;
+    /* NAT-T: IKE messages follow the non-ESP marker (ESP packets and
+     * keepalives are ignored) */
+    let nat_t = client_info
+        .port
+        .dst
+        .is_some_and(|p| config.nat_t_ports.contains(&p));
+    let data = if nat_t {
+        match data.strip_prefix(IKE_NON_ESP_MARKER) {
+            Some(d) => d,
+            None => {
+                debug!("IKE: not an IKE message (NAT-T)");
+                return None;
+            }
+        }
+    } else {
+        data
+    };
+    let (header, body) = ike_parse_header(data)?;
+    let payloads = ike_parse_payloads(header.next, body);
+    let transforms: Vec<IkeTransform> = payloads
+        .iter()
+        .filter(|(t, _)| *t == IKE_PAYLOAD_SA || *t == IKE2_PAYLOAD_SA)
+        .flat_map(|(_, sa)| ike_parse_sa(sa, header.version & 0xf0))
+        .collect();
+    let mut event = Event::new("ike_request", client_info);
+    event.set(
+        "version",
+        format!("{}.{}", header.version >> 4, header.version & 0xf),
+    );
+    event.set("exchange", header.exchange);
+    event.set("flags", header.flags);
+    event.set("initiator_cookie", encode(header.icookie, Encoding::Hex));
+    event.set("nat_t", nat_t);
+    event.set(
+        "transforms",
+        transforms
+            .iter()
+            .map(|t| ike_transform_json(t, header.version & 0xf0))
+            .collect::<Vec<Value>>(),
+    );
+    event.set(
+        "vendor_ids",
+        payloads
+            .iter()
+            .filter(|(t, _)| *t == IKE_PAYLOAD_VENDOR_ID || *t == IKE2_PAYLOAD_VENDOR_ID)
+            .map(|(_, v)| encode(v, Encoding::Hex))
+            .collect::<Vec<String>>(),
+    );
+    if let Some((_, id)) = payloads
+        .iter()
+        .find(|(t, b)| *t == IKE_PAYLOAD_ID && b.len() >= 4)
+    {
+        event.set("id_type", id[0]);
+        event.set("id", String::from_utf8_lossy(&id[4..]).to_string());
+    }
+    event.log();
+    let repl = match (header.version & 0xf0, header.exchange) {
+        (IKE_VERSION_1, IKE_EXCHANGE_MAIN_MODE | IKE_EXCHANGE_AGGRESSIVE)
+            if header.rcookie == [0; 8] =>
+        {
+            ike_v1(&header, &payloads, &transforms, masscanned, client_info)
+        }
+        (IKE_VERSION_2, IKE_EXCHANGE_SA_INIT)
+            if header.flags & (IKE_FLAG_INITIATOR | IKE_FLAG_RESPONSE) == IKE_FLAG_INITIATOR =>
+        {
+            ike_v2(&header, &payloads, &transforms, masscanned, client_info)
+        }
+        (version, exchange) => {
+            info!(
+                "IKE message not handled (version 0x{:02x}, exchange {})",
+                version, exchange
+            );
+            None
+        }
+    }?;
+    debug!("sending IKE data");
+    if nat_t {
+        let mut r = IKE_NON_ESP_MARKER.to_vec();
+        r.extend(repl);
+        return Some(r);
+    }
+    Some(repl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    const ICOOKIE: &[u8] = b"\x6f\x3a\x10\x87\xc2\x54\x9e\x01";
+
+    fn instance() -> Masscanned<'static> {
+        Masscanned {
+            synack_key: [0x1e9219e0b0e0b44c, 0x9e460bcddf4eaac9],
+            ..crate::proto::test_util::masscanned(Config::default())
+        }
+    }
+
+    fn client(port: u16) -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Udp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.src = Some(500);
+        client_info.port.dst = Some(port);
+        client_info
+    }
+
+    fn payload(next: u8, body: &[u8]) -> Vec<u8> {
+        let mut p = vec![next, 0];
+        p.extend_from_slice(&(body.len() as u16 + 4).to_be_bytes());
+        p.extend_from_slice(body);
+        p
+    }
+
+    fn message(version: u8, exchange: u8, flags: u8, payloads: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let mut msg = ICOOKIE.to_vec();
+        msg.extend_from_slice(&[0; 8]);
+        msg.extend_from_slice(&[
+            payloads[0].0,
+            version,
+            exchange,
+            flags,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        for (i, (_, body)) in payloads.iter().enumerate() {
+            msg.extend(payload(payloads.get(i + 1).map(|p| p.0).unwrap_or(0), body));
+        }
+        let len = (msg.len() as u32).to_be_bytes();
+        msg[24..28].copy_from_slice(&len);
+        msg
+    }
+
+    /* SA payload of ike-scan (default transforms: 3DES and DES, SHA-1
+     * and MD5, groups 2 and 1, pre-shared key, 28800 seconds) */
+    fn ikescan_sa(encryption: &[u16]) -> Vec<u8> {
+        let mut transforms = Vec::new();
+        let mut number = 0;
+        for group in [2u16, 1].iter() {
+            for enc in encryption.iter() {
+                for hash in [2u16, 1].iter() {
+                    number += 1;
+                    let mut t = vec![number, 1, 0, 0];
+                    for (attr, value) in
+                        [(1u16, *enc), (2, *hash), (3, 1), (4, *group), (11, 1)].iter()
+                    {
+                        t.extend_from_slice(&(0x8000 | attr).to_be_bytes());
+                        t.extend_from_slice(&value.to_be_bytes());
+                    }
+                    t.extend_from_slice(b"\x00\x0c\x00\x04\x00\x00\x70\x80");
+                    transforms.push(t);
+                }
+            }
+        }
+        let count = transforms.len();
+        let mut proposal = vec![1, IKE_PROTO_ISAKMP, 0, count as u8];
+        for (i, t) in transforms.iter().enumerate() {
+            proposal.extend(payload(if i + 1 < count { 3 } else { 0 }, t));
+        }
+        let mut sa = b"\x00\x00\x00\x01\x00\x00\x00\x01".to_vec();
+        sa.extend(payload(0, &proposal));
+        sa
+    }
+
+    #[test]
+    fn test_ike_main_mode() {
+        let masscanned = instance();
+        let probe = message(
+            IKE_VERSION_1,
+            IKE_EXCHANGE_MAIN_MODE,
+            0,
+            &[(IKE_PAYLOAD_SA, ikescan_sa(&[5, 1]))],
+        );
+        assert!(probe.len() == 336);
+        let mut client_info = client(500);
+        let repl_data = crate::proto::dispatch(&probe, &masscanned, &mut client_info, None)
+            .expect("no answer to Main Mode");
+        let (header, body) = ike_parse_header(&repl_data).unwrap();
+        assert!(header.icookie == ICOOKIE && header.rcookie != [0; 8]);
+        assert!(header.version == IKE_VERSION_1 && header.exchange == IKE_EXCHANGE_MAIN_MODE);
+        assert!(body.len() == repl_data.len() - IKE_HEADER_LEN);
+        let payloads = ike_parse_payloads(header.next, body);
+        assert!(payloads.len() == 5);
+        assert!(payloads[0].0 == IKE_PAYLOAD_SA);
+        /* the first transform (3DES, SHA-1, PSK, group 2), as offered */
+        let transforms = ike_parse_sa(payloads[0].1, IKE_VERSION_1);
+        assert!(transforms.len() == 1 && transforms[0].number == 1);
+        assert!(transforms[0].attributes[..4] == [(1, 5), (2, 2), (3, 1), (4, 2)]);
+        assert!(transforms[0].attributes[5] == (12, 28800));
+        assert!(transforms[0].raw[0] == 0);
+        assert!(payloads[1..]
+            .iter()
+            .all(|(t, _)| *t == IKE_PAYLOAD_VENDOR_ID));
+        assert!(payloads[2].1 == b"\x09\x00\x26\x89\xdf\xd6\xb7\x12");
+        /* stateless responder cookie */
+        let again = repl(&probe, &masscanned, &mut client_info).unwrap();
+        assert!(again[8..16] == repl_data[8..16]);
+        /* acceptable transforms only */
+        let mut masscanned = instance();
+        masscanned.config.ike.encryption = vec![1];
+        masscanned.config.ike.hash = vec![IKE_HASH_MD5];
+        masscanned.config.ike.group = vec![1];
+        let repl_data = repl(&probe, &masscanned, &mut client_info).unwrap();
+        let (header, body) = ike_parse_header(&repl_data).unwrap();
+        let payloads = ike_parse_payloads(header.next, body);
+        let transforms = ike_parse_sa(payloads[0].1, IKE_VERSION_1);
+        assert!(transforms[0].number == 8);
+        /* none: NO-PROPOSAL-CHOSEN */
+        masscanned.config.ike.encryption = vec![7];
+        let repl_data = repl(&probe, &masscanned, &mut client_info).unwrap();
+        let (header, body) = ike_parse_header(&repl_data).unwrap();
+        assert!(header.exchange == IKE_EXCHANGE_INFORMATIONAL && header.rcookie == [0; 8]);
+        let payloads = ike_parse_payloads(header.next, body);
+        assert!(payloads == [(IKE_PAYLOAD_NOTIFY, &b"\x00\x00\x00\x01\x01\x00\x00\x0e"[..])]);
+        /* later messages, truncated messages */
+        let mut later = probe.clone();
+        later[8] = 1;
+        assert!(repl(&later, &masscanned, &mut client_info).is_none());
+        assert!(repl(&probe[..335], &masscanned, &mut client_info).is_none());
+        assert!(Config::from_str("[ike]\nvendor_ids = [\"xyz\"]\n").is_err());
+        assert!(Config::from_str("[ike]\nhash = [4]\n").is_err());
+    }
+
+    #[test]
+    fn test_ike_aggressive() {
+        let masscanned = instance();
+        /* ike-scan --aggressive --id=vpngroup */
+        let ke_i = [0x5au8; 128].to_vec();
+        let nonce_i = [0xa5u8; 20].to_vec();
+        let sa_i = ikescan_sa(&[5, 1]);
+        let probe = message(
+            IKE_VERSION_1,
+            IKE_EXCHANGE_AGGRESSIVE,
+            0,
+            &[
+                (IKE_PAYLOAD_SA, sa_i.clone()),
+                (IKE_PAYLOAD_KE, ke_i.clone()),
+                (IKE_PAYLOAD_NONCE, nonce_i.clone()),
+                (IKE_PAYLOAD_ID, b"\x03\x00\x00\x00vpngroup".to_vec()),
+            ],
+        );
+        let mut client_info = client(500);
+        let repl_data = repl(&probe, &masscanned, &mut client_info).unwrap();
+        assert!(repl_data.len() <= 10 * probe.len());
+        let (header, body) = ike_parse_header(&repl_data).unwrap();
+        assert!(header.exchange == IKE_EXCHANGE_AGGRESSIVE);
+        let payloads = ike_parse_payloads(header.next, body);
+        let types: Vec<u8> = payloads.iter().map(|(t, _)| *t).collect();
+        assert!(
+            types
+                == [
+                    IKE_PAYLOAD_SA,
+                    IKE_PAYLOAD_KE,
+                    IKE_PAYLOAD_NONCE,
+                    IKE_PAYLOAD_ID,
+                    IKE_PAYLOAD_HASH,
+                    IKE_PAYLOAD_VENDOR_ID,
+                    IKE_PAYLOAD_VENDOR_ID,
+                    IKE_PAYLOAD_VENDOR_ID,
+                    IKE_PAYLOAD_VENDOR_ID,
+                ]
+        );
+        let (ke_r, nonce_r, id_r, hash_r) =
+            (payloads[1].1, payloads[2].1, payloads[3].1, payloads[4].1);
+        assert!(ke_r.len() == 128 && nonce_r.len() == 20);
+        assert!(id_r == b"\x01\x11\x01\xf4\xc0\x00\x02\x01");
+        /* HASH_R, as checked by the initiator (or psk-crack) */
+        let skeyid = ike_hmac::<Sha1>(b"cisco123", &[&nonce_i, nonce_r]);
+        let expected = ike_hmac::<Sha1>(
+            &skeyid,
+            &[ke_r, &ke_i, header.rcookie, ICOOKIE, &sa_i, id_r],
+        );
+        assert!(hash_r == &expected[..] && hash_r.len() == 20);
+        /* RFC 2202 section 2 */
+        assert!(
+            ike_hmac::<Md5>(b"Jefe", &[b"what do ya ", b"want for nothing?"])
+                == b"\x75\x0c\x78\x3e\x6a\xb0\xb5\x03\xea\xa8\x6e\x31\x0a\x5d\xb7\x38"
+        );
+    }
+
+    #[test]
+    fn test_ikev2_sa_init() {
+        let mut masscanned = instance();
+        /* ike-scan --ikev2: 3DES, HMAC-SHA1, HMAC-SHA1-96, groups 2
+         * and 14 */
+        let mut proposal = vec![1, IKE_PROTO_ISAKMP, 0, 5];
+        let transforms: [(u8, u16); 5] = [(1, 3), (2, 2), (3, 2), (4, 14), (4, 2)];
+        for (i, (type_, id)) in transforms.iter().enumerate() {
+            let mut t = vec![*type_, 0];
+            t.extend_from_slice(&id.to_be_bytes());
+            proposal.extend(payload(if i + 1 < transforms.len() { 3 } else { 0 }, &t));
+        }
+        let mut ke = b"\x00\x02\x00\x00".to_vec();
+        ke.extend_from_slice(&[0x33; 128]);
+        let probe = message(
+            IKE_VERSION_2,
+            IKE_EXCHANGE_SA_INIT,
+            IKE_FLAG_INITIATOR,
+            &[
+                (IKE2_PAYLOAD_SA, payload(0, &proposal)),
+                (IKE2_PAYLOAD_KE, ke),
+                (IKE2_PAYLOAD_NONCE, [0x44; 20].to_vec()),
+            ],
+        );
+        /* NAT-T */
+        let mut nat_t_probe = IKE_NON_ESP_MARKER.to_vec();
+        nat_t_probe.extend_from_slice(&probe);
+        let mut client_info = client(4500);
+        let repl_data = crate::proto::dispatch(&nat_t_probe, &masscanned, &mut client_info, None)
+            .expect("no answer to IKE_SA_INIT");
+        assert!(repl_data.starts_with(IKE_NON_ESP_MARKER));
+        let (header, body) = ike_parse_header(&repl_data[4..]).unwrap();
+        assert!(header.version == IKE_VERSION_2 && header.flags == IKE_FLAG_RESPONSE);
+        assert!(header.icookie == ICOOKIE && header.rcookie != [0; 8]);
+        let payloads = ike_parse_payloads(header.next, body);
+        assert!(payloads[0].0 == IKE2_PAYLOAD_SA && payloads[1].0 == IKE2_PAYLOAD_KE);
+        assert!(payloads[2].0 == IKE2_PAYLOAD_NONCE && payloads[3].0 == IKE2_PAYLOAD_VENDOR_ID);
+        /* one transform per type, the group of the KE payload */
+        let chosen: Vec<(u8, u16)> = ike_parse_sa(payloads[0].1, IKE_VERSION_2)
+            .iter()
+            .map(|t| (t.type_, t.id))
+            .collect();
+        assert!(chosen == [(1, 3), (2, 2), (3, 2), (4, 2)]);
+        assert!(payloads[1].1.len() == 132 && payloads[1].1[..2] == [0, 2]);
+        /* ESP (no marker) is ignored, and the marker is needed on 4500 */
+        assert!(repl(
+            b"\x00\x00\x10\x01\x00\x00\x00\x01",
+            &masscanned,
+            &mut client_info
+        )
+        .is_none());
+        assert!(repl(&probe, &masscanned, &mut client_info).is_none());
+        /* NO_PROPOSAL_CHOSEN */
+        masscanned.config.ike.ikev2 = Ikev2Answer::NoProposalChosen;
+        let mut client_info = client(500);
+        let repl_data = repl(&probe, &masscanned, &mut client_info).unwrap();
+        let (header, body) = ike_parse_header(&repl_data).unwrap();
+        assert!(header.rcookie == [0; 8]);
+        assert!(
+            ike_parse_payloads(header.next, body)
+                == [(IKE2_PAYLOAD_NOTIFY, &b"\x00\x00\x00\x0e"[..])]
+        );
+    }
+}
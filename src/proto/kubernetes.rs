@@ -0,0 +1,395 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::http::http_auth_event;
+
+/* Kubernetes API server (and kubelet), answered instead of the HTTP
+ * persona on the configured ports: /version, /api and /apis (discovery)
+ * are answered, other paths get a Status error (status, 401 or 403, for
+ * anonymous requests, 401 when a token is presented). Requests with
+ * credentials (e.g., a service account token) and denied requests are
+ * recorded.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KubernetesConfig {
+    pub ports: Vec<u16>,
+    /* vX.Y.Z - also gives major and minor */
+    pub git_version: String,
+    pub git_commit: String,
+    pub build_date: String,
+    pub go_version: String,
+    /* announced in /api */
+    pub server_address: String,
+    pub status: u16,
+}
+
+impl Default for KubernetesConfig {
+    fn default() -> Self {
+        KubernetesConfig {
+            ports: vec![6443, 10250],
+            git_version: "v1.28.4".to_string(),
+            git_commit: "bae2c62678db2b5053817bc97181fcc2e8388103".to_string(),
+            build_date: "2023-11-15T16:48:54Z".to_string(),
+            go_version: "go1.20.11".to_string(),
+            server_address: "10.0.0.1:6443".to_string(),
+            status: 403,
+        }
+    }
+}
+
+impl KubernetesConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if k8s_version(&self.git_version).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("kubernetes: invalid version: {:?}", self.git_version),
+            ));
+        }
+        if self.status != 401 && self.status != 403 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("kubernetes: invalid status: {} (401 or 403)", self.status),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* major and minor of a vX.Y.Z version */
+fn k8s_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.strip_prefix('v')?.splitn(3, '.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    /* the patch number may have a suffix (e.g., 1+k3s1) */
+    parts
+        .next()?
+        .split(['-', '+'])
+        .next()?
+        .parse::<u32>()
+        .ok()?;
+    Some((major, minor))
+}
+
+fn k8s_version_info(config: &KubernetesConfig) -> Value {
+    let (major, minor) = k8s_version(&config.git_version).unwrap_or((1, 0));
+    json!({
+        "major": major.to_string(),
+        "minor": minor.to_string(),
+        "gitVersion": config.git_version,
+        "gitCommit": config.git_commit,
+        "gitTreeState": "clean",
+        "buildDate": config.build_date,
+        "goVersion": config.go_version,
+        "compiler": "gc",
+        "platform": "linux/amd64",
+    })
+}
+
+fn k8s_api_versions(config: &KubernetesConfig) -> Value {
+    json!({
+        "kind": "APIVersions",
+        "versions": ["v1"],
+        "serverAddressByClientCIDRs": [{
+            "clientCIDR": "0.0.0.0/0",
+            "serverAddress": config.server_address,
+        }],
+    })
+}
+
+const K8S_API_GROUPS: [(&str, &str); 8] = [
+    ("apiregistration.k8s.io", "v1"),
+    ("apps", "v1"),
+    ("events.k8s.io", "v1"),
+    ("authentication.k8s.io", "v1"),
+    ("authorization.k8s.io", "v1"),
+    ("autoscaling", "v2"),
+    ("batch", "v1"),
+    ("rbac.authorization.k8s.io", "v1"),
+];
+
+fn k8s_api_groups() -> Value {
+    let groups: Vec<Value> = K8S_API_GROUPS
+        .iter()
+        .map(|(name, version)| {
+            let version = json!({
+                "groupVersion": format!("{}/{}", name, version),
+                "version": version,
+            });
+            json!({
+                "name": name,
+                "versions": [version],
+                "preferredVersion": version,
+            })
+        })
+        .collect();
+    json!({"kind": "APIGroupList", "apiVersion": "v1", "groups": groups})
+}
+
+/* Status object of a denied request */
+fn k8s_status(code: u16, verb: &str, path: &str) -> Value {
+    let (reason, message) = if code == 401 {
+        ("Unauthorized", "Unauthorized".to_string())
+    } else {
+        (
+            "Forbidden",
+            format!(
+                "forbidden: User \"system:anonymous\" cannot {} path \"{}\"",
+                verb.to_ascii_lowercase(),
+                path
+            ),
+        )
+    };
+    let mut status = json!({
+        "kind": "Status",
+        "apiVersion": "v1",
+        "metadata": {},
+        "status": "Failure",
+        "message": message,
+        "reason": reason,
+        "code": code,
+    });
+    if code == 403 {
+        status["details"] = json!({});
+    }
+    status
+}
+
+/* claims of a JWT (e.g., a service account token), not verified */
+fn k8s_token_claims(token: &str) -> Option<Value> {
+    let payload = token.split('.').nth(1)?;
+    let payload =
+        base64::decode_config(payload.trim_end_matches('='), base64::URL_SAFE_NO_PAD).ok()?;
+    serde_json::from_slice(&payload).ok()
+}
+
+fn k8s_event(
+    verb: &str,
+    uri: &str,
+    status: u16,
+    authorization: Option<&[u8]>,
+    client_info: &ClientInfo,
+) -> Event {
+    let mut event = Event::new("kubernetes_request", client_info);
+    event.set("verb", verb);
+    event.set("uri", uri);
+    event.set("status", status);
+    if let Some(value) = authorization {
+        http_auth_event(&mut event, value);
+        let value = String::from_utf8_lossy(value);
+        if let Some(token) = value.trim().strip_prefix("Bearer ") {
+            let token = token.trim();
+            event.set("token", token);
+            if let Some(claims) = k8s_token_claims(token) {
+                event.set("token_claims", claims);
+            }
+        }
+    }
+    event
+}
+
+/* Status, headers and content of the answer to a request (path is the
+ * normalized path of uri, None if invalid, authorization the value of
+ * the Authorization header).
+ **/
+pub fn k8s_page(
+    config: &KubernetesConfig,
+    verb: &str,
+    uri: &str,
+    path: Option<&str>,
+    authorization: Option<&[u8]>,
+    client_info: &ClientInfo,
+) -> (String, Vec<String>, String) {
+    let path = path.unwrap_or("/");
+    let headers = || {
+        vec![
+            "Audit-Id: ".to_string() + &k8s_audit_id(),
+            "Cache-Control: no-cache, private".to_string(),
+            "Content-Type: application/json".to_string(),
+            "X-Content-Type-Options: nosniff".to_string(),
+        ]
+    };
+    let discovery = match (verb, path.trim_end_matches('/')) {
+        ("GET" | "HEAD", "/version") => Some(k8s_version_info(config)),
+        ("GET" | "HEAD", "/api") => Some(k8s_api_versions(config)),
+        ("GET" | "HEAD", "/apis") => Some(k8s_api_groups()),
+        _ => None,
+    };
+    /* invalid tokens are rejected (401) even for discovery */
+    let status = match (authorization, discovery.is_some()) {
+        (Some(_), _) => 401,
+        (None, true) => 200,
+        (None, false) => config.status,
+    };
+    if status != 200 {
+        warn!("Kubernetes {} {}: {}", verb, uri, status);
+        k8s_event(verb, uri, status, authorization, client_info).log();
+    }
+    if let (200, Some(content)) = (status, discovery) {
+        let content = serde_json::to_string_pretty(&content).unwrap() + "\n";
+        return ("200 OK".to_string(), headers(), content);
+    }
+    let mut headers = headers();
+    let reason = if status == 401 {
+        headers.push("Www-Authenticate: Basic realm=\"kubernetes-master\"".to_string());
+        "401 Unauthorized"
+    } else {
+        "403 Forbidden"
+    };
+    let content = serde_json::to_string_pretty(&k8s_status(status, verb, path)).unwrap() + "\n";
+    (reason.to_string(), headers, content)
+}
+
+/* random request ID (UUID v4) */
+fn k8s_audit_id() -> String {
+    let mut b = rand::random::<[u8; 16]>();
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let h: String = b.iter().map(|c| format!("{:02x}", c)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &h[..8],
+        &h[8..12],
+        &h[12..16],
+        &h[16..20],
+        &h[20..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(
+        config: &KubernetesConfig,
+        verb: &str,
+        uri: &str,
+        authorization: Option<&[u8]>,
+    ) -> (String, Vec<String>, String) {
+        let path = uri.split('?').next();
+        k8s_page(config, verb, uri, path, authorization, &ClientInfo::new())
+    }
+
+    #[test]
+    fn test_k8s_discovery() {
+        let config = KubernetesConfig::default();
+        assert!(config.check().is_ok());
+        let (status, headers, content) = page(&config, "GET", "/version?timeout=32s", None);
+        assert!(status == "200 OK");
+        assert!(headers.contains(&"Content-Type: application/json".to_string()));
+        let version: Value = serde_json::from_str(&content).unwrap();
+        assert!(version["major"] == "1" && version["minor"] == "28");
+        assert!(version["gitVersion"] == "v1.28.4");
+        assert!(version["platform"] == "linux/amd64");
+        let (status, _, content) = page(&config, "GET", "/api", None);
+        assert!(status == "200 OK");
+        let api: Value = serde_json::from_str(&content).unwrap();
+        assert!(api["kind"] == "APIVersions" && api["versions"][0] == "v1");
+        assert!(api["serverAddressByClientCIDRs"][0]["serverAddress"] == "10.0.0.1:6443");
+        let (status, _, content) = page(&config, "GET", "/apis/", None);
+        assert!(status == "200 OK");
+        let apis: Value = serde_json::from_str(&content).unwrap();
+        assert!(apis["kind"] == "APIGroupList");
+        assert!(apis["groups"][1]["name"] == "apps");
+        assert!(apis["groups"][1]["preferredVersion"]["groupVersion"] == "apps/v1");
+        /* versions */
+        assert!(k8s_version("v1.27.3+k3s1") == Some((1, 27)));
+        for v in ["1.28.4", "v1.28", "v1.x.0"].iter() {
+            let config = KubernetesConfig {
+                git_version: v.to_string(),
+                ..Default::default()
+            };
+            assert!(config.check().is_err());
+        }
+        let config = KubernetesConfig {
+            status: 404,
+            ..Default::default()
+        };
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn test_k8s_denied() {
+        let mut config = KubernetesConfig::default();
+        /* anonymous requests */
+        let (status, headers, content) = page(&config, "GET", "/pods", None);
+        assert!(status == "403 Forbidden");
+        assert!(!headers.iter().any(|h| h.starts_with("Www-Authenticate: ")));
+        let s: Value = serde_json::from_str(&content).unwrap();
+        assert!(s["kind"] == "Status" && s["status"] == "Failure");
+        assert!(s["reason"] == "Forbidden" && s["code"] == 403);
+        assert!(s["message"] == "forbidden: User \"system:anonymous\" cannot get path \"/pods\"");
+        config.status = 401;
+        let (status, headers, content) = page(&config, "GET", "/api/v1/secrets", None);
+        assert!(status == "401 Unauthorized");
+        assert!(
+            headers.contains(&"Www-Authenticate: Basic realm=\"kubernetes-master\"".to_string())
+        );
+        let s: Value = serde_json::from_str(&content).unwrap();
+        assert!(s["reason"] == "Unauthorized" && s["code"] == 401);
+    }
+
+    #[test]
+    fn test_k8s_token() {
+        let config = KubernetesConfig::default();
+        /* service account token (header and payload only matter) */
+        let claims = json!({
+            "iss": "kubernetes/serviceaccount",
+            "kubernetes.io/serviceaccount/namespace": "kube-system",
+            "kubernetes.io/serviceaccount/service-account.name": "default",
+        });
+        let token = format!(
+            "eyJhbGciOiJSUzI1NiIsImtpZCI6IiJ9.{}.c2lnbmF0dXJl",
+            base64::encode_config(claims.to_string(), base64::URL_SAFE_NO_PAD)
+        );
+        let authorization = format!("Bearer {}", token);
+        /* rejected, even for discovery */
+        let (status, headers, _) = page(&config, "GET", "/version", Some(authorization.as_bytes()));
+        assert!(status == "401 Unauthorized");
+        assert!(headers
+            .iter()
+            .any(|h| h.starts_with("Www-Authenticate: Basic ")));
+        let event = k8s_event(
+            "GET",
+            "/api/v1/namespaces/kube-system/secrets",
+            401,
+            Some(authorization.as_bytes()),
+            &ClientInfo::new(),
+        );
+        assert!(event.name == "kubernetes_request");
+        assert!(event.fields["auth_scheme"] == "Bearer");
+        assert!(event.fields["token"] == token);
+        assert!(event.fields["token_claims"] == claims);
+        /* not a JWT */
+        let event = k8s_event("GET", "/", 401, Some(b"Bearer abc"), &ClientInfo::new());
+        assert!(event.fields["token"] == "abc" && event.fields.get("token_claims").is_none());
+        let event = k8s_event(
+            "GET",
+            "/",
+            401,
+            Some(b"Basic YWRtaW46YWRtaW4="),
+            &ClientInfo::new(),
+        );
+        assert!(event.fields["auth_user"] == "admin" && event.fields["auth_password"] == "admin");
+    }
+}
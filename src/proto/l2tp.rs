@@ -0,0 +1,461 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::hash::Hasher;
+
+use log::*;
+use serde::Deserialize;
+use siphasher::sip::SipHasher24;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::synackcookie;
+use crate::Masscanned;
+
+/* RFC 2661 section 3.1 */
+const L2TP_FLAG_TYPE: u16 = 0x8000;
+const L2TP_FLAG_LENGTH: u16 = 0x4000;
+const L2TP_FLAG_SEQUENCE: u16 = 0x0800;
+const L2TP_FLAG_OFFSET: u16 = 0x0200;
+const L2TP_VERSION: u16 = 2;
+
+/* RFC 2661 section 4.1 */
+const L2TP_AVP_MANDATORY: u16 = 0x8000;
+const L2TP_AVP_HIDDEN: u16 = 0x4000;
+
+/* RFC 2661 section 4.4 */
+const L2TP_AVP_MESSAGE_TYPE: u16 = 0;
+const L2TP_AVP_PROTOCOL_VERSION: u16 = 2;
+const L2TP_AVP_FRAMING_CAPABILITIES: u16 = 3;
+const L2TP_AVP_BEARER_CAPABILITIES: u16 = 4;
+const L2TP_AVP_FIRMWARE_REVISION: u16 = 6;
+const L2TP_AVP_HOST_NAME: u16 = 7;
+const L2TP_AVP_VENDOR_NAME: u16 = 8;
+const L2TP_AVP_ASSIGNED_TUNNEL_ID: u16 = 9;
+const L2TP_AVP_RECEIVE_WINDOW_SIZE: u16 = 10;
+const L2TP_AVP_CHALLENGE: u16 = 11;
+
+/* RFC 2661 section 3.2 */
+const L2TP_SCCRQ: u16 = 1;
+const L2TP_SCCRP: u16 = 2;
+const L2TP_SCCCN: u16 = 3;
+const L2TP_STOPCCN: u16 = 4;
+const L2TP_HELLO: u16 = 6;
+
+/* L2TP LNS (on the configured UDP ports): SCCRQ messages get an SCCRP
+ * with our host name, vendor name and tunnel ID, and the SCCCN (as
+ * well as HELLO and StopCCN messages) an acknowledgement (ZLB).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct L2tpConfig {
+    pub ports: Vec<u16>,
+    pub host_name: String,
+    pub vendor_name: String,
+    pub firmware_revision: u16,
+    pub framing_capabilities: u32,
+    pub bearer_capabilities: u32,
+    pub receive_window_size: u16,
+}
+
+impl Default for L2tpConfig {
+    fn default() -> Self {
+        L2tpConfig {
+            ports: vec![1701],
+            host_name: "lns".to_string(),
+            vendor_name: "Cisco Systems, Inc.".to_string(),
+            firmware_revision: 0x1120,
+            /* synchronous and asynchronous */
+            framing_capabilities: 3,
+            bearer_capabilities: 0,
+            receive_window_size: 4,
+        }
+    }
+}
+
+struct L2tpHeader {
+    tunnel_id: u16,
+    session_id: u16,
+    ns: u16,
+    nr: u16,
+}
+
+struct L2tpAvp<'a> {
+    mandatory: bool,
+    hidden: bool,
+    vendor_id: u16,
+    type_: u16,
+    value: &'a [u8],
+}
+
+/* control message header (T, L and S set): header and AVPs */
+fn l2tp_parse_header(data: &[u8]) -> Option<(L2tpHeader, &[u8])> {
+    if data.len() < 12 {
+        return None;
+    }
+    let flags = u16::from_be_bytes([data[0], data[1]]);
+    let required = L2TP_FLAG_TYPE | L2TP_FLAG_LENGTH | L2TP_FLAG_SEQUENCE;
+    if flags & required != required || flags & 0x000f != L2TP_VERSION {
+        return None;
+    }
+    let length = u16::from_be_bytes([data[2], data[3]]) as usize;
+    if length < 12 || length > data.len() {
+        return None;
+    }
+    let mut offset = 12;
+    /* not allowed in control messages, but skipped anyway */
+    if flags & L2TP_FLAG_OFFSET != 0 {
+        let size = u16::from_be_bytes([*data.get(12)?, *data.get(13)?]) as usize;
+        offset += 2 + size;
+    }
+    let u16_at = |o: usize| u16::from_be_bytes([data[o], data[o + 1]]);
+    Some((
+        L2tpHeader {
+            tunnel_id: u16_at(4),
+            session_id: u16_at(6),
+            ns: u16_at(8),
+            nr: u16_at(10),
+        },
+        data.get(offset..length)?,
+    ))
+}
+
+fn l2tp_parse_avps(mut data: &[u8]) -> Option<Vec<L2tpAvp<'_>>> {
+    let mut avps = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 6 {
+            return None;
+        }
+        let flags = u16::from_be_bytes([data[0], data[1]]);
+        let length = (flags & 0x03ff) as usize;
+        if length < 6 || length > data.len() {
+            return None;
+        }
+        avps.push(L2tpAvp {
+            mandatory: flags & L2TP_AVP_MANDATORY != 0,
+            hidden: flags & L2TP_AVP_HIDDEN != 0,
+            vendor_id: u16::from_be_bytes([data[2], data[3]]),
+            type_: u16::from_be_bytes([data[4], data[5]]),
+            value: &data[6..length],
+        });
+        data = &data[length..];
+    }
+    Some(avps)
+}
+
+fn l2tp_avp(mandatory: bool, type_: u16, value: &[u8]) -> Vec<u8> {
+    let mut flags = (6 + value.len()) as u16;
+    if mandatory {
+        flags |= L2TP_AVP_MANDATORY;
+    }
+    let mut avp = flags.to_be_bytes().to_vec();
+    avp.extend_from_slice(&0u16.to_be_bytes());
+    avp.extend_from_slice(&type_.to_be_bytes());
+    avp.extend_from_slice(value);
+    avp
+}
+
+fn l2tp_control(tunnel_id: u16, ns: u16, nr: u16, avps: &[Vec<u8>]) -> Vec<u8> {
+    let length: usize = 12 + avps.iter().map(|a| a.len()).sum::<usize>();
+    let flags = L2TP_FLAG_TYPE | L2TP_FLAG_LENGTH | L2TP_FLAG_SEQUENCE | L2TP_VERSION;
+    let mut msg = flags.to_be_bytes().to_vec();
+    msg.extend_from_slice(&(length as u16).to_be_bytes());
+    msg.extend_from_slice(&tunnel_id.to_be_bytes());
+    msg.extend_from_slice(&0u16.to_be_bytes());
+    msg.extend_from_slice(&ns.to_be_bytes());
+    msg.extend_from_slice(&nr.to_be_bytes());
+    for avp in avps {
+        msg.extend_from_slice(avp);
+    }
+    msg
+}
+
+/* our tunnel ID: the tunnel ID of the peer, masked with a keyed hash of
+ * the client 5-tuple, so that the tunnel ID of the peer is found back
+ * (without state) in the messages that follow */
+fn l2tp_tunnel_mask(client_info: &ClientInfo, key: &[u64; 2]) -> u16 {
+    let tuple = synackcookie::generate(client_info, key).unwrap_or(0);
+    let mut sip = SipHasher24::new_with_keys(key[0], key[1]);
+    sip.write_u32(tuple);
+    sip.write(b"l2tp");
+    sip.finish() as u16
+}
+
+fn l2tp_sccrp(config: &L2tpConfig, peer_tunnel_id: u16, tunnel_id: u16, nr: u16) -> Vec<u8> {
+    let avps = [
+        l2tp_avp(true, L2TP_AVP_MESSAGE_TYPE, &L2TP_SCCRP.to_be_bytes()),
+        l2tp_avp(true, L2TP_AVP_PROTOCOL_VERSION, &[1, 0]),
+        l2tp_avp(
+            true,
+            L2TP_AVP_FRAMING_CAPABILITIES,
+            &config.framing_capabilities.to_be_bytes(),
+        ),
+        l2tp_avp(
+            true,
+            L2TP_AVP_BEARER_CAPABILITIES,
+            &config.bearer_capabilities.to_be_bytes(),
+        ),
+        l2tp_avp(
+            false,
+            L2TP_AVP_FIRMWARE_REVISION,
+            &config.firmware_revision.to_be_bytes(),
+        ),
+        l2tp_avp(true, L2TP_AVP_HOST_NAME, config.host_name.as_bytes()),
+        l2tp_avp(false, L2TP_AVP_VENDOR_NAME, config.vendor_name.as_bytes()),
+        l2tp_avp(true, L2TP_AVP_ASSIGNED_TUNNEL_ID, &tunnel_id.to_be_bytes()),
+        l2tp_avp(
+            true,
+            L2TP_AVP_RECEIVE_WINDOW_SIZE,
+            &config.receive_window_size.to_be_bytes(),
+        ),
+    ];
+    l2tp_control(peer_tunnel_id, 0, nr, &avps)
+}
+
+fn l2tp_u16(value: &[u8]) -> Option<u16> {
+    match value {
+        [a, b] => Some(u16::from_be_bytes([*a, *b])),
+        _ => None,
+    }
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving L2TP data");
+    let config = &masscanned.config.l2tp;
+    let (header, avps) = match l2tp_parse_header(data) {
+        Some(h) => h,
+        None => {
+            info!("L2TP message not handled (not a control message)");
+            return None;
+        }
+    };
+    let avps = match l2tp_parse_avps(avps) {
+        Some(a) => a,
+        None => {
+            info!("L2TP message not handled (invalid AVPs)");
+            return None;
+        }
+    };
+    let mut event = Event::new("l2tp_control", client_info);
+    event.set("tunnel_id", header.tunnel_id);
+    event.set("session_id", header.session_id);
+    event.set("ns", header.ns);
+    event.set("nr", header.nr);
+    /* ZLB: acknowledgement only */
+    if avps.is_empty() {
+        event.log();
+        return None;
+    }
+    let mut message_type = None;
+    let mut peer_tunnel_id = None;
+    let mut hidden = 0;
+    for avp in avps.iter() {
+        /* the value of hidden AVPs is encrypted with the shared
+         * secret */
+        if avp.hidden {
+            hidden += 1;
+            continue;
+        }
+        if avp.vendor_id != 0 {
+            continue;
+        }
+        match avp.type_ {
+            L2TP_AVP_MESSAGE_TYPE => message_type = l2tp_u16(avp.value),
+            L2TP_AVP_ASSIGNED_TUNNEL_ID => {
+                peer_tunnel_id = l2tp_u16(avp.value);
+                event.set("assigned_tunnel_id", peer_tunnel_id);
+            }
+            L2TP_AVP_HOST_NAME => {
+                event.set("host_name", String::from_utf8_lossy(avp.value).to_string());
+            }
+            L2TP_AVP_VENDOR_NAME => {
+                event.set(
+                    "vendor_name",
+                    String::from_utf8_lossy(avp.value).to_string(),
+                );
+            }
+            L2TP_AVP_PROTOCOL_VERSION if avp.value.len() == 2 => {
+                event.set(
+                    "protocol_version",
+                    format!("{}.{}", avp.value[0], avp.value[1]),
+                );
+            }
+            L2TP_AVP_FRAMING_CAPABILITIES | L2TP_AVP_BEARER_CAPABILITIES => {
+                if let [a, b, c, d] = avp.value {
+                    let name = if avp.type_ == L2TP_AVP_FRAMING_CAPABILITIES {
+                        "framing_capabilities"
+                    } else {
+                        "bearer_capabilities"
+                    };
+                    event.set(name, u32::from_be_bytes([*a, *b, *c, *d]));
+                }
+            }
+            L2TP_AVP_CHALLENGE => {
+                event.set("challenge", encode(avp.value, Encoding::Hex));
+            }
+            _ => {}
+        }
+    }
+    /* the message type must be the first AVP */
+    if avps[0].type_ != L2TP_AVP_MESSAGE_TYPE || avps[0].hidden || !avps[0].mandatory {
+        message_type = None;
+    }
+    event.set("message_type", message_type);
+    if hidden > 0 {
+        event.set("hidden_avps", hidden);
+    }
+    event.log();
+    let mask = l2tp_tunnel_mask(client_info, &masscanned.synack_key);
+    let nr = header.ns.wrapping_add(1);
+    match message_type {
+        Some(L2TP_SCCRQ) => {
+            let peer_tunnel_id = match peer_tunnel_id {
+                Some(t) if t != 0 && t != mask => t,
+                _ => {
+                    info!("L2TP SCCRQ not handled (invalid tunnel ID)");
+                    return None;
+                }
+            };
+            warn!("L2TP SCCRQ (tunnel {})", peer_tunnel_id);
+            Some(l2tp_sccrp(
+                config,
+                peer_tunnel_id,
+                peer_tunnel_id ^ mask,
+                nr,
+            ))
+        }
+        /* our next Ns is the Nr of the peer */
+        Some(L2TP_SCCCN | L2TP_HELLO | L2TP_STOPCCN) if header.tunnel_id != 0 => {
+            Some(l2tp_control(header.tunnel_id ^ mask, header.nr, nr, &[]))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn instance() -> Masscanned<'static> {
+        Masscanned {
+            synack_key: [0x1e9219e0b0e0b44c, 0x9e460bcddf4eaac9],
+            ..crate::proto::test_util::masscanned(Config::default())
+        }
+    }
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Udp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.src = Some(1701);
+        client_info.port.dst = Some(1701);
+        client_info
+    }
+
+    #[test]
+    fn test_l2tp_avp() {
+        assert!(
+            l2tp_avp(true, L2TP_AVP_MESSAGE_TYPE, &L2TP_SCCRQ.to_be_bytes())
+                == b"\x80\x08\x00\x00\x00\x00\x00\x01"
+        );
+        assert!(l2tp_avp(false, L2TP_AVP_VENDOR_NAME, b"xelerance.com")[..2] == [0x00, 0x13]);
+        let mut data = l2tp_avp(true, L2TP_AVP_HOST_NAME, b"client");
+        /* hidden, vendor-specific */
+        data.extend_from_slice(b"\xc0\x0a\x01\x37\x00\x09\xde\xad\xbe\xef");
+        let avps = l2tp_parse_avps(&data).unwrap();
+        assert!(avps.len() == 2);
+        assert!(avps[0].mandatory && !avps[0].hidden);
+        assert!(avps[0].type_ == L2TP_AVP_HOST_NAME && avps[0].value == b"client");
+        assert!(avps[1].hidden && avps[1].vendor_id == 0x137 && avps[1].value.len() == 4);
+        /* truncated */
+        assert!(l2tp_parse_avps(&data[..data.len() - 1]).is_none());
+        assert!(l2tp_parse_avps(b"\x80\x04\x00\x00\x00\x00").is_none());
+    }
+
+    #[test]
+    fn test_l2tp_tunnel() {
+        let masscanned = instance();
+        let mut client_info = client();
+        /* SCCRQ (xl2tpd), with a hidden AVP */
+        let sccrq = l2tp_control(
+            0,
+            0,
+            0,
+            &[
+                l2tp_avp(true, L2TP_AVP_MESSAGE_TYPE, &L2TP_SCCRQ.to_be_bytes()),
+                l2tp_avp(true, L2TP_AVP_PROTOCOL_VERSION, &[1, 0]),
+                l2tp_avp(true, L2TP_AVP_FRAMING_CAPABILITIES, &3u32.to_be_bytes()),
+                l2tp_avp(true, L2TP_AVP_HOST_NAME, b"scanner"),
+                l2tp_avp(false, L2TP_AVP_VENDOR_NAME, b"xelerance.com"),
+                l2tp_avp(true, L2TP_AVP_ASSIGNED_TUNNEL_ID, &0x1234u16.to_be_bytes()),
+                b"\xc0\x0a\x00\x00\x00\x0c\x01\x02\x03\x04".to_vec(),
+            ],
+        );
+        let sccrp = repl(&sccrq, &masscanned, &mut client_info).expect("no SCCRP");
+        let (header, avps) = l2tp_parse_header(&sccrp).unwrap();
+        assert!(header.tunnel_id == 0x1234 && header.session_id == 0);
+        assert!(header.ns == 0 && header.nr == 1);
+        let avps = l2tp_parse_avps(avps).unwrap();
+        assert!(avps[0].type_ == L2TP_AVP_MESSAGE_TYPE);
+        assert!(avps[0].value == L2TP_SCCRP.to_be_bytes());
+        let value = |t: u16| avps.iter().find(|a| a.type_ == t).unwrap().value;
+        assert!(value(L2TP_AVP_HOST_NAME) == b"lns");
+        assert!(value(L2TP_AVP_VENDOR_NAME) == b"Cisco Systems, Inc.");
+        let tunnel_id = l2tp_u16(value(L2TP_AVP_ASSIGNED_TUNNEL_ID)).unwrap();
+        assert!(tunnel_id != 0 && tunnel_id != 0x1234);
+        /* SCCCN: acknowledged, to the tunnel of the peer */
+        let scccn = l2tp_control(
+            tunnel_id,
+            1,
+            1,
+            &[l2tp_avp(
+                true,
+                L2TP_AVP_MESSAGE_TYPE,
+                &L2TP_SCCCN.to_be_bytes(),
+            )],
+        );
+        let zlb = repl(&scccn, &masscanned, &mut client_info).expect("no ZLB");
+        assert!(zlb.len() == 12);
+        let (header, avps) = l2tp_parse_header(&zlb).unwrap();
+        assert!(avps.is_empty());
+        assert!(header.tunnel_id == 0x1234);
+        assert!(header.ns == 1 && header.nr == 2);
+        /* ZLB from the peer: not answered */
+        assert!(repl(
+            &l2tp_control(tunnel_id, 2, 1, &[]),
+            &masscanned,
+            &mut client_info
+        )
+        .is_none());
+        /* data messages, SCCRQ without a tunnel ID */
+        assert!(repl(b"\x00\x02\x12\x34\x00\x01", &masscanned, &mut client_info).is_none());
+        let sccrq = l2tp_control(
+            0,
+            0,
+            0,
+            &[l2tp_avp(
+                true,
+                L2TP_AVP_MESSAGE_TYPE,
+                &L2TP_SCCRQ.to_be_bytes(),
+            )],
+        );
+        assert!(repl(&sccrq, &masscanned, &mut client_info).is_none());
+    }
+}
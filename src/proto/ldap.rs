@@ -0,0 +1,506 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::ber::{
+    ber_decode_integer, ber_integer, ber_integer_value, ber_tlv, BerReader, BER_BOOLEAN,
+    BER_ENUMERATED, BER_INTEGER, BER_OCTET_STRING, BER_SEQUENCE, BER_SET,
+};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* RFC 4511: protocol operations */
+const LDAP_BIND_REQUEST: u8 = 0x60;
+const LDAP_BIND_RESPONSE: u8 = 0x61;
+const LDAP_UNBIND_REQUEST: u8 = 0x42;
+const LDAP_SEARCH_REQUEST: u8 = 0x63;
+const LDAP_SEARCH_RES_ENTRY: u8 = 0x64;
+const LDAP_SEARCH_RES_DONE: u8 = 0x65;
+const LDAP_MODIFY_REQUEST: u8 = 0x66;
+const LDAP_ADD_REQUEST: u8 = 0x68;
+const LDAP_DEL_REQUEST: u8 = 0x4a;
+const LDAP_MODIFY_DN_REQUEST: u8 = 0x6c;
+const LDAP_COMPARE_REQUEST: u8 = 0x6e;
+const LDAP_ABANDON_REQUEST: u8 = 0x50;
+const LDAP_EXTENDED_REQUEST: u8 = 0x77;
+const LDAP_EXTENDED_RESPONSE: u8 = 0x78;
+
+/* authentication choices of bind requests */
+const LDAP_AUTH_SIMPLE: u8 = 0x80;
+const LDAP_AUTH_SASL: u8 = 0xa3;
+
+/* result codes */
+const LDAP_SUCCESS: i64 = 0;
+const LDAP_PROTOCOL_ERROR: i64 = 2;
+const LDAP_AUTH_METHOD_NOT_SUPPORTED: i64 = 7;
+const LDAP_NO_SUCH_OBJECT: i64 = 32;
+const LDAP_INVALID_CREDENTIALS: i64 = 49;
+const LDAP_INSUFFICIENT_ACCESS_RIGHTS: i64 = 50;
+
+const LDAP_SCOPE_BASE: i64 = 0;
+
+/* size of a message kept between two segments */
+const LDAP_MAX_MESSAGE: usize = 65536;
+
+/* LDAP directory (on the TCP ports): anonymous binds succeed, other
+ * simple binds are refused unless login is set; the root DSE can be
+ * searched, and every other request gets an error.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LdapConfig {
+    pub ports: Vec<u16>,
+    pub login: bool,
+    pub naming_contexts: Vec<String>,
+    pub supported_ldap_version: Vec<u32>,
+    pub vendor_name: String,
+    pub dns_host_name: String,
+}
+
+impl Default for LdapConfig {
+    fn default() -> Self {
+        LdapConfig {
+            ports: vec![389, 3268],
+            login: false,
+            naming_contexts: vec![
+                "DC=corp,DC=local".to_string(),
+                "CN=Configuration,DC=corp,DC=local".to_string(),
+            ],
+            supported_ldap_version: vec![3, 2],
+            vendor_name: "Microsoft Corporation".to_string(),
+            dns_host_name: "dc01.corp.local".to_string(),
+        }
+    }
+}
+
+/* state of a flow: beginning of a message not received entirely yet */
+#[derive(Default)]
+pub struct LdapState {
+    buffer: Vec<u8>,
+}
+
+fn ldap_string(value: &[u8]) -> String {
+    String::from_utf8_lossy(value).to_string()
+}
+
+/* LDAPResult of the responses */
+fn ldap_result(op: u8, code: i64, diagnostic: &str) -> Vec<u8> {
+    let mut result = ber_tlv(BER_ENUMERATED, &ber_integer_value(code));
+    result.extend(ber_tlv(BER_OCTET_STRING, b""));
+    result.extend(ber_tlv(BER_OCTET_STRING, diagnostic.as_bytes()));
+    ber_tlv(op, &result)
+}
+
+fn ldap_message(message_id: i64, op: Vec<u8>) -> Vec<u8> {
+    let mut message = ber_integer(message_id);
+    message.extend(op);
+    ber_tlv(BER_SEQUENCE, &message)
+}
+
+/* entry of the root DSE, with the attributes requested (all of them
+ * for none, "*" or "+") */
+fn ldap_root_dse(config: &LdapConfig, requested: &[String]) -> Vec<u8> {
+    let attributes: Vec<(&str, Vec<String>)> = vec![
+        ("namingContexts", config.naming_contexts.clone()),
+        (
+            "supportedLDAPVersion",
+            config
+                .supported_ldap_version
+                .iter()
+                .map(|v| v.to_string())
+                .collect(),
+        ),
+        ("vendorName", vec![config.vendor_name.clone()]),
+        ("dnsHostName", vec![config.dns_host_name.clone()]),
+    ];
+    let all = requested.is_empty() || requested.iter().any(|a| a == "*" || a == "+");
+    let mut list = Vec::new();
+    for (name, values) in attributes.iter() {
+        if values.iter().all(|v| v.is_empty())
+            || !(all || requested.iter().any(|a| a.eq_ignore_ascii_case(name)))
+        {
+            continue;
+        }
+        let vals: Vec<u8> = values
+            .iter()
+            .flat_map(|v| ber_tlv(BER_OCTET_STRING, v.as_bytes()))
+            .collect();
+        let mut attribute = ber_tlv(BER_OCTET_STRING, name.as_bytes());
+        attribute.extend(ber_tlv(BER_SET, &vals));
+        list.extend(ber_tlv(BER_SEQUENCE, &attribute));
+    }
+    let mut entry = ber_tlv(BER_OCTET_STRING, b"");
+    entry.extend(ber_tlv(BER_SEQUENCE, &list));
+    ber_tlv(LDAP_SEARCH_RES_ENTRY, &entry)
+}
+
+fn ldap_bind(
+    message_id: i64,
+    request: &[u8],
+    config: &LdapConfig,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    let mut reader = BerReader::new(request);
+    let version = reader.integer()?;
+    let dn = ldap_string(reader.expect(BER_OCTET_STRING)?);
+    let (tag, credentials) = reader.read()?;
+    let mut event = Event::new("ldap_bind", client_info);
+    event.set("version", version);
+    event.set("dn", dn.as_str());
+    let code = match tag {
+        LDAP_AUTH_SIMPLE => {
+            let password = ldap_string(credentials);
+            warn!("LDAP bind: {}:{}", dn, password);
+            let code = if (dn.is_empty() && password.is_empty()) || config.login {
+                LDAP_SUCCESS
+            } else {
+                LDAP_INVALID_CREDENTIALS
+            };
+            event.set("password", password);
+            event.set("success", code == LDAP_SUCCESS);
+            code
+        }
+        LDAP_AUTH_SASL => {
+            let mechanism = BerReader::new(credentials).expect(BER_OCTET_STRING)?;
+            event.set("mechanism", ldap_string(mechanism));
+            LDAP_AUTH_METHOD_NOT_SUPPORTED
+        }
+        _ => LDAP_AUTH_METHOD_NOT_SUPPORTED,
+    };
+    event.log();
+    let diagnostic = match code {
+        LDAP_INVALID_CREDENTIALS => {
+            "80090308: LdapErr: DSID-0C090447, comment: AcceptSecurityContext error, data 52e, v3839"
+        }
+        _ => "",
+    };
+    Some(ldap_message(
+        message_id,
+        ldap_result(LDAP_BIND_RESPONSE, code, diagnostic),
+    ))
+}
+
+fn ldap_search(
+    message_id: i64,
+    request: &[u8],
+    config: &LdapConfig,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    let mut reader = BerReader::new(request);
+    let base = ldap_string(reader.expect(BER_OCTET_STRING)?);
+    let scope = ber_decode_integer(reader.expect(BER_ENUMERATED)?)?;
+    /* derefAliases, sizeLimit, timeLimit, typesOnly, filter */
+    reader.expect(BER_ENUMERATED)?;
+    reader.expect(BER_INTEGER)?;
+    reader.expect(BER_INTEGER)?;
+    reader.expect(BER_BOOLEAN)?;
+    reader.read()?;
+    let mut requested = Vec::new();
+    let mut attributes = BerReader::new(reader.expect(BER_SEQUENCE)?);
+    while let Some(a) = attributes.expect(BER_OCTET_STRING) {
+        requested.push(ldap_string(a));
+    }
+    let mut event = Event::new("ldap_request", client_info);
+    event.set("operation", "search");
+    event.set("base", base.as_str());
+    event.set("scope", scope);
+    event.set("attributes", requested.clone());
+    event.log();
+    let mut repl = Vec::new();
+    let code = if base.is_empty() && scope == LDAP_SCOPE_BASE {
+        repl.extend(ldap_message(message_id, ldap_root_dse(config, &requested)));
+        LDAP_SUCCESS
+    } else {
+        LDAP_NO_SUCH_OBJECT
+    };
+    repl.extend(ldap_message(
+        message_id,
+        ldap_result(LDAP_SEARCH_RES_DONE, code, ""),
+    ));
+    Some(repl)
+}
+
+/* answer to a message (None for those that get no answer) */
+fn ldap_answer(
+    message: &[u8],
+    config: &LdapConfig,
+    client_info: &mut ClientInfo,
+) -> Option<Vec<u8>> {
+    let mut reader = BerReader::new(message);
+    let message_id = reader.integer()?;
+    let (op, request) = reader.read()?;
+    let operation = match op {
+        LDAP_BIND_REQUEST => return ldap_bind(message_id, request, config, client_info),
+        LDAP_SEARCH_REQUEST => return ldap_search(message_id, request, config, client_info),
+        LDAP_UNBIND_REQUEST => {
+            client_info.close = true;
+            "unbind"
+        }
+        LDAP_ABANDON_REQUEST => "abandon",
+        LDAP_MODIFY_REQUEST => "modify",
+        LDAP_ADD_REQUEST => "add",
+        LDAP_DEL_REQUEST => "delete",
+        LDAP_MODIFY_DN_REQUEST => "modify_dn",
+        LDAP_COMPARE_REQUEST => "compare",
+        LDAP_EXTENDED_REQUEST => "extended",
+        _ => "unknown",
+    };
+    let mut event = Event::new("ldap_request", client_info);
+    event.set("operation", operation);
+    event.set("op", op);
+    let mut fields = BerReader::new(request);
+    match op {
+        /* entry (or extended operation) the request is about */
+        LDAP_MODIFY_REQUEST | LDAP_ADD_REQUEST | LDAP_MODIFY_DN_REQUEST | LDAP_COMPARE_REQUEST => {
+            if let Some(dn) = fields.expect(BER_OCTET_STRING) {
+                event.set("dn", ldap_string(dn));
+            }
+        }
+        LDAP_DEL_REQUEST => event.set("dn", ldap_string(request)),
+        LDAP_EXTENDED_REQUEST => {
+            if let Some(oid) = fields.expect(0x80) {
+                event.set("oid", ldap_string(oid));
+            }
+        }
+        _ => {}
+    }
+    event.log();
+    let (response, code) = match op {
+        LDAP_UNBIND_REQUEST | LDAP_ABANDON_REQUEST => return None,
+        LDAP_COMPARE_REQUEST => (op + 1, LDAP_NO_SUCH_OBJECT),
+        LDAP_MODIFY_REQUEST | LDAP_ADD_REQUEST | LDAP_MODIFY_DN_REQUEST => {
+            (op + 1, LDAP_INSUFFICIENT_ACCESS_RIGHTS)
+        }
+        /* primitive [APPLICATION 10], response [APPLICATION 11] */
+        LDAP_DEL_REQUEST => (0x6b, LDAP_INSUFFICIENT_ACCESS_RIGHTS),
+        _ => (LDAP_EXTENDED_RESPONSE, LDAP_PROTOCOL_ERROR),
+    };
+    Some(ldap_message(message_id, ldap_result(response, code, "")))
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving LDAP data");
+    let config = &masscanned.config.ldap;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Ldap(s)) => s,
+        _ => LdapState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while !state.buffer.is_empty() {
+        if state.buffer[0] != BER_SEQUENCE {
+            info!("LDAP: not a message - dropped");
+            client_info.close = true;
+            break;
+        }
+        let mut reader = BerReader::new(&state.buffer);
+        let message = match reader.read() {
+            Some((_, message)) => message.to_vec(),
+            None => break,
+        };
+        let len = state.buffer.len() - reader.remaining();
+        state.buffer.drain(..len);
+        if let Some(repl) = ldap_answer(&message, config, client_info) {
+            repl_data.extend(repl);
+        }
+        if client_info.close {
+            break;
+        }
+    }
+    if state.buffer.len() > LDAP_MAX_MESSAGE {
+        info!("LDAP message too large - dropped");
+        client_info.close = true;
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Ldap(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending LDAP data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    /* ldapsearch -x -s base -b "": anonymous bind, search, unbind */
+    const LDAPSEARCH_BIND: &[u8] = b"\x30\x0c\x02\x01\x01\x60\x07\x02\x01\x03\x04\x00\x80\x00";
+    const LDAPSEARCH_SEARCH: &[u8] = b"\x30\x25\x02\x01\x02\x63\x20\x04\x00\x0a\x01\x00\x0a\x01\x00\x02\x01\x00\x02\x01\x00\x01\x01\x00\x87\x0bobjectclass\x30\x00";
+    const LDAPSEARCH_UNBIND: &[u8] = b"\x30\x05\x02\x01\x03\x42\x00";
+
+    /* message ID, operation and content of the messages */
+    fn messages(data: &[u8]) -> Vec<(i64, u8, Vec<u8>)> {
+        let mut reader = BerReader::new(data);
+        let mut messages = Vec::new();
+        while !reader.is_empty() {
+            let mut message = BerReader::new(reader.expect(BER_SEQUENCE).unwrap());
+            let id = message.integer().unwrap();
+            let (op, content) = message.read().unwrap();
+            messages.push((id, op, content.to_vec()));
+        }
+        messages
+    }
+
+    fn result_code(content: &[u8]) -> i64 {
+        ber_decode_integer(BerReader::new(content).expect(BER_ENUMERATED).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_ldapsearch() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(389);
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = crate::proto::dispatch(
+            LDAPSEARCH_BIND,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        let m = messages(&repl_data);
+        assert!(m.len() == 1 && m[0].0 == 1 && m[0].1 == LDAP_BIND_RESPONSE);
+        assert!(result_code(&m[0].2) == LDAP_SUCCESS);
+        /* search, split across two segments */
+        assert!(crate::proto::dispatch(
+            &LDAPSEARCH_SEARCH[..20],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl_data = crate::proto::dispatch(
+            &LDAPSEARCH_SEARCH[20..],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        let m = messages(&repl_data);
+        assert!(m.len() == 2 && m.iter().all(|(id, _, _)| *id == 2));
+        assert!(m[0].1 == LDAP_SEARCH_RES_ENTRY);
+        let mut entry = BerReader::new(&m[0].2);
+        assert!(entry.expect(BER_OCTET_STRING) == Some(&b""[..]));
+        let mut attributes = BerReader::new(entry.expect(BER_SEQUENCE).unwrap());
+        let mut names = Vec::new();
+        while let Some(a) = attributes.expect(BER_SEQUENCE) {
+            let mut a = BerReader::new(a);
+            let name = ldap_string(a.expect(BER_OCTET_STRING).unwrap());
+            let mut vals = BerReader::new(a.expect(BER_SET).unwrap());
+            if name == "supportedLDAPVersion" {
+                assert!(vals.expect(BER_OCTET_STRING) == Some(&b"3"[..]));
+                assert!(vals.expect(BER_OCTET_STRING) == Some(&b"2"[..]));
+            }
+            names.push(name);
+        }
+        assert!(
+            names
+                == [
+                    "namingContexts",
+                    "supportedLDAPVersion",
+                    "vendorName",
+                    "dnsHostName"
+                ]
+        );
+        assert!(m[1].1 == LDAP_SEARCH_RES_DONE && result_code(&m[1].2) == LDAP_SUCCESS);
+        /* attributes requested, other bases */
+        let root_dse = ldap_root_dse(&masscanned.config.ldap, &["dnshostname".to_string()]);
+        assert!(root_dse.windows(15).any(|w| w == b"dc01.corp.local"));
+        assert!(!root_dse.windows(14).any(|w| w == b"namingContexts"));
+        let mut search = LDAPSEARCH_SEARCH.to_vec();
+        search[4] = 7;
+        search[11] = 2;
+        let repl_data =
+            crate::proto::dispatch(&search, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let m = messages(&repl_data);
+        assert!(m.len() == 1 && m[0].0 == 7 && m[0].1 == LDAP_SEARCH_RES_DONE);
+        assert!(result_code(&m[0].2) == LDAP_NO_SUCH_OBJECT);
+        assert!(crate::proto::dispatch(
+            LDAPSEARCH_UNBIND,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_ldap_bind() {
+        /* ldapsearch -x -D cn=admin,dc=example,dc=com -w secret, with
+         * short and long form lengths */
+        let short = b"\x30\x2c\x02\x01\x01\x60\x27\x02\x01\x03\x04\x1acn=admin,dc=example,dc=com\x80\x06secret";
+        let long = b"\x30\x81\x2e\x02\x01\x01\x60\x82\x00\x27\x02\x01\x03\x04\x1acn=admin,dc=example,dc=com\x80\x06secret";
+        for (login, code) in [(false, LDAP_INVALID_CREDENTIALS), (true, LDAP_SUCCESS)].iter() {
+            let masscanned = masscanned(Config {
+                ldap: LdapConfig {
+                    login: *login,
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+            for request in [&short[..], &long[..]].iter() {
+                let mut client_info = ClientInfo::new();
+                client_info.port.dst = Some(389);
+                let repl_data = repl(request, &masscanned, &mut client_info, None).unwrap();
+                let m = messages(&repl_data);
+                assert!(m[0].0 == 1 && m[0].1 == LDAP_BIND_RESPONSE);
+                assert!(result_code(&m[0].2) == *code);
+            }
+        }
+        /* writes are refused, unknown operations are errors */
+        let masscanned = self::masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        for (request, op, code) in [
+            (
+                &b"\x30\x0c\x02\x01\x05\x4a\x07cn=test"[..],
+                0x6b,
+                LDAP_INSUFFICIENT_ACCESS_RIGHTS,
+            ),
+            (
+                b"\x30\x1d\x02\x01\x06\x77\x18\x80\x161.3.6.1.4.1.1466.20037",
+                LDAP_EXTENDED_RESPONSE,
+                LDAP_PROTOCOL_ERROR,
+            ),
+        ]
+        .iter()
+        {
+            let m = messages(&repl(request, &masscanned, &mut client_info, None).unwrap());
+            assert!(m[0].1 == *op && result_code(&m[0].2) == *code);
+        }
+        /* not LDAP */
+        assert!(repl(b"\x16\x03\x01\x00", &masscanned, &mut client_info, None).is_none());
+        assert!(client_info.close);
+    }
+}
@@ -0,0 +1,145 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+
+/* maximum size of a partial line kept between two segments */
+pub const LINE_MAX_BUFFER: usize = 8192;
+
+/* Lines of line-based protocols (e.g., FTP), which may arrive several in
+ * one segment or split across segments: the end of the data received
+ * that does not hold an entire line yet is kept for the next segment.
+ **/
+#[derive(Default)]
+pub struct LineBuffer {
+    buffer: Vec<u8>,
+}
+
+impl LineBuffer {
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /* next complete line, without the line ending (LF or CRLF) */
+    pub fn line(&mut self) -> Option<Vec<u8>> {
+        let len = match self.buffer.iter().position(|&c| c == b'\n') {
+            Some(len) => len,
+            None => {
+                if self.buffer.len() > LINE_MAX_BUFFER {
+                    info!(
+                        "line too long ({} bytes received) - dropped",
+                        self.buffer.len()
+                    );
+                    self.buffer.clear();
+                }
+                return None;
+            }
+        };
+        let mut line: Vec<u8> = self.buffer.drain(..len + 1).collect();
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Some(line)
+    }
+
+    /* next len bytes (e.g., an IMAP literal), when received - len cannot
+     * exceed LINE_MAX_BUFFER, since that is what is kept */
+    pub fn take(&mut self, len: usize) -> Option<Vec<u8>> {
+        if self.buffer.len() < len {
+            return None;
+        }
+        Some(self.buffer.drain(..len).collect())
+    }
+
+    /* drop at most len bytes, returns the number of bytes dropped */
+    pub fn discard(&mut self, len: usize) -> usize {
+        let len = std::cmp::min(len, self.buffer.len());
+        self.buffer.drain(..len);
+        len
+    }
+
+    /* complete lines received so far */
+    pub fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.extend(data);
+        let mut lines = Vec::new();
+        while let Some(line) = self.line() {
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+/* command (uppercase) and argument of a command line (e.g., "USER
+ * anonymous") */
+pub fn line_command(line: &[u8]) -> (String, String) {
+    let line = String::from_utf8_lossy(line);
+    match line.find(' ') {
+        Some(i) => (line[..i].to_ascii_uppercase(), line[i + 1..].to_string()),
+        None => (line.to_ascii_uppercase(), String::new()),
+    }
+}
+
+/* SASL PLAIN credentials (RFC 4616): authorization identity, user,
+ * password */
+pub fn sasl_plain(creds: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let mut parts = creds.splitn(3, |&c| c == 0);
+    Some((parts.next()?, parts.next()?, parts.next()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_buffer() {
+        let mut lines = LineBuffer::default();
+        assert!(
+            lines.push(b"USER anonymous\r\nPASS a@b\nQU")
+                == [b"USER anonymous".to_vec(), b"PASS a@b".to_vec()]
+        );
+        assert!(lines.push(b"IT\r").is_empty());
+        assert!(lines.push(b"\n\r\n") == [b"QUIT".to_vec(), b"".to_vec()]);
+        /* overlong lines are dropped */
+        assert!(lines.push(&[b'a'; LINE_MAX_BUFFER + 1]).is_empty());
+        assert!(lines.push(b"b\r\n") == [b"b".to_vec()]);
+        /* lines and raw data */
+        lines.extend(b"a {5}\r\nb\r\nc\r\n");
+        assert!(lines.line() == Some(b"a {5}".to_vec()));
+        assert!(lines.take(7).is_none());
+        assert!(lines.take(5) == Some(b"b\r\nc\r".to_vec()));
+        assert!(lines.line() == Some(b"".to_vec()));
+        lines.extend(b"abc");
+        assert!(lines.discard(5) == 3);
+        assert!(lines.line().is_none());
+    }
+
+    #[test]
+    fn test_sasl_plain() {
+        assert!(
+            sasl_plain(b"\x00user\x00pass\x00word")
+                == Some((&b""[..], &b"user"[..], &b"pass\x00word"[..]))
+        );
+        assert!(sasl_plain(b"admin\x00user").is_none());
+    }
+
+    #[test]
+    fn test_line_command() {
+        assert!(line_command(b"user anonymous") == ("USER".to_string(), "anonymous".to_string()));
+        assert!(line_command(b"PASS  two words") == ("PASS".to_string(), " two words".to_string()));
+        assert!(line_command(b"Quit") == ("QUIT".to_string(), String::new()));
+    }
+}
@@ -0,0 +1,931 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use log::*;
+use pnet::util::MacAddr;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::dns::{
+    be16, dns_dotted, dns_labels, DNS_CLASS_IN, DNS_TYPE_A, DNS_TYPE_AAAA, DNS_TYPE_ANY,
+    DNS_TYPE_PTR, DNS_TYPE_SRV, DNS_TYPE_TXT,
+};
+use crate::Masscanned;
+
+/* RFC 6762 */
+pub const MDNS_PORT: u16 = 5353;
+const MDNS_GROUP_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+const MDNS_MAC_V4: MacAddr = MacAddr(0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb);
+const MDNS_MAC_V6: MacAddr = MacAddr(0x33, 0x33, 0x00, 0x00, 0x00, 0xfb);
+
+/* section 10.2: cache-flush bit of unique records, section 5.4:
+ * unicast-response bit of questions */
+const MDNS_CLASS_FLUSH: u16 = 0x8000;
+const MDNS_CLASS_MASK: u16 = 0x7fff;
+/* section 6.7: maximum TTL of answers to legacy (one-shot) queries */
+const MDNS_LEGACY_TTL: u32 = 10;
+
+/* RFC 6763 section 9 */
+const MDNS_SERVICES: [&str; 4] = ["_services", "_dns-sd", "_udp", "local"];
+const MDNS_DOMAIN: &str = "local";
+
+/* service advertised over mDNS / DNS-SD (RFC 6763) */
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MdnsService {
+    /* instance name (e.g., "HP LaserJet 400") */
+    pub name: String,
+    /* service type (e.g., "_ipp._tcp") */
+    pub service: String,
+    pub port: u16,
+    /* TXT record ("key=value" strings) */
+    pub txt: Vec<String>,
+}
+
+impl Default for MdnsService {
+    fn default() -> Self {
+        MdnsService {
+            name: "masscanned".to_string(),
+            service: "_http._tcp".to_string(),
+            port: 80,
+            txt: vec!["path=/".to_string()],
+        }
+    }
+}
+
+/* mDNS responder (on 224.0.0.251 and ff02::fb, port 5353): queries for
+ * the advertised services are answered with PTR, SRV, TXT and A/AAAA
+ * records (our addresses) - nothing is answered without a service.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MdnsConfig {
+    /* host name (in the .local domain) of the services */
+    pub hostname: String,
+    pub ttl: u32,
+    pub service: Vec<MdnsService>,
+}
+
+impl Default for MdnsConfig {
+    fn default() -> Self {
+        MdnsConfig {
+            hostname: "masscanned".to_string(),
+            ttl: 120,
+            service: Vec::new(),
+        }
+    }
+}
+
+fn mdns_invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("mdns: {}", msg))
+}
+
+fn mdns_check_label(label: &str) -> Result<(), io::Error> {
+    if label.is_empty() || label.len() > 63 {
+        return Err(mdns_invalid(&format!("invalid label {:?}", label)));
+    }
+    Ok(())
+}
+
+impl MdnsConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        mdns_check_label(&self.hostname)?;
+        if self.hostname.contains('.') {
+            return Err(mdns_invalid("invalid hostname (dot found)"));
+        }
+        for service in self.service.iter() {
+            mdns_check_label(&service.name)?;
+            let labels: Vec<&str> = service.service.split('.').collect();
+            if labels.len() != 2
+                || !labels[0].starts_with('_')
+                || !(labels[1] == "_tcp" || labels[1] == "_udp")
+            {
+                return Err(mdns_invalid(&format!(
+                    "invalid service type {:?}",
+                    service.service
+                )));
+            }
+            mdns_check_label(labels[0])?;
+            if service.txt.iter().any(|t| t.is_empty() || t.len() > 255) {
+                return Err(mdns_invalid("invalid TXT string"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn labels(name: &[&str]) -> Vec<Vec<u8>> {
+    name.iter().map(|l| l.as_bytes().to_vec()).collect()
+}
+
+fn same_name(a: &[Vec<u8>], b: &[Vec<u8>]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b.iter())
+            .all(|(x, y)| x.eq_ignore_ascii_case(y))
+}
+
+fn lowercase(name: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    name.iter().map(|l| l.to_ascii_lowercase()).collect()
+}
+
+/* "_ipp._tcp" -> _ipp._tcp.local */
+fn service_type(service: &MdnsService) -> Vec<Vec<u8>> {
+    let mut name: Vec<&str> = service.service.split('.').collect();
+    name.push(MDNS_DOMAIN);
+    labels(&name)
+}
+
+fn service_instance(service: &MdnsService) -> Vec<Vec<u8>> {
+    let mut name = vec![service.name.as_bytes().to_vec()];
+    name.extend(service_type(service));
+    name
+}
+
+fn host_name(config: &MdnsConfig) -> Vec<Vec<u8>> {
+    labels(&[&config.hostname, MDNS_DOMAIN])
+}
+
+#[derive(Clone, PartialEq)]
+enum MdnsData {
+    Ptr(Vec<Vec<u8>>),
+    Srv(u16, Vec<Vec<u8>>),
+    Txt(Vec<String>),
+    Address(IpAddr),
+}
+
+#[derive(Clone, PartialEq)]
+struct MdnsRecord {
+    name: Vec<Vec<u8>>,
+    rtype: u16,
+    /* unique records (not shared, e.g., PTR) get the cache-flush bit */
+    unique: bool,
+    data: MdnsData,
+}
+
+impl MdnsData {
+    /* RDATA without compression, names in lower case: to compare
+     * records with known answers */
+    fn canonical(&self) -> Vec<u8> {
+        let mut w = MdnsWriter::default();
+        match self {
+            MdnsData::Ptr(name) => w.name_uncompressed(&lowercase(name)),
+            MdnsData::Srv(port, target) => {
+                w.data.extend_from_slice(&[0, 0, 0, 0]);
+                w.data.extend_from_slice(&port.to_be_bytes());
+                w.name_uncompressed(&lowercase(target));
+            }
+            data => w.rdata(data),
+        }
+        w.data
+    }
+}
+
+/* records known by the querier (RFC 6762 section 7.1) */
+struct MdnsKnownAnswer {
+    name: Vec<Vec<u8>>,
+    rtype: u16,
+    ttl: u32,
+    /* canonical RDATA (see MdnsData::canonical) */
+    rdata: Vec<u8>,
+}
+
+struct MdnsQuestion {
+    name: Vec<Vec<u8>>,
+    qtype: u16,
+    qclass: u16,
+    unicast: bool,
+}
+
+struct MdnsQuery {
+    id: u16,
+    questions: Vec<MdnsQuestion>,
+    known: Vec<MdnsKnownAnswer>,
+}
+
+/* canonical RDATA of a record received, names are decompressed */
+fn mdns_canonical(data: &[u8], rtype: u16, offset: usize, end: usize) -> Option<Vec<u8>> {
+    let mut w = MdnsWriter::default();
+    match rtype {
+        DNS_TYPE_PTR => {
+            let (name, _) = dns_labels(data, offset)?;
+            w.name_uncompressed(&lowercase(&name));
+        }
+        DNS_TYPE_SRV => {
+            w.data.extend_from_slice(data.get(offset..offset + 6)?);
+            let (target, _) = dns_labels(data, offset + 6)?;
+            w.name_uncompressed(&lowercase(&target));
+        }
+        _ => w.data.extend_from_slice(data.get(offset..end)?),
+    }
+    Some(w.data)
+}
+
+fn mdns_parse_query(data: &[u8]) -> Option<MdnsQuery> {
+    let id = be16(data, 0)?;
+    let flags = be16(data, 2)?;
+    /* section 18.2 and 18.3: responses, and queries with another
+     * opcode, are ignored */
+    if flags & 0xf800 != 0 {
+        return None;
+    }
+    let qdcount = be16(data, 4)?;
+    let ancount = be16(data, 6)?;
+    let mut query = MdnsQuery {
+        id,
+        questions: Vec::new(),
+        known: Vec::new(),
+    };
+    let mut offset = 12;
+    for _ in 0..qdcount {
+        let (name, end) = dns_labels(data, offset)?;
+        let qclass = be16(data, end + 2)?;
+        query.questions.push(MdnsQuestion {
+            name,
+            qtype: be16(data, end)?,
+            qclass: qclass & MDNS_CLASS_MASK,
+            unicast: qclass & MDNS_CLASS_FLUSH != 0,
+        });
+        offset = end + 4;
+    }
+    for _ in 0..ancount {
+        let (name, end) = dns_labels(data, offset)?;
+        let rtype = be16(data, end)?;
+        let ttl = ((be16(data, end + 4)? as u32) << 16) | be16(data, end + 6)? as u32;
+        let rdlength = be16(data, end + 8)? as usize;
+        let rdata_end = end + 10 + rdlength;
+        if rdata_end > data.len() {
+            return None;
+        }
+        if let Some(rdata) = mdns_canonical(data, rtype, end + 10, rdata_end) {
+            query.known.push(MdnsKnownAnswer {
+                name,
+                rtype,
+                ttl,
+                rdata,
+            });
+        }
+        offset = rdata_end;
+    }
+    Some(query)
+}
+
+/* message encoder, with name compression (RFC 1035 section 4.1.4) */
+#[derive(Default)]
+struct MdnsWriter {
+    data: Vec<u8>,
+    /* names (lower case) already written, and their offsets */
+    names: Vec<(Vec<Vec<u8>>, usize)>,
+}
+
+impl MdnsWriter {
+    fn name(&mut self, name: &[Vec<u8>]) {
+        for i in 0..name.len() {
+            let suffix = lowercase(&name[i..]);
+            if let Some((_, offset)) = self.names.iter().find(|(n, _)| *n == suffix) {
+                self.data
+                    .extend_from_slice(&(0xc000 | *offset as u16).to_be_bytes());
+                return;
+            }
+            /* pointers are 14-bit long */
+            if self.data.len() < 0x4000 {
+                self.names.push((suffix, self.data.len()));
+            }
+            self.data.push(name[i].len() as u8);
+            self.data.extend_from_slice(&name[i]);
+        }
+        self.data.push(0);
+    }
+
+    fn name_uncompressed(&mut self, name: &[Vec<u8>]) {
+        for label in name.iter() {
+            self.data.push(label.len() as u8);
+            self.data.extend_from_slice(label);
+        }
+        self.data.push(0);
+    }
+
+    fn rdata(&mut self, data: &MdnsData) {
+        match data {
+            MdnsData::Ptr(name) => self.name(name),
+            MdnsData::Srv(port, target) => {
+                /* priority, weight */
+                self.data.extend_from_slice(&[0, 0, 0, 0]);
+                self.data.extend_from_slice(&port.to_be_bytes());
+                self.name(target);
+            }
+            MdnsData::Txt(strings) => {
+                /* RFC 6763 section 6.1: an empty TXT record holds an
+                 * empty string */
+                if strings.is_empty() {
+                    self.data.push(0);
+                }
+                for s in strings.iter() {
+                    self.data.push(s.len() as u8);
+                    self.data.extend_from_slice(s.as_bytes());
+                }
+            }
+            MdnsData::Address(IpAddr::V4(ip)) => self.data.extend_from_slice(&ip.octets()),
+            MdnsData::Address(IpAddr::V6(ip)) => self.data.extend_from_slice(&ip.octets()),
+        }
+    }
+
+    fn record(&mut self, record: &MdnsRecord, ttl: u32, flush: bool) {
+        self.name(&record.name);
+        self.data.extend_from_slice(&record.rtype.to_be_bytes());
+        let class = if flush && record.unique {
+            DNS_CLASS_IN | MDNS_CLASS_FLUSH
+        } else {
+            DNS_CLASS_IN
+        };
+        self.data.extend_from_slice(&class.to_be_bytes());
+        self.data.extend_from_slice(&ttl.to_be_bytes());
+        let offset = self.data.len();
+        self.data.extend_from_slice(&[0, 0]);
+        self.rdata(&record.data);
+        let rdlength = (self.data.len() - offset - 2) as u16;
+        self.data[offset..offset + 2].copy_from_slice(&rdlength.to_be_bytes());
+    }
+}
+
+/* records of a service instance: SRV, TXT */
+fn mdns_instance_records(config: &MdnsConfig, service: &MdnsService) -> Vec<MdnsRecord> {
+    vec![
+        MdnsRecord {
+            name: service_instance(service),
+            rtype: DNS_TYPE_SRV,
+            unique: true,
+            data: MdnsData::Srv(service.port, host_name(config)),
+        },
+        MdnsRecord {
+            name: service_instance(service),
+            rtype: DNS_TYPE_TXT,
+            unique: true,
+            data: MdnsData::Txt(service.txt.clone()),
+        },
+    ]
+}
+
+fn mdns_address_records(config: &MdnsConfig, addresses: &[IpAddr], rtype: u16) -> Vec<MdnsRecord> {
+    addresses
+        .iter()
+        .filter(|a| match rtype {
+            DNS_TYPE_ANY => true,
+            DNS_TYPE_A => a.is_ipv4(),
+            DNS_TYPE_AAAA => a.is_ipv6(),
+            _ => false,
+        })
+        .map(|a| MdnsRecord {
+            name: host_name(config),
+            rtype: if a.is_ipv4() {
+                DNS_TYPE_A
+            } else {
+                DNS_TYPE_AAAA
+            },
+            unique: true,
+            data: MdnsData::Address(*a),
+        })
+        .collect()
+}
+
+/* answers and additional records (RFC 6763 section 12) to a question */
+fn mdns_question(
+    question: &MdnsQuestion,
+    config: &MdnsConfig,
+    addresses: &[IpAddr],
+) -> (Vec<MdnsRecord>, Vec<MdnsRecord>) {
+    let mut answers = Vec::new();
+    let mut additional = Vec::new();
+    if question.qclass != DNS_CLASS_IN && question.qclass != DNS_TYPE_ANY {
+        return (answers, additional);
+    }
+    let qtype = question.qtype;
+    let ptr = qtype == DNS_TYPE_PTR || qtype == DNS_TYPE_ANY;
+    if same_name(&question.name, &labels(&MDNS_SERVICES)) {
+        /* service type enumeration (RFC 6763 section 9) */
+        if ptr {
+            for service in config.service.iter() {
+                let record = MdnsRecord {
+                    name: labels(&MDNS_SERVICES),
+                    rtype: DNS_TYPE_PTR,
+                    unique: false,
+                    data: MdnsData::Ptr(service_type(service)),
+                };
+                if !answers.contains(&record) {
+                    answers.push(record);
+                }
+            }
+        }
+        return (answers, additional);
+    }
+    if same_name(&question.name, &host_name(config)) {
+        answers.extend(mdns_address_records(config, addresses, qtype));
+        return (answers, additional);
+    }
+    for service in config.service.iter() {
+        if ptr && same_name(&question.name, &service_type(service)) {
+            answers.push(MdnsRecord {
+                name: service_type(service),
+                rtype: DNS_TYPE_PTR,
+                unique: false,
+                data: MdnsData::Ptr(service_instance(service)),
+            });
+            additional.extend(mdns_instance_records(config, service));
+            additional.extend(mdns_address_records(config, addresses, DNS_TYPE_ANY));
+        } else if same_name(&question.name, &service_instance(service)) {
+            for record in mdns_instance_records(config, service) {
+                if qtype == DNS_TYPE_ANY || qtype == record.rtype {
+                    answers.push(record);
+                }
+            }
+            if !answers.is_empty() {
+                additional.extend(mdns_address_records(config, addresses, DNS_TYPE_ANY));
+            }
+        }
+    }
+    (answers, additional)
+}
+
+/* RFC 6762 section 7.1: records the querier knows, with at least half
+ * of their TTL remaining, are not sent */
+fn mdns_known(record: &MdnsRecord, known: &[MdnsKnownAnswer], ttl: u32) -> bool {
+    let rdata = record.data.canonical();
+    known.iter().any(|k| {
+        k.rtype == record.rtype
+            && k.ttl >= ttl / 2
+            && same_name(&k.name, &record.name)
+            && k.rdata == rdata
+    })
+}
+
+/* Response to a query: legacy (one-shot, not from port 5353) queries
+ * get the ID and the questions back, and short TTLs (section 6.7);
+ * other responses have no question (section 6).
+ **/
+fn mdns_response(
+    query: &MdnsQuery,
+    config: &MdnsConfig,
+    addresses: &[IpAddr],
+    legacy: bool,
+) -> Option<Vec<u8>> {
+    let ttl = if legacy {
+        std::cmp::min(config.ttl, MDNS_LEGACY_TTL)
+    } else {
+        config.ttl
+    };
+    let mut answers: Vec<MdnsRecord> = Vec::new();
+    let mut additional: Vec<MdnsRecord> = Vec::new();
+    for question in query.questions.iter() {
+        let (an, ar) = mdns_question(question, config, addresses);
+        for record in an {
+            if !answers.contains(&record) && !mdns_known(&record, &query.known, ttl) {
+                answers.push(record);
+            }
+        }
+        additional.extend(ar);
+    }
+    if answers.is_empty() {
+        return None;
+    }
+    let mut dedup = Vec::new();
+    for record in additional {
+        if !answers.contains(&record) && !dedup.contains(&record) {
+            dedup.push(record);
+        }
+    }
+    let additional = dedup;
+    let mut w = MdnsWriter::default();
+    w.data
+        .extend_from_slice(&(if legacy { query.id } else { 0 }).to_be_bytes());
+    /* QR, AA */
+    w.data.extend_from_slice(&0x8400u16.to_be_bytes());
+    let qdcount = if legacy { query.questions.len() } else { 0 };
+    for count in [qdcount, answers.len(), 0, additional.len()].iter() {
+        w.data.extend_from_slice(&(*count as u16).to_be_bytes());
+    }
+    if legacy {
+        for question in query.questions.iter() {
+            w.name(&question.name);
+            w.data.extend_from_slice(&question.qtype.to_be_bytes());
+            w.data.extend_from_slice(&question.qclass.to_be_bytes());
+        }
+    }
+    for record in answers.iter().chain(additional.iter()) {
+        w.record(record, ttl, !legacy);
+    }
+    Some(w.data)
+}
+
+/* mDNS queries: sent to the group, or to one of our addresses */
+pub fn is_mdns(client_info: &ClientInfo) -> bool {
+    client_info.port.dst == Some(MDNS_PORT)
+        && match client_info.ip.dst {
+            Some(IpAddr::V4(ip)) => !ip.is_multicast() || ip == MDNS_GROUP_V4,
+            Some(IpAddr::V6(ip)) => !ip.is_multicast() || ip == MDNS_GROUP_V6,
+            None => true,
+        }
+}
+
+fn mdns_event(query: &MdnsQuery, unicast: bool, legacy: bool, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("mdns_query", client_info);
+    event.set(
+        "questions",
+        query
+            .questions
+            .iter()
+            .map(|q| dns_dotted(&q.name))
+            .collect::<Vec<String>>(),
+    );
+    event.set(
+        "qtypes",
+        query
+            .questions
+            .iter()
+            .map(|q| q.qtype)
+            .collect::<Vec<u16>>(),
+    );
+    event.set("known_answers", query.known.len());
+    event.set("unicast", unicast);
+    event.set("legacy", legacy);
+    event
+}
+
+/* Answer an mDNS query: the answer is sent to the group, unless a
+ * unicast response is requested (QU bit), the query was sent to one of
+ * our addresses, or the query comes from another port than 5353 (it is
+ * then never sent to the group). The source address of answers sent to
+ * the group is one of our addresses.
+ **/
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving mDNS data");
+    let config = &masscanned.config.mdns;
+    if config.service.is_empty() {
+        return None;
+    }
+    let query = match mdns_parse_query(data) {
+        Some(q) => q,
+        None => {
+            info!("mDNS data not handled (invalid query)");
+            return None;
+        }
+    };
+    let group = client_info.ip.dst.is_some_and(|ip| ip.is_multicast());
+    /* our addresses: the one that was probed, the configured ones */
+    let mut addresses = Vec::new();
+    if let Some(ip) = client_info.ip.dst.filter(|_| !group) {
+        addresses.push(ip);
+    }
+    if let Some(ips) = masscanned.ip_addresses {
+        let mut ips: Vec<IpAddr> = ips
+            .iter()
+            .filter(|ip| !ip.is_multicast() && !ip.is_unspecified())
+            .copied()
+            .collect::<HashSet<IpAddr>>()
+            .into_iter()
+            .collect();
+        ips.sort();
+        addresses.extend(ips.into_iter().filter(|ip| Some(*ip) != client_info.ip.dst));
+    }
+    let legacy = client_info.port.src != Some(MDNS_PORT);
+    let unicast = legacy || !group || query.questions.iter().any(|q| q.unicast);
+    mdns_event(&query, unicast, legacy, client_info).log();
+    /* answers to the group are sent from one of our addresses */
+    if group {
+        let source = addresses
+            .iter()
+            .find(|ip| ip.is_ipv4() == client_info.ip.dst.is_none_or(|d| d.is_ipv4()))
+            .copied();
+        match source {
+            Some(ip) => client_info.ip.dst = Some(ip),
+            None => {
+                info!("mDNS query to the group not answered (no address)");
+                return None;
+            }
+        }
+    }
+    let repl_data = mdns_response(&query, config, &addresses, legacy)?;
+    if !unicast {
+        match client_info.ip.dst {
+            Some(IpAddr::V4(_)) => {
+                client_info.ip.src = Some(IpAddr::V4(MDNS_GROUP_V4));
+                client_info.mac.src = Some(MDNS_MAC_V4);
+            }
+            Some(IpAddr::V6(_)) => {
+                client_info.ip.src = Some(IpAddr::V6(MDNS_GROUP_V6));
+                client_info.mac.src = Some(MDNS_MAC_V6);
+            }
+            None => {}
+        }
+    }
+    debug!("sending mDNS data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    use simple_dns::rdata::{RData, PTR};
+    use simple_dns::{Name, Packet, Question, ResourceRecord, CLASS, QCLASS, QTYPE, TYPE};
+
+    const CONFIG: &str = r#"
+[mdns]
+hostname = "nas"
+
+[[mdns.service]]
+name = "Web.Interface"
+service = "_http._tcp"
+port = 8080
+txt = ["path=/admin"]
+
+[[mdns.service]]
+name = "Printer"
+service = "_ipp._tcp"
+port = 631
+txt = []
+
+[[mdns.service]]
+name = "Other"
+service = "_http._tcp"
+port = 80
+"#;
+
+    fn masscanned<'a>(ips: &'a HashSet<IpAddr>) -> Masscanned<'a> {
+        Masscanned {
+            ip_addresses: Some(ips),
+            ..crate::proto::test_util::masscanned(Config::from_str(CONFIG).unwrap())
+        }
+    }
+
+    fn addresses() -> HashSet<IpAddr> {
+        let mut ips = HashSet::new();
+        ips.insert(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 10)));
+        ips
+    }
+
+    /* query from port 5353 to the group */
+    fn client_info() -> ClientInfo {
+        let mut client_info = test_util::client_info(
+            IpNextHeaderProtocols::Udp,
+            IpAddr::V4(MDNS_GROUP_V4),
+            MDNS_PORT,
+        );
+        client_info.mac.src = Some(MacAddr(0x02, 0, 0, 0, 0, 1));
+        client_info.port.src = Some(MDNS_PORT);
+        client_info
+    }
+
+    fn query(name: &str, unicast: bool, known: &[(&str, u32)]) -> Vec<u8> {
+        let mut packet = Packet::new_query(0x4242);
+        packet.questions.push(Question::new(
+            Name::new_unchecked(name),
+            QTYPE::TYPE(TYPE::PTR),
+            QCLASS::CLASS(CLASS::IN),
+            unicast,
+        ));
+        for (target, ttl) in known.iter() {
+            packet.answers.push(ResourceRecord::new(
+                Name::new_unchecked(name),
+                CLASS::IN,
+                *ttl,
+                RData::PTR(PTR(Name::new_unchecked(target))),
+            ));
+        }
+        packet.build_bytes_vec_compressed().unwrap()
+    }
+
+    fn ptr_targets(records: &[ResourceRecord]) -> Vec<String> {
+        records
+            .iter()
+            .filter_map(|r| match &r.rdata {
+                RData::PTR(PTR(name)) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_mdns_services() {
+        let ips = addresses();
+        let masscanned = masscanned(&ips);
+        let mut client_info = client_info();
+        let q = query("_services._dns-sd._udp.local", false, &[]);
+        let answer = repl(&q, &masscanned, &mut client_info).unwrap();
+        let packet = Packet::parse(&answer).unwrap();
+        /* section 18.1 and 6: no ID, no question */
+        assert!(packet.id() == 0);
+        assert!(packet.questions.is_empty());
+        assert!(packet.additional_records.is_empty());
+        /* each type once */
+        assert!(ptr_targets(&packet.answers) == ["_http._tcp.local", "_ipp._tcp.local"]);
+        for record in packet.answers.iter() {
+            assert!(record.name.to_string() == "_services._dns-sd._udp.local");
+            assert!(record.ttl == 120 && !record.cache_flush);
+        }
+        /* "local" is written once, then pointed to */
+        assert!(answer.windows(6).filter(|w| w == b"\x05local").count() == 1);
+        /* sent to the group, from our address */
+        assert!(client_info.ip.src == Some(IpAddr::V4(MDNS_GROUP_V4)));
+        assert!(client_info.ip.dst == Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 10))));
+        assert!(client_info.mac.src == Some(MDNS_MAC_V4));
+        assert!(client_info.port.src == Some(MDNS_PORT));
+    }
+
+    #[test]
+    fn test_mdns_http() {
+        let ips = addresses();
+        let masscanned = masscanned(&ips);
+        let mut client_info = client_info();
+        /* unicast response requested */
+        let q = query("_HTTP._tcp.local", true, &[]);
+        let answer = repl(&q, &masscanned, &mut client_info).unwrap();
+        let packet = Packet::parse(&answer).unwrap();
+        assert!(
+            ptr_targets(&packet.answers)
+                == ["Web.Interface._http._tcp.local", "Other._http._tcp.local"]
+        );
+        /* instance name with a dot: one label */
+        assert!(answer.windows(14).any(|w| w == b"\x0dWeb.Interface"));
+        let mut srv = Vec::new();
+        let mut txt = Vec::new();
+        let mut a = Vec::new();
+        for record in packet.additional_records.iter() {
+            assert!(record.cache_flush);
+            match &record.rdata {
+                RData::SRV(s) => {
+                    assert!(s.target.to_string() == "nas.local");
+                    srv.push((record.name.to_string(), s.port));
+                }
+                RData::TXT(t) => txt.push((record.name.to_string(), t.attributes())),
+                RData::A(r) => {
+                    assert!(record.name.to_string() == "nas.local");
+                    a.push(Ipv4Addr::from(r.address));
+                }
+                _ => panic!("unexpected record"),
+            }
+        }
+        assert!(
+            srv == [
+                ("Web.Interface._http._tcp.local".to_string(), 8080),
+                ("Other._http._tcp.local".to_string(), 80)
+            ]
+        );
+        assert!(txt.len() == 2 && txt[0].1.get("path") == Some(&Some("/admin".to_string())));
+        assert!(a == [Ipv4Addr::new(192, 0, 2, 10)]);
+        /* unicast: sent back to the client, from our address */
+        assert!(client_info.ip.src == Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+        assert!(client_info.ip.dst == Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 10))));
+        assert!(client_info.mac.src == Some(MacAddr(0x02, 0, 0, 0, 0, 1)));
+        /* not our services */
+        let q = query("_ssh._tcp.local", false, &[]);
+        assert!(repl(&q, &masscanned, &mut self::client_info()).is_none());
+    }
+
+    #[test]
+    fn test_mdns_known_answers() {
+        let ips = addresses();
+        let masscanned = masscanned(&ips);
+        let known = [("Other._http._tcp.local", 120)];
+        let q = query("_http._tcp.local", false, &known);
+        let answer = repl(&q, &masscanned, &mut client_info()).unwrap();
+        let packet = Packet::parse(&answer).unwrap();
+        assert!(ptr_targets(&packet.answers) == ["Web.Interface._http._tcp.local"]);
+        /* case does not matter */
+        let q = query(
+            "_ipp._tcp.local",
+            false,
+            &[("printer._IPP._tcp.local", 4500)],
+        );
+        assert!(repl(&q, &masscanned, &mut client_info()).is_none());
+        /* less than half of the TTL remaining */
+        for (ttl, answers) in [(59, 2), (60, 1)].iter() {
+            let q = query(
+                "_http._tcp.local",
+                false,
+                &[("Other._http._tcp.local", *ttl)],
+            );
+            let answer = repl(&q, &masscanned, &mut client_info()).unwrap();
+            let packet = Packet::parse(&answer).unwrap();
+            assert!(packet.answers.len() == *answers);
+        }
+    }
+
+    #[test]
+    fn test_mdns_legacy() {
+        let ips = addresses();
+        let masscanned = masscanned(&ips);
+        /* one-shot query (e.g., dig -p 5353) to the group */
+        let mut client_info = client_info();
+        client_info.port.src = Some(40000);
+        let q = query("_ipp._tcp.local", false, &[]);
+        let answer = repl(&q, &masscanned, &mut client_info).unwrap();
+        let packet = Packet::parse(&answer).unwrap();
+        assert!(packet.id() == 0x4242);
+        assert!(packet.questions.len() == 1);
+        assert!(packet.questions[0].qname.to_string() == "_ipp._tcp.local");
+        assert!(ptr_targets(&packet.answers) == ["Printer._ipp._tcp.local"]);
+        for record in packet
+            .answers
+            .iter()
+            .chain(packet.additional_records.iter())
+        {
+            assert!(record.ttl == MDNS_LEGACY_TTL && !record.cache_flush);
+        }
+        /* never sent to the group */
+        assert!(client_info.ip.src == Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+        assert!(client_info.port.src == Some(40000));
+        /* query to one of our addresses: that address is advertised */
+        let mut client_info = self::client_info();
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        let answer = repl(&q, &masscanned, &mut client_info).unwrap();
+        let packet = Packet::parse(&answer).unwrap();
+        let a: Vec<Ipv4Addr> = packet
+            .additional_records
+            .iter()
+            .filter_map(|r| match &r.rdata {
+                RData::A(a) => Some(Ipv4Addr::from(a.address)),
+                _ => None,
+            })
+            .collect();
+        assert!(a == [Ipv4Addr::new(198, 51, 100, 7), Ipv4Addr::new(192, 0, 2, 10)]);
+        assert!(client_info.ip.src == Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+        /* to the group, without an address of ours: not answered */
+        let empty = HashSet::new();
+        let masscanned = self::masscanned(&empty);
+        assert!(repl(&q, &masscanned, &mut self::client_info()).is_none());
+    }
+
+    #[test]
+    fn test_mdns_dispatch() {
+        let ips = addresses();
+        let mut masscanned = masscanned(&ips);
+        let q = query("_http._tcp.local", false, &[]);
+        let mut client_info = client_info();
+        assert!(crate::proto::dispatch(&q, &masscanned, &mut client_info, None).is_some());
+        /* other multicast groups or ports: not answered */
+        for (ip, port) in [
+            (Ipv4Addr::new(224, 0, 0, 252), MDNS_PORT),
+            (MDNS_GROUP_V4, 53),
+        ]
+        .iter()
+        {
+            let mut client_info = self::client_info();
+            client_info.ip.dst = Some(IpAddr::V4(*ip));
+            client_info.port.dst = Some(*port);
+            assert!(crate::proto::dispatch(&q, &masscanned, &mut client_info, None).is_none());
+        }
+        let mut client_info = self::client_info();
+        assert!(crate::proto::dispatch(
+            b"GET / HTTP/1.1\r\n\r\n",
+            &masscanned,
+            &mut client_info,
+            None
+        )
+        .is_none());
+        /* no service: not answered */
+        masscanned.config.mdns.service.clear();
+        let mut client_info = self::client_info();
+        assert!(crate::proto::dispatch(&q, &masscanned, &mut client_info, None).is_none());
+    }
+
+    #[test]
+    fn test_mdns_config() {
+        assert!(Config::from_str(CONFIG).is_ok());
+        for invalid in [
+            "[mdns]\nhostname = \"a.b\"\n",
+            "[mdns]\nhostname = \"\"\n",
+            "[[mdns.service]]\nservice = \"http._tcp\"\n",
+            "[[mdns.service]]\nservice = \"_http._sctp\"\n",
+            "[[mdns.service]]\nservice = \"_http\"\n",
+            "[[mdns.service]]\nname = \"\"\n",
+            "[[mdns.service]]\ntxt = [\"\"]\n",
+        ]
+        .iter()
+        {
+            assert!(Config::from_str(invalid).is_err());
+        }
+    }
+}
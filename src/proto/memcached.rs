@@ -0,0 +1,510 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::*;
+use pnet::packet::ip::IpNextHeaderProtocols;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* binary protocol: magic bytes, opcodes and status */
+const MEMCACHED_REQUEST: u8 = 0x80;
+const MEMCACHED_RESPONSE: u8 = 0x81;
+const MEMCACHED_OP_GET: u8 = 0x00;
+const MEMCACHED_OP_SET: u8 = 0x01;
+const MEMCACHED_OP_ADD: u8 = 0x02;
+const MEMCACHED_OP_REPLACE: u8 = 0x03;
+const MEMCACHED_OP_DELETE: u8 = 0x04;
+const MEMCACHED_OP_INCREMENT: u8 = 0x05;
+const MEMCACHED_OP_DECREMENT: u8 = 0x06;
+const MEMCACHED_OP_QUIT: u8 = 0x07;
+const MEMCACHED_OP_FLUSH: u8 = 0x08;
+const MEMCACHED_OP_NOOP: u8 = 0x0a;
+const MEMCACHED_OP_VERSION: u8 = 0x0b;
+const MEMCACHED_OP_GETK: u8 = 0x0c;
+const MEMCACHED_OP_STAT: u8 = 0x10;
+const MEMCACHED_STATUS_OK: u16 = 0x0000;
+const MEMCACHED_STATUS_NOT_FOUND: u16 = 0x0001;
+const MEMCACHED_STATUS_UNKNOWN: u16 = 0x0081;
+
+/* size of the UDP frame header (request id, sequence number, number
+ * of datagrams, reserved) */
+const MEMCACHED_UDP_HEADER: usize = 8;
+
+/* maximum size of the values stored, and of the data kept between two
+ * segments */
+const MEMCACHED_MAX_VALUE: usize = 65536;
+const MEMCACHED_MAX_BUFFER: usize = MEMCACHED_MAX_VALUE + 1024;
+/* number of bytes of the values that are logged */
+const MEMCACHED_LOG_VALUE: usize = 64;
+
+/* memcached server (over TCP and UDP, on the ports), with an empty
+ * cache: values stored are logged, and never found.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemcachedConfig {
+    pub ports: Vec<u16>,
+    pub version: String,
+}
+
+impl Default for MemcachedConfig {
+    fn default() -> Self {
+        MemcachedConfig {
+            ports: vec![11211],
+            version: "1.6.21".to_string(),
+        }
+    }
+}
+
+/* state of a memcached flow: data received, not a complete command
+ * yet */
+#[derive(Default)]
+pub struct MemcachedState {
+    buffer: Vec<u8>,
+}
+
+fn memcached_stats(config: &MemcachedConfig) -> Vec<(String, String)> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    vec![
+        ("pid".to_string(), "1".to_string()),
+        ("uptime".to_string(), "2592000".to_string()),
+        ("time".to_string(), now.to_string()),
+        ("version".to_string(), config.version.clone()),
+        ("curr_connections".to_string(), "2".to_string()),
+    ]
+}
+
+fn memcached_event(command: &str, binary: bool, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("memcached_command", client_info);
+    event.set("command", command.to_string());
+    event.set("binary", binary);
+    event
+}
+
+/* storage commands: what is stored */
+fn memcached_store_event(event: &mut Event, key: &[u8], flags: u32, exptime: u32, value: &[u8]) {
+    event.set("key", String::from_utf8_lossy(key).to_string());
+    event.set("flags", flags);
+    event.set("exptime", exptime);
+    event.set("length", value.len());
+    let len = std::cmp::min(value.len(), MEMCACHED_LOG_VALUE);
+    event.set("value", String::from_utf8_lossy(&value[..len]).to_string());
+}
+
+/* text protocol: answer to the next command and number of bytes used,
+ * None when data does not hold an entire command */
+fn memcached_text(
+    data: &[u8],
+    config: &MemcachedConfig,
+    client_info: &mut ClientInfo,
+) -> Option<(Vec<u8>, usize)> {
+    let end = data.iter().position(|c| *c == b'\n')?;
+    let line = String::from_utf8_lossy(&data[..end]).to_string();
+    let args = line.split_whitespace().collect::<Vec<&str>>();
+    let mut used = end + 1;
+    let command = match args.first() {
+        Some(c) => c.to_ascii_lowercase(),
+        None => return Some((b"ERROR\r\n".to_vec(), used)),
+    };
+    let mut event = memcached_event(&command, false, client_info);
+    let repl = match command.as_str() {
+        "set" | "add" | "replace" | "append" | "prepend" | "cas" => {
+            let count = if command == "cas" { 6 } else { 5 };
+            let fields = (
+                args.get(2).and_then(|a| a.parse::<u32>().ok()),
+                args.get(3).and_then(|a| a.parse::<u32>().ok()),
+                args.get(4).and_then(|a| a.parse::<usize>().ok()),
+            );
+            match fields {
+                (Some(flags), Some(exptime), Some(len)) if args.len() >= count => {
+                    if len > MEMCACHED_MAX_VALUE {
+                        client_info.close = true;
+                        b"SERVER_ERROR object too large for cache\r\n".to_vec()
+                    } else {
+                        let value = data.get(used..used + len)?;
+                        /* the value is followed by CRLF */
+                        data.get(used + len + 1)?;
+                        used += len + 2;
+                        memcached_store_event(
+                            &mut event,
+                            args[1].as_bytes(),
+                            flags,
+                            exptime,
+                            value,
+                        );
+                        if args.get(count) == Some(&"noreply") {
+                            Vec::new()
+                        } else if command == "cas" {
+                            b"NOT_FOUND\r\n".to_vec()
+                        } else {
+                            b"STORED\r\n".to_vec()
+                        }
+                    }
+                }
+                _ => b"CLIENT_ERROR bad command line format\r\n".to_vec(),
+            }
+        }
+        "get" | "gets" | "gat" | "gats" => {
+            event.set(
+                "key",
+                Value::Array(
+                    args[1..]
+                        .iter()
+                        .map(|k| Value::String(k.to_string()))
+                        .collect(),
+                ),
+            );
+            b"END\r\n".to_vec()
+        }
+        "version" => format!("VERSION {}\r\n", config.version).into_bytes(),
+        "stats" => {
+            let mut repl = String::new();
+            for (k, v) in memcached_stats(config) {
+                repl.push_str(&format!("STAT {} {}\r\n", k, v));
+            }
+            repl.push_str("END\r\n");
+            repl.into_bytes()
+        }
+        "delete" | "incr" | "decr" | "touch" => {
+            if let Some(k) = args.get(1) {
+                event.set("key", k.to_string());
+            }
+            b"NOT_FOUND\r\n".to_vec()
+        }
+        "flush_all" | "verbosity" => b"OK\r\n".to_vec(),
+        "quit" => {
+            client_info.close = true;
+            Vec::new()
+        }
+        _ => b"ERROR\r\n".to_vec(),
+    };
+    warn!("memcached command: {}", command);
+    event.log();
+    Some((repl, used))
+}
+
+fn memcached_binary_response(
+    opcode: u8,
+    status: u16,
+    opaque: &[u8],
+    key: &[u8],
+    value: &[u8],
+) -> Vec<u8> {
+    let mut repl = vec![MEMCACHED_RESPONSE, opcode];
+    repl.extend_from_slice(&(key.len() as u16).to_be_bytes());
+    /* no extras, raw data */
+    repl.extend_from_slice(&[0, 0]);
+    repl.extend_from_slice(&status.to_be_bytes());
+    repl.extend_from_slice(&((key.len() + value.len()) as u32).to_be_bytes());
+    repl.extend_from_slice(opaque);
+    /* CAS */
+    let cas: u64 = if status == MEMCACHED_STATUS_OK { 1 } else { 0 };
+    repl.extend_from_slice(&cas.to_be_bytes());
+    repl.extend_from_slice(key);
+    repl.extend_from_slice(value);
+    repl
+}
+
+/* binary protocol: answer to the next request and number of bytes
+ * used, None when data does not hold an entire request */
+fn memcached_binary(
+    data: &[u8],
+    config: &MemcachedConfig,
+    client_info: &mut ClientInfo,
+) -> Option<(Vec<u8>, usize)> {
+    let header = data.get(..24)?;
+    let opcode = header[1];
+    let key_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let extras_len = header[4] as usize;
+    let body_len = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+    let opaque = &header[12..16];
+    if body_len > MEMCACHED_MAX_BUFFER || extras_len + key_len > body_len {
+        info!("invalid memcached request - dropped");
+        client_info.close = true;
+        return Some((Vec::new(), data.len()));
+    }
+    let body = data.get(24..24 + body_len)?;
+    let (extras, rest) = body.split_at(extras_len);
+    let (key, value) = rest.split_at(key_len);
+    let name = match opcode {
+        MEMCACHED_OP_GET | MEMCACHED_OP_GETK => "get",
+        MEMCACHED_OP_SET => "set",
+        MEMCACHED_OP_ADD => "add",
+        MEMCACHED_OP_REPLACE => "replace",
+        MEMCACHED_OP_DELETE => "delete",
+        MEMCACHED_OP_INCREMENT => "incr",
+        MEMCACHED_OP_DECREMENT => "decr",
+        MEMCACHED_OP_QUIT => "quit",
+        MEMCACHED_OP_FLUSH => "flush_all",
+        MEMCACHED_OP_NOOP => "noop",
+        MEMCACHED_OP_VERSION => "version",
+        MEMCACHED_OP_STAT => "stats",
+        _ => "unknown",
+    };
+    let mut event = memcached_event(name, true, client_info);
+    let response = |status: u16, key: &[u8], value: &[u8]| {
+        memcached_binary_response(opcode, status, opaque, key, value)
+    };
+    let repl = match opcode {
+        MEMCACHED_OP_SET | MEMCACHED_OP_ADD | MEMCACHED_OP_REPLACE if extras.len() == 8 => {
+            let flags = u32::from_be_bytes([extras[0], extras[1], extras[2], extras[3]]);
+            let exptime = u32::from_be_bytes([extras[4], extras[5], extras[6], extras[7]]);
+            memcached_store_event(&mut event, key, flags, exptime, value);
+            response(MEMCACHED_STATUS_OK, b"", b"")
+        }
+        MEMCACHED_OP_GET
+        | MEMCACHED_OP_GETK
+        | MEMCACHED_OP_DELETE
+        | MEMCACHED_OP_INCREMENT
+        | MEMCACHED_OP_DECREMENT => {
+            event.set("key", String::from_utf8_lossy(key).to_string());
+            response(MEMCACHED_STATUS_NOT_FOUND, b"", b"Not found")
+        }
+        MEMCACHED_OP_QUIT => {
+            client_info.close = true;
+            response(MEMCACHED_STATUS_OK, b"", b"")
+        }
+        MEMCACHED_OP_FLUSH | MEMCACHED_OP_NOOP => response(MEMCACHED_STATUS_OK, b"", b""),
+        MEMCACHED_OP_VERSION => response(MEMCACHED_STATUS_OK, b"", config.version.as_bytes()),
+        MEMCACHED_OP_STAT => {
+            let mut repl = Vec::new();
+            for (k, v) in memcached_stats(config) {
+                repl.extend(response(MEMCACHED_STATUS_OK, k.as_bytes(), v.as_bytes()));
+            }
+            /* end of the list: no key, no value */
+            repl.extend(response(MEMCACHED_STATUS_OK, b"", b""));
+            repl
+        }
+        _ => {
+            event.set("opcode", opcode);
+            response(MEMCACHED_STATUS_UNKNOWN, b"", b"Unknown command")
+        }
+    };
+    warn!("memcached command: {} (binary)", name);
+    event.log();
+    Some((repl, 24 + body_len))
+}
+
+/* answers to the complete commands of data, and number of bytes used */
+fn memcached_answer(
+    data: &[u8],
+    config: &MemcachedConfig,
+    client_info: &mut ClientInfo,
+) -> (Vec<u8>, usize) {
+    let mut repl_data = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() && !client_info.close {
+        let next = if data[offset] == MEMCACHED_REQUEST {
+            memcached_binary(&data[offset..], config, client_info)
+        } else {
+            memcached_text(&data[offset..], config, client_info)
+        };
+        match next {
+            Some((repl, len)) => {
+                repl_data.extend(repl);
+                offset += len;
+            }
+            None => break,
+        }
+    }
+    (repl_data, offset)
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving memcached data");
+    let config = &masscanned.config.memcached;
+    let repl_data = if client_info.transport == Some(IpNextHeaderProtocols::Udp) {
+        /* frame header: the answer is one datagram, for the same
+         * request id */
+        if data.len() < MEMCACHED_UDP_HEADER {
+            info!("memcached data not handled (no UDP frame header)");
+            return None;
+        }
+        let (answer, _) = memcached_answer(&data[MEMCACHED_UDP_HEADER..], config, client_info);
+        if answer.is_empty() {
+            return None;
+        }
+        let mut repl_data = data[..2].to_vec();
+        repl_data.extend_from_slice(&[0, 0, 0, 1, 0, 0]);
+        repl_data.extend(answer);
+        repl_data
+    } else {
+        let t = tcb?;
+        let mut state = match std::mem::replace(&mut t.proto_state, ProtoState::None) {
+            ProtoState::Memcached(s) => s,
+            _ => MemcachedState::default(),
+        };
+        state.buffer.extend_from_slice(data);
+        let (answer, used) = memcached_answer(&state.buffer, config, client_info);
+        state.buffer.drain(..used);
+        if state.buffer.len() > MEMCACHED_MAX_BUFFER {
+            info!(
+                "memcached command too large ({} bytes received) - dropped",
+                state.buffer.len()
+            );
+            client_info.close = true;
+        }
+        t.proto_state = ProtoState::Memcached(state);
+        answer
+    };
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending memcached data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::udp::UdpPacket;
+    use pnet::packet::Packet;
+
+    fn memcached_client_info(transport: pnet::packet::ip::IpNextHeaderProtocol) -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.port.dst = Some(11211);
+        client_info.transport = Some(transport);
+        client_info
+    }
+
+    /* STAT lines, then END */
+    fn check_stats(data: &[u8]) {
+        let text = String::from_utf8_lossy(data).to_string();
+        let lines = text.split("\r\n").collect::<Vec<&str>>();
+        assert!(lines[0] == "STAT pid 1");
+        assert!(lines.contains(&"STAT version 1.6.21"));
+        assert!(lines
+            .iter()
+            .all(|l| l.starts_with("STAT ") || l.is_empty() || *l == "END"));
+        assert!(text.ends_with("\r\nEND\r\n"));
+    }
+
+    #[test]
+    fn test_memcached_tcp() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = memcached_client_info(IpNextHeaderProtocols::Tcp);
+        let mut tcb = TCPControlBlock::new();
+        let repl_data =
+            crate::proto::dispatch(b"stats\r\n", &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(tcb.proto_id == crate::proto::PROTO_MEMCACHED);
+        check_stats(&repl_data);
+        /* several commands, a value split across segments */
+        assert!(
+            repl(
+                b"version\r\nget a b\r\nset key 1 0 10\r\n01234",
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb)
+            )
+            .unwrap()
+                == b"VERSION 1.6.21\r\nEND\r\n"
+        );
+        assert!(
+            repl(b"56789\r\n", &masscanned, &mut client_info, Some(&mut tcb)).unwrap()
+                == b"STORED\r\n"
+        );
+        assert!(
+            repl(
+                b"set key 1 0 1 noreply\r\na\r\nset key\r\nfoo\r\nquit\r\nstats\r\n",
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb)
+            )
+            .unwrap()
+                == b"CLIENT_ERROR bad command line format\r\nERROR\r\n"
+        );
+        assert!(client_info.close);
+        let mut event = memcached_event("set", false, &client_info);
+        memcached_store_event(&mut event, b"key", 1, 0, &[b'a'; 100]);
+        assert!(event.fields["key"] == "key" && event.fields["flags"] == 1);
+        assert!(event.fields["length"] == 100);
+        assert!(event.fields["value"] == "a".repeat(MEMCACHED_LOG_VALUE));
+    }
+
+    #[test]
+    fn test_memcached_udp() {
+        let mut masscanned = masscanned(Config::default());
+        /* request id 0xbeef, sequence 0, 1 datagram */
+        let mut packet = b"\xc3\x50\x2b\xcb\x00\x17\x00\x00".to_vec();
+        packet.extend_from_slice(b"\xbe\xef\x00\x00\x00\x01\x00\x00stats\r\n");
+        let udp_req = UdpPacket::new(&packet).unwrap();
+        let mut client_info = memcached_client_info(IpNextHeaderProtocols::Udp);
+        let udp_repl = crate::layer_4::udp::repl(&udp_req, &masscanned, &mut client_info).unwrap();
+        assert!(udp_repl.get_destination() == 50000 && udp_repl.get_source() == 11211);
+        let repl_data = udp_repl.payload();
+        assert!(repl_data[..8] == *b"\xbe\xef\x00\x00\x00\x01\x00\x00");
+        check_stats(&repl_data[8..]);
+        /* the amplification limiter applies */
+        masscanned.config.udp.max_amplification = 2;
+        masscanned.config.udp.amplification_floor = 0;
+        let mut client_info = memcached_client_info(IpNextHeaderProtocols::Udp);
+        assert!(crate::layer_4::udp::repl(&udp_req, &masscanned, &mut client_info).is_none());
+        /* no frame header */
+        let mut client_info = memcached_client_info(IpNextHeaderProtocols::Udp);
+        assert!(repl(b"stats\r\n", &masscanned, &mut client_info, None).is_none());
+    }
+
+    #[test]
+    fn test_memcached_binary() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = memcached_client_info(IpNextHeaderProtocols::Tcp);
+        let mut tcb = TCPControlBlock::new();
+        /* version request (nmap memcached-info), opaque 0xdeadbeef */
+        let request = b"\x80\x0b\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\xde\xad\xbe\xef\x00\x00\x00\x00\x00\x00\x00\x00";
+        let repl_data = repl(request, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl_data[..2] == [MEMCACHED_RESPONSE, MEMCACHED_OP_VERSION]);
+        /* status, body length, opaque */
+        assert!(repl_data[6..8] == [0, 0]);
+        assert!(repl_data[8..12] == 6u32.to_be_bytes());
+        assert!(repl_data[12..16] == *b"\xde\xad\xbe\xef");
+        assert!(repl_data[24..] == *b"1.6.21");
+        /* stats: one response per stat, then an empty one */
+        let mut request = request.to_vec();
+        request[1] = MEMCACHED_OP_STAT;
+        let repl_data = repl(&request, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl_data[..2] == [MEMCACHED_RESPONSE, MEMCACHED_OP_STAT]);
+        assert!(repl_data[24..27] == *b"pid");
+        assert!(
+            repl_data[repl_data.len() - 24..repl_data.len() - 16]
+                == [MEMCACHED_RESPONSE, MEMCACHED_OP_STAT, 0, 0, 0, 0, 0, 0]
+        );
+        /* get: not found, over UDP */
+        let mut request = b"\x00\x01\x00\x00\x00\x01\x00\x00".to_vec();
+        request.extend_from_slice(b"\x80\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00k");
+        let mut client_info = memcached_client_info(IpNextHeaderProtocols::Udp);
+        let repl_data = repl(&request, &masscanned, &mut client_info, None).unwrap();
+        assert!(repl_data[8..10] == [MEMCACHED_RESPONSE, MEMCACHED_OP_GET]);
+        assert!(repl_data[14..16] == MEMCACHED_STATUS_NOT_FOUND.to_be_bytes());
+        assert!(repl_data[32..] == *b"Not found");
+    }
+}
@@ -0,0 +1,445 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* legacy (before 1.7) server list ping */
+const MC_LEGACY_PING: u8 = 0xfe;
+const MC_LEGACY_KICK: u8 = 0xff;
+
+const MC_NEXT_STATE_STATUS: i32 = 1;
+const MC_NEXT_STATE_LOGIN: i32 = 2;
+
+/* maximum size of a serverbound packet (handshake and login start are
+ * small) */
+const MC_MAX_PACKET: usize = 4096;
+
+/* Minecraft (Java Edition) server (on the configured TCP ports): the
+ * server list ping (handshake, status request and ping) gets the
+ * status below, the legacy ping the old-style kick, and login attempts
+ * a disconnection with kick_message.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MinecraftConfig {
+    pub ports: Vec<u16>,
+    pub version_name: String,
+    pub protocol: i32,
+    pub description: String,
+    pub players_online: i64,
+    pub players_max: i64,
+    /* names of the players listed (offline-mode UUIDs are derived from
+     * the names) */
+    pub sample: Vec<String>,
+    pub kick_message: String,
+}
+
+impl Default for MinecraftConfig {
+    fn default() -> Self {
+        MinecraftConfig {
+            ports: vec![25565],
+            version_name: "1.20.4".to_string(),
+            protocol: 765,
+            description: "A Minecraft Server".to_string(),
+            players_online: 0,
+            players_max: 20,
+            sample: Vec::new(),
+            kick_message: "You are not white-listed on this server!".to_string(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+enum McStep {
+    #[default]
+    Handshake,
+    Status,
+    Login,
+}
+
+/* beginning of a packet not received entirely yet, and state (set by
+ * the handshake) */
+#[derive(Default)]
+pub struct MinecraftState {
+    buffer: Vec<u8>,
+    step: McStep,
+}
+
+/* VarInt: 7 bits per byte, least significant group first, at most 5
+ * bytes - returns the value and its size */
+fn mc_read_varint(data: &[u8]) -> Option<(i32, usize)> {
+    let mut value: u32 = 0;
+    for (i, b) in data.iter().enumerate().take(5) {
+        value |= ((b & 0x7f) as u32) << (7 * i);
+        if b & 0x80 == 0 {
+            return Some((value as i32, i + 1));
+        }
+    }
+    None
+}
+
+fn mc_varint(value: i32) -> Vec<u8> {
+    let mut value = value as u32;
+    let mut out = Vec::new();
+    loop {
+        if value & !0x7f == 0 {
+            out.push(value as u8);
+            return out;
+        }
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+}
+
+fn mc_read_string(data: &[u8]) -> Option<(String, usize)> {
+    let (len, size) = mc_read_varint(data)?;
+    if len < 0 {
+        return None;
+    }
+    let end = size.checked_add(len as usize)?;
+    Some((
+        String::from_utf8_lossy(data.get(size..end)?).to_string(),
+        end,
+    ))
+}
+
+fn mc_string(s: &str) -> Vec<u8> {
+    let mut out = mc_varint(s.len() as i32);
+    out.extend_from_slice(s.as_bytes());
+    out
+}
+
+/* packet: length, ID and data */
+fn mc_packet(id: i32, data: &[u8]) -> Vec<u8> {
+    let mut body = mc_varint(id);
+    body.extend_from_slice(data);
+    let mut packet = mc_varint(body.len() as i32);
+    packet.extend(body);
+    packet
+}
+
+/* UUID of an offline-mode player: version 3 (MD5) UUID of
+ * "OfflinePlayer:<name>" */
+fn mc_offline_uuid(name: &str) -> String {
+    let mut h = Md5::digest(format!("OfflinePlayer:{}", name).as_bytes());
+    h[6] = (h[6] & 0x0f) | 0x30;
+    h[8] = (h[8] & 0x3f) | 0x80;
+    let hex: String = h.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn mc_status(config: &MinecraftConfig) -> Value {
+    let mut players = json!({
+        "max": config.players_max,
+        "online": config.players_online,
+    });
+    if !config.sample.is_empty() {
+        players["sample"] = config
+            .sample
+            .iter()
+            .map(|n| json!({"name": n, "id": mc_offline_uuid(n)}))
+            .collect();
+    }
+    json!({
+        "version": {"name": config.version_name, "protocol": config.protocol},
+        "players": players,
+        "description": {"text": config.description},
+        "enforcesSecureChat": false,
+    })
+}
+
+/* kick packet of the legacy ping: 1.4 to 1.6 clients send FE 01,
+ * older ones only FE */
+fn mc_legacy_kick(config: &MinecraftConfig, extended: bool) -> Vec<u8> {
+    let text = if extended {
+        format!(
+            "\u{a7}1\0{}\0{}\0{}\0{}\0{}",
+            config.protocol,
+            config.version_name,
+            config.description,
+            config.players_online,
+            config.players_max
+        )
+    } else {
+        format!(
+            "{}\u{a7}{}\u{a7}{}",
+            config.description, config.players_online, config.players_max
+        )
+    };
+    let utf16: Vec<u16> = text.encode_utf16().collect();
+    let mut repl = vec![MC_LEGACY_KICK];
+    repl.extend_from_slice(&(utf16.len() as u16).to_be_bytes());
+    for c in utf16 {
+        repl.extend_from_slice(&c.to_be_bytes());
+    }
+    repl
+}
+
+/* answer to one packet (ID and data) */
+fn mc_handle(
+    id: i32,
+    data: &[u8],
+    state: &mut MinecraftState,
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+) -> Option<Vec<u8>> {
+    let config = &masscanned.config.minecraft;
+    match (state.step, id) {
+        (McStep::Handshake, 0) => {
+            let (protocol, mut offset) = mc_read_varint(data)?;
+            let (address, size) = mc_read_string(&data[offset..])?;
+            offset += size;
+            let port = data.get(offset..offset + 2)?;
+            let port = u16::from_be_bytes([port[0], port[1]]);
+            let (next_state, _) = mc_read_varint(&data[offset + 2..])?;
+            let mut event = Event::new("minecraft_handshake", client_info);
+            event.set("protocol_version", protocol);
+            event.set("server_address", address);
+            event.set("server_port", port);
+            event.set("next_state", next_state);
+            event.log();
+            state.step = match next_state {
+                MC_NEXT_STATE_STATUS => McStep::Status,
+                MC_NEXT_STATE_LOGIN => McStep::Login,
+                _ => {
+                    client_info.close = true;
+                    return None;
+                }
+            };
+            None
+        }
+        (McStep::Status, 0) => {
+            warn!("Minecraft status request");
+            Some(mc_packet(0, &mc_string(&mc_status(config).to_string())))
+        }
+        (McStep::Status, 1) if data.len() == 8 => {
+            /* pong: same payload, then the connection is closed */
+            client_info.close = true;
+            Some(mc_packet(1, data))
+        }
+        (McStep::Login, 0) => {
+            let (username, _) = mc_read_string(data)?;
+            warn!("Minecraft login start ({})", username);
+            let mut event = Event::new("minecraft_login", client_info);
+            event.set("username", username);
+            event.log();
+            client_info.close = true;
+            let reason = json!({ "text": config.kick_message }).to_string();
+            Some(mc_packet(0, &mc_string(&reason)))
+        }
+        _ => {
+            info!("Minecraft packet not handled ({:?}, ID {})", state.step, id);
+            client_info.close = true;
+            None
+        }
+    }
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving Minecraft data");
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Minecraft(s)) => s,
+        _ => MinecraftState::default(),
+    };
+    if state.buffer.is_empty()
+        && state.step == McStep::Handshake
+        && data.first() == Some(&MC_LEGACY_PING)
+    {
+        let extended = data.get(1) == Some(&0x01);
+        let mut event = Event::new("minecraft_legacy_ping", client_info);
+        event.set("extended", extended);
+        event.log();
+        warn!("Minecraft legacy ping");
+        client_info.close = true;
+        return Some(mc_legacy_kick(&masscanned.config.minecraft, extended));
+    }
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    let mut offset = 0;
+    while let Some((len, size)) = mc_read_varint(&state.buffer[offset..]) {
+        if len <= 0 || len as usize > MC_MAX_PACKET {
+            info!("Minecraft packet not handled (length {})", len);
+            client_info.close = true;
+            return None;
+        }
+        let start = offset + size;
+        let packet = match state.buffer.get(start..start + len as usize) {
+            Some(p) => p.to_vec(),
+            None => break,
+        };
+        offset = start + len as usize;
+        let (id, id_size) = match mc_read_varint(&packet) {
+            Some(i) => i,
+            None => {
+                client_info.close = true;
+                return None;
+            }
+        };
+        if let Some(r) = mc_handle(id, &packet[id_size..], &mut state, masscanned, client_info) {
+            repl_data.extend(r);
+        }
+        if client_info.close {
+            break;
+        }
+    }
+    if state.buffer.len() - offset > MC_MAX_PACKET + 5 {
+        client_info.close = true;
+    }
+    state.buffer.drain(..offset);
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Minecraft(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending Minecraft data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.src = Some(54321);
+        client_info.port.dst = Some(25565);
+        client_info
+    }
+
+    #[test]
+    fn test_mc_varint() {
+        /* wiki.vg examples */
+        for (value, bytes) in [
+            (0, &b"\x00"[..]),
+            (1, b"\x01"),
+            (127, b"\x7f"),
+            (128, b"\x80\x01"),
+            (255, b"\xff\x01"),
+            (25565, b"\xdd\xc7\x01"),
+            (2097151, b"\xff\xff\x7f"),
+            (2147483647, b"\xff\xff\xff\xff\x07"),
+            (-1, b"\xff\xff\xff\xff\x0f"),
+            (-2147483648, b"\x80\x80\x80\x80\x08"),
+        ]
+        .iter()
+        {
+            assert!(mc_varint(*value) == *bytes);
+            assert!(mc_read_varint(bytes) == Some((*value, bytes.len())));
+        }
+        /* trailing data, truncated, too long */
+        assert!(mc_read_varint(b"\x80\x01\xff") == Some((128, 2)));
+        assert!(mc_read_varint(b"\x80\x80").is_none());
+        assert!(mc_read_varint(b"").is_none());
+        assert!(mc_read_varint(b"\xff\xff\xff\xff\xff\x01").is_none());
+    }
+
+    #[test]
+    fn test_mc_status() {
+        let mut masscanned = masscanned(Config::default());
+        masscanned.config.minecraft.sample = vec!["Notch".to_string()];
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        /* handshake (protocol 765, localhost:25565, status) and status
+         * request, split */
+        let data = b"\x10\x00\xfd\x05\x09localhost\x63\xdd\x01\x01\x00";
+        assert!(repl(&data[..5], &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        let status =
+            repl(&data[5..], &masscanned, &mut client_info, Some(&mut tcb)).expect("no status");
+        let (len, size) = mc_read_varint(&status).unwrap();
+        assert!(status.len() == size + len as usize);
+        assert!(status[size] == 0);
+        let (json, _) = mc_read_string(&status[size + 1..]).unwrap();
+        let json: Value = serde_json::from_str(&json).unwrap();
+        assert!(json["version"]["name"] == "1.20.4");
+        assert!(json["version"]["protocol"] == 765);
+        assert!(json["players"]["max"] == 20 && json["players"]["online"] == 0);
+        assert!(json["players"]["sample"][0]["name"] == "Notch");
+        assert!(json["players"]["sample"][0]["id"] == "b50ad385-829d-3141-a216-7e7d7539ba7f");
+        assert!(json["description"]["text"] == "A Minecraft Server");
+        assert!(!client_info.close);
+        /* ping */
+        let pong = repl(
+            b"\x09\x01\x00\x00\x01\x8c\x2f\x4e\x5a\x10",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .expect("no pong");
+        assert!(pong == b"\x09\x01\x00\x00\x01\x8c\x2f\x4e\x5a\x10");
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_mc_login_legacy() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        /* handshake (login) and login start */
+        let mut data = b"\x10\x00\xfd\x05\x09localhost\x63\xdd\x02".to_vec();
+        data.extend(mc_packet(0, &[&mc_string("Steve")[..], &[0; 16]].concat()));
+        let kick = repl(&data, &masscanned, &mut client_info, Some(&mut tcb)).expect("no kick");
+        assert!(kick[1] == 0);
+        assert!(mc_read_string(&kick[2..])
+            .unwrap()
+            .0
+            .contains("white-listed"));
+        assert!(client_info.close);
+        /* legacy pings */
+        let mut client_info = client();
+        let kick = repl(b"\xfe\x01\xfa", &masscanned, &mut client_info, None).unwrap();
+        assert!(kick[0] == MC_LEGACY_KICK);
+        let len = u16::from_be_bytes([kick[1], kick[2]]) as usize;
+        assert!(kick.len() == 3 + 2 * len);
+        assert!(kick[3..9] == [0x00, 0xa7, 0x00, 0x31, 0x00, 0x00]);
+        let kick = repl(b"\xfe", &masscanned, &mut client_info, None).unwrap();
+        assert!(kick.ends_with(b"\x00\xa7\x00\x30\x00\xa7\x00\x32\x00\x30"));
+        /* bad length */
+        let mut client_info = client();
+        assert!(repl(b"\x00", &masscanned, &mut client_info, None).is_none());
+        assert!(client_info.close);
+    }
+}
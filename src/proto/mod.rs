@@ -14,48 +14,567 @@
 // You should have received a copy of the GNU General Public License
 // along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
 
+use std::net::{IpAddr, Ipv4Addr};
+
 use lazy_static::lazy_static;
 use log::*;
 use pnet::packet::ip::IpNextHeaderProtocols;
-use std::collections::HashMap;
-use std::sync::Mutex;
 
 use crate::client::ClientInfo;
 use crate::smack::{Smack, SmackFlags, BASE_STATE, NO_MATCH, SMACK_CASE_SENSITIVE};
 use crate::Masscanned;
 
 mod http;
+pub use http::HttpConfig;
 use http::HTTP_VERBS;
 
 mod stun;
+pub use stun::StunConfig;
 use stun::{STUN_PATTERN_CHANGE_REQUEST, STUN_PATTERN_EMPTY, STUN_PATTERN_MAGIC};
 
 mod ssh;
+pub use ssh::SshConfig;
 use ssh::SSH_PATTERN_CLIENT_PROTOCOL;
 
+pub mod tcb;
+pub use tcb::TcpConfig;
+use tcb::{ProtoState, TCPControlBlock, CONTABLE};
+
+#[cfg(test)]
+pub(crate) mod test_util;
+
+mod upnp;
+mod websocket;
+mod winrm;
+
+mod docker;
+
+mod elasticsearch;
+
+mod ethereum;
+
+mod kubernetes;
+
+mod tracker;
+
+mod tls;
+pub use tls::TlsConfig;
+use tls::TLS_PATTERNS_CLIENT_HELLO;
+
+mod dtls;
+use dtls::DTLS_PATTERNS_CLIENT_HELLO;
+
+mod tor;
+pub use tor::TorConfig;
+
+mod ntlm;
+
+mod smb;
+pub use smb::SmbConfig;
+use smb::{NETBIOS_PATTERN_SESSION_REQUEST, SMB1_PATTERN_NEGOTIATE, SMB2_PATTERN_NEGOTIATE};
+
+mod line;
+
+mod ftp;
+pub use ftp::FtpConfig;
+
+mod smtp;
+pub use smtp::SmtpConfig;
+use smtp::SMTP_PATTERNS_HELLO;
+
+mod pop3;
+pub use pop3::Pop3Config;
+
+mod imap;
+pub use imap::ImapConfig;
+
+mod socks;
+pub use socks::SocksConfig;
+
+mod tacacs;
+pub use tacacs::TacacsConfig;
+
+mod telnet;
+pub use telnet::TelnetConfig;
+
+mod adb;
+pub use adb::AdbConfig;
+use adb::ADB_PATTERN_CNXN;
+
+mod ber;
+
+mod bencode;
+
+mod bitcoin;
+pub use bitcoin::BitcoinConfig;
+use bitcoin::BTC_PATTERNS_VERSION;
+
+mod dcerpc;
+pub use dcerpc::DcerpcConfig;
+use dcerpc::DCERPC_PATTERN_BIND;
+
+mod dhcp;
+pub use dhcp::DhcpConfig;
+
+mod dhcpv6;
+pub use dhcpv6::Dhcpv6Config;
+
+mod wsdiscovery;
+pub use wsdiscovery::WsdiscoveryConfig;
+
+mod fhrp;
+pub use fhrp::{vrrp, FhrpConfig};
+
+mod syslog;
+pub use syslog::SyslogConfig;
+
+mod dht;
+pub use dht::DhtConfig;
+use dht::DHT_PATTERN_QUERY;
+
+mod dns;
+pub use dns::DnsConfig;
+
+mod mdns;
+pub use mdns::MdnsConfig;
+
+mod nats;
+pub use nats::NatsConfig;
+
+mod nbns;
+pub use nbns::NbnsConfig;
+
+mod amqp;
+pub use amqp::AmqpConfig;
+use amqp::AMQP_PROTOCOL_HEADER;
+
+mod bacnet;
+pub use bacnet::BacnetConfig;
+
+mod bson;
+
+mod coap;
+pub use coap::CoapConfig;
+
+mod dnp3;
+pub use dnp3::Dnp3Config;
+
+mod enip;
+pub use enip::EnipConfig;
+
+mod finger;
+pub use finger::FingerConfig;
+
+mod gopher;
+pub use gopher::GopherConfig;
+
+mod iec104;
+pub use iec104::Iec104Config;
+
+mod ipmi;
+pub use ipmi::IpmiConfig;
+
+mod ike;
+pub use ike::IkeConfig;
+
+mod memcached;
+pub use memcached::MemcachedConfig;
+
+mod minecraft;
+pub use minecraft::MinecraftConfig;
+
+mod mongodb;
+pub use mongodb::MongodbConfig;
+
+mod mqtt;
+pub use mqtt::MqttConfig;
+use mqtt::{MQTT_PATTERN_CONNECT, MQTT_PATTERN_CONNECT_31};
+
+mod mssql;
+pub use mssql::MssqlConfig;
+use mssql::MSSQL_PATTERN_PRELOGIN;
+
+mod mysql;
+pub use mysql::MysqlConfig;
+
+mod postgres;
+pub use postgres::PostgresConfig;
+use postgres::{
+    POSTGRES_PATTERN_GSSENC_REQUEST, POSTGRES_PATTERN_SSL_REQUEST, POSTGRES_PATTERN_STARTUP,
+};
+
+mod rdp;
+pub use rdp::RdpConfig;
+use rdp::RDP_PATTERN_CONNECTION_REQUEST;
+
+mod redis;
+pub use redis::RedisConfig;
+use redis::REDIS_PATTERNS_ARRAY;
+
+mod gh0st;
+pub use gh0st::Gh0stConfig;
+use gh0st::GH0ST_PATTERN_TAG;
+
+mod git;
+pub use git::GitConfig;
+
+mod hint;
+pub use hint::HintsConfig;
+
+mod jdwp;
+pub use jdwp::JdwpConfig;
+use jdwp::JDWP_HANDSHAKE;
+
+mod kafka;
+pub use kafka::KafkaConfig;
+
+mod kerberos;
+pub use kerberos::KerberosConfig;
+
+mod openvpn;
+pub use openvpn::OpenvpnConfig;
+use openvpn::OPENVPN_PATTERN_HARD_RESET_TCP;
+
+mod l2tp;
+pub use l2tp::L2tpConfig;
+
+mod ldap;
+pub use ldap::LdapConfig;
+
+mod nfs;
+pub use nfs::NfsConfig;
+
+mod pptp;
+pub use pptp::PptpConfig;
+use pptp::PPTP_PATTERN_SCCRQ;
+
+mod radius;
+pub use radius::RadiusConfig;
+
+mod rmi;
+pub use rmi::RmiConfig;
+use rmi::RMI_PATTERN_HEADER;
+
+mod rpc;
+pub use rpc::RpcConfig;
+
+mod rsync;
+pub use rsync::RsyncConfig;
+
+mod rtsp;
+pub use rtsp::RtspConfig;
+use rtsp::RTSP_METHODS;
+
+mod s7;
+pub use s7::S7Config;
+
+mod sip;
+pub use sip::SipConfig;
+
+mod small_services;
+pub use small_services::SmallServicesConfig;
+
+mod steam;
+pub use steam::SteamConfig;
+use steam::A2S_PATTERN_INFO;
+
+mod tftp;
+pub use tftp::TftpConfig;
+
+mod tns;
+pub use tns::TnsConfig;
+
+mod tpkt;
+
+mod vnc;
+pub use vnc::VncConfig;
+
+mod whois;
+pub use whois::WhoisConfig;
+
+mod wireguard;
+pub use wireguard::WireguardConfig;
+
+mod snmp;
+pub use snmp::SnmpConfig;
+
+mod x11;
+pub use x11::X11Config;
+
+mod zabbix;
+pub use zabbix::ZabbixConfig;
+
+mod zlib;
+
 const PROTO_HTTP: usize = 1;
 const PROTO_STUN: usize = 2;
 const PROTO_SSH: usize = 3;
+const PROTO_TLS: usize = 4;
+const PROTO_DTLS: usize = 5;
+const PROTO_SMB: usize = 6;
+const PROTO_FTP: usize = 7;
+const PROTO_SMTP: usize = 8;
+const PROTO_POP3: usize = 9;
+const PROTO_IMAP: usize = 10;
+const PROTO_TELNET: usize = 11;
+const PROTO_DNS: usize = 12;
+const PROTO_MDNS: usize = 13;
+const PROTO_NBNS: usize = 14;
+const PROTO_SNMP: usize = 15;
+const PROTO_SIP: usize = 16;
+const PROTO_RTSP: usize = 17;
+const PROTO_RDP: usize = 18;
+const PROTO_VNC: usize = 19;
+const PROTO_MYSQL: usize = 20;
+const PROTO_POSTGRES: usize = 21;
+const PROTO_MSSQL: usize = 22;
+/* SQL Server Browser */
+const PROTO_SSRP: usize = 23;
+const PROTO_TNS: usize = 24;
+const PROTO_REDIS: usize = 25;
+const PROTO_MEMCACHED: usize = 26;
+const PROTO_MONGODB: usize = 27;
+const PROTO_AMQP: usize = 28;
+const PROTO_MQTT: usize = 29;
+const PROTO_COAP: usize = 30;
+const PROTO_S7: usize = 31;
+const PROTO_DNP3: usize = 32;
+const PROTO_BACNET: usize = 33;
+const PROTO_ENIP: usize = 34;
+const PROTO_IEC104: usize = 35;
+const PROTO_TFTP: usize = 36;
+const PROTO_RPC: usize = 37;
+const PROTO_KERBEROS: usize = 38;
+const PROTO_LDAP: usize = 39;
+const PROTO_RADIUS: usize = 40;
+const PROTO_TACACS: usize = 41;
+const PROTO_SOCKS: usize = 42;
+const PROTO_GIT: usize = 43;
+const PROTO_RSYNC: usize = 44;
+const PROTO_ZABBIX: usize = 45;
+const PROTO_NATS: usize = 46;
+const PROTO_KAFKA: usize = 47;
+const PROTO_IKE: usize = 48;
+const PROTO_OPENVPN: usize = 49;
+const PROTO_WIREGUARD: usize = 50;
+const PROTO_L2TP: usize = 51;
+const PROTO_PPTP: usize = 52;
+const PROTO_DHT: usize = 53;
+const PROTO_MINECRAFT: usize = 54;
+const PROTO_STEAM: usize = 55;
+const PROTO_SMALL_SERVICES: usize = 56;
+const PROTO_FINGER: usize = 57;
+const PROTO_GOPHER: usize = 58;
+const PROTO_WHOIS: usize = 59;
+const PROTO_X11: usize = 60;
+const PROTO_IPMI: usize = 61;
+const PROTO_DCERPC: usize = 62;
+const PROTO_ADB: usize = 63;
+const PROTO_JDWP: usize = 64;
+const PROTO_RMI: usize = 65;
+const PROTO_GH0ST: usize = 66;
+const PROTO_BITCOIN: usize = 67;
+const PROTO_TOR: usize = 68;
+const PROTO_DHCP: usize = 69;
+const PROTO_DHCPV6: usize = 70;
+const PROTO_WSDISCOVERY: usize = 71;
+const PROTO_HSRP: usize = 72;
+const PROTO_SYSLOG: usize = 73;
 
-struct TCPControlBlock {
-    proto_state: usize,
-}
+/* names of the protocols, as in the configuration (e.g., hints) */
+const PROTO_NAMES: [(&str, usize); 73] = [
+    ("http", PROTO_HTTP),
+    ("stun", PROTO_STUN),
+    ("ssh", PROTO_SSH),
+    ("tls", PROTO_TLS),
+    ("dtls", PROTO_DTLS),
+    ("smb", PROTO_SMB),
+    ("ftp", PROTO_FTP),
+    ("smtp", PROTO_SMTP),
+    ("pop3", PROTO_POP3),
+    ("imap", PROTO_IMAP),
+    ("telnet", PROTO_TELNET),
+    ("dns", PROTO_DNS),
+    ("mdns", PROTO_MDNS),
+    ("nbns", PROTO_NBNS),
+    ("snmp", PROTO_SNMP),
+    ("sip", PROTO_SIP),
+    ("rtsp", PROTO_RTSP),
+    ("rdp", PROTO_RDP),
+    ("vnc", PROTO_VNC),
+    ("mysql", PROTO_MYSQL),
+    ("postgres", PROTO_POSTGRES),
+    ("mssql", PROTO_MSSQL),
+    ("ssrp", PROTO_SSRP),
+    ("tns", PROTO_TNS),
+    ("redis", PROTO_REDIS),
+    ("memcached", PROTO_MEMCACHED),
+    ("mongodb", PROTO_MONGODB),
+    ("amqp", PROTO_AMQP),
+    ("mqtt", PROTO_MQTT),
+    ("coap", PROTO_COAP),
+    ("s7", PROTO_S7),
+    ("dnp3", PROTO_DNP3),
+    ("bacnet", PROTO_BACNET),
+    ("enip", PROTO_ENIP),
+    ("iec104", PROTO_IEC104),
+    ("tftp", PROTO_TFTP),
+    ("rpc", PROTO_RPC),
+    ("kerberos", PROTO_KERBEROS),
+    ("ldap", PROTO_LDAP),
+    ("radius", PROTO_RADIUS),
+    ("tacacs", PROTO_TACACS),
+    ("socks", PROTO_SOCKS),
+    ("git", PROTO_GIT),
+    ("rsync", PROTO_RSYNC),
+    ("zabbix", PROTO_ZABBIX),
+    ("nats", PROTO_NATS),
+    ("kafka", PROTO_KAFKA),
+    ("ike", PROTO_IKE),
+    ("openvpn", PROTO_OPENVPN),
+    ("wireguard", PROTO_WIREGUARD),
+    ("l2tp", PROTO_L2TP),
+    ("pptp", PROTO_PPTP),
+    ("dht", PROTO_DHT),
+    ("minecraft", PROTO_MINECRAFT),
+    ("steam", PROTO_STEAM),
+    ("small_services", PROTO_SMALL_SERVICES),
+    ("finger", PROTO_FINGER),
+    ("gopher", PROTO_GOPHER),
+    ("whois", PROTO_WHOIS),
+    ("x11", PROTO_X11),
+    ("ipmi", PROTO_IPMI),
+    ("dcerpc", PROTO_DCERPC),
+    ("adb", PROTO_ADB),
+    ("jdwp", PROTO_JDWP),
+    ("rmi", PROTO_RMI),
+    ("gh0st", PROTO_GH0ST),
+    ("bitcoin", PROTO_BITCOIN),
+    ("tor", PROTO_TOR),
+    ("dhcp", PROTO_DHCP),
+    ("dhcpv6", PROTO_DHCPV6),
+    ("wsdiscovery", PROTO_WSDISCOVERY),
+    ("hsrp", PROTO_HSRP),
+    ("syslog", PROTO_SYSLOG),
+];
+
+/* protocols that stay on a flow once identified (subsequent data is not
+ * looked for markers) */
+const PROTO_FLOW: [usize; 51] = [
+    PROTO_HTTP,
+    PROTO_STUN,
+    PROTO_SSH,
+    PROTO_SMB,
+    PROTO_FTP,
+    PROTO_SMTP,
+    PROTO_POP3,
+    PROTO_IMAP,
+    PROTO_TELNET,
+    PROTO_DNS,
+    PROTO_RTSP,
+    PROTO_VNC,
+    PROTO_MYSQL,
+    PROTO_POSTGRES,
+    PROTO_MSSQL,
+    PROTO_TNS,
+    PROTO_REDIS,
+    PROTO_MEMCACHED,
+    PROTO_MONGODB,
+    PROTO_AMQP,
+    PROTO_MQTT,
+    PROTO_S7,
+    PROTO_DNP3,
+    PROTO_ENIP,
+    PROTO_IEC104,
+    PROTO_RPC,
+    PROTO_KERBEROS,
+    PROTO_LDAP,
+    PROTO_TACACS,
+    PROTO_SOCKS,
+    PROTO_GIT,
+    PROTO_RSYNC,
+    PROTO_ZABBIX,
+    PROTO_NATS,
+    PROTO_KAFKA,
+    PROTO_OPENVPN,
+    PROTO_PPTP,
+    PROTO_MINECRAFT,
+    PROTO_SMALL_SERVICES,
+    PROTO_FINGER,
+    PROTO_GOPHER,
+    PROTO_WHOIS,
+    PROTO_X11,
+    PROTO_DCERPC,
+    PROTO_ADB,
+    PROTO_JDWP,
+    PROTO_RMI,
+    PROTO_GH0ST,
+    PROTO_BITCOIN,
+    PROTO_TOR,
+    PROTO_SYSLOG,
+];
+
+/* protocols only answering datagrams (their handlers are not given a
+ * flow, and some keep their own state in the flow table): they can only
+ * be hinted for UDP */
+const PROTO_DATAGRAM: [usize; 19] = [
+    PROTO_DTLS,
+    PROTO_MDNS,
+    PROTO_NBNS,
+    PROTO_SNMP,
+    PROTO_SSRP,
+    PROTO_COAP,
+    PROTO_BACNET,
+    PROTO_TFTP,
+    PROTO_RADIUS,
+    PROTO_IKE,
+    PROTO_WIREGUARD,
+    PROTO_L2TP,
+    PROTO_DHT,
+    PROTO_STEAM,
+    PROTO_IPMI,
+    PROTO_DHCP,
+    PROTO_DHCPV6,
+    PROTO_WSDISCOVERY,
+    PROTO_HSRP,
+];
 
 lazy_static! {
     static ref PROTO_SMACK: Smack = proto_init();
-    static ref CONTABLE: Mutex<HashMap<u32, TCPControlBlock>> = Mutex::new(HashMap::new());
 }
 
 fn proto_init() -> Smack {
     let mut smack = Smack::new("proto".to_string(), SMACK_CASE_SENSITIVE);
-    /* HTTP markers */
+    /* HTTP markers (sloppy clients may send lowercase verbs) */
     for (_, v) in HTTP_VERBS.iter().enumerate() {
         smack.add_pattern(
             format!("{} /", v).as_bytes(),
             PROTO_HTTP,
-            SmackFlags::ANCHOR_BEGIN,
+            SmackFlags::ANCHOR_BEGIN | SmackFlags::NOCASE,
         );
+        /* absolute URIs (proxy requests) */
+        for scheme in ["http", "https"].iter() {
+            smack.add_pattern(
+                format!("{} {}://", v, scheme).as_bytes(),
+                PROTO_HTTP,
+                SmackFlags::ANCHOR_BEGIN,
+            );
+        }
+    }
+    /* RTSP requests, with an absolute URI or * (on other ports than
+     * the RTSP ones) */
+    for m in RTSP_METHODS.iter() {
+        for scheme in ["rtsp", "rtsps"].iter() {
+            smack.add_pattern(
+                format!("{} {}://", m, scheme).as_bytes(),
+                PROTO_RTSP,
+                SmackFlags::ANCHOR_BEGIN,
+            );
+        }
     }
+    smack.add_pattern(b"OPTIONS * RTSP/", PROTO_RTSP, SmackFlags::ANCHOR_BEGIN);
+    /* CONNECT host:port (proxy requests) */
+    smack.add_pattern(b"CONNECT ", PROTO_HTTP, SmackFlags::ANCHOR_BEGIN);
     smack.add_pattern(
         STUN_PATTERN_MAGIC,
         PROTO_STUN,
@@ -76,6 +595,104 @@ fn proto_init() -> Smack {
         PROTO_SSH,
         SmackFlags::ANCHOR_BEGIN,
     );
+    for p in TLS_PATTERNS_CLIENT_HELLO.iter() {
+        smack.add_pattern(
+            *p,
+            PROTO_TLS,
+            SmackFlags::ANCHOR_BEGIN | SmackFlags::WILDCARDS,
+        );
+    }
+    /* SMB markers */
+    for p in [SMB1_PATTERN_NEGOTIATE, SMB2_PATTERN_NEGOTIATE].iter() {
+        smack.add_pattern(
+            *p,
+            PROTO_SMB,
+            SmackFlags::ANCHOR_BEGIN | SmackFlags::WILDCARDS,
+        );
+    }
+    smack.add_pattern(
+        NETBIOS_PATTERN_SESSION_REQUEST,
+        PROTO_SMB,
+        SmackFlags::ANCHOR_BEGIN | SmackFlags::WILDCARDS,
+    );
+    /* SMTP markers (e.g., after STARTTLS, the client speaks first) */
+    for p in SMTP_PATTERNS_HELLO.iter() {
+        smack.add_pattern(p, PROTO_SMTP, SmackFlags::ANCHOR_BEGIN | SmackFlags::NOCASE);
+    }
+    smack.add_pattern(
+        RDP_PATTERN_CONNECTION_REQUEST,
+        PROTO_RDP,
+        SmackFlags::ANCHOR_BEGIN | SmackFlags::WILDCARDS,
+    );
+    /* PostgreSQL markers */
+    for p in [
+        POSTGRES_PATTERN_SSL_REQUEST,
+        POSTGRES_PATTERN_GSSENC_REQUEST,
+        POSTGRES_PATTERN_STARTUP,
+    ]
+    .iter()
+    {
+        smack.add_pattern(
+            *p,
+            PROTO_POSTGRES,
+            SmackFlags::ANCHOR_BEGIN | SmackFlags::WILDCARDS,
+        );
+    }
+    /* Redis commands (inline commands are only identified by the
+     * port) */
+    for p in REDIS_PATTERNS_ARRAY.iter() {
+        smack.add_pattern(p, PROTO_REDIS, SmackFlags::ANCHOR_BEGIN);
+    }
+    smack.add_pattern(
+        &AMQP_PROTOCOL_HEADER[..4],
+        PROTO_AMQP,
+        SmackFlags::ANCHOR_BEGIN,
+    );
+    /* MQTT CONNECT (with a longer remaining length, only identified by
+     * the port) */
+    for p in [&MQTT_PATTERN_CONNECT[..], &MQTT_PATTERN_CONNECT_31[..]].iter() {
+        smack.add_pattern(
+            p,
+            PROTO_MQTT,
+            SmackFlags::ANCHOR_BEGIN | SmackFlags::WILDCARDS,
+        );
+    }
+    smack.add_pattern(
+        MSSQL_PATTERN_PRELOGIN,
+        PROTO_MSSQL,
+        SmackFlags::ANCHOR_BEGIN | SmackFlags::WILDCARDS,
+    );
+    /* DTLS markers */
+    for p in DTLS_PATTERNS_CLIENT_HELLO.iter() {
+        smack.add_pattern(
+            *p,
+            PROTO_DTLS,
+            SmackFlags::ANCHOR_BEGIN | SmackFlags::WILDCARDS,
+        );
+    }
+    /* OpenVPN over TCP (e.g., on 443) */
+    smack.add_pattern(
+        OPENVPN_PATTERN_HARD_RESET_TCP,
+        PROTO_OPENVPN,
+        SmackFlags::ANCHOR_BEGIN | SmackFlags::WILDCARDS,
+    );
+    smack.add_pattern(PPTP_PATTERN_SCCRQ, PROTO_PPTP, SmackFlags::ANCHOR_BEGIN);
+    /* DHT queries (e.g., on the port announced by a BitTorrent
+     * client) */
+    smack.add_pattern(DHT_PATTERN_QUERY, PROTO_DHT, SmackFlags::ANCHOR_BEGIN);
+    smack.add_pattern(A2S_PATTERN_INFO, PROTO_STEAM, SmackFlags::ANCHOR_BEGIN);
+    smack.add_pattern(DCERPC_PATTERN_BIND, PROTO_DCERPC, SmackFlags::ANCHOR_BEGIN);
+    smack.add_pattern(
+        ADB_PATTERN_CNXN,
+        PROTO_ADB,
+        SmackFlags::ANCHOR_BEGIN | SmackFlags::WILDCARDS,
+    );
+    smack.add_pattern(JDWP_HANDSHAKE, PROTO_JDWP, SmackFlags::ANCHOR_BEGIN);
+    smack.add_pattern(RMI_PATTERN_HEADER, PROTO_RMI, SmackFlags::ANCHOR_BEGIN);
+    smack.add_pattern(GH0ST_PATTERN_TAG, PROTO_GH0ST, SmackFlags::ANCHOR_BEGIN);
+    for p in BTC_PATTERNS_VERSION.iter() {
+        smack.add_pattern(*p, PROTO_BITCOIN, SmackFlags::ANCHOR_BEGIN);
+    }
     smack.compile();
     smack
 }
@@ -83,47 +700,546 @@ fn proto_init() -> Smack {
 pub fn repl<'a>(
     data: &'a [u8],
     masscanned: &Masscanned,
-    mut client_info: &mut ClientInfo,
+    client_info: &mut ClientInfo,
 ) -> Option<Vec<u8>> {
     debug!("packet payload: {:?}", data);
-    let mut id;
-    if client_info.transport == Some(IpNextHeaderProtocols::Tcp) && client_info.cookie == None {
+    if client_info.transport == Some(IpNextHeaderProtocols::Tcp) && client_info.cookie.is_none() {
         error!("Unexpected empty cookie");
         return None;
-    } else if client_info.cookie != None {
+    }
+    if let Some(cookie) = client_info.cookie {
         /* proto over TCP */
-        let cookie = client_info.cookie.unwrap();
         let mut ct = CONTABLE.lock().unwrap();
-        if !ct.contains_key(&cookie) {
-            ct.insert(
-                cookie,
-                TCPControlBlock {
-                    proto_state: BASE_STATE,
-                },
-            );
+        let tcb = ct.get(cookie, &masscanned.config.tcp);
+        return dispatch(data, masscanned, client_info, Some(tcb));
+    }
+    /* proto over else (e.g., UDP) */
+    if destination_allowed(masscanned, client_info, false)
+        && proto_by_config(masscanned, client_info, data, false) == Some(PROTO_TFTP)
+    {
+        /* TFTP transfers are kept in the flow table too: as for TCP,
+         * the table is locked here, and the handler given its entry */
+        let cookie = tftp::flow_cookie(masscanned, client_info)?;
+        let mut ct = CONTABLE.lock().unwrap();
+        let tcb = ct.get(cookie, &masscanned.config.tcp);
+        return tftp::repl(data, masscanned, client_info, tcb);
+    }
+    dispatch(data, masscanned, client_info, None)
+}
+
+/* Server-first protocols (e.g., FTP): the server speaks first, once the
+ * TCP handshake is completed. The protocol is chosen from the
+ * destination port, and the greeting is only sent on a flow that has
+ * seen nothing yet.
+ **/
+pub fn greeting(masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    let cookie = client_info.cookie?;
+    let port = client_info.port.dst?;
+    let config = &masscanned.config;
+    let id = if config.tls.implicit(port).is_some() {
+        /* implicit TLS: the client speaks first (ClientHello), the
+         * greeting comes once the handshake is completed */
+        return None;
+    } else if config.ftp.ports.contains(&port) {
+        PROTO_FTP
+    } else if config.smtp.ports.contains(&port) {
+        PROTO_SMTP
+    } else if config.pop3.ports.contains(&port) {
+        PROTO_POP3
+    } else if config.imap.ports.contains(&port) {
+        PROTO_IMAP
+    } else if config.telnet.ports.contains(&port) {
+        PROTO_TELNET
+    } else if config.vnc.ports.contains(&port) {
+        PROTO_VNC
+    } else if config.mysql.ports.contains(&port) {
+        PROTO_MYSQL
+    } else if config.rsync.ports.contains(&port) {
+        PROTO_RSYNC
+    } else if config.nats.ports.contains(&port) {
+        PROTO_NATS
+    } else if config.small_services.speaks_first(port) {
+        PROTO_SMALL_SERVICES
+    } else {
+        return None;
+    };
+    let mut ct = CONTABLE.lock().unwrap();
+    let tcb = ct.get(cookie, &config.tcp);
+    if tcb.proto_id != NO_MATCH || tcb.smack_state != BASE_STATE || tcb.sent != 0 {
+        return None;
+    }
+    tcb.proto_id = id;
+    proto_greeting(id, masscanned, client_info, tcb)
+}
+
+/* greeting of a server-first protocol (None for the others) */
+pub(crate) fn proto_greeting(
+    id: usize,
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: &mut TCPControlBlock,
+) -> Option<Vec<u8>> {
+    match id {
+        PROTO_FTP => ftp::greeting(masscanned, tcb),
+        PROTO_SMTP => smtp::greeting(masscanned, tcb),
+        PROTO_POP3 => pop3::greeting(masscanned, tcb),
+        PROTO_IMAP => imap::greeting(masscanned, tcb),
+        PROTO_TELNET => telnet::greeting(masscanned, tcb),
+        PROTO_VNC => vnc::greeting(masscanned, tcb),
+        PROTO_MYSQL => mysql::greeting(masscanned, tcb),
+        PROTO_RSYNC => rsync::greeting(masscanned, tcb),
+        PROTO_NATS => nats::greeting(masscanned, client_info, tcb),
+        PROTO_SMALL_SERVICES => small_services::greeting(masscanned, client_info, tcb),
+        _ => None,
+    }
+}
+
+/* Protocol hinted for the destination port: the first one that takes
+ * data (its markers are found, or it has none), NO_MATCH when none
+ * does (or the port has no hint), None when the port is strict and no
+ * protocol takes data (nothing answers).
+ **/
+fn proto_by_hint(masscanned: &Masscanned, client_info: &ClientInfo, data: &[u8]) -> Option<usize> {
+    let hint = match client_info.port.dst.and_then(|p| {
+        let tcp = client_info.transport == Some(IpNextHeaderProtocols::Tcp);
+        masscanned.config.hints.get(p, tcp)
+    }) {
+        Some(h) => h,
+        None => return Some(NO_MATCH),
+    };
+    let mut found = Vec::new();
+    let mut state = BASE_STATE;
+    let mut i = 0;
+    while i < data.len() {
+        let id = PROTO_SMACK.search_next(&mut state, data, &mut i);
+        if id == NO_MATCH {
+            break;
+        }
+        found.push(id);
+    }
+    if client_info.transport != Some(IpNextHeaderProtocols::Tcp) {
+        found.push(PROTO_SMACK.search_next_end(&mut state));
+    }
+    for id in hint.protocols.iter().filter_map(|p| hint::proto_id(p)) {
+        if found.contains(&id) || !PROTO_SMACK.has_pattern(id) {
+            return Some(id);
+        }
+    }
+    if hint.strict {
+        debug!("no hinted protocol for port {}", hint.port);
+        return None;
+    }
+    Some(NO_MATCH)
+}
+
+/* protocol that can answer a whole flow (e.g., the decrypted content
+ * of a TLS session), by its name */
+pub(crate) fn proto_flow_id(name: &str) -> Option<usize> {
+    hint::proto_id(name).filter(|id| PROTO_FLOW.contains(id))
+}
+
+/* Protocols only identified behind TLS (the decrypted content of a
+ * session), on their ports: Tor OR connections (by their VERSIONS
+ * cell) and DNS over TLS.
+ **/
+pub(crate) fn proto_behind_tls(
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+    data: &[u8],
+) -> usize {
+    if tor::is_or_handshake(data, masscanned, client_info) {
+        PROTO_TOR
+    } else if client_info
+        .port
+        .dst
+        .is_some_and(|p| masscanned.config.dns.tls_ports.contains(&p))
+    {
+        PROTO_DNS
+    } else {
+        NO_MATCH
+    }
+}
+
+/* Protocols without markers, identified by the destination port (a
+ * random transaction ID could match other patterns): over TCP and UDP
+ * (e.g., DNS), over TCP only (e.g., RTSP) or over UDP only (e.g., NBNS).
+ **/
+fn proto_by_port(masscanned: &Masscanned, client_info: &ClientInfo, tcp: bool) -> usize {
+    let config = &masscanned.config;
+    let port = match client_info.port.dst {
+        Some(p) => p,
+        None => return NO_MATCH,
+    };
+    if !tcp && mdns::is_mdns(client_info) {
+        PROTO_MDNS
+    } else if tcp && config.tls.implicit(port).is_some() {
+        PROTO_TLS
+    } else if config.dns.ports.contains(&port) {
+        PROTO_DNS
+    } else if config.memcached.ports.contains(&port) {
+        PROTO_MEMCACHED
+    } else if config.dnp3.ports.contains(&port) {
+        PROTO_DNP3
+    } else if config.enip.ports.contains(&port) {
+        PROTO_ENIP
+    } else if config.rpc.ports.contains(&port) || config.rpc.is_mapped(port, tcp) {
+        PROTO_RPC
+    } else if config.kerberos.ports.contains(&port) {
+        PROTO_KERBEROS
+    } else if config.stun.ports.contains(&port) {
+        PROTO_STUN
+    } else if config.openvpn.ports.contains(&port) {
+        PROTO_OPENVPN
+    } else if config.small_services.service(port).is_some() {
+        PROTO_SMALL_SERVICES
+    } else if tcp && config.rtsp.ports.contains(&port) {
+        PROTO_RTSP
+    } else if tcp && config.tns.ports.contains(&port) {
+        PROTO_TNS
+    } else if tcp && config.redis.ports.contains(&port) {
+        PROTO_REDIS
+    } else if tcp && config.mongodb.ports.contains(&port) {
+        PROTO_MONGODB
+    } else if tcp && config.amqp.ports.contains(&port) {
+        PROTO_AMQP
+    } else if tcp && config.mqtt.ports.contains(&port) {
+        PROTO_MQTT
+    } else if tcp && config.s7.ports.contains(&port) {
+        /* before the RDP marker (any X.224 Connection Request) */
+        PROTO_S7
+    } else if tcp && config.kafka.ports.contains(&port) {
+        PROTO_KAFKA
+    } else if tcp && config.zabbix.ports.contains(&port) {
+        PROTO_ZABBIX
+    } else if tcp && config.git.ports.contains(&port) {
+        PROTO_GIT
+    } else if tcp && config.socks.ports.contains(&port) {
+        PROTO_SOCKS
+    } else if tcp && config.tacacs.ports.contains(&port) {
+        PROTO_TACACS
+    } else if tcp && config.ldap.ports.contains(&port) {
+        PROTO_LDAP
+    } else if tcp && config.iec104.ports.contains(&port) {
+        PROTO_IEC104
+    } else if tcp && config.pptp.ports.contains(&port) {
+        PROTO_PPTP
+    } else if tcp && config.minecraft.ports.contains(&port) {
+        PROTO_MINECRAFT
+    } else if tcp && config.finger.ports.contains(&port) {
+        PROTO_FINGER
+    } else if tcp && config.gopher.ports.contains(&port) {
+        PROTO_GOPHER
+    } else if tcp && config.whois.ports.contains(&port) {
+        PROTO_WHOIS
+    } else if tcp && config.x11.ports.contains(&port) {
+        PROTO_X11
+    } else if tcp && config.dcerpc.ports.contains(&port) {
+        PROTO_DCERPC
+    } else if tcp && config.adb.ports.contains(&port) {
+        PROTO_ADB
+    } else if tcp && config.jdwp.ports.contains(&port) {
+        PROTO_JDWP
+    } else if tcp && config.rmi.ports.contains(&port) {
+        PROTO_RMI
+    } else if tcp && config.gh0st.ports.contains(&port) {
+        PROTO_GH0ST
+    } else if tcp && config.bitcoin.ports.contains(&port) {
+        PROTO_BITCOIN
+    } else if tcp && config.syslog.tcp_ports.contains(&port) {
+        PROTO_SYSLOG
+    } else if tcp {
+        NO_MATCH
+    } else if config.nbns.ports.contains(&port) {
+        PROTO_NBNS
+    } else if config.snmp.ports.contains(&port) {
+        PROTO_SNMP
+    } else if config.sip.ports.contains(&port) {
+        PROTO_SIP
+    } else if config.mssql.browser_ports.contains(&port) {
+        PROTO_SSRP
+    } else if config.coap.ports.contains(&port) {
+        PROTO_COAP
+    } else if config.bacnet.ports.contains(&port) {
+        PROTO_BACNET
+    } else if config.enip.udp_ports.contains(&port) {
+        PROTO_ENIP
+    } else if config.tftp.ports.contains(&port) {
+        PROTO_TFTP
+    } else if config.radius.ports.contains(&port) {
+        PROTO_RADIUS
+    } else if config.ike.ports.contains(&port) || config.ike.nat_t_ports.contains(&port) {
+        PROTO_IKE
+    } else if config.wireguard.ports.contains(&port) {
+        PROTO_WIREGUARD
+    } else if config.l2tp.ports.contains(&port) {
+        PROTO_L2TP
+    } else if config.dht.ports.contains(&port) {
+        PROTO_DHT
+    } else if config.steam.ports.contains(&port) {
+        PROTO_STEAM
+    } else if config.ipmi.ports.contains(&port) {
+        PROTO_IPMI
+    } else if config.syslog.ports.contains(&port) {
+        PROTO_SYSLOG
+    } else if dhcp::is_dhcp(masscanned, client_info) {
+        PROTO_DHCP
+    } else if dhcpv6::is_dhcpv6(masscanned, client_info) {
+        PROTO_DHCPV6
+    } else if wsdiscovery::is_wsdiscovery(masscanned, client_info) {
+        PROTO_WSDISCOVERY
+    } else if fhrp::is_hsrp(masscanned, client_info) {
+        PROTO_HSRP
+    } else {
+        NO_MATCH
+    }
+}
+
+/* protocol chosen by the hints, then by the ports (NO_MATCH: to be
+ * identified by its markers) */
+fn proto_by_config(
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+    data: &[u8],
+    tcp: bool,
+) -> Option<usize> {
+    let id = proto_by_hint(masscanned, client_info, data)?;
+    if id == NO_MATCH {
+        return Some(proto_by_port(masscanned, client_info, tcp));
+    }
+    Some(id)
+}
+
+/* multicast and broadcast destinations are only answered by the
+ * protocols made for them (mDNS, DHCPv6 and WS-Discovery, to their
+ * groups, and DHCP), or observed (HSRP) */
+fn destination_allowed(masscanned: &Masscanned, client_info: &ClientInfo, flow: bool) -> bool {
+    if client_info.ip.dst.is_some_and(|ip| ip.is_multicast()) {
+        return !flow
+            && (mdns::is_mdns(client_info)
+                || dhcpv6::is_dhcpv6(masscanned, client_info)
+                || wsdiscovery::is_wsdiscovery(masscanned, client_info)
+                || fhrp::is_hsrp(masscanned, client_info));
+    }
+    if client_info.ip.dst == Some(IpAddr::V4(Ipv4Addr::BROADCAST)) {
+        return !flow && dhcp::is_dhcp(masscanned, client_info);
+    }
+    true
+}
+
+/* Identify the protocol of data and answer: over a stream (tcb is set,
+ * e.g., a TCP flow or the decrypted content of a TLS session), the
+ * protocol is identified once for the whole flow.
+ **/
+pub(crate) fn dispatch(
+    data: &[u8],
+    masscanned: &Masscanned,
+    mut client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    let mut id;
+    if !destination_allowed(masscanned, client_info, tcb.is_some()) {
+        return None;
+    }
+    if let Some(ref mut t) = tcb {
+        /* flow upgraded to another protocol (e.g., HTTP to WebSocket) */
+        if let ProtoState::WebSocket(ws) = &mut t.proto_state {
+            return websocket::repl(data, masscanned, client_info, ws);
+        }
+        /* rest of a TLS ClientHello, or TLS session */
+        if let ProtoState::Tls(_) | ProtoState::TlsSession(_) = t.proto_state {
+            return tls::repl(data, masscanned, client_info, Some(t));
+        }
+        if PROTO_FLOW.contains(&t.proto_id) {
+            /* rest of the flow: HTTP keep-alive, SSH binary packets, SMB
+             * messages, FTP, SMTP, POP3 and IMAP commands, telnet
+             * sessions, DNS messages, RTSP requests, VNC, MySQL,
+             * PostgreSQL, TDS and TNS handshakes, Redis, memcached and
+             * MongoDB commands, AMQP connections, MQTT sessions, S7
+             * connections, DNP3 frames, EtherNet/IP messages, IEC 104
+             * connections, RPC records, Kerberos and LDAP
+             * messages, TACACS+ sessions, SOCKS negotiations and
+             * tunnels, git pkt-lines, rsync sessions, Zabbix
+             * requests, NATS connections, Kafka requests, STUN
+             * messages, OpenVPN packets, PPTP control
+             * connections, Minecraft pings, echo and chargen
+             * connections, finger queries, gopher selectors,
+             * WHOIS queries, X11 connections, DCE/RPC
+             * associations, ADB connections, JDWP
+             * packets, RMI calls, Gh0st packets, Bitcoin
+             * messages, Tor OR cells, syslog messages */
+            id = t.proto_id;
+        } else {
+            id = NO_MATCH;
+            if t.proto_id == NO_MATCH && t.smack_state == BASE_STATE {
+                id = proto_by_config(masscanned, client_info, data, true)?;
+            }
+            if id == NO_MATCH {
+                let mut i = 0;
+                id = PROTO_SMACK.search_next(&mut t.smack_state, &data.to_vec(), &mut i);
+            }
+            if id != NO_MATCH {
+                t.proto_id = id;
+            }
         }
-        let mut i = 0;
-        let mut tcb = ct.get_mut(&cookie).unwrap();
-        let mut state = tcb.proto_state;
-        id = PROTO_SMACK.search_next(&mut state, &data.to_vec(), &mut i);
-        tcb.proto_state = state;
     } else {
-        /* proto over else (e.g., UDP) */
-        let mut i = 0;
-        let mut state = BASE_STATE;
-        id = PROTO_SMACK.search_next(&mut state, &data.to_vec(), &mut i);
-        /* because we are not over TCP, we can afford to assume end of pattern */
+        id = proto_by_config(masscanned, client_info, data, false)?;
         if id == NO_MATCH {
-            id = PROTO_SMACK.search_next_end(&mut state);
+            let mut i = 0;
+            let mut state = BASE_STATE;
+            id = PROTO_SMACK.search_next(&mut state, &data.to_vec(), &mut i);
+            /* because we are not over TCP, we can afford to assume end of pattern */
+            if id == NO_MATCH {
+                id = PROTO_SMACK.search_next_end(&mut state);
+            }
         }
     }
-    /* proto over else (e.g., UDP) */
     if id == PROTO_HTTP {
-        return http::repl(data, masscanned, client_info);
+        return http::repl(data, masscanned, client_info, tcb);
     } else if id == PROTO_STUN {
         return stun::repl(data, masscanned, &mut client_info);
     } else if id == PROTO_SSH {
-        return ssh::repl(data, masscanned, &mut client_info);
+        return ssh::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_TLS {
+        return tls::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_SMB {
+        return smb::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_DTLS {
+        return dtls::repl(data, masscanned, client_info);
+    } else if id == PROTO_FTP {
+        return ftp::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_SMTP {
+        return smtp::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_POP3 {
+        return pop3::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_IMAP {
+        return imap::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_TELNET {
+        return telnet::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_DNS {
+        return dns::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_MDNS {
+        return mdns::repl(data, masscanned, client_info);
+    } else if id == PROTO_NBNS {
+        return nbns::repl(data, masscanned, client_info);
+    } else if id == PROTO_SNMP {
+        return snmp::repl(data, masscanned, client_info);
+    } else if id == PROTO_SIP {
+        return sip::repl(data, masscanned, client_info);
+    } else if id == PROTO_RTSP {
+        return rtsp::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_RDP {
+        return rdp::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_VNC {
+        return vnc::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_MYSQL {
+        return mysql::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_POSTGRES {
+        return postgres::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_MSSQL {
+        return mssql::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_SSRP {
+        return mssql::browser_repl(data, masscanned, client_info);
+    } else if id == PROTO_TNS {
+        return tns::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_REDIS {
+        return redis::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_MEMCACHED {
+        return memcached::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_MONGODB {
+        return mongodb::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_AMQP {
+        return amqp::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_MQTT {
+        return mqtt::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_S7 {
+        return s7::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_DNP3 {
+        return dnp3::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_ENIP {
+        return enip::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_IEC104 {
+        return iec104::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_TFTP {
+        /* transfers need a control block (see repl) */
+        return tftp::repl(data, masscanned, client_info, tcb?);
+    } else if id == PROTO_RPC {
+        return rpc::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_KERBEROS {
+        return kerberos::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_LDAP {
+        return ldap::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_RADIUS {
+        return radius::repl(data, masscanned, client_info);
+    } else if id == PROTO_IKE {
+        return ike::repl(data, masscanned, client_info);
+    } else if id == PROTO_WIREGUARD {
+        return wireguard::repl(data, masscanned, client_info);
+    } else if id == PROTO_L2TP {
+        return l2tp::repl(data, masscanned, client_info);
+    } else if id == PROTO_DHT {
+        return dht::repl(data, masscanned, client_info);
+    } else if id == PROTO_STEAM {
+        return steam::repl(data, masscanned, client_info);
+    } else if id == PROTO_IPMI {
+        return ipmi::repl(data, masscanned, client_info);
+    } else if id == PROTO_TACACS {
+        return tacacs::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_SOCKS {
+        return socks::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_GIT {
+        return git::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_RSYNC {
+        return rsync::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_ZABBIX {
+        return zabbix::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_NATS {
+        return nats::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_KAFKA {
+        return kafka::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_OPENVPN {
+        return openvpn::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_PPTP {
+        return pptp::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_MINECRAFT {
+        return minecraft::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_FINGER {
+        return finger::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_GOPHER {
+        return gopher::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_WHOIS {
+        return whois::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_X11 {
+        return x11::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_DCERPC {
+        return dcerpc::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_ADB {
+        return adb::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_JDWP {
+        return jdwp::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_RMI {
+        return rmi::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_GH0ST {
+        return gh0st::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_BITCOIN {
+        return bitcoin::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_SYSLOG {
+        return syslog::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_TOR {
+        return tor::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_SMALL_SERVICES {
+        return small_services::repl(data, masscanned, client_info, tcb);
+    } else if id == PROTO_COAP {
+        return coap::repl(data, masscanned, client_info);
+    } else if id == PROTO_BACNET {
+        return bacnet::repl(data, masscanned, client_info);
+    } else if id == PROTO_DHCP {
+        return dhcp::repl(data, masscanned, client_info);
+    } else if id == PROTO_DHCPV6 {
+        return dhcpv6::repl(data, masscanned, client_info);
+    } else if id == PROTO_WSDISCOVERY {
+        return wsdiscovery::repl(data, masscanned, client_info);
+    } else if id == PROTO_HSRP {
+        fhrp::hsrp(data, masscanned, client_info);
     } else {
         debug!("id: {}", id);
     }
@@ -133,6 +1249,8 @@ pub fn repl<'a>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
     use std::collections::HashSet;
     use std::net::{IpAddr, Ipv4Addr};
     use std::str::FromStr;
@@ -154,6 +1272,7 @@ mod tests {
             mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
             iface: None,
             ip_addresses: Some(&ips),
+            config: Config::default(),
         };
         /***** TEST STUN - MAGIC *****/
         /* test payload is:
@@ -213,6 +1332,7 @@ mod tests {
             mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
             iface: None,
             ip_addresses: Some(&ips),
+            config: Config::default(),
         };
         /***** TEST SSH *****/
         let payloads = [
@@ -236,4 +1356,25 @@ mod tests {
             };
         }
     }
+
+    #[test]
+    fn test_proto_dispatch_http() {
+        let mut client_info = ClientInfo::new();
+        let masscanned = masscanned(Config::default());
+        let payloads = [
+            "GET / HTTP/1.1\r\n\r\n",
+            "GET http://example.com/ HTTP/1.1\r\n\r\n",
+            "POST https://example.com/ HTTP/1.1\r\n\r\n",
+            "CONNECT example.com:443 HTTP/1.1\r\n\r\n",
+            /* verbs in any case */
+            "get / http/1.0\r\n\r\n",
+            "Head / HTTP/1.0\r\n\r\n",
+        ];
+        for payload in payloads.iter() {
+            match repl(payload.as_bytes(), &masscanned, &mut client_info) {
+                Some(answer) => assert!(answer.starts_with(b"HTTP/1.1 ")),
+                None => panic!("expected an answer, got nothing"),
+            }
+        }
+    }
 }
@@ -0,0 +1,488 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::bson::{bson_decode, bson_encode, bson_get, bson_to_json, Bson, BsonDocument};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* opcodes */
+const MONGODB_OP_REPLY: i32 = 1;
+const MONGODB_OP_QUERY: i32 = 2004;
+const MONGODB_OP_MSG: i32 = 2013;
+
+/* OP_MSG flags: checksum present */
+const MONGODB_MSG_CHECKSUM: u32 = 0x0000_0001;
+
+/* maximum size of the messages received */
+const MONGODB_MAX_MESSAGE: usize = 1 << 20;
+
+/* MongoDB server (standalone): isMaster, hello and buildInfo are
+ * answered (with version and max_wire_version), other commands get a
+ * "no such command" error.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MongodbConfig {
+    pub ports: Vec<u16>,
+    pub version: String,
+    pub max_wire_version: i32,
+}
+
+impl Default for MongodbConfig {
+    fn default() -> Self {
+        MongodbConfig {
+            ports: vec![27017],
+            version: "7.0.5".to_string(),
+            /* MongoDB 7.0 */
+            max_wire_version: 21,
+        }
+    }
+}
+
+impl MongodbConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if mongodb_version_array(&self.version).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("mongodb: invalid version: {:?}", self.version),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* versionArray of buildInfo: major, minor, patch, 0 */
+fn mongodb_version_array(version: &str) -> Option<Vec<Bson>> {
+    let mut parts = version
+        .split('.')
+        .map(|p| p.parse::<i32>().ok().map(Bson::Int32))
+        .collect::<Option<Vec<Bson>>>()?;
+    if parts.len() != 3 {
+        return None;
+    }
+    parts.push(Bson::Int32(0));
+    Some(parts)
+}
+
+/* state of a MongoDB flow: data received, id of the connection */
+pub struct MongodbState {
+    buffer: Vec<u8>,
+    connection_id: i32,
+    request_id: i32,
+}
+
+impl MongodbState {
+    fn new() -> Self {
+        MongodbState {
+            buffer: Vec::new(),
+            connection_id: 1 + (rand::random::<u16>() % 10000) as i32,
+            request_id: rand::random::<u16>() as i32,
+        }
+    }
+}
+
+/* command of a message: database and document, None when the message
+ * is not a command (or invalid) */
+fn mongodb_command(opcode: i32, body: &[u8]) -> Option<(String, BsonDocument)> {
+    match opcode {
+        MONGODB_OP_QUERY => {
+            /* flags, full collection name, number to skip and to
+             * return, query */
+            let name_len = body.get(4..)?.iter().position(|c| *c == 0)?;
+            let collection = String::from_utf8_lossy(&body[4..4 + name_len]).to_string();
+            let (database, collection) = collection.split_once('.')?;
+            if collection != "$cmd" {
+                return None;
+            }
+            let (doc, _) = bson_decode(body.get(4 + name_len + 1 + 8..)?)?;
+            /* wrapped queries (with a read preference) */
+            let doc = match bson_get(&doc, "$query") {
+                Some(Bson::Document(d)) => d.clone(),
+                _ => doc,
+            };
+            Some((database.to_string(), doc))
+        }
+        MONGODB_OP_MSG => {
+            let flags = u32::from_le_bytes([*body.first()?, body[1], body[2], body[3]]);
+            let end = if flags & MONGODB_MSG_CHECKSUM != 0 {
+                body.len().checked_sub(4)?
+            } else {
+                body.len()
+            };
+            let sections = body.get(4..end)?;
+            /* the body (section kind 0) holds the command */
+            let mut offset = 0;
+            while offset < sections.len() {
+                match sections[offset] {
+                    0 => {
+                        let (doc, _) = bson_decode(&sections[offset + 1..])?;
+                        let database = match bson_get(&doc, "$db") {
+                            Some(Bson::String(s)) => s.clone(),
+                            _ => "admin".to_string(),
+                        };
+                        return Some((database, doc));
+                    }
+                    1 => {
+                        let len = i32::from_le_bytes([
+                            *sections.get(offset + 1)?,
+                            *sections.get(offset + 2)?,
+                            *sections.get(offset + 3)?,
+                            *sections.get(offset + 4)?,
+                        ]);
+                        if len < 4 {
+                            return None;
+                        }
+                        offset += 1 + len as usize;
+                    }
+                    _ => return None,
+                }
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+fn mongodb_event(
+    command: &str,
+    database: &str,
+    opcode: i32,
+    doc: &[(String, Bson)],
+    client_info: &ClientInfo,
+) -> Event {
+    let mut event = Event::new("mongodb_command", client_info);
+    event.set("command", command.to_string());
+    event.set("database", database.to_string());
+    event.set("opcode", opcode);
+    if let Some(Bson::Document(client)) = bson_get(doc, "client") {
+        event.set("client", bson_to_json(client));
+    }
+    event
+}
+
+fn mongodb_answer(command: &str, config: &MongodbConfig, state: &MongodbState) -> BsonDocument {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64);
+    let field = |k: &str, v: Bson| (k.to_string(), v);
+    match command.to_ascii_lowercase().as_str() {
+        "ismaster" | "hello" => vec![
+            if command == "hello" {
+                field("isWritablePrimary", Bson::Bool(true))
+            } else {
+                field("ismaster", Bson::Bool(true))
+            },
+            field(
+                "topologyVersion",
+                Bson::Document(vec![
+                    field("processId", Bson::ObjectId(rand::random::<[u8; 12]>())),
+                    field("counter", Bson::Int64(0)),
+                ]),
+            ),
+            field("maxBsonObjectSize", Bson::Int32(16 * 1024 * 1024)),
+            field("maxMessageSizeBytes", Bson::Int32(48_000_000)),
+            field("maxWriteBatchSize", Bson::Int32(100_000)),
+            field("localTime", Bson::DateTime(now)),
+            field("logicalSessionTimeoutMinutes", Bson::Int32(30)),
+            field("connectionId", Bson::Int32(state.connection_id)),
+            field("minWireVersion", Bson::Int32(0)),
+            field("maxWireVersion", Bson::Int32(config.max_wire_version)),
+            field("readOnly", Bson::Bool(false)),
+            field("ok", Bson::Double(1.0)),
+        ],
+        "buildinfo" => vec![
+            field("version", Bson::String(config.version.clone())),
+            field(
+                "gitVersion",
+                Bson::String("202ad4fda2618c652e35f5981ef2f903d8dd1f1a".to_string()),
+            ),
+            field("modules", Bson::Array(Vec::new())),
+            field("allocator", Bson::String("tcmalloc".to_string())),
+            field("javascriptEngine", Bson::String("mozjs".to_string())),
+            field("sysInfo", Bson::String("deprecated".to_string())),
+            field(
+                "versionArray",
+                Bson::Array(mongodb_version_array(&config.version).unwrap_or_default()),
+            ),
+            field("bits", Bson::Int32(64)),
+            field("debug", Bson::Bool(false)),
+            field("maxBsonObjectSize", Bson::Int32(16 * 1024 * 1024)),
+            field("ok", Bson::Double(1.0)),
+        ],
+        _ => vec![
+            field("ok", Bson::Double(0.0)),
+            field(
+                "errmsg",
+                Bson::String(format!("no such command: '{}'", command)),
+            ),
+            field("code", Bson::Int32(59)),
+            field("codeName", Bson::String("CommandNotFound".to_string())),
+        ],
+    }
+}
+
+/* reply to a request, in the format of the request (OP_MSG, or
+ * OP_REPLY for OP_QUERY) */
+fn mongodb_reply(
+    opcode: i32,
+    response_to: i32,
+    doc: &BsonDocument,
+    state: &mut MongodbState,
+) -> Vec<u8> {
+    let mut body = Vec::new();
+    let reply_opcode = if opcode == MONGODB_OP_MSG {
+        /* flags, body section */
+        body.extend_from_slice(&[0, 0, 0, 0, 0]);
+        MONGODB_OP_MSG
+    } else {
+        /* response flags, cursor id, starting from, number returned */
+        body.extend_from_slice(&[0; 16]);
+        body.extend_from_slice(&1i32.to_le_bytes());
+        MONGODB_OP_REPLY
+    };
+    body.extend(bson_encode(doc));
+    state.request_id = state.request_id.wrapping_add(1);
+    let mut repl = ((body.len() + 16) as i32).to_le_bytes().to_vec();
+    repl.extend_from_slice(&state.request_id.to_le_bytes());
+    repl.extend_from_slice(&response_to.to_le_bytes());
+    repl.extend_from_slice(&reply_opcode.to_le_bytes());
+    repl.extend(body);
+    repl
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving MongoDB data");
+    let config = &masscanned.config.mongodb;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Mongodb(s)) => s,
+        _ => MongodbState::new(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while !client_info.close && state.buffer.len() >= 16 {
+        let header = &state.buffer[..16];
+        let len = i32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        if len < 16 || len as usize > MONGODB_MAX_MESSAGE {
+            info!("invalid MongoDB message length ({}) - dropped", len);
+            client_info.close = true;
+            break;
+        }
+        let len = len as usize;
+        if state.buffer.len() < len {
+            break;
+        }
+        let message = state.buffer.drain(..len).collect::<Vec<u8>>();
+        let request_id = i32::from_le_bytes([message[4], message[5], message[6], message[7]]);
+        let opcode = i32::from_le_bytes([message[12], message[13], message[14], message[15]]);
+        let (database, doc) = match mongodb_command(opcode, &message[16..]) {
+            Some((d, doc)) if !doc.is_empty() => (d, doc),
+            _ => {
+                info!("MongoDB message not handled (opcode {})", opcode);
+                client_info.close = true;
+                break;
+            }
+        };
+        let command = doc[0].0.clone();
+        warn!("MongoDB command: {}", command);
+        mongodb_event(&command, &database, opcode, &doc, client_info).log();
+        let answer = mongodb_answer(&command, config, &state);
+        repl_data.extend(mongodb_reply(opcode, request_id, &answer, &mut state));
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Mongodb(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending MongoDB data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    fn mongodb_client_info() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.port.dst = Some(27017);
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info
+    }
+
+    /* nmap mongodb-info: {buildinfo: 1} on admin.$cmd */
+    const NMAP_BUILDINFO: &[u8] = b"\x3f\x00\x00\x00\x2a\x00\x00\x00\x00\x00\x00\x00\xd4\x07\x00\x00\x00\x00\x00\x00admin.$cmd\x00\x00\x00\x00\x00\x01\x00\x00\x00\x18\x00\x00\x00\x01buildinfo\x00\x00\x00\x00\x00\x00\x00\xf0\x3f\x00";
+
+    fn s(v: &str) -> Bson {
+        Bson::String(v.to_string())
+    }
+
+    /* handshake of mongosh */
+    fn mongosh_hello() -> BsonDocument {
+        let field = |k: &str, v: Bson| (k.to_string(), v);
+        vec![
+            field("hello", Bson::Int32(1)),
+            field(
+                "client",
+                Bson::Document(vec![
+                    field(
+                        "application",
+                        Bson::Document(vec![field("name", s("mongosh 2.1.1"))]),
+                    ),
+                    field(
+                        "driver",
+                        Bson::Document(vec![
+                            field("name", s("nodejs|mongosh")),
+                            field("version", s("6.3.0|2.1.1")),
+                        ]),
+                    ),
+                    field("platform", s("Node.js v20.11.0, LE (unified)")),
+                    field(
+                        "os",
+                        Bson::Document(vec![
+                            field("name", s("linux")),
+                            field("architecture", s("x64")),
+                            field("version", s("5.15.0-91-generic")),
+                            field("type", s("Linux")),
+                        ]),
+                    ),
+                ]),
+            ),
+            field("compression", Bson::Array(vec![s("none")])),
+            field("loadBalanced", Bson::Bool(false)),
+            field("$db", s("admin")),
+        ]
+    }
+
+    fn op_msg(request_id: i32, doc: &BsonDocument) -> Vec<u8> {
+        let body = [vec![0, 0, 0, 0, 0], bson_encode(doc)].concat();
+        let mut msg = ((body.len() + 16) as i32).to_le_bytes().to_vec();
+        msg.extend_from_slice(&request_id.to_le_bytes());
+        msg.extend_from_slice(&[0; 4]);
+        msg.extend_from_slice(&MONGODB_OP_MSG.to_le_bytes());
+        msg.extend(body);
+        msg
+    }
+
+    /* header (length, request id, response to, opcode) and document of
+     * a reply */
+    fn parse_reply(data: &[u8]) -> (usize, i32, i32, BsonDocument) {
+        let field = |i: usize| i32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let offset = if field(12) == MONGODB_OP_MSG { 21 } else { 36 };
+        let (doc, len) = bson_decode(&data[offset..]).unwrap();
+        assert!(offset + len == field(0) as usize);
+        (field(0) as usize, field(8), field(12), doc)
+    }
+
+    #[test]
+    fn test_mongodb_buildinfo() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = mongodb_client_info();
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = crate::proto::dispatch(
+            NMAP_BUILDINFO,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(tcb.proto_id == crate::proto::PROTO_MONGODB);
+        let (len, response_to, opcode, doc) = parse_reply(&repl_data);
+        assert!(len == repl_data.len());
+        assert!(response_to == 0x2a && opcode == MONGODB_OP_REPLY);
+        /* number returned */
+        assert!(repl_data[32..36] == 1i32.to_le_bytes());
+        assert!(bson_get(&doc, "version") == Some(&s("7.0.5")));
+        assert!(
+            bson_get(&doc, "versionArray")
+                == Some(&Bson::Array(vec![
+                    Bson::Int32(7),
+                    Bson::Int32(0),
+                    Bson::Int32(5),
+                    Bson::Int32(0)
+                ]))
+        );
+        assert!(bson_get(&doc, "ok") == Some(&Bson::Double(1.0)));
+        /* other commands, split across segments */
+        let mut msg = NMAP_BUILDINFO.to_vec();
+        msg[44..53].copy_from_slice(b"listDatab");
+        assert!(repl(&msg[..20], &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        let repl_data = repl(&msg[20..], &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let (_, _, _, doc) = parse_reply(&repl_data);
+        assert!(bson_get(&doc, "ok") == Some(&Bson::Double(0.0)));
+        assert!(bson_get(&doc, "errmsg") == Some(&s("no such command: 'listDatab'")));
+        assert!(bson_get(&doc, "codeName") == Some(&s("CommandNotFound")));
+        assert!(Config::from_str("[mongodb]\nversion = \"7.0\"\n").is_err());
+    }
+
+    #[test]
+    fn test_mongodb_hello() {
+        let masscanned = masscanned(
+            Config::from_str("[mongodb]\nversion = \"4.4.29\"\nmax_wire_version = 9\n").unwrap(),
+        );
+        let mut client_info = mongodb_client_info();
+        let mut tcb = TCPControlBlock::new();
+        let hello = mongosh_hello();
+        let data = [op_msg(1, &hello), op_msg(2, &hello)].concat();
+        let repl_data = repl(&data, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let (len, response_to, opcode, doc) = parse_reply(&repl_data);
+        assert!(response_to == 1 && opcode == MONGODB_OP_MSG);
+        assert!(bson_get(&doc, "isWritablePrimary") == Some(&Bson::Bool(true)));
+        assert!(bson_get(&doc, "maxWireVersion") == Some(&Bson::Int32(9)));
+        assert!(bson_get(&doc, "setName").is_none());
+        let (_, response_to, _, _) = parse_reply(&repl_data[len..]);
+        assert!(response_to == 2);
+        let event = mongodb_event("hello", "admin", MONGODB_OP_MSG, &hello, &client_info);
+        assert!(event.fields["client"]["driver"]["name"] == "nodejs|mongosh");
+        assert!(event.fields["client"]["os"]["type"] == "Linux");
+        /* legacy handshake: isMaster over OP_QUERY */
+        let mut legacy = mongosh_hello();
+        legacy[0] = ("isMaster".to_string(), Bson::Int32(1));
+        let mut msg = NMAP_BUILDINFO[..39].to_vec();
+        msg.extend(bson_encode(&legacy));
+        let len = msg.len() as i32;
+        msg[..4].copy_from_slice(&len.to_le_bytes());
+        let (_, _, opcode, doc) =
+            parse_reply(&repl(&msg, &masscanned, &mut client_info, None).unwrap());
+        assert!(opcode == MONGODB_OP_REPLY);
+        assert!(bson_get(&doc, "ismaster") == Some(&Bson::Bool(true)));
+        /* not a command */
+        let mut client_info = mongodb_client_info();
+        let mut msg = NMAP_BUILDINFO.to_vec();
+        msg[20..30].copy_from_slice(b"admin.test");
+        assert!(repl(&msg, &masscanned, &mut client_info, None).is_none());
+        assert!(client_info.close);
+    }
+}
@@ -0,0 +1,650 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* CONNECT packets of MQTT 3.1.1 and 5.0, and of MQTT 3.1 (with
+ * a one-byte remaining length) */
+pub const MQTT_PATTERN_CONNECT: &[u8; 8] = b"\x10*\x00\x04MQTT";
+pub const MQTT_PATTERN_CONNECT_31: &[u8; 10] = b"\x10*\x00\x06MQIsdp";
+
+/* control packet types */
+const MQTT_CONNECT: u8 = 1;
+const MQTT_CONNACK: u8 = 2;
+const MQTT_PUBLISH: u8 = 3;
+const MQTT_PUBACK: u8 = 4;
+const MQTT_PUBREC: u8 = 5;
+const MQTT_PUBREL: u8 = 6;
+const MQTT_PUBCOMP: u8 = 7;
+const MQTT_SUBSCRIBE: u8 = 8;
+const MQTT_SUBACK: u8 = 9;
+const MQTT_UNSUBSCRIBE: u8 = 10;
+const MQTT_UNSUBACK: u8 = 11;
+const MQTT_PINGREQ: u8 = 12;
+const MQTT_PINGRESP: u8 = 13;
+const MQTT_DISCONNECT: u8 = 14;
+
+/* protocol level of MQTT 5.0, with properties in most packets */
+const MQTT_LEVEL_5: u8 = 5;
+
+/* largest packet accepted (and size of the data kept between two
+ * segments), and size of the payloads logged */
+const MQTT_MAX_PACKET: usize = 1 << 20;
+const MQTT_LOG_PAYLOAD: usize = 1024;
+
+/* MQTT broker: CONNECT packets get a CONNACK with return_code (0:
+ * accepted, 1 to 5: refused - unacceptable protocol version, identifier
+ * rejected, server unavailable, bad user name or password, not
+ * authorized), then SUBSCRIBE, UNSUBSCRIBE, PUBLISH and PINGREQ are
+ * acknowledged.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    pub ports: Vec<u16>,
+    pub return_code: u8,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            ports: vec![1883],
+            return_code: 0,
+        }
+    }
+}
+
+impl MqttConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if self.return_code > 5 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("mqtt: invalid return_code: {}", self.return_code),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* state of an MQTT flow: data received, not a complete packet yet, and
+ * protocol level of the CONNECT (None before) */
+#[derive(Default)]
+pub struct MqttState {
+    buffer: Vec<u8>,
+    level: Option<u8>,
+}
+
+/* Variable Byte Integer (the remaining length): value and number of
+ * bytes used - Ok(None) when incomplete, Err beyond 4 bytes */
+fn mqtt_varint(data: &[u8]) -> Result<Option<(usize, usize)>, &'static str> {
+    let mut value = 0;
+    for (i, c) in data.iter().enumerate() {
+        if i == 4 {
+            return Err("malformed remaining length");
+        }
+        value |= ((c & 0x7f) as usize) << (7 * i);
+        if c & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+    if data.len() >= 4 {
+        return Err("malformed remaining length");
+    }
+    Ok(None)
+}
+
+fn mqtt_encode_varint(mut value: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let c = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(c);
+            return out;
+        }
+        out.push(c | 0x80);
+    }
+}
+
+struct MqttPacket {
+    ptype: u8,
+    flags: u8,
+    body: Vec<u8>,
+}
+
+/* next control packet and number of bytes used */
+fn mqtt_parse_packet(data: &[u8]) -> Result<Option<(MqttPacket, usize)>, &'static str> {
+    if data.is_empty() {
+        return Ok(None);
+    }
+    let (len, n) = match mqtt_varint(&data[1..])? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    if len > MQTT_MAX_PACKET {
+        return Err("packet too large");
+    }
+    let end = 1 + n + len;
+    if data.len() < end {
+        return Ok(None);
+    }
+    Ok(Some((
+        MqttPacket {
+            ptype: data[0] >> 4,
+            flags: data[0] & 0x0f,
+            body: data[1 + n..end].to_vec(),
+        },
+        end,
+    )))
+}
+
+fn mqtt_packet(ptype: u8, flags: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![(ptype << 4) | flags];
+    packet.extend(mqtt_encode_varint(body.len()));
+    packet.extend_from_slice(body);
+    packet
+}
+
+/* reader of the variable header and payload of a packet */
+struct MqttReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> MqttReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        MqttReader { data, offset: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let b = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(b)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let b = self.bytes(2)?;
+        Some(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    /* UTF-8 strings and binary data, with a 16-bit length */
+    fn binary(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.bytes(len)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        Some(String::from_utf8_lossy(self.binary()?).to_string())
+    }
+
+    /* MQTT 5.0 properties: skipped */
+    fn properties(&mut self) -> Option<()> {
+        let (len, n) = mqtt_varint(&self.data[self.offset..]).ok()??;
+        self.offset += n;
+        self.bytes(len).map(|_| ())
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[std::cmp::min(self.offset, self.data.len())..];
+        self.offset = self.data.len();
+        rest
+    }
+
+    fn done(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+}
+
+fn mqtt_payload(event: &mut Event, name: &str, payload: &[u8]) {
+    let len = std::cmp::min(payload.len(), MQTT_LOG_PAYLOAD);
+    event.set(name, String::from_utf8_lossy(&payload[..len]).to_string());
+}
+
+/* CONNECT: protocol name and level, flags, keep alive, then client
+ * identifier, will topic and message, user name and password, depending
+ * on the flags */
+fn mqtt_connect(body: &[u8], client_info: &ClientInfo) -> Option<(u8, Event)> {
+    let mut r = MqttReader::new(body);
+    let protocol = r.string()?;
+    let level = r.u8()?;
+    let flags = r.u8()?;
+    let keepalive = r.u16()?;
+    if level == MQTT_LEVEL_5 {
+        r.properties()?;
+    }
+    let mut event = Event::new("mqtt_connect", client_info);
+    event.set("protocol", protocol);
+    event.set("level", level);
+    event.set("clean_session", flags & 0x02 != 0);
+    event.set("keepalive", keepalive);
+    event.set("client_id", r.string()?);
+    if flags & 0x04 != 0 {
+        if level == MQTT_LEVEL_5 {
+            r.properties()?;
+        }
+        event.set("will_topic", r.string()?);
+        let message = r.binary()?;
+        mqtt_payload(&mut event, "will_message", message);
+        event.set("will_qos", (flags >> 3) & 0x03);
+        event.set("will_retain", flags & 0x20 != 0);
+    }
+    if flags & 0x80 != 0 {
+        event.set("username", r.string()?);
+    }
+    if flags & 0x40 != 0 {
+        event.set("password", String::from_utf8_lossy(r.binary()?).to_string());
+    }
+    Some((level, event))
+}
+
+/* MQTT 5.0 reason codes of the MQTT 3.1.1 CONNACK return codes */
+fn mqtt_reason_code(return_code: u8) -> u8 {
+    match return_code {
+        0 => 0x00,
+        1 => 0x84,
+        2 => 0x85,
+        3 => 0x88,
+        4 => 0x86,
+        _ => 0x87,
+    }
+}
+
+/* PUBLISH: topic, packet identifier (QoS 1 and 2), properties (5.0),
+ * payload - the answer is a PUBACK or a PUBREC, depending on QoS */
+fn mqtt_publish(
+    packet: &MqttPacket,
+    level: u8,
+    client_info: &ClientInfo,
+) -> Option<(Event, Vec<u8>)> {
+    let qos = (packet.flags >> 1) & 0x03;
+    let mut r = MqttReader::new(&packet.body);
+    let topic = r.string()?;
+    let packet_id = if qos > 0 { Some(r.u16()?) } else { None };
+    if level == MQTT_LEVEL_5 {
+        r.properties()?;
+    }
+    let payload = r.rest();
+    warn!("MQTT PUBLISH to {}", topic);
+    let mut event = Event::new("mqtt_publish", client_info);
+    event.set("topic", topic);
+    event.set("qos", qos);
+    event.set("retain", packet.flags & 0x01 != 0);
+    event.set("length", payload.len());
+    mqtt_payload(&mut event, "payload", payload);
+    let repl = match (qos, packet_id) {
+        (1, Some(id)) => mqtt_packet(MQTT_PUBACK, 0, &id.to_be_bytes()),
+        (2, Some(id)) => mqtt_packet(MQTT_PUBREC, 0, &id.to_be_bytes()),
+        _ => Vec::new(),
+    };
+    Some((event, repl))
+}
+
+/* SUBSCRIBE and UNSUBSCRIBE: packet identifier, properties (5.0), topic
+ * filters (with options for SUBSCRIBE) - every filter is granted */
+fn mqtt_subscribe(
+    packet: &MqttPacket,
+    level: u8,
+    client_info: &ClientInfo,
+) -> Option<(Event, Vec<u8>)> {
+    let subscribe = packet.ptype == MQTT_SUBSCRIBE;
+    let mut r = MqttReader::new(&packet.body);
+    let packet_id = r.u16()?;
+    if level == MQTT_LEVEL_5 {
+        r.properties()?;
+    }
+    let mut topics = Vec::new();
+    let mut codes = Vec::new();
+    while !r.done() {
+        topics.push(Value::from(r.string()?));
+        if subscribe {
+            /* granted QoS: the requested one */
+            codes.push(std::cmp::min(r.u8()? & 0x03, 2));
+        } else {
+            codes.push(0);
+        }
+    }
+    if topics.is_empty() {
+        return None;
+    }
+    let mut event = Event::new(
+        if subscribe {
+            "mqtt_subscribe"
+        } else {
+            "mqtt_unsubscribe"
+        },
+        client_info,
+    );
+    event.set("topics", Value::Array(topics));
+    let mut body = packet_id.to_be_bytes().to_vec();
+    if level == MQTT_LEVEL_5 {
+        body.push(0);
+    }
+    if subscribe {
+        body.extend(codes);
+        Some((event, mqtt_packet(MQTT_SUBACK, 0, &body)))
+    } else {
+        /* MQTT 3.1.1 UNSUBACK: no payload */
+        if level == MQTT_LEVEL_5 {
+            body.extend(codes);
+        }
+        Some((event, mqtt_packet(MQTT_UNSUBACK, 0, &body)))
+    }
+}
+
+/* answer to a packet (possibly empty), and event to log */
+fn mqtt_answer(
+    packet: &MqttPacket,
+    config: &MqttConfig,
+    state: &mut MqttState,
+    client_info: &mut ClientInfo,
+) -> Result<(Option<Event>, Vec<u8>), &'static str> {
+    let level = match (state.level, packet.ptype) {
+        (None, MQTT_CONNECT) => {
+            let (level, event) =
+                mqtt_connect(&packet.body, client_info).ok_or("invalid CONNECT")?;
+            warn!(
+                "MQTT CONNECT from {}",
+                event.fields["client_id"].as_str().unwrap_or("")
+            );
+            state.level = Some(level);
+            let repl = if level == MQTT_LEVEL_5 {
+                mqtt_packet(
+                    MQTT_CONNACK,
+                    0,
+                    &[0, mqtt_reason_code(config.return_code), 0],
+                )
+            } else {
+                mqtt_packet(MQTT_CONNACK, 0, &[0, config.return_code])
+            };
+            /* refused: the connection is closed */
+            if config.return_code != 0 {
+                client_info.close = true;
+            }
+            return Ok((Some(event), repl));
+        }
+        (None, _) => return Err("first packet is not CONNECT"),
+        (Some(_), MQTT_CONNECT) => return Err("second CONNECT"),
+        (Some(level), _) => level,
+    };
+    let invalid = || Err("invalid packet");
+    match packet.ptype {
+        MQTT_PUBLISH => match mqtt_publish(packet, level, client_info) {
+            Some((event, repl)) => Ok((Some(event), repl)),
+            None => invalid(),
+        },
+        MQTT_SUBSCRIBE | MQTT_UNSUBSCRIBE => match mqtt_subscribe(packet, level, client_info) {
+            Some((event, repl)) => Ok((Some(event), repl)),
+            None => invalid(),
+        },
+        MQTT_PUBREL => match packet.body.get(..2) {
+            Some(id) => Ok((None, mqtt_packet(MQTT_PUBCOMP, 0, id))),
+            None => invalid(),
+        },
+        MQTT_PINGREQ => Ok((None, mqtt_packet(MQTT_PINGRESP, 0, &[]))),
+        MQTT_DISCONNECT => {
+            client_info.close = true;
+            Ok((None, Vec::new()))
+        }
+        MQTT_PUBACK | MQTT_PUBREC | MQTT_PUBCOMP => Ok((None, Vec::new())),
+        ptype => {
+            info!("MQTT packet type not handled: {}", ptype);
+            Ok((None, Vec::new()))
+        }
+    }
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving MQTT data");
+    let config = &masscanned.config.mqtt;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Mqtt(s)) => s,
+        _ => MqttState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while !client_info.close {
+        let result = mqtt_parse_packet(&state.buffer).and_then(|p| match p {
+            Some((packet, len)) => {
+                state.buffer.drain(..len);
+                mqtt_answer(&packet, config, &mut state, client_info).map(Some)
+            }
+            None => Ok(None),
+        });
+        match result {
+            Ok(Some((event, repl))) => {
+                if let Some(event) = event {
+                    event.log();
+                }
+                repl_data.extend(repl);
+            }
+            Ok(None) => break,
+            Err(e) => {
+                /* MQTT 3.1.1 section 4.8: protocol violations close the
+                 * connection */
+                info!("MQTT protocol error: {}", e);
+                client_info.close = true;
+            }
+        }
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Mqtt(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending MQTT data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    /* mosquitto_pub -V mqttv311 -i scanner -u admin -P password */
+    const CONNECT_CREDENTIALS: &[u8] =
+        b"\x10\x24\x00\x04MQTT\x04\xc2\x00\x3c\x00\x07scanner\x00\x05admin\x00\x08password";
+
+    #[test]
+    fn test_mqtt_remaining_length() {
+        for (value, encoded) in [
+            (0, &b"\x00"[..]),
+            (127, b"\x7f"),
+            (128, b"\x80\x01"),
+            (16383, b"\xff\x7f"),
+            (16384, b"\x80\x80\x01"),
+            (2097151, b"\xff\xff\x7f"),
+            (2097152, b"\x80\x80\x80\x01"),
+            (268435455, b"\xff\xff\xff\x7f"),
+        ]
+        .iter()
+        {
+            assert!(mqtt_encode_varint(*value) == *encoded);
+            assert!(mqtt_varint(encoded) == Ok(Some((*value, encoded.len()))));
+            /* incomplete */
+            assert!(mqtt_varint(&encoded[..encoded.len() - 1]) == Ok(None));
+        }
+        /* at most 4 bytes */
+        assert!(mqtt_varint(b"\xff\xff\xff\xff\x7f").is_err());
+        assert!(mqtt_varint(b"\xff\xff\xff\xff").is_err());
+        /* packets */
+        let mut publish = b"\x30\xcd\x01\x00\x03a/b".to_vec();
+        publish.extend_from_slice(&[b'x'; 200]);
+        let (packet, len) = mqtt_parse_packet(&publish).unwrap().unwrap();
+        assert!(len == publish.len() && packet.ptype == MQTT_PUBLISH && packet.body.len() == 205);
+        assert!(mqtt_packet(MQTT_PUBLISH, 0, &packet.body) == publish);
+        for i in 0..publish.len() {
+            assert!(mqtt_parse_packet(&publish[..i]).unwrap().is_none());
+        }
+        assert!(mqtt_parse_packet(b"\x30\xff\xff\xff\x7f").is_err());
+    }
+
+    #[test]
+    fn test_mqtt_connect() {
+        let client_info = ClientInfo::new();
+        let (level, event) = mqtt_connect(&CONNECT_CREDENTIALS[2..], &client_info).unwrap();
+        assert!(level == 4);
+        assert!(event.fields["protocol"] == "MQTT");
+        assert!(event.fields["clean_session"] == true);
+        assert!(event.fields["keepalive"] == 60);
+        assert!(event.fields["client_id"] == "scanner");
+        assert!(event.fields["username"] == "admin");
+        assert!(event.fields["password"] == "password");
+        assert!(event.fields.get("will_topic").is_none());
+        /* MQTT 3.1, with a will */
+        let (level, event) = mqtt_connect(
+            b"\x00\x06MQIsdp\x03\x0e\x00\x3c\x00\x01c\x00\x03t/w\x00\x03bye",
+            &client_info,
+        )
+        .unwrap();
+        assert!(level == 3);
+        assert!(event.fields["will_topic"] == "t/w");
+        assert!(event.fields["will_message"] == "bye");
+        assert!(event.fields["will_qos"] == 1);
+        assert!(event.fields.get("username").is_none());
+        /* MQTT 5.0: properties (session expiry interval), and a will with
+         * its own properties */
+        let (level, event) = mqtt_connect(
+            b"\x00\x04MQTT\x05\x06\x00\x3c\x05\x11\x00\x00\x00\x0a\x00\x03abc\x02\x01\x01\x00\x01t\x00\x01m",
+            &client_info,
+        )
+        .unwrap();
+        assert!(level == 5);
+        assert!(event.fields["client_id"] == "abc");
+        assert!(event.fields["will_topic"] == "t");
+        /* truncated */
+        assert!(mqtt_connect(&CONNECT_CREDENTIALS[2..30], &client_info).is_none());
+    }
+
+    #[test]
+    fn test_mqtt_session() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        /* CONNECT split across segments */
+        assert!(repl(
+            &CONNECT_CREDENTIALS[..3],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl_data = repl(
+            &CONNECT_CREDENTIALS[3..],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"\x20\x02\x00\x00");
+        /* SUBSCRIBE, PUBLISH (QoS 1 and 2), PUBREL, PINGREQ in one segment */
+        let repl_data = repl(
+            b"\x82\x06\x00\x01\x00\x01#\x01\
+            \x32\x0c\x00\x03a/b\x00\x02hello\
+            \x34\x07\x00\x01c\x00\x03!!\
+            \x62\x02\x00\x03\
+            \xc0\x00",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(
+            repl_data
+                == b"\x90\x03\x00\x01\x01\x40\x02\x00\x02\x50\x02\x00\x03\x70\x02\x00\x03\xd0\x00"
+        );
+        /* UNSUBSCRIBE, DISCONNECT */
+        let repl_data = repl(
+            b"\xa2\x05\x00\x04\x00\x01#\xe0\x00",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"\xb0\x02\x00\x04");
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_mqtt_refused() {
+        let masscanned = masscanned(Config::from_str("[mqtt]\nreturn_code = 5\n").unwrap());
+        assert!(Config::from_str("[mqtt]\nreturn_code = 6\n").is_err());
+        let mut client_info = ClientInfo::new();
+        let repl_data = repl(CONNECT_CREDENTIALS, &masscanned, &mut client_info, None).unwrap();
+        assert!(repl_data == b"\x20\x02\x00\x05");
+        assert!(client_info.close);
+        /* MQTT 5.0: reason code (not authorized), no properties */
+        let mut client_info = ClientInfo::new();
+        let repl_data = repl(
+            b"\x10\x0f\x00\x04MQTT\x05\x02\x00\x3c\x00\x00\x02id",
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        assert!(repl_data == b"\x20\x03\x00\x87\x00");
+        /* anything but CONNECT first */
+        let mut client_info = ClientInfo::new();
+        assert!(repl(b"\xc0\x00", &masscanned, &mut client_info, None).is_none());
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_mqtt_dispatch() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        /* CONNECT on another port (e.g., within TLS on 8883) */
+        client_info.port.dst = Some(8883);
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = crate::proto::dispatch(
+            CONNECT_CREDENTIALS,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"\x20\x02\x00\x00");
+        let repl_data =
+            crate::proto::dispatch(b"\xc0\x00", &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(repl_data == b"\xd0\x00");
+    }
+}
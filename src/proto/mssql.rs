@@ -0,0 +1,697 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* TDS packet types */
+const TDS_TABULAR_RESULT: u8 = 0x04;
+const TDS_LOGIN7: u8 = 0x10;
+const TDS_PRELOGIN: u8 = 0x12;
+/* status: end of message */
+const TDS_STATUS_EOM: u8 = 0x01;
+
+/* PRELOGIN options */
+const PRELOGIN_VERSION: u8 = 0x00;
+const PRELOGIN_ENCRYPTION: u8 = 0x01;
+const PRELOGIN_INSTOPT: u8 = 0x02;
+const PRELOGIN_THREADID: u8 = 0x03;
+const PRELOGIN_MARS: u8 = 0x04;
+const PRELOGIN_TERMINATOR: u8 = 0xff;
+
+/* tokens */
+const TDS_TOKEN_ERROR: u8 = 0xaa;
+const TDS_TOKEN_DONE: u8 = 0xfd;
+const TDS_DONE_ERROR: u16 = 0x0002;
+
+/* login failed */
+const MSSQL_ERROR_LOGIN_FAILED: u32 = 18456;
+
+/* maximum size of the packets and messages received */
+const TDS_MAX_PACKET: usize = 32768;
+const TDS_MAX_MESSAGE: usize = 65536;
+
+/* SQL Server Resolution Protocol (MC-SQLR) */
+const SSRP_CLNT_BCAST_EX: u8 = 0x02;
+const SSRP_CLNT_UCAST_EX: u8 = 0x03;
+const SSRP_CLNT_UCAST_INST: u8 = 0x04;
+const SSRP_SVR_RESP: u8 = 0x05;
+
+/* PRELOGIN, end of message, then the length and SPID 0 */
+pub const MSSQL_PATTERN_PRELOGIN: &[u8; 6] = b"\x12\x01**\x00\x00";
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MssqlEncryption {
+    Off,
+    On,
+    #[default]
+    NotSup,
+    Req,
+}
+
+/* Microsoft SQL Server: PRELOGIN requests get a PRELOGIN answer
+ * (version, encryption), and logins (LOGIN7) an error (error_message
+ * can use {user}). With encryption set to anything else than not_sup,
+ * the client goes on with a TLS handshake, which is not handled. The
+ * SQL Server Browser service (on the browser_ports, over UDP) reports
+ * the instance, as listening on port.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MssqlConfig {
+    pub version: String,
+    pub encryption: MssqlEncryption,
+    pub server_name: String,
+    pub instance: String,
+    pub port: u16,
+    pub browser_ports: Vec<u16>,
+    pub error_message: String,
+}
+
+impl Default for MssqlConfig {
+    fn default() -> Self {
+        MssqlConfig {
+            /* SQL Server 2019 RTM */
+            version: "15.0.2000.5".to_string(),
+            encryption: MssqlEncryption::NotSup,
+            server_name: "SQLSERVER".to_string(),
+            instance: "MSSQLSERVER".to_string(),
+            port: 1433,
+            browser_ports: vec![1434],
+            error_message: "Login failed for user '{user}'.".to_string(),
+        }
+    }
+}
+
+impl MssqlConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if mssql_version(&self.version).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("mssql: invalid version: {:?}", self.version),
+            ));
+        }
+        for s in [&self.server_name, &self.instance].iter() {
+            if s.is_empty() || s.contains(';') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("mssql: invalid name: {:?}", s),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/* VERSION option: major.minor.build[.subbuild] */
+fn mssql_version(version: &str) -> Option<[u8; 6]> {
+    let parts = version.split('.').collect::<Vec<&str>>();
+    if parts.len() != 3 && parts.len() != 4 {
+        return None;
+    }
+    let major = parts[0].parse::<u8>().ok()?;
+    let minor = parts[1].parse::<u8>().ok()?;
+    let build = parts[2].parse::<u16>().ok()?;
+    let subbuild = match parts.get(3) {
+        Some(p) => p.parse::<u16>().ok()?,
+        None => 0,
+    };
+    let mut v = [major, minor, 0, 0, 0, 0];
+    v[2..4].copy_from_slice(&build.to_be_bytes());
+    v[4..].copy_from_slice(&subbuild.to_be_bytes());
+    Some(v)
+}
+
+/* TDS packets: type, status, length (with the 8-byte header), SPID,
+ * packet id, window - messages may span several packets (until one
+ * has the end of message status) */
+#[derive(Default)]
+pub struct TdsCodec {
+    buffer: Vec<u8>,
+    message: Option<(u8, Vec<u8>)>,
+}
+
+impl TdsCodec {
+    /* complete messages received so far: type and payload */
+    pub fn push(&mut self, data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        self.buffer.extend_from_slice(data);
+        let mut messages = Vec::new();
+        while self.buffer.len() >= 8 {
+            let len = u16::from_be_bytes([self.buffer[2], self.buffer[3]]) as usize;
+            if !(8..=TDS_MAX_PACKET).contains(&len) {
+                info!("invalid TDS packet length ({} bytes) - dropped", len);
+                self.buffer.clear();
+                self.message = None;
+                break;
+            }
+            if self.buffer.len() < len {
+                break;
+            }
+            let (kind, status) = (self.buffer[0], self.buffer[1]);
+            let message = self.message.get_or_insert_with(|| (kind, Vec::new()));
+            message.1.extend_from_slice(&self.buffer[8..len]);
+            self.buffer.drain(..len);
+            if message.1.len() > TDS_MAX_MESSAGE {
+                info!("TDS message too large - dropped");
+                self.message = None;
+            } else if status & TDS_STATUS_EOM != 0 {
+                messages.extend(self.message.take());
+            }
+        }
+        messages
+    }
+}
+
+pub fn tds_packet(kind: u8, spid: u16, packet_id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind, TDS_STATUS_EOM];
+    packet.extend_from_slice(&((payload.len() + 8) as u16).to_be_bytes());
+    packet.extend_from_slice(&spid.to_be_bytes());
+    packet.push(packet_id);
+    packet.push(0);
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/* state of a TDS flow: messages, SPID of the session, packets sent */
+pub struct MssqlState {
+    codec: TdsCodec,
+    spid: u16,
+    packet_id: u8,
+}
+
+impl MssqlState {
+    fn new() -> Self {
+        MssqlState {
+            codec: TdsCodec::default(),
+            /* user sessions start at 51 */
+            spid: 51 + rand::random::<u16>() % 100,
+            packet_id: 0,
+        }
+    }
+
+    fn packet(&mut self, kind: u8, payload: &[u8]) -> Vec<u8> {
+        self.packet_id = self.packet_id.wrapping_add(1);
+        tds_packet(kind, self.spid, self.packet_id, payload)
+    }
+}
+
+/* PRELOGIN options (token, data), None when invalid */
+fn prelogin_parse(payload: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut options = Vec::new();
+    let mut offset = 0;
+    loop {
+        let token = *payload.get(offset)?;
+        if token == PRELOGIN_TERMINATOR {
+            return Some(options);
+        }
+        let entry = payload.get(offset + 1..offset + 5)?;
+        let start = u16::from_be_bytes([entry[0], entry[1]]) as usize;
+        let len = u16::from_be_bytes([entry[2], entry[3]]) as usize;
+        options.push((token, payload.get(start..start + len)?));
+        offset += 5;
+    }
+}
+
+fn prelogin_build(options: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut offset = options.len() * 5 + 1;
+    let mut table = Vec::new();
+    let mut data = Vec::new();
+    for (token, value) in options.iter() {
+        table.push(*token);
+        table.extend_from_slice(&(offset as u16).to_be_bytes());
+        table.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        data.extend_from_slice(value);
+        offset += value.len();
+    }
+    table.push(PRELOGIN_TERMINATOR);
+    table.extend(data);
+    table
+}
+
+fn prelogin_event(options: &[(u8, &[u8])], client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("mssql_prelogin", client_info);
+    for (token, value) in options.iter() {
+        match (*token, value.len()) {
+            (PRELOGIN_VERSION, 6) => event.set(
+                "version",
+                format!(
+                    "{}.{}.{}.{}",
+                    value[0],
+                    value[1],
+                    u16::from_be_bytes([value[2], value[3]]),
+                    u16::from_be_bytes([value[4], value[5]])
+                ),
+            ),
+            (PRELOGIN_ENCRYPTION, 1) => event.set("encryption", value[0]),
+            (PRELOGIN_INSTOPT, _) => {
+                let instance = value.split(|c| *c == 0).next().unwrap_or(&[]);
+                event.set("instance", String::from_utf8_lossy(instance).to_string());
+            }
+            (PRELOGIN_THREADID, 4) => event.set(
+                "thread_id",
+                u32::from_be_bytes([value[0], value[1], value[2], value[3]]),
+            ),
+            (PRELOGIN_MARS, 1) => event.set("mars", value[0] != 0),
+            _ => {}
+        }
+    }
+    event
+}
+
+fn prelogin_response(config: &MssqlConfig) -> Vec<u8> {
+    let version = mssql_version(&config.version).unwrap_or([0; 6]);
+    let encryption = [config.encryption as u8];
+    prelogin_build(&[
+        (PRELOGIN_VERSION, &version),
+        (PRELOGIN_ENCRYPTION, &encryption),
+        (PRELOGIN_INSTOPT, b"\x00"),
+        (PRELOGIN_THREADID, b""),
+        (PRELOGIN_MARS, b"\x00"),
+    ])
+}
+
+/* password of LOGIN7: nibbles swapped, then XOR 0xa5 */
+fn login7_password(data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .map(|c| (c ^ 0xa5).rotate_left(4))
+        .collect::<Vec<u8>>()
+}
+
+fn utf16le(data: &[u8]) -> String {
+    String::from_utf16_lossy(
+        &data
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect::<Vec<u16>>(),
+    )
+}
+
+fn utf16le_encode(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(|c| c.to_le_bytes()).collect()
+}
+
+/* fields of LOGIN7 (offset of the offset/length pair) */
+const LOGIN7_FIELDS: [(&str, usize); 8] = [
+    ("hostname", 36),
+    ("username", 40),
+    ("password", 44),
+    ("appname", 48),
+    ("server_name", 52),
+    ("library", 60),
+    ("language", 64),
+    ("database", 68),
+];
+
+/* LOGIN7 fields (name, value), None when invalid */
+fn login7_parse(payload: &[u8]) -> Option<Vec<(&'static str, String)>> {
+    if payload.len() < 72 {
+        return None;
+    }
+    let mut fields = Vec::new();
+    for (name, pos) in LOGIN7_FIELDS.iter() {
+        let start = u16::from_le_bytes([payload[*pos], payload[pos + 1]]) as usize;
+        let len = 2 * u16::from_le_bytes([payload[pos + 2], payload[pos + 3]]) as usize;
+        let value = payload.get(start..start + len)?;
+        let value = if *name == "password" {
+            utf16le(&login7_password(value))
+        } else {
+            utf16le(value)
+        };
+        fields.push((*name, value));
+    }
+    Some(fields)
+}
+
+fn login7_event(payload: &[u8], fields: &[(&str, String)], client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("mssql_login", client_info);
+    event.set(
+        "tds_version",
+        format!(
+            "0x{:08x}",
+            u32::from_le_bytes([payload[4], payload[5], payload[6], payload[7]])
+        ),
+    );
+    for (name, value) in fields.iter() {
+        event.set(name, value.to_string());
+    }
+    event
+}
+
+/* ERROR and DONE tokens */
+fn mssql_error(config: &MssqlConfig, number: u32, message: &str) -> Vec<u8> {
+    let message = utf16le_encode(message);
+    let server = utf16le_encode(&config.server_name);
+    let mut token = number.to_le_bytes().to_vec();
+    /* state, class */
+    token.extend_from_slice(&[1, 14]);
+    token.extend_from_slice(&((message.len() / 2) as u16).to_le_bytes());
+    token.extend(message);
+    token.push((server.len() / 2) as u8);
+    token.extend(server);
+    /* procedure name, line number */
+    token.push(0);
+    token.extend_from_slice(&1u32.to_le_bytes());
+    let mut repl = vec![TDS_TOKEN_ERROR];
+    repl.extend_from_slice(&(token.len() as u16).to_le_bytes());
+    repl.extend(token);
+    repl.push(TDS_TOKEN_DONE);
+    repl.extend_from_slice(&TDS_DONE_ERROR.to_le_bytes());
+    repl.extend_from_slice(&[0; 10]);
+    repl
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving TDS data");
+    let config = &masscanned.config.mssql;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Mssql(s)) => s,
+        _ => MssqlState::new(),
+    };
+    let mut repl_data = Vec::new();
+    for (kind, payload) in state.codec.push(data) {
+        match kind {
+            TDS_PRELOGIN => match prelogin_parse(&payload) {
+                Some(options) if !options.is_empty() => {
+                    prelogin_event(&options, client_info).log();
+                    repl_data.extend(state.packet(TDS_TABULAR_RESULT, &prelogin_response(config)));
+                }
+                _ => {
+                    /* e.g., a TLS handshake */
+                    info!("TDS PRELOGIN not handled");
+                    client_info.close = true;
+                }
+            },
+            TDS_LOGIN7 => {
+                let fields = match login7_parse(&payload) {
+                    Some(f) => f,
+                    None => {
+                        info!("TDS data not handled (invalid LOGIN7)");
+                        client_info.close = true;
+                        break;
+                    }
+                };
+                let user = fields
+                    .iter()
+                    .find(|(k, _)| *k == "username")
+                    .map_or("", |(_, v)| v.as_str());
+                warn!("MS-SQL login: {}", user);
+                login7_event(&payload, &fields, client_info).log();
+                let message = config.error_message.replace("{user}", user);
+                repl_data.extend(state.packet(
+                    TDS_TABULAR_RESULT,
+                    &mssql_error(config, MSSQL_ERROR_LOGIN_FAILED, &message),
+                ));
+                client_info.close = true;
+            }
+            kind => {
+                info!("TDS message not handled: {:#04x}", kind);
+                client_info.close = true;
+            }
+        }
+        if client_info.close {
+            break;
+        }
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Mssql(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending TDS data");
+    Some(repl_data)
+}
+
+/* SQL Server Browser (over UDP): instances of the server */
+pub fn browser_repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    debug!("receiving SQL Server Browser data");
+    let config = &masscanned.config.mssql;
+    let (kind, rest) = data.split_first()?;
+    let instance = match *kind {
+        SSRP_CLNT_BCAST_EX | SSRP_CLNT_UCAST_EX => None,
+        SSRP_CLNT_UCAST_INST => {
+            let instance = rest.split(|c| *c == 0).next().unwrap_or(&[]);
+            Some(String::from_utf8_lossy(instance).to_string())
+        }
+        _ => {
+            info!("SQL Server Browser request not handled: {:#04x}", kind);
+            return None;
+        }
+    };
+    let mut event = Event::new("mssql_browser_query", client_info);
+    event.set("type", *kind);
+    if let Some(i) = &instance {
+        event.set("instance", i.to_string());
+    }
+    event.log();
+    /* requests for another instance are not answered */
+    if instance.is_some_and(|i| !i.eq_ignore_ascii_case(&config.instance)) {
+        return None;
+    }
+    let resp = format!(
+        "ServerName;{};InstanceName;{};IsClustered;No;Version;{};tcp;{};;",
+        config.server_name, config.instance, config.version, config.port
+    );
+    let mut repl = vec![SSRP_SVR_RESP];
+    repl.extend_from_slice(&(resp.len() as u16).to_le_bytes());
+    repl.extend_from_slice(resp.as_bytes());
+    debug!("sending SQL Server Browser data");
+    Some(repl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use pnet::packet::udp::UdpPacket;
+    use pnet::packet::Packet;
+    use std::str::FromStr;
+
+    /* PRELOGIN of nmap (ms-sql-info) */
+    const NMAP_PRELOGIN: &[u8] = b"\x12\x01\x00\x2f\x00\x00\x01\x00\x00\x00\x1a\x00\x06\x01\x00\x20\x00\x01\x02\x00\x21\x00\x01\x03\x00\x22\x00\x04\x04\x00\x26\x00\x01\xff\x09\x00\x00\x00\x00\x00\x00\x00\x00\x00\x11\x5c\x00";
+
+    /* LOGIN7 (TDS 7.4) with the given fields */
+    fn login7(fields: &[(usize, &str)]) -> Vec<u8> {
+        let mut payload = vec![0; 94];
+        payload[4..8].copy_from_slice(&0x7400_0004u32.to_le_bytes());
+        for (pos, value) in fields.iter() {
+            let mut value = utf16le_encode(value);
+            if *pos == 44 {
+                /* password */
+                value = value
+                    .iter()
+                    .map(|c| c.rotate_left(4) ^ 0xa5)
+                    .collect::<Vec<u8>>();
+            }
+            let offset = payload.len() as u16;
+            payload[*pos..pos + 2].copy_from_slice(&offset.to_le_bytes());
+            payload[pos + 2..pos + 4].copy_from_slice(&((value.len() / 2) as u16).to_le_bytes());
+            payload.extend(value);
+        }
+        let len = payload.len() as u32;
+        payload[..4].copy_from_slice(&len.to_le_bytes());
+        tds_packet(TDS_LOGIN7, 0, 1, &payload)
+    }
+
+    #[test]
+    fn test_tds_codec() {
+        let mut codec = TdsCodec::default();
+        let mut first = tds_packet(TDS_LOGIN7, 0, 1, b"abc");
+        /* not the end of the message */
+        first[1] = 0;
+        let data = [first, tds_packet(TDS_LOGIN7, 0, 2, b"de")].concat();
+        assert!(data[..8] == *b"\x10\x00\x00\x0b\x00\x00\x01\x00");
+        assert!(codec.push(&data[..13]).is_empty());
+        assert!(codec.push(&data[13..]) == vec![(TDS_LOGIN7, b"abcde".to_vec())]);
+        /* invalid lengths are dropped */
+        assert!(codec.push(b"\x12\x01\x00\x02\x00\x00\x01\x00").is_empty());
+        assert!(
+            codec.push(&tds_packet(TDS_PRELOGIN, 0, 1, b"f"))
+                == vec![(TDS_PRELOGIN, b"f".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_mssql_prelogin() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.port.dst = Some(1433);
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        let mut tcb = TCPControlBlock::new();
+        /* split across segments */
+        assert!(crate::proto::dispatch(
+            &NMAP_PRELOGIN[..6],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl_data = crate::proto::dispatch(
+            &NMAP_PRELOGIN[6..],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(tcb.proto_id == crate::proto::PROTO_MSSQL);
+        assert!(!client_info.close);
+        let spid = match &tcb.proto_state {
+            ProtoState::Mssql(s) => s.spid,
+            _ => panic!("expected a TDS state"),
+        };
+        /* header: tabular result, end of message, length, SPID, packet 1 */
+        assert!(repl_data[..2] == [TDS_TABULAR_RESULT, TDS_STATUS_EOM]);
+        assert!(u16::from_be_bytes([repl_data[2], repl_data[3]]) as usize == repl_data.len());
+        assert!(u16::from_be_bytes([repl_data[4], repl_data[5]]) == spid);
+        assert!(repl_data[6..8] == [1, 0]);
+        let options = prelogin_parse(&repl_data[8..]).unwrap();
+        assert!(
+            options
+                == vec![
+                    (PRELOGIN_VERSION, &b"\x0f\x00\x07\xd0\x00\x05"[..]),
+                    (PRELOGIN_ENCRYPTION, &b"\x02"[..]),
+                    (PRELOGIN_INSTOPT, &b"\x00"[..]),
+                    (PRELOGIN_THREADID, &b""[..]),
+                    (PRELOGIN_MARS, &b"\x00"[..]),
+                ]
+        );
+        let event = prelogin_event(&prelogin_parse(&NMAP_PRELOGIN[8..]).unwrap(), &client_info);
+        assert!(event.fields["version"] == "9.0.0.0");
+        assert!(event.fields["encryption"] == 0);
+        assert!(event.fields["instance"] == "");
+        assert!(event.fields["thread_id"] == 0x115c);
+        assert!(event.fields["mars"] == false);
+        /* configured version and encryption */
+        let config =
+            Config::from_str("[mssql]\nversion = \"16.0.1000\"\nencryption = \"req\"\n").unwrap();
+        let options = prelogin_response(&config.mssql);
+        let options = prelogin_parse(&options).unwrap();
+        assert!(options[0].1 == b"\x10\x00\x03\xe8\x00\x00" && options[1].1 == b"\x03");
+        assert!(Config::from_str("[mssql]\nversion = \"16\"\n").is_err());
+        assert!(Config::from_str("[mssql]\ninstance = \"A;B\"\n").is_err());
+    }
+
+    #[test]
+    fn test_mssql_login() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        repl(NMAP_PRELOGIN, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let login = login7(&[
+            (36, "WORKSTATION"),
+            (40, "sa"),
+            (44, "s3cret"),
+            (48, "Nmap NSE"),
+            (52, "192.0.2.1"),
+            (60, "Nmap NSE"),
+        ]);
+        let fields = login7_parse(&login[8..]).unwrap();
+        let event = login7_event(&login[8..], &fields, &client_info);
+        assert!(event.fields["username"] == "sa");
+        assert!(event.fields["password"] == "s3cret");
+        assert!(event.fields["hostname"] == "WORKSTATION");
+        assert!(event.fields["appname"] == "Nmap NSE");
+        assert!(event.fields["server_name"] == "192.0.2.1");
+        assert!(event.fields["database"] == "");
+        assert!(event.fields["tds_version"] == "0x74000004");
+        let repl_data = repl(&login, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(client_info.close);
+        assert!(repl_data[0] == TDS_TABULAR_RESULT && repl_data[6] == 2);
+        assert!(u16::from_be_bytes([repl_data[2], repl_data[3]]) as usize == repl_data.len());
+        /* ERROR token: login failed, then DONE (error) */
+        assert!(repl_data[8] == TDS_TOKEN_ERROR);
+        assert!(repl_data[11..15] == MSSQL_ERROR_LOGIN_FAILED.to_le_bytes());
+        let message = utf16le_encode("Login failed for user 'sa'.");
+        assert!(repl_data.windows(message.len()).any(|w| w == &message[..]));
+        assert!(repl_data[repl_data.len() - 13..repl_data.len() - 10] == [TDS_TOKEN_DONE, 2, 0]);
+        /* invalid LOGIN7 */
+        let mut client_info = ClientInfo::new();
+        let data = tds_packet(TDS_LOGIN7, 0, 1, &[0; 20]);
+        assert!(repl(&data, &masscanned, &mut client_info, None).is_none());
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_mssql_browser() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.port.dst = Some(1434);
+        client_info.transport = Some(IpNextHeaderProtocols::Udp);
+        let expected = b"ServerName;SQLSERVER;InstanceName;MSSQLSERVER;IsClustered;No;Version;15.0.2000.5;tcp;1433;;";
+        /* CLNT_UCAST_EX (ms-sql-info), CLNT_BCAST_EX
+         * (broadcast-ms-sql-discover), CLNT_UCAST_INST */
+        for data in [
+            &b"\x03"[..],
+            b"\x02",
+            b"\x04MSSQLSERVER\x00",
+            b"\x04mssqlserver",
+        ]
+        .iter()
+        {
+            let repl_data =
+                crate::proto::dispatch(data, &masscanned, &mut client_info, None).unwrap();
+            assert!(repl_data[0] == SSRP_SVR_RESP);
+            assert!(u16::from_le_bytes([repl_data[1], repl_data[2]]) as usize == expected.len());
+            assert!(repl_data[3..] == expected[..]);
+        }
+        for data in [&b"\x04SQLEXPRESS\x00"[..], b"\x0f\x01MSSQLSERVER\x00", b""].iter() {
+            assert!(crate::proto::dispatch(data, &masscanned, &mut client_info, None).is_none());
+        }
+    }
+
+    #[test]
+    fn test_mssql_browser_udp() {
+        /* the answers are much larger than the 1-byte requests, but
+         * still sent with the default [udp] config */
+        let masscanned = masscanned(Config::default());
+        for data in [&b"\x03"[..], b"\x02"].iter() {
+            /* source port 50000, destination port 1434 */
+            let mut packet = b"\xc3\x50\x05\x9a\x00\x09\x00\x00".to_vec();
+            packet.extend_from_slice(data);
+            let udp_req = UdpPacket::new(&packet).unwrap();
+            let mut client_info = ClientInfo::new();
+            client_info.transport = Some(IpNextHeaderProtocols::Udp);
+            let udp_repl =
+                crate::layer_4::udp::repl(&udp_req, &masscanned, &mut client_info).unwrap();
+            assert!(udp_repl.get_destination() == 50000 && udp_repl.get_source() == 1434);
+            let repl_data = udp_repl.payload();
+            assert!(repl_data[0] == SSRP_SVR_RESP);
+            assert!(repl_data[3..].starts_with(b"ServerName;SQLSERVER;"));
+        }
+    }
+}
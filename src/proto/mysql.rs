@@ -0,0 +1,532 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* capability flags */
+const CLIENT_CONNECT_WITH_DB: u32 = 0x0000_0008;
+const CLIENT_PROTOCOL_41: u32 = 0x0000_0200;
+const CLIENT_SSL: u32 = 0x0000_0800;
+const CLIENT_SECURE_CONNECTION: u32 = 0x0000_8000;
+const CLIENT_PLUGIN_AUTH: u32 = 0x0008_0000;
+const CLIENT_CONNECT_ATTRS: u32 = 0x0010_0000;
+const CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA: u32 = 0x0020_0000;
+
+/* SERVER_STATUS_AUTOCOMMIT */
+const MYSQL_STATUS: u16 = 0x0002;
+/* ER_ACCESS_DENIED_ERROR */
+const MYSQL_ERROR_ACCESS_DENIED: u16 = 1045;
+
+/* maximum size of the packets received */
+const MYSQL_MAX_PACKET: usize = 65536;
+
+/* MySQL server: the Initial Handshake Packet (protocol 10) is sent when
+ * the TCP handshake is completed, and logins are refused (the
+ * error_message can use {user}, {host} and {password}: YES or NO).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MysqlConfig {
+    pub ports: Vec<u16>,
+    pub version: String,
+    pub capabilities: u32,
+    pub charset: u8,
+    pub auth_plugin: String,
+    pub error_message: String,
+}
+
+impl Default for MysqlConfig {
+    fn default() -> Self {
+        MysqlConfig {
+            ports: vec![3306],
+            version: "8.0.36-0ubuntu0.22.04.1".to_string(),
+            /* MySQL 8.0, without CLIENT_SSL */
+            capabilities: 0xdfff_f7ff,
+            /* utf8mb4_0900_ai_ci */
+            charset: 255,
+            auth_plugin: "caching_sha2_password".to_string(),
+            error_message: "Access denied for user '{user}'@'{host}' (using password: {password})"
+                .to_string(),
+        }
+    }
+}
+
+impl MysqlConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for s in [&self.version, &self.auth_plugin].iter() {
+            if s.contains('\0') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("mysql: invalid string (NUL found): {:?}", s),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/* MySQL packets: 3-byte length, sequence id, payload - packets may
+ * arrive several in one segment or split across segments */
+#[derive(Default)]
+pub struct MysqlCodec {
+    buffer: Vec<u8>,
+}
+
+impl MysqlCodec {
+    /* complete packets received so far: sequence id and payload */
+    pub fn push(&mut self, data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        self.buffer.extend_from_slice(data);
+        let mut packets = Vec::new();
+        while self.buffer.len() >= 4 {
+            let len =
+                u32::from_le_bytes([self.buffer[0], self.buffer[1], self.buffer[2], 0]) as usize;
+            if len > MYSQL_MAX_PACKET {
+                info!("MySQL packet too large ({} bytes) - dropped", len);
+                self.buffer.clear();
+                break;
+            }
+            if self.buffer.len() < 4 + len {
+                break;
+            }
+            let seq = self.buffer[3];
+            packets.push((seq, self.buffer[4..4 + len].to_vec()));
+            self.buffer.drain(..4 + len);
+        }
+        packets
+    }
+}
+
+pub fn mysql_packet(seq: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+    packet.push(seq);
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/* state of a MySQL flow: packets, scramble sent in the greeting */
+#[derive(Default)]
+pub struct MysqlState {
+    codec: MysqlCodec,
+    salt: [u8; 20],
+}
+
+/* Protocol::HandshakeV10 */
+fn mysql_handshake(config: &MysqlConfig, state: &MysqlState) -> Vec<u8> {
+    let mut payload = vec![10];
+    payload.extend_from_slice(config.version.as_bytes());
+    payload.push(0);
+    /* connection id */
+    payload.extend_from_slice(&(rand::random::<u16>() as u32 + 8).to_le_bytes());
+    payload.extend_from_slice(&state.salt[..8]);
+    payload.push(0);
+    payload.extend_from_slice(&(config.capabilities as u16).to_le_bytes());
+    payload.push(config.charset);
+    payload.extend_from_slice(&MYSQL_STATUS.to_le_bytes());
+    payload.extend_from_slice(&((config.capabilities >> 16) as u16).to_le_bytes());
+    payload.push(if config.capabilities & CLIENT_PLUGIN_AUTH != 0 {
+        state.salt.len() as u8 + 1
+    } else {
+        0
+    });
+    payload.extend_from_slice(&[0; 10]);
+    if config.capabilities & CLIENT_SECURE_CONNECTION != 0 {
+        payload.extend_from_slice(&state.salt[8..]);
+        payload.push(0);
+    }
+    if config.capabilities & CLIENT_PLUGIN_AUTH != 0 {
+        payload.extend_from_slice(config.auth_plugin.as_bytes());
+        payload.push(0);
+    }
+    payload
+}
+
+struct MysqlReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> MysqlReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (value, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(value)
+    }
+
+    fn int(&mut self, len: usize) -> Option<u64> {
+        let mut bytes = [0u8; 8];
+        bytes[..len].copy_from_slice(self.take(len)?);
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    /* length-encoded integer */
+    fn lenenc(&mut self) -> Option<u64> {
+        match self.int(1)? {
+            0xfc => self.int(2),
+            0xfd => self.int(3),
+            0xfe => self.int(8),
+            0xfb | 0xff => None,
+            n => Some(n),
+        }
+    }
+
+    fn lenenc_str(&mut self) -> Option<&'a [u8]> {
+        let len = self.lenenc()?;
+        if len > self.data.len() as u64 {
+            return None;
+        }
+        self.take(len as usize)
+    }
+
+    /* NUL-terminated (or up to the end of the packet) */
+    fn str_nul(&mut self) -> &'a [u8] {
+        let end = self.data.iter().position(|c| *c == 0);
+        let value = &self.data[..end.unwrap_or(self.data.len())];
+        self.data = &self.data[end.map_or(self.data.len(), |e| e + 1)..];
+        value
+    }
+}
+
+struct MysqlLogin<'a> {
+    capabilities: u32,
+    charset: Option<u8>,
+    user: &'a [u8],
+    auth_response: &'a [u8],
+    database: Option<&'a [u8]>,
+    plugin: Option<&'a [u8]>,
+    attributes: Vec<(&'a [u8], &'a [u8])>,
+}
+
+/* Protocol::HandshakeResponse41, or HandshakeResponse320 (old clients) */
+fn mysql_parse_login(payload: &[u8]) -> Option<MysqlLogin<'_>> {
+    let mut reader = MysqlReader { data: payload };
+    let mut capabilities = reader.int(2)? as u32;
+    let mut login = MysqlLogin {
+        capabilities,
+        charset: None,
+        user: b"",
+        auth_response: b"",
+        database: None,
+        plugin: None,
+        attributes: Vec::new(),
+    };
+    if capabilities & CLIENT_PROTOCOL_41 == 0 {
+        /* max packet size */
+        reader.take(3)?;
+        login.user = reader.str_nul();
+        login.auth_response = reader.str_nul();
+        return Some(login);
+    }
+    capabilities |= (reader.int(2)? as u32) << 16;
+    login.capabilities = capabilities;
+    reader.take(4)?;
+    login.charset = Some(reader.int(1)? as u8);
+    reader.take(23)?;
+    login.user = reader.str_nul();
+    login.auth_response = if capabilities & CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA != 0 {
+        reader.lenenc_str()?
+    } else if capabilities & CLIENT_SECURE_CONNECTION != 0 {
+        let len = reader.int(1)? as usize;
+        reader.take(len)?
+    } else {
+        reader.str_nul()
+    };
+    if capabilities & CLIENT_CONNECT_WITH_DB != 0 {
+        login.database = Some(reader.str_nul());
+    }
+    if capabilities & CLIENT_PLUGIN_AUTH != 0 {
+        login.plugin = Some(reader.str_nul());
+    }
+    if capabilities & CLIENT_CONNECT_ATTRS != 0 {
+        let mut attributes = MysqlReader {
+            data: reader.lenenc_str()?,
+        };
+        while !attributes.data.is_empty() {
+            let key = attributes.lenenc_str()?;
+            let value = attributes.lenenc_str()?;
+            login.attributes.push((key, value));
+        }
+    }
+    Some(login)
+}
+
+fn mysql_login_event(login: &MysqlLogin, state: &MysqlState, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("mysql_login", client_info);
+    event.set("user", String::from_utf8_lossy(login.user).to_string());
+    if let Some(database) = login.database {
+        event.set("database", String::from_utf8_lossy(database).to_string());
+    }
+    event.set("capabilities", login.capabilities);
+    if let Some(charset) = login.charset {
+        event.set("charset", charset);
+    }
+    if let Some(plugin) = login.plugin {
+        event.set("auth_plugin", String::from_utf8_lossy(plugin).to_string());
+    }
+    event.set("auth_response", encode(login.auth_response, Encoding::Hex));
+    event.set("salt", encode(&state.salt, Encoding::Hex));
+    if !login.attributes.is_empty() {
+        let mut attributes = Map::new();
+        for (k, v) in login.attributes.iter() {
+            attributes.insert(
+                String::from_utf8_lossy(k).to_string(),
+                Value::String(String::from_utf8_lossy(v).to_string()),
+            );
+        }
+        event.set("attributes", Value::Object(attributes));
+    }
+    event
+}
+
+/* ERR_Packet */
+fn mysql_error(code: u16, state: &str, message: &str) -> Vec<u8> {
+    let mut payload = vec![0xff];
+    payload.extend_from_slice(&code.to_le_bytes());
+    payload.push(b'#');
+    payload.extend_from_slice(state.as_bytes());
+    payload.extend_from_slice(message.as_bytes());
+    payload
+}
+
+/* Initial Handshake Packet, sent when the TCP handshake is completed */
+pub fn greeting(masscanned: &Masscanned, tcb: &mut TCPControlBlock) -> Option<Vec<u8>> {
+    let mut state = MysqlState::default();
+    /* printable scramble, as sent by MySQL */
+    for c in state.salt.iter_mut() {
+        *c = 0x21 + rand::random::<u8>() % 0x5e;
+    }
+    let repl = mysql_packet(0, &mysql_handshake(&masscanned.config.mysql, &state));
+    tcb.proto_state = ProtoState::Mysql(state);
+    Some(repl)
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving MySQL data");
+    let config = &masscanned.config.mysql;
+    /* MySQL is only answered over TCP, after the greeting */
+    let state = match tcb {
+        Some(TCPControlBlock {
+            proto_state: ProtoState::Mysql(s),
+            ..
+        }) => s,
+        _ => return None,
+    };
+    let (seq, payload) = state.codec.push(data).into_iter().next()?;
+    client_info.close = true;
+    let login = match mysql_parse_login(&payload) {
+        Some(l) if l.capabilities & CLIENT_SSL != 0 && payload.len() == 32 => {
+            info!("MySQL SSL request not handled");
+            return None;
+        }
+        Some(l) => l,
+        None => {
+            info!("MySQL data not handled (invalid handshake response)");
+            return Some(mysql_packet(
+                seq.wrapping_add(1),
+                &mysql_error(1043, "08S01", "Bad handshake"),
+            ));
+        }
+    };
+    let user = String::from_utf8_lossy(login.user).to_string();
+    warn!(
+        "MySQL login: {} (auth response {})",
+        user,
+        encode(login.auth_response, Encoding::Hex)
+    );
+    mysql_login_event(&login, state, client_info).log();
+    let host = client_info
+        .ip
+        .src
+        .map_or("localhost".to_string(), |ip| ip.to_string());
+    let message = config
+        .error_message
+        .replace("{user}", &user)
+        .replace("{host}", &host)
+        .replace(
+            "{password}",
+            if login.auth_response.is_empty() {
+                "NO"
+            } else {
+                "YES"
+            },
+        );
+    debug!("sending MySQL data");
+    Some(mysql_packet(
+        seq.wrapping_add(1),
+        &mysql_error(MYSQL_ERROR_ACCESS_DENIED, "28000", &message),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::str::FromStr;
+
+    /* Handshake Response of mysql (8.0 client), "mysql -u root -p" */
+    const MYSQL_CLIENT_LOGIN: &[u8] = b"\xd2\x00\x00\x01\x85\xa6\xff\x01\x00\x00\x00\x01\xff\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00root\x00 [\x1fj\x0e\x8b\xd1\xe2L\x0d:\x9b\xb6\xc7\xf1\xe6\xe8\xa2\xc8n\x0fM\x1d\x0c\x8e;\x9f'\xa1\xc6\xd5\xe4\xf3caching_sha2_password\x00u\x04_pid\x06152839\x09_platform\x06x86_64\x03_os\x05Linux\x0c_client_name\x08libmysql\x07os_user\x05alice\x0f_client_version\x068.0.36\x0cprogram_name\x05mysql";
+
+    #[test]
+    fn test_mysql_codec() {
+        let mut codec = MysqlCodec::default();
+        let packets = mysql_packet(0, b"abc")
+            .into_iter()
+            .chain(mysql_packet(1, b""))
+            .chain(mysql_packet(2, b"de"))
+            .collect::<Vec<u8>>();
+        assert!(packets[..7] == *b"\x03\x00\x00\x00abc");
+        assert!(codec.push(&packets[..5]).is_empty());
+        assert!(codec.push(&packets[5..12]) == vec![(0, b"abc".to_vec()), (1, Vec::new())]);
+        assert!(codec.push(&packets[12..]) == vec![(2, b"de".to_vec())]);
+        /* oversized packets are dropped */
+        assert!(codec.push(b"\xff\xff\xff\x00abc").is_empty());
+        assert!(codec.push(&mysql_packet(3, b"f")) == vec![(3, b"f".to_vec())]);
+    }
+
+    #[test]
+    fn test_mysql_greeting() {
+        let masscanned = masscanned(Config::default());
+        let mut tcb = TCPControlBlock::new();
+        let greeting = greeting(&masscanned, &mut tcb).unwrap();
+        let salt = match &tcb.proto_state {
+            ProtoState::Mysql(s) => s.salt,
+            _ => panic!("expected a MySQL state"),
+        };
+        /* what nmap mysql-info reads */
+        let mut codec = MysqlCodec::default();
+        let packets = codec.push(&greeting);
+        assert!(packets.len() == 1 && packets[0].0 == 0);
+        let mut reader = MysqlReader {
+            data: &packets[0].1,
+        };
+        assert!(reader.int(1) == Some(10));
+        assert!(reader.str_nul() == b"8.0.36-0ubuntu0.22.04.1");
+        let thread_id = reader.int(4).unwrap();
+        assert!(thread_id > 0);
+        assert!(reader.take(8) == Some(&salt[..8]));
+        assert!(reader.int(1) == Some(0));
+        let mut capabilities = reader.int(2).unwrap();
+        assert!(reader.int(1) == Some(255));
+        assert!(reader.int(2) == Some(MYSQL_STATUS as u64));
+        capabilities |= reader.int(2).unwrap() << 16;
+        assert!(capabilities == 0xdfff_f7ff);
+        assert!(reader.int(1) == Some(21));
+        assert!(reader.take(10) == Some(&[0; 10][..]));
+        assert!(reader.take(12) == Some(&salt[8..]));
+        assert!(reader.int(1) == Some(0));
+        assert!(reader.str_nul() == b"caching_sha2_password" && reader.data.is_empty());
+        assert!(salt.iter().all(|c| c.is_ascii_graphic()));
+        assert!(Config::from_str("[mysql]\nversion = \"5.7\\u0000\"\n").is_err());
+    }
+
+    #[test]
+    fn test_mysql_login() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        let mut tcb = TCPControlBlock::new();
+        greeting(&masscanned, &mut tcb);
+        /* split across segments */
+        assert!(repl(
+            &MYSQL_CLIENT_LOGIN[..40],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl_data = repl(
+            &MYSQL_CLIENT_LOGIN[40..],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        let message = b"Access denied for user 'root'@'192.0.2.1' (using password: YES)";
+        let mut expected = mysql_packet(2, &[&b"\xff\x15\x04#28000"[..], message].concat());
+        assert!(repl_data == expected);
+        assert!(client_info.close);
+        let login = mysql_parse_login(&MYSQL_CLIENT_LOGIN[4..]).unwrap();
+        let state = MysqlState::default();
+        let event = mysql_login_event(&login, &state, &client_info);
+        assert!(event.fields["user"] == "root");
+        assert!(event.fields.get("database").is_none());
+        assert!(event.fields["capabilities"] == 0x01ff_a685);
+        assert!(event.fields["auth_plugin"] == "caching_sha2_password");
+        assert!(
+            event.fields["auth_response"]
+                == "5b1f6a0e8bd1e24c0d3a9bb6c7f1e6e8a2c86e0f4d1d0c8e3b9f27a1c6d5e4f3"
+        );
+        assert!(event.fields["attributes"]["_client_name"] == "libmysql");
+        assert!(event.fields["attributes"]["program_name"] == "mysql");
+        /* database, 8-bit auth response length, no password */
+        let mut payload = (CLIENT_PROTOCOL_41 | CLIENT_SECURE_CONNECTION | CLIENT_CONNECT_WITH_DB)
+            .to_le_bytes()
+            .to_vec();
+        payload.extend_from_slice(&[0, 0, 0, 1, 33]);
+        payload.extend_from_slice(&[0; 23]);
+        payload.extend_from_slice(b"admin\x00\x00mysql\x00");
+        let login = mysql_parse_login(&payload).unwrap();
+        assert!(login.user == b"admin" && login.auth_response.is_empty());
+        assert!(login.database == Some(&b"mysql"[..]) && login.plugin.is_none());
+        let mut tcb = TCPControlBlock::new();
+        greeting(&masscanned, &mut tcb);
+        let repl_data = repl(
+            &mysql_packet(1, &payload),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        );
+        assert!(repl_data
+            .unwrap()
+            .ends_with(b"'admin'@'192.0.2.1' (using password: NO)"));
+        /* HandshakeResponse320 */
+        let login = mysql_parse_login(b"\x85\x24\x00\x00\x00guest\x00RTEYBXMH").unwrap();
+        assert!(login.user == b"guest" && login.auth_response == b"RTEYBXMH");
+        /* truncated responses */
+        for i in 0..MYSQL_CLIENT_LOGIN.len() - 4 {
+            if i < 32 || (i > 37 && i < 70) {
+                assert!(mysql_parse_login(&MYSQL_CLIENT_LOGIN[4..4 + i]).is_none());
+            }
+        }
+        let mut tcb = TCPControlBlock::new();
+        greeting(&masscanned, &mut tcb);
+        expected = mysql_packet(2, &mysql_error(1043, "08S01", "Bad handshake"));
+        let repl_data = repl(
+            &mysql_packet(1, b"\x85"),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        );
+        assert!(repl_data == Some(expected));
+    }
+}
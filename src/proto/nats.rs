@@ -0,0 +1,436 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::line::{LineBuffer, LINE_MAX_BUFFER};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* NATS server (server-first): the INFO message is sent once the TCP
+ * handshake is completed. CONNECT options (and credentials) are
+ * logged; with auth_required, the connection is then refused. SUB and
+ * PUB operations are logged, with the published payloads.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NatsConfig {
+    pub ports: Vec<u16>,
+    pub server_id: String,
+    pub server_name: String,
+    pub version: String,
+    pub go: String,
+    pub auth_required: bool,
+    pub max_payload: usize,
+}
+
+impl Default for NatsConfig {
+    fn default() -> Self {
+        NatsConfig {
+            ports: vec![4222],
+            server_id: "NCUFQX6SBTUQFUJFJ3TQYGGOG5NST7YSRWCZ5XQJRNGO7RMSME3UWCL4".to_string(),
+            server_name: "nats01".to_string(),
+            version: "2.10.7".to_string(),
+            go: "go1.21.5".to_string(),
+            auth_required: true,
+            max_payload: 1048576,
+        }
+    }
+}
+
+/* a PUB (or HPUB) line, whose payload (and CRLF) comes next */
+struct NatsPub {
+    subject: String,
+    reply: Option<String>,
+    size: usize,
+}
+
+/* state of a NATS flow: partial line, verbose flag of CONNECT, PUB
+ * waiting for its payload, size of a payload too large to be kept */
+#[derive(Default)]
+pub struct NatsState {
+    lines: LineBuffer,
+    verbose: bool,
+    publish: Option<NatsPub>,
+    discard: usize,
+}
+
+fn nats_err(text: &str) -> Vec<u8> {
+    format!("-ERR '{}'\r\n", text).into_bytes()
+}
+
+fn nats_ok(state: &NatsState) -> Vec<u8> {
+    if state.verbose {
+        b"+OK\r\n".to_vec()
+    } else {
+        Vec::new()
+    }
+}
+
+/* options of CONNECT, with user, pass, auth_token, jwt and nkey as
+ * credentials */
+fn nats_connect_event(options: Value, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("nats_connect", client_info);
+    for key in [
+        "user",
+        "pass",
+        "auth_token",
+        "jwt",
+        "nkey",
+        "name",
+        "lang",
+        "version",
+    ]
+    .iter()
+    {
+        if let Some(v) = options[key].as_str() {
+            event.set(key, v);
+        }
+    }
+    event.set("options", options);
+    event
+}
+
+/* "CONNECT {...}" */
+fn nats_connect(
+    options: &str,
+    config: &NatsConfig,
+    client_info: &mut ClientInfo,
+    state: &mut NatsState,
+) -> Vec<u8> {
+    let options: Value = match serde_json::from_str(options) {
+        Ok(v @ Value::Object(_)) => v,
+        _ => {
+            client_info.close = true;
+            return nats_err("Invalid Connect Options");
+        }
+    };
+    state.verbose = options["verbose"].as_bool().unwrap_or(false);
+    warn!(
+        "NATS CONNECT: {}:{}",
+        options["user"].as_str().unwrap_or(""),
+        options["pass"].as_str().unwrap_or("")
+    );
+    nats_connect_event(options, client_info).log();
+    if config.auth_required {
+        client_info.close = true;
+        return nats_err("Authorization Violation");
+    }
+    nats_ok(state)
+}
+
+/* "PUB <subject> [reply-to] <size>", "HPUB <subject> [reply-to]
+ * <header size> <total size>" */
+fn nats_parse_pub(args: &[&str], headers: bool) -> Option<NatsPub> {
+    let n = if headers { 3 } else { 2 };
+    if args.len() != n && args.len() != n + 1 {
+        return None;
+    }
+    let size = args.last()?.parse().ok()?;
+    if headers && args[args.len() - 2].parse::<usize>().ok()? > size {
+        return None;
+    }
+    Some(NatsPub {
+        subject: args[0].to_string(),
+        reply: if args.len() == n + 1 {
+            Some(args[1].to_string())
+        } else {
+            None
+        },
+        size,
+    })
+}
+
+/* answer to an operation line */
+fn nats_line(
+    line: &str,
+    config: &NatsConfig,
+    client_info: &mut ClientInfo,
+    state: &mut NatsState,
+) -> Vec<u8> {
+    let (op, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let args: Vec<&str> = rest.split_whitespace().collect();
+    match op.to_ascii_uppercase().as_str() {
+        "CONNECT" => nats_connect(rest, config, client_info, state),
+        "PING" => b"PONG\r\n".to_vec(),
+        "PONG" => Vec::new(),
+        "SUB" if args.len() == 2 || args.len() == 3 => {
+            let mut event = Event::new("nats_sub", client_info);
+            event.set("subject", args[0]);
+            if args.len() == 3 {
+                event.set("queue", args[1]);
+            }
+            event.set("sid", args[args.len() - 1]);
+            event.log();
+            nats_ok(state)
+        }
+        "UNSUB" if args.len() == 1 || args.len() == 2 => nats_ok(state),
+        op @ ("PUB" | "HPUB") => match nats_parse_pub(&args, op == "HPUB") {
+            Some(p) if p.size > config.max_payload => {
+                client_info.close = true;
+                nats_err("Maximum Payload Violation")
+            }
+            Some(p) => {
+                state.publish = Some(p);
+                Vec::new()
+            }
+            None => {
+                client_info.close = true;
+                nats_err("Unknown Protocol Operation")
+            }
+        },
+        "" => Vec::new(),
+        _ => {
+            client_info.close = true;
+            nats_err("Unknown Protocol Operation")
+        }
+    }
+}
+
+fn nats_pub_event(p: &NatsPub, payload: Option<&[u8]>, client_info: &ClientInfo) {
+    warn!("NATS PUB to {} ({} bytes)", p.subject, p.size);
+    let mut event = Event::new("nats_pub", client_info);
+    event.set("subject", p.subject.clone());
+    event.set("reply", p.reply.clone());
+    event.set("size", p.size);
+    if let Some(payload) = payload {
+        event.set("payload", String::from_utf8_lossy(payload));
+    }
+    event.log();
+}
+
+/* INFO, sent when the TCP handshake is completed */
+fn nats_info(config: &NatsConfig, client_info: &ClientInfo) -> Vec<u8> {
+    let mut info = json!({
+        "server_id": config.server_id,
+        "server_name": config.server_name,
+        "version": config.version,
+        "proto": 1,
+        "go": config.go,
+        "host": "0.0.0.0",
+        "port": client_info.port.dst.unwrap_or(4222),
+        "headers": true,
+        "max_payload": config.max_payload,
+        "client_id": rand::random::<u16>() as u32 + 1,
+    });
+    if config.auth_required {
+        info["auth_required"] = json!(true);
+    }
+    if let Some(ip) = client_info.ip.src {
+        info["client_ip"] = json!(ip.to_string());
+    }
+    format!("INFO {}\r\n", info).into_bytes()
+}
+
+pub fn greeting(
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+    tcb: &mut TCPControlBlock,
+) -> Option<Vec<u8>> {
+    tcb.proto_state = ProtoState::Nats(NatsState::default());
+    Some(nats_info(&masscanned.config.nats, client_info))
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving NATS data");
+    let config = &masscanned.config.nats;
+    /* NATS is only answered over TCP, after INFO */
+    let state = match tcb {
+        Some(TCPControlBlock {
+            proto_state: ProtoState::Nats(s),
+            ..
+        }) => s,
+        _ => return None,
+    };
+    let mut repl_data = Vec::new();
+    state.lines.extend(data);
+    while !client_info.close {
+        if state.discard > 0 {
+            state.discard -= state.lines.discard(state.discard);
+            if state.discard > 0 {
+                break;
+            }
+            continue;
+        }
+        if let Some(p) = state.publish.take() {
+            /* payload, then CRLF */
+            if p.size + 2 > LINE_MAX_BUFFER {
+                info!("NATS payload too large ({} bytes) - dropped", p.size);
+                nats_pub_event(&p, None, client_info);
+                state.discard = p.size + 2;
+                repl_data.extend(nats_ok(state));
+                continue;
+            }
+            match state.lines.take(p.size + 2) {
+                Some(payload) => {
+                    nats_pub_event(&p, Some(&payload[..p.size]), client_info);
+                    repl_data.extend(nats_ok(state));
+                    continue;
+                }
+                None => {
+                    state.publish = Some(p);
+                    break;
+                }
+            }
+        }
+        let line = match state.lines.line() {
+            Some(l) => String::from_utf8_lossy(&l).to_string(),
+            None => break,
+        };
+        repl_data.extend(nats_line(&line, config, client_info, state));
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending NATS data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_nats_connect() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        let info = greeting(&masscanned, &client_info, &mut tcb).unwrap();
+        assert!(info.starts_with(b"INFO {") && info.ends_with(b"}\r\n"));
+        let info: Value = serde_json::from_slice(&info[5..info.len() - 2]).unwrap();
+        assert!(info["server_id"] == masscanned.config.nats.server_id);
+        assert!(info["version"] == "2.10.7");
+        assert!(info["auth_required"] == true);
+        assert!(info["max_payload"] == 1048576);
+        /* PING, then CONNECT with credentials: refused */
+        let repl_data = repl(b"PING\r\n", &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl_data == b"PONG\r\n");
+        let mut state = NatsState::default();
+        let config = &masscanned.config.nats;
+        let connect = r#"{"verbose":true,"pedantic":false,"user":"admin","pass":"s3cr3t","name":"scan","lang":"go","version":"1.31.0"}"#;
+        let repl_data = nats_connect(connect, config, &mut client_info, &mut state);
+        assert!(repl_data == b"-ERR 'Authorization Violation'\r\n");
+        assert!(client_info.close && state.verbose);
+        let event = nats_connect_event(serde_json::from_str(connect).unwrap(), &client_info);
+        assert!(event.name == "nats_connect");
+        assert!(event.fields["user"] == "admin" && event.fields["pass"] == "s3cr3t");
+        assert!(event.fields["name"] == "scan" && event.fields["options"]["lang"] == "go");
+        let event = nats_connect_event(json!({"auth_token": "t0k3n"}), &client_info);
+        assert!(event.fields["auth_token"] == "t0k3n" && event.fields.get("user").is_none());
+        /* without authentication: +OK (verbose) */
+        let masscanned =
+            self::masscanned(Config::from_str("[nats]\nauth_required = false\n").unwrap());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        let info = greeting(&masscanned, &client_info, &mut tcb).unwrap();
+        assert!(!String::from_utf8_lossy(&info).contains("auth_required"));
+        let repl_data = repl(
+            b"CONNECT {\"verbose\":true,\"auth_token\":\"t0k3n\"}\r\nPING\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"+OK\r\nPONG\r\n" && !client_info.close);
+        /* invalid options, unknown operations */
+        let repl_data = repl(
+            b"CONNECT nope\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"-ERR 'Invalid Connect Options'\r\n" && client_info.close);
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        greeting(&masscanned, &client_info, &mut tcb);
+        let repl_data = repl(
+            b"GET / HTTP/1.1\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"-ERR 'Unknown Protocol Operation'\r\n" && client_info.close);
+    }
+
+    #[test]
+    fn test_nats_pub() {
+        let masscanned = masscanned(Config::from_str("[nats]\nauth_required = false\n").unwrap());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        greeting(&masscanned, &client_info, &mut tcb);
+        /* not verbose: no +OK; payload (with a CRLF inside) split across
+         * segments */
+        assert!(repl(
+            b"CONNECT {}\r\nSUB orders.* 1\r\nPUB orders.new _INBOX.1 12\r\nhello\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        match &tcb.proto_state {
+            ProtoState::Nats(s) => {
+                let p = s.publish.as_ref().unwrap();
+                assert!(p.subject == "orders.new" && p.reply.as_deref() == Some("_INBOX.1"));
+                assert!(p.size == 12);
+            }
+            _ => panic!("expected a NATS state"),
+        }
+        let repl_data = repl(
+            b"world\r\nPING\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"PONG\r\n");
+        match &tcb.proto_state {
+            ProtoState::Nats(s) => assert!(s.publish.is_none()),
+            _ => panic!("expected a NATS state"),
+        }
+        /* verbose, with headers, and a payload too large to be kept */
+        let mut data = b"CONNECT {\"verbose\":true}\r\nHPUB a 12 20000\r\n".to_vec();
+        data.extend(vec![b'x'; 20000]);
+        data.extend(b"\r\nPUB b 0\r\n\r\n");
+        let repl_data = repl(&data, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl_data == b"+OK\r\n+OK\r\n+OK\r\n" && !client_info.close);
+        /* invalid PUB lines, too large payloads */
+        assert!(nats_parse_pub(&["a", "x"], false).is_none());
+        assert!(nats_parse_pub(&["a", "b", "c", "1"], false).is_none());
+        assert!(nats_parse_pub(&["a", "5", "4"], true).is_none());
+        let repl_data = repl(
+            b"PUB a 2000000\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"-ERR 'Maximum Payload Violation'\r\n" && client_info.close);
+    }
+}
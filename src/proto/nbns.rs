@@ -0,0 +1,493 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use log::*;
+use pnet::util::MacAddr;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::dns::{be16, dns_labels, DNS_CLASS_IN};
+use crate::Masscanned;
+
+/* RFC 1002 section 4.2.1.3 */
+const NBNS_TYPE_NB: u16 = 0x0020;
+const NBNS_TYPE_NBSTAT: u16 = 0x0021;
+
+/* length of NetBIOS names (the last byte is the suffix) */
+const NBNS_NAME_LEN: usize = 16;
+
+/* RFC 1002 section 4.2.18: flags of the entries of the name table */
+const NBNS_NAME_GROUP: u16 = 0x8000;
+const NBNS_NAME_ACTIVE: u16 = 0x0400;
+
+/* suffixes (NetBIOS service of the names) */
+const NBNS_SUFFIX_WORKSTATION: u8 = 0x00;
+const NBNS_SUFFIX_SERVER: u8 = 0x20;
+
+/* NetBIOS name server (on the ports): name queries are answered with the
+ * address that was probed, for the configured names (or any name, when
+ * wildcard is set) and the workstation name; node status requests get
+ * the name table (workstation, domain, server service) and a MAC
+ * address (ours when not set).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NbnsConfig {
+    pub ports: Vec<u16>,
+    pub names: Vec<String>,
+    pub wildcard: bool,
+    pub workstation: String,
+    pub domain: String,
+    /* report the server service (<20>) in the name table */
+    pub server: bool,
+    pub mac: Option<String>,
+    pub ttl: u32,
+    #[serde(skip)]
+    unit_id: Option<MacAddr>,
+}
+
+impl Default for NbnsConfig {
+    fn default() -> Self {
+        NbnsConfig {
+            ports: vec![137],
+            names: Vec::new(),
+            wildcard: false,
+            workstation: "SERVER".to_string(),
+            domain: "WORKGROUP".to_string(),
+            server: true,
+            mac: None,
+            ttl: 300000,
+            unit_id: None,
+        }
+    }
+}
+
+fn nbns_check_name(name: &str) -> Result<(), io::Error> {
+    if name.is_empty() || name.len() >= NBNS_NAME_LEN || !name.is_ascii() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "nbns: invalid name (1 to 15 ASCII characters expected): {}",
+                name
+            ),
+        ));
+    }
+    Ok(())
+}
+
+impl NbnsConfig {
+    pub fn check(&mut self) -> Result<(), io::Error> {
+        for name in self.names.iter().chain([&self.workstation, &self.domain]) {
+            nbns_check_name(name)?;
+        }
+        if let Some(mac) = &self.mac {
+            self.unit_id = Some(MacAddr::from_str(mac).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("nbns: invalid MAC address: {}", mac),
+                )
+            })?);
+        }
+        Ok(())
+    }
+}
+
+/* name padded with spaces (upper case, truncated to 15 characters),
+ * followed by its suffix */
+fn nbns_pad_name(name: &[u8], suffix: u8) -> [u8; NBNS_NAME_LEN] {
+    let mut padded = [b' '; NBNS_NAME_LEN];
+    for (p, c) in padded.iter_mut().zip(name.iter().take(NBNS_NAME_LEN - 1)) {
+        *p = c.to_ascii_uppercase();
+    }
+    padded[NBNS_NAME_LEN - 1] = suffix;
+    padded
+}
+
+/* RFC 1001 section 14.1: first-level encoding, each half-byte of the
+ * (padded) name is written as a letter from 'A' to 'P' */
+pub fn nbns_encode_name(name: &[u8; NBNS_NAME_LEN]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(2 * NBNS_NAME_LEN);
+    for c in name.iter() {
+        encoded.push(b'A' + (c >> 4));
+        encoded.push(b'A' + (c & 0x0f));
+    }
+    encoded
+}
+
+pub fn nbns_decode_name(encoded: &[u8]) -> Option<[u8; NBNS_NAME_LEN]> {
+    if encoded.len() != 2 * NBNS_NAME_LEN {
+        return None;
+    }
+    let mut name = [0; NBNS_NAME_LEN];
+    for (c, pair) in name.iter_mut().zip(encoded.chunks(2)) {
+        if !(b'A'..=b'P').contains(&pair[0]) || !(b'A'..=b'P').contains(&pair[1]) {
+            return None;
+        }
+        *c = ((pair[0] - b'A') << 4) | (pair[1] - b'A');
+    }
+    Some(name)
+}
+
+/* name (padding removed) and suffix of a decoded name */
+pub(crate) fn nbns_split_name(name: &[u8; NBNS_NAME_LEN]) -> (Vec<u8>, u8) {
+    let mut n = name[..NBNS_NAME_LEN - 1].to_vec();
+    /* padded with spaces, or NUL bytes (e.g., the name "*") */
+    while n.last().is_some_and(|c| *c == b' ' || *c == 0) {
+        n.pop();
+    }
+    (n, name[NBNS_NAME_LEN - 1])
+}
+
+struct NbnsQuery<'a> {
+    id: u16,
+    recursion_desired: bool,
+    raw_name: [u8; NBNS_NAME_LEN],
+    /* scope (labels following the name) as sent */
+    scope: &'a [u8],
+    name: Vec<u8>,
+    suffix: u8,
+    qtype: u16,
+}
+
+fn nbns_parse_query(data: &[u8]) -> Option<NbnsQuery<'_>> {
+    let id = be16(data, 0)?;
+    let flags = be16(data, 2)?;
+    /* RFC 1002 section 4.2.1.1: requests with the QUERY opcode */
+    if flags & 0xf800 != 0 || be16(data, 4)? != 1 {
+        return None;
+    }
+    let (labels, end) = dns_labels(data, 12)?;
+    let raw_name = nbns_decode_name(labels.first()?)?;
+    let (name, suffix) = nbns_split_name(&raw_name);
+    if be16(data, end + 2)? != DNS_CLASS_IN {
+        return None;
+    }
+    Some(NbnsQuery {
+        id,
+        recursion_desired: flags & 0x0100 != 0,
+        raw_name,
+        scope: &data[12 + 1 + 2 * NBNS_NAME_LEN..end],
+        name,
+        suffix,
+        qtype: be16(data, end)?,
+    })
+}
+
+/* answer with one resource record named after the question */
+fn nbns_response(query: &NbnsQuery, flags: u16, ttl: u32, rdata: &[u8]) -> Vec<u8> {
+    let mut repl = Vec::new();
+    repl.extend_from_slice(&query.id.to_be_bytes());
+    repl.extend_from_slice(&flags.to_be_bytes());
+    /* no question, one answer */
+    repl.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0]);
+    /* RFC 1002 section 4.2.1.2: name of the question, encoded */
+    repl.push((2 * NBNS_NAME_LEN) as u8);
+    repl.extend(nbns_encode_name(&query.raw_name));
+    repl.extend_from_slice(query.scope);
+    repl.extend_from_slice(&query.qtype.to_be_bytes());
+    repl.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+    repl.extend_from_slice(&ttl.to_be_bytes());
+    repl.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    repl.extend_from_slice(rdata);
+    repl
+}
+
+/* name table of node status responses */
+fn nbns_names(config: &NbnsConfig) -> Vec<(&str, u8, u16)> {
+    let mut names = vec![(
+        config.workstation.as_str(),
+        NBNS_SUFFIX_WORKSTATION,
+        NBNS_NAME_ACTIVE,
+    )];
+    if config.server {
+        names.push((
+            config.workstation.as_str(),
+            NBNS_SUFFIX_SERVER,
+            NBNS_NAME_ACTIVE,
+        ));
+    }
+    names.push((
+        config.domain.as_str(),
+        NBNS_SUFFIX_WORKSTATION,
+        NBNS_NAME_GROUP | NBNS_NAME_ACTIVE,
+    ));
+    names
+}
+
+/* RFC 1002 section 4.2.18 */
+fn nbns_node_status(query: &NbnsQuery, config: &NbnsConfig, mac: MacAddr) -> Vec<u8> {
+    let names = nbns_names(config);
+    let mut rdata = vec![names.len() as u8];
+    for (name, suffix, flags) in names {
+        rdata.extend_from_slice(&nbns_pad_name(name.as_bytes(), suffix));
+        rdata.extend_from_slice(&flags.to_be_bytes());
+    }
+    /* statistics: unit ID (MAC address), other fields are 0 */
+    rdata.extend_from_slice(&[mac.0, mac.1, mac.2, mac.3, mac.4, mac.5]);
+    rdata.extend_from_slice(&[0; 40]);
+    /* R, AA */
+    nbns_response(query, 0x8400, 0, &rdata)
+}
+
+/* RFC 1002 section 4.2.13 */
+fn nbns_name_query(query: &NbnsQuery, config: &NbnsConfig, ip: &[u8; 4]) -> Option<Vec<u8>> {
+    let known = config.wildcard
+        || config
+            .names
+            .iter()
+            .chain([&config.workstation])
+            .any(|n| n.as_bytes().eq_ignore_ascii_case(&query.name));
+    if !known {
+        return None;
+    }
+    /* unique name, B node */
+    let mut rdata = vec![0, 0];
+    rdata.extend_from_slice(ip);
+    /* R, AA, RD (copied) */
+    let flags = if query.recursion_desired {
+        0x8500
+    } else {
+        0x8400
+    };
+    Some(nbns_response(query, flags, config.ttl, &rdata))
+}
+
+fn nbns_event(query: &NbnsQuery, answered: bool, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("nbns_query", client_info);
+    event.set("name", String::from_utf8_lossy(&query.name).to_string());
+    event.set("suffix", query.suffix);
+    event.set(
+        "type",
+        match query.qtype {
+            NBNS_TYPE_NB => "nb".to_string(),
+            NBNS_TYPE_NBSTAT => "nbstat".to_string(),
+            t => t.to_string(),
+        },
+    );
+    event.set("answered", answered);
+    event
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving NBNS data");
+    let config = &masscanned.config.nbns;
+    let query = match nbns_parse_query(data) {
+        Some(q) => q,
+        None => {
+            info!("NBNS data not handled (invalid query)");
+            return None;
+        }
+    };
+    let repl = match (query.qtype, client_info.ip.dst) {
+        (NBNS_TYPE_NBSTAT, _) => Some(nbns_node_status(
+            &query,
+            config,
+            config.unit_id.unwrap_or(masscanned.mac),
+        )),
+        (NBNS_TYPE_NB, Some(IpAddr::V4(ip))) => nbns_name_query(&query, config, &ip.octets()),
+        _ => None,
+    };
+    nbns_event(&query, repl.is_some(), client_info).log();
+    if repl.is_some() {
+        debug!("sending NBNS data");
+    }
+    repl
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::{client_info, masscanned, SERVER_IP};
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    /* node status request for "*", as sent by nbtscan */
+    const NBTSCAN_QUERY: &[u8] = b"\x00\x07\x00\x10\x00\x01\x00\x00\x00\x00\x00\x00\x20CKAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\x00\x00\x21\x00\x01";
+
+    #[test]
+    fn test_nbns_name_encoding() {
+        /* RFC 1001 section 14.1 */
+        let fred = nbns_pad_name(b"Fred", b' ');
+        assert!(fred == *b"FRED            ");
+        assert!(nbns_encode_name(&fred) == b"EGFCEFEECACACACACACACACACACACACA");
+        assert!(nbns_decode_name(b"EGFCEFEECACACACACACACACACACACACA") == Some(fred));
+        assert!(nbns_split_name(&fred) == (b"FRED".to_vec(), b' '));
+        /* "*", padded with NUL bytes */
+        let star = nbns_decode_name(b"CKAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA").unwrap();
+        assert!(star == *b"*\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        assert!(nbns_split_name(&star) == (b"*".to_vec(), 0));
+        /* names are truncated to 15 characters */
+        assert!(nbns_pad_name(b"ABCDEFGHIJKLMNOPQ", 0x20) == *b"ABCDEFGHIJKLMNO\x20");
+        for name in [&b"SERVER"[..], b"A B", b"MASSCANNED"].iter() {
+            for suffix in [0x00, 0x03, 0x1b, 0x20].iter() {
+                let padded = nbns_pad_name(name, *suffix);
+                let decoded = nbns_decode_name(&nbns_encode_name(&padded)).unwrap();
+                assert!(nbns_split_name(&decoded) == (name.to_vec(), *suffix));
+            }
+        }
+        /* invalid */
+        assert!(nbns_decode_name(b"EGFCEFEECACACACACACACACACACACAC").is_none());
+        assert!(nbns_decode_name(b"EGFCEFEECACACACACACACACACACACACZ").is_none());
+        assert!(nbns_decode_name(b"egfcefeecacacacacacacacacacacaca").is_none());
+    }
+
+    #[test]
+    fn test_nbns_node_status() {
+        let masscanned = masscanned(
+            Config::from_str(
+                "[nbns]\nworkstation = \"fileserver\"\ndomain = \"CORP\"\nmac = \"02:00:00:aa:bb:cc\"\n",
+            )
+            .unwrap(),
+        );
+        let answer = repl(
+            NBTSCAN_QUERY,
+            &masscanned,
+            &mut client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 137),
+        )
+        .unwrap();
+        assert!(answer[..12] == *b"\x00\x07\x84\x00\x00\x00\x00\x01\x00\x00\x00\x00");
+        /* question name, NBSTAT, IN, TTL 0 */
+        assert!(answer[12..46] == NBTSCAN_QUERY[12..46]);
+        assert!(answer[46..54] == *b"\x00\x21\x00\x01\x00\x00\x00\x00");
+        let rdlength = be16(&answer, 54).unwrap() as usize;
+        assert!(answer.len() == 56 + rdlength);
+        let rdata = &answer[56..];
+        assert!(rdata[0] == 3);
+        let mut names = Vec::new();
+        for entry in rdata[1..1 + 3 * 18].chunks(18) {
+            names.push((
+                String::from_utf8_lossy(&entry[..15]).to_string(),
+                entry[15],
+                be16(entry, 16).unwrap(),
+            ));
+        }
+        assert!(
+            names
+                == [
+                    ("FILESERVER     ".to_string(), 0x00, 0x0400),
+                    ("FILESERVER     ".to_string(), 0x20, 0x0400),
+                    ("CORP           ".to_string(), 0x00, 0x8400),
+                ]
+        );
+        /* statistics */
+        assert!(rdata[55..61] == *b"\x02\x00\x00\xaa\xbb\xcc");
+        assert!(rdata.len() == 1 + 3 * 18 + 46);
+        /* no server service, our MAC address */
+        let masscanned = self::masscanned(Config::from_str("[nbns]\nserver = false\n").unwrap());
+        let answer = repl(
+            NBTSCAN_QUERY,
+            &masscanned,
+            &mut client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 137),
+        )
+        .unwrap();
+        assert!(answer[56] == 2);
+        assert!(answer[56 + 1 + 2 * 18..56 + 1 + 2 * 18 + 6] == *b"\x00\x11\x22\x33\x44\x55");
+    }
+
+    /* name query (as sent by nmblookup -U) */
+    fn name_query(name: &[u8], suffix: u8) -> Vec<u8> {
+        let mut q = b"\x13\x37\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x20".to_vec();
+        q.extend(nbns_encode_name(&nbns_pad_name(name, suffix)));
+        q.extend_from_slice(b"\x00\x00\x20\x00\x01");
+        q
+    }
+
+    #[test]
+    fn test_nbns_name_query() {
+        let mut masscanned = masscanned(Config::default());
+        let q = name_query(b"server", NBNS_SUFFIX_SERVER);
+        let answer = repl(
+            &q,
+            &masscanned,
+            &mut client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 137),
+        )
+        .unwrap();
+        let mut expected = b"\x13\x37\x85\x00\x00\x00\x00\x01\x00\x00\x00\x00\x20".to_vec();
+        expected.extend_from_slice(b"FDEFFCFGEFFCCACACACACACACACACACA");
+        expected.extend_from_slice(b"\x00\x00\x20\x00\x01\x00\x04\x93\xe0\x00\x06\x00\x00");
+        expected.extend_from_slice(&[198, 51, 100, 7]);
+        assert!(answer == expected);
+        let name = nbns_decode_name(&answer[13..45]).unwrap();
+        assert!(nbns_split_name(&name) == (b"SERVER".to_vec(), 0x20));
+        /* other names: configured, or wildcard */
+        let q = name_query(b"NAS", 0);
+        assert!(repl(
+            &q,
+            &masscanned,
+            &mut client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 137)
+        )
+        .is_none());
+        masscanned.config = Config::from_str("[nbns]\nnames = [\"nas\"]\n").unwrap();
+        assert!(repl(
+            &q,
+            &masscanned,
+            &mut client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 137)
+        )
+        .is_some());
+        let q = name_query(b"ANYTHING", 0);
+        assert!(repl(
+            &q,
+            &masscanned,
+            &mut client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 137)
+        )
+        .is_none());
+        masscanned.config = Config::from_str("[nbns]\nwildcard = true\n").unwrap();
+        let answer = repl(
+            &q,
+            &masscanned,
+            &mut client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 137),
+        )
+        .unwrap();
+        assert!(answer[answer.len() - 4..] == [198, 51, 100, 7]);
+        /* responses are not answered */
+        let mut q = q;
+        q[2] |= 0x80;
+        assert!(repl(
+            &q,
+            &masscanned,
+            &mut client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 137)
+        )
+        .is_none());
+        /* dispatched by port */
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 137);
+        assert!(
+            crate::proto::dispatch(NBTSCAN_QUERY, &masscanned, &mut client_info, None).is_some()
+        );
+        client_info.port.dst = Some(138);
+        assert!(
+            crate::proto::dispatch(NBTSCAN_QUERY, &masscanned, &mut client_info, None).is_none()
+        );
+    }
+
+    #[test]
+    fn test_nbns_config() {
+        for invalid in [
+            "[nbns]\nworkstation = \"\"\n",
+            "[nbns]\ndomain = \"ABCDEFGHIJKLMNOP\"\n",
+            "[nbns]\nnames = [\"\u{e9}t\u{e9}\"]\n",
+            "[nbns]\nmac = \"00:11:22\"\n",
+        ]
+        .iter()
+        {
+            assert!(Config::from_str(invalid).is_err());
+        }
+    }
+}
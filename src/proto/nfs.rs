@@ -0,0 +1,389 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use crate::logger::Event;
+use crate::proto::rpc::{
+    xdr_opaque, xdr_string, xdr_u32, XdrReader, AUTH_SYS, RPC_GARBAGE_ARGS, RPC_PROC_UNAVAIL,
+};
+
+/* MOUNT protocol (RFC 1094 appendix A, RFC 1813 appendix I) */
+pub const MOUNT_PROGRAM: u32 = 100005;
+const MOUNT_NULL: u32 = 0;
+const MOUNT_MNT: u32 = 1;
+const MOUNT_DUMP: u32 = 2;
+const MOUNT_UMNT: u32 = 3;
+const MOUNT_UMNTALL: u32 = 4;
+const MOUNT_EXPORT: u32 = 5;
+const MOUNT_PATH_LEN: usize = 1024;
+/* mount status */
+const MNT_OK: u32 = 0;
+const MNT_ERR_NOENT: u32 = 2;
+const MNT_ERR_ACCES: u32 = 13;
+
+/* NFS (RFC 1813) */
+pub const NFS_PROGRAM: u32 = 100003;
+const NFS_NULL: u32 = 0;
+const NFS3_FSINFO: u32 = 19;
+const NFS3_OK: u32 = 0;
+const NFS3_ERR_STALE: u32 = 70;
+/* FSF3_LINK | FSF3_SYMLINK | FSF3_HOMOGENEOUS | FSF3_CANSETTIME */
+const NFS3_FS_PROPERTIES: u32 = 0x1b;
+
+/* size of the file handles (the fixed size of version 1 and 2) */
+const NFS_FHSIZE: usize = 32;
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NfsExport {
+    pub path: String,
+    pub groups: Vec<String>,
+}
+
+/* NFS shares (served by the mountd and nfs programs on the ports the
+ * portmapper advertises for them): exports are listed, and mounts are
+ * refused unless `mount` is set (file handles are then handed out).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NfsConfig {
+    pub export: Vec<NfsExport>,
+    pub mount: bool,
+}
+
+impl Default for NfsConfig {
+    fn default() -> Self {
+        NfsConfig {
+            export: vec![
+                NfsExport {
+                    path: "/srv/nfs".to_string(),
+                    groups: vec!["*".to_string()],
+                },
+                NfsExport {
+                    path: "/home".to_string(),
+                    groups: vec!["192.168.0.0/24".to_string()],
+                },
+            ],
+            mount: false,
+        }
+    }
+}
+
+impl NfsConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for e in self.export.iter() {
+            if !e.path.starts_with('/') || e.path.len() > MOUNT_PATH_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid NFS export path: {}", e.path),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/* file handle of an exported directory: the same path always gets the
+ * same handle */
+fn nfs_handle(path: &str) -> Vec<u8> {
+    let mut handle = vec![0x01, 0x00, 0x01, 0x00];
+    handle.extend(Sha1::digest(path.as_bytes()));
+    handle.resize(NFS_FHSIZE, 0);
+    handle
+}
+
+/* results of a mountd procedure, or the accept status */
+pub fn mountd(
+    version: u32,
+    procedure: u32,
+    args: &mut XdrReader,
+    config: &NfsConfig,
+    event: &mut Event,
+) -> Result<Vec<u8>, u32> {
+    let mut results = Vec::new();
+    match procedure {
+        MOUNT_NULL | MOUNT_UMNTALL => {}
+        MOUNT_MNT | MOUNT_UMNT => {
+            let path = args.string().ok_or(RPC_GARBAGE_ARGS)?;
+            warn!("NFS mount of {}", path);
+            event.set("path", path.as_str());
+            if procedure == MOUNT_UMNT {
+                return Ok(results);
+            }
+            let status = if !config.export.iter().any(|e| e.path == path) {
+                MNT_ERR_NOENT
+            } else if !config.mount {
+                MNT_ERR_ACCES
+            } else {
+                MNT_OK
+            };
+            event.set("mount_status", status);
+            xdr_u32(&mut results, status);
+            if status == MNT_OK {
+                let handle = nfs_handle(&path);
+                if version < 3 {
+                    results.extend(handle);
+                } else {
+                    xdr_opaque(&mut results, &handle);
+                    /* authentication flavors */
+                    xdr_u32(&mut results, 1);
+                    xdr_u32(&mut results, AUTH_SYS);
+                }
+            }
+        }
+        /* no client has anything mounted */
+        MOUNT_DUMP => xdr_u32(&mut results, 0),
+        MOUNT_EXPORT => {
+            for e in config.export.iter() {
+                /* value follows: directory, then the list of groups */
+                xdr_u32(&mut results, 1);
+                xdr_string(&mut results, &e.path);
+                for g in e.groups.iter() {
+                    xdr_u32(&mut results, 1);
+                    xdr_string(&mut results, g);
+                }
+                xdr_u32(&mut results, 0);
+            }
+            xdr_u32(&mut results, 0);
+        }
+        _ => return Err(RPC_PROC_UNAVAIL),
+    }
+    Ok(results)
+}
+
+/* results of an nfs procedure, or the accept status: only NULL and
+ * (version 3) FSINFO are answered */
+pub fn nfs(
+    version: u32,
+    procedure: u32,
+    args: &mut XdrReader,
+    config: &NfsConfig,
+    event: &mut Event,
+) -> Result<Vec<u8>, u32> {
+    let mut results = Vec::new();
+    match procedure {
+        NFS_NULL => {}
+        NFS3_FSINFO if version == 3 => {
+            let handle = args.opaque().ok_or(RPC_GARBAGE_ARGS)?;
+            let export = config.export.iter().find(|e| nfs_handle(&e.path) == handle);
+            if let Some(e) = export {
+                event.set("path", e.path.as_str());
+            }
+            xdr_u32(
+                &mut results,
+                if export.is_some() {
+                    NFS3_OK
+                } else {
+                    NFS3_ERR_STALE
+                },
+            );
+            /* post-operation attributes: none */
+            xdr_u32(&mut results, 0);
+            if export.is_none() {
+                return Ok(results);
+            }
+            /* rtmax, rtpref, rtmult, wtmax, wtpref, wtmult, dtpref */
+            for v in [1048576, 1048576, 4096, 1048576, 1048576, 4096, 1048576].iter() {
+                xdr_u32(&mut results, *v);
+            }
+            /* maxfilesize, time_delta (1 ns), properties */
+            results.extend_from_slice(&0x7fffffffffffffffu64.to_be_bytes());
+            xdr_u32(&mut results, 0);
+            xdr_u32(&mut results, 1);
+            xdr_u32(&mut results, NFS3_FS_PROPERTIES);
+        }
+        _ => return Err(RPC_PROC_UNAVAIL),
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientInfo;
+    use crate::config::Config;
+    use crate::proto::rpc::rpc_record;
+    use crate::proto::tcb::TCPControlBlock;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn client(tcp: bool, port: u16) -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(if tcp {
+            IpNextHeaderProtocols::Tcp
+        } else {
+            IpNextHeaderProtocols::Udp
+        });
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.dst = Some(port);
+        client_info
+    }
+
+    /* call header: xid, call, RPC version 2, program, version, procedure,
+     * AUTH_SYS credentials (machine "kali", uid 0, gid 0, groups [0]),
+     * AUTH_NONE verifier */
+    fn call(program: u32, version: u32, procedure: u32, args: &[u8]) -> Vec<u8> {
+        let mut call = Vec::new();
+        for v in [0x6b8b4567, 0, 2, program, version, procedure].iter() {
+            xdr_u32(&mut call, *v);
+        }
+        call.extend_from_slice(
+            b"\x00\x00\x00\x01\x00\x00\x00\x1c\x5f\x3e\x1a\x02\x00\x00\x00\x04kali\
+              \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\
+              \x00\x00\x00\x00\x00\x00\x00\x00",
+        );
+        call.extend_from_slice(args);
+        call
+    }
+
+    /* accept status and results of a reply */
+    fn results(reply: &[u8]) -> (u32, XdrReader<'_>) {
+        assert!(reply[..8] == [0x6b, 0x8b, 0x45, 0x67, 0, 0, 0, 1]);
+        let mut reader = XdrReader { data: &reply[12..] };
+        reader.u32();
+        reader.opaque();
+        (reader.u32().unwrap(), reader)
+    }
+
+    #[test]
+    fn test_showmount() {
+        let masscanned = masscanned(Config::default());
+        /* showmount -e: GETPORT of mountd version 1 (UDP), then EXPORT
+         * (TCP, on the port given) */
+        let getport = b"\x6b\x8b\x45\x67\x00\x00\x00\x00\x00\x00\x00\x02\x00\x01\x86\xa0\
+                        \x00\x00\x00\x02\x00\x00\x00\x03\x00\x00\x00\x00\x00\x00\x00\x00\
+                        \x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x86\xa5\x00\x00\x00\x01\
+                        \x00\x00\x00\x06\x00\x00\x00\x00";
+        let repl_data =
+            crate::proto::dispatch(getport, &masscanned, &mut client(false, 111), None).unwrap();
+        let (status, mut reader) = results(&repl_data);
+        assert!(status == 0);
+        let port = reader.u32().unwrap() as u16;
+        assert!(port == 20048);
+        let mut client_info = client(true, port);
+        let mut tcb = TCPControlBlock::new();
+        let mut request = vec![0x80, 0, 0, 0x44];
+        request.extend(call(MOUNT_PROGRAM, 1, MOUNT_EXPORT, &[]));
+        let repl_data =
+            crate::proto::dispatch(&request, &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(repl_data[..4] == [0x80, 0, 0, repl_data.len() as u8 - 4]);
+        let (status, mut reader) = results(&repl_data[4..]);
+        assert!(status == 0);
+        for e in masscanned.config.nfs.export.iter() {
+            assert!(reader.u32() == Some(1));
+            assert!(reader.string().unwrap() == e.path);
+            for g in e.groups.iter() {
+                assert!(reader.u32() == Some(1));
+                assert!(reader.string().unwrap() == *g);
+            }
+            assert!(reader.u32() == Some(0));
+        }
+        assert!(reader.u32() == Some(0) && reader.data.is_empty());
+        /* programs are only served on the ports advertised for them */
+        let mut config = Config::default();
+        for m in config.rpc.mapping.iter_mut() {
+            if m.program == MOUNT_PROGRAM {
+                m.port = 635;
+            }
+        }
+        let masscanned = self::masscanned(config);
+        let request = call(MOUNT_PROGRAM, 1, MOUNT_EXPORT, &[]);
+        assert!(
+            crate::proto::dispatch(&request, &masscanned, &mut client(false, 20048), None)
+                .is_none()
+        );
+        let repl_data =
+            crate::proto::dispatch(&request, &masscanned, &mut client(false, 635), None).unwrap();
+        assert!(results(&repl_data).0 == 0);
+        /* ...and with the versions advertised: 1 to 3 */
+        let repl_data = crate::proto::dispatch(
+            &call(MOUNT_PROGRAM, 4, MOUNT_NULL, &[]),
+            &masscanned,
+            &mut client(false, 635),
+            None,
+        )
+        .unwrap();
+        let (status, mut reader) = results(&repl_data);
+        assert!(status == 2 && reader.u32() == Some(1) && reader.u32() == Some(3));
+    }
+
+    #[test]
+    fn test_mount_fsinfo() {
+        let mut config = Config::default();
+        let mut args = Vec::new();
+        xdr_string(&mut args, "/srv/nfs");
+        let mnt = call(MOUNT_PROGRAM, 3, MOUNT_MNT, &args);
+        /* refused, unknown */
+        let repl_data = crate::proto::dispatch(
+            &mnt,
+            &masscanned(Config::default()),
+            &mut client(false, 20048),
+            None,
+        )
+        .unwrap();
+        let (_, mut reader) = results(&repl_data);
+        assert!(reader.u32() == Some(MNT_ERR_ACCES) && reader.data.is_empty());
+        let mut args = Vec::new();
+        xdr_string(&mut args, "/etc");
+        let repl_data = crate::proto::dispatch(
+            &call(MOUNT_PROGRAM, 1, MOUNT_MNT, &args),
+            &masscanned(Config::default()),
+            &mut client(false, 20048),
+            None,
+        )
+        .unwrap();
+        assert!(results(&repl_data).1.u32() == Some(MNT_ERR_NOENT));
+        /* accepted: a file handle, that FSINFO knows */
+        config.nfs.mount = true;
+        let masscanned = masscanned(config);
+        let repl_data =
+            crate::proto::dispatch(&mnt, &masscanned, &mut client(false, 20048), None).unwrap();
+        let (_, mut reader) = results(&repl_data);
+        assert!(reader.u32() == Some(MNT_OK));
+        let handle = reader.opaque().unwrap().to_vec();
+        assert!(handle.len() == NFS_FHSIZE);
+        assert!(reader.u32() == Some(1) && reader.u32() == Some(AUTH_SYS));
+        let mut tcb = TCPControlBlock::new();
+        let mut client_info = client(true, 2049);
+        for (handle, status) in [(handle, NFS3_OK), (vec![0; NFS_FHSIZE], NFS3_ERR_STALE)].iter() {
+            let mut args = Vec::new();
+            xdr_opaque(&mut args, handle);
+            let request = rpc_record(&call(NFS_PROGRAM, 3, NFS3_FSINFO, &args));
+            let repl_data =
+                crate::proto::dispatch(&request, &masscanned, &mut client_info, Some(&mut tcb))
+                    .unwrap();
+            let (_, mut reader) = results(&repl_data[4..]);
+            assert!(reader.u32() == Some(*status) && reader.u32() == Some(0));
+            assert!(reader.data.len() == if *status == NFS3_OK { 48 } else { 0 });
+        }
+        /* NFS version 4: NULL only */
+        let repl_data = crate::proto::dispatch(
+            &rpc_record(&call(NFS_PROGRAM, 4, NFS_NULL, &[])),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(results(&repl_data[4..]).0 == 0);
+    }
+}
@@ -0,0 +1,439 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use serde::Deserialize;
+
+use crate::logger::Event;
+use crate::proto::smb::{filetime_now, le16, le32};
+
+pub const NTLMSSP_SIGNATURE: &[u8; 8] = b"NTLMSSP\x00";
+pub const NTLMSSP_NEGOTIATE: u32 = 1;
+pub const NTLMSSP_CHALLENGE: u32 = 2;
+pub const NTLMSSP_AUTHENTICATE: u32 = 3;
+
+const NTLMSSP_NEGOTIATE_UNICODE: u32 = 0x00000001;
+const NTLMSSP_NEGOTIATE_OEM: u32 = 0x00000002;
+const NTLMSSP_REQUEST_TARGET: u32 = 0x00000004;
+const NTLMSSP_NEGOTIATE_SIGN: u32 = 0x00000010;
+const NTLMSSP_NEGOTIATE_NTLM: u32 = 0x00000200;
+const NTLMSSP_NEGOTIATE_ALWAYS_SIGN: u32 = 0x00008000;
+const NTLMSSP_TARGET_TYPE_DOMAIN: u32 = 0x00010000;
+const NTLMSSP_NEGOTIATE_EXTENDED_SESSIONSECURITY: u32 = 0x00080000;
+const NTLMSSP_NEGOTIATE_TARGET_INFO: u32 = 0x00800000;
+const NTLMSSP_NEGOTIATE_VERSION: u32 = 0x02000000;
+const NTLMSSP_NEGOTIATE_128: u32 = 0x20000000;
+const NTLMSSP_NEGOTIATE_KEY_EXCH: u32 = 0x40000000;
+const NTLMSSP_NEGOTIATE_56: u32 = 0x80000000;
+
+/* MS-NLMP section 2.2.2.1: AV pairs of the target info */
+const MSV_AV_EOL: u16 = 0;
+const MSV_AV_NB_COMPUTER_NAME: u16 = 1;
+const MSV_AV_NB_DOMAIN_NAME: u16 = 2;
+const MSV_AV_DNS_COMPUTER_NAME: u16 = 3;
+const MSV_AV_DNS_DOMAIN_NAME: u16 = 4;
+const MSV_AV_TIMESTAMP: u16 = 7;
+
+/* Windows 10 (build 19041), NTLM revision 15 */
+const NTLMSSP_VERSION: [u8; 8] = [10, 0, 0x61, 0x4a, 0, 0, 0, 15];
+
+fn utf16le(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(|c| c.to_le_bytes()).collect()
+}
+
+fn av_pair(id: u16, value: &[u8]) -> Vec<u8> {
+    let mut pair = id.to_le_bytes().to_vec();
+    pair.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    pair.extend_from_slice(value);
+    pair
+}
+
+/* Target presented in CHALLENGE messages: server challenge (16 hex
+ * digits, random for each message when not set), NetBIOS and DNS names
+ * of the domain and of the computer (DNS names default to the NetBIOS
+ * ones, lowercase).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NtlmConfig {
+    pub challenge: Option<String>,
+    pub domain: String,
+    pub computer: String,
+    pub dns_domain: Option<String>,
+    pub dns_computer: Option<String>,
+    #[serde(skip)]
+    server_challenge: Option<[u8; 8]>,
+}
+
+impl Default for NtlmConfig {
+    fn default() -> Self {
+        NtlmConfig {
+            challenge: None,
+            domain: "WORKGROUP".to_string(),
+            computer: "SERVER".to_string(),
+            dns_domain: None,
+            dns_computer: None,
+            server_challenge: None,
+        }
+    }
+}
+
+impl NtlmConfig {
+    pub fn check(&mut self) -> Result<(), io::Error> {
+        if let Some(c) = &self.challenge {
+            let mut challenge = [0u8; 8];
+            if c.len() != 16 || !c.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("ntlm: invalid challenge (16 hex digits expected): {}", c),
+                ));
+            }
+            for (i, b) in challenge.iter_mut().enumerate() {
+                *b = u8::from_str_radix(&c[2 * i..2 * i + 2], 16).unwrap();
+            }
+            self.server_challenge = Some(challenge);
+        }
+        Ok(())
+    }
+
+    pub fn server_challenge(&self) -> [u8; 8] {
+        self.server_challenge.unwrap_or_else(rand::random)
+    }
+}
+
+/* length, maximum length and offset of a payload field */
+fn ntlm_field(len: usize, offset: usize) -> Vec<u8> {
+    let mut field = (len as u16).to_le_bytes().to_vec();
+    field.extend_from_slice(&(len as u16).to_le_bytes());
+    field.extend_from_slice(&(offset as u32).to_le_bytes());
+    field
+}
+
+/* CHALLENGE message (MS-NLMP section 2.2.1.2) */
+pub fn ntlm_challenge(server_challenge: &[u8; 8], config: &NtlmConfig) -> Vec<u8> {
+    let dns_domain = config
+        .dns_domain
+        .clone()
+        .unwrap_or_else(|| config.domain.to_lowercase());
+    let dns_computer = config
+        .dns_computer
+        .clone()
+        .unwrap_or_else(|| config.computer.to_lowercase());
+    let target_name = utf16le(&config.domain);
+    let mut target_info = Vec::new();
+    target_info.extend(av_pair(MSV_AV_NB_DOMAIN_NAME, &utf16le(&config.domain)));
+    target_info.extend(av_pair(MSV_AV_NB_COMPUTER_NAME, &utf16le(&config.computer)));
+    target_info.extend(av_pair(MSV_AV_DNS_DOMAIN_NAME, &utf16le(&dns_domain)));
+    target_info.extend(av_pair(MSV_AV_DNS_COMPUTER_NAME, &utf16le(&dns_computer)));
+    target_info.extend(av_pair(MSV_AV_TIMESTAMP, &filetime_now().to_le_bytes()));
+    target_info.extend(av_pair(MSV_AV_EOL, &[]));
+    let flags = NTLMSSP_NEGOTIATE_UNICODE
+        | NTLMSSP_REQUEST_TARGET
+        | NTLMSSP_NEGOTIATE_SIGN
+        | NTLMSSP_NEGOTIATE_NTLM
+        | NTLMSSP_NEGOTIATE_ALWAYS_SIGN
+        | NTLMSSP_TARGET_TYPE_DOMAIN
+        | NTLMSSP_NEGOTIATE_EXTENDED_SESSIONSECURITY
+        | NTLMSSP_NEGOTIATE_TARGET_INFO
+        | NTLMSSP_NEGOTIATE_VERSION
+        | NTLMSSP_NEGOTIATE_128
+        | NTLMSSP_NEGOTIATE_KEY_EXCH
+        | NTLMSSP_NEGOTIATE_56;
+    /* fixed part, then target name and target info */
+    let offset = 56;
+    let mut msg = NTLMSSP_SIGNATURE.to_vec();
+    msg.extend_from_slice(&NTLMSSP_CHALLENGE.to_le_bytes());
+    msg.extend(ntlm_field(target_name.len(), offset));
+    msg.extend_from_slice(&flags.to_le_bytes());
+    msg.extend_from_slice(server_challenge);
+    msg.extend_from_slice(&[0; 8]);
+    msg.extend(ntlm_field(target_info.len(), offset + target_name.len()));
+    msg.extend_from_slice(&NTLMSSP_VERSION);
+    msg.extend(target_name);
+    msg.extend(target_info);
+    msg
+}
+
+/* NEGOTIATE or AUTHENTICATE message (MS-NLMP sections 2.2.1.1 and
+ * 2.2.1.3): what a client tells about itself */
+pub struct NtlmMessage {
+    pub message_type: u32,
+    pub flags: u32,
+    pub domain: Option<String>,
+    pub user: Option<String>,
+    pub workstation: Option<String>,
+    /* major.minor.build */
+    pub os_version: Option<String>,
+    pub lm_response_len: Option<usize>,
+    pub nt_response_len: Option<usize>,
+}
+
+/* content of the payload field described at offset, with the offset of
+ * the content */
+fn ntlm_payload(msg: &[u8], offset: usize) -> Option<(&[u8], usize)> {
+    let len = le16(msg, offset)? as usize;
+    let start = le32(msg, offset + 4)? as usize;
+    if len == 0 {
+        return Some((&[], start));
+    }
+    Some((msg.get(start..start.checked_add(len)?)?, start))
+}
+
+/* Unicode (UTF-16LE) or OEM (decoded as Latin-1) string - None when
+ * empty */
+fn ntlm_string(data: &[u8], unicode: bool) -> Option<String> {
+    if data.is_empty() {
+        return None;
+    }
+    if unicode {
+        let chars: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        Some(String::from_utf16_lossy(&chars))
+    } else {
+        Some(data.iter().map(|&c| c as char).collect())
+    }
+}
+
+impl NtlmMessage {
+    /* message found in a security blob (e.g., within SPNEGO) */
+    pub fn parse(blob: &[u8]) -> Option<Self> {
+        let start = blob
+            .windows(NTLMSSP_SIGNATURE.len())
+            .position(|w| w == NTLMSSP_SIGNATURE)?;
+        let msg = &blob[start..];
+        let message_type = le32(msg, 8)?;
+        let (flags, fields, version_offset) = match message_type {
+            NTLMSSP_NEGOTIATE => (le32(msg, 12)?, vec![16, 24], 32),
+            NTLMSSP_AUTHENTICATE => (le32(msg, 60)?, vec![12, 20, 28, 36, 44], 64),
+            _ => {
+                return Some(NtlmMessage {
+                    message_type,
+                    flags: 0,
+                    domain: None,
+                    user: None,
+                    workstation: None,
+                    os_version: None,
+                    lm_response_len: None,
+                    nt_response_len: None,
+                })
+            }
+        };
+        let payload = fields
+            .iter()
+            .map(|o| ntlm_payload(msg, *o))
+            .collect::<Option<Vec<(&[u8], usize)>>>()?;
+        /* the version is there if announced, and if the payload does not
+         * start before it */
+        let payload_start = payload
+            .iter()
+            .filter(|(d, _)| !d.is_empty())
+            .map(|(_, o)| *o)
+            .min()
+            .unwrap_or(msg.len());
+        let os_version =
+            if flags & NTLMSSP_NEGOTIATE_VERSION != 0 && payload_start >= version_offset + 8 {
+                msg.get(version_offset..version_offset + 8)
+                    .map(|v| format!("{}.{}.{}", v[0], v[1], u16::from_le_bytes([v[2], v[3]])))
+            } else {
+                None
+            };
+        let mut message = NtlmMessage {
+            message_type,
+            flags,
+            domain: None,
+            user: None,
+            workstation: None,
+            os_version,
+            lm_response_len: None,
+            nt_response_len: None,
+        };
+        if message_type == NTLMSSP_NEGOTIATE {
+            /* always OEM */
+            message.domain = ntlm_string(payload[0].0, false);
+            message.workstation = ntlm_string(payload[1].0, false);
+        } else {
+            let unicode = flags & NTLMSSP_NEGOTIATE_UNICODE != 0;
+            message.lm_response_len = Some(payload[0].0.len());
+            message.nt_response_len = Some(payload[1].0.len());
+            message.domain = ntlm_string(payload[2].0, unicode);
+            message.user = ntlm_string(payload[3].0, unicode);
+            message.workstation = ntlm_string(payload[4].0, unicode);
+        }
+        Some(message)
+    }
+
+    pub fn set_event(&self, event: &mut Event) {
+        event.set(
+            "ntlm_message_type",
+            match self.message_type {
+                NTLMSSP_NEGOTIATE => "negotiate".to_string(),
+                NTLMSSP_CHALLENGE => "challenge".to_string(),
+                NTLMSSP_AUTHENTICATE => "authenticate".to_string(),
+                t => t.to_string(),
+            },
+        );
+        event.set("ntlm_flags", self.flags);
+        if self.flags & (NTLMSSP_NEGOTIATE_UNICODE | NTLMSSP_NEGOTIATE_OEM) != 0 {
+            event.set(
+                "ntlm_encoding",
+                if self.flags & NTLMSSP_NEGOTIATE_UNICODE != 0 {
+                    "unicode"
+                } else {
+                    "oem"
+                },
+            );
+        }
+        for (key, value) in [
+            ("ntlm_domain", &self.domain),
+            ("ntlm_user", &self.user),
+            ("ntlm_workstation", &self.workstation),
+            ("ntlm_os_version", &self.os_version),
+        ]
+        .iter()
+        {
+            if let Some(v) = value {
+                event.set(key, v.clone());
+            }
+        }
+        if let Some(len) = self.lm_response_len {
+            event.set("ntlm_lm_response_length", len);
+        }
+        if let Some(len) = self.nt_response_len {
+            event.set("ntlm_nt_response_length", len);
+            /* NTLMv2 responses are longer than the 24 bytes of NTLMv1 */
+            event.set("ntlm_version", if len > 24 { "NTLMv2" } else { "NTLMv1" });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ntlm_challenge() {
+        let config = NtlmConfig {
+            computer: "FILES".to_string(),
+            dns_domain: Some("corp.example.com".to_string()),
+            ..Default::default()
+        };
+        let msg = ntlm_challenge(b"\x01\x02\x03\x04\x05\x06\x07\x08", &config);
+        assert!(msg.starts_with(NTLMSSP_SIGNATURE));
+        assert!(msg[8..12] == NTLMSSP_CHALLENGE.to_le_bytes());
+        assert!(&msg[24..32] == b"\x01\x02\x03\x04\x05\x06\x07\x08");
+        /* target name */
+        let len = u16::from_le_bytes([msg[12], msg[13]]) as usize;
+        let offset = u32::from_le_bytes([msg[16], msg[17], msg[18], msg[19]]) as usize;
+        assert!(msg[offset..offset + len] == utf16le("WORKGROUP")[..]);
+        /* target info: AV pairs, up to MsvAvEOL */
+        let len = u16::from_le_bytes([msg[40], msg[41]]) as usize;
+        let offset = u32::from_le_bytes([msg[44], msg[45], msg[46], msg[47]]) as usize;
+        assert!(offset + len == msg.len());
+        let mut info = &msg[offset..];
+        let mut ids = Vec::new();
+        while !info.is_empty() {
+            let id = u16::from_le_bytes([info[0], info[1]]);
+            let l = u16::from_le_bytes([info[2], info[3]]) as usize;
+            if id == MSV_AV_NB_COMPUTER_NAME {
+                assert!(info[4..4 + l] == utf16le("FILES")[..]);
+            } else if id == MSV_AV_DNS_DOMAIN_NAME {
+                assert!(info[4..4 + l] == utf16le("corp.example.com")[..]);
+            } else if id == MSV_AV_DNS_COMPUTER_NAME {
+                assert!(info[4..4 + l] == utf16le("files")[..]);
+            }
+            ids.push(id);
+            info = &info[4 + l..];
+        }
+        assert!(ids == [2, 1, 4, 3, 7, 0]);
+    }
+
+    #[test]
+    fn test_ntlm_config() {
+        let mut config = NtlmConfig {
+            challenge: Some("0123456789abcdef".to_string()),
+            ..Default::default()
+        };
+        config.check().unwrap();
+        assert!(config.server_challenge() == *b"\x01\x23\x45\x67\x89\xab\xcd\xef");
+        for c in ["0123456789abcde", "0123456789abcdeg", "0123456789abcdef0"].iter() {
+            let mut config = NtlmConfig {
+                challenge: Some(c.to_string()),
+                ..Default::default()
+            };
+            assert!(config.check().is_err());
+        }
+        /* random challenge by default */
+        let config = NtlmConfig::default();
+        assert!(config.server_challenge() != config.server_challenge());
+    }
+
+    #[test]
+    fn test_ntlm_negotiate() {
+        /* curl --ntlm (7.88.1): no domain, no workstation, no version */
+        let msg = base64::decode("TlRMTVNTUAABAAAABoIIAAAAAAAAAAAAAAAAAAAAAAA=").unwrap();
+        let m = NtlmMessage::parse(&msg).unwrap();
+        assert!(m.message_type == NTLMSSP_NEGOTIATE);
+        assert!(m.flags == 0x00088206);
+        assert!(m.domain.is_none() && m.workstation.is_none() && m.os_version.is_none());
+        /* OEM domain and workstation, version 10.0.19041, within a
+         * (truncated) SPNEGO NegTokenInit */
+        let mut msg = b"\x60\x48\x06\x06\x2b\x06\x01\x05\x05\x02\xa0\x3e".to_vec();
+        msg.extend_from_slice(NTLMSSP_SIGNATURE);
+        msg.extend_from_slice(&NTLMSSP_NEGOTIATE.to_le_bytes());
+        msg.extend_from_slice(&0xa208b217u32.to_le_bytes());
+        msg.extend(ntlm_field(4, 40));
+        msg.extend(ntlm_field(7, 44));
+        msg.extend_from_slice(b"\x0a\x00\x61\x4a\x00\x00\x00\x0f");
+        msg.extend_from_slice(b"CORPDESKTOP");
+        let m = NtlmMessage::parse(&msg).unwrap();
+        assert!(m.domain.as_deref() == Some("CORP"));
+        assert!(m.workstation.as_deref() == Some("DESKTOP"));
+        assert!(m.os_version.as_deref() == Some("10.0.19041"));
+        /* truncated payload */
+        assert!(NtlmMessage::parse(&msg[..msg.len() - 1]).is_none());
+        assert!(NtlmMessage::parse(b"NTLMSSP\x00\x01\x00").is_none());
+    }
+
+    #[test]
+    fn test_ntlm_authenticate() {
+        /* curl --ntlm -u 'WIDGETS\alice' (7.88.1), with Unicode and OEM
+         * strings */
+        for (msg, flags) in [
+            ("TlRMTVNTUAADAAAAGAAYAEAAAABaAFoAWAAAAA4ADgCyAAAACgAKAMAAAAAWABYAygAAAAAAAAAAAAAAB4KIAn+0hPvfyU9lcSeX27MvboA76NeGbcjsqgzTpEBBaR8i8CJQ0qKY+mgBAQAAAAAAAIDZiRKdW90BO+jXhm3I7KoAAAAAAgASAFcATwBSAEsARwBSAE8AVQBQAAEADABTAEUAUgBWAEUAUgAAAAAAAAAAAFcASQBEAEcARQBUAFMAYQBsAGkAYwBlAFcATwBSAEsAUwBUAEEAVABJAE8ATgA=", 0x02888207),
+            ("TlRMTVNTUAADAAAAGAAYAEAAAABaAFoAWAAAAAcABwCyAAAABQAFALkAAAALAAsAvgAAAAAAAAAAAAAABoKIAsEnUJ56C88p7zFbronRIH1zavIaY3QCY+yoLP830F8uin9MiHOfjaEBAQAAAAAAAABwIhOdW90Bc2ryGmN0AmMAAAAAAgASAFcATwBSAEsARwBSAE8AVQBQAAEADABTAEUAUgBWAEUAUgAAAAAAAAAAAFdJREdFVFNhbGljZVdPUktTVEFUSU9O", 0x02888206),
+        ]
+        .iter()
+        {
+            let m = NtlmMessage::parse(&base64::decode(msg).unwrap()).unwrap();
+            assert!(m.message_type == NTLMSSP_AUTHENTICATE);
+            assert!(m.flags == *flags);
+            assert!(m.domain.as_deref() == Some("WIDGETS"));
+            assert!(m.user.as_deref() == Some("alice"));
+            assert!(m.workstation.as_deref() == Some("WORKSTATION"));
+            assert!(m.os_version.is_none());
+            assert!(m.lm_response_len == Some(24));
+            assert!(m.nt_response_len == Some(90));
+            let mut event = Event::new("test", &crate::client::ClientInfo::new());
+            m.set_event(&mut event);
+            assert!(event.fields["ntlm_message_type"] == "authenticate");
+            assert!(event.fields["ntlm_user"] == "alice");
+            assert!(event.fields["ntlm_version"] == "NTLMv2");
+        }
+    }
+}
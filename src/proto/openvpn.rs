@@ -0,0 +1,429 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::hash::Hasher;
+
+use log::*;
+use serde::Deserialize;
+use siphasher::sip::SipHasher24;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::synackcookie;
+use crate::Masscanned;
+
+/* opcodes (high 5 bits of the first byte, the key ID is in the low 3
+ * bits) */
+const OPENVPN_HARD_RESET_CLIENT_V1: u8 = 1;
+const OPENVPN_HARD_RESET_SERVER_V1: u8 = 2;
+const OPENVPN_CONTROL_V1: u8 = 4;
+const OPENVPN_ACK_V1: u8 = 5;
+const OPENVPN_HARD_RESET_CLIENT_V2: u8 = 7;
+const OPENVPN_HARD_RESET_SERVER_V2: u8 = 8;
+
+/* over TCP, a hard reset (v2, key ID 0) after its length */
+pub const OPENVPN_PATTERN_HARD_RESET_TCP: &[u8; 3] = b"\x00*\x38";
+
+/* sizes of the HMAC of tls-auth (MD5, SHA-1, SHA-224, SHA-256,
+ * SHA-384, SHA-512) */
+const OPENVPN_HMAC_SIZES: [usize; 6] = [16, 20, 28, 32, 48, 64];
+
+/* maximum size of a packet (TCP) */
+const OPENVPN_MAX_PACKET: usize = 2048;
+
+/* OpenVPN server (on the UDP and TCP ports, and on TCP after a hard
+ * reset): hard resets get a server hard reset, control packets (the
+ * TLS handshake) an acknowledgement. Packets with a tls-auth HMAC
+ * (which cannot be checked) are only recorded.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenvpnConfig {
+    pub ports: Vec<u16>,
+}
+
+impl Default for OpenvpnConfig {
+    fn default() -> Self {
+        OpenvpnConfig { ports: vec![1194] }
+    }
+}
+
+/* beginning of a packet (TCP) not received entirely yet */
+#[derive(Default)]
+pub struct OpenvpnState {
+    buffer: Vec<u8>,
+}
+
+/* packet without tls-auth */
+struct OpenvpnPacket<'a> {
+    key_id: u8,
+    session_id: &'a [u8],
+    acks: Vec<u32>,
+    remote_session_id: Option<&'a [u8]>,
+    /* not in P_ACK_V1 */
+    packet_id: Option<u32>,
+    payload: &'a [u8],
+}
+
+fn openvpn_parse(data: &[u8]) -> Option<OpenvpnPacket<'_>> {
+    if data.len() < 10 {
+        return None;
+    }
+    let opcode = data[0] >> 3;
+    let count = data[9] as usize;
+    if count > 8 {
+        return None;
+    }
+    let mut offset = 10 + 4 * count;
+    let acks = data
+        .get(10..offset)?
+        .chunks(4)
+        .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+        .collect();
+    let remote_session_id = if count > 0 {
+        offset += 8;
+        Some(data.get(offset - 8..offset)?)
+    } else {
+        None
+    };
+    let packet_id = if opcode == OPENVPN_ACK_V1 {
+        None
+    } else {
+        offset += 4;
+        let p = data.get(offset - 4..offset)?;
+        Some(u32::from_be_bytes([p[0], p[1], p[2], p[3]]))
+    };
+    Some(OpenvpnPacket {
+        key_id: data[0] & 0x07,
+        session_id: &data[1..9],
+        acks,
+        remote_session_id,
+        packet_id,
+        payload: &data[offset..],
+    })
+}
+
+/* early negotiation TLVs (OpenVPN 2.6) may follow a hard reset */
+fn openvpn_plain_reset(packet: &OpenvpnPacket) -> bool {
+    packet.acks.is_empty()
+        && packet.packet_id == Some(0)
+        && (packet.payload.is_empty() || packet.payload.starts_with(b"\x00\x01"))
+}
+
+/* size of the HMAC of a hard reset with tls-auth: session ID, HMAC,
+ * replay packet ID and timestamp, empty ack array, packet ID */
+fn openvpn_hmac_size(data: &[u8]) -> Option<usize> {
+    let size = data.len().checked_sub(1 + 8 + 8 + 1 + 4)?;
+    OPENVPN_HMAC_SIZES.iter().find(|s| **s == size).copied()
+}
+
+/* our session ID: a keyed hash of the client 5-tuple and of its session
+ * ID, so that later packets are acknowledged without state */
+fn openvpn_session_id(client_info: &ClientInfo, key: &[u64; 2], remote: &[u8]) -> [u8; 8] {
+    let tuple = synackcookie::generate(client_info, key).unwrap_or(0);
+    let mut sip = SipHasher24::new_with_keys(key[1], key[0]);
+    sip.write_u32(tuple);
+    sip.write(remote);
+    sip.finish().to_be_bytes()
+}
+
+fn openvpn_reply(
+    opcode: u8,
+    key_id: u8,
+    session_id: &[u8],
+    ack: u32,
+    remote_session_id: &[u8],
+    packet_id: Option<u32>,
+) -> Vec<u8> {
+    let mut p = vec![(opcode << 3) | key_id];
+    p.extend_from_slice(session_id);
+    p.push(1);
+    p.extend_from_slice(&ack.to_be_bytes());
+    p.extend_from_slice(remote_session_id);
+    if let Some(id) = packet_id {
+        p.extend_from_slice(&id.to_be_bytes());
+    }
+    p
+}
+
+fn openvpn_packet(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    if data.len() < 9 {
+        info!("OpenVPN packet not handled (too short)");
+        return None;
+    }
+    let opcode = data[0] >> 3;
+    let mut event = Event::new("openvpn_packet", client_info);
+    event.set("opcode", opcode);
+    event.set("key_id", data[0] & 0x07);
+    event.set("session_id", encode(&data[1..9], Encoding::Hex));
+    let packet = openvpn_parse(data);
+    let reset = opcode == OPENVPN_HARD_RESET_CLIENT_V1 || opcode == OPENVPN_HARD_RESET_CLIENT_V2;
+    let plain = match &packet {
+        Some(p) if reset => openvpn_plain_reset(p),
+        Some(_) => true,
+        None => false,
+    };
+    event.set("tls_auth", !plain);
+    if !plain {
+        if let Some(size) = openvpn_hmac_size(data) {
+            event.set("hmac_size", size);
+        }
+        event.log();
+        warn!(
+            "OpenVPN packet with tls-auth (opcode {}): not answered",
+            opcode
+        );
+        return None;
+    }
+    let packet = packet?;
+    event.set("packet_id", packet.packet_id);
+    if opcode == OPENVPN_CONTROL_V1 {
+        event.set("payload_length", packet.payload.len());
+        event.set("tls", packet.payload.starts_with(b"\x16\x03"));
+    }
+    event.log();
+    let session_id = openvpn_session_id(client_info, &masscanned.synack_key, packet.session_id);
+    match (opcode, packet.packet_id) {
+        (OPENVPN_HARD_RESET_CLIENT_V1 | OPENVPN_HARD_RESET_CLIENT_V2, Some(id)) => {
+            warn!("OpenVPN hard reset (opcode {})", opcode);
+            let server = if opcode == OPENVPN_HARD_RESET_CLIENT_V1 {
+                OPENVPN_HARD_RESET_SERVER_V1
+            } else {
+                OPENVPN_HARD_RESET_SERVER_V2
+            };
+            Some(openvpn_reply(
+                server,
+                packet.key_id,
+                &session_id,
+                id,
+                packet.session_id,
+                Some(0),
+            ))
+        }
+        /* TLS data of a session of ours */
+        (OPENVPN_CONTROL_V1, Some(id))
+            if packet.remote_session_id.is_none_or(|r| r == session_id) =>
+        {
+            Some(openvpn_reply(
+                OPENVPN_ACK_V1,
+                packet.key_id,
+                &session_id,
+                id,
+                packet.session_id,
+                None,
+            ))
+        }
+        _ => None,
+    }
+}
+
+/* over TCP, packets follow their length */
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving OpenVPN data");
+    let mut state = match tcb.as_mut() {
+        None => {
+            let repl = openvpn_packet(data, masscanned, client_info)?;
+            debug!("sending OpenVPN data");
+            return Some(repl);
+        }
+        Some(t) => match std::mem::replace(&mut t.proto_state, ProtoState::None) {
+            ProtoState::Openvpn(s) => s,
+            _ => OpenvpnState::default(),
+        },
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    let mut offset = 0;
+    while state.buffer.len() >= offset + 2 {
+        let len = u16::from_be_bytes([state.buffer[offset], state.buffer[offset + 1]]) as usize;
+        if len > OPENVPN_MAX_PACKET {
+            info!("OpenVPN packet too large ({} bytes) - dropped", len);
+            client_info.close = true;
+            break;
+        }
+        let packet = match state.buffer.get(offset + 2..offset + 2 + len) {
+            Some(p) => p,
+            None => break,
+        };
+        if let Some(r) = openvpn_packet(packet, masscanned, client_info) {
+            repl_data.extend_from_slice(&(r.len() as u16).to_be_bytes());
+            repl_data.extend(r);
+        }
+        offset += 2 + len;
+    }
+    state.buffer.drain(..offset);
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Openvpn(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending OpenVPN data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::TCPControlBlock;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    const SESSION_ID: &[u8] = b"\x2a\x7c\x11\x93\xe0\x05\x6b\xd4";
+
+    fn instance() -> Masscanned<'static> {
+        Masscanned {
+            synack_key: [0x1e9219e0b0e0b44c, 0x9e460bcddf4eaac9],
+            ..crate::proto::test_util::masscanned(Config::default())
+        }
+    }
+
+    fn client(tcp: bool, port: u16) -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(if tcp {
+            IpNextHeaderProtocols::Tcp
+        } else {
+            IpNextHeaderProtocols::Udp
+        });
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.src = Some(41194);
+        client_info.port.dst = Some(port);
+        client_info
+    }
+
+    fn hard_reset(opcode: u8, key_id: u8) -> Vec<u8> {
+        let mut p = vec![(opcode << 3) | key_id];
+        p.extend_from_slice(SESSION_ID);
+        p.extend_from_slice(b"\x00\x00\x00\x00\x00");
+        p
+    }
+
+    fn check_reset_reply(reply: &[u8], opcode: u8, key_id: u8) -> Vec<u8> {
+        assert!(reply.len() == 26);
+        assert!(reply[0] == (opcode << 3) | key_id);
+        assert!(reply[1..9] != *SESSION_ID);
+        /* the client packet ID 0 is acknowledged */
+        assert!(reply[9..14] == b"\x01\x00\x00\x00\x00"[..]);
+        assert!(reply[14..22] == *SESSION_ID);
+        assert!(reply[22..] == [0, 0, 0, 0]);
+        reply[1..9].to_vec()
+    }
+
+    #[test]
+    fn test_openvpn_hard_reset() {
+        let masscanned = instance();
+        let mut client_info = client(false, 1194);
+        /* v1 */
+        let reply = super::repl(
+            &hard_reset(OPENVPN_HARD_RESET_CLIENT_V1, 0),
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .expect("no reply to hard reset v1");
+        check_reset_reply(&reply, OPENVPN_HARD_RESET_SERVER_V1, 0);
+        /* v2, with early negotiation (OpenVPN 2.6) and key ID 1 */
+        let mut data = hard_reset(OPENVPN_HARD_RESET_CLIENT_V2, 1);
+        data.extend_from_slice(b"\x00\x01\x00\x02\x00\x01");
+        let reply = super::repl(&data, &masscanned, &mut client_info, None)
+            .expect("no reply to hard reset v2");
+        let session_id = check_reset_reply(&reply, OPENVPN_HARD_RESET_SERVER_V2, 1);
+        /* the session ID is stable, and depends on the client */
+        let again = super::repl(&data, &masscanned, &mut client_info, None).unwrap();
+        assert!(again[1..9] == session_id[..]);
+        let mut other = client(false, 1194);
+        other.port.src = Some(41195);
+        let reply = super::repl(&data, &masscanned, &mut other, None).unwrap();
+        assert!(reply[1..9] != session_id[..]);
+        /* TLS ClientHello in the control channel: acknowledged */
+        let mut control = vec![OPENVPN_CONTROL_V1 << 3 | 1];
+        control.extend_from_slice(SESSION_ID);
+        control.push(1);
+        control.extend_from_slice(&[0, 0, 0, 0]);
+        control.extend_from_slice(&session_id);
+        control.extend_from_slice(&[0, 0, 0, 1]);
+        control.extend_from_slice(b"\x16\x03\x01\x00\x05hello");
+        let ack = super::repl(&control, &masscanned, &mut client_info, None).expect("no ack");
+        assert!(ack[0] == OPENVPN_ACK_V1 << 3 | 1);
+        assert!(ack[1..9] == session_id[..]);
+        assert!(ack[9..] == [&[1, 0, 0, 0, 1][..], SESSION_ID].concat()[..]);
+        /* not one of our sessions */
+        control[14] ^= 0xff;
+        assert!(super::repl(&control, &masscanned, &mut client_info, None).is_none());
+        /* garbage */
+        assert!(super::repl(b"\x38\x00", &masscanned, &mut client_info, None).is_none());
+    }
+
+    #[test]
+    fn test_openvpn_tls_auth() {
+        let masscanned = instance();
+        let mut client_info = client(false, 1194);
+        /* hard reset v2 with a SHA-1 HMAC, replay packet ID and time */
+        let mut data = vec![OPENVPN_HARD_RESET_CLIENT_V2 << 3];
+        data.extend_from_slice(SESSION_ID);
+        data.extend_from_slice(&[0x5a; 20]);
+        data.extend_from_slice(b"\x00\x00\x00\x01\x65\x4f\x1c\x20");
+        data.extend_from_slice(b"\x00\x00\x00\x00\x00");
+        assert!(openvpn_hmac_size(&data) == Some(20));
+        /* the HMAC is read as an ack array */
+        assert!(openvpn_parse(&data).is_none());
+        assert!(super::repl(&data, &masscanned, &mut client_info, None).is_none());
+        /* SHA-256 */
+        data.splice(9..9, [0x5a; 12]);
+        assert!(openvpn_hmac_size(&data) == Some(32));
+        assert!(super::repl(&data, &masscanned, &mut client_info, None).is_none());
+        assert!(openvpn_hmac_size(&hard_reset(OPENVPN_HARD_RESET_CLIENT_V2, 0)).is_none());
+    }
+
+    #[test]
+    fn test_openvpn_tcp() {
+        let masscanned = instance();
+        /* on 443, identified by the hard reset */
+        let mut client_info = client(true, 443);
+        let mut tcb = TCPControlBlock::new();
+        let mut data = vec![0, 14];
+        data.extend(hard_reset(OPENVPN_HARD_RESET_CLIENT_V2, 0));
+        /* split across segments */
+        assert!(
+            crate::proto::dispatch(&data[..5], &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        let reply =
+            crate::proto::dispatch(&data[5..], &masscanned, &mut client_info, Some(&mut tcb))
+                .expect("no reply to hard reset over TCP");
+        assert!(reply[..2] == [0, 26]);
+        check_reset_reply(&reply[2..], OPENVPN_HARD_RESET_SERVER_V2, 0);
+        assert!(!client_info.close);
+        /* absurd length */
+        assert!(
+            crate::proto::dispatch(b"\xff\xff", &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        assert!(client_info.close);
+    }
+}
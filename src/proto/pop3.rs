@@ -0,0 +1,363 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::line::{line_command, LineBuffer};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* POP3 service (server-first): the banner is sent (after "+OK ") once
+ * the TCP handshake is completed on one of the ports, followed by an
+ * APOP challenge when apop is set. Logins fail unless login is set, and
+ * the maildrop is always empty.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pop3Config {
+    pub ports: Vec<u16>,
+    pub banner: String,
+    pub hostname: String,
+    pub apop: bool,
+    pub login: bool,
+}
+
+impl Default for Pop3Config {
+    fn default() -> Self {
+        Pop3Config {
+            ports: vec![110],
+            banner: "Dovecot ready.".to_string(),
+            hostname: "mail.example.com".to_string(),
+            apop: false,
+            login: false,
+        }
+    }
+}
+
+impl Pop3Config {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for s in [&self.banner, &self.hostname].iter() {
+            if s.contains(['\r', '\n']) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("pop3: invalid line (CR or LF found): {:?}", s),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/* state of a POP3 flow: partial command line, user name given by USER,
+ * APOP challenge sent in the banner */
+#[derive(Default)]
+pub struct Pop3State {
+    lines: LineBuffer,
+    user: Option<String>,
+    challenge: Option<String>,
+    stls: bool,
+}
+
+fn pop3_ok(text: &str) -> Vec<u8> {
+    format!("+OK {}\r\n", text).into_bytes()
+}
+
+fn pop3_err(text: &str) -> Vec<u8> {
+    format!("-ERR {}\r\n", text).into_bytes()
+}
+
+fn pop3_login_event(
+    mechanism: &str,
+    user: &str,
+    config: &Pop3Config,
+    client_info: &ClientInfo,
+) -> Event {
+    let mut event = Event::new("pop3_login", client_info);
+    event.set("mechanism", mechanism);
+    event.set("user", user);
+    event.set("success", config.login);
+    event
+}
+
+fn pop3_login_result(config: &Pop3Config) -> Vec<u8> {
+    if config.login {
+        pop3_ok("Logged in.")
+    } else {
+        pop3_err("[AUTH] Authentication failed.")
+    }
+}
+
+/* answer to a command line, and the event to log (if any) */
+fn pop3_command(
+    line: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    state: &mut Pop3State,
+) -> (Vec<u8>, Option<Event>) {
+    let config = &masscanned.config.pop3;
+    let (command, argument) = line_command(line);
+    let mut event = Event::new("pop3_command", client_info);
+    event.set("command", command.clone());
+    event.set("argument", argument.clone());
+    let repl = match command.as_str() {
+        "USER" => {
+            state.user = Some(argument);
+            return (pop3_ok(""), None);
+        }
+        "PASS" => {
+            let user = match state.user.take() {
+                Some(u) => u,
+                None => return (pop3_err("No username given."), None),
+            };
+            warn!("POP3 login: {}:{}", user, argument);
+            let mut event = pop3_login_event("USER", &user, config, client_info);
+            event.set("password", argument);
+            return (pop3_login_result(config), Some(event));
+        }
+        "APOP" => {
+            /* RFC 1939 section 7: name and MD5 digest of challenge +
+             * password */
+            let mut args = argument.splitn(2, ' ');
+            let user = args.next().unwrap_or("");
+            let digest = match (args.next(), &state.challenge) {
+                (Some(d), Some(_)) => d,
+                (_, None) => return (pop3_err("APOP not supported."), Some(event)),
+                (None, _) => return (pop3_err("Invalid APOP parameters."), Some(event)),
+            };
+            warn!("POP3 APOP login: {} ({})", user, digest);
+            let mut event = pop3_login_event("APOP", user, config, client_info);
+            event.set("digest", digest);
+            event.set("challenge", state.challenge.clone());
+            return (pop3_login_result(config), Some(event));
+        }
+        "CAPA" => {
+            let mut repl = pop3_ok("");
+            for c in [
+                "CAPA",
+                "TOP",
+                "UIDL",
+                "RESP-CODES",
+                "PIPELINING",
+                "USER",
+                "STLS",
+            ]
+            .iter()
+            {
+                repl.extend(format!("{}\r\n", c).into_bytes());
+            }
+            repl.extend(b".\r\n");
+            repl
+        }
+        "STLS" => {
+            state.stls = true;
+            pop3_ok("Begin TLS negotiation now.")
+        }
+        "STAT" => pop3_ok("0 0"),
+        "LIST" | "UIDL" => {
+            if argument.is_empty() {
+                let mut repl = pop3_ok("0 messages:");
+                repl.extend(b".\r\n");
+                repl
+            } else {
+                pop3_err("There's no message.")
+            }
+        }
+        "NOOP" | "RSET" => pop3_ok(""),
+        "QUIT" => {
+            client_info.close = true;
+            pop3_ok("Logging out.")
+        }
+        "" => return (Vec::new(), None),
+        _ => pop3_err("Unknown command."),
+    };
+    (repl, Some(event))
+}
+
+/* banner, sent when the TCP handshake is completed */
+pub fn greeting(masscanned: &Masscanned, tcb: &mut TCPControlBlock) -> Option<Vec<u8>> {
+    let config = &masscanned.config.pop3;
+    let mut state = Pop3State::default();
+    let banner = if config.apop {
+        let challenge = format!(
+            "<{}.{}@{}>",
+            rand::random::<u16>(),
+            chrono::Utc::now().timestamp(),
+            config.hostname
+        );
+        let banner = format!("{} {}", config.banner, challenge);
+        state.challenge = Some(challenge);
+        banner
+    } else {
+        config.banner.clone()
+    };
+    tcb.proto_state = ProtoState::Pop3(state);
+    Some(pop3_ok(&banner))
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving POP3 data");
+    /* POP3 is only answered over TCP, after the banner */
+    let t = tcb?;
+    let state = match &mut t.proto_state {
+        ProtoState::Pop3(s) => s,
+        _ => return None,
+    };
+    let mut repl_data = Vec::new();
+    for line in state.lines.push(data) {
+        let (repl, event) = pop3_command(&line, masscanned, client_info, state);
+        if let Some(e) = event {
+            e.log();
+        }
+        repl_data.extend(repl);
+        if client_info.close || state.stls {
+            break;
+        }
+    }
+    if state.stls {
+        /* what comes next is a TLS handshake */
+        t.start_tls();
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending POP3 data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use md5::{Digest, Md5};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_pop3_login() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut state = Pop3State::default();
+        let (repl, _) = pop3_command(b"PASS secret", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"-ERR "));
+        let (repl, event) = pop3_command(b"USER bob", &masscanned, &mut client_info, &mut state);
+        assert!(repl == b"+OK \r\n" && event.is_none());
+        let (repl, event) =
+            pop3_command(b"pass hunter2", &masscanned, &mut client_info, &mut state);
+        assert!(repl == b"-ERR [AUTH] Authentication failed.\r\n");
+        let event = event.unwrap();
+        assert!(event.name == "pop3_login");
+        assert!(event.fields["mechanism"] == "USER");
+        assert!(event.fields["user"] == "bob");
+        assert!(event.fields["password"] == "hunter2");
+        assert!(event.fields["success"] == false);
+        let (repl, _) = pop3_command(b"STAT", &masscanned, &mut client_info, &mut state);
+        assert!(repl == b"+OK 0 0\r\n");
+        let (repl, _) = pop3_command(b"LIST", &masscanned, &mut client_info, &mut state);
+        assert!(repl == b"+OK 0 messages:\r\n.\r\n");
+        let (repl, _) = pop3_command(b"LIST 1", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"-ERR "));
+        let (repl, _) = pop3_command(b"CAPA", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"+OK \r\nCAPA\r\n") && repl.ends_with(b"STLS\r\n.\r\n"));
+        let (repl, _) = pop3_command(b"RETR 1", &masscanned, &mut client_info, &mut state);
+        assert!(repl == b"-ERR Unknown command.\r\n");
+        /* APOP without challenge */
+        let (repl, _) = pop3_command(
+            b"APOP bob c4c9334bac560ecc979e58001b3e22fb",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl.starts_with(b"-ERR "));
+        let (repl, _) = pop3_command(b"QUIT", &masscanned, &mut client_info, &mut state);
+        assert!(repl == b"+OK Logging out.\r\n" && client_info.close);
+    }
+
+    #[test]
+    fn test_pop3_apop() {
+        let masscanned = masscanned(Config::from_str("[pop3]\napop = true\n").unwrap());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        let banner = greeting(&masscanned, &mut tcb).unwrap();
+        assert!(
+            banner.starts_with(b"+OK Dovecot ready. <")
+                && banner.ends_with(b"@mail.example.com>\r\n")
+        );
+        let challenge = &banner[b"+OK Dovecot ready. ".len()..banner.len() - 2];
+        /* RFC 1939 section 7, as computed by a client */
+        let mut hasher = Md5::new();
+        hasher.update(challenge);
+        hasher.update(b"tanstaaf");
+        let digest: String = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let state = match &mut tcb.proto_state {
+            ProtoState::Pop3(s) => s,
+            _ => panic!("expected a POP3 state"),
+        };
+        let (repl, event) = pop3_command(
+            format!("APOP mrose {}", digest).as_bytes(),
+            &masscanned,
+            &mut client_info,
+            state,
+        );
+        assert!(repl.starts_with(b"-ERR [AUTH] "));
+        let event = event.unwrap();
+        assert!(event.fields["mechanism"] == "APOP");
+        assert!(event.fields["user"] == "mrose");
+        assert!(event.fields["digest"] == digest);
+        assert!(event.fields["challenge"] == String::from_utf8_lossy(challenge).to_string());
+    }
+
+    #[test]
+    fn test_pop3_repl() {
+        let masscanned = masscanned(Config::from_str("[pop3]\nlogin = true\n").unwrap());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        assert!(repl(b"CAPA\r\n", &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        assert!(greeting(&masscanned, &mut tcb).unwrap() == b"+OK Dovecot ready.\r\n");
+        /* command split across two segments */
+        assert!(repl(b"USER al", &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        let repl_data = repl(
+            b"ice\r\nPASS x\r\nSTAT\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"+OK \r\n+OK Logged in.\r\n+OK 0 0\r\n");
+        /* STLS: the rest of the flow is a TLS handshake */
+        let repl_data = repl(b"STLS\r\n", &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl_data == b"+OK Begin TLS negotiation now.\r\n");
+        assert!(matches!(tcb.proto_state, ProtoState::Tls(_)));
+    }
+
+    #[test]
+    fn test_pop3_config() {
+        assert!(Config::from_str("[pop3]\nbanner = \"ready\\r\\n+OK\"\n").is_err());
+    }
+}
@@ -0,0 +1,493 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* length (8, but wildcards have to be at the same place as in the
+ * startup pattern), then the request code */
+pub const POSTGRES_PATTERN_SSL_REQUEST: &[u8; 8] = b"\x00\x00**\x04\xd2\x16\x2f";
+pub const POSTGRES_PATTERN_GSSENC_REQUEST: &[u8; 8] = b"\x00\x00**\x04\xd2\x16\x30";
+/* length, then protocol version 3.0 */
+pub const POSTGRES_PATTERN_STARTUP: &[u8; 8] = b"\x00\x00**\x00\x03\x00\x00";
+
+const POSTGRES_SSL_REQUEST: u32 = 80877103;
+const POSTGRES_GSSENC_REQUEST: u32 = 80877104;
+const POSTGRES_CANCEL_REQUEST: u32 = 80877102;
+
+const POSTGRES_AUTH_CLEARTEXT: u32 = 3;
+const POSTGRES_AUTH_MD5: u32 = 5;
+
+/* maximum size of the messages received */
+const POSTGRES_MAX_MESSAGE: usize = 10000;
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PostgresAuth {
+    Cleartext,
+    #[default]
+    Md5,
+}
+
+/* PostgreSQL server: startup messages get a password request (auth),
+ * and the password a FATAL error (error_message can use {user}). The
+ * SSLRequest is accepted when ssl is set and TLS termination is
+ * enabled (what comes next is handled by the TLS layer).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PostgresConfig {
+    pub auth: PostgresAuth,
+    pub ssl: bool,
+    pub error_message: String,
+}
+
+impl Default for PostgresConfig {
+    fn default() -> Self {
+        PostgresConfig {
+            auth: PostgresAuth::Md5,
+            ssl: true,
+            error_message: "password authentication failed for user \"{user}\"".to_string(),
+        }
+    }
+}
+
+/* state of a PostgreSQL flow: data received, startup parameters (when
+ * the password is expected), salt of the MD5 request */
+#[derive(Default)]
+pub struct PostgresState {
+    buffer: Vec<u8>,
+    parameters: Option<Vec<(String, String)>>,
+    salt: [u8; 4],
+}
+
+impl PostgresState {
+    fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .as_ref()?
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/* message received: type, body and number of bytes used */
+type PostgresMessage = (u8, Vec<u8>, usize);
+
+/* next message - startup messages have no type (0) */
+fn postgres_message(buffer: &[u8], typed: bool) -> Option<Result<PostgresMessage, ()>> {
+    let offset = if typed { 1 } else { 0 };
+    let header = buffer.get(..offset + 4)?;
+    let len = u32::from_be_bytes([
+        header[offset],
+        header[offset + 1],
+        header[offset + 2],
+        header[offset + 3],
+    ]) as usize;
+    if !(4..=POSTGRES_MAX_MESSAGE).contains(&len) {
+        return Some(Err(()));
+    }
+    let body = buffer.get(offset + 4..offset + len)?;
+    let kind = if typed { buffer[0] } else { 0 };
+    Some(Ok((kind, body.to_vec(), offset + len)))
+}
+
+/* NUL-terminated strings */
+fn postgres_strings(body: &[u8]) -> Vec<String> {
+    body.split(|c| *c == 0)
+        .map(|s| String::from_utf8_lossy(s).to_string())
+        .collect()
+}
+
+fn postgres_reply(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut reply = vec![kind];
+    reply.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+    reply.extend_from_slice(body);
+    reply
+}
+
+/* ErrorResponse (FATAL) */
+fn postgres_error(code: &str, message: &str, routine: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (field, value) in [
+        (b'S', "FATAL"),
+        (b'V', "FATAL"),
+        (b'C', code),
+        (b'M', message),
+        (b'F', "auth.c"),
+        (b'R', routine),
+    ]
+    .iter()
+    {
+        body.push(*field);
+        body.extend_from_slice(value.as_bytes());
+        body.push(0);
+    }
+    body.push(0);
+    postgres_reply(b'E', &body)
+}
+
+fn postgres_startup_event(parameters: &[(String, String)], client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("postgres_startup", client_info);
+    let mut others = Map::new();
+    for (k, v) in parameters.iter() {
+        match k.as_str() {
+            "user" | "database" | "application_name" => event.set(k, v.clone()),
+            _ => {
+                others.insert(k.clone(), Value::String(v.clone()));
+            }
+        }
+    }
+    if !others.is_empty() {
+        event.set("parameters", Value::Object(others));
+    }
+    event
+}
+
+fn postgres_login_event(
+    password: &str,
+    state: &PostgresState,
+    config: &PostgresConfig,
+    client_info: &ClientInfo,
+) -> Event {
+    let mut event = Event::new("postgres_login", client_info);
+    for name in ["user", "database"].iter() {
+        if let Some(v) = state.parameter(name) {
+            event.set(name, v.to_string());
+        }
+    }
+    match config.auth {
+        PostgresAuth::Cleartext => {
+            event.set("method", "cleartext");
+            event.set("password", password.to_string());
+        }
+        PostgresAuth::Md5 => {
+            /* "md5" + md5(md5(password + user) + salt) */
+            event.set("method", "md5");
+            event.set("hash", password.to_string());
+            event.set("salt", encode(&state.salt, Encoding::Hex));
+        }
+    }
+    event
+}
+
+/* answer to a startup message (type 0), and whether the flow is done */
+fn postgres_startup(
+    body: &[u8],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+    state: &mut PostgresState,
+) -> (Vec<u8>, bool) {
+    let config = &masscanned.config.postgres;
+    let code = match body.get(..4) {
+        Some(c) => u32::from_be_bytes([c[0], c[1], c[2], c[3]]),
+        None => return (Vec::new(), true),
+    };
+    match code {
+        POSTGRES_SSL_REQUEST if config.ssl && masscanned.config.tls.enabled => {
+            (b"S".to_vec(), false)
+        }
+        POSTGRES_SSL_REQUEST | POSTGRES_GSSENC_REQUEST => (b"N".to_vec(), false),
+        POSTGRES_CANCEL_REQUEST => (Vec::new(), true),
+        _ if code >> 16 != 3 => {
+            info!(
+                "PostgreSQL protocol not handled: {}.{}",
+                code >> 16,
+                code & 0xffff
+            );
+            let message = format!(
+                "unsupported frontend protocol {}.{}: server supports 3.0 to 3.0",
+                code >> 16,
+                code & 0xffff
+            );
+            (
+                postgres_error("0A000", &message, "ProcessStartupPacket"),
+                true,
+            )
+        }
+        _ => {
+            let strings = postgres_strings(&body[4..]);
+            let parameters: Vec<(String, String)> = strings
+                .chunks(2)
+                .filter(|kv| kv.len() == 2 && !kv[0].is_empty())
+                .map(|kv| (kv[0].clone(), kv[1].clone()))
+                .collect();
+            postgres_startup_event(&parameters, client_info).log();
+            state.parameters = Some(parameters);
+            if state.parameter("user").is_none() {
+                return (
+                    postgres_error(
+                        "28000",
+                        "no PostgreSQL user name specified in startup packet",
+                        "ProcessStartupPacket",
+                    ),
+                    true,
+                );
+            }
+            let mut request = match config.auth {
+                PostgresAuth::Cleartext => POSTGRES_AUTH_CLEARTEXT,
+                PostgresAuth::Md5 => POSTGRES_AUTH_MD5,
+            }
+            .to_be_bytes()
+            .to_vec();
+            if config.auth == PostgresAuth::Md5 {
+                state.salt = rand::random();
+                request.extend_from_slice(&state.salt);
+            }
+            (postgres_reply(b'R', &request), false)
+        }
+    }
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving PostgreSQL data");
+    let config = &masscanned.config.postgres;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Postgres(s)) => s,
+        _ => PostgresState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    let mut tls = false;
+    while !client_info.close {
+        let typed = state.parameters.is_some();
+        let (kind, body, len) = match postgres_message(&state.buffer, typed) {
+            None => break,
+            Some(Ok(m)) => m,
+            Some(Err(())) => {
+                info!("invalid PostgreSQL message length - dropped");
+                client_info.close = true;
+                break;
+            }
+        };
+        state.buffer.drain(..len);
+        if !typed {
+            let (repl, done) = postgres_startup(&body, masscanned, client_info, &mut state);
+            tls = repl == b"S";
+            repl_data.extend(repl);
+            client_info.close = done;
+            if tls {
+                break;
+            }
+            continue;
+        }
+        if kind != b'p' {
+            info!("PostgreSQL message not handled: {:?}", kind as char);
+            client_info.close = true;
+            break;
+        }
+        let password = postgres_strings(&body).swap_remove(0);
+        let user = state.parameter("user").unwrap_or("").to_string();
+        warn!("PostgreSQL login: {}:{}", user, password);
+        postgres_login_event(&password, &state, config, client_info).log();
+        let message = config.error_message.replace("{user}", &user);
+        repl_data.extend(postgres_error("28P01", &message, "auth_failed"));
+        client_info.close = true;
+    }
+    if let Some(t) = tcb {
+        if tls {
+            /* what comes next is a TLS handshake */
+            t.reset_protocol();
+        } else {
+            t.proto_state = ProtoState::Postgres(state);
+        }
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending PostgreSQL data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::{client_info, masscanned, SERVER_IP};
+    use crate::smack::{BASE_STATE, NO_MATCH};
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    const SSL_REQUEST: &[u8] = b"\x00\x00\x00\x08\x04\xd2\x16\x2f";
+    const GSSENC_REQUEST: &[u8] = b"\x00\x00\x00\x08\x04\xd2\x16\x30";
+
+    /* StartupMessage of psql */
+    const PSQL_STARTUP: &[u8] = b"\x00\x00\x00\x53\x00\x03\x00\x00user\x00postgres\x00database\x00billing\x00application_name\x00psql\x00client_encoding\x00UTF8\x00\x00";
+
+    fn startup_parameters(data: &[u8]) -> Vec<(String, String)> {
+        let mut state = PostgresState::default();
+        postgres_startup(
+            &data[4..],
+            &masscanned(Config::default()),
+            &client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 5432),
+            &mut state,
+        );
+        state.parameters.unwrap()
+    }
+
+    #[test]
+    fn test_postgres_ssl_request() {
+        let mut client_info = client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 5432);
+        /* TLS termination disabled: N, then the startup in plaintext */
+        let masscanned = masscanned(Config::default());
+        let mut tcb = TCPControlBlock::new();
+        let repl_data =
+            crate::proto::dispatch(SSL_REQUEST, &masscanned, &mut client_info, Some(&mut tcb));
+        assert!(repl_data == Some(b"N".to_vec()));
+        let repl_data =
+            crate::proto::dispatch(PSQL_STARTUP, &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(repl_data.starts_with(b"R\x00\x00\x00\x0c\x00\x00\x00\x05"));
+        /* GSSAPI encryption is refused */
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = crate::proto::dispatch(
+            GSSENC_REQUEST,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        );
+        assert!(repl_data == Some(b"N".to_vec()));
+        /* TLS termination enabled: S, and the flow goes to the TLS layer */
+        let mut config = Config::default();
+        config.tls.enabled = true;
+        let masscanned = self::masscanned(config);
+        let mut tcb = TCPControlBlock::new();
+        let repl_data =
+            crate::proto::dispatch(SSL_REQUEST, &masscanned, &mut client_info, Some(&mut tcb));
+        assert!(repl_data == Some(b"S".to_vec()));
+        assert!(tcb.proto_id == NO_MATCH && tcb.smack_state == BASE_STATE);
+        assert!(!client_info.close);
+        let mut masscanned = masscanned;
+        masscanned.config.postgres.ssl = false;
+        let repl_data = repl(SSL_REQUEST, &masscanned, &mut client_info, None);
+        assert!(repl_data == Some(b"N".to_vec()));
+    }
+
+    #[test]
+    fn test_postgres_login() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 5432);
+        let mut tcb = TCPControlBlock::new();
+        /* startup split across segments */
+        assert!(crate::proto::dispatch(
+            &PSQL_STARTUP[..10],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl_data = repl(
+            &PSQL_STARTUP[10..],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        let salt = match &tcb.proto_state {
+            ProtoState::Postgres(s) => s.salt,
+            _ => panic!("expected a PostgreSQL state"),
+        };
+        assert!(repl_data == [&b"R\x00\x00\x00\x0c\x00\x00\x00\x05"[..], &salt].concat());
+        let password = b"p\x00\x00\x00\x28md5c8f1f3b6a5e9d0b7f1d0a7e6c4b3a291\x00";
+        let repl_data = repl(password, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let message = b"password authentication failed for user \"postgres\"";
+        let mut expected = b"SFATAL\x00VFATAL\x00C28P01\x00M".to_vec();
+        expected.extend_from_slice(message);
+        expected.extend_from_slice(b"\x00Fauth.c\x00Rauth_failed\x00\x00");
+        assert!(repl_data == postgres_reply(b'E', &expected));
+        assert!(client_info.close);
+        let state = match &tcb.proto_state {
+            ProtoState::Postgres(s) => s,
+            _ => panic!("expected a PostgreSQL state"),
+        };
+        let event = postgres_login_event(
+            "md5c8f1f3b6a5e9d0b7f1d0a7e6c4b3a291",
+            state,
+            &masscanned.config.postgres,
+            &client_info,
+        );
+        assert!(event.fields["user"] == "postgres" && event.fields["database"] == "billing");
+        assert!(event.fields["method"] == "md5");
+        assert!(event.fields["hash"] == "md5c8f1f3b6a5e9d0b7f1d0a7e6c4b3a291");
+        assert!(event.fields["salt"] == encode(&salt, Encoding::Hex));
+        let event = postgres_startup_event(&startup_parameters(PSQL_STARTUP), &client_info);
+        assert!(event.fields["application_name"] == "psql");
+        assert!(event.fields["parameters"]["client_encoding"] == "UTF8");
+        /* cleartext, startup and password in one segment */
+        let masscanned = self::masscanned(
+            Config::from_str("[postgres]\nauth = \"cleartext\"\nerror_message = \"denied\"\n")
+                .unwrap(),
+        );
+        let mut client_info = self::client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 5432);
+        let mut tcb = TCPControlBlock::new();
+        let data = [PSQL_STARTUP, b"p\x00\x00\x00\x0bs3cret\x00"].concat();
+        let repl_data = repl(&data, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl_data.starts_with(b"R\x00\x00\x00\x08\x00\x00\x00\x03E"));
+        assert!(repl_data.ends_with(b"C28P01\x00Mdenied\x00Fauth.c\x00Rauth_failed\x00\x00"));
+    }
+
+    #[test]
+    fn test_postgres_errors() {
+        let masscanned = masscanned(Config::default());
+        /* no user, unsupported protocol, invalid lengths, unexpected
+         * messages */
+        for (data, code) in [
+            (
+                &b"\x00\x00\x00\x14\x00\x03\x00\x00database\x00x\x00\x00"[..],
+                Some("28000"),
+            ),
+            (b"\x00\x00\x00\x08\x00\x02\x00\x00", Some("0A000")),
+            (b"\x00\x00\x00\x02\x00\x03\x00\x00", None),
+            (b"\xff\xff\xff\xff\x00\x03\x00\x00", None),
+            (
+                b"\x00\x00\x00\x10\x04\xd2\x16\x2e\x00\x00\x00\x01\x00\x00\x00\x02",
+                None,
+            ),
+        ]
+        .iter()
+        {
+            let mut client_info = client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 5432);
+            let repl_data = repl(data, &masscanned, &mut client_info, None);
+            match code {
+                Some(c) => assert!(repl_data
+                    .unwrap()
+                    .windows(7)
+                    .any(|w| w == format!("C{}\x00", c).as_bytes())),
+                None => assert!(repl_data.is_none()),
+            }
+            assert!(client_info.close);
+        }
+        let mut client_info = client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 5432);
+        let data = [PSQL_STARTUP, b"Q\x00\x00\x00\x0dSELECT 1\x00"].concat();
+        let repl_data = repl(&data, &masscanned, &mut client_info, None).unwrap();
+        assert!(repl_data[0] == b'R' && repl_data.len() == 13);
+        assert!(client_info.close);
+    }
+}
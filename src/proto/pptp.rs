@@ -0,0 +1,321 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* RFC 2637 section 2 */
+const PPTP_CONTROL_MESSAGE: u16 = 1;
+const PPTP_MAGIC_COOKIE: u32 = 0x1a2b3c4d;
+
+const PPTP_SCCRQ: u16 = 1;
+const PPTP_SCCRP: u16 = 2;
+const PPTP_ECHO_REQUEST: u16 = 5;
+const PPTP_ECHO_REPLY: u16 = 6;
+const PPTP_OCRQ: u16 = 7;
+const PPTP_CDN: u16 = 13;
+
+const PPTP_SCCRQ_LEN: usize = 156;
+const PPTP_ECHO_REQUEST_LEN: usize = 16;
+const PPTP_OCRQ_LEN: usize = 168;
+
+/* host names and vendor strings are padded with zeros to 64 bytes */
+const PPTP_NAME_LEN: usize = 64;
+
+/* the longest control message (Outgoing-Call-Request) is 168 bytes */
+const PPTP_MAX_MESSAGE: usize = 512;
+
+/* SCCRQ, on other ports */
+pub const PPTP_PATTERN_SCCRQ: &[u8; 10] = b"\x00\x9c\x00\x01\x1a\x2b\x3c\x4d\x00\x01";
+
+/* PPTP server (on the configured TCP ports, and on other ports after a
+ * Start-Control-Connection-Request): the SCCRQ gets an SCCRP with our
+ * firmware revision, host name and vendor name, Echo-Requests an
+ * Echo-Reply, and Outgoing-Call-Requests a Call-Disconnect-Notify.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PptpConfig {
+    pub ports: Vec<u16>,
+    pub host_name: String,
+    pub vendor_name: String,
+    pub firmware_revision: u16,
+    pub framing_capabilities: u32,
+    pub bearer_capabilities: u32,
+    pub max_channels: u16,
+}
+
+impl Default for PptpConfig {
+    fn default() -> Self {
+        PptpConfig {
+            ports: vec![1723],
+            host_name: "MikroTik".to_string(),
+            vendor_name: "MikroTik".to_string(),
+            firmware_revision: 1,
+            framing_capabilities: 1,
+            bearer_capabilities: 1,
+            max_channels: 1,
+        }
+    }
+}
+
+impl PptpConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for (name, value) in [
+            ("host_name", &self.host_name),
+            ("vendor_name", &self.vendor_name),
+        ] {
+            if value.len() > PPTP_NAME_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("pptp: {} longer than {} bytes", name, PPTP_NAME_LEN),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/* beginning of a control message not received entirely yet */
+#[derive(Default)]
+pub struct PptpState {
+    buffer: Vec<u8>,
+}
+
+fn pptp_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn pptp_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/* zero-padded string */
+fn pptp_name(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).to_string()
+}
+
+fn pptp_pad_name(msg: &mut Vec<u8>, name: &str) {
+    let start = msg.len();
+    msg.extend_from_slice(name.as_bytes());
+    msg.resize(start + PPTP_NAME_LEN, 0);
+}
+
+fn pptp_message(type_: u16, body: &[u8]) -> Vec<u8> {
+    let mut msg = ((12 + body.len()) as u16).to_be_bytes().to_vec();
+    msg.extend_from_slice(&PPTP_CONTROL_MESSAGE.to_be_bytes());
+    msg.extend_from_slice(&PPTP_MAGIC_COOKIE.to_be_bytes());
+    msg.extend_from_slice(&type_.to_be_bytes());
+    msg.extend_from_slice(&[0, 0]);
+    msg.extend_from_slice(body);
+    msg
+}
+
+fn pptp_sccrp(config: &PptpConfig) -> Vec<u8> {
+    /* protocol version 1.0, result code 1 (success), no error */
+    let mut body = vec![1, 0, 1, 0];
+    body.extend_from_slice(&config.framing_capabilities.to_be_bytes());
+    body.extend_from_slice(&config.bearer_capabilities.to_be_bytes());
+    body.extend_from_slice(&config.max_channels.to_be_bytes());
+    body.extend_from_slice(&config.firmware_revision.to_be_bytes());
+    pptp_pad_name(&mut body, &config.host_name);
+    pptp_pad_name(&mut body, &config.vendor_name);
+    pptp_message(PPTP_SCCRP, &body)
+}
+
+/* answer to one control message (the length, message type and magic
+ * cookie have been checked) */
+fn pptp_control(msg: &[u8], masscanned: &Masscanned, client_info: &ClientInfo) -> Option<Vec<u8>> {
+    let config = &masscanned.config.pptp;
+    let type_ = pptp_u16(msg, 8);
+    let mut event = Event::new("pptp_control", client_info);
+    event.set("message_type", type_);
+    let repl = match type_ {
+        PPTP_SCCRQ if msg.len() == PPTP_SCCRQ_LEN => {
+            event.set("protocol_version", format!("{}.{}", msg[12], msg[13]));
+            event.set("framing_capabilities", pptp_u32(msg, 16));
+            event.set("bearer_capabilities", pptp_u32(msg, 20));
+            event.set("max_channels", pptp_u16(msg, 24));
+            event.set("firmware_revision", pptp_u16(msg, 26));
+            event.set("host_name", pptp_name(&msg[28..92]));
+            event.set("vendor_name", pptp_name(&msg[92..156]));
+            warn!("PPTP Start-Control-Connection-Request");
+            Some(pptp_sccrp(config))
+        }
+        PPTP_ECHO_REQUEST if msg.len() == PPTP_ECHO_REQUEST_LEN => {
+            /* identifier, result code 1 (OK), no error */
+            let mut body = msg[12..16].to_vec();
+            body.extend_from_slice(&[1, 0, 0, 0]);
+            Some(pptp_message(PPTP_ECHO_REPLY, &body))
+        }
+        PPTP_OCRQ if msg.len() == PPTP_OCRQ_LEN => {
+            event.set("call_id", pptp_u16(msg, 12));
+            event.set("phone_number", pptp_name(&msg[40..104]));
+            /* call ID, result code 3 (administrative shutdown), no
+             * error, no cause, reserved, no call statistics */
+            let mut body = msg[12..14].to_vec();
+            body.extend_from_slice(&[3, 0, 0, 0, 0, 0]);
+            body.resize(136, 0);
+            Some(pptp_message(PPTP_CDN, &body))
+        }
+        _ => {
+            info!("PPTP control message not handled (type {})", type_);
+            None
+        }
+    };
+    event.log();
+    repl
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving PPTP data");
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Pptp(s)) => s,
+        _ => PptpState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    let mut offset = 0;
+    while state.buffer.len() >= offset + 12 {
+        let msg = &state.buffer[offset..];
+        let len = pptp_u16(msg, 0) as usize;
+        if !(12..=PPTP_MAX_MESSAGE).contains(&len)
+            || pptp_u16(msg, 2) != PPTP_CONTROL_MESSAGE
+            || pptp_u32(msg, 4) != PPTP_MAGIC_COOKIE
+        {
+            info!("PPTP message not handled (invalid header)");
+            client_info.close = true;
+            return None;
+        }
+        if msg.len() < len {
+            break;
+        }
+        if let Some(r) = pptp_control(&msg[..len], masscanned, client_info) {
+            repl_data.extend(r);
+        }
+        offset += len;
+    }
+    state.buffer.drain(..offset);
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Pptp(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending PPTP data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.src = Some(54321);
+        client_info.port.dst = Some(1723);
+        client_info
+    }
+
+    /* SCCRQ of nmap (pptp-version): version 1.0, asynchronous framing,
+     * analog bearer, no host name nor vendor */
+    fn nmap_sccrq() -> Vec<u8> {
+        let mut msg = b"\x00\x9c\x00\x01\x1a\x2b\x3c\x4d\x00\x01\x00\x00\
+                        \x01\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\x01\
+                        \x00\x00\x00\x00"
+            .to_vec();
+        msg.resize(PPTP_SCCRQ_LEN, 0);
+        msg
+    }
+
+    #[test]
+    fn test_pptp_sccrp() {
+        let mut masscanned = masscanned(Config::default());
+        masscanned.config.pptp.vendor_name = "Microsoft".to_string();
+        masscanned.config.pptp.firmware_revision = 0x0e00;
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        let data = nmap_sccrq();
+        assert!(data.starts_with(PPTP_PATTERN_SCCRQ));
+        /* split across segments */
+        assert!(repl(&data[..20], &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        let sccrp =
+            repl(&data[20..], &masscanned, &mut client_info, Some(&mut tcb)).expect("no SCCRP");
+        assert!(sccrp.len() == 156);
+        assert!(sccrp[..12] == b"\x00\x9c\x00\x01\x1a\x2b\x3c\x4d\x00\x02\x00\x00"[..]);
+        /* version 1.0, result code 1, error code 0 */
+        assert!(sccrp[12..16] == [1, 0, 1, 0]);
+        assert!(pptp_u32(&sccrp, 16) == 1 && pptp_u32(&sccrp, 20) == 1);
+        assert!(pptp_u16(&sccrp, 24) == 1);
+        assert!(sccrp[26..28] == [0x0e, 0x00]);
+        assert!(sccrp[28..36] == *b"MikroTik" && sccrp[36..92].iter().all(|b| *b == 0));
+        assert!(sccrp[92..101] == *b"Microsoft" && sccrp[101..].iter().all(|b| *b == 0));
+        assert!(!client_info.close);
+        /* bad magic cookie */
+        let mut data = nmap_sccrq();
+        data[7] = 0;
+        assert!(repl(&data, &masscanned, &mut client_info, None).is_none());
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_pptp_echo_call() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        /* Echo-Request, then Outgoing-Call-Request, in one segment */
+        let mut data = pptp_message(PPTP_ECHO_REQUEST, b"\xde\xad\xbe\xef");
+        let mut ocrq = b"\x12\x34\x00\x01".to_vec();
+        ocrq.resize(PPTP_OCRQ_LEN - 12, 0);
+        ocrq[28..32].copy_from_slice(b"5551");
+        data.extend(pptp_message(PPTP_OCRQ, &ocrq));
+        let repl = repl(&data, &masscanned, &mut client_info, None).expect("no reply");
+        assert!(repl.len() == 20 + 148);
+        assert!(repl[..12] == b"\x00\x14\x00\x01\x1a\x2b\x3c\x4d\x00\x06\x00\x00"[..]);
+        assert!(repl[12..20] == b"\xde\xad\xbe\xef\x01\x00\x00\x00"[..]);
+        let cdn = &repl[20..];
+        assert!(pptp_u16(cdn, 0) == 148 && pptp_u16(cdn, 8) == PPTP_CDN);
+        assert!(cdn[12..16] == [0x12, 0x34, 3, 0]);
+        assert!(pptp_name(&ocrq[28..92]) == "5551");
+    }
+}
@@ -0,0 +1,340 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::net::Ipv4Addr;
+
+use log::*;
+use md5::{Digest, Md5};
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::Masscanned;
+
+/* RFC 2865 */
+const RADIUS_ACCESS_REQUEST: u8 = 1;
+const RADIUS_ACCESS_REJECT: u8 = 3;
+const RADIUS_HEADER_LEN: usize = 20;
+const RADIUS_MAX_LEN: usize = 4096;
+
+/* attributes */
+const RADIUS_USER_NAME: u8 = 1;
+const RADIUS_USER_PASSWORD: u8 = 2;
+const RADIUS_CHAP_PASSWORD: u8 = 3;
+const RADIUS_NAS_IP_ADDRESS: u8 = 4;
+const RADIUS_REPLY_MESSAGE: u8 = 18;
+const RADIUS_NAS_IDENTIFIER: u8 = 32;
+const RADIUS_CHAP_CHALLENGE: u8 = 60;
+/* RFC 3579 section 3.2 */
+const RADIUS_MESSAGE_AUTHENTICATOR: u8 = 80;
+
+/* RADIUS server (on the UDP ports): Access-Request packets get an
+ * Access-Reject, authenticated with secret (the User-Password is also
+ * decrypted with it).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RadiusConfig {
+    pub ports: Vec<u16>,
+    pub secret: String,
+    pub reply_message: String,
+}
+
+impl Default for RadiusConfig {
+    fn default() -> Self {
+        RadiusConfig {
+            ports: vec![1812, 1645],
+            secret: "testing123".to_string(),
+            reply_message: String::new(),
+        }
+    }
+}
+
+struct RadiusPacket<'a> {
+    code: u8,
+    identifier: u8,
+    authenticator: &'a [u8],
+    /* type and value */
+    attributes: Vec<(u8, &'a [u8])>,
+}
+
+/* packet (shorter than its length field: dropped, longer: the rest is
+ * ignored); attributes are parsed up to the first malformed one */
+fn radius_parse(data: &[u8]) -> Option<RadiusPacket<'_>> {
+    if data.len() < RADIUS_HEADER_LEN {
+        return None;
+    }
+    let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+    if !(RADIUS_HEADER_LEN..=RADIUS_MAX_LEN).contains(&len) || len > data.len() {
+        return None;
+    }
+    let mut attributes = Vec::new();
+    let mut rest = &data[RADIUS_HEADER_LEN..len];
+    while let [t, l, ..] = rest {
+        let l = *l as usize;
+        if l < 2 || l > rest.len() {
+            info!("RADIUS: malformed attribute {}", t);
+            break;
+        }
+        attributes.push((*t, &rest[2..l]));
+        rest = &rest[l..];
+    }
+    Some(RadiusPacket {
+        code: data[0],
+        identifier: data[1],
+        authenticator: &data[4..RADIUS_HEADER_LEN],
+        attributes,
+    })
+}
+
+/* RFC 2865 section 5.2 */
+fn radius_decrypt_password(hidden: &[u8], authenticator: &[u8], secret: &[u8]) -> Vec<u8> {
+    let mut password = Vec::new();
+    let mut previous = authenticator;
+    for chunk in hidden.chunks(16) {
+        let mut hasher = Md5::new();
+        hasher.update(secret);
+        hasher.update(previous);
+        let b = hasher.finalize();
+        password.extend(chunk.iter().zip(b.iter()).map(|(c, b)| c ^ b));
+        previous = chunk;
+    }
+    while password.last() == Some(&0) {
+        password.pop();
+    }
+    password
+}
+
+/* RFC 2104, for the Message-Authenticator */
+fn hmac_md5(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut k = if key.len() > 64 {
+        Md5::digest(key).to_vec()
+    } else {
+        key.to_vec()
+    };
+    k.resize(64, 0);
+    let mut inner = Md5::new();
+    inner.update(k.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>());
+    inner.update(data);
+    let mut outer = Md5::new();
+    outer.update(k.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>());
+    outer.update(inner.finalize());
+    outer.finalize().to_vec()
+}
+
+/* response: the Response Authenticator (RFC 2865 section 3) is computed
+ * over the packet with the Request Authenticator, then the secret */
+fn radius_response(
+    code: u8,
+    request: &RadiusPacket,
+    attributes: &[(u8, &[u8])],
+    secret: &[u8],
+) -> Vec<u8> {
+    let mut packet = vec![code, request.identifier, 0, 0];
+    packet.extend_from_slice(request.authenticator);
+    let mut message_authenticator = None;
+    for (t, v) in attributes.iter() {
+        if *t == RADIUS_MESSAGE_AUTHENTICATOR {
+            message_authenticator = Some(packet.len() + 2);
+        }
+        packet.push(*t);
+        packet.push(v.len() as u8 + 2);
+        packet.extend_from_slice(v);
+    }
+    let len = (packet.len() as u16).to_be_bytes();
+    packet[2..4].copy_from_slice(&len);
+    if let Some(offset) = message_authenticator {
+        let hmac = hmac_md5(secret, &packet);
+        packet[offset..offset + 16].copy_from_slice(&hmac);
+    }
+    let mut hasher = Md5::new();
+    hasher.update(&packet);
+    hasher.update(secret);
+    let authenticator = hasher.finalize();
+    packet[4..RADIUS_HEADER_LEN].copy_from_slice(&authenticator);
+    packet
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving RADIUS data");
+    let config = &masscanned.config.radius;
+    let packet = radius_parse(data)?;
+    let mut event = Event::new("radius_request", client_info);
+    event.set("code", packet.code);
+    event.set("identifier", packet.identifier);
+    let mut message_authenticator = false;
+    for (t, v) in packet.attributes.iter() {
+        match *t {
+            RADIUS_USER_NAME => event.set("user_name", String::from_utf8_lossy(v).to_string()),
+            RADIUS_USER_PASSWORD => {
+                event.set("user_password", encode(v, Encoding::Hex));
+                let password =
+                    radius_decrypt_password(v, packet.authenticator, config.secret.as_bytes());
+                event.set("password", String::from_utf8_lossy(&password).to_string());
+            }
+            RADIUS_CHAP_PASSWORD => event.set("chap_password", encode(v, Encoding::Hex)),
+            RADIUS_CHAP_CHALLENGE => event.set("chap_challenge", encode(v, Encoding::Hex)),
+            RADIUS_NAS_IP_ADDRESS if v.len() == 4 => {
+                event.set(
+                    "nas_ip_address",
+                    Ipv4Addr::new(v[0], v[1], v[2], v[3]).to_string(),
+                );
+            }
+            RADIUS_NAS_IDENTIFIER => {
+                event.set("nas_identifier", String::from_utf8_lossy(v).to_string())
+            }
+            RADIUS_MESSAGE_AUTHENTICATOR => message_authenticator = true,
+            _ => {}
+        }
+    }
+    event.log();
+    if packet.code != RADIUS_ACCESS_REQUEST {
+        info!("RADIUS code not handled: {}", packet.code);
+        return None;
+    }
+    warn!("RADIUS Access-Request from {:?}", client_info.ip.src);
+    let mut attributes: Vec<(u8, &[u8])> = Vec::new();
+    if !config.reply_message.is_empty() {
+        let message = config.reply_message.as_bytes();
+        attributes.push((RADIUS_REPLY_MESSAGE, &message[..message.len().min(253)]));
+    }
+    /* clients sending one (RFC 3579, RFC 5080 section 2.2.2) expect one */
+    if message_authenticator {
+        attributes.push((RADIUS_MESSAGE_AUTHENTICATOR, &[0; 16]));
+    }
+    debug!("sending RADIUS data");
+    Some(radius_response(
+        RADIUS_ACCESS_REJECT,
+        &packet,
+        &attributes,
+        config.secret.as_bytes(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+
+    /* RFC 2865 section 7.1 */
+    const RFC_REQUEST: &[u8] = b"\x01\x00\x00\x38\x0f\x40\x3f\x94\x73\x97\x80\x57\xbd\x83\xd5\xcb\x98\xf4\x22\x7a\x01\x06nemo\x02\x12\x0d\xbe\x70\x8d\x93\xd4\x13\xce\x31\x96\xe4\x3f\x78\x2a\x0a\xee\x04\x06\xc0\xa8\x01\x10\x05\x06\x00\x00\x00\x03";
+    const RFC_ACCEPT: &[u8] = b"\x02\x00\x00\x26\x86\xfe\x22\x0e\x76\x24\xba\x2a\x10\x05\xf6\xbf\x9b\x55\xe0\xb2\x06\x06\x00\x00\x00\x01\x0f\x06\x00\x00\x00\x00\x0e\x06\xc0\xa8\x01\x03";
+
+    /* echo "User-Name = bob, User-Password = <password>" | radclient
+     * 127.0.0.1 auth testing123 */
+    fn radclient(password: &[u8]) -> Vec<u8> {
+        let authenticator = b"\x5b\x1e\xa3\x9c\x0d\x44\x87\x12\xe6\x73\x2f\x90\x11\x8a\xc4\x3d";
+        let mut padded = password.to_vec();
+        padded.resize(password.len().div_ceil(16).max(1) * 16, 0);
+        let mut hidden = Vec::new();
+        let mut previous = authenticator.to_vec();
+        for chunk in padded.chunks(16) {
+            let mut hasher = Md5::new();
+            hasher.update(b"testing123");
+            hasher.update(&previous);
+            let c: Vec<u8> = chunk
+                .iter()
+                .zip(hasher.finalize().iter())
+                .map(|(p, b)| p ^ b)
+                .collect();
+            hidden.extend_from_slice(&c);
+            previous = c;
+        }
+        let mut packet = vec![RADIUS_ACCESS_REQUEST, 0xb7, 0, 0];
+        packet.extend_from_slice(authenticator);
+        packet.extend_from_slice(b"\x01\x05bob");
+        packet.push(RADIUS_USER_PASSWORD);
+        packet.push(hidden.len() as u8 + 2);
+        packet.extend(hidden);
+        packet.extend_from_slice(b"\x04\x06\x7f\x00\x01\x01\x20\x0bradclient");
+        packet.extend_from_slice(b"\x50\x12");
+        packet.extend_from_slice(&[0; 16]);
+        let len = (packet.len() as u16).to_be_bytes();
+        packet[2..4].copy_from_slice(&len);
+        let offset = packet.len() - 16;
+        let hmac = hmac_md5(b"testing123", &packet);
+        packet[offset..].copy_from_slice(&hmac);
+        packet
+    }
+
+    #[test]
+    fn test_radius_authenticator() {
+        let request = radius_parse(RFC_REQUEST).unwrap();
+        assert!(request.code == RADIUS_ACCESS_REQUEST && request.identifier == 0);
+        assert!(request.attributes.len() == 4 && request.attributes[0] == (1, &b"nemo"[..]));
+        let attributes: [(u8, &[u8]); 3] = [
+            (6, b"\x00\x00\x00\x01"),
+            (15, b"\x00\x00\x00\x00"),
+            (14, b"\xc0\xa8\x01\x03"),
+        ];
+        assert!(radius_response(2, &request, &attributes, b"xyzzy5461") == RFC_ACCEPT);
+        /* RFC 2202 section 2 */
+        assert!(
+            hmac_md5(b"Jefe", b"what do ya want for nothing?")
+                == b"\x75\x0c\x78\x3e\x6a\xb0\xb5\x03\xea\xa8\x6e\x31\x0a\x5d\xb7\x38"
+        );
+        /* passwords, up to 16 bytes and longer */
+        for password in [&b"hello"[..], b"correct horse battery staple"].iter() {
+            let request = radclient(password);
+            let packet = radius_parse(&request).unwrap();
+            let (_, hidden) = packet.attributes[1];
+            assert!(
+                radius_decrypt_password(hidden, packet.authenticator, b"testing123") == *password
+            );
+        }
+    }
+
+    #[test]
+    fn test_radius_repl() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(pnet::packet::ip::IpNextHeaderProtocols::Udp);
+        client_info.port.dst = Some(1812);
+        let request = radclient(b"hello");
+        let repl_data = crate::proto::dispatch(&request, &masscanned, &mut client_info, None)
+            .expect("no answer to Access-Request");
+        assert!(repl_data[0] == RADIUS_ACCESS_REJECT && repl_data[1] == 0xb7);
+        assert!(u16::from_be_bytes([repl_data[2], repl_data[3]]) as usize == repl_data.len());
+        /* Response Authenticator */
+        let mut check = repl_data.clone();
+        check[4..20].copy_from_slice(&request[4..20]);
+        let mut hasher = Md5::new();
+        hasher.update(&check);
+        hasher.update(b"testing123");
+        assert!(repl_data[4..20] == hasher.finalize()[..]);
+        /* Message-Authenticator */
+        assert!(repl_data[20..22] == [RADIUS_MESSAGE_AUTHENTICATOR, 18]);
+        check[22..38].copy_from_slice(&[0; 16]);
+        assert!(repl_data[22..38] == hmac_md5(b"testing123", &check)[..]);
+        /* malformed attributes end the parsing */
+        let mut request = request[..20].to_vec();
+        request.extend_from_slice(b"\x01\x05bob\x20\x01radclient");
+        request[3] = request.len() as u8;
+        let packet = radius_parse(&request).unwrap();
+        assert!(packet.attributes == [(RADIUS_USER_NAME, &b"bob"[..])]);
+        request.extend_from_slice(b"\x20\xff");
+        request[3] = request.len() as u8;
+        assert!(radius_parse(&request).unwrap().attributes.len() == 1);
+        /* truncated packets, other codes */
+        request[3] += 1;
+        assert!(repl(&request, &masscanned, &mut client_info).is_none());
+        assert!(repl(&request[..19], &masscanned, &mut client_info).is_none());
+        request[0] = 4;
+        request[3] -= 1;
+        assert!(repl(&request, &masscanned, &mut client_info).is_none());
+    }
+}
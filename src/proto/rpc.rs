@@ -0,0 +1,698 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::net::IpAddr;
+
+use log::*;
+use pnet::packet::ip::IpNextHeaderProtocols;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::nfs::{self, MOUNT_PROGRAM, NFS_PROGRAM};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* ONC RPC (RFC 5531) */
+const RPC_VERSION: u32 = 2;
+const RPC_CALL: u32 = 0;
+const RPC_REPLY: u32 = 1;
+const RPC_MSG_ACCEPTED: u32 = 0;
+const RPC_MSG_DENIED: u32 = 1;
+
+/* accept status */
+const RPC_SUCCESS: u32 = 0;
+const RPC_PROG_UNAVAIL: u32 = 1;
+const RPC_PROG_MISMATCH: u32 = 2;
+pub const RPC_PROC_UNAVAIL: u32 = 3;
+pub const RPC_GARBAGE_ARGS: u32 = 4;
+
+/* reject status */
+const RPC_MISMATCH: u32 = 0;
+
+/* authentication flavors */
+const AUTH_NONE: u32 = 0;
+pub const AUTH_SYS: u32 = 1;
+
+/* portmapper (RFC 1833): version 2 is portmap, versions 3 and 4 are
+ * rpcbind */
+const PORTMAP_PROGRAM: u32 = 100000;
+const PORTMAP_VERSION_MIN: u32 = 2;
+const PORTMAP_VERSION_MAX: u32 = 4;
+
+const PORTMAP_NULL: u32 = 0;
+const PORTMAP_SET: u32 = 1;
+const PORTMAP_UNSET: u32 = 2;
+const PORTMAP_GETPORT: u32 = 3;
+const PORTMAP_DUMP: u32 = 4;
+const PORTMAP_CALLIT: u32 = 5;
+
+/* record marking (TCP): last fragment flag, fragment length */
+const RPC_LAST_FRAGMENT: u32 = 0x80000000;
+const RPC_MAX_FRAGMENT: usize = 8192;
+
+/* size of a record kept between two segments */
+const RPC_MAX_RECORD: usize = 65536;
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RpcProtocol {
+    Tcp,
+    Udp,
+}
+
+impl RpcProtocol {
+    fn number(&self) -> u32 {
+        match self {
+            RpcProtocol::Tcp => 6,
+            RpcProtocol::Udp => 17,
+        }
+    }
+
+    /* rpcbind network identifier */
+    fn netid(&self) -> &'static str {
+        match self {
+            RpcProtocol::Tcp => "tcp",
+            RpcProtocol::Udp => "udp",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RpcMapping {
+    pub program: u32,
+    pub version: u32,
+    pub protocol: RpcProtocol,
+    pub port: u16,
+}
+
+/* SunRPC portmapper and rpcbind (on the TCP and UDP ports): the
+ * mappings are listed (DUMP) and looked up (GETPORT, GETADDR), the
+ * other procedures are refused.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcConfig {
+    pub ports: Vec<u16>,
+    pub mapping: Vec<RpcMapping>,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        let mapping = |program, version, protocol, port| RpcMapping {
+            program,
+            version,
+            protocol,
+            port,
+        };
+        let mut mappings = Vec::new();
+        for version in [4, 3, 2].iter() {
+            for protocol in [RpcProtocol::Tcp, RpcProtocol::Udp].iter() {
+                mappings.push(mapping(PORTMAP_PROGRAM, *version, *protocol, 111));
+            }
+        }
+        /* status, mountd, nfs, nlockmgr */
+        mappings.extend(vec![
+            mapping(100024, 1, RpcProtocol::Udp, 47853),
+            mapping(100024, 1, RpcProtocol::Tcp, 36765),
+        ]);
+        for version in [1, 2, 3].iter() {
+            for protocol in [RpcProtocol::Udp, RpcProtocol::Tcp].iter() {
+                mappings.push(mapping(MOUNT_PROGRAM, *version, *protocol, 20048));
+            }
+        }
+        mappings.extend(vec![
+            mapping(NFS_PROGRAM, 3, RpcProtocol::Tcp, 2049),
+            mapping(NFS_PROGRAM, 4, RpcProtocol::Tcp, 2049),
+            mapping(100021, 4, RpcProtocol::Udp, 43519),
+            mapping(100021, 4, RpcProtocol::Tcp, 40273),
+        ]);
+        RpcConfig {
+            ports: vec![111],
+            mapping: mappings,
+        }
+    }
+}
+
+impl RpcConfig {
+    /* versions of a program served on a port: the lowest and highest of
+     * the mappings */
+    fn versions(&self, program: u32, port: u16, tcp: bool) -> Option<(u32, u32)> {
+        let protocol = if tcp {
+            RpcProtocol::Tcp
+        } else {
+            RpcProtocol::Udp
+        };
+        if program == PORTMAP_PROGRAM && self.ports.contains(&port) {
+            return Some((PORTMAP_VERSION_MIN, PORTMAP_VERSION_MAX));
+        }
+        let versions = self
+            .mapping
+            .iter()
+            .filter(|m| m.program == program && m.port == port && m.protocol == protocol)
+            .map(|m| m.version);
+        Some((versions.clone().min()?, versions.max()?))
+    }
+
+    /* port advertised for a program other than the portmapper */
+    pub fn is_mapped(&self, port: u16, tcp: bool) -> bool {
+        self.mapping.iter().any(|m| {
+            m.program != PORTMAP_PROGRAM
+                && m.port == port
+                && (m.protocol == RpcProtocol::Tcp) == tcp
+        })
+    }
+}
+
+/* state of a flow: fragments of the current record */
+#[derive(Default)]
+pub struct RpcState {
+    buffer: Vec<u8>,
+    record: Vec<u8>,
+}
+
+/* XDR (RFC 4506) decoding */
+pub struct XdrReader<'a> {
+    pub data: &'a [u8],
+}
+
+impl<'a> XdrReader<'a> {
+    pub fn u32(&mut self) -> Option<u32> {
+        let v = self.data.get(..4)?;
+        self.data = &self.data[4..];
+        Some(u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+    }
+
+    /* variable-length opaque data, padded to 4 bytes */
+    pub fn opaque(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        let padded = len.checked_add(3)? & !3;
+        let data = self.data.get(..len)?;
+        self.data = self.data.get(padded..)?;
+        Some(data)
+    }
+
+    pub fn string(&mut self) -> Option<String> {
+        Some(String::from_utf8_lossy(self.opaque()?).to_string())
+    }
+}
+
+pub fn xdr_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_be_bytes());
+}
+
+pub fn xdr_opaque(out: &mut Vec<u8>, data: &[u8]) {
+    xdr_u32(out, data.len() as u32);
+    out.extend_from_slice(data);
+    out.resize(out.len() + (4 - data.len() % 4) % 4, 0);
+}
+
+pub fn xdr_string(out: &mut Vec<u8>, s: &str) {
+    xdr_opaque(out, s.as_bytes());
+}
+
+/* credentials: AUTH_SYS ones are recorded */
+fn rpc_credentials(flavor: u32, body: &[u8], event: &mut Event) {
+    event.set("cred_flavor", flavor);
+    if flavor != AUTH_SYS {
+        return;
+    }
+    let mut reader = XdrReader { data: body };
+    /* stamp, machine name, uid, gid */
+    if let (Some(_), Some(machine_name), Some(uid), Some(gid)) =
+        (reader.u32(), reader.string(), reader.u32(), reader.u32())
+    {
+        event.set("machine_name", machine_name);
+        event.set("uid", uid);
+        event.set("gid", gid);
+    }
+}
+
+/* universal address (RFC 1833 section 2.2) of a port on the address
+ * the call was sent to */
+fn rpc_universal_address(port: u16, client_info: &ClientInfo) -> String {
+    let host = match client_info.ip.dst {
+        Some(IpAddr::V4(ip)) => ip.to_string(),
+        _ => "0.0.0.0".to_string(),
+    };
+    format!("{}.{}.{}", host, port >> 8, port & 0xff)
+}
+
+/* results of a portmapper procedure, or the accept status */
+fn rpc_portmap(
+    version: u32,
+    procedure: u32,
+    args: &mut XdrReader,
+    config: &RpcConfig,
+    client_info: &ClientInfo,
+    event: &mut Event,
+) -> Result<Vec<u8>, u32> {
+    let mut results = Vec::new();
+    match procedure {
+        PORTMAP_NULL => {}
+        /* registrations are refused */
+        PORTMAP_SET | PORTMAP_UNSET => xdr_u32(&mut results, 0),
+        PORTMAP_GETPORT if version == 2 => {
+            /* program, version, protocol, port */
+            let (program, prog_version, protocol) = match (args.u32(), args.u32(), args.u32()) {
+                (Some(p), Some(v), Some(t)) => (p, v, t),
+                _ => return Err(RPC_GARBAGE_ARGS),
+            };
+            event.set("query_program", program);
+            event.set("query_version", prog_version);
+            event.set("query_protocol", protocol);
+            let port = config
+                .mapping
+                .iter()
+                .find(|m| {
+                    m.program == program
+                        && m.version == prog_version
+                        && m.protocol.number() == protocol
+                })
+                .map(|m| m.port)
+                .unwrap_or(0);
+            xdr_u32(&mut results, port as u32);
+        }
+        PORTMAP_GETPORT => {
+            /* GETADDR: program, version, netid, address, owner */
+            let (program, prog_version, netid) = match (args.u32(), args.u32(), args.string()) {
+                (Some(p), Some(v), Some(n)) => (p, v, n),
+                _ => return Err(RPC_GARBAGE_ARGS),
+            };
+            event.set("query_program", program);
+            event.set("query_version", prog_version);
+            event.set("query_netid", netid.as_str());
+            let address = config
+                .mapping
+                .iter()
+                .find(|m| {
+                    m.program == program && m.version == prog_version && m.protocol.netid() == netid
+                })
+                .map(|m| rpc_universal_address(m.port, client_info))
+                .unwrap_or_default();
+            xdr_string(&mut results, &address);
+        }
+        PORTMAP_DUMP => {
+            for m in config.mapping.iter() {
+                /* value follows */
+                xdr_u32(&mut results, 1);
+                xdr_u32(&mut results, m.program);
+                xdr_u32(&mut results, m.version);
+                if version == 2 {
+                    xdr_u32(&mut results, m.protocol.number());
+                    xdr_u32(&mut results, m.port as u32);
+                } else {
+                    xdr_string(&mut results, m.protocol.netid());
+                    xdr_string(&mut results, &rpc_universal_address(m.port, client_info));
+                    xdr_string(&mut results, "superuser");
+                }
+            }
+            xdr_u32(&mut results, 0);
+        }
+        _ => return Err(RPC_PROC_UNAVAIL),
+    }
+    Ok(results)
+}
+
+/* reply to a call message (None for those that get no reply) */
+fn rpc_answer(call: &[u8], masscanned: &Masscanned, client_info: &ClientInfo) -> Option<Vec<u8>> {
+    let config = &masscanned.config.rpc;
+    let mut reader = XdrReader { data: call };
+    let xid = reader.u32()?;
+    if reader.u32()? != RPC_CALL {
+        return None;
+    }
+    let rpc_version = reader.u32()?;
+    let (program, version, procedure) = (reader.u32()?, reader.u32()?, reader.u32()?);
+    let cred_flavor = reader.u32()?;
+    let cred = reader.opaque()?;
+    /* verifier */
+    reader.u32()?;
+    reader.opaque()?;
+    let mut event = Event::new("rpc_call", client_info);
+    event.set("xid", xid);
+    event.set("program", program);
+    event.set("version", version);
+    event.set("procedure", procedure);
+    rpc_credentials(cred_flavor, cred, &mut event);
+    warn!(
+        "RPC call to program {} version {} procedure {}",
+        program, version, procedure
+    );
+    let mut reply = Vec::new();
+    xdr_u32(&mut reply, xid);
+    xdr_u32(&mut reply, RPC_REPLY);
+    if rpc_version != RPC_VERSION {
+        xdr_u32(&mut reply, RPC_MSG_DENIED);
+        xdr_u32(&mut reply, RPC_MISMATCH);
+        xdr_u32(&mut reply, RPC_VERSION);
+        xdr_u32(&mut reply, RPC_VERSION);
+        event.log();
+        return Some(reply);
+    }
+    xdr_u32(&mut reply, RPC_MSG_ACCEPTED);
+    /* verifier: AUTH_NONE */
+    xdr_u32(&mut reply, AUTH_NONE);
+    xdr_u32(&mut reply, 0);
+    /* programs are served on the ports the portmapper advertises */
+    let versions = config.versions(
+        program,
+        client_info.port.dst.unwrap_or(0),
+        client_info.transport == Some(IpNextHeaderProtocols::Tcp),
+    );
+    let results = match versions {
+        None => Err(RPC_PROG_UNAVAIL),
+        Some((low, high)) if !(low..=high).contains(&version) => Err(RPC_PROG_MISMATCH),
+        _ if program == PORTMAP_PROGRAM && procedure == PORTMAP_CALLIT => {
+            /* indirect calls (used for reflection) are never answered */
+            event.log();
+            return None;
+        }
+        _ => match program {
+            PORTMAP_PROGRAM => rpc_portmap(
+                version,
+                procedure,
+                &mut reader,
+                config,
+                client_info,
+                &mut event,
+            ),
+            MOUNT_PROGRAM => nfs::mountd(
+                version,
+                procedure,
+                &mut reader,
+                &masscanned.config.nfs,
+                &mut event,
+            ),
+            NFS_PROGRAM => nfs::nfs(
+                version,
+                procedure,
+                &mut reader,
+                &masscanned.config.nfs,
+                &mut event,
+            ),
+            /* other programs only answer NULL */
+            _ if procedure == 0 => Ok(Vec::new()),
+            _ => Err(RPC_PROC_UNAVAIL),
+        },
+    };
+    match results {
+        Ok(results) => {
+            xdr_u32(&mut reply, RPC_SUCCESS);
+            reply.extend(results);
+        }
+        Err(status) => {
+            event.set("accept_stat", status);
+            xdr_u32(&mut reply, status);
+            if let (RPC_PROG_MISMATCH, Some((low, high))) = (status, versions) {
+                xdr_u32(&mut reply, low);
+                xdr_u32(&mut reply, high);
+            }
+        }
+    }
+    event.log();
+    Some(reply)
+}
+
+/* record (TCP): fragments of at most RPC_MAX_FRAGMENT bytes */
+pub fn rpc_record(message: &[u8]) -> Vec<u8> {
+    let mut record = Vec::new();
+    let mut chunks = message.chunks(RPC_MAX_FRAGMENT).peekable();
+    while let Some(fragment) = chunks.next() {
+        let last = if chunks.peek().is_none() {
+            RPC_LAST_FRAGMENT
+        } else {
+            0
+        };
+        xdr_u32(&mut record, last | fragment.len() as u32);
+        record.extend_from_slice(fragment);
+    }
+    record
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving RPC data");
+    if client_info.transport != Some(IpNextHeaderProtocols::Tcp) {
+        return rpc_answer(data, masscanned, client_info);
+    }
+    let t = tcb?;
+    let mut state = match std::mem::replace(&mut t.proto_state, ProtoState::None) {
+        ProtoState::Rpc(s) => s,
+        _ => RpcState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while state.buffer.len() >= 4 {
+        let marker = u32::from_be_bytes([
+            state.buffer[0],
+            state.buffer[1],
+            state.buffer[2],
+            state.buffer[3],
+        ]);
+        let len = (marker & !RPC_LAST_FRAGMENT) as usize;
+        if state.record.len() + len > RPC_MAX_RECORD {
+            info!("RPC record too large - dropped");
+            client_info.close = true;
+            break;
+        }
+        if state.buffer.len() < 4 + len {
+            break;
+        }
+        state.record.extend_from_slice(&state.buffer[4..4 + len]);
+        state.buffer.drain(..4 + len);
+        if marker & RPC_LAST_FRAGMENT != 0 {
+            let record = std::mem::take(&mut state.record);
+            match rpc_answer(&record, masscanned, client_info) {
+                Some(reply) => repl_data.extend(rpc_record(&reply)),
+                None => info!("RPC record without an answer"),
+            }
+        }
+    }
+    t.proto_state = ProtoState::Rpc(state);
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending RPC data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::{client_info, masscanned, SERVER_IP};
+
+    /* call (AUTH_NONE, or AUTH_SYS from "scanner"), with arguments */
+    fn call(program: u32, version: u32, procedure: u32, auth_sys: bool, args: &[u8]) -> Vec<u8> {
+        let mut call = Vec::new();
+        for v in [
+            0x5d1f3a01,
+            RPC_CALL,
+            RPC_VERSION,
+            program,
+            version,
+            procedure,
+        ]
+        .iter()
+        {
+            xdr_u32(&mut call, *v);
+        }
+        if auth_sys {
+            let mut cred = Vec::new();
+            xdr_u32(&mut cred, 0x65a1b2c3);
+            xdr_string(&mut cred, "scanner");
+            for v in [0, 0, 0].iter() {
+                xdr_u32(&mut cred, *v);
+            }
+            xdr_u32(&mut call, AUTH_SYS);
+            xdr_u32(&mut call, cred.len() as u32);
+            call.extend(cred);
+        } else {
+            call.extend_from_slice(&[0; 8]);
+        }
+        call.extend_from_slice(&[0; 8]);
+        call.extend_from_slice(args);
+        call
+    }
+
+    /* accept status and results of a reply */
+    fn reply(reply: &[u8]) -> (u32, XdrReader<'_>) {
+        let mut reader = XdrReader { data: reply };
+        assert!(reader.u32() == Some(0x5d1f3a01));
+        assert!(reader.u32() == Some(RPC_REPLY));
+        assert!(reader.u32() == Some(RPC_MSG_ACCEPTED));
+        assert!(reader.u32() == Some(AUTH_NONE) && reader.opaque() == Some(&[][..]));
+        (reader.u32().unwrap(), reader)
+    }
+
+    #[test]
+    fn test_rpc_xdr() {
+        let mut data = Vec::new();
+        xdr_string(&mut data, "tcp");
+        xdr_string(&mut data, "");
+        xdr_string(&mut data, "1234");
+        assert!(data == b"\x00\x00\x00\x03tcp\x00\x00\x00\x00\x00\x00\x00\x00\x041234");
+        let mut reader = XdrReader { data: &data };
+        assert!(reader.string().unwrap() == "tcp");
+        assert!(reader.string().unwrap() == "");
+        assert!(reader.string().unwrap() == "1234");
+        assert!(reader.u32().is_none());
+        /* truncated, absurd lengths */
+        assert!(XdrReader { data: &data[..6] }.opaque().is_none());
+        assert!(XdrReader {
+            data: b"\xff\xff\xff\xff"
+        }
+        .opaque()
+        .is_none());
+        /* AUTH_SYS credentials */
+        let call = call(PORTMAP_PROGRAM, 2, PORTMAP_NULL, true, &[]);
+        let mut event = Event::new("rpc_call", &ClientInfo::new());
+        rpc_credentials(AUTH_SYS, &call[32..60], &mut event);
+        assert!(event.fields["machine_name"] == "scanner");
+        assert!(event.fields["uid"] == 0 && event.fields["gid"] == 0);
+        /* records: fragments */
+        let record = rpc_record(&[0; RPC_MAX_FRAGMENT + 10]);
+        assert!(record[..4] == (RPC_MAX_FRAGMENT as u32).to_be_bytes());
+        assert!(record[4 + RPC_MAX_FRAGMENT..8 + RPC_MAX_FRAGMENT] == [0x80, 0, 0, 10]);
+    }
+
+    #[test]
+    fn test_rpc_dump() {
+        let masscanned = masscanned(Config::default());
+        let mappings = &masscanned.config.rpc.mapping;
+        /* rpcinfo -p: portmap DUMP, over TCP */
+        let mut client_info = client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 111);
+        let mut tcb = TCPControlBlock::new();
+        let request = rpc_record(&call(PORTMAP_PROGRAM, 2, PORTMAP_DUMP, false, &[]));
+        assert!(request[..4] == [0x80, 0, 0, 40]);
+        let repl_data =
+            crate::proto::dispatch(&request, &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(repl_data[0] == 0x80 && repl_data.len() == 4 + 24 + mappings.len() * 20 + 4);
+        let (status, mut results) = reply(&repl_data[4..]);
+        assert!(status == RPC_SUCCESS);
+        for m in mappings.iter() {
+            assert!(results.u32() == Some(1));
+            assert!(results.u32() == Some(m.program) && results.u32() == Some(m.version));
+            assert!(results.u32() == Some(m.protocol.number()));
+            assert!(results.u32() == Some(m.port as u32));
+        }
+        assert!(results.u32() == Some(0) && results.data.is_empty());
+        /* rpcinfo -T tcp: rpcbind version 4 DUMP (AUTH_SYS), in two
+         * fragments and two segments */
+        let call = call(PORTMAP_PROGRAM, 4, PORTMAP_DUMP, true, &[]);
+        let mut request = (20u32).to_be_bytes().to_vec();
+        request.extend_from_slice(&call[..20]);
+        request.extend(rpc_record(&call[20..]));
+        assert!(crate::proto::dispatch(
+            &request[..30],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl_data = crate::proto::dispatch(
+            &request[30..],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        let (status, mut results) = reply(&repl_data[4..]);
+        assert!(status == RPC_SUCCESS);
+        assert!(results.u32() == Some(1));
+        assert!(results.u32() == Some(PORTMAP_PROGRAM) && results.u32() == Some(4));
+        assert!(results.string().unwrap() == "tcp");
+        assert!(results.string().unwrap() == "198.51.100.7.0.111");
+        assert!(results.string().unwrap() == "superuser");
+    }
+
+    #[test]
+    fn test_rpc_getport() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 111);
+        /* nfs version 3 over TCP, then an unknown program */
+        for (program, port) in [(100003, 2049), (100099, 0)].iter() {
+            let mut args = Vec::new();
+            for v in [*program, 3, 6, 0].iter() {
+                xdr_u32(&mut args, *v);
+            }
+            let repl_data = crate::proto::dispatch(
+                &call(PORTMAP_PROGRAM, 2, PORTMAP_GETPORT, false, &args),
+                &masscanned,
+                &mut client_info,
+                None,
+            )
+            .unwrap();
+            let (status, mut results) = reply(&repl_data);
+            assert!(status == RPC_SUCCESS && results.u32() == Some(*port));
+        }
+        /* rpcbind GETADDR */
+        let mut args = Vec::new();
+        xdr_u32(&mut args, 100005);
+        xdr_u32(&mut args, 3);
+        for s in ["udp", "", ""].iter() {
+            xdr_string(&mut args, s);
+        }
+        let repl_data = repl(
+            &call(PORTMAP_PROGRAM, 3, PORTMAP_GETPORT, false, &args),
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        let (_, mut results) = reply(&repl_data);
+        assert!(results.string().unwrap() == "198.51.100.7.78.80");
+        /* NULL, errors */
+        for (program, version, procedure, status) in [
+            (PORTMAP_PROGRAM, 2, PORTMAP_NULL, RPC_SUCCESS),
+            (100003, 3, PORTMAP_NULL, RPC_PROG_UNAVAIL),
+            (PORTMAP_PROGRAM, 5, PORTMAP_NULL, RPC_PROG_MISMATCH),
+            (PORTMAP_PROGRAM, 2, 9, RPC_PROC_UNAVAIL),
+            (PORTMAP_PROGRAM, 2, PORTMAP_GETPORT, RPC_GARBAGE_ARGS),
+        ]
+        .iter()
+        {
+            let repl_data = repl(
+                &call(*program, *version, *procedure, false, &[]),
+                &masscanned,
+                &mut client_info,
+                None,
+            )
+            .unwrap();
+            let (s, results) = reply(&repl_data);
+            assert!(s == *status);
+            assert!(results.data.len() == if s == RPC_PROG_MISMATCH { 8 } else { 0 });
+        }
+        assert!(repl(
+            &call(PORTMAP_PROGRAM, 2, PORTMAP_CALLIT, false, &[]),
+            &masscanned,
+            &mut client_info,
+            None
+        )
+        .is_none());
+        let mut denied = call(PORTMAP_PROGRAM, 2, PORTMAP_NULL, false, &[]);
+        denied[11] = 3;
+        let repl_data = repl(&denied, &masscanned, &mut client_info, None).unwrap();
+        assert!(repl_data[8..] == [0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 2]);
+    }
+}
@@ -0,0 +1,303 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::line::LineBuffer;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* rsync daemon (server-first): the version banner is sent once the TCP
+ * handshake is completed. A module listing gets the modules below, a
+ * module an authentication challenge: the response is logged, and the
+ * authentication fails.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RsyncConfig {
+    pub ports: Vec<u16>,
+    /* without the final LF */
+    pub banner: String,
+    pub modules: Vec<RsyncModule>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RsyncModule {
+    pub name: String,
+    #[serde(default)]
+    pub comment: String,
+}
+
+impl Default for RsyncConfig {
+    fn default() -> Self {
+        RsyncConfig {
+            ports: vec![873],
+            banner: "@RSYNCD: 31.0".to_string(),
+            modules: vec![
+                RsyncModule {
+                    name: "backup".to_string(),
+                    comment: "Backups".to_string(),
+                },
+                RsyncModule {
+                    name: "www".to_string(),
+                    comment: "Web server data".to_string(),
+                },
+            ],
+        }
+    }
+}
+
+impl RsyncConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if !self.banner.starts_with("@RSYNCD: ") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("rsync: invalid banner: {:?}", self.banner),
+            ));
+        }
+        let mut lines = vec![&self.banner];
+        for m in self.modules.iter() {
+            lines.push(&m.name);
+            lines.push(&m.comment);
+        }
+        for s in lines {
+            if s.contains(['\r', '\n']) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("rsync: invalid line (CR or LF found): {:?}", s),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/* state of an rsync flow: partial line, version given by the client,
+ * and module and challenge sent to the client */
+#[derive(Default)]
+pub struct RsyncState {
+    lines: LineBuffer,
+    version: Option<String>,
+    challenge: Option<(String, String)>,
+}
+
+fn rsync_error(text: &str) -> Vec<u8> {
+    format!("@ERROR: {}\n", text).into_bytes()
+}
+
+/* answer to a line */
+fn rsync_line(
+    line: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    state: &mut RsyncState,
+) -> Vec<u8> {
+    let config = &masscanned.config.rsync;
+    let line = String::from_utf8_lossy(line).to_string();
+    let version = match state.version.clone() {
+        Some(v) => v,
+        None => {
+            /* "@RSYNCD: 31.0" (and, for newer clients, digests) */
+            match line.strip_prefix("@RSYNCD: ") {
+                Some(v) => state.version = Some(v.to_string()),
+                None => {
+                    client_info.close = true;
+                    return rsync_error("protocol startup error");
+                }
+            }
+            return Vec::new();
+        }
+    };
+    if let Some((module, challenge)) = state.challenge.take() {
+        /* "user response" */
+        let (user, response) = line.split_once(' ').unwrap_or((&line, ""));
+        warn!("rsync login to {}: {}", module, user);
+        let mut event = Event::new("rsync_login", client_info);
+        event.set("version", version);
+        event.set("module", module.clone());
+        event.set("user", user);
+        event.set("challenge", challenge);
+        event.set("response", response);
+        event.log();
+        client_info.close = true;
+        return rsync_error(&format!("auth failed on module {}", module));
+    }
+    let mut event = Event::new("rsync_request", client_info);
+    event.set("version", version);
+    event.set("module", line.clone());
+    event.log();
+    if line.is_empty() || line == "#list" {
+        /* module listing */
+        let mut repl = Vec::new();
+        for m in config.modules.iter() {
+            repl.extend(format!("{:<15}\t{}\n", m.name, m.comment).into_bytes());
+        }
+        repl.extend(b"@RSYNCD: EXIT\n");
+        client_info.close = true;
+        return repl;
+    }
+    let module = line.trim_end_matches('/');
+    if !config.modules.iter().any(|m| m.name == module) {
+        client_info.close = true;
+        return rsync_error(&format!("Unknown module '{}'", module));
+    }
+    /* the challenge is 16 bytes in base64, without padding */
+    let challenge = base64::encode(rand::random::<[u8; 16]>())
+        .trim_end_matches('=')
+        .to_string();
+    let repl = format!("@RSYNCD: AUTHREQD {}\n", challenge).into_bytes();
+    state.challenge = Some((module.to_string(), challenge));
+    repl
+}
+
+/* banner, sent when the TCP handshake is completed */
+pub fn greeting(masscanned: &Masscanned, tcb: &mut TCPControlBlock) -> Option<Vec<u8>> {
+    tcb.proto_state = ProtoState::Rsync(RsyncState::default());
+    Some(format!("{}\n", masscanned.config.rsync.banner).into_bytes())
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving rsync data");
+    /* rsync is only answered over TCP, after the banner */
+    let state = match tcb {
+        Some(TCPControlBlock {
+            proto_state: ProtoState::Rsync(s),
+            ..
+        }) => s,
+        _ => return None,
+    };
+    let mut repl_data = Vec::new();
+    for line in state.lines.push(data) {
+        repl_data.extend(rsync_line(&line, masscanned, client_info, state));
+        if client_info.close {
+            break;
+        }
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending rsync data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_rsync_list() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        assert!(greeting(&masscanned, &mut tcb).unwrap() == b"@RSYNCD: 31.0\n");
+        /* version, then the (empty) module, split across segments */
+        assert!(repl(
+            b"@RSYNCD: 31.0 md5 md4\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        match &tcb.proto_state {
+            ProtoState::Rsync(s) => assert!(s.version.as_deref() == Some("31.0 md5 md4")),
+            _ => panic!("expected an rsync state"),
+        }
+        assert!(!client_info.close);
+        let repl_data = repl(b"\n", &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(
+            repl_data
+                == b"backup         \tBackups\nwww            \tWeb server data\n@RSYNCD: EXIT\n"
+        );
+        assert!(client_info.close);
+        /* not rsync */
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        greeting(&masscanned, &mut tcb);
+        let repl_data = repl(
+            b"GET / HTTP/1.0\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"@ERROR: protocol startup error\n" && client_info.close);
+    }
+
+    #[test]
+    fn test_rsync_auth() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        greeting(&masscanned, &mut tcb);
+        let repl_data = repl(
+            b"@RSYNCD: 31.0\nbackup/\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data.starts_with(b"@RSYNCD: AUTHREQD ") && repl_data.ends_with(b"\n"));
+        let challenge = &repl_data[b"@RSYNCD: AUTHREQD ".len()..repl_data.len() - 1];
+        assert!(challenge.len() == 22);
+        match &tcb.proto_state {
+            ProtoState::Rsync(s) => {
+                let (module, c) = s.challenge.as_ref().unwrap();
+                assert!(module == "backup" && c.as_bytes() == challenge);
+            }
+            _ => panic!("expected an rsync state"),
+        }
+        let repl_data = repl(
+            b"admin 6ZSlfAUzSo4qQ7V3Oyw+Yg\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"@ERROR: auth failed on module backup\n");
+        assert!(client_info.close);
+        /* unknown module */
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        greeting(&masscanned, &mut tcb);
+        let repl_data = repl(
+            b"@RSYNCD: 30.0\nsecret\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"@ERROR: Unknown module 'secret'\n" && client_info.close);
+        /* configuration */
+        assert!(Config::from_str("[rsync]\nbanner = \"RSYNCD 31\"\n").is_err());
+        assert!(Config::from_str("[[rsync.modules]]\nname = \"a\\nb\"\n").is_err());
+        let config = Config::from_str("[[rsync.modules]]\nname = \"data\"\n").unwrap();
+        assert!(config.rsync.modules.len() == 1 && config.rsync.modules[0].comment.is_empty());
+    }
+}
@@ -0,0 +1,471 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::http::http_auth_event;
+use crate::proto::line::LineBuffer;
+use crate::proto::sip::digest_auth_event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* RFC 2326 section 10 */
+pub const RTSP_METHODS: [&str; 11] = [
+    "OPTIONS",
+    "DESCRIBE",
+    "ANNOUNCE",
+    "SETUP",
+    "PLAY",
+    "PAUSE",
+    "TEARDOWN",
+    "GET_PARAMETER",
+    "SET_PARAMETER",
+    "REDIRECT",
+    "RECORD",
+];
+
+/* maximum number of header lines of a request */
+const RTSP_MAX_HEADERS: usize = 128;
+
+/* RTSP server (e.g., an IP camera): OPTIONS and DESCRIBE are answered,
+ * SETUP and PLAY (as well as the other methods that need a session) get
+ * a Digest challenge.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RtspConfig {
+    pub ports: Vec<u16>,
+    pub server: String,
+    pub realm: String,
+    pub public: String,
+    /* answer to DESCRIBE */
+    pub sdp: String,
+}
+
+impl Default for RtspConfig {
+    fn default() -> Self {
+        RtspConfig {
+            ports: vec![554, 8554],
+            server: "Rtsp Server/3.0".to_string(),
+            realm: "IP Camera".to_string(),
+            public: "OPTIONS, DESCRIBE, SETUP, PLAY, PAUSE, TEARDOWN, GET_PARAMETER, SET_PARAMETER"
+                .to_string(),
+            sdp: "v=0
+o=- 1109162014219182 1109162014219192 IN IP4 0.0.0.0
+s=Media Presentation
+e=NONE
+b=AS:5050
+t=0 0
+a=control:*
+m=video 0 RTP/AVP 96
+b=AS:5000
+a=control:trackID=1
+a=rtpmap:96 H264/90000
+a=fmtp:96 profile-level-id=420029; packetization-mode=1
+"
+            .to_string(),
+        }
+    }
+}
+
+/* state of an RTSP flow: partial request, and what is left of the body
+ * of the previous one */
+#[derive(Default)]
+pub struct RtspState {
+    lines: LineBuffer,
+    request: Vec<Vec<u8>>,
+    body: usize,
+}
+
+struct RtspRequest {
+    method: String,
+    uri: String,
+    headers: Vec<(String, String)>,
+}
+
+impl RtspRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(h, _)| h.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn rtsp_parse(lines: &[Vec<u8>]) -> Option<RtspRequest> {
+    let request_line = String::from_utf8_lossy(lines.first()?).to_string();
+    let mut parts = request_line.split(' ');
+    let method = parts.next()?;
+    let uri = parts.next()?;
+    if !parts.next()?.starts_with("RTSP/1.") || parts.next().is_some() || uri.is_empty() {
+        return None;
+    }
+    let mut headers = Vec::new();
+    for line in lines[1..].iter() {
+        let line = String::from_utf8_lossy(line);
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    Some(RtspRequest {
+        method: method.to_string(),
+        uri: uri.to_string(),
+        headers,
+    })
+}
+
+/* base of the presentation: the request URI, when absolute */
+fn rtsp_content_base(request: &RtspRequest, client_info: &ClientInfo) -> String {
+    let base = if request.uri.starts_with("rtsp://") || request.uri.starts_with("rtsps://") {
+        request.uri.clone()
+    } else {
+        let host = match client_info.ip.dst {
+            Some(std::net::IpAddr::V6(ip)) => format!("[{}]", ip),
+            Some(ip) => ip.to_string(),
+            None => "localhost".to_string(),
+        };
+        let port = client_info.port.dst.unwrap_or(554);
+        format!(
+            "rtsp://{}:{}{}",
+            host,
+            port,
+            request.uri.trim_start_matches('*')
+        )
+    };
+    if base.ends_with('/') {
+        base
+    } else {
+        format!("{}/", base)
+    }
+}
+
+fn rtsp_response(
+    request: &RtspRequest,
+    status: &str,
+    headers: &[String],
+    body: &str,
+    config: &RtspConfig,
+) -> Vec<u8> {
+    let mut repl = format!("RTSP/1.0 {}\r\n", status);
+    /* RFC 2326 section 12.17 */
+    if let Some(cseq) = request.header("CSeq") {
+        repl.push_str(&format!("CSeq: {}\r\n", cseq));
+    }
+    repl.push_str(&format!("Server: {}\r\n", config.server));
+    for header in headers.iter() {
+        repl.push_str(header);
+        repl.push_str("\r\n");
+    }
+    if !body.is_empty() {
+        repl.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    repl.push_str("\r\n");
+    repl.push_str(body);
+    repl.into_bytes()
+}
+
+fn rtsp_event(request: &RtspRequest, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("rtsp_request", client_info);
+    event.set("method", request.method.clone());
+    event.set("uri", request.uri.clone());
+    if let Some(ua) = request.header("User-Agent") {
+        event.set("user_agent", ua.to_string());
+    }
+    event
+}
+
+fn rtsp_auth_event(value: &str, request: &RtspRequest, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("rtsp_auth", client_info);
+    event.set("method", request.method.clone());
+    event.set("uri", request.uri.clone());
+    if !digest_auth_event(&mut event, value) {
+        /* Basic, or other schemes */
+        http_auth_event(&mut event, value.as_bytes());
+    }
+    event
+}
+
+fn rtsp_answer(
+    lines: &[Vec<u8>],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    let config = &masscanned.config.rtsp;
+    let request = match rtsp_parse(lines) {
+        Some(r) => r,
+        None => {
+            info!("RTSP data not handled (invalid request)");
+            return None;
+        }
+    };
+    rtsp_event(&request, client_info).log();
+    if request.header("CSeq").is_none() {
+        return Some(rtsp_response(&request, "400 Bad Request", &[], "", config));
+    }
+    if let Some(value) = request.header("Authorization") {
+        warn!(
+            "RTSP {} {} with credentials: {}",
+            request.method, request.uri, value
+        );
+        rtsp_auth_event(value, &request, client_info).log();
+    }
+    let repl = match request.method.as_str() {
+        "OPTIONS" => rtsp_response(
+            &request,
+            "200 OK",
+            &[format!("Public: {}", config.public)],
+            "",
+            config,
+        ),
+        "DESCRIBE" => {
+            let sdp: String = config.sdp.lines().map(|l| format!("{}\r\n", l)).collect();
+            rtsp_response(
+                &request,
+                "200 OK",
+                &[
+                    format!("Content-Base: {}", rtsp_content_base(&request, client_info)),
+                    "Content-Type: application/sdp".to_string(),
+                ],
+                &sdp,
+                config,
+            )
+        }
+        "TEARDOWN" | "GET_PARAMETER" => rtsp_response(&request, "200 OK", &[], "", config),
+        "SETUP" | "PLAY" | "PAUSE" | "RECORD" | "ANNOUNCE" | "SET_PARAMETER" => {
+            let challenge = format!(
+                "WWW-Authenticate: Digest realm=\"{}\", nonce=\"{:032x}\"",
+                config.realm.replace('"', "\\\""),
+                rand::random::<u128>()
+            );
+            rtsp_response(&request, "401 Unauthorized", &[challenge], "", config)
+        }
+        _ => rtsp_response(
+            &request,
+            "501 Not Implemented",
+            &[format!("Public: {}", config.public)],
+            "",
+            config,
+        ),
+    };
+    Some(repl)
+}
+
+/* length of the body of a request (e.g., SET_PARAMETER) */
+fn rtsp_content_length(lines: &[Vec<u8>]) -> usize {
+    lines
+        .iter()
+        .skip(1)
+        .filter_map(|l| {
+            let l = String::from_utf8_lossy(l);
+            let (name, value) = l.split_once(':')?;
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                value.trim().parse().ok()
+            } else {
+                None
+            }
+        })
+        .next()
+        .unwrap_or(0)
+}
+
+fn rtsp_requests(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+    state: &mut RtspState,
+) -> Vec<u8> {
+    state.lines.extend(data);
+    let mut repl_data = Vec::new();
+    loop {
+        if state.body > 0 {
+            state.body -= state.lines.discard(state.body);
+            if state.body > 0 {
+                break;
+            }
+        }
+        let line = match state.lines.line() {
+            Some(l) => l,
+            None => break,
+        };
+        if !line.is_empty() {
+            if state.request.len() >= RTSP_MAX_HEADERS {
+                info!("RTSP request too large - dropped");
+                state.request.clear();
+            }
+            state.request.push(line);
+            continue;
+        }
+        /* empty lines between requests are ignored */
+        if state.request.is_empty() {
+            continue;
+        }
+        let request = std::mem::take(&mut state.request);
+        state.body = rtsp_content_length(&request);
+        if let Some(repl) = rtsp_answer(&request, masscanned, client_info) {
+            repl_data.extend(repl);
+        }
+    }
+    repl_data
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving RTSP data");
+    let repl_data = match tcb.as_mut() {
+        Some(t) => {
+            let mut state = match std::mem::replace(&mut t.proto_state, ProtoState::None) {
+                ProtoState::Rtsp(s) => s,
+                _ => RtspState::default(),
+            };
+            let repl_data = rtsp_requests(data, masscanned, client_info, &mut state);
+            t.proto_state = ProtoState::Rtsp(state);
+            repl_data
+        }
+        /* over UDP: one request per datagram */
+        None => rtsp_requests(data, masscanned, client_info, &mut RtspState::default()),
+    };
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending RTSP data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::{client_info, masscanned, SERVER_IP};
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    fn text(data: Option<Vec<u8>>) -> String {
+        String::from_utf8(data.expect("expected an answer")).unwrap()
+    }
+
+    #[test]
+    fn test_rtsp_options() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 554);
+        let mut tcb = TCPControlBlock::new();
+        /* nmap rtsp-methods, then a request with * */
+        let repl_str = text(crate::proto::dispatch(
+            b"OPTIONS rtsp://198.51.100.7:554 RTSP/1.0\r\nCSeq: 1\r\n\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        ));
+        assert!(
+            repl_str
+                == format!(
+                    "RTSP/1.0 200 OK\r\nCSeq: 1\r\nServer: Rtsp Server/3.0\r\nPublic: {}\r\n\r\n",
+                    masscanned.config.rtsp.public
+                )
+        );
+        /* CSeq is echoed, requests split across segments or pipelined */
+        assert!(crate::proto::dispatch(
+            b"OPTIONS * RTSP/1.0\r\nCS",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl_str = text(crate::proto::dispatch(
+            b"eq: 2\r\nUser-Agent: LibVLC/3.0.18\r\n\r\nGET_PARAMETER * RTSP/1.0\r\nCSeq: 3\r\nContent-Length: 9\r\n\r\nposition\n\r\nFOO * RTSP/1.0\r\ncseq: 4\r\n\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        ));
+        assert!(repl_str.starts_with("RTSP/1.0 200 OK\r\nCSeq: 2\r\n"));
+        assert!(repl_str.contains("\r\n\r\nRTSP/1.0 200 OK\r\nCSeq: 3\r\n"));
+        assert!(repl_str.contains("\r\n\r\nRTSP/1.0 501 Not Implemented\r\nCSeq: 4\r\n"));
+        /* no CSeq */
+        let repl_str = text(crate::proto::dispatch(
+            b"OPTIONS * RTSP/1.0\r\n\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        ));
+        assert!(repl_str.starts_with("RTSP/1.0 400 Bad Request\r\nServer: "));
+    }
+
+    #[test]
+    fn test_rtsp_describe() {
+        let masscanned = masscanned(Config::default());
+        /* absolute URI, on another port (identified by the URI scheme) */
+        let mut client_info = client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 8080);
+        let mut tcb = TCPControlBlock::new();
+        let repl_str = text(crate::proto::dispatch(
+            b"DESCRIBE rtsp://198.51.100.7:8080/Streaming/Channels/101 RTSP/1.0\r\nCSeq: 2\r\nAccept: application/sdp\r\n\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        ));
+        let (head, body) = repl_str.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("RTSP/1.0 200 OK\r\nCSeq: 2\r\n"));
+        assert!(head.contains(
+            "\r\nContent-Base: rtsp://198.51.100.7:8080/Streaming/Channels/101/\r\nContent-Type: application/sdp\r\n"
+        ));
+        assert!(head.ends_with(&format!("\r\nContent-Length: {}", body.len())));
+        assert!(body.starts_with("v=0\r\no=- "));
+        assert!(body.contains("\r\nm=video 0 RTP/AVP 96\r\n") && body.ends_with("\r\n"));
+        /* relative URI */
+        let request = rtsp_parse(&[b"DESCRIBE /live RTSP/1.0".to_vec()]).unwrap();
+        assert!(rtsp_content_base(&request, &client_info) == "rtsp://198.51.100.7:8080/live/");
+    }
+
+    #[test]
+    fn test_rtsp_setup() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Tcp, SERVER_IP, 554);
+        let mut tcb = TCPControlBlock::new();
+        let setup = b"SETUP rtsp://198.51.100.7/live/trackID=1 RTSP/1.0\r\nCSeq: 3\r\nTransport: RTP/AVP/TCP;unicast;interleaved=0-1\r\n";
+        let repl_str = text(crate::proto::dispatch(
+            &[&setup[..], b"\r\n"].concat(),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        ));
+        assert!(repl_str.starts_with("RTSP/1.0 401 Unauthorized\r\nCSeq: 3\r\n"));
+        assert!(repl_str.contains("\r\nWWW-Authenticate: Digest realm=\"IP Camera\", nonce=\""));
+        /* with credentials: recorded, challenged again */
+        let auth = "Digest username=\"admin\", realm=\"IP Camera\", nonce=\"0123\", uri=\"rtsp://198.51.100.7/live/trackID=1\", response=\"5d41402abc4b2a76b9719d911017c592\"";
+        let repl_str = text(crate::proto::dispatch(
+            &[
+                &setup[..],
+                format!("Authorization: {}\r\n\r\n", auth).as_bytes(),
+            ]
+            .concat(),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        ));
+        assert!(repl_str.starts_with("RTSP/1.0 401 Unauthorized\r\n"));
+        let request = rtsp_parse(&[b"SETUP * RTSP/1.0".to_vec()]).unwrap();
+        let event = rtsp_auth_event(auth, &request, &client_info);
+        assert!(event.fields["auth_scheme"] == "Digest");
+        assert!(event.fields["auth_username"] == "admin");
+        assert!(event.fields["auth_response"] == "5d41402abc4b2a76b9719d911017c592");
+        let event = rtsp_auth_event("Basic YWRtaW46MTIzNDU=", &request, &client_info);
+        assert!(event.fields["auth_user"] == "admin");
+        assert!(event.fields["auth_password"] == "12345");
+    }
+}
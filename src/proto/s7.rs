@@ -0,0 +1,616 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::proto::tpkt::{
+    tpkt, tpkt_parse, x224_cc, x224_dt, x224_parameters, x224_parse_cr, x224_parse_dt,
+};
+use crate::Masscanned;
+
+const S7_PROTOCOL_ID: u8 = 0x32;
+
+/* message types (ROSCTR) */
+const S7_JOB: u8 = 1;
+const S7_ACK: u8 = 2;
+const S7_ACK_DATA: u8 = 3;
+const S7_USERDATA: u8 = 7;
+
+/* job functions */
+const S7_SETUP_COMMUNICATION: u8 = 0xf0;
+
+/* user data: request and response methods, CPU functions group, read
+ * SZL subfunction */
+const S7_USERDATA_REQUEST: u8 = 0x11;
+const S7_USERDATA_RESPONSE: u8 = 0x12;
+const S7_GROUP_CPU: u8 = 0x04;
+const S7_READ_SZL: u8 = 0x01;
+
+/* system status lists: module identification, component identification */
+const S7_SZL_MODULE_ID: u16 = 0x0011;
+const S7_SZL_COMPONENT_ID: u16 = 0x001c;
+
+/* X.224 parameters: TPDU size, calling and called TSAP */
+const COTP_TPDU_SIZE: u8 = 0xc0;
+const COTP_SRC_TSAP: u8 = 0xc1;
+const COTP_DST_TSAP: u8 = 0xc2;
+
+/* SRC-REF of our Connection Confirm */
+const S7_COTP_REF: [u8; 2] = [0x44, 0x31];
+
+/* size of the data kept between two segments */
+const S7_MAX_BUFFER: usize = 8192;
+
+/* Siemens S7 PLC (S7comm over COTP): connection requests are confirmed,
+ * setup communication jobs acknowledged (with a PDU size of at most
+ * max_pdu_size), and reads of the module (0x0011) and component
+ * (0x001c) identification lists answered with these strings.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S7Config {
+    pub ports: Vec<u16>,
+    pub max_pdu_size: u16,
+    /* order number of the module and of its basic hardware, firmware
+     * version (x.y.z) */
+    pub module: String,
+    pub basic_hardware: String,
+    pub version: String,
+    pub system_name: String,
+    pub module_name: String,
+    pub plant_id: String,
+    pub copyright: String,
+    pub serial: String,
+    pub module_type: String,
+}
+
+impl Default for S7Config {
+    fn default() -> Self {
+        S7Config {
+            ports: vec![102],
+            max_pdu_size: 480,
+            module: "6ES7 315-2EH14-0AB0 ".to_string(),
+            basic_hardware: "6ES7 315-2EH14-0AB0 ".to_string(),
+            version: "3.2.6".to_string(),
+            system_name: "SIMATIC 300(1)".to_string(),
+            module_name: "CPU 315-2 PN/DP".to_string(),
+            plant_id: String::new(),
+            copyright: "Original Siemens Equipment".to_string(),
+            serial: "S C-C2UR28922012".to_string(),
+            module_type: "CPU 315-2 PN/DP".to_string(),
+        }
+    }
+}
+
+impl S7Config {
+    pub fn check(&self) -> Result<(), io::Error> {
+        let err = |e: String| Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        if s7_version(&self.version).is_none() {
+            return err(format!("s7: invalid version: {:?}", self.version));
+        }
+        for (name, value, max) in [
+            ("module", &self.module, 20),
+            ("basic_hardware", &self.basic_hardware, 20),
+            ("system_name", &self.system_name, 32),
+            ("module_name", &self.module_name, 32),
+            ("plant_id", &self.plant_id, 32),
+            ("copyright", &self.copyright, 32),
+            ("serial", &self.serial, 32),
+            ("module_type", &self.module_type, 32),
+        ]
+        .iter()
+        {
+            if value.len() > *max {
+                return err(format!("s7: {} longer than {} bytes", name, max));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn s7_version(version: &str) -> Option<[u8; 3]> {
+    let parts = version
+        .split('.')
+        .map(|p| p.parse::<u8>().ok())
+        .collect::<Option<Vec<u8>>>()?;
+    match parts[..] {
+        [a, b, c] => Some([a, b, c]),
+        _ => None,
+    }
+}
+
+/* state of an S7 flow: data received, not a complete TPKT yet, and
+ * whether the COTP connection is established */
+#[derive(Default)]
+pub struct S7State {
+    buffer: Vec<u8>,
+    connected: bool,
+}
+
+struct S7Pdu<'a> {
+    rosctr: u8,
+    pdu_ref: u16,
+    params: &'a [u8],
+    data: &'a [u8],
+}
+
+/* header (with an error class and code for acknowledgements),
+ * parameters and data */
+fn s7_parse(pdu: &[u8]) -> Option<S7Pdu<'_>> {
+    if pdu.len() < 10 || pdu[0] != S7_PROTOCOL_ID {
+        return None;
+    }
+    let rosctr = pdu[1];
+    let header = if rosctr == S7_ACK || rosctr == S7_ACK_DATA {
+        12
+    } else {
+        10
+    };
+    let plen = u16::from_be_bytes([pdu[6], pdu[7]]) as usize;
+    let dlen = u16::from_be_bytes([pdu[8], pdu[9]]) as usize;
+    Some(S7Pdu {
+        rosctr,
+        pdu_ref: u16::from_be_bytes([pdu[4], pdu[5]]),
+        params: pdu.get(header..header + plen)?,
+        data: pdu.get(header + plen..header + plen + dlen)?,
+    })
+}
+
+/* PDU in a COTP data TPDU, in a TPKT */
+fn s7_pdu(rosctr: u8, pdu_ref: u16, error: Option<u16>, params: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut pdu = vec![S7_PROTOCOL_ID, rosctr, 0, 0];
+    pdu.extend_from_slice(&pdu_ref.to_be_bytes());
+    pdu.extend_from_slice(&(params.len() as u16).to_be_bytes());
+    pdu.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    if let Some(error) = error {
+        pdu.extend_from_slice(&error.to_be_bytes());
+    }
+    pdu.extend_from_slice(params);
+    pdu.extend_from_slice(data);
+    tpkt(&x224_dt(&pdu))
+}
+
+/* string of a fixed-size field, padded with pad */
+fn s7_string(s: &str, len: usize, pad: u8) -> Vec<u8> {
+    let mut field = s.as_bytes()[..std::cmp::min(s.len(), len)].to_vec();
+    field.resize(len, pad);
+    field
+}
+
+/* records of a system status list: (record length, records) */
+fn s7_szl(config: &S7Config, szl_id: u16) -> Option<(u16, Vec<Vec<u8>>)> {
+    match szl_id {
+        /* index, order number, module type, version (for the firmware:
+         * 'V', and the 3 numbers) */
+        S7_SZL_MODULE_ID => {
+            let version = s7_version(&config.version).unwrap_or([0, 0, 0]);
+            let record = |index: u16, mlfb: &str, version: [u8; 4]| {
+                let mut r = index.to_be_bytes().to_vec();
+                r.extend(s7_string(mlfb, 20, b' '));
+                r.extend_from_slice(&[0, 0]);
+                r.extend_from_slice(&version);
+                r
+            };
+            Some((
+                28,
+                vec![
+                    record(0x0001, &config.module, [0, 0xc0, 0, 0x04]),
+                    record(0x0006, &config.basic_hardware, [0, 0xc0, 0, 0x04]),
+                    record(0x0007, "", [b'V', version[0], version[1], version[2]]),
+                ],
+            ))
+        }
+        /* index, string */
+        S7_SZL_COMPONENT_ID => Some((
+            34,
+            [
+                (0x0001, &config.system_name),
+                (0x0002, &config.module_name),
+                (0x0003, &config.plant_id),
+                (0x0004, &config.copyright),
+                (0x0005, &config.serial),
+                (0x0007, &config.module_type),
+            ]
+            .iter()
+            .map(|(index, s)| {
+                let mut r = (*index as u16).to_be_bytes().to_vec();
+                r.extend(s7_string(s, 32, 0));
+                r
+            })
+            .collect(),
+        )),
+        _ => None,
+    }
+}
+
+/* answer to a read SZL request (user data): the list, or an error */
+fn s7_read_szl(pdu: &S7Pdu, config: &S7Config, event: &mut Event) -> Option<Vec<u8>> {
+    let sequence = *pdu.params.get(7)?;
+    let (szl_id, szl_index) = match pdu.data {
+        [_, _, _, _, i0, i1, x0, x1, ..] => (
+            u16::from_be_bytes([*i0, *i1]),
+            u16::from_be_bytes([*x0, *x1]),
+        ),
+        _ => return None,
+    };
+    event.set("szl_id", szl_id);
+    event.set("szl_index", szl_index);
+    warn!("S7 read SZL 0x{:04x} index 0x{:04x}", szl_id, szl_index);
+    let mut params = vec![
+        0x00,
+        0x01,
+        0x12,
+        0x08,
+        S7_USERDATA_RESPONSE,
+        0x80 | S7_GROUP_CPU,
+        S7_READ_SZL,
+        sequence,
+        0,
+        0,
+    ];
+    let data = match s7_szl(config, szl_id) {
+        Some((record_len, records)) => {
+            params.extend_from_slice(&[0, 0]);
+            let mut list = szl_id.to_be_bytes().to_vec();
+            list.extend_from_slice(&szl_index.to_be_bytes());
+            list.extend_from_slice(&record_len.to_be_bytes());
+            list.extend_from_slice(&(records.len() as u16).to_be_bytes());
+            for r in records {
+                list.extend(r);
+            }
+            /* return code: success, transport size: octet string */
+            let mut data = vec![0xff, 0x09];
+            data.extend_from_slice(&(list.len() as u16).to_be_bytes());
+            data.extend(list);
+            data
+        }
+        None => {
+            /* the list does not exist */
+            params.extend_from_slice(&[0xd4, 0x01]);
+            vec![0x0a, 0x00, 0x00, 0x00]
+        }
+    };
+    Some(s7_pdu(S7_USERDATA, pdu.pdu_ref, None, &params, &data))
+}
+
+/* answer to an S7 PDU */
+fn s7_answer(pdu: &S7Pdu, config: &S7Config, client_info: &ClientInfo) -> Option<Vec<u8>> {
+    let mut event = Event::new("s7_request", client_info);
+    event.set("rosctr", pdu.rosctr);
+    let repl = match (pdu.rosctr, pdu.params) {
+        (S7_JOB, [S7_SETUP_COMMUNICATION, _, c0, c1, d0, d1, p0, p1, ..]) => {
+            let requested = u16::from_be_bytes([*p0, *p1]);
+            let pdu_size = std::cmp::min(requested, config.max_pdu_size);
+            event.set("function", "setup_communication");
+            event.set("pdu_size", requested);
+            warn!("S7 setup communication (PDU size {})", requested);
+            let mut params = vec![S7_SETUP_COMMUNICATION, 0, *c0, *c1, *d0, *d1];
+            params.extend_from_slice(&pdu_size.to_be_bytes());
+            Some(s7_pdu(S7_ACK_DATA, pdu.pdu_ref, Some(0), &params, &[]))
+        }
+        (S7_JOB, [function, ..]) => {
+            /* e.g., read or write variables, PLC stop */
+            event.set("function", *function);
+            warn!("S7 job function 0x{:02x}", function);
+            /* error class: access, function not allowed in this state */
+            Some(s7_pdu(S7_ACK, pdu.pdu_ref, Some(0x8104), &[], &[]))
+        }
+        (S7_USERDATA, [0x00, 0x01, 0x12, _, S7_USERDATA_REQUEST, tg, S7_READ_SZL, ..])
+            if tg & 0x0f == S7_GROUP_CPU =>
+        {
+            event.set("function", "read_szl");
+            s7_read_szl(pdu, config, &mut event)
+        }
+        (S7_USERDATA, [0x00, 0x01, 0x12, _, _, tg, sub, ..]) => {
+            event.set("group", tg & 0x0f);
+            event.set("subfunction", *sub);
+            info!(
+                "S7 user data not handled: group {} subfunction {}",
+                tg & 0x0f,
+                sub
+            );
+            None
+        }
+        _ => {
+            info!("S7 PDU not handled (type {})", pdu.rosctr);
+            None
+        }
+    };
+    event.log();
+    repl
+}
+
+/* COTP Connection Request: the calling and called TSAP are confirmed
+ * (the called TSAP gives the rack and slot of the targeted CPU) */
+fn s7_connect(tpdu: &[u8], client_info: &ClientInfo) -> Option<Vec<u8>> {
+    let request = x224_parse_cr(tpdu)?;
+    let parameters = x224_parameters(request.variable)?;
+    let mut event = Event::new("s7_connect", client_info);
+    event.set("class", request.class);
+    let mut dst_tsap = String::new();
+    let mut variable = Vec::new();
+    for (code, value) in parameters.iter() {
+        let hex = value
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        match (*code, value) {
+            (COTP_TPDU_SIZE, [size]) => {
+                event.set("tpdu_size", 1u32 << std::cmp::min(*size, 16));
+            }
+            (COTP_SRC_TSAP, _) => event.set("src_tsap", hex),
+            (COTP_DST_TSAP, _) => {
+                /* the second byte gives the rack and slot */
+                if let [_, rack_slot] = value {
+                    event.set("rack", rack_slot >> 5);
+                    event.set("slot", rack_slot & 0x1f);
+                }
+                event.set("dst_tsap", hex.clone());
+                dst_tsap = hex;
+            }
+            _ => continue,
+        }
+        variable.push(*code);
+        variable.push(value.len() as u8);
+        variable.extend_from_slice(value);
+    }
+    warn!("S7 COTP connection request to TSAP {}", dst_tsap);
+    event.log();
+    Some(tpkt(&x224_cc(request.src_ref, S7_COTP_REF, &variable)))
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving S7 data");
+    let config = &masscanned.config.s7;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::S7(s)) => s,
+        _ => S7State::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while let Some((tpdu, len)) = tpkt_parse(&state.buffer) {
+        let tpdu = tpdu.to_vec();
+        state.buffer.drain(..len);
+        let repl = if state.connected {
+            match x224_parse_dt(&tpdu).and_then(s7_parse) {
+                Some(pdu) => s7_answer(&pdu, config, client_info),
+                None => {
+                    info!("S7 data not handled (invalid data TPDU)");
+                    client_info.close = true;
+                    None
+                }
+            }
+        } else {
+            let repl = s7_connect(&tpdu, client_info);
+            if repl.is_none() {
+                info!("S7 data not handled (invalid Connection Request)");
+                client_info.close = true;
+            }
+            state.connected = true;
+            repl
+        };
+        if let Some(r) = repl {
+            repl_data.extend(r);
+        }
+        if client_info.close {
+            break;
+        }
+    }
+    /* not a TPKT, or too large */
+    let invalid = match state.buffer[..] {
+        [3, 0, l0, l1, ..] => u16::from_be_bytes([l0, l1]) < 7,
+        [3, 0] | [3] | [] => false,
+        _ => true,
+    };
+    if !client_info.close && (invalid || state.buffer.len() > S7_MAX_BUFFER) {
+        info!("S7 data not handled (invalid TPKT)");
+        client_info.close = true;
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::S7(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending S7 data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::tcb::TCPControlBlock;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    /* plcscan: COTP Connection Request to rack 0, slot 2 */
+    const PLCSCAN_CR: &[u8] =
+        b"\x03\x00\x00\x16\x11\xe0\x00\x00\x00\x01\x00\xc1\x02\x01\x00\xc2\x02\x01\x02\xc0\x01\x09";
+    /* setup communication, PDU size 480 */
+    const SETUP: &[u8] = b"\x03\x00\x00\x19\x02\xf0\x80\x32\x01\x00\x00\x00\x00\x00\x08\x00\x00\xf0\x00\x00\x01\x00\x01\x01\xe0";
+
+    /* nmap s7-info: read SZL (id, index) */
+    fn read_szl(szl_id: u16, szl_index: u16) -> Vec<u8> {
+        let mut pdu =
+            b"\x03\x00\x00\x21\x02\xf0\x80\x32\x07\x00\x00\x00\x00\x00\x08\x00\x08\x00\x01\x12\x04\x11\x44\x01\x00\xff\x09\x00\x04"
+                .to_vec();
+        pdu.extend_from_slice(&szl_id.to_be_bytes());
+        pdu.extend_from_slice(&szl_index.to_be_bytes());
+        pdu
+    }
+
+    /* string of a fixed-size field */
+    fn field(data: &[u8], offset: usize, len: usize) -> String {
+        String::from_utf8_lossy(&data[offset..offset + len])
+            .trim_end_matches(&[' ', '\0'][..])
+            .to_string()
+    }
+
+    #[test]
+    fn test_s7_config() {
+        assert!(S7Config::default().check().is_ok());
+        assert!(s7_version("3.2.6") == Some([3, 2, 6]));
+        for version in ["3.2", "3.2.6.1", "3.x.6", "3.256.6"].iter() {
+            assert!(s7_version(version).is_none());
+        }
+        assert!(Config::from_str("[s7]\nversion = \"2.6\"\n").is_err());
+        assert!(Config::from_str("[s7]\nmodule = \"6ES7 315-2EH14-0AB0 V3.2\"\n").is_err());
+        assert!(Config::from_str("[s7]\nplant_id = \"plant\"\n").is_ok());
+    }
+
+    #[test]
+    fn test_s7_connect() {
+        let client_info = ClientInfo::new();
+        let repl_data = s7_connect(tpkt_parse(PLCSCAN_CR).unwrap().0, &client_info).unwrap();
+        /* parameters are confirmed, DST-REF is the SRC-REF of the request */
+        assert!(
+            repl_data
+                == b"\x03\x00\x00\x16\x11\xd0\x00\x01\x44\x31\x00\xc1\x02\x01\x00\xc2\x02\x01\x02\xc0\x01\x09"
+        );
+        /* not a Connection Request */
+        assert!(s7_connect(&SETUP[4..], &client_info).is_none());
+    }
+
+    #[test]
+    fn test_s7_parse() {
+        let request = read_szl(0x11, 1);
+        let pdu = s7_parse(x224_parse_dt(&request[4..]).unwrap()).unwrap();
+        assert!(pdu.rosctr == S7_USERDATA);
+        assert!(pdu.params.len() == 8 && pdu.data.len() == 8);
+        /* acknowledgements have an error class and code */
+        let ack = s7_pdu(S7_ACK, 0x1234, Some(0x8104), &[1], &[2, 3]);
+        let pdu = s7_parse(x224_parse_dt(tpkt_parse(&ack).unwrap().0).unwrap()).unwrap();
+        assert!(pdu.rosctr == S7_ACK && pdu.pdu_ref == 0x1234);
+        assert!(pdu.params == [1] && pdu.data == [2, 3]);
+        /* truncated */
+        assert!(s7_parse(&ack[7..ack.len() - 1]).is_none());
+        assert!(s7_parse(b"\x72\x01\x00\x00\x00\x00\x00\x00\x00\x00").is_none());
+    }
+
+    #[test]
+    fn test_s7_repl() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(102);
+        let mut tcb = TCPControlBlock::new();
+        let repl_data =
+            crate::proto::dispatch(PLCSCAN_CR, &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(repl_data[5] == crate::proto::tpkt::X224_CC);
+        /* setup communication, in two segments */
+        assert!(crate::proto::dispatch(
+            &SETUP[..10],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl_data =
+            crate::proto::dispatch(&SETUP[10..], &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(repl_data == b"\x03\x00\x00\x1b\x02\xf0\x80\x32\x03\x00\x00\x00\x00\x00\x08\x00\x00\x00\x00\xf0\x00\x00\x01\x00\x01\x01\xe0");
+        /* module identification, at the offsets used by nmap */
+        let repl_data = crate::proto::dispatch(
+            &read_szl(S7_SZL_MODULE_ID, 1),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data[7] == S7_PROTOCOL_ID && repl_data[8] == S7_USERDATA);
+        assert!(repl_data[29] == 0xff);
+        assert!(field(&repl_data, 43, 20) == "6ES7 315-2EH14-0AB0");
+        assert!(field(&repl_data, 71, 20) == "6ES7 315-2EH14-0AB0");
+        assert!(repl_data[121..125] == [b'V', 3, 2, 6]);
+        assert!(repl_data.len() == 125);
+        /* component identification */
+        let repl_data = crate::proto::dispatch(
+            &read_szl(S7_SZL_COMPONENT_ID, 1),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(field(&repl_data, 43, 32) == "SIMATIC 300(1)");
+        assert!(field(&repl_data, 77, 32) == "CPU 315-2 PN/DP");
+        assert!(field(&repl_data, 111, 32).is_empty());
+        assert!(field(&repl_data, 145, 32) == "Original Siemens Equipment");
+        assert!(field(&repl_data, 179, 32) == "S C-C2UR28922012");
+        assert!(repl_data.len() == 41 + 6 * 34);
+        /* other lists do not exist */
+        let repl_data = crate::proto::dispatch(
+            &read_szl(0x0424, 0),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data[27..29] == [0xd4, 0x01]);
+        assert!(repl_data[29..] == [0x0a, 0, 0, 0]);
+        assert!(!client_info.close);
+        /* other jobs (here, PLC stop) are refused */
+        let repl_data = crate::proto::dispatch(
+            b"\x03\x00\x00\x21\x02\xf0\x80\x32\x01\x00\x00\x00\x01\x00\x10\x00\x00\x29\x00\x00\x00\x00\x00\x09P_PROGRAM",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data[8] == S7_ACK && repl_data[17..19] == [0x81, 0x04]);
+        /* not a TPKT */
+        assert!(crate::proto::dispatch(
+            b"GET / HTTP/1.1\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_s7_rdp() {
+        /* X.224 Connection Requests on other ports are RDP */
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(3389);
+        let mut tcb = TCPControlBlock::new();
+        crate::proto::dispatch(PLCSCAN_CR, &masscanned, &mut client_info, Some(&mut tcb));
+        assert!(!matches!(tcb.proto_state, ProtoState::S7(_)));
+        client_info.port.dst = Some(102);
+        let mut tcb = TCPControlBlock::new();
+        crate::proto::dispatch(PLCSCAN_CR, &masscanned, &mut client_info, Some(&mut tcb));
+        assert!(matches!(tcb.proto_state, ProtoState::S7(_)));
+    }
+}
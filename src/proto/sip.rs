@@ -0,0 +1,493 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::net::IpAddr;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::Masscanned;
+
+/* RFC 3261 section 7.3.3 */
+const SIP_COMPACT_HEADERS: [(&str, &str); 10] = [
+    ("v", "Via"),
+    ("f", "From"),
+    ("t", "To"),
+    ("i", "Call-ID"),
+    ("m", "Contact"),
+    ("l", "Content-Length"),
+    ("c", "Content-Type"),
+    ("k", "Supported"),
+    ("s", "Subject"),
+    ("e", "Content-Encoding"),
+];
+
+/* SIP registrar (over UDP, on the ports): OPTIONS get a 200 answer,
+ * REGISTER requests a Digest challenge (the credentials of the retried
+ * requests are logged), other methods a 501 answer.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SipConfig {
+    pub ports: Vec<u16>,
+    pub server: String,
+    pub realm: String,
+    pub allow: String,
+}
+
+impl Default for SipConfig {
+    fn default() -> Self {
+        SipConfig {
+            ports: vec![5060],
+            server: "Asterisk PBX 18.10.0".to_string(),
+            realm: "asterisk".to_string(),
+            allow: "OPTIONS, REGISTER, SUBSCRIBE, NOTIFY, PUBLISH, INVITE, ACK, BYE, CANCEL, UPDATE, PRACK, INFO, MESSAGE, REFER"
+                .to_string(),
+        }
+    }
+}
+
+struct SipRequest {
+    method: String,
+    uri: String,
+    /* full names (compact forms expanded), in order */
+    headers: Vec<(String, String)>,
+}
+
+impl SipRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(h, _)| h.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn headers<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers
+            .iter()
+            .filter(move |(h, _)| h.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn sip_header_name(name: &str) -> String {
+    for (compact, full) in SIP_COMPACT_HEADERS.iter() {
+        if name.eq_ignore_ascii_case(compact) {
+            return full.to_string();
+        }
+    }
+    name.to_string()
+}
+
+/* split a header value on commas, outside of quoted strings and <> */
+fn sip_split(value: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut quoted, mut angle, mut escaped) = (false, false, false);
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '<' if !quoted => angle = true,
+            '>' if !quoted => angle = false,
+            ',' if !quoted && !angle => {
+                parts.push(value[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(value[start..].trim());
+    parts.retain(|p| !p.is_empty());
+    parts
+}
+
+/* RFC 3261 section 7 - request line and headers (the body is ignored) */
+fn sip_parse(data: &[u8]) -> Option<SipRequest> {
+    let text = std::str::from_utf8(data).ok()?;
+    let head = match text.find("\r\n\r\n") {
+        Some(i) => &text[..i],
+        None => text.trim_end_matches(['\r', '\n']),
+    };
+    let mut lines = head.split('\n').map(|l| l.trim_end_matches('\r'));
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?;
+    let uri = request_line.next()?;
+    if request_line.next()? != "SIP/2.0"
+        || request_line.next().is_some()
+        || method.is_empty()
+        || !method.bytes().all(|c| c.is_ascii_uppercase())
+    {
+        return None;
+    }
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in lines {
+        if line.starts_with([' ', '\t']) {
+            /* folded header */
+            let (_, value) = headers.last_mut()?;
+            value.push(' ');
+            value.push_str(line.trim());
+            continue;
+        }
+        let (name, value) = line.split_once(':')?;
+        let name = sip_header_name(name.trim());
+        if name.is_empty() {
+            return None;
+        }
+        /* headers with several values are split (Via) */
+        if name == "Via" {
+            for via in sip_split(value) {
+                headers.push((name.clone(), via.to_string()));
+            }
+        } else {
+            headers.push((name, value.trim().to_string()));
+        }
+    }
+    Some(SipRequest {
+        method: method.to_string(),
+        uri: uri.to_string(),
+        headers,
+    })
+}
+
+/* RFC 3261 section 18.2.1, RFC 3581: received and rport parameters of
+ * the topmost Via */
+fn sip_via_received(via: &str, client_info: &ClientInfo) -> String {
+    let ip = match client_info.ip.src {
+        Some(ip) => ip,
+        None => return via.to_string(),
+    };
+    let mut params: Vec<&str> = via.split(';').map(|p| p.trim()).collect();
+    /* sent-by: host[:port], IPv6 addresses between brackets */
+    let sent_by = params[0].split_whitespace().nth(1).unwrap_or("");
+    let host = match sent_by.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or(""),
+        None => sent_by.split(':').next().unwrap_or(""),
+    };
+    let rport = params.iter().any(|p| p.eq_ignore_ascii_case("rport"));
+    let mut via = params.remove(0).to_string();
+    for param in params.iter() {
+        let name = param.split('=').next().unwrap_or("");
+        if name.eq_ignore_ascii_case("received") || name.eq_ignore_ascii_case("rport") {
+            continue;
+        }
+        via.push(';');
+        via.push_str(param);
+    }
+    if rport || host.parse::<IpAddr>().ok() != Some(ip) {
+        via.push_str(&format!(";received={}", ip));
+    }
+    if rport {
+        if let Some(port) = client_info.port.src {
+            via.push_str(&format!(";rport={}", port));
+        }
+    }
+    via
+}
+
+/* parameters of Digest credentials (RFC 3261 section 22.4, RFC 2617) */
+fn sip_digest(value: &str) -> Option<Vec<(String, String)>> {
+    let (scheme, params) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Digest") {
+        return None;
+    }
+    Some(
+        sip_split(params)
+            .iter()
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| {
+                let v = v.trim();
+                let v = v
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(v);
+                (k.trim().to_ascii_lowercase(), v.replace("\\\"", "\""))
+            })
+            .collect(),
+    )
+}
+
+/* record Digest credentials (also used by RTSP), returns false for
+ * other schemes */
+pub(crate) fn digest_auth_event(event: &mut Event, value: &str) -> bool {
+    let params = match sip_digest(value) {
+        Some(p) => p,
+        None => return false,
+    };
+    event.set("auth_scheme", "Digest");
+    for (k, v) in params.iter() {
+        if let "username" | "realm" | "nonce" | "uri" | "response" | "algorithm" | "qop" | "nc"
+        | "cnonce" | "opaque" = k.as_str()
+        {
+            event.set(&format!("auth_{}", k), v.clone());
+        }
+    }
+    true
+}
+
+fn sip_auth_event(value: &str, request: &SipRequest, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("sip_auth", client_info);
+    event.set("method", request.method.clone());
+    if !digest_auth_event(&mut event, value) {
+        event.set("auth_data", value.to_string());
+    }
+    event
+}
+
+fn sip_event(request: &SipRequest, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("sip_request", client_info);
+    event.set("method", request.method.clone());
+    event.set("uri", request.uri.clone());
+    for (field, header) in [
+        ("from", "From"),
+        ("to", "To"),
+        ("call_id", "Call-ID"),
+        ("contact", "Contact"),
+        ("user_agent", "User-Agent"),
+    ]
+    .iter()
+    {
+        if let Some(v) = request.header(header) {
+            event.set(field, v.to_string());
+        }
+    }
+    event
+}
+
+fn sip_response(
+    request: &SipRequest,
+    status: &str,
+    extra: &[String],
+    config: &SipConfig,
+    client_info: &ClientInfo,
+) -> Vec<u8> {
+    let mut repl = format!("SIP/2.0 {}\r\n", status);
+    for (i, via) in request.headers("Via").enumerate() {
+        let via = if i == 0 {
+            sip_via_received(via, client_info)
+        } else {
+            via.to_string()
+        };
+        repl.push_str(&format!("Via: {}\r\n", via));
+    }
+    for header in ["From", "To", "Call-ID", "CSeq"].iter() {
+        let mut value = request.header(header).unwrap_or("").to_string();
+        /* RFC 3261 section 8.2.6.2 */
+        if *header == "To" && !value.to_ascii_lowercase().contains(";tag=") {
+            value.push_str(&format!(";tag={:08x}", rand::random::<u32>()));
+        }
+        repl.push_str(&format!("{}: {}\r\n", header, value));
+    }
+    repl.push_str(&format!("Server: {}\r\n", config.server));
+    for header in extra.iter() {
+        repl.push_str(header);
+        repl.push_str("\r\n");
+    }
+    repl.push_str("Content-Length: 0\r\n\r\n");
+    repl.into_bytes()
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving SIP data");
+    let config = &masscanned.config.sip;
+    let request = match sip_parse(data) {
+        Some(r) => r,
+        None => {
+            info!("SIP data not handled (invalid request)");
+            return None;
+        }
+    };
+    sip_event(&request, client_info).log();
+    /* no answer can be sent without these */
+    if ["Via", "From", "To", "Call-ID", "CSeq"]
+        .iter()
+        .any(|h| request.header(h).is_none())
+    {
+        info!("SIP {} without mandatory headers", request.method);
+        return None;
+    }
+    let allow = format!("Allow: {}", config.allow);
+    let repl = match request.method.as_str() {
+        /* RFC 3261 section 17.2.1: never answered */
+        "ACK" => return None,
+        "OPTIONS" => sip_response(
+            &request,
+            "200 OK",
+            &[allow, "Accept: application/sdp".to_string()],
+            config,
+            client_info,
+        ),
+        "REGISTER" => {
+            for header in ["Authorization", "Proxy-Authorization"].iter() {
+                if let Some(value) = request.header(header) {
+                    warn!("SIP {} with credentials: {}", request.method, value);
+                    sip_auth_event(value, &request, client_info).log();
+                }
+            }
+            let challenge = format!(
+                "WWW-Authenticate: Digest algorithm=MD5, realm=\"{}\", nonce=\"{:016x}\"",
+                config.realm.replace('"', "\\\""),
+                rand::random::<u64>()
+            );
+            sip_response(
+                &request,
+                "401 Unauthorized",
+                &[allow, challenge],
+                config,
+                client_info,
+            )
+        }
+        _ => sip_response(
+            &request,
+            "501 Not Implemented",
+            &[allow],
+            config,
+            client_info,
+        ),
+    };
+    debug!("sending SIP data");
+    Some(repl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::{client_info, masscanned, SERVER_IP};
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    /* OPTIONS sent by sipvicious (svmap), from behind a NAT */
+    const SVMAP_OPTIONS: &[u8] = b"OPTIONS sip:100@198.51.100.7 SIP/2.0\r\nVia: SIP/2.0/UDP 10.0.0.5:5061;branch=z9hG4bK-3980736355;rport\r\nContent-Length: 0\r\nFrom: \"sipvicious\"<sip:100@1.1.1.1>;tag=6135376330356661313363340131393536303734393233\r\nAccept: application/sdp\r\nUser-Agent: friendly-scanner\r\nTo: \"sipvicious\"<sip:100@1.1.1.1>\r\nContact: sip:100@1.1.1.1\r\nCSeq: 1 OPTIONS\r\nCall-ID: 759657640882885186605725\r\nMax-Forwards: 70\r\n\r\n";
+
+    /* REGISTER retried by svcrack with Digest credentials */
+    const SVCRACK_REGISTER: &[u8] = b"REGISTER sip:198.51.100.7 SIP/2.0\r\nVia: SIP/2.0/UDP 192.0.2.1:5061;branch=z9hG4bK-2603451691;rport\r\nContent-Length: 0\r\nFrom: \"100\"<sip:100@198.51.100.7>;tag=3130300131343133343335343638\r\nAccept: application/sdp\r\nUser-Agent: friendly-scanner\r\nTo: \"100\"<sip:100@198.51.100.7>\r\nContact: sip:123@1.1.1.1\r\nCSeq: 2 REGISTER\r\nCall-ID: 1820757216\r\nMax-Forwards: 70\r\nAuthorization: Digest username=\"100\",realm=\"asterisk\",nonce=\"0123456789abcdef\",uri=\"sip:198.51.100.7\",response=\"6a3f7d1fa8d2a8ab7c3c26e4a4e7b1c0\",algorithm=MD5\r\n\r\n";
+
+    fn headers(repl: &[u8]) -> Vec<String> {
+        String::from_utf8(repl.to_vec())
+            .unwrap()
+            .split("\r\n")
+            .map(|l| l.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_sip_options() {
+        let masscanned = masscanned(Config::default());
+        let repl_data = repl(
+            SVMAP_OPTIONS,
+            &masscanned,
+            &mut client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 5060),
+        )
+        .unwrap();
+        let lines = headers(&repl_data);
+        assert!(lines[0] == "SIP/2.0 200 OK");
+        assert!(lines[1] == "Via: SIP/2.0/UDP 10.0.0.5:5061;branch=z9hG4bK-3980736355;received=192.0.2.1;rport=40000");
+        assert!(lines[2] == "From: \"sipvicious\"<sip:100@1.1.1.1>;tag=6135376330356661313363340131393536303734393233");
+        assert!(lines[3].starts_with("To: \"sipvicious\"<sip:100@1.1.1.1>;tag="));
+        assert!(lines[4] == "Call-ID: 759657640882885186605725");
+        assert!(lines[5] == "CSeq: 1 OPTIONS");
+        assert!(lines.contains(&format!("Allow: {}", masscanned.config.sip.allow)));
+        assert!(repl_data.ends_with(b"\r\nContent-Length: 0\r\n\r\n"));
+        /* compact forms, several Via, no rport, folded header */
+        let data = b"OPTIONS sip:198.51.100.7 SIP/2.0\r\nv: SIP/2.0/UDP 192.0.2.1:5061;branch=z9hG4bK-1\r\nv: SIP/2.0/UDP 10.1.1.1;branch=z9hG4bK-2, SIP/2.0/TCP [2001:db8::1]:5060;branch=z9hG4bK-3\r\nf: <sip:a@b>;tag=1\r\nt: <sip:a@b>;tag=2\r\ni: abc\r\nCSeq: 7\r\n  OPTIONS\r\n\r\n";
+        let lines = headers(
+            &repl(
+                data,
+                &masscanned,
+                &mut client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 5060),
+            )
+            .unwrap(),
+        );
+        assert!(
+            lines[1..8]
+                == [
+                    "Via: SIP/2.0/UDP 192.0.2.1:5061;branch=z9hG4bK-1",
+                    "Via: SIP/2.0/UDP 10.1.1.1;branch=z9hG4bK-2",
+                    "Via: SIP/2.0/TCP [2001:db8::1]:5060;branch=z9hG4bK-3",
+                    "From: <sip:a@b>;tag=1",
+                    "To: <sip:a@b>;tag=2",
+                    "Call-ID: abc",
+                    "CSeq: 7 OPTIONS",
+                ]
+        );
+    }
+
+    #[test]
+    fn test_sip_register() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 5060);
+        let register = SVCRACK_REGISTER
+            .split(|c| *c == b'\n')
+            .filter(|l| !l.starts_with(b"Authorization"))
+            .collect::<Vec<&[u8]>>()
+            .join(&b'\n');
+        let lines = headers(&repl(&register, &masscanned, &mut client_info).unwrap());
+        assert!(lines[0] == "SIP/2.0 401 Unauthorized");
+        assert!(lines[1] == "Via: SIP/2.0/UDP 192.0.2.1:5061;branch=z9hG4bK-2603451691;received=192.0.2.1;rport=40000");
+        assert!(lines.iter().any(|l| l
+            .starts_with("WWW-Authenticate: Digest algorithm=MD5, realm=\"asterisk\", nonce=\"")));
+        /* retry with credentials: challenged again */
+        let lines = headers(&repl(SVCRACK_REGISTER, &masscanned, &mut client_info).unwrap());
+        assert!(lines[0] == "SIP/2.0 401 Unauthorized");
+        let request = sip_parse(SVCRACK_REGISTER).unwrap();
+        let event = sip_auth_event(
+            request.header("Authorization").unwrap(),
+            &request,
+            &client_info,
+        );
+        assert!(event.fields["auth_scheme"] == "Digest");
+        assert!(event.fields["auth_username"] == "100");
+        assert!(event.fields["auth_realm"] == "asterisk");
+        assert!(event.fields["auth_nonce"] == "0123456789abcdef");
+        assert!(event.fields["auth_uri"] == "sip:198.51.100.7");
+        assert!(event.fields["auth_response"] == "6a3f7d1fa8d2a8ab7c3c26e4a4e7b1c0");
+        assert!(event.fields["auth_algorithm"] == "MD5");
+        /* other schemes */
+        let event = sip_auth_event("Basic YTpi", &request, &client_info);
+        assert!(event.fields["auth_data"] == "Basic YTpi");
+    }
+
+    #[test]
+    fn test_sip_other() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 5060);
+        let invite = String::from_utf8(SVMAP_OPTIONS.to_vec())
+            .unwrap()
+            .replace("OPTIONS", "INVITE");
+        let lines = headers(&repl(invite.as_bytes(), &masscanned, &mut client_info).unwrap());
+        assert!(lines[0] == "SIP/2.0 501 Not Implemented");
+        assert!(lines[5] == "CSeq: 1 INVITE");
+        /* ACK is never answered */
+        let ack = invite.replace("INVITE", "ACK");
+        assert!(repl(ack.as_bytes(), &masscanned, &mut client_info).is_none());
+        /* invalid requests, missing headers */
+        for data in [
+            &b"OPTIONS sip:a@b SIP/1.0\r\n\r\n"[..],
+            b"options sip:a@b SIP/2.0\r\n\r\n",
+            b"GET / HTTP/1.1\r\n\r\n",
+            b"OPTIONS sip:a@b SIP/2.0\r\nVia SIP/2.0/UDP a\r\n\r\n",
+            b"OPTIONS sip:a@b SIP/2.0\r\nVia: SIP/2.0/UDP a\r\nCSeq: 1 OPTIONS\r\n\r\n",
+            b"\xff\xfe",
+        ]
+        .iter()
+        {
+            assert!(repl(data, &masscanned, &mut client_info).is_none());
+        }
+    }
+}
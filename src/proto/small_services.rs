@@ -0,0 +1,348 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::Utc;
+use log::*;
+use pnet::packet::ip::IpNextHeaderProtocols;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::TCPControlBlock;
+use crate::Masscanned;
+
+/* RFC 864: lines of 72 characters (and CR LF) taken from the 95
+ * printable ASCII characters, each line starting one character after
+ * the previous one */
+const CHARGEN_LINE_LEN: usize = 72;
+const CHARGEN_CHARS: usize = 95;
+
+/* RFC 868: seconds between 1900-01-01 and 1970-01-01 */
+const TIME_EPOCH_OFFSET: u64 = 2_208_988_800;
+
+/* maximum number of bytes of the data received written in events */
+const SMALL_SERVICES_LOG_MAX: usize = 256;
+
+/* Classic inetd services (echo, discard, daytime, chargen and time),
+ * over TCP and UDP: each one can be disabled, or moved to other ports.
+ * Over TCP, daytime, chargen and time speak first (daytime and time then
+ * close the connection); chargen sends chargen_lines lines per segment
+ * received (and per UDP datagram, subject to the UDP amplification
+ * limiter).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmallServicesConfig {
+    pub echo: bool,
+    pub echo_ports: Vec<u16>,
+    pub discard: bool,
+    pub discard_ports: Vec<u16>,
+    pub daytime: bool,
+    pub daytime_ports: Vec<u16>,
+    /* strftime format (in UTC), CR LF is appended */
+    pub daytime_format: String,
+    pub chargen: bool,
+    pub chargen_ports: Vec<u16>,
+    pub chargen_lines: usize,
+    pub time: bool,
+    pub time_ports: Vec<u16>,
+}
+
+impl Default for SmallServicesConfig {
+    fn default() -> Self {
+        SmallServicesConfig {
+            echo: true,
+            echo_ports: vec![7],
+            discard: true,
+            discard_ports: vec![9],
+            daytime: true,
+            daytime_ports: vec![13],
+            /* ctime(3), as inetd does */
+            daytime_format: "%a %b %e %H:%M:%S %Y".to_string(),
+            chargen: true,
+            chargen_ports: vec![19],
+            chargen_lines: 4,
+            time: true,
+            time_ports: vec![37],
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SmallService {
+    Echo,
+    Discard,
+    Daytime,
+    Chargen,
+    Time,
+}
+
+impl SmallService {
+    fn name(self) -> &'static str {
+        match self {
+            SmallService::Echo => "echo",
+            SmallService::Discard => "discard",
+            SmallService::Daytime => "daytime",
+            SmallService::Chargen => "chargen",
+            SmallService::Time => "time",
+        }
+    }
+}
+
+impl SmallServicesConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if StrftimeItems::new(&self.daytime_format).any(|i| i == Item::Error) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "small_services: invalid daytime_format {:?}",
+                    self.daytime_format
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /* service enabled on a port */
+    pub fn service(&self, port: u16) -> Option<SmallService> {
+        [
+            (self.echo, &self.echo_ports, SmallService::Echo),
+            (self.discard, &self.discard_ports, SmallService::Discard),
+            (self.daytime, &self.daytime_ports, SmallService::Daytime),
+            (self.chargen, &self.chargen_ports, SmallService::Chargen),
+            (self.time, &self.time_ports, SmallService::Time),
+        ]
+        .iter()
+        .find(|(enabled, ports, _)| *enabled && ports.contains(&port))
+        .map(|(_, _, service)| *service)
+    }
+
+    /* services that send data as soon as a TCP connection is open */
+    pub fn speaks_first(&self, port: u16) -> bool {
+        matches!(
+            self.service(port),
+            Some(SmallService::Daytime | SmallService::Chargen | SmallService::Time)
+        )
+    }
+}
+
+/* lines of the chargen pattern, from line number start */
+fn chargen_chunk(start: usize, lines: usize) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(lines * (CHARGEN_LINE_LEN + 2));
+    for line in start..start + lines {
+        for i in 0..CHARGEN_LINE_LEN {
+            chunk.push(b' ' + ((line + i) % CHARGEN_CHARS) as u8);
+        }
+        chunk.extend_from_slice(b"\r\n");
+    }
+    chunk
+}
+
+/* RFC 868: seconds since 1900-01-01 00:00 UTC, on 32 bits (this wraps
+ * around in 2036) */
+fn time_value(unix: u64) -> [u8; 4] {
+    ((unix + TIME_EPOCH_OFFSET) as u32).to_be_bytes()
+}
+
+fn answer(
+    service: SmallService,
+    data: &[u8],
+    config: &SmallServicesConfig,
+    sent: usize,
+) -> Option<Vec<u8>> {
+    match service {
+        SmallService::Echo if !data.is_empty() => Some(data.to_vec()),
+        SmallService::Echo | SmallService::Discard => None,
+        SmallService::Daytime => {
+            Some(format!("{}\r\n", Utc::now().format(&config.daytime_format)).into_bytes())
+        }
+        /* over TCP, the pattern goes on where the previous chunk ended */
+        SmallService::Chargen => Some(chargen_chunk(
+            sent / (CHARGEN_LINE_LEN + 2),
+            config.chargen_lines,
+        )),
+        SmallService::Time => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            Some(time_value(now).to_vec())
+        }
+    }
+}
+
+fn small_services_event(service: SmallService, data: &[u8], client_info: &ClientInfo) {
+    let mut event = Event::new("small_service", client_info);
+    event.set("service", service.name());
+    event.set("length", data.len());
+    if !data.is_empty() {
+        let len = std::cmp::min(data.len(), SMALL_SERVICES_LOG_MAX);
+        event.set("data", encode(&data[..len], Encoding::Hex));
+    }
+    event.log();
+}
+
+pub fn greeting(
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: &mut TCPControlBlock,
+) -> Option<Vec<u8>> {
+    let config = &masscanned.config.small_services;
+    let service = config.service(client_info.port.dst?)?;
+    small_services_event(service, &[], client_info);
+    if service != SmallService::Chargen {
+        client_info.close = true;
+    }
+    answer(service, &[], config, tcb.sent as usize)
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving small service data");
+    let config = &masscanned.config.small_services;
+    let service = config.service(client_info.port.dst?)?;
+    small_services_event(service, data, client_info);
+    if client_info.transport == Some(IpNextHeaderProtocols::Udp)
+        && client_info
+            .port
+            .src
+            .is_some_and(|p| config.service(p).is_some())
+    {
+        /* datagrams from another small service (e.g., echo to
+         * chargen, with a spoofed source) would loop forever */
+        info!("small service datagram from a small service port - dropped");
+        return None;
+    }
+    let sent = tcb.as_ref().map_or(0, |t| t.sent as usize);
+    if tcb.is_some() && matches!(service, SmallService::Daytime | SmallService::Time) {
+        client_info.close = true;
+    }
+    answer(service, data, config, sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::udp::UdpPacket;
+    use pnet::packet::Packet;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::str::FromStr;
+
+    fn client(port: u16) -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Udp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.src = Some(40000);
+        client_info.port.dst = Some(port);
+        client_info
+    }
+
+    #[test]
+    fn test_chargen_rotation() {
+        let chunk = chargen_chunk(0, 3);
+        assert!(chunk.len() == 3 * 74);
+        assert!(chunk[..74] == b" !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefg\r\n"[..]);
+        /* each line starts one character further */
+        assert!(chunk[74..76] == *b"!\"" && chunk[148..150] == *b"\"#");
+        assert!(chunk[72..74] == *b"\r\n");
+        /* the pattern wraps around after the 95 printable characters */
+        assert!(chargen_chunk(95, 1) == chargen_chunk(0, 1));
+        let line = chargen_chunk(30, 1);
+        assert!(line[0] == b'>' && line[64] == b'~' && line[65] == b' ');
+        /* over TCP, the next chunk follows the previous one */
+        let config = SmallServicesConfig::default();
+        let first = answer(SmallService::Chargen, &[], &config, 0).unwrap();
+        let next = answer(SmallService::Chargen, b"\r\n", &config, first.len()).unwrap();
+        assert!(next[..74] == chargen_chunk(4, 1)[..]);
+    }
+
+    #[test]
+    fn test_time_epoch() {
+        /* 1970-01-01 00:00 UTC */
+        assert!(time_value(0) == [0x83, 0xaa, 0x7e, 0x80]);
+        /* 2000-01-01 00:00 UTC: 3155673600 */
+        assert!(time_value(946_684_800) == 3_155_673_600u32.to_be_bytes());
+        /* 2036-02-07 06:28:16 UTC: the 32-bit value wraps around */
+        assert!(time_value(2_085_978_496) == [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_small_services_repl() {
+        let mut masscanned = masscanned(Config::default());
+        let mut client_info = client(7);
+        let reply = crate::proto::dispatch(b"hello\n", &masscanned, &mut client_info, None);
+        assert!(reply == Some(b"hello\n".to_vec()));
+        /* discard: nothing */
+        let mut client_info = client(9);
+        assert!(crate::proto::dispatch(b"hello\n", &masscanned, &mut client_info, None).is_none());
+        /* time */
+        let mut client_info = client(37);
+        let reply = crate::proto::dispatch(b"\n", &masscanned, &mut client_info, None).unwrap();
+        assert!(reply.len() == 4);
+        /* daytime, with another format */
+        masscanned.config.small_services.daytime_format = "%Y".to_string();
+        let mut client_info = client(13);
+        let reply = super::repl(b"\n", &masscanned, &mut client_info, None).unwrap();
+        assert!(reply.len() == 6 && reply.ends_with(b"\r\n"));
+        /* no loops between small services */
+        let mut client_info = client(7);
+        client_info.port.src = Some(19);
+        assert!(super::repl(b"hello\n", &masscanned, &mut client_info, None).is_none());
+        /* disabled service */
+        masscanned.config.small_services.echo = false;
+        let mut client_info = client(7);
+        assert!(super::repl(b"hello\n", &masscanned, &mut client_info, None).is_none());
+        assert!(Config::from_str("[small_services]\ndaytime_format = \"%Q\"\n").is_err());
+    }
+
+    #[test]
+    fn test_small_services_udp() {
+        /* empty (or short) requests get an answer with the default
+         * [udp] config */
+        let masscanned = masscanned(Config::default());
+        for (port, data, len) in [
+            (37, &b""[..], Some(4)),
+            (13, b"", None),
+            (13, b"\r\n", None),
+            (19, b"\n", Some(4 * 74)),
+        ]
+        .iter()
+        {
+            /* source port 40000 */
+            let mut packet = vec![0x9c, 0x40, 0, *port as u8, 0, 8 + data.len() as u8, 0, 0];
+            packet.extend_from_slice(data);
+            let udp_req = UdpPacket::new(&packet).unwrap();
+            let mut client_info = client(*port);
+            let udp_repl =
+                crate::layer_4::udp::repl(&udp_req, &masscanned, &mut client_info).unwrap();
+            assert!(udp_repl.get_destination() == 40000 && udp_repl.get_source() == *port);
+            let reply = udp_repl.payload();
+            assert!(len.is_none_or(|l| reply.len() == l));
+            assert!(*port != 13 || reply.ends_with(b"\r\n"));
+        }
+    }
+}
@@ -0,0 +1,989 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use chrono::Utc;
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::dns::dns_labels;
+use crate::proto::nbns::{nbns_decode_name, nbns_split_name};
+use crate::proto::ntlm::{ntlm_challenge, NtlmConfig, NtlmMessage, NTLMSSP_AUTHENTICATE};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* NetBIOS session message (RFC 1002 section 4.3.1) holding an SMB1 or
+ * an SMB2 message */
+pub const SMB1_PATTERN_NEGOTIATE: &[u8; 8] = b"\x00\x00**\xffSMB";
+pub const SMB2_PATTERN_NEGOTIATE: &[u8; 8] = b"\x00\x00**\xfeSMB";
+/* NetBIOS session request (on 139, before any SMB message): the called
+ * name comes first, its length is always 32 */
+pub const NETBIOS_PATTERN_SESSION_REQUEST: &[u8; 5] = b"\x81\x00**\x20";
+
+/* RFC 1002 section 4.3.1 */
+const NETBIOS_SESSION_MESSAGE: u8 = 0x00;
+const NETBIOS_SESSION_REQUEST: u8 = 0x81;
+const NETBIOS_POSITIVE_RESPONSE: u8 = 0x82;
+const NETBIOS_NEGATIVE_RESPONSE: u8 = 0x83;
+const NETBIOS_KEEP_ALIVE: u8 = 0x85;
+/* RFC 1002 section 4.3.4 */
+const NETBIOS_ERROR_UNSPECIFIED: u8 = 0x8f;
+
+const SMB1_COMMAND_NEGOTIATE: u8 = 0x72;
+const SMB1_HEADER_LEN: usize = 32;
+
+const SMB2_HEADER_LEN: usize = 64;
+const SMB2_NEGOTIATE: u16 = 0;
+const SMB2_SESSION_SETUP: u16 = 1;
+const SMB2_FLAGS_SERVER_TO_REDIR: u32 = 0x00000001;
+const SMB2_NEGOTIATE_SIGNING_ENABLED: u16 = 0x0001;
+
+const SMB2_PREAUTH_INTEGRITY_CAPABILITIES: u16 = 1;
+const SMB2_ENCRYPTION_CAPABILITIES: u16 = 2;
+const SMB2_HASH_SHA512: u16 = 1;
+
+const STATUS_SUCCESS: u32 = 0;
+const STATUS_MORE_PROCESSING_REQUIRED: u32 = 0xc0000016;
+const STATUS_LOGON_FAILURE: u32 = 0xc000006d;
+
+/* MS-SMB2 section 2.2.3 */
+const SMB2_DIALECTS: [u16; 5] = [0x0202, 0x0210, 0x0300, 0x0302, 0x0311];
+const SMB2_DIALECT_WILDCARD: u16 = 0x02ff;
+
+/* largest NetBIOS message kept between two segments */
+const SMB_MAX_MESSAGE: usize = 0x20000;
+
+/* What the server advertises in SMB2 NEGOTIATE responses */
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmbConfig {
+    /* highest dialect (e.g., 0x0302 for SMB 3.0.2) - the highest one
+     * offered by the client up to this one is selected */
+    pub dialect: u16,
+    /* e.g., "6ba7b810-9dad-11d1-80b4-00c04fd430c8" (random when not
+     * set) */
+    pub server_guid: Option<String>,
+    pub capabilities: u32,
+    pub max_transact_size: u32,
+    pub max_read_size: u32,
+    pub max_write_size: u32,
+    pub ntlm: NtlmConfig,
+    #[serde(skip)]
+    guid: [u8; 16],
+}
+
+impl Default for SmbConfig {
+    fn default() -> Self {
+        SmbConfig {
+            dialect: 0x0311,
+            server_guid: None,
+            /* DFS, leasing, large MTU, multi-channel, directory leasing */
+            capabilities: 0x0000002f,
+            max_transact_size: 8388608,
+            max_read_size: 8388608,
+            max_write_size: 8388608,
+            ntlm: NtlmConfig::default(),
+            guid: rand::random(),
+        }
+    }
+}
+
+fn smb_invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("smb: {}", msg))
+}
+
+/* GUID in its text form: the first three fields are little-endian
+ * (MS-DTYP section 2.3.4) */
+fn smb_parse_guid(s: &str) -> Option<[u8; 16]> {
+    let fields: Vec<&str> = s.split('-').collect();
+    if fields.len() != 5
+        || [8, 4, 4, 4, 12]
+            .iter()
+            .zip(fields.iter())
+            .any(|(l, f)| f.len() != *l || !f.bytes().all(|c| c.is_ascii_hexdigit()))
+    {
+        return None;
+    }
+    let hex: String = fields.concat();
+    let mut guid = [0u8; 16];
+    for (i, b) in guid.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    guid[0..4].reverse();
+    guid[4..6].reverse();
+    guid[6..8].reverse();
+    Some(guid)
+}
+
+impl SmbConfig {
+    pub fn check(&mut self) -> Result<(), io::Error> {
+        if !SMB2_DIALECTS.contains(&self.dialect) {
+            return Err(smb_invalid(format!(
+                "unsupported dialect: {:#06x}",
+                self.dialect
+            )));
+        }
+        if let Some(guid) = &self.server_guid {
+            self.guid = smb_parse_guid(guid)
+                .ok_or_else(|| smb_invalid(format!("invalid server GUID: {}", guid)))?;
+        }
+        self.ntlm.check()
+    }
+}
+
+/* current time, in 100ns intervals since January 1, 1601 */
+pub fn filetime_now() -> u64 {
+    let now = Utc::now();
+    (now.timestamp() + 11644473600) as u64 * 10_000_000 + now.timestamp_subsec_nanos() as u64 / 100
+}
+
+pub(crate) fn le16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+pub(crate) fn le32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+pub(crate) fn le64(data: &[u8], offset: usize) -> Option<u64> {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(data.get(offset..offset + 8)?);
+    Some(u64::from_le_bytes(b))
+}
+
+/* DER tag and length (X.690 section 8.1) */
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut v = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        v.push(len as u8);
+    } else if len < 0x100 {
+        v.extend_from_slice(&[0x81, len as u8]);
+    } else {
+        v.push(0x82);
+        v.extend_from_slice(&(len as u16).to_be_bytes());
+    }
+    v.extend_from_slice(content);
+    v
+}
+
+const OID_SPNEGO: &[u8] = b"\x06\x06\x2b\x06\x01\x05\x05\x02";
+const OID_NTLMSSP: &[u8] = b"\x06\x0a\x2b\x06\x01\x04\x01\x82\x37\x02\x02\x0a";
+
+/* RFC 4178: NegTokenInit advertising NTLMSSP */
+fn spnego_init() -> Vec<u8> {
+    let mech_types = der(0xa0, &der(0x30, OID_NTLMSSP));
+    let mut token = OID_SPNEGO.to_vec();
+    token.extend(der(0xa0, &der(0x30, &mech_types)));
+    der(0x60, &token)
+}
+
+/* RFC 4178: NegTokenResp accept-incomplete, with an NTLMSSP token */
+pub(crate) fn spnego_resp(token: &[u8]) -> Vec<u8> {
+    let mut resp = der(0xa0, b"\x0a\x01\x01");
+    resp.extend(der(0xa1, OID_NTLMSSP));
+    resp.extend(der(0xa2, &der(0x04, token)));
+    der(0xa1, &der(0x30, &resp))
+}
+
+struct Smb2Header {
+    command: u16,
+    credits: u16,
+    message_id: u64,
+    process_id: u32,
+    tree_id: u32,
+    session_id: u64,
+}
+
+impl Smb2Header {
+    fn parse(msg: &[u8]) -> Option<Self> {
+        if msg.len() < SMB2_HEADER_LEN || le16(msg, 4)? != SMB2_HEADER_LEN as u16 {
+            return None;
+        }
+        Some(Smb2Header {
+            command: le16(msg, 12)?,
+            credits: le16(msg, 14)?,
+            message_id: le64(msg, 24)?,
+            process_id: le32(msg, 32)?,
+            tree_id: le32(msg, 36)?,
+            session_id: le64(msg, 40)?,
+        })
+    }
+
+    /* header of the response, with a status */
+    fn response(&self, status: u32, session_id: u64) -> Vec<u8> {
+        let mut h = b"\xfeSMB".to_vec();
+        h.extend_from_slice(&(SMB2_HEADER_LEN as u16).to_le_bytes());
+        /* credit charge */
+        h.extend_from_slice(&[0, 0]);
+        h.extend_from_slice(&status.to_le_bytes());
+        h.extend_from_slice(&self.command.to_le_bytes());
+        h.extend_from_slice(&std::cmp::max(self.credits, 1).to_le_bytes());
+        h.extend_from_slice(&SMB2_FLAGS_SERVER_TO_REDIR.to_le_bytes());
+        /* next command */
+        h.extend_from_slice(&[0; 4]);
+        h.extend_from_slice(&self.message_id.to_le_bytes());
+        h.extend_from_slice(&self.process_id.to_le_bytes());
+        h.extend_from_slice(&self.tree_id.to_le_bytes());
+        h.extend_from_slice(&session_id.to_le_bytes());
+        /* signature */
+        h.extend_from_slice(&[0; 16]);
+        h
+    }
+}
+
+fn smb_dialect_name(dialect: u16) -> String {
+    format!("{:#06x}", dialect)
+}
+
+/* NEGOTIATE response (MS-SMB2 section 2.2.4); the client encryption
+ * ciphers (if any) are used for the contexts of SMB 3.1.1 */
+fn smb2_negotiate_response(
+    header: &Smb2Header,
+    dialect: u16,
+    ciphers: Option<&[u16]>,
+    config: &SmbConfig,
+) -> Vec<u8> {
+    let mut contexts = Vec::new();
+    if dialect == 0x0311 {
+        let mut data = 1u16.to_le_bytes().to_vec();
+        data.extend_from_slice(&32u16.to_le_bytes());
+        data.extend_from_slice(&SMB2_HASH_SHA512.to_le_bytes());
+        data.extend_from_slice(&rand::random::<[u8; 32]>());
+        contexts.push((SMB2_PREAUTH_INTEGRITY_CAPABILITIES, data));
+        if let Some(c) = ciphers.and_then(|c| c.first()) {
+            let mut data = 1u16.to_le_bytes().to_vec();
+            data.extend_from_slice(&c.to_le_bytes());
+            contexts.push((SMB2_ENCRYPTION_CAPABILITIES, data));
+        }
+    }
+    /* security buffer, then the contexts (8-byte aligned) */
+    let security = spnego_init();
+    let security_offset = SMB2_HEADER_LEN + 64;
+    let mut buffer = security.clone();
+    let mut contexts_offset = 0;
+    for (context_type, data) in contexts.iter() {
+        while !(security_offset + buffer.len()).is_multiple_of(8) {
+            buffer.push(0);
+        }
+        if contexts_offset == 0 {
+            contexts_offset = security_offset + buffer.len();
+        }
+        buffer.extend_from_slice(&context_type.to_le_bytes());
+        buffer.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        buffer.extend_from_slice(&[0; 4]);
+        buffer.extend_from_slice(data);
+    }
+    let mut msg = header.response(STATUS_SUCCESS, 0);
+    msg.extend_from_slice(&65u16.to_le_bytes());
+    msg.extend_from_slice(&SMB2_NEGOTIATE_SIGNING_ENABLED.to_le_bytes());
+    msg.extend_from_slice(&dialect.to_le_bytes());
+    msg.extend_from_slice(&(contexts.len() as u16).to_le_bytes());
+    msg.extend_from_slice(&config.guid);
+    msg.extend_from_slice(&config.capabilities.to_le_bytes());
+    msg.extend_from_slice(&config.max_transact_size.to_le_bytes());
+    msg.extend_from_slice(&config.max_read_size.to_le_bytes());
+    msg.extend_from_slice(&config.max_write_size.to_le_bytes());
+    msg.extend_from_slice(&filetime_now().to_le_bytes());
+    /* server start time */
+    msg.extend_from_slice(&0u64.to_le_bytes());
+    msg.extend_from_slice(&(security_offset as u16).to_le_bytes());
+    msg.extend_from_slice(&(security.len() as u16).to_le_bytes());
+    msg.extend_from_slice(&(contexts_offset as u32).to_le_bytes());
+    msg.extend(buffer);
+    msg
+}
+
+/* highest dialect offered by the client and supported */
+fn smb2_select_dialect(dialects: &[u16], config: &SmbConfig) -> Option<u16> {
+    dialects
+        .iter()
+        .filter(|d| SMB2_DIALECTS.contains(d) && **d <= config.dialect)
+        .max()
+        .cloned()
+}
+
+/* SMB1 NEGOTIATE listing SMB2 dialects (MS-SMB2 section 3.3.5.3.1) */
+fn smb1_negotiate(
+    msg: &[u8],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    let word_count = *msg.get(SMB1_HEADER_LEN)? as usize;
+    let offset = SMB1_HEADER_LEN + 1 + 2 * word_count;
+    let byte_count = le16(msg, offset)? as usize;
+    let bytes = msg.get(offset + 2..offset + 2 + byte_count)?;
+    let dialects: Vec<String> = bytes
+        .split(|&c| c == 0)
+        .filter(|d| d.first() == Some(&0x02))
+        .map(|d| String::from_utf8_lossy(&d[1..]).to_string())
+        .collect();
+    let mut event = Event::new("smb_negotiate", client_info);
+    event.set("smb_version", 1);
+    event.set("dialects", dialects.clone());
+    event.log();
+    let config = &masscanned.config.smb;
+    let dialect = if dialects.iter().any(|d| d == "SMB 2.???") && config.dialect > 0x0202 {
+        SMB2_DIALECT_WILDCARD
+    } else if dialects.iter().any(|d| d == "SMB 2.002") {
+        0x0202
+    } else {
+        info!("SMB1 negotiate not handled (no SMB2 dialect)");
+        return None;
+    };
+    let header = Smb2Header {
+        command: SMB2_NEGOTIATE,
+        credits: 1,
+        message_id: 0,
+        process_id: 0,
+        tree_id: 0,
+        session_id: 0,
+    };
+    Some(smb2_negotiate_response(&header, dialect, None, config))
+}
+
+fn smb2_negotiate(
+    header: &Smb2Header,
+    msg: &[u8],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    let body = &msg[SMB2_HEADER_LEN..];
+    let count = le16(body, 2)? as usize;
+    let security_mode = le16(body, 4)?;
+    let capabilities = le32(body, 8)?;
+    let client_guid = body.get(12..28)?;
+    let dialects = (0..count)
+        .map(|i| le16(body, 36 + 2 * i))
+        .collect::<Option<Vec<u16>>>()?;
+    /* negotiate contexts (SMB 3.1.1): encryption ciphers */
+    let mut ciphers = None;
+    if dialects.contains(&0x0311) {
+        let mut offset = le32(body, 28)? as usize;
+        for _ in 0..le16(body, 32)? {
+            let context_type = le16(msg, offset)?;
+            let len = le16(msg, offset + 2)? as usize;
+            let data = msg.get(offset + 8..offset + 8 + len)?;
+            if context_type == SMB2_ENCRYPTION_CAPABILITIES {
+                let n = le16(data, 0)? as usize;
+                ciphers = (0..n)
+                    .map(|i| le16(data, 2 + 2 * i))
+                    .collect::<Option<Vec<u16>>>();
+            }
+            offset = (offset + 8 + len + 7) & !7;
+        }
+    }
+    let mut event = Event::new("smb_negotiate", client_info);
+    event.set("smb_version", 2);
+    event.set(
+        "dialects",
+        dialects
+            .iter()
+            .map(|d| smb_dialect_name(*d))
+            .collect::<Vec<String>>(),
+    );
+    event.set("security_mode", security_mode);
+    event.set("capabilities", capabilities);
+    event.set("client_guid", encode(client_guid, Encoding::Hex));
+    event.log();
+    let config = &masscanned.config.smb;
+    let dialect = match smb2_select_dialect(&dialects, config) {
+        Some(d) => d,
+        None => {
+            info!("SMB2 negotiate not handled (no dialect supported)");
+            return None;
+        }
+    };
+    Some(smb2_negotiate_response(
+        header,
+        dialect,
+        ciphers.as_deref(),
+        config,
+    ))
+}
+
+/* SESSION_SETUP: NTLMSSP messages of the security blob are recorded,
+ * the first request gets an NTLM challenge, and the AUTHENTICATE message
+ * (or a request with the session id given) a logon failure.
+ **/
+fn smb2_session_setup(
+    header: &Smb2Header,
+    msg: &[u8],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    let body = &msg[SMB2_HEADER_LEN..];
+    let offset = le16(body, 12)? as usize;
+    let len = le16(body, 14)? as usize;
+    let blob = msg.get(offset..offset + len)?;
+    let ntlm = NtlmMessage::parse(blob);
+    let mut event = Event::new("smb_session_setup", client_info);
+    event.set("session_id", header.session_id);
+    event.set("security_blob_length", blob.len());
+    if let Some(m) = &ntlm {
+        m.set_event(&mut event);
+    }
+    event.log();
+    let authenticate = match &ntlm {
+        Some(m) if m.message_type == NTLMSSP_AUTHENTICATE => {
+            warn!(
+                "SMB NTLM authentication: {}\\{} from {}",
+                m.domain.as_deref().unwrap_or(""),
+                m.user.as_deref().unwrap_or(""),
+                m.workstation.as_deref().unwrap_or("-")
+            );
+            true
+        }
+        _ => false,
+    };
+    if authenticate || header.session_id != 0 {
+        return Some(header.response(STATUS_LOGON_FAILURE, header.session_id));
+    }
+    let config = &masscanned.config.smb;
+    let token = spnego_resp(&ntlm_challenge(
+        &config.ntlm.server_challenge(),
+        &config.ntlm,
+    ));
+    let session_id = rand::random::<u64>() | 1;
+    let mut repl = header.response(STATUS_MORE_PROCESSING_REQUIRED, session_id);
+    repl.extend_from_slice(&9u16.to_le_bytes());
+    /* session flags */
+    repl.extend_from_slice(&[0, 0]);
+    repl.extend_from_slice(&((SMB2_HEADER_LEN + 8) as u16).to_le_bytes());
+    repl.extend_from_slice(&(token.len() as u16).to_le_bytes());
+    repl.extend(token);
+    Some(repl)
+}
+
+fn smb_message(msg: &[u8], masscanned: &Masscanned, client_info: &ClientInfo) -> Option<Vec<u8>> {
+    if msg.starts_with(b"\xffSMB") {
+        if msg.get(4) == Some(&SMB1_COMMAND_NEGOTIATE) {
+            return smb1_negotiate(msg, masscanned, client_info);
+        }
+        info!("SMB1 command not handled: {:?}", msg.get(4));
+        return None;
+    }
+    if !msg.starts_with(b"\xfeSMB") {
+        info!("SMB data not handled");
+        return None;
+    }
+    let header = Smb2Header::parse(msg)?;
+    match header.command {
+        SMB2_NEGOTIATE => smb2_negotiate(&header, msg, masscanned, client_info),
+        SMB2_SESSION_SETUP => smb2_session_setup(&header, msg, masscanned, client_info),
+        c => {
+            info!("SMB2 command not handled: {}", c);
+            None
+        }
+    }
+}
+
+/* name (padding removed) and suffix of a name of a session request,
+ * then its scope, and the offset following it */
+fn netbios_name(data: &[u8], offset: usize) -> Option<(String, u8, String, usize)> {
+    let (labels, end) = dns_labels(data, offset)?;
+    let (name, suffix) = nbns_split_name(&nbns_decode_name(labels.first()?)?);
+    let scope: Vec<String> = labels[1..]
+        .iter()
+        .map(|l| String::from_utf8_lossy(l).to_string())
+        .collect();
+    Some((
+        String::from_utf8_lossy(&name).to_string(),
+        suffix,
+        scope.join("."),
+        end,
+    ))
+}
+
+/* RFC 1002 section 4.3.2: the called name (often the name, or the
+ * address, of the target - *SMBSERVER when unknown) and the calling
+ * name are recorded, and any name is accepted */
+fn netbios_session_request(request: &[u8], client_info: &ClientInfo) -> Vec<u8> {
+    let called = netbios_name(request, 0);
+    let calling = called.as_ref().and_then(|c| netbios_name(request, c.3));
+    let ((called, called_suffix, scope, _), (calling, calling_suffix, _, _)) =
+        match (called, calling) {
+            (Some(called), Some(calling)) => (called, calling),
+            _ => {
+                info!("NetBIOS session request not handled (invalid names)");
+                return vec![
+                    NETBIOS_NEGATIVE_RESPONSE,
+                    0,
+                    0,
+                    1,
+                    NETBIOS_ERROR_UNSPECIFIED,
+                ];
+            }
+        };
+    warn!("NetBIOS session request to {} from {}", called, calling);
+    let mut event = Event::new("netbios_session_request", client_info);
+    event.set("called", called);
+    event.set("called_suffix", called_suffix);
+    event.set("calling", calling);
+    event.set("calling_suffix", calling_suffix);
+    if !scope.is_empty() {
+        event.set("scope", scope);
+    }
+    event.log();
+    vec![NETBIOS_POSITIVE_RESPONSE, 0, 0, 0]
+}
+
+/* messages received on a flow, not complete yet */
+pub struct SmbState {
+    buffer: Vec<u8>,
+}
+
+impl SmbState {
+    pub fn new() -> Self {
+        SmbState { buffer: Vec::new() }
+    }
+}
+
+/* Answer the SMB messages (possibly several, or split across segments)
+ * of a flow, each one in a NetBIOS session message. Session requests
+ * (on 139) get a positive response, keep-alives are ignored.
+ **/
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving SMB data");
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Smb(s)) => s,
+        _ => SmbState::new(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while state.buffer.len() >= 4 {
+        let ptype = state.buffer[0];
+        if ![
+            NETBIOS_SESSION_MESSAGE,
+            NETBIOS_SESSION_REQUEST,
+            NETBIOS_KEEP_ALIVE,
+        ]
+        .contains(&ptype)
+        {
+            info!("NetBIOS message not handled: {}", ptype);
+            state.buffer.clear();
+            break;
+        }
+        /* 17-bit length */
+        let len = (state.buffer[1] as usize) << 16
+            | (state.buffer[2] as usize) << 8
+            | state.buffer[3] as usize;
+        if len > SMB_MAX_MESSAGE {
+            info!("SMB message too large ({} bytes) - dropped", len);
+            state.buffer.clear();
+            break;
+        }
+        if state.buffer.len() < 4 + len {
+            break;
+        }
+        let packet = &state.buffer[4..4 + len];
+        match ptype {
+            NETBIOS_SESSION_REQUEST => {
+                let response = netbios_session_request(packet, client_info);
+                if response[0] == NETBIOS_NEGATIVE_RESPONSE {
+                    client_info.close = true;
+                }
+                repl_data.extend(response);
+            }
+            NETBIOS_KEEP_ALIVE => {}
+            _ => {
+                if let Some(msg) = smb_message(packet, masscanned, client_info) {
+                    repl_data.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+                    repl_data.extend(msg);
+                }
+            }
+        }
+        state.buffer.drain(..4 + len);
+        if client_info.close {
+            break;
+        }
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Smb(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending SMB answer");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::ntlm::{NTLMSSP_CHALLENGE, NTLMSSP_SIGNATURE};
+    use crate::proto::test_util::masscanned;
+    use std::str::FromStr;
+
+    fn netbios(msg: &[u8]) -> Vec<u8> {
+        let mut data = (msg.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(msg);
+        data
+    }
+
+    /* SMB1 NEGOTIATE with a list of dialects */
+    fn smb1_negotiate_request(dialects: &[&str]) -> Vec<u8> {
+        let mut msg = b"\xffSMB\x72\x00\x00\x00\x00\x18\x53\xc8".to_vec();
+        msg.extend_from_slice(&[0; 12]);
+        /* tid, pid, uid, mid */
+        msg.extend_from_slice(b"\xff\xff\xff\xfe\x00\x00\x00\x00");
+        let mut bytes = Vec::new();
+        for d in dialects {
+            bytes.push(2);
+            bytes.extend_from_slice(d.as_bytes());
+            bytes.push(0);
+        }
+        msg.push(0);
+        msg.extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+        msg.extend(bytes);
+        netbios(&msg)
+    }
+
+    fn smb2_header(command: u16, message_id: u64, session_id: u64) -> Vec<u8> {
+        let mut h = b"\xfeSMB\x40\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        h.extend_from_slice(&command.to_le_bytes());
+        h.extend_from_slice(&[31, 0]);
+        h.extend_from_slice(&[0; 8]);
+        h.extend_from_slice(&message_id.to_le_bytes());
+        h.extend_from_slice(b"\xff\xfe\x00\x00\x00\x00\x00\x00");
+        h.extend_from_slice(&session_id.to_le_bytes());
+        h.extend_from_slice(&[0; 16]);
+        h
+    }
+
+    /* SMB2 NEGOTIATE, with the SMB 3.1.1 contexts (preauth integrity,
+     * encryption with AES-128-GCM then AES-128-CCM) if 0x0311 is listed */
+    fn smb2_negotiate_request(dialects: &[u16]) -> Vec<u8> {
+        let mut msg = smb2_header(SMB2_NEGOTIATE, 1, 0);
+        msg.extend_from_slice(&36u16.to_le_bytes());
+        msg.extend_from_slice(&(dialects.len() as u16).to_le_bytes());
+        msg.extend_from_slice(b"\x01\x00\x00\x00\x7f\x00\x00\x00");
+        msg.extend_from_slice(b"0123456789abcdef");
+        let offset = (SMB2_HEADER_LEN + 36 + 2 * dialects.len() + 7) & !7;
+        if dialects.contains(&0x0311) {
+            msg.extend_from_slice(&(offset as u32).to_le_bytes());
+            msg.extend_from_slice(b"\x02\x00\x00\x00");
+        } else {
+            msg.extend_from_slice(&[0; 8]);
+        }
+        for d in dialects {
+            msg.extend_from_slice(&d.to_le_bytes());
+        }
+        if dialects.contains(&0x0311) {
+            msg.resize(offset, 0);
+            msg.extend_from_slice(b"\x01\x00\x26\x00\x00\x00\x00\x00\x01\x00\x20\x00\x01\x00");
+            msg.extend_from_slice(&[0x42; 32]);
+            msg.extend_from_slice(&[0; 2]);
+            msg.extend_from_slice(b"\x02\x00\x06\x00\x00\x00\x00\x00\x02\x00\x02\x00\x01\x00");
+        }
+        netbios(&msg)
+    }
+
+    fn smb2_session_setup_request(session_id: u64, blob: &[u8]) -> Vec<u8> {
+        let mut msg = smb2_header(SMB2_SESSION_SETUP, 2, session_id);
+        msg.extend_from_slice(b"\x19\x00\x00\x01\x01\x00\x00\x00\x00\x00\x00\x00");
+        msg.extend_from_slice(&((SMB2_HEADER_LEN + 24) as u16).to_le_bytes());
+        msg.extend_from_slice(&(blob.len() as u16).to_le_bytes());
+        msg.extend_from_slice(&[0; 8]);
+        msg.extend_from_slice(blob);
+        netbios(&msg)
+    }
+
+    /* SMB2 message of a NetBIOS session message (with its status) */
+    fn smb2_response(data: &[u8]) -> (u32, &[u8]) {
+        let len = u32::from_be_bytes([0, data[1], data[2], data[3]]) as usize;
+        assert!(data.len() == 4 + len);
+        let msg = &data[4..];
+        assert!(msg.starts_with(b"\xfeSMB"));
+        assert!(le32(msg, 16) == Some(SMB2_FLAGS_SERVER_TO_REDIR));
+        (le32(msg, 8).unwrap(), msg)
+    }
+
+    /* dialect and negotiate context types of a NEGOTIATE response */
+    fn smb2_negotiated(data: &[u8]) -> (u16, Vec<u16>) {
+        let (status, msg) = smb2_response(data);
+        assert!(status == STATUS_SUCCESS);
+        let body = &msg[SMB2_HEADER_LEN..];
+        assert!(le16(body, 0) == Some(65));
+        /* security buffer: SPNEGO, advertising NTLMSSP */
+        let offset = le16(body, 56).unwrap() as usize;
+        let len = le16(body, 58).unwrap() as usize;
+        assert!(msg[offset..offset + len] == spnego_init()[..]);
+        assert!(msg[offset..offset + len].ends_with(OID_NTLMSSP));
+        let mut contexts = Vec::new();
+        let mut offset = le32(body, 60).unwrap() as usize;
+        for _ in 0..le16(body, 6).unwrap() {
+            assert!(offset.is_multiple_of(8));
+            contexts.push(le16(msg, offset).unwrap());
+            offset = (offset + 8 + le16(msg, offset + 2).unwrap() as usize + 7) & !7;
+        }
+        (le16(body, 4).unwrap(), contexts)
+    }
+
+    #[test]
+    fn test_smb1_negotiate() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        /* multi-protocol negotiate, with the dialects of smbclient */
+        let req =
+            smb1_negotiate_request(&["NT LANMAN 1.0", "NT LM 0.12", "SMB 2.002", "SMB 2.???"]);
+        let repl_data = repl(&req, &masscanned, &mut client_info, None).unwrap();
+        assert!(smb2_negotiated(&repl_data) == (SMB2_DIALECT_WILDCARD, Vec::new()));
+        let repl_data = crate::proto::repl(&req, &masscanned, &mut client_info).unwrap();
+        assert!(smb2_negotiated(&repl_data).0 == SMB2_DIALECT_WILDCARD);
+        let req = smb1_negotiate_request(&["NT LM 0.12", "SMB 2.002"]);
+        let repl_data = repl(&req, &masscanned, &mut client_info, None).unwrap();
+        assert!(smb2_negotiated(&repl_data).0 == 0x0202);
+        /* SMB1 only, like the first probe of nmap smb-protocols */
+        let req = smb1_negotiate_request(&["NT LM 0.12"]);
+        assert!(repl(&req, &masscanned, &mut client_info, None).is_none());
+    }
+
+    #[test]
+    fn test_smb2_negotiate() {
+        let mut masscanned = masscanned(
+            Config::from_str(
+                "[smb]\nserver_guid = \"6ba7b810-9dad-11d1-80b4-00c04fd430c8\"\ncapabilities = 7\n",
+            )
+            .unwrap(),
+        );
+        let mut client_info = ClientInfo::new();
+        /* one dialect at a time, like nmap smb-protocols */
+        for d in SMB2_DIALECTS.iter() {
+            let req = smb2_negotiate_request(&[*d]);
+            let repl_data = repl(&req, &masscanned, &mut client_info, None).unwrap();
+            let (dialect, contexts) = smb2_negotiated(&repl_data);
+            assert!(dialect == *d);
+            if *d == 0x0311 {
+                assert!(
+                    contexts
+                        == [
+                            SMB2_PREAUTH_INTEGRITY_CAPABILITIES,
+                            SMB2_ENCRYPTION_CAPABILITIES
+                        ]
+                );
+            } else {
+                assert!(contexts.is_empty());
+            }
+            let (_, msg) = smb2_response(&repl_data);
+            /* message id of the request */
+            assert!(le64(msg, 24) == Some(1));
+            assert!(
+                msg[SMB2_HEADER_LEN + 8..SMB2_HEADER_LEN + 24]
+                    == *b"\x10\xb8\xa7\x6b\xad\x9d\xd1\x11\x80\xb4\x00\xc0\x4f\xd4\x30\xc8"
+            );
+            assert!(le32(msg, SMB2_HEADER_LEN + 24) == Some(7));
+            assert!(le32(msg, SMB2_HEADER_LEN + 28) == Some(8388608));
+            /* system time: after 2020 */
+            assert!(le64(msg, SMB2_HEADER_LEN + 40).unwrap() > 132223104000000000);
+        }
+        /* all dialects, like smbclient: the highest one is selected,
+         * up to the configured one */
+        let req = smb2_negotiate_request(&SMB2_DIALECTS);
+        let repl_data = repl(&req, &masscanned, &mut client_info, None).unwrap();
+        assert!(smb2_negotiated(&repl_data).0 == 0x0311);
+        masscanned.config.smb.dialect = 0x0302;
+        let repl_data = repl(&req, &masscanned, &mut client_info, None).unwrap();
+        assert!(smb2_negotiated(&repl_data) == (0x0302, Vec::new()));
+        /* no dialect supported */
+        let req = smb2_negotiate_request(&[0x0311]);
+        assert!(repl(&req, &masscanned, &mut client_info, None).is_none());
+    }
+
+    #[test]
+    fn test_smb2_session_setup() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        /* negotiate and session setup in one segment, split anywhere */
+        let mut data = smb2_negotiate_request(&SMB2_DIALECTS);
+        let negotiate_len = data.len();
+        data.extend(smb2_session_setup_request(
+            0,
+            b"NTLMSSP\x00\x01\x00\x00\x00\x07\x82\x08\xa2",
+        ));
+        let mut repl_data = repl(
+            &data[..negotiate_len + 10],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl(
+            &data[negotiate_len + 10..][..5],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        repl_data.extend(
+            repl(
+                &data[negotiate_len + 15..],
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb),
+            )
+            .unwrap(),
+        );
+        let len = u32::from_be_bytes([0, repl_data[1], repl_data[2], repl_data[3]]) as usize;
+        smb2_negotiated(&repl_data[..4 + len]);
+        let (status, msg) = smb2_response(&repl_data[4 + len..]);
+        assert!(status == STATUS_MORE_PROCESSING_REQUIRED);
+        let session_id = le64(msg, 40).unwrap();
+        assert!(session_id != 0);
+        let body = &msg[SMB2_HEADER_LEN..];
+        assert!(le16(body, 0) == Some(9));
+        let offset = le16(body, 4).unwrap() as usize;
+        let len = le16(body, 6).unwrap() as usize;
+        assert!(offset + len == msg.len());
+        let token = &msg[offset..];
+        /* NegTokenResp, with the NTLMSSP challenge */
+        assert!(token[0] == 0xa1);
+        let ntlm = token
+            .windows(8)
+            .position(|w| w == NTLMSSP_SIGNATURE)
+            .unwrap();
+        assert!(le32(token, ntlm + 8) == Some(NTLMSSP_CHALLENGE));
+        /* authentication */
+        let repl_data = repl(
+            &smb2_session_setup_request(session_id, b"NTLMSSP\x00\x03\x00\x00\x00"),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        let (status, msg) = smb2_response(&repl_data);
+        assert!(status == STATUS_LOGON_FAILURE);
+        assert!(le64(msg, 40) == Some(session_id));
+        /* AUTHENTICATE messages never get a challenge: curl --ntlm
+         * (7.88.1) message, OEM strings */
+        let blob = base64::decode("TlRMTVNTUAADAAAAGAAYAEAAAABaAFoAWAAAAAcABwCyAAAABQAFALkAAAALAAsAvgAAAAAAAAAAAAAABoKIAsEnUJ56C88p7zFbronRIH1zavIaY3QCY+yoLP830F8uin9MiHOfjaEBAQAAAAAAAABwIhOdW90Bc2ryGmN0AmMAAAAAAgASAFcATwBSAEsARwBSAE8AVQBQAAEADABTAEUAUgBWAEUAUgAAAAAAAAAAAFdJREdFVFNhbGljZVdPUktTVEFUSU9O").unwrap();
+        let repl_data = repl(
+            &smb2_session_setup_request(0, &blob),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(smb2_response(&repl_data).0 == STATUS_LOGON_FAILURE);
+    }
+
+    /* smbclient -p 139 //192.0.2.2/share: session request from KALI to
+     * *SMBSERVER */
+    const NETBIOS_SESSION_REQUEST_SMBCLIENT: &[u8] = b"\x81\x00\x00\x44\
+        \x20CKFDENECFDEFFCFGEFFCCACACACACACA\x00\
+        \x20ELEBEMEJCACACACACACACACACACACAAA\x00";
+
+    #[test]
+    fn test_netbios_session() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(pnet::packet::ip::IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(139);
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = crate::proto::dispatch(
+            NETBIOS_SESSION_REQUEST_SMBCLIENT,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"\x82\x00\x00\x00");
+        assert!(tcb.proto_id == crate::proto::PROTO_SMB);
+        /* names: the called one, then the calling one */
+        let (called, suffix, scope, end) =
+            netbios_name(&NETBIOS_SESSION_REQUEST_SMBCLIENT[4..], 0).unwrap();
+        assert!(called == "*SMBSERVER" && suffix == 0x20 && scope.is_empty() && end == 34);
+        let (calling, suffix, _, _) =
+            netbios_name(&NETBIOS_SESSION_REQUEST_SMBCLIENT[4..], end).unwrap();
+        assert!(calling == "KALI" && suffix == 0);
+        /* with a scope, and the target name (nmap) */
+        let (called, suffix, scope, _) = netbios_name(
+            b"\x20EGEJEMEFFDFCFGDADBCACACACACACACA\x04corp\x07example\x00",
+            0,
+        )
+        .unwrap();
+        assert!(called == "FILESRV01" && suffix == 0x20 && scope == "corp.example");
+        /* keep-alive, then the negotiate request in the same segment */
+        let mut data = b"\x85\x00\x00\x00".to_vec();
+        data.extend(smb1_negotiate_request(&["NT LM 0.12", "SMB 2.002"]));
+        let repl_data =
+            crate::proto::dispatch(&data, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(smb2_negotiated(&repl_data).0 == 0x0202);
+        /* a keep-alive alone gets nothing */
+        assert!(crate::proto::dispatch(
+            b"\x85\x00\x00\x00",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        assert!(!client_info.close);
+        /* invalid names: negative response */
+        let repl_data = repl(
+            b"\x81\x00\x00\x04\x20ABC",
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        assert!(repl_data == b"\x83\x00\x00\x01\x8f");
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_smb_config() {
+        assert!(Config::from_str("[smb]\ndialect = 0x0302\n").is_ok());
+        assert!(Config::from_str("[smb]\ndialect = 0x0301\n").is_err());
+        assert!(Config::from_str("[smb]\nserver_guid = \"6ba7b810-9dad-11d1-80b4\"\n").is_err());
+        assert!(Config::from_str(
+            "[smb]\nserver_guid = \"6ba7b810-9dad-11d1-80b4-00c04fd430cg\"\n"
+        )
+        .is_err());
+        let config = Config::from_str("[smb.ntlm]\nchallenge = \"0011223344556677\"\n").unwrap();
+        assert!(config.smb.ntlm.server_challenge() == *b"\x00\x11\x22\x33\x44\x55\x66\x77");
+        assert!(Config::from_str("[smb.ntlm]\nchallenge = \"00112233\"\n").is_err());
+        /* random server GUID by default */
+        assert!(SmbConfig::default().guid != SmbConfig::default().guid);
+    }
+
+    #[test]
+    fn test_der() {
+        assert!(der(0x04, b"ab") == b"\x04\x02ab");
+        assert!(der(0x04, &[0; 200])[..3] == *b"\x04\x81\xc8");
+        assert!(der(0x04, &[0; 300])[..4] == *b"\x04\x82\x01\x2c");
+        /* RFC 4178 NegTokenInit with NTLMSSP */
+        assert!(
+            spnego_init()
+                == b"\x60\x1c\x06\x06\x2b\x06\x01\x05\x05\x02\xa0\x12\x30\x10\xa0\x0e\x30\x0c\
+                     \x06\x0a\x2b\x06\x01\x04\x01\x82\x37\x02\x02\x0a"
+        );
+    }
+}
@@ -0,0 +1,638 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::line::{line_command, sasl_plain, LineBuffer};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+pub const SMTP_PATTERNS_HELLO: [&[u8]; 2] = [b"EHLO ", b"HELO "];
+
+/* consecutive errors before the connection is closed */
+const SMTP_MAX_ERRORS: usize = 10;
+
+/* SMTP service (server-first): the banner is sent (after "220 ") once
+ * the TCP handshake is completed on one of the ports. Authentication
+ * fails unless auth is set. Messages are accepted (and dropped), and the
+ * beginning (at most log_data_max bytes, using log_data_encoding) is
+ * logged.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmtpConfig {
+    pub ports: Vec<u16>,
+    pub banner: String,
+    pub hostname: String,
+    pub auth: bool,
+    pub log_data_max: usize,
+    pub log_data_encoding: Encoding,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        SmtpConfig {
+            ports: vec![25, 587],
+            banner: "mail.example.com ESMTP Postfix".to_string(),
+            hostname: "mail.example.com".to_string(),
+            auth: false,
+            log_data_max: 16384,
+            log_data_encoding: Encoding::Base64,
+        }
+    }
+}
+
+impl SmtpConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for s in [&self.banner, &self.hostname].iter() {
+            if s.contains(['\r', '\n']) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("smtp: invalid line (CR or LF found): {:?}", s),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/* what the next line from the client is */
+#[derive(Default)]
+enum SmtpMode {
+    #[default]
+    Command,
+    /* AUTH PLAIN: credentials */
+    AuthPlain,
+    /* AUTH LOGIN: user name, then password */
+    AuthLoginUser,
+    AuthLoginPassword(String),
+    /* DATA: message, up to a line "." */
+    Data,
+}
+
+/* state of an SMTP flow: partial line, envelope of the message being
+ * sent */
+#[derive(Default)]
+pub struct SmtpState {
+    lines: LineBuffer,
+    mode: SmtpMode,
+    from: Option<String>,
+    rcpt: Vec<String>,
+    data: Vec<u8>,
+    data_len: usize,
+    errors: usize,
+    starttls: bool,
+}
+
+fn smtp_reply(code: u16, text: &str) -> Vec<u8> {
+    format!("{} {}\r\n", code, text).into_bytes()
+}
+
+/* address from a MAIL FROM:<...> or RCPT TO:<...> argument */
+fn smtp_address<'a>(argument: &'a str, prefix: &str) -> Option<&'a str> {
+    if !argument
+        .get(..prefix.len())
+        .is_some_and(|p| p.eq_ignore_ascii_case(prefix))
+    {
+        return None;
+    }
+    let address = argument[prefix.len()..].trim_start();
+    /* parameters (e.g., SIZE=) follow the address */
+    let address = address.split(' ').next().unwrap_or("");
+    Some(address.trim_start_matches('<').trim_end_matches('>'))
+}
+
+fn smtp_decode(data: &str) -> Option<Vec<u8>> {
+    base64::decode(data.trim()).ok()
+}
+
+fn smtp_auth_event(
+    mechanism: &str,
+    authzid: Option<&[u8]>,
+    user: &[u8],
+    password: &[u8],
+    success: bool,
+    client_info: &ClientInfo,
+) -> Event {
+    let user = String::from_utf8_lossy(user);
+    let password = String::from_utf8_lossy(password);
+    warn!("SMTP authentication ({}): {}:{}", mechanism, user, password);
+    let mut event = Event::new("smtp_auth", client_info);
+    event.set("mechanism", mechanism);
+    if let Some(authzid) = authzid.filter(|a| !a.is_empty()) {
+        event.set("authzid", String::from_utf8_lossy(authzid).to_string());
+    }
+    event.set("user", user.to_string());
+    event.set("password", password.to_string());
+    event.set("success", success);
+    event
+}
+
+fn smtp_auth_result(success: bool) -> Vec<u8> {
+    if success {
+        smtp_reply(235, "2.7.0 Authentication successful")
+    } else {
+        smtp_reply(
+            535,
+            "5.7.8 Error: authentication failed: authentication failure",
+        )
+    }
+}
+
+/* AUTH PLAIN credentials (RFC 4616): authzid, user, password */
+fn smtp_auth_plain(
+    data: &str,
+    config: &SmtpConfig,
+    client_info: &ClientInfo,
+) -> (Vec<u8>, Option<Event>) {
+    let creds = match smtp_decode(data) {
+        Some(c) => c,
+        None => return (smtp_reply(501, "5.5.2 Error: invalid base64 data"), None),
+    };
+    let (authzid, user, password) = match sasl_plain(&creds) {
+        Some(c) => c,
+        None => {
+            return (
+                smtp_reply(
+                    535,
+                    "5.7.8 Error: authentication failed: Invalid authentication mechanism",
+                ),
+                None,
+            )
+        }
+    };
+    let event = smtp_auth_event(
+        "PLAIN",
+        Some(authzid),
+        user,
+        password,
+        config.auth,
+        client_info,
+    );
+    (smtp_auth_result(config.auth), Some(event))
+}
+
+/* end of a message: the envelope and the beginning of the message are
+ * logged */
+fn smtp_data_event(state: &mut SmtpState, config: &SmtpConfig, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("smtp_data", client_info);
+    event.set("from", state.from.take().unwrap_or_default());
+    event.set("rcpt", std::mem::take(&mut state.rcpt));
+    event.set("length", state.data_len);
+    event.set("data", encode(&state.data, config.log_data_encoding));
+    state.data.clear();
+    state.data_len = 0;
+    event
+}
+
+/* answer to a line, and the event to log (if any) */
+fn smtp_line(
+    line: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    state: &mut SmtpState,
+) -> (Vec<u8>, Option<Event>) {
+    let config = &masscanned.config.smtp;
+    match std::mem::take(&mut state.mode) {
+        SmtpMode::Data => {
+            if line == b"." {
+                let id: String = (0..10)
+                    .map(|_| format!("{:X}", rand::random::<u8>() % 16))
+                    .collect();
+                return (
+                    smtp_reply(250, &format!("2.0.0 Ok: queued as {}", id)),
+                    Some(smtp_data_event(state, config, client_info)),
+                );
+            }
+            /* RFC 5321 section 4.5.2: transparency */
+            let line = line.strip_prefix(b".").unwrap_or(line);
+            state.data_len += line.len() + 2;
+            for l in [line, b"\r\n"].iter() {
+                let room = config.log_data_max.saturating_sub(state.data.len());
+                state
+                    .data
+                    .extend_from_slice(&l[..std::cmp::min(l.len(), room)]);
+            }
+            state.mode = SmtpMode::Data;
+            return (Vec::new(), None);
+        }
+        SmtpMode::AuthPlain | SmtpMode::AuthLoginUser | SmtpMode::AuthLoginPassword(_)
+            if line == b"*" =>
+        {
+            return (smtp_reply(501, "5.7.0 Authentication aborted"), None);
+        }
+        SmtpMode::AuthPlain => {
+            return smtp_auth_plain(&String::from_utf8_lossy(line), config, client_info);
+        }
+        SmtpMode::AuthLoginUser => {
+            return match smtp_decode(&String::from_utf8_lossy(line)) {
+                Some(user) => {
+                    state.mode =
+                        SmtpMode::AuthLoginPassword(String::from_utf8_lossy(&user).to_string());
+                    /* "Password:" */
+                    (smtp_reply(334, "UGFzc3dvcmQ6"), None)
+                }
+                None => (smtp_reply(501, "5.5.2 Error: invalid base64 data"), None),
+            };
+        }
+        SmtpMode::AuthLoginPassword(user) => {
+            return match smtp_decode(&String::from_utf8_lossy(line)) {
+                Some(password) => (
+                    smtp_auth_result(config.auth),
+                    Some(smtp_auth_event(
+                        "LOGIN",
+                        None,
+                        user.as_bytes(),
+                        &password,
+                        config.auth,
+                        client_info,
+                    )),
+                ),
+                None => (smtp_reply(501, "5.5.2 Error: invalid base64 data"), None),
+            };
+        }
+        SmtpMode::Command => {}
+    }
+    let (command, argument) = line_command(line);
+    let argument = argument.trim();
+    let mut event = Event::new("smtp_command", client_info);
+    event.set("command", command.clone());
+    event.set("argument", argument);
+    let repl = match command.as_str() {
+        "EHLO" => {
+            let mut repl = Vec::new();
+            for l in [
+                format!("{} Hello {}", config.hostname, argument).as_str(),
+                "PIPELINING",
+                "SIZE 10240000",
+                "AUTH LOGIN PLAIN",
+                "STARTTLS",
+                "ENHANCEDSTATUSCODES",
+                "8BITMIME",
+            ]
+            .iter()
+            {
+                repl.extend(format!("250-{}\r\n", l).into_bytes());
+            }
+            repl.extend(smtp_reply(250, "SMTPUTF8"));
+            repl
+        }
+        "HELO" => smtp_reply(250, &config.hostname),
+        "MAIL" => match smtp_address(argument, "FROM:") {
+            Some(address) => {
+                state.from = Some(address.to_string());
+                state.rcpt.clear();
+                event.set("address", address);
+                smtp_reply(250, "2.1.0 Ok")
+            }
+            None => smtp_reply(501, "5.5.4 Syntax: MAIL FROM:<address>"),
+        },
+        "RCPT" => match (&state.from, smtp_address(argument, "TO:")) {
+            (None, _) => smtp_reply(503, "5.5.1 Error: need MAIL command"),
+            (Some(_), Some(address)) => {
+                state.rcpt.push(address.to_string());
+                event.set("address", address);
+                smtp_reply(250, "2.1.5 Ok")
+            }
+            (Some(_), None) => smtp_reply(501, "5.5.4 Syntax: RCPT TO:<address>"),
+        },
+        "DATA" => {
+            if state.rcpt.is_empty() {
+                smtp_reply(503, "5.5.1 Error: need RCPT command")
+            } else {
+                state.mode = SmtpMode::Data;
+                smtp_reply(354, "End data with <CR><LF>.<CR><LF>")
+            }
+        }
+        "AUTH" => {
+            let mut args = argument.splitn(2, ' ');
+            let mechanism = args.next().unwrap_or("").to_ascii_uppercase();
+            let initial = args.next();
+            match (mechanism.as_str(), initial) {
+                ("PLAIN", Some(i)) => {
+                    return smtp_auth_plain(i, config, client_info);
+                }
+                ("PLAIN", None) => {
+                    state.mode = SmtpMode::AuthPlain;
+                    smtp_reply(334, "")
+                }
+                ("LOGIN", Some(i)) => {
+                    state.mode = SmtpMode::AuthLoginUser;
+                    return smtp_line(i.as_bytes(), masscanned, client_info, state);
+                }
+                ("LOGIN", None) => {
+                    state.mode = SmtpMode::AuthLoginUser;
+                    /* "Username:" */
+                    smtp_reply(334, "VXNlcm5hbWU6")
+                }
+                _ => smtp_reply(504, "5.5.4 Unrecognized authentication type"),
+            }
+        }
+        "STARTTLS" => {
+            state.starttls = true;
+            smtp_reply(220, "2.0.0 Ready to start TLS")
+        }
+        "RSET" => {
+            state.from = None;
+            state.rcpt.clear();
+            smtp_reply(250, "2.0.0 Ok")
+        }
+        "NOOP" => smtp_reply(250, "2.0.0 Ok"),
+        "VRFY" => smtp_reply(252, "2.0.0 Cannot VRFY user"),
+        "QUIT" => {
+            client_info.close = true;
+            smtp_reply(221, "2.0.0 Bye")
+        }
+        _ => {
+            state.errors += 1;
+            if state.errors >= SMTP_MAX_ERRORS {
+                client_info.close = true;
+                return (smtp_reply(421, "4.7.0 Error: too many errors"), Some(event));
+            }
+            return (
+                smtp_reply(500, "5.5.2 Error: command not recognized"),
+                Some(event),
+            );
+        }
+    };
+    state.errors = 0;
+    (repl, Some(event))
+}
+
+/* banner, sent when the TCP handshake is completed */
+pub fn greeting(masscanned: &Masscanned, tcb: &mut TCPControlBlock) -> Option<Vec<u8>> {
+    tcb.proto_state = ProtoState::Smtp(SmtpState::default());
+    Some(smtp_reply(220, &masscanned.config.smtp.banner))
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving SMTP data");
+    /* SMTP is only answered over TCP (or within TLS): after the banner,
+     * or from EHLO/HELO on (e.g., after STARTTLS) */
+    let t = tcb?;
+    if !matches!(t.proto_state, ProtoState::Smtp(_)) {
+        t.proto_state = ProtoState::Smtp(SmtpState::default());
+    }
+    let state = match &mut t.proto_state {
+        ProtoState::Smtp(s) => s,
+        _ => return None,
+    };
+    let mut repl_data = Vec::new();
+    for line in state.lines.push(data) {
+        let (repl, event) = smtp_line(&line, masscanned, client_info, state);
+        if let Some(e) = event {
+            e.log();
+        }
+        repl_data.extend(repl);
+        if client_info.close || state.starttls {
+            break;
+        }
+    }
+    if state.starttls {
+        /* what comes next is a TLS handshake */
+        t.start_tls();
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending SMTP data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use crate::smack::{BASE_STATE, NO_MATCH};
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_smtp_address() {
+        assert!(smtp_address("from:<a@b> SIZE=10", "FROM:") == Some("a@b"));
+        assert!(smtp_address("TO: a@b", "TO:") == Some("a@b"));
+        assert!(smtp_address("TO", "TO:").is_none());
+        /* RCPT T\x80:<a@b>, as decoded */
+        assert!(smtp_address(&String::from_utf8_lossy(b"T\x80:<a@b>"), "TO:").is_none());
+    }
+
+    #[test]
+    fn test_smtp_auth() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut state = SmtpState::default();
+        /* AUTH LOGIN, as sent by swaks */
+        let (repl, _) = smtp_line(b"AUTH LOGIN", &masscanned, &mut client_info, &mut state);
+        assert!(repl == b"334 VXNlcm5hbWU6\r\n");
+        let (repl, event) = smtp_line(b"YWRtaW4=", &masscanned, &mut client_info, &mut state);
+        assert!(repl == b"334 UGFzc3dvcmQ6\r\n" && event.is_none());
+        let (repl, event) = smtp_line(b"cGFzc3dvcmQ=", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"535 5.7.8 "));
+        let event = event.unwrap();
+        assert!(event.name == "smtp_auth");
+        assert!(event.fields["mechanism"] == "LOGIN");
+        assert!(event.fields["user"] == "admin");
+        assert!(event.fields["password"] == "password");
+        assert!(event.fields["success"] == false);
+        /* user name given with the command */
+        let (repl, _) = smtp_line(
+            b"auth login dXNlckBleGFtcGxlLmNvbQ==",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl == b"334 UGFzc3dvcmQ6\r\n");
+        let (_, event) = smtp_line(b"MTIzNDU2", &masscanned, &mut client_info, &mut state);
+        let event = event.unwrap();
+        assert!(event.fields["user"] == "user@example.com" && event.fields["password"] == "123456");
+        /* AUTH PLAIN, with the credentials given with the command, or
+         * after the continuation */
+        let (repl, event) = smtp_line(
+            b"AUTH PLAIN AHVzZXIAc2VjcmV0",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl.starts_with(b"535 "));
+        let event = event.unwrap();
+        assert!(event.fields["mechanism"] == "PLAIN");
+        assert!(event.fields["user"] == "user" && event.fields["password"] == "secret");
+        assert!(event.fields.get("authzid").is_none());
+        let (repl, _) = smtp_line(b"AUTH PLAIN", &masscanned, &mut client_info, &mut state);
+        assert!(repl == b"334 \r\n");
+        let (_, event) = smtp_line(
+            b"YWRtaW4AdXNlcgBzZWNyZXQ=",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(event.unwrap().fields["authzid"] == "admin");
+        /* aborted, invalid */
+        smtp_line(b"AUTH LOGIN", &masscanned, &mut client_info, &mut state);
+        let (repl, _) = smtp_line(b"*", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"501 "));
+        let (repl, _) = smtp_line(b"AUTH PLAIN !!!", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"501 "));
+        let (repl, _) = smtp_line(b"AUTH CRAM-MD5", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"504 "));
+        /* successful authentication */
+        let masscanned =
+            super::tests::masscanned(Config::from_str("[smtp]\nauth = true\n").unwrap());
+        let (repl, _) = smtp_line(
+            b"AUTH PLAIN AHVzZXIAc2VjcmV0",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        );
+        assert!(repl.starts_with(b"235 "));
+    }
+
+    #[test]
+    fn test_smtp_data() {
+        let masscanned = masscanned(Config::from_str("[smtp]\nlog_data_max = 16\n").unwrap());
+        let mut client_info = ClientInfo::new();
+        let mut state = SmtpState::default();
+        for line in [
+            &b"MAIL FROM:<alice@example.org> SIZE=100"[..],
+            b"RCPT TO:<bob@example.com>",
+            b"RCPT TO:bob@example.net",
+            b"DATA",
+            b"Subject: hi",
+            b"",
+            b"..hello",
+        ]
+        .iter()
+        {
+            let (repl, _) = smtp_line(line, &masscanned, &mut client_info, &mut state);
+            assert!(!repl.starts_with(b"5"));
+        }
+        let (repl, event) = smtp_line(b".", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"250 2.0.0 Ok: queued as "));
+        let event = event.unwrap();
+        assert!(event.name == "smtp_data");
+        assert!(event.fields["from"] == "alice@example.org");
+        assert!(event.fields["rcpt"] == serde_json::json!(["bob@example.com", "bob@example.net"]));
+        /* "Subject: hi\r\n\r\n.hello\r\n", truncated */
+        assert!(event.fields["length"] == 23);
+        assert!(event.fields["data"] == base64::encode(b"Subject: hi\r\n\r\n."));
+        /* new transaction */
+        let (repl, _) = smtp_line(b"DATA", &masscanned, &mut client_info, &mut state);
+        assert!(repl.starts_with(b"503 "));
+    }
+
+    #[test]
+    fn test_smtp_repl() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        assert!(repl(b"EHLO a\r\n", &masscanned, &mut client_info, None).is_none());
+        assert!(
+            greeting(&masscanned, &mut tcb).unwrap() == b"220 mail.example.com ESMTP Postfix\r\n"
+        );
+        let repl_data = repl(
+            b"EHLO client.example.org\r\nRCPT TO:<bob@example.com>\r\nMAIL FROM:<>\r\nRCPT TO:<bob@example.com>\r\nDATA\r\nSubject: hi\r",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data.starts_with(b"250-mail.example.com Hello client.example.org\r\n"));
+        assert!(repl_data
+            .windows(22)
+            .any(|w| w == b"250-AUTH LOGIN PLAIN\r\n"));
+        assert!(repl_data.windows(14).any(|w| w == b"250-STARTTLS\r\n"));
+        assert!(repl_data.ends_with(
+            b"503 5.5.1 Error: need MAIL command\r\n250 2.1.0 Ok\r\n250 2.1.5 Ok\r\n354 End data with <CR><LF>.<CR><LF>\r\n"
+        ));
+        /* message split across segments: no answer before its end */
+        assert!(repl(
+            b"\n\r\nhello\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        let repl_data = repl(
+            b".\r\nQUIT\r\n",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data.starts_with(b"250 2.0.0 Ok: queued as "));
+        assert!(repl_data.ends_with(b"\r\n221 2.0.0 Bye\r\n"));
+        assert!(client_info.close);
+        /* garbage: errors, then the connection is closed */
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = repl(
+            &b"\x16\x03\x01garbage\r\n".repeat(SMTP_MAX_ERRORS + 1),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data.starts_with(b"500 5.5.2 Error: command not recognized\r\n"));
+        assert!(repl_data.ends_with(b"421 4.7.0 Error: too many errors\r\n"));
+        assert!(client_info.close);
+        /* STARTTLS: the rest of the flow is a TLS handshake */
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        tcb.proto_id = crate::proto::PROTO_SMTP;
+        let repl_data = repl(
+            b"EHLO a\r\nSTARTTLS\r\n\x16\x03\x01",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data.ends_with(b"250 SMTPUTF8\r\n220 2.0.0 Ready to start TLS\r\n"));
+        assert!(tcb.proto_id == NO_MATCH && tcb.smack_state == BASE_STATE);
+        assert!(matches!(tcb.proto_state, ProtoState::Tls(_)));
+    }
+
+    #[test]
+    fn test_smtp_hello_any_case() {
+        let masscanned = masscanned(Config::default());
+        for hello in [&b"EHLO a\r\n"[..], b"ehlo a\r\n", b"Helo a\r\n"] {
+            let mut client_info = ClientInfo::new();
+            client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+            let mut tcb = TCPControlBlock::new();
+            let repl_data =
+                crate::proto::dispatch(hello, &masscanned, &mut client_info, Some(&mut tcb))
+                    .unwrap();
+            assert!(repl_data.starts_with(b"250"));
+            assert!(tcb.proto_id == crate::proto::PROTO_SMTP);
+        }
+    }
+
+    #[test]
+    fn test_smtp_config() {
+        assert!(Config::from_str("[smtp]\nbanner = \"ESMTP\\r\\n250 OK\"\n").is_err());
+        assert!(Config::from_str("[smtp]\nports = [25, 465, 587]\n").is_ok());
+    }
+}
@@ -0,0 +1,1231 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
+
+use lazy_static::lazy_static;
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::ber::{
+    ber_decode_integer, ber_decode_oid, ber_integer, ber_integer_value, ber_oid, ber_oid_string,
+    ber_parse_oid, ber_tlv, BerReader, BER_INTEGER, BER_NULL, BER_OCTET_STRING, BER_OID,
+    BER_SEQUENCE,
+};
+use crate::Masscanned;
+
+/* RFC 1157, RFC 3416, RFC 3412 */
+const SNMP_VERSION_1: i64 = 0;
+const SNMP_VERSION_2C: i64 = 1;
+const SNMP_VERSION_3: i64 = 3;
+
+const SNMP_PDU_GET: u8 = 0xa0;
+const SNMP_PDU_GETNEXT: u8 = 0xa1;
+const SNMP_PDU_RESPONSE: u8 = 0xa2;
+const SNMP_PDU_SET: u8 = 0xa3;
+const SNMP_PDU_GETBULK: u8 = 0xa5;
+const SNMP_PDU_REPORT: u8 = 0xa8;
+
+const SNMP_ERROR_NOSUCHNAME: i64 = 2;
+const SNMP_ERROR_NOTWRITABLE: i64 = 17;
+
+/* SNMPv2 exceptions (RFC 3416 section 3) */
+const SNMP_NOSUCHOBJECT: u8 = 0x80;
+const SNMP_ENDOFMIBVIEW: u8 = 0x82;
+
+const SNMP_IPADDRESS: u8 = 0x40;
+const SNMP_COUNTER: u8 = 0x41;
+const SNMP_GAUGE: u8 = 0x42;
+const SNMP_TIMETICKS: u8 = 0x43;
+
+/* maximum number of variable bindings answered to a GetBulk request */
+const SNMP_BULK_MAX: usize = 32;
+
+/* SNMPv3 msgFlags (RFC 3412 section 6.4), security model (RFC 3411
+ * section 5) */
+const SNMP_FLAG_AUTH: u8 = 0x01;
+const SNMP_FLAG_PRIV: u8 = 0x02;
+const SNMP_FLAG_REPORTABLE: u8 = 0x04;
+const SNMP_SECURITY_USM: i64 = 3;
+/* msgMaxSize announced */
+const SNMP_MAX_SIZE: i64 = 65507;
+/* engineBoots and engineTime (RFC 3414 section 2.2.1) */
+const SNMP_ENGINE_MAX: u64 = 2147483647;
+
+/* usmStatsUnknownUserNames.0, usmStatsUnknownEngineIDs.0 (RFC 3414
+ * section 5) */
+const SNMP_USM_UNKNOWN_USER_NAMES: [u32; 11] = [1, 3, 6, 1, 6, 3, 15, 1, 1, 3, 0];
+const SNMP_USM_UNKNOWN_ENGINE_IDS: [u32; 11] = [1, 3, 6, 1, 6, 3, 15, 1, 1, 4, 0];
+
+static SNMP_UNKNOWN_USER_NAMES: AtomicU32 = AtomicU32::new(0);
+static SNMP_UNKNOWN_ENGINE_IDS: AtomicU32 = AtomicU32::new(0);
+
+/* system group (RFC 1213) */
+const SNMP_SYSTEM: [u32; 7] = [1, 3, 6, 1, 2, 1, 1];
+
+lazy_static! {
+    static ref SNMP_START: Instant = Instant::now();
+}
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SnmpType {
+    #[default]
+    String,
+    Integer,
+    Oid,
+    Timeticks,
+    Counter,
+    Gauge,
+    Ipaddress,
+}
+
+/* additional entry of the MIB */
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SnmpOid {
+    pub oid: String,
+    #[serde(rename = "type", default)]
+    pub kind: SnmpType,
+    pub value: String,
+}
+
+/* format of the SNMPv3 engine ID, after the enterprise number (RFC
+ * 3411 section 5) */
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SnmpEngineFormat {
+    /* enterprise-specific (128) random bytes, as Net-SNMP does */
+    #[default]
+    Random,
+    /* MAC address of the interface (3) */
+    Mac,
+    /* address probed (1 for IPv4, 2 for IPv6) */
+    Ip,
+    /* engine_text (4) */
+    Text,
+}
+
+/* SNMP agent (v1 and v2c, on the ports): system group from the
+ * configuration, and additional entries - requests with an unknown
+ * community are ignored, unless answer_unknown is set. SNMPv3
+ * requests get the reports of an agent without users: engine ID
+ * discovery, then unknown user names.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnmpConfig {
+    pub ports: Vec<u16>,
+    pub communities: Vec<String>,
+    pub answer_unknown: bool,
+    pub sys_descr: String,
+    pub sys_object_id: String,
+    pub sys_contact: String,
+    pub sys_name: String,
+    pub sys_location: String,
+    pub oid: Vec<SnmpOid>,
+    /* SNMPv3 engine: ID in hex (e.g., "80001f8803001122334455"), or
+     * built from the enterprise number and the format */
+    pub engine_id: Option<String>,
+    pub engine_enterprise: u32,
+    pub engine_format: SnmpEngineFormat,
+    pub engine_text: String,
+    /* engineBoots, incremented when engineTime wraps */
+    pub engine_boots: u32,
+    #[serde(skip)]
+    engine_random: [u8; 8],
+}
+
+impl Default for SnmpConfig {
+    fn default() -> Self {
+        SnmpConfig {
+            ports: vec![161],
+            communities: vec!["public".to_string()],
+            answer_unknown: false,
+            sys_descr: "Linux server 5.10.0-23-amd64 #1 SMP Debian 5.10.179-1 (2023-05-12) x86_64"
+                .to_string(),
+            sys_object_id: "1.3.6.1.4.1.8072.3.2.10".to_string(),
+            sys_contact: "Me <me@example.org>".to_string(),
+            sys_name: "server".to_string(),
+            sys_location: "Sitting on the Dock of the Bay".to_string(),
+            oid: Vec::new(),
+            engine_id: None,
+            /* Net-SNMP */
+            engine_enterprise: 8072,
+            engine_format: SnmpEngineFormat::Random,
+            engine_text: String::new(),
+            engine_boots: 1,
+            engine_random: rand::random(),
+        }
+    }
+}
+
+fn snmp_from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+impl SnmpConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if let Some(id) = &self.engine_id {
+            /* SnmpEngineID (RFC 3411 section 5) */
+            match snmp_from_hex(id) {
+                Some(v) if (5..=32).contains(&v.len()) => {}
+                _ => return Err(snmp_invalid(format!("invalid engine ID: {}", id))),
+            }
+        }
+        if self.engine_enterprise >= 0x80000000 {
+            return Err(snmp_invalid(format!(
+                "invalid enterprise number: {}",
+                self.engine_enterprise
+            )));
+        }
+        if self.engine_format == SnmpEngineFormat::Text
+            && !(1..=27).contains(&self.engine_text.len())
+        {
+            return Err(snmp_invalid(format!(
+                "invalid engine text (1 to 27 bytes): {:?}",
+                self.engine_text
+            )));
+        }
+        if self.engine_boots as u64 >= SNMP_ENGINE_MAX {
+            return Err(snmp_invalid(format!(
+                "invalid engine boots: {}",
+                self.engine_boots
+            )));
+        }
+        snmp_table(self).map(|_| ())
+    }
+}
+
+enum SnmpValue {
+    Encoded(Vec<u8>),
+    /* sysUpTime */
+    Uptime,
+}
+
+fn snmp_invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("snmp: {}", msg))
+}
+
+fn snmp_unsigned(tag: u8, value: &str) -> Result<Vec<u8>, io::Error> {
+    let n: u32 = value
+        .parse()
+        .map_err(|_| snmp_invalid(format!("invalid unsigned value: {}", value)))?;
+    Ok(ber_tlv(tag, &ber_integer_value(n as i64)))
+}
+
+fn snmp_encode(kind: SnmpType, value: &str) -> Result<Vec<u8>, io::Error> {
+    match kind {
+        SnmpType::String => Ok(ber_tlv(BER_OCTET_STRING, value.as_bytes())),
+        SnmpType::Integer => {
+            let n: i32 = value
+                .parse()
+                .map_err(|_| snmp_invalid(format!("invalid integer: {}", value)))?;
+            Ok(ber_integer(n as i64))
+        }
+        SnmpType::Oid => {
+            Ok(ber_oid(&ber_parse_oid(value).ok_or_else(|| {
+                snmp_invalid(format!("invalid OID: {}", value))
+            })?))
+        }
+        SnmpType::Timeticks => snmp_unsigned(SNMP_TIMETICKS, value),
+        SnmpType::Counter => snmp_unsigned(SNMP_COUNTER, value),
+        SnmpType::Gauge => snmp_unsigned(SNMP_GAUGE, value),
+        SnmpType::Ipaddress => {
+            let ip: Ipv4Addr = value
+                .parse()
+                .map_err(|_| snmp_invalid(format!("invalid IPv4 address: {}", value)))?;
+            Ok(ber_tlv(SNMP_IPADDRESS, &ip.octets()))
+        }
+    }
+}
+
+fn snmp_system(n: u32) -> Vec<u32> {
+    let mut oid = SNMP_SYSTEM.to_vec();
+    oid.extend_from_slice(&[n, 0]);
+    oid
+}
+
+/* MIB answered, in lexicographic order of the OIDs */
+fn snmp_table(config: &SnmpConfig) -> Result<Vec<(Vec<u32>, SnmpValue)>, io::Error> {
+    let mut table = vec![
+        (
+            snmp_system(1),
+            SnmpValue::Encoded(snmp_encode(SnmpType::String, &config.sys_descr)?),
+        ),
+        (
+            snmp_system(2),
+            SnmpValue::Encoded(snmp_encode(SnmpType::Oid, &config.sys_object_id)?),
+        ),
+        (snmp_system(3), SnmpValue::Uptime),
+        (
+            snmp_system(4),
+            SnmpValue::Encoded(snmp_encode(SnmpType::String, &config.sys_contact)?),
+        ),
+        (
+            snmp_system(5),
+            SnmpValue::Encoded(snmp_encode(SnmpType::String, &config.sys_name)?),
+        ),
+        (
+            snmp_system(6),
+            SnmpValue::Encoded(snmp_encode(SnmpType::String, &config.sys_location)?),
+        ),
+    ];
+    for entry in config.oid.iter() {
+        let oid = ber_parse_oid(&entry.oid)
+            .ok_or_else(|| snmp_invalid(format!("invalid OID: {}", entry.oid)))?;
+        let value = SnmpValue::Encoded(snmp_encode(entry.kind, &entry.value)?);
+        /* additional entries replace the system ones */
+        table.retain(|(o, _)| *o != oid);
+        table.push((oid, value));
+    }
+    table.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(table)
+}
+
+fn snmp_value(value: &SnmpValue) -> Vec<u8> {
+    match value {
+        SnmpValue::Encoded(v) => v.clone(),
+        SnmpValue::Uptime => {
+            let ticks = (SNMP_START.elapsed().as_millis() / 10) as u32;
+            ber_tlv(SNMP_TIMETICKS, &ber_integer_value(ticks as i64))
+        }
+    }
+}
+
+struct SnmpRequest<'a> {
+    version: i64,
+    /* community (v1, v2c) or context name (v3) */
+    community: &'a [u8],
+    pdu: u8,
+    /* as sent, to be reflected */
+    request_id: &'a [u8],
+    /* GetBulk: non-repeaters, max-repetitions */
+    non_repeaters: i64,
+    max_repetitions: i64,
+    oids: Vec<Vec<u32>>,
+}
+
+fn snmp_parse_pdu<'a>(
+    version: i64,
+    community: &'a [u8],
+    pdu: u8,
+    content: &'a [u8],
+) -> Option<SnmpRequest<'a>> {
+    let mut content = BerReader::new(content);
+    let request_id = content.expect(BER_INTEGER)?;
+    let non_repeaters = content.integer()?;
+    let max_repetitions = content.integer()?;
+    let mut varbinds = BerReader::new(content.expect(BER_SEQUENCE)?);
+    let mut oids = Vec::new();
+    while !varbinds.is_empty() {
+        let mut varbind = BerReader::new(varbinds.expect(BER_SEQUENCE)?);
+        oids.push(ber_decode_oid(varbind.expect(BER_OID)?)?);
+        /* value (NULL in requests other than Set) */
+        varbind.read()?;
+    }
+    Some(SnmpRequest {
+        version,
+        community,
+        pdu,
+        request_id,
+        non_repeaters,
+        max_repetitions,
+        oids,
+    })
+}
+
+fn snmp_parse(data: &[u8]) -> Option<SnmpRequest<'_>> {
+    let mut message = BerReader::new(BerReader::new(data).expect(BER_SEQUENCE)?);
+    let version = message.integer()?;
+    let community = message.expect(BER_OCTET_STRING)?;
+    let (pdu, content) = message.read()?;
+    snmp_parse_pdu(version, community, pdu, content)
+}
+
+/* SNMPv3 message with the User-based Security Model (RFC 3412 section
+ * 6, RFC 3414 section 2.4) */
+struct SnmpV3Request<'a> {
+    msg_id: i64,
+    flags: u8,
+    engine_id: &'a [u8],
+    engine_boots: i64,
+    engine_time: i64,
+    user: &'a [u8],
+    /* scoped PDU, when not encrypted */
+    scoped: Option<SnmpRequest<'a>>,
+}
+
+fn snmp_v3_parse(data: &[u8]) -> Option<SnmpV3Request<'_>> {
+    let mut message = BerReader::new(BerReader::new(data).expect(BER_SEQUENCE)?);
+    if message.integer()? != SNMP_VERSION_3 {
+        return None;
+    }
+    let mut global = BerReader::new(message.expect(BER_SEQUENCE)?);
+    let msg_id = global.integer()?;
+    /* msgMaxSize */
+    global.integer()?;
+    let flags = *global.expect(BER_OCTET_STRING)?.first()?;
+    if global.integer()? != SNMP_SECURITY_USM {
+        return None;
+    }
+    /* the security parameters are BER-encoded in an OCTET STRING */
+    let mut usm =
+        BerReader::new(BerReader::new(message.expect(BER_OCTET_STRING)?).expect(BER_SEQUENCE)?);
+    let engine_id = usm.expect(BER_OCTET_STRING)?;
+    let engine_boots = usm.integer()?;
+    let engine_time = usm.integer()?;
+    let user = usm.expect(BER_OCTET_STRING)?;
+    /* authentication and privacy parameters */
+    usm.expect(BER_OCTET_STRING)?;
+    usm.expect(BER_OCTET_STRING)?;
+    let scoped = if flags & SNMP_FLAG_PRIV != 0 {
+        message.expect(BER_OCTET_STRING)?;
+        None
+    } else {
+        let mut scoped = BerReader::new(message.expect(BER_SEQUENCE)?);
+        /* contextEngineID */
+        scoped.expect(BER_OCTET_STRING)?;
+        let context_name = scoped.expect(BER_OCTET_STRING)?;
+        let (pdu, content) = scoped.read()?;
+        Some(snmp_parse_pdu(SNMP_VERSION_3, context_name, pdu, content)?)
+    };
+    Some(SnmpV3Request {
+        msg_id,
+        flags,
+        engine_id,
+        engine_boots,
+        engine_time,
+        user,
+        scoped,
+    })
+}
+
+fn snmp_response(
+    request: &SnmpRequest,
+    error: i64,
+    index: usize,
+    varbinds: &[(Vec<u32>, Vec<u8>)],
+) -> Vec<u8> {
+    let mut list = Vec::new();
+    for (oid, value) in varbinds.iter() {
+        let mut varbind = ber_oid(oid);
+        varbind.extend_from_slice(value);
+        list.extend(ber_tlv(BER_SEQUENCE, &varbind));
+    }
+    let mut pdu = ber_tlv(BER_INTEGER, request.request_id);
+    pdu.extend(ber_integer(error));
+    pdu.extend(ber_integer(index as i64));
+    pdu.extend(ber_tlv(BER_SEQUENCE, &list));
+    let mut message = ber_integer(request.version);
+    message.extend(ber_tlv(BER_OCTET_STRING, request.community));
+    message.extend(ber_tlv(SNMP_PDU_RESPONSE, &pdu));
+    ber_tlv(BER_SEQUENCE, &message)
+}
+
+/* the request unanswered: variables with NULL values (SNMPv1 errors) */
+fn snmp_error(request: &SnmpRequest, error: i64, index: usize) -> Vec<u8> {
+    let varbinds: Vec<(Vec<u32>, Vec<u8>)> = request
+        .oids
+        .iter()
+        .map(|o| (o.clone(), ber_tlv(BER_NULL, b"")))
+        .collect();
+    snmp_response(request, error, index, &varbinds)
+}
+
+fn snmp_next<'a>(
+    table: &'a [(Vec<u32>, SnmpValue)],
+    oid: &[u32],
+) -> Option<&'a (Vec<u32>, SnmpValue)> {
+    table.iter().find(|(o, _)| o.as_slice() > oid)
+}
+
+fn snmp_answer(request: &SnmpRequest, table: &[(Vec<u32>, SnmpValue)]) -> Option<Vec<u8>> {
+    let v1 = request.version == SNMP_VERSION_1;
+    let mut varbinds = Vec::new();
+    match request.pdu {
+        SNMP_PDU_GET | SNMP_PDU_GETNEXT => {
+            for (i, oid) in request.oids.iter().enumerate() {
+                let entry = if request.pdu == SNMP_PDU_GET {
+                    table.iter().find(|(o, _)| o == oid)
+                } else {
+                    snmp_next(table, oid)
+                };
+                match entry {
+                    Some((o, value)) => varbinds.push((o.clone(), snmp_value(value))),
+                    None if v1 => return Some(snmp_error(request, SNMP_ERROR_NOSUCHNAME, i + 1)),
+                    None => {
+                        let exception = if request.pdu == SNMP_PDU_GET {
+                            SNMP_NOSUCHOBJECT
+                        } else {
+                            SNMP_ENDOFMIBVIEW
+                        };
+                        varbinds.push((oid.clone(), ber_tlv(exception, b"")));
+                    }
+                }
+            }
+        }
+        SNMP_PDU_GETBULK if !v1 => {
+            /* RFC 3416 section 4.2.3 */
+            let non_repeaters = request.non_repeaters.clamp(0, request.oids.len() as i64) as usize;
+            let (single, repeated) = request.oids.split_at(non_repeaters);
+            let next = |oid: &[u32]| match snmp_next(table, oid) {
+                Some((o, value)) => (o.clone(), snmp_value(value)),
+                None => (oid.to_vec(), ber_tlv(SNMP_ENDOFMIBVIEW, b"")),
+            };
+            for oid in single.iter() {
+                varbinds.push(next(oid));
+            }
+            let mut current: Vec<Vec<u32>> = repeated.to_vec();
+            for _ in 0..request.max_repetitions.max(0) {
+                if current.is_empty() || varbinds.len() + current.len() > SNMP_BULK_MAX {
+                    break;
+                }
+                let mut end = true;
+                for oid in current.iter_mut() {
+                    let (o, value) = next(oid);
+                    end &= value[0] == SNMP_ENDOFMIBVIEW;
+                    *oid = o.clone();
+                    varbinds.push((o, value));
+                }
+                if end {
+                    break;
+                }
+            }
+        }
+        SNMP_PDU_SET => {
+            /* read-only */
+            let error = if v1 {
+                SNMP_ERROR_NOSUCHNAME
+            } else {
+                SNMP_ERROR_NOTWRITABLE
+            };
+            return Some(snmp_error(request, error, 1));
+        }
+        pdu => {
+            info!("SNMP PDU not handled: {:#x}", pdu);
+            return None;
+        }
+    }
+    Some(snmp_response(request, 0, 0, &varbinds))
+}
+
+/* PDU type, request ID and variables of a request */
+fn snmp_event_pdu(event: &mut Event, request: &SnmpRequest) {
+    event.set(
+        "pdu",
+        match request.pdu {
+            SNMP_PDU_GET => "get".to_string(),
+            SNMP_PDU_GETNEXT => "getnext".to_string(),
+            SNMP_PDU_GETBULK => "getbulk".to_string(),
+            SNMP_PDU_SET => "set".to_string(),
+            pdu => format!("{:#x}", pdu),
+        },
+    );
+    if let Some(id) = ber_decode_integer(request.request_id) {
+        event.set("request_id", id);
+    }
+    event.set(
+        "oids",
+        request
+            .oids
+            .iter()
+            .map(|o| ber_oid_string(o))
+            .collect::<Vec<String>>(),
+    );
+}
+
+fn snmp_event(request: &SnmpRequest, known: bool, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("snmp_request", client_info);
+    event.set(
+        "version",
+        if request.version == SNMP_VERSION_1 {
+            "1"
+        } else {
+            "2c"
+        },
+    );
+    event.set(
+        "community",
+        String::from_utf8_lossy(request.community).to_string(),
+    );
+    event.set("known_community", known);
+    snmp_event_pdu(&mut event, request);
+    event
+}
+
+fn snmp_v3_event(request: &SnmpV3Request, known: bool, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("snmp_request", client_info);
+    event.set("version", "3");
+    event.set("msg_id", request.msg_id);
+    event.set(
+        "security_level",
+        if request.flags & SNMP_FLAG_PRIV != 0 {
+            "authPriv"
+        } else if request.flags & SNMP_FLAG_AUTH != 0 {
+            "authNoPriv"
+        } else {
+            "noAuthNoPriv"
+        },
+    );
+    event.set("engine_id", encode(request.engine_id, Encoding::Hex));
+    event.set("known_engine_id", known);
+    event.set("engine_boots", request.engine_boots);
+    event.set("engine_time", request.engine_time);
+    event.set("user", String::from_utf8_lossy(request.user).to_string());
+    if let Some(scoped) = &request.scoped {
+        event.set(
+            "context",
+            String::from_utf8_lossy(scoped.community).to_string(),
+        );
+        snmp_event_pdu(&mut event, scoped);
+    }
+    event
+}
+
+/* snmpEngineID (RFC 3411 section 5): the enterprise number (with the
+ * high bit set), then the format and its data */
+fn snmp_engine_id(
+    config: &SnmpConfig,
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+) -> Vec<u8> {
+    if let Some(id) = config.engine_id.as_ref().and_then(|id| snmp_from_hex(id)) {
+        return id;
+    }
+    let mut id = (0x80000000 | config.engine_enterprise)
+        .to_be_bytes()
+        .to_vec();
+    match config.engine_format {
+        SnmpEngineFormat::Random => {
+            id.push(0x80);
+            id.extend_from_slice(&config.engine_random);
+        }
+        SnmpEngineFormat::Mac => {
+            id.push(3);
+            id.extend_from_slice(&<[u8; 6]>::from(masscanned.mac));
+        }
+        SnmpEngineFormat::Ip => match client_info.ip.dst {
+            Some(IpAddr::V6(ip)) => {
+                id.push(2);
+                id.extend_from_slice(&ip.octets());
+            }
+            Some(IpAddr::V4(ip)) => {
+                id.push(1);
+                id.extend_from_slice(&ip.octets());
+            }
+            None => {
+                id.push(1);
+                id.extend_from_slice(&Ipv4Addr::UNSPECIFIED.octets());
+            }
+        },
+        SnmpEngineFormat::Text => {
+            id.push(4);
+            id.extend_from_slice(config.engine_text.as_bytes());
+        }
+    }
+    id
+}
+
+/* engineBoots and engineTime: the time wraps (and the boots
+ * increment) after 2^31 - 1 seconds (RFC 3414 section 2.2.2) */
+fn snmp_engine_time(config: &SnmpConfig) -> (i64, i64) {
+    let elapsed = SNMP_START.elapsed().as_secs();
+    let boots = (config.engine_boots as u64 + elapsed / SNMP_ENGINE_MAX).min(SNMP_ENGINE_MAX);
+    (boots as i64, (elapsed % SNMP_ENGINE_MAX) as i64)
+}
+
+/* Report PDU (RFC 3412 section 7.1, step 3): unauthenticated, with
+ * the counter of the error */
+fn snmp_v3_report(
+    request: &SnmpV3Request,
+    config: &SnmpConfig,
+    engine_id: &[u8],
+    oid: &[u32],
+    count: u32,
+) -> Vec<u8> {
+    let mut varbind = ber_oid(oid);
+    varbind.extend(ber_tlv(SNMP_COUNTER, &ber_integer_value(count as i64)));
+    let (request_id, context) = match &request.scoped {
+        Some(scoped) => (scoped.request_id.to_vec(), scoped.community),
+        None => (ber_integer_value(0), &b""[..]),
+    };
+    let mut pdu = ber_tlv(BER_INTEGER, &request_id);
+    pdu.extend(ber_integer(0));
+    pdu.extend(ber_integer(0));
+    pdu.extend(ber_tlv(BER_SEQUENCE, &ber_tlv(BER_SEQUENCE, &varbind)));
+    let mut scoped = ber_tlv(BER_OCTET_STRING, engine_id);
+    scoped.extend(ber_tlv(BER_OCTET_STRING, context));
+    scoped.extend(ber_tlv(SNMP_PDU_REPORT, &pdu));
+    let (boots, time) = snmp_engine_time(config);
+    let mut usm = ber_tlv(BER_OCTET_STRING, engine_id);
+    usm.extend(ber_integer(boots));
+    usm.extend(ber_integer(time));
+    usm.extend(ber_tlv(BER_OCTET_STRING, request.user));
+    usm.extend(ber_tlv(BER_OCTET_STRING, b""));
+    usm.extend(ber_tlv(BER_OCTET_STRING, b""));
+    let mut global = ber_integer(request.msg_id);
+    global.extend(ber_integer(SNMP_MAX_SIZE));
+    /* noAuthNoPriv, not reportable */
+    global.extend(ber_tlv(BER_OCTET_STRING, b"\x00"));
+    global.extend(ber_integer(SNMP_SECURITY_USM));
+    let mut message = ber_integer(SNMP_VERSION_3);
+    message.extend(ber_tlv(BER_SEQUENCE, &global));
+    message.extend(ber_tlv(BER_OCTET_STRING, &ber_tlv(BER_SEQUENCE, &usm)));
+    message.extend(ber_tlv(BER_SEQUENCE, &scoped));
+    ber_tlv(BER_SEQUENCE, &message)
+}
+
+/* Answer to SNMPv3 requests as an agent without users would (RFC 3414
+ * section 3.2): discovery requests (and requests for another engine)
+ * get usmStatsUnknownEngineIDs, the next ones usmStatsUnknownUserNames.
+ **/
+fn snmp_v3_repl(
+    request: &SnmpV3Request,
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    let config = &masscanned.config.snmp;
+    let engine_id = snmp_engine_id(config, masscanned, client_info);
+    let known = request.engine_id == engine_id.as_slice();
+    snmp_v3_event(request, known, client_info).log();
+    if request.flags & SNMP_FLAG_REPORTABLE == 0 {
+        return None;
+    }
+    let (oid, counter) = if known {
+        (&SNMP_USM_UNKNOWN_USER_NAMES, &SNMP_UNKNOWN_USER_NAMES)
+    } else {
+        (&SNMP_USM_UNKNOWN_ENGINE_IDS, &SNMP_UNKNOWN_ENGINE_IDS)
+    };
+    let count = counter.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+    debug!("sending SNMP report");
+    Some(snmp_v3_report(request, config, &engine_id, oid, count))
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving SNMP data");
+    let config = &masscanned.config.snmp;
+    if let Some(request) = snmp_v3_parse(data) {
+        return snmp_v3_repl(&request, masscanned, client_info);
+    }
+    let request = match snmp_parse(data) {
+        Some(r) if r.version == SNMP_VERSION_1 || r.version == SNMP_VERSION_2C => r,
+        _ => {
+            info!("SNMP data not handled (invalid or unsupported message)");
+            return None;
+        }
+    };
+    let known = config
+        .communities
+        .iter()
+        .any(|c| c.as_bytes() == request.community);
+    snmp_event(&request, known, client_info).log();
+    if !known && !config.answer_unknown {
+        return None;
+    }
+    let table = match snmp_table(config) {
+        Ok(t) => t,
+        Err(e) => {
+            warn!("{}", e);
+            return None;
+        }
+    };
+    let repl = snmp_answer(&request, &table)?;
+    debug!("sending SNMP data");
+    Some(repl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::{client_info, masscanned, SERVER_IP};
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    /* SNMPv1 GetRequest for sysDescr.0, as sent by onesixtyone */
+    const ONESIXTYONE_PROBE: &[u8] = b"\x30\x29\x02\x01\x00\x04\x06public\xa0\x1c\x02\x04\x00\x00\x00\x01\x02\x01\x00\x02\x01\x00\x30\x0e\x30\x0c\x06\x08\x2b\x06\x01\x02\x01\x01\x01\x00\x05\x00";
+    /* SNMPv2c GetRequest for sysName.0, as sent by snmpget */
+    const SNMPGET_PROBE: &[u8] = b"\x30\x29\x02\x01\x01\x04\x06public\xa0\x1c\x02\x04\x1a\x2b\x3c\x4d\x02\x01\x00\x02\x01\x00\x30\x0e\x30\x0c\x06\x08\x2b\x06\x01\x02\x01\x01\x05\x00\x05\x00";
+
+    /* request with the given version, community, PDU and OIDs */
+    fn request(
+        version: i64,
+        community: &[u8],
+        pdu: u8,
+        oids: &[&str],
+        bulk: (i64, i64),
+    ) -> Vec<u8> {
+        let mut list = Vec::new();
+        for oid in oids.iter() {
+            let mut varbind = ber_oid(&ber_parse_oid(oid).unwrap());
+            varbind.extend(ber_tlv(BER_NULL, b""));
+            list.extend(ber_tlv(BER_SEQUENCE, &varbind));
+        }
+        let mut content = ber_integer(1234);
+        content.extend(ber_integer(bulk.0));
+        content.extend(ber_integer(bulk.1));
+        content.extend(ber_tlv(BER_SEQUENCE, &list));
+        let mut message = ber_integer(version);
+        message.extend(ber_tlv(BER_OCTET_STRING, community));
+        message.extend(ber_tlv(pdu, &content));
+        ber_tlv(BER_SEQUENCE, &message)
+    }
+
+    struct Response {
+        version: i64,
+        community: Vec<u8>,
+        request_id: i64,
+        error: i64,
+        index: i64,
+        varbinds: Vec<(String, u8, Vec<u8>)>,
+    }
+
+    fn response(data: &[u8]) -> Response {
+        let mut message = BerReader::new(BerReader::new(data).expect(BER_SEQUENCE).unwrap());
+        let version = message.integer().unwrap();
+        let community = message.expect(BER_OCTET_STRING).unwrap().to_vec();
+        let mut pdu = BerReader::new(message.expect(SNMP_PDU_RESPONSE).unwrap());
+        assert!(message.is_empty());
+        let request_id = pdu.integer().unwrap();
+        let error = pdu.integer().unwrap();
+        let index = pdu.integer().unwrap();
+        let mut list = BerReader::new(pdu.expect(BER_SEQUENCE).unwrap());
+        let mut varbinds = Vec::new();
+        while !list.is_empty() {
+            let mut varbind = BerReader::new(list.expect(BER_SEQUENCE).unwrap());
+            let oid = ber_decode_oid(varbind.expect(BER_OID).unwrap()).unwrap();
+            let (tag, value) = varbind.read().unwrap();
+            varbinds.push((ber_oid_string(&oid), tag, value.to_vec()));
+        }
+        Response {
+            version,
+            community,
+            request_id,
+            error,
+            index,
+            varbinds,
+        }
+    }
+
+    #[test]
+    fn test_snmp_get() {
+        let masscanned = masscanned(Config::default());
+        let config = &masscanned.config.snmp;
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 161);
+        /* onesixtyone (v1) */
+        let repl_data = repl(ONESIXTYONE_PROBE, &masscanned, &mut client_info).unwrap();
+        let mut expected = b"\x30\x72\x02\x01\x00\x04\x06public\xa2\x65\x02\x04\x00\x00\x00\x01\x02\x01\x00\x02\x01\x00\x30\x57\x30\x55\x06\x08\x2b\x06\x01\x02\x01\x01\x01\x00\x04\x49".to_vec();
+        expected.extend_from_slice(config.sys_descr.as_bytes());
+        assert!(repl_data == expected);
+        /* snmpget (v2c) */
+        let r = response(&repl(SNMPGET_PROBE, &masscanned, &mut client_info).unwrap());
+        assert!(r.version == SNMP_VERSION_2C && r.community == b"public");
+        assert!(r.request_id == 0x1a2b3c4d);
+        assert!(r.error == 0 && r.index == 0);
+        assert!(
+            r.varbinds
+                == vec![(
+                    "1.3.6.1.2.1.1.5.0".to_string(),
+                    BER_OCTET_STRING,
+                    b"server".to_vec()
+                )]
+        );
+        /* several variables, including dynamic sysUpTime */
+        let data = request(
+            SNMP_VERSION_2C,
+            b"public",
+            SNMP_PDU_GET,
+            &["1.3.6.1.2.1.1.2.0", "1.3.6.1.2.1.1.3.0"],
+            (0, 0),
+        );
+        let r = response(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(r.request_id == 1234);
+        assert!(r.varbinds.len() == 2);
+        assert!(r.varbinds[0].1 == BER_OID);
+        assert!(ber_decode_oid(&r.varbinds[0].2) == ber_parse_oid(&config.sys_object_id));
+        assert!(r.varbinds[1].0 == "1.3.6.1.2.1.1.3.0" && r.varbinds[1].1 == SNMP_TIMETICKS);
+    }
+
+    #[test]
+    fn test_snmp_errors() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 161);
+        let oids = ["1.3.6.1.2.1.1.5.0", "1.3.6.1.2.1.2.1.0"];
+        /* v1: noSuchName, with the index of the (1-based) variable */
+        let data = request(SNMP_VERSION_1, b"public", SNMP_PDU_GET, &oids, (0, 0));
+        let r = response(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(r.error == SNMP_ERROR_NOSUCHNAME && r.index == 2);
+        assert!(r.varbinds.len() == 2 && r.varbinds.iter().all(|v| v.1 == BER_NULL));
+        /* v2c: exceptions */
+        let data = request(SNMP_VERSION_2C, b"public", SNMP_PDU_GET, &oids, (0, 0));
+        let r = response(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(r.error == 0);
+        assert!(r.varbinds[0].1 == BER_OCTET_STRING);
+        assert!(r.varbinds[1].0 == oids[1] && r.varbinds[1].1 == SNMP_NOSUCHOBJECT);
+        /* Set is refused */
+        let data = request(SNMP_VERSION_1, b"private", SNMP_PDU_SET, &oids[..1], (0, 0));
+        assert!(repl(&data, &masscanned, &mut client_info).is_none());
+        let data = request(SNMP_VERSION_2C, b"public", SNMP_PDU_SET, &oids[..1], (0, 0));
+        let r = response(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(r.error == SNMP_ERROR_NOTWRITABLE && r.index == 1);
+        /* SNMPv3, and GetBulk in v1, are not answered */
+        for data in [
+            request(3, b"public", SNMP_PDU_GET, &oids[..1], (0, 0)),
+            request(SNMP_VERSION_1, b"public", SNMP_PDU_GETBULK, &oids, (0, 10)),
+        ]
+        .iter()
+        {
+            assert!(repl(data, &masscanned, &mut client_info).is_none());
+        }
+    }
+
+    #[test]
+    fn test_snmp_walk() {
+        let mut config = Config::from_str(
+            "[snmp]\nsys_name = \"printer\"\n[[snmp.oid]]\noid = \"1.3.6.1.2.1.25.1.1.0\"\ntype = \"timeticks\"\nvalue = \"4200\"\n",
+        )
+        .expect("error parsing config");
+        config.snmp.communities.push("private".to_string());
+        let masscanned = masscanned(config);
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 161);
+        /* snmpwalk of the system group and beyond, with both versions */
+        for version in [SNMP_VERSION_1, SNMP_VERSION_2C].iter() {
+            let mut oid = "1.3.6.1.2.1.1".to_string();
+            let mut walked = Vec::new();
+            loop {
+                let data = request(*version, b"private", SNMP_PDU_GETNEXT, &[&oid], (0, 0));
+                let r = response(&repl(&data, &masscanned, &mut client_info).unwrap());
+                let (next, tag, value) = r.varbinds[0].clone();
+                if r.error == SNMP_ERROR_NOSUCHNAME || tag == SNMP_ENDOFMIBVIEW {
+                    assert!(next == oid);
+                    assert!(
+                        (*version == SNMP_VERSION_1 && r.index == 1)
+                            || (*version == SNMP_VERSION_2C && r.error == 0)
+                    );
+                    break;
+                }
+                if next == "1.3.6.1.2.1.1.5.0" {
+                    assert!(value == b"printer");
+                }
+                walked.push(next.clone());
+                oid = next;
+            }
+            assert!(walked.len() == 7);
+            assert!(walked[0] == "1.3.6.1.2.1.1.1.0" && walked[6] == "1.3.6.1.2.1.25.1.1.0");
+        }
+        /* GetBulk: a non-repeater, then repetitions up to the end of the MIB */
+        let data = request(
+            SNMP_VERSION_2C,
+            b"public",
+            SNMP_PDU_GETBULK,
+            &["1.3.6.1.2.1.1.4.0", "1.3.6.1.2.1.1.5"],
+            (1, 10),
+        );
+        let r = response(&repl(&data, &masscanned, &mut client_info).unwrap());
+        let oids: Vec<&str> = r.varbinds.iter().map(|v| v.0.as_str()).collect();
+        assert!(
+            oids == vec![
+                "1.3.6.1.2.1.1.5.0",
+                "1.3.6.1.2.1.1.5.0",
+                "1.3.6.1.2.1.1.6.0",
+                "1.3.6.1.2.1.25.1.1.0",
+                "1.3.6.1.2.1.25.1.1.0"
+            ]
+        );
+        assert!(r.varbinds[3].1 == SNMP_TIMETICKS && r.varbinds[3].2 == b"\x10\x68");
+        assert!(r.varbinds[4].1 == SNMP_ENDOFMIBVIEW);
+    }
+
+    #[test]
+    fn test_snmp_community() {
+        let mut masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 161);
+        let data = request(
+            SNMP_VERSION_1,
+            b"secret",
+            SNMP_PDU_GET,
+            &["1.3.6.1.2.1.1.1.0"],
+            (0, 0),
+        );
+        /* unknown communities are ignored by default */
+        assert!(repl(&data, &masscanned, &mut client_info).is_none());
+        masscanned.config.snmp.answer_unknown = true;
+        let r = response(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(r.community == b"secret" && r.error == 0);
+    }
+
+    #[test]
+    fn test_snmp_malformed() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 161);
+        /* truncated messages */
+        for i in 0..ONESIXTYONE_PROBE.len() {
+            assert!(repl(&ONESIXTYONE_PROBE[..i], &masscanned, &mut client_info).is_none());
+        }
+        /* lengths beyond the data, indefinite, too long or absurd */
+        for (offset, len) in [
+            (1, &b"\xff"[..]),
+            (1, b"\x80"),
+            (1, b"\x85\x00\x00\x00\x00\x29"),
+            (1, b"\x84\xff\xff\xff\xff"),
+            (15, b"\x82\xff\xff"),
+            (29, b"\x7f"),
+        ]
+        .iter()
+        {
+            let mut data = ONESIXTYONE_PROBE[..*offset].to_vec();
+            data.extend_from_slice(len);
+            data.extend_from_slice(&ONESIXTYONE_PROBE[*offset + 1..]);
+            assert!(repl(&data, &masscanned, &mut client_info).is_none());
+        }
+        /* invalid configurations */
+        for config in [
+            "[snmp]\nsys_object_id = \"1.3.x\"\n",
+            "[[snmp.oid]]\noid = \"1.3.6.1.4.1.1.0\"\ntype = \"integer\"\nvalue = \"many\"\n",
+            "[[snmp.oid]]\noid = \"1.3.6.1.4.1.1.0\"\ntype = \"ipaddress\"\nvalue = \"::1\"\n",
+            "[[snmp.oid]]\noid = \"1\"\nvalue = \"test\"\n",
+        ]
+        .iter()
+        {
+            assert!(Config::from_str(config).is_err());
+        }
+    }
+
+    /* SNMPv3 engine ID discovery, as sent by snmpwalk -v3 */
+    const SNMPWALK_V3_DISCOVERY: &[u8] = b"\x30\x3a\x02\x01\x03\x30\x0f\x02\x02\x4a\x69\x02\x03\x00\xff\xe3\x04\x01\x04\x02\x01\x03\x04\x10\x30\x0e\x04\x00\x02\x01\x00\x02\x01\x00\x04\x00\x04\x00\x04\x00\x30\x12\x04\x00\x04\x00\xa0\x0c\x02\x02\x37\xf0\x02\x01\x00\x02\x01\x00\x30\x00";
+
+    /* SNMPv3 request for the engine, from the user, with a GetNext
+     * for the system group (or an encrypted PDU with authPriv) */
+    fn v3_request(flags: u8, engine_id: &[u8], user: &[u8]) -> Vec<u8> {
+        let mut global = ber_integer(0x4a6a);
+        global.extend(ber_integer(65507));
+        global.extend(ber_tlv(BER_OCTET_STRING, &[flags]));
+        global.extend(ber_integer(SNMP_SECURITY_USM));
+        let mut usm = ber_tlv(BER_OCTET_STRING, engine_id);
+        usm.extend(ber_integer(1));
+        usm.extend(ber_integer(42));
+        usm.extend(ber_tlv(BER_OCTET_STRING, user));
+        usm.extend(ber_tlv(BER_OCTET_STRING, &[0xaa; 12]));
+        usm.extend(ber_tlv(
+            BER_OCTET_STRING,
+            if flags & SNMP_FLAG_PRIV != 0 {
+                &[0x00, 0x00, 0x00, 0x01, 0x2c, 0x3d, 0x4e, 0x5f]
+            } else {
+                &[]
+            },
+        ));
+        let mut message = ber_integer(SNMP_VERSION_3);
+        message.extend(ber_tlv(BER_SEQUENCE, &global));
+        message.extend(ber_tlv(BER_OCTET_STRING, &ber_tlv(BER_SEQUENCE, &usm)));
+        if flags & SNMP_FLAG_PRIV != 0 {
+            message.extend(ber_tlv(BER_OCTET_STRING, &[0x5a; 48]));
+        } else {
+            let get = request(
+                SNMP_VERSION_2C,
+                b"",
+                SNMP_PDU_GETNEXT,
+                &["1.3.6.1.2.1.1"],
+                (0, 0),
+            );
+            let mut pdu = BerReader::new(BerReader::new(&get).expect(BER_SEQUENCE).unwrap());
+            pdu.integer().unwrap();
+            pdu.expect(BER_OCTET_STRING).unwrap();
+            let (tag, content) = pdu.read().unwrap();
+            let mut scoped = ber_tlv(BER_OCTET_STRING, engine_id);
+            scoped.extend(ber_tlv(BER_OCTET_STRING, b""));
+            scoped.extend(ber_tlv(tag, content));
+            message.extend(ber_tlv(BER_SEQUENCE, &scoped));
+        }
+        ber_tlv(BER_SEQUENCE, &message)
+    }
+
+    struct Report {
+        msg_id: i64,
+        flags: Vec<u8>,
+        engine_id: Vec<u8>,
+        engine_boots: i64,
+        user: Vec<u8>,
+        context_engine_id: Vec<u8>,
+        request_id: i64,
+        oid: String,
+        tag: u8,
+        count: i64,
+    }
+
+    fn report(data: &[u8]) -> Report {
+        let mut message = BerReader::new(BerReader::new(data).expect(BER_SEQUENCE).unwrap());
+        assert!(message.integer() == Some(SNMP_VERSION_3));
+        let mut global = BerReader::new(message.expect(BER_SEQUENCE).unwrap());
+        let msg_id = global.integer().unwrap();
+        assert!(global.integer() == Some(SNMP_MAX_SIZE));
+        let flags = global.expect(BER_OCTET_STRING).unwrap().to_vec();
+        assert!(global.integer() == Some(SNMP_SECURITY_USM) && global.is_empty());
+        let mut usm = BerReader::new(
+            BerReader::new(message.expect(BER_OCTET_STRING).unwrap())
+                .expect(BER_SEQUENCE)
+                .unwrap(),
+        );
+        let engine_id = usm.expect(BER_OCTET_STRING).unwrap().to_vec();
+        let engine_boots = usm.integer().unwrap();
+        assert!(usm.integer().unwrap() >= 0);
+        let user = usm.expect(BER_OCTET_STRING).unwrap().to_vec();
+        assert!(usm.expect(BER_OCTET_STRING) == Some(&b""[..]));
+        assert!(usm.expect(BER_OCTET_STRING) == Some(&b""[..]));
+        assert!(usm.is_empty());
+        let mut scoped = BerReader::new(message.expect(BER_SEQUENCE).unwrap());
+        assert!(message.is_empty());
+        let context_engine_id = scoped.expect(BER_OCTET_STRING).unwrap().to_vec();
+        assert!(scoped.expect(BER_OCTET_STRING) == Some(&b""[..]));
+        let mut pdu = BerReader::new(scoped.expect(SNMP_PDU_REPORT).unwrap());
+        let request_id = pdu.integer().unwrap();
+        assert!(pdu.integer() == Some(0) && pdu.integer() == Some(0));
+        let mut list = BerReader::new(pdu.expect(BER_SEQUENCE).unwrap());
+        let mut varbind = BerReader::new(list.expect(BER_SEQUENCE).unwrap());
+        assert!(list.is_empty());
+        let oid = ber_oid_string(&ber_decode_oid(varbind.expect(BER_OID).unwrap()).unwrap());
+        let (tag, value) = varbind.read().unwrap();
+        Report {
+            msg_id,
+            flags,
+            engine_id,
+            engine_boots,
+            user,
+            context_engine_id,
+            request_id,
+            oid,
+            tag,
+            count: ber_decode_integer(value).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_snmp_v3_discovery() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 161);
+        let r = report(&repl(SNMPWALK_V3_DISCOVERY, &masscanned, &mut client_info).unwrap());
+        assert!(r.msg_id == 0x4a69 && r.request_id == 0x37f0);
+        assert!(r.flags == b"\x00");
+        /* Net-SNMP, random */
+        assert!(r.engine_id.len() == 13 && r.engine_id[..5] == b"\x80\x00\x1f\x88\x80"[..]);
+        assert!(r.context_engine_id == r.engine_id);
+        assert!(r.engine_boots == 1 && r.user.is_empty());
+        assert!(r.oid == "1.3.6.1.6.3.15.1.1.4.0" && r.tag == SNMP_COUNTER);
+        /* the engine ID is stable, the counter increments */
+        let r2 = report(&repl(SNMPWALK_V3_DISCOVERY, &masscanned, &mut client_info).unwrap());
+        assert!(r2.engine_id == r.engine_id && r2.count > r.count);
+        /* the authenticated request that follows: unknown user */
+        let data = v3_request(
+            SNMP_FLAG_AUTH | SNMP_FLAG_REPORTABLE,
+            &r.engine_id,
+            b"admin",
+        );
+        let r3 = report(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(r3.msg_id == 0x4a6a && r3.request_id == 1234);
+        assert!(r3.user == b"admin" && r3.flags == b"\x00");
+        assert!(r3.oid == "1.3.6.1.6.3.15.1.1.3.0" && r3.count > 0);
+        /* encrypted PDU: the request ID is unknown */
+        let data = v3_request(
+            SNMP_FLAG_AUTH | SNMP_FLAG_PRIV | SNMP_FLAG_REPORTABLE,
+            &r.engine_id,
+            b"admin",
+        );
+        let r4 = report(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(r4.request_id == 0 && r4.oid == "1.3.6.1.6.3.15.1.1.3.0");
+        /* another engine, with a long user name (long BER lengths) */
+        let user = vec![b'u'; 300];
+        let data = v3_request(
+            SNMP_FLAG_REPORTABLE,
+            b"\x80\x00\x00\x09\x03\x00\x00\x0c\x07\xac\x01",
+            &user,
+        );
+        let r5 = report(&repl(&data, &masscanned, &mut client_info).unwrap());
+        assert!(r5.oid == "1.3.6.1.6.3.15.1.1.4.0" && r5.user == user);
+        /* no report for messages that are not reportable */
+        let data = v3_request(SNMP_FLAG_AUTH, &r.engine_id, b"admin");
+        assert!(repl(&data, &masscanned, &mut client_info).is_none());
+        /* truncated messages */
+        for i in 0..SNMPWALK_V3_DISCOVERY.len() {
+            assert!(repl(&SNMPWALK_V3_DISCOVERY[..i], &masscanned, &mut client_info).is_none());
+        }
+        /* other security models */
+        let mut data = SNMPWALK_V3_DISCOVERY.to_vec();
+        data[21] = 2;
+        assert!(repl(&data, &masscanned, &mut client_info).is_none());
+    }
+
+    #[test]
+    fn test_snmp_v3_engine_id() {
+        let mut client_info = client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 161);
+        for (config, engine_id) in [
+            (
+                "[snmp]\nengine_format = \"mac\"\n",
+                &b"\x80\x00\x1f\x88\x03\x00\x11\x22\x33\x44\x55"[..],
+            ),
+            (
+                "[snmp]\nengine_format = \"ip\"\nengine_enterprise = 9\n",
+                b"\x80\x00\x00\x09\x01\xc6\x33\x64\x07",
+            ),
+            (
+                "[snmp]\nengine_format = \"text\"\nengine_text = \"router\"\n",
+                b"\x80\x00\x1f\x88\x04router",
+            ),
+            (
+                "[snmp]\nengine_id = \"800007e580b5a3ef2c\"\n",
+                b"\x80\x00\x07\xe5\x80\xb5\xa3\xef\x2c",
+            ),
+        ]
+        .iter()
+        {
+            let mut config = Config::from_str(config).expect("error parsing config");
+            config.snmp.engine_boots = 7;
+            let masscanned = masscanned(config);
+            let r = report(&repl(SNMPWALK_V3_DISCOVERY, &masscanned, &mut client_info).unwrap());
+            assert!(r.engine_id == *engine_id && r.engine_boots == 7);
+        }
+        /* the IPv6 address probed */
+        let masscanned = self::masscanned(
+            Config::from_str("[snmp]\nengine_format = \"ip\"\n").expect("error parsing config"),
+        );
+        client_info.ip.dst = Some(IpAddr::from_str("2001:db8::7").unwrap());
+        let r = report(&repl(SNMPWALK_V3_DISCOVERY, &masscanned, &mut client_info).unwrap());
+        assert!(r.engine_id[..5] == b"\x80\x00\x1f\x88\x02"[..] && r.engine_id.len() == 21);
+        /* invalid configurations */
+        for config in [
+            "[snmp]\nengine_id = \"8000\"\n",
+            "[snmp]\nengine_id = \"800007e580b5a3ef2g\"\n",
+            "[snmp]\nengine_enterprise = 2147483648\n",
+            "[snmp]\nengine_format = \"text\"\n",
+            "[snmp]\nengine_format = \"serial\"\n",
+        ]
+        .iter()
+        {
+            assert!(Config::from_str(config).is_err());
+        }
+    }
+}
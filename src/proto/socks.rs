@@ -0,0 +1,589 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* SOCKS 4 (and 4a) */
+const SOCKS4_VERSION: u8 = 4;
+const SOCKS4_GRANTED: u8 = 0x5a;
+const SOCKS4_REJECTED: u8 = 0x5b;
+
+/* SOCKS 5 (RFC 1928, RFC 1929) */
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_NO_AUTH: u8 = 0x00;
+const SOCKS5_USER_PASS: u8 = 0x02;
+const SOCKS5_NO_ACCEPTABLE: u8 = 0xff;
+const SOCKS5_USER_PASS_VERSION: u8 = 1;
+const SOCKS5_ATYP_IPV4: u8 = 1;
+const SOCKS5_ATYP_DOMAIN: u8 = 3;
+const SOCKS5_ATYP_IPV6: u8 = 4;
+const SOCKS5_SUCCEEDED: u8 = 0x00;
+const SOCKS5_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const SOCKS5_ATYP_NOT_SUPPORTED: u8 = 0x08;
+
+const SOCKS_CONNECT: u8 = 1;
+
+/* size of a request kept between two segments */
+const SOCKS_MAX_BUFFER: usize = 1024;
+
+/* SOCKS proxy (on the TCP ports): CONNECT requests succeed (username /
+ * password authentication is chosen when offered), and with tunnel,
+ * what the client sends next is answered as if it had been sent to the
+ * target.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocksConfig {
+    pub ports: Vec<u16>,
+    pub tunnel: bool,
+}
+
+impl Default for SocksConfig {
+    fn default() -> Self {
+        SocksConfig {
+            ports: vec![1080],
+            tunnel: false,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum SocksStage {
+    Greeting,
+    Auth,
+    Request,
+    Tunnel,
+}
+
+/* state of a flow: stage of the negotiation, beginning of a message not
+ * received entirely yet, and the flow to the target once connected */
+pub struct SocksState {
+    stage: SocksStage,
+    buffer: Vec<u8>,
+    target: (Option<IpAddr>, u16),
+    tunnel: Box<TCPControlBlock>,
+}
+
+impl SocksState {
+    fn new() -> Self {
+        SocksState {
+            stage: SocksStage::Greeting,
+            buffer: Vec::new(),
+            target: (None, 0),
+            tunnel: Box::new(TCPControlBlock::new()),
+        }
+    }
+}
+
+/* message parsed: Ok(None) when data does not hold an entire message,
+ * Err(()) when it is not valid */
+type SocksParse<T> = Result<Option<(T, usize)>, ()>;
+
+/* NUL-terminated string, from offset */
+fn socks_cstring(data: &[u8], offset: usize) -> SocksParse<String> {
+    match data
+        .get(offset..)
+        .and_then(|d| d.iter().position(|c| *c == 0))
+    {
+        Some(n) => Ok(Some((
+            String::from_utf8_lossy(&data[offset..offset + n]).to_string(),
+            offset + n + 1,
+        ))),
+        None if data.len() > SOCKS_MAX_BUFFER => Err(()),
+        None => Ok(None),
+    }
+}
+
+/* SOCKS 5 address: type, address and port */
+fn socks5_address(data: &[u8]) -> SocksParse<(u8, String, Option<IpAddr>, u16)> {
+    let atyp = match data.first() {
+        Some(a) => *a,
+        None => return Ok(None),
+    };
+    let (address, ip, offset) = match atyp {
+        SOCKS5_ATYP_IPV4 => match data.get(1..5) {
+            Some(a) => {
+                let ip = Ipv4Addr::new(a[0], a[1], a[2], a[3]);
+                (ip.to_string(), Some(IpAddr::V4(ip)), 5)
+            }
+            None => return Ok(None),
+        },
+        SOCKS5_ATYP_DOMAIN => match data.get(1).and_then(|l| data.get(2..2 + *l as usize)) {
+            Some(name) => (
+                String::from_utf8_lossy(name).to_string(),
+                None,
+                2 + name.len(),
+            ),
+            None => return Ok(None),
+        },
+        SOCKS5_ATYP_IPV6 => match data.get(1..17) {
+            Some(a) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(a);
+                let ip = Ipv6Addr::from(octets);
+                (ip.to_string(), Some(IpAddr::V6(ip)), 17)
+            }
+            None => return Ok(None),
+        },
+        _ => return Err(()),
+    };
+    match data.get(offset..offset + 2) {
+        Some(p) => Ok(Some((
+            (atyp, address, ip, u16::from_be_bytes([p[0], p[1]])),
+            offset + 2,
+        ))),
+        None => Ok(None),
+    }
+}
+
+fn socks_connect_event(
+    version: u8,
+    command: u8,
+    address: &str,
+    port: u16,
+    client_info: &ClientInfo,
+) -> Event {
+    warn!("SOCKS{} connection to {}:{}", version, address, port);
+    let mut event = Event::new("socks_connect", client_info);
+    event.set("version", version);
+    event.set("command", command);
+    event.set("address", address);
+    event.set("port", port);
+    event
+}
+
+/* SOCKS 4 request: VN, CD, DSTPORT, DSTIP, USERID, NUL, and (SOCKS 4a,
+ * for DSTIP 0.0.0.x) the host name, NUL */
+fn socks4_request(
+    data: &[u8],
+    state: &mut SocksState,
+    client_info: &mut ClientInfo,
+) -> SocksParse<Vec<u8>> {
+    if data.len() < 8 {
+        return Ok(None);
+    }
+    let command = data[1];
+    let port = u16::from_be_bytes([data[2], data[3]]);
+    let ip = Ipv4Addr::new(data[4], data[5], data[6], data[7]);
+    let (userid, mut len) = match socks_cstring(data, 8)? {
+        Some(u) => u,
+        None => return Ok(None),
+    };
+    let socks4a = data[4..7] == [0, 0, 0] && data[7] != 0;
+    let (address, target) = if socks4a {
+        match socks_cstring(data, len)? {
+            Some((hostname, l)) => {
+                len = l;
+                (hostname, None)
+            }
+            None => return Ok(None),
+        }
+    } else {
+        (ip.to_string(), Some(IpAddr::V4(ip)))
+    };
+    let mut event = socks_connect_event(SOCKS4_VERSION, command, &address, port, client_info);
+    event.set("address_type", if socks4a { "domain" } else { "ipv4" });
+    event.set("userid", userid);
+    event.log();
+    let status = if command == SOCKS_CONNECT {
+        state.target = (target, port);
+        state.stage = SocksStage::Tunnel;
+        SOCKS4_GRANTED
+    } else {
+        client_info.close = true;
+        SOCKS4_REJECTED
+    };
+    let mut repl = vec![0, status];
+    repl.extend_from_slice(&data[2..8]);
+    Ok(Some((repl, len)))
+}
+
+/* SOCKS 5 greeting: VER, NMETHODS, METHODS */
+fn socks5_greeting(
+    data: &[u8],
+    state: &mut SocksState,
+    client_info: &mut ClientInfo,
+) -> SocksParse<Vec<u8>> {
+    let methods = match data.get(1).and_then(|n| data.get(2..2 + *n as usize)) {
+        Some(m) => m,
+        None => return Ok(None),
+    };
+    let method = if methods.contains(&SOCKS5_USER_PASS) {
+        state.stage = SocksStage::Auth;
+        SOCKS5_USER_PASS
+    } else if methods.contains(&SOCKS5_NO_AUTH) {
+        state.stage = SocksStage::Request;
+        SOCKS5_NO_AUTH
+    } else {
+        client_info.close = true;
+        SOCKS5_NO_ACCEPTABLE
+    };
+    Ok(Some((vec![SOCKS5_VERSION, method], 2 + methods.len())))
+}
+
+/* RFC 1929: VER, ULEN, UNAME, PLEN, PASSWD */
+fn socks5_auth(
+    data: &[u8],
+    state: &mut SocksState,
+    client_info: &mut ClientInfo,
+) -> SocksParse<Vec<u8>> {
+    if data.first().is_some_and(|v| *v != SOCKS5_USER_PASS_VERSION) {
+        return Err(());
+    }
+    let user = match data.get(1).and_then(|l| data.get(2..2 + *l as usize)) {
+        Some(u) => u,
+        None => return Ok(None),
+    };
+    let offset = 2 + user.len();
+    let password = match data
+        .get(offset)
+        .and_then(|l| data.get(offset + 1..offset + 1 + *l as usize))
+    {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    let len = offset + 1 + password.len();
+    let user = String::from_utf8_lossy(user).to_string();
+    let password = String::from_utf8_lossy(password).to_string();
+    warn!("SOCKS5 login: {}:{}", user, password);
+    let mut event = Event::new("socks_login", client_info);
+    event.set("user", user);
+    event.set("password", password.as_str());
+    event.log();
+    state.stage = SocksStage::Request;
+    Ok(Some((vec![SOCKS5_USER_PASS_VERSION, 0], len)))
+}
+
+/* SOCKS 5 request: VER, CMD, RSV, ATYP, DST.ADDR, DST.PORT - replies
+ * are bound to 0.0.0.0:0 */
+fn socks5_request(
+    data: &[u8],
+    state: &mut SocksState,
+    client_info: &mut ClientInfo,
+) -> SocksParse<Vec<u8>> {
+    if data.len() < 4 {
+        return Ok(None);
+    }
+    let command = data[1];
+    let mut repl = vec![
+        SOCKS5_VERSION,
+        SOCKS5_SUCCEEDED,
+        0,
+        SOCKS5_ATYP_IPV4,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+    ];
+    let ((atyp, address, ip, port), len) = match socks5_address(&data[3..]) {
+        Ok(Some(a)) => a,
+        Ok(None) => return Ok(None),
+        Err(()) => {
+            repl[1] = SOCKS5_ATYP_NOT_SUPPORTED;
+            client_info.close = true;
+            return Ok(Some((repl, data.len())));
+        }
+    };
+    let mut event = socks_connect_event(SOCKS5_VERSION, command, &address, port, client_info);
+    event.set(
+        "address_type",
+        match atyp {
+            SOCKS5_ATYP_IPV4 => "ipv4",
+            SOCKS5_ATYP_DOMAIN => "domain",
+            _ => "ipv6",
+        },
+    );
+    event.log();
+    if command == SOCKS_CONNECT {
+        state.target = (ip, port);
+        state.stage = SocksStage::Tunnel;
+    } else {
+        repl[1] = SOCKS5_COMMAND_NOT_SUPPORTED;
+        client_info.close = true;
+    }
+    Ok(Some((repl, 3 + len)))
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving SOCKS data");
+    let config = &masscanned.config.socks;
+    let t = tcb?;
+    let mut state = match std::mem::replace(&mut t.proto_state, ProtoState::None) {
+        ProtoState::Socks(s) => s,
+        _ => SocksState::new(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while !state.buffer.is_empty() && state.stage != SocksStage::Tunnel {
+        let buffer = std::mem::take(&mut state.buffer);
+        let parsed = match (&state.stage, buffer[0]) {
+            (SocksStage::Greeting, SOCKS4_VERSION) => {
+                socks4_request(&buffer, &mut state, client_info)
+            }
+            (SocksStage::Greeting, SOCKS5_VERSION) => {
+                socks5_greeting(&buffer, &mut state, client_info)
+            }
+            (SocksStage::Auth, _) => socks5_auth(&buffer, &mut state, client_info),
+            (SocksStage::Request, SOCKS5_VERSION) => {
+                socks5_request(&buffer, &mut state, client_info)
+            }
+            _ => Err(()),
+        };
+        state.buffer = buffer;
+        match parsed {
+            Ok(Some((repl, len))) => {
+                state.buffer.drain(..len);
+                repl_data.extend(repl);
+                if client_info.close {
+                    break;
+                }
+            }
+            Ok(None) => {
+                if state.buffer.len() > SOCKS_MAX_BUFFER {
+                    client_info.close = true;
+                }
+                break;
+            }
+            Err(()) => {
+                info!("SOCKS: invalid message - dropped");
+                client_info.close = true;
+                break;
+            }
+        }
+    }
+    if state.stage == SocksStage::Tunnel && !state.buffer.is_empty() && !client_info.close {
+        let data = std::mem::take(&mut state.buffer);
+        if config.tunnel {
+            /* answered as data sent to the target */
+            let mut target = *client_info;
+            if let Some(ip) = state.target.0 {
+                target.ip.dst = Some(ip);
+            }
+            target.port.dst = Some(state.target.1);
+            if let Some(repl) =
+                crate::proto::dispatch(&data, masscanned, &mut target, Some(&mut state.tunnel))
+            {
+                repl_data.extend(repl);
+            }
+            client_info.close |= target.close;
+        } else {
+            debug!("SOCKS: {} bytes for the target - ignored", data.len());
+        }
+    }
+    t.proto_state = ProtoState::Socks(state);
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending SOCKS data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    fn instance(tunnel: bool) -> Masscanned<'static> {
+        let mut config = Config::default();
+        config.socks.tunnel = tunnel;
+        crate::proto::test_util::masscanned(config)
+    }
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(1080);
+        client_info
+    }
+
+    #[test]
+    fn test_socks5() {
+        let masscanned = instance(false);
+        for (request, target) in [
+            (
+                &b"\x05\x01\x00\x01\x5d\xb8\xd8\x22\x00\x50"[..],
+                (Some(IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34))), 80),
+            ),
+            (b"\x05\x01\x00\x03\x0bexample.com\x01\xbb", (None, 443)),
+            (
+                b"\x05\x01\x00\x04\x20\x01\x0d\xb8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x00\x16",
+                (Some(IpAddr::V6(Ipv6Addr::from_str("2001:db8::1").unwrap())), 22),
+            ),
+        ]
+        .iter()
+        {
+            /* no authentication offered */
+            let mut client_info = client();
+            let mut tcb = TCPControlBlock::new();
+            let repl_data = crate::proto::dispatch(
+                b"\x05\x01\x00",
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb),
+            )
+            .unwrap();
+            assert!(repl_data == b"\x05\x00");
+            /* request split across two segments */
+            assert!(crate::proto::dispatch(
+                &request[..5],
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb)
+            )
+            .is_none());
+            let repl_data = crate::proto::dispatch(
+                &request[5..],
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb),
+            )
+            .unwrap();
+            assert!(repl_data == b"\x05\x00\x00\x01\x00\x00\x00\x00\x00\x00");
+            assert!(!client_info.close);
+            match &tcb.proto_state {
+                ProtoState::Socks(state) => {
+                    assert!(state.stage == SocksStage::Tunnel && state.target == *target)
+                }
+                _ => panic!("no SOCKS state"),
+            }
+        }
+        /* username / password, then BIND (not supported) */
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = crate::proto::dispatch(
+            b"\x05\x02\x00\x02\x01\x05admin\x06secret",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"\x05\x02\x01\x00");
+        let repl_data = crate::proto::dispatch(
+            b"\x05\x02\x00\x01\x00\x00\x00\x00\x00\x00",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data[1] == SOCKS5_COMMAND_NOT_SUPPORTED && client_info.close);
+        /* no acceptable method */
+        let mut client_info = client();
+        let repl_data = repl(
+            b"\x05\x01\x01",
+            &masscanned,
+            &mut client_info,
+            Some(&mut TCPControlBlock::new()),
+        )
+        .unwrap();
+        assert!(repl_data == b"\x05\xff" && client_info.close);
+    }
+
+    #[test]
+    fn test_socks5_binary_credentials() {
+        /* non-UTF-8 credentials: the length consumed is that of the raw
+         * bytes, the request that follows is handled */
+        let masscanned = instance(false);
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = crate::proto::dispatch(
+            b"\x05\x01\x02\x01\x04\xff\xff\xff\xff\x03\xff\xfe\xff\x05\x01\x00\x01\x5d\xb8\xd8\x22\x00\x50",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"\x05\x02\x01\x00\x05\x00\x00\x01\x00\x00\x00\x00\x00\x00");
+        match &tcb.proto_state {
+            ProtoState::Socks(state) => assert!(state.stage == SocksStage::Tunnel),
+            _ => panic!("no SOCKS state"),
+        }
+    }
+
+    #[test]
+    fn test_socks4() {
+        let masscanned = instance(false);
+        /* SOCKS 4 (userid "root"), SOCKS 4a (host name) */
+        for request in [
+            &b"\x04\x01\x00\x50\x5d\xb8\xd8\x22root\x00"[..],
+            b"\x04\x01\x00\x50\x00\x00\x00\x01\x00example.com\x00",
+        ]
+        .iter()
+        {
+            let mut client_info = client();
+            let mut tcb = TCPControlBlock::new();
+            assert!(crate::proto::dispatch(
+                &request[..request.len() - 2],
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb)
+            )
+            .is_none());
+            let repl_data = crate::proto::dispatch(
+                &request[request.len() - 2..],
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb),
+            )
+            .unwrap();
+            assert!(repl_data[..2] == [0, SOCKS4_GRANTED] && repl_data[2..] == request[2..8]);
+        }
+        /* BIND */
+        let mut client_info = client();
+        let repl_data = repl(
+            b"\x04\x02\x00\x50\x5d\xb8\xd8\x22\x00",
+            &masscanned,
+            &mut client_info,
+            Some(&mut TCPControlBlock::new()),
+        )
+        .unwrap();
+        assert!(repl_data[1] == SOCKS4_REJECTED && client_info.close);
+    }
+
+    #[test]
+    fn test_socks_tunnel() {
+        /* the HTTP request goes to the target */
+        let request =
+            b"\x04\x01\x00\x50\x5d\xb8\xd8\x22\x00GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        for tunnel in [false, true].iter() {
+            let masscanned = instance(*tunnel);
+            let mut client_info = client();
+            let mut tcb = TCPControlBlock::new();
+            let repl_data =
+                crate::proto::dispatch(request, &masscanned, &mut client_info, Some(&mut tcb))
+                    .unwrap();
+            assert!(repl_data[..2] == [0, SOCKS4_GRANTED]);
+            assert!(repl_data[8..].starts_with(b"HTTP/1.1 ") == *tunnel);
+            assert!(repl_data.len() == 8 || *tunnel);
+        }
+    }
+}
@@ -14,23 +14,820 @@
 // You should have received a copy of the GNU General Public License
 // along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
 
+use lazy_static::lazy_static;
 use log::*;
 
+use std::io;
 use std::str;
 
+use md5::{Digest, Md5};
+use serde::Deserialize;
+
 use crate::client::ClientInfo;
+use crate::config::select_by_ip;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
 use crate::Masscanned;
 
 pub const SSH_PATTERN_CLIENT_PROTOCOL: &[u8; 7] = b"SSH-2.0";
 
-pub fn repl<'a>(
+const SSH_MSG_KEXINIT: u8 = 20;
+
+/* RFC 4253 section 4.2 */
+const SSH_MAX_VERSION_LEN: usize = 255;
+/* RFC 4253 section 6.1 */
+const SSH_MAX_PACKET_LEN: usize = 35000;
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SshConfig {
+    /* personas to choose from - the default one if empty */
+    pub persona: Vec<SshPersona>,
+    /* choose the persona from the destination IP address (otherwise,
+     * the first one is always used) */
+    pub persona_by_ip: bool,
+}
+
+/* What the server looks like: identification string (without CR LF)
+ * and algorithms advertised in our KEXINIT (default: OpenSSH 8.x).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SshPersona {
+    pub banner: String,
+    pub kex_algorithms: Vec<String>,
+    pub host_key_algorithms: Vec<String>,
+    pub ciphers: Vec<String>,
+    pub macs: Vec<String>,
+    pub compression: Vec<String>,
+}
+
+fn ssh_list(l: &[&str]) -> Vec<String> {
+    l.iter().map(|s| s.to_string()).collect()
+}
+
+impl Default for SshPersona {
+    fn default() -> Self {
+        SshPersona {
+            banner: "SSH-2.0-1".to_string(),
+            kex_algorithms: ssh_list(&[
+                "curve25519-sha256",
+                "curve25519-sha256@libssh.org",
+                "ecdh-sha2-nistp256",
+                "ecdh-sha2-nistp384",
+                "ecdh-sha2-nistp521",
+                "diffie-hellman-group-exchange-sha256",
+                "diffie-hellman-group16-sha512",
+                "diffie-hellman-group18-sha512",
+                "diffie-hellman-group14-sha256",
+            ]),
+            host_key_algorithms: ssh_list(&[
+                "rsa-sha2-512",
+                "rsa-sha2-256",
+                "ssh-rsa",
+                "ecdsa-sha2-nistp256",
+                "ssh-ed25519",
+            ]),
+            ciphers: ssh_list(&[
+                "chacha20-poly1305@openssh.com",
+                "aes128-ctr",
+                "aes192-ctr",
+                "aes256-ctr",
+                "aes128-gcm@openssh.com",
+                "aes256-gcm@openssh.com",
+            ]),
+            macs: ssh_list(&[
+                "umac-64-etm@openssh.com",
+                "umac-128-etm@openssh.com",
+                "hmac-sha2-256-etm@openssh.com",
+                "hmac-sha2-512-etm@openssh.com",
+                "hmac-sha1-etm@openssh.com",
+                "umac-64@openssh.com",
+                "umac-128@openssh.com",
+                "hmac-sha2-256",
+                "hmac-sha2-512",
+                "hmac-sha1",
+            ]),
+            compression: ssh_list(&["none", "zlib@openssh.com"]),
+        }
+    }
+}
+
+fn ssh_invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("ssh: {}", msg))
+}
+
+/* RFC 4253 section 4.2: SSH-protoversion-softwareversion SP comments,
+ * printable US-ASCII, at most 255 characters with CR LF */
+fn ssh_check_banner(banner: &str) -> Result<(), io::Error> {
+    if banner.len() + 2 > SSH_MAX_VERSION_LEN {
+        return Err(ssh_invalid(format!("banner too long: {}", banner)));
+    }
+    if !banner.bytes().all(|c| (0x20..0x7f).contains(&c)) {
+        return Err(ssh_invalid(format!(
+            "banner must be printable ASCII: {:?}",
+            banner
+        )));
+    }
+    let version = match ssh_parse_version(banner) {
+        Some(v) => v,
+        None => return Err(ssh_invalid(format!("invalid banner: {}", banner))),
+    };
+    if version.proto_version != "2.0" && version.proto_version != "1.99" {
+        return Err(ssh_invalid(format!(
+            "unsupported protocol version: {}",
+            banner
+        )));
+    }
+    if version.software.is_empty() || version.software.contains('-') {
+        return Err(ssh_invalid(format!("invalid software version: {}", banner)));
+    }
+    Ok(())
+}
+
+/* RFC 4251 section 6: algorithm names are printable US-ASCII, without
+ * comma nor whitespace, at most 64 characters */
+fn ssh_check_algorithms(algorithms: &[String]) -> Result<(), io::Error> {
+    if algorithms.is_empty() {
+        return Err(ssh_invalid("empty algorithm list".to_string()));
+    }
+    for a in algorithms {
+        if a.is_empty()
+            || a.len() > 64
+            || !a.bytes().all(|c| (0x21..0x7f).contains(&c) && c != b',')
+        {
+            return Err(ssh_invalid(format!("invalid algorithm name: {:?}", a)));
+        }
+    }
+    Ok(())
+}
+
+impl SshConfig {
+    /* check the personas (and remove the end of line of the banners) */
+    pub fn check(&mut self) -> Result<(), io::Error> {
+        for persona in self.persona.iter_mut() {
+            let len = persona.banner.trim_end_matches(['\r', '\n']).len();
+            persona.banner.truncate(len);
+            ssh_check_banner(&persona.banner)?;
+            for algorithms in [
+                &persona.kex_algorithms,
+                &persona.host_key_algorithms,
+                &persona.ciphers,
+                &persona.macs,
+                &persona.compression,
+            ]
+            .iter()
+            {
+                ssh_check_algorithms(algorithms)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref SSH_DEFAULT_PERSONA: SshPersona = SshPersona::default();
+}
+
+fn ssh_persona<'a>(config: &'a SshConfig, client_info: &ClientInfo) -> &'a SshPersona {
+    let ip = if config.persona_by_ip {
+        client_info.ip.dst
+    } else {
+        None
+    };
+    select_by_ip(&config.persona, ip).unwrap_or(&SSH_DEFAULT_PERSONA)
+}
+
+/* state of an SSH flow: data not handled yet, and step of the
+ * exchange */
+pub struct SshState {
+    buffer: Vec<u8>,
+    client_version: Option<String>,
+    kexinit_done: bool,
+}
+
+impl SshState {
+    pub fn new() -> Self {
+        SshState {
+            buffer: Vec::new(),
+            client_version: None,
+            kexinit_done: false,
+        }
+    }
+}
+
+/* client identification string (RFC 4253 section 4.2):
+ * SSH-protoversion-softwareversion SP comments */
+struct SshVersion<'a> {
+    proto_version: &'a str,
+    software: &'a str,
+    comments: Option<&'a str>,
+}
+
+fn ssh_parse_version(line: &str) -> Option<SshVersion<'_>> {
+    let mut parts = line.strip_prefix("SSH-")?.splitn(2, '-');
+    let proto_version = parts.next()?;
+    let mut rest = parts.next()?.splitn(2, ' ');
+    Some(SshVersion {
+        proto_version,
+        software: rest.next()?,
+        comments: rest.next(),
+    })
+}
+
+/* KEXINIT fields (RFC 4253 section 7.1) */
+pub struct KexInit {
+    pub kex_algorithms: String,
+    pub host_key_algorithms: String,
+    pub encryption_c2s: String,
+    pub encryption_s2c: String,
+    pub mac_c2s: String,
+    pub mac_s2c: String,
+    pub compression_c2s: String,
+    pub compression_s2c: String,
+    pub first_kex_follows: bool,
+}
+
+/* bounds-checked reader of SSH fields */
+struct SshReader<'a> {
     data: &'a [u8],
-    _masscanned: &Masscanned,
-    mut _client_info: &mut ClientInfo,
+    pos: usize,
+}
+
+impl<'a> SshReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        SshReader { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return None;
+        }
+        self.pos += len;
+        Some(&self.data[self.pos - len..self.pos])
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn name_list(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        str::from_utf8(self.bytes(len)?).ok().map(|s| s.to_string())
+    }
+}
+
+impl KexInit {
+    /* payload of a KEXINIT message (with the message type) */
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let mut r = SshReader::new(payload);
+        if r.u8()? != SSH_MSG_KEXINIT {
+            return None;
+        }
+        /* cookie */
+        r.bytes(16)?;
+        let kexinit = KexInit {
+            kex_algorithms: r.name_list()?,
+            host_key_algorithms: r.name_list()?,
+            encryption_c2s: r.name_list()?,
+            encryption_s2c: r.name_list()?,
+            mac_c2s: r.name_list()?,
+            mac_s2c: r.name_list()?,
+            compression_c2s: r.name_list()?,
+            compression_s2c: r.name_list()?,
+            first_kex_follows: {
+                /* languages */
+                r.name_list()?;
+                r.name_list()?;
+                r.u8()? != 0
+            },
+        };
+        Some(kexinit)
+    }
+
+    /* HASSH: algorithms offered by the client */
+    pub fn hassh_algorithms(&self) -> String {
+        format!(
+            "{};{};{};{}",
+            self.kex_algorithms, self.encryption_c2s, self.mac_c2s, self.compression_c2s
+        )
+    }
+
+    pub fn hassh(&self) -> String {
+        Md5::digest(self.hassh_algorithms().as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    pub fn event(&self, name: &'static str, client_info: &ClientInfo) -> Event {
+        let list = |l: &str| -> Vec<String> {
+            l.split(',')
+                .filter(|a| !a.is_empty())
+                .map(|a| a.to_string())
+                .collect()
+        };
+        let mut event = Event::new(name, client_info);
+        event.set("kex_algorithms", list(&self.kex_algorithms));
+        event.set("host_key_algorithms", list(&self.host_key_algorithms));
+        event.set("encryption_c2s", list(&self.encryption_c2s));
+        event.set("encryption_s2c", list(&self.encryption_s2c));
+        event.set("mac_c2s", list(&self.mac_c2s));
+        event.set("mac_s2c", list(&self.mac_s2c));
+        event.set("compression_c2s", list(&self.compression_c2s));
+        event.set("compression_s2c", list(&self.compression_s2c));
+        event.set("first_kex_follows", self.first_kex_follows);
+        event.set("hassh", self.hassh());
+        event.set("hassh_algorithms", self.hassh_algorithms());
+        event
+    }
+}
+
+enum SshPacket<'a> {
+    Incomplete,
+    Invalid,
+    Complete(&'a [u8]),
+}
+
+/* binary packet without encryption nor MAC (RFC 4253 section 6) */
+fn ssh_parse_packet(data: &[u8]) -> SshPacket<'_> {
+    let mut r = SshReader::new(data);
+    let len = match r.u32() {
+        Some(l) => l as usize,
+        None => return SshPacket::Incomplete,
+    };
+    if !(5..=SSH_MAX_PACKET_LEN).contains(&len) {
+        return SshPacket::Invalid;
+    }
+    let packet = match r.bytes(len) {
+        Some(p) => p,
+        None => return SshPacket::Incomplete,
+    };
+    let padding = packet[0] as usize;
+    if padding + 1 > len {
+        return SshPacket::Invalid;
+    }
+    SshPacket::Complete(&packet[1..len - padding])
+}
+
+fn ssh_packet(payload: &[u8]) -> Vec<u8> {
+    /* length, padding length, payload and padding must be a multiple
+     * of 8, with at least 4 bytes of padding */
+    let mut padding = 8 - (5 + payload.len()) % 8;
+    if padding < 4 {
+        padding += 8;
+    }
+    let mut packet = ((1 + payload.len() + padding) as u32)
+        .to_be_bytes()
+        .to_vec();
+    packet.push(padding as u8);
+    packet.extend_from_slice(payload);
+    packet.extend(vec![0; padding]);
+    packet
+}
+
+fn ssh_kexinit(persona: &SshPersona) -> Vec<u8> {
+    let mut payload = vec![SSH_MSG_KEXINIT];
+    payload.extend_from_slice(&rand::random::<[u8; 16]>());
+    for list in [
+        &persona.kex_algorithms,
+        &persona.host_key_algorithms,
+        &persona.ciphers,
+        &persona.ciphers,
+        &persona.macs,
+        &persona.macs,
+        &persona.compression,
+        &persona.compression,
+    ]
+    .iter()
+    {
+        let list = list.join(",");
+        payload.extend_from_slice(&(list.len() as u32).to_be_bytes());
+        payload.extend_from_slice(list.as_bytes());
+    }
+    /* languages, first_kex_packet_follows, reserved */
+    payload.extend_from_slice(&[0; 13]);
+    ssh_packet(&payload)
+}
+
+fn ssh_repl_state(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+    state: &mut SshState,
+    stream: bool,
 ) -> Option<Vec<u8>> {
-    debug!("receiving SSH data");
-    let repl_data = b"SSH-2.0-1\r\n".to_vec();
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    if state.client_version.is_none() {
+        let end = match state.buffer.iter().position(|&c| c == b'\n') {
+            Some(e) => e,
+            /* not over a stream (e.g., UDP): no more data to wait for */
+            None if !stream => state.buffer.len(),
+            None => {
+                if state.buffer.len() > SSH_MAX_VERSION_LEN {
+                    info!("SSH identification string too long - dropped");
+                    state.buffer.clear();
+                }
+                return None;
+            }
+        };
+        let line = String::from_utf8_lossy(&state.buffer[..end])
+            .trim_end_matches('\r')
+            .to_string();
+        state
+            .buffer
+            .drain(..std::cmp::min(end + 1, state.buffer.len()));
+        warn!("SSH server banner to {}", line);
+        let mut event = Event::new("ssh_client_version", client_info);
+        event.set("version", line.clone());
+        if let Some(v) = ssh_parse_version(&line) {
+            event.set("proto_version", v.proto_version);
+            event.set("software", v.software);
+            if let Some(comments) = v.comments {
+                event.set("comments", comments);
+            }
+        }
+        event.log();
+        state.client_version = Some(line);
+        let persona = ssh_persona(&masscanned.config.ssh, client_info);
+        repl_data.extend_from_slice(persona.banner.as_bytes());
+        repl_data.extend_from_slice(b"\r\n");
+        repl_data.extend(ssh_kexinit(persona));
+    }
+    if !state.kexinit_done {
+        match ssh_parse_packet(&state.buffer) {
+            SshPacket::Incomplete => {}
+            SshPacket::Invalid => {
+                info!("SSH data not handled (invalid packet)");
+                state.kexinit_done = true;
+            }
+            SshPacket::Complete(payload) => {
+                match KexInit::parse(payload) {
+                    Some(kexinit) => {
+                        warn!("SSH KEXINIT (HASSH {})", kexinit.hassh());
+                        let mut event = kexinit.event("ssh_kexinit", client_info);
+                        if let Some(v) = &state.client_version {
+                            event.set("version", v.clone());
+                        }
+                        event.log();
+                    }
+                    None => info!("SSH message not handled (not a KEXINIT)"),
+                }
+                /* we do not go further (no key exchange) */
+                state.kexinit_done = true;
+            }
+        }
+    }
+    if state.kexinit_done {
+        state.buffer.clear();
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
     debug!("sending SSH answer");
-    warn!("SSH server banner to {}", str::from_utf8(&data).unwrap().trim_end());
-    return Some(repl_data);
+    Some(repl_data)
+}
+
+/* Answer the client identification string with ours and our KEXINIT,
+ * and record the client identification string and KEXINIT (possibly
+ * received in the same segment, or in several ones).
+ **/
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving SSH data");
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Ssh(s)) => s,
+        _ => SshState::new(),
+    };
+    let repl_data = ssh_repl_state(data, masscanned, client_info, &mut state, tcb.is_some());
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Ssh(state);
+    }
+    repl_data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use std::str::FromStr;
+
+    fn from_hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /* identification string and KEXINIT sent by ssh (OpenSSH 9.2p1) */
+    const KEXINIT_OPENSSH: &str = "\
+        5353482d322e302d4f70656e5353485f392e3270312044656269616e2d322b646562313275360d0a\
+        000006140814266342d1bdce0304a162fcee16d1f5e600000148736e747275703736317832353531\
+        392d7368613531322c736e747275703736317832353531392d736861353132406f70656e7373682e\
+        636f6d2c637572766532353531392d7368613235362c637572766532353531392d73686132353640\
+        6c69627373682e6f72672c656364682d736861322d6e697374703235362c656364682d736861322d\
+        6e697374703338342c656364682d736861322d6e697374703532312c6469666669652d68656c6c6d\
+        616e2d67726f75702d65786368616e67652d7368613235362c6469666669652d68656c6c6d616e2d\
+        67726f757031362d7368613531322c6469666669652d68656c6c6d616e2d67726f757031382d7368\
+        613531322c6469666669652d68656c6c6d616e2d67726f757031342d7368613235362c6578742d69\
+        6e666f2d632c6b65782d7374726963742d632d763030406f70656e7373682e636f6d000001cf7373\
+        682d656432353531392d636572742d763031406f70656e7373682e636f6d2c65636473612d736861\
+        322d6e697374703235362d636572742d763031406f70656e7373682e636f6d2c65636473612d7368\
+        61322d6e697374703338342d636572742d763031406f70656e7373682e636f6d2c65636473612d73\
+        6861322d6e697374703532312d636572742d763031406f70656e7373682e636f6d2c736b2d737368\
+        2d656432353531392d636572742d763031406f70656e7373682e636f6d2c736b2d65636473612d73\
+        6861322d6e697374703235362d636572742d763031406f70656e7373682e636f6d2c7273612d7368\
+        61322d3531322d636572742d763031406f70656e7373682e636f6d2c7273612d736861322d323536\
+        2d636572742d763031406f70656e7373682e636f6d2c7373682d656432353531392c65636473612d\
+        736861322d6e697374703235362c65636473612d736861322d6e697374703338342c65636473612d\
+        736861322d6e697374703532312c736b2d7373682d65643235353139406f70656e7373682e636f6d\
+        2c736b2d65636473612d736861322d6e69737470323536406f70656e7373682e636f6d2c7273612d\
+        736861322d3531322c7273612d736861322d3235360000006c63686163686132302d706f6c793133\
+        3035406f70656e7373682e636f6d2c6165733132382d6374722c6165733139322d6374722c616573\
+        3235362d6374722c6165733132382d67636d406f70656e7373682e636f6d2c6165733235362d6763\
+        6d406f70656e7373682e636f6d0000006c63686163686132302d706f6c7931333035406f70656e73\
+        73682e636f6d2c6165733132382d6374722c6165733139322d6374722c6165733235362d6374722c\
+        6165733132382d67636d406f70656e7373682e636f6d2c6165733235362d67636d406f70656e7373\
+        682e636f6d000000d5756d61632d36342d65746d406f70656e7373682e636f6d2c756d61632d3132\
+        382d65746d406f70656e7373682e636f6d2c686d61632d736861322d3235362d65746d406f70656e\
+        7373682e636f6d2c686d61632d736861322d3531322d65746d406f70656e7373682e636f6d2c686d\
+        61632d736861312d65746d406f70656e7373682e636f6d2c756d61632d3634406f70656e7373682e\
+        636f6d2c756d61632d313238406f70656e7373682e636f6d2c686d61632d736861322d3235362c68\
+        6d61632d736861322d3531322c686d61632d73686131000000d5756d61632d36342d65746d406f70\
+        656e7373682e636f6d2c756d61632d3132382d65746d406f70656e7373682e636f6d2c686d61632d\
+        736861322d3235362d65746d406f70656e7373682e636f6d2c686d61632d736861322d3531322d65\
+        746d406f70656e7373682e636f6d2c686d61632d736861312d65746d406f70656e7373682e636f6d\
+        2c756d61632d3634406f70656e7373682e636f6d2c756d61632d313238406f70656e7373682e636f\
+        6d2c686d61632d736861322d3235362c686d61632d736861322d3531322c686d61632d7368613100\
+        00001a6e6f6e652c7a6c6962406f70656e7373682e636f6d2c7a6c69620000001a6e6f6e652c7a6c\
+        6962406f70656e7373682e636f6d2c7a6c6962000000000000000000000000000000000000000000";
+
+    /* identification string and KEXINIT sent by curl sftp:// (libssh2
+     * 1.10.0) */
+    const KEXINIT_LIBSSH2: &str = "\
+        5353482d322e302d6c6962737368325f312e31302e300d0a000004640514adf8ce9136cd7139f451\
+        a19f62afb39500000140637572766532353531392d7368613235362c637572766532353531392d73\
+        6861323536406c69627373682e6f72672c656364682d736861322d6e697374703235362c65636468\
+        2d736861322d6e697374703338342c656364682d736861322d6e697374703532312c646966666965\
+        2d68656c6c6d616e2d67726f75702d65786368616e67652d7368613235362c6469666669652d6865\
+        6c6c6d616e2d67726f757031362d7368613531322c6469666669652d68656c6c6d616e2d67726f75\
+        7031382d7368613531322c6469666669652d68656c6c6d616e2d67726f757031342d736861323536\
+        2c6469666669652d68656c6c6d616e2d67726f757031342d736861312c6469666669652d68656c6c\
+        6d616e2d67726f7570312d736861312c6469666669652d68656c6c6d616e2d67726f75702d657863\
+        68616e67652d73686131000000d265636473612d736861322d6e697374703235362c65636473612d\
+        736861322d6e697374703338342c65636473612d736861322d6e697374703532312c65636473612d\
+        736861322d6e697374703235362d636572742d763031406f70656e7373682e636f6d2c6563647361\
+        2d736861322d6e697374703338342d636572742d763031406f70656e7373682e636f6d2c65636473\
+        612d736861322d6e697374703532312d636572742d763031406f70656e7373682e636f6d2c737368\
+        2d656432353531392c7373682d7273612c7373682d647373000000926165733132382d6374722c61\
+        65733139322d6374722c6165733235362d6374722c6165733235362d6362632c72696a6e6461656c\
+        2d636263406c797361746f722e6c69752e73652c6165733139322d6362632c6165733132382d6362\
+        632c626c6f77666973682d6362632c617263666f75723132382c617263666f75722c636173743132\
+        382d6362632c336465732d636263000000926165733132382d6374722c6165733139322d6374722c\
+        6165733235362d6374722c6165733235362d6362632c72696a6e6461656c2d636263406c79736174\
+        6f722e6c69752e73652c6165733139322d6362632c6165733132382d6362632c626c6f7766697368\
+        2d6362632c617263666f75723132382c617263666f75722c636173743132382d6362632c33646573\
+        2d63626300000071686d61632d736861322d3235362c686d61632d736861322d3531322c686d6163\
+        2d736861312c686d61632d736861312d39362c686d61632d6d64352c686d61632d6d64352d39362c\
+        686d61632d726970656d643136302c686d61632d726970656d64313630406f70656e7373682e636f\
+        6d00000071686d61632d736861322d3235362c686d61632d736861322d3531322c686d61632d7368\
+        61312c686d61632d736861312d39362c686d61632d6d64352c686d61632d6d64352d39362c686d61\
+        632d726970656d643136302c686d61632d726970656d64313630406f70656e7373682e636f6d0000\
+        00046e6f6e65000000046e6f6e6500000000000000000000000000580e6694eb";
+
+    #[test]
+    fn test_ssh_version() {
+        let v = ssh_parse_version("SSH-2.0-OpenSSH_9.2p1 Debian-2+deb12u6").unwrap();
+        assert!(v.proto_version == "2.0");
+        assert!(v.software == "OpenSSH_9.2p1");
+        assert!(v.comments == Some("Debian-2+deb12u6"));
+        let v = ssh_parse_version("SSH-2.0-libssh2_1.10.0").unwrap();
+        assert!(v.software == "libssh2_1.10.0" && v.comments.is_none());
+        assert!(ssh_parse_version("SSH-2.0").is_none());
+        assert!(ssh_parse_version("HTTP/1.1").is_none());
+    }
+
+    #[test]
+    fn test_ssh_kexinit() {
+        for (capture, version, hassh, kex) in [
+            (
+                KEXINIT_OPENSSH,
+                &b"SSH-2.0-OpenSSH_9.2p1 Debian-2+deb12u6\r\n"[..],
+                "472b5de333ad665af5cbf10ff892c4df",
+                "sntrup761x25519-sha512,",
+            ),
+            (
+                KEXINIT_LIBSSH2,
+                &b"SSH-2.0-libssh2_1.10.0\r\n"[..],
+                "b4b8ae3d7241d2c1dc54b4df7e8c19d1",
+                "curve25519-sha256,",
+            ),
+        ]
+        .iter()
+        {
+            let data = from_hex(capture);
+            assert!(data.starts_with(version));
+            let payload = match ssh_parse_packet(&data[version.len()..]) {
+                SshPacket::Complete(p) => p,
+                _ => panic!("expected a complete packet"),
+            };
+            let kexinit = KexInit::parse(payload).unwrap();
+            assert!(kexinit.kex_algorithms.starts_with(kex));
+            assert!(kexinit.compression_c2s.starts_with("none"));
+            assert!(!kexinit.first_kex_follows);
+            assert!(kexinit.hassh() == *hassh);
+            let event = kexinit.event("ssh_kexinit", &ClientInfo::new());
+            assert!(event.fields["hassh"] == *hassh);
+            assert!(
+                event.fields["mac_c2s"][0] == "umac-64-etm@openssh.com"
+                    || event.fields["mac_c2s"][0] == "hmac-sha2-256"
+            );
+            /* incomplete packets */
+            for i in 0..(data.len() - version.len()) {
+                assert!(matches!(
+                    ssh_parse_packet(&data[version.len()..version.len() + i]),
+                    SshPacket::Incomplete
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_ssh_server_kexinit() {
+        let config = SshPersona::default();
+        let packet = ssh_kexinit(&config);
+        assert!(packet.len().is_multiple_of(8));
+        let payload = match ssh_parse_packet(&packet) {
+            SshPacket::Complete(p) => p,
+            _ => panic!("expected a complete packet"),
+        };
+        let kexinit = KexInit::parse(payload).unwrap();
+        assert!(kexinit.kex_algorithms == config.kex_algorithms.join(","));
+        assert!(kexinit.host_key_algorithms == config.host_key_algorithms.join(","));
+        assert!(kexinit.encryption_s2c == config.ciphers.join(","));
+        assert!(kexinit.mac_s2c == config.macs.join(","));
+        assert!(kexinit.compression_s2c == "none,zlib@openssh.com");
+        /* padding: at least 4 bytes, whatever the payload length */
+        for len in 0..20 {
+            let packet = ssh_packet(&vec![0; len]);
+            assert!(packet.len().is_multiple_of(8));
+            assert!(packet[4] >= 4);
+        }
+    }
+
+    #[test]
+    fn test_ssh_repl() {
+        let masscanned = masscanned(Config::default());
+        let data = from_hex(KEXINIT_OPENSSH);
+        let version_len = b"SSH-2.0-OpenSSH_9.2p1 Debian-2+deb12u6\r\n".len();
+        /* identification string and KEXINIT in one segment, or split
+         * anywhere */
+        for cut in [
+            data.len(),
+            10,
+            version_len - 1,
+            version_len,
+            version_len + 100,
+        ]
+        .iter()
+        {
+            let mut client_info = ClientInfo::new();
+            let mut tcb = TCPControlBlock::new();
+            let mut answer = Vec::new();
+            for segment in [&data[..*cut], &data[*cut..]].iter() {
+                if let Some(r) = repl(segment, &masscanned, &mut client_info, Some(&mut tcb)) {
+                    answer.extend(r);
+                }
+            }
+            assert!(answer.starts_with(b"SSH-2.0-1\r\n"));
+            assert!(matches!(
+                ssh_parse_packet(&answer[11..]),
+                SshPacket::Complete(_)
+            ));
+            match &tcb.proto_state {
+                ProtoState::Ssh(s) => {
+                    assert!(s.kexinit_done);
+                    assert!(s.buffer.is_empty());
+                    assert!(
+                        s.client_version.as_deref()
+                            == Some("SSH-2.0-OpenSSH_9.2p1 Debian-2+deb12u6")
+                    );
+                }
+                _ => panic!("expected an SSH state"),
+            }
+            /* nothing more is answered */
+            assert!(repl(
+                b"\x00\x00\x00\x0c",
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb)
+            )
+            .is_none());
+        }
+        /* not over a stream: no end of line needed */
+        let mut client_info = ClientInfo::new();
+        let answer = repl(b"SSH-2.0-Go", &masscanned, &mut client_info, None).unwrap();
+        assert!(answer.starts_with(b"SSH-2.0-1\r\n"));
+    }
+
+    #[test]
+    fn test_ssh_persona() {
+        use std::net::{IpAddr, Ipv4Addr};
+        let config = Config::from_str(
+            "\
+[ssh]
+persona_by_ip = true
+
+[[ssh.persona]]
+banner = \"SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.6\"
+
+[[ssh.persona]]
+banner = \"SSH-2.0-OpenSSH_7.4\\r\\n\"
+kex_algorithms = [\"diffie-hellman-group14-sha1\"]
+",
+        )
+        .expect("error parsing configuration");
+        /* end of line removed */
+        assert!(config.ssh.persona[1].banner == "SSH-2.0-OpenSSH_7.4");
+        let masscanned = Masscanned {
+            config,
+            ..self::masscanned(Config::default())
+        };
+        let mut banners = std::collections::HashSet::new();
+        for o in 0..=255 {
+            let mut client_info = ClientInfo::new();
+            client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, o)));
+            let answer = repl(b"SSH-2.0-Go\r\n", &masscanned, &mut client_info, None).unwrap();
+            let end = answer.iter().position(|&c| c == b'\n').unwrap();
+            let banner = answer[..end + 1].to_vec();
+            /* same address, same banner */
+            assert!(repl(b"SSH-2.0-Go\r\n", &masscanned, &mut client_info, None)
+                .unwrap()
+                .starts_with(&banner));
+            if banner == b"SSH-2.0-OpenSSH_7.4\r\n" {
+                match ssh_parse_packet(&answer[end + 1..]) {
+                    SshPacket::Complete(p) => {
+                        assert!(
+                            KexInit::parse(p).unwrap().kex_algorithms
+                                == "diffie-hellman-group14-sha1"
+                        )
+                    }
+                    _ => panic!("expected a complete packet"),
+                }
+            }
+            banners.insert(banner);
+        }
+        assert!(banners.len() == 2);
+        assert!(banners.contains(&b"SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.6\r\n"[..]));
+    }
+
+    #[test]
+    fn test_ssh_persona_invalid() {
+        for banner in [
+            "OpenSSH_8.9",
+            "SSH-1.5-OpenSSH_8.9",
+            "SSH-2.0-",
+            "SSH-2.0-Open-SSH",
+            "SSH-2.0-OpenSSH_8.9\\r\\nSSH-2.0-x",
+            "SSH-2.0-OpenSSH_8.9\\u00e9",
+            "SSH-2.0-OpenSSH_8.9\\tUbuntu",
+        ]
+        .iter()
+        {
+            let config = format!("[[ssh.persona]]\nbanner = \"{}\"\n", banner);
+            assert!(Config::from_str(&config).is_err(), "{}", banner);
+        }
+        let long = format!(
+            "[[ssh.persona]]\nbanner = \"SSH-2.0-{}\"\n",
+            "a".repeat(250)
+        );
+        assert!(Config::from_str(&long).is_err());
+        for algorithms in [
+            "[]",
+            "[\"\"]",
+            "[\"aes128-ctr,aes256-ctr\"]",
+            "[\"aes 128\"]",
+        ]
+        .iter()
+        {
+            let config = format!("[[ssh.persona]]\nciphers = {}\n", algorithms);
+            assert!(Config::from_str(&config).is_err(), "{}", algorithms);
+        }
+        assert!(Config::from_str("[[ssh.persona]]\nbanner = \"SSH-1.99-Cisco-1.25\"\n").is_err());
+        assert!(Config::from_str("[[ssh.persona]]\nbanner = \"SSH-1.99-Cisco_1.25 x\"\n").is_ok());
+    }
 }
@@ -0,0 +1,320 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::hash::Hasher;
+
+use log::*;
+use serde::Deserialize;
+use siphasher::sip::SipHasher24;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::synackcookie;
+use crate::Masscanned;
+
+/* Source engine query protocol (A2S): every packet starts with -1 */
+const A2S_HEADER: &[u8; 4] = b"\xff\xff\xff\xff";
+const A2S_INFO: u8 = b'T';
+const A2S_PLAYER: u8 = b'U';
+const A2S_RULES: u8 = b'V';
+const S2A_INFO: u8 = b'I';
+const S2A_PLAYER: u8 = b'D';
+const S2C_CHALLENGE: u8 = b'A';
+
+const A2S_INFO_PAYLOAD: &[u8; 20] = b"Source Engine Query\0";
+
+pub const A2S_PATTERN_INFO: &[u8; 25] = b"\xff\xff\xff\xffTSource Engine Query\0";
+
+/* extra data flag: game port */
+const A2S_EDF_PORT: u8 = 0x80;
+
+/* Steam game server (on the configured UDP ports, and on other ports
+ * for A2S_INFO queries): A2S_INFO queries get the server information
+ * below, A2S_PLAYER queries the players list - both after a challenge
+ * (derived from the client address and port) when challenge is set.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SteamConfig {
+    pub ports: Vec<u16>,
+    pub challenge: bool,
+    pub name: String,
+    pub map: String,
+    /* game directory */
+    pub folder: String,
+    pub game: String,
+    pub app_id: u16,
+    pub players: Vec<String>,
+    pub max_players: u8,
+    pub bots: u8,
+    /* d (dedicated), l (listen) or p (SourceTV) */
+    pub server_type: char,
+    /* l (Linux), w (Windows) or m (macOS) */
+    pub environment: char,
+    pub password: bool,
+    pub vac: bool,
+    pub version: String,
+}
+
+impl Default for SteamConfig {
+    fn default() -> Self {
+        SteamConfig {
+            ports: vec![27015],
+            challenge: true,
+            name: "Team Fortress".to_string(),
+            map: "ctf_2fort".to_string(),
+            folder: "tf".to_string(),
+            game: "Team Fortress".to_string(),
+            app_id: 440,
+            players: Vec::new(),
+            max_players: 24,
+            bots: 0,
+            server_type: 'd',
+            environment: 'l',
+            password: false,
+            vac: true,
+            version: "8835751".to_string(),
+        }
+    }
+}
+
+/* challenge of a client: keyed hash of its 5-tuple */
+fn a2s_challenge(client_info: &ClientInfo, key: &[u64; 2]) -> [u8; 4] {
+    let tuple = synackcookie::generate(client_info, key).unwrap_or(0);
+    let mut sip = SipHasher24::new_with_keys(key[1], key[0]);
+    sip.write_u32(tuple);
+    sip.write(b"a2s");
+    (sip.finish() as u32).to_le_bytes()
+}
+
+fn a2s_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(s.as_bytes());
+    out.push(0);
+}
+
+fn a2s_info(config: &SteamConfig, port: Option<u16>) -> Vec<u8> {
+    let mut repl = A2S_HEADER.to_vec();
+    /* protocol 17 */
+    repl.extend_from_slice(&[S2A_INFO, 17]);
+    a2s_string(&mut repl, &config.name);
+    a2s_string(&mut repl, &config.map);
+    a2s_string(&mut repl, &config.folder);
+    a2s_string(&mut repl, &config.game);
+    repl.extend_from_slice(&config.app_id.to_le_bytes());
+    repl.extend_from_slice(&[
+        config.players.len().min(255) as u8,
+        config.max_players,
+        config.bots,
+        config.server_type as u8,
+        config.environment as u8,
+        config.password as u8,
+        config.vac as u8,
+    ]);
+    a2s_string(&mut repl, &config.version);
+    if let Some(port) = port {
+        repl.push(A2S_EDF_PORT);
+        repl.extend_from_slice(&port.to_le_bytes());
+    }
+    repl
+}
+
+fn a2s_players(config: &SteamConfig) -> Vec<u8> {
+    let mut repl = A2S_HEADER.to_vec();
+    let players = &config.players[..config.players.len().min(255)];
+    repl.extend_from_slice(&[S2A_PLAYER, players.len() as u8]);
+    for (i, name) in players.iter().enumerate() {
+        repl.push(i as u8);
+        a2s_string(&mut repl, name);
+        /* made-up score and connection time (seconds) */
+        let seed = name
+            .bytes()
+            .fold(i as u32, |h, b| h.wrapping_mul(31).wrapping_add(b as u32));
+        repl.extend_from_slice(&((seed % 40) as i32).to_le_bytes());
+        repl.extend_from_slice(&((seed % 3600) as f32 + 60.0).to_le_bytes());
+    }
+    repl
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving A2S data");
+    let config = &masscanned.config.steam;
+    let (type_, payload) = match data.strip_prefix(A2S_HEADER) {
+        Some([t, payload @ ..]) => (*t, payload),
+        _ => {
+            info!("A2S packet not handled (no header)");
+            return None;
+        }
+    };
+    /* the challenge follows the payload of A2S_INFO, and is the
+     * payload of the other queries (-1 to ask for one) */
+    let (name, challenge) = match type_ {
+        A2S_INFO => match payload.strip_prefix(&A2S_INFO_PAYLOAD[..]) {
+            Some(c) if c.is_empty() || c.len() == 4 => ("info", c),
+            _ => {
+                info!("A2S_INFO query not handled (invalid payload)");
+                return None;
+            }
+        },
+        A2S_PLAYER | A2S_RULES if payload.len() == 4 => (
+            if type_ == A2S_PLAYER {
+                "player"
+            } else {
+                "rules"
+            },
+            payload,
+        ),
+        _ => {
+            info!("A2S packet not handled (type {:#04x})", type_);
+            return None;
+        }
+    };
+    let expected = a2s_challenge(client_info, &masscanned.synack_key);
+    let valid = challenge == expected;
+    let mut event = Event::new("steam_query", client_info);
+    event.set("query", name);
+    event.set(
+        "challenge",
+        !challenge.is_empty() && challenge != A2S_HEADER,
+    );
+    event.set("challenge_valid", valid);
+    event.log();
+    /* A2S_PLAYER and A2S_RULES always need a challenge */
+    if (config.challenge || type_ != A2S_INFO) && !valid {
+        let mut repl = A2S_HEADER.to_vec();
+        repl.push(S2C_CHALLENGE);
+        repl.extend_from_slice(&expected);
+        return Some(repl);
+    }
+    let repl = match type_ {
+        A2S_INFO => {
+            warn!("A2S_INFO query");
+            a2s_info(config, client_info.port.dst)
+        }
+        A2S_PLAYER => {
+            warn!("A2S_PLAYER query");
+            a2s_players(config)
+        }
+        /* no rules */
+        _ => [&A2S_HEADER[..], b"E\x00\x00"].concat(),
+    };
+    debug!("sending A2S data");
+    Some(repl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn instance() -> Masscanned<'static> {
+        Masscanned {
+            synack_key: [0x1e9219e0b0e0b44c, 0x9e460bcddf4eaac9],
+            ..crate::proto::test_util::masscanned(Config::default())
+        }
+    }
+
+    fn client(port: u16) -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Udp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.src = Some(27005);
+        client_info.port.dst = Some(port);
+        client_info
+    }
+
+    /* strings of an S2A_INFO answer, and what follows */
+    fn info_strings(repl: &[u8]) -> (Vec<String>, &[u8]) {
+        assert!(repl[..6] == *b"\xff\xff\xff\xffI\x11");
+        let mut rest = &repl[6..];
+        let mut strings = Vec::new();
+        for _ in 0..4 {
+            let end = rest.iter().position(|b| *b == 0).unwrap();
+            strings.push(String::from_utf8(rest[..end].to_vec()).unwrap());
+            rest = &rest[end + 1..];
+        }
+        (strings, rest)
+    }
+
+    #[test]
+    fn test_a2s_info_no_challenge() {
+        let mut masscanned = instance();
+        masscanned.config.steam.challenge = false;
+        masscanned.config.steam.players = vec!["Heavy".to_string(), "Scout".to_string()];
+        let mut client_info = client(27015);
+        let repl = repl(A2S_PATTERN_INFO, &masscanned, &mut client_info).expect("no answer");
+        let (strings, rest) = info_strings(&repl);
+        assert!(strings == ["Team Fortress", "ctf_2fort", "tf", "Team Fortress"]);
+        /* app ID, players, max players, bots, type, environment,
+         * password, VAC */
+        assert!(rest[..9] == [0xb8, 0x01, 2, 24, 0, b'd', b'l', 0, 1]);
+        assert!(rest[9..] == *b"8835751\0\x80\x87\x69");
+        /* through the dispatcher, on another port */
+        let mut client_info = client(27016);
+        let repl = crate::proto::dispatch(A2S_PATTERN_INFO, &masscanned, &mut client_info, None)
+            .expect("no answer on another port");
+        assert!(repl.ends_with(b"\x80\x88\x69"));
+        /* invalid queries */
+        for data in [
+            &b"\xff\xff\xff\xffTSource Engine Query"[..],
+            b"\xff\xff\xff\xffTSource Engine Query\0\x01",
+            b"\xff\xff\xff\xffU\x01",
+            b"\xff\xff\xff\xfeTSource Engine Query\0",
+        ]
+        .iter()
+        {
+            assert!(super::repl(data, &masscanned, &mut client_info).is_none());
+        }
+    }
+
+    #[test]
+    fn test_a2s_challenge() {
+        let mut masscanned = instance();
+        masscanned.config.steam.players = vec!["Heavy".to_string()];
+        let mut client_info = client(27015);
+        /* A2S_INFO: challenge, then the query again with it */
+        let challenge = repl(A2S_PATTERN_INFO, &masscanned, &mut client_info).unwrap();
+        assert!(challenge.len() == 9 && challenge[..5] == *b"\xff\xff\xff\xffA");
+        let mut query = A2S_PATTERN_INFO.to_vec();
+        query.extend_from_slice(&challenge[5..]);
+        let (strings, _) = info_strings(&repl(&query, &masscanned, &mut client_info).unwrap());
+        assert!(strings[1] == "ctf_2fort");
+        /* the challenge depends on the client */
+        let mut other = client(27015);
+        other.port.src = Some(27006);
+        assert!(repl(&query, &masscanned, &mut other).unwrap() != challenge);
+        assert!(repl(&query, &masscanned, &mut other).unwrap()[4] == S2C_CHALLENGE);
+        /* A2S_PLAYER: -1 asks for a challenge */
+        let challenge = repl(
+            b"\xff\xff\xff\xffU\xff\xff\xff\xff",
+            &masscanned,
+            &mut client_info,
+        )
+        .unwrap();
+        assert!(challenge[4] == S2C_CHALLENGE);
+        let query = [&b"\xff\xff\xff\xffU"[..], &challenge[5..]].concat();
+        let players = repl(&query, &masscanned, &mut client_info).unwrap();
+        assert!(players[..12] == *b"\xff\xff\xff\xffD\x01\x00Heavy");
+        assert!(players.len() == 7 + 6 + 4 + 4);
+        /* A2S_RULES: none */
+        let query = [&b"\xff\xff\xff\xffV"[..], &challenge[5..]].concat();
+        assert!(
+            repl(&query, &masscanned, &mut client_info).unwrap() == b"\xff\xff\xff\xffE\x00\x00"
+        );
+    }
+}
@@ -17,13 +17,19 @@
 use log::*;
 
 use std::convert::TryInto;
+use std::hash::Hasher;
 
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use byteorder::{BigEndian, ByteOrder};
+use serde::Deserialize;
+use siphasher::sip::SipHasher24;
 use std::io;
 
 use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::synackcookie;
 use crate::Masscanned;
 
 /* RFC 5389: The magic cookie field MUST contain the fixed value 0x2112A442 in
@@ -34,19 +40,32 @@ pub const STUN_PATTERN_EMPTY: &[u8; 20] = b"\x00\x01\x00\x00****************";
 /* RFC 3489: support without cookie */
 pub const STUN_PATTERN_CHANGE_REQUEST: &[u8; 28] =
     b"\x00\x01\x00\x08****************\x00\x03\x00\x04\x00\x00\x00*";
-pub const _STUN_MAGIC: u32 = 0x2112a442;
+pub const STUN_MAGIC: u32 = 0x2112a442;
+/* RFC 5389 section 15.5 */
+pub const STUN_FINGERPRINT_XOR: u32 = 0x5354554e;
 
 pub const STUN_CLASS_REQUEST: u8 = 0b00;
 #[allow(dead_code)]
 pub const STUN_CLASS_INDICATE: u8 = 0b01;
 pub const STUN_CLASS_SUCCESS_RESPONSE: u8 = 0b10;
-#[allow(dead_code)]
 pub const STUN_CLASS_FAILURE_RESPONSE: u8 = 0b11;
 
 pub const STUN_ATTR_MAPPED_ADDRESS: u16 = 0x0001;
 pub const STUN_ATTR_CHANGE_REQUEST: u16 = 0x0003;
+pub const STUN_ATTR_USERNAME: u16 = 0x0006;
+pub const STUN_ATTR_MESSAGE_INTEGRITY: u16 = 0x0008;
+pub const STUN_ATTR_ERROR_CODE: u16 = 0x0009;
+/* RFC 5766 section 14 */
+pub const STUN_ATTR_LIFETIME: u16 = 0x000d;
+pub const STUN_ATTR_REALM: u16 = 0x0014;
+pub const STUN_ATTR_NONCE: u16 = 0x0015;
+pub const STUN_ATTR_XOR_RELAYED_ADDRESS: u16 = 0x0016;
+pub const STUN_ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+pub const STUN_ATTR_SOFTWARE: u16 = 0x8022;
+pub const STUN_ATTR_FINGERPRINT: u16 = 0x8028;
 
 pub const STUN_METHOD_BINDING: u16 = 0x001;
+pub const STUN_METHOD_ALLOCATE: u16 = 0x003;
 
 pub const STUN_PROTOCOL_FAMILY_IPV4: u8 = 0x01;
 pub const STUN_PROTOCOL_FAMILY_IPV6: u8 = 0x02;
@@ -54,6 +73,87 @@ pub const STUN_PROTOCOL_FAMILY_IPV6: u8 = 0x02;
 pub const STUN_CHANGE_REQUEST_MASK_IP: u32 = 0x00000004;
 pub const STUN_CHANGE_REQUEST_MASK_PORT: u32 = 0x00000002;
 
+/* RFC 5389 answers (to requests with the magic cookie) carry, besides
+ * MAPPED-ADDRESS, XOR-MAPPED-ADDRESS, SOFTWARE (unless empty) and
+ * FINGERPRINT; RFC 3489 answers only MAPPED-ADDRESS.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StunConfig {
+    /* besides the messages recognized on any port (binding requests),
+     * over UDP and TCP */
+    pub ports: Vec<u16>,
+    pub software: String,
+    pub turn: TurnConfig,
+}
+
+impl Default for StunConfig {
+    fn default() -> Self {
+        StunConfig {
+            ports: vec![3478],
+            software: "Coturn-4.6.2 'Gorst'".to_string(),
+            turn: TurnConfig::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TurnPolicy {
+    #[default]
+    Deny,
+    Allow,
+}
+
+/* TURN (RFC 5766) Allocate requests get a 401 challenge with realm and
+ * a nonce; with credentials (recorded) and a valid nonce, they get a 403
+ * (policy "deny") or a success with a relayed address (policy "allow" -
+ * without MESSAGE-INTEGRITY, since the password is not known). Nonces
+ * are stateless: a timestamp and a keyed hash of it and the client
+ * 5-tuple, valid for nonce_lifetime seconds.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TurnConfig {
+    pub realm: String,
+    pub policy: TurnPolicy,
+    pub nonce_lifetime: u64,
+    pub lifetime: u32,
+}
+
+impl Default for TurnConfig {
+    fn default() -> Self {
+        TurnConfig {
+            realm: "turn.local".to_string(),
+            policy: TurnPolicy::Deny,
+            nonce_lifetime: 600,
+            lifetime: 600,
+        }
+    }
+}
+
+/* attribute values are padded to a multiple of 4 bytes (RFC 5389
+ * section 15) */
+fn stun_padded(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/* CRC-32 of ISO/IEC 13239 (as in zlib) */
+fn stun_crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffffffff;
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0xedb88320;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    !crc
+}
+
 struct StunGenericAttribute {
     type_: u16,
     length: u16,
@@ -92,6 +192,7 @@ impl Into<Vec<u8>> for &StunGenericAttribute {
         v.append(&mut self.type_.to_be_bytes().to_vec());
         v.append(&mut self.length.to_be_bytes().to_vec());
         v.append(&mut self.data.clone());
+        v.resize(4 + stun_padded(self.data.len()), 0);
         v
     }
 }
@@ -166,6 +267,43 @@ impl Into<Vec<u8>> for &StunMappedAddressAttribute {
     }
 }
 
+/* RFC 5389 section 15.2: the port is XORed with the 16 most significant
+ * bits of the magic cookie, the address with the magic cookie (IPv4) or
+ * the magic cookie and the transaction ID (IPv6) - the 16 bytes of id */
+fn stun_xor_mapped_address(ip: IpAddr, port: u16, id: u128) -> StunGenericAttribute {
+    let mask = id.to_be_bytes();
+    let (family, octets) = match ip {
+        IpAddr::V4(ip) => (STUN_PROTOCOL_FAMILY_IPV4, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (STUN_PROTOCOL_FAMILY_IPV6, ip.octets().to_vec()),
+    };
+    let mut data = vec![0, family];
+    data.extend_from_slice(&(port ^ (STUN_MAGIC >> 16) as u16).to_be_bytes());
+    data.extend(octets.iter().zip(mask.iter()).map(|(o, m)| o ^ m));
+    StunGenericAttribute {
+        type_: STUN_ATTR_XOR_MAPPED_ADDRESS,
+        length: data.len() as u16,
+        data,
+    }
+}
+
+fn stun_error_code(code: u16, reason: &str) -> StunGenericAttribute {
+    let mut data = vec![0, 0, (code / 100) as u8, (code % 100) as u8];
+    data.extend_from_slice(reason.as_bytes());
+    StunGenericAttribute {
+        type_: STUN_ATTR_ERROR_CODE,
+        length: data.len() as u16,
+        data,
+    }
+}
+
+fn stun_generic(type_: u16, data: &[u8]) -> StunAttribute {
+    StunAttribute::Generic(StunGenericAttribute {
+        type_,
+        length: data.len() as u16,
+        data: data.to_vec(),
+    })
+}
+
 enum StunAttribute {
     MappedAddress(StunMappedAddressAttribute),
     ChangeRequest(StunChangeRequestAttribute),
@@ -202,7 +340,10 @@ impl From<Vec<u8>> for StunAttribute {
             panic!("not enough data");
         }
         match type_ {
-            STUN_ATTR_MAPPED_ADDRESS => {
+            STUN_ATTR_MAPPED_ADDRESS
+                if (length == 8 && v[5] == STUN_PROTOCOL_FAMILY_IPV4)
+                    || (length == 20 && v[5] == STUN_PROTOCOL_FAMILY_IPV6) =>
+            {
                 let reserved = v[4];
                 let protocol_family = v[5];
                 let port = BigEndian::read_u16(&v[6..8]);
@@ -230,18 +371,20 @@ impl From<Vec<u8>> for StunAttribute {
                     },
                 })
             }
-            STUN_ATTR_CHANGE_REQUEST => StunAttribute::ChangeRequest(StunChangeRequestAttribute {
-                type_,
-                length,
-                change_ip: (BigEndian::read_u32(&v[4..8]) & STUN_CHANGE_REQUEST_MASK_IP)
-                    == STUN_CHANGE_REQUEST_MASK_IP,
-                change_port: (BigEndian::read_u32(&v[4..8]) & STUN_CHANGE_REQUEST_MASK_PORT)
-                    == STUN_CHANGE_REQUEST_MASK_PORT,
-            }),
+            STUN_ATTR_CHANGE_REQUEST if length == 4 => {
+                StunAttribute::ChangeRequest(StunChangeRequestAttribute {
+                    type_,
+                    length,
+                    change_ip: (BigEndian::read_u32(&v[4..8]) & STUN_CHANGE_REQUEST_MASK_IP)
+                        == STUN_CHANGE_REQUEST_MASK_IP,
+                    change_port: (BigEndian::read_u32(&v[4..8]) & STUN_CHANGE_REQUEST_MASK_PORT)
+                        == STUN_CHANGE_REQUEST_MASK_PORT,
+                })
+            }
             _ => StunAttribute::Generic(StunGenericAttribute {
                 type_,
                 length,
-                data: v[4..].to_vec(),
+                data: v[4..4 + length as usize].to_vec(),
             }),
         }
     }
@@ -412,9 +555,13 @@ impl StunPacket {
     fn get_attributes(&self) -> Vec<StunAttribute> {
         let mut i = 0;
         let mut attributes = Vec::<StunAttribute>::new();
-        while i + 4 < self.data.len() {
-            let attr = StunAttribute::from(self.data[i..].to_vec());
-            i += 4 + attr.len() as usize;
+        while i + 4 <= self.data.len() {
+            let length = BigEndian::read_u16(&self.data[i + 2..i + 4]) as usize;
+            if i + 4 + length > self.data.len() {
+                break;
+            }
+            let attr = StunAttribute::from(self.data[i..i + 4 + length].to_vec());
+            i += 4 + stun_padded(length);
             attributes.push(attr);
         }
         attributes
@@ -423,9 +570,21 @@ impl StunPacket {
     fn set_length(&mut self) {
         self.length = 0;
         for attr in &self.attributes {
-            self.length += 4 + attr.len();
+            self.length += 4 + stun_padded(attr.len() as usize) as u16;
         }
     }
+
+    /* RFC 5389: the magic cookie is the first 4 bytes of id */
+    fn has_magic(&self) -> bool {
+        (self.id >> 96) as u32 == STUN_MAGIC
+    }
+
+    fn attribute(&self, type_: u16) -> Option<&[u8]> {
+        self.attributes.iter().find_map(|a| match a {
+            StunAttribute::Generic(g) if g.type_ == type_ => Some(&g.data[..]),
+            _ => None,
+        })
+    }
 }
 
 impl Into<Vec<u8>> for StunPacket {
@@ -510,7 +669,7 @@ pub fn repl<'a>(
 
 pub fn repl<'a>(
     data: &'a [u8],
-    _masscanned: &Masscanned,
+    masscanned: &Masscanned,
     mut client_info: &mut ClientInfo,
 ) -> Option<Vec<u8>> {
     debug!("receiving STUN data");
@@ -526,7 +685,7 @@ pub fn repl<'a>(
         );
         return None;
     }
-    if stun_req.method != STUN_METHOD_BINDING {
+    if stun_req.method != STUN_METHOD_BINDING && stun_req.method != STUN_METHOD_ALLOCATE {
         info!(
             "STUN packet not handled (method unknown: 0x{:03x})",
             stun_req.method
@@ -541,6 +700,26 @@ pub fn repl<'a>(
         error!("STUN packet not handled (expected client port address not found)");
         return None;
     }
+    /* credentials (short-term for ICE, long-term for TURN) cannot be
+     * checked, but are recorded */
+    if let Some(integrity) = stun_req.attribute(STUN_ATTR_MESSAGE_INTEGRITY) {
+        let mut event = Event::new("stun_credentials", client_info);
+        event.set("method", stun_req.method);
+        if let Some(username) = stun_req.attribute(STUN_ATTR_USERNAME) {
+            event.set("username", String::from_utf8_lossy(username).to_string());
+        }
+        if let Some(realm) = stun_req.attribute(STUN_ATTR_REALM) {
+            event.set("realm", String::from_utf8_lossy(realm).to_string());
+        }
+        if let Some(nonce) = stun_req.attribute(STUN_ATTR_NONCE) {
+            event.set("nonce", String::from_utf8_lossy(nonce).to_string());
+        }
+        event.set("message_integrity", encode(integrity, Encoding::Hex));
+        event.log();
+    }
+    if stun_req.method == STUN_METHOD_ALLOCATE {
+        return turn_allocate(&stun_req, masscanned, client_info);
+    }
     /* Change client_info if CHANGE_REQUEST was set by client */
     for attr in &stun_req.attributes {
         if let StunAttribute::ChangeRequest(a) = attr {
@@ -558,14 +737,157 @@ pub fn repl<'a>(
     stun_resp.attributes.push(StunAttribute::MappedAddress(
         StunMappedAddressAttribute::new(client_info.ip.src.unwrap(), client_info.port.src.unwrap()),
     ));
-    stun_resp.set_length();
+    if !stun_req.has_magic() {
+        stun_resp.set_length();
+        debug!("sending STUN answer");
+        return Some(stun_resp.into());
+    }
+    stun_resp
+        .attributes
+        .push(StunAttribute::Generic(stun_xor_mapped_address(
+            client_info.ip.src.unwrap(),
+            client_info.port.src.unwrap(),
+            stun_req.id,
+        )));
     debug!("sending STUN answer");
-    return Some(stun_resp.into());
+    Some(stun_finish(stun_resp, &masscanned.config.stun))
+}
+
+/* RFC 5389 answer: SOFTWARE and FINGERPRINT (CRC-32 of the message
+ * before it, with a length that includes it - section 15.5) */
+fn stun_finish(mut stun_resp: StunPacket, config: &StunConfig) -> Vec<u8> {
+    if !config.software.is_empty() {
+        stun_resp
+            .attributes
+            .push(stun_generic(STUN_ATTR_SOFTWARE, config.software.as_bytes()));
+    }
+    stun_resp
+        .attributes
+        .push(stun_generic(STUN_ATTR_FINGERPRINT, &[0; 4]));
+    stun_resp.set_length();
+    let mut resp: Vec<u8> = stun_resp.into();
+    let crc = stun_crc32(&resp[..resp.len() - 8]) ^ STUN_FINGERPRINT_XOR;
+    let len = resp.len();
+    resp[len - 4..].copy_from_slice(&crc.to_be_bytes());
+    resp
+}
+
+fn turn_nonce_mac(client_info: &ClientInfo, key: &[u64; 2], timestamp: u32) -> Option<u64> {
+    let tuple = synackcookie::generate(client_info, key).ok()?;
+    let mut sip = SipHasher24::new_with_keys(key[1], key[0]);
+    sip.write_u32(tuple);
+    sip.write_u32(timestamp);
+    Some(sip.finish())
+}
+
+/* timestamp (seconds since the epoch) and keyed hash, in hex */
+fn turn_nonce(client_info: &ClientInfo, key: &[u64; 2], now: u64) -> Option<String> {
+    let timestamp = now as u32;
+    let mac = turn_nonce_mac(client_info, key, timestamp)?;
+    Some(format!("{:08x}{:016x}", timestamp, mac))
+}
+
+fn turn_nonce_check(
+    nonce: &[u8],
+    client_info: &ClientInfo,
+    key: &[u64; 2],
+    now: u64,
+    lifetime: u64,
+) -> bool {
+    let nonce = match std::str::from_utf8(nonce) {
+        Ok(n) if n.len() == 24 && n.is_ascii() => n,
+        _ => return false,
+    };
+    let (timestamp, mac) = match (
+        u32::from_str_radix(&nonce[..8], 16),
+        u64::from_str_radix(&nonce[8..], 16),
+    ) {
+        (Ok(t), Ok(m)) => (t, m),
+        _ => return false,
+    };
+    let age = (now as u32).wrapping_sub(timestamp) as u64;
+    age <= lifetime && turn_nonce_mac(client_info, key, timestamp) == Some(mac)
+}
+
+/* TURN Allocate request (RFC 5766 section 6) */
+fn turn_allocate(
+    stun_req: &StunPacket,
+    masscanned: &Masscanned,
+    client_info: &ClientInfo,
+) -> Option<Vec<u8>> {
+    if !stun_req.has_magic() {
+        info!("TURN Allocate request not handled (no magic cookie)");
+        return None;
+    }
+    let config = &masscanned.config.stun.turn;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let key = &masscanned.synack_key;
+    let mut stun_resp: StunPacket = StunPacket::empty();
+    stun_resp.method = STUN_METHOD_ALLOCATE;
+    stun_resp.id = stun_req.id;
+    let authenticated = stun_req.attribute(STUN_ATTR_USERNAME).is_some()
+        && stun_req.attribute(STUN_ATTR_MESSAGE_INTEGRITY).is_some();
+    let nonce_valid = stun_req
+        .attribute(STUN_ATTR_NONCE)
+        .is_some_and(|n| turn_nonce_check(n, client_info, key, now, config.nonce_lifetime));
+    let error = match (authenticated, nonce_valid, config.policy) {
+        (false, _, _) => Some((401, "Unauthorized")),
+        (true, false, _) => Some((438, "Stale Nonce")),
+        (true, true, TurnPolicy::Deny) => Some((403, "Forbidden")),
+        (true, true, TurnPolicy::Allow) => None,
+    };
+    if let Some((code, reason)) = error {
+        warn!("TURN Allocate request: error {}", code);
+        stun_resp.class = STUN_CLASS_FAILURE_RESPONSE;
+        stun_resp
+            .attributes
+            .push(StunAttribute::Generic(stun_error_code(code, reason)));
+        if code != 403 {
+            stun_resp
+                .attributes
+                .push(stun_generic(STUN_ATTR_REALM, config.realm.as_bytes()));
+            let nonce = turn_nonce(client_info, key, now)?;
+            stun_resp
+                .attributes
+                .push(stun_generic(STUN_ATTR_NONCE, nonce.as_bytes()));
+        }
+    } else {
+        warn!("TURN Allocate request: relayed address allocated");
+        stun_resp.class = STUN_CLASS_SUCCESS_RESPONSE;
+        /* a port of the dynamic range, stable for the client */
+        let cookie = synackcookie::generate(client_info, key).ok()?;
+        let relayed_port = 49152 + (cookie % 16384) as u16;
+        let relayed_ip = client_info
+            .ip
+            .dst
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let mut relayed = stun_xor_mapped_address(relayed_ip, relayed_port, stun_req.id);
+        relayed.type_ = STUN_ATTR_XOR_RELAYED_ADDRESS;
+        stun_resp.attributes.push(StunAttribute::Generic(relayed));
+        stun_resp.attributes.push(stun_generic(
+            STUN_ATTR_LIFETIME,
+            &config.lifetime.to_be_bytes(),
+        ));
+        stun_resp
+            .attributes
+            .push(StunAttribute::Generic(stun_xor_mapped_address(
+                client_info.ip.src.unwrap(),
+                client_info.port.src.unwrap(),
+                stun_req.id,
+            )));
+    }
+    debug!("sending TURN answer");
+    Some(stun_finish(stun_resp, &masscanned.config.stun))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
     use std::collections::HashSet;
     use std::str::FromStr;
 
@@ -597,6 +919,7 @@ mod tests {
             mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
             iface: None,
             ip_addresses: Some(&ips),
+            config: Config::default(),
         };
         let payload_resp = if let Some(r) = repl(payload, &masscanned, &mut client_info) {
             r
@@ -612,7 +935,7 @@ mod tests {
                     b"\x21\x12\xa4\x42\xaa\xbb\xcc\xdd\xee\xff\xff\xee\xdd\xcc\xbb\xaa"
                 )
         );
-        assert!(stun_resp.attributes.len() == 1);
+        assert!(stun_resp.attributes.len() == 4);
         if let StunAttribute::MappedAddress(attr) = &stun_resp.attributes[0] {
             assert!(attr.type_ == STUN_ATTR_MAPPED_ADDRESS);
             assert!(attr.length == 8);
@@ -656,6 +979,7 @@ mod tests {
             mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
             iface: None,
             ip_addresses: Some(&ips),
+            config: Config::default(),
         };
         client_info.ip.src = Some(IpAddr::V6(test_ip_addr));
         client_info.ip.dst = Some(IpAddr::V6(masscanned_ip_addr));
@@ -675,7 +999,7 @@ mod tests {
                     b"\x21\x12\xa4\x42\xaa\xbb\xcc\xdd\xee\xff\xff\xee\xdd\xcc\xbb\xaa"
                 )
         );
-        assert!(stun_resp.attributes.len() == 1);
+        assert!(stun_resp.attributes.len() == 4);
         if let StunAttribute::MappedAddress(attr) = &stun_resp.attributes[0] {
             assert!(attr.type_ == STUN_ATTR_MAPPED_ADDRESS);
             assert!(attr.length == 20);
@@ -707,6 +1031,7 @@ mod tests {
             mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
             iface: None,
             ip_addresses: Some(&ips),
+            config: Config::default(),
         };
         client_info.ip.src = Some(IpAddr::V4(test_ip_addr));
         client_info.ip.dst = Some(IpAddr::V4(masscanned_ip_addr));
@@ -756,6 +1081,7 @@ mod tests {
             mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
             iface: None,
             ip_addresses: Some(&ips),
+            config: Config::default(),
         };
         client_info.ip.src = Some(IpAddr::V4(test_ip_addr));
         client_info.ip.dst = Some(IpAddr::V4(masscanned_ip_addr));
@@ -790,4 +1116,243 @@ mod tests {
         assert!(client_info.port.src == Some(55000));
         assert!(client_info.port.dst == Some(0));
     }
+
+    #[test]
+    fn test_stun_rfc5769() {
+        /* RFC 5769 section 2.1: sample request, with padding and a
+         * valid FINGERPRINT */
+        let request = b"\x00\x01\x00\x58\x21\x12\xa4\x42\xb7\xe7\xa7\x01\xbc\x34\xd6\x86\xfa\x87\xdf\xae\x80\x22\x00\x10STUN test client\x00\x24\x00\x04\x6e\x00\x01\xff\x80\x29\x00\x08\x93\x2f\xf9\xb1\x51\x26\x3b\x36\x00\x06\x00\x09evtj:h6vY   \x00\x08\x00\x14\x9a\xea\xa7\x0c\xbf\xd8\xcb\x56\x78\x1e\xf2\xb5\xb2\xd3\xf2\x49\xc1\xb5\x71\xa2\x80\x28\x00\x04\xe5\x7a\x3b\xcf";
+        assert!(stun_crc32(&request[..request.len() - 8]) ^ STUN_FINGERPRINT_XOR == 0xe57a3bcf);
+        let packet = StunPacket::new(request).unwrap();
+        assert!(packet.has_magic());
+        assert!(packet.attributes.len() == 6);
+        assert!(packet.attribute(STUN_ATTR_USERNAME) == Some(&b"evtj:h6vY"[..]));
+        assert!(packet.attribute(STUN_ATTR_MESSAGE_INTEGRITY).unwrap()[..2] == [0x9a, 0xea]);
+        assert!(packet.attribute(STUN_ATTR_FINGERPRINT) == Some(&b"\xe5\x7a\x3b\xcf"[..]));
+        /* sections 2.2 and 2.3: XOR-MAPPED-ADDRESS */
+        let id = BigEndian::read_u128(
+            b"\x21\x12\xa4\x42\xb7\xe7\xa7\x01\xbc\x34\xd6\x86\xfa\x87\xdf\xae",
+        );
+        let attr: Vec<u8> =
+            (&stun_xor_mapped_address(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 32853, id)).into();
+        assert!(attr == b"\x00\x20\x00\x08\x00\x01\xa1\x47\xe1\x12\xa6\x43");
+        let attr: Vec<u8> = (&stun_xor_mapped_address(
+            IpAddr::V6(Ipv6Addr::new(
+                0x2001, 0xdb8, 0x1234, 0x5678, 0x11, 0x2233, 0x4455, 0x6677,
+            )),
+            32853,
+            id,
+        ))
+            .into();
+        assert!(attr == b"\x00\x20\x00\x14\x00\x02\xa1\x47\x01\x13\xa9\xfa\xa5\xd3\xf1\x79\xbc\x25\xf4\xb5\xbe\xd2\xb9\xd9");
+        /* truncated or malformed attributes are not fatal */
+        let packet = StunPacket::new(b"\x00\x01\x00\x08\x21\x12\xa4\x42\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\x06\x00\x09").unwrap();
+        assert!(packet.attributes.len() == 1);
+        assert!(packet.attribute(STUN_ATTR_MAPPED_ADDRESS) == Some(&b""[..]));
+    }
+
+    #[test]
+    fn test_stun_stunclient() {
+        let mut masscanned = masscanned(Config::default());
+        /* stunclient binding request */
+        let request =
+            b"\x00\x01\x00\x00\x21\x12\xa4\x42\x63\xc7\x11\x7e\x07\x14\x27\x8f\x5d\xed\x32\x21";
+        let mut client_info = ClientInfo::new();
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.port.src = Some(40312);
+        client_info.port.dst = Some(3478);
+        let resp = repl(request, &masscanned, &mut client_info).unwrap();
+        assert!(resp[..] == b"\x01\x01\x00\x38\x21\x12\xa4\x42\x63\xc7\x11\x7e\x07\x14\x27\x8f\x5d\xed\x32\x21\x00\x01\x00\x08\x00\x01\x9d\x78\xc6\x33\x64\x07\x00\x20\x00\x08\x00\x01\xbc\x6a\xe7\x21\xc0\x45\x80\x22\x00\x14Coturn-4.6.2 'Gorst'\x80\x28\x00\x04\x18\xb2\x15\x44"[..]);
+        client_info.ip.src = Some(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 7)));
+        let resp = repl(request, &masscanned, &mut client_info).unwrap();
+        assert!(resp[..] == b"\x01\x01\x00\x50\x21\x12\xa4\x42\x63\xc7\x11\x7e\x07\x14\x27\x8f\x5d\xed\x32\x21\x00\x01\x00\x14\x00\x02\x9d\x78\x20\x01\x0d\xb8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x07\x00\x20\x00\x14\x00\x02\xbc\x6a\x01\x13\xa9\xfa\x63\xc7\x11\x7e\x07\x14\x27\x8f\x5d\xed\x32\x26\x80\x22\x00\x14Coturn-4.6.2 'Gorst'\x80\x28\x00\x04\x44\x93\x89\x7d"[..]);
+        /* SOFTWARE padded to 4 bytes, fingerprint still valid */
+        masscanned.config.stun.software = "test vector".to_string();
+        let resp = repl(request, &masscanned, &mut client_info).unwrap();
+        let packet = StunPacket::new(&resp).unwrap();
+        assert!(packet.length as usize == resp.len() - 20);
+        assert!(packet.attribute(STUN_ATTR_SOFTWARE) == Some(&b"test vector"[..]));
+        assert!(resp[resp.len() - 24..resp.len() - 8] == b"\x80\x22\x00\x0btest vector\x00"[..]);
+        let crc = stun_crc32(&resp[..resp.len() - 8]) ^ STUN_FINGERPRINT_XOR;
+        assert!(packet.attribute(STUN_ATTR_FINGERPRINT) == Some(&crc.to_be_bytes()[..]));
+        /* no SOFTWARE */
+        masscanned.config.stun.software = String::new();
+        let resp = repl(request, &masscanned, &mut client_info).unwrap();
+        assert!(StunPacket::new(&resp)
+            .unwrap()
+            .attribute(STUN_ATTR_SOFTWARE)
+            .is_none());
+    }
+
+    /* TURN Allocate request, with REQUESTED-TRANSPORT (UDP) */
+    fn allocate_request(attributes: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut body = b"\x00\x19\x00\x04\x11\x00\x00\x00".to_vec();
+        for (type_, data) in attributes {
+            let attr: Vec<u8> = (&StunGenericAttribute {
+                type_: *type_,
+                length: data.len() as u16,
+                data: data.to_vec(),
+            })
+                .into();
+            body.extend(attr);
+        }
+        let mut request = b"\x00\x03".to_vec();
+        request.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        request
+            .extend_from_slice(b"\x21\x12\xa4\x42\x8a\x3b\x11\x27\x56\xe0\x94\x02\xc3\x7f\x5a\x10");
+        request.extend(body);
+        request
+    }
+
+    fn error_code(packet: &StunPacket) -> u16 {
+        let data = packet.attribute(STUN_ATTR_ERROR_CODE).unwrap();
+        data[2] as u16 * 100 + data[3] as u16
+    }
+
+    #[test]
+    fn test_turn_allocate() {
+        let mut masscanned = Masscanned {
+            synack_key: [0x1e9219e0b0e0b44c, 0x9e460bcddf4eaac9],
+            ..crate::proto::test_util::masscanned(Config::default())
+        };
+        let mut client_info = ClientInfo::new();
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.src = Some(40312);
+        client_info.port.dst = Some(3478);
+        /* unauthenticated: 401 challenge */
+        let resp = repl(&allocate_request(&[]), &masscanned, &mut client_info).unwrap();
+        let packet = StunPacket::new(&resp).unwrap();
+        assert!(packet.class == STUN_CLASS_FAILURE_RESPONSE);
+        assert!(packet.method == STUN_METHOD_ALLOCATE);
+        assert!(packet.id == BigEndian::read_u128(&resp[4..20]));
+        assert!(error_code(&packet) == 401);
+        assert!(packet.attribute(STUN_ATTR_REALM) == Some(&b"turn.local"[..]));
+        let nonce = packet.attribute(STUN_ATTR_NONCE).unwrap().to_vec();
+        assert!(nonce.len() == 24);
+        let crc = stun_crc32(&resp[..resp.len() - 8]) ^ STUN_FINGERPRINT_XOR;
+        assert!(packet.attribute(STUN_ATTR_FINGERPRINT) == Some(&crc.to_be_bytes()[..]));
+        /* retry with credentials: 403 */
+        let integrity = [0x42; 20];
+        let credentials = [
+            (STUN_ATTR_USERNAME, &b"alice"[..]),
+            (STUN_ATTR_REALM, &b"turn.local"[..]),
+            (STUN_ATTR_NONCE, &nonce[..]),
+            (STUN_ATTR_MESSAGE_INTEGRITY, &integrity[..]),
+        ];
+        let request = allocate_request(&credentials);
+        let resp = repl(&request, &masscanned, &mut client_info).unwrap();
+        let packet = StunPacket::new(&resp).unwrap();
+        assert!(packet.class == STUN_CLASS_FAILURE_RESPONSE);
+        assert!(error_code(&packet) == 403);
+        assert!(packet.attribute(STUN_ATTR_NONCE).is_none());
+        /* fake success */
+        masscanned.config.stun.turn.policy = TurnPolicy::Allow;
+        let resp = repl(&request, &masscanned, &mut client_info).unwrap();
+        let packet = StunPacket::new(&resp).unwrap();
+        assert!(packet.class == STUN_CLASS_SUCCESS_RESPONSE);
+        assert!(packet.attribute(STUN_ATTR_ERROR_CODE).is_none());
+        let relayed = packet.attribute(STUN_ATTR_XOR_RELAYED_ADDRESS).unwrap();
+        assert!(relayed[4..] == [192 ^ 0x21, 0x12, 2 ^ 0xa4, 1 ^ 0x42]);
+        let port = u16::from_be_bytes([relayed[2], relayed[3]]) ^ 0x2112;
+        assert!(port >= 49152);
+        assert!(packet.attribute(STUN_ATTR_LIFETIME) == Some(&600u32.to_be_bytes()[..]));
+        assert!(packet.attribute(STUN_ATTR_XOR_MAPPED_ADDRESS).is_some());
+        /* another client, or a tampered nonce: 438 with a new nonce */
+        let mut other = client_info;
+        other.port.src = Some(40313);
+        let resp = repl(&request, &masscanned, &mut other).unwrap();
+        let packet = StunPacket::new(&resp).unwrap();
+        assert!(error_code(&packet) == 438);
+        assert!(packet.attribute(STUN_ATTR_NONCE).is_some());
+        let mut tampered = nonce.clone();
+        tampered[23] = if tampered[23] == b'0' { b'1' } else { b'0' };
+        let mut credentials = credentials;
+        credentials[2].1 = &tampered[..];
+        let resp = repl(
+            &allocate_request(&credentials),
+            &masscanned,
+            &mut client_info,
+        )
+        .unwrap();
+        assert!(error_code(&StunPacket::new(&resp).unwrap()) == 438);
+    }
+
+    #[test]
+    fn test_turn_nonce() {
+        let key = [0x1e9219e0b0e0b44c, 0x9e460bcddf4eaac9];
+        let mut client_info = ClientInfo::new();
+        client_info.ip.src = Some(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 7)));
+        client_info.ip.dst = Some(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)));
+        client_info.port.src = Some(40312);
+        client_info.port.dst = Some(3478);
+        let now = 1700000000;
+        let nonce = turn_nonce(&client_info, &key, now).unwrap();
+        assert!(nonce.starts_with("6553f100"));
+        let nonce = nonce.as_bytes();
+        assert!(turn_nonce_check(nonce, &client_info, &key, now, 600));
+        assert!(turn_nonce_check(nonce, &client_info, &key, now + 600, 600));
+        /* expired, or from the future */
+        assert!(!turn_nonce_check(nonce, &client_info, &key, now + 601, 600));
+        assert!(!turn_nonce_check(nonce, &client_info, &key, now - 1, 600));
+        /* other key, other client */
+        assert!(!turn_nonce_check(
+            nonce,
+            &client_info,
+            &[key[1], key[0]],
+            now,
+            600
+        ));
+        client_info.port.src = Some(40313);
+        assert!(!turn_nonce_check(nonce, &client_info, &key, now, 600));
+        /* not a nonce of ours */
+        assert!(!turn_nonce_check(b"", &client_info, &key, now, 600));
+        assert!(!turn_nonce_check(
+            b"6553f100zzzzzzzzzzzzzzzz",
+            &client_info,
+            &key,
+            now,
+            600
+        ));
+        /* no address: no nonce */
+        client_info.ip.dst = None;
+        assert!(turn_nonce(&client_info, &key, now).is_none());
+    }
+
+    #[test]
+    fn test_turn_tcp() {
+        use crate::proto::tcb::TCPControlBlock;
+        use pnet::packet::ip::IpNextHeaderProtocols;
+        let masscanned = Masscanned {
+            synack_key: [0x1e9219e0b0e0b44c, 0x9e460bcddf4eaac9],
+            ..crate::proto::test_util::masscanned(Config::default())
+        };
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.src = Some(40312);
+        client_info.port.dst = Some(3478);
+        let mut tcb = TCPControlBlock::new();
+        let resp = crate::proto::dispatch(
+            &allocate_request(&[]),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        let packet = StunPacket::new(&resp).unwrap();
+        assert!(error_code(&packet) == 401);
+        let nonce = packet.attribute(STUN_ATTR_NONCE).unwrap().to_vec();
+        /* same connection */
+        let request = allocate_request(&[
+            (STUN_ATTR_USERNAME, &b"alice"[..]),
+            (STUN_ATTR_REALM, &b"turn.local"[..]),
+            (STUN_ATTR_NONCE, &nonce[..]),
+            (STUN_ATTR_MESSAGE_INTEGRITY, &[0x42; 20][..]),
+        ]);
+        let resp = crate::proto::dispatch(&request, &masscanned, &mut client_info, Some(&mut tcb))
+            .unwrap();
+        assert!(error_code(&StunPacket::new(&resp).unwrap()) == 403);
+    }
 }
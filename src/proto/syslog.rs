@@ -0,0 +1,614 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::line::LINE_MAX_BUFFER;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+const SYSLOG_FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+const SYSLOG_SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+const SYSLOG_MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/* Syslog collector, over UDP (RFC 5426) on the ports, and over TCP
+ * (RFC 6587: octet-counted or LF-terminated messages) on the tcp_ports:
+ * messages are recorded, never answered. The beginning of each message
+ * (at most log_data_max bytes, using log_data_encoding) is recorded as
+ * received, along with the fields of the RFC 5424 or RFC 3164 header,
+ * when one is found.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyslogConfig {
+    pub ports: Vec<u16>,
+    pub tcp_ports: Vec<u16>,
+    pub log_data_max: usize,
+    pub log_data_encoding: Encoding,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        SyslogConfig {
+            ports: vec![514],
+            tcp_ports: Vec::new(),
+            log_data_max: 2048,
+            log_data_encoding: Encoding::Base64,
+        }
+    }
+}
+
+/* state of a syslog flow: beginning of a message */
+#[derive(Default)]
+pub struct SyslogState {
+    buffer: Vec<u8>,
+}
+
+/* fields of a message, None when absent (or nil, "-") */
+#[derive(Default, Debug, PartialEq)]
+struct SyslogMessage {
+    format: &'static str,
+    pri: Option<u8>,
+    version: Option<u8>,
+    timestamp: Option<String>,
+    hostname: Option<String>,
+    app_name: Option<String>,
+    procid: Option<String>,
+    msgid: Option<String>,
+    structured_data: Option<String>,
+    message: Option<String>,
+}
+
+/* RFC 5424 section 6.2.1: "<PRIVAL>", PRIVAL from 0 to 191 */
+fn syslog_pri(data: &[u8]) -> Option<(u8, &[u8])> {
+    if data.first() != Some(&b'<') {
+        return None;
+    }
+    let len = data[1..].iter().take(4).position(|&c| c == b'>')?;
+    let digits = &data[1..1 + len];
+    if digits.is_empty()
+        || (digits.len() > 1 && digits[0] == b'0')
+        || !digits.iter().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let pri = std::str::from_utf8(digits).ok()?.parse::<u16>().ok()?;
+    if pri > 191 {
+        return None;
+    }
+    Some((pri as u8, &data[len + 2..]))
+}
+
+/* header field: NILVALUE ("-") or printable ASCII, at most max bytes */
+fn syslog_field(field: &str, max: usize) -> Option<Option<String>> {
+    if field.is_empty() || field.len() > max || !field.bytes().all(|c| (33..=126).contains(&c)) {
+        None
+    } else if field == "-" {
+        Some(None)
+    } else {
+        Some(Some(field.to_string()))
+    }
+}
+
+/* RFC 5424 section 6.3: "-" or [id param="value" ...] elements, with
+ * '"', '\' and ']' escaped in values - returns the structured data
+ * and what follows */
+fn syslog_structured_data(data: &str) -> Option<(Option<String>, &str)> {
+    if let Some(rest) = data.strip_prefix('-') {
+        return Some((None, rest));
+    }
+    let b = data.as_bytes();
+    let mut end = 0;
+    while b.get(end) == Some(&b'[') {
+        let mut quoted = false;
+        let mut escaped = false;
+        let len = b[end..].iter().position(|&c| {
+            if escaped {
+                escaped = false;
+            } else if quoted && c == b'\\' {
+                escaped = true;
+            } else if c == b'"' {
+                quoted = !quoted;
+            } else if !quoted && c == b']' {
+                return true;
+            }
+            false
+        })?;
+        end += len + 1;
+    }
+    if end == 0 {
+        return None;
+    }
+    Some((Some(data[..end].to_string()), &data[end..]))
+}
+
+/* RFC 5424 section 6: VERSION SP TIMESTAMP SP HOSTNAME SP APP-NAME SP
+ * PROCID SP MSGID SP STRUCTURED-DATA [SP MSG] */
+fn syslog_rfc5424(pri: u8, data: &str) -> Option<SyslogMessage> {
+    let mut fields = data.splitn(7, ' ');
+    let version = fields.next()?;
+    if version.len() > 2 || version.starts_with('0') {
+        return None;
+    }
+    let version = version.parse::<u8>().ok()?;
+    let timestamp = syslog_field(fields.next()?, 64)?;
+    let hostname = syslog_field(fields.next()?, 255)?;
+    let app_name = syslog_field(fields.next()?, 48)?;
+    let procid = syslog_field(fields.next()?, 128)?;
+    let msgid = syslog_field(fields.next()?, 32)?;
+    let (structured_data, rest) = syslog_structured_data(fields.next()?)?;
+    let message = match rest.strip_prefix(' ') {
+        Some(msg) => Some(msg.trim_start_matches('\u{feff}').to_string()),
+        None if rest.is_empty() => None,
+        None => return None,
+    };
+    Some(SyslogMessage {
+        format: "rfc5424",
+        pri: Some(pri),
+        version: Some(version),
+        timestamp,
+        hostname,
+        app_name,
+        procid,
+        msgid,
+        structured_data,
+        message,
+    })
+}
+
+/* RFC 3164 section 4.1: TIMESTAMP ("Mmm dd hh:mm:ss") SP HOSTNAME SP
+ * MSG, MSG starting with a TAG (e.g., "sshd[42]: "); anything else
+ * after the PRI is the message */
+fn syslog_rfc3164(pri: u8, data: &str) -> SyslogMessage {
+    let mut msg = SyslogMessage {
+        format: "rfc3164",
+        pri: Some(pri),
+        ..SyslogMessage::default()
+    };
+    let b = data.as_bytes();
+    if b.len() < 16
+        || !data.get(..3).is_some_and(|m| SYSLOG_MONTHS.contains(&m))
+        || b[3] != b' '
+        || !(b[4] == b' ' || b[4].is_ascii_digit())
+        || !b[5].is_ascii_digit()
+        || b[6] != b' '
+        || b[9] != b':'
+        || b[12] != b':'
+        || b[15] != b' '
+        || ![7, 8, 10, 11, 13, 14]
+            .iter()
+            .all(|&i| b[i].is_ascii_digit())
+    {
+        msg.message = Some(data.to_string());
+        return msg;
+    }
+    msg.timestamp = Some(data[..15].to_string());
+    let (hostname, rest) = data[16..].split_once(' ').unwrap_or((&data[16..], ""));
+    if !hostname.is_empty() {
+        msg.hostname = Some(hostname.to_string());
+    }
+    /* TAG: at most 32 alphanumeric characters (and "-_./"), an
+     * optional "[pid]", then ':' */
+    let tag_len = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "-_./".contains(c)))
+        .unwrap_or(rest.len());
+    if tag_len > 0 && tag_len <= 32 {
+        let (tag, after) = rest.split_at(tag_len);
+        let (procid, after) = match after.strip_prefix('[').and_then(|a| a.split_once(']')) {
+            Some((pid, after)) => (Some(pid.to_string()), after),
+            None => (None, after),
+        };
+        if let Some(content) = after.strip_prefix(':') {
+            msg.app_name = Some(tag.to_string());
+            msg.procid = procid;
+            msg.message = Some(content.strip_prefix(' ').unwrap_or(content).to_string());
+            return msg;
+        }
+    }
+    if !rest.is_empty() {
+        msg.message = Some(rest.to_string());
+    }
+    msg
+}
+
+/* Fields of a message: without a valid PRI (e.g., binary data), the
+ * message is only recorded as received */
+fn syslog_parse(data: &[u8]) -> SyslogMessage {
+    let (pri, rest) = match syslog_pri(data) {
+        Some(p) => p,
+        None => {
+            return SyslogMessage {
+                format: "raw",
+                ..SyslogMessage::default()
+            }
+        }
+    };
+    let rest = String::from_utf8_lossy(rest);
+    let rest = rest.trim_end_matches(&['\r', '\n', '\0'][..]);
+    syslog_rfc5424(pri, rest).unwrap_or_else(|| syslog_rfc3164(pri, rest))
+}
+
+/* beginning of s, at most max bytes */
+fn syslog_truncate(s: &str, max: usize) -> &str {
+    let mut len = std::cmp::min(s.len(), max);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    &s[..len]
+}
+
+fn syslog_event(data: &[u8], masscanned: &Masscanned, client_info: &ClientInfo) -> Event {
+    let config = &masscanned.config.syslog;
+    let msg = syslog_parse(data);
+    let mut event = Event::new("syslog_message", client_info);
+    event.set("format", msg.format);
+    if let Some(pri) = msg.pri {
+        event.set("pri", pri);
+        event.set("facility", SYSLOG_FACILITIES[(pri >> 3) as usize]);
+        event.set("severity", SYSLOG_SEVERITIES[(pri & 7) as usize]);
+    }
+    if let Some(version) = msg.version {
+        event.set("version", version);
+    }
+    for (name, value) in [
+        ("timestamp", msg.timestamp),
+        ("hostname", msg.hostname),
+        ("app_name", msg.app_name),
+        ("procid", msg.procid),
+        ("msgid", msg.msgid),
+        ("structured_data", msg.structured_data),
+    ]
+    .iter()
+    {
+        if let Some(value) = value {
+            event.set(name, value.as_str());
+        }
+    }
+    if let Some(message) = msg.message {
+        event.set("message", syslog_truncate(&message, config.log_data_max));
+    }
+    event.set("length", data.len());
+    let len = std::cmp::min(data.len(), config.log_data_max);
+    event.set("data", encode(&data[..len], config.log_data_encoding));
+    if len < data.len() {
+        event.set("truncated", true);
+    }
+    event
+}
+
+/* RFC 6587 section 3.4: next message of a stream, octet-counted
+ * ("<length> <message>") or terminated by LF - overlong messages are
+ * recorded as received so far */
+fn syslog_frame(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let digits = buffer.iter().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 && digits <= 5 && buffer[0] != b'0' {
+        match buffer.get(digits) {
+            None => return None,
+            Some(b' ') => {
+                let len = std::str::from_utf8(&buffer[..digits])
+                    .ok()?
+                    .parse::<usize>()
+                    .ok()?;
+                let end = digits + 1 + len;
+                if buffer.len() >= end {
+                    let frame = buffer[digits + 1..end].to_vec();
+                    buffer.drain(..end);
+                    return Some(frame);
+                }
+                if len <= LINE_MAX_BUFFER {
+                    return None;
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    match buffer.iter().position(|&c| c == b'\n') {
+        Some(i) => {
+            let mut frame: Vec<u8> = buffer.drain(..i + 1).collect();
+            frame.pop();
+            Some(frame)
+        }
+        None if buffer.len() > LINE_MAX_BUFFER => {
+            info!(
+                "syslog message too long ({} bytes received) - recorded",
+                buffer.len()
+            );
+            Some(std::mem::take(buffer))
+        }
+        None => None,
+    }
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving syslog data");
+    let t = match tcb {
+        Some(t) => t,
+        None => {
+            syslog_event(data, masscanned, client_info).log();
+            return None;
+        }
+    };
+    let mut state = match std::mem::replace(&mut t.proto_state, ProtoState::None) {
+        ProtoState::Syslog(s) => s,
+        _ => SyslogState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    while let Some(frame) = syslog_frame(&mut state.buffer) {
+        /* empty lines between messages */
+        if !frame.iter().all(|c| c.is_ascii_whitespace()) {
+            syslog_event(&frame, masscanned, client_info).log();
+        }
+    }
+    t.proto_state = ProtoState::Syslog(state);
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    fn client(tcp: bool) -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(if tcp {
+            IpNextHeaderProtocols::Tcp
+        } else {
+            IpNextHeaderProtocols::Udp
+        });
+        client_info.port.src = Some(50514);
+        client_info.port.dst = Some(514);
+        client_info
+    }
+
+    /* RFC 5424 section 6.5, examples 1 and 3 */
+    const RFC5424_SU: &[u8] = b"<34>1 2003-10-11T22:14:15.003Z mymachine.example.com su - ID47 - \xef\xbb\xbf'su root' failed for lonvick on /dev/pts/8";
+    const RFC5424_SD: &[u8] = b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut=\"3\" eventSource=\"Application\" eventID=\"1011\"] An application event log entry...";
+    /* RFC 3164 section 5.4, examples 1 and 2 */
+    const RFC3164_SU: &[u8] =
+        b"<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8\n";
+    const RFC3164_NO_HEADER: &[u8] = b"<13>Use the BFG!";
+
+    #[test]
+    fn test_syslog_pri() {
+        assert!(syslog_pri(b"<0>a") == Some((0, &b"a"[..])));
+        assert!(syslog_pri(b"<191>") == Some((191, &b""[..])));
+        for data in [
+            &b"<192>a"[..],
+            b"<01>a",
+            b"<>a",
+            b"<1a>",
+            b"<1234>",
+            b"34>",
+            b"",
+        ]
+        .iter()
+        {
+            assert!(syslog_pri(data).is_none());
+        }
+    }
+
+    #[test]
+    fn test_syslog_rfc5424() {
+        let msg = syslog_parse(RFC5424_SU);
+        assert!(
+            msg == SyslogMessage {
+                format: "rfc5424",
+                pri: Some(34),
+                version: Some(1),
+                timestamp: Some("2003-10-11T22:14:15.003Z".to_string()),
+                hostname: Some("mymachine.example.com".to_string()),
+                app_name: Some("su".to_string()),
+                procid: None,
+                msgid: Some("ID47".to_string()),
+                structured_data: None,
+                message: Some("'su root' failed for lonvick on /dev/pts/8".to_string()),
+            }
+        );
+        let msg = syslog_parse(RFC5424_SD);
+        assert!(msg.format == "rfc5424" && msg.pri == Some(165));
+        assert!(msg.app_name.as_deref() == Some("evntslog"));
+        assert!(
+            msg.structured_data.as_deref()
+                == Some(
+                    "[exampleSDID@32473 iut=\"3\" eventSource=\"Application\" eventID=\"1011\"]"
+                )
+        );
+        assert!(msg.message.as_deref() == Some("An application event log entry..."));
+        /* several elements, escaped ']', no message */
+        let msg = syslog_parse(b"<14>1 - - - - - [a x=\"1\\]\"][b@1]");
+        assert!(msg.format == "rfc5424" && msg.hostname.is_none());
+        assert!(msg.structured_data.as_deref() == Some("[a x=\"1\\]\"][b@1]"));
+        assert!(msg.message.is_none());
+        /* unterminated structured data: not RFC 5424 */
+        assert!(syslog_parse(b"<14>1 - - - - - [a x=\"]").format == "rfc3164");
+    }
+
+    #[test]
+    fn test_syslog_rfc3164() {
+        let msg = syslog_parse(RFC3164_SU);
+        assert!(
+            msg == SyslogMessage {
+                format: "rfc3164",
+                pri: Some(34),
+                timestamp: Some("Oct 11 22:14:15".to_string()),
+                hostname: Some("mymachine".to_string()),
+                app_name: Some("su".to_string()),
+                message: Some("'su root' failed for lonvick on /dev/pts/8".to_string()),
+                ..SyslogMessage::default()
+            }
+        );
+        let msg = syslog_parse(b"<86>Feb  5 17:32:18 10.0.0.99 sshd[4821]: Accepted publickey");
+        assert!(msg.timestamp.as_deref() == Some("Feb  5 17:32:18"));
+        assert!(msg.hostname.as_deref() == Some("10.0.0.99"));
+        assert!(msg.app_name.as_deref() == Some("sshd") && msg.procid.as_deref() == Some("4821"));
+        assert!(msg.message.as_deref() == Some("Accepted publickey"));
+        /* no TAG */
+        let msg = syslog_parse(b"<13>Oct 11 22:14:15 host link down on eth0");
+        assert!(msg.app_name.is_none());
+        assert!(msg.message.as_deref() == Some("link down on eth0"));
+        /* no header */
+        let msg = syslog_parse(RFC3164_NO_HEADER);
+        assert!(msg.format == "rfc3164" && msg.pri == Some(13));
+        assert!(msg.timestamp.is_none() && msg.hostname.is_none());
+        assert!(msg.message.as_deref() == Some("Use the BFG!"));
+    }
+
+    #[test]
+    fn test_syslog_rfc3164_month() {
+        /* non-UTF-8 and multibyte month fields: no header */
+        for data in [
+            &b"<13>\xff\xfe\xfd 11 22:14:15 host su: failed"[..],
+            "<13>\u{e9}ct 11 22:14:15 host su: failed".as_bytes(),
+            "<13>Oc\u{e9} 11 22:14:15 host su: failed".as_bytes(),
+        ]
+        .iter()
+        {
+            let msg = syslog_parse(data);
+            assert!(msg.format == "rfc3164" && msg.pri == Some(13));
+            assert!(msg.timestamp.is_none() && msg.hostname.is_none());
+            assert!(msg.message.unwrap().ends_with(" host su: failed"));
+            let json = syslog_event(data, &masscanned(Config::default()), &client(false)).to_json();
+            assert!(json["data"] == base64::encode(data));
+        }
+    }
+
+    #[test]
+    fn test_syslog_garbage() {
+        let masscanned = masscanned(Config::default());
+        let garbage = b"\x00\xff\x13\x37<34\xfe";
+        assert!(syslog_parse(garbage).format == "raw");
+        let event = syslog_event(garbage, &masscanned, &client(false));
+        let json = event.to_json();
+        assert!(json["format"] == "raw" && json.get("pri").is_none());
+        assert!(json["length"] == garbage.len());
+        assert!(json["data"] == base64::encode(garbage));
+        /* never answered */
+        let mut client_info = client(false);
+        assert!(crate::proto::dispatch(garbage, &masscanned, &mut client_info, None).is_none());
+    }
+
+    #[test]
+    fn test_syslog_event() {
+        let masscanned = self::masscanned(
+            Config::from_str("[syslog]\nlog_data_max = 8\nlog_data_encoding = \"hex\"\n").unwrap(),
+        );
+        let json = syslog_event(RFC3164_SU, &masscanned, &client(false)).to_json();
+        assert!(json["event"] == "syslog_message" && json["transport"] == "udp");
+        assert!(json["facility"] == "auth" && json["severity"] == "crit");
+        assert!(json["hostname"] == "mymachine" && json["app_name"] == "su");
+        assert!(json["message"] == "'su root");
+        assert!(json["data"] == "3c33343e4f637420");
+        assert!(json["truncated"] == true);
+        let json = syslog_event(RFC5424_SD, &masscanned, &client(false)).to_json();
+        assert!(json["facility"] == "local4" && json["severity"] == "notice");
+        assert!(json["version"] == 1 && json["msgid"] == "ID47");
+        assert!(json.get("procid").is_none());
+    }
+
+    #[test]
+    fn test_syslog_frame() {
+        let mut buffer = b"9 <13>a\nb\nc<14>b\n11".to_vec();
+        assert!(syslog_frame(&mut buffer) == Some(b"<13>a\nb\nc".to_vec()));
+        assert!(syslog_frame(&mut buffer) == Some(b"<14>b".to_vec()));
+        /* incomplete */
+        assert!(syslog_frame(&mut buffer).is_none());
+        buffer.extend_from_slice(b" <13>hello");
+        assert!(syslog_frame(&mut buffer).is_none());
+        buffer.extend_from_slice(b"!\n");
+        assert!(syslog_frame(&mut buffer) == Some(b"<13>hello!\n".to_vec()));
+        assert!(buffer.is_empty());
+        /* overlong messages */
+        buffer.extend_from_slice(&[b'a'; LINE_MAX_BUFFER + 1]);
+        assert!(syslog_frame(&mut buffer).unwrap().len() == LINE_MAX_BUFFER + 1);
+        buffer.extend_from_slice(b"99999 <13>");
+        assert!(syslog_frame(&mut buffer).is_none());
+    }
+
+    #[test]
+    fn test_syslog_tcp() {
+        let mut config = Config::default();
+        let mut client_info = client(true);
+        let mut tcb = TCPControlBlock::new();
+        /* TCP is not handled by default */
+        let masscanned = self::masscanned(Config::default());
+        assert!(
+            crate::proto::dispatch(RFC3164_SU, &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        assert!(!matches!(tcb.proto_state, ProtoState::Syslog(_)));
+        config.syslog.tcp_ports = vec![514];
+        let masscanned = self::masscanned(config);
+        let mut tcb = TCPControlBlock::new();
+        /* message split across segments, then the beginning of an
+         * octet-counted one */
+        for (data, kept) in [
+            (&RFC3164_SU[..20], 20),
+            (&RFC3164_SU[20..], 0),
+            (b"80 <34>1 - -", 12),
+        ]
+        .iter()
+        {
+            assert!(
+                crate::proto::dispatch(data, &masscanned, &mut client_info, Some(&mut tcb))
+                    .is_none()
+            );
+            match &tcb.proto_state {
+                ProtoState::Syslog(s) => assert!(s.buffer.len() == *kept),
+                _ => panic!("expected syslog state"),
+            }
+        }
+    }
+}
@@ -0,0 +1,461 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use md5::{Digest, Md5};
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* RFC 8907 */
+const TAC_PLUS_MAJOR_VER: u8 = 0xc;
+const TAC_PLUS_HEADER_LEN: usize = 12;
+const TAC_PLUS_AUTHEN: u8 = 1;
+const TAC_PLUS_UNENCRYPTED_FLAG: u8 = 0x01;
+
+/* authentication type (START), status and flags (REPLY) */
+const TAC_PLUS_AUTHEN_TYPE_PAP: u8 = 2;
+const TAC_PLUS_AUTHEN_STATUS_FAIL: u8 = 2;
+const TAC_PLUS_AUTHEN_STATUS_GETPASS: u8 = 5;
+const TAC_PLUS_AUTHEN_STATUS_ERROR: u8 = 7;
+const TAC_PLUS_REPLY_FLAG_NOECHO: u8 = 0x01;
+
+/* size of a packet kept between two segments */
+const TAC_PLUS_MAX_LEN: usize = 65536;
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TacacsStatus {
+    #[default]
+    Getpass,
+    Fail,
+}
+
+/* TACACS+ server (on the TCP ports): authentication START packets get
+ * a GETPASS reply (the password in the CONTINUE packet that follows then
+ * gets a FAIL reply), or a FAIL reply. Packets are obfuscated with key,
+ * when set (packets obfuscated with another key are not answered).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TacacsConfig {
+    pub ports: Vec<u16>,
+    pub key: Option<String>,
+    pub status: TacacsStatus,
+    pub password_prompt: String,
+    pub fail_message: String,
+}
+
+impl Default for TacacsConfig {
+    fn default() -> Self {
+        TacacsConfig {
+            ports: vec![49],
+            key: None,
+            status: TacacsStatus::Getpass,
+            password_prompt: "Password: ".to_string(),
+            fail_message: "Authentication failed".to_string(),
+        }
+    }
+}
+
+/* state of a flow: beginning of a packet not received entirely yet, and
+ * the START of the current authentication session */
+#[derive(Default)]
+pub struct TacacsState {
+    buffer: Vec<u8>,
+    session: Option<(u32, String)>,
+}
+
+struct TacacsHeader {
+    version: u8,
+    packet_type: u8,
+    seq_no: u8,
+    flags: u8,
+    session_id: u32,
+    length: usize,
+}
+
+fn tacacs_parse_header(data: &[u8]) -> Option<TacacsHeader> {
+    if data.len() < TAC_PLUS_HEADER_LEN {
+        return None;
+    }
+    Some(TacacsHeader {
+        version: data[0],
+        packet_type: data[1],
+        seq_no: data[2],
+        flags: data[3],
+        session_id: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        length: u32::from_be_bytes([data[8], data[9], data[10], data[11]]) as usize,
+    })
+}
+
+/* RFC 8907 section 4.5: MD5 pseudo-pad */
+fn tacacs_pad(header: &TacacsHeader, key: &[u8], len: usize) -> Vec<u8> {
+    let mut pad = Vec::with_capacity(len + 16);
+    while pad.len() < len {
+        let mut hasher = Md5::new();
+        hasher.update(header.session_id.to_be_bytes());
+        hasher.update(key);
+        hasher.update([header.version, header.seq_no]);
+        if pad.len() >= 16 {
+            hasher.update(&pad[pad.len() - 16..]);
+        }
+        pad.extend(hasher.finalize());
+    }
+    pad.truncate(len);
+    pad
+}
+
+/* (de)obfuscation of a body, unless the packet is unencrypted */
+fn tacacs_crypt(header: &TacacsHeader, key: &[u8], body: &mut [u8]) {
+    if header.flags & TAC_PLUS_UNENCRYPTED_FLAG != 0 {
+        return;
+    }
+    let pad = tacacs_pad(header, key, body.len());
+    for (b, p) in body.iter_mut().zip(pad.iter()) {
+        *b ^= p;
+    }
+}
+
+/* fields of a body, from their lengths */
+fn tacacs_fields<'a>(data: &'a [u8], lengths: &[usize]) -> Option<Vec<&'a [u8]>> {
+    let mut fields = Vec::new();
+    let mut offset = 0;
+    for len in lengths.iter() {
+        fields.push(data.get(offset..offset + len)?);
+        offset += len;
+    }
+    Some(fields)
+}
+
+fn tacacs_string(value: &[u8]) -> String {
+    String::from_utf8_lossy(value).to_string()
+}
+
+/* authentication REPLY, with the flags of the request */
+fn tacacs_reply(
+    header: &TacacsHeader,
+    key: &[u8],
+    status: u8,
+    flags: u8,
+    message: &str,
+) -> Vec<u8> {
+    let reply = TacacsHeader {
+        seq_no: header.seq_no.wrapping_add(1),
+        length: 6 + message.len(),
+        ..*header
+    };
+    let mut body = vec![status, flags];
+    body.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    body.extend_from_slice(&[0, 0]);
+    body.extend_from_slice(message.as_bytes());
+    tacacs_crypt(&reply, key, &mut body);
+    let mut packet = vec![reply.version, reply.packet_type, reply.seq_no, reply.flags];
+    packet.extend_from_slice(&reply.session_id.to_be_bytes());
+    packet.extend_from_slice(&(reply.length as u32).to_be_bytes());
+    packet.extend(body);
+    packet
+}
+
+fn tacacs_login(user: &str, password: &[u8], client_info: &ClientInfo) {
+    let password = tacacs_string(password);
+    warn!("TACACS+ login: {}:{}", user, password);
+    let mut event = Event::new("tacacs_login", client_info);
+    event.set("user", user);
+    event.set("password", password);
+    event.log();
+}
+
+/* answer to an authentication packet (START, or CONTINUE) */
+fn tacacs_answer(
+    header: &TacacsHeader,
+    body: &[u8],
+    state: &mut TacacsState,
+    config: &TacacsConfig,
+    client_info: &mut ClientInfo,
+) -> Option<Vec<u8>> {
+    let key = config.key.as_deref().unwrap_or("").as_bytes();
+    let fail = |message: &str| tacacs_reply(header, key, TAC_PLUS_AUTHEN_STATUS_FAIL, 0, message);
+    if header.seq_no == 1 {
+        /* START: action, priv_lvl, authen_type, authen_service, then the
+         * lengths of user, port, rem_addr and data */
+        let lengths: Vec<usize> = body.get(4..8)?.iter().map(|l| *l as usize).collect();
+        let fields = tacacs_fields(body.get(8..)?, &lengths)?;
+        let user = tacacs_string(fields[0]);
+        let mut event = Event::new("tacacs_start", client_info);
+        event.set("session_id", header.session_id);
+        event.set("action", body[0]);
+        event.set("priv_lvl", body[1]);
+        event.set("authen_type", body[2]);
+        event.set("service", body[3]);
+        event.set("user", user.as_str());
+        event.set("port", tacacs_string(fields[1]));
+        event.set("rem_addr", tacacs_string(fields[2]));
+        event.set("data", tacacs_string(fields[3]));
+        event.log();
+        if body[2] == TAC_PLUS_AUTHEN_TYPE_PAP {
+            tacacs_login(&user, fields[3], client_info);
+            return Some(fail(&config.fail_message));
+        }
+        if config.status == TacacsStatus::Fail {
+            return Some(fail(&config.fail_message));
+        }
+        state.session = Some((header.session_id, user));
+        return Some(tacacs_reply(
+            header,
+            key,
+            TAC_PLUS_AUTHEN_STATUS_GETPASS,
+            TAC_PLUS_REPLY_FLAG_NOECHO,
+            &config.password_prompt,
+        ));
+    }
+    /* CONTINUE: user_msg_len, data_len, flags, user_msg, data */
+    let user = match state.session.take() {
+        Some((session_id, user)) if session_id == header.session_id => user,
+        _ => {
+            return Some(tacacs_reply(
+                header,
+                key,
+                TAC_PLUS_AUTHEN_STATUS_ERROR,
+                0,
+                "",
+            ))
+        }
+    };
+    let user_msg_len = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let fields = tacacs_fields(body.get(5..)?, &[user_msg_len])?;
+    tacacs_login(&user, fields[0], client_info);
+    Some(fail(&config.fail_message))
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving TACACS+ data");
+    let config = &masscanned.config.tacacs;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Tacacs(s)) => s,
+        _ => TacacsState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while let Some(header) = tacacs_parse_header(&state.buffer) {
+        /* client packets have odd sequence numbers; 255 cannot be
+         * answered (RFC 8907 section 4.1) */
+        if header.version >> 4 != TAC_PLUS_MAJOR_VER
+            || header.length > TAC_PLUS_MAX_LEN
+            || header.seq_no % 2 == 0
+            || header.seq_no == 255
+        {
+            info!("TACACS+: invalid packet - dropped");
+            client_info.close = true;
+            break;
+        }
+        if state.buffer.len() < TAC_PLUS_HEADER_LEN + header.length {
+            break;
+        }
+        let mut body: Vec<u8> = state
+            .buffer
+            .drain(..TAC_PLUS_HEADER_LEN + header.length)
+            .skip(TAC_PLUS_HEADER_LEN)
+            .collect();
+        let encrypted = header.flags & TAC_PLUS_UNENCRYPTED_FLAG == 0;
+        if encrypted && config.key.is_none() {
+            info!("TACACS+: obfuscated packet, no key - dropped");
+            client_info.close = true;
+            break;
+        }
+        tacacs_crypt(
+            &header,
+            config.key.as_deref().unwrap_or("").as_bytes(),
+            &mut body,
+        );
+        if header.packet_type != TAC_PLUS_AUTHEN {
+            info!("TACACS+ packet type not handled: {}", header.packet_type);
+            client_info.close = true;
+            break;
+        }
+        match tacacs_answer(&header, &body, &mut state, config, client_info) {
+            Some(reply) => repl_data.extend(reply),
+            None => {
+                /* malformed (or obfuscated with another key) */
+                info!("TACACS+: malformed packet - dropped");
+                client_info.close = true;
+                break;
+            }
+        }
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Tacacs(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending TACACS+ data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn instance(key: Option<&str>, status: TacacsStatus) -> Masscanned<'static> {
+        let mut config = Config::default();
+        config.tacacs.key = key.map(|k| k.to_string());
+        config.tacacs.status = status;
+        crate::proto::test_util::masscanned(config)
+    }
+
+    fn packet(seq_no: u8, flags: u8, key: &[u8], body: &[u8]) -> Vec<u8> {
+        let header = TacacsHeader {
+            version: 0xc1,
+            packet_type: TAC_PLUS_AUTHEN,
+            seq_no,
+            flags,
+            session_id: 0x12345678,
+            length: body.len(),
+        };
+        let mut body = body.to_vec();
+        tacacs_crypt(&header, key, &mut body);
+        let mut packet = vec![0xc1, TAC_PLUS_AUTHEN, seq_no, flags, 0x12, 0x34, 0x56, 0x78];
+        packet.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        packet.extend(body);
+        packet
+    }
+
+    /* header and (clear) body of a reply */
+    fn reply(data: &[u8], key: &[u8]) -> (TacacsHeader, Vec<u8>) {
+        let header = tacacs_parse_header(data).unwrap();
+        assert!(data.len() == TAC_PLUS_HEADER_LEN + header.length);
+        let mut body = data[TAC_PLUS_HEADER_LEN..].to_vec();
+        tacacs_crypt(&header, key, &mut body);
+        (header, body)
+    }
+
+    #[test]
+    fn test_tacacs_pad() {
+        /* reference: MD5(session_id, key, version, seq_no[, previous]) */
+        let header =
+            tacacs_parse_header(b"\xc1\x01\x01\x00\x12\x34\x56\x78\x00\x00\x00\x28").unwrap();
+        let pad = tacacs_pad(&header, b"testing123", header.length);
+        assert!(
+            pad == b"\x02\xcc\xcc\xe9\x22\xa5\xfd\x21\x6a\xab\xca\xb6\xff\x70\x40\xa1\
+                     \xaa\xb9\x81\x18\x31\x83\xce\x58\xf4\x4d\xab\x82\xac\x2a\x97\xfd\
+                     \xca\x50\x85\xf4\xa7\x55\xb7\xd4"[..]
+        );
+        assert!(tacacs_pad(&header, b"testing123", 5) == pad[..5]);
+        assert!(tacacs_pad(&header, b"other", 5) != pad[..5]);
+    }
+
+    #[test]
+    fn test_tacacs_login() {
+        let key = b"testing123";
+        let masscanned = instance(Some("testing123"), TacacsStatus::Getpass);
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(pnet::packet::ip::IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(49);
+        let mut tcb = TCPControlBlock::new();
+        /* START (login, ASCII): admin on tty0 from 10.0.0.1 */
+        let start = packet(
+            1,
+            0,
+            key,
+            b"\x01\x01\x01\x01\x05\x04\x08\x00admintty010.0.0.1",
+        );
+        let repl_data =
+            crate::proto::dispatch(&start, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        let (header, body) = reply(&repl_data, key);
+        assert!(header.seq_no == 2 && header.session_id == 0x12345678);
+        assert!(body[..6] == [TAC_PLUS_AUTHEN_STATUS_GETPASS, 1, 0, 10, 0, 0]);
+        assert!(body[6..] == b"Password: "[..]);
+        /* CONTINUE, in two segments */
+        let cont = packet(3, 0, key, b"\x00\x06\x00\x00\x00s3cret");
+        assert!(
+            crate::proto::dispatch(&cont[..14], &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        let repl_data =
+            crate::proto::dispatch(&cont[14..], &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        let (header, body) = reply(&repl_data, key);
+        assert!(header.seq_no == 4 && body[0] == TAC_PLUS_AUTHEN_STATUS_FAIL);
+        /* CONTINUE without START */
+        let repl_data =
+            crate::proto::dispatch(&cont, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(reply(&repl_data, key).1[0] == TAC_PLUS_AUTHEN_STATUS_ERROR);
+        assert!(!client_info.close);
+        /* server sequence numbers, other keys */
+        let mut even = start.clone();
+        even[2] = 2;
+        assert!(repl(&even, &masscanned, &mut client_info, None).is_none());
+        assert!(client_info.close);
+        client_info.close = false;
+        let other = packet(
+            1,
+            0,
+            b"other",
+            b"\x01\x01\x01\x01\x05\x04\x08\x00admintty010.0.0.1",
+        );
+        assert!(repl(&other, &masscanned, &mut client_info, None).is_none());
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_tacacs_unencrypted() {
+        /* PAP, unencrypted, no key: FAIL */
+        let masscanned = instance(None, TacacsStatus::Fail);
+        let mut client_info = ClientInfo::new();
+        let start = packet(
+            1,
+            TAC_PLUS_UNENCRYPTED_FLAG,
+            b"",
+            b"\x01\x01\x02\x01\x04\x00\x00\x06rootcisco1",
+        );
+        let repl_data = repl(&start, &masscanned, &mut client_info, None).unwrap();
+        assert!(repl_data[3] == TAC_PLUS_UNENCRYPTED_FLAG);
+        let (header, body) = reply(&repl_data, b"");
+        assert!(header.seq_no == 2 && body[0] == TAC_PLUS_AUTHEN_STATUS_FAIL);
+        assert!(body[6..] == b"Authentication failed"[..]);
+        /* ASCII: FAIL too */
+        let start = packet(
+            1,
+            TAC_PLUS_UNENCRYPTED_FLAG,
+            b"",
+            b"\x01\x01\x01\x01\x04\x00\x00\x00root",
+        );
+        let repl_data = repl(&start, &masscanned, &mut client_info, None).unwrap();
+        assert!(reply(&repl_data, b"").1[0] == TAC_PLUS_AUTHEN_STATUS_FAIL);
+        /* obfuscated, but no key */
+        assert!(repl(
+            &packet(1, 0, b"k", b"\x01\x01\x01\x01\x00\x00\x00\x00"),
+            &masscanned,
+            &mut client_info,
+            None
+        )
+        .is_none());
+        assert!(client_info.close);
+    }
+}
@@ -0,0 +1,341 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::proto::adb::AdbState;
+use crate::proto::amqp::AmqpState;
+use crate::proto::bitcoin::BitcoinState;
+use crate::proto::dcerpc::DcerpcState;
+use crate::proto::dnp3::Dnp3State;
+use crate::proto::dns::DnsState;
+use crate::proto::enip::EnipState;
+use crate::proto::finger::FingerState;
+use crate::proto::ftp::FtpState;
+use crate::proto::gh0st::Gh0stState;
+use crate::proto::git::GitState;
+use crate::proto::gopher::GopherState;
+use crate::proto::iec104::Iec104State;
+use crate::proto::imap::ImapState;
+use crate::proto::jdwp::JdwpState;
+use crate::proto::kafka::KafkaState;
+use crate::proto::kerberos::KerberosState;
+use crate::proto::ldap::LdapState;
+use crate::proto::memcached::MemcachedState;
+use crate::proto::minecraft::MinecraftState;
+use crate::proto::mongodb::MongodbState;
+use crate::proto::mqtt::MqttState;
+use crate::proto::mssql::MssqlState;
+use crate::proto::mysql::MysqlState;
+use crate::proto::nats::NatsState;
+use crate::proto::openvpn::OpenvpnState;
+use crate::proto::pop3::Pop3State;
+use crate::proto::postgres::PostgresState;
+use crate::proto::pptp::PptpState;
+use crate::proto::redis::RedisState;
+use crate::proto::rmi::RmiState;
+use crate::proto::rpc::RpcState;
+use crate::proto::rsync::RsyncState;
+use crate::proto::rtsp::RtspState;
+use crate::proto::s7::S7State;
+use crate::proto::smb::SmbState;
+use crate::proto::smtp::SmtpState;
+use crate::proto::socks::SocksState;
+use crate::proto::ssh::SshState;
+use crate::proto::syslog::SyslogState;
+use crate::proto::tacacs::TacacsState;
+use crate::proto::telnet::TelnetState;
+use crate::proto::tftp::TftpState;
+use crate::proto::tls::{TlsSession, TlsState};
+use crate::proto::tns::TnsState;
+use crate::proto::tor::TorState;
+use crate::proto::vnc::VncState;
+use crate::proto::websocket::WebSocketState;
+use crate::proto::whois::WhoisState;
+use crate::proto::x11::X11State;
+use crate::proto::zabbix::ZabbixState;
+use crate::smack::{BASE_STATE, NO_MATCH};
+
+/* Bounds of the table of TCP flows: flows idle for longer than
+ * flow_timeout seconds are forgotten, and when max_flows flows are
+ * known, the least recently seen one makes room for a new one.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TcpConfig {
+    pub max_flows: usize,
+    pub flow_timeout: u64,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        TcpConfig {
+            max_flows: 65536,
+            flow_timeout: 60,
+        }
+    }
+}
+
+/* state of the protocol of a flow, for protocols that need one */
+pub enum ProtoState {
+    None,
+    WebSocket(WebSocketState),
+    Tls(TlsState),
+    TlsSession(Box<TlsSession>),
+    Ssh(SshState),
+    Smb(SmbState),
+    Ftp(FtpState),
+    Smtp(SmtpState),
+    Pop3(Pop3State),
+    Imap(ImapState),
+    Telnet(TelnetState),
+    Dns(DnsState),
+    Rtsp(RtspState),
+    Vnc(VncState),
+    Mysql(MysqlState),
+    Postgres(PostgresState),
+    Mssql(MssqlState),
+    Tns(TnsState),
+    Redis(RedisState),
+    Memcached(MemcachedState),
+    Mongodb(MongodbState),
+    Amqp(AmqpState),
+    Mqtt(MqttState),
+    S7(S7State),
+    Dnp3(Dnp3State),
+    Enip(EnipState),
+    Iec104(Iec104State),
+    Tftp(TftpState),
+    Rpc(RpcState),
+    Kerberos(KerberosState),
+    Ldap(LdapState),
+    Tacacs(TacacsState),
+    Socks(SocksState),
+    Git(GitState),
+    Rsync(RsyncState),
+    Zabbix(ZabbixState),
+    Nats(NatsState),
+    Kafka(KafkaState),
+    Openvpn(OpenvpnState),
+    Pptp(PptpState),
+    Minecraft(MinecraftState),
+    Finger(FingerState),
+    Gopher(GopherState),
+    Whois(WhoisState),
+    X11(X11State),
+    Dcerpc(DcerpcState),
+    Adb(AdbState),
+    Jdwp(JdwpState),
+    Rmi(RmiState),
+    Gh0st(Gh0stState),
+    Bitcoin(BitcoinState),
+    Tor(TorState),
+    Syslog(SyslogState),
+}
+
+/* TCP control block: state of a TCP flow, identified by its
+ * SYNACK-cookie */
+pub struct TCPControlBlock {
+    /* state of the protocol detection automaton */
+    pub smack_state: usize,
+    /* protocol detected on the flow (NO_MATCH until detected) */
+    pub proto_id: usize,
+    pub proto_state: ProtoState,
+    /* number of bytes sent to the client: the next acknowledgement
+     * number expected is cookie + 1 + sent */
+    pub sent: u32,
+    last_seen: Instant,
+}
+
+impl TCPControlBlock {
+    pub fn new() -> Self {
+        TCPControlBlock {
+            smack_state: BASE_STATE,
+            proto_id: NO_MATCH,
+            proto_state: ProtoState::None,
+            sent: 0,
+            last_seen: Instant::now(),
+        }
+    }
+
+    /* forget the protocol of the flow: what comes next is identified
+     * again (e.g., a TLS handshake after STARTTLS) */
+    pub fn reset_protocol(&mut self) {
+        self.smack_state = BASE_STATE;
+        self.proto_id = NO_MATCH;
+        self.proto_state = ProtoState::None;
+    }
+
+    /* STARTTLS: what comes next is a TLS handshake, and the decrypted
+     * content of the session goes on with the protocol of the flow */
+    pub fn start_tls(&mut self) {
+        let inner = self.proto_id;
+        self.reset_protocol();
+        self.proto_state = ProtoState::Tls(TlsState::starttls(inner));
+    }
+}
+
+pub struct FlowTable {
+    flows: HashMap<u32, TCPControlBlock>,
+    /* the flows, least recently seen first */
+    by_last_seen: BTreeSet<(Instant, u32)>,
+}
+
+impl FlowTable {
+    fn new() -> Self {
+        FlowTable {
+            flows: HashMap::new(),
+            by_last_seen: BTreeSet::new(),
+        }
+    }
+
+    /* control block of a flow, created when the flow is not known */
+    pub fn get(&mut self, cookie: u32, config: &TcpConfig) -> &mut TCPControlBlock {
+        let now = Instant::now();
+        match self.flows.get(&cookie) {
+            Some(tcb) => {
+                self.by_last_seen.remove(&(tcb.last_seen, cookie));
+            }
+            None => self.expire(config, now),
+        }
+        self.by_last_seen.insert((now, cookie));
+        let tcb = self
+            .flows
+            .entry(cookie)
+            .or_insert_with(TCPControlBlock::new);
+        tcb.last_seen = now;
+        tcb
+    }
+
+    #[cfg(test)]
+    pub fn remove(&mut self, cookie: u32) {
+        if let Some(tcb) = self.flows.remove(&cookie) {
+            self.by_last_seen.remove(&(tcb.last_seen, cookie));
+        }
+    }
+
+    /* forget idle flows, and make room for a new one */
+    fn expire(&mut self, config: &TcpConfig, now: Instant) {
+        let timeout = Duration::from_secs(config.flow_timeout);
+        while let Some(&(last_seen, cookie)) = self.by_last_seen.first() {
+            if self.flows.len() < config.max_flows && now.duration_since(last_seen) < timeout {
+                break;
+            }
+            self.by_last_seen.remove(&(last_seen, cookie));
+            self.flows.remove(&cookie);
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref CONTABLE: Mutex<FlowTable> = Mutex::new(FlowTable::new());
+}
+
+/* number of bytes sent on the flow, if known */
+pub fn get_sent(cookie: u32) -> Option<u32> {
+    CONTABLE
+        .lock()
+        .unwrap()
+        .flows
+        .get(&cookie)
+        .map(|tcb| tcb.sent)
+}
+
+pub fn add_sent(cookie: u32, len: usize) {
+    if let Some(tcb) = CONTABLE.lock().unwrap().flows.get_mut(&cookie) {
+        tcb.sent = tcb.sent.wrapping_add(len as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tcb_sent() {
+        let cookie = 0xdeadbeef;
+        assert!(get_sent(cookie).is_none());
+        /* no control block: nothing is recorded */
+        add_sent(cookie, 10);
+        assert!(get_sent(cookie).is_none());
+        CONTABLE.lock().unwrap().get(cookie, &TcpConfig::default());
+        assert!(get_sent(cookie) == Some(0));
+        add_sent(cookie, 10);
+        add_sent(cookie, 5);
+        assert!(get_sent(cookie) == Some(15));
+        CONTABLE.lock().unwrap().remove(cookie);
+    }
+
+    #[test]
+    fn test_flow_table_bounds() {
+        let config = TcpConfig {
+            max_flows: 3,
+            flow_timeout: 60,
+        };
+        let mut table = FlowTable::new();
+        for cookie in 0..3 {
+            table.get(cookie, &config).proto_id = cookie as usize;
+        }
+        assert!(table.flows.len() == 3);
+        /* known flows are kept */
+        assert!(table.get(0, &config).proto_id == 0);
+        assert!(table.flows.len() == 3);
+        /* the least recently seen flow (1) makes room */
+        table.get(3, &config);
+        assert!(table.flows.len() == 3);
+        assert!(!table.flows.contains_key(&1));
+        assert!(table.flows.contains_key(&0));
+        /* idle flows expire */
+        let later = Instant::now() + Duration::from_secs(61);
+        table.expire(&config, later);
+        assert!(table.flows.is_empty() && table.by_last_seen.is_empty());
+    }
+
+    #[test]
+    fn test_flow_table_eviction() {
+        let config = TcpConfig {
+            max_flows: 1000,
+            flow_timeout: 60,
+        };
+        let mut table = FlowTable::new();
+        for cookie in 0..1000 {
+            table.get(cookie, &config);
+        }
+        /* the even flows are seen again */
+        for cookie in (0..1000).step_by(2) {
+            table.get(cookie, &config);
+        }
+        /* at capacity: each new flow evicts the least recently seen
+         * one, the odd flows first */
+        for cookie in 1000..1500 {
+            table.get(cookie, &config);
+            assert!(table.flows.len() == 1000 && table.by_last_seen.len() == 1000);
+        }
+        assert!((0..1000).all(|c| table.flows.contains_key(&c) == (c % 2 == 0)));
+        table.get(1500, &config);
+        assert!(!table.flows.contains_key(&0) && table.flows.contains_key(&2));
+        table.remove(2);
+        assert!(table.flows.len() == 999 && table.by_last_seen.len() == 999);
+        assert!(table
+            .by_last_seen
+            .iter()
+            .all(|(t, c)| table.flows[c].last_seen == *t));
+    }
+}
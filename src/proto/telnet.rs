@@ -0,0 +1,406 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::line::LineBuffer;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* RFC 854 */
+const TELNET_IAC: u8 = 255;
+const TELNET_DONT: u8 = 254;
+const TELNET_DO: u8 = 253;
+const TELNET_WONT: u8 = 252;
+const TELNET_WILL: u8 = 251;
+const TELNET_SB: u8 = 250;
+const TELNET_SE: u8 = 240;
+
+/* options accepted: RFC 857 and RFC 858 */
+const TELNET_OPT_ECHO: u8 = 1;
+const TELNET_OPT_SGA: u8 = 3;
+
+/* Telnet service (server-first): once the TCP handshake is completed
+ * on one of the ports, the server offers to echo and to suppress
+ * go-ahead, then sends the banner and the login prompt. Logins fail
+ * (at most max_attempts times) unless shell is set: a prompt is
+ * presented then, and the commands (at most max_commands) are logged.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelnetConfig {
+    pub ports: Vec<u16>,
+    pub banner: String,
+    pub login_prompt: String,
+    pub password_prompt: String,
+    pub shell: bool,
+    pub shell_prompt: String,
+    pub max_attempts: usize,
+    pub max_commands: usize,
+}
+
+impl Default for TelnetConfig {
+    fn default() -> Self {
+        TelnetConfig {
+            ports: vec![23, 2323],
+            banner: "\r\n".to_string(),
+            login_prompt: "login: ".to_string(),
+            password_prompt: "Password: ".to_string(),
+            shell: false,
+            shell_prompt: "# ".to_string(),
+            max_attempts: 3,
+            max_commands: 16,
+        }
+    }
+}
+
+/* what the next line from the client is */
+#[derive(Default)]
+enum TelnetMode {
+    #[default]
+    Login,
+    Password(String),
+    Shell,
+}
+
+/* state of a telnet flow: partial command sequence, partial line */
+#[derive(Default)]
+pub struct TelnetState {
+    /* beginning of a command (IAC ...) */
+    command: Vec<u8>,
+    /* within a subnegotiation (IAC SB ... IAC SE) */
+    sb: bool,
+    /* last data byte was CR */
+    cr: bool,
+    /* option requests answered */
+    answered: HashSet<(u8, u8)>,
+    lines: LineBuffer,
+    mode: TelnetMode,
+    attempts: usize,
+    commands: usize,
+}
+
+/* Data sent by the client without the telnet commands (CR NUL is a
+ * line end too), and the answers to its option requests: ECHO and SGA
+ * are accepted, other options refused, each request is answered once
+ * (RFC 854 loop avoidance).
+ **/
+fn telnet_input(data: &[u8], state: &mut TelnetState) -> (Vec<u8>, Vec<u8>) {
+    let mut input = std::mem::take(&mut state.command);
+    input.extend_from_slice(data);
+    let mut clean = Vec::new();
+    let mut repl = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let c = input[i];
+        if c != TELNET_IAC {
+            if !state.sb {
+                if c == 0 && state.cr {
+                    clean.push(b'\n');
+                } else if c != 0 {
+                    clean.push(c);
+                }
+                state.cr = c == b'\r';
+            }
+            i += 1;
+            continue;
+        }
+        let command = match input.get(i + 1) {
+            Some(c) => *c,
+            None => break,
+        };
+        match command {
+            TELNET_IAC => {
+                if !state.sb {
+                    clean.push(TELNET_IAC);
+                }
+                i += 2;
+            }
+            TELNET_DO | TELNET_DONT | TELNET_WILL | TELNET_WONT => {
+                let option = match input.get(i + 2) {
+                    Some(o) => *o,
+                    None => break,
+                };
+                let answer = match (command, option) {
+                    /* offered in the greeting */
+                    (TELNET_DO, TELNET_OPT_ECHO) | (TELNET_DO, TELNET_OPT_SGA) => None,
+                    (TELNET_DO, _) => Some(TELNET_WONT),
+                    (TELNET_WILL, TELNET_OPT_SGA) => Some(TELNET_DO),
+                    (TELNET_WILL, _) => Some(TELNET_DONT),
+                    _ => None,
+                };
+                if let Some(a) = answer {
+                    if state.answered.insert((command, option)) {
+                        repl.extend_from_slice(&[TELNET_IAC, a, option]);
+                    }
+                }
+                i += 3;
+            }
+            TELNET_SB => {
+                state.sb = true;
+                i += 2;
+            }
+            TELNET_SE => {
+                state.sb = false;
+                i += 2;
+            }
+            /* NOP, GA, etc. */
+            _ => i += 2,
+        }
+    }
+    state.command = input[i..].to_vec();
+    (clean, repl)
+}
+
+fn telnet_login_event(
+    user: &str,
+    password: &str,
+    config: &TelnetConfig,
+    client_info: &ClientInfo,
+) -> Event {
+    warn!("telnet login: {}:{}", user, password);
+    let mut event = Event::new("telnet_login", client_info);
+    event.set("user", user);
+    event.set("password", password);
+    event.set("success", config.shell);
+    event
+}
+
+/* answer to a line, and the event to log (if any) */
+fn telnet_line(
+    line: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    state: &mut TelnetState,
+) -> (Vec<u8>, Option<Event>) {
+    let config = &masscanned.config.telnet;
+    let line = String::from_utf8_lossy(line).to_string();
+    match std::mem::take(&mut state.mode) {
+        TelnetMode::Login => {
+            /* echo (the server has the ECHO option) */
+            let mut repl = format!("{}\r\n", line).into_bytes();
+            if line.is_empty() {
+                repl.extend(config.login_prompt.as_bytes());
+            } else {
+                state.mode = TelnetMode::Password(line);
+                repl.extend(config.password_prompt.as_bytes());
+            }
+            (repl, None)
+        }
+        TelnetMode::Password(user) => {
+            let event = telnet_login_event(&user, &line, config, client_info);
+            state.attempts += 1;
+            if config.shell {
+                state.mode = TelnetMode::Shell;
+                let mut repl = b"\r\n".to_vec();
+                repl.extend(config.shell_prompt.as_bytes());
+                return (repl, Some(event));
+            }
+            let mut repl = b"\r\nLogin incorrect\r\n".to_vec();
+            if state.attempts >= config.max_attempts {
+                client_info.close = true;
+            } else {
+                repl.extend(config.login_prompt.as_bytes());
+            }
+            (repl, Some(event))
+        }
+        TelnetMode::Shell => {
+            state.mode = TelnetMode::Shell;
+            let mut repl = format!("{}\r\n", line).into_bytes();
+            let command = line.trim();
+            if command.is_empty() {
+                repl.extend(config.shell_prompt.as_bytes());
+                return (repl, None);
+            }
+            let mut event = Event::new("telnet_command", client_info);
+            event.set("command", line.clone());
+            state.commands += 1;
+            if command == "exit" || command == "logout" || state.commands >= config.max_commands {
+                client_info.close = true;
+                return (repl, Some(event));
+            }
+            let mut words = command.split_whitespace();
+            match (words.next(), words.next()) {
+                /* what Mirai-like bots check */
+                (Some(b), Some(applet)) if b.ends_with("busybox") => {
+                    repl.extend(format!("{}: applet not found\r\n", applet).into_bytes());
+                }
+                (Some(c), _) => {
+                    repl.extend(format!("sh: {}: not found\r\n", c).into_bytes());
+                }
+                _ => {}
+            }
+            repl.extend(config.shell_prompt.as_bytes());
+            (repl, Some(event))
+        }
+    }
+}
+
+/* option offers, banner and login prompt, sent when the TCP handshake
+ * is completed */
+pub fn greeting(masscanned: &Masscanned, tcb: &mut TCPControlBlock) -> Option<Vec<u8>> {
+    let config = &masscanned.config.telnet;
+    tcb.proto_state = ProtoState::Telnet(TelnetState::default());
+    let mut repl = vec![
+        TELNET_IAC,
+        TELNET_WILL,
+        TELNET_OPT_ECHO,
+        TELNET_IAC,
+        TELNET_WILL,
+        TELNET_OPT_SGA,
+    ];
+    repl.extend(config.banner.as_bytes());
+    repl.extend(config.login_prompt.as_bytes());
+    Some(repl)
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving telnet data");
+    /* telnet is only answered over TCP, after the greeting */
+    let state = match tcb {
+        Some(TCPControlBlock {
+            proto_state: ProtoState::Telnet(s),
+            ..
+        }) => s,
+        _ => return None,
+    };
+    let (input, mut repl_data) = telnet_input(data, state);
+    for line in state.lines.push(&input) {
+        let (repl, event) = telnet_line(&line, masscanned, client_info, state);
+        if let Some(e) = event {
+            e.log();
+        }
+        repl_data.extend(repl);
+        if client_info.close {
+            break;
+        }
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending telnet data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_telnet_input() {
+        let mut state = TelnetState::default();
+        /* accepted options: no answer; refused options: answered once */
+        let (input, repl) = telnet_input(
+            b"\xff\xfd\x01\xff\xfd\x03\xff\xfd\x1f\xff\xfb\x18\xff\xfb\x03",
+            &mut state,
+        );
+        assert!(input.is_empty());
+        assert!(repl == b"\xff\xfc\x1f\xff\xfe\x18\xff\xfd\x03");
+        let (_, repl) = telnet_input(b"\xff\xfd\x1f\xff\xfc\x18\xff\xfe\x01", &mut state);
+        assert!(repl.is_empty());
+        /* commands and subnegotiations within a line, split across
+         * segments; escaped IAC; CR NUL */
+        let (input, repl) = telnet_input(b"ro\xff\xfa\x1f\x00\x50\x00\x18\xff", &mut state);
+        assert!(input == b"ro" && repl.is_empty());
+        let (input, _) = telnet_input(b"\xf0o\xff\xf1t\xff\xff\r", &mut state);
+        assert!(input == b"ot\xff\r");
+        let (input, _) = telnet_input(b"\x00", &mut state);
+        assert!(input == b"\n");
+    }
+
+    #[test]
+    fn test_telnet_mirai() {
+        let masscanned = masscanned(Config::from_str("[telnet]\nshell = true\n").unwrap());
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        assert!(repl(b"root\r\n", &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        let banner = greeting(&masscanned, &mut tcb).unwrap();
+        assert!(banner == b"\xff\xfb\x01\xff\xfb\x03\r\nlogin: ");
+        /* the dialog of the Mirai scanner (scanner.c): options accepted
+         * (WILL answered with DO), user name, password, then commands
+         * until its busybox check */
+        let mut repl_data = Vec::new();
+        for data in [
+            &b"\xff\xfd\x01\xff\xfd\x03"[..],
+            b"root\r\n",
+            b"xc3511\r\n",
+            b"enable\r\n",
+            b"system\r\n",
+            b"shell\r\n",
+            b"sh\r\n",
+            b"/bin/busybox ECCHI\r\n",
+        ]
+        .iter()
+        {
+            if let Some(r) = repl(data, &masscanned, &mut client_info, Some(&mut tcb)) {
+                repl_data.extend(r);
+            }
+        }
+        assert!(
+            repl_data.starts_with(b"root\r\nPassword: \r\n# enable\r\nsh: enable: not found\r\n# ")
+        );
+        assert!(repl_data.ends_with(b"/bin/busybox ECCHI\r\nECCHI: applet not found\r\n# "));
+        let repl_data = repl(b"exit\r\n", &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl_data == b"exit\r\n");
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_telnet_login() {
+        let masscanned = masscanned(Config::from_str("[telnet]\nmax_attempts = 2\n").unwrap());
+        let mut client_info = ClientInfo::new();
+        let mut state = TelnetState::default();
+        let (answer, event) = telnet_line(b"admin", &masscanned, &mut client_info, &mut state);
+        assert!(answer == b"admin\r\nPassword: " && event.is_none());
+        let (answer, event) = telnet_line(b"admin 123", &masscanned, &mut client_info, &mut state);
+        assert!(answer == b"\r\nLogin incorrect\r\nlogin: ");
+        let event = event.unwrap();
+        assert!(event.name == "telnet_login");
+        assert!(event.fields["user"] == "admin");
+        assert!(event.fields["password"] == "admin 123");
+        assert!(event.fields["success"] == false);
+        /* no shell: the connection is closed after max_attempts */
+        telnet_line(b"root", &masscanned, &mut client_info, &mut state);
+        let (answer, _) = telnet_line(b"vizxv", &masscanned, &mut client_info, &mut state);
+        assert!(answer == b"\r\nLogin incorrect\r\n");
+        assert!(client_info.close);
+        /* options within the user name are not logged */
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        greeting(&masscanned, &mut tcb);
+        let repl_data = repl(
+            b"sup\xff\xfb\x18port\r\x00",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"\xff\xfe\x18support\r\nPassword: ");
+    }
+}
@@ -0,0 +1,52 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+use pnet::packet::ip::IpNextHeaderProtocol;
+use pnet::util::MacAddr;
+
+use crate::client::ClientInfo;
+use crate::config::Config;
+use crate::Masscanned;
+
+/* address of the server in the tests (TEST-NET-2) */
+pub const SERVER_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7));
+
+/* instance used by the tests of the protocols: no interface, and no IP
+ * addresses (set ip_addresses when needed) */
+pub fn masscanned(config: Config) -> Masscanned<'static> {
+    Masscanned {
+        synack_key: [0, 0],
+        mac: MacAddr::from_str("00:11:22:33:44:55").expect("error parsing MAC address"),
+        iface: None,
+        ip_addresses: None,
+        config,
+    }
+}
+
+/* client 192.0.2.1, port 40000, talking to dst on port dport (the other
+ * fields, e.g., the source port, are set by the tests when needed) */
+pub fn client_info(transport: IpNextHeaderProtocol, dst: IpAddr, dport: u16) -> ClientInfo {
+    let mut client_info = ClientInfo::new();
+    client_info.transport = Some(transport);
+    client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+    client_info.ip.dst = Some(dst);
+    client_info.port.src = Some(40000);
+    client_info.port.dst = Some(dport);
+    client_info
+}
@@ -0,0 +1,488 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::synackcookie;
+use crate::Masscanned;
+
+/* opcodes */
+const TFTP_RRQ: u16 = 1;
+const TFTP_WRQ: u16 = 2;
+const TFTP_DATA: u16 = 3;
+const TFTP_ACK: u16 = 4;
+const TFTP_ERROR: u16 = 5;
+
+/* error codes */
+const TFTP_FILE_NOT_FOUND: u16 = 1;
+const TFTP_DISK_FULL: u16 = 3;
+const TFTP_ILLEGAL_OPERATION: u16 = 4;
+const TFTP_UNKNOWN_TID: u16 = 5;
+
+const TFTP_BLOCK_SIZE: usize = 512;
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TftpFile {
+    pub name: String,
+    pub content: String,
+}
+
+/* TFTP server (on the UDP ports): read requests for the files get
+ * their content (at most max_read_size bytes), other files do not
+ * exist; write requests are accepted, and the data blocks received
+ * logged (using log_data_encoding), up to max_write_blocks blocks.
+ * DATA packets are much larger than the ACKs that trigger them: reads
+ * (beyond [udp] amplification_floor) need [udp] max_amplification to
+ * be raised (or set to 0).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TftpConfig {
+    pub ports: Vec<u16>,
+    pub file: Vec<TftpFile>,
+    pub max_read_size: usize,
+    pub max_write_blocks: u16,
+    pub log_data_encoding: Encoding,
+}
+
+impl Default for TftpConfig {
+    fn default() -> Self {
+        TftpConfig {
+            ports: vec![69],
+            file: vec![TftpFile {
+                name: "startup-config".to_string(),
+                content: "!\nversion 15.2\nservice timestamps debug datetime msec\nservice password-encryption\n!\nhostname rtr-edge-01\n!\nenable secret 5 $1$mERr$hx5rVt7rPNoS4wqbXKX7m0\n!\nusername admin privilege 15 secret 5 $1$8kZ2$Hn8JaM0G6yZ2pU7y5L9cD/\n!\ninterface GigabitEthernet0/0\n ip address dhcp\n!\nsnmp-server community public RO\n!\nline vty 0 4\n login local\n transport input ssh telnet\n!\nend\n"
+                    .to_string(),
+            }],
+            max_read_size: 65536,
+            max_write_blocks: 128,
+            log_data_encoding: Encoding::Base64,
+        }
+    }
+}
+
+/* transfer in progress, with the last block sent or acknowledged */
+enum TftpTransfer {
+    Read { content: Vec<u8>, block: u16 },
+    Write { block: u16 },
+}
+
+pub struct TftpState {
+    transfer: TftpTransfer,
+}
+
+/* zero-terminated strings of a request: filename, mode, options (and
+ * their values) */
+fn tftp_strings(data: &[u8]) -> Option<Vec<String>> {
+    if data.last() != Some(&0) {
+        return None;
+    }
+    Some(
+        data[..data.len() - 1]
+            .split(|c| *c == 0)
+            .map(|s| String::from_utf8_lossy(s).to_string())
+            .collect(),
+    )
+}
+
+fn tftp_packet(opcode: u16, number: u16, data: &[u8]) -> Vec<u8> {
+    let mut packet = opcode.to_be_bytes().to_vec();
+    packet.extend_from_slice(&number.to_be_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+fn tftp_error(code: u16, message: &str) -> Vec<u8> {
+    let mut message = message.as_bytes().to_vec();
+    message.push(0);
+    tftp_packet(TFTP_ERROR, code, &message)
+}
+
+/* DATA packet of a block (numbered from 1) */
+fn tftp_block(content: &[u8], block: u16) -> Vec<u8> {
+    let start = std::cmp::min((block as usize - 1) * TFTP_BLOCK_SIZE, content.len());
+    let end = std::cmp::min(start + TFTP_BLOCK_SIZE, content.len());
+    tftp_packet(TFTP_DATA, block, &content[start..end])
+}
+
+/* answer to a read or write request, and the new transfer */
+fn tftp_request(
+    opcode: u16,
+    data: &[u8],
+    config: &TftpConfig,
+    client_info: &ClientInfo,
+) -> (Vec<u8>, Option<TftpTransfer>) {
+    let strings = match tftp_strings(data) {
+        Some(s) if s.len() >= 2 => s,
+        _ => {
+            info!("TFTP invalid request");
+            return (
+                tftp_error(TFTP_ILLEGAL_OPERATION, "Illegal TFTP operation"),
+                None,
+            );
+        }
+    };
+    let (filename, mode) = (&strings[0], &strings[1]);
+    let mut event = Event::new("tftp_request", client_info);
+    event.set("opcode", if opcode == TFTP_RRQ { "RRQ" } else { "WRQ" });
+    event.set("filename", filename.as_str());
+    event.set("mode", mode.as_str());
+    /* RFC 2347 options (e.g., blksize, tsize): ignored */
+    let options: Map<String, Value> = strings[2..]
+        .chunks(2)
+        .map(|o| (o[0].clone(), o.get(1).cloned().unwrap_or_default().into()))
+        .collect();
+    if !options.is_empty() {
+        event.set("options", options);
+    }
+    warn!(
+        "TFTP {} {:?} ({})",
+        if opcode == TFTP_RRQ { "read" } else { "write" },
+        filename,
+        mode
+    );
+    let repl = if opcode == TFTP_WRQ {
+        (
+            tftp_packet(TFTP_ACK, 0, &[]),
+            Some(TftpTransfer::Write { block: 0 }),
+        )
+    } else {
+        let name = filename.trim_start_matches('/');
+        match config.file.iter().find(|f| f.name == name) {
+            Some(file) => {
+                let len = std::cmp::min(file.content.len(), config.max_read_size);
+                let content = file.content.as_bytes()[..len].to_vec();
+                (
+                    tftp_block(&content, 1),
+                    Some(TftpTransfer::Read { content, block: 1 }),
+                )
+            }
+            None => (tftp_error(TFTP_FILE_NOT_FOUND, "File not found"), None),
+        }
+    };
+    event.set("found", repl.1.is_some());
+    event.log();
+    repl
+}
+
+/* answer to a packet of a transfer (ACK for reads, DATA for writes),
+ * and the transfer, when not over */
+fn tftp_transfer(
+    opcode: u16,
+    block: u16,
+    data: &[u8],
+    transfer: TftpTransfer,
+    config: &TftpConfig,
+    client_info: &ClientInfo,
+) -> (Option<Vec<u8>>, Option<TftpTransfer>) {
+    match (opcode, transfer) {
+        (
+            TFTP_ACK,
+            TftpTransfer::Read {
+                content,
+                block: sent,
+            },
+        ) => {
+            if block != sent {
+                /* duplicate ACK */
+                return (
+                    None,
+                    Some(TftpTransfer::Read {
+                        content,
+                        block: sent,
+                    }),
+                );
+            }
+            /* the last block is shorter than TFTP_BLOCK_SIZE */
+            if sent as usize * TFTP_BLOCK_SIZE > content.len() || sent == u16::MAX {
+                return (None, None);
+            }
+            let next = sent + 1;
+            (
+                Some(tftp_block(&content, next)),
+                Some(TftpTransfer::Read {
+                    content,
+                    block: next,
+                }),
+            )
+        }
+        (TFTP_DATA, TftpTransfer::Write { block: acked }) => {
+            if block != acked.wrapping_add(1) {
+                /* retransmission: acknowledged again */
+                return (
+                    Some(tftp_packet(TFTP_ACK, acked, &[])),
+                    Some(TftpTransfer::Write { block: acked }),
+                );
+            }
+            let mut event = Event::new("tftp_data", client_info);
+            event.set("block", block);
+            event.set("length", data.len());
+            event.set("data", encode(data, config.log_data_encoding));
+            event.log();
+            if block > config.max_write_blocks {
+                info!(
+                    "TFTP write stopped after {} blocks",
+                    config.max_write_blocks
+                );
+                return (
+                    Some(tftp_error(
+                        TFTP_DISK_FULL,
+                        "Disk full or allocation exceeded",
+                    )),
+                    None,
+                );
+            }
+            let ack = tftp_packet(TFTP_ACK, block, &[]);
+            if data.len() < TFTP_BLOCK_SIZE {
+                (Some(ack), None)
+            } else {
+                (Some(ack), Some(TftpTransfer::Write { block }))
+            }
+        }
+        _ => (
+            Some(tftp_error(TFTP_ILLEGAL_OPERATION, "Illegal TFTP operation")),
+            None,
+        ),
+    }
+}
+
+/* The transfers in progress are kept in the flow table, under a cookie
+ * of the client address and port (computed with the SYN-ACK key
+ * reversed, so that it does not collide with TCP flows).
+ **/
+pub fn flow_cookie(masscanned: &Masscanned, client_info: &ClientInfo) -> Option<u32> {
+    let key = [masscanned.synack_key[1], masscanned.synack_key[0]];
+    synackcookie::generate(client_info, &key).ok()
+}
+
+/* Answer to a TFTP packet, tcb being the control block of the
+ * transfer (see flow_cookie) */
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: &mut TCPControlBlock,
+) -> Option<Vec<u8>> {
+    debug!("receiving TFTP data");
+    let config = &masscanned.config.tftp;
+    if data.len() < 4 {
+        info!("TFTP data not handled (too short)");
+        return None;
+    }
+    let opcode = u16::from_be_bytes([data[0], data[1]]);
+    let transfer = match std::mem::replace(&mut tcb.proto_state, ProtoState::None) {
+        ProtoState::Tftp(s) => Some(s.transfer),
+        _ => None,
+    };
+    let (repl, transfer) = match (opcode, transfer) {
+        (TFTP_RRQ | TFTP_WRQ, _) => {
+            let (repl, transfer) = tftp_request(opcode, &data[2..], config, client_info);
+            (Some(repl), transfer)
+        }
+        (TFTP_ERROR, _) => {
+            let mut event = Event::new("tftp_error", client_info);
+            event.set("code", u16::from_be_bytes([data[2], data[3]]));
+            event.set(
+                "message",
+                String::from_utf8_lossy(&data[4..]).trim_end_matches('\0'),
+            );
+            event.log();
+            (None, None)
+        }
+        (TFTP_ACK | TFTP_DATA, Some(transfer)) => {
+            let block = u16::from_be_bytes([data[2], data[3]]);
+            tftp_transfer(opcode, block, &data[4..], transfer, config, client_info)
+        }
+        (TFTP_ACK | TFTP_DATA, None) => (
+            Some(tftp_error(TFTP_UNKNOWN_TID, "Unknown transfer ID")),
+            None,
+        ),
+        _ => (
+            Some(tftp_error(TFTP_ILLEGAL_OPERATION, "Illegal TFTP operation")),
+            None,
+        ),
+    };
+    if let Some(transfer) = transfer {
+        tcb.proto_state = ProtoState::Tftp(TftpState { transfer });
+    }
+    debug!("sending TFTP data");
+    repl
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::{self, SERVER_IP};
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    fn masscanned(config: Config) -> Masscanned<'static> {
+        Masscanned {
+            synack_key: [0x1234, 0x5678],
+            ..crate::proto::test_util::masscanned(config)
+        }
+    }
+
+    /* transfers are kept per client port: one per test */
+    fn client_info(port: u16) -> ClientInfo {
+        let mut client_info = test_util::client_info(IpNextHeaderProtocols::Udp, SERVER_IP, 69);
+        client_info.port.src = Some(port);
+        client_info
+    }
+
+    #[test]
+    fn test_tftp_read() {
+        let mut config = Config::default();
+        config.tftp.file.push(TftpFile {
+            name: "firmware.bin".to_string(),
+            content: "a".repeat(700),
+        });
+        let masscanned = masscanned(config);
+        /* through the flow table */
+        let mut client_info = client_info(50069);
+        let repl_data = crate::proto::repl(
+            b"\x00\x01/firmware.bin\x00octet\x00blksize\x001428\x00",
+            &masscanned,
+            &mut client_info,
+        )
+        .unwrap();
+        assert!(repl_data.len() == 4 + 512 && repl_data[..4] == [0, 3, 0, 1]);
+        /* duplicate ACKs are ignored */
+        assert!(crate::proto::repl(b"\x00\x04\x00\x00", &masscanned, &mut client_info).is_none());
+        let repl_data =
+            crate::proto::repl(b"\x00\x04\x00\x01", &masscanned, &mut client_info).unwrap();
+        assert!(repl_data[..4] == [0, 3, 0, 2] && repl_data[4..] == [b'a'; 188][..]);
+        /* last block acknowledged: the transfer is over */
+        assert!(crate::proto::repl(b"\x00\x04\x00\x02", &masscanned, &mut client_info).is_none());
+        let repl_data =
+            crate::proto::repl(b"\x00\x04\x00\x02", &masscanned, &mut client_info).unwrap();
+        assert!(repl_data == b"\x00\x05\x00\x05Unknown transfer ID\x00");
+        /* other transfers are kept per client port */
+        assert!(
+            crate::proto::repl(
+                b"\x00\x04\x00\x01",
+                &masscanned,
+                &mut self::client_info(50068)
+            )
+            .unwrap()[..4]
+                == [0, 5, 0, TFTP_UNKNOWN_TID as u8]
+        );
+        /* no control block: no transfer */
+        assert!(crate::proto::dispatch(
+            b"\x00\x01/firmware.bin\x00octet\x00",
+            &masscanned,
+            &mut client_info,
+            None
+        )
+        .is_none());
+        let mut tcb = TCPControlBlock::new();
+        /* the default file, in one block */
+        let repl_data = repl(
+            b"\x00\x01startup-config\x00netascii\x00",
+            &masscanned,
+            &mut client_info,
+            &mut tcb,
+        )
+        .unwrap();
+        assert!(repl_data[..4] == [0, 3, 0, 1] && repl_data.len() < 4 + 512);
+        assert!(repl_data[4..].starts_with(b"!\nversion 15.2\n"));
+        /* unknown files */
+        let repl_data = repl(
+            b"\x00\x01/etc/passwd\x00octet\x00",
+            &masscanned,
+            &mut client_info,
+            &mut tcb,
+        )
+        .unwrap();
+        assert!(repl_data == b"\x00\x05\x00\x01File not found\x00");
+        /* invalid requests */
+        let repl_data = repl(b"\x00\x01passwd", &masscanned, &mut client_info, &mut tcb).unwrap();
+        assert!(repl_data[..4] == [0, 5, 0, TFTP_ILLEGAL_OPERATION as u8]);
+    }
+
+    #[test]
+    fn test_tftp_write() {
+        let masscanned = masscanned(Config::from_str("[tftp]\nmax_write_blocks = 2\n").unwrap());
+        let mut client_info = client_info(50070);
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = repl(
+            b"\x00\x02backdoor.sh\x00octet\x00",
+            &masscanned,
+            &mut client_info,
+            &mut tcb,
+        )
+        .unwrap();
+        assert!(repl_data == b"\x00\x04\x00\x00");
+        let mut block = b"\x00\x03\x00\x01".to_vec();
+        block.extend_from_slice(&[b'x'; 512]);
+        let repl_data = repl(&block, &masscanned, &mut client_info, &mut tcb).unwrap();
+        assert!(repl_data == b"\x00\x04\x00\x01");
+        /* retransmission */
+        let repl_data = repl(&block, &masscanned, &mut client_info, &mut tcb).unwrap();
+        assert!(repl_data == b"\x00\x04\x00\x01");
+        let repl_data = repl(
+            b"\x00\x03\x00\x02#!/bin/sh\n",
+            &masscanned,
+            &mut client_info,
+            &mut tcb,
+        )
+        .unwrap();
+        assert!(repl_data == b"\x00\x04\x00\x02");
+        /* short block: the transfer is over */
+        let repl_data = repl(b"\x00\x03\x00\x03", &masscanned, &mut client_info, &mut tcb).unwrap();
+        assert!(repl_data[..4] == [0, 5, 0, TFTP_UNKNOWN_TID as u8]);
+        /* too many blocks */
+        repl(
+            b"\x00\x02big\x00octet\x00",
+            &masscanned,
+            &mut client_info,
+            &mut tcb,
+        )
+        .unwrap();
+        for b in 1..=2 {
+            block[3] = b;
+            assert!(repl(&block, &masscanned, &mut client_info, &mut tcb).unwrap()[..2] == [0, 4]);
+        }
+        block[3] = 3;
+        let repl_data = repl(&block, &masscanned, &mut client_info, &mut tcb).unwrap();
+        assert!(repl_data[..4] == [0, 5, 0, TFTP_DISK_FULL as u8]);
+        /* errors from the client end the transfer */
+        repl(
+            b"\x00\x02big\x00octet\x00",
+            &masscanned,
+            &mut client_info,
+            &mut tcb,
+        )
+        .unwrap();
+        assert!(repl(
+            b"\x00\x05\x00\x00bye\x00",
+            &masscanned,
+            &mut client_info,
+            &mut tcb
+        )
+        .is_none());
+        block[3] = 1;
+        let repl_data = repl(&block, &masscanned, &mut client_info, &mut tcb).unwrap();
+        assert!(repl_data[..4] == [0, 5, 0, TFTP_UNKNOWN_TID as u8]);
+    }
+}
@@ -0,0 +1,1445 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use log::*;
+
+use md5::{Digest, Md5};
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::crypto::ring::{default_provider, sign::any_supported_type};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::{ClientHello as RustlsClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{
+    DigitallySignedStruct, DistinguishedName, Error as RustlsError, ServerConfig, ServerConnection,
+    SignatureScheme,
+};
+use serde::Deserialize;
+use sha1::Sha1;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::proto::{dispatch, proto_behind_tls, proto_flow_id, proto_greeting};
+use crate::smack::{BASE_STATE, NO_MATCH};
+use crate::Masscanned;
+
+pub const TLS_CONTENT_TYPE_ALERT: u8 = 21;
+pub const TLS_CONTENT_TYPE_HANDSHAKE: u8 = 22;
+pub const TLS_HANDSHAKE_CLIENT_HELLO: u8 = 1;
+pub const TLS_ALERT_LEVEL_FATAL: u8 = 2;
+pub const TLS_ALERT_HANDSHAKE_FAILURE: u8 = 40;
+
+const TLS_EXT_SERVER_NAME: u16 = 0;
+const TLS_EXT_SUPPORTED_GROUPS: u16 = 10;
+const TLS_EXT_EC_POINT_FORMATS: u16 = 11;
+const TLS_EXT_ALPN: u16 = 16;
+const TLS_EXT_SUPPORTED_VERSIONS: u16 = 43;
+
+/* maximum size of a partial ClientHello kept between two segments */
+const TLS_MAX_BUFFER: usize = 65536;
+
+/* Record header of a ClientHello: handshake, version 3.x (SSLv3 to
+ * TLS 1.3), length, then the handshake type.
+ **/
+pub const TLS_PATTERNS_CLIENT_HELLO: [&[u8; 6]; 5] = [
+    b"\x16\x03\x00**\x01",
+    b"\x16\x03\x01**\x01",
+    b"\x16\x03\x02**\x01",
+    b"\x16\x03\x03**\x01",
+    b"\x16\x03\x04**\x01",
+];
+
+/* Configuration of TLS termination: when enabled, masscanned completes
+ * the handshakes, and the decrypted content of the sessions is answered
+ * like plaintext (e.g., HTTP over TLS gets the HTTP answers); otherwise,
+ * ClientHello messages get a handshake_failure alert.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    /* certificate chain and private key (PEM files) - when not set, a
+     * self-signed certificate for names is generated */
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub names: Vec<String>,
+    /* directory of certificates presented by SNI: <name>.crt and
+     * <name>.key */
+    pub cert_dir: Option<String>,
+    /* ask for a client certificate (optional, accepted without any
+     * verification): the certificates presented are recorded */
+    pub client_auth: bool,
+    /* sessions open at the same time - beyond, ClientHello messages
+     * get an alert */
+    pub max_sessions: usize,
+    /* ALPN protocols, in order of preference: clients that offer
+     * others only get a no_application_protocol alert (no ALPN when
+     * empty) */
+    pub alpn: Vec<String>,
+    /* implicit TLS: on these ports, the decrypted content of the
+     * sessions is answered by the given protocol (including its
+     * greeting, once the handshake is completed) */
+    pub ports: Vec<TlsPort>,
+    /* built by init() */
+    #[serde(skip)]
+    pub server_config: Option<Arc<ServerConfig>>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            enabled: false,
+            cert: None,
+            key: None,
+            names: vec!["localhost".to_string()],
+            cert_dir: None,
+            client_auth: false,
+            max_sessions: 1024,
+            alpn: vec!["http/1.1".to_string(), "dot".to_string()],
+            ports: vec![
+                tls_port(465, "smtp"),
+                tls_port(993, "imap"),
+                tls_port(995, "pop3"),
+                tls_port(8883, "mqtt"),
+            ],
+            server_config: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsPort {
+    pub port: u16,
+    pub protocol: String,
+}
+
+fn tls_port(port: u16, protocol: &str) -> TlsPort {
+    TlsPort {
+        port,
+        protocol: protocol.to_string(),
+    }
+}
+
+fn tls_error<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn tls_load_cert(cert: &Path, key: &Path) -> Result<CertifiedKey, io::Error> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(fs::File::open(cert)?))
+        .collect::<Result<Vec<CertificateDer>, io::Error>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(fs::File::open(key)?))?
+        .ok_or_else(|| tls_error(format!("no private key in {}", key.display())))?;
+    Ok(CertifiedKey::new(
+        certs,
+        any_supported_type(&key).map_err(tls_error)?,
+    ))
+}
+
+fn tls_self_signed(names: &[String]) -> Result<CertifiedKey, io::Error> {
+    let generated = rcgen::generate_simple_self_signed(names.to_vec()).map_err(tls_error)?;
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(generated.key_pair.serialize_der()));
+    Ok(CertifiedKey::new(
+        vec![generated.cert.der().clone()],
+        any_supported_type(&key).map_err(tls_error)?,
+    ))
+}
+
+/* certificate presented for the SNI of the ClientHello, if any */
+#[derive(Debug)]
+struct TlsCertResolver {
+    default: Arc<CertifiedKey>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for TlsCertResolver {
+    fn resolve(&self, hello: RustlsClientHello) -> Option<Arc<CertifiedKey>> {
+        hello
+            .server_name()
+            .and_then(|name| self.by_name.get(&name.to_ascii_lowercase()))
+            .or(Some(&self.default))
+            .cloned()
+    }
+}
+
+/* client certificates: any one is accepted, as long as the client
+ * proves it has the private key */
+#[derive(Debug)]
+struct TlsAnyClientCert {
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ClientCertVerifier for TlsAnyClientCert {
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, RustlsError> {
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, RustlsError> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, RustlsError> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+impl TlsConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for (i, p) in self.ports.iter().enumerate() {
+            if self.ports[..i].iter().any(|o| o.port == p.port) {
+                return Err(tls_error(format!("tls: port {} given twice", p.port)));
+            }
+            if proto_flow_id(&p.protocol).is_none() {
+                return Err(tls_error(format!(
+                    "tls: protocol {:?} cannot be used for port {}",
+                    p.protocol, p.port
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /* implicit TLS ports given on the command line (465=smtp,993=imap):
+     * TLS termination is enabled */
+    pub fn add_ports(&mut self, value: &str) -> Result<(), io::Error> {
+        let mut ports = Vec::new();
+        for item in value.split(',') {
+            match item
+                .split_once('=')
+                .map(|(p, n)| (p.trim().parse(), n.trim()))
+            {
+                Some((Ok(port), protocol)) if proto_flow_id(protocol).is_some() => {
+                    ports.push(tls_port(port, protocol))
+                }
+                _ => return Err(tls_error(format!("tls: invalid port mapping {:?}", item))),
+            }
+        }
+        /* the command line takes precedence over the configuration */
+        self.ports
+            .retain(|p| ports.iter().all(|o| o.port != p.port));
+        self.ports.extend(ports);
+        self.check()?;
+        self.enabled = true;
+        if self.server_config.is_none() {
+            self.init()?;
+        }
+        Ok(())
+    }
+
+    /* protocol answering the decrypted content of the sessions on a
+     * port, when TLS termination is enabled */
+    pub fn implicit(&self, port: u16) -> Option<usize> {
+        self.server_config.as_ref()?;
+        self.ports
+            .iter()
+            .find(|p| p.port == port)
+            .and_then(|p| proto_flow_id(&p.protocol))
+    }
+
+    /* load (or generate) the certificates */
+    pub fn init(&mut self) -> Result<(), io::Error> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let default = match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => tls_load_cert(Path::new(cert), Path::new(key))?,
+            (None, None) => tls_self_signed(&self.names)?,
+            _ => return Err(tls_error("tls: cert and key must be set together")),
+        };
+        let mut by_name = HashMap::new();
+        if let Some(dir) = &self.cert_dir {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("crt") {
+                    continue;
+                }
+                let name = match path.file_stem().and_then(|n| n.to_str()) {
+                    Some(n) => n.to_ascii_lowercase(),
+                    None => continue,
+                };
+                let cert = tls_load_cert(&path, &path.with_extension("key"))?;
+                by_name.insert(name, Arc::new(cert));
+            }
+        }
+        let provider = default_provider();
+        let algorithms = provider.signature_verification_algorithms;
+        let builder = ServerConfig::builder_with_provider(Arc::new(provider))
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?;
+        let builder = if self.client_auth {
+            builder.with_client_cert_verifier(Arc::new(TlsAnyClientCert { algorithms }))
+        } else {
+            builder.with_no_client_auth()
+        };
+        let mut config = builder.with_cert_resolver(Arc::new(TlsCertResolver {
+            default: Arc::new(default),
+            by_name,
+        }));
+        config.alpn_protocols = self.alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+        self.server_config = Some(Arc::new(config));
+        Ok(())
+    }
+}
+
+/* bounds-checked reader of big-endian TLS fields */
+pub struct TlsReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> TlsReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        TlsReader { data, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return None;
+        }
+        self.pos += len;
+        Some(&self.data[self.pos - len..self.pos])
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn u24(&mut self) -> Option<usize> {
+        self.bytes(3)
+            .map(|b| (b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize)
+    }
+
+    /* vector with a 1-byte or 2-byte length */
+    pub fn vec8(&mut self) -> Option<&'a [u8]> {
+        let len = self.u8()? as usize;
+        self.bytes(len)
+    }
+
+    pub fn vec16(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.bytes(len)
+    }
+}
+
+fn tls_u16_list(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .collect()
+}
+
+/* RFC 8701: GREASE values (0x0a0a, 0x1a1a, ..., 0xfafa) are ignored
+ * in fingerprints */
+fn tls_is_grease(v: u16) -> bool {
+    v & 0x0f0f == 0x0a0a && v >> 8 == v & 0xff
+}
+
+/* ClientHello fields (RFC 8446 section 4.1.2) */
+pub struct ClientHello {
+    pub version: u16,
+    pub ciphers: Vec<u16>,
+    pub extensions: Vec<u16>,
+    pub sni: Option<String>,
+    pub alpn: Vec<String>,
+    pub supported_versions: Vec<u16>,
+    pub groups: Vec<u16>,
+    pub ec_point_formats: Vec<u8>,
+}
+
+impl ClientHello {
+    /* body of a ClientHello handshake message (without the type and
+     * length) - None if it is malformed */
+    pub fn parse(body: &[u8]) -> Option<Self> {
+        let mut r = TlsReader::new(body);
+        let version = r.u16()?;
+        /* random */
+        r.bytes(32)?;
+        /* session id */
+        r.vec8()?;
+        let ciphers = tls_u16_list(r.vec16()?);
+        /* compression methods */
+        r.vec8()?;
+        let mut hello = ClientHello {
+            version,
+            ciphers,
+            extensions: Vec::new(),
+            sni: None,
+            alpn: Vec::new(),
+            supported_versions: Vec::new(),
+            groups: Vec::new(),
+            ec_point_formats: Vec::new(),
+        };
+        /* extensions are optional (e.g., SSLv3) */
+        if r.is_empty() {
+            return Some(hello);
+        }
+        let mut exts = TlsReader::new(r.vec16()?);
+        while !exts.is_empty() {
+            let ext_type = exts.u16()?;
+            let mut ext = TlsReader::new(exts.vec16()?);
+            hello.extensions.push(ext_type);
+            match ext_type {
+                TLS_EXT_SERVER_NAME => {
+                    let mut names = TlsReader::new(ext.vec16()?);
+                    while !names.is_empty() {
+                        let name_type = names.u8()?;
+                        let name = names.vec16()?;
+                        /* host_name */
+                        if name_type == 0 {
+                            hello.sni = Some(String::from_utf8_lossy(name).to_string());
+                        }
+                    }
+                }
+                TLS_EXT_ALPN => {
+                    let mut protos = TlsReader::new(ext.vec16()?);
+                    while !protos.is_empty() {
+                        hello
+                            .alpn
+                            .push(String::from_utf8_lossy(protos.vec8()?).to_string());
+                    }
+                }
+                TLS_EXT_SUPPORTED_VERSIONS => {
+                    hello.supported_versions = tls_u16_list(ext.vec8()?);
+                }
+                TLS_EXT_SUPPORTED_GROUPS => {
+                    hello.groups = tls_u16_list(ext.vec16()?);
+                }
+                TLS_EXT_EC_POINT_FORMATS => {
+                    hello.ec_point_formats = ext.vec8()?.to_vec();
+                }
+                _ => {}
+            }
+        }
+        Some(hello)
+    }
+
+    /* JA3 fingerprint: version, ciphers, extensions, groups and point
+     * formats, in decimal, without GREASE values */
+    pub fn ja3(&self) -> String {
+        let list = |l: &[u16]| {
+            l.iter()
+                .filter(|v| !tls_is_grease(**v))
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join("-")
+        };
+        format!(
+            "{},{},{},{},{}",
+            self.version,
+            list(&self.ciphers),
+            list(&self.extensions),
+            list(&self.groups),
+            self.ec_point_formats
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join("-")
+        )
+    }
+
+    pub fn ja3_md5(&self) -> String {
+        Md5::digest(self.ja3().as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    pub fn event(&self, name: &'static str, client_info: &ClientInfo) -> Event {
+        let mut event = Event::new(name, client_info);
+        event.set("version", self.version);
+        event.set("ciphers", self.ciphers.clone());
+        event.set("extensions", self.extensions.clone());
+        if let Some(sni) = &self.sni {
+            event.set("sni", sni.clone());
+        }
+        event.set("alpn", self.alpn.clone());
+        event.set("supported_versions", self.supported_versions.clone());
+        event.set("groups", self.groups.clone());
+        event.set("ec_point_formats", self.ec_point_formats.clone());
+        event.set("ja3", self.ja3());
+        event.set("ja3_md5", self.ja3_md5());
+        event
+    }
+}
+
+/* handshake messages received on a flow, before the ClientHello is
+ * complete */
+pub struct TlsState {
+    buffer: Vec<u8>,
+    /* protocol of the flow before STARTTLS (NO_MATCH otherwise) */
+    inner: usize,
+}
+
+impl TlsState {
+    pub fn new() -> Self {
+        TlsState {
+            buffer: Vec::new(),
+            inner: NO_MATCH,
+        }
+    }
+
+    /* after STARTTLS, the decrypted content goes on with the protocol
+     * of the flow (without any new greeting) */
+    pub fn starttls(inner: usize) -> Self {
+        TlsState {
+            buffer: Vec::new(),
+            inner,
+        }
+    }
+}
+
+/* number of TLS sessions open */
+static TLS_SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/* TLS session terminated by masscanned: the decrypted content is a flow
+ * of its own */
+pub struct TlsSession {
+    conn: ServerConnection,
+    /* handshake messages received, until the ClientHello is recorded */
+    hello: Option<Vec<u8>>,
+    /* client certificates recorded (once the handshake is completed) */
+    peer_recorded: bool,
+    /* protocol of the decrypted content, and whether it gets its
+     * greeting, set once the handshake is completed (implicit TLS and
+     * STARTTLS) */
+    start: Option<(usize, bool)>,
+    inner: TCPControlBlock,
+}
+
+impl TlsSession {
+    fn new(conn: ServerConnection, start: Option<(usize, bool)>) -> Self {
+        TLS_SESSIONS.fetch_add(1, Ordering::Relaxed);
+        TlsSession {
+            conn,
+            hello: Some(Vec::new()),
+            peer_recorded: false,
+            start,
+            inner: TCPControlBlock::new(),
+        }
+    }
+
+    /* the handshake is completed: the inner protocol starts */
+    fn start_inner(&mut self, masscanned: &Masscanned, client_info: &mut ClientInfo) {
+        if self.conn.is_handshaking() {
+            return;
+        }
+        if let Some((id, greeting)) = self.start.take() {
+            self.inner.proto_id = id;
+            /* after STARTTLS, the greeting only sets the state of the
+             * protocol up: it is not sent again */
+            let repl_data = proto_greeting(id, masscanned, client_info, &mut self.inner);
+            if let Some(d) = repl_data.filter(|_| greeting) {
+                let _ = self.conn.writer().write_all(&d);
+            }
+        }
+    }
+
+    fn record_hello(&mut self, data: &[u8], client_info: &ClientInfo) {
+        let buffer = match self.hello.as_mut() {
+            Some(b) => b,
+            None => return,
+        };
+        buffer.extend_from_slice(data);
+        match tls_handshake(buffer) {
+            TlsHandshake::Complete(_, handshake) => {
+                if handshake[0] == TLS_HANDSHAKE_CLIENT_HELLO {
+                    tls_client_hello(&handshake, client_info);
+                }
+                self.hello = None;
+            }
+            TlsHandshake::Incomplete if buffer.len() <= TLS_MAX_BUFFER => {}
+            _ => self.hello = None,
+        }
+    }
+
+    fn record_peer(&mut self, client_info: &ClientInfo) {
+        if self.peer_recorded || self.conn.is_handshaking() {
+            return;
+        }
+        self.peer_recorded = true;
+        if let Some(certs) = self.conn.peer_certificates() {
+            let mut event = Event::new("tls_client_certificate", client_info);
+            event.set(
+                "sha1",
+                certs
+                    .iter()
+                    .map(|c| format!("{:x}", Sha1::digest(c)))
+                    .collect::<Vec<String>>(),
+            );
+            event.set(
+                "certificates",
+                certs.iter().map(base64::encode).collect::<Vec<String>>(),
+            );
+            warn!("TLS client certificate: {:x}", Sha1::digest(&certs[0]));
+            event.log();
+        }
+    }
+
+    /* decrypt data, answer its content and encrypt the answer */
+    fn repl(
+        &mut self,
+        data: &[u8],
+        masscanned: &Masscanned,
+        client_info: &mut ClientInfo,
+    ) -> Option<Vec<u8>> {
+        self.record_hello(data, client_info);
+        let mut input = data;
+        let mut repl_data = Vec::new();
+        while !input.is_empty() && !client_info.close {
+            match self.conn.read_tls(&mut input) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    info!("TLS data not handled: {}", e);
+                    client_info.close = true;
+                    break;
+                }
+            }
+            let state = match self.conn.process_new_packets() {
+                Ok(s) => s,
+                Err(e) => {
+                    /* the alert (if any) is sent below */
+                    info!("TLS error: {}", e);
+                    client_info.close = true;
+                    break;
+                }
+            };
+            self.record_peer(client_info);
+            self.start_inner(masscanned, client_info);
+            let mut plaintext = Vec::new();
+            /* WouldBlock: no more data for now */
+            let _ = self.conn.reader().read_to_end(&mut plaintext);
+            if !plaintext.is_empty() {
+                if self.inner.proto_id == NO_MATCH && self.inner.smack_state == BASE_STATE {
+                    self.inner.proto_id = proto_behind_tls(masscanned, client_info, &plaintext);
+                }
+                if let Some(d) =
+                    dispatch(&plaintext, masscanned, client_info, Some(&mut self.inner))
+                {
+                    let _ = self.conn.writer().write_all(&d);
+                }
+            }
+            if state.peer_has_closed() {
+                client_info.close = true;
+            }
+        }
+        if client_info.close {
+            self.conn.send_close_notify();
+        }
+        while self.conn.wants_write() {
+            if self.conn.write_tls(&mut repl_data).is_err() {
+                break;
+            }
+        }
+        if repl_data.is_empty() {
+            return None;
+        }
+        Some(repl_data)
+    }
+}
+
+impl Drop for TlsSession {
+    fn drop(&mut self) {
+        TLS_SESSIONS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+enum TlsHandshake {
+    Incomplete,
+    Invalid,
+    /* record version and handshake message (type, length and body) */
+    Complete(u16, Vec<u8>),
+}
+
+/* first handshake message of data, possibly fragmented across several
+ * records (RFC 8446 section 5.1) */
+fn tls_handshake(data: &[u8]) -> TlsHandshake {
+    let mut r = TlsReader::new(data);
+    let mut handshake = Vec::new();
+    let mut version = 0;
+    loop {
+        if handshake.len() >= 4 {
+            let len = TlsReader::new(&handshake[1..4]).u24().unwrap();
+            if handshake.len() >= 4 + len {
+                handshake.truncate(4 + len);
+                return TlsHandshake::Complete(version, handshake);
+            }
+        }
+        let content_type = match r.u8() {
+            Some(t) => t,
+            None => return TlsHandshake::Incomplete,
+        };
+        if content_type != TLS_CONTENT_TYPE_HANDSHAKE {
+            return TlsHandshake::Invalid;
+        }
+        version = match r.u16() {
+            Some(v) if v >> 8 == 3 => v,
+            Some(_) => return TlsHandshake::Invalid,
+            None => return TlsHandshake::Incomplete,
+        };
+        match r.vec16() {
+            Some(fragment) => handshake.extend_from_slice(fragment),
+            None => return TlsHandshake::Incomplete,
+        }
+    }
+}
+
+pub fn tls_alert(version: u16, description: u8) -> Vec<u8> {
+    let mut alert = vec![TLS_CONTENT_TYPE_ALERT];
+    alert.extend_from_slice(&version.to_be_bytes());
+    alert.extend_from_slice(&[0, 2, TLS_ALERT_LEVEL_FATAL, description]);
+    alert
+}
+
+/* parse and record a ClientHello handshake message */
+fn tls_client_hello(handshake: &[u8], client_info: &ClientInfo) -> Option<ClientHello> {
+    let hello = ClientHello::parse(&handshake[4..])?;
+    warn!(
+        "TLS ClientHello (SNI {}, JA3 {})",
+        hello.sni.as_deref().unwrap_or("-"),
+        hello.ja3_md5()
+    );
+    hello.event("tls_client_hello", client_info).log();
+    Some(hello)
+}
+
+/* Record the ClientHello (possibly received in several segments), then
+ * either terminate the session (when configured, see TlsConfig) or
+ * answer with a handshake_failure alert.
+ **/
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving TLS data");
+    let config = &masscanned.config.tls;
+    if let Some(t) = tcb.as_mut() {
+        if let ProtoState::TlsSession(session) = &mut t.proto_state {
+            let repl_data = session.repl(data, masscanned, client_info);
+            if client_info.close {
+                t.proto_state = ProtoState::None;
+            }
+            return repl_data;
+        }
+        /* protocol of the decrypted content, when known: from
+         * STARTTLS, or from the port (implicit TLS) */
+        let start = match &t.proto_state {
+            ProtoState::None => Some(
+                client_info
+                    .port
+                    .dst
+                    .and_then(|p| config.implicit(p))
+                    .map(|id| (id, true)),
+            ),
+            ProtoState::Tls(s) if s.buffer.is_empty() => Some(
+                Some(s.inner)
+                    .filter(|id| *id != NO_MATCH)
+                    .map(|id| (id, false)),
+            ),
+            _ => None,
+        };
+        if let (Some(server_config), Some(start)) = (&config.server_config, start) {
+            if TLS_SESSIONS.load(Ordering::Relaxed) < config.max_sessions {
+                match ServerConnection::new(server_config.clone()) {
+                    Ok(conn) => {
+                        let mut session = Box::new(TlsSession::new(conn, start));
+                        let repl_data = session.repl(data, masscanned, client_info);
+                        if !client_info.close {
+                            t.proto_state = ProtoState::TlsSession(session);
+                        }
+                        return repl_data;
+                    }
+                    Err(e) => error!("cannot start TLS session: {}", e),
+                }
+            } else {
+                info!("too many TLS sessions - not terminating");
+            }
+        }
+    }
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Tls(s)) => s,
+        _ => TlsState::new(),
+    };
+    state.buffer.extend_from_slice(data);
+    let (version, handshake) = match tls_handshake(&state.buffer) {
+        TlsHandshake::Complete(v, h) => (v, h),
+        TlsHandshake::Incomplete => {
+            if state.buffer.len() > TLS_MAX_BUFFER {
+                info!("TLS handshake too large - dropped");
+            } else if let Some(t) = tcb {
+                /* wait for the rest of the ClientHello */
+                t.proto_state = ProtoState::Tls(state);
+            }
+            return None;
+        }
+        TlsHandshake::Invalid => {
+            info!("TLS data not handled (invalid record)");
+            return None;
+        }
+    };
+    if handshake[0] != TLS_HANDSHAKE_CLIENT_HELLO {
+        info!("TLS handshake message not handled: {}", handshake[0]);
+        return None;
+    }
+    /* a fatal alert closes the connection (RFC 8446 section 6) */
+    client_info.close = true;
+    match tls_client_hello(&handshake, client_info) {
+        Some(hello) => Some(tls_alert(
+            std::cmp::min(hello.version, 0x0303),
+            TLS_ALERT_HANDSHAKE_FAILURE,
+        )),
+        None => {
+            info!("TLS ClientHello not handled (malformed)");
+            Some(tls_alert(version, TLS_ALERT_HANDSHAKE_FAILURE))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::tcb::CONTABLE;
+    use crate::proto::test_util::masscanned;
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    fn from_hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /* openssl s_client -servername www.example.com -alpn h2,http/1.1
+     * (OpenSSL 3.0) */
+    const HELLO_OPENSSL: &str = "\
+        160301014e0100014a0303bde8ad081a855548227bae0fdd1a758856f7e9e5eef2110eebe0a20b23\
+        ae742c201a0da085dc55ebcbdb82e8455a2f4f473e0d59571f513f68d7a1a0ce67e94a11003e1302\
+        13031301c02cc030009fcca9cca8ccaac02bc02f009ec024c028006bc023c0270067c00ac0140039\
+        c009c0130033009d009c003d003c0035002f00ff010000c300000014001200000f7777772e657861\
+        6d706c652e636f6d000b000403000102000a00160014001d0017001e001900180100010101020103\
+        0104002300000010000e000c02683208687474702f312e310016000000170000000d002a00280403\
+        05030603080708080809080a080b080408050806040105010601030303010302040205020602002b\
+        0009080304030303020301002d00020101003300260024001d0020d52d5c797c59e5294dfeec2e22\
+        1c357c374957f5803cf9c454e7e00158a47761";
+
+    /* curl https://localhost/ (curl 7.88, OpenSSL 3.0) */
+    const HELLO_CURL: &str = "\
+        1603010200010001fc03033f89817c4aacc360dc096b4f801d58847db1a0b135958d804b22509413\
+        ca61e420b606033e6a2cf96bdd1d092a1a464fe3d4ea30faa100971f0e2327116ae4cc0b003e1302\
+        13031301c02cc030009fcca9cca8ccaac02bc02f009ec024c028006bc023c0270067c00ac0140039\
+        c009c0130033009d009c003d003c0035002f00ff010001750000000e000c0000096c6f63616c686f\
+        7374000b000403000102000a00160014001d0017001e00190018010001010102010301040010000e\
+        000c02683208687474702f312e31001600000017000000310000000d002a00280403050306030807\
+        08080809080a080b080408050806040105010601030303010302040205020602002b000908030403\
+        0303020301002d00020101003300260024001d0020fcf517e45dc212046f651e994fd8836f7ab4f1\
+        100f83f77e662066e541277c2b001500b40000000000000000000000000000000000000000000000\
+        00000000000000000000000000000000000000000000000000000000000000000000000000000000\
+        00000000000000000000000000000000000000000000000000000000000000000000000000000000\
+        00000000000000000000000000000000000000000000000000000000000000000000000000000000\
+        00000000000000000000000000000000000000000000000000000000000000000000000000";
+
+    #[test]
+    fn test_tls_client_hello_openssl() {
+        let data = from_hex(HELLO_OPENSSL);
+        let (version, handshake) = match tls_handshake(&data) {
+            TlsHandshake::Complete(v, h) => (v, h),
+            _ => panic!("expected a complete handshake message"),
+        };
+        assert!(version == 0x0301);
+        assert!(handshake[0] == TLS_HANDSHAKE_CLIENT_HELLO);
+        let hello = ClientHello::parse(&handshake[4..]).unwrap();
+        assert!(hello.version == 0x0303);
+        assert!(hello.ciphers.len() == 31);
+        assert!(hello.ciphers[..3] == [0x1302, 0x1303, 0x1301]);
+        assert!(hello.sni.as_deref() == Some("www.example.com"));
+        assert!(hello.alpn == ["h2", "http/1.1"]);
+        assert!(hello.supported_versions == [0x0304, 0x0303, 0x0302, 0x0301]);
+        assert!(hello.ec_point_formats == [0, 1, 2]);
+        assert!(hello.ja3() == "771,4866-4867-4865-49196-49200-159-52393-52392-52394-49195-49199-158-49188-49192-107-49187-49191-103-49162-49172-57-49161-49171-51-157-156-61-60-53-47-255,0-11-10-35-16-22-23-13-43-45-51,29-23-30-25-24-256-257-258-259-260,0-1-2");
+        assert!(hello.ja3_md5() == "5a1edc7f170af1014fc65c994878e63c");
+    }
+
+    #[test]
+    fn test_tls_client_hello_curl() {
+        let data = from_hex(HELLO_CURL);
+        let handshake = match tls_handshake(&data) {
+            TlsHandshake::Complete(_, h) => h,
+            _ => panic!("expected a complete handshake message"),
+        };
+        let hello = ClientHello::parse(&handshake[4..]).unwrap();
+        assert!(hello.sni.as_deref() == Some("localhost"));
+        assert!(hello.alpn == ["h2", "http/1.1"]);
+        /* padding extension (21) */
+        assert!(hello.extensions.last() == Some(&21));
+        assert!(hello.ja3_md5() == "0149f47eabf9a20d0893e2a44e5a6323");
+        let event = hello.event("tls_client_hello", &ClientInfo::new());
+        assert!(event.fields["sni"] == "localhost");
+        assert!(event.fields["ja3_md5"] == "0149f47eabf9a20d0893e2a44e5a6323");
+    }
+
+    #[test]
+    fn test_tls_grease() {
+        assert!(tls_is_grease(0x0a0a) && tls_is_grease(0xfafa) && tls_is_grease(0x3a3a));
+        assert!(!tls_is_grease(0x0a1a) && !tls_is_grease(0x1301) && !tls_is_grease(0));
+        let hello = ClientHello {
+            version: 0x0303,
+            ciphers: vec![0x8a8a, 0x1301],
+            extensions: vec![0x2a2a, 0, 0xbaba],
+            sni: None,
+            alpn: Vec::new(),
+            supported_versions: Vec::new(),
+            groups: vec![0xdada, 29],
+            ec_point_formats: vec![0],
+        };
+        assert!(hello.ja3() == "771,4865,0,29,0");
+    }
+
+    #[test]
+    fn test_tls_fragmented() {
+        let masscanned = masscanned(Config::default());
+        let data = from_hex(HELLO_OPENSSL);
+        /* same handshake message, in two records */
+        let handshake = &data[5..];
+        let mut records = Vec::new();
+        for fragment in [&handshake[..100], &handshake[100..]].iter() {
+            records.extend_from_slice(&[0x16, 0x03, 0x01]);
+            records.extend_from_slice(&(fragment.len() as u16).to_be_bytes());
+            records.extend_from_slice(fragment);
+        }
+        match tls_handshake(&records) {
+            TlsHandshake::Complete(_, h) => assert!(h == handshake),
+            _ => panic!("expected a complete handshake message"),
+        }
+        /* in several segments */
+        for cut in [3, 5, 50, 104, 106, 200, records.len() - 1].iter() {
+            let mut client_info = ClientInfo::new();
+            let mut tcb = TCPControlBlock::new();
+            assert!(repl(
+                &records[..*cut],
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb)
+            )
+            .is_none());
+            assert!(matches!(tcb.proto_state, ProtoState::Tls(_)));
+            let alert = repl(
+                &records[*cut..],
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb),
+            )
+            .unwrap();
+            assert!(alert == b"\x15\x03\x03\x00\x02\x02\x28");
+            assert!(client_info.close);
+            assert!(matches!(tcb.proto_state, ProtoState::None));
+        }
+        /* not a handshake */
+        assert!(matches!(
+            tls_handshake(b"\x17\x03\x03\x00\x01\x00"),
+            TlsHandshake::Invalid
+        ));
+        assert!(matches!(
+            tls_handshake(b"\x16\x04\x03\x00\x01\x00"),
+            TlsHandshake::Invalid
+        ));
+        /* truncated ClientHello */
+        let mut client_info = ClientInfo::new();
+        let mut bad = data[..50].to_vec();
+        bad[3..5].copy_from_slice(&45u16.to_be_bytes());
+        bad[6..9].copy_from_slice(&[0, 0, 41]);
+        let alert = repl(&bad, &masscanned, &mut client_info, None).unwrap();
+        assert!(alert == b"\x15\x03\x01\x00\x02\x02\x28");
+    }
+
+    /* exchange data between a rustls client and masscanned, until none
+     * of them has anything to send - returns the plaintext received */
+    fn tls_exchange(
+        client: &mut rustls::ClientConnection,
+        masscanned: &Masscanned,
+        client_info: &mut ClientInfo,
+    ) -> Vec<u8> {
+        let mut plaintext = Vec::new();
+        for _ in 0..10 {
+            let mut data = Vec::new();
+            while client.wants_write() {
+                client.write_tls(&mut data).unwrap();
+            }
+            if data.is_empty() {
+                break;
+            }
+            if let Some(repl_data) = crate::proto::repl(&data, masscanned, client_info) {
+                client.read_tls(&mut &repl_data[..]).unwrap();
+                client.process_new_packets().unwrap();
+                let _ = client.reader().read_to_end(&mut plaintext);
+            }
+        }
+        plaintext
+    }
+
+    fn tls_client(
+        masscanned: &Masscanned,
+        ca: &CertificateDer,
+        name: &str,
+    ) -> rustls::ClientConnection {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(ca.clone()).unwrap();
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        assert!(masscanned.config.tls.server_config.is_some());
+        rustls::ClientConnection::new(
+            Arc::new(config),
+            rustls::pki_types::ServerName::try_from(name.to_string()).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_tls_termination() {
+        /* certificates: default one, and one for www.example.com */
+        let dir = std::env::temp_dir().join(format!("masscanned-tls-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sni")).unwrap();
+        let mut certs = Vec::new();
+        for (name, path) in [
+            ("localhost", dir.join("default")),
+            ("www.example.com", dir.join("sni").join("www.example.com")),
+        ]
+        .iter()
+        {
+            let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+            let path = path.display();
+            fs::write(format!("{}.crt", path), generated.cert.pem()).unwrap();
+            fs::write(format!("{}.key", path), generated.key_pair.serialize_pem()).unwrap();
+            certs.push(generated.cert.der().clone());
+        }
+        let mut config = Config::from_str(&format!(
+            "[tls]\nenabled = true\ncert = {:?}\nkey = {:?}\ncert_dir = {:?}\n",
+            dir.join("default.crt"),
+            dir.join("default.key"),
+            dir.join("sni")
+        ))
+        .unwrap();
+        config.tls.init().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let mut masscanned = masscanned(config);
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(pnet::packet::ip::IpNextHeaderProtocols::Tcp);
+        /* HTTP over TLS, with keep-alive */
+        for (i, (name, ca)) in [("localhost", &certs[0]), ("www.example.com", &certs[1])]
+            .iter()
+            .enumerate()
+        {
+            client_info.cookie = Some(0x7150_0000 + i as u32);
+            client_info.close = false;
+            let mut client = tls_client(&masscanned, ca, name);
+            for _ in 0..2 {
+                client
+                    .writer()
+                    .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                    .unwrap();
+                let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+                assert!(plaintext.starts_with(b"HTTP/1.1 401 Unauthorized\n"));
+                assert!(!client_info.close);
+            }
+            assert!(client.peer_certificates().unwrap()[0] == **ca);
+            /* closing the session */
+            client.send_close_notify();
+            tls_exchange(&mut client, &masscanned, &mut client_info);
+            assert!(client_info.close);
+        }
+        /* no session left: ClientHello messages get an alert */
+        masscanned.config.tls.max_sessions = 0;
+        client_info.cookie = Some(0x7150_0010);
+        client_info.close = false;
+        let mut client = tls_client(&masscanned, &certs[0], "localhost");
+        let mut data = Vec::new();
+        client.write_tls(&mut data).unwrap();
+        let alert = crate::proto::repl(&data, &masscanned, &mut client_info).unwrap();
+        assert!(alert == b"\x15\x03\x03\x00\x02\x02\x28");
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_tls_client_certificate() {
+        let dir =
+            std::env::temp_dir().join(format!("masscanned-tls-client-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        fs::write(dir.join("server.crt"), generated.cert.pem()).unwrap();
+        fs::write(dir.join("server.key"), generated.key_pair.serialize_pem()).unwrap();
+        let ca = generated.cert.der().clone();
+        let mut config = Config::from_str(&format!(
+            "[tls]\nenabled = true\nclient_auth = true\ncert = {:?}\nkey = {:?}\n",
+            dir.join("server.crt"),
+            dir.join("server.key"),
+        ))
+        .unwrap();
+        config.tls.init().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let masscanned = masscanned(config);
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(pnet::packet::ip::IpNextHeaderProtocols::Tcp);
+        /* without a certificate: the client authentication is optional */
+        client_info.cookie = Some(0x7150_0020);
+        let mut client = tls_client(&masscanned, &ca, "localhost");
+        client
+            .writer()
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+        assert!(plaintext.starts_with(b"HTTP/1.1 401 Unauthorized\n"));
+        /* a client with a (self-signed) certificate */
+        let generated = rcgen::generate_simple_self_signed(vec!["client".to_string()]).unwrap();
+        let client_cert = generated.cert.der().clone();
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(ca.clone()).unwrap();
+        let client_config =
+            rustls::ClientConfig::builder_with_provider(Arc::new(default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_client_auth_cert(
+                    vec![client_cert.clone()],
+                    PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+                        generated.key_pair.serialize_der(),
+                    )),
+                )
+                .unwrap();
+        let mut client = rustls::ClientConnection::new(
+            Arc::new(client_config),
+            rustls::pki_types::ServerName::try_from("localhost").unwrap(),
+        )
+        .unwrap();
+        client_info.cookie = Some(0x7150_0021);
+        client_info.close = false;
+        client
+            .writer()
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+        assert!(plaintext.starts_with(b"HTTP/1.1 401 Unauthorized\n"));
+        let mut ct = CONTABLE.lock().unwrap();
+        match &ct.get(0x7150_0021, &masscanned.config.tcp).proto_state {
+            ProtoState::TlsSession(session) => {
+                assert!(session.peer_recorded);
+                assert!(session.conn.peer_certificates().unwrap() == [client_cert]);
+            }
+            _ => panic!("no TLS session"),
+        }
+    }
+
+    #[test]
+    fn test_tls_tor() {
+        let dir = std::env::temp_dir().join(format!("masscanned-tls-tor-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        fs::write(dir.join("server.crt"), generated.cert.pem()).unwrap();
+        fs::write(dir.join("server.key"), generated.key_pair.serialize_pem()).unwrap();
+        let ca = generated.cert.der().clone();
+        let mut config = Config::from_str(&format!(
+            "[tls]\nenabled = true\ncert = {:?}\nkey = {:?}\n",
+            dir.join("server.crt"),
+            dir.join("server.key"),
+        ))
+        .unwrap();
+        config.tls.init().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let masscanned = masscanned(config);
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(pnet::packet::ip::IpNextHeaderProtocols::Tcp);
+        /* VERSIONS cell on an OR port, then elsewhere */
+        for (i, port) in [9001, 8443].iter().enumerate() {
+            client_info.cookie = Some(0x7150_0030 + i as u32);
+            client_info.port.dst = Some(*port);
+            client_info.close = false;
+            let mut client = tls_client(&masscanned, &ca, "localhost");
+            client
+                .writer()
+                .write_all(b"\x00\x00\x07\x00\x06\x00\x03\x00\x04\x00\x05")
+                .unwrap();
+            let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+            if *port == 9001 {
+                assert!(plaintext.starts_with(b"\x00\x00\x07\x00\x06\x00\x03\x00\x04\x00\x05"));
+                assert!(plaintext[11..16] == *b"\x00\x00\x00\x00\x08");
+                assert!(plaintext.len() == 11 + 514);
+            } else {
+                assert!(plaintext.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_tls_dot() {
+        let dir = std::env::temp_dir().join(format!("masscanned-tls-dot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        fs::write(dir.join("server.crt"), generated.cert.pem()).unwrap();
+        fs::write(dir.join("server.key"), generated.key_pair.serialize_pem()).unwrap();
+        let ca = generated.cert.der().clone();
+        let mut config = Config::from_str(&format!(
+            "[tls]\nenabled = true\ncert = {:?}\nkey = {:?}\n",
+            dir.join("server.crt"),
+            dir.join("server.key"),
+        ))
+        .unwrap();
+        config.tls.init().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let masscanned = masscanned(config);
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(pnet::packet::ip::IpNextHeaderProtocols::Tcp);
+        client_info.ip.dst = Some(std::net::IpAddr::V4(std::net::Ipv4Addr::new(
+            198, 51, 100, 7,
+        )));
+        /* example.com A IN, RD set */
+        let query = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+                      \x07example\x03com\x00\x00\x01\x00\x01";
+        /* kdig +tls (ALPN dot), then curl (h2 and http/1.1) */
+        for (i, (alpn, port, expected)) in [
+            (vec!["dot"], 853, "dot"),
+            (vec!["h2", "http/1.1"], 443, "http/1.1"),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let mut roots = rustls::RootCertStore::empty();
+            roots.add(ca.clone()).unwrap();
+            let mut client_config =
+                rustls::ClientConfig::builder_with_provider(Arc::new(default_provider()))
+                    .with_safe_default_protocol_versions()
+                    .unwrap()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth();
+            client_config.alpn_protocols = alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+            let mut client = rustls::ClientConnection::new(
+                Arc::new(client_config),
+                rustls::pki_types::ServerName::try_from("localhost").unwrap(),
+            )
+            .unwrap();
+            client_info.cookie = Some(0x7150_0040 + i as u32);
+            client_info.port.dst = Some(*port);
+            client_info.close = false;
+            if *port == 853 {
+                /* two queries, the second one split across records */
+                let mut data = (query.len() as u16).to_be_bytes().to_vec();
+                data.extend_from_slice(query);
+                data.extend_from_slice(&data.clone()[..5]);
+                client.writer().write_all(&data).unwrap();
+                let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+                assert!(client.alpn_protocol() == Some(expected.as_bytes()));
+                let len = u16::from_be_bytes([plaintext[0], plaintext[1]]) as usize;
+                assert!(plaintext.len() == 2 + len);
+                assert!(plaintext[2..6] == *b"\x12\x34\x85\x00");
+                assert!(plaintext.ends_with(b"\xc6\x33\x64\x07"));
+                let mut rest = (query.len() as u16).to_be_bytes().to_vec();
+                rest.extend_from_slice(query);
+                client.writer().write_all(&rest[5..]).unwrap();
+                let second = tls_exchange(&mut client, &masscanned, &mut client_info);
+                assert!(second == plaintext);
+            } else {
+                client
+                    .writer()
+                    .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                    .unwrap();
+                let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+                assert!(client.alpn_protocol() == Some(expected.as_bytes()));
+                assert!(plaintext.starts_with(b"HTTP/1.1 "));
+            }
+        }
+    }
+
+    /* TLS termination with a self-signed certificate for localhost */
+    fn tls_masscanned(conf: &str) -> (Masscanned<'static>, CertificateDer<'static>) {
+        let dir = std::env::temp_dir().join(format!(
+            "masscanned-tls-{}-{}",
+            conf.len(),
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        fs::write(dir.join("server.crt"), generated.cert.pem()).unwrap();
+        fs::write(dir.join("server.key"), generated.key_pair.serialize_pem()).unwrap();
+        let mut config = Config::from_str(&format!(
+            "[tls]\nenabled = true\ncert = {:?}\nkey = {:?}\n{}",
+            dir.join("server.crt"),
+            dir.join("server.key"),
+            conf
+        ))
+        .unwrap();
+        config.tls.init().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let masscanned = masscanned(config);
+        (masscanned, generated.cert.der().clone())
+    }
+
+    #[test]
+    fn test_tls_implicit() {
+        let (masscanned, ca) = tls_masscanned("");
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(pnet::packet::ip::IpNextHeaderProtocols::Tcp);
+        /* smtps: no greeting before the handshake, the banner after */
+        client_info.cookie = Some(0x7150_0050);
+        client_info.port.dst = Some(465);
+        assert!(crate::proto::greeting(&masscanned, &mut client_info).is_none());
+        let mut client = tls_client(&masscanned, &ca, "localhost");
+        let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+        assert!(plaintext == b"220 mail.example.com ESMTP Postfix\r\n");
+        client.writer().write_all(b"EHLO client\r\n").unwrap();
+        let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+        assert!(plaintext.starts_with(b"250-mail.example.com Hello client\r\n"));
+        client.writer().write_all(b"QUIT\r\n").unwrap();
+        let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+        assert!(plaintext.starts_with(b"221 "));
+        assert!(client_info.close);
+        /* mqtts: the client speaks first */
+        client_info.cookie = Some(0x7150_0051);
+        client_info.port.dst = Some(8883);
+        client_info.close = false;
+        assert!(crate::proto::greeting(&masscanned, &mut client_info).is_none());
+        let mut client = tls_client(&masscanned, &ca, "localhost");
+        let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+        assert!(plaintext.is_empty());
+        client
+            .writer()
+            .write_all(b"\x10\x10\x00\x04MQTT\x04\x02\x00\x3c\x00\x04test")
+            .unwrap();
+        let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+        assert!(plaintext == b"\x20\x02\x00\x00");
+        /* a port mapped to a protocol with markers of its own */
+        let (masscanned, ca) = tls_masscanned("[[tls.ports]]\nport = 25\nprotocol = \"imap\"\n");
+        client_info.cookie = Some(0x7150_0052);
+        client_info.port.dst = Some(25);
+        assert!(crate::proto::greeting(&masscanned, &mut client_info).is_none());
+        let mut client = tls_client(&masscanned, &ca, "localhost");
+        let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+        assert!(plaintext.starts_with(b"* OK [CAPABILITY IMAP4rev1 "));
+        /* the implicit ports have no effect without TLS termination */
+        let masscanned = self::masscanned(Config::default());
+        assert!(masscanned.config.tls.implicit(465).is_none());
+    }
+
+    #[test]
+    fn test_tls_starttls() {
+        let (masscanned, ca) = tls_masscanned("");
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(pnet::packet::ip::IpNextHeaderProtocols::Tcp);
+        client_info.cookie = Some(0x7150_0060);
+        client_info.port.dst = Some(25);
+        let banner = crate::proto::greeting(&masscanned, &mut client_info).unwrap();
+        assert!(banner.starts_with(b"220 "));
+        let repl_data =
+            crate::proto::repl(b"EHLO a\r\nSTARTTLS\r\n", &masscanned, &mut client_info).unwrap();
+        assert!(repl_data.ends_with(b"220 2.0.0 Ready to start TLS\r\n"));
+        /* SMTP goes on within the session, without a new banner */
+        let mut client = tls_client(&masscanned, &ca, "localhost");
+        let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+        assert!(plaintext.is_empty());
+        client.writer().write_all(b"EHLO b\r\n").unwrap();
+        let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+        assert!(plaintext.starts_with(b"250-mail.example.com Hello b\r\n"));
+        /* IMAP commands are not identified by markers */
+        client_info.cookie = Some(0x7150_0061);
+        client_info.port.dst = Some(143);
+        assert!(crate::proto::greeting(&masscanned, &mut client_info).is_some());
+        let repl_data =
+            crate::proto::repl(b"a1 STARTTLS\r\n", &masscanned, &mut client_info).unwrap();
+        assert!(repl_data == b"a1 OK Begin TLS negotiation now.\r\n");
+        let mut client = tls_client(&masscanned, &ca, "localhost");
+        tls_exchange(&mut client, &masscanned, &mut client_info);
+        client.writer().write_all(b"a2 CAPABILITY\r\n").unwrap();
+        let plaintext = tls_exchange(&mut client, &masscanned, &mut client_info);
+        assert!(plaintext.starts_with(b"* CAPABILITY IMAP4rev1 "));
+        assert!(plaintext.ends_with(
+            b"\r\na2 OK Pre-login capabilities listed, post-login capabilities have more.\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_tls_ports_config() {
+        for conf in [
+            "[[tls.ports]]\nport = 465\nprotocol = \"smtps\"\n",
+            "[[tls.ports]]\nport = 3702\nprotocol = \"wsdiscovery\"\n",
+            "[[tls.ports]]\nport = 993\nprotocol = \"tls\"\n",
+            "[[tls.ports]]\nport = 465\nprotocol = \"smtp\"\n[[tls.ports]]\nport = 465\nprotocol = \"imap\"\n",
+        ]
+        .iter()
+        {
+            assert!(Config::from_str(conf).is_err());
+        }
+        let mut config = TlsConfig::default();
+        for value in ["465", "x=smtp", "465=smtp,", "465=dhcp"].iter() {
+            assert!(config.add_ports(value).is_err());
+        }
+        assert!(config.server_config.is_none());
+        config.add_ports("465=http, 2993=imap").unwrap();
+        assert!(config.enabled && config.server_config.is_some());
+        assert!(config.implicit(465) == crate::proto::proto_flow_id("http"));
+        assert!(config.implicit(2993) == crate::proto::proto_flow_id("imap"));
+        assert!(config.implicit(993) == crate::proto::proto_flow_id("imap"));
+        assert!(config.implicit(143).is_none());
+    }
+}
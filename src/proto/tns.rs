@@ -0,0 +1,501 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* packet types */
+const TNS_CONNECT: u8 = 1;
+const TNS_ACCEPT: u8 = 2;
+const TNS_REFUSE: u8 = 4;
+const TNS_DATA: u8 = 6;
+const TNS_RESEND: u8 = 11;
+
+/* highest protocol version accepted: from 315 on, packet lengths are
+ * 32-bit once negotiated */
+const TNS_MAX_VERSION: u16 = 314;
+/* size of the fixed part of a CONNECT packet (after the header) */
+const TNS_CONNECT_HEADER: usize = 26;
+
+/* maximum size of the connect data */
+const TNS_MAX_CONNECT_DATA: usize = 16384;
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TnsAnswer {
+    Accept,
+    #[default]
+    Refuse,
+    Resend,
+}
+
+/* Oracle TNS listener: CONNECT packets are accepted, refused with
+ * refuse_data ({vsnnum} is replaced by the version number of version,
+ * e.g., 318767104 for 19.0.0.0.0), or get a RESEND first (the resent
+ * CONNECT is then refused), depending on answer.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TnsConfig {
+    pub ports: Vec<u16>,
+    pub answer: TnsAnswer,
+    pub version: String,
+    pub refuse_data: String,
+}
+
+impl Default for TnsConfig {
+    fn default() -> Self {
+        TnsConfig {
+            ports: vec![1521],
+            answer: TnsAnswer::Refuse,
+            version: "19.0.0.0.0".to_string(),
+            /* ORA-12514: listener does not currently know of service
+             * requested in connect descriptor */
+            refuse_data: "(DESCRIPTION=(TMP=)(VSNNUM={vsnnum})(ERR=12514)(ERROR_STACK=(ERROR=(CODE=12514)(EMFI=4))))"
+                .to_string(),
+        }
+    }
+}
+
+impl TnsConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if tns_vsnnum(&self.version).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("tns: invalid version: {:?}", self.version),
+            ));
+        }
+        if self.refuse_data.len() > 0xffff - 12 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "tns: refuse_data too long",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* version number: 8 bits (version), 4 bits (release), 4 bits (update),
+ * 8 bits (port release), 8 bits (port update) - 10.2.0.1.0 is
+ * 0x0a200100 */
+fn tns_vsnnum(version: &str) -> Option<u32> {
+    let parts = version
+        .split('.')
+        .map(|p| p.parse::<u32>().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    if parts.len() != 5 {
+        return None;
+    }
+    let mut vsnnum = 0;
+    for (part, (bits, shift)) in parts
+        .iter()
+        .zip([(8, 24), (4, 20), (4, 16), (8, 8), (8, 0)].iter())
+    {
+        if *part >= 1 << bits {
+            return None;
+        }
+        vsnnum |= part << shift;
+    }
+    Some(vsnnum)
+}
+
+fn tns_packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = ((body.len() + 8) as u16).to_be_bytes().to_vec();
+    /* packet checksum, type, flags, header checksum */
+    packet.extend_from_slice(&[0, 0, kind, 0, 0, 0]);
+    packet.extend_from_slice(body);
+    packet
+}
+
+/* CONNECT packet: what the answer depends on */
+struct TnsConnect {
+    version: u16,
+    sdu: u16,
+    tdu: u16,
+    data_len: usize,
+}
+
+/* state of a TNS flow: data received, CONNECT waiting for its connect
+ * data (sent in a DATA packet when too long), RESEND sent */
+#[derive(Default)]
+pub struct TnsState {
+    buffer: Vec<u8>,
+    connect: Option<TnsConnect>,
+    resent: bool,
+}
+
+/* leaves of a connect descriptor (path of the parameter, value), e.g.,
+ * ("CONNECT_DATA.CID.PROGRAM", "sqlplus") for
+ * (DESCRIPTION=(CONNECT_DATA=(CID=(PROGRAM=sqlplus)))) */
+fn tns_descriptor(data: &str) -> Vec<(String, String)> {
+    let mut leaves = Vec::new();
+    let mut path: Vec<String> = Vec::new();
+    let mut token = String::new();
+    let mut value = None;
+    for c in data.chars() {
+        match c {
+            '(' => {
+                if let Some(v) = value.take() {
+                    path.push(v);
+                }
+                token.clear();
+            }
+            '=' if value.is_none() => {
+                value = Some(token.trim().to_ascii_uppercase());
+                token.clear();
+            }
+            ')' => {
+                match value.take() {
+                    Some(key) => {
+                        let mut p = path.clone();
+                        p.push(key);
+                        /* the outer DESCRIPTION is not kept */
+                        if p.len() > 1 && p[0] == "DESCRIPTION" {
+                            p.remove(0);
+                        }
+                        leaves.push((p.join("."), token.trim().to_string()));
+                    }
+                    None => {
+                        path.pop();
+                    }
+                }
+                token.clear();
+            }
+            c => token.push(c),
+        }
+    }
+    leaves
+}
+
+fn tns_connect_event(connect: &TnsConnect, data: &[u8], client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("tns_connect", client_info);
+    let data = String::from_utf8_lossy(data).to_string();
+    event.set("version", connect.version);
+    for (path, value) in tns_descriptor(&data) {
+        let name = match path.as_str() {
+            "CONNECT_DATA.SERVICE_NAME" => "service_name",
+            "CONNECT_DATA.SID" => "sid",
+            "CONNECT_DATA.COMMAND" => "command",
+            "CONNECT_DATA.CID.PROGRAM" => "program",
+            "CONNECT_DATA.CID.HOST" => "host",
+            "CONNECT_DATA.CID.USER" => "user",
+            _ => continue,
+        };
+        if !event.fields.contains_key(name) {
+            event.set(name, value);
+        }
+    }
+    event.set("descriptor", data);
+    event
+}
+
+fn tns_accept(connect: &TnsConnect) -> Vec<u8> {
+    let mut body = std::cmp::min(connect.version, TNS_MAX_VERSION)
+        .to_be_bytes()
+        .to_vec();
+    /* service options */
+    body.extend_from_slice(&[0, 0]);
+    body.extend_from_slice(&std::cmp::min(connect.sdu, 0x2000).to_be_bytes());
+    body.extend_from_slice(&std::cmp::min(connect.tdu, 0x7fff).to_be_bytes());
+    /* value of 1 in hardware byte order, no accept data (at offset
+     * 24), connect flags */
+    body.extend_from_slice(&[0x01, 0x00, 0x00, 0x00, 0x00, 0x18, 0x41, 0x01]);
+    tns_packet(TNS_ACCEPT, &body)
+}
+
+fn tns_refuse(config: &TnsConfig) -> Vec<u8> {
+    let vsnnum = tns_vsnnum(&config.version).unwrap_or(0);
+    let data = config
+        .refuse_data
+        .replace("{vsnnum}", &format!("{}", vsnnum));
+    /* user and system reasons, data length */
+    let mut body = vec![0x22, 0x00];
+    body.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    body.extend_from_slice(data.as_bytes());
+    tns_packet(TNS_REFUSE, &body)
+}
+
+/* answer to a CONNECT (and its connect data) */
+fn tns_answer(
+    connect: &TnsConnect,
+    data: &[u8],
+    config: &TnsConfig,
+    client_info: &mut ClientInfo,
+    resent: &mut bool,
+) -> Vec<u8> {
+    tns_connect_event(connect, data, client_info).log();
+    match config.answer {
+        TnsAnswer::Accept => tns_accept(connect),
+        TnsAnswer::Resend if !*resent => {
+            *resent = true;
+            tns_packet(TNS_RESEND, &[])
+        }
+        _ => {
+            client_info.close = true;
+            tns_refuse(config)
+        }
+    }
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving TNS data");
+    let config = &masscanned.config.tns;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Tns(s)) => s,
+        _ => TnsState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while !client_info.close && state.buffer.len() >= 8 {
+        let len = u16::from_be_bytes([state.buffer[0], state.buffer[1]]) as usize;
+        if len < 8 {
+            info!("invalid TNS packet length ({} bytes) - dropped", len);
+            client_info.close = true;
+            break;
+        }
+        if state.buffer.len() < len {
+            break;
+        }
+        let packet = state.buffer.drain(..len).collect::<Vec<u8>>();
+        let body = &packet[8..];
+        match (packet[4], state.connect.take()) {
+            (TNS_CONNECT, _) => {
+                if body.len() < TNS_CONNECT_HEADER {
+                    info!("TNS data not handled (invalid CONNECT)");
+                    client_info.close = true;
+                    break;
+                }
+                let field = |i: usize| u16::from_be_bytes([body[i], body[i + 1]]);
+                let connect = TnsConnect {
+                    version: field(0),
+                    sdu: field(6),
+                    tdu: field(8),
+                    data_len: field(16) as usize,
+                };
+                let offset = field(18) as usize;
+                if connect.data_len > TNS_MAX_CONNECT_DATA {
+                    info!("TNS connect data too large - dropped");
+                    client_info.close = true;
+                    break;
+                }
+                match packet.get(offset..offset + connect.data_len) {
+                    Some(d) if connect.data_len > 0 => repl_data.extend(tns_answer(
+                        &connect,
+                        d,
+                        config,
+                        client_info,
+                        &mut state.resent,
+                    )),
+                    /* the connect data comes next */
+                    _ => state.connect = Some(connect),
+                }
+            }
+            (TNS_DATA, Some(connect)) if body.len() >= 2 => {
+                /* data flags, then the connect data */
+                let d = &body[2..std::cmp::min(body.len(), 2 + connect.data_len)];
+                repl_data.extend(tns_answer(
+                    &connect,
+                    d,
+                    config,
+                    client_info,
+                    &mut state.resent,
+                ));
+            }
+            (kind, _) => {
+                info!("TNS packet not handled: {}", kind);
+                client_info.close = true;
+            }
+        }
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Tns(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending TNS data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    /* nmap version probe (oracle-tns) */
+    const NMAP_CONNECT: &[u8] = b"\x00\x5a\x00\x00\x01\x00\x00\x00\x01\x36\x01\x2c\x00\x00\x08\x00\x7f\xff\x7f\x08\x00\x00\x00\x01\x00\x20\x00\x3a\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x34\xe6\x00\x00\x00\x01\x00\x00(CONNECT_DATA=(COMMAND=version))";
+
+    /* tnscmd ping */
+    const TNSCMD_PING: &str = "(CONNECT_DATA=(CID=(PROGRAM=)(HOST=linux)(USER=oracle))(COMMAND=ping)(ARGUMENTS=64)(SERVICE=LISTENER)(VERSION=135294976))";
+
+    /* CONNECT packet (as sent by tnscmd), with the connect data
+     * included or not */
+    fn connect(version: u16, data: &str, inline: bool) -> Vec<u8> {
+        let mut body = version.to_be_bytes().to_vec();
+        body.extend_from_slice(b"\x01\x2c\x00\x00\x08\x00\x7f\xff\x7f\x08\x00\x00\x00\x01");
+        body.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        body.extend_from_slice(b"\x00\x3a\x00\x00\x00\x00\x00\x00");
+        body.extend_from_slice(&[0; 24]);
+        if inline {
+            body.extend_from_slice(data.as_bytes());
+        }
+        tns_packet(TNS_CONNECT, &body)
+    }
+
+    fn tns_client_info() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.port.dst = Some(1521);
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info
+    }
+
+    #[test]
+    fn test_tns_vsnnum() {
+        assert!(tns_vsnnum("10.2.0.1.0") == Some(0x0a20_0100));
+        assert!(tns_vsnnum("19.0.0.0.0") == Some(318767104));
+        for v in ["19.0.0.0", "19.16.0.0.0", "19.0.0.0.x", ""].iter() {
+            assert!(tns_vsnnum(v).is_none());
+        }
+        assert!(Config::from_str("[tns]\nversion = \"19c\"\n").is_err());
+        assert!(Config::from_str("[tns]\nanswer = \"redirect\"\n").is_err());
+    }
+
+    #[test]
+    fn test_tns_descriptor() {
+        let descriptor = "(DESCRIPTION=(ADDRESS=(PROTOCOL=TCP)(HOST=192.0.2.1)(PORT=1521))(CONNECT_DATA=(SERVICE_NAME=ORCLPDB1)(CID=(PROGRAM=sqlplus)(HOST=box)(USER=alice))))";
+        let leaves = tns_descriptor(descriptor);
+        assert!(leaves[0] == ("ADDRESS.PROTOCOL".to_string(), "TCP".to_string()));
+        assert!(leaves[1] == ("ADDRESS.HOST".to_string(), "192.0.2.1".to_string()));
+        assert!(leaves.len() == 7);
+        let connect = TnsConnect {
+            version: 318,
+            sdu: 0x2000,
+            tdu: 0xffff,
+            data_len: descriptor.len(),
+        };
+        let event = tns_connect_event(&connect, descriptor.as_bytes(), &tns_client_info());
+        assert!(event.fields["service_name"] == "ORCLPDB1");
+        assert!(event.fields["program"] == "sqlplus");
+        /* the host of the client, not the one of the listener */
+        assert!(event.fields["host"] == "box");
+        assert!(event.fields["user"] == "alice");
+        assert!(event.fields.get("sid").is_none());
+        assert!(event.fields["descriptor"] == descriptor);
+        let event = tns_connect_event(&connect, TNSCMD_PING.as_bytes(), &tns_client_info());
+        assert!(event.fields["command"] == "ping");
+        assert!(event.fields["program"] == "");
+        assert!(event.fields["user"] == "oracle");
+    }
+
+    #[test]
+    fn test_tns_refuse() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = tns_client_info();
+        let mut tcb = TCPControlBlock::new();
+        let repl_data =
+            crate::proto::dispatch(NMAP_CONNECT, &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(tcb.proto_id == crate::proto::PROTO_TNS);
+        assert!(u16::from_be_bytes([repl_data[0], repl_data[1]]) as usize == repl_data.len());
+        assert!(repl_data[4] == TNS_REFUSE);
+        let data = b"(DESCRIPTION=(TMP=)(VSNNUM=318767104)(ERR=12514)(ERROR_STACK=(ERROR=(CODE=12514)(EMFI=4))))";
+        assert!(u16::from_be_bytes([repl_data[10], repl_data[11]]) as usize == data.len());
+        assert!(repl_data[12..] == data[..]);
+        assert!(client_info.close);
+        /* configured version and error */
+        let masscanned = self::masscanned(
+            Config::from_str(
+                "[tns]\nversion = \"10.2.0.1.0\"\nrefuse_data = \"(DESCRIPTION=(ERR=1189)(VSNNUM={vsnnum}))\"\n",
+            )
+            .unwrap(),
+        );
+        let repl_data = repl(NMAP_CONNECT, &masscanned, &mut tns_client_info(), None).unwrap();
+        assert!(repl_data[12..] == b"(DESCRIPTION=(ERR=1189)(VSNNUM=169869568))"[..]);
+        /* not TNS */
+        let mut client_info = tns_client_info();
+        assert!(repl(
+            b"\x00\x04\x00\x00\x01\x00\x00\x00",
+            &masscanned,
+            &mut client_info,
+            None
+        )
+        .is_none());
+        assert!(client_info.close);
+    }
+
+    #[test]
+    fn test_tns_accept_resend() {
+        let masscanned = masscanned(Config::from_str("[tns]\nanswer = \"accept\"\n").unwrap());
+        for (version, accepted) in [(0x0136, 0x0136), (0x013e, TNS_MAX_VERSION)].iter() {
+            let mut client_info = tns_client_info();
+            let repl_data = repl(
+                &connect(*version, TNSCMD_PING, true),
+                &masscanned,
+                &mut client_info,
+                None,
+            )
+            .unwrap();
+            assert!(repl_data[4] == TNS_ACCEPT);
+            assert!(u16::from_be_bytes([repl_data[0], repl_data[1]]) as usize == repl_data.len());
+            assert!(u16::from_be_bytes([repl_data[8], repl_data[9]]) == *accepted);
+            /* SDU and TDU */
+            assert!(repl_data[12..16] == [0x08, 0x00, 0x7f, 0xff]);
+            assert!(!client_info.close);
+        }
+        /* connect data in a DATA packet */
+        let mut client_info = tns_client_info();
+        let mut tcb = TCPControlBlock::new();
+        let data = [
+            connect(0x0136, TNSCMD_PING, false),
+            tns_packet(
+                TNS_DATA,
+                &[b"\x00\x00".to_vec(), TNSCMD_PING.as_bytes().to_vec()].concat(),
+            ),
+        ];
+        assert!(repl(&data[0], &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        let repl_data = repl(&data[1], &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl_data[4] == TNS_ACCEPT);
+        /* a RESEND, then the CONNECT is refused */
+        let masscanned =
+            self::masscanned(Config::from_str("[tns]\nanswer = \"resend\"\n").unwrap());
+        let mut client_info = tns_client_info();
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = repl(NMAP_CONNECT, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl_data == b"\x00\x08\x00\x00\x0b\x00\x00\x00");
+        assert!(!client_info.close);
+        let repl_data = repl(NMAP_CONNECT, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl_data[4] == TNS_REFUSE);
+        assert!(client_info.close);
+    }
+}
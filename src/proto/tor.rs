@@ -0,0 +1,585 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use chrono::Utc;
+use log::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* tor-spec section 3: cells are a circuit ID (2 bytes before link
+ * version 4, 4 bytes after), a command, then a fixed-size payload, or
+ * a length and a payload for variable-length cells (VERSIONS, and
+ * commands 128 and above) */
+const TOR_PAYLOAD_LEN: usize = 509;
+
+const TOR_CMD_PADDING: u8 = 0;
+const TOR_CMD_VERSIONS: u8 = 7;
+const TOR_CMD_NETINFO: u8 = 8;
+const TOR_CMD_VPADDING: u8 = 128;
+const TOR_CMD_CERTS: u8 = 129;
+const TOR_CMD_AUTH_CHALLENGE: u8 = 130;
+const TOR_CMD_AUTHENTICATE: u8 = 131;
+
+/* tor-spec section 6.4 */
+const TOR_ADDRESS_IPV4: u8 = 4;
+const TOR_ADDRESS_IPV6: u8 = 6;
+
+/* Tor OR (onion router) connections, behind TLS on the configured
+ * ports (TLS termination must be enabled, see TlsConfig): the VERSIONS
+ * cell of the initiator gets our VERSIONS cell and a NETINFO cell,
+ * using the highest link version we have in common. Cells received are
+ * recorded, with the certificates of CERTS cells (types, lengths and
+ * SHA-1 fingerprints).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TorConfig {
+    pub ports: Vec<u16>,
+    /* link protocol versions (3 to 5) */
+    pub versions: Vec<u16>,
+}
+
+impl Default for TorConfig {
+    fn default() -> Self {
+        TorConfig {
+            ports: vec![9001, 443],
+            versions: vec![3, 4, 5],
+        }
+    }
+}
+
+impl TorConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if self.versions.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "tor: no link version",
+            ));
+        }
+        if let Some(v) = self.versions.iter().find(|v| !(3..=5).contains(*v)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("tor: unsupported link version: {} (3 to 5)", v),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct TorState {
+    buffer: Vec<u8>,
+    /* negotiated link version (None until the VERSIONS cells have
+     * been exchanged) */
+    link_version: Option<u16>,
+}
+
+struct Cell {
+    circ_id: u32,
+    command: u8,
+    payload: Vec<u8>,
+}
+
+fn tor_circ_id_len(link_version: Option<u16>) -> usize {
+    match link_version {
+        Some(v) if v >= 4 => 4,
+        _ => 2,
+    }
+}
+
+fn tor_is_variable(command: u8) -> bool {
+    command == TOR_CMD_VERSIONS || command >= 128
+}
+
+fn tor_command_name(command: u8) -> Option<&'static str> {
+    match command {
+        TOR_CMD_PADDING => Some("PADDING"),
+        1 => Some("CREATE"),
+        2 => Some("CREATED"),
+        3 => Some("RELAY"),
+        4 => Some("DESTROY"),
+        5 => Some("CREATE_FAST"),
+        6 => Some("CREATED_FAST"),
+        TOR_CMD_VERSIONS => Some("VERSIONS"),
+        TOR_CMD_NETINFO => Some("NETINFO"),
+        9 => Some("RELAY_EARLY"),
+        10 => Some("CREATE2"),
+        11 => Some("CREATED2"),
+        12 => Some("PADDING_NEGOTIATE"),
+        TOR_CMD_VPADDING => Some("VPADDING"),
+        TOR_CMD_CERTS => Some("CERTS"),
+        TOR_CMD_AUTH_CHALLENGE => Some("AUTH_CHALLENGE"),
+        TOR_CMD_AUTHENTICATE => Some("AUTHENTICATE"),
+        132 => Some("AUTHORIZE"),
+        _ => None,
+    }
+}
+
+/* returns the cell and the number of bytes used, or None when data
+ * does not hold an entire cell */
+fn tor_parse_cell(data: &[u8], link_version: Option<u16>) -> Option<(Cell, usize)> {
+    let circ_len = tor_circ_id_len(link_version);
+    if data.len() <= circ_len {
+        return None;
+    }
+    let circ_id = data[..circ_len]
+        .iter()
+        .fold(0u32, |id, b| (id << 8) | *b as u32);
+    let command = data[circ_len];
+    let (offset, len) = if tor_is_variable(command) {
+        if data.len() < circ_len + 3 {
+            return None;
+        }
+        let len = u16::from_be_bytes([data[circ_len + 1], data[circ_len + 2]]) as usize;
+        (circ_len + 3, len)
+    } else {
+        (circ_len + 1, TOR_PAYLOAD_LEN)
+    };
+    if data.len() < offset + len {
+        return None;
+    }
+    Some((
+        Cell {
+            circ_id,
+            command,
+            payload: data[offset..offset + len].to_vec(),
+        },
+        offset + len,
+    ))
+}
+
+fn tor_cell(link_version: Option<u16>, circ_id: u32, command: u8, payload: &[u8]) -> Vec<u8> {
+    let circ_len = tor_circ_id_len(link_version);
+    let mut cell = circ_id.to_be_bytes()[4 - circ_len..].to_vec();
+    cell.push(command);
+    if tor_is_variable(command) {
+        cell.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        cell.extend_from_slice(payload);
+    } else {
+        cell.extend_from_slice(payload);
+        cell.resize(circ_len + 1 + TOR_PAYLOAD_LEN, 0);
+    }
+    cell
+}
+
+/* VERSIONS cells always have a 2-byte circuit ID (tor-spec section
+ * 4.1) */
+fn tor_versions_cell(versions: &[u16]) -> Vec<u8> {
+    let payload: Vec<u8> = versions.iter().flat_map(|v| v.to_be_bytes()).collect();
+    tor_cell(None, 0, TOR_CMD_VERSIONS, &payload)
+}
+
+fn tor_parse_versions(payload: &[u8]) -> Option<Vec<u16>> {
+    if payload.is_empty() || !payload.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(
+        payload
+            .chunks(2)
+            .map(|v| u16::from_be_bytes([v[0], v[1]]))
+            .collect(),
+    )
+}
+
+/* tor-spec section 4.5 - unknown addresses are sent as 0.0.0.0 */
+fn tor_address(ip: Option<IpAddr>) -> Vec<u8> {
+    match ip {
+        Some(IpAddr::V6(ip)) => {
+            let mut a = vec![TOR_ADDRESS_IPV6, 16];
+            a.extend_from_slice(&ip.octets());
+            a
+        }
+        Some(IpAddr::V4(ip)) => {
+            let mut a = vec![TOR_ADDRESS_IPV4, 4];
+            a.extend_from_slice(&ip.octets());
+            a
+        }
+        None => vec![TOR_ADDRESS_IPV4, 4, 0, 0, 0, 0],
+    }
+}
+
+/* returns the address (None for other types) and the number of bytes
+ * used */
+fn tor_parse_address(data: &[u8]) -> Option<(Option<IpAddr>, usize)> {
+    let (atype, len) = (*data.first()?, *data.get(1)? as usize);
+    let value = data.get(2..2 + len)?;
+    let ip = match (atype, len) {
+        (TOR_ADDRESS_IPV4, 4) => Some(IpAddr::V4(Ipv4Addr::new(
+            value[0], value[1], value[2], value[3],
+        ))),
+        (TOR_ADDRESS_IPV6, 16) => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(value);
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    };
+    Some((ip, 2 + len))
+}
+
+/* NETINFO: time, address of the other side, and our addresses */
+fn tor_netinfo_cell(
+    link_version: u16,
+    time: u32,
+    other: Option<IpAddr>,
+    mine: Option<IpAddr>,
+) -> Vec<u8> {
+    let mut payload = time.to_be_bytes().to_vec();
+    payload.extend(tor_address(other));
+    payload.push(1);
+    payload.extend(tor_address(mine));
+    tor_cell(Some(link_version), 0, TOR_CMD_NETINFO, &payload)
+}
+
+struct Netinfo {
+    time: u32,
+    other: Option<IpAddr>,
+    mine: Vec<IpAddr>,
+}
+
+fn tor_parse_netinfo(payload: &[u8]) -> Option<Netinfo> {
+    let time = u32::from_be_bytes([
+        *payload.first()?,
+        *payload.get(1)?,
+        *payload.get(2)?,
+        *payload.get(3)?,
+    ]);
+    let (other, len) = tor_parse_address(&payload[4..])?;
+    let mut offset = 4 + len;
+    let count = *payload.get(offset)?;
+    offset += 1;
+    let mut mine = Vec::new();
+    for _ in 0..count {
+        let (ip, len) = tor_parse_address(&payload[offset..])?;
+        offset += len;
+        mine.extend(ip);
+    }
+    Some(Netinfo { time, other, mine })
+}
+
+/* CERTS: certificate types and values */
+fn tor_parse_certs(payload: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let count = *payload.first()?;
+    let mut offset = 1;
+    let mut certs = Vec::new();
+    for _ in 0..count {
+        let ctype = *payload.get(offset)?;
+        let len = u16::from_be_bytes([*payload.get(offset + 1)?, *payload.get(offset + 2)?]);
+        let cert = payload.get(offset + 3..offset + 3 + len as usize)?;
+        offset += 3 + len as usize;
+        certs.push((ctype, cert));
+    }
+    Some(certs)
+}
+
+fn tor_event(cell: &Cell, link_version: Option<u16>, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("tor_cell", client_info);
+    match tor_command_name(cell.command) {
+        Some(name) => event.set("command", name),
+        None => event.set("command", cell.command),
+    }
+    event.set("circ_id", cell.circ_id);
+    event.set("length", cell.payload.len());
+    if let Some(v) = link_version {
+        event.set("link_version", v);
+    }
+    match cell.command {
+        TOR_CMD_CERTS => match tor_parse_certs(&cell.payload) {
+            Some(certs) => event.set(
+                "certificates",
+                certs
+                    .iter()
+                    .map(|(t, c)| {
+                        json!({
+                            "type": t,
+                            "length": c.len(),
+                            "sha1": format!("{:x}", Sha1::digest(c)),
+                        })
+                    })
+                    .collect::<Vec<Value>>(),
+            ),
+            None => event.set("error", "invalid CERTS cell"),
+        },
+        TOR_CMD_NETINFO => match tor_parse_netinfo(&cell.payload) {
+            Some(n) => {
+                event.set("netinfo_time", n.time);
+                if let Some(ip) = n.other {
+                    event.set("other_address", ip.to_string());
+                }
+                event.set(
+                    "my_addresses",
+                    n.mine.iter().map(|ip| ip.to_string()).collect::<Vec<_>>(),
+                );
+            }
+            None => event.set("error", "invalid NETINFO cell"),
+        },
+        _ => {}
+    }
+    event
+}
+
+/* Is data (the decrypted content of a TLS session) the VERSIONS cell
+ * that starts an OR connection, on one of the configured ports?
+ **/
+pub fn is_or_handshake(data: &[u8], masscanned: &Masscanned, client_info: &ClientInfo) -> bool {
+    match client_info.port.dst {
+        Some(p) if masscanned.config.tor.ports.contains(&p) => {}
+        _ => return false,
+    }
+    data.len() >= 5
+        && data[..3] == [0, 0, TOR_CMD_VERSIONS]
+        && u16::from_be_bytes([data[3], data[4]]).is_multiple_of(2)
+}
+
+/* Answer the cells of an OR connection: the VERSIONS cell gets ours
+ * and a NETINFO cell (no CERTS and AUTH_CHALLENGE: the initiators
+ * close the connection there), other cells are recorded. Initiators
+ * with no link version in common are disconnected.
+ **/
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving Tor data");
+    let config = &masscanned.config.tor;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Tor(s)) => s,
+        _ => TorState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    while let Some((cell, len)) = tor_parse_cell(&state.buffer, state.link_version) {
+        state.buffer.drain(..len);
+        if state.link_version.is_some() {
+            tor_event(&cell, state.link_version, client_info).log();
+            continue;
+        }
+        let versions = match (cell.command, tor_parse_versions(&cell.payload)) {
+            (TOR_CMD_VERSIONS, Some(v)) => v,
+            _ => {
+                info!("Tor cell not handled before VERSIONS: {}", cell.command);
+                client_info.close = true;
+                return None;
+            }
+        };
+        let link_version = versions
+            .iter()
+            .filter(|v| config.versions.contains(v))
+            .max()
+            .copied();
+        let mut event = tor_event(&cell, link_version, client_info);
+        event.set("versions", versions.clone());
+        event.log();
+        match link_version {
+            Some(v) => {
+                warn!("Tor OR connection (link version {})", v);
+                repl_data.extend(tor_versions_cell(&config.versions));
+                repl_data.extend(tor_netinfo_cell(
+                    v,
+                    Utc::now().timestamp() as u32,
+                    client_info.ip.src,
+                    client_info.ip.dst,
+                ));
+                state.link_version = Some(v);
+            }
+            None => {
+                info!("Tor link versions not supported: {:?}", versions);
+                client_info.close = true;
+                return None;
+            }
+        }
+    }
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::Tor(state);
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending Tor data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+
+    /* first flight of a tor 0.4.8 client, then its NETINFO cell (link
+     * version 5) */
+    const TOR_CLIENT_VERSIONS: &[u8] = b"\x00\x00\x07\x00\x06\x00\x03\x00\x04\x00\x05";
+
+    fn client_netinfo() -> Vec<u8> {
+        let mut cell = b"\x00\x00\x00\x00\x08\x65\x4b\x1a\x80\x04\x04\xc0\x00\x02\x01\x00".to_vec();
+        cell.resize(514, 0);
+        cell
+    }
+
+    #[test]
+    fn test_tor_cells() {
+        /* VERSIONS */
+        let cell = tor_versions_cell(&[3, 4, 5]);
+        assert!(cell == TOR_CLIENT_VERSIONS);
+        let (c, len) = tor_parse_cell(&cell, None).unwrap();
+        assert!(len == cell.len() && c.circ_id == 0 && c.command == TOR_CMD_VERSIONS);
+        assert!(tor_parse_versions(&c.payload) == Some(vec![3, 4, 5]));
+        assert!(tor_parse_versions(b"\x00\x03\x00").is_none());
+        for i in 0..cell.len() {
+            assert!(tor_parse_cell(&cell[..i], None).is_none());
+        }
+        /* NETINFO: 4-byte circuit IDs from link version 4 */
+        let other = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        let mine = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        let cell = tor_netinfo_cell(5, 0x654b1a80, other, Some(mine));
+        assert!(cell.len() == 514);
+        assert!(cell[..15] == *b"\x00\x00\x00\x00\x08\x65\x4b\x1a\x80\x04\x04\xc0\x00\x02\x01");
+        assert!(cell[15..18] == [1, TOR_ADDRESS_IPV6, 16]);
+        /* read with 2-byte circuit IDs, it is a PADDING cell */
+        assert!(tor_parse_cell(&cell, None).unwrap().0.command == TOR_CMD_PADDING);
+        let (c, len) = tor_parse_cell(&cell, Some(4)).unwrap();
+        assert!(len == 514 && c.command == TOR_CMD_NETINFO);
+        let n = tor_parse_netinfo(&c.payload).unwrap();
+        assert!(n.time == 0x654b1a80 && n.other == other && n.mine == vec![mine]);
+        /* 2-byte circuit IDs before */
+        let cell = tor_netinfo_cell(3, 0, None, None);
+        assert!(cell.len() == 512 && cell[..3] == [0, 0, TOR_CMD_NETINFO]);
+        let (c, _) = tor_parse_cell(&cell, Some(3)).unwrap();
+        let n = tor_parse_netinfo(&c.payload).unwrap();
+        assert!(n.other == Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)));
+        /* CERTS */
+        let cell = tor_cell(
+            Some(4),
+            0,
+            TOR_CMD_CERTS,
+            b"\x02\x01\x00\x03abc\x04\x00\x02de",
+        );
+        let (c, _) = tor_parse_cell(&cell, Some(4)).unwrap();
+        let certs = tor_parse_certs(&c.payload).unwrap();
+        assert!(certs == vec![(1, &b"abc"[..]), (4, &b"de"[..])]);
+        let event = tor_event(&c, Some(4), &ClientInfo::new());
+        assert!(event.fields["command"] == "CERTS");
+        assert!(event.fields["certificates"][0]["type"] == 1);
+        assert!(event.fields["certificates"][0]["length"] == 3);
+        assert!(
+            event.fields["certificates"][0]["sha1"] == "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert!(tor_parse_certs(b"\x01\x01\x00\x03ab").is_none());
+        /* configuration */
+        assert!(TorConfig::default().check().is_ok());
+        for versions in [vec![], vec![2, 3], vec![6]].iter() {
+            let config = TorConfig {
+                versions: versions.clone(),
+                ..Default::default()
+            };
+            assert!(config.check().is_err());
+        }
+    }
+
+    #[test]
+    fn test_tor_repl() {
+        let mut masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.ip.src = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        client_info.port.dst = Some(9001);
+        assert!(is_or_handshake(
+            TOR_CLIENT_VERSIONS,
+            &masscanned,
+            &client_info
+        ));
+        assert!(!is_or_handshake(
+            b"GET / HTTP/1.1\r\n",
+            &masscanned,
+            &client_info
+        ));
+        let mut tcb = TCPControlBlock::new();
+        let repl_data = repl(
+            TOR_CLIENT_VERSIONS,
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data[..11] == *TOR_CLIENT_VERSIONS);
+        assert!(repl_data.len() == 11 + 514);
+        let (c, _) = tor_parse_cell(&repl_data[11..], Some(5)).unwrap();
+        assert!(c.command == TOR_CMD_NETINFO);
+        let n = tor_parse_netinfo(&c.payload).unwrap();
+        assert!(n.other == client_info.ip.src && n.mine == vec![client_info.ip.dst.unwrap()]);
+        match &tcb.proto_state {
+            ProtoState::Tor(s) => assert!(s.link_version == Some(5)),
+            _ => panic!("no Tor state"),
+        }
+        /* the NETINFO cell of the client, in two segments */
+        let netinfo = client_netinfo();
+        assert!(repl(
+            &netinfo[..100],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        assert!(repl(
+            &netinfo[100..],
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        assert!(!client_info.close);
+        match &tcb.proto_state {
+            ProtoState::Tor(s) => assert!(s.buffer.is_empty()),
+            _ => panic!("no Tor state"),
+        }
+        /* link version 3, in the same segment as the cells that follow */
+        masscanned.config.tor.versions = vec![3];
+        let mut tcb = TCPControlBlock::new();
+        let mut data = TOR_CLIENT_VERSIONS.to_vec();
+        data.extend(tor_cell(Some(3), 0, TOR_CMD_VPADDING, b"xx"));
+        let repl_data = repl(&data, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl_data[..7] == *b"\x00\x00\x07\x00\x02\x00\x03");
+        assert!(repl_data.len() == 7 + 512);
+        assert!(repl_data[7..10] == [0, 0, TOR_CMD_NETINFO]);
+        /* no version in common */
+        let mut tcb = TCPControlBlock::new();
+        assert!(repl(
+            b"\x00\x00\x07\x00\x02\x00\x04",
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb)
+        )
+        .is_none());
+        assert!(client_info.close);
+        /* other cells first */
+        client_info.close = false;
+        assert!(repl(&netinfo, &masscanned, &mut client_info, None).is_none());
+        assert!(client_info.close);
+    }
+}
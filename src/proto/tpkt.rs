@@ -0,0 +1,133 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+/* TPKT (RFC 1006 section 6) and the X.224 (ISO 8073, also known as
+ * COTP) TPDUs it carries, as used by RDP and S7comm.
+ **/
+
+/* X.224 TPDU codes (ISO 8073 section 13) */
+pub const X224_CR: u8 = 0xe0;
+pub const X224_CC: u8 = 0xd0;
+pub const X224_DT: u8 = 0xf0;
+
+/* end of TSDU mark of data TPDUs */
+const X224_EOT: u8 = 0x80;
+
+/* TPDU of the TPKT at the beginning of data, and length of the TPKT:
+ * None when data does not start with an entire TPKT */
+pub fn tpkt_parse(data: &[u8]) -> Option<(&[u8], usize)> {
+    if data.len() < 4 || data[0] != 3 || data[1] != 0 {
+        return None;
+    }
+    let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+    Some((data.get(4..len)?, len))
+}
+
+pub fn tpkt(tpdu: &[u8]) -> Vec<u8> {
+    let mut tpkt = vec![3, 0];
+    tpkt.extend_from_slice(&((4 + tpdu.len()) as u16).to_be_bytes());
+    tpkt.extend_from_slice(tpdu);
+    tpkt
+}
+
+/* Connection Request: SRC-REF, class and variable part
+ * (parameters, then user data) */
+pub struct X224ConnectionRequest<'a> {
+    pub src_ref: [u8; 2],
+    pub class: u8,
+    pub variable: &'a [u8],
+}
+
+pub fn x224_parse_cr(tpdu: &[u8]) -> Option<X224ConnectionRequest<'_>> {
+    let li = *tpdu.first()? as usize;
+    /* code, DST-REF, SRC-REF, class */
+    if li < 6 || tpdu.len() < li + 1 || tpdu[1] & 0xf0 != X224_CR {
+        return None;
+    }
+    Some(X224ConnectionRequest {
+        src_ref: [tpdu[4], tpdu[5]],
+        class: tpdu[6],
+        variable: &tpdu[7..li + 1],
+    })
+}
+
+/* Connection Confirm: DST-REF is the SRC-REF of the request, class 0 */
+pub fn x224_cc(dst_ref: [u8; 2], src_ref: [u8; 2], variable: &[u8]) -> Vec<u8> {
+    let mut tpdu = vec![
+        6 + variable.len() as u8,
+        X224_CC,
+        dst_ref[0],
+        dst_ref[1],
+        src_ref[0],
+        src_ref[1],
+        0,
+    ];
+    tpdu.extend_from_slice(variable);
+    tpdu
+}
+
+/* user data of a (class 0) data TPDU */
+pub fn x224_parse_dt(tpdu: &[u8]) -> Option<&[u8]> {
+    match tpdu {
+        [2, X224_DT, _, data @ ..] => Some(data),
+        _ => None,
+    }
+}
+
+pub fn x224_dt(data: &[u8]) -> Vec<u8> {
+    let mut tpdu = vec![2, X224_DT, X224_EOT];
+    tpdu.extend_from_slice(data);
+    tpdu
+}
+
+/* parameters of the variable part of CR and CC TPDUs (code, value) */
+pub fn x224_parameters(mut variable: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut parameters = Vec::new();
+    while !variable.is_empty() {
+        let code = variable[0];
+        let len = *variable.get(1)? as usize;
+        parameters.push((code, variable.get(2..2 + len)?));
+        variable = &variable[2 + len..];
+    }
+    Some(parameters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tpkt() {
+        let data = tpkt(&x224_dt(b"abc"));
+        assert!(data == b"\x03\x00\x00\x0a\x02\xf0\x80abc");
+        let (tpdu, len) = tpkt_parse(&data).unwrap();
+        assert!(len == data.len());
+        assert!(x224_parse_dt(tpdu) == Some(&b"abc"[..]));
+        for i in 0..data.len() {
+            assert!(tpkt_parse(&data[..i]).is_none());
+        }
+        /* wrong version, length shorter than the header */
+        assert!(tpkt_parse(b"\x02\x00\x00\x04").is_none());
+        assert!(tpkt_parse(b"\x03\x00\x00\x02").is_none());
+        let cr =
+            x224_parse_cr(b"\x0d\xe0\x00\x00\x00\x01\x00\xc1\x02\x01\x00\xc0\x01\x0a").unwrap();
+        assert!(cr.src_ref == [0, 1] && cr.class == 0);
+        let parameters = x224_parameters(cr.variable).unwrap();
+        assert!(parameters == vec![(0xc1, &b"\x01\x00"[..]), (0xc0, b"\x0a")]);
+        assert!(x224_parameters(b"\xc1\x02\x01").is_none());
+        assert!(x224_cc([0, 1], [0x12, 0x34], b"") == b"\x06\xd0\x00\x01\x12\x34\x00");
+    }
+}
@@ -0,0 +1,339 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::net::SocketAddr;
+
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::bencode::{bencode_bytes, bencode_dict, Bencode};
+
+/* BitTorrent HTTP tracker (BEP 3, BEP 23 and BEP 48), on every HTTP
+ * persona: GET requests to announce_path get the interval and the
+ * peers (packed, IPv4 ones in peers and IPv6 ones in peers6), requests
+ * to scrape_path the (fake) statistics of the info hashes requested.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackerConfig {
+    pub enabled: bool,
+    pub announce_path: String,
+    pub scrape_path: String,
+    pub interval: i64,
+    pub min_interval: i64,
+    /* "address:port" */
+    pub peers: Vec<String>,
+    pub complete: i64,
+    pub incomplete: i64,
+    pub downloaded: i64,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        TrackerConfig {
+            enabled: true,
+            announce_path: "/announce".to_string(),
+            scrape_path: "/scrape".to_string(),
+            interval: 1800,
+            min_interval: 900,
+            peers: Vec::new(),
+            complete: 0,
+            incomplete: 0,
+            downloaded: 0,
+        }
+    }
+}
+
+impl TrackerConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if let Some(p) = self.peers.iter().find(|p| p.parse::<SocketAddr>().is_err()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("tracker: invalid peer (not address:port): {}", p),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* query string values are binary (e.g., info_hash): %XX sequences
+ * must be valid, and + stands for a space */
+pub fn tracker_percent_decode(value: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut i = 0;
+    while i < value.len() {
+        match value[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(value.get(i + 1..i + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            c => {
+                decoded.push(c);
+                i += 1;
+            }
+        }
+    }
+    Some(decoded)
+}
+
+/* BEP 23 (IPv4) and BEP 7 (IPv6): address and port of each peer */
+pub fn tracker_compact_peers(peers: &[SocketAddr]) -> (Vec<u8>, Vec<u8>) {
+    let mut peers4 = Vec::new();
+    let mut peers6 = Vec::new();
+    for peer in peers {
+        match peer {
+            SocketAddr::V4(p) => {
+                peers4.extend_from_slice(&p.ip().octets());
+                peers4.extend_from_slice(&p.port().to_be_bytes());
+            }
+            SocketAddr::V6(p) => {
+                peers6.extend_from_slice(&p.ip().octets());
+                peers6.extend_from_slice(&p.port().to_be_bytes());
+            }
+        }
+    }
+    (peers4, peers6)
+}
+
+/* decoded query parameters (names may repeat, e.g., info_hash for a
+ * scrape) */
+pub(crate) fn tracker_query(uri: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let query = match uri.iter().position(|c| *c == b'?') {
+        Some(i) => &uri[i + 1..],
+        None => return Vec::new(),
+    };
+    let end = query.iter().position(|c| *c == b'#').unwrap_or(query.len());
+    query[..end]
+        .split(|c| *c == b'&')
+        .filter(|p| !p.is_empty())
+        .filter_map(|p| {
+            let (k, v) = match p.iter().position(|c| *c == b'=') {
+                Some(i) => (&p[..i], &p[i + 1..]),
+                None => (p, &b""[..]),
+            };
+            Some((tracker_percent_decode(k)?, tracker_percent_decode(v)?))
+        })
+        .collect()
+}
+
+fn tracker_failure(reason: &str) -> Vec<u8> {
+    bencode_dict(vec![(b"failure reason", bencode_bytes(reason.as_bytes()))]).encode()
+}
+
+/* answer (status, headers, content) to requests to the announce or
+ * scrape paths of the tracker - None for other paths */
+pub fn tracker_page(
+    config: &TrackerConfig,
+    path: &str,
+    uri: &[u8],
+    client_info: &ClientInfo,
+) -> Option<(String, Vec<String>, Vec<u8>)> {
+    let scrape = if !config.enabled {
+        return None;
+    } else if path == config.announce_path {
+        false
+    } else if path == config.scrape_path {
+        true
+    } else {
+        return None;
+    };
+    let query = tracker_query(uri);
+    let param = |name: &[u8]| query.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+    let text = |name: &[u8]| param(name).map(|v| String::from_utf8_lossy(v).to_string());
+    let number = |name: &[u8]| text(name).and_then(|v| v.parse::<i64>().ok());
+    let info_hashes: Vec<&Vec<u8>> = query
+        .iter()
+        .filter(|(k, _)| k == b"info_hash")
+        .map(|(_, v)| v)
+        .collect();
+    let mut event = Event::new("tracker_request", client_info);
+    event.set("type", if scrape { "scrape" } else { "announce" });
+    event.set(
+        "info_hash",
+        info_hashes
+            .iter()
+            .map(|h| encode(h, Encoding::Hex))
+            .collect::<Vec<String>>(),
+    );
+    let stats = || {
+        vec![
+            (&b"complete"[..], Bencode::Integer(config.complete)),
+            (b"downloaded", Bencode::Integer(config.downloaded)),
+            (b"incomplete", Bencode::Integer(config.incomplete)),
+        ]
+    };
+    let content = if scrape {
+        let files = info_hashes
+            .iter()
+            .filter(|h| h.len() == 20)
+            .map(|h| ((*h).clone(), bencode_dict(stats())))
+            .collect();
+        bencode_dict(vec![(b"files", Bencode::Dict(files))]).encode()
+    } else {
+        if let Some(peer_id) = param(b"peer_id") {
+            event.set("peer_id", encode(peer_id, Encoding::Hex));
+        }
+        for name in [
+            "port",
+            "uploaded",
+            "downloaded",
+            "left",
+            "numwant",
+            "compact",
+        ]
+        .iter()
+        {
+            if let Some(n) = number(name.as_bytes()) {
+                event.set(name, n);
+            }
+        }
+        for name in ["event", "ip", "key"].iter() {
+            if let Some(v) = text(name.as_bytes()) {
+                event.set(name, v);
+            }
+        }
+        match info_hashes.first() {
+            Some(h) if h.len() == 20 && param(b"peer_id").is_some_and(|p| p.len() == 20) => {
+                let peers: Vec<SocketAddr> =
+                    config.peers.iter().filter_map(|p| p.parse().ok()).collect();
+                let (peers4, peers6) = tracker_compact_peers(&peers);
+                let mut items = vec![
+                    (&b"interval"[..], Bencode::Integer(config.interval)),
+                    (b"min interval", Bencode::Integer(config.min_interval)),
+                    (b"peers", bencode_bytes(&peers4)),
+                ];
+                if !peers6.is_empty() {
+                    items.push((b"peers6", bencode_bytes(&peers6)));
+                }
+                items.extend(stats());
+                bencode_dict(items).encode()
+            }
+            Some(_) => tracker_failure("invalid info_hash or peer_id"),
+            None => tracker_failure("missing info_hash"),
+        }
+    };
+    event.log();
+    Some((
+        "200 OK".to_string(),
+        vec!["Content-Type: text/plain".to_string()],
+        content,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::bencode::bencode_decode;
+
+    const INFO_HASH: &[u8; 20] =
+        b"\x12\x34\x56\x78\x9a\xbc\xde\xf1\x23\x45\x67\x89\xab\xcd\xef\x12\x34\x56\x78\x9a";
+
+    #[test]
+    fn test_tracker_percent_decode() {
+        /* as sent by clients: unreserved characters are not encoded */
+        let encoded = b"%124Vx%9A%BC%DE%F1%23Eg%89%AB%CD%EF%124Vx%9a";
+        assert!(tracker_percent_decode(encoded).as_deref() == Some(&INFO_HASH[..]));
+        assert!(tracker_percent_decode(b"a+b%20c").as_deref() == Some(&b"a b c"[..]));
+        assert!(tracker_percent_decode(b"%00%ff").as_deref() == Some(&b"\x00\xff"[..]));
+        for bad in [&b"%"[..], b"%1", b"%zz", b"abc%g0"].iter() {
+            assert!(tracker_percent_decode(bad).is_none());
+        }
+        let query = tracker_query(
+            b"/scrape?info_hash=%124Vx%9A%BC%DE%F1%23Eg%89%AB%CD%EF%124Vx%9A&info_hash=abc&x#frag",
+        );
+        assert!(query.len() == 3);
+        assert!(query[0] == (b"info_hash".to_vec(), INFO_HASH.to_vec()));
+        assert!(query[2] == (b"x".to_vec(), Vec::new()));
+    }
+
+    #[test]
+    fn test_tracker_compact_peers() {
+        let peers: Vec<SocketAddr> = ["10.0.0.1:6881", "[2001:db8::1]:51413", "192.0.2.7:443"]
+            .iter()
+            .map(|p| p.parse().unwrap())
+            .collect();
+        let (peers4, peers6) = tracker_compact_peers(&peers);
+        assert!(peers4 == b"\x0a\x00\x00\x01\x1a\xe1\xc0\x00\x02\x07\x01\xbb");
+        assert!(peers6.len() == 18);
+        assert!(peers6[..2] == [0x20, 0x01] && peers6[15] == 1 && peers6[16..] == [0xc8, 0xd5]);
+    }
+
+    #[test]
+    fn test_tracker_page() {
+        let mut config = TrackerConfig {
+            peers: vec!["10.0.0.1:6881".to_string()],
+            ..Default::default()
+        };
+        assert!(config.check().is_ok());
+        let client_info = ClientInfo::new();
+        let uri = b"/announce?info_hash=%124Vx%9A%BC%DE%F1%23Eg%89%AB%CD%EF%124Vx%9A\
+                    &peer_id=-qB4520-abcdefghijkl&port=6881&uploaded=0&downloaded=0\
+                    &left=1048576&event=started&compact=1";
+        let (status, headers, content) =
+            tracker_page(&config, "/announce", uri, &client_info).unwrap();
+        assert!(status == "200 OK" && headers == ["Content-Type: text/plain"]);
+        let answer = bencode_decode(&content).expect("invalid answer");
+        assert!(answer.get(b"interval").and_then(|i| i.integer()) == Some(1800));
+        assert!(
+            answer.get(b"peers").and_then(|p| p.bytes()) == Some(&b"\x0a\x00\x00\x01\x1a\xe1"[..])
+        );
+        assert!(answer.get(b"peers6").is_none());
+        /* failures */
+        let (_, _, content) = tracker_page(
+            &config,
+            "/announce",
+            b"/announce?info_hash=abc",
+            &client_info,
+        )
+        .unwrap();
+        assert!(content == b"d14:failure reason28:invalid info_hash or peer_ide");
+        let (_, _, content) =
+            tracker_page(&config, "/announce", b"/announce", &client_info).unwrap();
+        assert!(content == b"d14:failure reason17:missing info_hashe");
+        /* scrape */
+        let (_, _, content) = tracker_page(
+            &config,
+            "/scrape",
+            b"/scrape?info_hash=%124Vx%9A%BC%DE%F1%23Eg%89%AB%CD%EF%124Vx%9A",
+            &client_info,
+        )
+        .unwrap();
+        assert!(
+            content
+                == [
+                    &b"d5:filesd20:"[..],
+                    INFO_HASH,
+                    b"d8:completei0e10:downloadedi0e10:incompletei0eeee"
+                ]
+                .concat()
+        );
+        let (_, _, content) = tracker_page(&config, "/scrape", b"/scrape", &client_info).unwrap();
+        assert!(content == b"d5:filesdee");
+        /* other paths, disabled */
+        assert!(tracker_page(&config, "/", b"/", &client_info).is_none());
+        config.enabled = false;
+        assert!(tracker_page(&config, "/announce", uri, &client_info).is_none());
+        config.peers.push("10.0.0.1".to_string());
+        assert!(config.check().is_err());
+    }
+}
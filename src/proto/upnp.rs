@@ -0,0 +1,697 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::net::IpAddr;
+
+use log::*;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+
+const UPNP_WANIPCONNECTION: &str = "urn:schemas-upnp-org:service:WANIPConnection:1";
+
+/* UPnP Internet Gateway Device (IGD 1.0), on an HTTP persona when
+ * enabled: GET requests to description_path get the description of a
+ * gateway (whose WANIPConnection service has its control and event
+ * URLs on the persona), requests to scpd_path the description of the
+ * service, and POST requests to control_path (SOAP actions) an answer:
+ * port mappings are accepted (AddPortMapping, DeletePortMapping) unless
+ * accept_mappings is unset, and GetExternalIPAddress gets external_ip.
+ * Every action is recorded with its arguments.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpnpConfig {
+    pub enabled: bool,
+    pub description_path: String,
+    pub scpd_path: String,
+    pub control_path: String,
+    pub event_path: String,
+    pub friendly_name: String,
+    pub manufacturer: String,
+    pub model_name: String,
+    pub model_number: String,
+    pub serial_number: String,
+    pub uuid: String,
+    pub external_ip: String,
+    pub accept_mappings: bool,
+}
+
+impl Default for UpnpConfig {
+    fn default() -> Self {
+        UpnpConfig {
+            enabled: false,
+            description_path: "/rootDesc.xml".to_string(),
+            scpd_path: "/WANIPCn.xml".to_string(),
+            control_path: "/ctl/IPConn".to_string(),
+            event_path: "/evt/IPConn".to_string(),
+            friendly_name: "Wireless Router".to_string(),
+            manufacturer: "Linksys".to_string(),
+            model_name: "WRT54GL".to_string(),
+            model_number: "4.30.18".to_string(),
+            serial_number: "CL7C1H3A5089".to_string(),
+            uuid: "3ddcd1d3-2380-45f5-b069-2c4d54a0e4b7".to_string(),
+            external_ip: "203.0.113.1".to_string(),
+            accept_mappings: true,
+        }
+    }
+}
+
+impl UpnpConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        let paths = [
+            &self.description_path,
+            &self.scpd_path,
+            &self.control_path,
+            &self.event_path,
+        ];
+        if let Some(p) = paths.iter().find(|p| !p.starts_with('/')) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("upnp: invalid path: {:?}", p),
+            ));
+        }
+        if (1..paths.len()).any(|i| paths[..i].contains(&paths[i])) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "upnp: paths must be different",
+            ));
+        }
+        if self.external_ip.parse::<IpAddr>().is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("upnp: invalid external_ip: {:?}", self.external_ip),
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/* UDN of an embedded device: the last digit of the root one changed */
+fn upnp_udn(config: &UpnpConfig, n: u32) -> String {
+    let mut uuid = config.uuid.clone();
+    if let Some(d) = uuid.pop().and_then(|c| c.to_digit(16)) {
+        uuid.push(std::char::from_digit((d + n) % 16, 16).unwrap());
+    }
+    format!("uuid:{}", uuid)
+}
+
+/* UPnP Device Architecture 1.0 section 2.1 */
+fn upnp_description(config: &UpnpConfig, host: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\"?>
+<root xmlns=\"urn:schemas-upnp-org:device-1-0\">
+<specVersion><major>1</major><minor>0</minor></specVersion>
+<URLBase>http://{host}/</URLBase>
+<device>
+<deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+<friendlyName>{name}</friendlyName>
+<manufacturer>{manufacturer}</manufacturer>
+<modelDescription>{name}</modelDescription>
+<modelName>{model_name}</modelName>
+<modelNumber>{model_number}</modelNumber>
+<serialNumber>{serial}</serialNumber>
+<UDN>uuid:{uuid}</UDN>
+<deviceList>
+<device>
+<deviceType>urn:schemas-upnp-org:device:WANDevice:1</deviceType>
+<friendlyName>WANDevice</friendlyName>
+<manufacturer>{manufacturer}</manufacturer>
+<modelName>{model_name}</modelName>
+<UDN>{udn1}</UDN>
+<deviceList>
+<device>
+<deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
+<friendlyName>WANConnectionDevice</friendlyName>
+<manufacturer>{manufacturer}</manufacturer>
+<modelName>{model_name}</modelName>
+<UDN>{udn2}</UDN>
+<serviceList>
+<service>
+<serviceType>{service}</serviceType>
+<serviceId>urn:upnp-org:serviceId:WANIPConn1</serviceId>
+<SCPDURL>{scpd}</SCPDURL>
+<controlURL>{control}</controlURL>
+<eventSubURL>{event}</eventSubURL>
+</service>
+</serviceList>
+</device>
+</deviceList>
+</device>
+</deviceList>
+<presentationURL>http://{host}/</presentationURL>
+</device>
+</root>
+",
+        host = xml_escape(host),
+        name = xml_escape(&config.friendly_name),
+        manufacturer = xml_escape(&config.manufacturer),
+        model_name = xml_escape(&config.model_name),
+        model_number = xml_escape(&config.model_number),
+        serial = xml_escape(&config.serial_number),
+        uuid = xml_escape(&config.uuid),
+        udn1 = xml_escape(&upnp_udn(config, 1)),
+        udn2 = xml_escape(&upnp_udn(config, 2)),
+        service = UPNP_WANIPCONNECTION,
+        scpd = xml_escape(&config.scpd_path),
+        control = xml_escape(&config.control_path),
+        event = xml_escape(&config.event_path),
+    )
+}
+
+/* argument of an action: name, direction, related state variable */
+type UpnpArgument = (&'static str, &'static str, &'static str);
+
+/* actions of the WANIPConnection service announced in its SCPD, with
+ * their arguments */
+const UPNP_ACTIONS: [(&str, &[UpnpArgument]); 6] = [
+    (
+        "AddPortMapping",
+        &[
+            ("NewRemoteHost", "in", "RemoteHost"),
+            ("NewExternalPort", "in", "ExternalPort"),
+            ("NewProtocol", "in", "PortMappingProtocol"),
+            ("NewInternalPort", "in", "InternalPort"),
+            ("NewInternalClient", "in", "InternalClient"),
+            ("NewEnabled", "in", "PortMappingEnabled"),
+            ("NewPortMappingDescription", "in", "PortMappingDescription"),
+            ("NewLeaseDuration", "in", "PortMappingLeaseDuration"),
+        ],
+    ),
+    (
+        "DeletePortMapping",
+        &[
+            ("NewRemoteHost", "in", "RemoteHost"),
+            ("NewExternalPort", "in", "ExternalPort"),
+            ("NewProtocol", "in", "PortMappingProtocol"),
+        ],
+    ),
+    (
+        "GetExternalIPAddress",
+        &[("NewExternalIPAddress", "out", "ExternalIPAddress")],
+    ),
+    (
+        "GetGenericPortMappingEntry",
+        &[
+            ("NewPortMappingIndex", "in", "PortMappingNumberOfEntries"),
+            ("NewRemoteHost", "out", "RemoteHost"),
+            ("NewExternalPort", "out", "ExternalPort"),
+            ("NewProtocol", "out", "PortMappingProtocol"),
+            ("NewInternalPort", "out", "InternalPort"),
+            ("NewInternalClient", "out", "InternalClient"),
+            ("NewEnabled", "out", "PortMappingEnabled"),
+            ("NewPortMappingDescription", "out", "PortMappingDescription"),
+            ("NewLeaseDuration", "out", "PortMappingLeaseDuration"),
+        ],
+    ),
+    (
+        "GetSpecificPortMappingEntry",
+        &[
+            ("NewRemoteHost", "in", "RemoteHost"),
+            ("NewExternalPort", "in", "ExternalPort"),
+            ("NewProtocol", "in", "PortMappingProtocol"),
+            ("NewInternalPort", "out", "InternalPort"),
+            ("NewInternalClient", "out", "InternalClient"),
+            ("NewEnabled", "out", "PortMappingEnabled"),
+            ("NewPortMappingDescription", "out", "PortMappingDescription"),
+            ("NewLeaseDuration", "out", "PortMappingLeaseDuration"),
+        ],
+    ),
+    (
+        "GetStatusInfo",
+        &[
+            ("NewConnectionStatus", "out", "ConnectionStatus"),
+            ("NewLastConnectionError", "out", "LastConnectionError"),
+            ("NewUptime", "out", "Uptime"),
+        ],
+    ),
+];
+
+/* state variables of the WANIPConnection service (name, type) */
+const UPNP_STATE_VARIABLES: [(&str, &str); 13] = [
+    ("ConnectionStatus", "string"),
+    ("LastConnectionError", "string"),
+    ("Uptime", "ui4"),
+    ("ExternalIPAddress", "string"),
+    ("RemoteHost", "string"),
+    ("ExternalPort", "ui2"),
+    ("InternalPort", "ui2"),
+    ("PortMappingProtocol", "string"),
+    ("InternalClient", "string"),
+    ("PortMappingEnabled", "boolean"),
+    ("PortMappingDescription", "string"),
+    ("PortMappingLeaseDuration", "ui4"),
+    ("PortMappingNumberOfEntries", "ui2"),
+];
+
+/* UPnP Device Architecture 1.0 section 2.3 */
+fn upnp_scpd() -> String {
+    let mut scpd = "<?xml version=\"1.0\"?>
+<scpd xmlns=\"urn:schemas-upnp-org:service-1-0\">
+<specVersion><major>1</major><minor>0</minor></specVersion>
+<actionList>
+"
+    .to_string();
+    for (name, args) in UPNP_ACTIONS.iter() {
+        scpd.push_str(&format!("<action><name>{}</name><argumentList>\n", name));
+        for (arg, direction, variable) in args.iter() {
+            scpd.push_str(&format!(
+                "<argument><name>{}</name><direction>{}</direction>\
+<relatedStateVariable>{}</relatedStateVariable></argument>\n",
+                arg, direction, variable
+            ));
+        }
+        scpd.push_str("</argumentList></action>\n");
+    }
+    scpd.push_str("</actionList>\n<serviceStateTable>\n");
+    for (name, data_type) in UPNP_STATE_VARIABLES.iter() {
+        scpd.push_str(&format!(
+            "<stateVariable sendEvents=\"no\"><name>{}</name><dataType>{}</dataType></stateVariable>\n",
+            name, data_type
+        ));
+    }
+    scpd.push_str("</serviceStateTable>\n</scpd>\n");
+    scpd
+}
+
+/* tag of an XML document: its position, the offset after it, its
+ * local name (without namespace prefix), whether it is an end tag, and
+ * whether it is an empty-element tag */
+pub struct XmlTag {
+    pub start: usize,
+    pub end: usize,
+    pub name: String,
+    pub closing: bool,
+    pub empty: bool,
+}
+
+/* next tag from offset - declarations, processing instructions and
+ * comments are skipped */
+pub fn xml_next_tag(doc: &str, mut offset: usize) -> Option<XmlTag> {
+    loop {
+        let start = offset + doc.get(offset..)?.find('<')?;
+        let end = start + doc[start..].find('>')? + 1;
+        let inner = &doc[start + 1..end - 1];
+        if inner.starts_with('?') || inner.starts_with('!') {
+            offset = if inner.starts_with("!--") {
+                start + doc[start..].find("-->")? + 3
+            } else {
+                end
+            };
+            continue;
+        }
+        let closing = inner.starts_with('/');
+        let empty = inner.ends_with('/');
+        let qname = inner
+            .trim_start_matches('/')
+            .trim_end_matches('/')
+            .split(|c: char| c.is_whitespace())
+            .next()
+            .unwrap_or("");
+        let name = qname.rsplit(':').next().unwrap_or("").to_string();
+        return Some(XmlTag {
+            start,
+            end,
+            name,
+            closing,
+            empty,
+        });
+    }
+}
+
+/* name and arguments of the action in the Body of a SOAP envelope -
+ * namespace prefixes (of any name) are ignored */
+fn upnp_soap_action(body: &str) -> Option<(String, Vec<(String, String)>)> {
+    let mut tag = xml_next_tag(body, 0)?;
+    while tag.closing || tag.name != "Body" {
+        tag = xml_next_tag(body, tag.end)?;
+    }
+    let action = xml_next_tag(body, tag.end)?;
+    if action.closing {
+        return None;
+    }
+    let mut args = Vec::new();
+    if action.empty {
+        return Some((action.name, args));
+    }
+    let mut offset = action.end;
+    loop {
+        let arg = xml_next_tag(body, offset)?;
+        if arg.closing {
+            /* end of the action */
+            break;
+        }
+        if arg.empty {
+            args.push((arg.name, String::new()));
+            offset = arg.end;
+            continue;
+        }
+        let close = xml_next_tag(body, arg.end)?;
+        if !close.closing || close.name != arg.name {
+            /* nested elements are not expected */
+            return None;
+        }
+        args.push((arg.name, xml_unescape(body[arg.end..close.start].trim())));
+        offset = close.end;
+    }
+    Some((action.name, args))
+}
+
+/* service type and action name of a SOAPAction header */
+fn upnp_soap_action_header(value: &[u8]) -> Option<(String, String)> {
+    let value = String::from_utf8_lossy(value);
+    let (service, action) = value.trim().trim_matches('"').split_once('#')?;
+    Some((service.to_string(), action.to_string()))
+}
+
+fn upnp_soap_envelope(content: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\"?>
+<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body>{}</s:Body></s:Envelope>
+",
+        content
+    )
+}
+
+fn upnp_response(service: &str, action: &str, args: &[(&str, String)]) -> String {
+    let mut content = format!("<u:{}Response xmlns:u=\"{}\">", action, xml_escape(service));
+    for (name, value) in args.iter() {
+        content.push_str(&format!("<{0}>{1}</{0}>", name, xml_escape(value)));
+    }
+    content.push_str(&format!("</u:{}Response>", action));
+    upnp_soap_envelope(&content)
+}
+
+/* UPnP Device Architecture 1.0 section 3.2.2 */
+fn upnp_error(code: u16, description: &str) -> String {
+    upnp_soap_envelope(&format!(
+        "<s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring>\
+<detail><UPnPError xmlns=\"urn:schemas-upnp-org:control-1-0\">\
+<errorCode>{}</errorCode><errorDescription>{}</errorDescription>\
+</UPnPError></detail></s:Fault>",
+        code, description
+    ))
+}
+
+/* answer to a SOAP action: arguments of the response, or error code and
+ * description */
+fn upnp_control(
+    config: &UpnpConfig,
+    action: &str,
+) -> Result<Vec<(&'static str, String)>, (u16, &'static str)> {
+    match action {
+        "AddPortMapping" | "DeletePortMapping" if config.accept_mappings => Ok(Vec::new()),
+        "AddPortMapping" | "DeletePortMapping" => Err((606, "Action not authorized")),
+        "GetExternalIPAddress" => Ok(vec![("NewExternalIPAddress", config.external_ip.clone())]),
+        "GetStatusInfo" => Ok(vec![
+            ("NewConnectionStatus", "Connected".to_string()),
+            ("NewLastConnectionError", "ERROR_NONE".to_string()),
+            ("NewUptime", "86400".to_string()),
+        ]),
+        "GetGenericPortMappingEntry" => Err((713, "SpecifiedArrayIndexInvalid")),
+        "GetSpecificPortMappingEntry" => Err((714, "NoSuchEntryInArray")),
+        _ => Err((401, "Invalid Action")),
+    }
+}
+
+/* answer (status, headers, content) to requests to the description,
+ * SCPD and control paths of the gateway - None for other paths (host
+ * is the Host header of the request, used in URLBase) */
+pub fn upnp_page(
+    config: &UpnpConfig,
+    verb: &str,
+    path: &str,
+    host: &str,
+    body: &[u8],
+    soap_action: Option<&[u8]>,
+    client_info: &ClientInfo,
+) -> Option<(String, Vec<String>, String)> {
+    if !config.enabled {
+        return None;
+    }
+    let xml = || vec!["Content-Type: text/xml; charset=\"utf-8\"".to_string()];
+    let mut event = Event::new("upnp_request", client_info);
+    match verb {
+        "GET" | "HEAD" if path == config.description_path => {
+            event.set("type", "description");
+            event.log();
+            let host = match (host.is_empty(), client_info.ip.dst, client_info.port.dst) {
+                (true, Some(IpAddr::V4(ip)), Some(port)) => format!("{}:{}", ip, port),
+                (true, Some(IpAddr::V6(ip)), Some(port)) => format!("[{}]:{}", ip, port),
+                _ => host.to_string(),
+            };
+            Some(("200 OK".to_string(), xml(), upnp_description(config, &host)))
+        }
+        "GET" | "HEAD" if path == config.scpd_path => {
+            event.set("type", "scpd");
+            event.log();
+            Some(("200 OK".to_string(), xml(), upnp_scpd()))
+        }
+        "POST" if path == config.control_path => {
+            event.set("type", "control");
+            let header = soap_action.and_then(upnp_soap_action_header);
+            if let Some(value) = soap_action {
+                event.set("soap_action", String::from_utf8_lossy(value));
+            }
+            let request = upnp_soap_action(&String::from_utf8_lossy(body));
+            /* the action of the body, that of the header otherwise */
+            let action = request
+                .as_ref()
+                .map(|(a, _)| a.clone())
+                .or_else(|| header.as_ref().map(|(_, a)| a.clone()));
+            let service = header
+                .as_ref()
+                .map_or(UPNP_WANIPCONNECTION, |(s, _)| &s[..]);
+            if let Some(a) = &action {
+                event.set("action", a.clone());
+            }
+            if let Some((_, args)) = &request {
+                let mut arguments = Map::new();
+                for (name, value) in args.iter() {
+                    arguments.insert(name.clone(), Value::from(value.clone()));
+                }
+                event.set("arguments", arguments);
+                if action.as_deref() == Some("AddPortMapping") {
+                    let arg = |n: &str| {
+                        args.iter()
+                            .find(|(k, _)| k == n)
+                            .map_or("", |(_, v)| &v[..])
+                    };
+                    warn!(
+                        "UPnP AddPortMapping: {} {} -> {}:{} ({})",
+                        arg("NewProtocol"),
+                        arg("NewExternalPort"),
+                        arg("NewInternalClient"),
+                        arg("NewInternalPort"),
+                        arg("NewPortMappingDescription")
+                    );
+                }
+            }
+            let answer = match (&action, &request) {
+                (Some(a), Some(_)) => upnp_control(config, a),
+                _ => Err((402, "Invalid Args")),
+            };
+            let mut headers = xml();
+            headers.push("EXT:".to_string());
+            let (status, content) = match answer {
+                Ok(args) => (
+                    "200 OK",
+                    upnp_response(service, action.as_deref().unwrap_or(""), &args),
+                ),
+                Err((code, description)) => {
+                    event.set("error", code);
+                    ("500 Internal Server Error", upnp_error(code, description))
+                }
+            };
+            event.log();
+            Some((status.to_string(), headers, content))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* upnpc -a 192.168.1.10 80 8080 TCP (miniupnpc 2.2.4) */
+    const UPNPC_ADD: &str = "<?xml version=\"1.0\"?>\r\n<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body><u:AddPortMapping xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\"><NewRemoteHost></NewRemoteHost><NewExternalPort>8080</NewExternalPort><NewProtocol>TCP</NewProtocol><NewInternalPort>80</NewInternalPort><NewInternalClient>192.168.1.10</NewInternalClient><NewEnabled>1</NewEnabled><NewPortMappingDescription>libminiupnpc</NewPortMappingDescription><NewLeaseDuration>0</NewLeaseDuration></u:AddPortMapping></s:Body></s:Envelope>\r\n";
+
+    /* miranda (1.3) "host send 0 WANConnectionDevice WANIPConnection
+     * AddPortMapping" */
+    const MIRANDA_ADD: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<SOAP-ENV:Envelope xmlns:SOAP-ENV=\"http://schemas.xmlsoap.org/soap/envelope/\" SOAP-ENV:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">
+<SOAP-ENV:Body>
+\t<m:AddPortMapping xmlns:m=\"urn:schemas-upnp-org:service:WANIPConnection:1\">
+<NewPortMappingDescription>miranda &amp; co</NewPortMappingDescription>
+<NewLeaseDuration>0</NewLeaseDuration>
+<NewInternalClient>10.0.0.66</NewInternalClient>
+<NewEnabled>1</NewEnabled>
+<NewExternalPort>445</NewExternalPort>
+<NewRemoteHost/>
+<NewProtocol>TCP</NewProtocol>
+<NewInternalPort>445</NewInternalPort>
+\t</m:AddPortMapping>
+</SOAP-ENV:Body>
+</SOAP-ENV:Envelope>
+";
+
+    fn enabled() -> UpnpConfig {
+        UpnpConfig {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_upnp_soap_action() {
+        let (action, args) = upnp_soap_action(UPNPC_ADD).unwrap();
+        assert!(action == "AddPortMapping" && args.len() == 8);
+        assert!(args[0] == ("NewRemoteHost".to_string(), String::new()));
+        assert!(args[4] == ("NewInternalClient".to_string(), "192.168.1.10".to_string()));
+        let (action, args) = upnp_soap_action(MIRANDA_ADD).unwrap();
+        assert!(action == "AddPortMapping" && args.len() == 8);
+        assert!(args[0].1 == "miranda & co");
+        assert!(args[5] == ("NewRemoteHost".to_string(), String::new()));
+        /* default namespace, comment, action without arguments */
+        let (action, args) = upnp_soap_action(
+            "<Envelope xmlns=\"http://schemas.xmlsoap.org/soap/envelope/\"><!-- <Body> --><Body><GetExternalIPAddress xmlns=\"urn:schemas-upnp-org:service:WANIPConnection:1\"/></Body></Envelope>",
+        )
+        .unwrap();
+        assert!(action == "GetExternalIPAddress" && args.is_empty());
+        /* no Body, truncated, nested elements */
+        assert!(upnp_soap_action("<s:Envelope></s:Envelope>").is_none());
+        assert!(upnp_soap_action(&UPNPC_ADD[..200]).is_none());
+        assert!(upnp_soap_action("<s:Body><u:A><B><C>1</C></B></u:A></s:Body>").is_none());
+        assert!(
+            upnp_soap_action_header(
+                b" \"urn:schemas-upnp-org:service:WANIPConnection:1#AddPortMapping\""
+            ) == Some((
+                UPNP_WANIPCONNECTION.to_string(),
+                "AddPortMapping".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_upnp_page() {
+        let mut config = enabled();
+        config.check().unwrap();
+        let client_info = ClientInfo::new();
+        let (status, headers, content) = upnp_page(
+            &config,
+            "GET",
+            "/rootDesc.xml",
+            "192.0.2.1:5000",
+            b"",
+            None,
+            &client_info,
+        )
+        .unwrap();
+        assert!(status == "200 OK" && headers[0].starts_with("Content-Type: text/xml"));
+        assert!(content.contains("<URLBase>http://192.0.2.1:5000/</URLBase>"));
+        assert!(content.contains("<controlURL>/ctl/IPConn</controlURL>"));
+        assert!(content.contains("<eventSubURL>/evt/IPConn</eventSubURL>"));
+        assert!(content.contains("<UDN>uuid:3ddcd1d3-2380-45f5-b069-2c4d54a0e4b9</UDN>"));
+        let (_, _, content) =
+            upnp_page(&config, "GET", "/WANIPCn.xml", "", b"", None, &client_info).unwrap();
+        assert!(content.contains("<name>AddPortMapping</name>"));
+        /* AddPortMapping, accepted or not */
+        let soap_action = b"\"urn:schemas-upnp-org:service:WANIPConnection:1#AddPortMapping\"";
+        for (accept, expected) in [(true, "200 OK"), (false, "500 Internal Server Error")] {
+            config.accept_mappings = accept;
+            let (status, headers, content) = upnp_page(
+                &config,
+                "POST",
+                "/ctl/IPConn",
+                "",
+                MIRANDA_ADD.as_bytes(),
+                Some(soap_action),
+                &client_info,
+            )
+            .unwrap();
+            assert!(status == expected && headers.contains(&"EXT:".to_string()));
+            if accept {
+                assert!(content.contains(
+                    "<u:AddPortMappingResponse xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\"></u:AddPortMappingResponse>"
+                ));
+            } else {
+                assert!(content.contains("<errorCode>606</errorCode>"));
+            }
+        }
+        let (status, _, content) = upnp_page(
+            &config,
+            "POST",
+            "/ctl/IPConn",
+            "",
+            b"<s:Envelope><s:Body><u:GetExternalIPAddress/></s:Body></s:Envelope>",
+            None,
+            &client_info,
+        )
+        .unwrap();
+        assert!(status == "200 OK");
+        assert!(content.contains("<NewExternalIPAddress>203.0.113.1</NewExternalIPAddress>"));
+        /* invalid body, unknown action */
+        for (body, code) in [
+            (&b"garbage"[..], 402),
+            (b"<s:Body><u:Reboot/></s:Body>", 401),
+        ] {
+            let (_, _, content) =
+                upnp_page(&config, "POST", "/ctl/IPConn", "", body, None, &client_info).unwrap();
+            assert!(content.contains(&format!("<errorCode>{}</errorCode>", code)));
+        }
+        /* other paths, disabled */
+        assert!(upnp_page(&config, "GET", "/", "", b"", None, &client_info).is_none());
+        assert!(upnp_page(&config, "GET", "/ctl/IPConn", "", b"", None, &client_info).is_none());
+        assert!(upnp_page(
+            &UpnpConfig::default(),
+            "GET",
+            "/rootDesc.xml",
+            "",
+            b"",
+            None,
+            &client_info
+        )
+        .is_none());
+        /* configuration */
+        config.control_path = config.scpd_path.clone();
+        assert!(config.check().is_err());
+        let config = UpnpConfig {
+            external_ip: "wan".to_string(),
+            ..Default::default()
+        };
+        assert!(config.check().is_err());
+    }
+}
@@ -0,0 +1,423 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* RFC 6143 section 7.2.1 */
+const VNC_SECURITY_NONE: u8 = 1;
+const VNC_SECURITY_VNC: u8 = 2;
+
+const VNC_RESULT_OK: u32 = 0;
+const VNC_RESULT_FAILED: u32 = 1;
+
+/* VNC server (RFB): the version banner is sent when the TCP handshake
+ * is completed; with VNC Authentication, the response to the challenge
+ * is logged and the authentication fails, with None the client gets the
+ * ServerInit message (and nothing more).
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VncConfig {
+    pub ports: Vec<u16>,
+    /* without the final LF */
+    pub version: String,
+    /* security types offered (1: None, 2: VNC Authentication) */
+    pub security: Vec<u8>,
+    pub failure_reason: String,
+    /* ServerInit */
+    pub name: String,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Default for VncConfig {
+    fn default() -> Self {
+        VncConfig {
+            ports: vec![5900, 5901],
+            version: "RFB 003.008".to_string(),
+            security: vec![VNC_SECURITY_VNC],
+            failure_reason: "Authentication failed".to_string(),
+            name: "QEMU".to_string(),
+            width: 1024,
+            height: 768,
+        }
+    }
+}
+
+fn vnc_invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("vnc: {}", msg))
+}
+
+impl VncConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if vnc_parse_version(format!("{}\n", self.version).as_bytes()).is_none() {
+            return Err(vnc_invalid(format!("invalid version: {:?}", self.version)));
+        }
+        if self.security.is_empty() {
+            return Err(vnc_invalid("no security type".to_string()));
+        }
+        for s in self.security.iter() {
+            if *s != VNC_SECURITY_NONE && *s != VNC_SECURITY_VNC {
+                return Err(vnc_invalid(format!("unsupported security type: {}", s)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+enum VncStep {
+    /* ProtocolVersion (12 bytes) */
+    #[default]
+    Version,
+    /* security type chosen (1 byte) */
+    Security,
+    /* response to the challenge (16 bytes) */
+    Response,
+    /* ClientInit (1 byte) */
+    ClientInit,
+    /* nothing more is answered */
+    Done,
+}
+
+/* state of a VNC flow: step of the handshake, data received for it */
+#[derive(Default)]
+pub struct VncState {
+    step: VncStep,
+    buffer: Vec<u8>,
+    /* minor version negotiated (3, 7 or 8) */
+    minor: u16,
+    challenge: [u8; 16],
+}
+
+/* "RFB 003.008\n" */
+fn vnc_parse_version(data: &[u8]) -> Option<(u16, u16)> {
+    if data.len() != 12 || !data.starts_with(b"RFB ") || data[7] != b'.' || data[11] != b'\n' {
+        return None;
+    }
+    let number = |d: &[u8]| -> Option<u16> {
+        if !d.iter().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        std::str::from_utf8(d).ok()?.parse().ok()
+    };
+    Some((number(&data[4..7])?, number(&data[8..11])?))
+}
+
+/* RFC 6143 section 7.1.1: versions other than 3.3, 3.7 and 3.8 are
+ * handled as 3.3 (or 3.8, when newer) */
+fn vnc_minor(server: (u16, u16), client: (u16, u16)) -> u16 {
+    let minor = std::cmp::min(server, client).1;
+    match minor {
+        0..=6 => 3,
+        7 => 7,
+        _ => 8,
+    }
+}
+
+fn vnc_failure(state: &VncState, config: &VncConfig) -> Vec<u8> {
+    let mut repl = VNC_RESULT_FAILED.to_be_bytes().to_vec();
+    if state.minor >= 8 {
+        repl.extend_from_slice(&(config.failure_reason.len() as u32).to_be_bytes());
+        repl.extend_from_slice(config.failure_reason.as_bytes());
+    }
+    repl
+}
+
+/* RFC 6143 section 7.3.2 */
+fn vnc_server_init(config: &VncConfig) -> Vec<u8> {
+    let mut repl = Vec::new();
+    repl.extend_from_slice(&config.width.to_be_bytes());
+    repl.extend_from_slice(&config.height.to_be_bytes());
+    /* 32 bits per pixel, depth 24, little-endian, true colour, 8 bits
+     * per component */
+    repl.extend_from_slice(&[32, 24, 0, 1, 0, 255, 0, 255, 0, 255, 16, 8, 0, 0, 0, 0]);
+    repl.extend_from_slice(&(config.name.len() as u32).to_be_bytes());
+    repl.extend_from_slice(config.name.as_bytes());
+    repl
+}
+
+/* answer to the security type chosen (or imposed, with 3.3) */
+fn vnc_security(
+    security: u8,
+    state: &mut VncState,
+    config: &VncConfig,
+    client_info: &mut ClientInfo,
+) -> Vec<u8> {
+    match security {
+        VNC_SECURITY_VNC => {
+            state.challenge = rand::random();
+            state.step = VncStep::Response;
+            state.challenge.to_vec()
+        }
+        VNC_SECURITY_NONE => {
+            state.step = VncStep::ClientInit;
+            if state.minor >= 8 {
+                VNC_RESULT_OK.to_be_bytes().to_vec()
+            } else {
+                Vec::new()
+            }
+        }
+        _ => {
+            info!("VNC security type not offered: {}", security);
+            state.step = VncStep::Done;
+            client_info.close = true;
+            if state.minor >= 8 {
+                vnc_failure(state, config)
+            } else {
+                Vec::new()
+            }
+        }
+    }
+}
+
+fn vnc_auth_event(state: &VncState, response: &[u8], client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("vnc_auth", client_info);
+    event.set("version", format!("3.{}", state.minor));
+    event.set("challenge", encode(&state.challenge, Encoding::Hex));
+    event.set("response", encode(response, Encoding::Hex));
+    event
+}
+
+/* ProtocolVersion, sent when the TCP handshake is completed */
+pub fn greeting(masscanned: &Masscanned, tcb: &mut TCPControlBlock) -> Option<Vec<u8>> {
+    tcb.proto_state = ProtoState::Vnc(VncState::default());
+    Some(format!("{}\n", masscanned.config.vnc.version).into_bytes())
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving VNC data");
+    let config = &masscanned.config.vnc;
+    /* VNC is only answered over TCP, after the greeting */
+    let state = match tcb {
+        Some(TCPControlBlock {
+            proto_state: ProtoState::Vnc(s),
+            ..
+        }) => s,
+        _ => return None,
+    };
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    loop {
+        let len = match state.step {
+            VncStep::Version => 12,
+            VncStep::Security | VncStep::ClientInit => 1,
+            VncStep::Response => 16,
+            VncStep::Done => {
+                state.buffer.clear();
+                break;
+            }
+        };
+        if state.buffer.len() < len {
+            break;
+        }
+        let message: Vec<u8> = state.buffer.drain(..len).collect();
+        match state.step {
+            VncStep::Version => {
+                let mut event = Event::new("vnc_client_version", client_info);
+                event.set(
+                    "version",
+                    String::from_utf8_lossy(&message).trim_end().to_string(),
+                );
+                event.log();
+                let server =
+                    vnc_parse_version(format!("{}\n", config.version).as_bytes()).unwrap_or((3, 8));
+                let client = match vnc_parse_version(&message) {
+                    Some(v) => v,
+                    None => {
+                        info!("invalid VNC version: {:?}", message);
+                        state.step = VncStep::Done;
+                        client_info.close = true;
+                        continue;
+                    }
+                };
+                state.minor = vnc_minor(server, client);
+                if state.minor == 3 {
+                    /* RFC 6143 section 7.1.2: the server decides */
+                    let security = if config.security.contains(&VNC_SECURITY_VNC) {
+                        VNC_SECURITY_VNC
+                    } else {
+                        VNC_SECURITY_NONE
+                    };
+                    repl_data.extend_from_slice(&(security as u32).to_be_bytes());
+                    repl_data.extend(vnc_security(security, state, config, client_info));
+                } else {
+                    repl_data.push(config.security.len() as u8);
+                    repl_data.extend_from_slice(&config.security);
+                    state.step = VncStep::Security;
+                }
+            }
+            VncStep::Security => {
+                let security = if config.security.contains(&message[0]) {
+                    message[0]
+                } else {
+                    0
+                };
+                repl_data.extend(vnc_security(security, state, config, client_info));
+            }
+            VncStep::Response => {
+                warn!(
+                    "VNC authentication: challenge {}, response {}",
+                    encode(&state.challenge, Encoding::Hex),
+                    encode(&message, Encoding::Hex)
+                );
+                vnc_auth_event(state, &message, client_info).log();
+                repl_data.extend(vnc_failure(state, config));
+                state.step = VncStep::Done;
+                client_info.close = true;
+            }
+            VncStep::ClientInit => {
+                repl_data.extend(vnc_server_init(config));
+                state.step = VncStep::Done;
+            }
+            VncStep::Done => {}
+        }
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending VNC data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use std::str::FromStr;
+
+    fn send(
+        data: &[u8],
+        masscanned: &Masscanned,
+        client_info: &mut ClientInfo,
+        tcb: &mut TCPControlBlock,
+    ) -> Option<Vec<u8>> {
+        repl(data, masscanned, client_info, Some(tcb))
+    }
+
+    #[test]
+    fn test_vnc_version() {
+        assert!(vnc_parse_version(b"RFB 003.008\n") == Some((3, 8)));
+        assert!(vnc_parse_version(b"RFB 003.889\n") == Some((3, 889)));
+        assert!(vnc_parse_version(b"RFB 003.008").is_none());
+        assert!(vnc_parse_version(b"RFB 00+.008\n").is_none());
+        assert!(vnc_minor((3, 8), (3, 3)) == 3);
+        assert!(vnc_minor((3, 8), (3, 5)) == 3);
+        assert!(vnc_minor((3, 8), (3, 7)) == 7);
+        assert!(vnc_minor((3, 8), (3, 889)) == 8);
+        assert!(vnc_minor((3, 7), (3, 8)) == 7);
+        /* 3.8: list of security types, security type split across
+         * segments */
+        let masscanned = self::masscanned(
+            Config::from_str("[vnc]\nsecurity = [1, 2]\nname = \"desk\"\n").unwrap(),
+        );
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        assert!(greeting(&masscanned, &mut tcb) == Some(b"RFB 003.008\n".to_vec()));
+        assert!(send(b"RFB 003", &masscanned, &mut client_info, &mut tcb).is_none());
+        assert!(
+            send(b".008\n", &masscanned, &mut client_info, &mut tcb)
+                == Some(b"\x02\x01\x02".to_vec())
+        );
+        /* None: SecurityResult, then ServerInit */
+        assert!(
+            send(b"\x01", &masscanned, &mut client_info, &mut tcb)
+                == Some(b"\x00\x00\x00\x00".to_vec())
+        );
+        let server_init = send(b"\x01", &masscanned, &mut client_info, &mut tcb).unwrap();
+        assert!(server_init.starts_with(b"\x04\x00\x03\x00\x20\x18"));
+        assert!(server_init.ends_with(b"\x00\x00\x00\x04desk"));
+        assert!(send(b"\x03\x00", &masscanned, &mut client_info, &mut tcb).is_none());
+        /* 3.3: the server chooses */
+        let mut tcb = TCPControlBlock::new();
+        greeting(&masscanned, &mut tcb);
+        let repl_data = send(b"RFB 003.003\n", &masscanned, &mut client_info, &mut tcb).unwrap();
+        assert!(repl_data.len() == 20 && repl_data.starts_with(b"\x00\x00\x00\x02"));
+        /* invalid versions, security types not offered */
+        let masscanned = self::masscanned(Config::default());
+        let mut tcb = TCPControlBlock::new();
+        greeting(&masscanned, &mut tcb);
+        assert!(send(b"SSH-2.0-Go\r\n", &masscanned, &mut client_info, &mut tcb).is_none());
+        assert!(client_info.close);
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        greeting(&masscanned, &mut tcb);
+        assert!(
+            send(b"RFB 003.008\n", &masscanned, &mut client_info, &mut tcb)
+                == Some(b"\x01\x02".to_vec())
+        );
+        let repl_data = send(b"\x01", &masscanned, &mut client_info, &mut tcb).unwrap();
+        assert!(repl_data == b"\x00\x00\x00\x01\x00\x00\x00\x15Authentication failed");
+        assert!(client_info.close);
+        /* not after a greeting */
+        assert!(repl(b"RFB 003.008\n", &masscanned, &mut client_info, None).is_none());
+        assert!(Config::from_str("[vnc]\nsecurity = [16]\n").is_err());
+        assert!(Config::from_str("[vnc]\nversion = \"RFB 3.8\"\n").is_err());
+    }
+
+    #[test]
+    fn test_vnc_auth() {
+        let masscanned = masscanned(Config::default());
+        for (version, failure) in [
+            (
+                &b"RFB 003.008\n"[..],
+                &b"\x00\x00\x00\x01\x00\x00\x00\x15Authentication failed"[..],
+            ),
+            (b"RFB 003.007\n", b"\x00\x00\x00\x01"),
+        ]
+        .iter()
+        {
+            let mut client_info = ClientInfo::new();
+            let mut tcb = TCPControlBlock::new();
+            greeting(&masscanned, &mut tcb);
+            assert!(
+                send(version, &masscanned, &mut client_info, &mut tcb)
+                    == Some(b"\x01\x02".to_vec())
+            );
+            let challenge = send(b"\x02", &masscanned, &mut client_info, &mut tcb).unwrap();
+            assert!(challenge.len() == 16);
+            /* response, in two segments */
+            let response = b"\x8b\x3a\x01\x5c\xd2\x7e\x49\x10\xa4\x62\x5f\x93\x0c\xe8\x77\x21";
+            assert!(send(&response[..5], &masscanned, &mut client_info, &mut tcb).is_none());
+            assert!(!client_info.close);
+            let repl_data = send(&response[5..], &masscanned, &mut client_info, &mut tcb).unwrap();
+            assert!(repl_data == *failure);
+            assert!(client_info.close);
+            let state = match &tcb.proto_state {
+                ProtoState::Vnc(s) => s,
+                _ => panic!("expected a VNC state"),
+            };
+            assert!(state.challenge[..] == challenge[..]);
+            let event = vnc_auth_event(state, response, &client_info);
+            assert!(event.fields["challenge"] == encode(&challenge, Encoding::Hex));
+            assert!(event.fields["response"] == "8b3a015cd27e4910a4625f930ce87721");
+        }
+    }
+}
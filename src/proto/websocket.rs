@@ -0,0 +1,296 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+
+use sha1::{Digest, Sha1};
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Event};
+use crate::Masscanned;
+
+/* RFC 6455 section 1.3 */
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const WS_OPCODE_CONTINUATION: u8 = 0x0;
+const WS_OPCODE_TEXT: u8 = 0x1;
+const WS_OPCODE_BINARY: u8 = 0x2;
+const WS_OPCODE_CLOSE: u8 = 0x8;
+const WS_OPCODE_PING: u8 = 0x9;
+const WS_OPCODE_PONG: u8 = 0xa;
+
+/* maximum size of a partial frame kept between two segments */
+const WS_MAX_BUFFER: usize = 65536;
+
+/* state of a flow upgraded to WebSocket: beginning of a frame not
+ * received entirely yet */
+pub struct WebSocketState {
+    buffer: Vec<u8>,
+}
+
+impl WebSocketState {
+    pub fn new() -> Self {
+        WebSocketState { buffer: Vec::new() }
+    }
+}
+
+/* value of Sec-WebSocket-Accept for a Sec-WebSocket-Key */
+pub fn ws_accept(key: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key);
+    hasher.update(WS_GUID.as_bytes());
+    base64::encode(hasher.finalize())
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    masked: bool,
+    /* unmasked */
+    payload: Vec<u8>,
+}
+
+/* RFC 6455 section 5.2 - returns the frame and the number of bytes used,
+ * or None when data does not hold an entire frame */
+fn ws_parse_frame(data: &[u8]) -> Option<(Frame, usize)> {
+    if data.len() < 2 {
+        return None;
+    }
+    let fin = data[0] & 0x80 != 0;
+    let opcode = data[0] & 0x0f;
+    let masked = data[1] & 0x80 != 0;
+    let mut offset = 2;
+    let len = match data[1] & 0x7f {
+        126 => {
+            if data.len() < 4 {
+                return None;
+            }
+            offset = 4;
+            u16::from_be_bytes([data[2], data[3]]) as u64
+        }
+        127 => {
+            if data.len() < 10 {
+                return None;
+            }
+            offset = 10;
+            let mut len = [0u8; 8];
+            len.copy_from_slice(&data[2..10]);
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    let mask = if masked {
+        if data.len() < offset + 4 {
+            return None;
+        }
+        offset += 4;
+        Some([
+            data[offset - 4],
+            data[offset - 3],
+            data[offset - 2],
+            data[offset - 1],
+        ])
+    } else {
+        None
+    };
+    if ((data.len() - offset) as u64) < len {
+        return None;
+    }
+    let end = offset + len as usize;
+    let mut payload = data[offset..end].to_vec();
+    if let Some(mask) = mask {
+        for (i, c) in payload.iter_mut().enumerate() {
+            *c ^= mask[i % 4];
+        }
+    }
+    Some((
+        Frame {
+            fin,
+            opcode,
+            masked,
+            payload,
+        },
+        end,
+    ))
+}
+
+/* frame sent by the server (never masked) */
+fn ws_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    if payload.len() < 126 {
+        frame.push(payload.len() as u8);
+    } else if payload.len() <= 0xffff {
+        frame.push(126);
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn ws_event(frame: &Frame, masscanned: &Masscanned, client_info: &ClientInfo) -> Event {
+    let config = &masscanned.config.http;
+    let mut event = Event::new("websocket_frame", client_info);
+    event.set("fin", frame.fin);
+    event.set("opcode", frame.opcode);
+    event.set("masked", frame.masked);
+    event.set("length", frame.payload.len());
+    if config.log_body {
+        let len = std::cmp::min(frame.payload.len(), config.log_body_max);
+        event.set(
+            "payload",
+            encode(&frame.payload[..len], config.log_body_encoding),
+        );
+    }
+    event
+}
+
+/* Answer to the frames received on a WebSocket flow: pings get a pong,
+ * close frames a close frame (and the connection is closed), text frames
+ * are echoed when configured.
+ **/
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    state: &mut WebSocketState,
+) -> Option<Vec<u8>> {
+    debug!("receiving WebSocket data");
+    state.buffer.extend_from_slice(data);
+    let mut repl_data = Vec::new();
+    let mut offset = 0;
+    while let Some((frame, len)) = ws_parse_frame(&state.buffer[offset..]) {
+        offset += len;
+        ws_event(&frame, masscanned, client_info).log();
+        match frame.opcode {
+            WS_OPCODE_TEXT | WS_OPCODE_CONTINUATION | WS_OPCODE_BINARY => {
+                if frame.opcode == WS_OPCODE_TEXT && masscanned.config.http.websocket_echo {
+                    repl_data.extend(ws_frame(WS_OPCODE_TEXT, &frame.payload));
+                }
+            }
+            WS_OPCODE_PING => {
+                repl_data.extend(ws_frame(WS_OPCODE_PONG, &frame.payload));
+            }
+            WS_OPCODE_CLOSE => {
+                /* RFC 6455 section 5.5.1: echo the status code */
+                let code = if frame.payload.len() >= 2 {
+                    &frame.payload[..2]
+                } else {
+                    &[]
+                };
+                repl_data.extend(ws_frame(WS_OPCODE_CLOSE, code));
+                client_info.close = true;
+                state.buffer.clear();
+                return Some(repl_data);
+            }
+            WS_OPCODE_PONG => {}
+            opcode => {
+                info!("WebSocket opcode not handled: {}", opcode);
+            }
+        }
+    }
+    state.buffer.drain(..offset);
+    if state.buffer.len() > WS_MAX_BUFFER {
+        info!(
+            "WebSocket frame too large ({} bytes received) - dropped",
+            state.buffer.len()
+        );
+        state.buffer.clear();
+    }
+    if repl_data.is_empty() {
+        return None;
+    }
+    debug!("sending WebSocket data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+
+    #[test]
+    fn test_ws_accept() {
+        /* RFC 6455 section 1.3 */
+        assert!(ws_accept(b"dGhlIHNhbXBsZSBub25jZQ==") == "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_ws_parse_frame() {
+        /* RFC 6455 section 5.7: masked text frame "Hello" */
+        let data = b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58";
+        let (frame, len) = ws_parse_frame(data).unwrap();
+        assert!(len == data.len());
+        assert!(frame.fin && frame.masked);
+        assert!(frame.opcode == WS_OPCODE_TEXT);
+        assert!(frame.payload == b"Hello");
+        /* incomplete frames */
+        for i in 0..data.len() {
+            assert!(ws_parse_frame(&data[..i]).is_none());
+        }
+        /* unmasked, fragmented */
+        let (frame, _) = ws_parse_frame(b"\x01\x03Hel").unwrap();
+        assert!(!frame.fin && !frame.masked && frame.payload == b"Hel");
+        /* 16-bit and 64-bit lengths */
+        let payload = vec![b'a'; 300];
+        for frame in [ws_frame(WS_OPCODE_BINARY, &payload), {
+            let mut f = vec![0x82, 127];
+            f.extend_from_slice(&300u64.to_be_bytes());
+            f.extend_from_slice(&payload);
+            f
+        }]
+        .iter()
+        {
+            let (f, len) = ws_parse_frame(frame).unwrap();
+            assert!(len == frame.len());
+            assert!(f.opcode == WS_OPCODE_BINARY && f.payload == payload);
+        }
+        /* absurd length */
+        let mut f = vec![0x82, 127];
+        f.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(ws_parse_frame(&f).is_none());
+    }
+
+    #[test]
+    fn test_ws_repl() {
+        let mut masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        let mut state = WebSocketState::new();
+        let hello = b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58";
+        /* text frames are not echoed by default */
+        assert!(repl(hello, &masscanned, &mut client_info, &mut state).is_none());
+        masscanned.config.http.websocket_echo = true;
+        let repl_data = repl(hello, &masscanned, &mut client_info, &mut state).unwrap();
+        assert!(repl_data == b"\x81\x05Hello");
+        /* frame split across segments */
+        assert!(repl(&hello[..3], &masscanned, &mut client_info, &mut state).is_none());
+        let repl_data = repl(&hello[3..], &masscanned, &mut client_info, &mut state).unwrap();
+        assert!(repl_data == b"\x81\x05Hello");
+        /* ping, then close with status 1000 */
+        let repl_data = repl(
+            b"\x89\x02hi\x88\x82\x00\x00\x00\x00\x03\xe8",
+            &masscanned,
+            &mut client_info,
+            &mut state,
+        )
+        .unwrap();
+        assert!(repl_data == b"\x8a\x02hi\x88\x02\x03\xe8");
+        assert!(client_info.close);
+    }
+}
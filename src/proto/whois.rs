@@ -0,0 +1,264 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::net::IpAddr;
+
+use chrono::Utc;
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::line::LINE_MAX_BUFFER;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* WHOIS server (RFC 3912), over TCP: the query (one line) gets the
+ * template matching the object queried (domain name, IP address or AS
+ * number), with {query} (as received), {QUERY} (uppercase) and {date}
+ * substituted, and the connection is closed.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WhoisConfig {
+    pub ports: Vec<u16>,
+    pub domain_template: String,
+    pub ip_template: String,
+    pub asn_template: String,
+    /* maximum length of the query in events */
+    pub log_max: usize,
+}
+
+impl Default for WhoisConfig {
+    fn default() -> Self {
+        WhoisConfig {
+            ports: vec![43],
+            domain_template: "   Domain Name: {QUERY}\n\
+                              \x20  Registrar WHOIS Server: whois.registrar.example\n\
+                              \x20  Updated Date: 2024-01-15T08:12:44Z\n\
+                              \x20  Creation Date: 2009-03-02T17:21:09Z\n\
+                              \x20  Registry Expiry Date: 2027-03-02T17:21:09Z\n\
+                              \x20  Registrar: Example Registrar, Inc.\n\
+                              \x20  Domain Status: clientTransferProhibited\n\
+                              \x20  Name Server: NS1.{QUERY}\n\
+                              \x20  Name Server: NS2.{QUERY}\n\
+                              \x20  DNSSEC: unsigned\n\
+                              >>> Last update of whois database: {date} <<<\n"
+                .to_string(),
+            ip_template: "inetnum:        {query}\n\
+                          netname:        CUSTOMER-NET\n\
+                          country:        EU\n\
+                          admin-c:        NOC1-RIPE\n\
+                          status:         ASSIGNED PA\n\
+                          mnt-by:         CUSTOMER-MNT\n\
+                          source:         RIPE\n"
+                .to_string(),
+            asn_template: "aut-num:        {QUERY}\n\
+                           as-name:        CUSTOMER-AS\n\
+                           org:            ORG-CA1-RIPE\n\
+                           admin-c:        NOC1-RIPE\n\
+                           mnt-by:         CUSTOMER-MNT\n\
+                           source:         RIPE\n"
+                .to_string(),
+            log_max: 256,
+        }
+    }
+}
+
+/* state of a WHOIS flow: beginning of the query */
+#[derive(Default)]
+pub struct WhoisState {
+    buffer: Vec<u8>,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum WhoisObject {
+    Domain,
+    Ip,
+    Asn,
+}
+
+impl WhoisObject {
+    fn name(self) -> &'static str {
+        match self {
+            WhoisObject::Domain => "domain",
+            WhoisObject::Ip => "ip",
+            WhoisObject::Asn => "asn",
+        }
+    }
+}
+
+/* object queried: the last word of the query (before it, registries
+ * accept flags, e.g., "-B 192.0.2.1" or "n + 192.0.2.1") */
+fn whois_object(query: &str) -> (&str, WhoisObject) {
+    let object = query.split_whitespace().last().unwrap_or("");
+    let kind = if object.len() > 2
+        && object.as_bytes()[..2].eq_ignore_ascii_case(b"as")
+        && object.as_bytes()[2..].iter().all(|c| c.is_ascii_digit())
+    {
+        WhoisObject::Asn
+    } else if object.parse::<IpAddr>().is_ok()
+        || object
+            .split_once('/')
+            .is_some_and(|(ip, len)| ip.parse::<IpAddr>().is_ok() && len.parse::<u8>().is_ok())
+    {
+        WhoisObject::Ip
+    } else {
+        WhoisObject::Domain
+    };
+    (object, kind)
+}
+
+fn whois_answer(config: &WhoisConfig, object: &str, kind: WhoisObject) -> Vec<u8> {
+    let template = match kind {
+        WhoisObject::Domain => &config.domain_template,
+        WhoisObject::Ip => &config.ip_template,
+        WhoisObject::Asn => &config.asn_template,
+    };
+    /* the object is sent back: no control characters */
+    let object: String = object.chars().filter(|c| !c.is_control()).collect();
+    let answer = template
+        .replace("{query}", &object)
+        .replace("{QUERY}", &object.to_uppercase())
+        .replace(
+            "{date}",
+            &Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        );
+    let mut data = Vec::new();
+    for line in answer.lines() {
+        data.extend_from_slice(line.as_bytes());
+        data.extend_from_slice(b"\r\n");
+    }
+    data
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving WHOIS data");
+    let config = &masscanned.config.whois;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Whois(s)) => s,
+        _ => WhoisState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    /* overlong queries are answered anyway */
+    let query = match state.buffer.iter().position(|&c| c == b'\n') {
+        Some(i) => &state.buffer[..i],
+        None if state.buffer.len() > LINE_MAX_BUFFER => &state.buffer[..],
+        None => {
+            if let Some(t) = tcb {
+                t.proto_state = ProtoState::Whois(state);
+            }
+            return None;
+        }
+    };
+    let query = String::from_utf8_lossy(query);
+    let query = query.trim_end_matches('\r');
+    let (object, kind) = whois_object(query);
+    let mut event = Event::new("whois_query", client_info);
+    if query.len() > config.log_max {
+        let mut len = config.log_max;
+        while !query.is_char_boundary(len) {
+            len -= 1;
+        }
+        event.set("query", &query[..len]);
+        event.set("truncated", true);
+    } else {
+        event.set("query", query);
+    }
+    event.set("length", query.len());
+    event.set("object_type", kind.name());
+    event.log();
+    client_info.close = true;
+    debug!("sending WHOIS data");
+    Some(whois_answer(config, object, kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    #[test]
+    fn test_whois_object() {
+        assert!(whois_object("example.com") == ("example.com", WhoisObject::Domain));
+        assert!(whois_object("-B 192.0.2.1") == ("192.0.2.1", WhoisObject::Ip));
+        assert!(whois_object("n + 2001:db8::/32") == ("2001:db8::/32", WhoisObject::Ip));
+        assert!(whois_object("as64496") == ("as64496", WhoisObject::Asn));
+        assert!(whois_object("ASN").1 == WhoisObject::Domain);
+        assert!(whois_object("") == ("", WhoisObject::Domain));
+        /* multibyte first character (e.g., a 0xff byte, as decoded) */
+        assert!(whois_object("\u{fffd}").1 == WhoisObject::Domain);
+        assert!(whois_object("\u{e9}s1").1 == WhoisObject::Domain);
+    }
+
+    #[test]
+    fn test_whois_template() {
+        let mut masscanned = masscanned(Config::default());
+        let reply = String::from_utf8(whois_answer(
+            &masscanned.config.whois,
+            "example.com",
+            WhoisObject::Domain,
+        ))
+        .unwrap();
+        assert!(reply.starts_with("   Domain Name: EXAMPLE.COM\r\n"));
+        assert!(reply.contains("\r\n   Name Server: NS2.EXAMPLE.COM\r\n"));
+        assert!(!reply.contains('{') && reply.ends_with(" <<<\r\n"));
+        masscanned.config.whois.asn_template = "{query} / {QUERY}\nok\n".to_string();
+        let reply = whois_answer(&masscanned.config.whois, "as64496", WhoisObject::Asn);
+        assert!(reply == b"as64496 / AS64496\r\nok\r\n");
+        /* control characters are not sent back */
+        let reply = whois_answer(&masscanned.config.whois, "as\x1b[2J1", WhoisObject::Asn);
+        assert!(reply.starts_with(b"as[2J1 / "));
+    }
+
+    #[test]
+    fn test_whois_close() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(43);
+        let mut tcb = TCPControlBlock::new();
+        /* query split across segments: the answer and the close come
+         * with the end of the line */
+        assert!(
+            crate::proto::dispatch(b"-B 192.0", &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        assert!(!client_info.close);
+        let reply =
+            crate::proto::dispatch(b".2.1\r\n", &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(reply.starts_with(b"inetnum:        192.0.2.1\r\n"));
+        assert!(client_info.close);
+        /* overlong query: answered anyway */
+        let mut client_info = ClientInfo::new();
+        let mut tcb = TCPControlBlock::new();
+        let query = vec![b'a'; LINE_MAX_BUFFER + 1];
+        let reply = repl(&query, &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(reply.starts_with(b"   Domain Name: AAAA"));
+        assert!(client_info.close);
+    }
+}
@@ -0,0 +1,414 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use log::*;
+use serde::Deserialize;
+use std::io;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::http::{http_auth_event, http_normalize_path};
+use crate::proto::ntlm::{
+    ntlm_challenge, NtlmConfig, NtlmMessage, NTLMSSP_AUTHENTICATE, NTLMSSP_NEGOTIATE,
+};
+use crate::proto::smb::spnego_resp;
+
+/* Windows Remote Management (WS-Management over HTTP), answered
+ * instead of the HTTP persona on the configured ports: POST requests
+ * to /wsman get a 401 asking for Negotiate authentication, NTLM
+ * NEGOTIATE messages a CHALLENGE, and AUTHENTICATE messages (recorded
+ * with the domain and user) a 401 again - or, with accept, an answer:
+ * the IdentifyResponse to Identify requests, an AccessDenied fault to
+ * anything else. Identify requests with the WSMANIDENTIFY:
+ * unauthenticated header are answered without credentials, as by
+ * Windows, unless anonymous_identify is unset.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WinrmConfig {
+    pub ports: Vec<u16>,
+    pub accept: bool,
+    pub anonymous_identify: bool,
+    pub product_vendor: String,
+    pub product_version: String,
+    pub ntlm: NtlmConfig,
+}
+
+impl Default for WinrmConfig {
+    fn default() -> Self {
+        WinrmConfig {
+            ports: vec![5985, 5986],
+            accept: false,
+            anonymous_identify: true,
+            product_vendor: "Microsoft Corporation".to_string(),
+            product_version: "OS: 0.0.0 SP: 0.0 Stack: 3.0".to_string(),
+            ntlm: NtlmConfig::default(),
+        }
+    }
+}
+
+impl WinrmConfig {
+    pub fn check(&mut self) -> Result<(), io::Error> {
+        self.ntlm.check()
+    }
+}
+
+const WSMAN_PROTOCOL_VERSION: &str = "http://schemas.dmtf.org/wbem/wsman/1/wsman.xsd";
+pub const WINRM_SERVER: &str = "Microsoft-HTTPAPI/2.0";
+const WINRM_CONTENT_TYPE: &str = "Content-Type: application/soap+xml;charset=UTF-8";
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/* DSP0226 section 11: answer to an Identify request */
+fn winrm_identify_response(config: &WinrmConfig) -> String {
+    format!(
+        "<s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\" \
+xmlns:wsmid=\"http://schemas.dmtf.org/wbem/wsman/identity/1/wsmanidentity.xsd\">\
+<s:Header/><s:Body><wsmid:IdentifyResponse>\
+<wsmid:ProtocolVersion>{}</wsmid:ProtocolVersion>\
+<wsmid:ProductVendor>{}</wsmid:ProductVendor>\
+<wsmid:ProductVersion>{}</wsmid:ProductVersion>\
+<wsmid:SecurityProfiles>\
+<wsmid:SecurityProfileName>http://schemas.dmtf.org/wbem/wsman/1/wsman/secprofile/http/spnego-kerberos</wsmid:SecurityProfileName>\
+</wsmid:SecurityProfiles>\
+</wsmid:IdentifyResponse></s:Body></s:Envelope>",
+        WSMAN_PROTOCOL_VERSION,
+        xml_escape(&config.product_vendor),
+        xml_escape(&config.product_version)
+    )
+}
+
+/* fault sent by WinRM to users that are not allowed to connect */
+fn winrm_access_denied() -> String {
+    "<s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\" \
+xmlns:w=\"http://schemas.dmtf.org/wbem/wsman/1/wsman.xsd\">\
+<s:Header/><s:Body><s:Fault><s:Code><s:Value>s:Sender</s:Value>\
+<s:Subcode><s:Value>w:AccessDenied</s:Value></s:Subcode></s:Code>\
+<s:Reason><s:Text xml:lang=\"en-US\">Access is denied. </s:Text></s:Reason>\
+</s:Fault></s:Body></s:Envelope>"
+        .to_string()
+}
+
+/* the body is an Identify request (not an IdentifyResponse, nor an
+ * Action URI ending with Identify) */
+fn winrm_is_identify(body: &[u8]) -> bool {
+    [
+        &b":Identify/>"[..],
+        b":Identify>",
+        b"<Identify/>",
+        b"<Identify>",
+    ]
+    .iter()
+    .any(|p| body.windows(p.len()).any(|w| w == *p))
+}
+
+/* content of the (first) Action header of a SOAP envelope */
+fn winrm_action(body: &[u8]) -> Option<String> {
+    let start = body.windows(7).position(|w| w == b":Action")? + 7;
+    let start = start + body[start..].iter().position(|c| *c == b'>')? + 1;
+    let len = body[start..].iter().position(|c| *c == b'<')?;
+    Some(
+        String::from_utf8_lossy(&body[start..start + len])
+            .trim()
+            .to_string(),
+    )
+}
+
+/* scheme and decoded token of a Negotiate (or NTLM) Authorization */
+fn winrm_token(authorization: &[u8]) -> Option<(String, Vec<u8>)> {
+    let value = String::from_utf8_lossy(authorization);
+    let (scheme, token) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Negotiate") && !scheme.eq_ignore_ascii_case("NTLM") {
+        return None;
+    }
+    Some((scheme.to_string(), base64::decode(token.trim()).ok()?))
+}
+
+/* Status, headers and content of the answer to a request
+ * (authorization is the value of the Authorization header, identify
+ * that of the WSMANIDENTIFY header).
+ **/
+pub fn winrm_page(
+    config: &WinrmConfig,
+    verb: &str,
+    uri: &str,
+    body: &[u8],
+    authorization: Option<&[u8]>,
+    identify: Option<&[u8]>,
+    client_info: &ClientInfo,
+) -> (String, Vec<String>, String) {
+    let path = http_normalize_path(uri.as_bytes());
+    if !path.is_some_and(|p| p.trim_end_matches('/').eq_ignore_ascii_case("/wsman")) {
+        return ("404 Not Found".to_string(), Vec::new(), String::new());
+    }
+    if verb != "POST" {
+        return (
+            "405 Method Not Allowed".to_string(),
+            vec!["Allow: POST".to_string()],
+            String::new(),
+        );
+    }
+    let is_identify = winrm_is_identify(body);
+    let mut event = Event::new("winrm_request", client_info);
+    event.set("verb", verb);
+    event.set("uri", uri);
+    event.set("identify", is_identify);
+    if let Some(action) = winrm_action(body) {
+        event.set("action", action);
+    }
+    let token = authorization.and_then(winrm_token);
+    let ntlm = token.as_ref().and_then(|(_, t)| NtlmMessage::parse(t));
+    if let Some(value) = authorization {
+        http_auth_event(&mut event, value);
+    }
+    if let Some(m) = &ntlm {
+        m.set_event(&mut event);
+    }
+    let unauthenticated = identify.is_some_and(|v| v.eq_ignore_ascii_case(b"unauthenticated"));
+    let (status, headers, content) = match (&token, &ntlm) {
+        (Some((scheme, t)), Some(m)) if m.message_type == NTLMSSP_NEGOTIATE => {
+            let challenge = ntlm_challenge(&config.ntlm.server_challenge(), &config.ntlm);
+            /* raw NTLMSSP, or wrapped in SPNEGO as received */
+            let challenge = if t.first() == Some(&0x60) {
+                spnego_resp(&challenge)
+            } else {
+                challenge
+            };
+            (
+                401,
+                vec![format!(
+                    "WWW-Authenticate: {} {}",
+                    scheme,
+                    base64::encode(challenge)
+                )],
+                String::new(),
+            )
+        }
+        (_, Some(m)) if m.message_type == NTLMSSP_AUTHENTICATE => {
+            warn!(
+                "WinRM NTLM authentication: {}\\{} from {}",
+                m.domain.as_deref().unwrap_or(""),
+                m.user.as_deref().unwrap_or(""),
+                m.workstation.as_deref().unwrap_or("-")
+            );
+            if !config.accept {
+                (401, Vec::new(), String::new())
+            } else if is_identify {
+                (200, Vec::new(), winrm_identify_response(config))
+            } else {
+                (500, Vec::new(), winrm_access_denied())
+            }
+        }
+        _ if authorization.is_none()
+            && unauthenticated
+            && is_identify
+            && config.anonymous_identify =>
+        {
+            (200, Vec::new(), winrm_identify_response(config))
+        }
+        _ => (401, Vec::new(), String::new()),
+    };
+    event.set("status", status);
+    event.log();
+    let mut headers = headers;
+    match status {
+        401 => {
+            if headers.is_empty() {
+                headers.push("WWW-Authenticate: Negotiate".to_string());
+            }
+            ("401 Unauthorized".to_string(), headers, content)
+        }
+        200 => {
+            headers.push(WINRM_CONTENT_TYPE.to_string());
+            ("200 OK".to_string(), headers, content)
+        }
+        _ => {
+            headers.push(WINRM_CONTENT_TYPE.to_string());
+            ("500 Internal Server Error".to_string(), headers, content)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::ntlm::{NTLMSSP_CHALLENGE, NTLMSSP_SIGNATURE};
+
+    const IDENTIFY: &[u8] = b"<s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\" xmlns:wsmid=\"http://schemas.dmtf.org/wbem/wsman/identity/1/wsmanidentity.xsd\"><s:Header/><s:Body><wsmid:Identify/></s:Body></s:Envelope>";
+
+    fn authenticate_header(scheme: &str) -> Vec<u8> {
+        /* curl --ntlm -u 'WIDGETS\alice' (7.88.1) */
+        format!("{} TlRMTVNTUAADAAAAGAAYAEAAAABaAFoAWAAAAA4ADgCyAAAACgAKAMAAAAAWABYAygAAAAAAAAAAAAAAB4KIAn+0hPvfyU9lcSeX27MvboA76NeGbcjsqgzTpEBBaR8i8CJQ0qKY+mgBAQAAAAAAAIDZiRKdW90BO+jXhm3I7KoAAAAAAgASAFcATwBSAEsARwBSAE8AVQBQAAEADABTAEUAUgBWAEUAUgAAAAAAAAAAAFcASQBEAEcARQBUAFMAYQBsAGkAYwBlAFcATwBSAEsAUwBUAEEAVABJAE8ATgA=", scheme).into_bytes()
+    }
+
+    #[test]
+    fn test_winrm_challenge() {
+        let mut config = WinrmConfig::default();
+        config.ntlm.challenge = Some("0123456789abcdef".to_string());
+        config.check().unwrap();
+        let client_info = ClientInfo::new();
+        /* no credentials */
+        let (status, headers, content) =
+            winrm_page(&config, "POST", "/wsman", b"", None, None, &client_info);
+        assert!(status == "401 Unauthorized" && content.is_empty());
+        assert!(headers == ["WWW-Authenticate: Negotiate"]);
+        /* NEGOTIATE (curl --ntlm), raw and within SPNEGO */
+        for (scheme, token, spnego) in [
+            (
+                "Negotiate",
+                "TlRMTVNTUAABAAAABoIIAAAAAAAAAAAAAAAAAAAAAAA=",
+                false,
+            ),
+            (
+                "NTLM",
+                "TlRMTVNTUAABAAAABoIIAAAAAAAAAAAAAAAAAAAAAAA=",
+                false,
+            ),
+            (
+                "Negotiate",
+                "YEAGBisGAQUFAqA2MDSgDjAMBgorBgEEAYI3AgIKoiIEIE5UTE1TU1AAAQAAAAaCCAAAAAAAAAAAAAAAAAAAAAAA",
+                true,
+            ),
+        ] {
+            let authorization = format!("{} {}", scheme, token);
+            let (status, headers, _) = winrm_page(
+                &config,
+                "POST",
+                "/wsman?PSVersion=5.1.17763.592",
+                b"",
+                Some(authorization.as_bytes()),
+                None,
+                &client_info,
+            );
+            assert!(status == "401 Unauthorized" && headers.len() == 1);
+            let prefix = format!("WWW-Authenticate: {} ", scheme);
+            let challenge = base64::decode(headers[0].strip_prefix(&prefix).unwrap()).unwrap();
+            assert!(challenge.starts_with(&[0xa1]) == spnego);
+            let start = challenge
+                .windows(8)
+                .position(|w| w == NTLMSSP_SIGNATURE)
+                .unwrap();
+            let msg = &challenge[start..];
+            assert!(msg[8..12] == NTLMSSP_CHALLENGE.to_le_bytes());
+            assert!(&msg[24..32] == b"\x01\x23\x45\x67\x89\xab\xcd\xef");
+        }
+        /* AUTHENTICATE: denied, and the client starts over */
+        let authorization = authenticate_header("Negotiate");
+        let (status, headers, _) = winrm_page(
+            &config,
+            "POST",
+            "/wsman",
+            IDENTIFY,
+            Some(&authorization),
+            None,
+            &client_info,
+        );
+        assert!(status == "401 Unauthorized");
+        assert!(headers == ["WWW-Authenticate: Negotiate"]);
+        /* accepted */
+        config.accept = true;
+        let (status, _, content) = winrm_page(
+            &config,
+            "POST",
+            "/wsman",
+            IDENTIFY,
+            Some(&authorization),
+            None,
+            &client_info,
+        );
+        assert!(status == "200 OK" && content.contains("<wsmid:IdentifyResponse>"));
+        let (status, _, content) = winrm_page(
+            &config,
+            "POST",
+            "/wsman",
+            b"<s:Envelope><s:Header><a:Action s:mustUnderstand=\"true\">http://schemas.xmlsoap.org/ws/2004/09/transfer/Create</a:Action></s:Header><s:Body/></s:Envelope>",
+            Some(&authorization),
+            None,
+            &client_info,
+        );
+        assert!(status == "500 Internal Server Error" && content.contains("w:AccessDenied"));
+        /* other paths and verbs */
+        let (status, _, _) = winrm_page(&config, "POST", "/", b"", None, None, &client_info);
+        assert!(status == "404 Not Found");
+        let (status, _, _) = winrm_page(&config, "GET", "/wsman", b"", None, None, &client_info);
+        assert!(status == "405 Method Not Allowed");
+    }
+
+    #[test]
+    fn test_winrm_identify() {
+        let mut config = WinrmConfig {
+            product_version: "OS: 10.0.17763 SP: 0.0 Stack: 3.0".to_string(),
+            ..Default::default()
+        };
+        let client_info = ClientInfo::new();
+        /* Test-WSMan -Authentication None */
+        let (status, headers, content) = winrm_page(
+            &config,
+            "POST",
+            "/wsman",
+            IDENTIFY,
+            None,
+            Some(b"unauthenticated"),
+            &client_info,
+        );
+        assert!(status == "200 OK");
+        assert!(headers == [WINRM_CONTENT_TYPE]);
+        assert!(content.contains(
+            "<wsmid:ProtocolVersion>http://schemas.dmtf.org/wbem/wsman/1/wsman.xsd</wsmid:ProtocolVersion>"
+        ));
+        assert!(
+            content.contains("<wsmid:ProductVendor>Microsoft Corporation</wsmid:ProductVendor>")
+        );
+        assert!(content.contains(
+            "<wsmid:ProductVersion>OS: 10.0.17763 SP: 0.0 Stack: 3.0</wsmid:ProductVersion>"
+        ));
+        /* without the header, or when disabled */
+        let (status, _, _) = winrm_page(
+            &config,
+            "POST",
+            "/wsman",
+            IDENTIFY,
+            None,
+            None,
+            &client_info,
+        );
+        assert!(status == "401 Unauthorized");
+        config.anonymous_identify = false;
+        let (status, _, _) = winrm_page(
+            &config,
+            "POST",
+            "/wsman",
+            IDENTIFY,
+            None,
+            Some(b"unauthenticated"),
+            &client_info,
+        );
+        assert!(status == "401 Unauthorized");
+        /* parsing helpers */
+        assert!(!winrm_is_identify(b"<wsmid:IdentifyResponse/>"));
+        assert!(
+            winrm_action(b"<a:Action s:mustUnderstand=\"true\">\n urn:x </a:Action>").as_deref()
+                == Some("urn:x")
+        );
+        config.product_vendor = "A & <B>".to_string();
+        assert!(winrm_identify_response(&config).contains(">A &amp; &lt;B&gt;<"));
+    }
+}
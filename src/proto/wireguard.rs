@@ -0,0 +1,272 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use lazy_static::lazy_static;
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::Masscanned;
+
+/* message types */
+const WG_HANDSHAKE_INITIATION: u8 = 1;
+const WG_HANDSHAKE_RESPONSE: u8 = 2;
+const WG_COOKIE_REPLY: u8 = 3;
+const WG_TRANSPORT_DATA: u8 = 4;
+
+const WG_INITIATION_LEN: usize = 148;
+const WG_RESPONSE_LEN: usize = 92;
+const WG_COOKIE_REPLY_LEN: usize = 64;
+/* header, counter and authentication tag of an empty (keepalive)
+ * packet */
+const WG_DATA_MIN_LEN: usize = 32;
+
+/* maximum number of sources counted */
+const WG_MAX_SOURCES: usize = 65536;
+
+/* WireGuard (on the configured UDP ports): the Noise handshake cannot
+ * be completed without the keys, so messages are only recorded (and
+ * counted per source). When cookie_reply is set, handshake initiations
+ * get a cookie reply (with a random encrypted cookie), as sent by a
+ * server under load, so that the client retries.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WireguardConfig {
+    pub ports: Vec<u16>,
+    pub cookie_reply: bool,
+}
+
+impl Default for WireguardConfig {
+    fn default() -> Self {
+        WireguardConfig {
+            ports: vec![51820],
+            cookie_reply: false,
+        }
+    }
+}
+
+/* messages received from a source, by type */
+struct WgSource {
+    count: [u64; 4],
+    last_seen: Instant,
+}
+
+lazy_static! {
+    static ref WG_SOURCES: Mutex<HashMap<IpAddr, WgSource>> = Mutex::new(HashMap::new());
+}
+
+/* number of messages of this type received from the source, this one
+ * included - the least recently seen source makes room for new ones */
+fn wg_count(ip: Option<IpAddr>, type_: u8) -> u64 {
+    let ip = match ip {
+        Some(ip) => ip,
+        None => return 1,
+    };
+    let mut sources = WG_SOURCES.lock().unwrap();
+    if !sources.contains_key(&ip) && sources.len() >= WG_MAX_SOURCES {
+        if let Some(oldest) = sources
+            .iter()
+            .min_by_key(|(_, s)| s.last_seen)
+            .map(|(ip, _)| *ip)
+        {
+            sources.remove(&oldest);
+        }
+    }
+    let source = sources.entry(ip).or_insert_with(|| WgSource {
+        count: [0; 4],
+        last_seen: Instant::now(),
+    });
+    source.last_seen = Instant::now();
+    let count = &mut source.count[(type_ - 1) as usize];
+    *count += 1;
+    *count
+}
+
+fn wg_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+/* the type and the length of each message are fixed (but for data
+ * packets, padded to 16 bytes), and the reserved bytes are zero */
+fn wg_check(data: &[u8]) -> Option<u8> {
+    if data.len() < 4 || data[1..4] != [0, 0, 0] {
+        return None;
+    }
+    let valid = match data[0] {
+        WG_HANDSHAKE_INITIATION => data.len() == WG_INITIATION_LEN,
+        WG_HANDSHAKE_RESPONSE => data.len() == WG_RESPONSE_LEN,
+        WG_COOKIE_REPLY => data.len() == WG_COOKIE_REPLY_LEN,
+        WG_TRANSPORT_DATA => data.len() >= WG_DATA_MIN_LEN && data.len().is_multiple_of(16),
+        _ => false,
+    };
+    if valid {
+        Some(data[0])
+    } else {
+        None
+    }
+}
+
+/* cookie reply to a handshake initiation: receiver index, nonce and
+ * encrypted cookie (random, since the MAC key of the client is not
+ * known) */
+fn wg_cookie_reply(sender_index: &[u8]) -> Vec<u8> {
+    let mut repl = vec![WG_COOKIE_REPLY, 0, 0, 0];
+    repl.extend_from_slice(sender_index);
+    repl.extend_from_slice(&rand::random::<[u8; 24]>());
+    repl.extend_from_slice(&rand::random::<[u8; 32]>());
+    repl
+}
+
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving WireGuard data");
+    let type_ = match wg_check(data) {
+        Some(t) => t,
+        None => {
+            info!("WireGuard message not handled (invalid type or length)");
+            return None;
+        }
+    };
+    let mut event = Event::new("wireguard_message", client_info);
+    event.set("type", type_);
+    event.set("count", wg_count(client_info.ip.src, type_));
+    match type_ {
+        WG_HANDSHAKE_INITIATION => {
+            event.set("sender_index", wg_u32(&data[4..8]));
+            event.set("ephemeral", encode(&data[8..40], Encoding::Hex));
+            /* mac2 is only set after a cookie reply */
+            event.set("mac1", data[116..132].iter().any(|b| *b != 0));
+            event.set("mac2", data[132..148].iter().any(|b| *b != 0));
+        }
+        WG_HANDSHAKE_RESPONSE => {
+            event.set("sender_index", wg_u32(&data[4..8]));
+            event.set("receiver_index", wg_u32(&data[8..12]));
+        }
+        WG_COOKIE_REPLY => {
+            event.set("receiver_index", wg_u32(&data[4..8]));
+        }
+        _ => {
+            event.set("receiver_index", wg_u32(&data[4..8]));
+            event.set(
+                "counter",
+                u64::from_le_bytes([
+                    data[8], data[9], data[10], data[11], data[12], data[13], data[14], data[15],
+                ]),
+            );
+            event.set("length", data.len());
+        }
+    }
+    event.log();
+    if type_ != WG_HANDSHAKE_INITIATION || !masscanned.config.wireguard.cookie_reply {
+        return None;
+    }
+    warn!("WireGuard handshake initiation: sending a cookie reply");
+    let repl = wg_cookie_reply(&data[4..8]);
+    debug!("sending WireGuard data");
+    Some(repl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::Ipv4Addr;
+
+    /* handshake initiation (mac1 set, no mac2) */
+    const INITIATION: &[u8; 148] =
+        b"\x01\x00\x00\x00\x86\x5e\xc7\x91\x3d\xd4\x6c\xbe\x59\xb3\xbb\x28\
+          \x99\x7e\x14\xe9\x98\x1c\x76\x2c\x84\x32\xec\x7d\x90\x4e\xcd\x04\
+          \x8a\x80\x65\x76\xc4\x97\x47\x91\xee\xed\x4f\xa8\x01\x2d\x98\x00\
+          \x56\x5b\xb0\x0f\x38\xfb\x29\x9e\xf0\xde\x7f\xa0\x79\x95\x4f\x54\
+          \xcc\x2a\x8b\x3b\x7d\xd0\x7d\x4f\xf0\xe6\x78\xa7\x71\xfc\x35\x1b\
+          \x71\xe8\x9d\x4e\xe5\x95\xfd\x40\xff\x77\x46\x62\x50\x19\xdc\x72\
+          \x4d\xdb\x0d\x0d\xc6\x1f\xaf\x81\x88\x55\x83\x8e\x53\xe6\xd9\xee\
+          \xdc\x36\xbe\x03\xa6\xd6\xe1\x67\x04\x87\xf1\x43\x85\xc3\x7f\x6f\
+          \xd4\xa6\xe8\x92\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+          \x00\x00\x00\x00";
+
+    fn client(src: Ipv4Addr) -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Udp);
+        client_info.ip.src = Some(IpAddr::V4(src));
+        client_info.ip.dst = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        client_info.port.src = Some(51820);
+        client_info.port.dst = Some(51820);
+        client_info
+    }
+
+    #[test]
+    fn test_wireguard_initiation() {
+        assert!(wg_check(INITIATION) == Some(WG_HANDSHAKE_INITIATION));
+        assert!(wg_u32(&INITIATION[4..8]) == 0x91c75e86);
+        assert!(
+            encode(&INITIATION[8..40], Encoding::Hex)
+                == "3dd46cbe59b3bb28997e14e9981c762c8432ec7d904ecd048a806576c4974791"
+        );
+        /* mac1 */
+        assert!(
+            INITIATION[116..132]
+                == *b"\xa6\xd6\xe1\x67\x04\x87\xf1\x43\x85\xc3\x7f\x6f\xd4\xa6\xe8\x92"
+        );
+        /* wrong lengths, reserved bytes or types */
+        assert!(wg_check(&INITIATION[..147]).is_none());
+        let mut data = INITIATION.to_vec();
+        data.push(0);
+        assert!(wg_check(&data).is_none());
+        data = INITIATION.to_vec();
+        data[2] = 1;
+        assert!(wg_check(&data).is_none());
+        data[2] = 0;
+        data[0] = 5;
+        assert!(wg_check(&data).is_none());
+        /* no answer by default */
+        let mut masscanned = masscanned(Config::default());
+        let mut client_info = client(Ipv4Addr::new(198, 51, 100, 20));
+        assert!(repl(INITIATION, &masscanned, &mut client_info).is_none());
+        masscanned.config.wireguard.cookie_reply = true;
+        let cookie = repl(INITIATION, &masscanned, &mut client_info).expect("no cookie reply");
+        assert!(cookie.len() == WG_COOKIE_REPLY_LEN);
+        assert!(cookie[..4] == [WG_COOKIE_REPLY, 0, 0, 0]);
+        assert!(cookie[4..8] == INITIATION[4..8]);
+        assert!(wg_check(&cookie) == Some(WG_COOKIE_REPLY));
+        assert!(wg_count(client_info.ip.src, WG_HANDSHAKE_INITIATION) == 3);
+    }
+
+    #[test]
+    fn test_wireguard_data() {
+        let mut masscanned = masscanned(Config::default());
+        masscanned.config.wireguard.cookie_reply = true;
+        let mut client_info = client(Ipv4Addr::new(198, 51, 100, 21));
+        /* keepalive, then 16 bytes of payload: counted, not answered */
+        let mut data = vec![WG_TRANSPORT_DATA, 0, 0, 0, 0x2a, 0, 0, 0];
+        data.extend_from_slice(&[0; 24]);
+        assert!(repl(&data, &masscanned, &mut client_info).is_none());
+        data.extend_from_slice(&[0; 16]);
+        assert!(repl(&data, &masscanned, &mut client_info).is_none());
+        data.push(0);
+        assert!(wg_check(&data).is_none());
+        assert!(wg_count(client_info.ip.src, WG_TRANSPORT_DATA) == 3);
+        assert!(wg_count(client_info.ip.src, WG_HANDSHAKE_INITIATION) == 1);
+    }
+}
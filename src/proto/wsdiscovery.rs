@@ -0,0 +1,644 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use log::*;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::upnp::{xml_escape, xml_next_tag, xml_unescape};
+use crate::Masscanned;
+
+/* WS-Discovery (April 2005, as used by ONVIF) over SOAP-over-UDP */
+const WSD_PORT: u16 = 3702;
+const WSD_GROUP_V4: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const WSD_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xc);
+
+const WSD_NS_DISCOVERY: &str = "http://schemas.xmlsoap.org/ws/2005/04/discovery";
+const WSD_NS_ADDRESSING: &str = "http://schemas.xmlsoap.org/ws/2004/08/addressing";
+const WSD_NS_SOAP: &str = "http://www.w3.org/2003/05/soap-envelope";
+const WSD_ANONYMOUS: &str = "http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous";
+
+/* namespaces of the prefixes usable in the types of the configuration */
+const WSD_TYPE_NAMESPACES: [(&str, &str); 3] = [
+    ("dn", "http://www.onvif.org/ver10/network/wsdl"),
+    ("tds", "http://www.onvif.org/ver10/device/wsdl"),
+    ("wsdp", "http://schemas.xmlsoap.org/ws/2006/02/devprof"),
+];
+
+/* RFC 4122 appendix C: name space for URLs */
+const WSD_UUID_NAMESPACE: [u8; 16] = [
+    0x6b, 0xa7, 0xb8, 0x11, 0x9d, 0xad, 0x11, 0xd1, 0x80, 0xb4, 0x00, 0xc0, 0x4f, 0xd4, 0x30, 0xc8,
+];
+
+lazy_static! {
+    /* AppSequence: instance (start time) and number of messages sent */
+    static ref WSD_INSTANCE_ID: u64 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+}
+static WSD_MESSAGE_NUMBER: AtomicU32 = AtomicU32::new(1);
+
+/* Probes (sent to 239.255.255.250, ff02::c or one of our addresses)
+ * whose types and scopes match ours get a ProbeMatch, sent to the
+ * prober, so that masscanned is discovered as an ONVIF camera. The
+ * device service is announced at xaddrs_path, to be served by an HTTP
+ * route.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WsdiscoveryConfig {
+    pub enabled: bool,
+    pub ports: Vec<u16>,
+    /* qualified names, with the prefixes dn, tds or wsdp */
+    pub types: Vec<String>,
+    pub scopes: Vec<String>,
+    /* ONVIF hardware, name and location scopes (not sent when empty) */
+    pub hardware: String,
+    pub name: String,
+    pub location: String,
+    pub xaddrs_port: u16,
+    pub xaddrs_path: String,
+}
+
+impl Default for WsdiscoveryConfig {
+    fn default() -> Self {
+        WsdiscoveryConfig {
+            enabled: true,
+            ports: vec![WSD_PORT],
+            types: vec![
+                "dn:NetworkVideoTransmitter".to_string(),
+                "tds:Device".to_string(),
+            ],
+            scopes: vec![
+                "onvif://www.onvif.org/type/video_encoder".to_string(),
+                "onvif://www.onvif.org/type/Network_Video_Transmitter".to_string(),
+                "onvif://www.onvif.org/Profile/Streaming".to_string(),
+            ],
+            hardware: "DS-2CD2143G0-I".to_string(),
+            name: "HIKVISION DS-2CD2143G0-I".to_string(),
+            location: "city/hangzhou".to_string(),
+            xaddrs_port: 80,
+            xaddrs_path: "/onvif/device_service".to_string(),
+        }
+    }
+}
+
+fn wsd_invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("wsdiscovery: {}", msg))
+}
+
+impl WsdiscoveryConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for t in &self.types {
+            let valid = match t.split_once(':') {
+                Some((prefix, name)) => {
+                    WSD_TYPE_NAMESPACES.iter().any(|(p, _)| *p == prefix)
+                        && !name.is_empty()
+                        && name
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+                }
+                None => false,
+            };
+            if !valid {
+                return Err(wsd_invalid(format!("invalid type: {:?}", t)));
+            }
+        }
+        for scope in &self.scopes {
+            if scope.is_empty() || scope.contains(char::is_whitespace) {
+                return Err(wsd_invalid(format!("invalid scope: {:?}", scope)));
+            }
+        }
+        if !self.xaddrs_path.starts_with('/') || self.xaddrs_path.contains(char::is_whitespace) {
+            return Err(wsd_invalid(format!(
+                "invalid xaddrs_path: {:?}",
+                self.xaddrs_path
+            )));
+        }
+        Ok(())
+    }
+}
+
+/* WS-Discovery message to the group, or to one of our addresses */
+pub fn is_wsdiscovery(masscanned: &Masscanned, client_info: &ClientInfo) -> bool {
+    let config = &masscanned.config.wsdiscovery;
+    config.enabled
+        && client_info
+            .port
+            .dst
+            .is_some_and(|p| config.ports.contains(&p))
+        && match client_info.ip.dst {
+            Some(IpAddr::V4(ip)) => !ip.is_multicast() || ip == WSD_GROUP_V4,
+            Some(IpAddr::V6(ip)) => !ip.is_multicast() || ip == WSD_GROUP_V6,
+            None => true,
+        }
+}
+
+struct WsdProbe {
+    message_id: String,
+    /* requested types and scopes */
+    types: Vec<String>,
+    scopes: Vec<String>,
+    /* namespaces (when declared) and local names of the types */
+    type_names: Vec<(Option<String>, String)>,
+}
+
+/* namespace of a prefix, from the first declaration found in the
+ * document (scoping is ignored) */
+fn wsd_namespace(doc: &str, prefix: &str) -> Option<String> {
+    let decl = format!("xmlns:{}=", prefix);
+    let value = &doc[doc.find(&decl)? + decl.len()..];
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &value[1..];
+    Some(xml_unescape(&value[..value.find(quote)?]))
+}
+
+/* namespace (when known) and local name of a qualified name */
+fn wsd_qname(doc: &str, qname: &str) -> (Option<String>, String) {
+    match qname.split_once(':') {
+        Some((prefix, name)) => (wsd_namespace(doc, prefix), name.to_string()),
+        None => (None, qname.to_string()),
+    }
+}
+
+/* MessageID, Types and Scopes of a Probe - namespace prefixes are
+ * ignored, and other messages (e.g., Hello or Resolve) are not
+ * parsed */
+fn wsd_parse_probe(doc: &str) -> Option<WsdProbe> {
+    let mut message_id = None;
+    let mut probe = false;
+    let mut types = Vec::new();
+    let mut scopes = Vec::new();
+    let mut offset = 0;
+    while let Some(tag) = xml_next_tag(doc, offset) {
+        offset = tag.end;
+        if tag.closing {
+            continue;
+        }
+        match &tag.name[..] {
+            "Probe" => probe = true,
+            "MessageID" | "Types" | "Scopes" if !tag.empty => {
+                let close = xml_next_tag(doc, tag.end)?;
+                if !close.closing || close.name != tag.name {
+                    return None;
+                }
+                let text = xml_unescape(doc[tag.end..close.start].trim());
+                let list = || text.split_whitespace().map(|s| s.to_string()).collect();
+                match &tag.name[..] {
+                    "MessageID" => message_id = Some(text.clone()),
+                    "Types" => types = list(),
+                    _ => scopes = list(),
+                }
+                offset = close.end;
+            }
+            _ => {}
+        }
+    }
+    if !probe {
+        return None;
+    }
+    Some(WsdProbe {
+        message_id: message_id?,
+        type_names: types.iter().map(|t| wsd_qname(doc, t)).collect(),
+        types,
+        scopes,
+    })
+}
+
+/* percent-encoding of a scope segment */
+fn wsd_scope_escape(s: &str, keep: &str) -> String {
+    s.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) || keep.as_bytes().contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect()
+}
+
+fn wsd_scopes(config: &WsdiscoveryConfig) -> Vec<String> {
+    let mut scopes = config.scopes.clone();
+    for (kind, value, keep) in [
+        ("hardware", &config.hardware, ""),
+        ("name", &config.name, ""),
+        ("location", &config.location, "/"),
+    ] {
+        if !value.is_empty() {
+            scopes.push(format!(
+                "onvif://www.onvif.org/{}/{}",
+                kind,
+                wsd_scope_escape(value, keep)
+            ));
+        }
+    }
+    scopes
+}
+
+/* section 5.1: a requested scope matches one of ours that is equal or
+ * that it is a prefix of, segment-wise (compared case-insensitively,
+ * which simplifies the RFC 3986 rule) */
+fn wsd_scope_match(ours: &str, requested: &str) -> bool {
+    let ours = ours.trim_end_matches('/');
+    let requested = requested.trim_end_matches('/');
+    ours.get(..requested.len())
+        .is_some_and(|p| p.eq_ignore_ascii_case(requested))
+        && matches!(ours.as_bytes().get(requested.len()), None | Some(b'/'))
+}
+
+/* every requested type (by local name, and namespace when declared)
+ * and scope must be ours */
+fn wsd_match(probe: &WsdProbe, config: &WsdiscoveryConfig, scopes: &[String]) -> bool {
+    let types: Vec<(&str, &str)> = config
+        .types
+        .iter()
+        .filter_map(|t| {
+            let (prefix, name) = t.split_once(':')?;
+            let ns = WSD_TYPE_NAMESPACES.iter().find(|(p, _)| *p == prefix)?.1;
+            Some((ns, name))
+        })
+        .collect();
+    probe.type_names.iter().all(|(ns, name)| {
+        types
+            .iter()
+            .any(|(n, t)| t == name && ns.as_ref().is_none_or(|ns| ns == n))
+    }) && probe
+        .scopes
+        .iter()
+        .all(|r| scopes.iter().any(|s| wsd_scope_match(s, r)))
+}
+
+fn wsd_uuid(b: &[u8]) -> String {
+    let hex = |r: std::ops::Range<usize>| {
+        b[r].iter()
+            .map(|x| format!("{:02x}", x))
+            .collect::<String>()
+    };
+    format!(
+        "urn:uuid:{}-{}-{}-{}-{}",
+        hex(0..4),
+        hex(4..6),
+        hex(6..8),
+        hex(8..10),
+        hex(10..16)
+    )
+}
+
+/* endpoint reference of an address: stable name-based (version 5)
+ * UUID, RFC 4122 section 4.3 */
+fn wsd_endpoint(ip: Option<IpAddr>) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(WSD_UUID_NAMESPACE);
+    if let Some(ip) = ip {
+        hasher.update(format!("http://{}/", ip).as_bytes());
+    }
+    let mut b = hasher.finalize()[..16].to_vec();
+    b[6] = (b[6] & 0x0f) | 0x50;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    wsd_uuid(&b)
+}
+
+/* random (version 4) UUID */
+fn wsd_message_id() -> String {
+    let mut b: [u8; 16] = rand::random();
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    wsd_uuid(&b)
+}
+
+fn wsd_xaddrs(config: &WsdiscoveryConfig, ip: Option<IpAddr>) -> Option<String> {
+    let host = match ip? {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{}]", ip),
+    };
+    let port = if config.xaddrs_port == 80 {
+        String::new()
+    } else {
+        format!(":{}", config.xaddrs_port)
+    };
+    Some(format!("http://{}{}{}", host, port, config.xaddrs_path))
+}
+
+/* ProbeMatches message (section 5.3), from the address ip */
+fn wsd_probe_matches(
+    probe: &WsdProbe,
+    config: &WsdiscoveryConfig,
+    scopes: &[String],
+    ip: Option<IpAddr>,
+) -> String {
+    let namespaces: String = WSD_TYPE_NAMESPACES
+        .iter()
+        .map(|(p, ns)| format!(" xmlns:{}=\"{}\"", p, ns))
+        .collect();
+    let xaddrs = wsd_xaddrs(config, ip)
+        .map(|x| format!("<d:XAddrs>{}</d:XAddrs>", xml_escape(&x)))
+        .unwrap_or_default();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+<SOAP-ENV:Envelope xmlns:SOAP-ENV=\"{soap}\" xmlns:wsa=\"{wsa}\" xmlns:d=\"{d}\"{namespaces}>\
+<SOAP-ENV:Header>\
+<wsa:MessageID>{message_id}</wsa:MessageID>\
+<wsa:RelatesTo>{relates_to}</wsa:RelatesTo>\
+<wsa:To SOAP-ENV:mustUnderstand=\"true\">{anonymous}</wsa:To>\
+<wsa:Action SOAP-ENV:mustUnderstand=\"true\">{d}/ProbeMatches</wsa:Action>\
+<d:AppSequence InstanceId=\"{instance}\" MessageNumber=\"{number}\"/>\
+</SOAP-ENV:Header>\
+<SOAP-ENV:Body>\
+<d:ProbeMatches>\
+<d:ProbeMatch>\
+<wsa:EndpointReference><wsa:Address>{endpoint}</wsa:Address></wsa:EndpointReference>\
+<d:Types>{types}</d:Types>\
+<d:Scopes>{scopes}</d:Scopes>\
+{xaddrs}\
+<d:MetadataVersion>1</d:MetadataVersion>\
+</d:ProbeMatch>\
+</d:ProbeMatches>\
+</SOAP-ENV:Body>\
+</SOAP-ENV:Envelope>",
+        soap = WSD_NS_SOAP,
+        wsa = WSD_NS_ADDRESSING,
+        d = WSD_NS_DISCOVERY,
+        namespaces = namespaces,
+        message_id = wsd_message_id(),
+        relates_to = xml_escape(&probe.message_id),
+        anonymous = WSD_ANONYMOUS,
+        instance = *WSD_INSTANCE_ID,
+        number = WSD_MESSAGE_NUMBER.fetch_add(1, Ordering::Relaxed),
+        endpoint = wsd_endpoint(ip),
+        types = xml_escape(&config.types.join(" ")),
+        scopes = xml_escape(&scopes.join(" ")),
+        xaddrs = xaddrs,
+    )
+}
+
+fn wsd_event(probe: &WsdProbe, matched: bool, client_info: &ClientInfo) -> Event {
+    let mut event = Event::new("wsdiscovery_probe", client_info);
+    event.set("message_id", probe.message_id.clone());
+    event.set("types", probe.types.clone());
+    event.set("scopes", probe.scopes.clone());
+    event.set("matched", matched);
+    event
+}
+
+/* Answer a Probe with a ProbeMatch sent to the prober, when its types
+ * and scopes match. Probes sent to the group are answered from one of
+ * our addresses.
+ **/
+pub fn repl(data: &[u8], masscanned: &Masscanned, client_info: &mut ClientInfo) -> Option<Vec<u8>> {
+    debug!("receiving WS-Discovery data");
+    let config = &masscanned.config.wsdiscovery;
+    let probe = match std::str::from_utf8(data).ok().and_then(wsd_parse_probe) {
+        Some(p) => p,
+        None => {
+            info!("WS-Discovery data not handled (not a Probe)");
+            return None;
+        }
+    };
+    let scopes = wsd_scopes(config);
+    let matched = wsd_match(&probe, config, &scopes);
+    wsd_event(&probe, matched, client_info).log();
+    if !matched {
+        return None;
+    }
+    if client_info.ip.dst.is_some_and(|ip| ip.is_multicast()) {
+        let v4 = client_info.ip.dst.is_some_and(|ip| ip.is_ipv4());
+        let source = masscanned.ip_addresses.and_then(|ips| {
+            ips.iter()
+                .filter(|ip| ip.is_ipv4() == v4 && !ip.is_multicast() && !ip.is_unspecified())
+                .min()
+                .copied()
+        });
+        match source {
+            Some(ip) => client_info.ip.dst = Some(ip),
+            None => {
+                info!("WS-Discovery Probe to the group not answered (no address)");
+                return None;
+            }
+        }
+    }
+    let repl_data = wsd_probe_matches(&probe, config, &scopes, client_info.ip.dst).into_bytes();
+    debug!("sending WS-Discovery data");
+    Some(repl_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    fn masscanned<'a>(config: Config, ips: Option<&'a HashSet<IpAddr>>) -> Masscanned<'a> {
+        Masscanned {
+            ip_addresses: ips,
+            ..crate::proto::test_util::masscanned(config)
+        }
+    }
+
+    /* from 192.168.1.10, port 50123, to the group */
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.ip.src = Some(IpAddr::from_str("192.168.1.10").unwrap());
+        client_info.ip.dst = Some(IpAddr::V4(WSD_GROUP_V4));
+        client_info.port.src = Some(50123);
+        client_info.port.dst = Some(WSD_PORT);
+        client_info
+    }
+
+    /* Probes sent by ONVIF Device Manager, for network video
+     * transmitters and for devices */
+    const ODM_PROBE_NVT: &str = "<s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\" xmlns:a=\"http://schemas.xmlsoap.org/ws/2004/08/addressing\"><s:Header><a:Action s:mustUnderstand=\"1\">http://schemas.xmlsoap.org/ws/2005/04/discovery/Probe</a:Action><a:MessageID>urn:uuid:6f1e1dd0-2a4b-4a3f-8f0c-5d3e2b1c9a77</a:MessageID><a:ReplyTo><a:Address>http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous</a:Address></a:ReplyTo><a:To s:mustUnderstand=\"1\">urn:schemas-xmlsoap-org:ws:2005:04:discovery</a:To></s:Header><s:Body><Probe xmlns:i=\"http://www.w3.org/2001/XMLSchema-instance\" xmlns:d=\"http://www.w3.org/2001/XMLSchema\" xmlns=\"http://schemas.xmlsoap.org/ws/2005/04/discovery\"><Types xmlns:dp0=\"http://www.onvif.org/ver10/network/wsdl\">dp0:NetworkVideoTransmitter</Types></Probe></s:Body></s:Envelope>";
+    const ODM_PROBE_DEVICE: &str = "<s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\" xmlns:a=\"http://schemas.xmlsoap.org/ws/2004/08/addressing\"><s:Header><a:Action s:mustUnderstand=\"1\">http://schemas.xmlsoap.org/ws/2005/04/discovery/Probe</a:Action><a:MessageID>urn:uuid:0c3a4b8e-77d2-4f5e-9a1b-2e6d8c4f1b03</a:MessageID><a:ReplyTo><a:Address>http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous</a:Address></a:ReplyTo><a:To s:mustUnderstand=\"1\">urn:schemas-xmlsoap-org:ws:2005:04:discovery</a:To></s:Header><s:Body><Probe xmlns:i=\"http://www.w3.org/2001/XMLSchema-instance\" xmlns:d=\"http://www.w3.org/2001/XMLSchema\" xmlns=\"http://schemas.xmlsoap.org/ws/2005/04/discovery\"><Types xmlns:dp0=\"http://www.onvif.org/ver10/device/wsdl\">dp0:Device</Types></Probe></s:Body></s:Envelope>";
+
+    /* text of the first element of the given local name */
+    fn text(doc: &str, name: &str) -> Option<String> {
+        let mut tag = xml_next_tag(doc, 0)?;
+        while tag.closing || tag.name != name {
+            tag = xml_next_tag(doc, tag.end)?;
+        }
+        let close = xml_next_tag(doc, tag.end)?;
+        Some(doc[tag.end..close.start].to_string())
+    }
+
+    #[test]
+    fn test_wsd_probe_odm() {
+        let ips: HashSet<IpAddr> = ["192.168.1.20", "192.168.1.21", "fe80::211:22ff:fe33:4455"]
+            .iter()
+            .map(|ip| IpAddr::from_str(ip).unwrap())
+            .collect();
+        let masscanned = self::masscanned(Config::default(), Some(&ips));
+        let mut endpoints = HashSet::new();
+        for probe in [ODM_PROBE_NVT, ODM_PROBE_DEVICE].iter() {
+            let parsed = wsd_parse_probe(probe).unwrap();
+            let mut client_info = client();
+            let repl_data = repl(probe.as_bytes(), &masscanned, &mut client_info).unwrap();
+            /* unicast, from one of our addresses, to the prober */
+            assert!(client_info.ip.dst == Some(IpAddr::from_str("192.168.1.20").unwrap()));
+            assert!(client_info.ip.src == Some(IpAddr::from_str("192.168.1.10").unwrap()));
+            assert!(client_info.port.src == Some(50123));
+            let doc = String::from_utf8(repl_data).unwrap();
+            assert!(text(&doc, "RelatesTo") == Some(parsed.message_id.clone()));
+            assert!(text(&doc, "MessageID").unwrap().starts_with("urn:uuid:"));
+            assert!(text(&doc, "MessageID") != Some(parsed.message_id));
+            assert!(text(&doc, "To") == Some(WSD_ANONYMOUS.to_string()));
+            assert!(text(&doc, "Action") == Some(format!("{}/ProbeMatches", WSD_NS_DISCOVERY)));
+            assert!(
+                text(&doc, "Types") == Some("dn:NetworkVideoTransmitter tds:Device".to_string())
+            );
+            let scopes = text(&doc, "Scopes").unwrap();
+            assert!(scopes.contains(" onvif://www.onvif.org/hardware/DS-2CD2143G0-I "));
+            assert!(scopes.contains(" onvif://www.onvif.org/name/HIKVISION%20DS-2CD2143G0-I "));
+            assert!(scopes.ends_with(" onvif://www.onvif.org/location/city/hangzhou"));
+            assert!(
+                text(&doc, "XAddrs")
+                    == Some("http://192.168.1.20/onvif/device_service".to_string())
+            );
+            endpoints.insert(text(&doc, "Address").unwrap());
+        }
+        /* the endpoint reference is stable, and depends on the address */
+        assert!(endpoints.len() == 1);
+        let endpoint = endpoints.into_iter().next().unwrap();
+        assert!(endpoint == wsd_endpoint(Some(IpAddr::from_str("192.168.1.20").unwrap())));
+        assert!(endpoint != wsd_endpoint(Some(IpAddr::from_str("192.168.1.21").unwrap())));
+        assert!(endpoint.len() == 45 && endpoint.as_bytes()[23] == b'5');
+        /* IPv6 group: from the IPv6 address */
+        let mut client_info = client();
+        client_info.ip.src = Some(IpAddr::from_str("fe80::a00:27ff:fe1a:2b3c").unwrap());
+        client_info.ip.dst = Some(IpAddr::V6(WSD_GROUP_V6));
+        let repl_data = repl(ODM_PROBE_NVT.as_bytes(), &masscanned, &mut client_info).unwrap();
+        assert!(client_info.ip.dst == Some(IpAddr::from_str("fe80::211:22ff:fe33:4455").unwrap()));
+        let doc = String::from_utf8(repl_data).unwrap();
+        assert!(
+            text(&doc, "XAddrs")
+                == Some("http://[fe80::211:22ff:fe33:4455]/onvif/device_service".to_string())
+        );
+        /* no address to answer the group from */
+        let masscanned = self::masscanned(Config::default(), None);
+        let mut client_info = client();
+        assert!(repl(ODM_PROBE_NVT.as_bytes(), &masscanned, &mut client_info).is_none());
+        /* unicast probe: from the probed address, on another port */
+        let mut config = Config::from_str("[wsdiscovery]\nxaddrs_port = 8000\n").unwrap();
+        config.wsdiscovery.xaddrs_path = "/onvif/device".to_string();
+        let masscanned = self::masscanned(config, None);
+        client_info.ip.dst = Some(IpAddr::from_str("10.0.0.1").unwrap());
+        let repl_data = repl(ODM_PROBE_NVT.as_bytes(), &masscanned, &mut client_info).unwrap();
+        assert!(client_info.ip.dst == Some(IpAddr::from_str("10.0.0.1").unwrap()));
+        let doc = String::from_utf8(repl_data).unwrap();
+        assert!(text(&doc, "XAddrs") == Some("http://10.0.0.1:8000/onvif/device".to_string()));
+    }
+
+    #[test]
+    fn test_wsd_match() {
+        let masscanned = self::masscanned(Config::default(), None);
+        let probe = |types: &str, scopes: &str| {
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<soap:Envelope xmlns:soap=\"{}\" xmlns:wsa=\"{}\" xmlns:wsd=\"{}\"><soap:Header><wsa:MessageID>urn:uuid:a9b8c7d6-e5f4-4321-8765-0123456789ab</wsa:MessageID><wsa:Action>{}/Probe</wsa:Action></soap:Header><soap:Body><wsd:Probe>{}{}</wsd:Probe></soap:Body></soap:Envelope>",
+                WSD_NS_SOAP, WSD_NS_ADDRESSING, WSD_NS_DISCOVERY, WSD_NS_DISCOVERY, types, scopes
+            )
+        };
+        let answered = |doc: &str| {
+            let mut client_info = client();
+            client_info.ip.dst = Some(IpAddr::from_str("10.0.0.1").unwrap());
+            repl(doc.as_bytes(), &masscanned, &mut client_info).is_some()
+        };
+        /* no types or scopes: any device */
+        assert!(answered(&probe("", "")));
+        assert!(answered(&probe("<wsd:Types/>", "<wsd:Scopes />")));
+        assert!(answered(&probe(
+            "<wsd:Types>tds:Device dn:NetworkVideoTransmitter</wsd:Types>",
+            ""
+        )));
+        /* Windows looking for computers */
+        assert!(!answered(&probe(
+            "<wsd:Types xmlns:pub=\"http://schemas.microsoft.com/windows/pub/2005/07\">pub:Computer</wsd:Types>",
+            ""
+        )));
+        assert!(!answered(&probe(
+            "<wsd:Types xmlns:wsdp=\"http://schemas.xmlsoap.org/ws/2006/02/devprof\">wsdp:Device</wsd:Types>",
+            ""
+        )));
+        for (scope, matched) in [
+            ("onvif://www.onvif.org/location/city", true),
+            ("onvif://www.onvif.org/location/city/hangzhou/", true),
+            ("ONVIF://www.onvif.org/Profile/Streaming", true),
+            ("onvif://www.onvif.org/location/ci", false),
+            ("onvif://www.onvif.org/Profile/G", false),
+        ]
+        .iter()
+        {
+            let scopes = format!("<wsd:Scopes>{}</wsd:Scopes>", scope);
+            assert!(answered(&probe("", &scopes)) == *matched);
+        }
+        /* not probes */
+        assert!(!answered(&probe("", "").replace("Probe>", "Hello>")));
+        assert!(!answered(&probe("", "").replace("MessageID", "RelatesTo")));
+        assert!(!answered("SSDP"));
+        assert!(!answered(&ODM_PROBE_NVT[..400]));
+    }
+
+    #[test]
+    fn test_wsd_dispatch() {
+        let mut config = Config::default();
+        let mut client_info = client();
+        assert!(is_wsdiscovery(
+            &masscanned(Config::default(), None),
+            &client_info
+        ));
+        /* other groups */
+        client_info.ip.dst = Some(IpAddr::from_str("224.0.0.251").unwrap());
+        assert!(!is_wsdiscovery(
+            &masscanned(Config::default(), None),
+            &client_info
+        ));
+        client_info.ip.dst = Some(IpAddr::V6(WSD_GROUP_V6));
+        assert!(is_wsdiscovery(
+            &masscanned(Config::default(), None),
+            &client_info
+        ));
+        config.wsdiscovery.enabled = false;
+        assert!(!is_wsdiscovery(&masscanned(config, None), &client_info));
+    }
+
+    #[test]
+    fn test_wsd_config() {
+        for conf in [
+            "types = [\"NetworkVideoTransmitter\"]",
+            "types = [\"pub:Computer\"]",
+            "types = [\"dn:\"]",
+            "scopes = [\"onvif://www.onvif.org/name/IP Camera\"]",
+            "xaddrs_path = \"onvif/device_service\"",
+        ]
+        .iter()
+        {
+            assert!(Config::from_str(&format!("[wsdiscovery]\n{}\n", conf)).is_err());
+        }
+        let config = Config::from_str(
+            "[wsdiscovery]\ntypes = [\"wsdp:Device\"]\nscopes = []\nhardware = \"\"\nname = \"IP Camera\"\nlocation = \"\"\n",
+        )
+        .unwrap();
+        assert!(wsd_scopes(&config.wsdiscovery) == ["onvif://www.onvif.org/name/IP%20Camera"]);
+    }
+}
@@ -0,0 +1,465 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::{encode, Encoding, Event};
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* byte order of the client: MSB first, or LSB first */
+const X11_MSB_FIRST: u8 = b'B';
+const X11_LSB_FIRST: u8 = b'l';
+
+const X11_FAILED: u8 = 0;
+const X11_SUCCESS: u8 = 1;
+
+const X11_PROTOCOL_MAJOR: u16 = 11;
+const X11_PROTOCOL_MINOR: u16 = 0;
+
+/* maximum size of a connection setup request kept (authorization
+ * protocols have short names and data) */
+const X11_MAX_SETUP: usize = 4096;
+
+/* X11 server, over TCP: connection setup requests get a Failed
+ * response with the reason below, or, when greedy is set, a Success
+ * response describing a server with one screen.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct X11Config {
+    pub ports: Vec<u16>,
+    pub greedy: bool,
+    pub reason: String,
+    pub vendor: String,
+    pub release: u32,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Default for X11Config {
+    fn default() -> Self {
+        X11Config {
+            ports: vec![6000],
+            greedy: false,
+            reason: "Authorization required, but no authorization protocol specified\n".to_string(),
+            vendor: "The X.Org Foundation".to_string(),
+            release: 12101004,
+            width: 1920,
+            height: 1080,
+        }
+    }
+}
+
+impl X11Config {
+    pub fn check(&self) -> Result<(), io::Error> {
+        if self.reason.len() > u8::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("x11: reason longer than {} bytes", u8::MAX),
+            ));
+        }
+        if self.vendor.len() > X11_MAX_SETUP {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("x11: vendor longer than {} bytes", X11_MAX_SETUP),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* state of an X11 flow: beginning of the connection setup request,
+ * and whether it has been answered */
+#[derive(Default)]
+pub struct X11State {
+    buffer: Vec<u8>,
+    done: bool,
+}
+
+/* multi-byte values, in the byte order of the client */
+struct X11Writer {
+    msb: bool,
+    data: Vec<u8>,
+}
+
+impl X11Writer {
+    fn u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        if self.msb {
+            self.data.extend_from_slice(&v.to_be_bytes());
+        } else {
+            self.data.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    fn u32(&mut self, v: u32) {
+        if self.msb {
+            self.data.extend_from_slice(&v.to_be_bytes());
+        } else {
+            self.data.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    /* bytes, padded to a multiple of 4 */
+    fn padded(&mut self, v: &[u8]) {
+        self.data.extend_from_slice(v);
+        self.data.resize(self.data.len() + x11_pad(v.len()), 0);
+    }
+}
+
+fn x11_pad(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+fn x11_u16(data: &[u8], msb: bool) -> u16 {
+    if msb {
+        u16::from_be_bytes([data[0], data[1]])
+    } else {
+        u16::from_le_bytes([data[0], data[1]])
+    }
+}
+
+struct X11Setup {
+    msb: bool,
+    major: u16,
+    minor: u16,
+    auth_name: Vec<u8>,
+    auth_data: Vec<u8>,
+}
+
+/* connection setup request and its length, None when incomplete, or
+ * an error for what is not one */
+fn x11_parse_setup(data: &[u8]) -> Result<Option<(X11Setup, usize)>, ()> {
+    let msb = match data.first() {
+        None => return Ok(None),
+        Some(&X11_MSB_FIRST) => true,
+        Some(&X11_LSB_FIRST) => false,
+        Some(_) => return Err(()),
+    };
+    if data.len() < 12 {
+        return Ok(None);
+    }
+    let name_len = x11_u16(&data[6..8], msb) as usize;
+    let data_len = x11_u16(&data[8..10], msb) as usize;
+    let name_end = 12 + name_len;
+    let data_start = name_end + x11_pad(name_len);
+    let len = data_start + data_len + x11_pad(data_len);
+    if len > X11_MAX_SETUP {
+        return Err(());
+    }
+    if data.len() < len {
+        return Ok(None);
+    }
+    Ok(Some((
+        X11Setup {
+            msb,
+            major: x11_u16(&data[2..4], msb),
+            minor: x11_u16(&data[4..6], msb),
+            auth_name: data[12..name_end].to_vec(),
+            auth_data: data[data_start..data_start + data_len].to_vec(),
+        },
+        len,
+    )))
+}
+
+fn x11_failed(config: &X11Config, msb: bool) -> Vec<u8> {
+    let reason = config.reason.as_bytes();
+    let mut w = X11Writer {
+        msb,
+        data: Vec::new(),
+    };
+    w.u8(X11_FAILED);
+    w.u8(reason.len() as u8);
+    w.u16(X11_PROTOCOL_MAJOR);
+    w.u16(X11_PROTOCOL_MINOR);
+    /* in 4-byte units */
+    w.u16(((reason.len() + x11_pad(reason.len())) / 4) as u16);
+    w.padded(reason);
+    w.data
+}
+
+/* one screen, with one depth (24 bits) and one visual (TrueColor) */
+fn x11_success(config: &X11Config, msb: bool) -> Vec<u8> {
+    let vendor = config.vendor.as_bytes();
+    /* (depth, bits per pixel, scanline pad) */
+    let formats: [(u8, u8, u8); 2] = [(1, 1, 32), (24, 32, 32)];
+    let mut w = X11Writer {
+        msb,
+        data: Vec::new(),
+    };
+    w.u8(X11_SUCCESS);
+    w.u8(0);
+    w.u16(X11_PROTOCOL_MAJOR);
+    w.u16(X11_PROTOCOL_MINOR);
+    /* filled below, in 4-byte units */
+    w.u16(0);
+    w.u32(config.release);
+    /* resource ID base and mask */
+    w.u32(0x0040_0000);
+    w.u32(0x001f_ffff);
+    /* motion buffer size */
+    w.u32(256);
+    w.u16(vendor.len() as u16);
+    /* maximum request length */
+    w.u16(0xffff);
+    /* screens and formats */
+    w.u8(1);
+    w.u8(formats.len() as u8);
+    /* image byte order, bitmap format bit order (LSB first) */
+    w.u8(0);
+    w.u8(0);
+    /* bitmap format scanline unit and pad */
+    w.u8(32);
+    w.u8(32);
+    /* minimum and maximum keycodes */
+    w.u8(8);
+    w.u8(255);
+    w.u32(0);
+    w.padded(vendor);
+    for (depth, bpp, pad) in formats.iter() {
+        w.u8(*depth);
+        w.u8(*bpp);
+        w.u8(*pad);
+        w.data.extend_from_slice(&[0; 5]);
+    }
+    /* screen: root window, default colormap, white and black pixels,
+     * current input masks */
+    w.u32(0x0000_01e1);
+    w.u32(0x0000_0020);
+    w.u32(0x00ff_ffff);
+    w.u32(0);
+    w.u32(0);
+    w.u16(config.width);
+    w.u16(config.height);
+    /* size in millimeters, at 96 dpi */
+    w.u16((config.width as u32 * 254 / 960) as u16);
+    w.u16((config.height as u32 * 254 / 960) as u16);
+    /* installed maps (min, max) */
+    w.u16(1);
+    w.u16(1);
+    /* root visual */
+    w.u32(0x21);
+    /* backing stores (never), save unders, root depth, depths */
+    w.u8(0);
+    w.u8(0);
+    w.u8(24);
+    w.u8(1);
+    /* depth: 24 bits, one visual */
+    w.u8(24);
+    w.u8(0);
+    w.u16(1);
+    w.u32(0);
+    /* visual: TrueColor, 8 bits per RGB value, 256 colormap entries */
+    w.u32(0x21);
+    w.u8(4);
+    w.u8(8);
+    w.u16(256);
+    w.u32(0x00ff_0000);
+    w.u32(0x0000_ff00);
+    w.u32(0x0000_00ff);
+    w.u32(0);
+    let len = ((w.data.len() - 8) / 4) as u16;
+    let len = if msb {
+        len.to_be_bytes()
+    } else {
+        len.to_le_bytes()
+    };
+    w.data[6..8].copy_from_slice(&len);
+    w.data
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving X11 data");
+    let config = &masscanned.config.x11;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::X11(s)) => s,
+        _ => X11State::default(),
+    };
+    /* requests after a successful setup are ignored */
+    let repl_data = if state.done {
+        None
+    } else {
+        state.buffer.extend_from_slice(data);
+        match x11_parse_setup(&state.buffer) {
+            Err(()) => {
+                info!("invalid X11 connection setup");
+                client_info.close = true;
+                return None;
+            }
+            Ok(None) => None,
+            Ok(Some((setup, _))) => {
+                let mut event = Event::new("x11_setup", client_info);
+                event.set("byte_order", if setup.msb { "msb" } else { "lsb" });
+                event.set("protocol_major", setup.major);
+                event.set("protocol_minor", setup.minor);
+                event.set(
+                    "auth_name",
+                    String::from_utf8_lossy(&setup.auth_name).to_string(),
+                );
+                event.set("auth_data_length", setup.auth_data.len());
+                if !setup.auth_data.is_empty() {
+                    event.set("auth_data", encode(&setup.auth_data, Encoding::Hex));
+                }
+                event.set("greedy", config.greedy);
+                event.log();
+                state.buffer.clear();
+                state.done = true;
+                if config.greedy {
+                    Some(x11_success(config, setup.msb))
+                } else {
+                    client_info.close = true;
+                    Some(x11_failed(config, setup.msb))
+                }
+            }
+        }
+    };
+    if let Some(t) = tcb {
+        t.proto_state = ProtoState::X11(state);
+    }
+    debug!("sending X11 data");
+    repl_data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    /* setup request with MIT-MAGIC-COOKIE-1 (18 bytes, padded to 20)
+     * and a 16-byte cookie */
+    fn setup(msb: bool) -> Vec<u8> {
+        let mut w = X11Writer {
+            msb,
+            data: vec![if msb { b'B' } else { b'l' }, 0],
+        };
+        w.u16(11);
+        w.u16(0);
+        w.u16(18);
+        w.u16(16);
+        w.u16(0);
+        w.padded(b"MIT-MAGIC-COOKIE-1");
+        w.padded(&[0x42; 16]);
+        w.data
+    }
+
+    #[test]
+    fn test_x11_byte_orders() {
+        for msb in [true, false] {
+            let data = setup(msb);
+            assert!(data.len() == 12 + 20 + 16);
+            let (s, len) = x11_parse_setup(&data).unwrap().unwrap();
+            assert!(len == data.len());
+            assert!(s.msb == msb && s.major == 11 && s.minor == 0);
+            assert!(s.auth_name == b"MIT-MAGIC-COOKIE-1" && s.auth_data == [0x42; 16]);
+            /* incomplete */
+            for i in 0..data.len() {
+                assert!(matches!(x11_parse_setup(&data[..i]), Ok(None)));
+            }
+        }
+        /* not X11 */
+        assert!(x11_parse_setup(b"GET / HTTP/1.0\r\n").is_err());
+        /* no authorization */
+        let (s, len) = x11_parse_setup(b"l\x00\x0b\x00\x00\x00\x00\x00\x00\x00\x00\x00")
+            .unwrap()
+            .unwrap();
+        assert!(len == 12 && s.auth_name.is_empty() && s.auth_data.is_empty());
+    }
+
+    #[test]
+    fn test_x11_failed() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(6000);
+        let mut tcb = TCPControlBlock::new();
+        let data = setup(true);
+        /* split across segments */
+        assert!(
+            crate::proto::dispatch(&data[..20], &masscanned, &mut client_info, Some(&mut tcb))
+                .is_none()
+        );
+        let reply =
+            crate::proto::dispatch(&data[20..], &masscanned, &mut client_info, Some(&mut tcb))
+                .unwrap();
+        assert!(client_info.close);
+        /* 64-byte reason, padded to 64 (16 units) */
+        let reason = masscanned.config.x11.reason.as_bytes();
+        assert!(reason.len() == 64);
+        assert!(reply[..8] == [0, 64, 0, 11, 0, 0, 0, 16]);
+        assert!(reply[8..] == *reason);
+        /* LSB first, and a reason to pad */
+        let config = X11Config {
+            reason: "No protocol specified\n".to_string(),
+            ..Default::default()
+        };
+        let reply = x11_failed(&config, false);
+        assert!(reply[..8] == [0, 22, 11, 0, 0, 0, 6, 0]);
+        assert!(reply.len() == 8 + 24 && reply[8 + 22..] == [0, 0]);
+    }
+
+    #[test]
+    fn test_x11_success() {
+        let mut masscanned = masscanned(Config::default());
+        masscanned.config.x11.greedy = true;
+        for msb in [true, false] {
+            let mut client_info = ClientInfo::new();
+            let mut tcb = TCPControlBlock::new();
+            let reply = repl(&setup(msb), &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+            assert!(!client_info.close);
+            let r16 = |i: usize| x11_u16(&reply[i..i + 2], msb);
+            assert!(reply[0] == 1 && r16(2) == 11 && r16(4) == 0);
+            /* additional data: 8 + 2n + (v + p + m) / 4 units, with 2
+             * formats, a 20-byte vendor and a 72-byte screen */
+            assert!(r16(6) as usize == 8 + 2 * 2 + (20 + 72) / 4);
+            assert!(reply.len() == 8 + r16(6) as usize * 4);
+            assert!(r16(24) == 20 && reply[28] == 1 && reply[29] == 2);
+            assert!(reply[40..60] == *b"The X.Org Foundation");
+            /* screen size */
+            assert!(r16(60 + 16 + 20) == 1920 && r16(60 + 16 + 22) == 1080);
+            /* later requests are ignored */
+            assert!(repl(
+                b"\x62\x00\x05\x00",
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb)
+            )
+            .is_none());
+        }
+        /* vendor to pad */
+        masscanned.config.x11.vendor = "Xvfb!".to_string();
+        let reply = x11_success(&masscanned.config.x11, true);
+        assert!(reply.len().is_multiple_of(4));
+        assert!(u16::from_be_bytes([reply[6], reply[7]]) as usize == 8 + 4 + (8 + 72) / 4);
+    }
+}
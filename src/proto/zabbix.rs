@@ -0,0 +1,310 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+
+use log::*;
+use serde::Deserialize;
+
+use crate::client::ClientInfo;
+use crate::logger::Event;
+use crate::proto::tcb::{ProtoState, TCPControlBlock};
+use crate::Masscanned;
+
+/* ZBXD, flags (1: Zabbix protocol), data length (8 bytes, little
+ * endian - the 4 upper bytes are reserved) */
+const ZBX_MAGIC: &[u8] = b"ZBXD";
+const ZBX_FLAG_PROTOCOL: u8 = 0x01;
+const ZBX_HEADER_LEN: usize = 13;
+
+const ZBX_NOTSUPPORTED: &[u8] = b"ZBX_NOTSUPPORTED\0Unsupported item key.";
+
+/* size of a request kept between two segments */
+const ZBX_MAX_BUFFER: usize = 65536;
+
+/* Zabbix agent (passive checks): the items below are answered, any
+ * other one is not supported. Each connection gets one answer.
+ **/
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ZabbixConfig {
+    pub ports: Vec<u16>,
+    pub version: String,
+    pub hostname: String,
+    pub uname: String,
+}
+
+impl Default for ZabbixConfig {
+    fn default() -> Self {
+        ZabbixConfig {
+            ports: vec![10050],
+            version: "6.0.25".to_string(),
+            hostname: "zabbix01".to_string(),
+            uname: "Linux zabbix01 5.15.0-91-generic #101-Ubuntu SMP Tue Nov 14 13:30:08 UTC \
+                2023 x86_64"
+                .to_string(),
+        }
+    }
+}
+
+impl ZabbixConfig {
+    pub fn check(&self) -> Result<(), io::Error> {
+        for s in [&self.version, &self.hostname, &self.uname].iter() {
+            if s.contains('\0') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("zabbix: invalid value (NUL found): {:?}", s),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/* beginning of a request not received entirely yet */
+#[derive(Default)]
+pub struct ZabbixState {
+    buffer: Vec<u8>,
+}
+
+enum ZbxRequest {
+    Incomplete,
+    Invalid,
+    /* key, and whether it was framed */
+    Complete(Vec<u8>, bool),
+}
+
+fn zbx_parse(data: &[u8]) -> ZbxRequest {
+    if data.len() < ZBX_MAGIC.len() {
+        return if ZBX_MAGIC.starts_with(data) {
+            ZbxRequest::Incomplete
+        } else {
+            zbx_parse_line(data)
+        };
+    }
+    if !data.starts_with(ZBX_MAGIC) {
+        return zbx_parse_line(data);
+    }
+    if data.len() < ZBX_HEADER_LEN {
+        return ZbxRequest::Incomplete;
+    }
+    /* compressed and large packets are not handled */
+    if data[4] != ZBX_FLAG_PROTOCOL {
+        return ZbxRequest::Invalid;
+    }
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&data[5..ZBX_HEADER_LEN]);
+    let len = u64::from_le_bytes(len);
+    if len > ZBX_MAX_BUFFER as u64 {
+        return ZbxRequest::Invalid;
+    }
+    let end = ZBX_HEADER_LEN + len as usize;
+    match data.get(ZBX_HEADER_LEN..end) {
+        Some(key) => ZbxRequest::Complete(key.to_vec(), true),
+        None => ZbxRequest::Incomplete,
+    }
+}
+
+/* plaintext: the key, up to LF (or CRLF) */
+fn zbx_parse_line(data: &[u8]) -> ZbxRequest {
+    match data.iter().position(|&c| c == b'\n') {
+        Some(i) => {
+            let mut key = &data[..i];
+            if key.last() == Some(&b'\r') {
+                key = &key[..key.len() - 1];
+            }
+            ZbxRequest::Complete(key.to_vec(), false)
+        }
+        None => ZbxRequest::Incomplete,
+    }
+}
+
+fn zbx_frame(data: &[u8]) -> Vec<u8> {
+    let mut frame = ZBX_MAGIC.to_vec();
+    frame.push(ZBX_FLAG_PROTOCOL);
+    frame.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    frame.extend_from_slice(data);
+    frame
+}
+
+/* value of an item (e.g., system.uname, or vfs.file.contents[/etc/passwd]) */
+fn zbx_item(key: &str, config: &ZabbixConfig) -> Vec<u8> {
+    let name = key.split('[').next().unwrap_or("");
+    match name {
+        "agent.ping" => b"1".to_vec(),
+        "agent.version" => config.version.as_bytes().to_vec(),
+        "agent.hostname" | "system.hostname" => config.hostname.as_bytes().to_vec(),
+        "system.uname" => config.uname.as_bytes().to_vec(),
+        _ => ZBX_NOTSUPPORTED.to_vec(),
+    }
+}
+
+pub fn repl(
+    data: &[u8],
+    masscanned: &Masscanned,
+    client_info: &mut ClientInfo,
+    mut tcb: Option<&mut TCPControlBlock>,
+) -> Option<Vec<u8>> {
+    debug!("receiving Zabbix data");
+    let config = &masscanned.config.zabbix;
+    let mut state = match tcb
+        .as_mut()
+        .map(|t| std::mem::replace(&mut t.proto_state, ProtoState::None))
+    {
+        Some(ProtoState::Zabbix(s)) => s,
+        _ => ZabbixState::default(),
+    };
+    state.buffer.extend_from_slice(data);
+    let (key, framed) = match zbx_parse(&state.buffer) {
+        ZbxRequest::Complete(key, framed) => (key, framed),
+        ZbxRequest::Incomplete => {
+            if state.buffer.len() > ZBX_MAX_BUFFER {
+                info!("Zabbix request too large - dropped");
+                client_info.close = true;
+            } else if let Some(t) = tcb {
+                t.proto_state = ProtoState::Zabbix(state);
+            }
+            return None;
+        }
+        ZbxRequest::Invalid => {
+            info!("Zabbix request not handled (invalid header)");
+            client_info.close = true;
+            return None;
+        }
+    };
+    let key = String::from_utf8_lossy(&key).to_string();
+    let value = zbx_item(&key, config);
+    warn!("Zabbix item {}", key);
+    let mut event = Event::new("zabbix_request", client_info);
+    event.set("key", key);
+    event.set("framed", framed);
+    event.set("supported", !value.starts_with(b"ZBX_NOTSUPPORTED"));
+    event.log();
+    /* the agent closes the connection after its answer */
+    client_info.close = true;
+    debug!("sending Zabbix data");
+    if framed {
+        Some(zbx_frame(&value))
+    } else {
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::test_util::masscanned;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::str::FromStr;
+
+    fn client() -> ClientInfo {
+        let mut client_info = ClientInfo::new();
+        client_info.transport = Some(IpNextHeaderProtocols::Tcp);
+        client_info.port.dst = Some(10050);
+        client_info
+    }
+
+    #[test]
+    fn test_zabbix_framed() {
+        let masscanned = masscanned(Config::default());
+        let request = zbx_frame(b"agent.version");
+        assert!(request == b"ZBXD\x01\x0d\x00\x00\x00\x00\x00\x00\x00agent.version");
+        /* request split across segments, through dispatch */
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        for cut in [3, 10].iter() {
+            client_info.close = false;
+            let mut tcb = TCPControlBlock::new();
+            assert!(crate::proto::dispatch(
+                &request[..*cut],
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb)
+            )
+            .is_none());
+            let repl_data = crate::proto::dispatch(
+                &request[*cut..],
+                &masscanned,
+                &mut client_info,
+                Some(&mut tcb),
+            )
+            .unwrap();
+            assert!(repl_data == zbx_frame(b"6.0.25"));
+            assert!(client_info.close);
+        }
+        client_info.close = false;
+        let repl_data = repl(
+            &zbx_frame(b"agent.ping"),
+            &masscanned,
+            &mut client_info,
+            Some(&mut tcb),
+        )
+        .unwrap();
+        assert!(repl_data == b"ZBXD\x01\x01\x00\x00\x00\x00\x00\x00\x001");
+        /* compressed, or absurd length */
+        for bad in [
+            &b"ZBXD\x03\x01\x00\x00\x00\x01\x00\x00\x00x"[..],
+            b"ZBXD\x01\xff\xff\xff\xff\xff\xff\xff\xff",
+        ]
+        .iter()
+        {
+            let mut client_info = client();
+            assert!(repl(bad, &masscanned, &mut client_info, None).is_none());
+            assert!(client_info.close);
+        }
+    }
+
+    #[test]
+    fn test_zabbix_plaintext() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        let mut tcb = TCPControlBlock::new();
+        assert!(repl(b"system.un", &masscanned, &mut client_info, Some(&mut tcb)).is_none());
+        let repl_data = repl(b"ame\r\n", &masscanned, &mut client_info, Some(&mut tcb)).unwrap();
+        assert!(repl_data.starts_with(b"Linux zabbix01 5.15.0-91-generic "));
+        assert!(client_info.close);
+        let mut client_info = client();
+        let repl_data = repl(b"agent.ping\n", &masscanned, &mut client_info, None).unwrap();
+        assert!(repl_data == b"1");
+    }
+
+    #[test]
+    fn test_zabbix_not_supported() {
+        let masscanned = masscanned(Config::default());
+        let mut client_info = client();
+        let repl_data = repl(
+            &zbx_frame(b"system.run[cat /etc/passwd]"),
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        assert!(repl_data == zbx_frame(b"ZBX_NOTSUPPORTED\0Unsupported item key."));
+        let mut client_info = client();
+        let repl_data = repl(
+            b"vfs.file.contents[/etc/shadow]\n",
+            &masscanned,
+            &mut client_info,
+            None,
+        )
+        .unwrap();
+        assert!(repl_data == ZBX_NOTSUPPORTED);
+        /* items with parameters */
+        assert!(zbx_item("agent.hostname[]", &ZabbixConfig::default()) == b"zabbix01");
+        assert!(Config::from_str("[zabbix]\nversion = \"6\\u0000\"\n").is_err());
+    }
+}
@@ -0,0 +1,367 @@
+// This file is part of masscanned.
+// Copyright 2021 - The IVRE project
+//
+// Masscanned is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Masscanned is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY
+// or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public
+// License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Masscanned. If not, see <http://www.gnu.org/licenses/>.
+
+/* zlib streams (RFC 1950) of deflate data (RFC 1951): decompression
+ * with a bound on the size of the output, and compression with stored
+ * blocks only */
+
+const ZLIB_CM_DEFLATE: u8 = 8;
+const ZLIB_FDICT: u8 = 0x20;
+
+/* deflate stored blocks hold at most 65535 bytes */
+const DEFLATE_STORED_MAX: usize = 0xffff;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/* order of the code length code lengths */
+const CLEN_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for c in chunk {
+            a += *c as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/* bits are read from the least significant one of each byte */
+struct BitReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    bit: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        let mut v = self.bit;
+        while self.count < n {
+            v |= (*self.data.get(self.offset)? as u32) << self.count;
+            self.offset += 1;
+            self.count += 8;
+        }
+        self.bit = v >> n;
+        self.count -= n;
+        Some(v & ((1u32 << n) - 1))
+    }
+
+    /* stored blocks start on a byte boundary */
+    fn align(&mut self) {
+        self.bit = 0;
+        self.count = 0;
+    }
+}
+
+/* canonical Huffman code: number of codes of each length, and symbols
+ * ordered by code */
+struct Huffman {
+    count: [u16; 16],
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    /* None when the lengths are over-subscribed (incomplete codes are
+     * accepted, e.g., for a single distance code) */
+    fn new(lengths: &[u8]) -> Option<Self> {
+        let mut count = [0u16; 16];
+        for l in lengths {
+            count[*l as usize] += 1;
+        }
+        let mut left: i32 = 1;
+        for c in count.iter().skip(1) {
+            left = (left << 1) - *c as i32;
+            if left < 0 {
+                return None;
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for l in 1..15 {
+            offsets[l + 1] = offsets[l] + count[l];
+        }
+        let mut symbol = vec![0u16; lengths.len()];
+        for (s, l) in lengths.iter().enumerate() {
+            if *l != 0 {
+                symbol[offsets[*l as usize] as usize] = s as u16;
+                offsets[*l as usize] += 1;
+            }
+        }
+        Some(Huffman { count, symbol })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.count[len] as i32;
+            if code - first < count {
+                return self.symbol.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+fn inflate_codes(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    max: usize,
+    lencode: &Huffman,
+    distcode: &Huffman,
+) -> Option<()> {
+    loop {
+        let symbol = lencode.decode(reader)? as usize;
+        if symbol < 256 {
+            if out.len() >= max {
+                return None;
+            }
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Some(());
+        } else {
+            let i = symbol - 257;
+            let len =
+                *LENGTH_BASE.get(i)? as usize + reader.bits(*LENGTH_EXTRA.get(i)? as u32)? as usize;
+            let d = distcode.decode(reader)? as usize;
+            let dist =
+                *DIST_BASE.get(d)? as usize + reader.bits(*DIST_EXTRA.get(d)? as u32)? as usize;
+            if dist > out.len() || out.len() + len > max {
+                return None;
+            }
+            let start = out.len() - dist;
+            for k in 0..len {
+                out.push(out[start + k]);
+            }
+        }
+    }
+}
+
+fn inflate_fixed() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (i, l) in lengths.iter_mut().enumerate() {
+        *l = match i {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (
+        Huffman::new(&lengths).unwrap(),
+        Huffman::new(&[5u8; 30]).unwrap(),
+    )
+}
+
+fn inflate_dynamic(reader: &mut BitReader) -> Option<(Huffman, Huffman)> {
+    let nlen = reader.bits(5)? as usize + 257;
+    let ndist = reader.bits(5)? as usize + 1;
+    let ncode = reader.bits(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return None;
+    }
+    let mut lengths = [0u8; 19];
+    for i in CLEN_ORDER.iter().take(ncode) {
+        lengths[*i] = reader.bits(3)? as u8;
+    }
+    let clencode = Huffman::new(&lengths)?;
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut i = 0;
+    while i < nlen + ndist {
+        let symbol = clencode.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => (
+                *lengths.get(i.checked_sub(1)?)?,
+                3 + reader.bits(2)? as usize,
+            ),
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if i + repeat > nlen + ndist {
+            return None;
+        }
+        for l in lengths[i..i + repeat].iter_mut() {
+            *l = value;
+        }
+        i += repeat;
+    }
+    /* the end-of-block code must be there */
+    if lengths[256] == 0 {
+        return None;
+    }
+    Some((
+        Huffman::new(&lengths[..nlen])?,
+        Huffman::new(&lengths[nlen..])?,
+    ))
+}
+
+/* deflate data, at most max bytes once decompressed - None when the
+ * data is invalid, truncated or too large */
+fn inflate(data: &[u8], max: usize) -> Option<(Vec<u8>, usize)> {
+    let mut reader = BitReader {
+        data,
+        offset: 0,
+        bit: 0,
+        count: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let o = reader.offset;
+                let len = u16::from_le_bytes([*data.get(o)?, *data.get(o + 1)?]);
+                let nlen = u16::from_le_bytes([*data.get(o + 2)?, *data.get(o + 3)?]);
+                if len != !nlen || out.len() + len as usize > max {
+                    return None;
+                }
+                out.extend_from_slice(data.get(o + 4..o + 4 + len as usize)?);
+                reader.offset = o + 4 + len as usize;
+            }
+            1 => {
+                let (lencode, distcode) = inflate_fixed();
+                inflate_codes(&mut reader, &mut out, max, &lencode, &distcode)?;
+            }
+            2 => {
+                let (lencode, distcode) = inflate_dynamic(&mut reader)?;
+                inflate_codes(&mut reader, &mut out, max, &lencode, &distcode)?;
+            }
+            _ => return None,
+        }
+        if last {
+            return Some((out, reader.offset));
+        }
+    }
+}
+
+/* content of a zlib stream, if valid (header and Adler-32) and no
+ * larger than max bytes */
+pub fn zlib_decompress(data: &[u8], max: usize) -> Option<Vec<u8>> {
+    let (cmf, flg) = (*data.first()?, *data.get(1)?);
+    if cmf & 0x0f != ZLIB_CM_DEFLATE
+        || cmf >> 4 > 7
+        || flg & ZLIB_FDICT != 0
+        || !((cmf as u16) << 8 | flg as u16).is_multiple_of(31)
+    {
+        return None;
+    }
+    let (out, len) = inflate(&data[2..], max)?;
+    let check = data.get(2 + len..2 + len + 4)?;
+    if u32::from_be_bytes([check[0], check[1], check[2], check[3]]) != adler32(&out) {
+        return None;
+    }
+    Some(out)
+}
+
+/* zlib stream of data, in stored (uncompressed) deflate blocks */
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut chunks = data.chunks(DEFLATE_STORED_MAX).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        out.push(if chunks.peek().is_none() { 1 } else { 0 });
+        out.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(chunk.len() as u16)).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert!(adler32(b"") == 1);
+        assert!(adler32(b"Wikipedia") == 0x11e60398);
+    }
+
+    #[test]
+    fn test_zlib_decompress() {
+        /* fixed Huffman codes, with back-references */
+        let data = b"\x78\xda\xf3\x48\xcd\xc9\xc9\xd7\x51\xf0\xc0\xa4\x14\xb1\x09\x12\x25\x07\x00\x83\x06\x1a\xe0";
+        let text = b"Hello, Hello, Hello, Hello! ".repeat(3);
+        assert!(zlib_decompress(data, 1024).unwrap() == text);
+        /* dynamic Huffman codes */
+        let text: Vec<u8> = (0..30)
+            .flat_map(|i| format!("{}: gh0st {}\n", i, i * i).into_bytes())
+            .collect();
+        let data = unhex("78da45d0b11103310804c0fcaaf812044248b8213bb7fb1fa38023bb191e6e5fe3f5bc3fe3fb7b06a4a2402b1a66c580f103c7aaac0b5e793a363703a7b21ba2f211c8e0a191b5ddab3963b59841665706a401e19026a441bc413923434fee1132356f4673f34f69b1b4282d6639eb673806a56569406959dba1b4785a9416bf335af6dda365df9bfd2cd9f707973461f2");
+        assert!(zlib_decompress(&data, 1024).unwrap() == text);
+        /* output larger than allowed */
+        assert!(zlib_decompress(&data, text.len()).is_some());
+        assert!(zlib_decompress(&data, text.len() - 1).is_none());
+        /* truncated, corrupted, bad checksum, bad header */
+        for i in 0..data.len() {
+            assert!(zlib_decompress(&data[..i], 1024).is_none());
+        }
+        let mut bad = data.clone();
+        bad[20] ^= 0x55;
+        assert!(zlib_decompress(&bad, 1024).is_none());
+        let mut bad = data.clone();
+        *bad.last_mut().unwrap() ^= 1;
+        assert!(zlib_decompress(&bad, 1024).is_none());
+        assert!(zlib_decompress(b"\x78\xdb\x03\x00\x00\x00\x00\x01", 1024).is_none());
+        assert!(zlib_decompress(b"\x78\xda\x03\x00\x00\x00\x00\x01", 1024)
+            .unwrap()
+            .is_empty());
+        /* invalid block type */
+        assert!(zlib_decompress(b"\x78\x01\x07\x00", 1024).is_none());
+    }
+
+    #[test]
+    fn test_zlib_compress() {
+        assert!(zlib_compress(b"\x00") == b"\x78\x01\x01\x01\x00\xfe\xff\x00\x00\x01\x00\x01");
+        for len in [0, 1, 1000, 70000, 140000] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            assert!(zlib_decompress(&zlib_compress(&data), len).unwrap() == data);
+        }
+    }
+}
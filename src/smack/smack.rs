@@ -5,6 +5,10 @@ use crate::smack::smack_pattern::SmackPattern;
 use crate::smack::smack_queue::SmackQueue;
 use crate::smack::smack_utils::{row_shift_from_symbol_count, SmackFlags};
 
+/* bound on the letters of a case-insensitive pattern, expanded in
+ * 2^letters variants */
+const NOCASE_MAX_LETTERS: usize = 12;
+
 struct SmackRow {
     next_state: Vec<usize>,
     fail: usize,
@@ -70,6 +74,30 @@ fn make_copy_of_pattern(pattern: &[u8], is_nocase: bool) -> Vec<u8> {
     p
 }
 
+/* every upper/lower case combination of the ASCII letters of a
+ * pattern (the number of variants doubles with each letter) */
+fn make_case_variants(pattern: &[u8]) -> Vec<Vec<u8>> {
+    let letters: Vec<usize> = (0..pattern.len())
+        .filter(|i| pattern[*i].is_ascii_alphabetic())
+        .collect();
+    assert!(
+        letters.len() <= NOCASE_MAX_LETTERS,
+        "smack: too many letters in a case-insensitive pattern"
+    );
+    let lower = pattern.to_ascii_lowercase();
+    (0..1usize << letters.len())
+        .map(|mask| {
+            let mut p = lower.clone();
+            for (bit, i) in letters.iter().enumerate() {
+                if mask & (1 << bit) != 0 {
+                    p[*i] = p[*i].to_ascii_uppercase();
+                }
+            }
+            p
+        })
+        .collect()
+}
+
 impl Smack {
     pub fn new(name: String, nocase: bool) -> Self {
         Smack {
@@ -122,7 +150,20 @@ impl Smack {
             }
         }
     }
+    /* Register a pattern reported as id: with ANCHOR_BEGIN, it only
+     * matches at offset 0 of the data; with WILDCARDS, each '*' of the
+     * pattern matches any single byte; with NOCASE, ASCII letters match
+     * in any case. */
     pub fn add_pattern(&mut self, pattern: &[u8], id: usize, flags: SmackFlags) {
+        if flags.contains(SmackFlags::NOCASE) && !self.is_nocase {
+            /* case-insensitive pattern in a case-sensitive automaton:
+             * every variant is added as an exact pattern, so that
+             * matching costs the same */
+            for p in make_case_variants(pattern) {
+                self.add_pattern(&p, id, flags - SmackFlags::NOCASE);
+            }
+            return;
+        }
         let p = SmackPattern::new(make_copy_of_pattern(pattern, self.is_nocase), id, flags);
         if p.is_anchor_begin {
             self.is_anchor_begin = true;
@@ -134,6 +175,10 @@ impl Smack {
         self.m_pattern_list.push(p);
         self.m_pattern_count += 1;
     }
+    /* whether a pattern was registered for id */
+    pub fn has_pattern(&self, id: usize) -> bool {
+        self.m_pattern_list.iter().any(|p| p.id == id)
+    }
     fn set_goto(&mut self, r: usize, a: usize, h: usize) {
         self.m_state_table[r].next_state[a] = h;
     }
@@ -267,22 +312,49 @@ impl Smack {
             }
         }
     }
+    /* rows with matches go last: the order is computed first, then the
+     * rows are moved and the transitions renumbered at once */
     fn stage3_sort(&mut self) {
+        let mut order: Vec<usize> = (0..self.m_state_count).collect();
         let mut start = 0;
         let mut end = self.m_state_count;
         loop {
-            while start < end && self.m_match[start].m_count == 0 {
+            while start < end && self.m_match[order[start]].m_count == 0 {
                 start += 1;
             }
-            while start < end && self.m_match[end - 1].m_count != 0 {
+            while start < end && self.m_match[order[end - 1]].m_count != 0 {
                 end -= 1;
             }
             if start >= end {
                 break;
             }
-            self.swap_rows(start, end - 1);
+            order.swap(start, end - 1);
         }
         self.m_match_limit = start;
+        let mut new_row = vec![0; self.m_state_count];
+        for (i, o) in order.iter().enumerate() {
+            new_row[*o] = i;
+        }
+        let mut table: Vec<Option<SmackRow>> = mem::take(&mut self.m_state_table)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut matches: Vec<Option<SmackMatches>> =
+            mem::take(&mut self.m_match).into_iter().map(Some).collect();
+        let rest = order.len()..table.len();
+        for o in order.into_iter().chain(rest) {
+            let mut row = table[o].take().unwrap();
+            for s in row.next_state.iter_mut() {
+                if *s < self.m_state_count {
+                    *s = new_row[*s];
+                }
+            }
+            if row.fail < self.m_state_count {
+                row.fail = new_row[row.fail];
+            }
+            self.m_state_table.push(row);
+            self.m_match.push(matches[o].take().unwrap());
+        }
     }
     fn stage4_make_final_table(&mut self) {
         let row_count = self.m_state_count;
@@ -359,6 +431,9 @@ impl Smack {
         }
         (idx - px_start, row)
     }
+    /* Id of the next pattern matched (or NO_MATCH), searching data
+     * from offset: offset is set past the end of the match, and
+     * current_state keeps the search going across calls (and segments). */
     pub fn search_next(&self, current_state: &mut usize, v_px: &[u8], offset: &mut usize) -> usize {
         let px = v_px;
         let length = px.len();
@@ -755,4 +830,78 @@ mod tests {
         let id = smack.search_next(&mut state, &b"\x00\x00aa\xffSMB".to_vec(), &mut offset);
         assert!(id == PROTO_SMB);
     }
+
+    #[test]
+    fn test_nocase_pattern() {
+        const PROTO_HTTP: usize = 0;
+        const PROTO_SMB: usize = 1;
+        const PROTO_OTHER: usize = 2;
+        let mut smack = Smack::new("proto".to_string(), SMACK_CASE_SENSITIVE);
+        smack.add_pattern(
+            b"GET /",
+            PROTO_HTTP,
+            SmackFlags::ANCHOR_BEGIN | SmackFlags::NOCASE,
+        );
+        smack.add_pattern(b"SMB", PROTO_SMB, SmackFlags::ANCHOR_BEGIN);
+        smack.add_pattern(
+            b"a@[*z",
+            PROTO_OTHER,
+            SmackFlags::ANCHOR_BEGIN | SmackFlags::WILDCARDS | SmackFlags::NOCASE,
+        );
+        smack.compile();
+        let search = |text: &[u8]| {
+            let mut state = BASE_STATE;
+            let mut offset = 0;
+            smack.search_next(&mut state, text, &mut offset)
+        };
+        for text in [&b"GET /"[..], b"get /", b"gEt /", b"GeT /index"] {
+            assert!(search(text) == PROTO_HTTP);
+        }
+        /* near misses */
+        for text in [
+            &b"GET  /"[..],
+            b"GEU /",
+            b"GE\x14 /",
+            b"xget /",
+            b"GET\x00/",
+        ] {
+            assert!(search(text) == NO_MATCH);
+        }
+        /* exact patterns are unaffected */
+        assert!(search(b"SMB") == PROTO_SMB);
+        assert!(search(b"smb") == NO_MATCH);
+        assert!(search(b"SmB") == NO_MATCH);
+        /* only ASCII letters are folded */
+        assert!(search(b"A@[\x00Z") == PROTO_OTHER);
+        assert!(search(b"a@[-z") == PROTO_OTHER);
+        assert!(search(b"a`[-z") == NO_MATCH);
+        assert!(search(b"a@{-z") == NO_MATCH);
+        assert!(make_case_variants(b"a-B").len() == 4);
+    }
+
+    #[test]
+    fn test_anchor_and_wildcard_offsets() {
+        let mut smack = Smack::new("test".to_string(), SMACK_CASE_SENSITIVE);
+        smack.add_pattern(b"\xffSMB", 0, SmackFlags::ANCHOR_BEGIN);
+        smack.add_pattern(
+            b"\x16\x03**\x01",
+            1,
+            SmackFlags::ANCHOR_BEGIN | SmackFlags::WILDCARDS,
+        );
+        smack.compile();
+        let search = |text: &[u8]| {
+            let mut state = BASE_STATE;
+            let mut offset = 0;
+            let id = smack.search_next(&mut state, text, &mut offset);
+            (id, offset)
+        };
+        /* anchored: at offset 0 only */
+        assert!(search(b"\xffSMBr") == (0, 4));
+        assert!(search(b"\x00\x00\x00\xffSMBr").0 == NO_MATCH);
+        /* each wildcard matches any single byte */
+        assert!(search(b"\x16\x03\x01\x00\x01") == (1, 5));
+        assert!(search(b"\x16\x03\x03\x02\x01...") == (1, 5));
+        assert!(search(b"\x16\x03\x01\x01").0 == NO_MATCH);
+        assert!(search(b"\x16\x03\x01\x00\x02").0 == NO_MATCH);
+    }
 }
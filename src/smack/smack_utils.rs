@@ -4,6 +4,7 @@ bitflags! {
         const ANCHOR_BEGIN  = 0x01;
         const ANCHOR_END    = 0x02;
         const WILDCARDS     = 0x04;
+        const NOCASE        = 0x08;
     }
 }
 
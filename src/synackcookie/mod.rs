@@ -90,6 +90,7 @@ mod tests {
                 dst: Some(tcp_dport),
             },
             cookie: None,
+            close: false,
         };
         let res = generate(&client_info, &key);
         if let Ok(_) = res {
@@ -125,6 +126,7 @@ mod tests {
                 dst: Some(tcp_dport),
             },
             cookie: None,
+            close: false,
         };
         let res = generate(&client_info, &key);
         if let Ok(_) = res {
@@ -158,6 +160,7 @@ mod tests {
                 dst: Some(tcp_dport),
             },
             cookie: None,
+            close: false,
         };
         let res = generate(&client_info, &key);
         if let Ok(_) = res {
@@ -210,6 +213,7 @@ mod tests {
                 dst: Some(tcp_dport),
             },
             cookie: None,
+            close: false,
         };
         let ref_cookie = generate(&client_info, &ref_key).unwrap();
         assert!(_check(&client_info, ref_cookie, &ref_key));
@@ -246,6 +250,7 @@ mod tests {
                 dst: Some(tcp_dport),
             },
             cookie: None,
+            close: false,
         };
         let ref_cookie = generate(&client_info, &key).unwrap();
         assert!(_check(&client_info, ref_cookie, &key));
@@ -279,6 +284,7 @@ mod tests {
                 dst: Some(tcp_dport),
             },
             cookie: None,
+            close: false,
         };
         let ref_cookie = generate(&client_info, &key).unwrap();
         assert!(_check(&client_info, ref_cookie, &key));
@@ -312,6 +318,7 @@ mod tests {
                 dst: Some(tcp_dport),
             },
             cookie: None,
+            close: false,
         };
         let ref_cookie = generate(&client_info, &key).unwrap();
         assert!(_check(&client_info, ref_cookie, &key));
@@ -345,6 +352,7 @@ mod tests {
                 dst: Some(tcp_dport),
             },
             cookie: None,
+            close: false,
         };
         let ref_cookie = generate(&client_info, &key).unwrap();
         assert!(_check(&client_info, ref_cookie, &key));
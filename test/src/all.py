@@ -16,6 +16,7 @@
 
 import logging
 import struct
+import zlib
 
 from scapy.compat import raw
 from scapy.data import ETHER_BROADCAST
@@ -743,6 +744,27 @@ def test_ipv6_udp_http_ko(iface):
         assert resp is None, "expecting no answer, got one"
 
 
+def check_stun_rfc5389(resp, sport, addr):
+    """XOR-MAPPED-ADDRESS, SOFTWARE and FINGERPRINT after MAPPED-ADDRESS"""
+    mask = resp[4:20]
+    offset = 20 + 8 + len(addr)
+    xor = bytes(a ^ m for a, m in zip(addr, mask))
+    family = 1 if len(addr) == 4 else 2
+    expected = struct.pack(">HHBBH", 0x20, 4 + len(addr), 0, family, sport ^ 0x2112)
+    expected += xor
+    assert (
+        resp[offset : offset + len(expected)] == expected
+    ), "unexpected XOR-MAPPED-ADDRESS: {!r}".format(resp[offset:])
+    offset += len(expected)
+    type_, length = struct.unpack(">HH", resp[offset : offset + 4])
+    assert type_ == 0x8022, "expected SOFTWARE, got 0x{:04x}".format(type_)
+    offset += 4 + ((length + 3) & ~3)
+    fingerprint = struct.pack(">HHI", 0x8028, 4, zlib.crc32(resp[:offset]) ^ 0x5354554E)
+    assert resp[offset:] == fingerprint, "unexpected FINGERPRINT: {!r}".format(
+        resp[offset:]
+    )
+
+
 @test
 def test_ipv4_udp_stun(iface):
     sports = [12345, 55555, 80, 43273]
@@ -768,7 +790,7 @@ def test_ipv4_udp_stun(iface):
             tid = resp_payload[8:20]
             data = resp_payload[20:]
             assert type_ == 0x0101, "expected type 0X0101, got 0x{:04x}".format(type_)
-            assert length == 12, "expected length 12, got {}".format(length)
+            assert length == 56, "expected length 56, got {}".format(length)
             assert (
                 magic == 0x2112A442
             ), "expected magic 0x2112a442, got 0x{:08x}".format(magic)
@@ -779,8 +801,9 @@ def test_ipv4_udp_stun(iface):
                 ">HBBBB", sport, 192, 0, 0, 0
             )
             assert (
-                data == expected_data
-            ), f"unexpected data {data!r} != {expected_data!r}"
+                data[:12] == expected_data
+            ), f"unexpected data {data[:12]!r} != {expected_data!r}"
+            check_stun_rfc5389(resp_payload, sport, bytes([192, 0, 0, 0]))
 
 
 @test
@@ -808,16 +831,17 @@ def test_ipv6_udp_stun(iface):
             tid = resp_payload[8:20]
             data = resp_payload[20:]
             assert type_ == 0x0101, "expected type 0X0101, got 0x{:04x}".format(type_)
-            assert length == 24, "expected length 24, got {}".format(length)
+            assert length == 80, "expected length 80, got {}".format(length)
             assert (
                 magic == 0x2112A442
             ), "expected magic 0x2112a442, got 0x{:08x}".format(magic)
             assert (
                 tid == b"\x00" * 12
             ), "expected tid 0x000000000000000000000000, got {:x}".format(tid)
-            assert data == bytes.fromhex("000100140002") + struct.pack(
+            assert data[:24] == bytes.fromhex("000100140002") + struct.pack(
                 ">H", sport
             ) + bytes.fromhex("00000000" * 4), "unexpected data: {}".format(data)
+            check_stun_rfc5389(resp_payload, sport, bytes(16))
 
 
 @test